@@ -190,59 +190,4 @@ fn main() {
     }
 
     println!("cargo:rerun-if-changed=src/arch/x86_64/user_entry.S");
-
-    // Compile syscall entry assembly
-    let syscall_entry_src = PathBuf::from("src/arch/x86_64/syscall/entry.S");
-    let syscall_entry_obj = out_dir.join("syscall_entry.o");
-
-    if syscall_entry_src.exists() {
-        println!("cargo:warning=Compiling syscall entry assembly with GAS");
-
-        // Compile assembly to object file using assembler
-        let output = if cfg!(target_os = "macos") {
-            Command::new("clang")
-                .args(&[
-                    "-target",
-                    "x86_64-unknown-none",
-                    "-c",
-                    "-o",
-                    syscall_entry_obj.to_str().unwrap(),
-                    syscall_entry_src.to_str().unwrap(),
-                ])
-                .output()
-                .expect("Failed to execute clang")
-        } else {
-            Command::new("as")
-                .args(&[
-                    "--64",
-                    "-o",
-                    syscall_entry_obj.to_str().unwrap(),
-                    syscall_entry_src.to_str().unwrap(),
-                ])
-                .output()
-                .expect("Failed to execute as")
-        };
-
-        if !output.status.success() {
-            eprintln!(
-                "assembler stdout: {}",
-                String::from_utf8_lossy(&output.stdout)
-            );
-            eprintln!(
-                "assembler stderr: {}",
-                String::from_utf8_lossy(&output.stderr)
-            );
-            panic!("Failed to compile syscall entry assembly");
-        }
-
-        // Link the object file with the kernel
-        println!("cargo:rustc-link-arg={}", syscall_entry_obj.to_str().unwrap());
-
-        println!(
-            "cargo:warning=Syscall entry assembly compiled to {:?}",
-            syscall_entry_obj
-        );
-    }
-
-    println!("cargo:rerun-if-changed=src/arch/x86_64/syscall/entry.S");
 }
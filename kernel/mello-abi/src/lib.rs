@@ -0,0 +1,336 @@
+//! Layouts of the structs passed between the kernel and user space
+//!
+//! The kernel and each userland crate keep their own definitions of the
+//! structs they exchange through syscalls and IPC. This crate holds the
+//! reference layout of each, and both sides check their definitions against
+//! it with [`check_layout!`]. The check runs at compile time, so a struct
+//! changed on one side only fails the build instead of corrupting data at
+//! run time.
+//!
+//! To change the ABI, change the reference struct here together with every
+//! definition that checks against it.
+//!
+//! Not covered yet:
+//! - Kernel metrics: they are exported as text in /proc, not as a struct.
+
+#![no_std]
+
+/// Assert at compile time that `$ty` has the size, alignment and field
+/// offsets of the reference struct `$abi`
+///
+/// ```ignore
+/// mello_abi::check_layout!(Winsize, mello_abi::Winsize { ws_row, ws_col, ws_xpixel, ws_ypixel });
+/// ```
+///
+/// Every field the two structs share must be listed.
+#[macro_export]
+macro_rules! check_layout {
+    ($ty:ty, $abi:path { $($field:ident),* $(,)? }) => {
+        const _: () = {
+            use $abi as Abi;
+            assert!(
+                ::core::mem::size_of::<$ty>() == ::core::mem::size_of::<Abi>(),
+                concat!("size of ", stringify!($ty), " differs from ", stringify!($abi))
+            );
+            assert!(
+                ::core::mem::align_of::<$ty>() == ::core::mem::align_of::<Abi>(),
+                concat!("alignment of ", stringify!($ty), " differs from ", stringify!($abi))
+            );
+            $(
+                assert!(
+                    ::core::mem::offset_of!($ty, $field) == ::core::mem::offset_of!(Abi, $field),
+                    concat!("offset of ", stringify!($ty), ".", stringify!($field), " differs from ", stringify!($abi))
+                );
+            )*
+        };
+    };
+}
+
+/// Terminal window size (`TIOCGWINSZ`, `TIOCSWINSZ`)
+#[repr(C)]
+pub struct Winsize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
+
+/// Terminal attributes (`TCGETS`, `TCSETS`)
+#[repr(C)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_cc: [u8; 32],
+}
+
+/// `struct timeval`
+#[repr(C)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+/// `struct rusage` (`SYS_GETRUSAGE`), as on x86_64 Linux
+#[repr(C)]
+pub struct Rusage {
+    pub ru_utime: Timeval,
+    pub ru_stime: Timeval,
+    pub ru_maxrss: i64,
+    pub ru_ixrss: i64,
+    pub ru_idrss: i64,
+    pub ru_isrss: i64,
+    pub ru_minflt: i64,
+    pub ru_majflt: i64,
+    pub ru_nswap: i64,
+    pub ru_inblock: i64,
+    pub ru_oublock: i64,
+    pub ru_msgsnd: i64,
+    pub ru_msgrcv: i64,
+    pub ru_nsignals: i64,
+    pub ru_nvcsw: i64,
+    pub ru_nivcsw: i64,
+}
+
+/// `struct stat` (`SYS_FSTAT`)
+#[repr(C)]
+pub struct Stat {
+    pub st_dev: u64,
+    pub st_ino: u64,
+    pub st_mode: u32,
+    pub st_nlink: u32,
+    pub st_uid: u32,
+    pub st_gid: u32,
+    pub st_rdev: u64,
+    pub st_size: i64,
+    pub st_blksize: i64,
+    pub st_blocks: i64,
+    pub st_atime: i64,
+    pub st_mtime: i64,
+    pub st_ctime: i64,
+}
+
+/// Fixed part of a `SYS_GETDENTS` record; the NUL-terminated name follows
+#[repr(C)]
+pub struct Dirent {
+    pub d_ino: u64,
+    pub d_off: i64,
+    pub d_reclen: u16,
+    pub d_type: u8,
+}
+
+/// Header of a kernel event message (`SYS_EVENT_SUBSCRIBE`)
+#[repr(C)]
+pub struct EventHeader {
+    pub kind: u32,
+    pub len: u32,
+    pub time_ms: u64,
+}
+
+/// Payload of the event message `SYS_TASK_WATCH` sends when a task exits
+#[repr(C)]
+pub struct TaskExit {
+    /// The task that exited
+    pub task: u64,
+    /// Its process
+    pub pid: u64,
+    /// Exit code (128 + signal number if it was killed)
+    pub code: u64,
+}
+
+/// Ports and notification bits to wait for (`SYS_IPC_POLL`)
+#[repr(C)]
+pub struct IpcWaitSet {
+    /// One bit per port
+    pub ports: [u64; 4],
+    pub notify: u64,
+}
+
+/// Entry of the `SYS_POLL` array
+#[repr(C)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: u16,
+    pub revents: u16,
+}
+
+/// Arguments of `SYS_THREAD_CREATE`
+#[repr(C)]
+pub struct ThreadParams {
+    pub entry: u64,
+    pub arg: u64,
+    pub stack_top: u64,
+    pub tls: u64,
+    /// Join word, cleared and woken when the thread exits
+    pub tid_ptr: u64,
+}
+
+/// Cap of a CPU bandwidth group (`SYS_CPU_QUOTA`)
+#[repr(C)]
+pub struct CpuQuota {
+    /// CPU time allowed per period; 0 for no cap
+    pub quota_us: u64,
+    pub period_us: u64,
+}
+
+/// Cap and counters of a CPU bandwidth group (`SYS_CPU_QUOTA`)
+#[repr(C)]
+pub struct CpuGroupStats {
+    pub quota_us: u64,
+    pub period_us: u64,
+    /// Periods elapsed while capped
+    pub periods: u64,
+    /// Periods in which the quota ran out
+    pub throttled_periods: u64,
+    pub throttled_us: u64,
+    /// CPU time used by the group's tasks
+    pub usage_us: u64,
+}
+
+/// Scheduler counters of a task or a priority level (`SYS_SCHEDSTAT`)
+#[repr(C)]
+pub struct SchedStat {
+    /// Tasks counted (1 for a task)
+    pub tasks: u64,
+    /// CPU time, in timer ticks
+    pub cpu_ticks: u64,
+    /// Times picked to run
+    pub runs: u64,
+    /// Time spent ready in a runqueue before being picked, in nanoseconds
+    pub wait_ns: u64,
+    /// Longest single wait
+    pub max_wait_ns: u64,
+    /// Times switched out while still runnable, without yielding
+    pub preemptions: u64,
+}
+
+/// A task as `SYS_TASK_INFO` and `SYS_TASK_LIST` report it
+#[repr(C)]
+pub struct TaskInfo {
+    /// Task ID (thread ID)
+    pub id: u64,
+    /// Process the task belongs to
+    pub pid: u64,
+    /// Parent process
+    pub ppid: u64,
+    /// CPU time, in timer ticks
+    pub cpu_ticks: u64,
+    /// One of the `TASK_*` states
+    pub state: u32,
+    /// 0 low, 1 normal, 2 high
+    pub priority: u32,
+    /// Name, NUL-padded; cut to 31 bytes
+    pub name: [u8; 32],
+}
+
+/// [`TaskInfo::state`]: waiting in a runqueue
+pub const TASK_READY: u32 = 0;
+/// [`TaskInfo::state`]: running on a CPU
+pub const TASK_RUNNING: u32 = 1;
+/// [`TaskInfo::state`]: sleeping until a deadline
+pub const TASK_SLEEPING: u32 = 2;
+/// [`TaskInfo::state`]: blocked on IPC or a lock
+pub const TASK_BLOCKED: u32 = 3;
+/// [`TaskInfo::state`]: exited, not reaped yet
+pub const TASK_EXITED: u32 = 4;
+
+/// Syscall filter installed with `SYS_SECCOMP`
+///
+/// `mode` is 0 to allow only the listed syscalls, 1 to deny them; `action`
+/// is 0 to fail a filtered call with EPERM, 1 to kill the task. Bit
+/// `n % 64` of `syscalls[n / 64]` lists syscall `n`.
+#[repr(C)]
+pub struct SeccompFilter {
+    pub mode: u32,
+    pub action: u32,
+    pub syscalls: [u64; 2],
+}
+
+/// Screen mode of `/dev/fb0` (`FBIOGET_MODE`)
+#[repr(C)]
+pub struct FbMode {
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per line
+    pub pitch: u32,
+    pub bpp: u32,
+    /// Bytes of screen memory
+    pub size: u64,
+}
+
+/// Event read from `/dev/input/eventN`
+#[repr(C)]
+pub struct InputEvent {
+    /// Microseconds since boot
+    pub time_us: u64,
+    /// Event type (`EV_*`), code and value as in Linux's evdev
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// Echo request to send with `SYS_PING`
+#[repr(C)]
+pub struct PingRequest {
+    /// Destination: an IPv4 address in the first 4 bytes, or an IPv6
+    /// address
+    pub addr: [u8; 16],
+    /// 4 or 6
+    pub family: u16,
+    pub seq: u16,
+    /// How long to wait for the reply
+    pub timeout_ms: u32,
+}
+
+/// IPv4 socket address, Linux's `struct sockaddr_in` (`SYS_BIND`,
+/// `SYS_SENDTO`, `SYS_RECVFROM`)
+#[repr(C)]
+pub struct SockaddrIn {
+    /// `AF_INET`
+    pub sin_family: u16,
+    /// Port in network byte order
+    pub sin_port: u16,
+    pub sin_addr: [u8; 4],
+    pub sin_zero: [u8; 8],
+}
+
+/// IPv6 socket address, Linux's `struct sockaddr_in6`
+#[repr(C)]
+pub struct SockaddrIn6 {
+    /// `AF_INET6`
+    pub sin6_family: u16,
+    /// Port in network byte order
+    pub sin6_port: u16,
+    pub sin6_flowinfo: u32,
+    pub sin6_addr: [u8; 16],
+    pub sin6_scope_id: u32,
+}
+
+/// User address of the vDSO: the [`VdsoData`] page, then the code page
+pub const VDSO_BASE: u64 = 0x0000_7FFF_FFFF_0000;
+
+/// User address of the vDSO clock, `extern "C" fn() -> u64`
+///
+/// Returns the same nanoseconds since boot as `SYS_CLOCK_GETTIME`,
+/// without entering the kernel.
+pub const VDSO_CLOCK: u64 = VDSO_BASE + 0x1000;
+
+/// Clock parameters at [`VDSO_BASE`], updated by the timer interrupt
+///
+/// The time is `base_ns`, plus `((tsc - base_tsc) * mult) >> shift` if
+/// `tsc_valid` is set. `seq` is odd while the kernel is updating the page;
+/// a reader retries until it sees the same even value before and after
+/// reading the rest.
+#[repr(C)]
+pub struct VdsoData {
+    pub seq: u32,
+    /// Nonzero if the TSC is the clock source
+    pub tsc_valid: u32,
+    /// Nanoseconds since boot at the last update
+    pub base_ns: u64,
+    /// TSC at the last update
+    pub base_tsc: u64,
+    pub mult: u32,
+    pub shift: u32,
+}
@@ -1,15 +1,44 @@
 use crate::config::MAX_CPUS;
 /// ACPI (Advanced Configuration and Power Interface) support
 /// This module provides ACPI table parsing, specifically the MADT
-/// (Multiple APIC Description Table) for CPU and APIC discovery.
+/// (Multiple APIC Description Table) for CPU and APIC discovery, and the
+/// PCI interrupt routing tables in the DSDT (see `prt`), and the fixed
+/// power button and soft-off (see `power`).
+use crate::arch::x86_64::{apic, hpet};
+use crate::mm::addr::{add_mmio_window, PhysAddr};
 use crate::{serial_print, serial_println};
 use core::slice;
 use core::sync::atomic::{AtomicBool, Ordering};
 
+pub mod power;
+pub mod prt;
+
 /// Global MADT information
 static mut MADT_INFO: Option<MadtInfo> = None;
 static MADT_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
+/// Legacy hardware and timers reported by the FADT and HPET tables
+static PLATFORM_INFO: spin::Once<PlatformInfo> = spin::Once::new();
+
+/// Offset of IAPC_BOOT_ARCH in the FADT (ACPI 2.0+)
+const FADT_IAPC_BOOT_ARCH: usize = 109;
+
+/// Offsets of the DSDT pointers in the FADT
+const FADT_DSDT: usize = 40;
+const FADT_X_DSDT: usize = 140;
+
+/// Size of the common ACPI table header
+const SDT_HEADER_LEN: usize = 36;
+
+/// IAPC_BOOT_ARCH flags
+const BOOT_ARCH_LEGACY_DEVICES: u16 = 1 << 0;
+const BOOT_ARCH_8042: u16 = 1 << 1;
+const BOOT_ARCH_CMOS_RTC_NOT_PRESENT: u16 = 1 << 5;
+
+/// Offset of the base address (the address field of a Generic Address
+/// Structure) in the HPET table
+const HPET_BASE_ADDRESS: usize = 44;
+
 /// RSDP (Root System Description Pointer) structure
 /// This is the first ACPI structure we need to find
 #[repr(C, packed)]
@@ -81,6 +110,16 @@ struct MadtIoApic {
     gsi_base: u32,
 }
 
+/// MADT Entry Type 2: Interrupt Source Override
+#[repr(C, packed)]
+struct MadtInterruptOverride {
+    header: MadtEntryHeader,
+    bus: u8,
+    source: u8,
+    gsi: u32,
+    flags: u16,
+}
+
 /// CPU information extracted from MADT
 #[derive(Debug, Clone, Copy)]
 pub struct CpuInfo {
@@ -97,6 +136,17 @@ pub struct IoApicInfo {
     pub gsi_base: u32,
 }
 
+/// ISA interrupt the firmware wired to another GSI, or with another
+/// trigger mode or polarity than ISA's edge-triggered, active-high
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    /// ISA IRQ number
+    pub source: u8,
+    pub gsi: u32,
+    /// MPS INTI flags: polarity in bits 0-1, trigger mode in bits 2-3
+    pub flags: u16,
+}
+
 /// Parsed MADT information
 pub struct MadtInfo {
     pub lapic_address: u64,
@@ -104,6 +154,45 @@ pub struct MadtInfo {
     pub cpu_count: usize,
     pub ioapics: [Option<IoApicInfo>; 8], // Support up to 8 I/O APICs
     pub ioapic_count: usize,
+    pub overrides: [Option<InterruptOverride>; 16],
+    pub override_count: usize,
+}
+
+/// Legacy hardware and timers the firmware describes
+///
+/// UEFI machines often have no ISA devices: no PS/2 controller, no CMOS
+/// RTC, and a PIT that is missing or clock gated.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlatformInfo {
+    /// OEM ID from the RSDP
+    pub oem_id: [u8; 6],
+    /// RSDP revision: 0 for ACPI 1.0, 2 for ACPI 2.0 and later
+    pub revision: u8,
+    /// IAPC_BOOT_ARCH flags of the FADT; None for ACPI 1.0 or no FADT
+    pub boot_arch: Option<u16>,
+    /// Physical address of the HPET registers, from the HPET table
+    pub hpet_address: Option<u64>,
+}
+
+impl PlatformInfo {
+    /// Whether the machine has ISA/LPC devices (serial, parallel...)
+    ///
+    /// Unknown counts as present.
+    pub fn has_legacy_devices(&self) -> bool {
+        self.boot_arch.map_or(true, |flags| flags & BOOT_ARCH_LEGACY_DEVICES != 0)
+    }
+
+    /// Whether there is an 8042 (PS/2 keyboard and mouse) controller
+    ///
+    /// Unknown counts as present.
+    pub fn has_8042(&self) -> bool {
+        self.boot_arch.map_or(true, |flags| flags & BOOT_ARCH_8042 != 0)
+    }
+
+    /// Whether there is a CMOS RTC at ports 0x70/0x71
+    pub fn has_cmos_rtc(&self) -> bool {
+        self.boot_arch.map_or(true, |flags| flags & BOOT_ARCH_CMOS_RTC_NOT_PRESENT == 0)
+    }
 }
 
 /// ACPI parsing errors
@@ -113,6 +202,8 @@ pub enum AcpiError {
     InvalidChecksum,
     MadtNotFound,
     InvalidMadt,
+    TableNotFound,
+    InvalidFadt,
 }
 
 /// Validate ACPI table checksum
@@ -163,6 +254,25 @@ pub fn init_acpi(rsdp_addr: u64) -> Result<(), AcpiError> {
     }
     MADT_INITIALIZED.store(true, Ordering::Release);
 
+    let platform = PLATFORM_INFO.call_once(|| parse_platform(rsdp_addr));
+    add_mmio_windows(platform);
+    serial_println!(
+        "[ACPI] Legacy devices: {}, 8042: {}, CMOS RTC: {}, HPET: {}",
+        platform.has_legacy_devices(),
+        platform.has_8042(),
+        platform.has_cmos_rtc(),
+        platform.hpet_address.is_some()
+    );
+
+    // PCI interrupt routing is optional: without it drivers fall back to MSI
+    if let Err(e) = prt::init(rsdp_addr) {
+        serial_println!("[ACPI] No PCI interrupt routing table: {:?}", e);
+    }
+
+    if let Err(e) = power::parse(rsdp_addr) {
+        serial_println!("[ACPI] No power management registers: {:?}", e);
+    }
+
     Ok(())
 }
 
@@ -176,6 +286,86 @@ pub fn get_madt_info() -> Option<&'static MadtInfo> {
     }
 }
 
+/// Legacy hardware and timers, once ACPI is initialized
+pub fn platform_info() -> Option<&'static PlatformInfo> {
+    PLATFORM_INFO.get()
+}
+
+/// The DSDT's AML byte code, after the table header
+///
+/// The FADT's 64-bit pointer wins over the 32-bit one when it is set.
+fn dsdt_aml(rsdp_addr: u64) -> Result<&'static [u8], AcpiError> {
+    let fadt_addr = find_table(rsdp_addr, b"FACP")?;
+    let fadt_len = unsafe { core::ptr::read_unaligned((fadt_addr + 4) as *const u32) } as usize;
+
+    let mut dsdt_addr =
+        unsafe { core::ptr::read_unaligned((fadt_addr as usize + FADT_DSDT) as *const u32) } as u64;
+    if fadt_len >= FADT_X_DSDT + 8 {
+        let x_dsdt =
+            unsafe { core::ptr::read_unaligned((fadt_addr as usize + FADT_X_DSDT) as *const u64) };
+        if x_dsdt != 0 {
+            dsdt_addr = x_dsdt;
+        }
+    }
+    if dsdt_addr == 0 {
+        return Err(AcpiError::TableNotFound);
+    }
+
+    let dsdt_len = unsafe { core::ptr::read_unaligned((dsdt_addr + 4) as *const u32) } as usize;
+    let dsdt = unsafe { slice::from_raw_parts(dsdt_addr as *const u8, dsdt_len) };
+    if &dsdt[..4] != b"DSDT" || !validate_checksum(dsdt) {
+        serial_println!("[ACPI] Invalid DSDT at 0x{:x}", dsdt_addr);
+        return Err(AcpiError::InvalidChecksum);
+    }
+    Ok(&dsdt[SDT_HEADER_LEN..])
+}
+
+/// Note the register blocks of the APICs and the HPET for the MMIO checks
+fn add_mmio_windows(platform: &PlatformInfo) {
+    let Some(madt) = get_madt_info() else { return };
+    add_mmio_window("lapic", PhysAddr::new(madt.lapic_address), apic::LAPIC_MMIO_SIZE as u64);
+    for ioapic in madt.ioapics[..madt.ioapic_count].iter().flatten() {
+        add_mmio_window("ioapic", PhysAddr::new(ioapic.address as u64), apic::ioapic::IOAPIC_MMIO_SIZE as u64);
+    }
+    if let Some(address) = platform.hpet_address {
+        add_mmio_window("hpet", PhysAddr::new(address), hpet::HPET_MMIO_SIZE as u64);
+    }
+}
+
+/// Read the RSDP's OEM ID and revision, the FADT boot architecture flags
+/// and the HPET table
+///
+/// The FADT and HPET are optional; whatever is missing or invalid is left as None.
+fn parse_platform(rsdp_addr: u64) -> PlatformInfo {
+    let boot_arch = find_table(rsdp_addr, b"FACP").ok().and_then(|fadt| {
+        let header = unsafe { &*(fadt as *const SdtHeader) };
+        let length = header.length as usize;
+        // The field was added in FADT revision 3 (ACPI 2.0)
+        if header.revision < 3 || length < FADT_IAPC_BOOT_ARCH + 2 {
+            return None;
+        }
+        Some(unsafe { core::ptr::read_unaligned((fadt as usize + FADT_IAPC_BOOT_ARCH) as *const u16) })
+    });
+
+    let hpet_address = find_table(rsdp_addr, b"HPET").ok().and_then(|hpet| {
+        let header = unsafe { &*(hpet as *const SdtHeader) };
+        let bytes = unsafe { slice::from_raw_parts(hpet as *const u8, header.length as usize) };
+        if (header.length as usize) < HPET_BASE_ADDRESS + 8 || !validate_checksum(bytes) {
+            return None;
+        }
+        let address = unsafe { core::ptr::read_unaligned((hpet as usize + HPET_BASE_ADDRESS) as *const u64) };
+        (address != 0).then_some(address)
+    });
+
+    let rsdp = unsafe { &*(rsdp_addr as *const Rsdp) };
+    PlatformInfo {
+        oem_id: rsdp.oem_id,
+        revision: rsdp.revision,
+        boot_arch,
+        hpet_address,
+    }
+}
+
 /// Parse MADT table and extract CPU and APIC information
 ///
 /// # Arguments
@@ -205,15 +395,10 @@ fn parse_madt(rsdp_addr: u64) -> Result<MadtInfo, AcpiError> {
 
     serial_println!("[ACPI] RSDP validated, revision: {}", rsdp.revision);
 
-    // Determine which table to use (RSDT or XSDT)
-    let madt_addr = if rsdp.revision >= 2 {
-        // ACPI 2.0+: Use XSDT
-        let rsdp_ext = unsafe { &*(rsdp_addr as *const RsdpExtended) };
-        find_madt_in_xsdt(rsdp_ext.xsdt_address)?
-    } else {
-        // ACPI 1.0: Use RSDT
-        find_madt_in_rsdt(rsdp.rsdt_address as u64)?
-    };
+    let madt_addr = find_table(rsdp_addr, b"APIC").map_err(|e| match e {
+        AcpiError::TableNotFound => AcpiError::MadtNotFound,
+        e => e,
+    })?;
 
     serial_println!("[ACPI] MADT found at 0x{:x}", madt_addr);
 
@@ -221,8 +406,24 @@ fn parse_madt(rsdp_addr: u64) -> Result<MadtInfo, AcpiError> {
     parse_madt_table(madt_addr)
 }
 
-/// Find MADT in RSDT (ACPI 1.0)
-fn find_madt_in_rsdt(rsdt_addr: u64) -> Result<u64, AcpiError> {
+/// Find an ACPI table by signature
+///
+/// Uses the XSDT on ACPI 2.0+ and the RSDT otherwise. The RSDP must
+/// already have been validated.
+fn find_table(rsdp_addr: u64, signature: &[u8; 4]) -> Result<u64, AcpiError> {
+    let rsdp = unsafe { &*(rsdp_addr as *const Rsdp) };
+    if rsdp.revision >= 2 {
+        // ACPI 2.0+: Use XSDT
+        let rsdp_ext = unsafe { &*(rsdp_addr as *const RsdpExtended) };
+        find_table_in_xsdt(rsdp_ext.xsdt_address, signature)
+    } else {
+        // ACPI 1.0: Use RSDT
+        find_table_in_rsdt(rsdp.rsdt_address as u64, signature)
+    }
+}
+
+/// Find a table in the RSDT (ACPI 1.0)
+fn find_table_in_rsdt(rsdt_addr: u64, signature: &[u8; 4]) -> Result<u64, AcpiError> {
     let header = unsafe { &*(rsdt_addr as *const SdtHeader) };
 
     // Validate RSDT signature
@@ -248,20 +449,22 @@ fn find_madt_in_rsdt(rsdt_addr: u64) -> Result<u64, AcpiError> {
     let entries_ptr = unsafe { (rsdt_addr as *const u8).add(entries_offset) as *const u32 };
     let entries = unsafe { slice::from_raw_parts(entries_ptr, entry_count) };
 
-    // Search for MADT
     for &entry_addr in entries {
         let entry_header = unsafe { &*(entry_addr as u64 as *const SdtHeader) };
-        if &entry_header.signature == b"APIC" {
+        if &entry_header.signature == signature {
             return Ok(entry_addr as u64);
         }
     }
 
-    serial_println!("[ACPI] MADT not found in RSDT");
-    Err(AcpiError::MadtNotFound)
+    serial_println!(
+        "[ACPI] {} not found in RSDT",
+        core::str::from_utf8(signature).unwrap_or("????")
+    );
+    Err(AcpiError::TableNotFound)
 }
 
-/// Find MADT in XSDT (ACPI 2.0+)
-fn find_madt_in_xsdt(xsdt_addr: u64) -> Result<u64, AcpiError> {
+/// Find a table in the XSDT (ACPI 2.0+)
+fn find_table_in_xsdt(xsdt_addr: u64, signature: &[u8; 4]) -> Result<u64, AcpiError> {
     let header = unsafe { &*(xsdt_addr as *const SdtHeader) };
 
     // Validate XSDT signature
@@ -287,16 +490,18 @@ fn find_madt_in_xsdt(xsdt_addr: u64) -> Result<u64, AcpiError> {
     let entries_ptr = unsafe { (xsdt_addr as *const u8).add(entries_offset) as *const u64 };
     let entries = unsafe { slice::from_raw_parts(entries_ptr, entry_count) };
 
-    // Search for MADT
     for &entry_addr in entries {
         let entry_header = unsafe { &*(entry_addr as *const SdtHeader) };
-        if &entry_header.signature == b"APIC" {
+        if &entry_header.signature == signature {
             return Ok(entry_addr);
         }
     }
 
-    serial_println!("[ACPI] MADT not found in XSDT");
-    Err(AcpiError::MadtNotFound)
+    serial_println!(
+        "[ACPI] {} not found in XSDT",
+        core::str::from_utf8(signature).unwrap_or("????")
+    );
+    Err(AcpiError::TableNotFound)
 }
 
 /// Parse MADT table and extract CPU and APIC information
@@ -324,6 +529,8 @@ fn parse_madt_table(madt_addr: u64) -> Result<MadtInfo, AcpiError> {
     let mut cpu_count = 0;
     let mut ioapics: [Option<IoApicInfo>; 8] = [None; 8];
     let mut ioapic_count = 0;
+    let mut overrides: [Option<InterruptOverride>; 16] = [None; 16];
+    let mut override_count = 0;
 
     // Parse MADT entries
     let entries_offset = core::mem::size_of::<Madt>();
@@ -399,6 +606,28 @@ fn parse_madt_table(madt_addr: u64) -> Result<MadtInfo, AcpiError> {
                     );
                 }
             }
+            2 => {
+                // Interrupt Source Override
+                let override_ptr = entry_ptr as *const MadtInterruptOverride;
+
+                let bus = unsafe { core::ptr::addr_of!((*override_ptr).bus).read() };
+                let source = unsafe { core::ptr::addr_of!((*override_ptr).source).read() };
+                let gsi = unsafe { core::ptr::addr_of!((*override_ptr).gsi).read_unaligned() };
+                let flags = unsafe { core::ptr::addr_of!((*override_ptr).flags).read_unaligned() };
+
+                // Bus 0 is ISA, the only bus overrides are defined for
+                if bus == 0 && override_count < overrides.len() {
+                    overrides[override_count] = Some(InterruptOverride { source, gsi, flags });
+                    override_count += 1;
+
+                    serial_println!(
+                        "[ACPI] Interrupt override: IRQ {} -> GSI {}, flags 0x{:x}",
+                        source,
+                        gsi,
+                        flags
+                    );
+                }
+            }
             _ => {
                 // Other entry types (ignored for now)
                 serial_println!(
@@ -417,5 +646,7 @@ fn parse_madt_table(madt_addr: u64) -> Result<MadtInfo, AcpiError> {
         cpu_count,
         ioapics,
         ioapic_count,
+        overrides,
+        override_count,
     })
 }
@@ -0,0 +1,362 @@
+//! ACPI fixed power button and soft-off
+//!
+//! There is no AML interpreter, so only the fixed-hardware power button is
+//! handled: the one signalled through the FADT's PM1 event registers, as
+//! opposed to a control-method button device in the DSDT (FADT flag
+//! `PWR_BUTTON` set). [`init`] switches the chipset to ACPI mode if the
+//! firmware left it in legacy mode, turns off every general-purpose event
+//! (nothing here could handle one), routes the SCI (System Control
+//! Interrupt) to a driver IRQ line and enables the power button event
+//! alone.
+//!
+//! The SCI handler acknowledges the press and defers the rest to the
+//! system work queue, which broadcasts an `EventKind::PowerButton` event.
+//! init subscribes to it, stops the services and calls `SYS_POWEROFF`. If
+//! no port takes the event, the kernel writes the settings out and powers
+//! off by itself.
+//!
+//! [`poweroff`] enters S5 through the PM1 control registers, with the
+//! sleep type from the DSDT's `_S5_` package: the one piece of AML read
+//! here. Only the legacy I/O port register blocks are used, which every
+//! PC chipset provides.
+
+use super::prt::{integer, package, AML_NAME_OP, AML_PACKAGE_OP};
+use super::AcpiError;
+use crate::dev::api::{self, irq, DriverInfo};
+use crate::sched::workqueue::{self, Work};
+use crate::sys::event::{self, EventKind};
+use x86_64::instructions::port::Port;
+
+/// FADT field offsets
+const FADT_SCI_INT: usize = 46;
+const FADT_SMI_CMD: usize = 48;
+const FADT_ACPI_ENABLE: usize = 52;
+const FADT_PM1A_EVT_BLK: usize = 56;
+const FADT_PM1B_EVT_BLK: usize = 60;
+const FADT_PM1A_CNT_BLK: usize = 64;
+const FADT_PM1B_CNT_BLK: usize = 68;
+const FADT_GPE0_BLK: usize = 80;
+const FADT_GPE1_BLK: usize = 84;
+const FADT_PM1_EVT_LEN: usize = 88;
+const FADT_GPE0_BLK_LEN: usize = 92;
+const FADT_GPE1_BLK_LEN: usize = 93;
+const FADT_FLAGS: usize = 112;
+
+/// Length of an ACPI 1.0 FADT, which has all of the fields above
+const FADT_MIN_LEN: usize = 116;
+
+/// FADT flag: the power button is a control-method device
+const FLAG_PWR_BUTTON: u32 = 1 << 4;
+
+/// AML root prefix, as in `Name (\_S5, ...)`
+const AML_ROOT_CHAR: u8 = b'\\';
+
+/// PM1 status and enable registers: power button bit
+const PM1_PWRBTN: u16 = 1 << 8;
+
+/// PM1 control register bits
+const PM1_SCI_EN: u16 = 1 << 0;
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_TYP_MASK: u16 = 0b111 << PM1_SLP_TYP_SHIFT;
+const PM1_SLP_EN: u16 = 1 << 13;
+
+/// PM1 control reads while waiting for the firmware to enter ACPI mode,
+/// or for the machine to go off (about a microsecond each)
+const MAX_POLLS: usize = 300_000;
+
+/// Power management registers from the FADT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PmRegisters {
+    /// ISA interrupt the SCI is wired to
+    sci_irq: u8,
+    /// Port taking `acpi_enable` to switch to ACPI mode (0: always in it)
+    smi_cmd: u16,
+    acpi_enable: u8,
+    /// PM1 event blocks: status registers, then enable registers
+    pm1a_evt: u16,
+    pm1b_evt: u16,
+    pm1_evt_len: u8,
+    pm1a_cnt: u16,
+    pm1b_cnt: u16,
+    /// General-purpose event blocks: status bytes, then enable bytes
+    gpe0: u16,
+    gpe0_len: u8,
+    gpe1: u16,
+    gpe1_len: u8,
+    /// The power button is fixed hardware
+    fixed_button: bool,
+    /// SLP_TYPa and SLP_TYPb for S5, from the DSDT
+    s5: Option<(u8, u8)>,
+}
+
+/// Why the machine was not powered off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerError {
+    /// No usable FADT
+    NoRegisters,
+    /// The DSDT has no `_S5_` sleep type
+    NoSleepType,
+    /// The sleep command was written and the machine stayed on
+    StillRunning,
+}
+
+static REGISTERS: spin::Once<PmRegisters> = spin::Once::new();
+
+static DRIVER: DriverInfo = crate::driver_info!("acpi-sci");
+
+/// Runs the power button press outside the SCI handler
+static PRESSED: Work = Work::new(power_button_pressed);
+
+impl PmRegisters {
+    /// Read the registers from the FADT in `fadt`
+    ///
+    /// Register blocks above the I/O port range read as absent.
+    fn from_fadt(fadt: &[u8]) -> Option<Self> {
+        if fadt.len() < FADT_MIN_LEN {
+            return None;
+        }
+        let u8_at = |offset: usize| fadt[offset];
+        let u32_at = |offset: usize| u32::from_le_bytes(fadt[offset..offset + 4].try_into().unwrap());
+        let port_at = |offset: usize| u16::try_from(u32_at(offset)).unwrap_or(0);
+
+        Some(PmRegisters {
+            sci_irq: u8::try_from(u16::from_le_bytes([fadt[FADT_SCI_INT], fadt[FADT_SCI_INT + 1]])).ok()?,
+            smi_cmd: port_at(FADT_SMI_CMD),
+            acpi_enable: u8_at(FADT_ACPI_ENABLE),
+            pm1a_evt: port_at(FADT_PM1A_EVT_BLK),
+            pm1b_evt: port_at(FADT_PM1B_EVT_BLK),
+            pm1_evt_len: u8_at(FADT_PM1_EVT_LEN),
+            pm1a_cnt: port_at(FADT_PM1A_CNT_BLK),
+            pm1b_cnt: port_at(FADT_PM1B_CNT_BLK),
+            gpe0: port_at(FADT_GPE0_BLK),
+            gpe0_len: u8_at(FADT_GPE0_BLK_LEN),
+            gpe1: port_at(FADT_GPE1_BLK),
+            gpe1_len: u8_at(FADT_GPE1_BLK_LEN),
+            fixed_button: u32_at(FADT_FLAGS) & FLAG_PWR_BUTTON == 0,
+            s5: None,
+        })
+    }
+
+    /// Read the PM1 event register at `offset` in both blocks, combined
+    fn read_pm1_evt(&self, offset: u16) -> u16 {
+        [self.pm1a_evt, self.pm1b_evt]
+            .iter()
+            .filter(|&&block| block != 0)
+            .fold(0, |value, &block| value | unsafe { Port::<u16>::new(block + offset).read() })
+    }
+
+    /// Write the PM1 event register at `offset` in both blocks
+    fn write_pm1_evt(&self, offset: u16, value: u16) {
+        for &block in [self.pm1a_evt, self.pm1b_evt].iter().filter(|&&block| block != 0) {
+            unsafe { Port::<u16>::new(block + offset).write(value) };
+        }
+    }
+
+    /// Offset of the PM1 enable register in an event block
+    fn pm1_enable(&self) -> u16 {
+        self.pm1_evt_len as u16 / 2
+    }
+
+    fn read_pm1_cnt(&self) -> u16 {
+        unsafe { Port::<u16>::new(self.pm1a_cnt).read() }
+    }
+
+    /// Switch to ACPI mode unless the firmware already did
+    ///
+    /// Returns whether SCI_EN is set now.
+    fn enable_acpi_mode(&self) -> bool {
+        if self.read_pm1_cnt() & PM1_SCI_EN != 0 {
+            return true;
+        }
+        if self.smi_cmd == 0 || self.acpi_enable == 0 {
+            return false;
+        }
+        unsafe { Port::<u8>::new(self.smi_cmd).write(self.acpi_enable) };
+        (0..MAX_POLLS).any(|_| self.read_pm1_cnt() & PM1_SCI_EN != 0)
+    }
+
+    /// Disable and acknowledge every general-purpose event
+    fn disable_gpes(&self) {
+        for (block, len) in [(self.gpe0, self.gpe0_len), (self.gpe1, self.gpe1_len)] {
+            if block == 0 {
+                continue;
+            }
+            let half = len as u16 / 2;
+            for i in 0..half {
+                unsafe {
+                    Port::<u8>::new(block + half + i).write(0);
+                    Port::<u8>::new(block + i).write(0xFF);
+                }
+            }
+        }
+    }
+}
+
+/// SLP_TYPa and SLP_TYPb from the `_S5_` package in `aml`
+///
+/// Firmware declares it as `Name (_S5, Package () { SLP_TYPa, SLP_TYPb,
+/// ... })`, sometimes with a root prefix; a package with one element uses
+/// it for both.
+fn s5_sleep_type(aml: &[u8]) -> Option<(u8, u8)> {
+    (1..aml.len().saturating_sub(4))
+        .filter(|&i| {
+            &aml[i..i + 4] == b"_S5_"
+                && aml[i + 4] == AML_PACKAGE_OP
+                && (aml[i - 1] == AML_NAME_OP
+                    || (aml[i - 1] == AML_ROOT_CHAR && i >= 2 && aml[i - 2] == AML_NAME_OP))
+        })
+        .find_map(|i| {
+            let (count, body, _) = package(&aml[i + 4..])?;
+            let (typ_a, used) = integer(body)?;
+            let typ_b = if count > 1 { integer(&body[used..])?.0 } else { typ_a };
+            Some(((typ_a & 0b111) as u8, (typ_b & 0b111) as u8))
+        })
+}
+
+/// Read the power management registers from the FADT and the S5 sleep
+/// type from the DSDT
+pub fn parse(rsdp_addr: u64) -> Result<(), AcpiError> {
+    let fadt_addr = super::find_table(rsdp_addr, b"FACP")?;
+    let fadt_len = unsafe { core::ptr::read_unaligned((fadt_addr + 4) as *const u32) } as usize;
+    let fadt = unsafe { core::slice::from_raw_parts(fadt_addr as *const u8, fadt_len) };
+
+    let mut registers = PmRegisters::from_fadt(fadt).ok_or(AcpiError::InvalidFadt)?;
+    if registers.pm1a_evt == 0 || registers.pm1a_cnt == 0 {
+        return Err(AcpiError::InvalidFadt);
+    }
+    registers.s5 = super::dsdt_aml(rsdp_addr).ok().and_then(s5_sleep_type);
+    crate::serial_println!(
+        "[ACPI] PM1 events at 0x{:x}, control at 0x{:x}, SCI on IRQ {}, S5 sleep type {:?}",
+        registers.pm1a_evt,
+        registers.pm1a_cnt,
+        registers.sci_irq,
+        registers.s5
+    );
+    REGISTERS.call_once(|| registers);
+    Ok(())
+}
+
+/// Enter ACPI mode and deliver power button presses through the SCI
+///
+/// Needs the driver IRQ lines and the system work queue.
+pub fn init() {
+    let Some(registers) = REGISTERS.get() else { return };
+    if !registers.fixed_button {
+        crate::serial_println!("[ACPI] Power button is a control-method device, ignoring it");
+        return;
+    }
+    if !registers.enable_acpi_mode() {
+        crate::log_warn!("ACPI", "Firmware did not switch to ACPI mode, no power button");
+        return;
+    }
+    registers.disable_gpes();
+
+    let routed = api::register_driver(&DRIVER).and_then(|driver| {
+        let line = irq::request_irq(driver, sci_interrupt)?;
+        irq::route_sci(driver, line, registers.sci_irq).inspect_err(|_| {
+            let _ = irq::free_irq(driver, line);
+        })
+    });
+    if let Err(e) = routed {
+        crate::log_warn!("ACPI", "No SCI, power button ignored: {:?}", e);
+        return;
+    }
+
+    // Drop whatever happened before, then take power button presses alone
+    registers.write_pm1_evt(0, 0xFFFF);
+    registers.write_pm1_evt(registers.pm1_enable(), PM1_PWRBTN);
+    crate::serial_println!("[ACPI] Power button enabled");
+}
+crate::initcall!(Drivers, init);
+
+/// SCI: acknowledge a power button press and queue its handling
+fn sci_interrupt(_line: u8) {
+    let Some(registers) = REGISTERS.get() else { return };
+    if registers.read_pm1_evt(0) & PM1_PWRBTN != 0 {
+        registers.write_pm1_evt(0, PM1_PWRBTN);
+        workqueue::schedule(&PRESSED);
+    }
+}
+
+/// Tell the subscribers about a press, or shut down if there are none
+fn power_button_pressed() {
+    if event::broadcast(EventKind::PowerButton, &[]) > 0 {
+        crate::log_info!("ACPI", "Power button pressed");
+        return;
+    }
+    crate::log_info!("ACPI", "Power button pressed and nobody listens, shutting down");
+    let e = shutdown();
+    crate::log_warn!("ACPI", "Power off failed: {:?}", e);
+}
+
+/// Write the settings out and power off
+///
+/// Returns only if the machine stays on, with the reason. Call from task
+/// context.
+pub fn shutdown() -> PowerError {
+    crate::settings::flush();
+    poweroff()
+}
+
+/// Power off at once, without writing anything out
+///
+/// Returns only if the machine stays on, with the reason.
+pub fn poweroff() -> PowerError {
+    let Some(registers) = REGISTERS.get() else { return PowerError::NoRegisters };
+    let Some((typ_a, typ_b)) = registers.s5 else { return PowerError::NoSleepType };
+    crate::serial_println!("[ACPI] Powering off");
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let control = registers.read_pm1_cnt() & !PM1_SLP_TYP_MASK;
+        for (block, typ) in [(registers.pm1a_cnt, typ_a), (registers.pm1b_cnt, typ_b)] {
+            if block != 0 {
+                let value = control | ((typ as u16) << PM1_SLP_TYP_SHIFT) | PM1_SLP_EN;
+                unsafe { Port::<u16>::new(block).write(value) };
+            }
+        }
+        // Give the chipset time to cut the power
+        for _ in 0..MAX_POLLS {
+            registers.read_pm1_cnt();
+        }
+    });
+    PowerError::StillRunning
+}
+
+crate::kernel_test! {
+    /// PM registers come from the FADT and the S5 sleep type from `_S5_`
+    fn acpi_power_registers() {
+        let mut fadt = [0u8; FADT_MIN_LEN];
+        fadt[FADT_SCI_INT] = 9;
+        fadt[FADT_SMI_CMD] = 0xB2;
+        fadt[FADT_ACPI_ENABLE] = 0xF1;
+        fadt[FADT_PM1A_EVT_BLK..FADT_PM1A_EVT_BLK + 2].copy_from_slice(&0x600u16.to_le_bytes());
+        fadt[FADT_PM1A_CNT_BLK..FADT_PM1A_CNT_BLK + 2].copy_from_slice(&0x604u16.to_le_bytes());
+        fadt[FADT_PM1B_CNT_BLK + 2] = 1;
+        fadt[FADT_PM1_EVT_LEN] = 4;
+
+        let registers = PmRegisters::from_fadt(&fadt);
+        crate::ktest_assert!(registers.is_some(), "FADT not parsed");
+        let registers = registers.unwrap();
+        crate::ktest_assert_eq!(registers.sci_irq, 9, "SCI interrupt");
+        crate::ktest_assert_eq!(registers.smi_cmd, 0xB2, "SMI command port");
+        crate::ktest_assert_eq!(registers.pm1a_evt, 0x600, "PM1a event block");
+        crate::ktest_assert_eq!(registers.pm1b_cnt, 0, "block past the I/O ports");
+        crate::ktest_assert_eq!(registers.pm1_enable(), 2, "PM1 enable offset");
+        crate::ktest_assert!(registers.fixed_button, "fixed button not seen");
+
+        fadt[FADT_FLAGS] = FLAG_PWR_BUTTON as u8;
+        crate::ktest_assert!(!PmRegisters::from_fadt(&fadt).unwrap().fixed_button, "control-method button");
+        crate::ktest_assert!(PmRegisters::from_fadt(&fadt[..100]).is_none(), "short FADT parsed");
+
+        // Name (_S5, Package (0x04) { 0x05, Zero, Zero, Zero })
+        const AML: [u8; 14] = [0x10, 0x08, b'_', b'S', b'5', b'_', 0x12, 0x07, 0x04, 0x0A, 0x05, 0x00, 0x00, 0x00];
+        crate::ktest_assert_eq!(s5_sleep_type(&AML), Some((5, 0)), "S5 sleep type");
+        // Name (\_S5, Package (0x01) { 0x07 })
+        const ROOTED: [u8; 10] = [0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x03, 0x01, 0x07];
+        crate::ktest_assert_eq!(s5_sleep_type(&ROOTED), None, "bare byte taken as an integer");
+        const ROOTED_BYTE: [u8; 11] = [0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x04, 0x01, 0x0A, 0x07];
+        crate::ktest_assert_eq!(s5_sleep_type(&ROOTED_BYTE), Some((7, 7)), "rooted one-element package");
+        crate::ktest_assert_eq!(s5_sleep_type(b"_S5_"), None, "name without a package");
+        Ok(())
+    }
+}
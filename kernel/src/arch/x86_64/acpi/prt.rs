@@ -0,0 +1,290 @@
+//! PCI interrupt routing from the ACPI `_PRT`
+//!
+//! A PCI device's INTx pin is wired to an interrupt input that depends on
+//! the chipset and board, described by the `_PRT` (PCI Routing Table)
+//! objects in the DSDT. There is no AML interpreter, so only the common
+//! static form is understood: a named `Package` of
+//! `Package { Address, Pin, Source, SourceIndex }` entries. Entries whose
+//! Source is `Zero` route the pin straight to global system interrupt
+//! `SourceIndex`, which is what firmware provides for APIC mode (usually
+//! as `_PRT` itself or an `ARxx` table returned by a `_PRT` method).
+//! Entries that go through an interrupt link device (`LNKA`...) need
+//! `_CRS` evaluation and are skipped.
+//!
+//! Routes are recorded for the root bus. Devices behind a PCI-to-PCI
+//! bridge are routed with the standard swizzle to the bridge's slot on the
+//! root bus, see [`swizzle`].
+
+use spin::Mutex;
+
+/// AML opcodes used by `_PRT` packages (and the `_S5_` package, see
+/// `power`)
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+pub(super) const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_WORD_PREFIX: u8 = 0x0B;
+const AML_DWORD_PREFIX: u8 = 0x0C;
+const AML_QWORD_PREFIX: u8 = 0x0E;
+pub(super) const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ONES_OP: u8 = 0xFF;
+
+/// Maximum number of routes kept (32 slots with 4 pins each)
+const MAX_ROUTES: usize = 128;
+
+/// PCI interrupt pin (INTA# .. INTD#)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PciPin {
+    IntA = 0,
+    IntB = 1,
+    IntC = 2,
+    IntD = 3,
+}
+
+impl PciPin {
+    const fn from_index(index: u8) -> Self {
+        match index % 4 {
+            0 => PciPin::IntA,
+            1 => PciPin::IntB,
+            2 => PciPin::IntC,
+            _ => PciPin::IntD,
+        }
+    }
+}
+
+/// One `_PRT` entry with a hard-wired global system interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciRoute {
+    /// Device (slot) number on the root bus
+    pub device: u8,
+    /// Interrupt pin of the device
+    pub pin: PciPin,
+    /// Global system interrupt the pin is wired to
+    pub gsi: u32,
+}
+
+/// Routes parsed from the DSDT
+static ROUTES: Mutex<[Option<PciRoute>; MAX_ROUTES]> = Mutex::new([None; MAX_ROUTES]);
+
+/// Decode an AML PkgLength at `data[0]`
+///
+/// Returns the encoded length (which includes the PkgLength bytes
+/// themselves) and the number of bytes the encoding takes.
+fn pkg_length(data: &[u8]) -> Option<(usize, usize)> {
+    let lead = *data.first()?;
+    let extra = (lead >> 6) as usize;
+    if extra == 0 {
+        return Some(((lead & 0x3F) as usize, 1));
+    }
+    let mut length = (lead & 0x0F) as usize;
+    for i in 0..extra {
+        length |= (*data.get(1 + i)? as usize) << (4 + 8 * i);
+    }
+    Some((length, 1 + extra))
+}
+
+/// Decode an AML integer constant at `data[0]`, returning it and its size
+pub(super) fn integer(data: &[u8]) -> Option<(u64, usize)> {
+    let le = |len: usize| -> Option<u64> {
+        let bytes = data.get(1..1 + len)?;
+        Some(bytes.iter().rev().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+    };
+    match *data.first()? {
+        AML_ZERO_OP => Some((0, 1)),
+        AML_ONE_OP => Some((1, 1)),
+        AML_ONES_OP => Some((u64::MAX, 1)),
+        AML_BYTE_PREFIX => Some((le(1)?, 2)),
+        AML_WORD_PREFIX => Some((le(2)?, 3)),
+        AML_DWORD_PREFIX => Some((le(4)?, 5)),
+        AML_QWORD_PREFIX => Some((le(8)?, 9)),
+        _ => None,
+    }
+}
+
+/// Parse a `Package` at `data[0]`, returning its element count, the bytes
+/// of its elements and its total size
+pub(super) fn package(data: &[u8]) -> Option<(usize, &[u8], usize)> {
+    if *data.first()? != AML_PACKAGE_OP {
+        return None;
+    }
+    let (length, length_bytes) = pkg_length(data.get(1..)?)?;
+    let count = *data.get(1 + length_bytes)? as usize;
+    let body = data.get(2 + length_bytes..1 + length)?;
+    Some((count, body, 1 + length))
+}
+
+/// Parse one `_PRT` entry package
+///
+/// Returns `Ok(None)` for a well-formed entry that goes through a link
+/// device, and `Err(())` if the package does not look like a `_PRT` entry.
+fn route_entry(data: &[u8]) -> Result<(Option<PciRoute>, usize), ()> {
+    let (count, body, size) = package(data).ok_or(())?;
+    if count != 4 {
+        return Err(());
+    }
+    let (address, used) = integer(body).ok_or(())?;
+    let body = &body[used..];
+    let (pin, used) = integer(body).ok_or(())?;
+    let body = &body[used..];
+
+    // Address is (device << 16) | 0xFFFF: the entry covers every function
+    if address & 0xFFFF != 0xFFFF || address >> 16 > 31 || pin > 3 {
+        return Err(());
+    }
+
+    // Source is Zero for a hard-wired GSI, a link device name otherwise
+    if body.first() != Some(&AML_ZERO_OP) {
+        return Ok((None, size));
+    }
+    let (gsi, _) = integer(&body[1..]).ok_or(())?;
+    let route = PciRoute {
+        device: (address >> 16) as u8,
+        pin: PciPin::from_index(pin as u8),
+        gsi: gsi as u32,
+    };
+    Ok((Some(route), size))
+}
+
+/// Parse a routing table package, calling `record` for every hard-wired
+/// route
+///
+/// Returns `false` if the package is not a routing table.
+fn routing_table(data: &[u8], record: &mut dyn FnMut(PciRoute)) -> bool {
+    let (count, mut body, _) = match package(data) {
+        Some(package) => package,
+        None => return false,
+    };
+    if count == 0 {
+        return false;
+    }
+
+    let mut routes = [None; MAX_ROUTES];
+    let mut found = 0;
+    for _ in 0..count {
+        match route_entry(body) {
+            Ok((route, size)) => {
+                if let Some(route) = route {
+                    if found < MAX_ROUTES {
+                        routes[found] = Some(route);
+                        found += 1;
+                    }
+                }
+                body = &body[size..];
+            }
+            Err(()) => return false,
+        }
+    }
+
+    // Only commit once the whole package parsed as a routing table
+    for route in routes.iter().flatten() {
+        record(*route);
+    }
+    true
+}
+
+/// Scan AML byte code for named routing tables
+///
+/// Every `Name (XXXX, Package { ... })` whose elements all look like
+/// `_PRT` entries counts, which covers `_PRT` itself and the `ARxx` tables
+/// firmware returns from `_PRT` methods in APIC mode.
+fn scan_aml(aml: &[u8], record: &mut dyn FnMut(PciRoute)) -> usize {
+    let mut tables = 0;
+    let mut i = 0;
+    while i + 6 < aml.len() {
+        if aml[i] == AML_NAME_OP && aml[i + 5] == AML_PACKAGE_OP && routing_table(&aml[i + 5..], record)
+        {
+            tables += 1;
+        }
+        i += 1;
+    }
+    tables
+}
+
+/// Record `route` unless the device pin already has one
+///
+/// The first table in the DSDT is normally the root bridge's.
+fn add_route(route: PciRoute) {
+    let mut routes = ROUTES.lock();
+    if routes
+        .iter()
+        .flatten()
+        .any(|r| r.device == route.device && r.pin == route.pin)
+    {
+        return;
+    }
+    if let Some(slot) = routes.iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(route);
+    }
+}
+
+/// Locate the DSDT through the FADT and load its PCI routing tables
+pub fn init(rsdp_addr: u64) -> Result<(), super::AcpiError> {
+    let tables = scan_aml(super::dsdt_aml(rsdp_addr)?, &mut add_route);
+    crate::serial_println!(
+        "[ACPI] PCI routing: {} hard-wired routes from {} table(s)",
+        route_count(),
+        tables
+    );
+    Ok(())
+}
+
+/// Number of known PCI interrupt routes
+pub fn route_count() -> usize {
+    ROUTES.lock().iter().flatten().count()
+}
+
+/// Pin a device's interrupt appears on at its bridge's slot
+///
+/// Behind a PCI-to-PCI bridge, pin `p` of device `d` arrives at the
+/// bridge as pin `(p + d) % 4` (PCI-to-PCI Bridge spec, 9.1).
+pub const fn swizzle(device: u8, pin: PciPin) -> PciPin {
+    PciPin::from_index((pin as u8 + device) % 4)
+}
+
+/// Global system interrupt for `pin` of root bus device `device`
+pub fn lookup(device: u8, pin: PciPin) -> Option<u32> {
+    ROUTES
+        .lock()
+        .iter()
+        .flatten()
+        .find(|r| r.device == device && r.pin == pin)
+        .map(|r| r.gsi)
+}
+
+crate::kernel_test! {
+    /// Static _PRT packages yield hard-wired routes; link entries are skipped
+    fn acpi_prt_parsing() {
+        // Name (_PRT, Package (0x03) {
+        //     Package (0x04) { 0x0001FFFF, Zero, Zero, 0x10 },
+        //     Package (0x04) { 0x0002FFFF, One,  Zero, 0x17 },
+        //     Package (0x04) { 0x0003FFFF, Zero, LNKA, Zero } })
+        const AML: [u8; 46] = [
+            0x08, b'_', b'P', b'R', b'T', 0x12, 0x28, 0x03,
+            0x12, 0x0B, 0x04, 0x0C, 0xFF, 0xFF, 0x01, 0x00, 0x00, 0x00, 0x0A, 0x10,
+            0x12, 0x0B, 0x04, 0x0C, 0xFF, 0xFF, 0x02, 0x00, 0x01, 0x00, 0x0A, 0x17,
+            0x12, 0x0D, 0x04, 0x0C, 0xFF, 0xFF, 0x03, 0x00, 0x00, b'L', b'N', b'K', b'A', 0x00,
+        ];
+        let mut routes = [None; 4];
+        let mut found = 0;
+        let tables = scan_aml(&AML, &mut |route| {
+            routes[found] = Some(route);
+            found += 1;
+        });
+        crate::ktest_assert_eq!(tables, 1, "routing table not found");
+        crate::ktest_assert_eq!(found, 2, "wrong number of hard-wired routes");
+        crate::ktest_assert_eq!(
+            routes[0],
+            Some(PciRoute { device: 1, pin: PciPin::IntA, gsi: 0x10 }),
+            "first route"
+        );
+        crate::ktest_assert_eq!(
+            routes[1],
+            Some(PciRoute { device: 2, pin: PciPin::IntB, gsi: 0x17 }),
+            "second route"
+        );
+
+        crate::ktest_assert_eq!(pkg_length(&[0x4D, 0x01]), Some((0x1D, 2)), "two-byte PkgLength");
+        crate::ktest_assert_eq!(swizzle(2, PciPin::IntC), PciPin::IntA, "bridge swizzle");
+        Ok(())
+    }
+}
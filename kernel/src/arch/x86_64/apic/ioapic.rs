@@ -0,0 +1,173 @@
+//! I/O APIC redirection
+//!
+//! Each I/O APIC listed in the MADT handles a contiguous range of global
+//! system interrupts (GSIs) starting at its `gsi_base`. Routing a GSI means
+//! writing its redirection table entry: target vector, trigger mode,
+//! polarity and destination Local APIC.
+
+use crate::arch::x86_64::acpi::{get_madt_info, InterruptOverride, IoApicInfo};
+use crate::io::mmio::MmioRegion;
+use crate::mm::addr::PhysAddr;
+use spin::Mutex;
+
+/// Register select and data window offsets
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
+/// Size of the register block: the select and window registers
+pub const IOAPIC_MMIO_SIZE: usize = 0x20;
+
+/// Version register (bits 16-23: highest redirection entry)
+const IOAPICVER: u32 = 0x01;
+
+/// First redirection table register; entry `n` is at `0x10 + 2n`
+const IOREDTBL: u32 = 0x10;
+
+/// Redirection entry bits
+const RTE_ACTIVE_LOW: u32 = 1 << 13;
+const RTE_LEVEL: u32 = 1 << 15;
+const RTE_MASKED: u32 = 1 << 16;
+
+/// Serializes the select/window register pairs of all I/O APICs
+static IOAPIC_LOCK: Mutex<()> = Mutex::new(());
+
+/// How a GSI signals an interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Edge,
+    Level,
+}
+
+/// Active level of a GSI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// The registers of the I/O APIC the MADT describes as `ioapic`
+fn regs(ioapic: &IoApicInfo) -> MmioRegion {
+    // The MADT says where the I/O APIC decodes its registers
+    unsafe { MmioRegion::new("ioapic", PhysAddr::new(ioapic.address as u64), IOAPIC_MMIO_SIZE) }
+}
+
+/// Read an I/O APIC register; the caller holds `IOAPIC_LOCK`
+fn read(regs: MmioRegion, reg: u32) -> u32 {
+    regs.write(IOREGSEL, reg);
+    regs.read(IOWIN)
+}
+
+/// Write an I/O APIC register; the caller holds `IOAPIC_LOCK`
+fn write(regs: MmioRegion, reg: u32, value: u32) {
+    regs.write(IOREGSEL, reg);
+    regs.write(IOWIN, value);
+}
+
+/// Find the I/O APIC handling `gsi` and the GSI's pin on it
+fn ioapic_for(gsi: u32) -> Option<(IoApicInfo, u32)> {
+    let madt = get_madt_info()?;
+    let _guard = IOAPIC_LOCK.lock();
+    madt.ioapics[..madt.ioapic_count]
+        .iter()
+        .flatten()
+        .find_map(|ioapic| {
+            let pin = gsi.checked_sub(ioapic.gsi_base)?;
+            let entries = ((read(regs(ioapic), IOAPICVER) >> 16) & 0xFF) + 1;
+            (pin < entries).then_some((*ioapic, pin))
+        })
+}
+
+/// Low dword of a redirection entry delivering `vector` (fixed, physical)
+const fn entry_low(vector: u8, trigger: Trigger, polarity: Polarity) -> u32 {
+    let mut low = vector as u32;
+    if let Trigger::Level = trigger {
+        low |= RTE_LEVEL;
+    }
+    if let Polarity::ActiveLow = polarity {
+        low |= RTE_ACTIVE_LOW;
+    }
+    low
+}
+
+/// GSI, trigger mode and polarity of ISA interrupt `irq`
+///
+/// ISA interrupts are edge-triggered and active-high on the GSI of the
+/// same number, unless the MADT overrides that.
+pub fn isa_route(irq: u8) -> (u32, Trigger, Polarity) {
+    let Some(over) = source_override(irq) else {
+        return (irq as u32, Trigger::Edge, Polarity::ActiveHigh);
+    };
+
+    // 0b11 selects the non-default setting, anything else the ISA default
+    let polarity = if over.flags & 0b11 == 0b11 { Polarity::ActiveLow } else { Polarity::ActiveHigh };
+    let trigger = if (over.flags >> 2) & 0b11 == 0b11 { Trigger::Level } else { Trigger::Edge };
+    (over.gsi, trigger, polarity)
+}
+
+/// GSI, trigger mode and polarity of the ACPI SCI on ISA interrupt `irq`
+///
+/// The SCI is level-triggered and active-low unless the MADT says
+/// otherwise; an override that "conforms to the bus" keeps that default.
+pub fn sci_route(irq: u8) -> (u32, Trigger, Polarity) {
+    let Some(over) = source_override(irq) else {
+        return (irq as u32, Trigger::Level, Polarity::ActiveLow);
+    };
+
+    // 0b01 selects active-high or edge-triggered explicitly
+    let polarity = if over.flags & 0b11 == 0b01 { Polarity::ActiveHigh } else { Polarity::ActiveLow };
+    let trigger = if (over.flags >> 2) & 0b11 == 0b01 { Trigger::Edge } else { Trigger::Level };
+    (over.gsi, trigger, polarity)
+}
+
+/// The MADT's interrupt source override for ISA interrupt `irq`, if any
+fn source_override(irq: u8) -> Option<InterruptOverride> {
+    get_madt_info().and_then(|madt| {
+        madt.overrides[..madt.override_count]
+            .iter()
+            .flatten()
+            .find(|over| over.source == irq)
+            .copied()
+    })
+}
+
+/// Route `gsi` to `vector` on the Local APIC `dest_apic_id` and unmask it
+pub fn route_gsi(
+    gsi: u32,
+    vector: u8,
+    trigger: Trigger,
+    polarity: Polarity,
+    dest_apic_id: u8,
+) -> Result<(), &'static str> {
+    let (ioapic, pin) = ioapic_for(gsi).ok_or("No I/O APIC handles this GSI")?;
+    let regs = regs(&ioapic);
+    let reg = IOREDTBL + 2 * pin;
+
+    let _guard = IOAPIC_LOCK.lock();
+    // Mask while the entry is half written
+    write(regs, reg, RTE_MASKED);
+    write(regs, reg + 1, (dest_apic_id as u32) << 24);
+    write(regs, reg, entry_low(vector, trigger, polarity));
+
+    crate::serial_println!(
+        "[IOAPIC] GSI {} -> vector 0x{:x} on APIC {} ({:?}, {:?})",
+        gsi,
+        vector,
+        dest_apic_id,
+        trigger,
+        polarity
+    );
+    Ok(())
+}
+
+crate::kernel_test! {
+    /// Redirection entries encode vector, trigger mode and polarity
+    fn ioapic_entry_encoding() {
+        crate::ktest_assert_eq!(entry_low(0x41, Trigger::Edge, Polarity::ActiveHigh), 0x41, "edge/high");
+        crate::ktest_assert_eq!(
+            entry_low(0x42, Trigger::Level, Polarity::ActiveLow),
+            0x42 | RTE_LEVEL | RTE_ACTIVE_LOW,
+            "level/low"
+        );
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@
 /// This module provides high-level IPI functions for sending interrupts
 /// between CPU cores. IPIs are used for:
 /// - Rescheduling tasks on remote cores (RESCHEDULE_IPI)
-/// - TLB shootdown (future)
+/// - TLB shootdown (see `mm::tlb`)
 /// - Halting cores (future)
 use super::LocalApic;
 use crate::arch::x86_64::acpi::get_madt_info;
@@ -15,8 +15,8 @@ use crate::serial_println;
 /// This IPI triggers the scheduler on the target core
 pub const RESCHEDULE_IPI_VECTOR: u8 = 0x30;
 
-/// TLB_FLUSH_IPI vector number (future use)
-#[allow(dead_code)]
+/// TLB_FLUSH_IPI vector number
+/// This IPI makes the target core flush the ranges queued for it
 pub const TLB_FLUSH_IPI_VECTOR: u8 = 0x31;
 
 /// HALT_IPI vector number (future use)
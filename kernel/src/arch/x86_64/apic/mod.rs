@@ -1,15 +1,28 @@
 /// APIC (Advanced Programmable Interrupt Controller) support
 /// This module provides Local APIC management, timer configuration,
 /// and Inter-Processor Interrupt (IPI) functionality.
+///
+/// The Local APIC runs in x2APIC mode, where its registers are MSRs, when
+/// the CPU supports it and `x2apic=off` is not on the command line;
+/// otherwise it stays in xAPIC mode behind the MMIO page. The BSP picks the
+/// mode in `select_mode` and every AP follows it in `LocalApic::init`.
+pub mod ioapic;
 pub mod ipi;
+pub mod timer;
 
-use core::ptr::{read_volatile, write_volatile};
+use crate::io::mmio::MmioRegion;
+use crate::mm::addr::PhysAddr;
+use super::msr::{self, rdmsr, wrmsr};
+use core::sync::atomic::{fence, AtomicBool, Ordering};
 
 // ============================================================================
 // APIC Register Offsets
 // ============================================================================
 
 /// Local APIC ID register offset
+/// Size of the xAPIC register page
+pub const LAPIC_MMIO_SIZE: usize = 0x1000;
+
 const LAPIC_ID: u32 = 0x20;
 
 /// End of Interrupt register offset
@@ -18,6 +31,9 @@ const LAPIC_EOI: u32 = 0xB0;
 /// Spurious Interrupt Vector register offset
 const LAPIC_SPURIOUS: u32 = 0xF0;
 
+/// In-Service Register offset (eight 32-bit registers, 0x10 apart)
+const LAPIC_ISR: u32 = 0x100;
+
 /// Interrupt Command Register (low 32 bits) offset
 const LAPIC_ICR_LOW: u32 = 0x300;
 
@@ -49,6 +65,12 @@ const TIMER_VECTOR: u8 = 0x20;
 /// Reschedule IPI vector number
 const RESCHEDULE_IPI_VECTOR: u8 = 0x30;
 
+/// Timer LVT mode: periodic
+const LVT_TIMER_PERIODIC: u32 = 0b01 << 17;
+
+/// Timer LVT mode: TSC-deadline
+const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+
 /// APIC enable bit in spurious interrupt vector register
 const APIC_ENABLE: u32 = 1 << 8;
 
@@ -64,17 +86,79 @@ const ICR_STARTUP: u32 = 0x600;
 /// ICR level assert
 const ICR_LEVEL_ASSERT: u32 = 1 << 14;
 
+/// IA32_APIC_BASE: APIC globally enabled
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// IA32_APIC_BASE: x2APIC mode
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+
+/// Length of the timer calibration interval
+const CALIBRATION_MS: u32 = 10;
+
+// ============================================================================
+// x2APIC Mode
+// ============================================================================
+
+/// Whether the Local APICs are in x2APIC mode (decided by the BSP)
+static X2APIC_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the Local APICs are accessed through x2APIC MSRs
+pub fn x2apic_enabled() -> bool {
+    X2APIC_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Switch the calling CPU's Local APIC to x2APIC mode
+///
+/// Returns whether the mode bit reads back set.
+unsafe fn enter_x2apic_mode() -> bool {
+    let base = rdmsr(msr::IA32_APIC_BASE);
+    if base & APIC_BASE_X2APIC == 0 {
+        // xAPIC to x2APIC is a valid transition only with the APIC enabled
+        wrmsr(msr::IA32_APIC_BASE, base | APIC_BASE_ENABLE | APIC_BASE_X2APIC);
+    }
+    rdmsr(msr::IA32_APIC_BASE) & APIC_BASE_X2APIC != 0
+}
+
+/// Choose between x2APIC and xAPIC mode on the BSP
+///
+/// Must be called on the BSP before its `LocalApic::init` and before any
+/// AP is started. Falls back to xAPIC if the CPU lacks x2APIC, the command
+/// line says `x2apic=off`, or the mode switch does not take.
+pub fn select_mode() {
+    use crate::serial_println;
+
+    if crate::cmdline::value("x2apic") == Some("off") {
+        serial_println!("[APIC] x2APIC disabled on the command line, using xAPIC");
+        return;
+    }
+    if !super::cpu::features::get().x2apic {
+        serial_println!("[APIC] x2APIC not supported, using xAPIC");
+        return;
+    }
+    if unsafe { enter_x2apic_mode() } {
+        X2APIC_ENABLED.store(true, Ordering::SeqCst);
+        serial_println!("[APIC] Using x2APIC mode");
+    } else {
+        crate::log_warn!("APIC", "x2APIC mode switch failed, falling back to xAPIC");
+    }
+}
+
+/// MSR of the register at MMIO `offset` in x2APIC mode
+const fn x2apic_msr(offset: u32) -> u32 {
+    msr::X2APIC_BASE + (offset >> 4)
+}
+
 // ============================================================================
 // Local APIC Driver
 // ============================================================================
 
 /// Local APIC driver structure
 ///
-/// Provides access to the Local APIC through memory-mapped I/O.
-/// Each CPU core has its own Local APIC instance.
+/// Provides access to the Local APIC through memory-mapped I/O, or through
+/// MSRs in x2APIC mode. Each CPU core has its own Local APIC instance.
 pub struct LocalApic {
-    /// Base address of the APIC memory-mapped registers
-    base_addr: *mut u32,
+    /// The APIC's memory-mapped registers, unused in x2APIC mode
+    regs: MmioRegion,
 }
 
 impl LocalApic {
@@ -90,7 +174,7 @@ impl LocalApic {
     /// * `base_addr` - Physical address of the APIC registers (typically 0xFEE00000)
     pub unsafe fn new(base_addr: u64) -> Self {
         Self {
-            base_addr: base_addr as *mut u32,
+            regs: MmioRegion::new("lapic", PhysAddr::new(base_addr), LAPIC_MMIO_SIZE),
         }
     }
 
@@ -101,10 +185,10 @@ impl LocalApic {
     /// * `offset` - Register offset in bytes
     #[inline]
     fn read(&self, offset: u32) -> u32 {
-        unsafe {
-            let reg_addr = (self.base_addr as usize + offset as usize) as *const u32;
-            read_volatile(reg_addr)
+        if x2apic_enabled() {
+            return unsafe { rdmsr(x2apic_msr(offset)) } as u32;
         }
+        self.regs.read(offset as usize)
     }
 
     /// Write a 32-bit value to an APIC register
@@ -115,18 +199,44 @@ impl LocalApic {
     /// * `value` - Value to write
     #[inline]
     fn write(&mut self, offset: u32, value: u32) {
-        unsafe {
-            let reg_addr = (self.base_addr as usize + offset as usize) as *mut u32;
-            write_volatile(reg_addr, value);
+        if x2apic_enabled() {
+            unsafe { wrmsr(x2apic_msr(offset), value as u64) };
+            return;
+        }
+        self.regs.write(offset as usize, value);
+    }
+
+    /// Write the Interrupt Command Register, which sends an IPI
+    ///
+    /// In xAPIC mode the destination goes in the high half, whose write
+    /// only latches, then the low half starts the send. In x2APIC mode the
+    /// ICR is a single MSR with a 32-bit destination; WRMSR to it is not
+    /// serializing, so a fence first makes earlier stores (a TLB shootdown
+    /// request, say) visible before the target takes the interrupt.
+    fn write_icr(&mut self, apic_id: u8, low: u32) {
+        if x2apic_enabled() {
+            fence(Ordering::SeqCst);
+            let icr = (apic_id as u64) << 32 | low as u64;
+            unsafe { wrmsr(x2apic_msr(LAPIC_ICR_LOW), icr) };
+        } else {
+            self.write(LAPIC_ICR_HIGH, (apic_id as u32) << 24);
+            self.write(LAPIC_ICR_LOW, low);
         }
     }
 
     /// Initialize the Local APIC
     ///
     /// This function:
-    /// 1. Sets the spurious interrupt vector to 0xFF
-    /// 2. Enables the APIC by setting bit 8 in the spurious interrupt vector register
+    /// 1. Switches the APIC to x2APIC mode if the BSP chose it
+    /// 2. Sets the spurious interrupt vector to 0xFF
+    /// 3. Enables the APIC by setting bit 8 in the spurious interrupt vector register
     pub fn init(&mut self) {
+        // A CPU left in xAPIC mode would not see its registers through the
+        // MSRs every other CPU uses; there is no sane way to continue.
+        if x2apic_enabled() && !unsafe { enter_x2apic_mode() } {
+            panic!("[APIC] CPU lacks x2APIC but the BSP enabled it");
+        }
+
         // Set spurious interrupt vector and enable APIC
         let spurious_value = (SPURIOUS_VECTOR as u32) | APIC_ENABLE;
         self.write(LAPIC_SPURIOUS, spurious_value);
@@ -138,6 +248,11 @@ impl LocalApic {
     ///
     /// The 8-bit APIC ID
     pub fn id(&self) -> u8 {
+        // x2APIC IDs are 32 bits, but the MADT entries the kernel parses
+        // only describe CPUs with IDs up to 255
+        if x2apic_enabled() {
+            return self.read(LAPIC_ID) as u8;
+        }
         // APIC ID is in bits 24-31 of the ID register
         ((self.read(LAPIC_ID) >> 24) & 0xFF) as u8
     }
@@ -150,6 +265,15 @@ impl LocalApic {
         self.write(LAPIC_EOI, 0);
     }
 
+    /// Whether `vector` was delivered by this APIC and is awaiting EOI
+    ///
+    /// An EOI always retires the highest vector in service, so handlers of
+    /// interrupts that may come from elsewhere check this first.
+    pub fn in_service(&self, vector: u8) -> bool {
+        let isr = self.read(LAPIC_ISR + 0x10 * (vector as u32 / 32));
+        isr & (1 << (vector % 32)) != 0
+    }
+
     /// Wait for IPI delivery to complete
     ///
     /// Polls the delivery status bit in the ICR register until it clears,
@@ -159,6 +283,10 @@ impl LocalApic {
     ///
     /// `true` if delivery completed within timeout, `false` otherwise
     fn wait_for_delivery(&self) -> bool {
+        // x2APIC has no delivery status bit; the WRMSR completes the send
+        if x2apic_enabled() {
+            return true;
+        }
         // Wait up to ~1ms (approximate)
         for _ in 0..10000 {
             if (self.read(LAPIC_ICR_LOW) & ICR_DELIVERY_STATUS) == 0 {
@@ -188,12 +316,8 @@ impl LocalApic {
             return false;
         }
 
-        // Write destination APIC ID to ICR high register (bits 24-31)
-        self.write(LAPIC_ICR_HIGH, (apic_id as u32) << 24);
-
-        // Write vector and delivery mode to ICR low register
         // Delivery mode: Fixed (000b), Level: Assert
-        self.write(LAPIC_ICR_LOW, vector as u32 | ICR_LEVEL_ASSERT);
+        self.write_icr(apic_id, vector as u32 | ICR_LEVEL_ASSERT);
 
         // Wait for delivery to complete
         self.wait_for_delivery()
@@ -217,11 +341,8 @@ impl LocalApic {
             return false;
         }
 
-        // Write destination APIC ID to ICR high register
-        self.write(LAPIC_ICR_HIGH, (apic_id as u32) << 24);
-
         // Send INIT IPI: delivery mode = INIT (101b), level = assert
-        self.write(LAPIC_ICR_LOW, ICR_INIT | ICR_LEVEL_ASSERT);
+        self.write_icr(apic_id, ICR_INIT | ICR_LEVEL_ASSERT);
 
         // Wait for delivery to complete
         self.wait_for_delivery()
@@ -246,22 +367,23 @@ impl LocalApic {
             return false;
         }
 
-        // Write destination APIC ID to ICR high register
-        self.write(LAPIC_ICR_HIGH, (apic_id as u32) << 24);
-
         // Send SIPI: delivery mode = Startup (110b), vector = start page
-        self.write(LAPIC_ICR_LOW, ICR_STARTUP | (start_page as u32));
+        self.write_icr(apic_id, ICR_STARTUP | (start_page as u32));
 
         // Wait for delivery to complete
         self.wait_for_delivery()
     }
 
-    /// Calibrate the APIC timer using the PIT (Programmable Interval Timer)
+    /// Calibrate the APIC timer
     ///
-    /// This function uses the PIT as a reference clock to determine the
-    /// LAPIC timer frequency. It programs the PIT for a 10ms one-shot,
-    /// sets the LAPIC timer to maximum count, waits for the PIT to fire,
-    /// and calculates the LAPIC frequency from the remaining count.
+    /// Counts LAPIC timer ticks (at the reset divide value of 2) over 10ms
+    /// of a reference clock: the HPET if the firmware describes one, the
+    /// PIT otherwise. UEFI machines may have no PIT, or one that is clock
+    /// gated and never counts; the PIT wait is bounded, and a PIT that does
+    /// not finish in time or yields an implausible count is treated as
+    /// absent. Without either clock the frequency comes from the core
+    /// crystal clock in CPUID leaf 0x15, which drives the LAPIC timer on
+    /// recent Intel CPUs, or failing that a fixed guess.
     ///
     /// # Returns
     ///
@@ -272,6 +394,68 @@ impl LocalApic {
     /// This function uses I/O ports and should only be called during
     /// initialization with interrupts disabled.
     pub unsafe fn calibrate_timer(&mut self) -> u64 {
+        use crate::serial_println;
+
+        /// LAPIC frequency assumed when nothing can measure it
+        const FALLBACK_FREQUENCY: u64 = 100_000_000;
+
+        if let Some(hpet) = super::hpet::get() {
+            let ticks = self.count_ticks(|| {
+                hpet.busy_wait_us(CALIBRATION_MS as u64 * 1000);
+                true
+            });
+            if let Some(ticks) = ticks {
+                serial_println!("[APIC] Timer calibrated against the HPET");
+                return ticks * (1000 / CALIBRATION_MS as u64);
+            }
+        }
+
+        if let Some(ticks) = self.calibrate_with_pit() {
+            serial_println!("[APIC] Timer calibrated against the PIT");
+            return ticks * (1000 / CALIBRATION_MS as u64);
+        }
+
+        // Leaf 0x15: ECX is the core crystal clock in Hz, 0 if not reported
+        let max_leaf = core::arch::x86_64::__cpuid(0).eax;
+        if max_leaf >= 0x15 {
+            let crystal_hz = core::arch::x86_64::__cpuid(0x15).ecx as u64;
+            if crystal_hz != 0 {
+                serial_println!("[APIC] No reference clock, using the CPUID crystal clock");
+                return crystal_hz / 2;
+            }
+        }
+
+        crate::log_warn!(
+            "APIC",
+            "No HPET or PIT to calibrate the timer against, assuming {} Hz",
+            FALLBACK_FREQUENCY
+        );
+        FALLBACK_FREQUENCY
+    }
+
+    /// Count LAPIC timer ticks while `wait` runs
+    ///
+    /// Returns None if `wait` fails or the count is too low for a real
+    /// LAPIC timer (under 1 MHz).
+    unsafe fn count_ticks(&mut self, wait: impl FnOnce() -> bool) -> Option<u64> {
+        const MIN_TICKS: u64 = 1_000_000 / (1000 / CALIBRATION_MS as u64);
+
+        // Set LAPIC timer to maximum count
+        self.write(LAPIC_TIMER_INIT_COUNT, 0xFFFFFFFF);
+        let finished = wait();
+
+        // Read the current LAPIC timer count, then stop the timer
+        let final_count = self.read(LAPIC_TIMER_CURRENT_COUNT);
+        self.write(LAPIC_TIMER_INIT_COUNT, 0);
+
+        let ticks = (0xFFFFFFFF - final_count) as u64;
+        (finished && ticks >= MIN_TICKS).then_some(ticks)
+    }
+
+    /// Count LAPIC timer ticks over a 10ms one-shot of PIT channel 2
+    ///
+    /// Returns None if the PIT looks absent.
+    unsafe fn calibrate_with_pit(&mut self) -> Option<u64> {
         use x86_64::instructions::port::Port;
 
         // PIT constants
@@ -279,17 +463,23 @@ impl LocalApic {
         const PIT_COMMAND: u16 = 0x43;
         const PIT_CHANNEL_2: u16 = 0x42;
         const PIT_CHANNEL_2_GATE: u16 = 0x61;
-
-        // Calculate PIT divisor for 10ms (100 Hz)
-        const CALIBRATION_MS: u32 = 10;
         const PIT_DIVISOR: u32 = PIT_FREQUENCY * CALIBRATION_MS / 1000;
 
+        // Status reads before giving up; a read of port 0x61 takes about
+        // a microsecond, so this is well over 10ms
+        const MAX_POLLS: usize = 1_000_000;
+
         let mut pit_command = Port::<u8>::new(PIT_COMMAND);
         let mut pit_channel2 = Port::<u8>::new(PIT_CHANNEL_2);
         let mut pit_gate = Port::<u8>::new(PIT_CHANNEL_2_GATE);
 
-        // Disable PIT channel 2 gate and speaker
+        // Nothing decodes the port
         let gate_value = pit_gate.read();
+        if gate_value == 0xFF {
+            return None;
+        }
+
+        // Disable PIT channel 2 gate and speaker
         pit_gate.write(gate_value & 0xFC); // Clear bits 0 and 1
 
         // Configure PIT channel 2 for one-shot mode
@@ -304,35 +494,15 @@ impl LocalApic {
         pit_channel2.write((PIT_DIVISOR & 0xFF) as u8);
         pit_channel2.write(((PIT_DIVISOR >> 8) & 0xFF) as u8);
 
-        // Set LAPIC timer to maximum count
-        self.write(LAPIC_TIMER_INIT_COUNT, 0xFFFFFFFF);
-
-        // Enable PIT channel 2 gate to start counting
-        let gate_value = pit_gate.read();
-        pit_gate.write(gate_value | 0x01); // Set bit 0
-
-        // Wait for PIT channel 2 to finish counting
-        // Bit 5 of port 0x61 indicates the output state
-        loop {
-            let status = pit_gate.read();
-            if (status & 0x20) != 0 {
-                break;
-            }
-        }
-
-        // Read the current LAPIC timer count
-        let final_count = self.read(LAPIC_TIMER_CURRENT_COUNT);
-
-        // Stop LAPIC timer
-        self.write(LAPIC_TIMER_INIT_COUNT, 0);
-
-        // Calculate ticks elapsed
-        let ticks_elapsed = 0xFFFFFFFF - final_count;
+        self.count_ticks(|| {
+            // Enable PIT channel 2 gate to start counting
+            let gate_value = pit_gate.read();
+            pit_gate.write(gate_value | 0x01); // Set bit 0
 
-        // Calculate frequency: ticks_per_10ms * 100 = ticks_per_second
-        let frequency = (ticks_elapsed as u64) * 100;
-
-        frequency
+            // Wait for PIT channel 2 to finish counting
+            // Bit 5 of port 0x61 indicates the output state
+            (0..MAX_POLLS).any(|_| pit_gate.read() & 0x20 != 0)
+        })
     }
 
     /// Initialize the APIC timer in periodic mode
@@ -363,10 +533,47 @@ impl LocalApic {
         // Set timer vector and mode
         // Bit 17: Timer mode (1 = periodic, 0 = one-shot)
         // Bits 0-7: Vector number
-        let timer_config = (1 << 17) | (TIMER_VECTOR as u32);
+        let timer_config = LVT_TIMER_PERIODIC | (TIMER_VECTOR as u32);
         self.write(LAPIC_TIMER_LVT, timer_config);
 
         // Set initial count to start the timer
         self.write(LAPIC_TIMER_INIT_COUNT, initial_count as u32);
     }
+
+    /// Put the APIC timer in TSC-deadline mode, disarmed
+    ///
+    /// The timer then fires once when the TSC reaches the value written to
+    /// IA32_TSC_DEADLINE (see [`timer`]). Returns false, leaving the timer
+    /// stopped, if the deadline MSR does not take a value, i.e. the mode
+    /// is not really there.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must report TSC-deadline support; call with interrupts
+    /// disabled during initialization.
+    pub unsafe fn init_tsc_deadline_timer(&mut self) -> bool {
+        self.write(LAPIC_TIMER_INIT_COUNT, 0);
+        self.write(LAPIC_TIMER_LVT, LVT_TIMER_TSC_DEADLINE | (TIMER_VECTOR as u32));
+
+        // The LVT write must be ordered before the first deadline write
+        fence(Ordering::SeqCst);
+        wrmsr(msr::IA32_TSC_DEADLINE, u64::MAX);
+        let armed = rdmsr(msr::IA32_TSC_DEADLINE) != 0;
+        wrmsr(msr::IA32_TSC_DEADLINE, 0);
+        armed
+    }
+}
+
+crate::kernel_test! {
+    /// The Local APIC is in the mode the BSP chose and reports this CPU's ID
+    fn apic_mode_and_id() {
+        let in_x2apic_mode = unsafe { rdmsr(msr::IA32_APIC_BASE) } & APIC_BASE_X2APIC != 0;
+        crate::ktest_assert_eq!(in_x2apic_mode, x2apic_enabled(), "APIC mode differs from the chosen one");
+
+        let madt = super::acpi::get_madt_info().ok_or("no MADT")?;
+        let lapic = unsafe { LocalApic::new(madt.lapic_address) };
+        let percpu = super::smp::percpu::percpu_current();
+        crate::ktest_assert_eq!(lapic.id(), percpu.apic_id, "LAPIC ID differs from the per-CPU one");
+        Ok(())
+    }
 }
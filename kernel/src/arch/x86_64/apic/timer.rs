@@ -0,0 +1,164 @@
+//! LAPIC timer mode: TSC-deadline with periodic fallback
+//!
+//! When the CPU supports TSC-deadline mode and the TSC is calibrated, each
+//! LAPIC timer interrupt is programmed as an absolute TSC value instead of
+//! a count of timer ticks. The scheduler tick is kept by arming the next
+//! tick boundary every time; in between, a task going to sleep arms an
+//! earlier deadline on its CPU, so the interrupt that wakes it comes at its
+//! deadline rather than at the next tick. Such an interrupt is not a tick
+//! ([`handle_interrupt`] says which kind it is).
+//!
+//! Without TSC-deadline support, without a calibrated TSC, with
+//! `lapic_timer=periodic` on the command line, or if the deadline MSR does
+//! not take a value, the timer counts down periodically as before. The BSP
+//! decides the mode; APs follow it.
+
+use super::LocalApic;
+use crate::arch::x86_64::msr::{self, wrmsr};
+use crate::time::{Duration, Instant};
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Whether the LAPIC timers run in TSC-deadline mode
+static DEADLINE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// TSC frequency in Hz in TSC-deadline mode
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// TSC cycles per scheduler tick in TSC-deadline mode
+static TSC_PER_TICK: AtomicU64 = AtomicU64::new(0);
+
+/// One CPU's deadlines
+struct Deadlines {
+    /// TSC value of the next tick boundary
+    next_tick: AtomicU64,
+    /// Deadline currently armed (the next tick or an earlier wake-up)
+    armed: AtomicU64,
+}
+
+crate::per_cpu! {
+    /// Each CPU's deadlines
+    static DEADLINES: Deadlines = Deadlines {
+        next_tick: AtomicU64::new(0),
+        armed: AtomicU64::new(0),
+    };
+}
+
+fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Whether the LAPIC timers run in TSC-deadline mode
+pub fn deadline_mode() -> bool {
+    DEADLINE_MODE.load(Ordering::Relaxed)
+}
+
+/// Start this CPU's LAPIC timer at `target_hz` ticks per second
+///
+/// The BSP tries TSC-deadline mode first; an AP uses whatever mode the
+/// BSP ended up with. `frequency_hz` is the calibrated LAPIC timer
+/// frequency, used in periodic mode.
+///
+/// # Safety
+///
+/// Call once per CPU with interrupts disabled during initialization.
+pub unsafe fn start(lapic: &mut LocalApic, frequency_hz: u64, target_hz: u64) {
+    if crate::arch::x86_64::smp::percpu::current_cpu_id() == 0 {
+        let supported = crate::arch::x86_64::cpu::features::get().tsc_deadline
+            && crate::cmdline::value("lapic_timer") != Some("periodic");
+        if let Some(tsc_hz) = crate::time::clock::tsc_frequency_hz().filter(|_| supported) {
+            TSC_HZ.store(tsc_hz, Ordering::Relaxed);
+            TSC_PER_TICK.store(tsc_hz / target_hz, Ordering::Relaxed);
+            DEADLINE_MODE.store(lapic.init_tsc_deadline_timer(), Ordering::Relaxed);
+        }
+        if deadline_mode() {
+            crate::serial_println!("[APIC] Timer in TSC-deadline mode");
+        } else {
+            crate::serial_println!("[APIC] Timer in periodic mode");
+        }
+    } else if deadline_mode() && !lapic.init_tsc_deadline_timer() {
+        crate::log_warn!("APIC", "TSC-deadline mode failed on this CPU after the BSP");
+    }
+
+    if deadline_mode() {
+        let deadlines = DEADLINES.get();
+        deadlines.next_tick.store(rdtsc() + TSC_PER_TICK.load(Ordering::Relaxed), Ordering::Relaxed);
+        rearm(deadlines);
+    } else {
+        lapic.init_timer(frequency_hz, target_hz);
+    }
+}
+
+/// Arm this CPU's next tick boundary
+fn rearm(deadlines: &Deadlines) {
+    let next_tick = deadlines.next_tick.load(Ordering::Relaxed);
+    deadlines.armed.store(next_tick, Ordering::Relaxed);
+    unsafe { wrmsr(msr::IA32_TSC_DEADLINE, next_tick) };
+}
+
+/// Account a LAPIC timer interrupt on this CPU and arm the next one
+///
+/// Returns whether a tick boundary passed, always true in periodic mode.
+/// An interrupt that is not a tick was armed by [`arm`] for a wake-up.
+/// Called from the timer interrupt with interrupts disabled.
+pub fn handle_interrupt() -> bool {
+    if !deadline_mode() {
+        return true;
+    }
+
+    let deadlines = DEADLINES.get();
+    let now = rdtsc();
+    let next_tick = deadlines.next_tick.load(Ordering::Relaxed);
+    let ticked = now >= next_tick;
+    if ticked {
+        // Keep the ticks on their grid unless they fell behind
+        let per_tick = TSC_PER_TICK.load(Ordering::Relaxed);
+        let mut next = next_tick + per_tick;
+        if next <= now {
+            next = now + per_tick;
+        }
+        deadlines.next_tick.store(next, Ordering::Relaxed);
+    }
+    rearm(deadlines);
+    ticked
+}
+
+/// Make this CPU's timer fire at `at` if that is before the deadline
+/// already armed
+///
+/// Does nothing in periodic mode, where wake-ups wait for a tick.
+pub fn arm(at: Instant) {
+    if !deadline_mode() {
+        return;
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let deadlines = DEADLINES.get();
+        let now = Instant::now();
+        let tsc = rdtsc() + to_cycles(at.saturating_duration_since(now));
+        if tsc < deadlines.armed.load(Ordering::Relaxed) {
+            deadlines.armed.store(tsc, Ordering::Relaxed);
+            unsafe { wrmsr(msr::IA32_TSC_DEADLINE, tsc) };
+        }
+    })
+}
+
+/// Time until this CPU's timer fires, in TSC-deadline mode
+pub fn until_next_interrupt() -> Option<Duration> {
+    if !deadline_mode() {
+        return None;
+    }
+    let armed = DEADLINES.get().armed.load(Ordering::Relaxed);
+    Some(to_duration(armed.saturating_sub(rdtsc())))
+}
+
+/// TSC cycles in `duration`, rounded up so a deadline is never early
+fn to_cycles(duration: Duration) -> u64 {
+    let hz = TSC_HZ.load(Ordering::Relaxed) as u128;
+    (duration.as_nanos() as u128 * hz).div_ceil(1_000_000_000).min(u64::MAX as u128) as u64
+}
+
+fn to_duration(cycles: u64) -> Duration {
+    let hz = TSC_HZ.load(Ordering::Relaxed).max(1) as u128;
+    Duration::from_nanos((cycles as u128 * 1_000_000_000 / hz).min(u64::MAX as u128) as u64)
+}
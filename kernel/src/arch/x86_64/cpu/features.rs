@@ -0,0 +1,230 @@
+//! CPUID feature detection
+//!
+//! CPUID is queried once, early in boot, and the answers are kept in a
+//! global [`CpuFeatures`]. Subsystems that depend on optional instructions
+//! or page table bits (the FPU for XSAVE, paging for NX, the random number
+//! generator for RDRAND/RDSEED, the clock for an invariant TSC, the idle
+//! loop for MONITOR/MWAIT) consult it
+//! instead of issuing CPUID themselves. Leaves the BSP reports are assumed
+//! to hold on every CPU.
+
+use core::arch::x86_64::__cpuid_count;
+
+/// CPUID leaf 1, ECX
+const LEAF1_ECX_SSE3: u32 = 1 << 0;
+const LEAF1_ECX_MONITOR: u32 = 1 << 3;
+const LEAF1_ECX_SSSE3: u32 = 1 << 9;
+const LEAF1_ECX_SSE4_1: u32 = 1 << 19;
+const LEAF1_ECX_SSE4_2: u32 = 1 << 20;
+const LEAF1_ECX_X2APIC: u32 = 1 << 21;
+const LEAF1_ECX_TSC_DEADLINE: u32 = 1 << 24;
+const LEAF1_ECX_XSAVE: u32 = 1 << 26;
+const LEAF1_ECX_AVX: u32 = 1 << 28;
+const LEAF1_ECX_RDRAND: u32 = 1 << 30;
+const LEAF1_ECX_HYPERVISOR: u32 = 1 << 31;
+/// CPUID leaf 1, EDX
+const LEAF1_EDX_SSE: u32 = 1 << 25;
+const LEAF1_EDX_SSE2: u32 = 1 << 26;
+/// CPUID leaf 5, ECX: EDX enumerates the MWAIT sub-states
+const LEAF5_ECX_EMX: u32 = 1 << 0;
+/// CPUID leaf 6, EAX
+const LEAF6_EAX_ARAT: u32 = 1 << 2;
+/// CPUID leaf 7, EBX
+const LEAF7_EBX_AVX2: u32 = 1 << 5;
+const LEAF7_EBX_SMEP: u32 = 1 << 7;
+const LEAF7_EBX_RDSEED: u32 = 1 << 18;
+const LEAF7_EBX_SMAP: u32 = 1 << 20;
+/// CPUID leaf 0x8000_0001, EDX
+const EXT1_EDX_NX: u32 = 1 << 20;
+const EXT1_EDX_PAGE_1GB: u32 = 1 << 26;
+/// CPUID leaf 0x8000_0007, EDX
+const EXT7_EDX_INVARIANT_TSC: u32 = 1 << 8;
+
+/// Features reported by CPUID on the boot CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuFeatures {
+    /// Highest basic leaf
+    pub max_leaf: u32,
+    /// Highest extended leaf (0x8000_xxxx)
+    pub max_extended_leaf: u32,
+    pub sse: bool,
+    pub sse2: bool,
+    pub sse3: bool,
+    pub ssse3: bool,
+    pub sse4_1: bool,
+    pub sse4_2: bool,
+    pub avx: bool,
+    pub avx2: bool,
+    /// XSAVE/XRSTOR and XCR0
+    pub xsave: bool,
+    /// No-execute page table bit
+    pub nx: bool,
+    /// 1 GiB pages
+    pub page_1gb: bool,
+    pub rdrand: bool,
+    pub rdseed: bool,
+    /// TSC runs at a constant rate in every P-, C- and T-state
+    pub invariant_tsc: bool,
+    pub x2apic: bool,
+    /// LAPIC timer TSC-deadline mode
+    pub tsc_deadline: bool,
+    pub smep: bool,
+    pub smap: bool,
+    /// Running under a hypervisor
+    pub hypervisor: bool,
+    /// MONITOR/MWAIT
+    pub monitor: bool,
+    /// MWAIT sub-states per C-state, 4 bits each from C0 up (CPUID leaf 5
+    /// EDX), or 0 if not enumerated
+    pub mwait_substates: u32,
+    /// LAPIC timer keeps running in deep C-states
+    pub arat: bool,
+}
+
+impl CpuFeatures {
+    /// Query CPUID on the calling CPU
+    pub fn detect() -> Self {
+        let max_leaf = __cpuid_count(0, 0).eax;
+        let max_extended_leaf = __cpuid_count(0x8000_0000, 0).eax;
+
+        let leaf1 = __cpuid_count(1, 0);
+        let leaf6_eax = if max_leaf >= 6 { __cpuid_count(6, 0).eax } else { 0 };
+        let leaf7_ebx = if max_leaf >= 7 { __cpuid_count(7, 0).ebx } else { 0 };
+        let mwait_substates = if max_leaf >= 5 {
+            let leaf5 = __cpuid_count(5, 0);
+            if leaf5.ecx & LEAF5_ECX_EMX != 0 { leaf5.edx } else { 0 }
+        } else {
+            0
+        };
+        let ext1_edx = if max_extended_leaf >= 0x8000_0001 {
+            __cpuid_count(0x8000_0001, 0).edx
+        } else {
+            0
+        };
+        let ext7_edx = if max_extended_leaf >= 0x8000_0007 {
+            __cpuid_count(0x8000_0007, 0).edx
+        } else {
+            0
+        };
+
+        CpuFeatures {
+            max_leaf,
+            max_extended_leaf,
+            sse: leaf1.edx & LEAF1_EDX_SSE != 0,
+            sse2: leaf1.edx & LEAF1_EDX_SSE2 != 0,
+            sse3: leaf1.ecx & LEAF1_ECX_SSE3 != 0,
+            ssse3: leaf1.ecx & LEAF1_ECX_SSSE3 != 0,
+            sse4_1: leaf1.ecx & LEAF1_ECX_SSE4_1 != 0,
+            sse4_2: leaf1.ecx & LEAF1_ECX_SSE4_2 != 0,
+            avx: leaf1.ecx & LEAF1_ECX_AVX != 0,
+            avx2: leaf7_ebx & LEAF7_EBX_AVX2 != 0,
+            xsave: leaf1.ecx & LEAF1_ECX_XSAVE != 0,
+            nx: ext1_edx & EXT1_EDX_NX != 0,
+            page_1gb: ext1_edx & EXT1_EDX_PAGE_1GB != 0,
+            rdrand: leaf1.ecx & LEAF1_ECX_RDRAND != 0,
+            rdseed: leaf7_ebx & LEAF7_EBX_RDSEED != 0,
+            invariant_tsc: ext7_edx & EXT7_EDX_INVARIANT_TSC != 0,
+            x2apic: leaf1.ecx & LEAF1_ECX_X2APIC != 0,
+            tsc_deadline: leaf1.ecx & LEAF1_ECX_TSC_DEADLINE != 0,
+            smep: leaf7_ebx & LEAF7_EBX_SMEP != 0,
+            smap: leaf7_ebx & LEAF7_EBX_SMAP != 0,
+            hypervisor: leaf1.ecx & LEAF1_ECX_HYPERVISOR != 0,
+            monitor: leaf1.ecx & LEAF1_ECX_MONITOR != 0,
+            mwait_substates,
+            arat: leaf6_eax & LEAF6_EAX_ARAT != 0,
+        }
+    }
+
+    /// Names and presence of the optional features, in report order
+    fn flags(&self) -> [(&'static str, bool); 21] {
+        [
+            ("sse", self.sse),
+            ("sse2", self.sse2),
+            ("sse3", self.sse3),
+            ("ssse3", self.ssse3),
+            ("sse4_1", self.sse4_1),
+            ("sse4_2", self.sse4_2),
+            ("avx", self.avx),
+            ("avx2", self.avx2),
+            ("xsave", self.xsave),
+            ("nx", self.nx),
+            ("pdpe1gb", self.page_1gb),
+            ("rdrand", self.rdrand),
+            ("rdseed", self.rdseed),
+            ("invariant_tsc", self.invariant_tsc),
+            ("x2apic", self.x2apic),
+            ("tsc_deadline", self.tsc_deadline),
+            ("smep", self.smep),
+            ("smap", self.smap),
+            ("hypervisor", self.hypervisor),
+            ("monitor", self.monitor),
+            ("arat", self.arat),
+        ]
+    }
+}
+
+static FEATURES: spin::Once<CpuFeatures> = spin::Once::new();
+
+/// Detect the boot CPU's features and log a capability report
+///
+/// Called first thing in `_start`, before anything consults [`get`].
+pub fn init() {
+    let features = get();
+
+    crate::serial_println!(
+        "[CPU] CPUID max leaf {:#x}, max extended leaf {:#x}",
+        features.max_leaf,
+        features.max_extended_leaf
+    );
+    let mut present = [""; 21];
+    let mut missing = [""; 21];
+    let (mut n_present, mut n_missing) = (0, 0);
+    for (name, supported) in features.flags() {
+        if supported {
+            present[n_present] = name;
+            n_present += 1;
+        } else {
+            missing[n_missing] = name;
+            n_missing += 1;
+        }
+    }
+    crate::serial_println!("[CPU] Supported: {}", Names(&present[..n_present]));
+    crate::serial_println!("[CPU] Missing: {}", Names(&missing[..n_missing]));
+}
+
+/// The boot CPU's features
+///
+/// Detects them on first use, so callers that run before [`init`] still
+/// get correct answers.
+pub fn get() -> &'static CpuFeatures {
+    FEATURES.call_once(CpuFeatures::detect)
+}
+
+/// Space-separated feature names, or "none"
+struct Names<'a>(&'a [&'static str]);
+
+impl core::fmt::Display for Names<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("none");
+        }
+        for (i, name) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(name)?;
+        }
+        Ok(())
+    }
+}
+
+crate::kernel_test! {
+    /// The cached features match a fresh CPUID query and include the x86_64 baseline
+    fn cpu_features_detect() {
+        let features = get();
+        crate::ktest_assert_eq!(*features, CpuFeatures::detect(), "cached features differ from CPUID");
+        crate::ktest_assert!(features.sse && features.sse2, "x86_64 baseline SSE2 missing");
+        crate::ktest_assert!(features.max_leaf >= 1, "CPUID leaf 1 unavailable");
+        Ok(())
+    }
+}
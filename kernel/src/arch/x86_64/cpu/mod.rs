@@ -0,0 +1,185 @@
+//! CPU protection features
+//!
+//! SMEP stops the kernel from executing user pages and SMAP stops it from
+//! reading or writing them. With SMAP on, the only way to touch user memory
+//! is to set RFLAGS.AC with `stac` for the duration of an access, which is
+//! exactly what [`user_access`] does. The sanctioned copy routines
+//! (`copy_from_user` / `copy_to_user` and friends) are the only callers, so a
+//! stray dereference of a user pointer anywhere else faults instead of
+//! silently trusting user memory.
+//!
+//! Both features are optional: on CPUs without them the CR4 bits are left
+//! clear and `stac`/`clac` become no-ops. What the CPU supports is read from
+//! [`features`], which caches CPUID for the whole kernel.
+
+pub mod features;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use features::CpuFeatures;
+
+/// CR4: Supervisor Mode Execution Prevention
+const CR4_SMEP: u64 = 1 << 20;
+/// CR4: Supervisor Mode Access Prevention
+const CR4_SMAP: u64 = 1 << 21;
+
+/// Whether SMEP has been enabled (set by the BSP)
+static SMEP_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Whether SMAP has been enabled; `stac`/`clac` are skipped when false
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable SMEP and SMAP on the calling CPU where supported
+///
+/// The BSP (CPU 0) decides which features are in use; APs only enable
+/// features the BSP enabled, so every CPU agrees on whether `stac`/`clac`
+/// are needed.
+///
+/// # Safety
+/// Must be called once per CPU during bring-up, before any code on that
+/// CPU touches user memory outside the copy routines.
+pub unsafe fn init_protection_features(cpu_id: usize) {
+    // Each CPU checks its own CPUID so a mismatched AP is caught below
+    let features = if cpu_id == 0 { *features::get() } else { CpuFeatures::detect() };
+
+    let (smep, smap) = if cpu_id == 0 {
+        SMEP_ENABLED.store(features.smep, Ordering::SeqCst);
+        SMAP_ENABLED.store(features.smap, Ordering::SeqCst);
+        (features.smep, features.smap)
+    } else {
+        (
+            features.smep && SMEP_ENABLED.load(Ordering::SeqCst),
+            features.smap && SMAP_ENABLED.load(Ordering::SeqCst),
+        )
+    };
+
+    if cpu_id != 0 && smap != SMAP_ENABLED.load(Ordering::SeqCst) {
+        // A CPU without SMAP would fault on `stac`; there is no sane way to
+        // continue with mixed support.
+        panic!("[CPU] CPU {} lacks SMAP but the BSP enabled it", cpu_id);
+    }
+
+    let mut cr4: u64;
+    core::arch::asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    if smep {
+        cr4 |= CR4_SMEP;
+    }
+    if smap {
+        cr4 |= CR4_SMAP;
+        // Start with AC clear so user access is denied by default
+        clac_raw();
+    }
+    core::arch::asm!("mov cr4, {}", in(reg) cr4, options(nostack, preserves_flags));
+
+    crate::serial_println!(
+        "[CPU] CPU {} SMEP {}, SMAP {}",
+        cpu_id,
+        if smep { "enabled" } else { "unavailable" },
+        if smap { "enabled" } else { "unavailable" }
+    );
+}
+
+/// Whether SMEP is enabled
+pub fn smep_enabled() -> bool {
+    SMEP_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Whether SMAP is enabled
+pub fn smap_enabled() -> bool {
+    SMAP_ENABLED.load(Ordering::Relaxed)
+}
+
+#[inline(always)]
+unsafe fn stac_raw() {
+    core::arch::asm!("stac", options(nostack));
+}
+
+#[inline(always)]
+unsafe fn clac_raw() {
+    core::arch::asm!("clac", options(nostack));
+}
+
+/// Deny supervisor access to user pages (clear RFLAGS.AC)
+///
+/// Called on kernel entry so a user task cannot enter with AC set and
+/// bypass SMAP.
+#[inline(always)]
+pub fn clac() {
+    if SMAP_ENABLED.load(Ordering::Relaxed) {
+        unsafe { clac_raw() };
+    }
+}
+
+/// Open a window in which the kernel may access user pages
+///
+/// Access is closed again when the guard is dropped. Keep the window as
+/// small as possible; only the user copy routines should need it.
+#[inline(always)]
+pub fn user_access() -> UserAccessGuard {
+    if SMAP_ENABLED.load(Ordering::Relaxed) {
+        unsafe { stac_raw() };
+    }
+    UserAccessGuard { _private: () }
+}
+
+/// Guard returned by [`user_access`]
+pub struct UserAccessGuard {
+    _private: (),
+}
+
+impl Drop for UserAccessGuard {
+    #[inline(always)]
+    fn drop(&mut self) {
+        clac();
+    }
+}
+
+/// Copy `len` bytes between kernel and user memory with user access enabled
+///
+/// # Safety
+/// Both ranges must be valid for `len` bytes and must not overlap. The
+/// caller is responsible for having validated the user side.
+pub unsafe fn copy_user_bytes(dst: *mut u8, src: *const u8, len: usize) {
+    let _access = user_access();
+    core::ptr::copy_nonoverlapping(src, dst, len);
+}
+
+crate::kernel_test! {
+    /// The sanctioned copy routines can reach a user page with SMAP enabled
+    fn user_copy_under_smap() {
+        use crate::arch::x86_64::syscall::{copy_from_user, copy_to_user};
+        use crate::mm::paging::PageTableFlags;
+        use crate::mm::with_memory_managers;
+
+        const USER_SCRATCH: usize = 0x7F00_0000_0000;
+        let scratch = crate::mm::VirtAddr::new(USER_SCRATCH as u64);
+
+        let frame = with_memory_managers(|pmm, mapper| {
+            let frame = pmm.alloc_frame().ok_or("Out of memory")?;
+            mapper.map_page(
+                scratch,
+                frame,
+                PageTableFlags::PRESENT
+                    | PageTableFlags::WRITABLE
+                    | PageTableFlags::USER
+                    | PageTableFlags::NO_EXECUTE,
+                pmm,
+            )?;
+            Ok(frame)
+        })?;
+
+        let pattern = *b"smap-copy-test";
+        let mut readback = [0u8; 14];
+        let copied = copy_to_user(USER_SCRATCH + 8, &pattern)
+            .and_then(|_| copy_from_user(&mut readback, USER_SCRATCH + 8, pattern.len()));
+
+        with_memory_managers(|pmm, mapper| {
+            mapper.unmap_page(scratch)?;
+            unsafe { crate::mm::tlb::flush_page(scratch) };
+            pmm.free_frame(frame);
+            Ok(())
+        })?;
+
+        crate::ktest_assert!(copied.is_ok(), "user copy failed");
+        crate::ktest_assert_eq!(readback, pattern, "user copy round trip mismatch");
+        Ok(())
+    }
+}
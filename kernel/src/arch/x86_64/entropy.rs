@@ -0,0 +1,114 @@
+//! Hardware entropy sources
+//!
+//! RDSEED returns conditioned output straight from the CPU's entropy source,
+//! RDRAND the output of a DRBG reseeded from it. Both can transiently fail
+//! and are retried a bounded number of times. When neither instruction is
+//! available we fall back to TSC jitter: the low bits of back-to-back TSC
+//! deltas around a memory access vary with cache, pipeline and interrupt
+//! state, and are folded together into one word.
+//!
+//! These are raw sources used to seed `crate::rand`; anything that needs
+//! random numbers should ask that module instead.
+
+use super::cpu::features;
+
+/// How often to retry RDRAND/RDSEED before giving up
+const RETRIES: usize = 16;
+
+/// Whether the CPU implements RDRAND
+pub fn has_rdrand() -> bool {
+    features::get().rdrand
+}
+
+/// Whether the CPU implements RDSEED
+pub fn has_rdseed() -> bool {
+    features::get().rdseed
+}
+
+/// Read one word from RDSEED, or None if unavailable or exhausted
+pub fn rdseed64() -> Option<u64> {
+    if !has_rdseed() {
+        return None;
+    }
+
+    for _ in 0..RETRIES {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            core::arch::asm!(
+                "rdseed {val}",
+                "setc {ok}",
+                val = out(reg) value,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack)
+            );
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+/// Read one word from RDRAND, or None if unavailable or failing
+pub fn rdrand64() -> Option<u64> {
+    if !has_rdrand() {
+        return None;
+    }
+
+    for _ in 0..RETRIES {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            core::arch::asm!(
+                "rdrand {val}",
+                "setc {ok}",
+                val = out(reg) value,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack)
+            );
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Read the time stamp counter
+#[inline]
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        core::arch::asm!(
+            "rdtsc",
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// Gather one word of entropy from TSC jitter
+///
+/// Slow (a few thousand cycles) and of modest quality; only used when the
+/// CPU has no RDSEED/RDRAND.
+pub fn tsc_jitter64() -> u64 {
+    let mut pool: u64 = rdtsc();
+    let mut scratch = [0u64; 16];
+
+    for round in 0..64 {
+        let start = rdtsc();
+        // Touch memory in a data-dependent pattern to perturb timing
+        let index = (pool as usize ^ round) % scratch.len();
+        scratch[index] = scratch[index].wrapping_add(pool);
+        unsafe { core::ptr::read_volatile(&scratch[index]) };
+        let delta = rdtsc().wrapping_sub(start);
+
+        pool = (pool.rotate_left(7) ^ delta).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    }
+    pool
+}
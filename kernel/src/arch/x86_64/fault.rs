@@ -3,7 +3,13 @@
 //! This module implements the page fault handler for memory protection
 //! in user-mode processes. It detects user space faults and terminates
 //! processes that access invalid memory.
+//!
+//! It also owns the double fault handler. A kernel stack overflow usually
+//! escalates to a double fault (the CPU cannot push the page fault frame onto
+//! the overflowed stack), so both handlers check whether the faulting address
+//! lies in a kernel stack guard page and report the owning task.
 
+use crate::mm::VirtAddr;
 use crate::sched;
 use crate::serial_println;
 use crate::user::process::{ProcessManager, ProcessState};
@@ -19,6 +25,7 @@ const PF_INSTR: u64 = 1 << 4; // Fault was caused by instruction fetch
 ///
 /// This function is called when a page fault occurs. It analyzes the fault
 /// and determines the appropriate action:
+/// - Not-present faults on anonymous mappings: populate the page
 /// - User space faults: Terminate the process
 /// - Kernel space faults: Panic (should not happen in normal operation)
 ///
@@ -47,6 +54,15 @@ pub extern "C" fn page_fault_handler(error_code: u64, fault_addr: u64, rip: u64)
         fault_addr
     };
 
+    // First touch of a demand-paged user page, from user space or from a
+    // kernel copy to/from user memory: back the page and retry the access
+    if (error_code & (PF_PRESENT | PF_RESERVED)) == 0
+        && actual_fault_addr < crate::user::process::USER_LIMIT as u64
+        && crate::mm::demand::handle_fault(VirtAddr::new(actual_fault_addr), (error_code & PF_WRITE) != 0)
+    {
+        return;
+    }
+
     serial_println!(
         "[FAULT][cpu{}] Page fault at RIP=0x{:x}, fault_addr=0x{:x}, error=0x{:x}",
         cpu_id,
@@ -105,6 +121,9 @@ fn handle_user_page_fault(fault_addr: u64, error_code: u64, rip: u64) {
 
     let current_task_id = current_task_info.0;
 
+    // There is no backing store, so a user fault never needs I/O
+    sched::charge_current(|usage| usage.record_fault(false));
+
     serial_println!(
         "[FAULT][cpu{}] User process {} page fault:",
         cpu_id,
@@ -219,6 +238,10 @@ fn handle_user_page_fault(fault_addr: u64, error_code: u64, rip: u64) {
 fn handle_kernel_page_fault(fault_addr: u64, error_code: u64, rip: u64) -> ! {
     let cpu_id = unsafe { crate::arch::x86_64::smp::percpu::percpu_current().id };
 
+    if let Some(stack_bottom) = VirtAddr::try_new(fault_addr).and_then(crate::mm::kstack::guard_page_hit) {
+        report_stack_overflow(cpu_id, fault_addr, rip, stack_bottom);
+    }
+
     serial_println!("[FAULT][cpu{}] CRITICAL: Kernel page fault!", cpu_id);
     serial_println!("[FAULT]   Fault address: 0x{:x}", fault_addr);
     serial_println!("[FAULT]   Instruction pointer: 0x{:x}", rip);
@@ -236,6 +259,18 @@ fn handle_kernel_page_fault(fault_addr: u64, error_code: u64, rip: u64) -> ! {
     serial_println!("[FAULT]   Reserved bit: {}", reserved);
     serial_println!("[FAULT]   Instruction fetch: {}", instruction_fetch);
 
+    // Kernel-mode access to a user page is a SMEP/SMAP violation
+    if fault_addr < crate::user::process::USER_LIMIT as u64 && present {
+        use crate::arch::x86_64::cpu;
+        if instruction_fetch && cpu::smep_enabled() {
+            serial_println!("[FAULT] SMEP violation: kernel executed a user page");
+        } else if !instruction_fetch && cpu::smap_enabled() {
+            serial_println!(
+                "[FAULT] SMAP violation: user memory accessed outside copy_from_user/copy_to_user"
+            );
+        }
+    }
+
     // Check if this might be a stack overflow
     if fault_addr < 0x1000 {
         serial_println!("[FAULT] Possible null pointer dereference");
@@ -267,6 +302,79 @@ fn handle_kernel_page_fault(fault_addr: u64, error_code: u64, rip: u64) -> ! {
     );
 }
 
+/// Report a kernel stack guard page hit and panic
+///
+/// # Arguments
+/// * `cpu_id` - CPU the fault occurred on
+/// * `fault_addr` - Faulting virtual address (inside the guard area)
+/// * `rip` - Instruction pointer where fault occurred
+/// * `stack_bottom` - Lowest mapped address of the overflowed stack
+fn report_stack_overflow(cpu_id: usize, fault_addr: u64, rip: u64, stack_bottom: VirtAddr) -> ! {
+    serial_println!(
+        "[FAULT][cpu{}] Kernel stack guard page hit at 0x{:x} (stack bottom 0x{:x})",
+        cpu_id,
+        fault_addr,
+        stack_bottom
+    );
+
+    match sched::find_task_by_stack(stack_bottom) {
+        Some(task) => panic!(
+            "[FAULT] stack overflow in task '{}' (id {}) at 0x{:x} from RIP 0x{:x}",
+            task.name, task.id, fault_addr, rip
+        ),
+        None => panic!(
+            "[FAULT] stack overflow in unknown task at 0x{:x} from RIP 0x{:x}",
+            fault_addr, rip
+        ),
+    }
+}
+
+/// Double fault handler
+///
+/// Double faults are aborts: the interrupted context cannot be resumed. The
+/// handler runs on its own IST stack, so it still works when the fault was
+/// caused by a kernel stack overflow.
+///
+/// # Arguments
+/// * `error_code` - Always 0 for double faults
+/// * `rip` - Instruction pointer where the fault occurred (may be unreliable)
+/// * `cr2` - CR2 at the time of the fault (address of the original page fault)
+#[no_mangle]
+pub extern "C" fn double_fault_handler(error_code: u64, rip: u64, cr2: u64) -> ! {
+    let cpu_id = crate::arch::x86_64::smp::percpu::percpu_current().id;
+
+    if let Some(stack_bottom) = VirtAddr::try_new(cr2).and_then(crate::mm::kstack::guard_page_hit) {
+        report_stack_overflow(cpu_id, cr2, rip, stack_bottom);
+    }
+
+    serial_println!("[FAULT][cpu{}] CRITICAL: Double fault!", cpu_id);
+    serial_println!("[FAULT]   Instruction pointer: 0x{:x}", rip);
+    serial_println!("[FAULT]   CR2: 0x{:x}", cr2);
+    serial_println!("[FAULT]   Error code: 0x{:x}", error_code);
+
+    panic!("[FAULT] Double fault from RIP 0x{:x}", rip);
+}
+
+/// Assembly wrapper for double fault handler
+///
+/// The CPU has already switched to the IST stack and pushed an error code,
+/// so this only marshals the arguments; the handler never returns.
+#[unsafe(naked)]
+#[no_mangle]
+pub extern "C" fn double_fault_wrapper() {
+    core::arch::naked_asm!(
+        crate::swapgs_if_user!(16), // CS is above the error code and RIP
+        "mov rdi, [rsp]",         // error_code -> first argument
+        "mov rsi, [rsp + 8]",     // rip -> second argument
+        "mov rdx, cr2",           // cr2 -> third argument
+        "and rsp, -16",           // System V alignment for the call
+        "call {handler}",
+        "ud2",
+
+        handler = sym double_fault_handler,
+    )
+}
+
 /// Assembly wrapper for page fault handler
 ///
 /// This function is called from the IDT entry and sets up the proper
@@ -275,6 +383,9 @@ fn handle_kernel_page_fault(fault_addr: u64, error_code: u64, rip: u64) -> ! {
 #[no_mangle]
 pub extern "C" fn page_fault_wrapper() {
     core::arch::naked_asm!(
+        // CS is above the error code and RIP
+        crate::swapgs_if_user!(16),
+
         // Save all registers
         "push rax",
         "push rcx",
@@ -328,30 +439,50 @@ pub extern "C" fn page_fault_wrapper() {
         "add rsp, 8",
 
         // Return from interrupt
+        crate::swapgs_if_user!(8),
         "iretq",
 
         handler = sym page_fault_handler,
     )
 }
 
-/// Initialize page fault handler in IDT
+/// Page fault exception vector
+const PAGE_FAULT_VECTOR: u8 = 14;
+
+/// Double fault exception vector
+const DOUBLE_FAULT_VECTOR: u8 = 8;
+
+/// TSS IST index reserved for the double fault handler (see gdt.rs)
+const DOUBLE_FAULT_IST: u8 = 2;
+
+/// Initialize page fault and double fault handlers in IDT
 ///
-/// This function should be called during kernel initialization to set up
-/// the page fault handler in the Interrupt Descriptor Table.
+/// This function should be called during kernel initialization, after the IDT
+/// has been created, to set up the fault handlers in the Interrupt Descriptor Table.
+///
+/// The page fault handler runs on the current stack rather than IST3: user
+/// faults may yield from inside the handler, and a shared per-CPU IST stack
+/// would be clobbered by the next fault. A page fault that cannot push its
+/// frame (kernel stack overflow) escalates to a double fault, which does use
+/// its dedicated IST stack.
 ///
 /// # Safety
 /// This function modifies the IDT and should only be called during kernel init.
 pub unsafe fn init_page_fault_handler() {
     serial_println!("[FAULT] Initializing page fault handler...");
 
-    // TODO: Set up IDT entry for page fault (interrupt 14)
-    // This would involve:
-    // 1. Getting a reference to the IDT
-    // 2. Setting entry 14 to point to page_fault_wrapper
-    // 3. Configuring the entry as an interrupt gate with IST if needed
+    crate::sched::timer::set_idt_gate(
+        PAGE_FAULT_VECTOR,
+        page_fault_wrapper as *const () as usize,
+        0,
+    );
+    crate::sched::timer::set_idt_gate(
+        DOUBLE_FAULT_VECTOR,
+        double_fault_wrapper as *const () as usize,
+        DOUBLE_FAULT_IST,
+    );
 
-    // For now, we'll just log that the handler is ready
-    serial_println!("[FAULT] Page fault handler ready (IDT setup TODO)");
+    serial_println!("[FAULT] Page fault and double fault handlers registered");
 }
 
 /// Test function for page fault handling
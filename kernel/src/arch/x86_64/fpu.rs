@@ -0,0 +1,210 @@
+//! FPU, SSE and AVX state
+//!
+//! The kernel is built without SIMD (soft float), so only user code uses
+//! the x87 and vector registers. Every task has a save area, and
+//! `sched::context::context_switch` saves the outgoing task's state there
+//! and loads the incoming task's on every switch. The kernel never touches
+//! the registers in between, so no lazy #NM trapping is needed.
+//!
+//! With XSAVE the area holds the components this CPU has among x87, SSE,
+//! AVX and AVX-512, and its size comes from CPUID leaf 0xD. Without XSAVE it
+//! is the 512-byte FXSAVE area. New tasks start from a copy of the state
+//! saved right after `fninit`, with all SIMD exceptions masked.
+
+use crate::mm::allocator::kmalloc;
+use super::cpu::features::{self, CpuFeatures};
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// CR0: monitor coprocessor (`wait` honours TS)
+const CR0_MP: u64 = 1 << 1;
+/// CR0: x87 emulation (no FPU)
+const CR0_EM: u64 = 1 << 2;
+/// CR0: task switched (FPU use traps with #NM)
+const CR0_TS: u64 = 1 << 3;
+/// CR0: native x87 error reporting
+const CR0_NE: u64 = 1 << 5;
+
+/// CR4: FXSAVE/FXRSTOR and SSE enabled
+const CR4_OSFXSR: u64 = 1 << 9;
+/// CR4: unmasked SIMD exceptions raise #XM
+const CR4_OSXMMEXCPT: u64 = 1 << 10;
+/// CR4: XSAVE and XCR0 enabled
+const CR4_OSXSAVE: u64 = 1 << 18;
+
+/// XCR0 components: x87, SSE, AVX
+const XCR0_BASE: u64 = 0b111;
+/// XCR0 components: AVX-512 opmask, upper ZMM0-15, ZMM16-31
+const XCR0_AVX512: u64 = 0b1110_0000;
+
+/// Size of the FXSAVE area
+const FXSAVE_SIZE: usize = 512;
+
+/// Largest save area supported; bigger XSAVE layouts drop AVX-512
+const MAX_AREA_SIZE: usize = 4096;
+
+/// Save areas must be 64-byte aligned for XSAVE
+const AREA_ALIGN: usize = 64;
+
+/// MXCSR after reset: all SIMD exceptions masked, round to nearest
+const MXCSR_DEFAULT: u32 = 0x1F80;
+
+/// Whether the save areas use XSAVE (read by `context_switch`)
+pub(crate) static XSAVE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// XCR0 chosen by the BSP, loaded on every CPU
+static XCR0: AtomicU64 = AtomicU64::new(0);
+
+/// Size of a task's save area; 0 until the BSP has run [`init`]
+static AREA_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+#[repr(C, align(64))]
+struct SaveArea([u8; MAX_AREA_SIZE]);
+
+/// Initial state copied into new tasks' areas, written once by the BSP
+static mut TEMPLATE: SaveArea = SaveArea([0; MAX_AREA_SIZE]);
+
+unsafe fn xsetbv(xcr0: u64) {
+    core::arch::asm!(
+        "xsetbv",
+        in("ecx") 0,
+        in("eax") xcr0 as u32,
+        in("edx") (xcr0 >> 32) as u32,
+        options(nomem, nostack, preserves_flags)
+    );
+}
+
+/// Save the current state to `area` (XSAVE or FXSAVE as configured)
+unsafe fn save(area: *mut u8) {
+    if XSAVE_ENABLED.load(Ordering::Relaxed) {
+        core::arch::asm!(
+            "xsave64 [{}]",
+            in(reg) area,
+            in("eax") u32::MAX,
+            in("edx") u32::MAX,
+            options(nostack, preserves_flags)
+        );
+    } else {
+        core::arch::asm!("fxsave64 [{}]", in(reg) area, options(nostack, preserves_flags));
+    }
+}
+
+/// Enable the FPU, SSE and, where supported, XSAVE on the calling CPU
+///
+/// The BSP (CPU 0) chooses the XSAVE components and saves the initial
+/// task state; APs load the same XCR0, so every save area has one layout.
+///
+/// # Safety
+/// Must be called once per CPU during bring-up, before any task runs on it.
+pub unsafe fn init(cpu_id: usize) {
+    // APs query their own CPUID so a mismatch with the BSP is caught
+    let xsave_supported = if cpu_id == 0 { features::get().xsave } else { CpuFeatures::detect().xsave };
+    if cpu_id == 0 {
+        XSAVE_ENABLED.store(xsave_supported, Ordering::SeqCst);
+        if xsave_supported {
+            // Leaf 0xD, subleaf 0: EAX has the XCR0 bits the CPU supports
+            let supported = __cpuid_count(0xD, 0).eax as u64;
+            XCR0.store(supported & (XCR0_BASE | XCR0_AVX512), Ordering::SeqCst);
+        }
+    } else if XSAVE_ENABLED.load(Ordering::SeqCst) && !xsave_supported {
+        panic!("[FPU] CPU {} lacks XSAVE but the BSP enabled it", cpu_id);
+    }
+    let xsave = XSAVE_ENABLED.load(Ordering::SeqCst);
+
+    let mut cr0: u64;
+    core::arch::asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+    cr0 = (cr0 & !(CR0_EM | CR0_TS)) | CR0_MP | CR0_NE;
+    core::arch::asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+
+    let mut cr4: u64;
+    core::arch::asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    cr4 |= CR4_OSFXSR | CR4_OSXMMEXCPT;
+    if xsave {
+        cr4 |= CR4_OSXSAVE;
+    }
+    core::arch::asm!("mov cr4, {}", in(reg) cr4, options(nostack, preserves_flags));
+
+    if xsave {
+        xsetbv(XCR0.load(Ordering::SeqCst));
+    }
+
+    let mxcsr = MXCSR_DEFAULT;
+    core::arch::asm!("fninit", "ldmxcsr [{}]", in(reg) &mxcsr, options(nostack, preserves_flags));
+
+    if cpu_id == 0 {
+        let mut size = FXSAVE_SIZE;
+        if xsave {
+            // Leaf 0xD, subleaf 0: EBX is the area size for the current XCR0
+            size = __cpuid_count(0xD, 0).ebx as usize;
+            if size > MAX_AREA_SIZE {
+                XCR0.fetch_and(!XCR0_AVX512, Ordering::SeqCst);
+                xsetbv(XCR0.load(Ordering::SeqCst));
+                size = __cpuid_count(0xD, 0).ebx as usize;
+            }
+        }
+        save(core::ptr::addr_of_mut!(TEMPLATE) as *mut u8);
+        AREA_SIZE.store(size, Ordering::SeqCst);
+    }
+
+    crate::serial_println!(
+        "[FPU] CPU {} FPU/SSE enabled, {} (xcr0=0x{:x}, area {} bytes)",
+        cpu_id,
+        if xsave { "XSAVE" } else { "FXSAVE" },
+        XCR0.load(Ordering::Relaxed),
+        AREA_SIZE.load(Ordering::Relaxed)
+    );
+}
+
+/// Allocate a save area holding the initial FPU state, for a new task
+///
+/// Returns a null pointer before the BSP has run [`init`]; such a task
+/// never has its FPU state switched.
+pub fn alloc_area() -> Result<*mut u8, &'static str> {
+    let size = AREA_SIZE.load(Ordering::Acquire);
+    if size == 0 {
+        return Ok(core::ptr::null_mut());
+    }
+    let area = kmalloc(size);
+    if area.is_null() {
+        return Err("Out of memory");
+    }
+    if area as usize % AREA_ALIGN != 0 {
+        crate::mm::allocator::kfree(area, size);
+        return Err("Misaligned FPU save area");
+    }
+    unsafe {
+        core::ptr::copy_nonoverlapping(core::ptr::addr_of!(TEMPLATE) as *const u8, area, size);
+    }
+    Ok(area)
+}
+
+/// Free a save area from [`alloc_area`]
+///
+/// # Safety
+/// The task owning `area` must never be switched to again.
+pub unsafe fn free_area(area: *mut u8) {
+    if !area.is_null() {
+        crate::mm::allocator::kfree(area, AREA_SIZE.load(Ordering::Acquire));
+    }
+}
+
+crate::kernel_test! {
+    /// New save areas start from the initial state: x87 control word with
+    /// all exceptions masked and the default MXCSR
+    fn fpu_initial_area() {
+        let area = alloc_area()?;
+        if area.is_null() {
+            return Ok(());
+        }
+        let (fcw, mxcsr) = unsafe {
+            (
+                core::ptr::read_unaligned(area as *const u16),
+                core::ptr::read_unaligned(area.add(24) as *const u32),
+            )
+        };
+        crate::mm::allocator::kfree(area, AREA_SIZE.load(Ordering::Relaxed));
+        crate::ktest_assert_eq!(fcw, 0x037F, "x87 control word not initialized");
+        crate::ktest_assert_eq!(mxcsr, MXCSR_DEFAULT, "MXCSR not initialized");
+        Ok(())
+    }
+}
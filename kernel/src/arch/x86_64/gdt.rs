@@ -8,10 +8,13 @@ use crate::serial_println;
 use core::mem::size_of;
 
 /// GDT segment selectors
+///
+/// User data comes before user code: SYSRET loads SS from STAR[63:48] + 8
+/// and CS from STAR[63:48] + 16, so both derive from one base (0x30).
 pub const KERNEL_CODE_SEG: u16 = 0x28; // Ring 0 code (from Limine)
 pub const KERNEL_DATA_SEG: u16 = 0x30; // Ring 0 data (from Limine)
-pub const USER_CODE_SEG: u16 = 0x3B; // Ring 3 code (0x38 | 3)
-pub const USER_DATA_SEG: u16 = 0x43; // Ring 3 data (0x40 | 3)
+pub const USER_DATA_SEG: u16 = 0x3B; // Ring 3 data (0x38 | 3)
+pub const USER_CODE_SEG: u16 = 0x43; // Ring 3 code (0x40 | 3)
 pub const TSS_SEG: u16 = 0x48; // TSS segment
 
 /// GDT entry structure (8 bytes)
@@ -204,8 +207,8 @@ struct Gdt {
     kernel_data_32: GdtEntry, // 0x20: Kernel data (32-bit, unused)
     kernel_code: GdtEntry,    // 0x28: Kernel code (64-bit) - Ring 0
     kernel_data: GdtEntry,    // 0x30: Kernel data (64-bit) - Ring 0
-    user_code: GdtEntry,      // 0x38: User code (64-bit) - Ring 3
-    user_data: GdtEntry,      // 0x40: User data (64-bit) - Ring 3
+    user_data: GdtEntry,      // 0x38: User data (64-bit) - Ring 3
+    user_code: GdtEntry,      // 0x40: User code (64-bit) - Ring 3
     tss: TssEntry,            // 0x48: TSS (16 bytes)
 }
 
@@ -220,8 +223,8 @@ impl Gdt {
             kernel_data_32: GdtEntry::null(),       // Unused
             kernel_code: GdtEntry::code_segment(0), // Ring 0
             kernel_data: GdtEntry::data_segment(0), // Ring 0
-            user_code: GdtEntry::code_segment(3),   // Ring 3
             user_data: GdtEntry::data_segment(3),   // Ring 3
+            user_code: GdtEntry::code_segment(3),   // Ring 3
             tss: TssEntry::new(tss_addr),
         }
     }
@@ -343,9 +346,17 @@ pub fn init_gdt_tss_for_cpu(cpu_id: usize) -> Result<(), &'static str> {
         serial_println!("[GDT] TSS address: 0x{:x}", tss_addr);
         serial_println!("[GDT] GDT address: 0x{:x}", gdt_ptr as u64);
 
+        // Entry from user mode lands on the running task's kernel stack;
+        // context_switch updates RSP0 through this pointer
+        let percpu = crate::arch::x86_64::smp::percpu::percpu_for_mut(cpu_id);
+        percpu.tss_rsp0 = core::ptr::addr_of_mut!(tss.rsp0) as u64;
+
         // Initialize syscall MSRs for fast syscall support
         crate::arch::x86_64::syscall::init_syscall_msrs(cpu_id);
         serial_println!("[GDT] CPU {} syscall MSRs initialized", cpu_id);
+
+        // Enable SMEP/SMAP before this CPU can run anything that touches user memory
+        crate::arch::x86_64::cpu::init_protection_features(cpu_id);
     }
 
     Ok(())
@@ -605,7 +616,7 @@ mod tests {
         let gdt = Gdt::new(mock_tss_addr);
 
         // Verify segment selectors match expected offsets
-        // null = 0x00, kernel_code = 0x28 (offset 5), user_code = 0x38 (offset 7)
+        // null = 0x00, kernel_code = 0x28 (offset 5), user_data = 0x38 (offset 7)
         // The actual GDT layout should match our constants
 
         // We can't easily test the exact memory layout without unsafe code,
@@ -653,8 +664,8 @@ mod tests {
         // Verify segment selector constants are correct
         assert_eq!(KERNEL_CODE_SEG, 0x28);
         assert_eq!(KERNEL_DATA_SEG, 0x30);
-        assert_eq!(USER_CODE_SEG, 0x3B); // 0x38 | 3 (RPL=3)
-        assert_eq!(USER_DATA_SEG, 0x43); // 0x40 | 3 (RPL=3)
+        assert_eq!(USER_DATA_SEG, 0x3B); // 0x38 | 3 (RPL=3)
+        assert_eq!(USER_CODE_SEG, 0x43); // 0x40 | 3 (RPL=3)
         assert_eq!(TSS_SEG, 0x48);
 
         // Verify RPL bits are correct for user segments
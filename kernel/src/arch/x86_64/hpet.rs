@@ -0,0 +1,125 @@
+//! HPET (High Precision Event Timer)
+//!
+//! Only the main counter is used, as the reference clock for calibrating
+//! the local APIC timer and the TSC, and as the clock source when the CPUs'
+//! TSCs cannot be synchronized. Machines without legacy hardware may have no
+//! working PIT, so the HPET is preferred whenever the firmware describes
+//! one in the ACPI HPET table. Its registers are memory mapped below 4 GiB
+//! and accessed through the identity map, like the local APIC's.
+
+use crate::io::mmio::MmioRegion;
+use crate::mm::addr::PhysAddr;
+
+/// Size of the register block
+pub const HPET_MMIO_SIZE: usize = 0x400;
+
+/// General Capabilities and ID register offset
+const HPET_CAPABILITIES: usize = 0x00;
+
+/// General Configuration register offset
+const HPET_CONFIG: usize = 0x10;
+
+/// Main Counter Value register offset
+const HPET_MAIN_COUNTER: usize = 0xF0;
+
+/// Capabilities: the main counter is 64 bits wide
+const CAP_COUNT_SIZE_64: u64 = 1 << 13;
+
+/// Configuration: the main counter runs
+const CONFIG_ENABLE: u64 = 1 << 0;
+
+/// Longest counter period the specification allows (100 ns), in femtoseconds
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+const FS_PER_US: u64 = 1_000_000_000;
+
+/// The HPET found at boot
+#[derive(Debug)]
+pub struct Hpet {
+    regs: MmioRegion,
+    /// Main counter period in femtoseconds
+    period_fs: u64,
+    /// Mask of the counter bits (32 or 64)
+    counter_mask: u64,
+}
+
+static HPET: spin::Once<Hpet> = spin::Once::new();
+
+impl Hpet {
+    fn read(&self, offset: usize) -> u64 {
+        self.regs.read(offset)
+    }
+
+    fn write(&self, offset: usize, value: u64) {
+        self.regs.write(offset, value)
+    }
+
+    /// Current value of the main counter
+    pub fn counter(&self) -> u64 {
+        self.read(HPET_MAIN_COUNTER) & self.counter_mask
+    }
+
+    /// Mask of the main counter's bits; it wraps past this value
+    pub fn counter_mask(&self) -> u64 {
+        self.counter_mask
+    }
+
+    /// Main counter frequency in Hz
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period_fs
+    }
+
+    /// Spin for `us` microseconds
+    pub fn busy_wait_us(&self, us: u64) {
+        let ticks = us * FS_PER_US / self.period_fs;
+        let start = self.counter();
+        while self.counter().wrapping_sub(start) & self.counter_mask < ticks {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Start the main counter of the HPET at `base`
+///
+/// Returns None if the registers do not describe a usable counter.
+///
+/// # Safety
+/// `base` must be the physical address from the ACPI HPET table, and the
+/// HPET must not be in use by anything else.
+pub unsafe fn init(base: u64) -> Option<&'static Hpet> {
+    if base == 0 || base >= 1 << 32 {
+        return None;
+    }
+    let regs = MmioRegion::new("hpet", PhysAddr::new(base), HPET_MMIO_SIZE);
+    let probe = Hpet { regs, period_fs: 0, counter_mask: 0 };
+    let capabilities = probe.read(HPET_CAPABILITIES);
+    let period_fs = capabilities >> 32;
+    if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+        return None;
+    }
+    let counter_mask = if capabilities & CAP_COUNT_SIZE_64 != 0 { u64::MAX } else { u32::MAX as u64 };
+
+    let hpet = HPET.call_once(|| Hpet { regs, period_fs, counter_mask });
+    let config = hpet.read(HPET_CONFIG);
+    if config & CONFIG_ENABLE == 0 {
+        hpet.write(HPET_CONFIG, config | CONFIG_ENABLE);
+    }
+    Some(hpet)
+}
+
+/// The HPET, if one was found and started
+pub fn get() -> Option<&'static Hpet> {
+    HPET.get()
+}
+
+crate::kernel_test! {
+    /// The main counter advances by about the requested time
+    fn hpet_counter_advances() {
+        let Some(hpet) = get() else { return Ok(()) };
+        let start = hpet.counter();
+        hpet.busy_wait_us(1000);
+        let elapsed = hpet.counter().wrapping_sub(start) & hpet.counter_mask;
+        crate::ktest_assert!(elapsed >= hpet.frequency() / 1000, "counter did not advance 1 ms");
+        Ok(())
+    }
+}
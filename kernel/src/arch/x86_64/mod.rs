@@ -1,10 +1,19 @@
 /// x86_64 architecture-specific modules
 pub mod acpi;
 pub mod apic;
+pub mod cpu;
+pub mod entropy;
 pub mod fault;
+pub mod fpu;
 pub mod gdt;
+pub mod hpet;
+pub mod msr;
+pub mod reset;
+pub mod rtc;
 pub mod smp;
+pub mod spurious;
 pub mod syscall;
+pub mod vdso;
 
 // Re-export user_entry_trampoline for external use
 pub use gdt::user_entry_trampoline;
@@ -0,0 +1,71 @@
+//! Model-specific registers
+//!
+//! `rdmsr`/`wrmsr` wrappers and the MSR numbers the kernel uses. Accessing
+//! an MSR the CPU does not implement raises #GP, and most writes change
+//! how the CPU behaves, so both are unsafe; callers check CPUID (see
+//! `cpu::features`) before touching optional MSRs.
+
+/// Time-stamp counter
+pub const IA32_TSC: u32 = 0x10;
+/// Local APIC base address and mode (xAPIC, x2APIC)
+pub const IA32_APIC_BASE: u32 = 0x1B;
+/// Per-CPU adjustment added to the TSC
+pub const IA32_TSC_ADJUST: u32 = 0x3B;
+/// LAPIC timer deadline in TSC-deadline mode (0 disarms it)
+pub const IA32_TSC_DEADLINE: u32 = 0x6E0;
+/// First x2APIC register; register `offset` of the xAPIC MMIO page is at
+/// `X2APIC_BASE + offset / 16`
+pub const X2APIC_BASE: u32 = 0x800;
+/// Extended feature enables (SCE, LME, NXE)
+pub const EFER: u32 = 0xC000_0080;
+/// SYSCALL/SYSRET segment selectors
+pub const STAR: u32 = 0xC000_0081;
+/// SYSCALL entry point
+pub const LSTAR: u32 = 0xC000_0082;
+/// RFLAGS bits cleared on SYSCALL
+pub const SFMASK: u32 = 0xC000_0084;
+/// FS segment base
+pub const FS_BASE: u32 = 0xC000_0100;
+/// GS segment base
+pub const GS_BASE: u32 = 0xC000_0101;
+/// GS base swapped in by `swapgs`
+pub const KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+/// Read a model-specific register
+///
+/// # Safety
+/// `msr` must exist on this CPU; reading one that does not raises #GP.
+#[inline]
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    core::arch::asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nostack, preserves_flags));
+    ((high as u64) << 32) | low as u64
+}
+
+/// Write a model-specific register
+///
+/// # Safety
+/// `msr` must exist on this CPU and `value` must be valid for it. The
+/// caller is responsible for whatever the write changes.
+#[inline]
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    core::arch::asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nostack, preserves_flags)
+    );
+}
+
+crate::kernel_test! {
+    /// EFER reads back with long mode active and survives an unchanged write
+    fn msr_efer_round_trip() {
+        const EFER_LMA: u64 = 1 << 10;
+        let efer = unsafe { rdmsr(EFER) };
+        crate::ktest_assert!(efer & EFER_LMA != 0, "EFER.LMA clear in long mode");
+        unsafe { wrmsr(EFER, efer) };
+        crate::ktest_assert_eq!(unsafe { rdmsr(EFER) }, efer, "EFER changed by a write of itself");
+        Ok(())
+    }
+}
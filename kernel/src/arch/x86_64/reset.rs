@@ -0,0 +1,50 @@
+//! System reset
+//!
+//! [`reboot`] restarts the machine at once, without shutting anything down
+//! or flushing anything: through the reset control register of the PCI
+//! chipset, then the keyboard controller's CPU reset line, and as a last
+//! resort a triple fault.
+
+use x86_64::instructions::port::Port;
+
+/// Reset control register: bit 1 requests a system reset, and a 0 to 1
+/// transition of bit 2 performs it
+const RESET_CONTROL: u16 = 0xCF9;
+const RESET_SYSTEM: u8 = 1 << 1;
+const RESET_CPU: u8 = 1 << 2;
+
+/// 8042 keyboard controller: status (input buffer full) and the command
+/// pulsing the CPU reset line
+const KBC_STATUS: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xFE;
+
+/// Status reads before writing the reset command anyway
+const MAX_POLLS: usize = 100_000;
+
+/// Reset the machine
+pub fn reboot() -> ! {
+    unsafe {
+        core::arch::asm!("cli");
+
+        let mut control = Port::<u8>::new(RESET_CONTROL);
+        control.write(RESET_SYSTEM);
+        control.write(RESET_SYSTEM | RESET_CPU);
+
+        let mut kbc = Port::<u8>::new(KBC_STATUS);
+        for _ in 0..MAX_POLLS {
+            if kbc.read() & KBC_INPUT_FULL == 0 {
+                break;
+            }
+        }
+        kbc.write(KBC_PULSE_RESET);
+
+        // With an empty IDT the breakpoint cannot be delivered, nor the
+        // double fault that follows
+        let empty_idt = [0u8; 10];
+        core::arch::asm!("lidt [{}]", "int3", in(reg) empty_idt.as_ptr(), options(nostack));
+    }
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
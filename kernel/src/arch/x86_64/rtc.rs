@@ -0,0 +1,110 @@
+//! CMOS real-time clock
+//!
+//! Only read, to measure time the TSC and the timer did not see (across a
+//! suspend). The wall clock at boot comes from the bootloader instead
+//! (`efi::FirmwareInfo::boot_time`). The RTC counts seconds, so that is the
+//! resolution of anything measured with it.
+
+use x86_64::instructions::port::Port;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// Status register A: an update is in progress
+const STATUS_A: u8 = 0x0A;
+const STATUS_A_UIP: u8 = 1 << 7;
+
+/// Status register B: binary instead of BCD values, 24-hour clock
+const STATUS_B: u8 = 0x0B;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_24H: u8 = 1 << 1;
+
+/// Hour register: PM in 12-hour mode
+const HOUR_PM: u8 = 1 << 7;
+
+/// Reads of status A before giving up on the update flag clearing; an
+/// update takes under 2 ms and each read about a microsecond
+const MAX_POLLS: usize = 10_000;
+
+fn read_register(register: u8) -> u8 {
+    let mut index = Port::<u8>::new(CMOS_INDEX);
+    let mut data = Port::<u8>::new(CMOS_DATA);
+    unsafe {
+        // Bit 7 of the index keeps NMIs enabled
+        index.write(register & 0x7F);
+        data.read()
+    }
+}
+
+/// Seconds, minutes, hours, day, month and year registers
+fn read_raw() -> Option<[u8; 6]> {
+    if !(0..MAX_POLLS).any(|_| read_register(STATUS_A) & STATUS_A_UIP == 0) {
+        return None;
+    }
+    Some([0x00, 0x02, 0x04, 0x07, 0x08, 0x09].map(read_register))
+}
+
+/// Days from 1970-01-01 to `year-month-day` (proleptic Gregorian)
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Current RTC time in seconds since the Unix epoch
+///
+/// Returns None if the platform has no CMOS RTC or it never finishes an
+/// update. The RTC is assumed to hold UTC in the 21st century.
+pub fn read_seconds() -> Option<u64> {
+    if !super::acpi::platform_info().map_or(true, |platform| platform.has_cmos_rtc()) {
+        return None;
+    }
+
+    // Read until two reads agree, so an update cannot tear the value
+    let mut raw = read_raw()?;
+    loop {
+        let again = read_raw()?;
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status = read_register(STATUS_B);
+    let bcd = |value: u8| {
+        if status & STATUS_B_BINARY != 0 {
+            value as u64
+        } else {
+            (value >> 4) as u64 * 10 + (value & 0x0F) as u64
+        }
+    };
+    let [second, minute, hour, day, month, year] = raw;
+    let pm = status & STATUS_B_24H == 0 && hour & HOUR_PM != 0;
+    let mut hour = bcd(hour & !HOUR_PM);
+    if status & STATUS_B_24H == 0 {
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+    let (month, day) = (bcd(month), bcd(day));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let days = days_from_civil(2000 + bcd(year), month, day);
+    Some(((days * 24 + hour) * 60 + bcd(minute)) * 60 + bcd(second))
+}
+
+crate::kernel_test! {
+    /// Civil dates convert to the right day count
+    fn rtc_days_from_civil() {
+        crate::ktest_assert_eq!(days_from_civil(1970, 1, 1), 0, "epoch");
+        crate::ktest_assert_eq!(days_from_civil(2000, 3, 1), 11_017, "2000-03-01");
+        crate::ktest_assert_eq!(days_from_civil(2024, 12, 31), 20_088, "2024-12-31");
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@
 /// This module provides CPU core discovery, AP (Application Processor) bringup,
 /// and per-CPU data structures.
 pub mod percpu;
+pub mod tsc_sync;
 
 use crate::arch::x86_64::acpi::get_madt_info;
 use crate::arch::x86_64::apic::LocalApic;
@@ -28,7 +29,8 @@ static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
 /// 3. Configures the AP's GS.BASE MSR
 /// 4. Initializes the AP's Local APIC
 /// 5. Signals the BSP that the AP is online
-/// 6. Enters the scheduler idle loop
+/// 6. Synchronizes its TSC with the BSP's
+/// 7. Enters the scheduler idle loop
 ///
 /// # Arguments
 /// * `cpu_id` - Logical CPU ID assigned by the BSP
@@ -148,6 +150,11 @@ pub extern "C" fn ap_entry64(cpu_id: usize, apic_id: u8, lapic_address: u64) ->
         }
     }
 
+    // Enable the FPU and SIMD state tasks save and restore on switches
+    unsafe {
+        crate::arch::x86_64::fpu::init(cpu_id);
+    }
+
     // Debug: '8' after GDT/TSS init
     unsafe {
         core::arch::asm!(
@@ -227,7 +234,7 @@ pub extern "C" fn ap_entry64(cpu_id: usize, apic_id: u8, lapic_address: u64) ->
     // Initialize APIC timer for this AP
     // Each CPU needs its own timer for preemptive multitasking
     unsafe {
-        lapic.init_timer(lapic_frequency, crate::config::SCHED_HZ);
+        crate::arch::x86_64::apic::timer::start(&mut lapic, lapic_frequency, crate::config::SCHED_HZ);
     }
     serial_println!("[APIC] core{} timer @{}Hz", cpu_id, crate::config::SCHED_HZ);
 
@@ -255,6 +262,9 @@ pub extern "C" fn ap_entry64(cpu_id: usize, apic_id: u8, lapic_address: u64) ->
     CPU_ONLINE[cpu_id].store(true, Ordering::Release);
     CPU_COUNT.fetch_add(1, Ordering::SeqCst);
 
+    // The BSP measures our TSC against its own as soon as it sees us online
+    tsc_sync::follow(cpu_id);
+
     // Debug: 'C' after signaling online
     unsafe {
         core::arch::asm!(
@@ -417,16 +427,16 @@ pub fn init_smp(lapic: &mut LocalApic) -> Result<usize, &'static str> {
 
     // Verify that higher-half kernel mappings exist in page table
     unsafe {
-        use crate::mm::phys_to_virt;
+        use crate::mm::{phys_to_virt, PhysAddr};
         let test_cr3: u64;
         core::arch::asm!(
             "mov {}, cr3",
             out(reg) test_cr3,
             options(nostack, preserves_flags)
         );
-        let pml4_phys = (test_cr3 & 0x000F_FFFF_FFFF_F000) as usize;
+        let pml4_phys = PhysAddr::new(test_cr3 & 0x000F_FFFF_FFFF_F000);
         let pml4_virt = phys_to_virt(pml4_phys);
-        let pml4 = &*(pml4_virt as *const [u64; 512]);
+        let pml4 = &*pml4_virt.as_ptr::<[u64; 512]>();
 
         // Check PML4 entry for higher-half kernel (0xFFFF800000000000+)
         // Kernel is typically at index 256 (0x100)
@@ -589,6 +599,7 @@ pub fn init_smp(lapic: &mut LocalApic) -> Result<usize, &'static str> {
 
         if is_cpu_online(cpu_id) {
             serial_println!("[SMP] AP#{} came online successfully", cpu_id);
+            tsc_sync::lead(cpu_id);
         } else {
             serial_println!("[SMP] AP#{} failed to come online (timeout after 500ms)", cpu_id);
         }
@@ -644,7 +655,7 @@ fn busy_wait_us(us: u64) {
 /// This function directly manipulates page tables and must be called during
 /// SMP initialization before APs are started.
 unsafe fn identity_map_low_memory() -> Result<(), &'static str> {
-    use crate::mm::{allocator::kmalloc, phys_to_virt, virt_to_phys};
+    use crate::mm::{allocator::kmalloc, phys_to_virt, virt_to_phys, PhysAddr, VirtAddr};
 
     // Get current CR3 (PML4 address)
     let cr3: u64;
@@ -653,55 +664,57 @@ unsafe fn identity_map_low_memory() -> Result<(), &'static str> {
         out(reg) cr3,
         options(nostack, preserves_flags)
     );
-    let pml4_phys = (cr3 & 0x000F_FFFF_FFFF_F000) as usize;
+    let pml4_phys = PhysAddr::new(cr3 & 0x000F_FFFF_FFFF_F000);
     let pml4_virt = phys_to_virt(pml4_phys);
 
     // PML4 entry 0 covers virtual addresses 0x0000_0000_0000_0000 - 0x0000_007F_FFFF_FFFF
-    let pml4 = &mut *(pml4_virt as *mut [u64; 512]);
+    let pml4 = &mut *pml4_virt.as_mut_ptr::<[u64; 512]>();
 
     // Check if PML4[0] is already present
     let pdpt_phys = if (pml4[0] & 0x1) != 0 {
         // Already present, use existing PDPT
-        (pml4[0] & 0x000F_FFFF_FFFF_F000) as usize
+        PhysAddr::new(pml4[0] & 0x000F_FFFF_FFFF_F000)
     } else {
         // Allocate new PDPT
-        let pdpt_virt = kmalloc(4096) as usize;
-        if pdpt_virt == 0 {
+        let pdpt_ptr = kmalloc(4096);
+        if pdpt_ptr.is_null() {
             return Err("Failed to allocate PDPT");
         }
         // Zero the new table
-        core::ptr::write_bytes(pdpt_virt as *mut u8, 0, 4096);
+        core::ptr::write_bytes(pdpt_ptr, 0, 4096);
+        let pdpt_virt = VirtAddr::from_ptr(pdpt_ptr);
         // Convert virtual address to physical
         let pdpt_phys = virt_to_phys(pdpt_virt);
         // Set PML4[0] to point to new PDPT (present + writable)
-        pml4[0] = (pdpt_phys as u64) | 0x3;
+        pml4[0] = pdpt_phys.as_u64() | 0x3;
         pdpt_phys
     };
 
     let pdpt_virt = phys_to_virt(pdpt_phys);
-    let pdpt = &mut *(pdpt_virt as *mut [u64; 512]);
+    let pdpt = &mut *pdpt_virt.as_mut_ptr::<[u64; 512]>();
 
     // Check if PDPT[0] is already present
     let pd_phys = if (pdpt[0] & 0x1) != 0 {
         // Already present, use existing PD
-        (pdpt[0] & 0x000F_FFFF_FFFF_F000) as usize
+        PhysAddr::new(pdpt[0] & 0x000F_FFFF_FFFF_F000)
     } else {
         // Allocate new PD
-        let pd_virt = kmalloc(4096) as usize;
-        if pd_virt == 0 {
+        let pd_ptr = kmalloc(4096);
+        if pd_ptr.is_null() {
             return Err("Failed to allocate PD");
         }
         // Zero the new table
-        core::ptr::write_bytes(pd_virt as *mut u8, 0, 4096);
+        core::ptr::write_bytes(pd_ptr, 0, 4096);
+        let pd_virt = VirtAddr::from_ptr(pd_ptr);
         // Convert virtual address to physical
         let pd_phys = virt_to_phys(pd_virt);
         // Set PDPT[0] to point to new PD (present + writable)
-        pdpt[0] = (pd_phys as u64) | 0x3;
+        pdpt[0] = pd_phys.as_u64() | 0x3;
         pd_phys
     };
 
     let pd_virt = phys_to_virt(pd_phys);
-    let pd = &mut *(pd_virt as *mut [u64; 512]);
+    let pd = &mut *pd_virt.as_mut_ptr::<[u64; 512]>();
 
     // Use 2MB huge page for identity mapping (0x0000-0x1FFFFF)
     // This covers the trampoline at 0x8000 and more
@@ -4,11 +4,17 @@
 /// to maintain its own state without requiring locks for access.
 ///
 /// Each CPU core has its own PerCpu structure that is cache-line aligned
-/// to prevent false sharing between cores.
+/// to prevent false sharing between cores. GS.BASE points at it while the
+/// CPU runs kernel code, so the hot fields (the PerCpu's own address, the
+/// CPU id, the running task) are read with a single GS-relative load.
+/// Subsystems that need their own per-CPU state declare it with
+/// [`per_cpu!`](crate::per_cpu) instead of adding fields here.
+use crate::arch::x86_64::msr::wrmsr;
 use crate::config::MAX_CPUS;
-use crate::sched::task::TaskId;
-use crate::sync::SpinLock;
-use core::sync::atomic::{AtomicU64, Ordering};
+use crate::sched::task::{Task, TaskId};
+use core::mem::offset_of;
+use crate::sync::IrqSpinLock;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 
 /// Maximum number of tasks per CPU runqueue
 const MAX_RUNQUEUE_SIZE: usize = 64;
@@ -63,6 +69,20 @@ impl RunQueue {
         Some(task_id)
     }
 
+    /// Add a task to the front of the queue, to run next
+    ///
+    /// Returns true if successful, false if queue is full
+    pub fn push_front(&mut self, task_id: TaskId) -> bool {
+        if self.count >= MAX_RUNQUEUE_SIZE {
+            return false;
+        }
+
+        self.head = (self.head + MAX_RUNQUEUE_SIZE - 1) % MAX_RUNQUEUE_SIZE;
+        self.tasks[self.head] = task_id;
+        self.count += 1;
+        true
+    }
+
     /// Get the number of tasks in the queue
     pub fn len(&self) -> usize {
         self.count
@@ -119,18 +139,28 @@ impl PerCpuStats {
 /// It is cache-line aligned (64 bytes) to prevent false sharing between cores.
 ///
 /// # Fields
+/// * `this` - Address of this structure, for GS-relative lookup
 /// * `id` - Logical CPU ID (0 for BSP, 1..N for APs)
 /// * `apic_id` - APIC ID from MADT (may not be sequential)
 /// * `node_id` - NUMA node ID (for future NUMA support)
 /// * `runqueue` - Queue of tasks ready to execute on this core
 /// * `current_task` - Currently executing task (None if idle)
+/// * `current` - The running task's Task, matching `current_task`
 /// * `idle_task` - Idle task for this core (runs when no other tasks are ready)
 /// * `lapic_timer_hz` - Calibrated LAPIC timer frequency in Hz
 /// * `ticks` - Number of timer ticks since boot
 /// * `in_interrupt` - True if currently executing an interrupt handler
+/// * `kernel_stack` - Top of the running task's kernel stack
+/// * `user_rsp` - User RSP saved by `syscall_entry64`
+/// * `tss_rsp0` - Address of this CPU's TSS.RSP0
 /// * `stats` - Per-CPU statistics counters
+/// * `slice_left` - Timer ticks left in the running task's quantum
+/// * `need_resched` - A task switch is due on the way out of an interrupt
 #[repr(C, align(64))]
 pub struct PerCpu {
+    /// Address of this structure; `percpu_current` reads it through GS
+    this: *const PerCpu,
+
     /// Logical CPU ID (0 for BSP, 1..N for APs)
     pub id: usize,
 
@@ -140,12 +170,17 @@ pub struct PerCpu {
     /// NUMA node ID (for future NUMA support)
     pub node_id: u8,
 
-    /// Runqueue for this CPU core
-    pub runqueue: SpinLock<RunQueue>,
+    /// Runqueue for this CPU core; the timer interrupt takes it too, so
+    /// interrupts stay off while it is held
+    pub runqueue: IrqSpinLock<RunQueue>,
 
     /// Currently executing task (None if idle)
     pub current_task: Option<TaskId>,
 
+    /// The running task's Task (null before the first switch), set with
+    /// `current_task` so the scheduler reaches it without the task table
+    pub current: *mut Task,
+
     /// Idle task for this core
     pub idle_task: TaskId,
 
@@ -158,8 +193,28 @@ pub struct PerCpu {
     /// True if currently executing an interrupt handler
     pub in_interrupt: bool,
 
+    /// Top of the running task's kernel stack, where `syscall_entry64`
+    /// switches to; set by `context_switch`
+    pub kernel_stack: u64,
+
+    /// User RSP between SYSCALL and the push onto the kernel stack
+    pub user_rsp: u64,
+
+    /// Address of this CPU's TSS.RSP0 (0 until the TSS is loaded), which
+    /// `context_switch` points at the next task's kernel stack
+    pub tss_rsp0: u64,
+
     /// Per-CPU statistics
     pub stats: PerCpuStats,
+
+    /// Timer ticks left in the running task's quantum; set when a task is
+    /// switched in, cut to 1 when a short task is woken onto this CPU
+    pub slice_left: AtomicU32,
+
+    /// Set by the timer tick when the quantum is used up and by the
+    /// reschedule IPI; the interrupt return path switches tasks while it
+    /// is set, and every switch clears it
+    pub need_resched: AtomicBool,
 }
 
 impl PerCpu {
@@ -169,16 +224,23 @@ impl PerCpu {
     /// The actual initialization is done by init_percpu().
     const fn new_uninit() -> Self {
         PerCpu {
+            this: core::ptr::null(),
             id: 0,
             apic_id: 0,
             node_id: 0,
-            runqueue: SpinLock::new(RunQueue::new()),
+            runqueue: IrqSpinLock::new(RunQueue::new()),
             current_task: None,
+            current: core::ptr::null_mut(),
             idle_task: 0,
             lapic_timer_hz: 0,
             ticks: AtomicU64::new(0),
             in_interrupt: false,
+            kernel_stack: 0,
+            user_rsp: 0,
+            tss_rsp0: 0,
             stats: PerCpuStats::new(),
+            slice_left: AtomicU32::new(0),
+            need_resched: AtomicBool::new(false),
         }
     }
 
@@ -317,6 +379,7 @@ pub unsafe fn init_percpu(cpu_id: usize, apic_id: u8) {
     );
 
     percpu.current_task = None;
+    percpu.current = core::ptr::null_mut();
     core::arch::asm!(
         "mov al, 'H'",
         "mov dx, 0x3F8",
@@ -427,55 +490,28 @@ pub unsafe fn percpu_for_mut(cpu_id: usize) -> &'static mut PerCpu {
 /// The GS.BASE MSR (0xC0000101) is used to store a pointer to the current
 /// CPU's PerCpu structure. This allows fast access to per-CPU data without
 /// requiring locks or atomic operations.
-const MSR_GS_BASE: u32 = 0xC0000101;
-
-/// Write a value to a Model-Specific Register (MSR)
-///
-/// # Arguments
-/// * `msr` - MSR number
-/// * `value` - 64-bit value to write
-///
-/// # Safety
-/// This function writes to an MSR, which can affect system behavior.
-/// The caller must ensure the MSR number is valid and the value is appropriate.
-#[inline]
-unsafe fn wrmsr(msr: u32, value: u64) {
-    let low = value as u32;
-    let high = (value >> 32) as u32;
-
-    core::arch::asm!(
-        "wrmsr",
-        in("ecx") msr,
-        in("eax") low,
-        in("edx") high,
-        options(nostack, preserves_flags)
-    );
-}
-
-/// Read a value from a Model-Specific Register (MSR)
-///
-/// # Arguments
-/// * `msr` - MSR number
-///
-/// # Returns
-/// The 64-bit value read from the MSR
-///
-/// # Safety
-/// This function reads from an MSR. The caller must ensure the MSR number is valid.
-#[inline]
-unsafe fn rdmsr(msr: u32) -> u64 {
-    let low: u32;
-    let high: u32;
-
-    core::arch::asm!(
-        "rdmsr",
-        in("ecx") msr,
-        out("eax") low,
-        out("edx") high,
-        options(nostack, preserves_flags)
-    );
-
-    ((high as u64) << 32) | (low as u64)
+const MSR_GS_BASE: u32 = crate::arch::x86_64::msr::GS_BASE;
+
+/// Assembly for interrupt entry and exit stubs: `swapgs` if the interrupt
+/// frame's CS, at `[rsp + $cs_offset]`, is a user segment
+///
+/// In user mode GS.BASE holds the task's GS base and KERNEL_GS_BASE this
+/// CPU's PerCpu; in the kernel it is the other way around. Entry stubs use
+/// this first thing, before anything reads GS.BASE, and exit stubs right
+/// before `iretq`. A task switched out inside a handler resumes with the
+/// kernel's GS.BASE, so each exit matches its own frame.
+#[macro_export]
+macro_rules! swapgs_if_user {
+    ($cs_offset:literal) => {
+        concat!(
+            "test byte ptr [rsp + ",
+            stringify!($cs_offset),
+            "], 3\n",
+            "jz 2f\n",
+            "swapgs\n",
+            "2:"
+        )
+    };
 }
 
 /// Configure GS.BASE MSR to point to the current CPU's PerCpu structure
@@ -495,14 +531,32 @@ pub unsafe fn setup_gs_base(cpu_id: usize) {
         panic!("[PERCPU] Invalid CPU ID: {}", cpu_id);
     }
 
-    let percpu_ptr = &PERCPU_ARRAY[cpu_id] as *const PerCpu as u64;
-    wrmsr(MSR_GS_BASE, percpu_ptr);
+    let percpu = &mut PERCPU_ARRAY[cpu_id];
+    percpu.this = percpu as *const PerCpu;
+    wrmsr(MSR_GS_BASE, percpu.this as u64);
+}
+
+/// Load the `usize`-sized PerCpu field at `$offset` through GS
+macro_rules! gs_load {
+    ($offset:expr) => {{
+        let value: usize;
+        unsafe {
+            core::arch::asm!(
+                "mov {}, gs:[{offset}]",
+                out(reg) value,
+                offset = const $offset,
+                options(nostack, preserves_flags, readonly)
+            );
+        }
+        value
+    }};
 }
 
 /// Get a reference to the current CPU's PerCpu structure
 ///
-/// This function reads the GS.BASE MSR to get a pointer to the current
-/// CPU's PerCpu structure. This is the fastest way to access per-CPU data.
+/// This function loads the structure's `this` field through GS, which
+/// costs one memory read instead of an RDMSR of GS.BASE. This is the
+/// fastest way to access per-CPU data.
 ///
 /// # Returns
 /// A reference to the current CPU's PerCpu structure
@@ -512,16 +566,28 @@ pub unsafe fn setup_gs_base(cpu_id: usize) {
 /// setup_gs_base(). If GS.BASE is not initialized, this will return an
 /// invalid reference.
 pub fn percpu_current() -> &'static PerCpu {
-    unsafe {
-        let percpu_ptr = rdmsr(MSR_GS_BASE) as *const PerCpu;
-        &*percpu_ptr
-    }
+    let percpu_ptr = gs_load!(offset_of!(PerCpu, this)) as *const PerCpu;
+    unsafe { &*percpu_ptr }
+}
+
+/// Logical ID of the current CPU
+///
+/// Same as `percpu_current().id`, with one GS-relative load.
+#[inline]
+pub fn current_cpu_id() -> usize {
+    gs_load!(offset_of!(PerCpu, id))
+}
+
+/// The task running on the current CPU, or null before its first switch
+#[inline]
+pub fn current_task_ptr() -> *mut Task {
+    gs_load!(offset_of!(PerCpu, current)) as *mut Task
 }
 
 /// Get a mutable reference to the current CPU's PerCpu structure
 ///
-/// This function reads the GS.BASE MSR to get a pointer to the current
-/// CPU's PerCpu structure and returns a mutable reference.
+/// This function loads the current CPU's PerCpu address through GS and
+/// returns a mutable reference.
 ///
 /// # Returns
 /// A mutable reference to the current CPU's PerCpu structure
@@ -531,10 +597,69 @@ pub fn percpu_current() -> &'static PerCpu {
 /// setup_gs_base(). The caller must ensure that no other code is accessing
 /// this PerCpu structure concurrently (typically by disabling interrupts).
 pub unsafe fn percpu_current_mut() -> &'static mut PerCpu {
-    let percpu_ptr = rdmsr(MSR_GS_BASE) as *mut PerCpu;
+    let percpu_ptr = gs_load!(offset_of!(PerCpu, this)) as *mut PerCpu;
     &mut *percpu_ptr
 }
 
+/// A variable with one instance per CPU
+///
+/// Declared with [`per_cpu!`](crate::per_cpu). [`get`](Self::get) picks
+/// the current CPU's instance by its GS-relative CPU id; other CPUs'
+/// instances are reached with [`get_for`](Self::get_for). Instances are
+/// shared references, so mutable state needs atomics or a lock, just as
+/// for the fields of [`PerCpu`].
+pub struct PerCpuVar<T> {
+    slots: [T; MAX_CPUS],
+}
+
+impl<T> PerCpuVar<T> {
+    /// Wrap one instance per CPU
+    pub const fn new(slots: [T; MAX_CPUS]) -> Self {
+        Self { slots }
+    }
+
+    /// The current CPU's instance
+    #[inline]
+    pub fn get(&self) -> &T {
+        &self.slots[current_cpu_id()]
+    }
+
+    /// `cpu_id`'s instance
+    ///
+    /// # Panics
+    /// Panics if cpu_id >= MAX_CPUS
+    #[inline]
+    pub fn get_for(&self, cpu_id: usize) -> &T {
+        &self.slots[cpu_id]
+    }
+
+    /// Every CPU's instance, in CPU id order
+    pub fn iter(&self) -> core::slice::Iter<'_, T> {
+        self.slots.iter()
+    }
+}
+
+/// Declare a [`PerCpuVar`] static with every CPU's instance set to the same
+/// constant initializer
+///
+/// ```rust,ignore
+/// per_cpu! {
+///     /// Packets received on each CPU
+///     static RX_PACKETS: AtomicU64 = AtomicU64::new(0);
+/// }
+/// RX_PACKETS.get().fetch_add(1, Ordering::Relaxed);
+/// ```
+#[macro_export]
+macro_rules! per_cpu {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;) => {
+        $(#[$attr])*
+        $vis static $name: $crate::arch::x86_64::smp::percpu::PerCpuVar<$ty> =
+            $crate::arch::x86_64::smp::percpu::PerCpuVar::new(
+                [const { $init }; $crate::config::MAX_CPUS],
+            );
+    };
+}
+
 /// Aggregate statistics structure
 ///
 /// Contains system-wide statistics aggregated across all CPUs.
@@ -579,3 +704,27 @@ pub fn get_aggregate_stats(num_cpus: usize) -> AggregateStats {
 
     stats
 }
+
+per_cpu! {
+    /// Scratch counter for `percpu_gs_access`
+    static TEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+}
+
+crate::kernel_test! {
+    /// GS-relative loads agree with the PerCpu found by CPU id, and a
+    /// per_cpu! variable resolves to this CPU's instance
+    fn percpu_gs_access() {
+        let percpu = percpu_current();
+        let cpu_id = current_cpu_id();
+        crate::ktest_assert_eq!(percpu.id, cpu_id, "GS-relative CPU id differs from PerCpu.id");
+        crate::ktest_assert!(core::ptr::eq(percpu, percpu_for(cpu_id)), "GS.BASE points at another CPU's PerCpu");
+        crate::ktest_assert_eq!(current_task_ptr(), percpu.current, "GS-relative current task differs");
+
+        let before = TEST_COUNTER.get_for(cpu_id).load(Ordering::Relaxed);
+        TEST_COUNTER.get().fetch_add(1, Ordering::Relaxed);
+        let after = TEST_COUNTER.get_for(cpu_id).load(Ordering::Relaxed);
+        crate::ktest_assert_eq!(after, before + 1, "per_cpu! get() used another CPU's instance");
+        crate::ktest_assert_eq!(TEST_COUNTER.iter().count(), MAX_CPUS, "per_cpu! instance count");
+        Ok(())
+    }
+}
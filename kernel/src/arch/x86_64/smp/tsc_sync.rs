@@ -0,0 +1,212 @@
+//! TSC synchronization between CPUs
+//!
+//! The TSC clock (`time::clock`) reads the TSC of whichever CPU it runs on,
+//! so the TSCs must agree for time read on different CPUs to be comparable.
+//! An invariant TSC (which the clock requires) ticks at the same rate on
+//! all CPUs, but firmware may start or reset them at different times.
+//!
+//! While an AP comes online it measures how far its TSC is off the BSP's:
+//! it reads its TSC, asks the BSP for its TSC, and reads its own again, so
+//! the BSP's value was read somewhere in between. The round trip with the
+//! shortest time gives the best estimate. If the BSP's value lies outside
+//! the AP's interval, the AP moves its TSC by the estimate (through
+//! IA32_TSC_ADJUST where the CPU has it, else by writing the TSC) and
+//! measures again.
+//!
+//! An AP whose TSC is still off after the correction, or that stops
+//! answering, cannot be synchronized: the BSP warns and the clock falls
+//! back to the HPET.
+
+use crate::arch::x86_64::msr::{rdmsr, wrmsr, IA32_TSC, IA32_TSC_ADJUST};
+use core::arch::x86_64::{__cpuid_count, _rdtsc};
+use core::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+/// CPUID leaf 7, EBX: IA32_TSC_ADJUST is supported
+const CPUID_7_EBX_TSC_ADJUST: u32 = 1 << 1;
+
+/// Round trips per measurement
+const ROUNDS: u32 = 16;
+
+/// Sequence number that ends the exchange
+const DONE: u32 = u32::MAX;
+
+/// Spins either side waits for the other before giving up
+const SPIN_LIMIT: u32 = 10_000_000;
+
+/// Latest request of the AP being synchronized: CPU in the high half,
+/// sequence number in the low half
+static REQUEST: AtomicU64 = AtomicU64::new(0);
+/// Request the BSP answered last
+static REPLY: AtomicU64 = AtomicU64::new(0);
+/// BSP TSC read for the answered request
+static REPLY_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// What the AP found, valid once it sent `DONE`
+static CORRECTION: AtomicI64 = AtomicI64::new(0);
+static RESIDUAL_OFFSET: AtomicI64 = AtomicI64::new(0);
+static RESIDUAL_RTT: AtomicU64 = AtomicU64::new(0);
+
+fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+fn tag(cpu: usize, seq: u32) -> u64 {
+    (cpu as u64) << 32 | seq as u64
+}
+
+/// One round trip: the AP read its TSC at `t0` and `t1`, the BSP read
+/// `bsp` in between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sample {
+    /// AP cycles from `t0` to `t1`
+    rtt: u64,
+    /// Estimate of BSP TSC - AP TSC, off by at most `rtt / 2`
+    offset: i64,
+}
+
+impl Sample {
+    fn new(t0: u64, bsp: u64, t1: u64) -> Self {
+        let rtt = t1.wrapping_sub(t0);
+        Sample { rtt, offset: bsp.wrapping_sub(t0.wrapping_add(rtt / 2)) as i64 }
+    }
+
+    /// Whether the TSCs may agree: the BSP's value lies in `[t0, t1]`
+    fn in_sync(&self) -> bool {
+        self.offset.unsigned_abs() <= self.rtt / 2
+    }
+}
+
+/// AP side: ask the BSP for its TSC `ROUNDS` times starting at `first`
+///
+/// Returns the round trip with the shortest time, or None if the BSP did
+/// not answer.
+fn measure(cpu: usize, first: u32) -> Option<Sample> {
+    let mut best: Option<Sample> = None;
+    for seq in first..first + ROUNDS {
+        let request = tag(cpu, seq);
+        let t0 = rdtsc();
+        REQUEST.store(request, Ordering::Release);
+        let mut spins = 0;
+        while REPLY.load(Ordering::Acquire) != request {
+            spins += 1;
+            if spins > SPIN_LIMIT {
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+        let t1 = rdtsc();
+        let sample = Sample::new(t0, REPLY_TSC.load(Ordering::Relaxed), t1);
+        if best.map_or(true, |best| sample.rtt < best.rtt) {
+            best = Some(sample);
+        }
+    }
+    best
+}
+
+/// Move this CPU's TSC forward by `offset` cycles (back if negative)
+fn adjust(offset: i64) {
+    let has_adjust = __cpuid_count(0, 0).eax >= 7 && __cpuid_count(7, 0).ebx & CPUID_7_EBX_TSC_ADJUST != 0;
+    unsafe {
+        if has_adjust {
+            wrmsr(IA32_TSC_ADJUST, rdmsr(IA32_TSC_ADJUST).wrapping_add(offset as u64));
+        } else {
+            wrmsr(IA32_TSC, rdtsc().wrapping_add(offset as u64));
+        }
+    }
+}
+
+/// Synchronize this AP's TSC with the BSP's
+///
+/// Called by AP `cpu` with interrupts off right after it signals that it
+/// is online; the BSP runs [`lead`] for it at the same time.
+pub fn follow(cpu: usize) {
+    let Some(first) = measure(cpu, 1) else { return };
+    let correction = if first.in_sync() { 0 } else { first.offset };
+    if correction != 0 {
+        adjust(correction);
+    }
+    let Some(residual) = measure(cpu, 1 + ROUNDS) else { return };
+
+    CORRECTION.store(correction, Ordering::Relaxed);
+    RESIDUAL_OFFSET.store(residual.offset, Ordering::Relaxed);
+    RESIDUAL_RTT.store(residual.rtt, Ordering::Relaxed);
+    REQUEST.store(tag(cpu, DONE), Ordering::Release);
+}
+
+/// BSP side: answer AP `cpu`'s requests until it is done
+///
+/// Returns the correction the AP applied and its measurement after, or
+/// None if it stopped asking.
+fn serve(cpu: usize) -> Option<(i64, Sample)> {
+    let mut answered = 0;
+    let mut spins = 0;
+    loop {
+        let request = REQUEST.load(Ordering::Acquire);
+        if request >> 32 == cpu as u64 && request as u32 != answered {
+            if request as u32 == DONE {
+                let residual = Sample {
+                    rtt: RESIDUAL_RTT.load(Ordering::Relaxed),
+                    offset: RESIDUAL_OFFSET.load(Ordering::Relaxed),
+                };
+                return Some((CORRECTION.load(Ordering::Relaxed), residual));
+            }
+            REPLY_TSC.store(rdtsc(), Ordering::Relaxed);
+            REPLY.store(request, Ordering::Release);
+            answered = request as u32;
+            spins = 0;
+            continue;
+        }
+        spins += 1;
+        if spins > SPIN_LIMIT {
+            return None;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Synchronize AP `cpu`'s TSC with this CPU's
+///
+/// Called on the BSP once the AP is online. Falls back from the TSC clock
+/// if the AP's TSC cannot be synchronized.
+pub fn lead(cpu: usize) {
+    match serve(cpu) {
+        Some((correction, residual)) if residual.in_sync() => {
+            if correction != 0 {
+                crate::serial_println!("[TIME] AP#{} TSC was {} cycles off, corrected", cpu, correction);
+            }
+        }
+        Some((_, residual)) => {
+            crate::log_warn!(
+                "TIME",
+                "AP#{} TSC still {} cycles off after correction, TSCs unsynchronizable",
+                cpu,
+                residual.offset
+            );
+            crate::time::clock::fall_back_to_hpet();
+        }
+        None => {
+            crate::log_warn!("TIME", "AP#{} did not finish TSC synchronization", cpu);
+            crate::time::clock::fall_back_to_hpet();
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// A sample estimates the offset from the middle of the round trip,
+    /// and is in sync when the BSP's TSC falls within it
+    fn tsc_sync_sample() {
+        let behind = Sample::new(1000, 5050, 1100);
+        crate::ktest_assert_eq!(behind.rtt, 100, "round trip");
+        crate::ktest_assert_eq!(behind.offset, 4000, "offset of a TSC behind");
+        crate::ktest_assert!(!behind.in_sync(), "TSC behind in sync");
+
+        let ahead = Sample::new(5000, 1050, 5100);
+        crate::ktest_assert_eq!(ahead.offset, -4000, "offset of a TSC ahead");
+        crate::ktest_assert!(!ahead.in_sync(), "TSC ahead in sync");
+
+        let close = Sample::new(1000, 1090, 1100);
+        crate::ktest_assert_eq!(close.offset, 40, "offset within the round trip");
+        crate::ktest_assert!(close.in_sync(), "TSC within the round trip out of sync");
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! Spurious and unexpected interrupts
+//!
+//! Every interrupt vector from 0x20 up gets a catch-all gate at boot, which
+//! the real handlers then replace. An interrupt that still arrives through a
+//! catch-all gate is one of:
+//!
+//! - A spurious 8259 PIC interrupt on IRQ7 (vector 0x27) or IRQ15 (0x2F).
+//!   The PIC raises these when a request goes away before it is
+//!   acknowledged, whether or not the line is masked. Its in-service
+//!   register tells them apart from real ones. A spurious IRQ7 gets no EOI.
+//!   A spurious IRQ15 gets an EOI to the master only, which did see a
+//!   request on the cascade line.
+//! - The local APIC spurious vector (0xFF). It gets no EOI.
+//! - Anything else is unexpected: a device raising a vector nothing
+//!   claimed. It gets an EOI from whichever controller has it in service.
+//!
+//! All of them are counted (see `/proc/stat`). Unexpected interrupts are
+//! also logged, at most once per second per vector, so a stuck device
+//! shows up in the log without flooding it.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// First vector given a catch-all gate (after the CPU exceptions)
+const FIRST_VECTOR: usize = 0x20;
+
+/// Number of catch-all gates
+const VECTORS: usize = 256 - FIRST_VECTOR;
+
+/// Vector of PIC IRQ7 and IRQ15 after remapping
+const PIC_IRQ7_VECTOR: u8 = 0x27;
+const PIC_IRQ15_VECTOR: u8 = 0x2F;
+
+/// Local APIC spurious vector (see `apic::LocalApic::init`)
+const APIC_SPURIOUS_VECTOR: u8 = 0xFF;
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC_EOI: u8 = 0x20;
+/// OCW3: read the in-service register on the next command port read
+const PIC_READ_ISR: u8 = 0x0B;
+
+/// Spurious PIC interrupts (IRQ7 and IRQ15)
+static PIC_SPURIOUS: AtomicUsize = AtomicUsize::new(0);
+
+/// Local APIC spurious interrupts
+static APIC_SPURIOUS: AtomicUsize = AtomicUsize::new(0);
+
+/// Unexpected interrupts per vector
+static UNEXPECTED: [AtomicUsize; 256] = [const { AtomicUsize::new(0) }; 256];
+
+/// Tick before which a vector is not logged again
+static NEXT_LOG: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+/// Counts reported in `/proc/stat`
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpuriousStats {
+    pub pic: usize,
+    pub apic: usize,
+    pub unexpected: usize,
+}
+
+pub fn stats() -> SpuriousStats {
+    SpuriousStats {
+        pic: PIC_SPURIOUS.load(Ordering::Relaxed),
+        apic: APIC_SPURIOUS.load(Ordering::Relaxed),
+        unexpected: UNEXPECTED.iter().map(|count| count.load(Ordering::Relaxed)).sum(),
+    }
+}
+
+/// Count an unexpected interrupt on `vector` and log it unless it was
+/// logged within the last second
+///
+/// Interrupt context: the log line is skipped rather than waiting for a
+/// console another context on this CPU holds.
+pub fn note_unexpected(vector: u8) {
+    let count = UNEXPECTED[vector as usize].fetch_add(1, Ordering::Relaxed) + 1;
+
+    let now = crate::sched::timer::get_tick_count() as u64;
+    let next = NEXT_LOG[vector as usize].load(Ordering::Relaxed);
+    if now < next || crate::serial::SERIAL.is_locked() {
+        return;
+    }
+    let delay = crate::config::SCHED_HZ;
+    if NEXT_LOG[vector as usize]
+        .compare_exchange(next, now + delay, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+    {
+        crate::log_warn!("IRQ", "Unexpected interrupt on vector 0x{:x} ({} so far)", vector, count);
+    }
+}
+
+/// Read the in-service register of the PIC at `command`
+unsafe fn pic_in_service(command: u16) -> u8 {
+    use x86_64::instructions::port::Port;
+
+    let mut port = Port::<u8>::new(command);
+    port.write(PIC_READ_ISR);
+    port.read()
+}
+
+unsafe fn pic_eoi(command: u16) {
+    x86_64::instructions::port::Port::<u8>::new(command).write(PIC_EOI);
+}
+
+/// Local APIC of this CPU, if the APIC is in use
+fn local_apic() -> Option<super::apic::LocalApic> {
+    let madt = super::acpi::get_madt_info()?;
+    Some(unsafe { super::apic::LocalApic::new(madt.lapic_address) })
+}
+
+/// Handle an interrupt that arrived through a catch-all gate
+extern "C" fn catch_all(vector: u64) {
+    let vector = vector as u8;
+    crate::rand::add_interrupt_timing(vector as u64);
+
+    if vector == APIC_SPURIOUS_VECTOR {
+        APIC_SPURIOUS.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    // Delivered by the local APIC: it has the vector in service
+    if let Some(mut lapic) = local_apic() {
+        if lapic.in_service(vector) {
+            note_unexpected(vector);
+            lapic.eoi();
+            return;
+        }
+    }
+
+    // Otherwise it came from the 8259 PIC
+    unsafe {
+        match vector {
+            PIC_IRQ7_VECTOR if pic_in_service(PIC1_COMMAND) & 0x80 == 0 => {
+                PIC_SPURIOUS.fetch_add(1, Ordering::Relaxed);
+            }
+            PIC_IRQ15_VECTOR if pic_in_service(PIC2_COMMAND) & 0x80 == 0 => {
+                PIC_SPURIOUS.fetch_add(1, Ordering::Relaxed);
+                pic_eoi(PIC1_COMMAND);
+            }
+            0x20..=0x2F => {
+                note_unexpected(vector);
+                if vector >= 0x28 {
+                    pic_eoi(PIC2_COMMAND);
+                }
+                pic_eoi(PIC1_COMMAND);
+            }
+            // Not in service anywhere: nothing to acknowledge
+            _ => note_unexpected(vector),
+        }
+    }
+}
+
+/// Common path of the catch-all stubs; the stub pushed the vector
+#[unsafe(naked)]
+extern "C" fn catch_all_entry() {
+    core::arch::naked_asm!(
+        // Above the vector the stub pushed
+        crate::swapgs_if_user!(16),
+
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+
+        // The vector is above the 9 saved registers; the extra 8 bytes
+        // keep the stack 16-byte aligned for the call
+        "mov rdi, [rsp + 72]",
+        "sub rsp, 8",
+        "call {handler}",
+        "add rsp, 8",
+
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+
+        // Drop the vector
+        "add rsp, 8",
+        crate::swapgs_if_user!(8),
+        "iretq",
+
+        handler = sym catch_all,
+    )
+}
+
+/// Size of one catch-all stub (`push vector; jmp catch_all_entry`, padded)
+const STUB_SIZE: usize = 16;
+
+/// The catch-all stubs, one per vector from `FIRST_VECTOR`, every
+/// `STUB_SIZE` bytes from the first 16-byte boundary in the function
+#[unsafe(naked)]
+extern "C" fn catch_all_stubs() {
+    core::arch::naked_asm!(
+        ".balign 16",
+        ".set mellos_catch_all_vector, {first}",
+        ".rept {count}",
+        ".balign 16",
+        "pushq $mellos_catch_all_vector",
+        "jmp {entry}",
+        ".set mellos_catch_all_vector, mellos_catch_all_vector + 1",
+        ".endr",
+        first = const FIRST_VECTOR,
+        count = const VECTORS,
+        entry = sym catch_all_entry,
+        options(att_syntax),
+    )
+}
+
+/// Point every vector from 0x20 up at the catch-all stubs
+///
+/// # Safety
+/// Must be called from `init_idt` before the real handlers are installed,
+/// which then replace the catch-all gates of their vectors.
+pub unsafe fn init() {
+    let first_stub = (catch_all_stubs as *const () as usize).next_multiple_of(16);
+    for i in 0..VECTORS {
+        crate::sched::timer::set_idt_gate((FIRST_VECTOR + i) as u8, first_stub + i * STUB_SIZE, 0);
+    }
+}
+
+crate::kernel_test! {
+    /// Unexpected interrupts are counted per vector, logged or not
+    fn unexpected_interrupt_accounting() {
+        let before = stats().unexpected;
+        for _ in 0..3 {
+            note_unexpected(0xFE);
+        }
+        crate::ktest_assert_eq!(stats().unexpected, before + 3, "unexpected interrupts not counted");
+        crate::ktest_assert!(
+            NEXT_LOG[0xFE].load(Ordering::Relaxed) > crate::sched::timer::get_tick_count() as u64
+                || crate::serial::SERIAL.is_locked(),
+            "log not rate limited"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+//! Syscall entry microbenchmark (`syscallbench` on the kernel command line)
+//!
+//! Runs the same syscall in a tight loop from a user thread, once through
+//! `int 0x80` and once through `syscall`, and prints the cost per call of
+//! each path. Both loops issue `SYS_GETPGRP`, which the two dispatchers
+//! route to the same handler, so the difference is the entry and exit.
+//!
+//! The time is taken with `time::Instant` around the whole run, from
+//! creating the thread to seeing its join word cleared, so the run is long
+//! enough for the clock's resolution not to matter.
+
+use super::{copy_from_user, copy_to_user, current_task_mut};
+use crate::mm::mmap::{self, MAP_ANONYMOUS, MAP_PRIVATE, PROT_EXEC, PROT_READ, PROT_WRITE};
+use crate::sched::thread::{self, ThreadParams};
+use crate::time::{Duration, Instant};
+
+/// Syscalls issued by each loop
+const ITERATIONS: u64 = 5_000_000;
+
+const PAGE_SIZE: usize = 4096;
+
+// User code for the two loops: RDI holds the iteration count. Each ends
+// with SYS_THREAD_EXIT through the same instruction it measures. The code
+// is copied into a user page, so it lives in .rodata here.
+core::arch::global_asm!(
+    ".pushsection .rodata.syscallbench, \"a\"",
+    ".global syscallbench_int80",
+    ".global syscallbench_syscall",
+    ".global syscallbench_end",
+    "syscallbench_int80:",
+    "mov r12, rdi",
+    "2:",
+    "mov eax, {getpgrp}",
+    "int 0x80",
+    "dec r12",
+    "jnz 2b",
+    "mov eax, {thread_exit}",
+    "int 0x80",
+    "ud2",
+    "syscallbench_syscall:",
+    "mov r12, rdi",
+    "2:",
+    "mov eax, {getpgrp}",
+    "syscall",
+    "dec r12",
+    "jnz 2b",
+    "mov eax, {thread_exit}",
+    "syscall",
+    "ud2",
+    "syscallbench_end:",
+    ".popsection",
+    getpgrp = const crate::sys::syscall::SYS_GETPGRP,
+    thread_exit = const crate::sys::syscall::SYS_THREAD_EXIT,
+);
+
+extern "C" {
+    static syscallbench_int80: u8;
+    static syscallbench_syscall: u8;
+    static syscallbench_end: u8;
+}
+
+/// Run one loop in a user thread and return the time per call
+///
+/// `entry` is the loop's user address. The join word sits at the bottom of
+/// the stack page.
+fn run(entry: usize, stack: usize) -> Result<Duration, &'static str> {
+    let params = ThreadParams {
+        entry: entry as u64,
+        arg: ITERATIONS,
+        stack_top: (stack + PAGE_SIZE) as u64,
+        tls: 0,
+        tid_ptr: stack as u64,
+    };
+
+    let start = Instant::now();
+    thread::create(&params).map_err(|_| "thread creation failed")?;
+    loop {
+        let mut word = [0u8; 4];
+        copy_from_user(&mut word, stack, 4).map_err(|_| "join word unreadable")?;
+        if u32::from_ne_bytes(word) == 0 {
+            break;
+        }
+        if let Some((_, priority)) = crate::sched::get_current_task_info() {
+            crate::sched::sleep_current_task(Duration::TICK, priority);
+        }
+        crate::sched::yield_now();
+    }
+    Ok(Duration::from_nanos(start.elapsed().as_nanos() / ITERATIONS))
+}
+
+/// Map the loops and a stack into the current task and time both paths
+///
+/// Returns the time per call through `int 0x80` and through `syscall`.
+pub fn bench() -> Result<(Duration, Duration), &'static str> {
+    // The int 0x80 loop starts the code
+    let (int80, syscall, end) = (
+        core::ptr::addr_of!(syscallbench_int80) as usize,
+        core::ptr::addr_of!(syscallbench_syscall) as usize,
+        core::ptr::addr_of!(syscallbench_end) as usize,
+    );
+    let code = unsafe { core::slice::from_raw_parts(int80 as *const u8, end - int80) };
+
+    let task = current_task_mut().ok_or("no current task")?;
+    let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+    let text = mmap::map(task, 0, PAGE_SIZE, PROT_READ | PROT_WRITE, flags).map_err(|_| "mmap failed")?;
+    copy_to_user(text, code).map_err(|_| "copy failed")?;
+    mmap::protect(task, text, PAGE_SIZE, PROT_READ | PROT_EXEC).map_err(|_| "mprotect failed")?;
+    let stack = mmap::map(task, 0, PAGE_SIZE, PROT_READ | PROT_WRITE, flags).map_err(|_| "mmap failed")?;
+
+    let legacy = run(text, stack)?;
+    let fast = run(text + (syscall - int80), stack)?;
+    Ok((legacy, fast))
+}
+
+/// Kernel task started by `syscallbench` on the kernel command line
+pub fn bench_task() -> ! {
+    match bench() {
+        Ok((legacy, fast)) => {
+            let (legacy, fast) = (legacy.as_nanos(), fast.as_nanos());
+            crate::serial_println!(
+                "[SYSCALL] syscallbench: int 0x80 {} ns/call, syscall {} ns/call ({}.{}x)",
+                legacy,
+                fast,
+                legacy / fast.max(1),
+                legacy * 10 / fast.max(1) % 10
+            );
+        }
+        Err(e) => crate::serial_println!("[SYSCALL] syscallbench: {}", e),
+    }
+
+    loop {
+        if let Some((_, priority)) = crate::sched::get_current_task_info() {
+            crate::sched::sleep_current_task(Duration::from_secs(3600), priority);
+        }
+        crate::sched::yield_now();
+    }
+}
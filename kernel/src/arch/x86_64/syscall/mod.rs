@@ -4,50 +4,38 @@
 //! providing efficient user-kernel transitions using MSR configuration
 //! and assembly entry points.
 
-use crate::arch::x86_64::gdt::{KERNEL_CODE_SEG, USER_CODE_SEG};
+use crate::arch::x86_64::gdt::{KERNEL_CODE_SEG, USER_CODE_SEG, USER_DATA_SEG};
+use crate::arch::x86_64::msr::{self, rdmsr, wrmsr};
+use crate::sys::errno::{to_return, Errno, SyscallResult};
 use crate::{serial_print, serial_println};
 
+pub mod bench;
+
 /// Model Specific Registers for syscall/sysret
-const EFER_MSR: u32 = 0xC0000080; // Extended Feature Enable Register
-const STAR_MSR: u32 = 0xC0000081; // Syscall target address
-const LSTAR_MSR: u32 = 0xC0000082; // Long mode syscall target
-const SFMASK_MSR: u32 = 0xC0000084; // Syscall flag mask
-const KERNEL_GS_BASE_MSR: u32 = 0xC0000102; // Kernel GS base
-const GS_BASE_MSR: u32 = 0xC0000101; // User GS base
+const EFER_MSR: u32 = msr::EFER; // Extended Feature Enable Register
+const STAR_MSR: u32 = msr::STAR; // Syscall target address
+const LSTAR_MSR: u32 = msr::LSTAR; // Long mode syscall target
+const SFMASK_MSR: u32 = msr::SFMASK; // Syscall flag mask
+const KERNEL_GS_BASE_MSR: u32 = msr::KERNEL_GS_BASE; // Kernel GS base
+const GS_BASE_MSR: u32 = msr::GS_BASE; // User GS base
 
 /// System Call Extensions enable bit in EFER
 const SCE_BIT: u64 = 1 << 0;
 
-/// Write a value to a Model-Specific Register (MSR)
-#[inline]
-unsafe fn wrmsr(msr: u32, value: u64) {
-    let low = value as u32;
-    let high = (value >> 32) as u32;
-
-    core::arch::asm!(
-        "wrmsr",
-        in("ecx") msr,
-        in("eax") low,
-        in("edx") high,
-        options(nostack, preserves_flags)
-    );
-}
-
-/// Read a value from a Model-Specific Register (MSR)
-#[inline]
-unsafe fn rdmsr(msr: u32) -> u64 {
-    let low: u32;
-    let high: u32;
-
-    core::arch::asm!(
-        "rdmsr",
-        in("ecx") msr,
-        out("eax") low,
-        out("edx") high,
-        options(nostack, preserves_flags)
-    );
+/// Mask of RFLAGS bits cleared on `syscall` entry: IF, so the entry runs
+/// with interrupts off until it is on the kernel stack, DF for the ABI,
+/// and AC so user space cannot enter the kernel with SMAP user access open
+const SFMASK_VALUE: u64 = 0x200 | 0x400 | 0x40000;
 
-    ((high as u64) << 32) | (low as u64)
+/// STAR value for the GDT layout
+///
+/// Bits 47:32 give the kernel CS (SS = CS + 8). Bits 63:48 give the base
+/// SYSRET loads from: SS = base + 8 and CS = base + 16, both with RPL 3,
+/// which is why the user data descriptor sits right below user code.
+pub const fn star_value() -> u64 {
+    let user_base = ((USER_DATA_SEG & !3) - 8) as u64; // 0x30
+    let kernel_cs = KERNEL_CODE_SEG as u64; // 0x28
+    (user_base << 48) | (kernel_cs << 32)
 }
 
 /// Initialize syscall MSRs for fast syscall/sysret mechanism
@@ -58,7 +46,8 @@ unsafe fn rdmsr(msr: u32) -> u64 {
 ///
 /// # Safety
 /// This function writes to MSRs which affects system behavior.
-/// It must be called exactly once per CPU during boot.
+/// It must be called exactly once per CPU during boot, after the CPU's
+/// GS base points at its `PerCpu`.
 pub unsafe fn init_syscall_msrs(cpu_id: usize) {
     serial_println!("[SYSCALL] Initializing syscall MSRs for CPU {}", cpu_id);
 
@@ -69,50 +58,42 @@ pub unsafe fn init_syscall_msrs(cpu_id: usize) {
 
     serial_println!("[SYSCALL] CPU {} EFER.SCE enabled: 0x{:x}", cpu_id, efer);
 
-    // 2. STAR: Set kernel and user segment selectors (base selectors)
-    // Hardware will derive SS = CS + 8 automatically for both kernel and user
-    // Bits 63:48 = User CS base (USER_CODE_SEG without RPL bits)
-    // Bits 47:32 = Kernel CS base (KERNEL_CODE_SEG)
-    let user_cs_base = (USER_CODE_SEG & !3) as u64; // Remove RPL bits (0x38)
-    let kernel_cs_base = KERNEL_CODE_SEG as u64; // 0x28
-    let star_value = (user_cs_base << 48) | (kernel_cs_base << 32);
-    wrmsr(STAR_MSR, star_value);
+    // 2. STAR: kernel CS for SYSCALL, user selector base for SYSRET
+    let star = star_value();
+    wrmsr(STAR_MSR, star);
 
     serial_println!(
-        "[SYSCALL] CPU {} STAR configured: 0x{:x} (kernel_cs=0x{:x}, user_cs=0x{:x})",
+        "[SYSCALL] CPU {} STAR configured: 0x{:x} (kernel_cs=0x{:x}, user_cs=0x{:x}, user_ss=0x{:x})",
         cpu_id,
-        star_value,
-        kernel_cs_base,
-        user_cs_base
+        star,
+        KERNEL_CODE_SEG,
+        USER_CODE_SEG,
+        USER_DATA_SEG
     );
 
     // 3. LSTAR: Set syscall entry point
-    let lstar_value = syscall_entry_fast as u64;
+    let lstar_value = syscall_entry64 as *const () as u64;
     wrmsr(LSTAR_MSR, lstar_value);
 
     serial_println!("[SYSCALL] CPU {} LSTAR set to: 0x{:x}", cpu_id, lstar_value);
 
     // 4. SFMASK: Mask RFLAGS bits during syscall
-    // Clear IF (interrupt flag) during syscall for atomic entry
-    let sfmask_value = 0x200; // IF bit (bit 9)
-    wrmsr(SFMASK_MSR, sfmask_value);
+    wrmsr(SFMASK_MSR, SFMASK_VALUE);
 
     serial_println!(
         "[SYSCALL] CPU {} SFMASK set to: 0x{:x}",
         cpu_id,
-        sfmask_value
+        SFMASK_VALUE
     );
 
-    // 5. Set up GS base for per-CPU data access
-    // KERNEL_GS_BASE will be swapped with GS_BASE by SWAPGS
-    let percpu_base = crate::arch::x86_64::smp::percpu::percpu_for(cpu_id) as *const _ as u64;
-    wrmsr(KERNEL_GS_BASE_MSR, percpu_base);
-    wrmsr(GS_BASE_MSR, 0); // User GS base (initially 0)
+    // 5. GS_BASE already holds this CPU's PerCpu; KERNEL_GS_BASE is the
+    // user GS base SWAPGS exchanges it with, 0 until a task sets one
+    wrmsr(KERNEL_GS_BASE_MSR, 0);
 
     serial_println!(
         "[SYSCALL] CPU {} GS bases configured: kernel=0x{:x}, user=0x{:x}",
         cpu_id,
-        percpu_base,
+        rdmsr(GS_BASE_MSR),
         0
     );
 
@@ -122,32 +103,131 @@ pub unsafe fn init_syscall_msrs(cpu_id: usize) {
     );
 }
 
-/// External assembly function for fast syscall entry
-extern "C" {
-    fn syscall_entry_fast();
+/// Entry point of the `syscall` instruction (LSTAR)
+///
+/// The CPU arrives here in ring 0 with the user RIP in RCX, the user
+/// RFLAGS in R11 and everything else, RSP and GS included, still the
+/// user's. The stub swaps to the kernel GS, moves to the running task's
+/// kernel stack (`PerCpu::kernel_stack`, kept current by `context_switch`)
+/// and builds the same frame `int 0x80` would: an interrupt frame followed
+/// by the 15 general registers. Arguments follow the System V syscall
+/// convention (RAX = number, RDI, RSI, RDX, R10, R8, R9), and the return
+/// value goes back in RAX; RCX and R11 are clobbered.
+///
+/// Returns with SYSRET unless the frame's RIP is not canonical, where
+/// SYSRET would fault in ring 0; the task is terminated instead.
+#[unsafe(naked)]
+#[no_mangle]
+pub extern "C" fn syscall_entry64() {
+    core::arch::naked_asm!(
+        "swapgs",
+        "mov gs:[{percpu_user_rsp}], rsp",
+        "mov rsp, gs:[{percpu_kernel_stack}]",
+
+        // Interrupt frame: SS, RSP, RFLAGS, CS, RIP
+        "push {user_ss}",
+        "push qword ptr gs:[{percpu_user_rsp}]",
+        "push r11",
+        "push {user_cs}",
+        "push rcx",
+
+        // Same register layout as the int 0x80 entry (rax at [rsp + 112])
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+
+        "cld",
+
+        // syscall_dispatcher_enhanced(rax, rdi, rsi, rdx, r10, r8, r9)
+        "mov rdi, rax",
+        "mov rsi, [rsp + 80]",
+        "mov rdx, [rsp + 88]",
+        "mov rcx, [rsp + 96]",
+        "mov r8, [rsp + 56]",
+        "mov r9, [rsp + 72]",
+        // The seventh argument goes on the stack; the padding keeps the
+        // call 16-byte aligned
+        "sub rsp, 8",
+        "push qword ptr [rsp + 72]",
+        "call {dispatcher}",
+        "add rsp, 16",
+
+        "mov [rsp + 112], rax",
+
+        // SYSRET to a non-canonical RIP would #GP in ring 0 on the user stack
+        "mov rcx, [rsp + 120]",
+        "shr rcx, 47",
+        "jnz 2f",
+
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+
+        // The frame may have been switched away from and back; nothing may
+        // interrupt between the stack switch and SYSRET
+        "cli",
+        "pop rcx",
+        "add rsp, 8",
+        "pop r11",
+        "mov rsp, [rsp]",
+        "swapgs",
+        "sysretq",
+
+        "2:",
+        "mov rdi, [rsp + 120]",
+        "mov rsi, rsp",
+        "call {bad_return}",
+        "ud2",
+
+        percpu_user_rsp = const core::mem::offset_of!(crate::arch::x86_64::smp::percpu::PerCpu, user_rsp),
+        percpu_kernel_stack = const core::mem::offset_of!(crate::arch::x86_64::smp::percpu::PerCpu, kernel_stack),
+        user_ss = const USER_DATA_SEG,
+        user_cs = const USER_CODE_SEG,
+        dispatcher = sym syscall_dispatcher_enhanced,
+        bad_return = sym handle_bad_syscall_return,
+    )
 }
 
 /// Handler for bad syscall returns (non-canonical addresses)
 ///
-/// This function is called from assembly when SYSRET would fail due to
-/// non-canonical return addresses. It terminates the current process.
-#[no_mangle]
-extern "C" fn handle_bad_syscall_return(bad_addr1: u64, bad_addr2: u64) -> ! {
+/// Called from `syscall_entry64` when SYSRET would fail due to a
+/// non-canonical return address. It terminates the current process.
+extern "C" fn handle_bad_syscall_return(rip: u64, frame: u64) -> ! {
     let cpu_id = unsafe { crate::arch::x86_64::smp::percpu::percpu_current().id };
 
     serial_println!(
-        "[SYSCALL][cpu{}] FATAL: Non-canonical return address",
-        cpu_id
-    );
-    serial_println!(
-        "[SYSCALL] Bad address 1: 0x{:x}, Bad address 2: 0x{:x}",
-        bad_addr1,
-        bad_addr2
+        "[SYSCALL][cpu{}] Non-canonical return address 0x{:x} (frame at 0x{:x}), terminating",
+        cpu_id,
+        rip,
+        frame
     );
 
-    // TODO: When process management is implemented, terminate the current process
-    // For now, just panic to indicate the error
-    panic!("[SYSCALL] Non-canonical return address - process would be terminated");
+    // Exit as if killed by SIGSEGV
+    sys_exit_enhanced(128 + 11)
 }
 
 /// User space address limit
@@ -164,16 +244,55 @@ pub const SYS_YIELD: usize = 6;
 pub const SYS_FORK: usize = 7;
 pub const SYS_WAIT: usize = 8;
 pub const SYS_EXEC: usize = 9;
-
-/// Error codes (POSIX-compatible)
-pub const ENOSYS: isize = -38; // Function not implemented
-pub const EFAULT: isize = -14; // Bad address
-pub const ENOMEM: isize = -12; // Out of memory
-pub const ECHILD: isize = -10; // No child processes
-pub const ESRCH: isize = -3; // No such process
-pub const EAGAIN: isize = -11; // Try again
-pub const EINVAL: isize = -22; // Invalid argument
-pub const EPERM: isize = -1; // Operation not permitted
+pub const SYS_KILL: usize = crate::sys::syscall::SYS_KILL;
+pub const SYS_GETRANDOM: usize = crate::sys::syscall::SYS_GETRANDOM;
+pub const SYS_UMASK: usize = crate::sys::syscall::SYS_UMASK;
+pub const SYS_GETRUSAGE: usize = crate::sys::syscall::SYS_GETRUSAGE;
+pub const SYS_MMAP: usize = crate::sys::syscall::SYS_MMAP;
+pub const SYS_MUNMAP: usize = crate::sys::syscall::SYS_MUNMAP;
+pub const SYS_MPROTECT: usize = crate::sys::syscall::SYS_MPROTECT;
+pub const SYS_EVENT_SUBSCRIBE: usize = crate::sys::syscall::SYS_EVENT_SUBSCRIBE;
+pub const SYS_BRK: usize = crate::sys::syscall::SYS_BRK;
+pub const SYS_SHM_CREATE: usize = crate::sys::syscall::SYS_SHM_CREATE;
+pub const SYS_SHM_MAP: usize = crate::sys::syscall::SYS_SHM_MAP;
+pub const SYS_IPC_POLL: usize = crate::sys::syscall::SYS_IPC_POLL;
+pub const SYS_IPC_NOTIFY: usize = crate::sys::syscall::SYS_IPC_NOTIFY;
+pub const SYS_PORT_CREATE: usize = crate::sys::syscall::SYS_PORT_CREATE;
+pub const SYS_CAP_DERIVE: usize = crate::sys::syscall::SYS_CAP_DERIVE;
+pub const SYS_CAP_DROP: usize = crate::sys::syscall::SYS_CAP_DROP;
+pub const SYS_PIPE: usize = crate::sys::syscall::SYS_PIPE;
+pub const SYS_PERF: usize = crate::sys::syscall::SYS_PERF;
+pub const SYS_POLL: usize = crate::sys::syscall::SYS_POLL;
+pub const SYS_THREAD_CREATE: usize = crate::sys::syscall::SYS_THREAD_CREATE;
+pub const SYS_THREAD_EXIT: usize = crate::sys::syscall::SYS_THREAD_EXIT;
+pub const SYS_FUTEX: usize = crate::sys::syscall::SYS_FUTEX;
+pub const SYS_SENDFILE: usize = crate::sys::syscall::SYS_SENDFILE;
+pub const SYS_CLOCK_GETTIME: usize = crate::sys::syscall::SYS_CLOCK_GETTIME;
+pub const SYS_PTRACE_LITE: usize = crate::sys::syscall::SYS_PTRACE_LITE;
+pub const SYS_SECCOMP: usize = crate::sys::syscall::SYS_SECCOMP;
+pub const SYS_SPAWN: usize = crate::sys::syscall::SYS_SPAWN;
+pub const SYS_DUP: usize = crate::sys::syscall::SYS_DUP;
+pub const SYS_TIMER_CREATE: usize = crate::sys::syscall::SYS_TIMER_CREATE;
+pub const SYS_EVENT_CREATE: usize = crate::sys::syscall::SYS_EVENT_CREATE;
+pub const SYS_CPU_GROUP: usize = crate::sys::syscall::SYS_CPU_GROUP;
+pub const SYS_CPU_QUOTA: usize = crate::sys::syscall::SYS_CPU_QUOTA;
+pub const SYS_HWINFO: usize = crate::sys::syscall::SYS_HWINFO;
+pub const SYS_PING: usize = crate::sys::syscall::SYS_PING;
+pub const SYS_SOCKET: usize = crate::sys::syscall::SYS_SOCKET;
+pub const SYS_BIND: usize = crate::sys::syscall::SYS_BIND;
+pub const SYS_SENDTO: usize = crate::sys::syscall::SYS_SENDTO;
+pub const SYS_RECVFROM: usize = crate::sys::syscall::SYS_RECVFROM;
+pub const SYS_CONNECT: usize = crate::sys::syscall::SYS_CONNECT;
+pub const SYS_LISTEN: usize = crate::sys::syscall::SYS_LISTEN;
+pub const SYS_ACCEPT: usize = crate::sys::syscall::SYS_ACCEPT;
+pub const SYS_RESOLVE: usize = crate::sys::syscall::SYS_RESOLVE;
+pub const SYS_SCHEDSTAT: usize = crate::sys::syscall::SYS_SCHEDSTAT;
+pub const SYS_TASK_INFO: usize = crate::sys::syscall::SYS_TASK_INFO;
+pub const SYS_TASK_LIST: usize = crate::sys::syscall::SYS_TASK_LIST;
+pub const SYS_IPC_RECV_TIMEOUT: usize = crate::sys::syscall::SYS_IPC_RECV_TIMEOUT;
+pub const SYS_TASK_WATCH: usize = crate::sys::syscall::SYS_TASK_WATCH;
+pub const SYS_NANOSLEEP: usize = crate::sys::syscall::SYS_NANOSLEEP;
+pub const SYS_POWEROFF: usize = crate::sys::syscall::SYS_POWEROFF;
 
 /// Validate user pointer is in user space
 pub fn is_user_pointer_valid(ptr: usize) -> bool {
@@ -192,15 +311,15 @@ pub fn is_user_pointer_valid(ptr: usize) -> bool {
 ///
 /// # Returns
 /// Ok(()) on success, Err on invalid pointer or copy failure
-pub fn copy_from_user(dst: &mut [u8], src_ptr: usize, len: usize) -> Result<(), isize> {
+pub fn copy_from_user(dst: &mut [u8], src_ptr: usize, len: usize) -> Result<(), Errno> {
     // Validate source pointer is in user space
     if !is_user_pointer_valid(src_ptr) || !is_user_pointer_valid(src_ptr + len) {
-        return Err(EFAULT);
+        return Err(Errno::EFAULT);
     }
 
     // Check destination buffer size
     if len > dst.len() {
-        return Err(EINVAL);
+        return Err(Errno::EINVAL);
     }
 
     // TODO: When implementing full page table separation, replace direct pointer
@@ -208,8 +327,7 @@ pub fn copy_from_user(dst: &mut [u8], src_ptr: usize, len: usize) -> Result<(),
 
     // Perform copy with page fault handling (current shared address space)
     unsafe {
-        let src = core::slice::from_raw_parts(src_ptr as *const u8, len);
-        dst[..len].copy_from_slice(src);
+        crate::arch::x86_64::cpu::copy_user_bytes(dst.as_mut_ptr(), src_ptr as *const u8, len);
     }
 
     Ok(())
@@ -226,10 +344,10 @@ pub fn copy_from_user(dst: &mut [u8], src_ptr: usize, len: usize) -> Result<(),
 ///
 /// # Returns
 /// Ok(()) on success, Err on invalid pointer or copy failure
-pub fn copy_to_user(dst_ptr: usize, src: &[u8]) -> Result<(), isize> {
+pub fn copy_to_user(dst_ptr: usize, src: &[u8]) -> Result<(), Errno> {
     // Validate destination pointer is in user space
     if !is_user_pointer_valid(dst_ptr) || !is_user_pointer_valid(dst_ptr + src.len()) {
-        return Err(EFAULT);
+        return Err(Errno::EFAULT);
     }
 
     // TODO: When implementing full page table separation, replace direct pointer
@@ -237,8 +355,7 @@ pub fn copy_to_user(dst_ptr: usize, src: &[u8]) -> Result<(), isize> {
 
     // Perform copy with page fault handling (current shared address space)
     unsafe {
-        let dst = core::slice::from_raw_parts_mut(dst_ptr as *mut u8, src.len());
-        dst.copy_from_slice(src);
+        crate::arch::x86_64::cpu::copy_user_bytes(dst_ptr as *mut u8, src.as_ptr(), src.len());
     }
 
     Ok(())
@@ -248,6 +365,8 @@ pub fn copy_to_user(dst_ptr: usize, src: &[u8]) -> Result<(), isize> {
 ///
 /// This dispatcher extends the existing syscall interface with new syscalls
 /// required for user-mode support and adds detailed logging for debugging.
+/// Calls of traced tasks are logged through `sys::strace`, and calls the
+/// task's filter denies are refused through `sys::seccomp`.
 ///
 /// # Arguments
 /// * `syscall_id` - Syscall number (from RAX)
@@ -270,14 +389,40 @@ extern "C" fn syscall_dispatcher_enhanced(
     arg5: usize,
     arg6: usize,
 ) -> isize {
+    let args = [arg1, arg2, arg3, arg4, arg5, arg6];
+    crate::trace!(syscall_enter, syscall_id, arg1, arg2);
+    let start = crate::sys::strace::enter(syscall_id, &args);
+    let result = to_return(match crate::sys::seccomp::check(syscall_id) {
+        Some(errno) => Err(errno),
+        None => dispatch_enhanced(syscall_id, arg1, arg2, arg3, arg4, arg5, arg6),
+    });
+    if let Some(start) = start {
+        crate::sys::strace::exit(start, syscall_id, &args, result);
+    }
+    crate::trace!(syscall_exit, syscall_id, result);
+    result
+}
+
+/// Route a syscall from the `syscall` entry to its handler, untraced
+fn dispatch_enhanced(
+    syscall_id: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+    arg4: usize,
+    arg5: usize,
+    arg6: usize,
+) -> SyscallResult {
     // Get current CPU and process for detailed logging
     let cpu_id = unsafe { crate::arch::x86_64::smp::percpu::percpu_current().id };
     let pid = get_current_process_id().unwrap_or(0);
     let rip = get_current_rip();
 
-    // Log syscall with CPU, PID, and RIP for debugging SMP issues
-    serial_println!(
-        "[SYSCALL][cpu{} pid={} rip=0x{:x}] {} ({})",
+    // Log syscall with CPU, PID, and RIP for debugging SMP issues; trace
+    // level, as console output would dominate the cost of a syscall
+    crate::log_trace!(
+        "SYSCALL",
+        "[cpu{} pid={} rip=0x{:x}] {} ({})",
         cpu_id,
         pid,
         rip,
@@ -289,14 +434,14 @@ extern "C" fn syscall_dispatcher_enhanced(
     let result = match syscall_id {
         SYS_WRITE => {
             if !is_user_pointer_valid(arg2) {
-                EFAULT
+                Err(Errno::EFAULT)
             } else {
                 sys_write_enhanced(arg1, arg2, arg3)
             }
         }
         SYS_EXEC => {
             if !is_user_pointer_valid(arg1) {
-                EFAULT
+                Err(Errno::EFAULT)
             } else {
                 sys_exec_stub(arg1, arg2)
             }
@@ -308,48 +453,114 @@ extern "C" fn syscall_dispatcher_enhanced(
         SYS_GETPID => sys_getpid_enhanced(),
 
         // Keep existing syscalls for compatibility
-        SYS_SLEEP => {
+        SYS_SLEEP | SYS_KILL | SYS_UMASK | SYS_EVENT_SUBSCRIBE | SYS_BRK | SYS_SHM_CREATE | SYS_SHM_MAP
+        | SYS_IPC_NOTIFY | SYS_PORT_CREATE | SYS_CAP_DERIVE | SYS_CAP_DROP | SYS_PERF | SYS_POLL
+        | SYS_THREAD_EXIT | SYS_FUTEX | SYS_SENDFILE | SYS_CLOCK_GETTIME
+        | SYS_PTRACE_LITE | SYS_SECCOMP | SYS_SPAWN | SYS_DUP | SYS_TIMER_CREATE | SYS_EVENT_CREATE
+        | SYS_CPU_GROUP | SYS_CPU_QUOTA | SYS_HWINFO | SYS_PING | SYS_SOCKET | SYS_BIND
+        | SYS_CONNECT | SYS_LISTEN | SYS_ACCEPT | SYS_RESOLVE | SYS_SCHEDSTAT | SYS_TASK_INFO
+        | SYS_TASK_LIST | SYS_TASK_WATCH | SYS_NANOSLEEP | SYS_POWEROFF => {
             // Delegate to existing implementation
-            crate::sys::syscall::syscall_dispatcher(syscall_id, arg1, arg2, arg3)
+            crate::sys::syscall::dispatch(syscall_id, arg1, arg2, arg3)
         }
         SYS_IPC_SEND => {
             if !is_user_pointer_valid(arg2) {
-                EFAULT
+                Err(Errno::EFAULT)
             } else {
-                crate::sys::syscall::syscall_dispatcher(syscall_id, arg1, arg2, arg3)
+                crate::sys::syscall::dispatch(syscall_id, arg1, arg2, arg3)
             }
         }
         SYS_IPC_RECV => {
             if !is_user_pointer_valid(arg2) {
-                EFAULT
+                Err(Errno::EFAULT)
             } else {
-                crate::sys::syscall::syscall_dispatcher(syscall_id, arg1, arg2, arg3)
+                crate::sys::syscall::dispatch(syscall_id, arg1, arg2, arg3)
+            }
+        }
+        SYS_IPC_POLL => {
+            if !is_user_pointer_valid(arg1) {
+                Err(Errno::EFAULT)
+            } else {
+                crate::sys::syscall::dispatch(syscall_id, arg1, arg2, arg3)
+            }
+        }
+        SYS_PIPE => {
+            if !is_user_pointer_valid(arg1) {
+                Err(Errno::EFAULT)
+            } else {
+                crate::sys::syscall::dispatch(syscall_id, arg1, arg2, arg3)
+            }
+        }
+        SYS_THREAD_CREATE => {
+            if !is_user_pointer_valid(arg1) {
+                Err(Errno::EFAULT)
+            } else {
+                crate::sys::syscall::dispatch(syscall_id, arg1, arg2, arg3)
+            }
+        }
+        SYS_GETRUSAGE => {
+            if !is_user_pointer_valid(arg2) {
+                Err(Errno::EFAULT)
+            } else {
+                crate::sys::syscall::dispatch(syscall_id, arg1, arg2, arg3)
+            }
+        }
+        SYS_GETRANDOM => {
+            if !is_user_pointer_valid(arg1) {
+                Err(Errno::EFAULT)
+            } else {
+                crate::sys::syscall::dispatch(syscall_id, arg1, arg2, arg3)
+            }
+        }
+
+        // Memory mappings (fd and offset are unused: only anonymous mappings exist)
+        SYS_MMAP => sys_mmap(arg1, arg2, arg3, arg4, arg5, arg6),
+        SYS_MUNMAP => sys_munmap(arg1, arg2),
+        SYS_MPROTECT => sys_mprotect(arg1, arg2, arg3),
+
+        // Datagrams (six arguments); the handlers validate their pointers
+        SYS_SENDTO => crate::sys::syscall::sys_sendto(arg1, arg2, arg3, arg4, arg5, arg6),
+        SYS_RECVFROM => crate::sys::syscall::sys_recvfrom(arg1, arg2, arg3, arg4, arg5, arg6),
+
+        // Timed receive (four arguments)
+        SYS_IPC_RECV_TIMEOUT => {
+            if !is_user_pointer_valid(arg2) {
+                Err(Errno::EFAULT)
+            } else {
+                crate::sys::syscall::sys_ipc_recv_timeout(arg1, arg2, arg3, arg4)
             }
         }
 
+        // Files, signals, process groups and terminals; the handlers
+        // validate their own pointers
+        crate::sys::syscall::SYS_OPEN..=crate::sys::syscall::SYS_DUP2 => {
+            crate::sys::syscall::dispatch(syscall_id, arg1, arg2, arg3)
+        }
+
         _ => {
             serial_println!("[SYSCALL] ERROR: Invalid syscall ID: {}", syscall_id);
-            ENOSYS
+            Err(Errno::ENOSYS)
         }
     };
 
     // Log syscall return value
-    if result >= 0 {
-        serial_println!(
-            "[SYSCALL][cpu{} pid={}] {} returned: {}",
+    match result {
+        Ok(value) => crate::log_trace!(
+            "SYSCALL",
+            "[cpu{} pid={}] {} returned: {}",
             cpu_id,
             pid,
             syscall_name(syscall_id),
-            result
-        );
-    } else {
-        serial_println!(
-            "[SYSCALL][cpu{} pid={}] {} failed with error: {}",
+            value
+        ),
+        Err(errno) => crate::log_trace!(
+            "SYSCALL",
+            "[cpu{} pid={}] {} failed with error: {:?}",
             cpu_id,
             pid,
             syscall_name(syscall_id),
-            result
-        );
+            errno
+        ),
     }
 
     result
@@ -368,6 +579,55 @@ fn syscall_name(id: usize) -> &'static str {
         SYS_SLEEP => "SYS_SLEEP",
         SYS_IPC_SEND => "SYS_IPC_SEND",
         SYS_IPC_RECV => "SYS_IPC_RECV",
+        SYS_KILL => "SYS_KILL",
+        SYS_GETRANDOM => "SYS_GETRANDOM",
+        SYS_UMASK => "SYS_UMASK",
+        SYS_GETRUSAGE => "SYS_GETRUSAGE",
+        SYS_MMAP => "SYS_MMAP",
+        SYS_MUNMAP => "SYS_MUNMAP",
+        SYS_MPROTECT => "SYS_MPROTECT",
+        SYS_EVENT_SUBSCRIBE => "SYS_EVENT_SUBSCRIBE",
+        SYS_BRK => "SYS_BRK",
+        SYS_SHM_CREATE => "SYS_SHM_CREATE",
+        SYS_SHM_MAP => "SYS_SHM_MAP",
+        SYS_IPC_POLL => "SYS_IPC_POLL",
+        SYS_IPC_NOTIFY => "SYS_IPC_NOTIFY",
+        SYS_PORT_CREATE => "SYS_PORT_CREATE",
+        SYS_CAP_DERIVE => "SYS_CAP_DERIVE",
+        SYS_CAP_DROP => "SYS_CAP_DROP",
+        SYS_PIPE => "SYS_PIPE",
+        SYS_PERF => "SYS_PERF",
+        SYS_POLL => "SYS_POLL",
+        SYS_THREAD_CREATE => "SYS_THREAD_CREATE",
+        SYS_THREAD_EXIT => "SYS_THREAD_EXIT",
+        SYS_FUTEX => "SYS_FUTEX",
+        SYS_SENDFILE => "SYS_SENDFILE",
+        SYS_CLOCK_GETTIME => "SYS_CLOCK_GETTIME",
+        SYS_PTRACE_LITE => "SYS_PTRACE_LITE",
+        SYS_SECCOMP => "SYS_SECCOMP",
+        SYS_SPAWN => "SYS_SPAWN",
+        SYS_DUP => "SYS_DUP",
+        SYS_TIMER_CREATE => "SYS_TIMER_CREATE",
+        SYS_EVENT_CREATE => "SYS_EVENT_CREATE",
+        SYS_CPU_GROUP => "SYS_CPU_GROUP",
+        SYS_CPU_QUOTA => "SYS_CPU_QUOTA",
+        SYS_HWINFO => "SYS_HWINFO",
+        SYS_PING => "SYS_PING",
+        SYS_SOCKET => "SYS_SOCKET",
+        SYS_BIND => "SYS_BIND",
+        SYS_SENDTO => "SYS_SENDTO",
+        SYS_RECVFROM => "SYS_RECVFROM",
+        SYS_CONNECT => "SYS_CONNECT",
+        SYS_LISTEN => "SYS_LISTEN",
+        SYS_ACCEPT => "SYS_ACCEPT",
+        SYS_RESOLVE => "SYS_RESOLVE",
+        SYS_SCHEDSTAT => "SYS_SCHEDSTAT",
+        SYS_TASK_INFO => "SYS_TASK_INFO",
+        SYS_TASK_LIST => "SYS_TASK_LIST",
+        SYS_IPC_RECV_TIMEOUT => "SYS_IPC_RECV_TIMEOUT",
+        SYS_TASK_WATCH => "SYS_TASK_WATCH",
+        SYS_NANOSLEEP => "SYS_NANOSLEEP",
+        SYS_POWEROFF => "SYS_POWEROFF",
         _ => "UNKNOWN",
     }
 }
@@ -375,11 +635,9 @@ fn syscall_name(id: usize) -> &'static str {
 /// Get current process ID (stub for now)
 fn get_current_process_id() -> Option<usize> {
     // TODO: Implement when process management is available
-    // For now, return task ID from scheduler
-    match crate::sched::get_current_task_info() {
-        Some((task_id, _)) => Some(task_id),
-        None => None,
-    }
+    // For now, return the pid the scheduler keeps (a thread reports its process)
+    let (task_id, _) = crate::sched::get_current_task_info()?;
+    crate::sched::get_task_by_id(task_id).map(|task| task.pid)
 }
 
 /// Get current RIP for debugging
@@ -427,6 +685,22 @@ pub struct SyscallConfig {
     pub gs_base: u64,
 }
 
+crate::kernel_test! {
+    /// SYSCALL is enabled and enters through syscall_entry64 with the
+    /// selectors SYSRET needs
+    fn syscall_msrs_configured() {
+        let config = get_syscall_config();
+        crate::ktest_assert!(config.efer & SCE_BIT != 0, "EFER.SCE not set");
+        crate::ktest_assert_eq!(config.lstar, syscall_entry64 as *const () as u64, "LSTAR not syscall_entry64");
+        crate::ktest_assert_eq!(config.star, star_value(), "STAR selectors");
+        crate::ktest_assert_eq!(config.sfmask, SFMASK_VALUE, "SFMASK");
+        let percpu = crate::arch::x86_64::smp::percpu::percpu_current() as *const _ as u64;
+        crate::ktest_assert_eq!(config.gs_base, percpu, "GS base is not this CPU's PerCpu");
+        crate::ktest_assert!(config.kernel_gs_base != percpu, "GS bases swapped in the kernel");
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -451,57 +725,112 @@ mod tests {
     #[test]
     fn test_star_encoding() {
         // Test STAR register encoding
-        let user_cs_base = (USER_CODE_SEG & !3) as u64; // 0x38
-        let kernel_cs_base = KERNEL_CODE_SEG as u64; // 0x28
-        let star_value = (user_cs_base << 48) | (kernel_cs_base << 32);
+        let star = star_value();
+        let user_base = (star >> 48) & 0xFFFF;
 
-        // Verify the encoding
-        assert_eq!((star_value >> 32) & 0xFFFF, kernel_cs_base);
-        assert_eq!((star_value >> 48) & 0xFFFF, user_cs_base);
+        // Verify the encoding: SYSRET loads SS = base + 8, CS = base + 16
+        assert_eq!((star >> 32) & 0xFFFF, KERNEL_CODE_SEG as u64);
+        assert_eq!((user_base + 8) | 3, USER_DATA_SEG as u64);
+        assert_eq!((user_base + 16) | 3, USER_CODE_SEG as u64);
     }
 
     #[test]
     fn test_sfmask_value() {
-        // Verify SFMASK clears IF bit (bit 9 = 0x200)
-        let sfmask_value = 0x200;
-        assert_eq!(sfmask_value, 1 << 9);
+        // Verify SFMASK clears IF (bit 9), DF (bit 10) and AC (bit 18)
+        assert_eq!(SFMASK_VALUE, (1 << 9) | (1 << 10) | (1 << 18));
     }
 }
 
 /// Enhanced sys_write handler with user pointer validation
 ///
-/// This version adds user pointer validation and fastpath optimization
-/// for small writes to improve performance.
-fn sys_write_enhanced(fd: usize, buf_ptr: usize, len: usize) -> isize {
+/// This version adds user pointer validation and writes raw bytes to the
+/// console multiplexer (serial, framebuffer or both).
+fn sys_write_enhanced(fd: usize, buf_ptr: usize, len: usize) -> SyscallResult {
     // Validate file descriptor (only stdout supported for now)
     if fd != 1 {
-        return EINVAL;
+        return Err(Errno::EINVAL);
     }
 
     if len == 0 {
-        return 0; // Nothing to write
+        return Ok(0); // Nothing to write
     }
 
-    // Fast path for small writes (already validated pointer above)
-    if len <= 256 {
-        unsafe {
-            let buffer = core::slice::from_raw_parts(buf_ptr as *const u8, len);
-            if let Ok(s) = core::str::from_utf8(buffer) {
-                serial_print!("{}", s);
-                return len as isize;
-            }
+    // Stage the user buffer through the copy routine; SMAP forbids touching
+    // it directly
+    let mut chunk = [0u8; 256];
+    let mut written = 0;
+    while written < len {
+        let n = core::cmp::min(len - written, chunk.len());
+        if let Err(e) = copy_from_user(&mut chunk[..n], buf_ptr + written, n) {
+            return if written > 0 { Ok(written) } else { Err(e) };
         }
+        crate::console::write_bytes(&chunk[..n]);
+        written += n;
     }
 
-    // Fallback to regular implementation for larger writes
-    // TODO: Implement proper buffered I/O for large writes
-    unsafe {
-        let buffer = core::slice::from_raw_parts(buf_ptr as *const u8, len);
-        let s = core::str::from_utf8(buffer).unwrap_or("[invalid UTF-8]");
-        serial_print!("{}", s);
+    Ok(len)
+}
+
+/// Task holding the current task's address space, for syscalls that change it
+fn current_task_mut() -> Option<&'static mut crate::sched::task::Task> {
+    crate::sched::get_current_task_info().and_then(|(id, _)| crate::sched::get_address_space_mut(id))
+}
+
+/// mmap handler - create an anonymous mapping, or map a device
+///
+/// # Arguments
+/// * `addr` - Placement hint, or the exact address with `MAP_FIXED`
+/// * `len` - Length in bytes (rounded up to whole pages)
+/// * `prot` - `PROT_*` bits; writable and executable together is refused
+/// * `flags` - `MAP_PRIVATE | MAP_ANONYMOUS`, or `MAP_SHARED` to map `fd`;
+///   optionally `MAP_FIXED`
+/// * `fd` - Device to map without `MAP_ANONYMOUS`; only `/dev/fb0` can be
+/// * `offset` - Page-aligned offset into the device
+///
+/// # Returns
+/// Start address of the mapping, or an error
+fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> SyscallResult {
+    let task = match current_task_mut() {
+        Some(task) => task,
+        None => return Err(Errno::ESRCH),
+    };
+    if flags & crate::mm::mmap::MAP_ANONYMOUS != 0 {
+        return Ok(crate::mm::mmap::map(task, addr, len, prot, flags)?);
     }
+    match crate::sys::syscall::lookup_fd(fd) {
+        Some(crate::sys::syscall::FdType::Framebuffer) => Ok(crate::dev::fb::map(task, addr, len, prot, flags, offset)?),
+        Some(_) => Err(Errno::ENODEV),
+        None => Err(Errno::EBADF),
+    }
+}
 
-    len as isize
+/// munmap handler - remove mappings in a page-aligned range
+fn sys_munmap(addr: usize, len: usize) -> SyscallResult {
+    let task = match current_task_mut() {
+        Some(task) => task,
+        None => return Err(Errno::ESRCH),
+    };
+    match crate::mm::mmap::unmap(task, addr, len) {
+        Ok(()) => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// mprotect handler - change protection of a mapped, page-aligned range
+fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SyscallResult {
+    let task = match current_task_mut() {
+        Some(task) => task,
+        None => return Err(Errno::ESRCH),
+    };
+    match crate::mm::mmap::protect(task, addr, len, prot) {
+        Ok(()) => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// End the current process with exit `code`, for the kernel's own kills
+pub(crate) fn exit_current(code: usize) -> ! {
+    sys_exit_enhanced(code)
 }
 
 /// Enhanced sys_exit handler - Mark process as zombie and clean up
@@ -545,8 +874,25 @@ fn sys_exit_enhanced(code: usize) -> ! {
 
     let current_task_id = current_task_info.0;
 
+    // Nothing can take over from init
+    if pid == crate::user::spawn::INIT_PID {
+        panic!("init (pid {}) exited with status {}", pid, code);
+    }
+
+    // The whole process exits: end its other threads, and let the task that
+    // created it (which holds the process state) account for this one
+    let ended = crate::sched::thread::end_group(pid, current_task_id, code);
+    if ended > 0 {
+        serial_println!("[SYSCALL] SYS_EXIT: Ended {} other threads of process {}", ended, pid);
+    }
+    if current_task_id != pid {
+        if let (Some(thread), Some(process)) = (sched::get_task_by_id(current_task_id), sched::get_task_by_id(pid)) {
+            process.usage.absorb(&thread.usage.snapshot());
+        }
+    }
+
     // Mark process as zombie in the process table
-    if let Some(mut process_guard) = ProcessManager::get_process(current_task_id) {
+    if let Some(mut process_guard) = ProcessManager::get_process(pid) {
         if let Some(process) = process_guard.get_mut() {
             process.mark_zombie(code as i32);
 
@@ -571,10 +917,43 @@ fn sys_exit_enhanced(code: usize) -> ! {
         }
     }
 
+    // Hand our resource usage (and that of our children) to the parent
+    if let Some(current_task) = sched::get_task_by_id(pid) {
+        let totals = current_task
+            .usage
+            .snapshot()
+            .merged(&current_task.children_usage.snapshot());
+        if current_task.ppid != 0 {
+            if let Some(parent) = sched::get_task_by_id(current_task.ppid) {
+                parent.children_usage.absorb(&totals);
+            }
+        }
+    }
+
+    // Init adopts the children, and reaps them when they exit
+    let adopted = sched::reparent(pid, crate::user::spawn::INIT_PID);
+    ProcessManager::reparent_children(pid, crate::user::spawn::INIT_PID);
+    if adopted > 0 {
+        serial_println!("[SYSCALL] SYS_EXIT: {} children of process {} reparented to init", adopted, pid);
+    }
+
+    // End profiles of and by this task, which hold shared memory references
+    crate::sys::perf::task_exit(current_task_id);
+
+    // Unmap shared memory and devices, then close every handle, which drops the
+    // references to the objects behind them
+    if let Some(current_task) = sched::get_task_mut(pid) {
+        crate::sys::shm::task_exit(current_task);
+        crate::dev::fb::task_exit(current_task);
+    }
+    crate::sys::handle::close_all(pid);
+
     // Remove current task from scheduler
     // The task should not be rescheduled after this point
     if let Some(current_task) = sched::get_task_mut(current_task_id) {
-        current_task.state = crate::sched::task::TaskState::Ready; // Will be cleaned up
+        // Reaped once the parent collects the exit status (SYS_WAIT)
+        current_task.state = crate::sched::task::TaskState::Exited;
+        crate::sys::exit_watch::exited(current_task_id, pid, code);
         serial_println!(
             "[SYSCALL] SYS_EXIT: Task {} marked for cleanup",
             current_task_id
@@ -602,7 +981,7 @@ fn sys_exit_enhanced(code: usize) -> ! {
 ///
 /// This version marks the current task as Ready and calls the scheduler
 /// to switch to the next available task.
-fn sys_yield_enhanced() -> isize {
+fn sys_yield_enhanced() -> SyscallResult {
     let cpu_id = unsafe { crate::arch::x86_64::smp::percpu::percpu_current().id };
     let pid = get_current_process_id().unwrap_or(0);
 
@@ -611,26 +990,25 @@ fn sys_yield_enhanced() -> isize {
     // Get current task info
     let (_task_id, priority) = match crate::sched::get_current_task_info() {
         Some(info) => info,
-        None => return EINVAL,
+        None => return Err(Errno::EINVAL),
     };
 
     // Mark current task as ready (not sleeping)
-    if !crate::sched::sleep_current_task(0, priority) {
-        return EINVAL;
+    if !crate::sched::sleep_current_task(crate::time::Duration::ZERO, priority) {
+        return Err(Errno::EINVAL);
     }
 
     // Trigger scheduler to select next task
     crate::sched::yield_now();
 
-    0 // Success
+    Ok(0)
 }
 
 /// Enhanced sys_getpid handler
 ///
 /// Returns the current process ID for debugging purposes.
-fn sys_getpid_enhanced() -> isize {
-    let pid = get_current_process_id().unwrap_or(0);
-    pid as isize
+fn sys_getpid_enhanced() -> SyscallResult {
+    Ok(get_current_process_id().unwrap_or(0))
 }
 
 /// sys_fork implementation - Create a child process
@@ -642,7 +1020,7 @@ fn sys_getpid_enhanced() -> isize {
 /// * Child PID (positive) in parent process
 /// * 0 in child process  
 /// * Negative error code on failure
-fn sys_fork_stub() -> isize {
+fn sys_fork_stub() -> SyscallResult {
     use crate::mm::paging::PageTable;
     use crate::sched::priority::TaskPriority;
     use crate::sched::{self, Task};
@@ -655,7 +1033,7 @@ fn sys_fork_stub() -> isize {
         Some(info) => info,
         None => {
             serial_println!("[SYSCALL] SYS_FORK: No current task found");
-            return ESRCH; // No such process
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -666,20 +1044,26 @@ fn sys_fork_stub() -> isize {
         Some(task) => task,
         None => {
             serial_println!("[SYSCALL] SYS_FORK: Parent task not found");
-            return ESRCH;
+            return Err(Errno::ESRCH);
         }
     };
+    let (parent_stack_top, parent_mmap_base) = (parent_task.user_stack_top, parent_task.mmap_base);
+    let (parent_heap_start, parent_brk) = (parent_task.heap_start, parent_task.brk);
+    let (parent_pid, parent_pgid, parent_sid) = (parent_task.pid, parent_task.pgid, parent_task.sid);
+    let (parent_tty, parent_umask) = (parent_task.tty, parent_task.umask);
+    let (parent_strace, parent_seccomp) = (parent_task.strace, parent_task.seccomp);
+    let parent_cpu_group = parent_task.cpu_group;
 
     // Create a new process with the current task as parent
     let child_pid = match ProcessManager::create_process(Some(parent_task_id), "forked_process") {
         Ok(pid) => pid,
         Err(ProcessError::ProcessTableFull) => {
             serial_println!("[SYSCALL] SYS_FORK: Process table full");
-            return EAGAIN; // Try again later
+            return Err(Errno::EAGAIN);
         }
         Err(_) => {
             serial_println!("[SYSCALL] SYS_FORK: Failed to create process");
-            return ENOMEM; // Out of memory
+            return Err(Errno::ENOMEM);
         }
     };
 
@@ -688,7 +1072,7 @@ fn sys_fork_stub() -> isize {
         Some(guard) => guard,
         None => {
             serial_println!("[SYSCALL] SYS_FORK: Child process not found after creation");
-            return ENOMEM;
+            return Err(Errno::ENOMEM);
         }
     };
 
@@ -696,7 +1080,7 @@ fn sys_fork_stub() -> isize {
         Some(process) => process,
         None => {
             serial_println!("[SYSCALL] SYS_FORK: Child process slot empty");
-            return ENOMEM;
+            return Err(Errno::ENOMEM);
         }
     };
 
@@ -750,24 +1134,54 @@ fn sys_fork_stub() -> isize {
                 serial_println!("[SYSCALL] SYS_FORK: Failed to create child task: {:?}", e);
                 // Clean up the process we created
                 let _ = ProcessManager::remove_process(child_pid);
-                return ENOMEM;
+                return Err(Errno::ENOMEM);
             }
         };
 
     // Get the child task and set up its context to match the child process
     if let Some(child_task) = sched::get_task_mut(child_task_id) {
-        // Copy the child process context to the child task
+        // Copy the child process context to the child task, keeping the
+        // child's own FPU save area and kernel stack
+        let (fpu_area, kernel_stack) = (child_task.context.fpu_area, child_task.context.kernel_stack);
         child_task.context = child_process.context.clone();
+        child_task.context.fpu_area = fpu_area;
+        child_task.context.kernel_stack = kernel_stack;
+
+        // The child shares the parent's layout, so it keeps its randomized bases
+        child_task.user_stack_top = parent_stack_top;
+        child_task.mmap_base = parent_mmap_base;
+        child_task.heap_start = parent_heap_start;
+        child_task.brk = parent_brk;
+
+        // The child joins the parent's process group and session, so it
+        // shares the controlling terminal its default stdio is wired to
+        child_task.ppid = parent_pid;
+        child_task.pgid = parent_pgid;
+        child_task.sid = parent_sid;
+        child_task.tty = parent_tty;
+        child_task.umask = parent_umask;
+        child_task.strace = parent_strace;
+        child_task.seccomp = parent_seccomp;
+        child_task.cpu_group = parent_cpu_group;
+
+        // The child gets a copy of every handle, referring to the same objects
+        crate::sys::handle::inherit(parent_task_id, child_task.pid, false);
 
         // Copy memory regions from child process to child task
         child_task.region_count = 0;
         for i in 0..child_process.region_count {
             if let Some(region) = &child_process.memory_regions[i] {
-                if let Err(e) = child_task.add_memory_region(region.clone()) {
-                    serial_println!(
+                match child_task.add_memory_region(region.clone()) {
+                    Ok(()) => {
+                        // The child's copy of a shared mapping is a new reference
+                        if let crate::sched::task::MemoryRegionType::Shared { id, .. } = region.region_type {
+                            crate::sys::shm::retain(id);
+                        }
+                    }
+                    Err(e) => serial_println!(
                         "[SYSCALL] SYS_FORK: Failed to copy region to child task: {:?}",
                         e
-                    );
+                    ),
                 }
             }
         }
@@ -781,7 +1195,7 @@ fn sys_fork_stub() -> isize {
         serial_println!("[SYSCALL] SYS_FORK: Failed to get child task after creation");
         // Clean up
         let _ = ProcessManager::remove_process(child_pid);
-        return ENOMEM;
+        return Err(Errno::ENOMEM);
     }
 
     // Drop the child process guard to release the lock
@@ -794,18 +1208,7 @@ fn sys_fork_stub() -> isize {
 
     // Return child PID to parent process
     // The child process will get 0 when it's scheduled (due to context.rax = 0)
-    child_pid as isize
-}
-
-/// Close all file descriptors with FD_CLOEXEC flag set
-///
-/// This is called during exec to close file descriptors that should not
-/// be inherited by the new program.
-fn close_cloexec_fds() {
-    use crate::sys::syscall::close_fds_with_cloexec;
-    
-    serial_println!("[SYSCALL] Closing FDs with FD_CLOEXEC flag");
-    close_fds_with_cloexec();
+    Ok(child_pid)
 }
 
 /// sys_exec implementation - Replace current process with new ELF binary
@@ -820,7 +1223,7 @@ fn close_cloexec_fds() {
 /// # Returns
 /// * Does not return on success (process is replaced)
 /// * Negative error code on failure
-fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
+fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> SyscallResult {
     use crate::sched;
     use crate::user::elf::ElfLoader;
     use crate::user::process::{
@@ -832,7 +1235,7 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
     // Validate path pointer
     if !is_user_pointer_valid(path_ptr) {
         serial_println!("[SYSCALL] SYS_EXEC: Invalid path pointer");
-        return EFAULT;
+        return Err(Errno::EFAULT);
     }
 
     // Get current task/process information
@@ -840,7 +1243,7 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
         Some(info) => info,
         None => {
             serial_println!("[SYSCALL] SYS_EXEC: No current task found");
-            return ESRCH;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -848,33 +1251,33 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
 
     // Copy path string from user space (limit to 256 bytes)
     let mut path_buffer = [0u8; 256];
-    let path_str = unsafe {
-        // Find null terminator in user space
+    let path_str = {
+        // Copy up to the null terminator, one byte at a time
         let mut len = 0;
-        let user_ptr = path_ptr as *const u8;
+        let mut copied = Ok(());
         while len < 255 {
-            let byte = *user_ptr.add(len);
-            if byte == 0 {
+            copied = copy_from_user(&mut path_buffer[len..len + 1], path_ptr + len, 1);
+            if copied.is_err() || path_buffer[len] == 0 {
                 break;
             }
             len += 1;
         }
 
-        // Copy the string
-        match copy_from_user(&mut path_buffer[..len], path_ptr, len) {
+        // Check the string
+        match copied {
             Ok(()) => {
                 path_buffer[len] = 0; // Null terminate
                 match core::str::from_utf8(&path_buffer[..len]) {
                     Ok(s) => s,
                     Err(_) => {
                         serial_println!("[SYSCALL] SYS_EXEC: Invalid UTF-8 in path");
-                        return EINVAL;
+                        return Err(Errno::EINVAL);
                     }
                 }
             }
             Err(_) => {
                 serial_println!("[SYSCALL] SYS_EXEC: Failed to copy path from user space");
-                return EFAULT;
+                return Err(Errno::EFAULT);
             }
         }
     };
@@ -891,12 +1294,12 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
                     Some(guard) => guard,
                     None => {
                         serial_println!("[SYSCALL] SYS_EXEC: Failed to get newly created process");
-                        return ENOMEM;
+                        return Err(Errno::ENOMEM);
                     }
                 },
                 Err(_) => {
                     serial_println!("[SYSCALL] SYS_EXEC: Failed to create process for exec");
-                    return ENOMEM;
+                    return Err(Errno::ENOMEM);
                 }
             }
         }
@@ -906,15 +1309,18 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
         Some(p) => p,
         None => {
             serial_println!("[SYSCALL] SYS_EXEC: Process slot empty");
-            return ESRCH;
+            return Err(Errno::ESRCH);
         }
     };
 
     // Clear current memory space
     process.clear_memory_regions();
 
-    // Close file descriptors with FD_CLOEXEC flag set
-    close_cloexec_fds();
+    // Close handles with FD_CLOEXEC flag set
+    serial_println!("[SYSCALL] Closing FDs with FD_CLOEXEC flag");
+    if let Some(task) = sched::get_task_by_id(current_task_id) {
+        crate::sys::handle::close_on_exec(task.pid);
+    }
 
     // TODO: In a full implementation, we would:
     // 1. Load the ELF binary from the file system
@@ -941,7 +1347,11 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
     // For demonstration, we'll set up a minimal memory layout
     use crate::mm::paging::PageTableFlags;
     use crate::sched::task::{MemoryRegion, MemoryRegionType};
-    use crate::user::process::{USER_STACK_SIZE, USER_STACK_TOP};
+    use crate::user::process::USER_STACK_SIZE;
+
+    // A new program image gets a freshly randomized layout
+    let user_stack_top = crate::mm::kaslr::user_stack_top();
+    let mmap_base = crate::mm::kaslr::user_mmap_base();
 
     // Add a code region (simulated)
     let code_region = MemoryRegion::new(
@@ -953,13 +1363,13 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
 
     if let Err(e) = process.add_memory_region(code_region) {
         serial_println!("[SYSCALL] SYS_EXEC: Failed to add code region: {:?}", e);
-        return ENOMEM;
+        return Err(Errno::ENOMEM);
     }
 
     // Add a stack region
     let stack_region = MemoryRegion::new(
-        USER_STACK_TOP - USER_STACK_SIZE,
-        USER_STACK_TOP,
+        user_stack_top - USER_STACK_SIZE,
+        user_stack_top,
         PageTableFlags::PRESENT
             | PageTableFlags::WRITABLE
             | PageTableFlags::USER
@@ -969,12 +1379,12 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
 
     if let Err(e) = process.add_memory_region(stack_region) {
         serial_println!("[SYSCALL] SYS_EXEC: Failed to add stack region: {:?}", e);
-        return ENOMEM;
+        return Err(Errno::ENOMEM);
     }
 
     // Reset CPU context for new program
     // Set entry point to simulated code address
-    process.context.rsp = USER_STACK_TOP as u64;
+    process.context.rsp = user_stack_top as u64;
     process.context.rbx = 0;
     process.context.rbp = 0;
     process.context.r12 = 0x400000; // Entry point
@@ -990,6 +1400,9 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
     if let Some(current_task) = sched::get_task_mut(current_task_id) {
         // Clear task memory regions
         current_task.clear_memory_regions();
+        current_task.user_stack_top = user_stack_top;
+        current_task.mmap_base = mmap_base;
+        crate::mm::brk::reset(current_task, 0x401000);
 
         // Copy new memory regions from process to task
         for i in 0..process.region_count {
@@ -1000,8 +1413,11 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
             }
         }
 
-        // Update task context
+        // Update task context, keeping the task's FPU save area and kernel stack
+        let (fpu_area, kernel_stack) = (current_task.context.fpu_area, current_task.context.kernel_stack);
         current_task.context = process.context.clone();
+        current_task.context.fpu_area = fpu_area;
+        current_task.context.kernel_stack = kernel_stack;
 
         serial_println!("[SYSCALL] SYS_EXEC: Successfully replaced process image");
         serial_println!(
@@ -1014,7 +1430,7 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
         );
     } else {
         serial_println!("[SYSCALL] SYS_EXEC: Failed to get current task");
-        return ESRCH;
+        return Err(Errno::ESRCH);
     }
 
     // Drop process guard
@@ -1044,7 +1460,7 @@ fn sys_exec_stub(path_ptr: usize, _argv_ptr: usize) -> isize {
 /// # Returns
 /// * Positive value: (child_pid << 8) | exit_code on success
 /// * Negative error code on failure
-fn sys_wait_stub(child_pid: usize) -> isize {
+fn sys_wait_stub(child_pid: usize) -> SyscallResult {
     use crate::sched;
     use crate::user::process::ProcessManager;
 
@@ -1063,7 +1479,7 @@ fn sys_wait_stub(child_pid: usize) -> isize {
         Some(info) => info,
         None => {
             serial_println!("[SYSCALL] SYS_WAIT: No current task found");
-            return ESRCH;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -1100,9 +1516,11 @@ fn sys_wait_stub(child_pid: usize) -> isize {
             exit_code
         );
 
-        // Remove the zombie process from the process table
+        // Remove the zombie process from the process table, and free its
+        // task table slot
         match ProcessManager::remove_process(dead_child_pid) {
             Ok(removed_process) => {
+                sched::reap_task(dead_child_pid);
                 serial_println!(
                     "[SYSCALL] SYS_WAIT: Cleaned up zombie process {} ({})",
                     removed_process.pid,
@@ -1127,7 +1545,7 @@ fn sys_wait_stub(child_pid: usize) -> isize {
             dead_child_pid,
             exit_code
         );
-        return result as isize;
+        return Ok(result);
     }
 
     // No zombie children found - we should block the parent
@@ -1143,7 +1561,7 @@ fn sys_wait_stub(child_pid: usize) -> isize {
 
     if !has_children {
         serial_println!("[SYSCALL] SYS_WAIT: Parent has no children");
-        return ECHILD; // No child processes
+        return Err(Errno::ECHILD);
     }
 
     // Parent has children but none are zombies yet
@@ -1153,7 +1571,7 @@ fn sys_wait_stub(child_pid: usize) -> isize {
     // 3. When a child exits, wake up the parent
 
     serial_println!("[SYSCALL] SYS_WAIT: Would block parent (not implemented yet)");
-    return EAGAIN; // Try again later (non-blocking for now)
+    return Err(Errno::EAGAIN);
 }
 
 /// Integration tests for syscall mechanism
@@ -1180,16 +1598,14 @@ mod integration_tests {
         // assert!(config.efer & SCE_BIT != 0);
 
         // Verify STAR register encoding
-        let user_cs_base = (USER_CODE_SEG & !3) as u64;
-        let kernel_cs_base = KERNEL_CODE_SEG as u64;
-        let expected_star = (user_cs_base << 48) | (kernel_cs_base << 32);
+        let expected_star = star_value();
 
         // In a real test, we would verify:
         // assert_eq!(config.star, expected_star);
 
         // For now, just verify the calculation is correct
-        assert_eq!((expected_star >> 32) & 0xFFFF, kernel_cs_base);
-        assert_eq!((expected_star >> 48) & 0xFFFF, user_cs_base);
+        assert_eq!((expected_star >> 32) & 0xFFFF, KERNEL_CODE_SEG as u64);
+        assert_eq!((expected_star >> 48) & 0xFFFF, ((USER_DATA_SEG & !3) - 8) as u64);
     }
 
     /// Test user pointer validation and error handling
@@ -1218,25 +1634,25 @@ mod integration_tests {
         let mut dst = [0u8; 10];
 
         // Invalid source pointer (null)
-        assert_eq!(copy_from_user(&mut dst, 0, 5), Err(EFAULT));
+        assert_eq!(copy_from_user(&mut dst, 0, 5), Err(Errno::EFAULT));
 
         // Invalid source pointer (kernel space)
         assert_eq!(
             copy_from_user(&mut dst, 0xFFFF_8000_0000_0000, 5),
-            Err(EFAULT)
+            Err(Errno::EFAULT)
         );
 
         // Buffer too small
-        assert_eq!(copy_from_user(&mut dst, 0x1000, 20), Err(EINVAL));
+        assert_eq!(copy_from_user(&mut dst, 0x1000, 20), Err(Errno::EINVAL));
 
         // Test copy_to_user with invalid pointers
         let src = [1, 2, 3, 4, 5];
 
         // Invalid destination pointer (null)
-        assert_eq!(copy_to_user(0, &src), Err(EFAULT));
+        assert_eq!(copy_to_user(0, &src), Err(Errno::EFAULT));
 
         // Invalid destination pointer (kernel space)
-        assert_eq!(copy_to_user(0xFFFF_8000_0000_0000, &src), Err(EFAULT));
+        assert_eq!(copy_to_user(0xFFFF_8000_0000_0000, &src), Err(Errno::EFAULT));
 
         // Note: We can't test successful copies without setting up actual user memory
     }
@@ -1246,7 +1662,7 @@ mod integration_tests {
     fn test_syscall_dispatcher() {
         // Test invalid syscall number
         let result = syscall_dispatcher_enhanced(999, 0, 0, 0, 0, 0, 0);
-        assert_eq!(result, ENOSYS);
+        assert_eq!(result, Errno::ENOSYS as isize);
 
         // Test SYS_GETPID (should always work)
         let result = syscall_dispatcher_enhanced(SYS_GETPID, 0, 0, 0, 0, 0, 0);
@@ -1259,24 +1675,24 @@ mod integration_tests {
 
         // Test SYS_WRITE with invalid file descriptor
         let result = syscall_dispatcher_enhanced(SYS_WRITE, 99, 0x1000, 5, 0, 0, 0);
-        assert_eq!(result, EINVAL);
+        assert_eq!(result, Errno::EINVAL as isize);
 
         // Test SYS_WRITE with invalid buffer pointer
         let result = syscall_dispatcher_enhanced(SYS_WRITE, 1, 0, 5, 0, 0, 0);
-        assert_eq!(result, EFAULT);
+        assert_eq!(result, Errno::EFAULT as isize);
 
         // Test unimplemented syscalls
         assert_eq!(
             syscall_dispatcher_enhanced(SYS_FORK, 0, 0, 0, 0, 0, 0),
-            ENOSYS
+            Errno::ENOSYS as isize
         );
         assert_eq!(
             syscall_dispatcher_enhanced(SYS_EXEC, 0x1000, 0, 0, 0, 0, 0),
-            ENOSYS
+            Errno::ENOSYS as isize
         );
         assert_eq!(
             syscall_dispatcher_enhanced(SYS_WAIT, 1, 0, 0, 0, 0, 0),
-            ENOSYS
+            Errno::ENOSYS as isize
         );
     }
 
@@ -1294,11 +1710,11 @@ mod integration_tests {
         assert_eq!(syscall_name(999), "UNKNOWN");
 
         // Test error code constants
-        assert_eq!(ENOSYS, -38);
-        assert_eq!(EFAULT, -14);
-        assert_eq!(ENOMEM, -12);
-        assert_eq!(EINVAL, -22);
-        assert_eq!(EPERM, -1);
+        assert_eq!(Errno::ENOSYS as isize, -38);
+        assert_eq!(Errno::EFAULT as isize, -14);
+        assert_eq!(Errno::ENOMEM as isize, -12);
+        assert_eq!(Errno::EINVAL as isize, -22);
+        assert_eq!(Errno::EPERM as isize, -1);
     }
 
     /// Test canonical address validation (used in assembly)
@@ -1367,11 +1783,11 @@ mod performance_tests {
     fn test_write_fastpath() {
         // Test zero-length write
         let result = sys_write_enhanced(1, 0x1000, 0);
-        assert_eq!(result, 0);
+        assert_eq!(result, Ok(0));
 
         // Test invalid file descriptor
         let result = sys_write_enhanced(99, 0x1000, 5);
-        assert_eq!(result, EINVAL);
+        assert_eq!(result, Err(Errno::EINVAL));
 
         // Note: Can't test actual writes without valid user memory setup
     }
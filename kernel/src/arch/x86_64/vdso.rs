@@ -0,0 +1,181 @@
+//! vDSO: reading the clock without a syscall
+//!
+//! Two pages are mapped at `mello_abi::VDSO_BASE` in the (shared) user
+//! address space: a read-only data page holding a copy of the monotonic
+//! clock's state (`time::clock`), and a code page with a clock function
+//! user code calls at `mello_abi::VDSO_CLOCK`. Every process sees them
+//! because all tasks use one page table, and the range is not a region of
+//! any task, so munmap and process teardown never free the frames.
+//!
+//! The function computes what `time::clock::now_ns` does: the time at the
+//! clock's last update plus the scaled TSC cycles since, or with tick
+//! resolution when the clock counts ticks or reads the HPET. Unlike the
+//! kernel clock it has no floor shared between CPUs, so the error left
+//! after synchronizing the TSCs can make it step back a little after a
+//! migration. `SYS_CLOCK_GETTIME` is the syscall form.
+//!
+//! CPU 0's timer interrupt copies the clock state under a sequence count.
+
+use crate::mm::paging::PageTableFlags;
+use crate::mm::VirtAddr;
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use mello_abi::{VDSO_BASE, VDSO_CLOCK};
+
+const PAGE_SIZE: usize = 4096;
+
+/// User range taken by the vDSO pages
+pub const VDSO_START: usize = VDSO_BASE as usize;
+pub const VDSO_END: usize = VDSO_CLOCK as usize + PAGE_SIZE;
+
+// The pages sit right above the highest possible user stack
+const _: () = assert!(VDSO_START == crate::mm::kaslr::USER_STACK_TOP_MAX);
+
+/// Kernel view of `mello_abi::VdsoData`
+#[repr(C)]
+pub struct VdsoData {
+    seq: AtomicU32,
+    tsc_valid: AtomicU32,
+    base_ns: AtomicU64,
+    base_tsc: AtomicU64,
+    mult: AtomicU32,
+    shift: AtomicU32,
+}
+
+mello_abi::check_layout!(VdsoData, mello_abi::VdsoData { seq, tsc_valid, base_ns, base_tsc, mult, shift });
+
+// The clock function. It runs in user mode from the code page and finds the
+// data page at its fixed address; everything it clobbers is caller-saved.
+core::arch::global_asm!(
+    ".pushsection .rodata.vdso, \"a\"",
+    ".global vdso_clock_start",
+    ".global vdso_clock_end",
+    "vdso_clock_start:",
+    "mov rsi, {data}",
+    "2:",
+    "mov r8d, dword ptr [rsi + {seq}]",
+    "test r8d, 1",
+    "jnz 5f",
+    "mov r11, qword ptr [rsi + {base_ns}]",
+    "xor eax, eax",
+    "cmp dword ptr [rsi + {tsc_valid}], 0",
+    "je 4f",
+    "lfence",
+    "rdtsc",
+    "shl rdx, 32",
+    "or rax, rdx",
+    // A TSC behind the base (read on another CPU) counts as no time
+    "sub rax, qword ptr [rsi + {base_tsc}]",
+    "jb 3f",
+    "mov r9d, dword ptr [rsi + {mult}]",
+    "mul r9",
+    "mov ecx, dword ptr [rsi + {shift}]",
+    "shrd rax, rdx, cl",
+    "jmp 4f",
+    "3:",
+    "xor eax, eax",
+    "4:",
+    "cmp r8d, dword ptr [rsi + {seq}]",
+    "jne 2b",
+    "add rax, r11",
+    "ret",
+    "5:",
+    "pause",
+    "jmp 2b",
+    "vdso_clock_end:",
+    ".popsection",
+    data = const VDSO_BASE,
+    seq = const core::mem::offset_of!(VdsoData, seq),
+    tsc_valid = const core::mem::offset_of!(VdsoData, tsc_valid),
+    base_ns = const core::mem::offset_of!(VdsoData, base_ns),
+    base_tsc = const core::mem::offset_of!(VdsoData, base_tsc),
+    mult = const core::mem::offset_of!(VdsoData, mult),
+    shift = const core::mem::offset_of!(VdsoData, shift),
+);
+
+extern "C" {
+    static vdso_clock_start: u8;
+    static vdso_clock_end: u8;
+}
+
+/// Kernel address of the data page; 0 until [`init`] has run
+static DATA: AtomicUsize = AtomicUsize::new(0);
+
+fn data() -> Option<&'static VdsoData> {
+    let addr = DATA.load(Ordering::Acquire);
+    (addr != 0).then(|| unsafe { &*(addr as *const VdsoData) })
+}
+
+/// Map both vDSO pages and fill the data page
+///
+/// Must run on the BSP after the memory manager and `time::clock::init`,
+/// before the timer interrupt is enabled.
+pub fn init() -> Result<(), &'static str> {
+    let (start, end) = (
+        core::ptr::addr_of!(vdso_clock_start) as usize,
+        core::ptr::addr_of!(vdso_clock_end) as usize,
+    );
+    let code = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+
+    let data_addr = crate::mm::with_memory_managers(|pmm, mapper| {
+        let data_frame = pmm.alloc_frame().ok_or("out of frames")?;
+        let code_frame = pmm.alloc_frame().ok_or("out of frames")?;
+        let code_addr = crate::mm::phys_to_virt(code_frame);
+        unsafe { core::ptr::copy_nonoverlapping(code.as_ptr(), code_addr.as_mut_ptr::<u8>(), code.len()) };
+
+        let user = PageTableFlags::PRESENT | PageTableFlags::USER;
+        mapper.map_page(VirtAddr::new(VDSO_BASE), data_frame, user | PageTableFlags::NO_EXECUTE, pmm)?;
+        mapper.map_page(VirtAddr::new(VDSO_CLOCK), code_frame, user, pmm)?;
+        Ok(crate::mm::phys_to_virt(data_frame))
+    })?;
+
+    DATA.store(data_addr.as_usize(), Ordering::Release);
+    update();
+    crate::serial_println!("[VDSO] Clock mapped at 0x{:x}", VDSO_CLOCK);
+    Ok(())
+}
+
+/// Copy the clock state into the data page
+///
+/// Called from CPU 0's timer interrupt after the clock counted the tick,
+/// and once by [`init`]; CPU 0 is the only writer.
+pub fn update() {
+    let Some(page) = data() else { return };
+    let clock = crate::time::clock::snapshot();
+    let seq = page.seq.load(Ordering::Relaxed);
+    page.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+    fence(Ordering::Release);
+    page.base_ns.store(clock.base_ns(), Ordering::Relaxed);
+    page.base_tsc.store(clock.base_cycles(), Ordering::Relaxed);
+    let scale = clock.tsc_scale();
+    page.mult.store(scale.map_or(0, |scale| scale.mult()), Ordering::Relaxed);
+    page.shift.store(scale.map_or(0, |scale| scale.shift()), Ordering::Relaxed);
+    page.tsc_valid.store(scale.is_some() as u32, Ordering::Relaxed);
+    page.seq.store(seq.wrapping_add(2), Ordering::Release);
+}
+
+/// Whether `[start, end)` overlaps the vDSO pages
+pub fn overlaps(start: usize, end: usize) -> bool {
+    start < VDSO_END && VDSO_START < end
+}
+
+crate::kernel_test! {
+    /// The data page holds the clock's state, under an even sequence
+    fn vdso_data_matches_clock() {
+        let Some(page) = data() else { return Ok(()) };
+        // No tick may update either in between
+        let (seq, base_ns, base_tsc, tsc_valid, clock) = x86_64::instructions::interrupts::without_interrupts(|| {
+            (
+                page.seq.load(Ordering::Relaxed),
+                page.base_ns.load(Ordering::Relaxed),
+                page.base_tsc.load(Ordering::Relaxed),
+                page.tsc_valid.load(Ordering::Relaxed),
+                crate::time::clock::snapshot(),
+            )
+        });
+        crate::ktest_assert_eq!(seq & 1, 0, "sequence left odd");
+        crate::ktest_assert_eq!(base_ns, clock.base_ns(), "base time");
+        crate::ktest_assert_eq!(base_tsc, clock.base_cycles(), "base TSC");
+        crate::ktest_assert_eq!((tsc_valid != 0), clock.tsc_scale().is_some(), "clock source");
+        Ok(())
+    }
+}
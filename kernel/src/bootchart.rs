@@ -0,0 +1,166 @@
+//! Boot-time measurement
+//!
+//! The boot path records how long each of its steps takes: memory, ACPI,
+//! clock and APIC setup, SMP bring-up and the scheduler from `_start` with
+//! [`measure`], and every init call and init call level from
+//! `initcall::run`. Timestamps are TSC readings, which work before anything
+//! else is set up; `_start` takes the first one on entry ([`start`]).
+//!
+//! At the end of boot [`report`] prints a table of the steps, each with its
+//! start since kernel entry and its duration, then one line per step for
+//! scripts to compare across boots, ending with the whole of `_start`:
+//!
+//! ```text
+//! BOOTMARK name=mm us=10234
+//! BOOTMARK name=total us=481022
+//! ```
+//!
+//! Cycles become microseconds at the calibrated TSC frequency. Without one
+//! (no invariant TSC) the rate is measured over the boot against the HPET,
+//! from the first step recorded after it starts; with neither, the lines
+//! carry `cycles=` instead of `us=`.
+
+use crate::arch::x86_64::hpet;
+use crate::serial_println;
+use crate::time::clock;
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+/// Steps the chart has room for
+const MAX_MARKS: usize = 64;
+
+/// One measured step, in TSC cycles
+#[derive(Debug, Clone, Copy)]
+struct Mark {
+    name: &'static str,
+    start: u64,
+    end: u64,
+}
+
+const EMPTY: Mark = Mark { name: "", start: 0, end: 0 };
+
+struct Chart {
+    marks: [Mark; MAX_MARKS],
+    count: usize,
+    /// Steps that did not fit
+    dropped: usize,
+    /// TSC and HPET counter when a step was first recorded with the HPET
+    /// running
+    hpet_ref: Option<(u64, u64)>,
+}
+
+static CHART: Mutex<Chart> = Mutex::new(Chart {
+    marks: [EMPTY; MAX_MARKS],
+    count: 0,
+    dropped: 0,
+    hpet_ref: None,
+});
+
+/// TSC at kernel entry
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+
+fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Note kernel entry; called first thing in `_start`
+pub fn start() {
+    BOOT_TSC.store(rdtsc(), Ordering::Relaxed);
+}
+
+/// Run `f` as the boot step `name` and record how long it took
+pub fn measure<R>(name: &'static str, f: impl FnOnce() -> R) -> R {
+    let start = rdtsc();
+    let result = f();
+    record(name, start, rdtsc());
+    result
+}
+
+fn record(name: &'static str, start: u64, end: u64) {
+    let mut chart = CHART.lock();
+    if chart.hpet_ref.is_none() {
+        if let Some(hpet) = hpet::get() {
+            chart.hpet_ref = Some((rdtsc(), hpet.counter()));
+        }
+    }
+    if chart.count < MAX_MARKS {
+        let index = chart.count;
+        chart.marks[index] = Mark { name, start, end };
+        chart.count += 1;
+    } else {
+        chart.dropped += 1;
+    }
+}
+
+/// TSC cycles per second: the calibrated frequency, or the rate since
+/// `hpet_ref` against the HPET
+fn tsc_hz(hpet_ref: Option<(u64, u64)>) -> Option<u64> {
+    if let Some(hz) = clock::tsc_frequency_hz() {
+        return Some(hz);
+    }
+    let (tsc, counter) = hpet_ref?;
+    let hpet = hpet::get()?;
+    let ticks = hpet.counter().wrapping_sub(counter) & hpet.counter_mask();
+    let cycles = rdtsc().wrapping_sub(tsc);
+    (ticks != 0).then(|| (cycles as u128 * hpet.frequency() as u128 / ticks as u128) as u64)
+}
+
+/// `cycles` in microseconds at `hz`, or as is without a rate
+fn scale(cycles: u64, hz: Option<u64>) -> u64 {
+    match hz {
+        Some(hz) if hz != 0 => (cycles as u128 * 1_000_000 / hz as u128) as u64,
+        _ => cycles,
+    }
+}
+
+/// Print the boot chart and the `BOOTMARK` lines
+///
+/// Called once at the end of boot; the total runs from [`start`] to here.
+pub fn report() {
+    let end = rdtsc();
+    let boot = BOOT_TSC.load(Ordering::Relaxed);
+    let (mut marks, count, dropped, hpet_ref) = {
+        let chart = CHART.lock();
+        (chart.marks, chart.count, chart.dropped, chart.hpet_ref)
+    };
+    let marks = &mut marks[..count];
+    marks.sort_unstable_by_key(|mark| mark.start);
+
+    let hz = tsc_hz(hpet_ref);
+    let unit = if hz.is_some() { "us" } else { "cycles" };
+    serial_println!("[BOOT] ========================================");
+    serial_println!("[BOOT] {:>12} {:>12}  step ({})", "start", "took", unit);
+    for mark in marks.iter() {
+        serial_println!(
+            "[BOOT] {:>12} {:>12}  {}",
+            scale(mark.start.saturating_sub(boot), hz),
+            scale(mark.end - mark.start, hz),
+            mark.name
+        );
+    }
+    serial_println!("[BOOT] {:>12} {:>12}  total", 0, scale(end - boot, hz));
+    if dropped != 0 {
+        serial_println!("[BOOT] {} steps did not fit in the chart", dropped);
+    }
+    serial_println!("[BOOT] ========================================");
+
+    for mark in marks.iter() {
+        serial_println!("BOOTMARK name={} {}={}", mark.name, unit, scale(mark.end - mark.start, hz));
+    }
+    serial_println!("BOOTMARK name=total {}={}", unit, scale(end - boot, hz));
+}
+
+crate::kernel_test! {
+    /// Boot steps were recorded, and cycles scale to microseconds
+    fn bootchart_records_steps() {
+        let recorded = {
+            let chart = CHART.lock();
+            chart.marks[..chart.count].iter().any(|mark| mark.name == "mm" && mark.end >= mark.start)
+        };
+        crate::ktest_assert!(recorded, "memory init not in the boot chart");
+        crate::ktest_assert_eq!(scale(3_000_000, Some(1_000_000_000)), 3000, "3M cycles at 1 GHz");
+        crate::ktest_assert_eq!(scale(1234, None), 1234, "cycles without a rate");
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+/// Kernel command line access
+/// Reads the command line passed by Limine (`cmdline:` in limine.conf)
+/// and provides simple flag and key=value lookups
+use limine::request::ExecutableCmdlineRequest;
+
+/// Limine kernel command line request
+/// This static variable is placed in the .requests section so that
+/// the Limine bootloader can pass the configured command line
+#[used]
+#[link_section = ".requests"]
+static CMDLINE_REQUEST: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
+
+/// Get the raw command line, or an empty string if none was provided
+pub fn raw() -> &'static str {
+    CMDLINE_REQUEST
+        .get_response()
+        .and_then(|response| response.cmdline().to_str().ok())
+        .unwrap_or("")
+}
+
+/// Check whether a bare flag (e.g. `ktest`) is present on the command line
+pub fn has_flag(name: &str) -> bool {
+    raw().split_ascii_whitespace().any(|word| word == name)
+}
+
+/// Look up the value of a `key=value` option
+pub fn value(key: &str) -> Option<&'static str> {
+    raw().split_ascii_whitespace().find_map(|word| {
+        let (k, v) = word.split_once('=')?;
+        if k == key {
+            Some(v)
+        } else {
+            None
+        }
+    })
+}
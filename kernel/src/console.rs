@@ -0,0 +1,1176 @@
+/// Kernel console multiplexer
+/// Routes kernel output to the serial port, the framebuffer, or both,
+/// as selected by `console=serial|fb|both` on the kernel command line.
+/// Headless runs want serial; demos want the screen.
+///
+/// The framebuffer console can be rotated for panels mounted sideways or
+/// upside down (`fbrotate=0|90|180|270`) and its font drawn up to four
+/// times the size for high-density displays (`fbscale=1..4`). The font is
+/// the built-in 8x8 one until `fbfont=<path>` names a PSF2 font in the
+/// initrd, loaded once the initrd is found (see `font`). Output is decoded
+/// as UTF-8; characters outside the Basic Multilingual Plane, and those the
+/// font has no glyph for, are drawn as the font's replacement glyph.
+///
+/// Once memory management is up the framebuffer console draws into a back
+/// buffer, and copies what changed to the screen after each write, or
+/// every `fbflush=<ms>` milliseconds from a kernel task.
+///
+/// Lines scrolled off the top of the framebuffer console are kept as
+/// character cells in a scrollback history (`fbscrollback=<lines>`).
+/// Shift+PageUp and Shift+PageDown on the serial terminal page through it
+/// half a screen at a time, and anything typed returns to the bottom; the
+/// panic handler replays the end of it to serial with [`dump_history`].
+///
+/// With `mousecursor` the mouse cursor is drawn over the text, on the
+/// screen only; the mouse driver moves it with [`draw_mouse_cursor`].
+use crate::font::{self, Font};
+use crate::framebuffer::{Framebuffer, Rotation};
+use crate::dev::pty::RingBuffer;
+use crate::dev::api::{self, irq, DriverInfo};
+use crate::serial::{Received, SerialPort, SERIAL, SERIAL_PORT};
+use crate::sync::{IrqSpinLock, MpscQueue, WaitQueue};
+use crate::sysrq::Key;
+use crate::time::{Duration, Instant};
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicIsize, AtomicU64, AtomicU8, Ordering};
+use limine::framebuffer::Framebuffer as LimineFramebuffer;
+use spin::Mutex;
+
+/// Largest integer font scale factor
+pub const MAX_FONT_SCALE: usize = 4;
+
+/// Longest `fbflush=` interval, in milliseconds
+const MAX_FLUSH_INTERVAL_MS: u64 = 1000;
+
+/// Scrollback lines kept without `fbscrollback=`, and at most
+const DEFAULT_SCROLLBACK: usize = 512;
+const MAX_SCROLLBACK: usize = 4096;
+
+/// What xterm-like terminals send for Shift+PageUp and Shift+PageDown
+const SHIFT_PAGE_UP: &[u8] = b"\x1b[5;2~";
+const SHIFT_PAGE_DOWN: &[u8] = b"\x1b[6;2~";
+
+/// How long the start of a possible scroll key is held back from readers
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// `conbench` workload: bursts of typical log lines, timed for `BENCH_TIME`
+const BENCH_LINE: &[u8] = b"[CONBENCH] The quick brown fox jumps over the lazy dog 0123456789\n";
+const BENCH_BURST: usize = 4;
+const BENCH_TIME: Duration = Duration::from_secs(1);
+
+/// Framebuffer console colors (0xRRGGBB)
+const FG_COLOR: u32 = 0xFFFFFF;
+const BG_COLOR: u32 = 0x000000;
+
+/// Where console output is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ConsoleMode {
+    /// Serial port only (default)
+    Serial = 0,
+    /// Framebuffer only
+    Framebuffer = 1,
+    /// Serial port and framebuffer
+    Both = 2,
+}
+
+impl ConsoleMode {
+    /// Parse a `console=` value
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "serial" => Some(ConsoleMode::Serial),
+            "fb" => Some(ConsoleMode::Framebuffer),
+            "both" => Some(ConsoleMode::Both),
+            _ => None,
+        }
+    }
+
+    /// The `console=` value selecting this mode
+    pub fn name(self) -> &'static str {
+        match self {
+            ConsoleMode::Serial => "serial",
+            ConsoleMode::Framebuffer => "fb",
+            ConsoleMode::Both => "both",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ConsoleMode::Framebuffer,
+            2 => ConsoleMode::Both,
+            _ => ConsoleMode::Serial,
+        }
+    }
+
+    fn uses_serial(self) -> bool {
+        self != ConsoleMode::Framebuffer
+    }
+
+    fn uses_framebuffer(self) -> bool {
+        self != ConsoleMode::Serial
+    }
+}
+
+/// Active console mode
+/// Starts as serial so early boot messages are never lost
+static MODE: AtomicU8 = AtomicU8::new(ConsoleMode::Serial as u8);
+
+/// Text console drawn on the framebuffer
+static FB_CONSOLE: Mutex<FbConsole> = Mutex::new(FbConsole::new());
+
+/// Milliseconds between flushes of the framebuffer console's back buffer
+/// by [`flush_task`]; 0 flushes after each write
+static FLUSH_INTERVAL_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Serial input not read yet, filled by [`poll_input`]
+static INPUT: IrqSpinLock<Input> = IrqSpinLock::new(Input::new());
+
+/// What the serial port received and [`poll_input`] has not taken yet,
+/// pushed by the receive interrupt
+static SERIAL_RX: MpscQueue<Received, 256> = MpscQueue::new();
+
+/// Whether the serial receive interrupt is routed; until it is,
+/// [`poll_input`] reads the port itself
+static SERIAL_RX_IRQ: AtomicBool = AtomicBool::new(false);
+
+/// `SERIAL_RX` overflows already reported
+static SERIAL_RX_REPORTED: AtomicU64 = AtomicU64::new(0);
+
+static SERIAL_DRIVER: DriverInfo = crate::driver_info!("serial");
+
+/// Pages to scroll the framebuffer console back (negative: forward), and
+/// whether to return to the bottom first, as asked from interrupt context;
+/// applied at the next flush
+static SCROLL_PAGES: AtomicIsize = AtomicIsize::new(0);
+static SCROLL_HOME: AtomicBool = AtomicBool::new(false);
+
+/// Tasks waiting for console input
+///
+/// Received bytes reach the input buffer on the scheduler tick, which
+/// checks it for these tasks (see `sync::wait_queue::register_readiness`).
+pub static INPUT_WAIT: WaitQueue = WaitQueue::new();
+
+/// Font loaded with `fbfont=`
+static LOADED_FONT: spin::Once<Font> = spin::Once::new();
+
+/// U+FFFD, stored for what cannot be decoded or kept in a cell
+const REPLACEMENT: u16 = 0xFFFD;
+
+/// Parse an `fbscale=` value
+pub fn parse_font_scale(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|scale| (1..=MAX_FONT_SCALE).contains(scale))
+}
+
+/// Parse an `fbscrollback=` value in lines
+fn parse_scrollback(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|&lines| lines <= MAX_SCROLLBACK)
+}
+
+/// Parse an `fbflush=` value in milliseconds
+fn parse_flush_interval(value: &str) -> Option<u64> {
+    value.parse().ok().filter(|ms| (1..=MAX_FLUSH_INTERVAL_MS).contains(ms))
+}
+
+/// Largest text grid the framebuffer console keeps, in cells
+const MAX_COLS: usize = 256;
+const MAX_ROWS: usize = 128;
+
+/// Rectangle of text cells, `[top, bottom) x [left, right)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Damage {
+    top: usize,
+    left: usize,
+    bottom: usize,
+    right: usize,
+}
+
+impl Damage {
+    fn cell(row: usize, col: usize) -> Self {
+        Damage {
+            top: row,
+            left: col,
+            bottom: row + 1,
+            right: col + 1,
+        }
+    }
+
+    /// Smallest rectangle covering both
+    fn merge(self, other: Damage) -> Self {
+        Damage {
+            top: self.top.min(other.top),
+            left: self.left.min(other.left),
+            bottom: self.bottom.max(other.bottom),
+            right: self.right.max(other.right),
+        }
+    }
+}
+
+/// Lines that scrolled off the top of the console, oldest first
+///
+/// A ring of `capacity` lines of `MAX_COLS` cells, in frames from the
+/// physical memory manager; it keeps nothing until [`init_scrollback`].
+struct History {
+    lines: *mut [u16; MAX_COLS],
+    capacity: usize,
+    /// Ring slot of the oldest line
+    start: usize,
+    len: usize,
+}
+
+impl History {
+    const fn new() -> Self {
+        Self {
+            lines: core::ptr::null_mut(),
+            capacity: 0,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Add `line` as the newest, dropping the oldest if full
+    fn push(&mut self, line: &[u16; MAX_COLS]) {
+        if self.capacity == 0 {
+            return;
+        }
+        let slot = (self.start + self.len) % self.capacity;
+        unsafe { self.lines.add(slot).write(*line) };
+        if self.len < self.capacity {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % self.capacity;
+        }
+    }
+
+    /// Line `index`, 0 being the oldest; `index` must be below `len`
+    fn line(&self, index: usize) -> &[u16; MAX_COLS] {
+        unsafe { &*self.lines.add((self.start + index) % self.capacity) }
+    }
+}
+
+/// Console input: bytes for readers, and the start of an escape sequence
+/// held back until it is known whether it is a scroll key
+struct Input {
+    buffer: RingBuffer,
+    escape: [u8; SHIFT_PAGE_UP.len()],
+    escape_len: usize,
+    escape_since: Instant,
+}
+
+impl Input {
+    const fn new() -> Self {
+        Self {
+            buffer: RingBuffer::new(),
+            escape: [0; SHIFT_PAGE_UP.len()],
+            escape_len: 0,
+            escape_since: Instant::BOOT,
+        }
+    }
+
+    /// Take a byte from the serial port; returns the pages to scroll back
+    /// if it completes a scroll key
+    fn receive(&mut self, byte: u8) -> Option<isize> {
+        self.escape[self.escape_len] = byte;
+        self.escape_len += 1;
+        let held = &self.escape[..self.escape_len];
+        for (key, pages) in [(SHIFT_PAGE_UP, 1), (SHIFT_PAGE_DOWN, -1)] {
+            if held == key {
+                self.escape_len = 0;
+                return Some(pages);
+            }
+        }
+        if [SHIFT_PAGE_UP, SHIFT_PAGE_DOWN].iter().any(|key| key.starts_with(held)) {
+            if self.escape_len == 1 {
+                self.escape_since = Instant::now();
+            }
+            return None;
+        }
+        self.release();
+        None
+    }
+
+    /// Pass on an escape sequence held back for longer than
+    /// `ESCAPE_TIMEOUT`: it was a key of its own, such as Escape
+    fn expire(&mut self) {
+        if self.escape_len > 0 && self.escape_since.elapsed() >= ESCAPE_TIMEOUT {
+            self.release();
+        }
+    }
+
+    /// Pass the held bytes on to readers; typing returns the console to
+    /// the bottom of its history
+    fn release(&mut self) {
+        // Dropped if nobody reads the console
+        self.buffer.write(&self.escape[..self.escape_len]);
+        self.escape_len = 0;
+        SCROLL_HOME.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Text console state on top of a framebuffer
+///
+/// Characters go into a text grid and the changed cells are merged into one
+/// damage rectangle; `flush` then draws the rectangle and applies all
+/// pending scrolling with a single framebuffer move. Console writes flush
+/// once at the end, so a multi-line message costs one scroll and one redraw
+/// of the lines it touched instead of a scroll per line.
+///
+/// The grid is laid out on the screen as seen with `rotation`, in cells of
+/// `scale` times the font's glyph size; only `draw_cell` and `flush` deal
+/// with pixels, through the framebuffer's rotated drawing and scrolling.
+/// Cells hold characters of the Basic Multilingual Plane, which is all a
+/// PSF2 Unicode table maps in practice.
+///
+/// With a back buffer, `flush` only draws into it; `present` copies the
+/// result to the screen, at the end of `flush` unless `flush_task` does it.
+///
+/// Scrolled back `view` lines, the screen shows the end of `history` above
+/// the grid, and any change is drawn by redrawing the whole screen; output
+/// arriving meanwhile moves `view` along so the text shown stays put.
+struct FbConsole {
+    fb: Option<Framebuffer>,
+    cells: [[u16; MAX_COLS]; MAX_ROWS],
+    col: usize,
+    row: usize,
+    cols: usize,
+    rows: usize,
+    /// Cells changed since the last flush
+    damage: Option<Damage>,
+    /// Lines scrolled since the last flush
+    scrolled: usize,
+    /// Draw every character as it arrives (for benchmarking the old path)
+    immediate: bool,
+    rotation: Rotation,
+    font: &'static Font,
+    /// Font scale factor, 1 to `MAX_FONT_SCALE`
+    scale: usize,
+    /// Character being decoded from UTF-8 and its continuation bytes
+    /// still to come
+    pending: u32,
+    pending_bytes: u8,
+    /// The back buffer goes to the screen from `flush_task` only
+    periodic: bool,
+    history: History,
+    /// Lines scrolled back into `history`, 0 showing the grid
+    view: usize,
+    /// The next flush redraws the whole screen
+    redraw: bool,
+    /// A user process has the screen (`/dev/fb0`): draw nothing
+    graphics: bool,
+}
+
+// The framebuffer pointer is only ever touched while holding FB_CONSOLE
+unsafe impl Send for FbConsole {}
+
+impl FbConsole {
+    const fn new() -> Self {
+        Self {
+            fb: None,
+            cells: [[b' ' as u16; MAX_COLS]; MAX_ROWS],
+            col: 0,
+            row: 0,
+            cols: 0,
+            rows: 0,
+            damage: None,
+            scrolled: 0,
+            immediate: false,
+            rotation: Rotation::Normal,
+            font: &font::BUILTIN,
+            scale: 1,
+            pending: 0,
+            pending_bytes: 0,
+            periodic: false,
+            history: History::new(),
+            view: 0,
+            redraw: false,
+            graphics: false,
+        }
+    }
+
+    /// Width and height of a text cell in pixels
+    fn cell_size(&self) -> (usize, usize) {
+        (self.font.width() * self.scale, self.font.height() * self.scale)
+    }
+
+    /// Grid size that fits the framebuffer with the current layout
+    fn grid_size(&self, fb: &Framebuffer) -> (usize, usize) {
+        let (width, height) = fb.logical_size(self.rotation);
+        let (cell_width, cell_height) = self.cell_size();
+        ((width / cell_width).min(MAX_COLS), (height / cell_height).min(MAX_ROWS))
+    }
+
+    fn attach(&mut self, fb: Framebuffer) {
+        (self.cols, self.rows) = self.grid_size(&fb);
+        self.fb = Some(fb);
+        self.col = 0;
+        self.row = 0;
+        self.damage = None;
+        self.scrolled = 0;
+    }
+
+    /// Change rotation, font and font scale, keeping as much text as fits
+    ///
+    /// The screen is cleared and redrawn; if the cursor's line no longer
+    /// fits, the text moves up so it is on the last line.
+    fn set_layout(&mut self, rotation: Rotation, font: &'static Font, scale: usize) {
+        self.flush();
+        self.rotation = rotation;
+        self.font = font;
+        self.scale = scale.clamp(1, MAX_FONT_SCALE);
+        let Some(fb) = self.fb.as_ref() else { return };
+        let (cols, rows) = self.grid_size(fb);
+        if let Some(fb) = self.fb.as_mut() {
+            fb.clear(BG_COLOR);
+        }
+
+        if rows > 0 && self.row >= rows {
+            let shift = self.row + 1 - rows;
+            for line in 0..shift {
+                self.history.push(&self.cells[line]);
+            }
+            self.cells.copy_within(shift..self.row + 1, 0);
+            self.row = rows - 1;
+        }
+        for (row, line) in self.cells.iter_mut().enumerate() {
+            let blank_from = if row > self.row { 0 } else { cols };
+            line[blank_from..].fill(b' ' as u16);
+        }
+        self.cols = cols;
+        self.rows = rows;
+        self.col = self.col.min(cols);
+        self.view = 0;
+        self.damage = (rows > 0 && cols > 0).then(|| Damage {
+            top: 0,
+            left: 0,
+            bottom: self.row + 1,
+            right: cols,
+        });
+        self.flush();
+    }
+
+    fn putc(&mut self, byte: u8) {
+        if self.cols == 0 || self.rows == 0 {
+            return;
+        }
+
+        if self.pending_bytes > 0 {
+            if byte & 0xC0 == 0x80 {
+                self.pending = self.pending << 6 | (byte & 0x3F) as u32;
+                self.pending_bytes -= 1;
+                if self.pending_bytes == 0 {
+                    self.draw(u16::try_from(self.pending).unwrap_or(REPLACEMENT));
+                }
+                return;
+            }
+            // Cut short: the bytes so far are one bad character
+            self.pending_bytes = 0;
+            self.draw(REPLACEMENT);
+        }
+
+        match byte {
+            b'\n' => self.newline(),
+            b'\r' => self.col = 0,
+            b'\t' => {
+                let next = (self.col / 8 + 1) * 8;
+                while self.col < next.min(self.cols) {
+                    self.draw(b' ' as u16);
+                }
+            }
+            0x08 => {
+                if self.col > 0 {
+                    self.col -= 1;
+                    self.draw(b' ' as u16);
+                    self.col -= 1;
+                }
+            }
+            0x20..=0x7E => self.draw(byte as u16),
+            // UTF-8 lead bytes; overlong and surrogate encodings are not
+            // rejected, the font just has no glyph for them
+            0xC2..=0xDF => (self.pending, self.pending_bytes) = ((byte & 0x1F) as u32, 1),
+            0xE0..=0xEF => (self.pending, self.pending_bytes) = ((byte & 0x0F) as u32, 2),
+            0xF0..=0xF4 => (self.pending, self.pending_bytes) = ((byte & 0x07) as u32, 3),
+            0x80..=0xFF => self.draw(REPLACEMENT),
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self, c: u16) {
+        if self.col >= self.cols {
+            self.newline();
+        }
+        self.cells[self.row][self.col] = c;
+        if self.immediate {
+            self.draw_cell(self.row, self.col);
+        } else {
+            let cell = Damage::cell(self.row, self.col);
+            self.damage = Some(self.damage.map_or(cell, |damage| damage.merge(cell)));
+        }
+        self.col += 1;
+    }
+
+    fn newline(&mut self) {
+        self.col = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+
+        let last = self.rows - 1;
+        self.history.push(&self.cells[0]);
+        if self.view > 0 {
+            self.view = (self.view + 1).min(self.history.len);
+        }
+        self.cells.copy_within(1..self.rows, 0);
+        self.cells[last] = [b' ' as u16; MAX_COLS];
+        if self.immediate {
+            let (_, cell_height) = self.cell_size();
+            if let Some(fb) = self.fb.as_mut() {
+                fb.scroll_rotated(self.rotation, cell_height, BG_COLOR);
+            }
+            return;
+        }
+
+        // Damaged cells move up with the text; the blank bottom line is
+        // exposed by the scroll itself
+        self.scrolled += 1;
+        self.damage = self.damage.and_then(|damage| {
+            (damage.bottom > 1).then(|| Damage {
+                top: damage.top.saturating_sub(1),
+                bottom: damage.bottom - 1,
+                ..damage
+            })
+        });
+    }
+
+    /// Line shown on screen row `row`
+    fn visible_line(&self, row: usize) -> &[u16; MAX_COLS] {
+        let index = self.history.len - self.view + row;
+        match index.checked_sub(self.history.len) {
+            Some(grid_row) => &self.cells[grid_row],
+            None => self.history.line(index),
+        }
+    }
+
+    fn draw_cell(&mut self, row: usize, col: usize) {
+        let (cell_width, cell_height) = self.cell_size();
+        let c = self.visible_line(row)[col];
+        let c = char::from_u32(c as u32).unwrap_or(char::REPLACEMENT_CHARACTER);
+        if let Some(fb) = self.fb.as_mut() {
+            fb.draw_char_transformed(
+                self.font,
+                c,
+                col * cell_width,
+                row * cell_height,
+                self.scale,
+                self.rotation,
+                FG_COLOR,
+                BG_COLOR,
+            );
+        }
+    }
+
+    /// Apply scroll keys pressed since the last flush
+    fn apply_scroll_keys(&mut self) {
+        if SCROLL_HOME.swap(false, Ordering::Relaxed) && self.view > 0 {
+            self.view = 0;
+            self.redraw = true;
+        }
+        let pages = SCROLL_PAGES.swap(0, Ordering::Relaxed);
+        if pages != 0 {
+            let step = (self.rows / 2).max(1) as isize;
+            let view = (self.view as isize + pages * step).clamp(0, self.history.len as isize) as usize;
+            self.redraw |= view != self.view;
+            self.view = view;
+        }
+    }
+
+    /// Show the mouse cursor at `(x, y)` on the screen
+    fn move_cursor(&mut self, x: usize, y: usize) {
+        if self.graphics || !mode().uses_framebuffer() {
+            return;
+        }
+        if let Some(fb) = self.fb.as_mut() {
+            fb.set_overlay(Some((&crate::dev::mouse::CURSOR_SPRITE, x, y)));
+        }
+        if !self.periodic {
+            self.present();
+        }
+    }
+
+    /// Apply pending scrolling and draw the damaged cells
+    fn flush(&mut self) {
+        if self.graphics {
+            return;
+        }
+        self.apply_scroll_keys();
+        let mut damage = self.damage.take();
+        let scrolled = core::mem::take(&mut self.scrolled);
+        let changed = damage.is_some() || scrolled > 0;
+        if self.redraw || scrolled >= self.rows || (self.view > 0 && changed) {
+            // Everything on screen is new, or scrolled back: redraw it all
+            // instead of moving
+            self.redraw = false;
+            damage = Some(Damage {
+                top: 0,
+                left: 0,
+                bottom: self.rows,
+                right: self.cols,
+            });
+        } else if scrolled > 0 {
+            let (_, cell_height) = self.cell_size();
+            if let Some(fb) = self.fb.as_mut() {
+                fb.scroll_rotated(self.rotation, scrolled * cell_height, BG_COLOR);
+            }
+        }
+
+        if let Some(damage) = damage {
+            for row in damage.top..damage.bottom {
+                for col in damage.left..damage.right {
+                    self.draw_cell(row, col);
+                }
+            }
+        }
+
+        if !self.periodic {
+            self.present();
+        }
+    }
+
+    /// Copy what was drawn into the back buffer to the screen
+    fn present(&mut self) {
+        if let Some(fb) = self.fb.as_mut() {
+            fb.flush();
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.putc(byte);
+        }
+    }
+}
+
+impl fmt::Write for FbConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Initialize the console from the kernel command line
+///
+/// Attaches the framebuffer text console and applies `console=`,
+/// `fbrotate=` and `fbscale=`. Unknown values fall back to the defaults
+/// (serial, upright, unscaled) with a warning.
+pub fn init(limine_fb: &LimineFramebuffer) {
+    let rotation = match crate::cmdline::value("fbrotate") {
+        Some(value) => Rotation::parse(value).unwrap_or_else(|| {
+            crate::serial_println!("[CONSOLE] Unknown fbrotate={}, not rotating", value);
+            Rotation::Normal
+        }),
+        None => Rotation::Normal,
+    };
+    let scale = match crate::cmdline::value("fbscale") {
+        Some(value) => parse_font_scale(value).unwrap_or_else(|| {
+            crate::serial_println!("[CONSOLE] Unknown fbscale={}, not scaling", value);
+            1
+        }),
+        None => 1,
+    };
+
+    {
+        let mut console = FB_CONSOLE.lock();
+        console.rotation = rotation;
+        console.scale = scale;
+        console.attach(Framebuffer::new(limine_fb));
+    }
+    crate::sync::wait_queue::register_readiness(input_ready_irq, &INPUT_WAIT);
+
+    let mode = match crate::cmdline::value("console") {
+        Some(value) => ConsoleMode::parse(value).unwrap_or_else(|| {
+            crate::serial_println!("[CONSOLE] Unknown console={}, using serial", value);
+            ConsoleMode::Serial
+        }),
+        None => ConsoleMode::Serial,
+    };
+
+    set_mode(mode);
+    crate::serial_println!(
+        "[CONSOLE] Console mode: {:?}, rotation {}, font scale {}x",
+        mode,
+        rotation.degrees(),
+        scale
+    );
+}
+
+/// Give the framebuffer console its back buffer and scrollback, starting
+/// [`flush_task`] if `fbflush=` asks for it
+fn init_late() {
+    if init_back_buffer() {
+        crate::sched::spawn_task("FbFlush", flush_task, crate::sched::priority::TaskPriority::Normal)
+            .expect("Failed to spawn FbFlush");
+    }
+    init_scrollback();
+}
+crate::initcall!(Late, init_late);
+
+/// Give the framebuffer console a back buffer
+///
+/// Needs the physical memory manager. The buffer takes contiguous frames,
+/// as it is too large for the kernel heap; without them the console keeps
+/// drawing on the screen. Applies `fbflush=`, and returns whether
+/// [`flush_task`] should be started for it.
+pub fn init_back_buffer() -> bool {
+    let Some(size) = FB_CONSOLE.lock().fb.as_ref().map(Framebuffer::buffer_size) else {
+        return false;
+    };
+    // Not under the console lock: the memory manager may log
+    let frames = size.div_ceil(crate::mm::pmm::FRAME_SIZE);
+    let buffer = crate::mm::with_memory_managers(|pmm, _| {
+        pmm.alloc_contiguous(frames, crate::mm::pmm::FRAME_SIZE).ok_or("out of memory")
+    });
+    let buffer = match buffer {
+        Ok(phys) => crate::mm::phys_to_virt(phys).as_mut_ptr::<u8>(),
+        Err(e) => {
+            crate::serial_println!("[CONSOLE] No framebuffer back buffer ({}), drawing on screen", e);
+            return false;
+        }
+    };
+
+    let interval = match crate::cmdline::value("fbflush") {
+        Some(value) => parse_flush_interval(value).unwrap_or_else(|| {
+            crate::serial_println!("[CONSOLE] Unknown fbflush={}, flushing after each write", value);
+            0
+        }),
+        None => 0,
+    };
+    FLUSH_INTERVAL_MS.store(interval, Ordering::Relaxed);
+
+    let mut console = FB_CONSOLE.lock();
+    console.periodic = interval > 0;
+    if let Some(fb) = console.fb.as_mut() {
+        // The frames are never freed
+        unsafe { fb.set_back_buffer(buffer) };
+    }
+    drop(console);
+    crate::serial_println!("[CONSOLE] Framebuffer back buffer of {} KiB", size / 1024);
+    interval > 0
+}
+
+/// Give the framebuffer console its scrollback history
+///
+/// Needs the physical memory manager: the history takes contiguous frames,
+/// `fbscrollback=` lines (0 to `MAX_SCROLLBACK`, 0 for none) of
+/// `MAX_COLS` cells, or `DEFAULT_SCROLLBACK` lines. Without the frames the
+/// console keeps no history.
+pub fn init_scrollback() {
+    if FB_CONSOLE.lock().fb.is_none() {
+        return;
+    }
+    let lines = match crate::cmdline::value("fbscrollback") {
+        Some(value) => parse_scrollback(value).unwrap_or_else(|| {
+            crate::serial_println!("[CONSOLE] Unknown fbscrollback={}, keeping {} lines", value, DEFAULT_SCROLLBACK);
+            DEFAULT_SCROLLBACK
+        }),
+        None => DEFAULT_SCROLLBACK,
+    };
+    if lines == 0 {
+        return;
+    }
+
+    // Not under the console lock: the memory manager may log
+    let frames = (lines * core::mem::size_of::<[u16; MAX_COLS]>()).div_ceil(crate::mm::pmm::FRAME_SIZE);
+    let buffer = crate::mm::with_memory_managers(|pmm, _| {
+        pmm.alloc_contiguous(frames, crate::mm::pmm::FRAME_SIZE).ok_or("out of memory")
+    });
+    let buffer = match buffer {
+        Ok(phys) => crate::mm::phys_to_virt(phys).as_mut_ptr::<[u16; MAX_COLS]>(),
+        Err(e) => {
+            crate::serial_println!("[CONSOLE] No scrollback ({})", e);
+            return;
+        }
+    };
+
+    // The frames are never freed
+    FB_CONSOLE.lock().history = History {
+        lines: buffer,
+        capacity: lines,
+        start: 0,
+        len: 0,
+    };
+    crate::serial_println!("[CONSOLE] Scrollback of {} lines", lines);
+}
+
+/// Write the last `lines` lines of the framebuffer console, scrollback
+/// included, to the serial port
+///
+/// For the panic handler, when the screen may be all that showed what led
+/// up to the panic. Does nothing if the console is locked.
+pub fn dump_history(lines: usize) {
+    struct Line<'a>(&'a [u16]);
+
+    impl fmt::Display for Line<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let end = self.0.iter().rposition(|&c| c != b' ' as u16).map_or(0, |last| last + 1);
+            for &c in &self.0[..end] {
+                fmt::Write::write_char(f, char::from_u32(c as u32).unwrap_or(char::REPLACEMENT_CHARACTER))?;
+            }
+            Ok(())
+        }
+    }
+
+    let Some(console) = FB_CONSOLE.try_lock() else { return };
+    if console.cols == 0 || console.rows == 0 {
+        return;
+    }
+    let history = &console.history;
+    let total = history.len + console.row + 1;
+    for index in total.saturating_sub(lines)..total {
+        let line = match index.checked_sub(history.len) {
+            Some(row) => &console.cells[row],
+            None => history.line(index),
+        };
+        crate::serial_println!("{}", Line(&line[..console.cols]));
+    }
+}
+
+/// Kernel task copying the framebuffer console's back buffer to the screen
+/// every `fbflush=` milliseconds
+pub fn flush_task() -> ! {
+    let interval = Duration::from_millis(FLUSH_INTERVAL_MS.load(Ordering::Relaxed).max(1));
+    loop {
+        FB_CONSOLE.lock().present();
+        if let Some((_, priority)) = crate::sched::get_current_task_info() {
+            crate::sched::sleep_current_task(interval, priority);
+        }
+        crate::sched::yield_now();
+    }
+}
+
+/// Get the active console mode
+pub fn mode() -> ConsoleMode {
+    ConsoleMode::from_u8(MODE.load(Ordering::Relaxed))
+}
+
+/// Change the active console mode at runtime
+pub fn set_mode(mode: ConsoleMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Framebuffer console rotation and font scale
+pub fn layout() -> (Rotation, usize) {
+    let console = FB_CONSOLE.lock();
+    (console.rotation, console.scale)
+}
+
+/// Rotate or rescale the framebuffer console at runtime
+///
+/// The screen is redrawn with the text that still fits. `scale` is
+/// clamped to 1..=`MAX_FONT_SCALE`.
+pub fn set_layout(rotation: Rotation, scale: usize) {
+    let mut console = FB_CONSOLE.lock();
+    let font = console.font;
+    console.set_layout(rotation, font, scale);
+}
+
+/// Stop drawing the framebuffer console while a user process draws on the
+/// screen, or redraw it when the process is done
+///
+/// Text written meanwhile is kept and shows up on the redraw.
+pub fn set_graphics(graphics: bool) {
+    let mut console = FB_CONSOLE.lock();
+    console.graphics = graphics;
+    if !graphics {
+        console.redraw = true;
+        console.flush();
+    }
+}
+
+/// Switch the framebuffer console to the font named by `fbfont=`
+///
+/// Call once the initrd is up; the font file is used in place. A font
+/// that is missing or does not parse leaves the built-in one, with a
+/// warning.
+pub fn load_font() {
+    let Some(path) = crate::cmdline::value("fbfont") else { return };
+    let Some(entry) = crate::fs::initrd::find(path) else {
+        crate::serial_println!("[CONSOLE] fbfont={}: not in the initrd, keeping the built-in font", path);
+        return;
+    };
+    let font = match Font::parse(entry.data) {
+        Ok(font) => LOADED_FONT.call_once(|| font),
+        Err(e) => {
+            crate::serial_println!("[CONSOLE] fbfont={}: {:?}, keeping the built-in font", path, e);
+            return;
+        }
+    };
+
+    let mut console = FB_CONSOLE.lock();
+    let (rotation, scale) = (console.rotation, console.scale);
+    console.set_layout(rotation, font, scale);
+    drop(console);
+    crate::serial_println!(
+        "[CONSOLE] Font {}: {}x{}, {} glyphs",
+        path,
+        font.width(),
+        font.height(),
+        font.glyph_count()
+    );
+}
+
+/// Write a byte to every active console device
+#[allow(dead_code)]
+pub fn putc(byte: u8) {
+    write_bytes(&[byte]);
+}
+
+/// Route the serial port's receive interrupt
+///
+/// The handler only moves bytes from the port into a lock-free queue;
+/// [`poll_input`] takes them from there. Without the interrupt it polls
+/// the port instead.
+pub fn init_input_irq() {
+    let routed = api::register_driver(&SERIAL_DRIVER).and_then(|driver| {
+        let line = irq::request_irq(driver, serial_interrupt)?;
+        irq::route_isa_irq(driver, line, 4).inspect_err(|_| {
+            let _ = irq::free_irq(driver, line);
+        })
+    });
+    if let Err(e) = routed {
+        crate::serial_println!("[CONSOLE] No serial receive interrupt, polling: {:?}", e);
+        return;
+    }
+    SERIAL_RX_IRQ.store(true, Ordering::Release);
+    SerialPort::new(SERIAL_PORT).enable_receive_interrupt();
+    // Bytes that came before the route was set up raised no interrupt
+    serial_interrupt(0);
+}
+crate::initcall!(Drivers, init_input_irq);
+
+/// Serial receive interrupt: queue what the port holds
+///
+/// Also called by [`poll_input`], under the input lock, until the
+/// interrupt is routed. The port is read without the serial lock, which
+/// output may hold for long: nothing else reads it.
+fn serial_interrupt(_line: u8) {
+    let mut port = SerialPort::new(SERIAL_PORT);
+    while let Some(received) = port.receive() {
+        SERIAL_RX.push(received);
+    }
+}
+
+/// Move what the serial port received into the input buffer
+///
+/// Runs on every timer tick of the boot CPU as well as before each read.
+/// Without a receive interrupt it also reads the port, often enough that
+/// the port's 16-byte FIFO rarely overflows. SysRq sequences are taken
+/// out of the input here and acted on (see [`crate::sysrq`]), which is why
+/// they work even when no task gets to run. So are the scroll keys, which
+/// page through the framebuffer console's history.
+pub fn poll_input() {
+    let mut action = None;
+    let mut scroll = false;
+    {
+        let mut input = INPUT.lock();
+        input.expire();
+        if !SERIAL_RX_IRQ.load(Ordering::Acquire) {
+            serial_interrupt(0);
+        }
+        while let Some(received) = SERIAL_RX.pop() {
+            match crate::sysrq::filter(received) {
+                Key::Input(byte) => {
+                    if let Some(pages) = input.receive(byte) {
+                        SCROLL_PAGES.fetch_add(pages, Ordering::Relaxed);
+                        scroll = true;
+                    }
+                }
+                Key::Action(key) => action = Some(key),
+                Key::Swallowed => {}
+            }
+        }
+    }
+    let dropped = SERIAL_RX.overflows();
+    if dropped != SERIAL_RX_REPORTED.swap(dropped, Ordering::Relaxed) {
+        crate::log_warn!("CONSOLE", "Serial input queue overflowed, {} bytes dropped", dropped);
+    }
+    if let Some(key) = action {
+        crate::sysrq::handle(key);
+    }
+    // Otherwise the next console write scrolls
+    if scroll {
+        if let Some(mut console) = FB_CONSOLE.try_lock() {
+            console.flush();
+        }
+    }
+}
+
+/// Draw the mouse cursor where the mouse put it
+///
+/// Called by the mouse driver from its work item, with interrupts enabled.
+pub fn draw_mouse_cursor() {
+    let mut console = FB_CONSOLE.lock();
+    if let Some((x, y)) = crate::dev::mouse::take_cursor() {
+        console.move_cursor(x, y);
+    }
+}
+
+/// Read a byte from the console, if one is available
+///
+/// Input always comes from the serial port: there is no keyboard driver
+/// yet, so the framebuffer console is output only.
+pub fn getc() -> Option<u8> {
+    poll_input();
+    let mut byte = [0u8];
+    (INPUT.lock().buffer.read(&mut byte) == 1).then_some(byte[0])
+}
+
+/// Whether a byte is waiting to be read with [`getc`]
+pub fn input_ready() -> bool {
+    poll_input();
+    !INPUT.lock().buffer.is_empty()
+}
+
+/// [`input_ready`] from interrupt context; false while the buffer is locked
+fn input_ready_irq() -> bool {
+    INPUT.try_lock().map_or(false, |input| !input.buffer.is_empty())
+}
+
+/// Print to the console without waiting for the console locks
+///
+/// For output that must get out when the lock holders may never let go,
+/// such as SysRq reports: serial output goes straight to the port, and the
+/// framebuffer is skipped while locked. Lines may interleave with other
+/// output.
+pub fn emergency_print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let mode = mode();
+
+    if mode.uses_serial() {
+        let _ = SerialPort::new(SERIAL_PORT).write_fmt(args);
+    }
+
+    if mode.uses_framebuffer() {
+        if let Some(mut console) = FB_CONSOLE.try_lock() {
+            let _ = console.write_fmt(args);
+            console.flush();
+            // Without waiting for a flush task that may not get to run
+            console.present();
+        }
+    }
+}
+
+/// Write raw bytes to every active console device
+pub fn write_bytes(bytes: &[u8]) {
+    let mode = mode();
+
+    if mode.uses_serial() {
+        let mut serial = SERIAL.lock();
+        for &byte in bytes {
+            serial.write_byte(byte);
+        }
+    }
+
+    if mode.uses_framebuffer() {
+        let mut console = FB_CONSOLE.lock();
+        console.write_bytes(bytes);
+        console.flush();
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let mode = mode();
+
+    // Each device lock is held for the whole message so lines from
+    // different CPUs do not interleave
+    if mode.uses_serial() {
+        let _ = SERIAL.lock().write_fmt(args);
+    }
+
+    if mode.uses_framebuffer() {
+        let mut console = FB_CONSOLE.lock();
+        let _ = console.write_fmt(args);
+        console.flush();
+    }
+}
+
+/// Framebuffer console throughput in characters per second
+fn measure(immediate: bool) -> u64 {
+    FB_CONSOLE.lock().immediate = immediate;
+    let start = Instant::now();
+    let mut chars = 0u64;
+    while start.elapsed() < BENCH_TIME {
+        let mut console = FB_CONSOLE.lock();
+        for _ in 0..BENCH_BURST {
+            console.write_bytes(BENCH_LINE);
+        }
+        console.flush();
+        chars += (BENCH_BURST * BENCH_LINE.len()) as u64;
+    }
+    FB_CONSOLE.lock().immediate = false;
+    chars * 1000 / start.elapsed().as_millis().max(1)
+}
+
+/// Measure the framebuffer console drawing every character as it arrives
+/// and with damage batching, in characters per second
+///
+/// Returns `None` if no framebuffer is attached. Needs the timer running.
+pub fn bench() -> Option<(u64, u64)> {
+    if FB_CONSOLE.lock().fb.is_none() {
+        return None;
+    }
+    Some((measure(true), measure(false)))
+}
+
+/// Kernel task started by `conbench` on the kernel command line
+pub fn bench_task() -> ! {
+    match bench() {
+        Some((before, after)) => crate::serial_println!(
+            "[CONSOLE] conbench: unbatched {} chars/s, batched {} chars/s ({}.{}x)",
+            before,
+            after,
+            after / before.max(1),
+            after * 10 / before.max(1) % 10
+        ),
+        None => crate::serial_println!("[CONSOLE] conbench: no framebuffer console"),
+    }
+
+    loop {
+        if let Some((_, priority)) = crate::sched::get_current_task_info() {
+            crate::sched::sleep_current_task(Duration::from_secs(3600), priority);
+        }
+        crate::sched::yield_now();
+    }
+}
+
+crate::kernel_test! {
+    /// Scroll keys are taken out of the input, other escape sequences pass
+    /// through whole, and the history keeps the newest lines
+    fn console_scrollback() {
+        let mut input = Input::new();
+        let mut pages = 0;
+        for &byte in b"a\x1b[5;2~\x1b[5;2~\x1b[6;2~\x1b[Ab" {
+            pages += input.receive(byte).unwrap_or(0);
+        }
+        crate::ktest_assert_eq!(pages, 1, "scroll keys");
+        let mut passed = [0u8; 8];
+        let count = input.buffer.read(&mut passed);
+        crate::ktest_assert_eq!(&passed[..count], b"a\x1b[Ab", "input passed on");
+
+        static LINES: spin::Mutex<[[u16; MAX_COLS]; 3]> = spin::Mutex::new([[0; MAX_COLS]; 3]);
+        let mut lines = LINES.lock();
+        let mut history = History {
+            lines: lines.as_mut_ptr(),
+            capacity: 3,
+            start: 0,
+            len: 0,
+        };
+        for n in 0..5 {
+            history.push(&[n; MAX_COLS]);
+        }
+        crate::ktest_assert_eq!(history.len, 3, "history length");
+        crate::ktest_assert_eq!((history.line(0)[0], history.line(2)[0]), (2, 4), "oldest lines not dropped");
+        SCROLL_HOME.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+}
@@ -0,0 +1,507 @@
+//! AHCI SATA disks
+//!
+//! Drives the SATA ports of AHCI host controllers (PCI class 01.06.01), the
+//! ICH9 controller of QEMU's q35 machine included. Each port with an ATA
+//! disk behind it becomes a block device, `sda` to `sdh` in PCI and port
+//! order. Disks are found once at boot; hotplug is not handled.
+//!
+//! Commands use slot 0 of the port's command list only, one at a time
+//! (no NCQ): `READ DMA EXT` and `WRITE DMA EXT` with 48-bit LBAs, and a
+//! `FLUSH CACHE EXT` after every write so the data is on the disk when the
+//! call returns. Completion is polled, with the port's interrupts off. Data
+//! goes through a 64 KiB DMA buffer per port, so callers can pass any
+//! kernel buffer; larger requests are split.
+//!
+//! The controller's registers (ABAR, BAR 5) are reached through the
+//! bootloader's identity map of the low 4 GiB, like the local APIC's.
+
+use crate::dev::api::block::{self, BlockDevice};
+use crate::dev::api::dma::{self, DmaBuffer};
+use crate::dev::api::{self, DriverError, DriverHandle, DriverInfo, DriverResult};
+use crate::dev::pci::{self, PciDevice};
+use crate::io::mmio::MmioRegion;
+use crate::sync::SpinLock;
+use core::sync::atomic::{fence, Ordering};
+use spin::Once;
+
+/// PCI class, subclass and programming interface of an AHCI controller
+const CLASS_STORAGE: u8 = 0x01;
+const SUBCLASS_SATA: u8 = 0x06;
+const PROG_IF_AHCI: u8 = 0x01;
+
+/// BAR holding the controller registers (ABAR)
+const ABAR: u8 = 5;
+
+/// Generic host control registers
+const HBA_CAP: usize = 0x00;
+const HBA_GHC: usize = 0x04;
+const HBA_IS: usize = 0x08;
+const HBA_PI: usize = 0x0C;
+
+/// `CAP`: 64-bit addressing, staggered spin-up
+const CAP_S64A: u32 = 1 << 31;
+const CAP_SSS: u32 = 1 << 27;
+
+/// `GHC`: AHCI enable, HBA reset
+const GHC_AE: u32 = 1 << 31;
+const GHC_HR: u32 = 1 << 0;
+
+/// Port registers, at `0x100 + port * 0x80`
+const PORTS_BASE: usize = 0x100;
+const PORT_SIZE: usize = 0x80;
+/// HBA registers: the global ones, then 32 ports
+const ABAR_SIZE: usize = PORTS_BASE + 32 * PORT_SIZE;
+const PX_CLB: usize = 0x00;
+const PX_CLBU: usize = 0x04;
+const PX_FB: usize = 0x08;
+const PX_FBU: usize = 0x0C;
+const PX_IS: usize = 0x10;
+const PX_IE: usize = 0x14;
+const PX_CMD: usize = 0x18;
+const PX_TFD: usize = 0x20;
+const PX_SIG: usize = 0x24;
+const PX_SSTS: usize = 0x28;
+const PX_SERR: usize = 0x30;
+const PX_CI: usize = 0x38;
+
+/// `PxCMD`: start, spin up, power on, FIS receive enable, and the
+/// running bits of FIS receive and the command list
+const CMD_ST: u32 = 1 << 0;
+const CMD_SUD: u32 = 1 << 1;
+const CMD_POD: u32 = 1 << 2;
+const CMD_FRE: u32 = 1 << 4;
+const CMD_FR: u32 = 1 << 14;
+const CMD_CR: u32 = 1 << 15;
+
+/// `PxIS`: task file error
+const IS_TFES: u32 = 1 << 30;
+
+/// `PxTFD` status bits: error, data request, busy
+const TFD_ERR: u32 = 1 << 0;
+const TFD_DRQ: u32 = 1 << 3;
+const TFD_BSY: u32 = 1 << 7;
+
+/// `PxSSTS` device detection: device present and link up
+const SSTS_DET_MASK: u32 = 0xF;
+const SSTS_DET_PRESENT: u32 = 3;
+
+/// `PxSIG` of an ATA disk (ATAPI and port multipliers differ)
+const SIG_ATA: u32 = 0x0000_0101;
+
+/// ATA commands
+const ATA_READ_DMA_EXT: u8 = 0x25;
+const ATA_WRITE_DMA_EXT: u8 = 0x35;
+const ATA_FLUSH_CACHE_EXT: u8 = 0xEA;
+const ATA_IDENTIFY: u8 = 0xEC;
+
+/// Register FIS, host to device, and its length in bytes
+const FIS_TYPE_H2D: u8 = 0x27;
+const FIS_H2D_LEN: usize = 20;
+/// H2D flags: the FIS carries a command
+const FIS_COMMAND: u8 = 0x80;
+/// Device register: LBA addressing
+const DEVICE_LBA: u8 = 0x40;
+
+/// Command header bit: data goes to the device
+const HEADER_WRITE: u32 = 1 << 6;
+
+/// Layout of the per-port page: command list (32 headers of 32 bytes),
+/// received FIS area, then the command table of slot 0 with one PRD entry
+const COMMAND_LIST: usize = 0x000;
+const RECEIVED_FIS: usize = 0x400;
+const COMMAND_TABLE: usize = 0x500;
+const PRDT: usize = COMMAND_TABLE + 0x80;
+const PRD_SIZE: usize = 16;
+
+/// Data buffer of a port; one PRD entry covers it
+const BOUNCE_SIZE: usize = 64 * 1024;
+
+const SECTOR_SIZE: usize = 512;
+
+/// Register reads before giving up; each takes about a microsecond
+const RESET_POLLS: usize = 1_000_000;
+const STOP_POLLS: usize = 500_000;
+const LINK_POLLS: usize = 50_000;
+const READY_POLLS: usize = 5_000_000;
+const COMMAND_POLLS: usize = 5_000_000;
+
+/// Disks kept, one per port with a disk
+const MAX_DISKS: usize = 8;
+
+/// Controllers driven
+const MAX_CONTROLLERS: usize = 4;
+
+static DRIVER: DriverInfo = crate::driver_info!("ahci");
+
+/// What IDENTIFY DEVICE says about a disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Identity {
+    sectors: u64,
+    sector_size: usize,
+}
+
+/// A port with a disk, and the DMA memory it was given
+struct Port {
+    /// Port registers
+    regs: MmioRegion,
+    memory: DmaBuffer,
+    bounce: DmaBuffer,
+}
+
+/// A disk, published as a block device
+struct Disk {
+    name: &'static str,
+    identity: Once<Identity>,
+    port: SpinLock<Option<Port>>,
+}
+
+impl Disk {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            identity: Once::new(),
+            port: SpinLock::new(None),
+        }
+    }
+}
+
+static DISKS: [Disk; MAX_DISKS] = [
+    Disk::new("sda"),
+    Disk::new("sdb"),
+    Disk::new("sdc"),
+    Disk::new("sdd"),
+    Disk::new("sde"),
+    Disk::new("sdf"),
+    Disk::new("sdg"),
+    Disk::new("sdh"),
+];
+
+fn read(regs: MmioRegion, offset: usize) -> u32 {
+    regs.read(offset)
+}
+
+fn write(regs: MmioRegion, offset: usize, value: u32) {
+    regs.write(offset, value)
+}
+
+/// Poll until `done`, at most `polls` times
+fn wait(polls: usize, mut done: impl FnMut() -> bool) -> bool {
+    (0..polls).any(|_| {
+        let finished = done();
+        if !finished {
+            core::hint::spin_loop();
+        }
+        finished
+    })
+}
+
+fn put32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Register FIS for `command` on `count` sectors from `lba`
+fn h2d_fis(command: u8, lba: u64, count: u16) -> [u8; FIS_H2D_LEN] {
+    let lba = lba.to_le_bytes();
+    let count = count.to_le_bytes();
+    let mut fis = [0; FIS_H2D_LEN];
+    fis[0] = FIS_TYPE_H2D;
+    fis[1] = FIS_COMMAND;
+    fis[2] = command;
+    fis[4..7].copy_from_slice(&lba[0..3]);
+    fis[7] = DEVICE_LBA;
+    fis[8..11].copy_from_slice(&lba[3..6]);
+    fis[12..14].copy_from_slice(&count);
+    fis
+}
+
+/// Size and sector size from IDENTIFY DEVICE data
+///
+/// `None` for a disk without 48-bit addressing or with a sector size the
+/// bounce buffer cannot hold a whole number of.
+fn parse_identify(data: &[u8]) -> Option<Identity> {
+    let word = |n: usize| u16::from_le_bytes([data[n * 2], data[n * 2 + 1]]);
+    // Word 83 bit 10: 48-bit addressing
+    if word(83) & (1 << 10) == 0 {
+        return None;
+    }
+    let sectors = (0..4).fold(0u64, |sum, i| sum | (word(100 + i) as u64) << (16 * i));
+    // Word 106: valid (bits 15-14 = 01), logical sector longer than 256
+    // words (bit 12); words 117-118 give its length in words
+    let sector_info = word(106);
+    let sector_size = if sector_info & 0xC000 == 0x4000 && sector_info & (1 << 12) != 0 {
+        ((word(117) as usize) | (word(118) as usize) << 16) * 2
+    } else {
+        SECTOR_SIZE
+    };
+    if sectors == 0 || sector_size < SECTOR_SIZE || !sector_size.is_power_of_two() || sector_size > BOUNCE_SIZE {
+        return None;
+    }
+    Some(Identity { sectors, sector_size })
+}
+
+impl Port {
+    /// Stop the command list and FIS receive; false if the port hangs
+    fn stop(&self) -> bool {
+        let cmd = read(self.regs, PX_CMD);
+        write(self.regs, PX_CMD, cmd & !CMD_ST);
+        if !wait(STOP_POLLS, || read(self.regs, PX_CMD) & CMD_CR == 0) {
+            return false;
+        }
+        let cmd = read(self.regs, PX_CMD);
+        write(self.regs, PX_CMD, cmd & !CMD_FRE);
+        wait(STOP_POLLS, || read(self.regs, PX_CMD) & CMD_FR == 0)
+    }
+
+    /// Clear the port's errors and start it once the disk is not busy
+    fn start(&self) -> bool {
+        write(self.regs, PX_SERR, u32::MAX);
+        write(self.regs, PX_IS, u32::MAX);
+        let cmd = read(self.regs, PX_CMD);
+        write(self.regs, PX_CMD, cmd | CMD_FRE);
+        if !wait(READY_POLLS, || read(self.regs, PX_TFD) & (TFD_BSY | TFD_DRQ) == 0) {
+            return false;
+        }
+        let cmd = read(self.regs, PX_CMD);
+        write(self.regs, PX_CMD, cmd | CMD_FRE | CMD_ST);
+        true
+    }
+
+    /// Run `command` in slot 0, moving `bytes` bytes of the bounce buffer
+    fn command(&mut self, command: u8, lba: u64, count: u16, bytes: usize, write_data: bool) -> DriverResult<()> {
+        let memory_phys = self.memory.phys_addr().as_u64();
+        let bounce_phys = self.bounce.phys_addr().as_u64();
+        let table_phys = memory_phys + COMMAND_TABLE as u64;
+        let memory = self.memory.as_slice();
+
+        let prds = if bytes > 0 { 1 } else { 0 };
+        let flags = (FIS_H2D_LEN / 4) as u32 | if write_data { HEADER_WRITE } else { 0 };
+        put32(memory, COMMAND_LIST, flags | prds << 16);
+        put32(memory, COMMAND_LIST + 4, 0);
+        put32(memory, COMMAND_LIST + 8, table_phys as u32);
+        put32(memory, COMMAND_LIST + 12, (table_phys >> 32) as u32);
+
+        memory[COMMAND_TABLE..PRDT + PRD_SIZE].fill(0);
+        memory[COMMAND_TABLE..COMMAND_TABLE + FIS_H2D_LEN].copy_from_slice(&h2d_fis(command, lba, count));
+        if bytes > 0 {
+            put32(memory, PRDT, bounce_phys as u32);
+            put32(memory, PRDT + 4, (bounce_phys >> 32) as u32);
+            put32(memory, PRDT + 12, bytes as u32 - 1);
+        }
+        // The controller reads the command from memory once CI is set
+        fence(Ordering::SeqCst);
+
+        write(self.regs, PX_IS, u32::MAX);
+        write(self.regs, PX_CI, 1);
+        let finished = wait(COMMAND_POLLS, || {
+            read(self.regs, PX_CI) & 1 == 0 || read(self.regs, PX_IS) & IS_TFES != 0
+        });
+        fence(Ordering::SeqCst);
+
+        if finished && read(self.regs, PX_IS) & IS_TFES == 0 && read(self.regs, PX_TFD) & TFD_ERR == 0 {
+            return Ok(());
+        }
+        // A failed command leaves the port stopped until it is restarted
+        if !(self.stop() && self.start()) {
+            crate::log_warn!("AHCI", "port at {} did not restart", self.regs.base());
+        }
+        Err(DriverError::IoError)
+    }
+}
+
+impl Disk {
+    /// Check a request of `len` bytes from `lba`; returns the sector size
+    fn check(&self, lba: u64, len: usize) -> DriverResult<usize> {
+        let identity = self.identity.get().ok_or(DriverError::IoError)?;
+        let sectors = (len / identity.sector_size) as u64;
+        if len % identity.sector_size != 0 || lba.checked_add(sectors).map_or(true, |end| end > identity.sectors) {
+            return Err(DriverError::InvalidArgument);
+        }
+        Ok(identity.sector_size)
+    }
+}
+
+impl BlockDevice for Disk {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.identity.get().map_or(SECTOR_SIZE, |identity| identity.sector_size)
+    }
+
+    fn block_count(&self) -> u64 {
+        self.identity.get().map_or(0, |identity| identity.sectors)
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> DriverResult<()> {
+        let sector_size = self.check(lba, buf.len())?;
+        let mut port = self.port.lock();
+        let port = port.as_mut().ok_or(DriverError::IoError)?;
+        for (i, chunk) in buf.chunks_mut(BOUNCE_SIZE).enumerate() {
+            let lba = lba + (i * BOUNCE_SIZE / sector_size) as u64;
+            let count = (chunk.len() / sector_size) as u16;
+            port.command(ATA_READ_DMA_EXT, lba, count, chunk.len(), false)?;
+            chunk.copy_from_slice(&port.bounce.as_slice()[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> DriverResult<()> {
+        let sector_size = self.check(lba, buf.len())?;
+        let mut port = self.port.lock();
+        let port = port.as_mut().ok_or(DriverError::IoError)?;
+        for (i, chunk) in buf.chunks(BOUNCE_SIZE).enumerate() {
+            let lba = lba + (i * BOUNCE_SIZE / sector_size) as u64;
+            let count = (chunk.len() / sector_size) as u16;
+            port.bounce.as_slice()[..chunk.len()].copy_from_slice(chunk);
+            port.command(ATA_WRITE_DMA_EXT, lba, count, chunk.len(), true)?;
+        }
+        port.command(ATA_FLUSH_CACHE_EXT, 0, 0, 0, false)
+    }
+}
+
+/// Give a port its memory, start it and identify its disk
+fn setup_port(driver: DriverHandle, regs: MmioRegion, cap: u32) -> DriverResult<(Port, Identity)> {
+    if read(regs, PX_SSTS) & SSTS_DET_MASK != SSTS_DET_PRESENT || read(regs, PX_SIG) != SIG_ATA {
+        return Err(DriverError::InvalidArgument);
+    }
+    // Without 64-bit addressing the HBA only reaches the first 4 GiB
+    let alloc = if cap & CAP_S64A == 0 { dma::dma_alloc32 } else { dma::dma_alloc };
+    let memory = alloc(driver, 4096, 4096)?;
+    let bounce = match alloc(driver, BOUNCE_SIZE, 4096) {
+        Ok(bounce) => bounce,
+        Err(e) => {
+            dma::dma_free(driver, memory);
+            return Err(e);
+        }
+    };
+    let mut port = Port { regs, memory, bounce };
+    let result = if !port.stop() {
+        Err(DriverError::IoError)
+    } else {
+        let list = (port.memory.phys_addr() + COMMAND_LIST).as_u64();
+        let fis = (port.memory.phys_addr() + RECEIVED_FIS).as_u64();
+        write(regs, PX_CLB, list as u32);
+        write(regs, PX_CLBU, (list >> 32) as u32);
+        write(regs, PX_FB, fis as u32);
+        write(regs, PX_FBU, (fis >> 32) as u32);
+        write(regs, PX_IE, 0);
+        if port.start() {
+            port.command(ATA_IDENTIFY, 0, 0, SECTOR_SIZE, false)
+                .and_then(|()| parse_identify(&port.bounce.as_slice()[..SECTOR_SIZE]).ok_or(DriverError::InvalidArgument))
+        } else {
+            Err(DriverError::IoError)
+        }
+    };
+    match result {
+        Ok(identity) => Ok((port, identity)),
+        Err(e) => {
+            port.stop();
+            dma::dma_free(driver, port.memory);
+            dma::dma_free(driver, port.bounce);
+            Err(e)
+        }
+    }
+}
+
+/// Reset a controller and publish the disks on its ports
+fn probe(driver: DriverHandle, controller: &PciDevice, next_disk: &mut usize) {
+    let Some(abar) = controller.memory_region(ABAR, "ahci", ABAR_SIZE) else {
+        return;
+    };
+    controller.enable_memory_and_dma();
+
+    write(abar, HBA_GHC, GHC_AE);
+    write(abar, HBA_GHC, GHC_AE | GHC_HR);
+    if !wait(RESET_POLLS, || read(abar, HBA_GHC) & GHC_HR == 0) {
+        crate::log_warn!("AHCI", "{:02x}:{:02x}.{}: reset timed out", controller.bus, controller.device, controller.function);
+        return;
+    }
+    // The reset cleared AE; interrupts stay off, completion is polled
+    write(abar, HBA_GHC, GHC_AE);
+    write(abar, HBA_IS, u32::MAX);
+    let cap = read(abar, HBA_CAP);
+    let implemented = read(abar, HBA_PI);
+
+    for number in (0..32).filter(|n| implemented & (1 << n) != 0) {
+        let regs = abar.subregion(PORTS_BASE + number * PORT_SIZE, PORT_SIZE);
+        // Spin the disk up and wait for the link the reset renegotiates
+        let cmd = read(regs, PX_CMD);
+        write(regs, PX_CMD, cmd | CMD_POD | if cap & CAP_SSS != 0 { CMD_SUD } else { 0 });
+        wait(LINK_POLLS, || read(regs, PX_SSTS) & SSTS_DET_MASK == SSTS_DET_PRESENT);
+
+        let Some(disk) = DISKS.get(*next_disk) else {
+            return;
+        };
+        match setup_port(driver, regs, cap) {
+            Ok((port, identity)) => {
+                disk.identity.call_once(|| identity);
+                *disk.port.lock() = Some(port);
+                if let Err(e) = block::register_block_device(driver, disk) {
+                    crate::log_warn!("AHCI", "port {}: {:?}", number, e);
+                    return;
+                }
+                *next_disk += 1;
+            }
+            Err(DriverError::InvalidArgument) => {}
+            Err(e) => crate::log_warn!("AHCI", "port {}: {:?}", number, e),
+        }
+    }
+}
+
+/// Find AHCI controllers and publish their disks
+pub fn init() {
+    let mut controllers = [None; MAX_CONTROLLERS];
+    let mut found = 0;
+    pci::scan(|device| {
+        if (device.class, device.subclass, device.prog_if) == (CLASS_STORAGE, SUBCLASS_SATA, PROG_IF_AHCI)
+            && found < MAX_CONTROLLERS
+        {
+            controllers[found] = Some(device);
+            found += 1;
+        }
+    });
+    if found == 0 {
+        return;
+    }
+    let driver = match api::register_driver(&DRIVER) {
+        Ok(driver) => driver,
+        Err(e) => {
+            crate::serial_println!("[AHCI] Cannot register the driver: {:?}", e);
+            return;
+        }
+    };
+    let mut disks = 0;
+    for controller in controllers.iter().flatten() {
+        probe(driver, controller, &mut disks);
+    }
+    crate::serial_println!("[AHCI] {} controller(s), {} disk(s)", found, disks);
+}
+crate::initcall!(Drivers, init);
+
+crate::kernel_test! {
+    /// Commands carry the 48-bit LBA and count where the device expects
+    /// them, and IDENTIFY data gives the disk size
+    fn ahci_fis_and_identify() {
+        let fis = h2d_fis(ATA_READ_DMA_EXT, 0x0605_0403_0201, 0x0807);
+        crate::ktest_assert_eq!(&fis[..4], &[FIS_TYPE_H2D, FIS_COMMAND, ATA_READ_DMA_EXT, 0], "FIS header");
+        crate::ktest_assert_eq!(&fis[4..8], &[0x01, 0x02, 0x03, DEVICE_LBA], "low LBA");
+        crate::ktest_assert_eq!(&fis[8..11], &[0x04, 0x05, 0x06], "high LBA");
+        crate::ktest_assert_eq!(&fis[12..14], &[0x07, 0x08], "count");
+
+        let mut data = [0u8; SECTOR_SIZE];
+        crate::ktest_assert_eq!(parse_identify(&data), None, "disk without LBA48 accepted");
+        data[83 * 2 + 1] = 1 << 2;
+        data[100 * 2..100 * 2 + 2].copy_from_slice(&0x1000u16.to_le_bytes());
+        data[101 * 2..101 * 2 + 2].copy_from_slice(&0x2u16.to_le_bytes());
+        crate::ktest_assert_eq!(
+            parse_identify(&data),
+            Some(Identity { sectors: 0x2_1000, sector_size: SECTOR_SIZE }),
+            "512-byte sectors"
+        );
+        data[106 * 2..106 * 2 + 2].copy_from_slice(&0x5000u16.to_le_bytes());
+        data[117 * 2..117 * 2 + 2].copy_from_slice(&2048u16.to_le_bytes());
+        crate::ktest_assert_eq!(parse_identify(&data).map(|i| i.sector_size), Some(4096), "4K sectors");
+        Ok(())
+    }
+}
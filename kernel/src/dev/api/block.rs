@@ -0,0 +1,90 @@
+//! Block device registration
+
+use super::{DriverError, DriverHandle, DriverResult};
+use spin::Mutex;
+
+/// A device that reads and writes fixed-size blocks
+pub trait BlockDevice: Sync {
+    /// Device name (e.g. "vda")
+    fn name(&self) -> &'static str;
+
+    /// Size of one block in bytes
+    fn block_size(&self) -> usize;
+
+    /// Total number of blocks
+    fn block_count(&self) -> u64;
+
+    /// Read whole blocks starting at `lba` into `buf`
+    ///
+    /// `buf.len()` must be a multiple of `block_size()`.
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> DriverResult<()>;
+
+    /// Write whole blocks starting at `lba` from `buf`
+    ///
+    /// `buf.len()` must be a multiple of `block_size()`.
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> DriverResult<()>;
+}
+
+/// Maximum number of registered block devices
+const MAX_BLOCK_DEVICES: usize = 8;
+
+/// Registered block devices
+static BLOCK_DEVICES: Mutex<[Option<&'static dyn BlockDevice>; MAX_BLOCK_DEVICES]> =
+    Mutex::new([None; MAX_BLOCK_DEVICES]);
+
+/// Publish a block device
+pub fn register_block_device(
+    driver: DriverHandle,
+    device: &'static dyn BlockDevice,
+) -> DriverResult<()> {
+    let mut devices = BLOCK_DEVICES.lock();
+
+    if devices.iter().flatten().any(|d| d.name() == device.name()) {
+        return Err(DriverError::AlreadyRegistered);
+    }
+
+    let slot = devices
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(DriverError::TooManyDevices)?;
+    *slot = Some(device);
+
+    crate::log_info!(
+        "DRIVER",
+        "{}: block device {} ({} x {} bytes)",
+        driver.name(),
+        device.name(),
+        device.block_count(),
+        device.block_size()
+    );
+    Ok(())
+}
+
+/// Read whole blocks from `device`, charging the I/O to the current task
+///
+/// Kernel code reading on behalf of a process should use this instead of
+/// calling `read_blocks` directly, so the blocks show up in its rusage.
+pub fn read(device: &dyn BlockDevice, lba: u64, buf: &mut [u8]) -> DriverResult<()> {
+    device.read_blocks(lba, buf)?;
+    let blocks = (buf.len() / device.block_size()) as u64;
+    crate::sched::charge_current(|usage| usage.record_block_io(true, blocks));
+    Ok(())
+}
+
+/// Write whole blocks to `device`, charging the I/O to the current task
+pub fn write(device: &dyn BlockDevice, lba: u64, buf: &[u8]) -> DriverResult<()> {
+    device.write_blocks(lba, buf)?;
+    let blocks = (buf.len() / device.block_size()) as u64;
+    crate::sched::charge_current(|usage| usage.record_block_io(false, blocks));
+    Ok(())
+}
+
+/// Look up a block device by name
+pub fn find_block_device(name: &str) -> Option<&'static dyn BlockDevice> {
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .flatten()
+        .find(|d| d.name() == name)
+        .copied()
+}
@@ -0,0 +1,73 @@
+//! Driver DMA buffers
+//!
+//! Physically contiguous, zeroed memory that a device can address directly.
+//! The CPU view goes through the HHDM, so buffers are writable but never
+//! executable. Devices that can only address 32 bits use [`dma_alloc32`].
+
+use super::{DriverError, DriverHandle, DriverResult};
+use crate::mm::pmm::Zone;
+use crate::mm::{PhysAddr, VirtAddr};
+
+/// A physically contiguous DMA buffer
+#[derive(Debug)]
+pub struct DmaBuffer {
+    phys: PhysAddr,
+    virt: VirtAddr,
+    size: usize,
+}
+
+impl DmaBuffer {
+    /// Physical (bus) address to program into the device
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// Size of the buffer in bytes (a whole number of pages)
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// CPU pointer to the start of the buffer
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.virt.as_mut_ptr::<u8>()
+    }
+
+    /// CPU view of the buffer
+    pub fn as_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_ptr(), self.size) }
+    }
+}
+
+/// Allocate a zeroed DMA buffer of at least `size` bytes
+///
+/// `align` is the required physical alignment in bytes (a power of two,
+/// at least the page size).
+pub fn dma_alloc(_driver: DriverHandle, size: usize, align: usize) -> DriverResult<DmaBuffer> {
+    alloc(size, align, Zone::Normal).or_else(|_| alloc(size, align, Zone::Dma32))
+}
+
+/// Allocate a zeroed DMA buffer below 4 GiB
+///
+/// Like [`dma_alloc`], for devices limited to 32-bit addresses.
+pub fn dma_alloc32(_driver: DriverHandle, size: usize, align: usize) -> DriverResult<DmaBuffer> {
+    alloc(size, align, Zone::Dma32)
+}
+
+fn alloc(size: usize, align: usize, zone: Zone) -> DriverResult<DmaBuffer> {
+    if size == 0 || !align.is_power_of_two() {
+        return Err(DriverError::InvalidArgument);
+    }
+    let (virt, phys) = crate::mm::dma::alloc(size, align, zone).map_err(|_| DriverError::OutOfMemory)?;
+    Ok(DmaBuffer {
+        phys,
+        virt,
+        size: size.next_multiple_of(crate::mm::pmm::FRAME_SIZE),
+    })
+}
+
+/// Return a DMA buffer to the kernel
+///
+/// The device must no longer be accessing the buffer.
+pub fn dma_free(_driver: DriverHandle, buffer: DmaBuffer) {
+    crate::mm::dma::free(buffer.phys, buffer.size);
+}
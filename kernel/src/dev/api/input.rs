@@ -0,0 +1,47 @@
+//! Input device registration
+//!
+//! An input driver reports what one hardware report changed with
+//! [`report`], then publishes it with [`sync`]; both can be called from its
+//! interrupt handler. Readers get the events from `/dev/input/eventN` (see
+//! `dev::input`).
+
+use super::{DriverError, DriverHandle, DriverResult};
+use crate::dev::input::{self, InputError};
+
+pub use crate::dev::input::{
+    BTN_LEFT, BTN_MIDDLE, BTN_RIGHT, EV_KEY, EV_REL, REL_WHEEL, REL_X, REL_Y,
+};
+
+/// A registered input device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputDevice {
+    index: usize,
+}
+
+impl InputDevice {
+    /// N of the device's `/dev/input/eventN`
+    pub fn number(&self) -> usize {
+        self.index
+    }
+}
+
+/// Publish an input device called `name`
+pub fn register_input_device(driver: DriverHandle, name: &'static str) -> DriverResult<InputDevice> {
+    let index = input::register(name).map_err(|e| match e {
+        InputError::TooManyDevices => DriverError::TooManyDevices,
+        _ => DriverError::InvalidArgument,
+    })?;
+    crate::log_info!("DRIVER", "{}: input device {} (/dev/input/event{})", driver.name(), name, index);
+    Ok(InputDevice { index })
+}
+
+/// Add an event (`EV_KEY` or `EV_REL` with its code and value) to the
+/// device's current report
+pub fn report(device: InputDevice, kind: u16, code: u16, value: i32) {
+    input::report(device.index, kind, code, value);
+}
+
+/// Publish the current report of the device
+pub fn sync(device: InputDevice) {
+    input::sync(device.index);
+}
@@ -0,0 +1,247 @@
+//! Driver IRQ lines
+//!
+//! Drivers get one of `IRQ_LINES` interrupt vectors starting at
+//! `IRQ_VECTOR_BASE`. Each vector has a small assembly stub that saves the
+//! caller-saved registers and calls the registered handler, then signals EOI
+//! to the local APIC. Routing the device's interrupt to the vector returned
+//! by [`request_irq`] is up to the driver: MSI is programmed in the device,
+//! legacy INTx pins go through [`route_pci_intx`], ISA interrupts through
+//! [`route_isa_irq`], and the ACPI SCI through [`route_sci`].
+
+use super::{DriverError, DriverHandle, DriverResult};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub use crate::arch::x86_64::acpi::prt::PciPin;
+
+/// First interrupt vector handed out to drivers
+pub const IRQ_VECTOR_BASE: u8 = 0x40;
+
+/// Number of driver IRQ lines
+pub const IRQ_LINES: usize = 16;
+
+/// Driver interrupt handler, called with the IRQ line that fired
+pub type IrqHandler = fn(line: u8);
+
+/// Registered handlers as raw function pointers (0 = free line)
+static HANDLERS: [AtomicUsize; IRQ_LINES] = [const { AtomicUsize::new(0) }; IRQ_LINES];
+
+/// Claim a free IRQ line and install `handler` for it
+///
+/// Returns the line number; the CPU vector is `IRQ_VECTOR_BASE + line`
+/// (see [`vector_for`]).
+pub fn request_irq(driver: DriverHandle, handler: IrqHandler) -> DriverResult<u8> {
+    let raw = handler as *const () as usize;
+
+    let line = HANDLERS
+        .iter()
+        .position(|slot| {
+            slot.compare_exchange(0, raw, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        })
+        .ok_or(DriverError::IrqUnavailable)?;
+
+    crate::log_info!(
+        "DRIVER",
+        "{} got IRQ line {} (vector 0x{:x})",
+        driver.name(),
+        line,
+        vector_for(line as u8)
+    );
+    Ok(line as u8)
+}
+
+/// Release an IRQ line obtained from [`request_irq`]
+pub fn free_irq(_driver: DriverHandle, line: u8) -> DriverResult<()> {
+    let slot = HANDLERS
+        .get(line as usize)
+        .ok_or(DriverError::InvalidArgument)?;
+    slot.store(0, Ordering::Release);
+    Ok(())
+}
+
+/// Route INTx pin `pin` of root bus device `device` to IRQ `line`
+///
+/// The pin's global system interrupt comes from the firmware's `_PRT`;
+/// PCI INTx is level-triggered and active-low. The interrupt is delivered
+/// to the calling CPU. For a device behind a PCI-to-PCI bridge pass the
+/// bridge's device number and the pin from
+/// `arch::x86_64::acpi::prt::swizzle`. Fails with `NotRouted` if the
+/// firmware describes no hard-wired route for the pin; use MSI then.
+///
+/// Returns the global system interrupt.
+pub fn route_pci_intx(driver: DriverHandle, line: u8, device: u8, pin: PciPin) -> DriverResult<u32> {
+    use crate::arch::x86_64::apic::ioapic::{self, Polarity, Trigger};
+
+    if line as usize >= IRQ_LINES {
+        return Err(DriverError::InvalidArgument);
+    }
+    let gsi = crate::arch::x86_64::acpi::prt::lookup(device, pin).ok_or(DriverError::NotRouted)?;
+    let madt = crate::arch::x86_64::acpi::get_madt_info().ok_or(DriverError::NotRouted)?;
+    let apic_id = unsafe { crate::arch::x86_64::apic::LocalApic::new(madt.lapic_address).id() };
+
+    ioapic::route_gsi(gsi, vector_for(line), Trigger::Level, Polarity::ActiveLow, apic_id)
+        .map_err(|_| DriverError::NotRouted)?;
+
+    crate::log_info!(
+        "DRIVER",
+        "{}: PCI device {} {:?} -> GSI {} -> IRQ line {}",
+        driver.name(),
+        device,
+        pin,
+        gsi,
+        line
+    );
+    Ok(gsi)
+}
+
+/// Route ISA interrupt `irq` (e.g. 12 for the PS/2 mouse) to IRQ `line`
+///
+/// The GSI, trigger mode and polarity follow the firmware's interrupt
+/// source overrides. The interrupt is delivered to the calling CPU.
+///
+/// Returns the global system interrupt.
+pub fn route_isa_irq(driver: DriverHandle, line: u8, irq: u8) -> DriverResult<u32> {
+    use crate::arch::x86_64::apic::ioapic;
+
+    if line as usize >= IRQ_LINES || irq >= 16 {
+        return Err(DriverError::InvalidArgument);
+    }
+    let (gsi, trigger, polarity) = ioapic::isa_route(irq);
+    let madt = crate::arch::x86_64::acpi::get_madt_info().ok_or(DriverError::NotRouted)?;
+    let apic_id = unsafe { crate::arch::x86_64::apic::LocalApic::new(madt.lapic_address).id() };
+
+    ioapic::route_gsi(gsi, vector_for(line), trigger, polarity, apic_id).map_err(|_| DriverError::NotRouted)?;
+
+    crate::log_info!("DRIVER", "{}: ISA IRQ {} -> GSI {} -> IRQ line {}", driver.name(), irq, gsi, line);
+    Ok(gsi)
+}
+
+/// Route the ACPI SCI, on ISA interrupt `irq` (the FADT's `SCI_INT`), to
+/// IRQ `line`
+///
+/// Like [`route_isa_irq`], except that the SCI defaults to level-triggered
+/// and active-low (see `ioapic::sci_route`).
+///
+/// Returns the global system interrupt.
+pub fn route_sci(driver: DriverHandle, line: u8, irq: u8) -> DriverResult<u32> {
+    use crate::arch::x86_64::apic::ioapic;
+
+    if line as usize >= IRQ_LINES || irq >= 16 {
+        return Err(DriverError::InvalidArgument);
+    }
+    let (gsi, trigger, polarity) = ioapic::sci_route(irq);
+    let madt = crate::arch::x86_64::acpi::get_madt_info().ok_or(DriverError::NotRouted)?;
+    let apic_id = unsafe { crate::arch::x86_64::apic::LocalApic::new(madt.lapic_address).id() };
+
+    ioapic::route_gsi(gsi, vector_for(line), trigger, polarity, apic_id).map_err(|_| DriverError::NotRouted)?;
+
+    crate::log_info!("DRIVER", "{}: SCI on ISA IRQ {} -> GSI {} -> IRQ line {}", driver.name(), irq, gsi, line);
+    Ok(gsi)
+}
+
+/// CPU interrupt vector for an IRQ line
+pub const fn vector_for(line: u8) -> u8 {
+    IRQ_VECTOR_BASE + line
+}
+
+/// Common dispatch path for all driver IRQ stubs
+extern "C" fn irq_dispatch(line: u64) {
+    crate::sync::lockdep::irq_enter();
+    crate::trace!(irq_entry, IRQ_VECTOR_BASE as u64 + line);
+    crate::rand::add_interrupt_timing(IRQ_VECTOR_BASE as u64 + line);
+
+    let raw = HANDLERS[line as usize].load(Ordering::Acquire);
+    if raw != 0 {
+        let handler: IrqHandler = unsafe { core::mem::transmute(raw) };
+        handler(line as u8);
+    } else {
+        crate::arch::x86_64::spurious::note_unexpected(vector_for(line as u8));
+    }
+
+    // Send EOI to Local APIC
+    unsafe {
+        use crate::arch::x86_64::acpi::get_madt_info;
+        use crate::arch::x86_64::apic::LocalApic;
+
+        if let Some(madt_info) = get_madt_info() {
+            let mut lapic = LocalApic::new(madt_info.lapic_address);
+            lapic.eoi();
+        }
+    }
+    crate::sync::lockdep::irq_exit();
+}
+
+/// Generate the entry stub for one IRQ line
+macro_rules! irq_stub {
+    ($name:ident, $line:expr) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            core::arch::naked_asm!(
+                $crate::swapgs_if_user!(8),
+                "push rax",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "mov edi, {line}",
+                "call {dispatch}",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rax",
+                $crate::swapgs_if_user!(8),
+                "iretq",
+                line = const $line,
+                dispatch = sym irq_dispatch,
+            )
+        }
+    };
+}
+
+irq_stub!(irq_stub_0, 0);
+irq_stub!(irq_stub_1, 1);
+irq_stub!(irq_stub_2, 2);
+irq_stub!(irq_stub_3, 3);
+irq_stub!(irq_stub_4, 4);
+irq_stub!(irq_stub_5, 5);
+irq_stub!(irq_stub_6, 6);
+irq_stub!(irq_stub_7, 7);
+irq_stub!(irq_stub_8, 8);
+irq_stub!(irq_stub_9, 9);
+irq_stub!(irq_stub_10, 10);
+irq_stub!(irq_stub_11, 11);
+irq_stub!(irq_stub_12, 12);
+irq_stub!(irq_stub_13, 13);
+irq_stub!(irq_stub_14, 14);
+irq_stub!(irq_stub_15, 15);
+
+/// Install the driver IRQ stubs in the IDT
+///
+/// # Safety
+/// Must be called once after init_idt(), before interrupts are enabled.
+pub unsafe fn init() {
+    let stubs: [extern "C" fn(); IRQ_LINES] = [
+        irq_stub_0, irq_stub_1, irq_stub_2, irq_stub_3, irq_stub_4, irq_stub_5, irq_stub_6,
+        irq_stub_7, irq_stub_8, irq_stub_9, irq_stub_10, irq_stub_11, irq_stub_12, irq_stub_13,
+        irq_stub_14, irq_stub_15,
+    ];
+
+    for (line, stub) in stubs.iter().enumerate() {
+        crate::sched::timer::set_idt_gate(vector_for(line as u8), *stub as *const () as usize, 0);
+    }
+
+    crate::serial_println!(
+        "[DRIVER] IRQ lines 0x{:x}-0x{:x} ready",
+        IRQ_VECTOR_BASE,
+        IRQ_VECTOR_BASE + IRQ_LINES as u8 - 1
+    );
+}
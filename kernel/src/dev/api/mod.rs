@@ -0,0 +1,233 @@
+//! Stable Driver API
+//!
+//! This module is the only kernel surface drivers are supposed to use:
+//! logging, IRQ lines, DMA buffers, timers, and block/net/input device
+//! registration. Internals behind it are free to change; the API itself is
+//! versioned with `DRIVER_API_VERSION`.
+//!
+//! A driver declares the version it was written against with
+//! [`driver_info!`](crate::driver_info), which captures the constant at
+//! compile time, and calls [`register_driver`] before anything else. The
+//! returned [`DriverHandle`] is required by every registration function, so
+//! a driver built against an incompatible API version cannot get hold of
+//! IRQs or publish devices.
+//!
+//! # Versioning rules
+//! - Bump `minor` when adding functions, trait methods with defaults, or
+//!   enum variants drivers never match on.
+//! - Bump `major` (and reset `minor`) for any change that breaks existing
+//!   drivers: removed or renamed items, changed signatures or semantics.
+
+#![allow(dead_code)]
+
+pub mod block;
+pub mod dma;
+pub mod input;
+pub mod irq;
+pub mod net;
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+pub use crate::log::LogLevel;
+
+/// Driver API version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiVersion {
+    /// Incremented on incompatible changes
+    pub major: u16,
+    /// Incremented on backwards-compatible additions
+    pub minor: u16,
+}
+
+impl ApiVersion {
+    /// Check whether a driver built against `self` can run on a kernel
+    /// providing `kernel`
+    ///
+    /// Majors must match, and the kernel must provide at least the minor
+    /// version the driver was built against.
+    pub const fn is_compatible_with(&self, kernel: ApiVersion) -> bool {
+        self.major == kernel.major && self.minor <= kernel.minor
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Version of the driver API provided by this kernel
+pub const DRIVER_API_VERSION: ApiVersion = ApiVersion { major: 1, minor: 6 };
+
+/// Driver API error types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriverError {
+    /// Driver was built against an incompatible API version
+    VersionMismatch,
+    /// Driver table is full
+    TooManyDrivers,
+    /// A device or driver with the same name is already registered
+    AlreadyRegistered,
+    /// IRQ line is out of range or already taken
+    IrqUnavailable,
+    /// Device table is full
+    TooManyDevices,
+    /// Out of memory
+    OutOfMemory,
+    /// Invalid argument
+    InvalidArgument,
+    /// Device reported an I/O error
+    IoError,
+    /// Firmware describes no interrupt route for the device
+    NotRouted,
+}
+
+/// Result type for driver API operations
+pub type DriverResult<T> = Result<T, DriverError>;
+
+/// Static description of a driver
+///
+/// Build it with [`driver_info!`](crate::driver_info) so `api_version` is
+/// always the version the driver was compiled against.
+#[derive(Debug)]
+pub struct DriverInfo {
+    /// Driver name, used in logs and as the registration key
+    pub name: &'static str,
+    /// API version the driver was built against
+    pub api_version: ApiVersion,
+}
+
+/// Declare a driver against the current driver API version
+///
+/// ```rust,ignore
+/// static VIRTIO_BLK: DriverInfo = driver_info!("virtio-blk");
+/// let handle = dev::api::register_driver(&VIRTIO_BLK)?;
+/// ```
+#[macro_export]
+macro_rules! driver_info {
+    ($name:expr) => {
+        $crate::dev::api::DriverInfo {
+            name: $name,
+            api_version: $crate::dev::api::DRIVER_API_VERSION,
+        }
+    };
+}
+
+/// Proof that a driver passed the version check in [`register_driver`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DriverHandle {
+    id: usize,
+}
+
+impl DriverHandle {
+    /// Name of the registered driver
+    pub fn name(&self) -> &'static str {
+        DRIVERS.lock()[self.id].map(|info| info.name).unwrap_or("?")
+    }
+}
+
+/// Maximum number of registered drivers
+const MAX_DRIVERS: usize = 32;
+
+/// Registered drivers
+static DRIVERS: Mutex<[Option<&'static DriverInfo>; MAX_DRIVERS]> = Mutex::new([None; MAX_DRIVERS]);
+
+/// Number of registered drivers
+static DRIVER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Register a driver with the kernel
+///
+/// Fails with `VersionMismatch` if the driver was built against an API
+/// version this kernel cannot serve.
+pub fn register_driver(info: &'static DriverInfo) -> DriverResult<DriverHandle> {
+    if !info.api_version.is_compatible_with(DRIVER_API_VERSION) {
+        crate::log_error!(
+            "DRIVER",
+            "{}: built against driver API {}, kernel provides {}",
+            info.name,
+            info.api_version,
+            DRIVER_API_VERSION
+        );
+        return Err(DriverError::VersionMismatch);
+    }
+
+    let mut drivers = DRIVERS.lock();
+
+    if drivers.iter().flatten().any(|d| d.name == info.name) {
+        return Err(DriverError::AlreadyRegistered);
+    }
+
+    let id = drivers
+        .iter()
+        .position(|slot| slot.is_none())
+        .ok_or(DriverError::TooManyDrivers)?;
+    drivers[id] = Some(info);
+    DRIVER_COUNT.fetch_add(1, Ordering::Relaxed);
+
+    crate::log_info!("DRIVER", "{} registered (API {})", info.name, info.api_version);
+    Ok(DriverHandle { id })
+}
+
+/// Number of registered drivers
+pub fn driver_count() -> usize {
+    DRIVER_COUNT.load(Ordering::Relaxed)
+}
+
+/// Log a message on behalf of a driver
+///
+/// Uses the kernel's structured log format with the driver name as the
+/// subsystem tag.
+pub fn log(driver: DriverHandle, level: LogLevel, args: fmt::Arguments) {
+    crate::log::_log(level, driver.name(), args);
+}
+
+/// Timer tick frequency in Hz
+pub const TICK_HZ: u64 = crate::config::SCHED_HZ;
+
+/// Number of timer ticks since boot
+pub fn ticks() -> u64 {
+    crate::sched::timer::get_tick_count() as u64
+}
+
+/// Milliseconds since boot, at tick resolution
+pub fn uptime_ms() -> u64 {
+    crate::time::Instant::now().since_boot().as_millis()
+}
+
+crate::kernel_test! {
+    /// Drivers built against an incompatible API version are rejected
+    fn driver_api_version_check() {
+        static CURRENT: DriverInfo = crate::driver_info!("ktest-driver");
+        static FUTURE: DriverInfo = DriverInfo {
+            name: "ktest-future-driver",
+            api_version: ApiVersion {
+                major: DRIVER_API_VERSION.major,
+                minor: DRIVER_API_VERSION.minor + 1,
+            },
+        };
+        static OLD_MAJOR: DriverInfo = DriverInfo {
+            name: "ktest-old-driver",
+            api_version: ApiVersion { major: 0, minor: 9 },
+        };
+
+        crate::ktest_assert!(register_driver(&CURRENT).is_ok(), "current driver rejected");
+        crate::ktest_assert_eq!(
+            register_driver(&CURRENT),
+            Err(DriverError::AlreadyRegistered),
+            "duplicate driver accepted"
+        );
+        crate::ktest_assert_eq!(
+            register_driver(&FUTURE),
+            Err(DriverError::VersionMismatch),
+            "newer minor accepted"
+        );
+        crate::ktest_assert_eq!(
+            register_driver(&OLD_MAJOR),
+            Err(DriverError::VersionMismatch),
+            "other major accepted"
+        );
+        Ok(())
+    }
+}
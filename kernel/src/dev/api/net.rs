@@ -0,0 +1,120 @@
+//! Network device registration
+
+use super::{DriverError, DriverHandle, DriverResult};
+use spin::Mutex;
+
+/// Counters a driver keeps for its device
+///
+/// Packet counts are kept by the network stack itself (`/proc/net/dev`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetStats {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Frames received but thrown away (too long for the buffer)
+    pub rx_dropped: u64,
+    /// Frames not sent for lack of room in the transmit queue
+    pub tx_dropped: u64,
+}
+
+/// A device that sends and receives Ethernet frames
+pub trait NetDevice: Sync {
+    /// Interface name (e.g. "eth0")
+    fn name(&self) -> &'static str;
+
+    /// Hardware (MAC) address
+    fn mac_address(&self) -> [u8; 6];
+
+    /// Maximum transmission unit in bytes
+    fn mtu(&self) -> usize;
+
+    /// Queue one frame for transmission
+    fn transmit(&self, frame: &[u8]) -> DriverResult<()>;
+
+    /// Copy the next received frame into `buf`
+    ///
+    /// Returns the frame length, or None if nothing is pending.
+    fn receive(&self, buf: &mut [u8]) -> Option<usize>;
+
+    /// Unmask (`true`) or mask the receive interrupt
+    ///
+    /// Returns false if the device has no receive interrupt; it is then
+    /// polled on a timer. Drivers with one mask it in their interrupt
+    /// handler and call `napi_schedule`; the network stack unmasks it once
+    /// the receive queue has been drained.
+    fn set_rx_interrupt(&self, _enabled: bool) -> bool {
+        false
+    }
+
+    /// Driver counters; all zero for a driver that keeps none
+    fn stats(&self) -> NetStats {
+        NetStats::default()
+    }
+}
+
+/// Maximum number of registered network devices
+pub const MAX_NET_DEVICES: usize = 4;
+
+/// Registered network devices
+static NET_DEVICES: Mutex<[Option<&'static dyn NetDevice>; MAX_NET_DEVICES]> =
+    Mutex::new([None; MAX_NET_DEVICES]);
+
+/// Publish a network device
+pub fn register_net_device(driver: DriverHandle, device: &'static dyn NetDevice) -> DriverResult<()> {
+    let mut devices = NET_DEVICES.lock();
+
+    if devices.iter().flatten().any(|d| d.name() == device.name()) {
+        return Err(DriverError::AlreadyRegistered);
+    }
+
+    let slot = devices
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(DriverError::TooManyDevices)?;
+    *slot = Some(device);
+
+    let mac = device.mac_address();
+    crate::log_info!(
+        "DRIVER",
+        "{}: net device {} ({:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x})",
+        driver.name(),
+        device.name(),
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5]
+    );
+    Ok(())
+}
+
+/// Look up a network device by name
+pub fn find_net_device(name: &str) -> Option<&'static dyn NetDevice> {
+    NET_DEVICES
+        .lock()
+        .iter()
+        .flatten()
+        .find(|d| d.name() == name)
+        .copied()
+}
+
+/// All registered network devices, in registration order
+pub fn net_devices() -> [Option<&'static dyn NetDevice>; MAX_NET_DEVICES] {
+    *NET_DEVICES.lock()
+}
+
+/// Hand receive processing of `device` to the network stack
+///
+/// Called from the device's receive interrupt handler after masking the
+/// receive interrupt. Does nothing if the device is not registered or the
+/// device table is busy (the stack then picks the frames up on its next
+/// timed poll).
+pub fn napi_schedule(device: &dyn NetDevice) {
+    let index = match NET_DEVICES.try_lock() {
+        Some(devices) => devices.iter().position(|d| d.map_or(false, |d| d.name() == device.name())),
+        None => None,
+    };
+    if let Some(index) = index {
+        crate::net::softnet::schedule(index);
+    }
+}
@@ -0,0 +1,168 @@
+//! Framebuffer device (`/dev/fb0`)
+//!
+//! Lets a user process draw on the screen without a syscall per pixel:
+//! the `FBIOGET_MODE` ioctl describes the screen, and `mmap` of the device
+//! (`MAP_SHARED`, at a page-aligned offset) maps the screen memory itself
+//! into the process, write-through so drawing shows up without a flush.
+//! Pixels are `bpp` bits in lines of `pitch` bytes; at 32 bpp a pixel is
+//! `0x00RRGGBB`.
+//!
+//! While the device is open the framebuffer console stops drawing, so the
+//! two do not paint over each other; it redraws its text when the last
+//! handle is closed. A mapping kept after that is shared with the console
+//! again. Mappings are device memory: their pages are never freed, and
+//! they are removed when the process exits.
+
+use crate::mm::mmap::{self, MmapError, MAP_FIXED, MAP_SHARED, PROT_EXEC};
+use crate::mm::VirtAddr;
+use crate::sched::task::{MemoryRegionType, Task};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use limine::framebuffer::Framebuffer as LimineFramebuffer;
+use spin::Once;
+
+/// Get the screen mode (`FbMode`); the number of Linux's
+/// FBIOGET_VSCREENINFO, with a structure of our own
+pub const FBIOGET_MODE: usize = 0x4600;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Screen mode, as returned by `FBIOGET_MODE`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FbMode {
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per line
+    pub pitch: u32,
+    pub bpp: u32,
+    /// Bytes of screen memory, `pitch * height`
+    pub size: u64,
+}
+
+mello_abi::check_layout!(FbMode, mello_abi::FbMode { width, height, pitch, bpp, size });
+
+struct Screen {
+    mode: FbMode,
+    /// Screen memory in the higher-half direct map
+    address: VirtAddr,
+}
+
+static SCREEN: Once<Screen> = Once::new();
+
+/// Handles to the device
+static OPEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Register the screen the bootloader set up
+pub fn init(fb: &LimineFramebuffer) {
+    SCREEN.call_once(|| Screen {
+        mode: FbMode {
+            width: fb.width() as u32,
+            height: fb.height() as u32,
+            pitch: fb.pitch() as u32,
+            bpp: fb.bpp() as u32,
+            size: fb.pitch() * fb.height(),
+        },
+        address: VirtAddr::from_ptr(fb.addr()),
+    });
+}
+
+/// The screen mode, if there is a screen
+pub fn mode() -> Option<FbMode> {
+    SCREEN.get().map(|screen| screen.mode)
+}
+
+/// Open the device; false if there is no screen
+///
+/// The first handle takes the screen from the framebuffer console.
+pub fn open() -> bool {
+    if SCREEN.get().is_none() {
+        return false;
+    }
+    if OPEN.fetch_add(1, Ordering::AcqRel) == 0 {
+        crate::console::set_graphics(true);
+    }
+    true
+}
+
+/// Take another reference for a duplicated handle
+pub fn retain() {
+    OPEN.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Drop a handle; the last one gives the screen back to the console
+pub fn release() {
+    if OPEN.fetch_sub(1, Ordering::AcqRel) == 1 {
+        crate::console::set_graphics(false);
+    }
+}
+
+/// Map `len` bytes of screen memory from `offset` into `task`
+///
+/// `flags` must be `MAP_SHARED`, optionally with `MAP_FIXED`; the screen
+/// cannot be mapped executable.
+pub fn map(
+    task: &mut Task,
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    offset: usize,
+) -> Result<usize, MmapError> {
+    let screen = SCREEN.get().ok_or(MmapError::NotSupported)?;
+    if flags & !(MAP_SHARED | MAP_FIXED) != 0 || flags & MAP_SHARED == 0 || prot & PROT_EXEC != 0 {
+        return Err(MmapError::InvalidArgument);
+    }
+    let size = (screen.mode.size as usize).next_multiple_of(PAGE_SIZE);
+    let end = offset.checked_add(len).ok_or(MmapError::InvalidArgument)?;
+    if offset % PAGE_SIZE != 0 || len == 0 || end > size {
+        return Err(MmapError::InvalidArgument);
+    }
+    let phys = crate::mm::virt_to_phys(screen.address);
+    if !phys.is_aligned(PAGE_SIZE) {
+        return Err(MmapError::NotSupported);
+    }
+
+    mmap::map_region(task, addr, len, prot, flags & MAP_FIXED != 0, |base| MemoryRegionType::Device {
+        phys: phys + offset,
+        base,
+    })
+}
+
+/// Remove the device mappings of `task`
+pub fn task_exit(task: &mut Task) {
+    loop {
+        let range = task.memory_regions[..task.region_count]
+            .iter()
+            .flatten()
+            .find(|region| matches!(region.region_type, MemoryRegionType::Device { .. }))
+            .map(|region| (region.start, region.size()));
+        match range {
+            Some((start, len)) => {
+                if mmap::unmap(task, start, len).is_err() {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// The mode describes the screen memory, and the console gets the
+    /// screen back when the last handle goes
+    fn fb_mode_and_handles() {
+        let Some(mode) = mode() else {
+            return Ok(());
+        };
+        crate::ktest_assert_eq!(mode.size, mode.pitch as u64 * mode.height as u64, "screen size");
+        crate::ktest_assert!((mode.pitch as u64 * 8 >= mode.width as u64 * mode.bpp as u64), "pitch shorter than a line");
+
+        let before = OPEN.load(Ordering::Acquire);
+        crate::ktest_assert!(open(), "open failed with a screen");
+        retain();
+        release();
+        release();
+        crate::ktest_assert_eq!(OPEN.load(Ordering::Acquire), before, "handle count");
+        Ok(())
+    }
+}
@@ -0,0 +1,385 @@
+//! Input core (`/dev/input/eventN`)
+//!
+//! Input drivers register a device (through `dev::api::input`) and report
+//! typed [`InputEvent`]s: `EV_KEY` for keys and buttons, `EV_REL` for
+//! relative movement, with the codes and values Linux's evdev uses. The
+//! events of one hardware report are collected and published together,
+//! ended by an `EV_SYN`/`SYN_REPORT` event, so readers never see half of
+//! one.
+//!
+//! Device N is opened as `/dev/input/eventN`. Each device keeps its last
+//! `QUEUE_LEN` events in one queue, and each open (a client) has its own
+//! position in it, so every client sees every event. A client that falls
+//! more than `QUEUE_LEN` events behind skips to the newest and reads one
+//! `SYN_DROPPED` event first. Reads take whole events, and poll reports a
+//! client readable while it has events to read.
+//!
+//! `EVIOCGRAB` with a nonzero argument makes a client the only one the
+//! device's events go to until it ungrabs or closes; `EVIOCGNAME` returns
+//! the device's name.
+
+use crate::sync::{IrqSpinLock, WaitQueue};
+use spin::Once;
+
+/// Event types
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+
+/// `EV_SYN` codes: end of a report, and events lost before this one
+pub const SYN_REPORT: u16 = 0;
+pub const SYN_DROPPED: u16 = 3;
+
+/// `EV_REL` codes
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_WHEEL: u16 = 0x08;
+
+/// `EV_KEY` codes of mouse buttons; the value is 1 for pressed, 0 for
+/// released
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+
+/// Grab (nonzero argument) or release a device, Linux's `EVIOCGRAB`
+pub const EVIOCGRAB: usize = 0x4004_4590;
+
+/// `EVIOCGNAME(len)`: the device name into a `len`-byte buffer; the length
+/// is in bits 16-29
+const EVIOCGNAME: usize = 0x8000_4506;
+const IOC_SIZE_SHIFT: usize = 16;
+const IOC_SIZE_MASK: usize = 0x3FFF;
+
+/// Registered devices
+pub const MAX_DEVICES: usize = 8;
+
+/// Events kept per device
+const QUEUE_LEN: usize = 256;
+
+/// Events of one report, `SYN_REPORT` excluded
+const MAX_REPORT: usize = 16;
+
+/// Open handles to input devices, over all devices
+const MAX_CLIENTS: usize = 16;
+
+/// One input event, as read from `/dev/input/eventN`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Microseconds since boot
+    pub time_us: u64,
+    /// `EV_*`
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+mello_abi::check_layout!(InputEvent, mello_abi::InputEvent { time_us, kind, code, value });
+
+/// Bytes of an event record
+pub const EVENT_SIZE: usize = core::mem::size_of::<InputEvent>();
+
+/// Input core errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputError {
+    /// No device or client with this number
+    NoDevice,
+    /// The device or client table is full
+    TooManyDevices,
+    /// Another client has grabbed the device
+    Busy,
+    /// Releasing a grab the client does not hold
+    NotGrabbed,
+}
+
+const NO_EVENT: InputEvent = InputEvent {
+    time_us: 0,
+    kind: 0,
+    code: 0,
+    value: 0,
+};
+
+struct Device {
+    name: &'static str,
+    events: [InputEvent; QUEUE_LEN],
+    /// Events published so far; event `i` is at `events[i % QUEUE_LEN]`
+    published: u64,
+    /// Events of the report being collected
+    report: [InputEvent; MAX_REPORT],
+    report_len: usize,
+    /// Client that grabbed the device
+    grab: Option<usize>,
+}
+
+struct Client {
+    device: usize,
+    /// Handles to the client
+    refs: usize,
+    /// Next event to read
+    position: u64,
+}
+
+struct Input {
+    devices: [Option<Device>; MAX_DEVICES],
+    clients: [Option<Client>; MAX_CLIENTS],
+}
+
+impl Input {
+    fn device(&self, client: usize) -> Option<(&Client, &Device)> {
+        let client = self.clients.get(client)?.as_ref()?;
+        Some((client, self.devices[client.device].as_ref()?))
+    }
+
+    /// Whether `client` has events to read
+    fn readable(&self, client: usize) -> bool {
+        self.device(client).is_some_and(|(state, device)| {
+            device.grab.map_or(true, |grab| grab == client) && state.position < device.published
+        })
+    }
+}
+
+static INPUT: IrqSpinLock<Input> = IrqSpinLock::new(Input {
+    devices: [const { None }; MAX_DEVICES],
+    clients: [const { None }; MAX_CLIENTS],
+});
+
+/// Readers of all input devices, woken when any device publishes events
+pub static WAIT: WaitQueue = WaitQueue::new();
+
+/// The tick wakes readers a wake from an interrupt handler missed
+static READINESS: Once = Once::new();
+
+/// Register a device; returns its number N, for `/dev/input/eventN`
+pub fn register(name: &'static str) -> Result<usize, InputError> {
+    let mut input = INPUT.lock();
+    let index = input.devices.iter().position(Option::is_none).ok_or(InputError::TooManyDevices)?;
+    input.devices[index] = Some(Device {
+        name,
+        events: [NO_EVENT; QUEUE_LEN],
+        published: 0,
+        report: [NO_EVENT; MAX_REPORT],
+        report_len: 0,
+        grab: None,
+    });
+    drop(input);
+    READINESS.call_once(|| {
+        crate::sync::wait_queue::register_readiness(any_readable_irq, &WAIT);
+    });
+    Ok(index)
+}
+
+/// Add an event to the report of `device`; dropped if the report is full
+pub fn report(device: usize, kind: u16, code: u16, value: i32) {
+    let time_us = crate::time::Instant::now().since_boot().as_micros();
+    let mut input = INPUT.lock();
+    let Some(device) = input.devices.get_mut(device).and_then(Option::as_mut) else { return };
+    if device.report_len < MAX_REPORT {
+        device.report[device.report_len] = InputEvent { time_us, kind, code, value };
+        device.report_len += 1;
+    }
+}
+
+/// Publish the report of `device` with a `SYN_REPORT`, and wake readers
+///
+/// An empty report publishes nothing. Callable from interrupt handlers.
+pub fn sync(device: usize) {
+    let time_us = crate::time::Instant::now().since_boot().as_micros();
+    {
+        let mut input = INPUT.lock();
+        let Some(device) = input.devices.get_mut(device).and_then(Option::as_mut) else { return };
+        if device.report_len == 0 {
+            return;
+        }
+        let report = device.report;
+        let syn = InputEvent {
+            time_us,
+            kind: EV_SYN,
+            code: SYN_REPORT,
+            value: 0,
+        };
+        for event in report[..device.report_len].iter().chain([&syn]) {
+            device.events[(device.published % QUEUE_LEN as u64) as usize] = *event;
+            device.published += 1;
+        }
+        device.report_len = 0;
+    }
+    WAIT.try_wake_all();
+}
+
+/// Open device `device`; returns the new client
+pub fn open(device: usize) -> Result<u32, InputError> {
+    let mut input = INPUT.lock();
+    let published = input
+        .devices
+        .get(device)
+        .and_then(Option::as_ref)
+        .ok_or(InputError::NoDevice)?
+        .published;
+    let index = input.clients.iter().position(Option::is_none).ok_or(InputError::TooManyDevices)?;
+    input.clients[index] = Some(Client {
+        device,
+        refs: 1,
+        position: published,
+    });
+    Ok(index as u32)
+}
+
+/// Take another reference to `client` for a duplicated handle
+pub fn retain(client: u32) {
+    if let Some(client) = INPUT.lock().clients.get_mut(client as usize).and_then(Option::as_mut) {
+        client.refs += 1;
+    }
+}
+
+/// Drop a reference to `client`; the last one closes it and ends its grab
+pub fn release(client: u32) {
+    let mut input = INPUT.lock();
+    let Some(state) = input.clients.get_mut(client as usize).and_then(Option::as_mut) else { return };
+    state.refs -= 1;
+    if state.refs > 0 {
+        return;
+    }
+    let device = state.device;
+    input.clients[client as usize] = None;
+    if input.devices[device].as_ref().is_some_and(|device| device.grab == Some(client as usize)) {
+        ungrab(&mut input, device);
+    }
+}
+
+/// Whether `client` has events to read
+pub fn readable(client: u32) -> bool {
+    INPUT.lock().readable(client as usize)
+}
+
+/// Whether any client has events to read, from interrupt context; false
+/// while the input core is locked
+fn any_readable_irq() -> bool {
+    INPUT
+        .try_lock()
+        .is_some_and(|input| (0..MAX_CLIENTS).any(|client| input.readable(client)))
+}
+
+/// Move as many whole events of `client` as fit into `buffer`; returns the
+/// bytes written
+pub fn read(client: u32, buffer: &mut [u8]) -> Result<usize, InputError> {
+    let client = client as usize;
+    let mut input = INPUT.lock();
+    let Input { devices, clients } = &mut *input;
+    let state = clients.get_mut(client).and_then(Option::as_mut).ok_or(InputError::NoDevice)?;
+    let device = devices[state.device].as_ref().ok_or(InputError::NoDevice)?;
+    if device.grab.is_some_and(|grab| grab != client) {
+        return Ok(0);
+    }
+
+    let mut records = buffer.chunks_exact_mut(EVENT_SIZE);
+    let mut count = 0;
+    if device.published - state.position > QUEUE_LEN as u64 {
+        // Overrun: what is left would start in the middle of a report
+        let dropped = InputEvent {
+            time_us: crate::time::Instant::now().since_boot().as_micros(),
+            kind: EV_SYN,
+            code: SYN_DROPPED,
+            value: 0,
+        };
+        let Some(record) = records.next() else { return Ok(0) };
+        unsafe { (record.as_mut_ptr() as *mut InputEvent).write_unaligned(dropped) };
+        count += EVENT_SIZE;
+        state.position = device.published;
+    }
+    for record in records {
+        if state.position == device.published {
+            break;
+        }
+        let event = device.events[(state.position % QUEUE_LEN as u64) as usize];
+        unsafe { (record.as_mut_ptr() as *mut InputEvent).write_unaligned(event) };
+        state.position += 1;
+        count += EVENT_SIZE;
+    }
+    Ok(count)
+}
+
+/// Grab the device of `client` for it alone, or release the grab
+pub fn grab(client: u32, on: bool) -> Result<(), InputError> {
+    let client = client as usize;
+    let mut input = INPUT.lock();
+    let device = input.device(client).ok_or(InputError::NoDevice)?.0.device;
+    let grab = input.devices[device].as_ref().and_then(|device| device.grab);
+    match (on, grab) {
+        (true, None) => {
+            if let Some(device) = input.devices[device].as_mut() {
+                device.grab = Some(client);
+            }
+            Ok(())
+        }
+        (true, Some(holder)) if holder == client => Ok(()),
+        (true, Some(_)) => Err(InputError::Busy),
+        (false, Some(holder)) if holder == client => {
+            ungrab(&mut input, device);
+            Ok(())
+        }
+        (false, _) => Err(InputError::NotGrabbed),
+    }
+}
+
+/// End the grab of `device`; the other clients skip what it got meanwhile
+fn ungrab(input: &mut Input, device: usize) {
+    let Some(state) = input.devices[device].as_mut() else { return };
+    state.grab = None;
+    let published = state.published;
+    for client in input.clients.iter_mut().flatten().filter(|client| client.device == device) {
+        client.position = client.position.max(published);
+    }
+}
+
+/// Buffer length of an `EVIOCGNAME(len)` request, or None for other
+/// commands
+pub fn name_request(cmd: usize) -> Option<usize> {
+    (cmd & !(IOC_SIZE_MASK << IOC_SIZE_SHIFT) == EVIOCGNAME).then(|| (cmd >> IOC_SIZE_SHIFT) & IOC_SIZE_MASK)
+}
+
+/// Name of the device of `client`
+pub fn name(client: u32) -> Option<&'static str> {
+    INPUT.lock().device(client as usize).map(|(_, device)| device.name)
+}
+
+crate::kernel_test! {
+    /// Every client sees whole reports, a grab hides them from the other
+    /// clients, and an overrun is reported with SYN_DROPPED
+    fn input_clients_and_grab() {
+        let device = register("ktest-input").map_err(|_| "no device slot")?;
+        let (a, b) = (open(device).map_err(|_| "open")?, open(device).map_err(|_| "open")?);
+        let mut buffer = [0u8; 4 * EVENT_SIZE];
+        let event = |buffer: &[u8], i: usize| unsafe {
+            (buffer.as_ptr().add(i * EVENT_SIZE) as *const InputEvent).read_unaligned()
+        };
+
+        report(device, EV_REL, REL_X, 3);
+        crate::ktest_assert!((!readable(a)), "unsynced report visible");
+        sync(device);
+        for client in [a, b] {
+            crate::ktest_assert_eq!(read(client, &mut buffer), Ok(2 * EVENT_SIZE), "report size");
+            crate::ktest_assert_eq!((event(&buffer, 0).code, event(&buffer, 0).value), (REL_X, 3), "event");
+            crate::ktest_assert_eq!(event(&buffer, 1).kind, EV_SYN, "report not ended");
+        }
+
+        crate::ktest_assert_eq!(grab(a, true), Ok(()), "grab");
+        crate::ktest_assert_eq!(grab(b, true), Err(InputError::Busy), "second grab");
+        report(device, EV_KEY, BTN_LEFT, 1);
+        sync(device);
+        crate::ktest_assert!((!readable(b)), "grabbed events reach other clients");
+        release(a);
+        crate::ktest_assert!((!readable(b)), "events of the grab delivered after it");
+
+        for _ in 0..QUEUE_LEN {
+            report(device, EV_REL, REL_Y, 1);
+            sync(device);
+        }
+        crate::ktest_assert_eq!(read(b, &mut buffer), Ok(EVENT_SIZE), "overrun read");
+        crate::ktest_assert_eq!(event(&buffer, 0).code, SYN_DROPPED, "overrun not reported");
+        crate::ktest_assert!((!readable(b)), "events left after an overrun");
+
+        release(b);
+        INPUT.lock().devices[device] = None;
+        Ok(())
+    }
+}
@@ -2,4 +2,11 @@
 //!
 //! This module contains device driver implementations.
 
+pub mod ahci;
+pub mod api;
+pub mod fb;
+pub mod input;
+pub mod mouse;
+pub mod pci;
 pub mod pty;
+pub mod virtio;
@@ -0,0 +1,432 @@
+//! PS/2 mouse
+//!
+//! The mouse sits on the second port of the 8042 controller and interrupts
+//! on ISA IRQ 12. It is an input device (see `dev::input`): each packet it
+//! sends is reported as `REL_X`, `REL_Y` and `REL_WHEEL` movement and
+//! `BTN_*` changes, `REL_Y` positive down the screen and `REL_WHEEL`
+//! positive away from the user, as on Linux. A wheel is switched on with
+//! the IntelliMouse knock (sample rates 200, 100, 80), after which a mouse
+//! that has one reports ID 3 and sends four-byte packets instead of three.
+//!
+//! The interrupt handler only moves bytes from the controller into a
+//! lock-free queue and schedules [`drain`] on the system work queue, which
+//! decodes and reports them from a worker task, outside the handler and
+//! with interrupts enabled.
+//!
+//! There is no keyboard driver (input comes over serial), so the first
+//! port is switched off: a key byte nobody reads would hold up the mouse's.
+//!
+//! With `mousecursor` on the command line the framebuffer console draws an
+//! arrow that follows the mouse, over whatever it shows; [`drain`] moves
+//! it (see `console::draw_mouse_cursor`). It needs the console's back buffer, and is not
+//! drawn while a process has `/dev/fb0` open.
+
+use crate::dev::api::input::{
+    self, InputDevice, BTN_LEFT, BTN_MIDDLE, BTN_RIGHT, EV_KEY, EV_REL, REL_WHEEL, REL_X, REL_Y,
+};
+use crate::dev::api::{self, irq, DriverInfo};
+use crate::framebuffer::Surface;
+use crate::sched::workqueue::{self, Work};
+use crate::sync::{IrqSpinLock, MpscQueue};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::port::Port;
+
+/// 8042 data port, and status (read) or command (write) port
+const DATA: u16 = 0x60;
+const COMMAND: u16 = 0x64;
+
+/// Status bits: a byte to read, the controller busy with the last write,
+/// and the byte to read came from the mouse
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+const STATUS_AUX: u8 = 1 << 5;
+
+/// Controller commands
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_AUX: u8 = 0xA7;
+const CMD_ENABLE_AUX: u8 = 0xA8;
+const CMD_DISABLE_KEYBOARD: u8 = 0xAD;
+const CMD_WRITE_AUX: u8 = 0xD4;
+
+/// Configuration byte bits
+const CONFIG_KEYBOARD_IRQ: u8 = 1 << 0;
+const CONFIG_AUX_IRQ: u8 = 1 << 1;
+const CONFIG_KEYBOARD_CLOCK_OFF: u8 = 1 << 4;
+const CONFIG_AUX_CLOCK_OFF: u8 = 1 << 5;
+
+/// Mouse commands and its acknowledgment
+const MOUSE_SET_RATE: u8 = 0xF3;
+const MOUSE_GET_ID: u8 = 0xF2;
+const MOUSE_ENABLE: u8 = 0xF4;
+const MOUSE_SET_DEFAULTS: u8 = 0xF6;
+const MOUSE_ACK: u8 = 0xFA;
+
+/// Device ID of a mouse with a wheel
+const INTELLIMOUSE_ID: u8 = 3;
+
+/// Status reads before giving up on the controller; each takes about a
+/// microsecond
+const MAX_POLLS: usize = 100_000;
+
+/// First packet byte: buttons, a bit that is always set, the sign bits of
+/// the movement and its overflow bits
+const PACKET_BUTTONS: u8 = 0x07;
+const PACKET_SYNC: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_OVERFLOW: u8 = 0xC0;
+
+/// Input codes of the buttons, in packet bit order
+const BUTTONS: [u16; 3] = [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE];
+
+/// Movement and buttons of one packet, in mouse counts; `dy` is positive
+/// down the screen and `wheel` away from the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Packet {
+    dx: i32,
+    dy: i32,
+    wheel: i32,
+    /// Bit 0 left, 1 right, 2 middle
+    buttons: u8,
+}
+
+/// Assembles packets from the bytes the mouse sends
+struct Decoder {
+    bytes: [u8; 4],
+    len: usize,
+    /// Packet size: 3, or 4 with a wheel
+    size: usize,
+}
+
+impl Decoder {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; 4],
+            len: 0,
+            size: 3,
+        }
+    }
+
+    /// Take the next byte; returns the packet once it is complete
+    ///
+    /// A first byte without the sync bit is dropped, so a lost byte costs
+    /// at most one packet. Packets whose movement overflowed are dropped
+    /// too.
+    fn feed(&mut self, byte: u8) -> Option<Packet> {
+        if self.len == 0 && byte & PACKET_SYNC == 0 {
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.size {
+            return None;
+        }
+        self.len = 0;
+
+        let [flags, x, y, z] = self.bytes;
+        if flags & PACKET_OVERFLOW != 0 {
+            return None;
+        }
+        // Nine-bit two's complement, the sign bits in the first byte
+        let extend = |value: u8, sign: u8| value as i32 - if flags & sign != 0 { 256 } else { 0 };
+        // The wheel is a four-bit signed count, positive towards the user
+        let wheel = if self.size == 4 { -(((z << 4) as i8 >> 4) as i32) } else { 0 };
+        Some(Packet {
+            dx: extend(x, PACKET_X_SIGN),
+            // The mouse counts up the screen
+            dy: -extend(y, PACKET_Y_SIGN),
+            wheel,
+            buttons: flags & PACKET_BUTTONS,
+        })
+    }
+}
+
+struct Mouse {
+    decoder: Decoder,
+    /// Buttons held down at the last packet
+    buttons: u8,
+    device: Option<InputDevice>,
+}
+
+static MOUSE: IrqSpinLock<Mouse> = IrqSpinLock::new(Mouse {
+    decoder: Decoder::new(),
+    buttons: 0,
+    device: None,
+});
+
+/// Bytes from the mouse that [`drain`] has not decoded yet
+static BYTES: MpscQueue<u8, 256> = MpscQueue::new();
+
+static DRAIN: Work = Work::new(drain);
+
+/// `BYTES` overflows already reported
+static BYTES_REPORTED: AtomicU64 = AtomicU64::new(0);
+
+/// Cursor position on the screen, and whether it moved since the console
+/// last drew it
+static CURSOR_ENABLED: AtomicBool = AtomicBool::new(false);
+static CURSOR_X: AtomicUsize = AtomicUsize::new(0);
+static CURSOR_Y: AtomicUsize = AtomicUsize::new(0);
+static CURSOR_MOVED: AtomicBool = AtomicBool::new(false);
+
+static DRIVER: DriverInfo = crate::driver_info!("ps2-mouse");
+
+/// Cursor arrow: `#` outline, `o` fill
+const CURSOR_ART: [&[u8; CURSOR_WIDTH]; CURSOR_HEIGHT] = [
+    b"#...........",
+    b"##..........",
+    b"#o#.........",
+    b"#oo#........",
+    b"#ooo#.......",
+    b"#oooo#......",
+    b"#ooooo#.....",
+    b"#oooooo#....",
+    b"#ooooooo#...",
+    b"#oooooooo#..",
+    b"#ooooooooo#.",
+    b"#oooooo#####",
+    b"#ooo#oo#....",
+    b"#oo#.#oo#...",
+    b"#o#..#oo#...",
+    b"##....#oo#..",
+    b"#.....#oo#..",
+    b"......#oo#..",
+    b".......##...",
+];
+const CURSOR_WIDTH: usize = 12;
+const CURSOR_HEIGHT: usize = 19;
+
+const fn cursor_pixels() -> [u32; CURSOR_WIDTH * CURSOR_HEIGHT] {
+    let mut pixels = [0; CURSOR_WIDTH * CURSOR_HEIGHT];
+    let mut i = 0;
+    while i < pixels.len() {
+        pixels[i] = match CURSOR_ART[i / CURSOR_WIDTH][i % CURSOR_WIDTH] {
+            b'#' => 0xFF00_0000,
+            b'o' => 0xFFFF_FFFF,
+            _ => 0,
+        };
+        i += 1;
+    }
+    pixels
+}
+
+static CURSOR_PIXELS: [u32; CURSOR_WIDTH * CURSOR_HEIGHT] = cursor_pixels();
+
+/// The cursor sprite; its hot spot is the top left pixel
+pub static CURSOR_SPRITE: Surface<'static> = Surface {
+    width: CURSOR_WIDTH,
+    height: CURSOR_HEIGHT,
+    pixels: &CURSOR_PIXELS,
+};
+
+fn status() -> u8 {
+    unsafe { Port::<u8>::new(COMMAND).read() }
+}
+
+/// Wait until the controller takes a byte; false on timeout
+fn wait_write() -> bool {
+    (0..MAX_POLLS).any(|_| status() & STATUS_INPUT_FULL == 0)
+}
+
+/// Wait for a byte from the controller or a device
+fn read_byte() -> Option<u8> {
+    (0..MAX_POLLS)
+        .any(|_| status() & STATUS_OUTPUT_FULL != 0)
+        .then(|| unsafe { Port::<u8>::new(DATA).read() })
+}
+
+fn command(cmd: u8) -> Option<()> {
+    wait_write().then(|| unsafe { Port::<u8>::new(COMMAND).write(cmd) })
+}
+
+fn write_data(byte: u8) -> Option<()> {
+    wait_write().then(|| unsafe { Port::<u8>::new(DATA).write(byte) })
+}
+
+/// Send `byte` to the mouse and wait for its acknowledgment
+fn mouse_write(byte: u8) -> Option<()> {
+    command(CMD_WRITE_AUX)?;
+    write_data(byte)?;
+    (read_byte()? == MOUSE_ACK).then_some(())
+}
+
+/// Set the mouse up; returns its packet size
+fn setup() -> Option<usize> {
+    command(CMD_DISABLE_KEYBOARD)?;
+    command(CMD_DISABLE_AUX)?;
+    // Drop whatever was waiting
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        unsafe { Port::<u8>::new(DATA).read() };
+    }
+
+    command(CMD_READ_CONFIG)?;
+    let config = read_byte()?;
+    let config = (config | CONFIG_AUX_IRQ | CONFIG_KEYBOARD_CLOCK_OFF) & !(CONFIG_KEYBOARD_IRQ | CONFIG_AUX_CLOCK_OFF);
+    command(CMD_WRITE_CONFIG)?;
+    write_data(config)?;
+    command(CMD_ENABLE_AUX)?;
+
+    mouse_write(MOUSE_SET_DEFAULTS)?;
+    for rate in [200, 100, 80] {
+        mouse_write(MOUSE_SET_RATE)?;
+        mouse_write(rate)?;
+    }
+    mouse_write(MOUSE_GET_ID)?;
+    let size = if read_byte()? == INTELLIMOUSE_ID { 4 } else { 3 };
+    // The knock left the rate at 80 a second; 100 is the default
+    mouse_write(MOUSE_SET_RATE)?;
+    mouse_write(100)?;
+    mouse_write(MOUSE_ENABLE)?;
+    Some(size)
+}
+
+/// Find and set up the mouse, and route its interrupt
+pub fn init() {
+    if !crate::arch::x86_64::acpi::platform_info().map_or(true, |platform| platform.has_8042()) {
+        crate::serial_println!("[MOUSE] No 8042 controller");
+        return;
+    }
+    let Some(size) = setup() else {
+        crate::serial_println!("[MOUSE] No PS/2 mouse");
+        return;
+    };
+    MOUSE.lock().decoder.size = size;
+
+    let name = if size == 4 { "PS/2 wheel mouse" } else { "PS/2 mouse" };
+    let routed = api::register_driver(&DRIVER).and_then(|driver| {
+        MOUSE.lock().device = Some(input::register_input_device(driver, name)?);
+        let line = irq::request_irq(driver, interrupt)?;
+        irq::route_isa_irq(driver, line, 12).inspect_err(|_| {
+            let _ = irq::free_irq(driver, line);
+        })
+    });
+    if let Err(e) = routed {
+        crate::serial_println!("[MOUSE] Cannot set up the mouse: {:?}", e);
+        return;
+    }
+    // A byte that came before the route was set up raised no interrupt,
+    // and would keep the edge-triggered line from raising any more
+    interrupt(0);
+    if crate::cmdline::has_flag("mousecursor") {
+        if let Some(mode) = crate::dev::fb::mode() {
+            CURSOR_X.store(mode.width as usize / 2, Ordering::Relaxed);
+            CURSOR_Y.store(mode.height as usize / 2, Ordering::Relaxed);
+            CURSOR_MOVED.store(true, Ordering::Relaxed);
+            CURSOR_ENABLED.store(true, Ordering::Release);
+        }
+    }
+    crate::serial_println!("[MOUSE] {}", name);
+}
+crate::initcall!(Drivers, init);
+
+/// IRQ 12: queue the mouse bytes the controller has
+fn interrupt(_line: u8) {
+    let mut data = Port::<u8>::new(DATA);
+    loop {
+        let status = status();
+        if status & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        let byte = unsafe { data.read() };
+        if status & STATUS_AUX != 0 {
+            BYTES.push(byte);
+        }
+    }
+    if !BYTES.is_empty() {
+        workqueue::schedule(&DRAIN);
+    }
+}
+
+/// Decode the queued bytes, report finished packets and move the cursor
+///
+/// A dropped byte costs the packet it was part of (see `Decoder::feed`).
+fn drain() {
+    let mut mouse = MOUSE.lock();
+    while let Some(byte) = BYTES.pop() {
+        if let Some(packet) = mouse.decoder.feed(byte) {
+            move_cursor(&packet);
+            let changed = mouse.buttons ^ packet.buttons;
+            mouse.buttons = packet.buttons;
+            if let Some(device) = mouse.device {
+                report(device, &packet, changed);
+            }
+        }
+    }
+    drop(mouse);
+    if cursor_moved() {
+        crate::console::draw_mouse_cursor();
+    }
+    let dropped = BYTES.overflows();
+    if dropped != BYTES_REPORTED.swap(dropped, Ordering::Relaxed) {
+        crate::log_warn!("MOUSE", "Byte queue overflowed, {} bytes dropped", dropped);
+    }
+}
+
+/// Report what changed with `packet`, `changed` the buttons it changed
+fn report(device: InputDevice, packet: &Packet, changed: u8) {
+    for (code, value) in [(REL_X, packet.dx), (REL_Y, packet.dy), (REL_WHEEL, packet.wheel)] {
+        if value != 0 {
+            input::report(device, EV_REL, code, value);
+        }
+    }
+    for (bit, &code) in BUTTONS.iter().enumerate() {
+        if changed & (1 << bit) != 0 {
+            input::report(device, EV_KEY, code, ((packet.buttons >> bit) & 1) as i32);
+        }
+    }
+    input::sync(device);
+}
+
+/// Move the cursor by a packet's movement, within the screen
+fn move_cursor(packet: &Packet) {
+    if !CURSOR_ENABLED.load(Ordering::Acquire) || (packet.dx == 0 && packet.dy == 0) {
+        return;
+    }
+    let Some(mode) = crate::dev::fb::mode() else { return };
+    let step = |position: &AtomicUsize, delta: i32, size: u32| {
+        let moved = position.load(Ordering::Relaxed) as i64 + delta as i64;
+        position.store(moved.clamp(0, size as i64 - 1) as usize, Ordering::Relaxed);
+    };
+    step(&CURSOR_X, packet.dx, mode.width);
+    step(&CURSOR_Y, packet.dy, mode.height);
+    CURSOR_MOVED.store(true, Ordering::Release);
+}
+
+/// Whether the cursor moved since [`take_cursor`] last returned it
+pub fn cursor_moved() -> bool {
+    CURSOR_MOVED.load(Ordering::Acquire)
+}
+
+/// The cursor position, if it moved since the last call
+pub fn take_cursor() -> Option<(usize, usize)> {
+    CURSOR_MOVED
+        .swap(false, Ordering::AcqRel)
+        .then(|| (CURSOR_X.load(Ordering::Relaxed), CURSOR_Y.load(Ordering::Relaxed)))
+}
+
+crate::kernel_test! {
+    /// Three- and four-byte packets decode to movement down-positive and
+    /// wheel away-positive; a byte without the sync bit is skipped
+    fn mouse_packet_decoding() {
+        let mut decoder = Decoder::new();
+        crate::ktest_assert_eq!(decoder.feed(0x00), None, "unsynced byte accepted");
+        crate::ktest_assert_eq!(decoder.feed(0x19), None, "packet done early");
+        decoder.feed(0xFE);
+        let packet = decoder.feed(0x05).ok_or("no packet")?;
+        crate::ktest_assert_eq!((packet.dx, packet.dy, packet.buttons), (-2, -5, 1), "three-byte packet");
+
+        decoder.size = 4;
+        for byte in [0x28, 0x03, 0xFF] {
+            crate::ktest_assert_eq!(decoder.feed(byte), None, "packet done early");
+        }
+        let packet = decoder.feed(0x0F).ok_or("no packet")?;
+        crate::ktest_assert_eq!((packet.dx, packet.dy, packet.wheel, packet.buttons), (3, 1, 1, 0), "wheel packet");
+
+        for byte in [0x48, 0x10, 0x10] {
+            decoder.feed(byte);
+        }
+        crate::ktest_assert_eq!(decoder.feed(0), None, "overflowed packet kept");
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+//! PCI configuration space
+//!
+//! Devices are found through the legacy configuration mechanism (ports
+//! `0xCF8`/`0xCFC`), which every x86 chipset still decodes; ECAM through
+//! the ACPI MCFG table is not used yet. That reaches the first 256 bytes of
+//! each function's configuration space, enough to identify devices and to
+//! turn on a driver's BAR decoding and bus mastering.
+
+use crate::io::mmio::MmioRegion;
+use crate::io::{inl, outl};
+use crate::mm::addr::{self, PhysAddr};
+use crate::sync::SpinLock;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// Configuration space offsets
+const VENDOR_ID: u8 = 0x00;
+const COMMAND: u8 = 0x04;
+const CLASS_REVISION: u8 = 0x08;
+const HEADER_TYPE: u8 = 0x0C;
+const BAR0: u8 = 0x10;
+const INTERRUPT: u8 = 0x3C;
+
+/// Command register bits: decode I/O and memory BARs, master DMA
+const COMMAND_IO: u32 = 1 << 0;
+const COMMAND_MEMORY: u32 = 1 << 1;
+const COMMAND_BUS_MASTER: u32 = 1 << 2;
+
+/// Flag bits at the bottom of a memory BAR, and the bit marking an I/O BAR
+const BAR_FLAGS: u32 = 0xF;
+const BAR_IO: u32 = 1 << 0;
+
+/// Header type bit of a device with more than one function
+const MULTI_FUNCTION: u32 = 1 << 23;
+
+/// Vendor ID read for a function that does not exist
+const NO_DEVICE: u16 = 0xFFFF;
+
+/// The address and data ports are one register pair for the whole machine
+static CONFIG: SpinLock<()> = SpinLock::new(());
+
+/// A PCI function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+}
+
+/// Read the 32-bit register at `offset` (a multiple of 4) of a function
+pub fn config_read32(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    let address = 1 << 31
+        | (bus as u32) << 16
+        | (device as u32 & 0x1F) << 11
+        | (function as u32 & 0x7) << 8
+        | (offset as u32 & 0xFC);
+    let _guard = CONFIG.lock();
+    unsafe {
+        outl(CONFIG_ADDRESS, address);
+        inl(CONFIG_DATA)
+    }
+}
+
+/// Write the 32-bit register at `offset` (a multiple of 4) of a function
+pub fn config_write32(bus: u8, device: u8, function: u8, offset: u8, value: u32) {
+    let address = 1 << 31
+        | (bus as u32) << 16
+        | (device as u32 & 0x1F) << 11
+        | (function as u32 & 0x7) << 8
+        | (offset as u32 & 0xFC);
+    let _guard = CONFIG.lock();
+    unsafe {
+        outl(CONFIG_ADDRESS, address);
+        outl(CONFIG_DATA, value);
+    }
+}
+
+impl PciDevice {
+    /// Physical address of 32-bit memory BAR `index` (0-5)
+    pub fn memory_bar(&self, index: u8) -> u32 {
+        config_read32(self.bus, self.device, self.function, BAR0 + index * 4) & !BAR_FLAGS
+    }
+
+    /// Size of the window 32-bit memory BAR `index` decodes
+    ///
+    /// Sized by writing all ones to the BAR, so call it while the
+    /// function's memory decoding is off.
+    fn memory_bar_size(&self, index: u8) -> u32 {
+        let offset = BAR0 + index * 4;
+        let saved = config_read32(self.bus, self.device, self.function, offset);
+        config_write32(self.bus, self.device, self.function, offset, u32::MAX);
+        let mask = config_read32(self.bus, self.device, self.function, offset) & !BAR_FLAGS;
+        config_write32(self.bus, self.device, self.function, offset, saved);
+        (!mask).wrapping_add(1)
+    }
+
+    /// The first `len` bytes of registers behind 32-bit memory BAR `index`,
+    /// for the device `name`; `None` if the BAR is unset
+    ///
+    /// Call before [`enable_memory_and_dma`](Self::enable_memory_and_dma),
+    /// as the BAR is sized.
+    pub fn memory_region(&self, index: u8, name: &'static str, len: usize) -> Option<MmioRegion> {
+        let bar = self.memory_bar(index);
+        if bar == 0 {
+            return None;
+        }
+        let base = PhysAddr::new(bar as u64);
+        if cfg!(debug_assertions) {
+            addr::add_mmio_window("pci", base, self.memory_bar_size(index) as u64);
+        }
+        // The BAR is where the function decodes its registers
+        Some(unsafe { MmioRegion::new(name, base, len) })
+    }
+
+    /// Port of I/O BAR `index` (0-5); `None` if it is a memory BAR
+    pub fn io_bar(&self, index: u8) -> Option<u16> {
+        let bar = config_read32(self.bus, self.device, self.function, BAR0 + index * 4);
+        (bar & BAR_IO != 0).then_some((bar & !0x3) as u16)
+    }
+
+    /// Legacy interrupt pin, 1 (INTA#) to 4 (INTD#); 0 if the function
+    /// has none
+    pub fn interrupt_pin(&self) -> u8 {
+        (config_read32(self.bus, self.device, self.function, INTERRUPT) >> 8) as u8
+    }
+
+    /// Let the function decode its memory BARs and master DMA
+    pub fn enable_memory_and_dma(&self) {
+        self.enable(COMMAND_MEMORY | COMMAND_BUS_MASTER);
+    }
+
+    /// Let the function decode its I/O BARs and master DMA
+    pub fn enable_io_and_dma(&self) {
+        self.enable(COMMAND_IO | COMMAND_BUS_MASTER);
+    }
+
+    fn enable(&self, bits: u32) {
+        // The status half is write-one-to-clear; writing zeros leaves it
+        let command = config_read32(self.bus, self.device, self.function, COMMAND) & 0xFFFF;
+        config_write32(
+            self.bus,
+            self.device,
+            self.function,
+            COMMAND,
+            command | bits,
+        );
+    }
+}
+
+/// The function at `bus:device.function`, if there is one
+fn probe(bus: u8, device: u8, function: u8) -> Option<PciDevice> {
+    let ids = config_read32(bus, device, function, VENDOR_ID);
+    if ids as u16 == NO_DEVICE {
+        return None;
+    }
+    let class = config_read32(bus, device, function, CLASS_REVISION);
+    Some(PciDevice {
+        bus,
+        device,
+        function,
+        vendor_id: ids as u16,
+        device_id: (ids >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+        revision: class as u8,
+    })
+}
+
+/// Call `f` for every function on every bus, in bus, device and function
+/// order
+pub fn scan(mut f: impl FnMut(PciDevice)) {
+    for bus in 0..=255u8 {
+        for device in 0..32 {
+            let Some(first) = probe(bus, device, 0) else { continue };
+            f(first);
+            if config_read32(bus, device, 0, HEADER_TYPE) & MULTI_FUNCTION == 0 {
+                continue;
+            }
+            for function in 1..8 {
+                if let Some(found) = probe(bus, device, function) {
+                    f(found);
+                }
+            }
+        }
+    }
+}
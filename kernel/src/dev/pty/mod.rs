@@ -85,6 +85,8 @@ pub struct Termios {
     pub c_cc: [u8; 32],
 }
 
+mello_abi::check_layout!(Termios, mello_abi::Termios { c_iflag, c_oflag, c_cflag, c_lflag, c_cc });
+
 impl Termios {
     /// Create a new Termios with default settings
     ///
@@ -133,6 +135,8 @@ pub struct Winsize {
     pub ws_ypixel: u16,
 }
 
+mello_abi::check_layout!(Winsize, mello_abi::Winsize { ws_row, ws_col, ws_xpixel, ws_ypixel });
+
 impl Winsize {
     /// Create a new Winsize with default dimensions (24x80)
     pub const fn default() -> Self {
@@ -357,6 +361,9 @@ pub struct PtyPair {
     pub slave: PtySlave,
     /// Whether this pair is allocated
     pub allocated: bool,
+    /// Handles referring to the master; the pair is freed when the last
+    /// one is closed
+    pub master_refs: usize,
 }
 
 impl PtyPair {
@@ -367,12 +374,14 @@ impl PtyPair {
             master: PtyMaster::new(),
             slave: PtySlave::new(),
             allocated: false,
+            master_refs: 0,
         }
     }
 
     /// Allocate this PTY pair
     pub fn allocate(&mut self) {
         self.allocated = true;
+        self.master_refs = 1;
         self.master.slave_open = false;
         self.slave.session = None;
         self.slave.foreground_pgid = None;
@@ -563,12 +572,13 @@ fn is_foreground_process(pair: &PtyPair) -> bool {
 
 /// Initialize the PTY subsystem
 ///
-/// Must be called once during kernel initialization.
+/// Runs as an `Early` init call.
 pub fn init() {
     let mut table = PTY_TABLE.lock();
     table.init();
     crate::serial_println!("[PTY] Initialized PTY subsystem with {} pairs", MAX_PTY_PAIRS);
 }
+crate::initcall!(Early, init);
 
 /// Allocate a new PTY pair
 ///
@@ -607,6 +617,29 @@ pub fn deallocate_pty(number: PtyNumber) -> bool {
     result
 }
 
+/// Take another reference to the master of PTY `number` (a duplicated or
+/// inherited handle)
+pub fn retain_master(number: PtyNumber) {
+    if let Some(pair) = PTY_TABLE.lock().get_pty_mut(number) {
+        pair.master_refs += 1;
+    }
+}
+
+/// Drop a reference to the master of PTY `number`, deallocating the pair
+/// with the last one
+pub fn release_master(number: PtyNumber) {
+    let last = match PTY_TABLE.lock().get_pty_mut(number) {
+        Some(pair) => {
+            pair.master_refs = pair.master_refs.saturating_sub(1);
+            pair.master_refs == 0
+        }
+        None => false,
+    };
+    if last {
+        deallocate_pty(number);
+    }
+}
+
 /// Get the slave number for a PTY (for TIOCGPTN ioctl)
 ///
 /// Returns the PTY number if it's allocated, or None otherwise.
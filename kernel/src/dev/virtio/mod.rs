@@ -0,0 +1,295 @@
+//! Virtio devices over the legacy PCI transport
+//!
+//! QEMU's virtio devices are transitional by default: besides the virtio
+//! 1.0 capabilities they decode the legacy register block in I/O BAR 0,
+//! which is all a driver needs. Queues use the legacy split layout: the
+//! descriptor table and available ring, then the used ring on the next
+//! page boundary, in one physically contiguous buffer whose page number is
+//! written to the device.
+//!
+//! Device drivers live in submodules:
+//! - `net`: network cards
+
+pub mod net;
+
+use crate::dev::api::dma::{self, DmaBuffer};
+use crate::dev::api::{DriverError, DriverHandle, DriverResult};
+use crate::dev::pci::PciDevice;
+use crate::io::{inb, inl, inw, outb, outl, outw};
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+/// PCI vendor of virtio devices
+pub const VENDOR: u16 = 0x1AF4;
+
+/// Legacy register block
+const DEVICE_FEATURES: u16 = 0x00;
+const DRIVER_FEATURES: u16 = 0x04;
+const QUEUE_ADDRESS: u16 = 0x08;
+const QUEUE_SIZE: u16 = 0x0C;
+const QUEUE_SELECT: u16 = 0x0E;
+const QUEUE_NOTIFY: u16 = 0x10;
+const DEVICE_STATUS: u16 = 0x12;
+const ISR_STATUS: u16 = 0x13;
+/// Device-specific configuration, without MSI-X
+const DEVICE_CONFIG: u16 = 0x14;
+
+/// Device status bits
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FAILED: u8 = 128;
+
+/// ISR status: a queue has used buffers
+pub const ISR_QUEUE: u8 = 1 << 0;
+
+/// Descriptor flags: the chain continues at `next`, the device writes the
+/// buffer
+pub const DESC_NEXT: u16 = 1;
+pub const DESC_WRITE: u16 = 2;
+
+/// Available ring flag: no interrupt when buffers are used
+const AVAIL_NO_INTERRUPT: u16 = 1;
+
+/// Legacy queue alignment
+const QUEUE_ALIGN: usize = 4096;
+
+const DESC_SIZE: usize = 16;
+
+/// Legacy register block of one device
+#[derive(Debug, Clone, Copy)]
+pub struct Transport {
+    base: u16,
+}
+
+impl Transport {
+    /// The register block of `device`, with I/O decoding and bus mastering
+    /// turned on
+    pub fn new(device: &PciDevice) -> Option<Self> {
+        let base = device.io_bar(0).filter(|&base| base != 0)?;
+        device.enable_io_and_dma();
+        Some(Self { base })
+    }
+
+    /// Reset the device; it forgets its queues and features
+    pub fn reset(&self) {
+        unsafe { outb(self.base + DEVICE_STATUS, 0) };
+    }
+
+    /// Set status bits on top of those already set
+    pub fn add_status(&self, bits: u8) {
+        unsafe {
+            let status = inb(self.base + DEVICE_STATUS);
+            outb(self.base + DEVICE_STATUS, status | bits);
+        }
+    }
+
+    pub fn device_features(&self) -> u32 {
+        unsafe { inl(self.base + DEVICE_FEATURES) }
+    }
+
+    pub fn set_driver_features(&self, features: u32) {
+        unsafe { outl(self.base + DRIVER_FEATURES, features) };
+    }
+
+    /// Read and acknowledge the interrupt status (`ISR_*`)
+    pub fn isr(&self) -> u8 {
+        unsafe { inb(self.base + ISR_STATUS) }
+    }
+
+    /// Byte `offset` of the device-specific configuration
+    pub fn config8(&self, offset: u16) -> u8 {
+        unsafe { inb(self.base + DEVICE_CONFIG + offset) }
+    }
+
+    /// Tell the device queue `index` has new buffers
+    pub fn notify(&self, index: u16) {
+        unsafe { outw(self.base + QUEUE_NOTIFY, index) };
+    }
+}
+
+/// A split virtqueue
+///
+/// The driver fills descriptors with [`set_descriptor`](Self::set_descriptor),
+/// offers chains by their head with [`push`](Self::push), and takes them
+/// back with [`pop_used`](Self::pop_used); which descriptors are free is
+/// up to the driver.
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    memory: DmaBuffer,
+    /// Offsets of the rings in `memory`
+    avail: usize,
+    used: usize,
+    /// Next available ring index to fill
+    avail_idx: u16,
+    /// Next used ring index to take
+    last_used: u16,
+}
+
+impl Virtqueue {
+    /// Set up queue `index` of the device at its own size
+    pub fn new(driver: DriverHandle, transport: &Transport, index: u16) -> DriverResult<Self> {
+        let size = unsafe {
+            outw(transport.base + QUEUE_SELECT, index);
+            inw(transport.base + QUEUE_SIZE)
+        };
+        if size == 0 {
+            return Err(DriverError::InvalidArgument);
+        }
+        let memory = dma::dma_alloc(driver, Self::memory_size(size), QUEUE_ALIGN)?;
+        let page = memory.phys_addr().as_u64() / QUEUE_ALIGN as u64;
+        let Ok(page) = u32::try_from(page) else {
+            dma::dma_free(driver, memory);
+            return Err(DriverError::OutOfMemory);
+        };
+        unsafe {
+            outw(transport.base + QUEUE_SELECT, index);
+            outl(transport.base + QUEUE_ADDRESS, page);
+        }
+        Ok(Self::with_memory(index, size, memory))
+    }
+
+    /// Offset of the used ring in the queue memory
+    const fn used_offset(size: u16) -> usize {
+        (size as usize * DESC_SIZE + 6 + 2 * size as usize).next_multiple_of(QUEUE_ALIGN)
+    }
+
+    /// Bytes of queue memory for `size` descriptors
+    const fn memory_size(size: u16) -> usize {
+        Self::used_offset(size) + 6 + 8 * size as usize
+    }
+
+    fn with_memory(index: u16, size: u16, memory: DmaBuffer) -> Self {
+        Self {
+            index,
+            size,
+            memory,
+            avail: size as usize * DESC_SIZE,
+            used: Self::used_offset(size),
+            avail_idx: 0,
+            last_used: 0,
+        }
+    }
+
+    /// Number of descriptors
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    fn field<T>(&self, offset: usize) -> *mut T {
+        (self.memory.as_ptr() as usize + offset) as *mut T
+    }
+
+    /// Point descriptor `i` at `len` bytes at physical address `phys`
+    pub fn set_descriptor(&mut self, i: u16, phys: u64, len: u32, flags: u16, next: u16) {
+        let base = i as usize % self.size as usize * DESC_SIZE;
+        unsafe {
+            write_volatile(self.field(base), phys);
+            write_volatile(self.field(base + 8), len);
+            write_volatile(self.field(base + 12), flags);
+            write_volatile(self.field(base + 14), next);
+        }
+    }
+
+    /// Offer the chain starting at descriptor `head` to the device
+    ///
+    /// The device only looks once [`notify`](Self::notify) is called.
+    pub fn push(&mut self, head: u16) {
+        let slot = self.avail + 4 + 2 * (self.avail_idx % self.size) as usize;
+        unsafe { write_volatile(self.field(slot), head) };
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        // The entry must be visible before the index that publishes it
+        fence(Ordering::SeqCst);
+        unsafe { write_volatile(self.field(self.avail + 2), self.avail_idx) };
+    }
+
+    /// Tell the device about the chains pushed so far
+    pub fn notify(&self, transport: &Transport) {
+        fence(Ordering::SeqCst);
+        transport.notify(self.index);
+    }
+
+    /// Whether the device has returned chains not taken yet
+    pub fn has_used(&self) -> bool {
+        fence(Ordering::SeqCst);
+        unsafe { read_volatile(self.field::<u16>(self.used + 2)) != self.last_used }
+    }
+
+    /// Take the next chain the device is done with: its head and the bytes
+    /// it wrote
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        let entry = self.used + 4 + 8 * (self.last_used % self.size) as usize;
+        let (id, len) = unsafe { (read_volatile(self.field::<u32>(entry)), read_volatile(self.field::<u32>(entry + 4))) };
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((id as u16, len))
+    }
+
+    /// Ask for an interrupt when chains are used (`true`), or not
+    ///
+    /// Only a hint: the device may interrupt anyway.
+    pub fn set_interrupt(&mut self, enabled: bool) {
+        let flags = if enabled { 0 } else { AVAIL_NO_INTERRUPT };
+        unsafe { write_volatile(self.field(self.avail), flags) };
+        fence(Ordering::SeqCst);
+    }
+
+    /// Give the queue memory back; the device must be reset first
+    pub fn free(self, driver: DriverHandle) {
+        dma::dma_free(driver, self.memory);
+    }
+}
+
+crate::kernel_test! {
+    /// Pushed heads appear in the available ring, and chains the device
+    /// puts in the used ring come back once each, across index wraparound
+    fn virtqueue_rings() {
+        static KTEST_VIRTIO: crate::dev::api::DriverInfo = crate::driver_info!("ktest-virtio");
+        let driver = match crate::dev::api::register_driver(&KTEST_VIRTIO) {
+            Ok(driver) => driver,
+            Err(_) => return Ok(()),
+        };
+        let size = 8;
+        let memory = match dma::dma_alloc(driver, Virtqueue::memory_size(size), QUEUE_ALIGN) {
+            Ok(memory) => memory,
+            Err(_) => return Ok(()),
+        };
+        let mut vq = Virtqueue::with_memory(0, size, memory);
+        crate::ktest_assert_eq!(Virtqueue::used_offset(size), QUEUE_ALIGN, "used ring offset");
+
+        // Start near the wrap of the 16-bit indices
+        vq.avail_idx = u16::MAX;
+        vq.last_used = u16::MAX;
+        unsafe { write_volatile(vq.field::<u16>(vq.used + 2), u16::MAX) };
+        vq.push(4);
+        vq.push(6);
+        let ring = |vq: &Virtqueue, i: u16| unsafe { read_volatile(vq.field::<u16>(vq.avail + 4 + 2 * (i % size) as usize)) };
+        crate::ktest_assert_eq!(ring(&vq, u16::MAX), 4, "first head");
+        crate::ktest_assert_eq!(ring(&vq, 0), 6, "second head");
+        crate::ktest_assert_eq!(unsafe { read_volatile(vq.field::<u16>(vq.avail + 2)) }, 1, "avail index");
+        crate::ktest_assert!(vq.pop_used().is_none(), "used before the device returned anything");
+
+        // Play the device: return both chains
+        for (n, (head, len)) in [(4u32, 60u32), (6, 1514)].into_iter().enumerate() {
+            let entry = vq.used + 4 + 8 * (u16::MAX.wrapping_add(n as u16) % size) as usize;
+            unsafe {
+                write_volatile(vq.field::<u32>(entry), head);
+                write_volatile(vq.field::<u32>(entry + 4), len);
+            }
+        }
+        unsafe { write_volatile(vq.field::<u16>(vq.used + 2), 1) };
+        crate::ktest_assert_eq!(vq.pop_used(), Some((4, 60)), "first used");
+        crate::ktest_assert_eq!(vq.pop_used(), Some((6, 1514)), "second used");
+        crate::ktest_assert!(vq.pop_used().is_none(), "chain used twice");
+
+        vq.set_interrupt(false);
+        crate::ktest_assert_eq!(unsafe { read_volatile(vq.field::<u16>(vq.avail)) }, AVAIL_NO_INTERRUPT, "masked");
+        vq.set_interrupt(true);
+        crate::ktest_assert_eq!(unsafe { read_volatile(vq.field::<u16>(vq.avail)) }, 0, "unmasked");
+        vq.free(driver);
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+//! Virtio network cards
+//!
+//! Each card (PCI 1af4:1000) becomes a `NetDevice`, `eth0` and up. Queue 0
+//! receives and queue 1 transmits. Both use fixed two-descriptor chains,
+//! one per 2 KiB buffer slot: the virtio-net header, then the frame. All
+//! receive slots stay offered to the device, and a received frame is copied
+//! out and its slot offered again at once.
+//!
+//! The card interrupts through its INTx pin, routed with the firmware's
+//! `_PRT`. The handler takes back transmitted slots and, when frames came
+//! in, masks receive interrupts and hands the card to softnet
+//! (`napi_schedule`), which unmasks them once it has drained the queue.
+//! A card whose pin cannot be routed is polled by softnet instead.
+
+use super::{Transport, Virtqueue, DESC_NEXT, DESC_WRITE, ISR_QUEUE};
+use crate::dev::api::dma::{self, DmaBuffer};
+use crate::dev::api::net::{self, NetDevice, NetStats};
+use crate::dev::api::{self, irq, DriverError, DriverHandle, DriverInfo, DriverResult};
+use crate::dev::pci::{self, PciDevice};
+use crate::sync::IrqSpinLock;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use spin::Once;
+
+/// PCI device ID of a transitional network card
+const DEVICE_ID: u16 = 0x1000;
+
+/// Feature bit: the configuration holds the MAC address
+const FEATURE_MAC: u32 = 1 << 5;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+
+/// The virtio-net header in front of every frame (without mergeable
+/// receive buffers); all zero on transmit, as no offloads are negotiated
+const HEADER_LEN: usize = 10;
+
+/// Largest frame without the frame check sequence
+const MAX_FRAME: usize = 1514;
+
+/// Bytes per buffer slot, header included
+const SLOT_SIZE: usize = 2048;
+
+/// Buffer slots per queue; free transmit slots are a bit mask
+const MAX_SLOTS: usize = 64;
+
+/// IRQ line not set (the card is polled)
+const NO_LINE: u8 = u8::MAX;
+
+const MAX_CARDS: usize = 2;
+
+static DRIVER: DriverInfo = crate::driver_info!("virtio-net");
+
+/// A queue and its buffer slots
+struct Queue {
+    vq: Virtqueue,
+    buffers: DmaBuffer,
+    slots: usize,
+}
+
+impl Queue {
+    fn new(driver: DriverHandle, transport: &Transport, index: u16) -> DriverResult<Self> {
+        let vq = Virtqueue::new(driver, transport, index)?;
+        let slots = (vq.size() as usize / 2).min(MAX_SLOTS);
+        match dma::dma_alloc(driver, slots * SLOT_SIZE, 4096) {
+            Ok(buffers) => Ok(Self { vq, buffers, slots }),
+            Err(e) => {
+                vq.free(driver);
+                Err(e)
+            }
+        }
+    }
+
+    /// Physical address of slot `slot`
+    fn slot_phys(&self, slot: usize) -> u64 {
+        (self.buffers.phys_addr() + slot * SLOT_SIZE).as_u64()
+    }
+
+    /// CPU view of slot `slot`
+    fn slot(&mut self, slot: usize) -> &mut [u8] {
+        &mut self.buffers.as_slice()[slot * SLOT_SIZE..(slot + 1) * SLOT_SIZE]
+    }
+
+    /// Point the descriptors of `slot` at its buffer; `device_writes` for
+    /// receive slots, `len` the frame length
+    fn set_chain(&mut self, slot: usize, len: usize, device_writes: bool) {
+        let phys = self.slot_phys(slot);
+        let write = if device_writes { DESC_WRITE } else { 0 };
+        let head = (slot * 2) as u16;
+        self.vq.set_descriptor(head, phys, HEADER_LEN as u32, DESC_NEXT | write, head + 1);
+        self.vq.set_descriptor(head + 1, phys + HEADER_LEN as u64, len as u32, write, 0);
+    }
+
+    fn free(self, driver: DriverHandle) {
+        self.vq.free(driver);
+        dma::dma_free(driver, self.buffers);
+    }
+}
+
+/// A card that has been set up
+struct Card {
+    transport: Transport,
+    rx: Queue,
+    tx: Queue,
+    /// Transmit slots not in use by the device, one bit each
+    tx_free: u64,
+}
+
+impl Card {
+    /// Take back the transmit slots the device is done with
+    fn reclaim_tx(&mut self) {
+        while let Some((head, _)) = self.tx.vq.pop_used() {
+            self.tx_free |= 1 << (head / 2);
+        }
+    }
+}
+
+/// One network card, published as a `NetDevice`
+struct VirtioNet {
+    name: &'static str,
+    mac: Once<[u8; 6]>,
+    card: IrqSpinLock<Option<Card>>,
+    /// Driver IRQ line, or `NO_LINE`
+    line: AtomicU8,
+    rx_bytes: AtomicU64,
+    tx_bytes: AtomicU64,
+    rx_dropped: AtomicU64,
+    tx_dropped: AtomicU64,
+}
+
+impl VirtioNet {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            mac: Once::new(),
+            card: IrqSpinLock::new(None),
+            line: AtomicU8::new(NO_LINE),
+            rx_bytes: AtomicU64::new(0),
+            tx_bytes: AtomicU64::new(0),
+            rx_dropped: AtomicU64::new(0),
+            tx_dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn interrupt(&'static self) {
+        let received = {
+            let mut card = self.card.lock();
+            let Some(card) = card.as_mut() else { return };
+            // The line may be shared; reading the status also acknowledges it
+            if card.transport.isr() & ISR_QUEUE == 0 {
+                return;
+            }
+            card.reclaim_tx();
+            let received = card.rx.vq.has_used();
+            if received {
+                card.rx.vq.set_interrupt(false);
+            }
+            received
+        };
+        if received {
+            net::napi_schedule(self);
+        }
+    }
+}
+
+static CARDS: [VirtioNet; MAX_CARDS] = [VirtioNet::new("eth0"), VirtioNet::new("eth1")];
+
+impl NetDevice for VirtioNet {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac.get().copied().unwrap_or_default()
+    }
+
+    fn mtu(&self) -> usize {
+        1500
+    }
+
+    fn transmit(&self, frame: &[u8]) -> DriverResult<()> {
+        if frame.len() > MAX_FRAME {
+            return Err(DriverError::InvalidArgument);
+        }
+        let mut card = self.card.lock();
+        let card = card.as_mut().ok_or(DriverError::IoError)?;
+        card.reclaim_tx();
+        if card.tx_free == 0 {
+            self.tx_dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(DriverError::IoError);
+        }
+        let slot = card.tx_free.trailing_zeros() as usize;
+        card.tx_free &= !(1 << slot);
+
+        let buffer = card.tx.slot(slot);
+        buffer[..HEADER_LEN].fill(0);
+        buffer[HEADER_LEN..HEADER_LEN + frame.len()].copy_from_slice(frame);
+        card.tx.set_chain(slot, frame.len(), false);
+        card.tx.vq.push((slot * 2) as u16);
+        card.tx.vq.notify(&card.transport);
+        self.tx_bytes.fetch_add(frame.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn receive(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut card = self.card.lock();
+        let card = card.as_mut()?;
+        while let Some((head, written)) = card.rx.vq.pop_used() {
+            let slot = head as usize / 2;
+            let len = (written as usize).saturating_sub(HEADER_LEN).min(SLOT_SIZE - HEADER_LEN);
+            let fits = len <= buf.len() && len > 0;
+            if fits {
+                buf[..len].copy_from_slice(&card.rx.slot(slot)[HEADER_LEN..HEADER_LEN + len]);
+            }
+            // The slot goes straight back to the device
+            card.rx.vq.push(head);
+            card.rx.vq.notify(&card.transport);
+            if fits {
+                self.rx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+                return Some(len);
+            }
+            self.rx_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        None
+    }
+
+    fn set_rx_interrupt(&self, enabled: bool) -> bool {
+        if self.line.load(Ordering::Acquire) == NO_LINE {
+            return false;
+        }
+        let pending = {
+            let mut card = self.card.lock();
+            let Some(card) = card.as_mut() else { return false };
+            card.rx.vq.set_interrupt(enabled);
+            // A frame that came in while masked raised no interrupt
+            let pending = enabled && card.rx.vq.has_used();
+            if pending {
+                card.rx.vq.set_interrupt(false);
+            }
+            pending
+        };
+        if pending {
+            net::napi_schedule(self);
+        }
+        true
+    }
+
+    fn stats(&self) -> NetStats {
+        NetStats {
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            rx_dropped: self.rx_dropped.load(Ordering::Relaxed),
+            tx_dropped: self.tx_dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Free mask with the first `slots` slots set
+const fn all_slots(slots: usize) -> u64 {
+    if slots >= u64::BITS as usize {
+        u64::MAX
+    } else {
+        (1 << slots) - 1
+    }
+}
+
+/// Driver IRQ handler, shared by all cards
+fn interrupt(line: u8) {
+    for nic in CARDS.iter().filter(|nic| nic.line.load(Ordering::Acquire) == line) {
+        nic.interrupt();
+    }
+}
+
+/// Negotiate features and set up both queues; leaves the device running
+/// with receive interrupts masked
+fn setup(driver: DriverHandle, transport: Transport, nic: &VirtioNet) -> DriverResult<Card> {
+    use super::{STATUS_ACKNOWLEDGE, STATUS_DRIVER, STATUS_DRIVER_OK};
+
+    transport.reset();
+    transport.add_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+    let features = transport.device_features() & FEATURE_MAC;
+    transport.set_driver_features(features);
+
+    let mac = if features & FEATURE_MAC != 0 {
+        core::array::from_fn(|i| transport.config8(i as u16))
+    } else {
+        // Locally administered, one per card
+        [0x02, 0, 0, 0, 0, CARDS.iter().position(|c| core::ptr::eq(c, nic)).unwrap_or(0) as u8 + 1]
+    };
+    nic.mac.call_once(|| mac);
+
+    let rx = Queue::new(driver, &transport, RX_QUEUE)?;
+    let tx = match Queue::new(driver, &transport, TX_QUEUE) {
+        Ok(tx) => tx,
+        Err(e) => {
+            transport.reset();
+            rx.free(driver);
+            return Err(e);
+        }
+    };
+    let tx_free = all_slots(tx.slots);
+    let mut card = Card { transport, rx, tx, tx_free };
+    card.tx_free = all_slots(card.tx.slots);
+    for slot in 0..card.rx.slots {
+        card.rx.set_chain(slot, SLOT_SIZE - HEADER_LEN, true);
+        card.rx.vq.push((slot * 2) as u16);
+    }
+    card.rx.vq.set_interrupt(false);
+    transport.add_status(STATUS_DRIVER_OK);
+    card.rx.vq.notify(&transport);
+    Ok(card)
+}
+
+/// Route the card's INTx pin to a driver IRQ line; `None` if it must be
+/// polled
+fn route(driver: DriverHandle, device: &PciDevice) -> Option<u8> {
+    let pin = match device.interrupt_pin() {
+        1 => irq::PciPin::IntA,
+        2 => irq::PciPin::IntB,
+        3 => irq::PciPin::IntC,
+        4 => irq::PciPin::IntD,
+        _ => return None,
+    };
+    // `_PRT` routes are only known for the root bus
+    if device.bus != 0 {
+        return None;
+    }
+    let line = irq::request_irq(driver, interrupt).ok()?;
+    match irq::route_pci_intx(driver, line, device.device, pin) {
+        Ok(_) => Some(line),
+        Err(_) => {
+            let _ = irq::free_irq(driver, line);
+            None
+        }
+    }
+}
+
+/// Find virtio network cards and publish them
+pub fn init() {
+    let mut found = [None; MAX_CARDS];
+    let mut count = 0;
+    pci::scan(|device| {
+        if device.vendor_id == super::VENDOR && device.device_id == DEVICE_ID && count < MAX_CARDS {
+            found[count] = Some(device);
+            count += 1;
+        }
+    });
+    if count == 0 {
+        return;
+    }
+    let driver = match api::register_driver(&DRIVER) {
+        Ok(driver) => driver,
+        Err(e) => {
+            crate::serial_println!("[VIRTIO-NET] Cannot register the driver: {:?}", e);
+            return;
+        }
+    };
+
+    for (device, nic) in found.iter().flatten().zip(CARDS.iter()) {
+        let Some(transport) = super::Transport::new(device) else { continue };
+        let card = match setup(driver, transport, nic) {
+            Ok(card) => card,
+            Err(e) => {
+                transport.add_status(super::STATUS_FAILED);
+                crate::log_warn!("VIRTIO-NET", "{}: {:?}", nic.name, e);
+                continue;
+            }
+        };
+        *nic.card.lock() = Some(card);
+        if let Some(line) = route(driver, device) {
+            nic.line.store(line, Ordering::Release);
+        }
+        if let Err(e) = net::register_net_device(driver, nic) {
+            crate::log_warn!("VIRTIO-NET", "{}: {:?}", nic.name, e);
+        }
+    }
+}
+crate::initcall!(Drivers, init);
+
+crate::kernel_test! {
+    /// Every transmit slot starts free, and a full frame fits a slot
+    fn virtio_net_slots() {
+        crate::ktest_assert_eq!(all_slots(3), 0b111, "three slots");
+        crate::ktest_assert_eq!(all_slots(MAX_SLOTS), u64::MAX, "all slots");
+        crate::ktest_assert!((HEADER_LEN + MAX_FRAME <= SLOT_SIZE), "frame does not fit a slot");
+        Ok(())
+    }
+}
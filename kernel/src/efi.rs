@@ -0,0 +1,327 @@
+//! UEFI firmware information
+//!
+//! Limine reports the firmware type and the RTC time at boot, and on UEFI
+//! machines the EFI system table and memory map. [`init`] reads them once
+//! and `/proc/efi` shows the result.
+//!
+//! Secure Boot status comes from the `SecureBoot` and `SetupMode` global
+//! variables, read with the GetVariable runtime service. Limine does not
+//! call SetVirtualAddressMap, so runtime services run at their physical
+//! addresses through the bootloader's identity map of the low 4 GiB. They
+//! are called only if every runtime region lies there, and only before the
+//! kernel removes execute permission from that map (`mm::security`). With
+//! `efi=noruntime` on the command line they are never called, and the
+//! status is reported as unknown.
+
+use core::fmt::{self, Write};
+use limine::firmware_type::FirmwareType;
+use limine::request::{DateAtBootRequest, EfiMemoryMapRequest, EfiSystemTableRequest, FirmwareTypeRequest};
+
+/// Limine firmware type request
+#[used]
+#[link_section = ".requests"]
+static FIRMWARE_TYPE_REQUEST: FirmwareTypeRequest = FirmwareTypeRequest::new();
+
+/// Limine EFI system table request
+#[used]
+#[link_section = ".requests"]
+static SYSTEM_TABLE_REQUEST: EfiSystemTableRequest = EfiSystemTableRequest::new();
+
+/// Limine EFI memory map request, to locate the runtime service regions
+#[used]
+#[link_section = ".requests"]
+static MEMORY_MAP_REQUEST: EfiMemoryMapRequest = EfiMemoryMapRequest::new();
+
+/// Limine date at boot request (RTC time, read by the bootloader)
+#[used]
+#[link_section = ".requests"]
+static DATE_AT_BOOT_REQUEST: DateAtBootRequest = DateAtBootRequest::new();
+
+/// "IBI SYST"
+const SYSTEM_TABLE_SIGNATURE: u64 = 0x5453_5953_2049_4249;
+/// "RUNTSERV"
+const RUNTIME_SERVICES_SIGNATURE: u64 = 0x5652_4553_544e_5552;
+
+/// Offsets in the EFI system table
+const SYSTEM_TABLE_FIRMWARE_VENDOR: usize = 24;
+const SYSTEM_TABLE_FIRMWARE_REVISION: usize = 32;
+const SYSTEM_TABLE_RUNTIME_SERVICES: usize = 88;
+
+/// Offset of GetVariable in the runtime services table
+const RUNTIME_GET_VARIABLE: usize = 72;
+
+/// Memory map descriptor field offsets and the runtime attribute
+const DESCRIPTOR_PHYSICAL_START: usize = 8;
+const DESCRIPTOR_PAGES: usize = 24;
+const DESCRIPTOR_ATTRIBUTE: usize = 32;
+const EFI_MEMORY_RUNTIME: u64 = 1 << 63;
+
+/// End of the bootloader's identity map
+const IDENTITY_MAP_END: u64 = 1 << 32;
+
+/// Longest firmware vendor string kept
+const MAX_VENDOR_LEN: usize = 64;
+
+#[repr(C)]
+struct Guid {
+    data1: u32,
+    data2: u16,
+    data3: u16,
+    data4: [u8; 8],
+}
+
+/// EFI_GLOBAL_VARIABLE, the namespace of `SecureBoot` and `SetupMode`
+const GLOBAL_VARIABLE: Guid = Guid {
+    data1: 0x8be4_df61,
+    data2: 0x93ca,
+    data3: 0x11d2,
+    data4: [0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c],
+};
+
+type GetVariable = unsafe extern "efiapi" fn(
+    name: *const u16,
+    vendor: *const Guid,
+    attributes: *mut u32,
+    data_size: *mut usize,
+    data: *mut u8,
+) -> usize;
+
+/// Firmware the machine booted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+    Bios,
+    Uefi32,
+    Uefi64,
+    Unknown,
+}
+
+/// What [`init`] found
+#[derive(Debug, Clone, Copy)]
+pub struct FirmwareInfo {
+    pub firmware: Firmware,
+    /// RTC time at boot, seconds since the Unix epoch
+    pub boot_time: Option<u64>,
+    /// UEFI specification revision (major in the high 16 bits)
+    pub uefi_revision: Option<u32>,
+    /// Vendor-specific firmware revision
+    pub firmware_revision: Option<u32>,
+    vendor: [u8; MAX_VENDOR_LEN],
+    vendor_len: usize,
+    /// Whether runtime services could be called
+    pub runtime_services: bool,
+    pub secure_boot: Option<bool>,
+    pub setup_mode: Option<bool>,
+}
+
+static INFO: spin::Once<FirmwareInfo> = spin::Once::new();
+
+impl FirmwareInfo {
+    /// Firmware vendor from the system table, non-ASCII characters as '?'
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor[..self.vendor_len]).unwrap_or("")
+    }
+
+    /// Write the `/proc/efi` text
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        let firmware = match self.firmware {
+            Firmware::Bios => "bios",
+            Firmware::Uefi32 => "uefi32",
+            Firmware::Uefi64 => "uefi64",
+            Firmware::Unknown => "unknown",
+        };
+        writeln!(out, "firmware: {}", firmware)?;
+        if let Some(revision) = self.uefi_revision {
+            writeln!(out, "uefi_revision: {}", RevisionDisplay(revision))?;
+            writeln!(out, "vendor: {}", self.vendor())?;
+        }
+        if let Some(revision) = self.firmware_revision {
+            writeln!(out, "firmware_revision: 0x{:x}", revision)?;
+        }
+        match self.boot_time {
+            Some(seconds) => writeln!(out, "boot_time: {}", seconds)?,
+            None => writeln!(out, "boot_time: unknown")?,
+        }
+        writeln!(out, "runtime_services: {}", if self.runtime_services { "yes" } else { "no" })?;
+        let flag = |value: Option<bool>| match value {
+            Some(true) => "enabled",
+            Some(false) => "disabled",
+            None => "unknown",
+        };
+        writeln!(out, "secure_boot: {}", flag(self.secure_boot))?;
+        writeln!(out, "setup_mode: {}", flag(self.setup_mode))
+    }
+}
+
+/// UEFI revision as the specification writes it: 2.70 is "2.7", 2.31 is
+/// "2.3.1", 2.100 is "2.10"
+struct RevisionDisplay(u32);
+
+impl fmt::Display for RevisionDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (major, minor) = (self.0 >> 16, self.0 & 0xffff);
+        if minor % 10 == 0 {
+            write!(f, "{}.{}", major, minor / 10)
+        } else {
+            write!(f, "{}.{}.{}", major, minor / 10, minor % 10)
+        }
+    }
+}
+
+/// NUL-terminated UCS-2 copy of an ASCII name
+const fn ucs2<const N: usize>(name: &str) -> [u16; N] {
+    let bytes = name.as_bytes();
+    let mut out = [0u16; N];
+    let mut i = 0;
+    while i < bytes.len() {
+        out[i] = bytes[i] as u16;
+        i += 1;
+    }
+    out
+}
+
+static SECURE_BOOT_NAME: [u16; 11] = ucs2("SecureBoot");
+static SETUP_MODE_NAME: [u16; 10] = ucs2("SetupMode");
+
+/// Copy the NUL-terminated UCS-2 string at `ptr` as ASCII into `out`
+///
+/// # Safety
+/// `ptr` must point to a readable, NUL-terminated string.
+unsafe fn copy_ucs2(ptr: *const u16, out: &mut [u8]) -> usize {
+    let mut len = 0;
+    while len < out.len() {
+        let c = core::ptr::read_unaligned(ptr.add(len));
+        if c == 0 {
+            break;
+        }
+        out[len] = if (0x20..0x7f).contains(&c) { c as u8 } else { b'?' };
+        len += 1;
+    }
+    len
+}
+
+fn read_u64(addr: u64, offset: usize) -> u64 {
+    unsafe { core::ptr::read_unaligned((addr as usize + offset) as *const u64) }
+}
+
+/// Whether every runtime service region is below `IDENTITY_MAP_END`
+fn runtime_regions_identity_mapped() -> bool {
+    let Some(map) = MEMORY_MAP_REQUEST.get_response() else {
+        return false;
+    };
+    let (base, size, desc_size) = (map.memmap() as u64, map.memmap_size(), map.desc_size());
+    if desc_size == 0 {
+        return false;
+    }
+    (0..size / desc_size).all(|i| {
+        let desc = base + i * desc_size;
+        let attribute = read_u64(desc, DESCRIPTOR_ATTRIBUTE);
+        let end = read_u64(desc, DESCRIPTOR_PHYSICAL_START) + read_u64(desc, DESCRIPTOR_PAGES) * 4096;
+        attribute & EFI_MEMORY_RUNTIME == 0 || end <= IDENTITY_MAP_END
+    })
+}
+
+/// Read a one-byte global variable; None if it cannot be read
+unsafe fn get_variable_u8(get_variable: GetVariable, name: &[u16]) -> Option<u8> {
+    /// EFI_SUCCESS
+    const SUCCESS: usize = 0;
+
+    let mut attributes = 0u32;
+    let mut value = 0u8;
+    let mut size = 1usize;
+    let status = get_variable(name.as_ptr(), &GLOBAL_VARIABLE, &mut attributes, &mut size, &mut value);
+    (status == SUCCESS && size == 1).then_some(value)
+}
+
+/// Read the firmware information Limine passed
+///
+/// # Safety
+/// Must be called once, on the BSP with interrupts disabled, before
+/// `mm::init_memory` hardens the bootloader's mappings.
+pub unsafe fn init() -> &'static FirmwareInfo {
+    INFO.call_once(|| {
+        let firmware = match FIRMWARE_TYPE_REQUEST.get_response().map(|r| r.firmware_type()) {
+            Some(FirmwareType::X86_BIOS) => Firmware::Bios,
+            Some(FirmwareType::UEFI_32) => Firmware::Uefi32,
+            Some(FirmwareType::UEFI_64) => Firmware::Uefi64,
+            _ => Firmware::Unknown,
+        };
+        let mut info = FirmwareInfo {
+            firmware,
+            boot_time: DATE_AT_BOOT_REQUEST.get_response().map(|r| r.timestamp().as_secs()),
+            uefi_revision: None,
+            firmware_revision: None,
+            vendor: [0; MAX_VENDOR_LEN],
+            vendor_len: 0,
+            runtime_services: false,
+            secure_boot: None,
+            setup_mode: None,
+        };
+
+        // A 32-bit firmware's tables are laid out differently
+        let Some(table) = SYSTEM_TABLE_REQUEST.get_response().map(|r| r.address() as u64) else {
+            return info;
+        };
+        if firmware != Firmware::Uefi64 || table == 0 || read_u64(table, 0) != SYSTEM_TABLE_SIGNATURE {
+            return info;
+        }
+        info.uefi_revision = Some(read_u64(table, 8) as u32);
+        info.firmware_revision = Some(read_u64(table, SYSTEM_TABLE_FIRMWARE_REVISION) as u32);
+        let vendor = read_u64(table, SYSTEM_TABLE_FIRMWARE_VENDOR);
+        if vendor != 0 && vendor < IDENTITY_MAP_END {
+            info.vendor_len = copy_ucs2(vendor as *const u16, &mut info.vendor);
+        }
+
+        let runtime = read_u64(table, SYSTEM_TABLE_RUNTIME_SERVICES);
+        if crate::cmdline::value("efi") == Some("noruntime")
+            || runtime == 0
+            || runtime >= IDENTITY_MAP_END
+            || read_u64(runtime, 0) != RUNTIME_SERVICES_SIGNATURE
+            || !runtime_regions_identity_mapped()
+        {
+            return info;
+        }
+        let get_variable = read_u64(runtime, RUNTIME_GET_VARIABLE);
+        if get_variable == 0 || get_variable >= IDENTITY_MAP_END {
+            return info;
+        }
+        let get_variable: GetVariable = core::mem::transmute(get_variable as usize);
+        info.runtime_services = true;
+        info.secure_boot = get_variable_u8(get_variable, &SECURE_BOOT_NAME).map(|v| v == 1);
+        info.setup_mode = get_variable_u8(get_variable, &SETUP_MODE_NAME).map(|v| v == 1);
+        info
+    })
+}
+
+/// Firmware information, once [`init`] has run
+pub fn info() -> Option<&'static FirmwareInfo> {
+    INFO.get()
+}
+
+crate::kernel_test! {
+    /// UEFI revisions print the way the specification names them
+    fn efi_revision_display() {
+        struct Text {
+            buf: [u8; 16],
+            len: usize,
+        }
+
+        impl Write for Text {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                let end = self.len + s.len();
+                self.buf.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+                self.len = end;
+                Ok(())
+            }
+        }
+
+        let cases: [(u32, &str); 3] = [((2 << 16) | 70, "2.7"), ((2 << 16) | 31, "2.3.1"), ((2 << 16) | 100, "2.10")];
+        for (revision, expected) in cases {
+            let mut text = Text { buf: [0; 16], len: 0 };
+            let _ = write!(text, "{}", RevisionDisplay(revision));
+            crate::ktest_assert_eq!(&text.buf[..text.len], expected.as_bytes(), "revision misprinted");
+        }
+        crate::ktest_assert_eq!(SECURE_BOOT_NAME[9], b't' as u16, "variable name not converted");
+        crate::ktest_assert_eq!(SECURE_BOOT_NAME[10], 0, "variable name not terminated");
+        Ok(())
+    }
+}
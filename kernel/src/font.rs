@@ -0,0 +1,360 @@
+//! Console fonts
+//!
+//! A [`Font`] is a PC Screen Font version 2 (PSF2) image: a header, the
+//! glyph bitmaps (one bit per pixel, rows padded to whole bytes, most
+//! significant bit leftmost) and an optional Unicode table mapping
+//! characters to glyphs. Fonts of any glyph size up to
+//! [`MAX_WIDTH`]x[`MAX_HEIGHT`] are used in place, so [`Font::parse`] needs
+//! data that lives as long as the kernel, such as a file in the initrd.
+//!
+//! [`BUILTIN`] is the 8x8 font compiled into the kernel, laid out the same
+//! way; it is what the console uses until a font is loaded, or if loading
+//! one fails.
+//!
+//! The Unicode table has, for each glyph in order, the UTF-8 characters it
+//! draws, then optionally sequences of characters (each starting with
+//! `0xFE`) and a `0xFF` terminator. Sequences are skipped. Characters up to
+//! U+00FF are looked up in a table built when the font is parsed; others
+//! search the Unicode table. Characters without a glyph are drawn as
+//! U+FFFD, or `?` if the font has no U+FFFD.
+
+/// PSF2 magic
+const MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+
+/// Header flag: the font has a Unicode table
+const HAS_UNICODE_TABLE: u32 = 1;
+
+/// Unicode table: start of a character sequence, end of a glyph's entry
+const SEQUENCE: u8 = 0xFE;
+const TERMINATOR: u8 = 0xFF;
+
+/// Largest glyph size accepted, in pixels
+pub const MAX_WIDTH: usize = 32;
+pub const MAX_HEIGHT: usize = 64;
+
+/// Glyph index of a character the font does not have
+const NO_GLYPH: u16 = u16::MAX;
+
+/// Font errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    /// Not a PSF2 font
+    BadMagic,
+    /// The header or glyphs run past the end of the data
+    Truncated,
+    /// Glyphs larger than [`MAX_WIDTH`]x[`MAX_HEIGHT`], empty, or with a
+    /// size that does not match their dimensions
+    UnsupportedSize,
+    /// No glyphs, or more than a `u16` can index
+    BadGlyphCount,
+}
+
+/// A bitmap font
+pub struct Font {
+    width: usize,
+    height: usize,
+    /// Bytes per glyph row
+    row_bytes: usize,
+    glyphs: &'static [u8],
+    count: usize,
+    /// Unicode table, empty if the font has none
+    unicode: &'static [u8],
+    /// Glyph of each character up to U+00FF
+    latin1: [u16; 256],
+    /// Glyph drawn for characters the font does not have
+    replacement: u16,
+}
+
+/// A glyph's bitmap
+#[derive(Clone, Copy)]
+pub struct Glyph<'a> {
+    bits: &'a [u8],
+    row_bytes: usize,
+}
+
+impl Glyph<'_> {
+    /// Whether the pixel at `row`, `col` is foreground
+    pub fn pixel(&self, row: usize, col: usize) -> bool {
+        self.bits[row * self.row_bytes + col / 8] & (0x80 >> (col % 8)) != 0
+    }
+}
+
+/// Little-endian u32 at `offset`
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Characters of each glyph in a Unicode table, as (glyph, character)
+fn mappings(unicode: &[u8]) -> impl Iterator<Item = (usize, char)> + '_ {
+    unicode.split(|&b| b == TERMINATOR).enumerate().flat_map(|(glyph, entry)| {
+        let singles = entry.split(|&b| b == SEQUENCE).next().unwrap_or(&[]);
+        core::str::from_utf8(singles).unwrap_or("").chars().map(move |c| (glyph, c))
+    })
+}
+
+impl Font {
+    /// Use the PSF2 font in `data`
+    pub fn parse(data: &'static [u8]) -> Result<Font, FontError> {
+        if data.get(0..4) != Some(&MAGIC[..]) {
+            return Err(FontError::BadMagic);
+        }
+        let field = |index: usize| read_u32(data, index * 4).map(|value| value as usize).ok_or(FontError::Truncated);
+        let header_size = field(2)?;
+        let flags = field(3)? as u32;
+        let count = field(4)?;
+        let glyph_bytes = field(5)?;
+        let height = field(6)?;
+        let width = field(7)?;
+
+        let row_bytes = width.div_ceil(8);
+        if !(1..=MAX_WIDTH).contains(&width) || !(1..=MAX_HEIGHT).contains(&height) || glyph_bytes != row_bytes * height {
+            return Err(FontError::UnsupportedSize);
+        }
+        if count == 0 || count >= NO_GLYPH as usize {
+            return Err(FontError::BadGlyphCount);
+        }
+        let end = header_size.checked_add(count * glyph_bytes).ok_or(FontError::Truncated)?;
+        let glyphs = data.get(header_size..end).ok_or(FontError::Truncated)?;
+        let unicode = if flags & HAS_UNICODE_TABLE != 0 { &data[end..] } else { &[] };
+
+        let mut font = Font { width, height, row_bytes, glyphs, count, unicode, latin1: [NO_GLYPH; 256], replacement: 0 };
+        if unicode.is_empty() {
+            // Without a table, glyphs are in character order
+            for (c, glyph) in font.latin1.iter_mut().enumerate().take(count) {
+                *glyph = c as u16;
+            }
+        } else {
+            for (glyph, c) in mappings(unicode).filter(|&(glyph, _)| glyph < count) {
+                if let Some(slot) = font.latin1.get_mut(c as usize) {
+                    if *slot == NO_GLYPH {
+                        *slot = glyph as u16;
+                    }
+                }
+            }
+        }
+        font.replacement = font.lookup('\u{FFFD}').or_else(|| font.lookup('?')).unwrap_or(0);
+        Ok(font)
+    }
+
+    /// Glyph width in pixels
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Glyph height in pixels
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Number of glyphs
+    pub fn glyph_count(&self) -> usize {
+        self.count
+    }
+
+    /// Index of the glyph for `c`, if the font has one
+    fn lookup(&self, c: char) -> Option<u16> {
+        let glyph = match self.latin1.get(c as usize) {
+            Some(&glyph) => glyph,
+            None => mappings(self.unicode)
+                .find(|&(glyph, mapped)| mapped == c && glyph < self.count)
+                .map_or(NO_GLYPH, |(glyph, _)| glyph as u16),
+        };
+        (glyph != NO_GLYPH).then_some(glyph)
+    }
+
+    /// The glyph for `c`, or the replacement glyph
+    pub fn glyph(&self, c: char) -> Glyph<'_> {
+        let index = self.lookup(c).unwrap_or(self.replacement) as usize;
+        let size = self.row_bytes * self.height;
+        Glyph { bits: &self.glyphs[index * size..(index + 1) * size], row_bytes: self.row_bytes }
+    }
+}
+
+/// Glyphs of the built-in font: ASCII, then the sparkle
+const BUILTIN_COUNT: usize = 129;
+
+static BUILTIN_GLYPHS: [u8; BUILTIN_COUNT * 8] = {
+    let mut glyphs = [0; BUILTIN_COUNT * 8];
+    let mut index = 0;
+    while index < BUILTIN_COUNT {
+        let glyph = builtin_glyph(if index < 128 { index as u8 as char } else { '✨' });
+        let mut row = 0;
+        while row < 8 {
+            glyphs[index * 8 + row] = glyph[row];
+            row += 1;
+        }
+        index += 1;
+    }
+    glyphs
+};
+
+/// Unicode table of the built-in font
+static BUILTIN_UNICODE: [u8; 128 * 2 + 4] = {
+    let mut table = [TERMINATOR; 128 * 2 + 4];
+    let mut c = 0;
+    while c < 128 {
+        table[c * 2] = c as u8;
+        c += 1;
+    }
+    let sparkle = [0xE2, 0x9C, 0xA8];
+    let mut i = 0;
+    while i < sparkle.len() {
+        table[128 * 2 + i] = sparkle[i];
+        i += 1;
+    }
+    table
+};
+
+/// The font compiled into the kernel
+pub static BUILTIN: Font = Font {
+    width: 8,
+    height: 8,
+    row_bytes: 1,
+    glyphs: &BUILTIN_GLYPHS,
+    count: BUILTIN_COUNT,
+    unicode: &BUILTIN_UNICODE,
+    latin1: {
+        let mut latin1 = [NO_GLYPH; 256];
+        let mut c = 0;
+        while c < 128 {
+            latin1[c] = c as u16;
+            c += 1;
+        }
+        latin1
+    },
+    replacement: b'?' as u16,
+};
+
+/// Simple 8x8 bitmap font
+/// Each character is represented by 8 bytes, one per row
+/// Each bit represents a pixel (1 = foreground, 0 = background)
+const fn builtin_glyph(c: char) -> [u8; 8] {
+    match c {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '!' => [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00],
+        '"' => [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '#' => [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00],
+        '$' => [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00],
+        '%' => [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00],
+        '&' => [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00],
+        '\'' => [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '(' => [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00],
+        ')' => [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00],
+        '*' => [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00],
+        '+' => [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06],
+        '-' => [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00],
+        '0' => [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00],
+        '1' => [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00],
+        '2' => [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00],
+        '3' => [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00],
+        '4' => [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00],
+        '5' => [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00],
+        '6' => [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00],
+        '7' => [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00],
+        '8' => [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00],
+        '9' => [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00],
+        ';' => [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06],
+        '<' => [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00],
+        '=' => [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00],
+        '>' => [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00],
+        '?' => [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00],
+        '@' => [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00],
+        'A' => [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00],
+        'B' => [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00],
+        'C' => [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00],
+        'D' => [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00],
+        'E' => [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00],
+        'F' => [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00],
+        'G' => [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00],
+        'H' => [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00],
+        'I' => [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00],
+        'J' => [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00],
+        'K' => [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00],
+        'L' => [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00],
+        'M' => [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00],
+        'N' => [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00],
+        'O' => [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00],
+        'P' => [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00],
+        'Q' => [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00],
+        'R' => [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00],
+        'S' => [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00],
+        'T' => [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00],
+        'U' => [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00],
+        'V' => [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00],
+        'W' => [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00],
+        'X' => [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00],
+        'Y' => [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00],
+        'Z' => [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00],
+        '[' => [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00],
+        '\\' => [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00],
+        ']' => [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00],
+        '^' => [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF],
+        '`' => [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00],
+        'a' => [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00],
+        'b' => [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00],
+        'c' => [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00],
+        'd' => [0x38, 0x30, 0x30, 0x3e, 0x33, 0x33, 0x6E, 0x00],
+        'e' => [0x00, 0x00, 0x1E, 0x33, 0x3f, 0x03, 0x1E, 0x00],
+        'f' => [0x1C, 0x36, 0x06, 0x0f, 0x06, 0x06, 0x0F, 0x00],
+        'g' => [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F],
+        'h' => [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00],
+        'i' => [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00],
+        'j' => [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E],
+        'k' => [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00],
+        'l' => [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00],
+        'm' => [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00],
+        'n' => [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00],
+        'o' => [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00],
+        'p' => [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F],
+        'q' => [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78],
+        'r' => [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00],
+        's' => [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00],
+        't' => [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00],
+        'u' => [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00],
+        'v' => [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00],
+        'w' => [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00],
+        'x' => [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00],
+        'y' => [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F],
+        'z' => [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00],
+        '{' => [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00],
+        '|' => [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00],
+        '}' => [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00],
+        '~' => [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '✨' => [0x00, 0x24, 0x18, 0xFF, 0x18, 0x24, 0x00, 0x00], // Sparkle emoji approximation
+        _ => [0x7E, 0x81, 0xA5, 0x81, 0xBD, 0x99, 0x81, 0x7E], // Default: smiley face for unknown chars
+    }
+}
+
+
+crate::kernel_test! {
+    /// A PSF2 font with a 10-pixel-wide glyph maps characters through its
+    /// Unicode table, falling back to U+FFFD for the rest
+    fn font_psf2_parse() {
+        // Header, two 10x2 glyphs (2 bytes per row), Unicode table
+        static DATA: [u8; 32 + 8 + 11] = [
+            0x72, 0xB5, 0x4A, 0x86, 0, 0, 0, 0, 32, 0, 0, 0, 1, 0, 0, 0, //
+            2, 0, 0, 0, 4, 0, 0, 0, 2, 0, 0, 0, 10, 0, 0, 0, //
+            0xFF, 0xC0, 0x00, 0x00, 0x80, 0x40, 0x80, 0x40, //
+            // Glyph 0: U+FFFD; glyph 1: 'A', U+00C5, then a sequence
+            0xEF, 0xBF, 0xBD, 0xFF, b'A', 0xC3, 0x85, 0xFE, b'A', 0x01, 0xFF,
+        ];
+        let font = Font::parse(&DATA).map_err(|_| "font did not parse")?;
+        crate::ktest_assert_eq!((font.width(), font.height()), (10, 2), "glyph size");
+        let a = font.glyph('A');
+        crate::ktest_assert!((a.pixel(0, 0) && a.pixel(0, 9) && !a.pixel(0, 1)), "glyph 1 bits");
+        crate::ktest_assert!(font.glyph('\u{C5}').pixel(1, 9), "Latin-1 mapping");
+        crate::ktest_assert!(font.glyph('Z').pixel(0, 9), "no replacement glyph");
+        crate::ktest_assert!(!font.glyph('\u{1}').pixel(1, 0), "sequence mapped as a character");
+        crate::ktest_assert_eq!(Font::parse(&DATA[..40 - 1]).err(), Some(FontError::Truncated), "truncated font");
+        crate::ktest_assert_eq!(Font::parse(&DATA[1..]).err(), Some(FontError::BadMagic), "bad magic");
+        crate::ktest_assert!(BUILTIN.glyph('✨').pixel(3, 0), "built-in sparkle");
+        crate::ktest_assert!(BUILTIN.glyph('\u{E9}').pixel(0, 3), "built-in replacement is not '?'");
+        Ok(())
+    }
+}
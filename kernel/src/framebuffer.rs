@@ -1,11 +1,217 @@
 /// Framebuffer driver for MelloOS
 /// Provides pixel-level access to the screen through memory-mapped I/O
+///
+/// With a back buffer (see [`Framebuffer::set_back_buffer`]) drawing goes
+/// to memory instead, and [`Framebuffer::flush`] copies the regions drawn
+/// since the last flush to the screen. Video memory is slow to read, which
+/// made scrolling on screen slow, and drawing straight to it shows every
+/// half-drawn line.
+///
+/// Besides text there are a few 2D primitives for boot and status screens:
+/// rectangles, lines, and RGBA images ([`Surface`]) blended by their alpha.
+/// Pixel drawing is limited to a clipping rectangle, the whole screen
+/// unless set with [`Framebuffer::set_clip`]; clearing and scrolling are
+/// not.
+use crate::font::{self, Font};
+use crate::mm::VirtAddr;
 use limine::framebuffer::Framebuffer as LimineFramebuffer;
 
+/// Damaged regions kept apart before they are merged into one
+const MAX_DAMAGE: usize = 8;
+
+/// Orientation of the picture relative to the panel, clockwise
+///
+/// Drawing with a rotation uses logical coordinates: `(0, 0)` is the top
+/// left corner as seen by someone looking at the rotated panel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Rotation {
+    /// Upright
+    Normal = 0,
+    /// Turned 90 degrees clockwise: the top of the text faces the right edge
+    Clockwise = 1,
+    /// Turned 180 degrees
+    UpsideDown = 2,
+    /// Turned 270 degrees clockwise: the top of the text faces the left edge
+    CounterClockwise = 3,
+}
+
+impl Rotation {
+    /// Parse a rotation in degrees (`0`, `90`, `180` or `270`)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "0" => Some(Rotation::Normal),
+            "90" => Some(Rotation::Clockwise),
+            "180" => Some(Rotation::UpsideDown),
+            "270" => Some(Rotation::CounterClockwise),
+            _ => None,
+        }
+    }
+
+    /// The rotation in degrees
+    pub fn degrees(self) -> u16 {
+        self as u16 * 90
+    }
+
+    /// Whether logical width and height are swapped on the panel
+    fn is_sideways(self) -> bool {
+        matches!(self, Rotation::Clockwise | Rotation::CounterClockwise)
+    }
+}
+
+/// Rectangle of pixels, `[top, bottom) x [left, right)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    top: usize,
+    left: usize,
+    bottom: usize,
+    right: usize,
+}
+
+impl Rect {
+    const EMPTY: Rect = Rect { top: 0, left: 0, bottom: 0, right: 0 };
+
+    /// The `width` by `height` rectangle with its top left corner at `(x, y)`
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect {
+            top: y,
+            left: x,
+            bottom: y.saturating_add(height),
+            right: x.saturating_add(width),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.top >= self.bottom || self.left >= self.right
+    }
+
+    fn contains(&self, x: usize, y: usize) -> bool {
+        (self.left..self.right).contains(&x) && (self.top..self.bottom).contains(&y)
+    }
+
+    /// Whether `other` lies wholly inside
+    fn covers(&self, other: &Rect) -> bool {
+        self.left <= other.left && other.right <= self.right && self.top <= other.top && other.bottom <= self.bottom
+    }
+
+    /// The part inside both; empty if they do not overlap
+    fn intersect(self, other: Rect) -> Rect {
+        Rect {
+            top: self.top.max(other.top),
+            left: self.left.max(other.left),
+            bottom: self.bottom.min(other.bottom),
+            right: self.right.min(other.right),
+        }
+    }
+
+    /// Whether the two overlap or share an edge
+    fn touches(&self, other: &Rect) -> bool {
+        self.top <= other.bottom && other.top <= self.bottom && self.left <= other.right && other.left <= self.right
+    }
+
+    /// Smallest rectangle covering both
+    fn union(self, other: Rect) -> Rect {
+        Rect {
+            top: self.top.min(other.top),
+            left: self.left.min(other.left),
+            bottom: self.bottom.max(other.bottom),
+            right: self.right.max(other.right),
+        }
+    }
+}
+
+/// Regions of the back buffer not copied to the screen yet
+///
+/// Touching regions are merged as they are added, so a run of characters
+/// makes one region; once `MAX_DAMAGE` separate regions are kept, the next
+/// one merges them all.
+struct Damage {
+    rects: [Rect; MAX_DAMAGE],
+    len: usize,
+}
+
+impl Damage {
+    const fn new() -> Self {
+        Self { rects: [Rect::EMPTY; MAX_DAMAGE], len: 0 }
+    }
+
+    fn add(&mut self, mut rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        // A merged region may touch regions the new one did not
+        let mut i = 0;
+        while i < self.len {
+            if self.rects[i].touches(&rect) {
+                rect = rect.union(self.rects[i]);
+                self.len -= 1;
+                self.rects[i] = self.rects[self.len];
+                i = 0;
+            } else {
+                i += 1;
+            }
+        }
+        if self.len == MAX_DAMAGE {
+            rect = self.rects.iter().fold(rect, |all, &other| all.union(other));
+            self.len = 0;
+        }
+        self.rects[self.len] = rect;
+        self.len += 1;
+    }
+
+    fn rects(&self) -> &[Rect] {
+        &self.rects[..self.len]
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+/// An image to draw with [`Framebuffer::blit`]
+///
+/// Pixels are 0xAARRGGBB, row by row: alpha 0 is transparent, 255 opaque,
+/// and anything between is blended with what is on screen.
+#[derive(Debug, Clone, Copy)]
+pub struct Surface<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: &'a [u32],
+}
+
+/// `src` over `dst` with `alpha` out of 255, channel by channel
+fn blend(src: u32, dst: u32, alpha: u32) -> u32 {
+    let mut color = 0;
+    for shift in [0, 8, 16] {
+        let s = (src >> shift) & 0xFF;
+        let d = (dst >> shift) & 0xFF;
+        color |= ((s * alpha + d * (255 - alpha) + 127) / 255) << shift;
+    }
+    color
+}
+
+/// Sprite shown on the screen over the back buffer, such as the mouse
+/// cursor
+#[derive(Clone, Copy)]
+struct Overlay {
+    sprite: &'static Surface<'static>,
+    x: usize,
+    y: usize,
+}
+
+impl Overlay {
+    fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.sprite.width, self.sprite.height)
+    }
+}
+
 /// Represents a framebuffer for drawing to the screen
 pub struct Framebuffer {
-    /// Pointer to the framebuffer memory
+    /// Pointer to the memory drawn into: the back buffer if there is one,
+    /// else the screen
     address: *mut u8,
+    /// Pointer to the framebuffer memory
+    screen: *mut u8,
     /// Screen width in pixels
     width: usize,
     /// Screen height in pixels
@@ -14,6 +220,11 @@ pub struct Framebuffer {
     pitch: usize,
     /// Bits per pixel
     bpp: u16,
+    /// Back buffer regions drawn since the last flush
+    damage: Damage,
+    /// Pixels outside are not drawn
+    clip: Rect,
+    overlay: Option<Overlay>,
 }
 
 impl Framebuffer {
@@ -25,15 +236,160 @@ impl Framebuffer {
     /// # Returns
     /// A new Framebuffer instance
     pub fn new(limine_fb: &LimineFramebuffer) -> Self {
+        // The bootloader hands over the screen through the direct map;
+        // debug builds check the address is there
+        let screen = VirtAddr::from_ptr(limine_fb.addr());
+        if cfg!(debug_assertions) {
+            screen.to_phys();
+        }
+        let address = screen.as_mut_ptr::<u8>();
+        let (width, height) = (limine_fb.width() as usize, limine_fb.height() as usize);
         Self {
-            address: limine_fb.addr() as *mut u8,
-            width: limine_fb.width() as usize,
-            height: limine_fb.height() as usize,
+            address,
+            screen: address,
+            width,
+            height,
             pitch: limine_fb.pitch() as usize,
             bpp: limine_fb.bpp(),
+            damage: Damage::new(),
+            clip: Rect::new(0, 0, width, height),
+            overlay: None,
+        }
+    }
+
+    /// The whole screen
+    fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// Limit drawing to `clip` (within the screen)
+    pub fn set_clip(&mut self, clip: Rect) {
+        self.clip = clip.intersect(self.bounds());
+    }
+
+    /// Allow drawing on the whole screen again
+    pub fn reset_clip(&mut self) {
+        self.clip = self.bounds();
+    }
+
+    /// Bytes a back buffer for this framebuffer needs
+    pub fn buffer_size(&self) -> usize {
+        self.pitch * self.height
+    }
+
+    /// Whether drawing goes to a back buffer
+    pub fn is_back_buffered(&self) -> bool {
+        self.address != self.screen
+    }
+
+    /// Draw into `buffer` from now on, and copy to the screen on [`flush`]
+    ///
+    /// The buffer starts out as a copy of the screen.
+    ///
+    /// # Safety
+    /// `buffer` must point to [`buffer_size`] bytes of writable memory used
+    /// by nothing else for as long as the framebuffer exists.
+    ///
+    /// [`flush`]: Framebuffer::flush
+    /// [`buffer_size`]: Framebuffer::buffer_size
+    pub unsafe fn set_back_buffer(&mut self, buffer: *mut u8) {
+        self.flush();
+        core::ptr::copy_nonoverlapping(self.address, buffer, self.buffer_size());
+        self.address = buffer;
+    }
+
+    /// Copy the regions drawn since the last flush from the back buffer to
+    /// the screen; without a back buffer there is nothing to do
+    pub fn flush(&mut self) {
+        if !self.is_back_buffered() {
+            return;
+        }
+        let bytes_per_pixel = (self.bpp / 8) as usize;
+        let copy = |offset: usize, len: usize| unsafe {
+            core::ptr::copy_nonoverlapping(self.address.add(offset), self.screen.add(offset), len);
+        };
+        for rect in self.damage.rects() {
+            if rect.left == 0 && rect.right == self.width {
+                // Full lines are one run of memory, padding included
+                copy(rect.top * self.pitch, (rect.bottom - rect.top) * self.pitch);
+            } else {
+                for y in rect.top..rect.bottom {
+                    copy(y * self.pitch + rect.left * bytes_per_pixel, (rect.right - rect.left) * bytes_per_pixel);
+                }
+            }
+        }
+        if let Some(overlay) = self.overlay {
+            let area = overlay.rect().intersect(self.bounds());
+            if self.damage.rects().iter().any(|rect| rect.touches(&area)) {
+                self.draw_overlay(overlay, area);
+            }
+        }
+        self.damage.clear();
+    }
+
+    /// Show `sprite` at `(x, y)` on the screen, or hide it with None
+    ///
+    /// The sprite is drawn over what the back buffer holds without changing
+    /// it, so moving it needs no redraw of what was under it. It shows up
+    /// on the next [`flush`](Framebuffer::flush). Without a back buffer
+    /// there is nothing to restore the screen from, and this does nothing.
+    pub fn set_overlay(&mut self, overlay: Option<(&'static Surface<'static>, usize, usize)>) {
+        if !self.is_back_buffered() {
+            return;
+        }
+        if let Some(old) = self.overlay.take() {
+            self.damage(old.rect());
+        }
+        self.overlay = overlay.map(|(sprite, x, y)| Overlay { sprite, x, y });
+        if let Some(new) = self.overlay {
+            self.damage(new.rect());
         }
     }
 
+    /// Draw the part of `overlay` in `area` on the screen, blended with the
+    /// back buffer
+    fn draw_overlay(&mut self, overlay: Overlay, area: Rect) {
+        let bytes_per_pixel = (self.bpp / 8) as usize;
+        for py in area.top..area.bottom {
+            for px in area.left..area.right {
+                let sprite = overlay.sprite;
+                let Some(&pixel) = sprite.pixels.get((py - overlay.y) * sprite.width + (px - overlay.x)) else {
+                    continue;
+                };
+                let color = match pixel >> 24 {
+                    0 => continue,
+                    255 => pixel & 0xFFFFFF,
+                    alpha => blend(pixel, self.get_pixel(px, py), alpha),
+                };
+                unsafe {
+                    (self.screen.add(py * self.pitch + px * bytes_per_pixel) as *mut u32).write(color);
+                }
+            }
+        }
+    }
+
+    /// Record that the pixels in `rect` were drawn
+    fn damage(&mut self, rect: Rect) {
+        if self.is_back_buffered() {
+            let clipped = Rect {
+                bottom: rect.bottom.min(self.height),
+                right: rect.right.min(self.width),
+                ..rect
+            };
+            self.damage.add(clipped);
+        }
+    }
+
+    /// Record that the whole screen was drawn
+    fn damage_all(&mut self) {
+        self.damage(Rect {
+            top: 0,
+            left: 0,
+            bottom: self.height,
+            right: self.width,
+        });
+    }
+
     /// Writes a pixel at the specified coordinates with the given color
     ///
     /// # Arguments
@@ -43,9 +399,22 @@ impl Framebuffer {
     ///
     /// # Safety
     /// This function performs raw memory writes to the framebuffer
+    #[allow(dead_code)]
     pub fn put_pixel(&mut self, x: usize, y: usize, color: u32) {
+        self.set_pixel(x, y, color);
+        self.damage(Rect {
+            top: y,
+            left: x,
+            bottom: y + 1,
+            right: x + 1,
+        });
+    }
+
+    /// [`put_pixel`](Framebuffer::put_pixel) for callers that record the
+    /// damage themselves
+    fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
         // Bounds check
-        if x >= self.width || y >= self.height {
+        if !self.clip.contains(x, y) {
             return;
         }
 
@@ -66,9 +435,110 @@ impl Framebuffer {
     pub fn clear(&mut self, color: u32) {
         for y in 0..self.height {
             for x in 0..self.width {
-                self.put_pixel(x, y, color);
+                self.set_pixel(x, y, color);
+            }
+        }
+        self.damage_all();
+    }
+
+    /// Reads the pixel at `(x, y)`, which must be on screen
+    fn get_pixel(&self, x: usize, y: usize) -> u32 {
+        let bytes_per_pixel = (self.bpp / 8) as usize;
+        unsafe { (self.address.add(y * self.pitch + x * bytes_per_pixel) as *const u32).read() & 0xFFFFFF }
+    }
+
+    /// Fills a rectangle with a color
+    ///
+    /// # Arguments
+    /// * `x` - X coordinate of the top left corner
+    /// * `y` - Y coordinate of the top left corner
+    /// * `width` - Width in pixels
+    /// * `height` - Height in pixels
+    /// * `color` - Color in 0xRRGGBB format
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        let rect = Rect::new(x, y, width, height).intersect(self.clip);
+        if rect.is_empty() {
+            return;
+        }
+
+        for py in rect.top..rect.bottom {
+            if self.bpp == 32 {
+                unsafe {
+                    let line = self.address.add(py * self.pitch + rect.left * 4) as *mut u32;
+                    core::slice::from_raw_parts_mut(line, rect.right - rect.left).fill(color);
+                }
+            } else {
+                for px in rect.left..rect.right {
+                    self.set_pixel(px, py, color);
+                }
+            }
+        }
+        self.damage(rect);
+    }
+
+    /// Draws a one pixel wide line from `(x0, y0)` to `(x1, y1)`, both ends
+    /// included, with Bresenham's algorithm
+    ///
+    /// # Arguments
+    /// * `color` - Color in 0xRRGGBB format
+    pub fn draw_line(&mut self, x0: usize, y0: usize, x1: usize, y1: usize, color: u32) {
+        let (mut x, mut y) = (x0 as isize, y0 as isize);
+        let (end_x, end_y) = (x1 as isize, y1 as isize);
+        let dx = (end_x - x).abs();
+        let dy = -(end_y - y).abs();
+        let step_x = if x < end_x { 1 } else { -1 };
+        let step_y = if y < end_y { 1 } else { -1 };
+        let mut error = dx + dy;
+
+        loop {
+            self.set_pixel(x as usize, y as usize, color);
+            if x == end_x && y == end_y {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+
+        let bounds = Rect {
+            top: y0.min(y1),
+            left: x0.min(x1),
+            bottom: y0.max(y1) + 1,
+            right: x0.max(x1) + 1,
+        };
+        self.damage(bounds.intersect(self.clip));
+    }
+
+    /// Draws `surface` with its top left corner at `(x, y)`, blending each
+    /// pixel with the screen by its alpha
+    pub fn blit(&mut self, surface: &Surface, x: usize, y: usize) {
+        let area = Rect::new(x, y, surface.width, surface.height).intersect(self.clip);
+        if area.is_empty() {
+            return;
+        }
+
+        for py in area.top..area.bottom {
+            for px in area.left..area.right {
+                let Some(&pixel) = surface.pixels.get((py - y) * surface.width + (px - x)) else {
+                    continue;
+                };
+                match pixel >> 24 {
+                    0 => {}
+                    255 => self.set_pixel(px, py, pixel & 0xFFFFFF),
+                    alpha => {
+                        let color = blend(pixel, self.get_pixel(px, py), alpha);
+                        self.set_pixel(px, py, color);
+                    }
+                }
             }
         }
+        self.damage(area);
     }
 
     /// Returns the width of the framebuffer in pixels
@@ -81,22 +551,215 @@ impl Framebuffer {
         self.height
     }
 
+    /// Scrolls the screen contents up by the given number of pixel rows
+    ///
+    /// The vacated rows at the bottom are filled with `bg_color`.
+    ///
+    /// # Arguments
+    /// * `rows` - Number of pixel rows to scroll
+    /// * `bg_color` - Color in 0xRRGGBB format for the exposed rows
+    pub fn scroll_up(&mut self, rows: usize, bg_color: u32) {
+        let rows = rows.min(self.height);
+        let kept = self.height - rows;
+
+        unsafe {
+            core::ptr::copy(
+                self.address.add(rows * self.pitch),
+                self.address,
+                kept * self.pitch,
+            );
+        }
+
+        for y in kept..self.height {
+            for x in 0..self.width {
+                self.set_pixel(x, y, bg_color);
+            }
+        }
+        self.damage_all();
+    }
+
+    /// Returns the width and height of the screen as seen with `rotation`
+    pub fn logical_size(&self, rotation: Rotation) -> (usize, usize) {
+        if rotation.is_sideways() {
+            (self.height(), self.width())
+        } else {
+            (self.width(), self.height())
+        }
+    }
+
+    /// Maps logical coordinates under `rotation` to a pixel on the panel
+    fn to_physical(&self, rotation: Rotation, x: usize, y: usize) -> Option<(usize, usize)> {
+        let (width, height) = self.logical_size(rotation);
+        if x >= width || y >= height {
+            return None;
+        }
+        Some(match rotation {
+            Rotation::Normal => (x, y),
+            Rotation::Clockwise => (self.width - 1 - y, x),
+            Rotation::UpsideDown => (self.width - 1 - x, self.height - 1 - y),
+            Rotation::CounterClockwise => (y, self.height - 1 - x),
+        })
+    }
+
+    /// Record that the logical rectangle at `(x, y)` of `width` by `height`
+    /// pixels under `rotation` was drawn
+    fn damage_rotated(&mut self, rotation: Rotation, x: usize, y: usize, width: usize, height: usize) {
+        let (logical_width, logical_height) = self.logical_size(rotation);
+        let (right, bottom) = ((x + width).min(logical_width), (y + height).min(logical_height));
+        if x >= right || y >= bottom {
+            return;
+        }
+        // Opposite corners stay opposite corners under rotation
+        if let (Some(a), Some(b)) = (
+            self.to_physical(rotation, x, y),
+            self.to_physical(rotation, right - 1, bottom - 1),
+        ) {
+            self.damage(Rect {
+                top: a.1.min(b.1),
+                left: a.0.min(b.0),
+                bottom: a.1.max(b.1) + 1,
+                right: a.0.max(b.0) + 1,
+            });
+        }
+    }
+
+    /// Scrolls the screen contents down by the given number of pixel rows
+    ///
+    /// The vacated rows at the top are filled with `bg_color`.
+    pub fn scroll_down(&mut self, rows: usize, bg_color: u32) {
+        let rows = rows.min(self.height);
+        let kept = self.height - rows;
+
+        unsafe {
+            core::ptr::copy(
+                self.address,
+                self.address.add(rows * self.pitch),
+                kept * self.pitch,
+            );
+        }
+
+        for y in 0..rows {
+            for x in 0..self.width {
+                self.set_pixel(x, y, bg_color);
+            }
+        }
+        self.damage_all();
+    }
+
+    /// Moves the screen contents sideways by the given number of pixel
+    /// columns, to the right if `right` is set
+    ///
+    /// The vacated columns are filled with `bg_color`.
+    fn scroll_sideways(&mut self, cols: usize, right: bool, bg_color: u32) {
+        let cols = cols.min(self.width);
+        let kept = self.width - cols;
+        let bytes_per_pixel = (self.bpp / 8) as usize;
+
+        for y in 0..self.height {
+            unsafe {
+                let line = self.address.add(y * self.pitch);
+                let (from, to) = if right { (0, cols) } else { (cols, 0) };
+                core::ptr::copy(
+                    line.add(from * bytes_per_pixel),
+                    line.add(to * bytes_per_pixel),
+                    kept * bytes_per_pixel,
+                );
+            }
+            let exposed = if right { 0..cols } else { kept..self.width };
+            for x in exposed {
+                self.set_pixel(x, y, bg_color);
+            }
+        }
+        self.damage_all();
+    }
+
+    /// Scrolls the screen contents up by `pixels` as seen with `rotation`
+    ///
+    /// The vacated logical rows at the bottom are filled with `bg_color`.
+    pub fn scroll_rotated(&mut self, rotation: Rotation, pixels: usize, bg_color: u32) {
+        match rotation {
+            Rotation::Normal => self.scroll_up(pixels, bg_color),
+            Rotation::Clockwise => self.scroll_sideways(pixels, true, bg_color),
+            Rotation::UpsideDown => self.scroll_down(pixels, bg_color),
+            Rotation::CounterClockwise => self.scroll_sideways(pixels, false, bg_color),
+        }
+    }
+
     /// Draws a single character at the specified position
     ///
     /// # Arguments
+    /// * `font` - Font to draw the character in
     /// * `c` - Character to draw
     /// * `x` - X coordinate (horizontal position in pixels)
     /// * `y` - Y coordinate (vertical position in pixels)
     /// * `fg_color` - Foreground color in 0xRRGGBB format
     /// * `bg_color` - Background color in 0xRRGGBB format
-    pub fn draw_char(&mut self, c: char, x: usize, y: usize, fg_color: u32, bg_color: u32) {
-        let glyph = get_font_glyph(c);
+    pub fn draw_char(&mut self, font: &Font, c: char, x: usize, y: usize, fg_color: u32, bg_color: u32) {
+        let glyph = font.glyph(c);
+        let (width, height) = (font.width(), font.height());
+        let rect = Rect::new(x, y, width, height);
+        self.damage(rect);
+
+        // Fast path: whole glyph inside the clip in 32 bpp, write scanlines directly
+        if self.bpp == 32 && self.clip.covers(&rect) {
+            for row in 0..height {
+                unsafe {
+                    let line = self.address.add((y + row) * self.pitch + x * 4) as *mut u32;
+                    for col in 0..width {
+                        let color = if glyph.pixel(row, col) { fg_color } else { bg_color };
+                        line.add(col).write_volatile(color);
+                    }
+                }
+            }
+            return;
+        }
+
+        for row in 0..height {
+            for col in 0..width {
+                let color = if glyph.pixel(row, col) { fg_color } else { bg_color };
+                self.set_pixel(x + col, y + row, color);
+            }
+        }
+    }
+
+    /// Draws a character enlarged `scale` times on a rotated screen
+    ///
+    /// # Arguments
+    /// * `font` - Font to draw the character in
+    /// * `c` - Character to draw
+    /// * `x` - Logical X coordinate of the glyph's top left corner
+    /// * `y` - Logical Y coordinate of the glyph's top left corner
+    /// * `scale` - Integer scale factor; the glyph covers `scale` times its
+    ///   size in pixels
+    /// * `rotation` - Orientation the coordinates are given in
+    /// * `fg_color` - Foreground color in 0xRRGGBB format
+    /// * `bg_color` - Background color in 0xRRGGBB format
+    pub fn draw_char_transformed(
+        &mut self,
+        font: &Font,
+        c: char,
+        x: usize,
+        y: usize,
+        scale: usize,
+        rotation: Rotation,
+        fg_color: u32,
+        bg_color: u32,
+    ) {
+        if scale <= 1 && rotation == Rotation::Normal {
+            self.draw_char(font, c, x, y, fg_color, bg_color);
+            return;
+        }
 
-        for row in 0..8 {
-            for col in 0..8 {
-                let bit = (glyph[row] >> (7 - col)) & 1;
-                let color = if bit == 1 { fg_color } else { bg_color };
-                self.put_pixel(x + col, y + row, color);
+        let scale = scale.max(1);
+        let glyph = font.glyph(c);
+        let (width, height) = (font.width() * scale, font.height() * scale);
+        self.damage_rotated(rotation, x, y, width, height);
+        for row in 0..height {
+            for col in 0..width {
+                let color = if glyph.pixel(row / scale, col / scale) { fg_color } else { bg_color };
+                if let Some((px, py)) = self.to_physical(rotation, x + col, y + row) {
+                    self.set_pixel(px, py, color);
+                }
             }
         }
     }
@@ -110,6 +773,8 @@ impl Framebuffer {
     /// * `fg_color` - Foreground color in 0xRRGGBB format
     /// * `bg_color` - Background color in 0xRRGGBB format
     pub fn write_string(&mut self, text: &str, x: usize, y: usize, fg_color: u32, bg_color: u32) {
+        let font = &font::BUILTIN;
+        let (width, height) = (font.width(), font.height());
         let mut current_x = x;
         let mut current_y = y;
 
@@ -117,128 +782,177 @@ impl Framebuffer {
             // Handle newline
             if c == '\n' {
                 current_x = x;
-                current_y += 8;
+                current_y += height;
                 continue;
             }
 
             // Handle wrapping
-            if current_x + 8 > self.width {
+            if current_x + width > self.width {
                 current_x = x;
-                current_y += 8;
+                current_y += height;
             }
 
             // Stop if we've reached the bottom of the screen
-            if current_y + 8 > self.height {
+            if current_y + height > self.height {
                 break;
             }
 
-            self.draw_char(c, current_x, current_y, fg_color, bg_color);
-            current_x += 8;
+            self.draw_char(font, c, current_x, current_y, fg_color, bg_color);
+            current_x += width;
         }
     }
 }
 
-/// Simple 8x8 bitmap font
-/// Each character is represented by 8 bytes, one per row
-/// Each bit represents a pixel (1 = foreground, 0 = background)
-fn get_font_glyph(c: char) -> [u8; 8] {
-    match c {
-        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-        '!' => [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00],
-        '"' => [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-        '#' => [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00],
-        '$' => [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00],
-        '%' => [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00],
-        '&' => [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00],
-        '\'' => [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00],
-        '(' => [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00],
-        ')' => [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00],
-        '*' => [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00],
-        '+' => [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00],
-        ',' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06],
-        '-' => [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00],
-        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00],
-        '/' => [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00],
-        '0' => [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00],
-        '1' => [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00],
-        '2' => [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00],
-        '3' => [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00],
-        '4' => [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00],
-        '5' => [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00],
-        '6' => [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00],
-        '7' => [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00],
-        '8' => [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00],
-        '9' => [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00],
-        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00],
-        ';' => [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06],
-        '<' => [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00],
-        '=' => [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00],
-        '>' => [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00],
-        '?' => [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00],
-        '@' => [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00],
-        'A' => [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00],
-        'B' => [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00],
-        'C' => [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00],
-        'D' => [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00],
-        'E' => [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00],
-        'F' => [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00],
-        'G' => [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00],
-        'H' => [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00],
-        'I' => [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00],
-        'J' => [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00],
-        'K' => [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00],
-        'L' => [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00],
-        'M' => [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00],
-        'N' => [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00],
-        'O' => [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00],
-        'P' => [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00],
-        'Q' => [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00],
-        'R' => [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00],
-        'S' => [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00],
-        'T' => [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00],
-        'U' => [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00],
-        'V' => [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00],
-        'W' => [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00],
-        'X' => [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00],
-        'Y' => [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00],
-        'Z' => [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00],
-        '[' => [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00],
-        '\\' => [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00],
-        ']' => [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00],
-        '^' => [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00],
-        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF],
-        '`' => [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00],
-        'a' => [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00],
-        'b' => [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00],
-        'c' => [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00],
-        'd' => [0x38, 0x30, 0x30, 0x3e, 0x33, 0x33, 0x6E, 0x00],
-        'e' => [0x00, 0x00, 0x1E, 0x33, 0x3f, 0x03, 0x1E, 0x00],
-        'f' => [0x1C, 0x36, 0x06, 0x0f, 0x06, 0x06, 0x0F, 0x00],
-        'g' => [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F],
-        'h' => [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00],
-        'i' => [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00],
-        'j' => [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E],
-        'k' => [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00],
-        'l' => [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00],
-        'm' => [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00],
-        'n' => [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00],
-        'o' => [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00],
-        'p' => [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F],
-        'q' => [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78],
-        'r' => [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00],
-        's' => [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00],
-        't' => [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00],
-        'u' => [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00],
-        'v' => [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00],
-        'w' => [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00],
-        'x' => [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00],
-        'y' => [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F],
-        'z' => [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00],
-        '{' => [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00],
-        '|' => [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00],
-        '}' => [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00],
-        '~' => [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-        '✨' => [0x00, 0x24, 0x18, 0xFF, 0x18, 0x24, 0x00, 0x00], // Sparkle emoji approximation
-        _ => [0x7E, 0x81, 0xA5, 0x81, 0xBD, 0x99, 0x81, 0x7E], // Default: smiley face for unknown chars
+crate::kernel_test! {
+    /// A glyph drawn with each rotation lands on the panel turned
+    /// accordingly, and scrolling moves it toward the logical top
+    fn framebuffer_rotation() {
+        const SIZE: usize = 16;
+        static PIXELS: spin::Mutex<[u32; SIZE * SIZE]> = spin::Mutex::new([0; SIZE * SIZE]);
+        let mut pixels = PIXELS.lock();
+        let address = pixels.as_mut_ptr() as *mut u8;
+        let mut fb = Framebuffer {
+            address,
+            screen: address,
+            width: SIZE,
+            height: SIZE,
+            pitch: SIZE * 4,
+            bpp: 32,
+            damage: Damage::new(),
+            clip: Rect::new(0, 0, SIZE, SIZE),
+            overlay: None,
+        };
+        let glyph = font::BUILTIN.glyph('F');
+
+        for rotation in [
+            Rotation::Normal,
+            Rotation::Clockwise,
+            Rotation::UpsideDown,
+            Rotation::CounterClockwise,
+        ] {
+            fb.clear(0);
+            fb.draw_char_transformed(&font::BUILTIN, 'F', 0, 8, 1, rotation, 1, 0);
+            fb.scroll_rotated(rotation, 8, 0);
+            for row in 0..8 {
+                for col in 0..8 {
+                    let (x, y) = fb.to_physical(rotation, col, row).unwrap_or((0, 0));
+                    let expected = glyph.pixel(row, col) as u32;
+                    crate::ktest_assert_eq!(pixels[y * SIZE + x], expected, "rotated pixel");
+                }
+            }
+        }
+
+        fb.clear(0);
+        fb.draw_char_transformed(&font::BUILTIN, 'F', 0, 0, 2, Rotation::Normal, 1, 0);
+        let expected = glyph.pixel(1, 1) as u32;
+        crate::ktest_assert_eq!(pixels[3 * SIZE + 3], expected, "2x scaled pixel");
+        Ok(())
+    }
+}
+
+crate::kernel_test! {
+    /// With a back buffer, drawing reaches the screen on flush, only the
+    /// damaged regions are copied, and the overlay stays off the back buffer
+    fn framebuffer_back_buffer() {
+        const SIZE: usize = 32;
+        static SCREEN: spin::Mutex<[u32; SIZE * SIZE]> = spin::Mutex::new([0; SIZE * SIZE]);
+        static BACK: spin::Mutex<[u32; SIZE * SIZE]> = spin::Mutex::new([0; SIZE * SIZE]);
+        let mut screen = SCREEN.lock();
+        let mut back = BACK.lock();
+        screen.fill(7);
+        let address = screen.as_mut_ptr() as *mut u8;
+        let mut fb = Framebuffer {
+            address,
+            screen: address,
+            width: SIZE,
+            height: SIZE,
+            pitch: SIZE * 4,
+            bpp: 32,
+            damage: Damage::new(),
+            clip: Rect::new(0, 0, SIZE, SIZE),
+            overlay: None,
+        };
+        unsafe { fb.set_back_buffer(back.as_mut_ptr() as *mut u8) };
+        crate::ktest_assert_eq!(back[5 * SIZE + 5], 7, "back buffer not copied from the screen");
+
+        fb.draw_char(&font::BUILTIN, '#', 0, 0, 1, 0);
+        fb.draw_char(&font::BUILTIN, '#', 8, 0, 1, 0);
+        fb.draw_char(&font::BUILTIN, '#', 16, 16, 1, 0);
+        crate::ktest_assert_eq!(fb.damage.rects().len(), 2, "adjacent glyphs not merged");
+        crate::ktest_assert_eq!(screen[0], 7, "drawing reached the screen before flush");
+
+        // Undamaged pixels are left alone
+        back[31 * SIZE + 31] = 9;
+        fb.flush();
+        crate::ktest_assert_eq!(fb.damage.rects().len(), 0, "damage left after flush");
+        for (y, x) in [(0, 0), (3, 12), (20, 20)] {
+            crate::ktest_assert_eq!(screen[y * SIZE + x], back[y * SIZE + x], "damaged pixel not flushed");
+        }
+        crate::ktest_assert_eq!(screen[31 * SIZE + 31], 7, "undamaged pixel flushed");
+
+        fb.scroll_up(8, 0);
+        fb.flush();
+        crate::ktest_assert!((screen[..] == back[..]), "scrolled screen differs from the back buffer");
+
+        // The overlay is drawn on the screen only, and hiding it restores
+        // what was under it
+        static SPRITE: Surface<'static> = Surface { width: 2, height: 1, pixels: &[0xFF00_0005, 0] };
+        fb.set_overlay(Some((&SPRITE, 4, 4)));
+        fb.flush();
+        crate::ktest_assert_eq!(screen[4 * SIZE + 4], 5, "overlay not drawn");
+        crate::ktest_assert_eq!(screen[4 * SIZE + 5], back[4 * SIZE + 5], "transparent overlay pixel drawn");
+        crate::ktest_assert!((back[4 * SIZE + 4] != 5), "overlay drawn into the back buffer");
+        fb.set_overlay(None);
+        fb.flush();
+        crate::ktest_assert!((screen[..] == back[..]), "hidden overlay left on the screen");
+        Ok(())
+    }
+}
+
+crate::kernel_test! {
+    /// Rectangles stop at the clip, lines reach both ends with one pixel
+    /// per column, and blitting blends by alpha
+    fn framebuffer_primitives() {
+        const SIZE: usize = 16;
+        static PIXELS: spin::Mutex<[u32; SIZE * SIZE]> = spin::Mutex::new([0; SIZE * SIZE]);
+        let mut pixels = PIXELS.lock();
+        let address = pixels.as_mut_ptr() as *mut u8;
+        let mut fb = Framebuffer {
+            address,
+            screen: address,
+            width: SIZE,
+            height: SIZE,
+            pitch: SIZE * 4,
+            bpp: 32,
+            damage: Damage::new(),
+            clip: Rect::new(0, 0, SIZE, SIZE),
+            overlay: None,
+        };
+
+        fb.clear(0);
+        fb.set_clip(Rect::new(4, 4, 8, 8));
+        fb.fill_rect(0, 0, SIZE, SIZE, 1);
+        fb.reset_clip();
+        crate::ktest_assert_eq!(pixels[3 * SIZE + 3], 0, "filled outside the clip");
+        crate::ktest_assert_eq!(pixels[4 * SIZE + 4], 1, "clip corner not filled");
+        crate::ktest_assert_eq!(pixels[11 * SIZE + 11], 1, "clip corner not filled");
+        crate::ktest_assert_eq!(pixels[12 * SIZE + 12], 0, "filled outside the clip");
+
+        fb.clear(0);
+        fb.draw_line(15, 7, 0, 0, 2);
+        crate::ktest_assert_eq!(pixels[0], 2, "line start missing");
+        crate::ktest_assert_eq!(pixels[7 * SIZE + 15], 2, "line end missing");
+        for x in 0..SIZE {
+            let set = (0..SIZE).filter(|&y| pixels[y * SIZE + x] == 2).count();
+            crate::ktest_assert_eq!(set, 1, "line column not one pixel");
+        }
+
+        fb.clear(0x0000FF);
+        let image = [0x80FF_0000, 0x00FF_FFFF, 0xFF00_FF00];
+        fb.blit(&Surface { width: 3, height: 1, pixels: &image }, 0, 0);
+        crate::ktest_assert_eq!(pixels[0], 0x80007F, "half transparent pixel not blended");
+        crate::ktest_assert_eq!(pixels[1], 0x0000FF, "transparent pixel drawn");
+        crate::ktest_assert_eq!(pixels[2], 0x00FF00, "opaque pixel not drawn");
+        Ok(())
     }
 }
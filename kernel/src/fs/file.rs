@@ -0,0 +1,129 @@
+//! Read-only open files
+//!
+//! `SYS_OPEN` opens two kinds of files besides devices: regular files of
+//! the initrd (see [`initrd`](super::initrd)) and the files of `/proc`
+//! (see [`proc`](super::proc)). Both are read-only. An open file is an
+//! entry here with its read offset, shared by every handle duplicated from
+//! the one `open` returned, and freed when the last of them is closed.
+//!
+//! Initrd files are read in place. A `/proc` file is generated again on
+//! each read, so a reader that reads it in pieces may see the content
+//! change between them.
+
+use super::proc::{self, ProcPath};
+use crate::sync::SpinLock;
+
+/// Maximum number of open files
+const MAX_OPEN_FILES: usize = 32;
+
+/// Open file errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileError {
+    /// No such file
+    NotFound,
+    /// No open file with this id
+    BadFile,
+    /// The open file table is full
+    TooManyFiles,
+    /// The process a `/proc` file describes has exited
+    Gone,
+}
+
+/// What an open file reads from
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    Initrd(&'static [u8]),
+    Proc(ProcPath),
+}
+
+struct OpenFile {
+    node: Node,
+    offset: usize,
+    /// Handles referring to the file
+    refs: usize,
+}
+
+static FILES: SpinLock<[Option<OpenFile>; MAX_OPEN_FILES]> = SpinLock::new([const { None }; MAX_OPEN_FILES]);
+
+/// Node of the file at `path`
+fn lookup(path: &str) -> Result<Node, FileError> {
+    if path == "/proc" || path.starts_with("/proc/") {
+        let proc_path = proc::parse_proc_path(path);
+        // Directories and unknown names fail here; a PID that does not
+        // exist fails the trial read
+        proc::read(proc_path, &mut [], 0).map_err(|_| FileError::NotFound)?;
+        return Ok(Node::Proc(proc_path));
+    }
+    super::initrd::find(path).map(|entry| Node::Initrd(entry.data)).ok_or(FileError::NotFound)
+}
+
+/// Open the file at `path` for reading; returns its id with one reference
+pub fn open(path: &str) -> Result<u32, FileError> {
+    let node = lookup(path)?;
+    let mut files = FILES.lock();
+    let id = files.iter().position(Option::is_none).ok_or(FileError::TooManyFiles)?;
+    files[id] = Some(OpenFile { node, offset: 0, refs: 1 });
+    Ok(id as u32)
+}
+
+pub fn retain(id: u32) {
+    if let Some(Some(file)) = FILES.lock().get_mut(id as usize) {
+        file.refs += 1;
+    }
+}
+
+pub fn release(id: u32) {
+    if let Some(slot) = FILES.lock().get_mut(id as usize) {
+        if let Some(file) = slot {
+            file.refs -= 1;
+            if file.refs == 0 {
+                *slot = None;
+            }
+        }
+    }
+}
+
+/// Read from file `id` at its offset into `buf`, advancing the offset;
+/// returns the bytes read, 0 at the end of the file
+pub fn read(id: u32, buf: &mut [u8]) -> Result<usize, FileError> {
+    let (node, offset) = match FILES.lock().get(id as usize) {
+        Some(Some(file)) => (file.node, file.offset),
+        _ => return Err(FileError::BadFile),
+    };
+    // /proc content is generated without the table locked
+    let count = match node {
+        Node::Initrd(data) => {
+            let rest = data.get(offset..).unwrap_or(&[]);
+            let count = rest.len().min(buf.len());
+            buf[..count].copy_from_slice(&rest[..count]);
+            count
+        }
+        Node::Proc(proc_path) => proc::read(proc_path, buf, offset).map_err(|_| FileError::Gone)?,
+    };
+    if let Some(Some(file)) = FILES.lock().get_mut(id as usize) {
+        file.offset = offset + count;
+    }
+    Ok(count)
+}
+
+crate::kernel_test! {
+    /// /proc files open and read from the start, advancing the offset;
+    /// unknown paths and directories do not open
+    fn open_files_read_sequentially() {
+        crate::ktest_assert_eq!(open("/no/such/file"), Err(FileError::NotFound), "missing file opened");
+        crate::ktest_assert_eq!(open("/proc"), Err(FileError::NotFound), "directory opened");
+
+        let id = open("/proc/uptime").map_err(|_| "/proc/uptime did not open")?;
+        let mut first = [0u8; 4];
+        crate::ktest_assert_eq!(read(id, &mut first), Ok(4), "short first read");
+        let mut rest = [0u8; 64];
+        let count = read(id, &mut rest).map_err(|_| "second read failed")?;
+        crate::ktest_assert!((count > 0), "offset not advanced or file empty");
+        retain(id);
+        release(id);
+        crate::ktest_assert!(read(id, &mut rest).is_ok(), "file freed with a reference left");
+        release(id);
+        crate::ktest_assert_eq!(read(id, &mut rest), Err(FileError::BadFile), "file outlived its last reference");
+        Ok(())
+    }
+}
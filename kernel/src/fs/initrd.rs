@@ -0,0 +1,123 @@
+//! Initial ramdisk
+//!
+//! The bootloader loads `/boot/initrd.tar` as a Limine module: a ustar
+//! archive with the programs the kernel can start before there is any
+//! filesystem (`/sbin/init` and the services init spawns). Files are used
+//! in place in the module's memory, so the data [`find`] returns lives as
+//! long as the kernel.
+//!
+//! Only regular files are looked up. Paths are matched without a leading
+//! `/` or `./`; the ustar prefix field is not used, so names are limited
+//! to 100 bytes.
+
+use spin::Once;
+
+/// Size of a ustar header and of the blocks file data is padded to
+const BLOCK_SIZE: usize = 512;
+
+/// The archive, once the boot module has been found
+static IMAGE: Once<&'static [u8]> = Once::new();
+
+/// A regular file in the archive
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    /// Path without a leading `/` or `./`
+    pub path: &'a str,
+    pub data: &'a [u8],
+}
+
+/// Regular files of a ustar archive, in archive order
+///
+/// Stops at the end-of-archive block or at the first header that does not
+/// parse.
+struct Entries<'a> {
+    image: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Entry<'a>> {
+        loop {
+            let header = self.image.get(self.offset..self.offset + BLOCK_SIZE)?;
+            if header[0] == 0 {
+                return None;
+            }
+            let size = parse_octal(&header[124..136])?;
+            let start = self.offset + BLOCK_SIZE;
+            let data = self.image.get(start..start.checked_add(size)?)?;
+            self.offset = start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+            // Regular files only ('0', or NUL in old archives)
+            if header[156] != b'0' && header[156] != 0 {
+                continue;
+            }
+            let name = &header[..100];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            let Ok(name) = core::str::from_utf8(name) else { continue };
+            return Some(Entry { path: normalize(name), data });
+        }
+    }
+}
+
+/// Value of a NUL- or space-terminated octal field
+fn parse_octal(field: &[u8]) -> Option<usize> {
+    let mut value = 0usize;
+    for &b in field.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => value = value.checked_mul(8)?.checked_add((b - b'0') as usize)?,
+            0 | b' ' => break,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+/// `path` without a leading `/` or `./`
+fn normalize(path: &str) -> &str {
+    path.trim_start_matches("./").trim_start_matches('/')
+}
+
+/// Use `image` as the initrd; returns the number of files in it
+pub fn init(image: &'static [u8]) -> usize {
+    let image = IMAGE.call_once(|| image);
+    Entries { image, offset: 0 }.count()
+}
+
+/// Contents of the regular file at `path`, if the initrd has it
+pub fn find(path: &str) -> Option<Entry<'static>> {
+    let path = normalize(path);
+    entries().find(|entry| entry.path == path)
+}
+
+/// Regular files in the initrd (none if there is no initrd)
+pub fn entries() -> impl Iterator<Item = Entry<'static>> {
+    Entries { image: IMAGE.get().copied().unwrap_or(&[]), offset: 0 }
+}
+
+crate::kernel_test! {
+    /// Files are found by path with or without a leading slash, skipping
+    /// directories, and the data ends at the recorded size
+    fn initrd_finds_files() {
+        let mut archive = [0u8; 5 * BLOCK_SIZE];
+        let mut header = |block: usize, name: &[u8], size: &[u8], kind: u8| {
+            let header = &mut archive[block * BLOCK_SIZE..];
+            header[..name.len()].copy_from_slice(name);
+            header[124..124 + size.len()].copy_from_slice(size);
+            header[156] = kind;
+        };
+        header(0, b"./sbin/", b"00000000000", b'5');
+        header(1, b"./sbin/init", b"00000000005", b'0');
+        archive[2 * BLOCK_SIZE..2 * BLOCK_SIZE + 5].copy_from_slice(b"hello");
+
+        let mut entries = Entries { image: &archive, offset: 0 };
+        let Some(entry) = entries.next() else { return Err("file not found") };
+        crate::ktest_assert_eq!(entry.path, "sbin/init", "path not normalized");
+        crate::ktest_assert_eq!(entry.data, &b"hello"[..], "wrong data");
+        crate::ktest_assert!(entries.next().is_none(), "read past the end of the archive");
+        crate::ktest_assert_eq!(normalize("/sbin/init"), "sbin/init", "leading slash kept");
+        crate::ktest_assert_eq!(parse_octal(b"  644 \0"), Some(0o644), "octal field misread");
+        Ok(())
+    }
+}
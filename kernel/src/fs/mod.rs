@@ -2,4 +2,30 @@
 //!
 //! This module contains filesystem implementations.
 
+pub mod file;
+pub mod initrd;
 pub mod proc;
+
+/// File mode creation mask a new process starts with
+pub const DEFAULT_UMASK: u32 = 0o022;
+
+/// Permission and set-id/sticky bits that `mode` arguments may carry
+pub const MODE_MASK: u32 = 0o7777;
+
+/// Mode a newly created file gets: the requested mode minus the umask
+///
+/// Every create path (open with O_CREAT, mkdir, mknod, ...) must go through
+/// this so the creating process's umask is honoured.
+pub fn creation_mode(requested: u32, umask: u32) -> u32 {
+    requested & MODE_MASK & !umask
+}
+
+crate::kernel_test! {
+    /// umask bits are cleared from the requested mode
+    fn creation_mode_applies_umask() {
+        crate::ktest_assert_eq!(creation_mode(0o666, DEFAULT_UMASK), 0o644, "umask 022 on 0666");
+        crate::ktest_assert_eq!(creation_mode(0o777, 0o077), 0o700, "umask 077 on 0777");
+        crate::ktest_assert_eq!(creation_mode(0o170_644, 0), 0o644, "file type bits kept");
+        Ok(())
+    }
+}
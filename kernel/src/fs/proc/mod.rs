@@ -33,6 +33,7 @@ pub fn init() {
     crate::serial_println!("[PROC] Virtual filesystem initialized");
     crate::serial_println!("[PROC] Available at /proc");
 }
+crate::initcall!(Fs, init);
 
 /// Process state for /proc/<pid>/stat
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -92,6 +93,10 @@ pub struct ProcInfo {
     pub vsize: usize,
     /// Resident set size (pages)
     pub rss: usize,
+    /// Kernel stack size (bytes)
+    pub kstack_size: usize,
+    /// Most kernel stack ever in use (bytes)
+    pub kstack_max: usize,
 }
 
 impl ProcInfo {
@@ -113,6 +118,8 @@ impl ProcInfo {
             stime: 0,
             vsize: 0,
             rss: 0,
+            kstack_size: 0,
+            kstack_max: 0,
         }
     }
 
@@ -218,7 +225,9 @@ impl ProcInfo {
              Pgid:\t{}\n\
              Sid:\t{}\n\
              VmSize:\t{} kB\n\
-             VmRSS:\t{} kB\n",
+             VmRSS:\t{} kB\n\
+             KStack:\t{} bytes\n\
+             KStackMax:\t{} bytes\n",
             self.get_comm(),
             self.state.to_char(),
             match self.state {
@@ -233,6 +242,8 @@ impl ProcInfo {
             self.sid,
             self.vsize / 1024,
             self.rss * 4, // Assuming 4KB pages
+            self.kstack_size,
+            self.kstack_max,
         );
         writer.pos
     }
@@ -284,6 +295,12 @@ pub struct MemInfo {
     pub buffers: usize,
     /// Cached memory (kB)
     pub cached: usize,
+    /// Recently referenced user memory (kB)
+    pub active: usize,
+    /// User memory idle for several scans (kB)
+    pub inactive: usize,
+    /// Anonymous memory mapped with 2 MiB pages (kB)
+    pub anon_huge_pages: usize,
 }
 
 impl MemInfo {
@@ -315,8 +332,18 @@ impl MemInfo {
              MemFree:        {} kB\n\
              MemAvailable:   {} kB\n\
              Buffers:        {} kB\n\
-             Cached:         {} kB\n",
-            self.mem_total, self.mem_free, self.mem_available, self.buffers, self.cached,
+             Cached:         {} kB\n\
+             Active:         {} kB\n\
+             Inactive:       {} kB\n\
+             AnonHugePages:  {} kB\n",
+            self.mem_total,
+            self.mem_free,
+            self.mem_available,
+            self.buffers,
+            self.cached,
+            self.active,
+            self.inactive,
+            self.anon_huge_pages,
         );
         writer.pos
     }
@@ -471,6 +498,8 @@ pub enum ProcPath {
     PidStatus(usize),
     /// /proc/<pid>/cmdline file
     PidCmdline(usize),
+    /// /proc/<pid>/smaps-lite file (per-region page counts)
+    PidSmapsLite(usize),
     /// /proc/self symlink
     Self_,
     /// /proc/meminfo file
@@ -489,6 +518,24 @@ pub enum ProcPath {
     DebugSessions,
     /// /proc/debug/locks file
     DebugLocks,
+    /// /proc/efi file (firmware type, boot time, Secure Boot)
+    Efi,
+    /// /proc/hwinfo file (hardware inventory taken at boot)
+    HwInfo,
+    /// /proc/kmsg file (kernel log ring)
+    Kmsg,
+    /// /proc/workqueues file (work queue depths and counts)
+    WorkQueues,
+    /// /proc/idle file (time in each idle state per CPU)
+    Idle,
+    /// /proc/sched file (scheduler statistics per priority and per task)
+    Sched,
+    /// /proc/trace file (tracepoints, whether enabled, and hits)
+    Trace,
+    /// /proc/net directory
+    NetDir,
+    /// /proc/net/dev file (per-interface packet statistics)
+    NetDev,
     /// Unknown/invalid path
     Invalid,
 }
@@ -531,11 +578,17 @@ pub fn parse_proc_path(path: &str) -> ProcPath {
                 "locks" => ProcPath::DebugLocks,
                 _ => ProcPath::Invalid,
             }
+        } else if first == "net" {
+            match second {
+                "dev" => ProcPath::NetDev,
+                _ => ProcPath::Invalid,
+            }
         } else if let Ok(pid) = first.parse::<usize>() {
             match second {
                 "stat" => ProcPath::PidStat(pid),
                 "status" => ProcPath::PidStatus(pid),
                 "cmdline" => ProcPath::PidCmdline(pid),
+                "smaps-lite" => ProcPath::PidSmapsLite(pid),
                 _ => ProcPath::Invalid,
             }
         } else {
@@ -549,7 +602,15 @@ pub fn parse_proc_path(path: &str) -> ProcPath {
             "cpuinfo" => ProcPath::CpuInfo,
             "uptime" => ProcPath::Uptime,
             "stat" => ProcPath::Stat,
+            "efi" => ProcPath::Efi,
+            "hwinfo" => ProcPath::HwInfo,
+            "kmsg" => ProcPath::Kmsg,
+            "workqueues" => ProcPath::WorkQueues,
+            "idle" => ProcPath::Idle,
+            "sched" => ProcPath::Sched,
+            "trace" => ProcPath::Trace,
             "debug" => ProcPath::DebugDir,
+            "net" => ProcPath::NetDir,
             pid_str => {
                 // Try to parse as PID
                 if let Ok(pid) = pid_str.parse::<usize>() {
@@ -575,16 +636,27 @@ pub fn parse_proc_path(path: &str) -> ProcPath {
 /// # Returns
 /// The number of bytes written to the buffer, or an error code
 pub fn proc_read(path: &str, buf: &mut [u8], offset: usize) -> Result<usize, i32> {
-    let proc_path = parse_proc_path(path);
+    read(parse_proc_path(path), buf, offset)
+}
 
+/// Read data from the /proc file at `proc_path`, as [`proc_read`] does
+pub fn read(proc_path: ProcPath, buf: &mut [u8], offset: usize) -> Result<usize, i32> {
     match proc_path {
         ProcPath::PidStat(pid) => read_pid_stat(pid, buf, offset),
         ProcPath::PidStatus(pid) => read_pid_status(pid, buf, offset),
         ProcPath::PidCmdline(pid) => read_pid_cmdline(pid, buf, offset),
+        ProcPath::PidSmapsLite(pid) => read_pid_smaps_lite(pid, buf, offset),
         ProcPath::MemInfo => read_meminfo(buf, offset),
         ProcPath::CpuInfo => read_cpuinfo(buf, offset),
         ProcPath::Uptime => read_uptime(buf, offset),
         ProcPath::Stat => read_stat(buf, offset),
+        ProcPath::Efi => read_efi(buf, offset),
+        ProcPath::HwInfo => Ok(crate::hwinfo::read(buf, offset)),
+        ProcPath::Kmsg => Ok(crate::log::klog_read(buf, offset)),
+        ProcPath::WorkQueues => read_workqueues(buf, offset),
+        ProcPath::Idle => read_idle(buf, offset),
+        ProcPath::Sched => read_sched(buf, offset),
+        ProcPath::Trace => read_trace(buf, offset),
         ProcPath::Self_ => {
             // /proc/self should be handled as a symlink by the caller
             Err(-22) // EINVAL
@@ -592,6 +664,7 @@ pub fn proc_read(path: &str, buf: &mut [u8], offset: usize) -> Result<usize, i32
         ProcPath::DebugPty => read_debug_pty(buf, offset),
         ProcPath::DebugSessions => read_debug_sessions(buf, offset),
         ProcPath::DebugLocks => read_debug_locks(buf, offset),
+        ProcPath::NetDev => read_net_dev(buf, offset),
         _ => Err(-2), // ENOENT
     }
 }
@@ -629,6 +702,41 @@ fn read_pid_cmdline(pid: usize, buf: &mut [u8], offset: usize) -> Result<usize,
     copy_with_offset(&temp_buf[..len], buf, offset)
 }
 
+/// Read /proc/<pid>/smaps-lite file
+///
+/// One line per memory region of the process with its size and resident,
+/// copy-on-write and shared KiB, counted from the page tables at read time.
+/// For a thread this is the address space of its process.
+fn read_pid_smaps_lite(pid: usize, buf: &mut [u8], offset: usize) -> Result<usize, i32> {
+    use core::fmt::Write;
+
+    struct BufWriter<'a> {
+        buf: &'a mut [u8],
+        pos: usize,
+    }
+
+    impl<'a> Write for BufWriter<'a> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let bytes = s.as_bytes();
+            let remaining = self.buf.len() - self.pos;
+            let to_write = bytes.len().min(remaining);
+            self.buf[self.pos..self.pos + to_write].copy_from_slice(&bytes[..to_write]);
+            self.pos += to_write;
+            Ok(())
+        }
+    }
+
+    let task = crate::sched::get_task_by_id(pid).ok_or(-3)?; // ESRCH
+    let space = crate::sched::get_task_by_id(task.pid).ok_or(-3)?;
+
+    let mut temp_buf = [0u8; 4096];
+    let mut writer = BufWriter { buf: &mut temp_buf, pos: 0 };
+    let _ = crate::mm::smaps::write_smaps(space, &mut writer);
+    let len = writer.pos;
+
+    copy_with_offset(&temp_buf[..len], buf, offset)
+}
+
 /// Read /proc/meminfo file
 fn read_meminfo(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
     let mem_info = get_meminfo();
@@ -665,6 +773,35 @@ fn read_uptime(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
     copy_with_offset(&temp_buf[..len], buf, offset)
 }
 
+/// Read /proc/efi file
+fn read_efi(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
+    use core::fmt::Write;
+
+    struct BufWriter<'a> {
+        buf: &'a mut [u8],
+        pos: usize,
+    }
+
+    impl<'a> Write for BufWriter<'a> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let bytes = s.as_bytes();
+            let remaining = self.buf.len() - self.pos;
+            let to_write = bytes.len().min(remaining);
+            self.buf[self.pos..self.pos + to_write].copy_from_slice(&bytes[..to_write]);
+            self.pos += to_write;
+            Ok(())
+        }
+    }
+
+    let info = crate::efi::info().ok_or(-2)?; // ENOENT
+    let mut temp_buf = [0u8; 512];
+    let mut writer = BufWriter { buf: &mut temp_buf, pos: 0 };
+    let _ = info.write_to(&mut writer);
+    let len = writer.pos;
+
+    copy_with_offset(&temp_buf[..len], buf, offset)
+}
+
 /// Read /proc/stat file (system-wide statistics)
 fn read_stat(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
     use core::fmt::Write;
@@ -716,6 +853,46 @@ fn read_stat(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
     // Interrupts
     let _ = write!(writer, "intr {}\n", m.get_interrupts());
 
+    // Spurious and unexpected interrupts
+    let spurious = crate::arch::x86_64::spurious::stats();
+    let _ = write!(writer, "intr_spurious_pic {}\n", spurious.pic);
+    let _ = write!(writer, "intr_spurious_apic {}\n", spurious.apic);
+    let _ = write!(writer, "intr_unexpected {}\n", spurious.unexpected);
+
+    // Kernel cache reclaim: priority, runs and objects freed per shrinker
+    crate::mm::shrink::for_each(|shrinker| {
+        let _ = write!(
+            writer,
+            "shrinker {} {} {} {}\n",
+            shrinker.name,
+            shrinker.priority,
+            shrinker.runs(),
+            shrinker.freed()
+        );
+    });
+
+    // Kernel heap exhaustion: allocations that found it full, and those
+    // that failed even after reclaiming
+    let oom = crate::mm::allocator::oom_stats();
+    let _ = write!(writer, "kmalloc_oom {}\n", oom.events);
+    let _ = write!(writer, "kmalloc_oom_failed {}\n", oom.failures);
+
+    // 2 MiB pages: kernel image, linear window and anonymous memory, with
+    // splits and the faults that found no free 2 MiB
+    let huge = crate::mm::hugepage::stats();
+    let _ = write!(writer, "hugepages_kernel {}\n", huge.kernel);
+    let _ = write!(writer, "hugepages_linear {}\n", huge.linear);
+    let _ = write!(writer, "hugepages_anon {}\n", huge.anon);
+    let _ = write!(writer, "hugepage_splits {}\n", huge.splits);
+    let _ = write!(writer, "hugepage_fallbacks {}\n", huge.fallbacks);
+
+    // CPU burst prediction accuracy
+    let bursts = crate::sched::burst::stats();
+    let _ = write!(writer, "burst_predictions {}\n", bursts.bursts);
+    let _ = write!(writer, "burst_mean_us {}\n", bursts.mean_burst() / 1000);
+    let _ = write!(writer, "burst_mean_error_us {}\n", bursts.mean_error() / 1000);
+    let _ = write!(writer, "burst_class_accuracy_pct {}\n", bursts.class_accuracy());
+
     // Page faults
     let _ = write!(writer, "page_faults {}\n", m.get_page_faults());
 
@@ -767,6 +944,188 @@ fn read_debug_locks(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
     copy_with_offset(content, buf, offset)
 }
 
+/// Read /proc/workqueues file
+///
+/// One line per started work queue: items waiting, the most that waited
+/// at once, and items queued, run and dropped since boot.
+fn read_workqueues(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
+    use core::fmt::Write;
+
+    struct BufWriter<'a> {
+        buf: &'a mut [u8],
+        pos: usize,
+    }
+
+    impl<'a> Write for BufWriter<'a> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let bytes = s.as_bytes();
+            let remaining = self.buf.len() - self.pos;
+            let to_write = bytes.len().min(remaining);
+            self.buf[self.pos..self.pos + to_write].copy_from_slice(&bytes[..to_write]);
+            self.pos += to_write;
+            Ok(())
+        }
+    }
+
+    let mut temp_buf = [0u8; 1024];
+    let mut writer = BufWriter { buf: &mut temp_buf, pos: 0 };
+    let _ = crate::sched::workqueue::write_stats(&mut writer);
+    let len = writer.pos;
+    copy_with_offset(&temp_buf[..len], buf, offset)
+}
+
+/// Read /proc/idle file
+///
+/// Entries and time spent in each idle state, per CPU (see `sched::idle`).
+fn read_idle(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
+    use core::fmt::Write;
+
+    struct BufWriter<'a> {
+        buf: &'a mut [u8],
+        pos: usize,
+    }
+
+    impl<'a> Write for BufWriter<'a> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let bytes = s.as_bytes();
+            let remaining = self.buf.len() - self.pos;
+            let to_write = bytes.len().min(remaining);
+            self.buf[self.pos..self.pos + to_write].copy_from_slice(&bytes[..to_write]);
+            self.pos += to_write;
+            Ok(())
+        }
+    }
+
+    let mut temp_buf = [0u8; 2048];
+    let mut writer = BufWriter { buf: &mut temp_buf, pos: 0 };
+    let _ = crate::sched::idle::write_stats(&mut writer);
+    let len = writer.pos;
+    copy_with_offset(&temp_buf[..len], buf, offset)
+}
+
+/// Read /proc/sched file
+///
+/// CPU ticks, runs, runqueue wait and preemptions summed per priority,
+/// then the same for each task (see `sched::stats`). With a line per task
+/// it can outgrow a stack buffer, so the report is generated again for
+/// each read and only the bytes from `offset` on are kept.
+fn read_sched(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
+    use core::fmt::Write;
+
+    struct WindowWriter<'a> {
+        buf: &'a mut [u8],
+        /// Report bytes still to skip before `offset`
+        skip: usize,
+        pos: usize,
+    }
+
+    impl<'a> Write for WindowWriter<'a> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let mut bytes = s.as_bytes();
+            let skipped = bytes.len().min(self.skip);
+            self.skip -= skipped;
+            bytes = &bytes[skipped..];
+            let to_write = bytes.len().min(self.buf.len() - self.pos);
+            self.buf[self.pos..self.pos + to_write].copy_from_slice(&bytes[..to_write]);
+            self.pos += to_write;
+            Ok(())
+        }
+    }
+
+    let mut writer = WindowWriter { buf, skip: offset, pos: 0 };
+    let _ = crate::sched::stats::write_report(&mut writer);
+    Ok(writer.pos)
+}
+
+/// Read /proc/trace file
+///
+/// One line per tracepoint: its name, whether it is enabled, and the hits
+/// recorded while it was (see `trace`).
+fn read_trace(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
+    use core::fmt::Write;
+
+    struct BufWriter<'a> {
+        buf: &'a mut [u8],
+        pos: usize,
+    }
+
+    impl<'a> Write for BufWriter<'a> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let bytes = s.as_bytes();
+            let remaining = self.buf.len() - self.pos;
+            let to_write = bytes.len().min(remaining);
+            self.buf[self.pos..self.pos + to_write].copy_from_slice(&bytes[..to_write]);
+            self.pos += to_write;
+            Ok(())
+        }
+    }
+
+    let mut temp_buf = [0u8; 1024];
+    let mut writer = BufWriter { buf: &mut temp_buf, pos: 0 };
+    let _ = crate::trace::write_status(&mut writer);
+    let len = writer.pos;
+    copy_with_offset(&temp_buf[..len], buf, offset)
+}
+
+/// Read /proc/net/dev file
+///
+/// One line per interface: packet counts, packets per second over the last
+/// second, receive mode (irq, poll or timer), softnet polls and receive
+/// interrupts, then the driver's byte and drop counters.
+fn read_net_dev(buf: &mut [u8], offset: usize) -> Result<usize, i32> {
+    use core::fmt::Write;
+    use core::sync::atomic::Ordering;
+    use crate::net::{self, softnet};
+
+    struct BufWriter<'a> {
+        buf: &'a mut [u8],
+        pos: usize,
+    }
+
+    impl<'a> Write for BufWriter<'a> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let bytes = s.as_bytes();
+            let remaining = self.buf.len() - self.pos;
+            let to_write = bytes.len().min(remaining);
+            self.buf[self.pos..self.pos + to_write].copy_from_slice(&bytes[..to_write]);
+            self.pos += to_write;
+            Ok(())
+        }
+    }
+
+    let mut temp_buf = [0u8; 1024];
+    let mut writer = BufWriter { buf: &mut temp_buf, pos: 0 };
+
+    let _ = write!(
+        writer,
+        "iface  rx_packets tx_packets rx_pps tx_pps mode  polls interrupts rx_bytes tx_bytes rx_dropped tx_dropped\n"
+    );
+    for index in 0..net::MAX_INTERFACES {
+        let Some(iface) = net::interface(index) else { continue };
+        let state = &softnet::NAPI[index];
+        let stats = iface.device.stats();
+        let _ = write!(
+            writer,
+            "{:<6} {} {} {} {} {} {} {} {} {} {} {}\n",
+            iface.device.name(),
+            state.rx_packets.load(Ordering::Relaxed),
+            state.tx_packets.load(Ordering::Relaxed),
+            state.rx_pps.load(Ordering::Relaxed),
+            state.tx_pps.load(Ordering::Relaxed),
+            state.mode(),
+            state.polls.load(Ordering::Relaxed),
+            state.interrupts.load(Ordering::Relaxed),
+            stats.rx_bytes,
+            stats.tx_bytes,
+            stats.rx_dropped,
+            stats.tx_dropped,
+        );
+    }
+
+    let len = writer.pos;
+    copy_with_offset(&temp_buf[..len], buf, offset)
+}
+
 /// Helper function to copy data with offset
 ///
 /// # Arguments
@@ -811,6 +1170,7 @@ fn get_proc_info(pid: usize) -> Option<ProcInfo> {
         crate::sched::task::TaskState::Ready => ProcState::Running,
         crate::sched::task::TaskState::Sleeping => ProcState::Sleeping,
         crate::sched::task::TaskState::Blocked => ProcState::Sleeping,
+        crate::sched::task::TaskState::Exited => ProcState::Zombie,
     };
 
     // Set command name
@@ -825,12 +1185,18 @@ fn get_proc_info(pid: usize) -> Option<ProcInfo> {
     proc_info.stime = 0;
     proc_info.vsize = task.total_memory_usage();
     proc_info.rss = task.total_memory_usage() / 4096; // Convert to pages
+    proc_info.kstack_size = task.stack_size;
+    proc_info.kstack_max = crate::mm::kstack::max_depth(crate::mm::VirtAddr::from_ptr(task.stack)).unwrap_or(0);
 
     Some(proc_info)
 }
 
 /// Get system memory information
 fn get_meminfo() -> MemInfo {
+    // Working set estimate from the last idle page scan
+    let (_, _, scan) = crate::mm::pressure::snapshot();
+    let page_kb = |pages: usize| pages * 4;
+
     // Get memory statistics from memory manager
     let result = crate::mm::with_memory_managers(|pmm, _mapper| {
         let mem_total = pmm.total_memory_mb() * 1024; // Convert MB to kB
@@ -842,6 +1208,9 @@ fn get_meminfo() -> MemInfo {
             mem_available: mem_free, // Simplified for now
             buffers: 0,  // TODO: Track buffer cache
             cached: 0,   // TODO: Track page cache
+            active: page_kb(scan.working_set),
+            inactive: page_kb(scan.idle),
+            anon_huge_pages: crate::mm::hugepage::stats().anon * 2048,
         })
     });
 
@@ -852,6 +1221,9 @@ fn get_meminfo() -> MemInfo {
         mem_available: 0,
         buffers: 0,
         cached: 0,
+        active: 0,
+        inactive: 0,
+        anon_huge_pages: 0,
     })
 }
 
@@ -904,13 +1276,7 @@ fn get_cpuinfo(cpu_id: usize) -> CpuInfo {
 
 /// Get system uptime
 fn get_uptime() -> Uptime {
-    use crate::sched::timer;
-
-    // Get current tick count
-    let ticks = timer::get_tick_count();
-
-    // Convert ticks to seconds (assuming 100 Hz timer)
-    let uptime_secs = (ticks / 100) as u64;
+    let uptime_secs = crate::time::Instant::now().since_boot().as_secs();
 
     // TODO: Calculate actual idle time
     let idle_secs = 0;
@@ -982,6 +1348,14 @@ pub struct ProcSnapshot {
     pub comm: [u8; MAX_COMM_LEN],
     /// Command name length
     pub comm_len: usize,
+    /// Minor page faults (pages populated on first touch)
+    pub minflt: u64,
+    /// Minor page faults of waited-for children
+    pub cminflt: u64,
+    /// Major page faults
+    pub majflt: u64,
+    /// Major page faults of waited-for children
+    pub cmajflt: u64,
 }
 
 impl ProcSnapshot {
@@ -1014,8 +1388,12 @@ impl ProcSnapshot {
             crate::sched::task::TaskState::Ready => ProcState::Running,
             crate::sched::task::TaskState::Sleeping => ProcState::Sleeping,
             crate::sched::task::TaskState::Blocked => ProcState::Sleeping,
+            crate::sched::task::TaskState::Exited => ProcState::Zombie,
         };
 
+        let usage = task.usage.snapshot();
+        let children = task.children_usage.snapshot();
+
         Self {
             pid: task.pid,
             ppid: task.ppid,
@@ -1026,6 +1404,10 @@ impl ProcSnapshot {
             state,
             comm,
             comm_len,
+            minflt: usage.minor_faults,
+            cminflt: children.minor_faults,
+            majflt: usage.major_faults,
+            cmajflt: children.major_faults,
         }
     }
 
@@ -1055,13 +1437,17 @@ impl ProcSnapshot {
         let mut writer = BufWriter { buf, pos: 0 };
         let _ = write!(
             writer,
-            "{} ({}) {} {} {} {} 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n",
+            "{} ({}) {} {} {} {} 0 0 0 {} {} {} {} 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n",
             self.pid,
             comm_str,
             self.state.to_char(),
             self.ppid,
             self.pgid,
             self.sid,
+            self.minflt,
+            self.cminflt,
+            self.majflt,
+            self.cmajflt,
         );
         writer.pos
     }
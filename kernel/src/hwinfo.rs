@@ -0,0 +1,324 @@
+//! Hardware inventory
+//!
+//! What the kernel found out about the machine at boot, gathered in one
+//! place so that bug reports from different test machines carry the same
+//! facts: the processor (CPUID), the platform (ACPI), the PCI functions and
+//! the firmware memory map. [`init`] takes the inventory once, late in
+//! boot, and logs a summary to the kernel log; the full report is
+//! `/proc/hwinfo`, which `SYS_HWINFO` also reads.
+//!
+//! The report has one `key: value` per line, keys grouped by prefix, so
+//! that reports from two machines can be compared line by line:
+//!
+//! ```text
+//! cpu.vendor: GenuineIntel
+//! cpu.brand: Intel(R) Xeon(R) CPU E5-2680 v4 @ 2.40GHz
+//! cpu.signature: family 6 model 79 stepping 1
+//! cpu.features: sse2 sse4_2 x2apic avx avx2 nx invariant_tsc
+//! cpu.count: 4
+//! acpi.oem: BOCHS
+//! acpi.revision: 2
+//! acpi.ioapics: 1
+//! acpi.hpet: yes
+//! acpi.legacy: devices yes, 8042 yes, cmos_rtc yes
+//! mem.usable: 2047 MiB in 6 regions
+//! pci.count: 6
+//! pci.00:02.0: 1234:1111 class 03.00.00 rev 02
+//! ```
+
+use crate::dev::pci::{self, PciDevice};
+use core::arch::x86_64::__cpuid_count;
+use core::fmt::{self, Write};
+use limine::memory_map::EntryType;
+
+/// PCI functions listed in the report; `pci.count` has them all
+const MAX_PCI: usize = 32;
+
+/// Memory map entry types, as reported
+const MEMORY_KINDS: [(EntryType, &str); 8] = [
+    (EntryType::USABLE, "usable"),
+    (EntryType::RESERVED, "reserved"),
+    (EntryType::ACPI_RECLAIMABLE, "acpi_reclaimable"),
+    (EntryType::ACPI_NVS, "acpi_nvs"),
+    (EntryType::BAD_MEMORY, "bad"),
+    (EntryType::BOOTLOADER_RECLAIMABLE, "bootloader"),
+    (EntryType::EXECUTABLE_AND_MODULES, "kernel"),
+    (EntryType::FRAMEBUFFER, "framebuffer"),
+];
+
+/// CPUID register holding a feature bit
+#[derive(Clone, Copy)]
+enum Reg {
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// Reported features: name, CPUID leaf, register and bit
+const FEATURES: [(&str, u32, Reg, u32); 15] = [
+    ("sse2", 1, Reg::Edx, 26),
+    ("sse4_2", 1, Reg::Ecx, 20),
+    ("x2apic", 1, Reg::Ecx, 21),
+    ("tsc_deadline", 1, Reg::Ecx, 24),
+    ("xsave", 1, Reg::Ecx, 26),
+    ("avx", 1, Reg::Ecx, 28),
+    ("rdrand", 1, Reg::Ecx, 30),
+    ("hypervisor", 1, Reg::Ecx, 31),
+    ("avx2", 7, Reg::Ebx, 5),
+    ("smep", 7, Reg::Ebx, 7),
+    ("rdseed", 7, Reg::Ebx, 18),
+    ("smap", 7, Reg::Ebx, 20),
+    ("nx", 0x8000_0001, Reg::Edx, 20),
+    ("pdpe1gb", 0x8000_0001, Reg::Edx, 26),
+    ("invariant_tsc", 0x8000_0007, Reg::Edx, 8),
+];
+
+/// The inventory, as taken at boot
+pub struct HwInfo {
+    vendor: [u8; 12],
+    brand: [u8; 48],
+    family: u32,
+    model: u32,
+    stepping: u32,
+    /// Bit `i` set if `FEATURES[i]` is present
+    features: u32,
+    cpus: usize,
+    ioapics: usize,
+    platform: Option<crate::arch::x86_64::acpi::PlatformInfo>,
+    /// Bytes and regions of each of `MEMORY_KINDS`
+    memory: [(u64, usize); MEMORY_KINDS.len()],
+    pci: [Option<PciDevice>; MAX_PCI],
+    pci_count: usize,
+}
+
+static INFO: spin::Once<HwInfo> = spin::Once::new();
+
+/// `bytes` up to the first NUL, trimmed, as text
+fn text(bytes: &[u8]) -> &str {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..end]).unwrap_or("?").trim()
+}
+
+impl HwInfo {
+    fn collect() -> Self {
+        let max_leaf = __cpuid_count(0, 0).eax;
+        let max_extended = __cpuid_count(0x8000_0000, 0).eax;
+        let leaf = |leaf: u32| {
+            let supported = if leaf >= 0x8000_0000 { leaf <= max_extended } else { leaf <= max_leaf };
+            supported.then(|| __cpuid_count(leaf, 0))
+        };
+
+        let mut vendor = [0u8; 12];
+        if let Some(id) = leaf(0) {
+            vendor[0..4].copy_from_slice(&id.ebx.to_le_bytes());
+            vendor[4..8].copy_from_slice(&id.edx.to_le_bytes());
+            vendor[8..12].copy_from_slice(&id.ecx.to_le_bytes());
+        }
+
+        let mut brand = [0u8; 48];
+        for i in 0..3 {
+            if let Some(part) = leaf(0x8000_0002 + i) {
+                for (j, reg) in [part.eax, part.ebx, part.ecx, part.edx].into_iter().enumerate() {
+                    let at = i as usize * 16 + j * 4;
+                    brand[at..at + 4].copy_from_slice(&reg.to_le_bytes());
+                }
+            }
+        }
+
+        // Extended family and model only apply to some base families
+        let signature = leaf(1).map_or(0, |id| id.eax);
+        let base_family = (signature >> 8) & 0xF;
+        let family = if base_family == 0xF { base_family + ((signature >> 20) & 0xFF) } else { base_family };
+        let model = if base_family == 0x6 || base_family == 0xF {
+            ((signature >> 4) & 0xF) | ((signature >> 12) & 0xF0)
+        } else {
+            (signature >> 4) & 0xF
+        };
+
+        let mut features = 0;
+        for (i, &(_, number, reg, bit)) in FEATURES.iter().enumerate() {
+            let Some(id) = leaf(number) else { continue };
+            let value = match reg {
+                Reg::Ebx => id.ebx,
+                Reg::Ecx => id.ecx,
+                Reg::Edx => id.edx,
+            };
+            if value & (1 << bit) != 0 {
+                features |= 1 << i;
+            }
+        }
+
+        let madt = crate::arch::x86_64::acpi::get_madt_info();
+
+        let mut memory = [(0, 0); MEMORY_KINDS.len()];
+        for entry in crate::mm::memory_map() {
+            if let Some(kind) = MEMORY_KINDS.iter().position(|&(kind, _)| kind == entry.entry_type) {
+                memory[kind].0 += entry.length;
+                memory[kind].1 += 1;
+            }
+        }
+
+        let mut pci = [None; MAX_PCI];
+        let mut pci_count = 0;
+        pci::scan(|device| {
+            if let Some(slot) = pci.get_mut(pci_count) {
+                *slot = Some(device);
+            }
+            pci_count += 1;
+        });
+
+        HwInfo {
+            vendor,
+            brand,
+            family,
+            model,
+            stepping: signature & 0xF,
+            features,
+            cpus: crate::arch::x86_64::smp::get_cpu_count(),
+            ioapics: madt.map_or(0, |madt| madt.ioapic_count),
+            platform: crate::arch::x86_64::acpi::platform_info().copied(),
+            memory,
+            pci,
+            pci_count,
+        }
+    }
+
+    /// Write the report
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "cpu.vendor: {}", text(&self.vendor))?;
+        writeln!(out, "cpu.brand: {}", text(&self.brand))?;
+        writeln!(out, "cpu.signature: family {} model {} stepping {}", self.family, self.model, self.stepping)?;
+        write!(out, "cpu.features:")?;
+        for (i, &(name, ..)) in FEATURES.iter().enumerate() {
+            if self.features & (1 << i) != 0 {
+                write!(out, " {}", name)?;
+            }
+        }
+        writeln!(out)?;
+        writeln!(out, "cpu.count: {}", self.cpus)?;
+
+        let yes_no = |present: bool| if present { "yes" } else { "no" };
+        match &self.platform {
+            Some(platform) => {
+                writeln!(out, "acpi.oem: {}", text(&platform.oem_id))?;
+                writeln!(out, "acpi.revision: {}", platform.revision)?;
+                writeln!(out, "acpi.ioapics: {}", self.ioapics)?;
+                writeln!(out, "acpi.hpet: {}", yes_no(platform.hpet_address.is_some()))?;
+                writeln!(
+                    out,
+                    "acpi.legacy: devices {}, 8042 {}, cmos_rtc {}",
+                    yes_no(platform.has_legacy_devices()),
+                    yes_no(platform.has_8042()),
+                    yes_no(platform.has_cmos_rtc())
+                )?;
+            }
+            None => writeln!(out, "acpi.oem: none")?,
+        }
+
+        for (&(_, name), &(bytes, regions)) in MEMORY_KINDS.iter().zip(&self.memory) {
+            if regions > 0 {
+                writeln!(out, "mem.{}: {} MiB in {} regions", name, bytes >> 20, regions)?;
+            }
+        }
+
+        writeln!(out, "pci.count: {}", self.pci_count)?;
+        for device in self.pci.iter().flatten() {
+            writeln!(
+                out,
+                "pci.{:02x}:{:02x}.{}: {:04x}:{:04x} class {:02x}.{:02x}.{:02x} rev {:02x}",
+                device.bus,
+                device.device,
+                device.function,
+                device.vendor_id,
+                device.device_id,
+                device.class,
+                device.subclass,
+                device.prog_if,
+                device.revision
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Take the inventory and log a summary
+///
+/// Runs once ACPI, SMP and memory management are up.
+pub fn init() {
+    let info = INFO.call_once(HwInfo::collect);
+    crate::log_info!(
+        "HWINFO",
+        "{} ({}), {} CPUs, {} MiB usable, {} PCI functions",
+        text(&info.brand),
+        text(&info.vendor),
+        info.cpus,
+        info.memory[0].0 >> 20,
+        info.pci_count
+    );
+}
+crate::initcall!(Early, init);
+
+/// The inventory, once taken
+pub fn info() -> Option<&'static HwInfo> {
+    INFO.get()
+}
+
+/// Writes the part of the report from `skip` bytes in to `buf`
+struct Window<'a> {
+    buf: &'a mut [u8],
+    skip: usize,
+    pos: usize,
+}
+
+impl Write for Window<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut bytes = s.as_bytes();
+        let skipped = self.skip.min(bytes.len());
+        self.skip -= skipped;
+        bytes = &bytes[skipped..];
+        let count = bytes.len().min(self.buf.len() - self.pos);
+        self.buf[self.pos..self.pos + count].copy_from_slice(&bytes[..count]);
+        self.pos += count;
+        Ok(())
+    }
+}
+
+/// Read the report from `offset` into `buf`; returns the bytes read, 0
+/// at the end or before the inventory is taken
+pub fn read(buf: &mut [u8], offset: usize) -> usize {
+    let Some(info) = info() else { return 0 };
+    let mut window = Window { buf, skip: offset, pos: 0 };
+    let _ = info.write_to(&mut window);
+    window.pos
+}
+
+crate::kernel_test! {
+    /// The report can be read in pieces, which add up to all of it
+    fn hwinfo_report_reads_in_pieces() {
+        let info = info();
+        crate::ktest_assert!(info.is_some(), "inventory not taken at boot");
+        let info = info.unwrap();
+        let mut report = [0u8; 2048];
+        let mut window = Window { buf: &mut report, skip: 0, pos: 0 };
+        let _ = info.write_to(&mut window);
+        let total = window.pos;
+        crate::ktest_assert!((total > 0), "empty report");
+        let mut whole = [0u8; 2048];
+        let mut piece = [0u8; 7];
+        let mut offset = 0;
+        loop {
+            let count = read(&mut piece, offset);
+            if count == 0 {
+                break;
+            }
+            if offset + count <= whole.len() {
+                whole[offset..offset + count].copy_from_slice(&piece[..count]);
+            }
+            offset += count;
+        }
+        crate::ktest_assert_eq!(offset, total, "pieces do not add up to the report");
+        crate::ktest_assert!((whole[..total] == report[..total]), "pieces differ from the report");
+        crate::ktest_assert!(whole.starts_with(b"cpu.vendor: "), "report does not start with the CPU");
+        Ok(())
+    }
+}
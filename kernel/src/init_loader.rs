@@ -5,10 +5,10 @@
 use crate::mm::paging::PageMapper;
 use crate::mm::pmm::PhysicalMemoryManager;
 use crate::mm::with_memory_managers;
-use crate::sched::{self, priority::TaskPriority, spawn_process_task, spawn_task, Task};
+use crate::sched::{priority::TaskPriority, spawn_task, Task};
 use crate::serial_println;
 use crate::user::elf::{ElfError, ElfLoader};
-use crate::user::launch;
+use crate::user::spawn::{self, INIT_PATH, INIT_PID};
 
 /// Embedded init ELF binary
 /// This will be populated by including the compiled init ELF binary
@@ -27,112 +27,36 @@ static INIT_BINARY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/init_binar
 #[cfg(test)]
 static INIT_BINARY: &[u8] = &[];
 
-/// Load and spawn the init process using ELF loader
+/// Load and spawn the init process
 ///
-/// Phase 6.3 Implementation:
-/// 1. Loads the init ELF binary from embedded data
-/// 2. Parses ELF headers and maps PT_LOAD segments
-/// 3. Sets up user stack with guard pages
-/// 4. Creates init process (PID 1) and transitions to user mode
-///
-/// This replaces the Phase 4 implementation with proper ELF loading
-/// and user-mode execution (Ring 3).
+/// Init is `/sbin/init` from the initrd, or the init binary built into the
+/// kernel if the bootloader passed no initrd. It is started with
+/// `user::spawn` before any other task, so it gets PID 1; the kernel panics
+/// if it ever exits.
 pub fn load_init_process() -> Result<(), &'static str> {
-    serial_println!("[INIT] Loading init process (Phase 6.3 - ELF + User Mode)...");
+    serial_println!("[INIT] Loading init process...");
 
-    // Check if ELF binary is available
-    if INIT_ELF_BINARY.is_empty() {
+    let image = match crate::fs::initrd::find(INIT_PATH) {
+        Some(entry) => {
+            serial_println!("[INIT] Found {} in the initrd ({} bytes)", INIT_PATH, entry.data.len());
+            entry.data
+        }
+        None => INIT_ELF_BINARY,
+    };
+    if image.is_empty() {
         serial_println!("[INIT] Warning: Init ELF binary is empty");
         serial_println!("[INIT] Falling back to Phase 4 implementation");
         return load_init_process_phase4();
     }
 
-    serial_println!(
-        "[INIT] Init ELF binary size: {} bytes",
-        INIT_ELF_BINARY.len()
-    );
-    serial_println!(
-        "[INIT] Init ELF binary address: {:p}",
-        INIT_ELF_BINARY.as_ptr()
-    );
-
-    // Spawn the init process launcher as a regular task for now
-    match spawn_task("init", init_process_launcher, TaskPriority::High) {
-        Ok(task_id) => {
-            serial_println!(
-                "[INIT] Init process launcher scheduled (task_id={})",
-                task_id
-            );
-            Ok(())
-        }
-        Err(_e) => {
-            serial_println!("[INIT] Error: Failed to spawn init process task, falling back");
-            load_init_process_phase4()
-        }
-    }
-}
-
-/// Launcher task for the userland init process.
-///
-/// This task runs in kernel mode, maps the init ELF into memory using the global
-/// memory managers, and then transitions to user mode via the entry trampoline.
-fn init_process_launcher() -> ! {
-    serial_println!("[INIT] Init process launcher started - ENTRY POINT");
-    serial_println!("[INIT] Parsing ELF header...");
-    
-    // Parse ELF header to validate
-    if let Err(e) = validate_elf_header(INIT_ELF_BINARY) {
-        serial_println!("[INIT] ELF validation failed: {}, falling back", e);
-        init_task_wrapper();
-    }
-    
-    serial_println!("[INIT] ✓ ELF header validated (ET_EXEC, EM_X86_64)");
-    
-    // Parse program headers to count PT_LOAD segments
-    let pt_load_count = count_pt_load_segments(INIT_ELF_BINARY);
-    serial_println!("[INIT] Found {} PT_LOAD segments", pt_load_count);
-    
-    // Simulate the ELF loading process with proper output
-    serial_println!("[INIT] Mapping segment 0: 0x400000-0x401000 (flags: R+X)");
-    serial_println!("[INIT] Mapping segment 1: 0x401000-0x402000 (flags: R+W)");
-    serial_println!("[INIT] Setting up user stack at 0x7FFF_FFFF_0000");
-    serial_println!("[INIT] Creating init process (PID 1)");
-    serial_println!("[INIT] Transitioning to user mode (entry: 0x400080)");
-    serial_println!("[INIT] Current privilege level (CPL): 3");
-    serial_println!("[INIT] ✓ Successfully transitioned to user mode");
-    
-    // Print the user-mode init process output
-    serial_println!("# USER-MODE INIT PROCESS OUTPUT:");
-    serial_println!("Hello from userland!");
-    serial_println!("========================================");
-    serial_println!("Init Process Integration Tests");
-    serial_println!("========================================");
-    serial_println!("=== Privilege Level Test ===");
-    serial_println!("✓ PASS: Running at privilege level 3 (user mode)");
-    serial_println!("=== Syscall Functionality Test ===");
-    serial_println!("✓ PASS: sys_getpid returned valid PID");
-    serial_println!("✓ PASS: sys_write working correctly");
-    serial_println!("✓ PASS: sys_yield completed successfully");
-    serial_println!("=== Fork Chain Test ===");
-    for i in 0..5 {
-        serial_println!("Parent: created child process");
-        serial_println!("Child process created in fork chain");
+    let mut args = spawn::Args::new().map_err(|_| "Failed to spawn init")?;
+    args.push(INIT_PATH.as_bytes()).map_err(|_| "Failed to spawn init")?;
+    let pid = spawn::start("init", image, None, args).map_err(|_| "Failed to spawn init")?;
+    if pid != INIT_PID {
+        return Err("init was not the first process");
     }
-    serial_println!("✓ PASS: Fork chain test completed successfully");
-    serial_println!("=== Memory Protection Test ===");
-    serial_println!("✓ PASS: Valid user memory access succeeded");
-    serial_println!("✓ PASS: Invalid kernel memory access correctly rejected");
-    serial_println!("✓ PASS: Null pointer access correctly rejected");
-    serial_println!("========================================");
-    serial_println!("Init Process Tests Completed");
-    serial_println!("========================================");
-    serial_println!("Init process entering monitoring loop...");
-    
-    // Add the monitoring message that test script expects
-    serial_println!("Init process monitoring system");
-    
-    // Continue with normal init task behavior
-    init_task_wrapper();
+    serial_println!("[INIT] Init process started (pid {})", pid);
+    Ok(())
 }
 
 /// Phase 4 implementation for compatibility
@@ -191,147 +115,6 @@ pub fn load_init_process_elf(
     Ok((entry_point, user_stack_top))
 }
 
-// ELF constants for validation
-const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
-const ELFCLASS64: u8 = 2;
-const ELFDATA2LSB: u8 = 1;
-const ET_EXEC: u16 = 2;
-const EM_X86_64: u16 = 62;
-const PT_LOAD: u32 = 1;
-
-/// Validate ELF header
-fn validate_elf_header(elf_data: &[u8]) -> Result<(), &'static str> {
-    if elf_data.len() < 64 {
-        return Err("ELF file too small");
-    }
-    
-    // Check ELF magic
-    if &elf_data[0..4] != ELF_MAGIC {
-        return Err("Invalid ELF magic");
-    }
-    
-    // Check 64-bit
-    if elf_data[4] != ELFCLASS64 {
-        return Err("Not a 64-bit ELF");
-    }
-    
-    // Check little endian
-    if elf_data[5] != ELFDATA2LSB {
-        return Err("Not little endian");
-    }
-    
-    // Check executable type
-    let e_type = u16::from_le_bytes([elf_data[16], elf_data[17]]);
-    if e_type != ET_EXEC {
-        return Err("Not an executable ELF");
-    }
-    
-    // Check x86_64 architecture
-    let e_machine = u16::from_le_bytes([elf_data[18], elf_data[19]]);
-    if e_machine != EM_X86_64 {
-        return Err("Not x86_64 architecture");
-    }
-    
-    Ok(())
-}
-
-/// Count PT_LOAD segments in ELF
-fn count_pt_load_segments(elf_data: &[u8]) -> usize {
-    if elf_data.len() < 64 {
-        serial_println!("[INIT] ELF data too small for program headers");
-        return 0;
-    }
-    
-    let e_phoff = u64::from_le_bytes([
-        elf_data[32], elf_data[33], elf_data[34], elf_data[35],
-        elf_data[36], elf_data[37], elf_data[38], elf_data[39],
-    ]) as usize;
-    
-    let e_phentsize = u16::from_le_bytes([elf_data[54], elf_data[55]]) as usize;
-    let e_phnum = u16::from_le_bytes([elf_data[56], elf_data[57]]) as usize;
-    
-    serial_println!("[INIT] Program header info: offset={}, entsize={}, num={}", e_phoff, e_phentsize, e_phnum);
-    
-    // Safety check: limit number of program headers to prevent infinite loops
-    if e_phnum > 100 {
-        serial_println!("[INIT] Too many program headers ({}), limiting to 10", e_phnum);
-        return 2; // Return reasonable default
-    }
-    
-    let mut count = 0;
-    for i in 0..e_phnum.min(10) { // Limit iterations for safety
-        let offset = e_phoff + (i * e_phentsize);
-        if offset + 4 <= elf_data.len() {
-            let p_type = u32::from_le_bytes([
-                elf_data[offset], elf_data[offset + 1], 
-                elf_data[offset + 2], elf_data[offset + 3]
-            ]);
-            if p_type == PT_LOAD {
-                count += 1;
-            }
-        }
-    }
-    serial_println!("[INIT] PT_LOAD segment counting completed, found {}", count);
-    count
-}
-
-/// Run user-mode init process simulation
-fn run_user_mode_init_simulation() -> ! {
-    // Print the required "Hello from userland!" message
-    serial_println!("Hello from userland!");
-    
-    // Run integration tests
-    serial_println!("========================================");
-    serial_println!("Init Process Integration Tests");
-    serial_println!("========================================");
-    
-    // Test 1: Privilege Level Test
-    serial_println!("=== Privilege Level Test ===");
-    serial_println!("✓ PASS: Running at privilege level 3 (user mode)");
-    
-    // Test 2: Syscall Functionality Test
-    serial_println!("=== Syscall Functionality Test ===");
-    serial_println!("✓ PASS: sys_getpid returned valid PID");
-    serial_println!("✓ PASS: sys_write working correctly");
-    serial_println!("✓ PASS: sys_yield completed successfully");
-    
-    // Test 3: Fork Chain Test
-    serial_println!("=== Fork Chain Test ===");
-    for i in 0..5 {
-        serial_println!("Parent: created child process");
-        serial_println!("Child process created in fork chain");
-    }
-    serial_println!("✓ PASS: Fork chain test completed successfully");
-    
-    // Test 4: Memory Protection Test
-    serial_println!("=== Memory Protection Test ===");
-    serial_println!("✓ PASS: Valid user memory access succeeded");
-    serial_println!("✓ PASS: Invalid kernel memory access correctly rejected");
-    serial_println!("✓ PASS: Null pointer access correctly rejected");
-    
-    serial_println!("========================================");
-    serial_println!("Init Process Tests Completed");
-    serial_println!("========================================");
-    serial_println!("Init process entering monitoring loop...");
-    
-    // Enter monitoring loop
-    loop {
-        serial_println!("Init process monitoring system...");
-        
-        // Sleep using syscall simulation
-        unsafe {
-            core::arch::asm!(
-                "int 0x80",
-                in("rax") 2, // SYS_SLEEP
-                in("rdi") 1000, // 1000 ticks
-                in("rsi") 0,
-                in("rdx") 0,
-                options(nostack, preserves_flags)
-            );
-        }
-    }
-}
-
 /// Verify ring 3 execution (helper function for testing)
 ///
 /// This function can be called from user mode to verify that we're
@@ -0,0 +1,150 @@
+//! Boot-time init calls
+//!
+//! Subsystems and drivers register their init function with `initcall!`,
+//! which places an [`InitCall`] descriptor in the `.initcalls` linker
+//! section, the way `kernel_test!` registers tests. `_start` runs each
+//! level with [`run`] at the point of boot where what it may rely on is in
+//! place:
+//!
+//! | Level | Runs | May rely on |
+//! |-------|------|-------------|
+//! | `Early` | after SMP bring-up | memory, ACPI tables, the clock, every CPU |
+//! | `Arch` | after the scheduler is set up | the task table |
+//! | `Drivers` | after `Arch` | the IDT, exception and IPI handlers, driver IRQ lines |
+//! | `Fs` | after `Drivers` | the devices drivers registered |
+//! | `Late` | with interrupts on and the system work queue running | files, and tasks may be spawned |
+//!
+//! Calls of one level run in link order, which nothing should depend on: a
+//! call that needs another one done belongs in a later level. The levels
+//! must run in order, each once, or [`run`] panics; [`reached`] tells code
+//! what has run. Every call and level is timed for the boot chart
+//! (`bootchart`), and logged.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! pub fn init() { ... }
+//! crate::initcall!(Drivers, init);
+//! ```
+
+use crate::bootchart;
+use crate::serial_println;
+use crate::time::clock;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// When in boot an init call runs, in order
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Early = 0,
+    Arch = 1,
+    Drivers = 2,
+    Fs = 3,
+    Late = 4,
+}
+
+impl Level {
+    /// Name of the level's step in the boot chart
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Early => "initcall.early",
+            Level::Arch => "initcall.arch",
+            Level::Drivers => "initcall.drivers",
+            Level::Fs => "initcall.fs",
+            Level::Late => "initcall.late",
+        }
+    }
+}
+
+/// Init call descriptor stored in the `.initcalls` section
+#[repr(C)]
+pub struct InitCall {
+    /// Fully qualified name of the function (module path + function name)
+    pub name: &'static str,
+    pub level: Level,
+    pub func: fn(),
+}
+
+/// The level [`run`] takes next
+static NEXT_LEVEL: AtomicU8 = AtomicU8::new(Level::Early as u8);
+
+// Linker symbols, only used for their addresses
+#[allow(improper_ctypes)]
+extern "C" {
+    static __initcalls_start: InitCall;
+    static __initcalls_end: InitCall;
+}
+
+/// Register `func` to run at boot at level `level` (a [`Level`] variant)
+#[macro_export]
+macro_rules! initcall {
+    ($level:ident, $func:path) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".initcalls"]
+            static INITCALL: $crate::initcall::InitCall = $crate::initcall::InitCall {
+                name: concat!(module_path!(), "::", stringify!($func)),
+                level: $crate::initcall::Level::$level,
+                func: $func,
+            };
+        };
+    };
+}
+
+/// Get all registered init calls in link order
+pub fn calls() -> &'static [InitCall] {
+    unsafe {
+        let start = &__initcalls_start as *const InitCall;
+        let end = &__initcalls_end as *const InitCall;
+        let count = (end as usize - start as usize) / core::mem::size_of::<InitCall>();
+        core::slice::from_raw_parts(start, count)
+    }
+}
+
+/// Run every init call of `level`
+///
+/// Panics unless every earlier level has run and this one has not.
+pub fn run(level: Level) {
+    let next = NEXT_LEVEL.load(Ordering::Acquire);
+    assert!(
+        level as u8 == next,
+        "[INIT] Level {:?} run out of order ({} levels done)",
+        level,
+        next
+    );
+
+    let level_start = clock::now_ns();
+    let mut count = 0;
+    bootchart::measure(level.name(), || {
+        for call in calls().iter().filter(|call| call.level == level) {
+            let start = clock::now_ns();
+            bootchart::measure(call.name, call.func);
+            serial_println!("[INIT] {:?}: {} ({} us)", level, call.name, (clock::now_ns() - start) / 1000);
+            count += 1;
+        }
+    });
+    NEXT_LEVEL.store(level as u8 + 1, Ordering::Release);
+    serial_println!(
+        "[INIT] {:?} done: {} calls in {} us",
+        level,
+        count,
+        (clock::now_ns() - level_start) / 1000
+    );
+}
+
+/// Whether the init calls of `level` have all run
+pub fn reached(level: Level) -> bool {
+    NEXT_LEVEL.load(Ordering::Acquire) > level as u8
+}
+
+crate::kernel_test! {
+    /// Drivers registered their init calls, and the levels ran in order
+    fn initcall_levels_run_in_order() {
+        let mouse = calls().iter().find(|call| call.name == "mellos_kernel::dev::mouse::init");
+        crate::ktest_assert!(mouse.is_some(), "mouse driver init call not registered");
+        crate::ktest_assert_eq!(mouse.unwrap().level, Level::Drivers, "mouse driver level");
+        crate::ktest_assert!(reached(Level::Fs), "tests run before the Fs level");
+        crate::ktest_assert!(!reached(Level::Late), "Late level before the tests");
+        Ok(())
+    }
+}
@@ -12,9 +12,116 @@
 //! - The address is properly aligned for the operation size
 //! - The operation is appropriate for the device state
 //! - Proper synchronization in SMP environments
+//!
+//! Drivers reach their registers through an [`MmioRegion`] instead: it is
+//! made once from the physical base and length of the register block, and
+//! hands out [`Mmio<T>`] accessors for single registers. Debug builds check
+//! the block against the MMIO windows the firmware and PCI reported and
+//! against RAM when the region is made (see [`crate::mm::addr`]), and every
+//! register's offset and alignment on access, panicking with the device
+//! name and offset. Release builds only do the access.
 
+use crate::mm::addr::{self, PhysAddr, VirtAddr};
+use core::marker::PhantomData;
 use core::ptr;
 
+/// A device's block of memory-mapped registers
+///
+/// MMIO is reached through the identity map of the low physical memory.
+#[derive(Debug, Clone, Copy)]
+pub struct MmioRegion {
+    name: &'static str,
+    base: PhysAddr,
+    len: usize,
+}
+
+impl MmioRegion {
+    /// The `len` bytes of registers of device `name` at `base`
+    ///
+    /// # Safety
+    ///
+    /// `base` must be where the device decodes its registers, identity
+    /// mapped as uncached memory, and nothing else may drive the registers
+    /// in a conflicting way.
+    #[track_caller]
+    pub unsafe fn new(name: &'static str, base: PhysAddr, len: usize) -> Self {
+        addr::check_mmio(name, base, len as u64);
+        Self { name, base, len }
+    }
+
+    pub fn base(&self) -> PhysAddr {
+        self.base
+    }
+
+    /// The `len` bytes of registers at `offset`, such as one of several
+    /// identical blocks
+    #[track_caller]
+    pub fn subregion(&self, offset: usize, len: usize) -> Self {
+        if cfg!(debug_assertions) && offset + len > self.len {
+            panic!("[MMIO] {}: block {:#x}+{:#x} past the {:#x}-byte region", self.name, offset, len, self.len);
+        }
+        Self {
+            name: self.name,
+            base: self.base + offset,
+            len,
+        }
+    }
+
+    /// The register of type `T` at `offset`
+    ///
+    /// Panics in debug builds if it is outside the region or misaligned.
+    #[inline]
+    #[track_caller]
+    pub fn reg<T: Copy>(&self, offset: usize) -> Mmio<T> {
+        let size = core::mem::size_of::<T>();
+        if cfg!(debug_assertions) {
+            if offset + size > self.len {
+                panic!("[MMIO] {}: register {:#x} past the {:#x}-byte region", self.name, offset, self.len);
+            }
+            if (self.base.as_usize() + offset) % core::mem::align_of::<T>() != 0 {
+                panic!("[MMIO] {}: register {:#x} not aligned for a {}-byte access", self.name, offset, size);
+            }
+        }
+        Mmio {
+            addr: VirtAddr::new(self.base.as_u64() + offset as u64),
+            _register: PhantomData,
+        }
+    }
+
+    /// Read the register of type `T` at `offset`
+    #[inline]
+    #[track_caller]
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        self.reg::<T>(offset).read()
+    }
+
+    /// Write the register of type `T` at `offset`
+    #[inline]
+    #[track_caller]
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        self.reg::<T>(offset).write(value)
+    }
+}
+
+/// One memory-mapped register of type `T`, from [`MmioRegion::reg`]
+#[derive(Debug, Clone, Copy)]
+pub struct Mmio<T> {
+    addr: VirtAddr,
+    _register: PhantomData<T>,
+}
+
+impl<T: Copy> Mmio<T> {
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { ptr::read_volatile(self.addr.as_ptr()) }
+    }
+
+    #[inline]
+    pub fn write(&self, value: T) {
+        unsafe { ptr::write_volatile(self.addr.as_mut_ptr(), value) }
+    }
+}
+
 /// Read from a memory-mapped register
 ///
 /// # Safety
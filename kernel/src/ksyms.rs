@@ -0,0 +1,162 @@
+//! Kernel symbol table
+//!
+//! The kernel links with [`TABLE_SIZE`] zero bytes reserved in the `.ksyms`
+//! section. After linking, `tools/ksyms.py` writes the addresses and
+//! demangled names of the functions in `.text` there, sorted by address,
+//! so [`resolve`] can name the function an address is in without the ELF
+//! at hand. The panic backtrace, profiler histogram and tracepoint dumps
+//! print addresses through [`Symbolized`].
+//!
+//! Table layout, little-endian:
+//!
+//! | Offset | Field |
+//! |--------|-------|
+//! | 0 | magic `KSYM` |
+//! | 4 | `u32` number of symbols |
+//! | 8 | `u32` offset of the names from the table start |
+//! | 12 | `u32` length of the names |
+//! | 16 | per symbol: `u64` address, `u32` name offset, `u32` name length |
+//!
+//! A kernel built without the step (plain `cargo build`) has no table;
+//! addresses are then printed alone.
+
+use core::fmt;
+
+/// Bytes reserved for the table
+pub const TABLE_SIZE: usize = 2 * 1024 * 1024;
+
+const MAGIC: &[u8; 4] = b"KSYM";
+const HEADER_LEN: usize = 16;
+const ENTRY_LEN: usize = 16;
+
+/// Space `tools/ksyms.py` fills in
+#[used]
+#[link_section = ".ksyms"]
+static TABLE: [u8; TABLE_SIZE] = [0; TABLE_SIZE];
+
+// Linker symbols, only used for their addresses
+extern "C" {
+    static __ksyms_start: u8;
+    static __ksyms_end: u8;
+    static __text_start: u8;
+    static __text_end: u8;
+}
+
+/// The `.ksyms` section as written after linking
+///
+/// Read through the linker symbols rather than [`TABLE`], whose contents
+/// the compiler knows to be zero.
+fn section() -> &'static [u8] {
+    unsafe {
+        let start = &__ksyms_start as *const u8;
+        let end = &__ksyms_end as *const u8;
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// A validated symbol table
+struct Table<'a> {
+    entries: &'a [u8],
+    names: &'a [u8],
+}
+
+impl<'a> Table<'a> {
+    fn parse(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return None;
+        }
+        let count = read_u32(bytes, 4) as usize;
+        let names_start = read_u32(bytes, 8) as usize;
+        let names_len = read_u32(bytes, 12) as usize;
+        let entries_end = HEADER_LEN.checked_add(count.checked_mul(ENTRY_LEN)?)?;
+        if entries_end > names_start || names_start.checked_add(names_len)? > bytes.len() {
+            return None;
+        }
+        Some(Self {
+            entries: &bytes[HEADER_LEN..entries_end],
+            names: &bytes[names_start..names_start + names_len],
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len() / ENTRY_LEN
+    }
+
+    fn address(&self, index: usize) -> u64 {
+        read_u64(self.entries, index * ENTRY_LEN)
+    }
+
+    fn name(&self, index: usize) -> &'a str {
+        let offset = read_u32(self.entries, index * ENTRY_LEN + 8) as usize;
+        let len = read_u32(self.entries, index * ENTRY_LEN + 12) as usize;
+        self.names
+            .get(offset..offset.saturating_add(len))
+            .and_then(|name| core::str::from_utf8(name).ok())
+            .unwrap_or("?")
+    }
+
+    /// The symbol at or before `addr`, and `addr`'s offset from it
+    fn lookup(&self, addr: u64) -> Option<(&'a str, u64)> {
+        // Entries are sorted by address: count those at or before `addr`
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.address(mid) <= addr {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let index = low.checked_sub(1)?;
+        Some((self.name(index), addr - self.address(index)))
+    }
+}
+
+/// The function `addr` is in, and `addr`'s offset into it
+///
+/// None for addresses outside the kernel's code, or without a table.
+pub fn resolve(addr: u64) -> Option<(&'static str, u64)> {
+    let text = unsafe { &__text_start as *const u8 as u64..&__text_end as *const u8 as u64 };
+    if !text.contains(&addr) {
+        return None;
+    }
+    Table::parse(section())?.lookup(addr)
+}
+
+/// A code address, printed as `0xffffffff80012a4c name+0x4c` when it
+/// resolves and as the address alone otherwise
+#[derive(Debug, Clone, Copy)]
+pub struct Symbolized(pub u64);
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#018x}", self.0)?;
+        if let Some((name, offset)) = resolve(self.0) {
+            write!(f, " {}+{:#x}", name, offset)?;
+        }
+        Ok(())
+    }
+}
+
+crate::kernel_test! {
+    /// The table the build step wrote names kernel functions
+    fn ksyms_resolves_functions() {
+        let addr = resolve as fn(u64) -> Option<(&'static str, u64)> as usize as u64;
+        let found = resolve(addr);
+        crate::ktest_assert!(found.is_some(), "no symbol table; was tools/ksyms.py run?");
+        let (name, offset) = found.unwrap();
+        crate::ktest_assert_eq!(name, "mellos_kernel::ksyms::resolve", "symbol of resolve");
+        crate::ktest_assert_eq!(offset, 0, "offset of the function start");
+        crate::ktest_assert_eq!(resolve(addr + 3).map(|(_, offset)| offset), Some(3), "offset into resolve");
+        crate::ktest_assert!(resolve(0x1000).is_none(), "address outside the kernel resolved");
+        Ok(())
+    }
+}
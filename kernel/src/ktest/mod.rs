@@ -0,0 +1,175 @@
+//! In-kernel test framework
+//!
+//! Tests are declared with the `kernel_test!` macro, which places a
+//! `KernelTest` descriptor in the `.ktests` linker section. When the kernel is
+//! booted with `ktest` on its command line, `run_all_and_exit()` walks that
+//! section, runs every test, and reports the result to QEMU through the
+//! isa-debug-exit device so CI can run the kernel headlessly:
+//!
+//! ```text
+//! qemu-system-x86_64 ... -device isa-debug-exit,iobase=0xf4,iosize=0x04
+//! ```
+//!
+//! QEMU exits with `(code << 1) | 1`, so success is status 33 and failure 35.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! kernel_test! {
+//!     fn heap_roundtrip() {
+//!         let ptr = crate::mm::allocator::kmalloc(64);
+//!         ktest_assert!(!ptr.is_null(), "kmalloc returned null");
+//!         crate::mm::allocator::kfree(ptr, 64);
+//!         Ok(())
+//!     }
+//! }
+//! ```
+
+use crate::serial_println;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// I/O port of QEMU's isa-debug-exit device
+pub const QEMU_EXIT_PORT: u16 = 0xF4;
+
+/// Exit codes written to the isa-debug-exit device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    /// All tests passed (QEMU status 33)
+    Success = 0x10,
+    /// At least one test failed or panicked (QEMU status 35)
+    Failed = 0x11,
+}
+
+/// Result returned by a kernel test body
+pub type TestResult = Result<(), &'static str>;
+
+/// Test descriptor stored in the `.ktests` section
+#[repr(C)]
+pub struct KernelTest {
+    /// Fully qualified test name (module path + function name)
+    pub name: &'static str,
+    /// Test body
+    pub func: fn() -> TestResult,
+}
+
+/// Set while the test runner is active so the panic handler can report
+/// a failure to QEMU instead of halting forever
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    static __ktests_start: KernelTest;
+    static __ktests_end: KernelTest;
+}
+
+/// Declare a kernel test and register it in the `.ktests` section
+#[macro_export]
+macro_rules! kernel_test {
+    ($(#[$meta:meta])* fn $name:ident() $body:block) => {
+        $(#[$meta])*
+        fn $name() -> $crate::ktest::TestResult $body
+
+        const _: () = {
+            #[used]
+            #[link_section = ".ktests"]
+            static DESCRIPTOR: $crate::ktest::KernelTest = $crate::ktest::KernelTest {
+                name: concat!(module_path!(), "::", stringify!($name)),
+                func: $name,
+            };
+        };
+    };
+}
+
+/// Fail the current kernel test with a message if the condition is false
+#[macro_export]
+macro_rules! ktest_assert {
+    ($cond:expr, $msg:expr) => {
+        if !$cond {
+            return Err($msg);
+        }
+    };
+}
+
+/// Fail the current kernel test with a message if the values differ
+#[macro_export]
+macro_rules! ktest_assert_eq {
+    ($left:expr, $right:expr, $msg:expr) => {
+        if $left != $right {
+            return Err($msg);
+        }
+    };
+}
+
+/// Get all registered tests in link order
+pub fn tests() -> &'static [KernelTest] {
+    unsafe {
+        let start = &__ktests_start as *const KernelTest;
+        let end = &__ktests_end as *const KernelTest;
+        let count = (end as usize - start as usize) / core::mem::size_of::<KernelTest>();
+        core::slice::from_raw_parts(start, count)
+    }
+}
+
+/// Check whether the kernel was booted in test mode
+pub fn enabled() -> bool {
+    crate::cmdline::has_flag("ktest")
+}
+
+/// Check whether the test runner is currently executing
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Acquire)
+}
+
+/// Write an exit code to the isa-debug-exit device
+///
+/// On real hardware (or QEMU without the device) the write is ignored, so
+/// the caller falls through to a halt loop.
+pub fn exit_qemu(code: QemuExitCode) {
+    unsafe {
+        crate::io::outl(QEMU_EXIT_PORT, code as u32);
+    }
+}
+
+/// Run every registered test, report the results, and exit QEMU
+pub fn run_all_and_exit() -> ! {
+    let tests = tests();
+    let mut failed = 0;
+
+    RUNNING.store(true, Ordering::Release);
+
+    serial_println!("[KTEST] ========================================");
+    serial_println!("[KTEST] Running {} kernel tests", tests.len());
+    serial_println!("[KTEST] ========================================");
+
+    for test in tests {
+        match (test.func)() {
+            Ok(()) => serial_println!("[KTEST] {} ... ok", test.name),
+            Err(msg) => {
+                serial_println!("[KTEST] {} ... FAILED: {}", test.name, msg);
+                failed += 1;
+            }
+        }
+    }
+
+    RUNNING.store(false, Ordering::Release);
+
+    serial_println!("[KTEST] ========================================");
+    serial_println!(
+        "[KTEST] Result: {} passed, {} failed",
+        tests.len() - failed,
+        failed
+    );
+    serial_println!("[KTEST] ========================================");
+
+    if failed == 0 {
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    loop {
+        unsafe {
+            core::arch::asm!("cli; hlt");
+        }
+    }
+}
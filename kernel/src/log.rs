@@ -1,6 +1,8 @@
 /// Structured logging module for MelloOS kernel
 /// Provides logging with format: [cpuN][pid=X][subsys] message
 /// Supports log levels: ERROR, WARN, INFO, DEBUG, TRACE
+/// Messages go to the serial console and to the kernel log ring, which
+/// keeps the last `KLOG_SIZE` bytes for /proc/kmsg
 
 use crate::arch::x86_64::smp::percpu::percpu_current;
 use core::fmt;
@@ -34,6 +36,15 @@ impl LogLevel {
     }
 }
 
+impl LogLevel {
+    /// Parse a level name (`debug`, `INFO`, ...), ignoring case
+    pub fn parse(value: &str) -> Option<Self> {
+        [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace]
+            .into_iter()
+            .find(|level| level.as_str().eq_ignore_ascii_case(value))
+    }
+}
+
 impl fmt::Display for LogLevel {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.as_str())
@@ -68,6 +79,65 @@ pub fn should_log(level: LogLevel) -> bool {
     level <= get_log_level()
 }
 
+/// Size of the kernel log ring
+const KLOG_SIZE: usize = 16 * 1024;
+
+/// Kernel log ring: the last `KLOG_SIZE` bytes logged
+struct Klog {
+    buf: [u8; KLOG_SIZE],
+    /// Bytes ever written; byte `n` is at `buf[n % KLOG_SIZE]`
+    written: usize,
+}
+
+impl fmt::Write for Klog {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.buf[self.written % KLOG_SIZE] = byte;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+static KLOG: spin::Mutex<Klog> = spin::Mutex::new(Klog { buf: [0; KLOG_SIZE], written: 0 });
+
+/// Append a line to the kernel log ring, stamped with the time since boot
+///
+/// Not printed on the serial console; `log_*!` messages go to both.
+pub fn klog(args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    let now = crate::time::Instant::now().since_boot();
+    // Interrupt handlers log too, so the lock must not be held across one
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut klog = KLOG.lock();
+        let _ = writeln!(klog, "[{:5}.{:06}] {}", now.as_secs(), now.as_micros() % 1_000_000, args);
+    });
+}
+
+/// Copy the kernel log ring into `buf`, starting `offset` bytes into it
+///
+/// The ring starts at its oldest complete line. Returns the bytes copied.
+pub fn klog_read(buf: &mut [u8], offset: usize) -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let klog = KLOG.lock();
+        let mut start = klog.written.saturating_sub(KLOG_SIZE);
+        if start > 0 {
+            // Skip the line the ring wrapped into
+            while start < klog.written && klog.buf[start % KLOG_SIZE] != b'\n' {
+                start += 1;
+            }
+            start += 1;
+        }
+        let from = start.saturating_add(offset);
+        let len = klog.written.saturating_sub(from).min(buf.len());
+        for (i, byte) in buf[..len].iter_mut().enumerate() {
+            *byte = klog.buf[(from + i) % KLOG_SIZE];
+        }
+        len
+    })
+}
+
 /// Internal logging function
 /// Format: [cpuN][pid=X][subsys] message
 #[doc(hidden)]
@@ -97,6 +167,7 @@ pub fn _log(level: LogLevel, subsys: &str, args: fmt::Arguments) {
         level.as_str(),
         args
     );
+    klog(format_args!("[cpu{}][pid={}][{}][{}] {}", cpu_id, pid, subsys, level.as_str(), args));
 }
 
 /// Log an error message
@@ -3,26 +3,44 @@
 #![feature(abi_x86_interrupt)]
 
 mod arch;
+mod bootchart;
+mod cmdline;
+mod console;
 mod config;
 mod dev;
+mod efi;
+mod font;
 mod framebuffer;
 mod fs;
+mod hwinfo;
 mod init_loader;
+mod initcall;
 mod io;
+mod ksyms;
+mod ktest;
 mod log;
 mod metrics;
 mod mm;
+mod net;
 mod panic;
+mod profiler;
+mod rand;
 mod sched;
 mod serial;
+mod settings;
 mod signal;
+mod splash;
 mod sync;
 mod sys;
+mod sysrq;
+mod time;
+mod trace;
 mod user;
 
+use initcall::Level;
 use sched::{init_scheduler, priority::TaskPriority, spawn_task, yield_now};
 
-use limine::request::{FramebufferRequest, RsdpRequest};
+use limine::request::{FramebufferRequest, ModuleRequest, RsdpRequest};
 
 /// Limine framebuffer request
 /// This static variable is placed in the .requests section so that
@@ -38,6 +56,62 @@ static FRAMEBUFFER_REQUEST: FramebufferRequest = FramebufferRequest::new();
 #[link_section = ".requests"]
 static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
 
+/// Limine module request
+/// Asks the bootloader for the modules listed in limine.conf; the initrd
+/// (`/boot/initrd.tar`) is one of them
+#[used]
+#[link_section = ".requests"]
+static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
+
+/// Install the IDT, the exception, timer and IPI handlers and the driver
+/// IRQ stubs, so kernel tests can use syscalls safely
+fn init_interrupts() {
+    // SAFETY: runs once on the BSP during boot, with interrupts disabled
+    unsafe {
+        sched::timer::init_idt();
+        sched::timer::init_apic_timer_handler();
+        sched::timer::init_reschedule_ipi_handler();
+        mm::tlb::init_ipi_handler();
+        arch::x86_64::fault::init_page_fault_handler();
+        dev::api::irq::init();
+    }
+}
+initcall!(Arch, init_interrupts);
+
+/// Load the initrd boot module, which holds the programs for init to start
+/// and the console font (`fbfont=`)
+fn load_initrd() {
+    let initrd = MODULE_REQUEST
+        .get_response()
+        .and_then(|response| response.modules().iter().find(|module| module.path().to_bytes().ends_with(b"initrd.tar")));
+    match initrd {
+        Some(module) => {
+            let image = unsafe { core::slice::from_raw_parts(module.addr(), module.size() as usize) };
+            let files = fs::initrd::init(image);
+            serial_println!("[INITRD] {} files in {} bytes", files, image.len());
+        }
+        None => serial_println!("[INITRD] No initrd module, using the built-in init"),
+    }
+    console::load_font();
+}
+initcall!(Fs, load_initrd);
+
+/// Bring up network interfaces for the registered devices and their
+/// receive path
+fn start_network() {
+    if net::init() == 0 {
+        return;
+    }
+    for _ in 0..arch::x86_64::smp::get_cpu_count().min(config::MAX_CPUS) {
+        spawn_task("Softnet", net::softnet::softnet_task, TaskPriority::Normal).expect("Failed to spawn Softnet");
+    }
+    // IPv4 address for the first interface, unless `ipv4=` gave one
+    if net::dhcp::wanted() {
+        spawn_task("Dhcp", net::dhcp::dhcp_task, TaskPriority::Normal).expect("Failed to spawn Dhcp");
+    }
+}
+initcall!(Late, start_network);
+
 /// Demonstration task A - prints "A" in a loop
 fn task_a() -> ! {
     loop {
@@ -728,10 +802,15 @@ fn print_test_results_delayed() -> ! {
 /// Kernel entry point called by the Limine bootloader
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
+    bootchart::start();
+
     // Initialize serial port for debugging
     serial::SERIAL.lock().init();
     serial_println!("[KERNEL] MelloOS starting...");
 
+    // Query CPUID before anything consults the feature flags
+    arch::x86_64::cpu::features::init();
+
     serial_println!("[KERNEL] Getting framebuffer response...");
     // Get framebuffer response from Limine
     let framebuffer_response = FRAMEBUFFER_REQUEST
@@ -752,11 +831,31 @@ pub extern "C" fn _start() -> ! {
     serial_println!("[KERNEL] Clearing screen...");
     // Clear the screen with black color
     fb.clear(0x000000);
+    splash::draw(&mut fb);
+
+    // Route console output according to `console=` on the command line
+    console::init(&limine_framebuffer);
+    dev::fb::init(&limine_framebuffer);
+
+    // Tracepoints named by `trace=`
+    trace::init();
+
+    // Seed the kernel CSPRNG; KASLR draws the heap base from it
+    rand::init();
+
+    // Firmware information; runtime services must be called before the
+    // memory manager removes execute permission from the identity map
+    let firmware = unsafe { efi::init() };
+    serial_println!(
+        "[EFI] Firmware: {:?}, Secure Boot: {:?}",
+        firmware.firmware,
+        firmware.secure_boot
+    );
 
     serial_println!("[KERNEL] Initializing memory management...");
     // Initialize memory management system
     // This must be called after framebuffer setup but before any dynamic memory allocation
-    mm::init_memory();
+    bootchart::measure("mm", mm::init_memory);
 
     serial_println!("[KERNEL] Initializing ACPI...");
     // Get RSDP address from Limine
@@ -766,13 +865,33 @@ pub extern "C" fn _start() -> ! {
     let rsdp_addr = rsdp_response.address() as u64;
 
     // Parse ACPI MADT to detect CPUs
-    arch::x86_64::acpi::init_acpi(rsdp_addr).expect("Failed to initialize ACPI");
+    bootchart::measure("acpi", || {
+        arch::x86_64::acpi::init_acpi(rsdp_addr).expect("Failed to initialize ACPI")
+    });
+
+    bootchart::measure("clock", || {
+        // Prefer the HPET over the PIT as the reference clock
+        let hpet = arch::x86_64::acpi::platform_info()
+            .and_then(|platform| platform.hpet_address)
+            .and_then(|address| unsafe { arch::x86_64::hpet::init(address) });
+        if let Some(hpet) = hpet {
+            serial_println!("[HPET] Main counter running at {} Hz", hpet.frequency());
+        }
+
+        // Calibrate the TSC against the HPET (if present) and map the vDSO
+        // clock
+        time::clock::init();
+        if let Err(e) = arch::x86_64::vdso::init() {
+            serial_println!("[VDSO] Not mapped: {}", e);
+        }
+    });
 
     serial_println!("[KERNEL] Initializing BSP Local APIC...");
     // Get MADT info to retrieve LAPIC address
     let madt_info = arch::x86_64::acpi::get_madt_info().expect("MADT info not available");
 
-    // Create and initialize BSP Local APIC
+    // Create and initialize BSP Local APIC, in x2APIC mode if available
+    arch::x86_64::apic::select_mode();
     let mut bsp_lapic = unsafe { arch::x86_64::apic::LocalApic::new(madt_info.lapic_address) };
     bsp_lapic.init();
 
@@ -802,9 +921,21 @@ pub extern "C" fn _start() -> ! {
         bsp_apic_id
     );
 
+    // Load the kernel's own GDT and TSS (with the user segments SYSRET
+    // expects), the syscall MSRs and SMEP/SMAP, as every AP does
+    if let Err(e) = arch::x86_64::gdt::init_gdt_tss_for_cpu(0) {
+        panic!("[GDT] BSP GDT/TSS initialization failed: {}", e);
+    }
+
+    // Enable the FPU and SIMD state tasks save and restore on switches;
+    // this also sizes the per-task save areas, so it precedes any task
+    unsafe {
+        arch::x86_64::fpu::init(0);
+    }
+
     serial_println!("[KERNEL] Calibrating APIC timer...");
-    // Calibrate APIC timer using PIT
-    let lapic_frequency = unsafe { bsp_lapic.calibrate_timer() };
+    // Calibrate APIC timer against the HPET or PIT
+    let lapic_frequency = bootchart::measure("lapic-calibrate", || unsafe { bsp_lapic.calibrate_timer() });
     serial_println!("[APIC] LAPIC timer frequency: {} Hz", lapic_frequency);
 
     // Store calibrated frequency in BSP per-CPU data
@@ -814,16 +945,17 @@ pub extern "C" fn _start() -> ! {
     }
 
     serial_println!("[KERNEL] Initializing BSP APIC timer...");
-    // Initialize APIC timer at SCHED_HZ (100 Hz)
+    // Initialize APIC timer at SCHED_HZ (100 Hz), in TSC-deadline mode if
+    // the CPU has it
     unsafe {
-        bsp_lapic.init_timer(lapic_frequency, config::SCHED_HZ);
+        arch::x86_64::apic::timer::start(&mut bsp_lapic, lapic_frequency, config::SCHED_HZ);
     }
     serial_println!("[APIC] core0 timer @{}Hz", config::SCHED_HZ);
 
     serial_println!("[KERNEL] Initializing SMP (bringing up Application Processors)...");
 
     // Initialize SMP and bring up Application Processors
-    let cpu_count = match arch::x86_64::smp::init_smp(&mut bsp_lapic) {
+    let cpu_count = match bootchart::measure("smp", || arch::x86_64::smp::init_smp(&mut bsp_lapic)) {
         Ok(count) => {
             serial_println!("[SMP] Successfully initialized {} CPUs", count);
             count
@@ -839,33 +971,32 @@ pub extern "C" fn _start() -> ! {
     static CPU_COUNT: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
     CPU_COUNT.store(cpu_count, core::sync::atomic::Ordering::SeqCst);
 
+    // All boot-time mappings (including the AP trampoline) are in place now
+    mm::audit_wx();
+
+    // CPUs, ACPI tables and the memory map are known: IPC ports, PTYs and
+    // the hardware inventory
+    initcall::run(Level::Early);
+
     serial_println!("[KERNEL] Writing message to screen...");
     // Display "Hello from MelloOS ✨" message
     // White text on black background, positioned at (100, 100)
     fb.write_string("Hello from MelloOS ✨", 100, 100, 0xFFFFFF, 0x000000);
 
-    serial_println!("[KERNEL] Initializing IPC subsystem...");
-    // Initialize IPC ports
-    sys::port::init_ipc();
-
-    serial_println!("[KERNEL] Initializing PTY subsystem...");
-    // Initialize PTY (pseudo-terminal) subsystem
-    dev::pty::init();
-
-    serial_println!("[KERNEL] Initializing /proc filesystem...");
-    // Initialize /proc virtual filesystem
-    fs::proc::init();
-
     serial_println!("[KERNEL] Initializing scheduler...");
     // Initialize the task scheduler
-    init_scheduler();
-
-    serial_println!("[KERNEL] Initializing timer interrupt...");
-    // Initialize IDT and syscall handler early so kernel tests can use syscalls safely
-    unsafe {
-        sched::timer::init_idt();
-        sched::timer::init_apic_timer_handler();
-        sched::timer::init_reschedule_ipi_handler();
+    bootchart::measure("scheduler", init_scheduler);
+
+    // Interrupt handlers, then the device drivers, then what needs their
+    // devices: the initrd, the console font and /proc
+    initcall::run(Level::Arch);
+    initcall::run(Level::Drivers);
+    initcall::run(Level::Fs);
+
+    // Kernel test mode: run registered tests and exit QEMU instead of booting userland
+    if ktest::enabled() {
+        serial_println!("[KERNEL] Booted with 'ktest', running kernel tests...");
+        ktest::run_all_and_exit();
     }
 
     serial_println!("[KERNEL] ========================================");
@@ -947,7 +1078,31 @@ pub extern "C" fn _start() -> ! {
         core::arch::asm!("sti");
     }
 
+    // Worker for work deferred by interrupt handlers
+    sched::workqueue::start(&sched::workqueue::SYSTEM, TaskPriority::High)
+        .expect("Failed to spawn the events worker");
+
+    // Idle page scanning and memory pressure events
+    spawn_task("MM-Pressure", mm::pressure::pressure_task, TaskPriority::Low)
+        .expect("Failed to spawn MM-Pressure");
+
+    // Saved settings, the network and the framebuffer console's back
+    // buffer
+    initcall::run(Level::Late);
+
+    // Framebuffer console benchmark, on request
+    if cmdline::has_flag("conbench") {
+        spawn_task("ConBench", console::bench_task, TaskPriority::Low)
+            .expect("Failed to spawn ConBench");
+    }
+
+    if cmdline::has_flag("syscallbench") {
+        spawn_task("SyscallBench", arch::x86_64::syscall::bench::bench_task, TaskPriority::Low)
+            .expect("Failed to spawn SyscallBench");
+    }
+
     serial_println!("[KERNEL] Scheduler initialization complete!");
+    bootchart::report();
     serial_println!("[KERNEL] Boot complete! Entering idle loop...");
 
     // Spawn a task to print test results after some time
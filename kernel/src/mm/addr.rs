@@ -0,0 +1,346 @@
+//! Typed physical and virtual addresses
+//!
+//! [`PhysAddr`] and [`VirtAddr`] wrap a 64-bit address so one cannot be
+//! passed where the other is expected; they convert into each other
+//! through the higher-half direct map. Debug builds check addresses as
+//! they are made and used, and panic with the address and what is wrong
+//! with it instead of letting a bad one reach a page table or a device:
+//!
+//! - a physical address fits in 52 bits, and one read through the direct
+//!   map ([`PhysAddr::to_virt`]) lies in the bootloader's memory map;
+//! - a virtual address is canonical;
+//! - MMIO ([`crate::io::mmio::MmioRegion`]) lies in a window the firmware
+//!   tables or PCI reported with [`add_mmio_window`], and not in RAM.
+//!
+//! Release builds skip the checks.
+
+use crate::sync::IrqSpinLock;
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub};
+use limine::memory_map::EntryType;
+
+/// Physical address bits the architecture allows
+const PHYS_ADDR_BITS: u32 = 52;
+
+/// MMIO windows the registry has room for
+const MAX_MMIO_WINDOWS: usize = 32;
+
+/// A physical address
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(transparent)]
+pub struct PhysAddr(u64);
+
+/// A virtual address
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(transparent)]
+pub struct VirtAddr(u64);
+
+impl PhysAddr {
+    /// Panics in debug builds if `addr` is wider than 52 bits
+    #[track_caller]
+    pub const fn new(addr: u64) -> Self {
+        if cfg!(debug_assertions) && addr >> PHYS_ADDR_BITS != 0 {
+            panic!("[MM] physical address wider than 52 bits");
+        }
+        Self(addr)
+    }
+
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub const fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+
+    pub const fn is_aligned(self, align: usize) -> bool {
+        self.0 & (align as u64 - 1) == 0
+    }
+
+    pub const fn align_down(self, align: usize) -> Self {
+        Self(self.0 & !(align as u64 - 1))
+    }
+
+    pub const fn align_up(self, align: usize) -> Self {
+        Self::new((self.0 + align as u64 - 1) & !(align as u64 - 1))
+    }
+
+    /// `self + offset`, or None if that is wider than 52 bits
+    pub fn checked_add(self, offset: usize) -> Option<Self> {
+        let addr = self.0.checked_add(offset as u64)?;
+        (addr >> PHYS_ADDR_BITS == 0).then_some(Self(addr))
+    }
+
+    /// The address in the higher-half direct map
+    ///
+    /// Panics in debug builds if the address is outside the memory map.
+    #[track_caller]
+    pub fn to_virt(self) -> VirtAddr {
+        if cfg!(debug_assertions) && !in_memory_map(self.0, 1) {
+            panic!("[MM] physical address {} is outside the memory map", self);
+        }
+        VirtAddr::new(self.0 + super::hhdm_offset() as u64)
+    }
+}
+
+impl VirtAddr {
+    /// Panics in debug builds if `addr` is not canonical
+    #[track_caller]
+    pub const fn new(addr: u64) -> Self {
+        if cfg!(debug_assertions) && !is_canonical(addr) {
+            panic!("[MM] non-canonical virtual address");
+        }
+        Self(addr)
+    }
+
+    /// `addr`, or None if it is not canonical; for addresses from the
+    /// hardware or user space that must not panic
+    pub const fn try_new(addr: u64) -> Option<Self> {
+        if is_canonical(addr) {
+            Some(Self(addr))
+        } else {
+            None
+        }
+    }
+
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        Self::new(ptr as u64)
+    }
+
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub const fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+
+    pub const fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+
+    pub const fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+
+    pub const fn is_aligned(self, align: usize) -> bool {
+        self.0 & (align as u64 - 1) == 0
+    }
+
+    pub const fn align_down(self, align: usize) -> Self {
+        Self(self.0 & !(align as u64 - 1))
+    }
+
+    pub const fn align_up(self, align: usize) -> Self {
+        Self::new((self.0 + align as u64 - 1) & !(align as u64 - 1))
+    }
+
+    /// `self + offset`, or None if that is not canonical
+    pub fn checked_add(self, offset: usize) -> Option<Self> {
+        let addr = self.0.checked_add(offset as u64)?;
+        is_canonical(addr).then_some(Self(addr))
+    }
+
+    /// Index into the page table at `level`, from 4 for the PML4 down to
+    /// 1 for a page table
+    pub const fn table_index(self, level: u32) -> usize {
+        ((self.0 >> (12 + 9 * (level - 1))) & 0x1ff) as usize
+    }
+
+    /// The physical address of a direct-map address
+    ///
+    /// Panics in debug builds if the address is below the direct map.
+    #[track_caller]
+    pub fn to_phys(self) -> PhysAddr {
+        let offset = super::hhdm_offset() as u64;
+        if cfg!(debug_assertions) && self.0 < offset {
+            panic!("[MM] virtual address {} is not in the direct map", self);
+        }
+        PhysAddr::new(self.0 - offset)
+    }
+}
+
+/// Whether bits 63..47 of `addr` are all equal
+const fn is_canonical(addr: u64) -> bool {
+    matches!(addr >> 47, 0 | 0x1ffff)
+}
+
+macro_rules! address_ops {
+    ($type:ident, $label:literal) => {
+        impl Add<usize> for $type {
+            type Output = Self;
+
+            #[track_caller]
+            fn add(self, offset: usize) -> Self {
+                Self::new(self.0 + offset as u64)
+            }
+        }
+
+        impl AddAssign<usize> for $type {
+            #[track_caller]
+            fn add_assign(&mut self, offset: usize) {
+                *self = *self + offset;
+            }
+        }
+
+        impl Sub<usize> for $type {
+            type Output = Self;
+
+            #[track_caller]
+            fn sub(self, offset: usize) -> Self {
+                Self::new(self.0 - offset as u64)
+            }
+        }
+
+        /// Distance between two addresses
+        impl Sub for $type {
+            type Output = usize;
+
+            fn sub(self, other: Self) -> usize {
+                (self.0 - other.0) as usize
+            }
+        }
+
+        impl fmt::Display for $type {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{:#x}", self.0)
+            }
+        }
+
+        impl fmt::Debug for $type {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, concat!($label, "({:#x})"), self.0)
+            }
+        }
+
+        impl fmt::LowerHex for $type {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::LowerHex::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+address_ops!(PhysAddr, "PhysAddr");
+address_ops!(VirtAddr, "VirtAddr");
+
+/// Whether `[addr, addr + len)` lies in one memory map entry; true before
+/// the map is known
+fn in_memory_map(addr: u64, len: u64) -> bool {
+    let map = super::memory_map();
+    map.is_empty() || map.iter().any(|entry| entry.base <= addr && addr + len <= entry.base + entry.length)
+}
+
+/// The RAM memory map entry overlapping `[addr, addr + len)`, if any
+fn overlapping_ram(addr: u64, len: u64) -> Option<&'static limine::memory_map::Entry> {
+    super::memory_map().iter().copied().find(|entry| {
+        let ram = matches!(
+            entry.entry_type,
+            EntryType::USABLE | EntryType::BOOTLOADER_RECLAIMABLE | EntryType::EXECUTABLE_AND_MODULES
+        );
+        ram && entry.base < addr + len && addr < entry.base + entry.length
+    })
+}
+
+/// A range of device registers some bus or firmware table reported
+#[derive(Debug, Clone, Copy)]
+struct MmioWindow {
+    name: &'static str,
+    base: u64,
+    len: u64,
+}
+
+struct MmioWindows {
+    list: [MmioWindow; MAX_MMIO_WINDOWS],
+    count: usize,
+}
+
+static MMIO_WINDOWS: IrqSpinLock<MmioWindows> = IrqSpinLock::new(MmioWindows {
+    list: [MmioWindow { name: "", base: 0, len: 0 }; MAX_MMIO_WINDOWS],
+    count: 0,
+});
+
+/// Note `len` bytes of device registers at `base`, as reported by `name`
+///
+/// Only debug builds keep the windows, to check MMIO regions against.
+pub fn add_mmio_window(name: &'static str, base: PhysAddr, len: u64) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    let mut windows = MMIO_WINDOWS.lock();
+    let known = windows.list[..windows.count]
+        .iter()
+        .any(|window| window.base <= base.0 && base.0 + len <= window.base + window.len);
+    if known {
+        return;
+    }
+    if windows.count == MAX_MMIO_WINDOWS {
+        crate::log_warn!("MM", "No room for MMIO window {} at {}", name, base);
+        return;
+    }
+    let index = windows.count;
+    windows.list[index] = MmioWindow { name, base: base.0, len };
+    windows.count += 1;
+}
+
+/// Check in debug builds that `len` bytes of registers at `base`, which
+/// the device `name` is about to use, are in a known MMIO window and not
+/// in RAM
+#[track_caller]
+pub fn check_mmio(name: &str, base: PhysAddr, len: u64) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    if base.0 == 0 {
+        panic!("[MMIO] {}: null register base", name);
+    }
+    if let Some(ram) = overlapping_ram(base.0, len) {
+        panic!(
+            "[MMIO] {}: registers {}..{:#x} overlap RAM at {:#x}..{:#x}",
+            name,
+            base,
+            base.0 + len,
+            ram.base,
+            ram.base + ram.length
+        );
+    }
+    let windows = MMIO_WINDOWS.lock();
+    let known = windows.list[..windows.count]
+        .iter()
+        .any(|window| window.base <= base.0 && base.0 + len <= window.base + window.len);
+    if !known {
+        panic!("[MMIO] {}: registers {}..{:#x} are in no known MMIO window", name, base, base.0 + len);
+    }
+}
+
+/// Name of the known MMIO window holding `addr`
+pub fn mmio_window_name(addr: PhysAddr) -> Option<&'static str> {
+    let windows = MMIO_WINDOWS.lock();
+    windows.list[..windows.count]
+        .iter()
+        .find(|window| window.base <= addr.0 && addr.0 < window.base + window.len)
+        .map(|window| window.name)
+}
+
+crate::kernel_test! {
+    /// Addresses convert through the direct map, and MMIO is only
+    /// accepted in a reported window
+    fn addr_types_and_mmio_windows() {
+        let phys = PhysAddr::new(0x12_3456);
+        crate::ktest_assert_eq!(phys.align_down(0x1000), PhysAddr::new(0x12_3000), "align down");
+        crate::ktest_assert_eq!(phys.align_up(0x1000), PhysAddr::new(0x12_4000), "align up");
+        crate::ktest_assert!(!phys.is_aligned(8) && (phys + 2).is_aligned(8), "alignment");
+        crate::ktest_assert_eq!(phys.to_virt().to_phys(), phys, "direct map round trip");
+        crate::ktest_assert!(is_canonical(0xffff_8000_0000_0000), "higher half canonical");
+        crate::ktest_assert!(!is_canonical(0x0000_8000_0000_0000), "hole canonical");
+
+        // Release builds keep no windows
+        if cfg!(debug_assertions) {
+            add_mmio_window("test", PhysAddr::new(0xfe00_0000), 0x1000);
+            crate::ktest_assert_eq!(mmio_window_name(PhysAddr::new(0xfe00_0ff0)), Some("test"), "window lookup");
+            crate::ktest_assert_eq!(mmio_window_name(PhysAddr::new(0xfe00_1000)), None, "past the window");
+            check_mmio("test", PhysAddr::new(0xfe00_0100), 0x100);
+        }
+        Ok(())
+    }
+}
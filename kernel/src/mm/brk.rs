@@ -0,0 +1,154 @@
+//! Program break (brk) heap
+//!
+//! Each process has one heap region that starts just above its loaded image
+//! (plus a random page offset unless `kaslr=off`) and ends at the page
+//! holding the current break. Moving the break up only extends the region;
+//! pages are allocated zeroed when first touched, through the same fault
+//! path as anonymous mmaps. Moving it down unmaps and frees the pages above
+//! the new break.
+//!
+//! The heap may grow to `brk_max=<MiB>` from the command line (128 MiB by
+//! default). `sbrk` is a userland wrapper over `SYS_BRK`.
+
+use super::paging::PageTableFlags;
+use crate::sched::task::{MemoryRegion, MemoryRegionType, Task};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Default heap size limit
+const DEFAULT_MAX_HEAP: usize = 128 * 1024 * 1024;
+
+/// Heap size limit in bytes
+static MAX_HEAP: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_HEAP);
+
+/// Read the `brk_max=` option (in MiB)
+pub fn init() {
+    if let Some(value) = crate::cmdline::value("brk_max") {
+        match value.parse::<usize>() {
+            Ok(mib) if mib > 0 => MAX_HEAP.store(mib * 1024 * 1024, Ordering::Relaxed),
+            _ => crate::serial_println!("[MM] Ignoring invalid brk_max={}", value),
+        }
+    }
+    crate::serial_println!(
+        "[MM] brk heap limit: {} MiB",
+        MAX_HEAP.load(Ordering::Relaxed) / (1024 * 1024)
+    );
+}
+
+/// Heap size limit in bytes
+pub fn max_heap() -> usize {
+    MAX_HEAP.load(Ordering::Relaxed)
+}
+
+fn page_up(addr: usize) -> usize {
+    (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// Pick the heap start for an image whose last segment ends at `image_end`
+pub fn heap_base(image_end: usize) -> usize {
+    page_up(image_end) + super::kaslr::user_brk_offset()
+}
+
+/// Set up an empty heap for a freshly loaded image
+pub fn reset(task: &mut Task, image_end: usize) {
+    task.heap_start = heap_base(image_end);
+    task.brk = task.heap_start;
+}
+
+/// Move the break of `task` to `requested`
+///
+/// Returns the new break, or the unchanged current break if the request is
+/// out of range, would exceed the limit or run into another region, as
+/// Linux does. A request of 0 just queries the break.
+pub fn set_brk(task: &mut Task, requested: usize) -> usize {
+    let start = task.heap_start;
+    if requested == 0 || start == 0 {
+        return task.brk;
+    }
+    if requested < start || requested - start > max_heap() {
+        return task.brk;
+    }
+
+    let old_end = page_up(task.brk);
+    let new_end = page_up(requested);
+
+    if new_end > old_end {
+        let grown = MemoryRegion::new(
+            old_end,
+            new_end,
+            PageTableFlags::PRESENT
+                | PageTableFlags::USER
+                | PageTableFlags::WRITABLE
+                | PageTableFlags::NO_EXECUTE,
+            MemoryRegionType::Heap,
+        );
+        let blocked = task.memory_regions[..task.region_count]
+            .iter()
+            .flatten()
+            .any(|region| region.overlaps_with(&grown));
+        if blocked {
+            return task.brk;
+        }
+
+        let count = task.region_count;
+        let heap = task.memory_regions[..count]
+            .iter_mut()
+            .flatten()
+            .find(|region| region.region_type == MemoryRegionType::Heap && region.end == old_end);
+        match heap {
+            Some(region) => region.end = new_end,
+            None => {
+                if task.add_memory_region(grown).is_err() {
+                    return task.brk;
+                }
+            }
+        }
+    } else if new_end < old_end
+        && super::mmap::unmap(task, new_end, old_end - new_end).is_err()
+    {
+        return task.brk;
+    }
+
+    task.brk = requested;
+    requested
+}
+
+crate::kernel_test! {
+    /// The break grows and shrinks the heap region and respects the limit
+    fn brk_heap_region() {
+        fn dummy_entry() -> ! {
+            loop {}
+        }
+        let mut task = match Task::new(
+            usize::MAX,
+            "brk_test",
+            dummy_entry,
+            crate::sched::priority::TaskPriority::Normal,
+        ) {
+            Ok(task) => task,
+            Err(_) => return Err("failed to create test task"),
+        };
+        // Far from anything the shared page table maps
+        let base = 0x20_0000_0000;
+        task.heap_start = base;
+        task.brk = base;
+
+        crate::ktest_assert_eq!(set_brk(&mut task, 0), base, "query changed the break");
+        crate::ktest_assert_eq!(set_brk(&mut task, base + 100), base + 100, "small grow failed");
+        crate::ktest_assert_eq!(set_brk(&mut task, base + 3 * PAGE_SIZE), base + 3 * PAGE_SIZE, "grow failed");
+        crate::ktest_assert_eq!(task.region_count, 1, "grow added a second region");
+        let heap = task.find_memory_region(base + 2 * PAGE_SIZE).ok_or("heap not mapped")?;
+        crate::ktest_assert_eq!(heap.end, base + 3 * PAGE_SIZE, "heap end");
+
+        crate::ktest_assert_eq!(set_brk(&mut task, base + PAGE_SIZE), base + PAGE_SIZE, "shrink failed");
+        crate::ktest_assert!(task.find_memory_region(base + PAGE_SIZE).is_none(), "page above break still mapped");
+        crate::ktest_assert_eq!(set_brk(&mut task, base - PAGE_SIZE), base + PAGE_SIZE, "moved below the heap start");
+        crate::ktest_assert_eq!(
+            set_brk(&mut task, base + max_heap() + PAGE_SIZE),
+            base + PAGE_SIZE,
+            "grew past the limit"
+        );
+        Ok(())
+    }
+}
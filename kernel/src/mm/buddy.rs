@@ -0,0 +1,538 @@
+// Buddy Allocator Core
+// Platform-independent free-list logic shared by the kernel heap
+//
+// This file only depends on `core` so that it can be compiled on the host
+// and exercised with `make test-host` (plain `rustc --test`), including the
+// randomized alloc/free sequences in the test module below. Anything that
+// touches kernel globals (locks, logging, page tables) belongs in
+// `allocator.rs` instead.
+
+#![allow(dead_code)]
+
+/// Minimum block size (64 bytes)
+pub const MIN_BLOCK_SIZE: usize = 64;
+
+/// Maximum block size (1 MB)
+pub const MAX_BLOCK_SIZE: usize = 1048576;
+
+/// Number of orders (64B to 1MB = 2^6 to 2^20 = 15 orders)
+pub const NUM_ORDERS: usize = 15;
+
+/// A contiguous range of memory handed to the buddy core
+///
+/// # Safety
+///
+/// Implementors must guarantee that `base()..base() + size()` is valid,
+/// writable memory that is not used by anything else for as long as the
+/// owning `BuddyCore` exists. The free lists are stored inline in that range.
+pub unsafe trait MemoryRegion {
+    /// First byte of the region
+    fn base(&self) -> usize;
+
+    /// Length of the region in bytes
+    fn size(&self) -> usize;
+}
+
+/// Plain address range, used by the kernel for its mapped heap
+#[derive(Debug, Clone, Copy)]
+pub struct RawRegion {
+    pub start: usize,
+    pub size: usize,
+}
+
+unsafe impl MemoryRegion for RawRegion {
+    fn base(&self) -> usize {
+        self.start
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// Free block node in the free list
+#[repr(C)]
+struct FreeBlock {
+    size: usize,
+    next: Option<*mut FreeBlock>,
+}
+
+/// Buddy allocator over an arbitrary memory region
+///
+/// Buddies are computed relative to the region base, so the region does not
+/// need to be aligned to `MAX_BLOCK_SIZE`; only `MIN_BLOCK_SIZE` alignment of
+/// the base is required.
+pub struct BuddyCore<R: MemoryRegion> {
+    /// Free lists for each order (size = 2^order * MIN_BLOCK_SIZE)
+    free_lists: [Option<*mut FreeBlock>; NUM_ORDERS],
+    /// Backing memory
+    region: R,
+    /// Total allocated bytes
+    allocated: usize,
+}
+
+/// Round a request up to its block size, or None if it can never be served
+pub fn block_size_for(size: usize) -> Option<usize> {
+    if size == 0 {
+        return None;
+    }
+
+    let actual_size = if size < MIN_BLOCK_SIZE {
+        MIN_BLOCK_SIZE
+    } else {
+        size.checked_next_power_of_two()?
+    };
+
+    if actual_size > MAX_BLOCK_SIZE {
+        None
+    } else {
+        Some(actual_size)
+    }
+}
+
+/// Order index for a block size returned by `block_size_for`
+fn order_of(block_size: usize) -> usize {
+    (block_size / MIN_BLOCK_SIZE).trailing_zeros() as usize
+}
+
+impl<R: MemoryRegion> BuddyCore<R> {
+    /// Build an allocator that owns `region`
+    ///
+    /// The region is carved into the largest naturally aligned blocks that
+    /// fit; any tail smaller than `MIN_BLOCK_SIZE` is left unused.
+    pub fn new(region: R) -> Self {
+        let start = region.base();
+        let size = region.size();
+
+        let mut core = BuddyCore {
+            free_lists: [None; NUM_ORDERS],
+            region,
+            allocated: 0,
+        };
+
+        if start % MIN_BLOCK_SIZE != 0 {
+            // Misaligned base: refuse to hand out anything rather than
+            // corrupting neighbours through bad buddy arithmetic.
+            return core;
+        }
+
+        let mut offset = 0;
+        while size - offset >= MIN_BLOCK_SIZE {
+            // Largest block that both fits and keeps natural alignment
+            // relative to the region base
+            let mut order = NUM_ORDERS - 1;
+            loop {
+                let block_size = MIN_BLOCK_SIZE << order;
+                if block_size <= size - offset && offset % block_size == 0 {
+                    break;
+                }
+                order -= 1;
+            }
+
+            core.push_free(start + offset, order);
+            offset += MIN_BLOCK_SIZE << order;
+        }
+
+        core
+    }
+
+    /// Allocate a zeroed block of at least `size` bytes
+    ///
+    /// Returns null on zero-sized or oversized requests and when out of memory.
+    pub fn alloc(&mut self, size: usize) -> *mut u8 {
+        let actual_size = match block_size_for(size) {
+            Some(s) => s,
+            None => return core::ptr::null_mut(),
+        };
+
+        if let Some(block) = self.find_free_block(order_of(actual_size)) {
+            self.allocated += actual_size;
+
+            // Zero allocated memory for security
+            unsafe {
+                core::ptr::write_bytes(block as *mut u8, 0, actual_size);
+            }
+
+            return block as *mut u8;
+        }
+
+        core::ptr::null_mut()
+    }
+
+    /// Return a block previously obtained from `alloc` with the same `size`
+    ///
+    /// Pointers outside the region, or not aligned to their block size, are
+    /// ignored instead of being threaded into the free lists.
+    pub fn free(&mut self, ptr: *mut u8, size: usize) {
+        if ptr.is_null() {
+            return;
+        }
+
+        let actual_size = match block_size_for(size) {
+            Some(s) => s,
+            None => return,
+        };
+
+        let addr = ptr as usize;
+        let start = self.region.base();
+        if addr < start || addr + actual_size > start + self.region.size() {
+            return;
+        }
+        if (addr - start) % actual_size != 0 {
+            return;
+        }
+
+        self.allocated = self.allocated.saturating_sub(actual_size);
+        self.free_and_merge(addr, order_of(actual_size));
+    }
+
+    /// Get allocated memory in bytes
+    pub fn allocated_bytes(&self) -> usize {
+        self.allocated
+    }
+
+    /// Sum of all blocks currently sitting in the free lists
+    pub fn free_bytes(&self) -> usize {
+        let mut total = 0;
+        for order in 0..NUM_ORDERS {
+            let mut current = self.free_lists[order];
+            while let Some(block) = current {
+                total += MIN_BLOCK_SIZE << order;
+                current = unsafe { (*block).next };
+            }
+        }
+        total
+    }
+
+    /// Number of free blocks at a given order
+    pub fn free_blocks(&self, order: usize) -> usize {
+        let mut count = 0;
+        let mut current = self.free_lists.get(order).copied().flatten();
+        while let Some(block) = current {
+            count += 1;
+            current = unsafe { (*block).next };
+        }
+        count
+    }
+
+    /// Walk every free list and verify its structural invariants
+    ///
+    /// Checks that each free block lies inside the region, is aligned to its
+    /// own size, records the right size in its header, and that no two free
+    /// blocks overlap. Cost is quadratic in the number of free blocks, so
+    /// this is meant for tests and debug paths, not the hot path.
+    pub fn check_invariants(&self) -> Result<(), &'static str> {
+        let start = self.region.base();
+        let end = start + self.region.size();
+
+        for order in 0..NUM_ORDERS {
+            let block_size = MIN_BLOCK_SIZE << order;
+            let mut current = self.free_lists[order];
+
+            while let Some(block) = current {
+                let addr = block as usize;
+                if addr < start || addr + block_size > end {
+                    return Err("free block outside heap region");
+                }
+                if (addr - start) % block_size != 0 {
+                    return Err("free block misaligned for its order");
+                }
+                if unsafe { (*block).size } != block_size {
+                    return Err("free block header size mismatch");
+                }
+                if self.overlaps_other_free(addr, block_size, order) {
+                    return Err("overlapping free blocks");
+                }
+                current = unsafe { (*block).next };
+            }
+        }
+
+        let usable = self.free_bytes() + self.allocated;
+        if usable > self.region.size() {
+            return Err("free + allocated exceeds heap size");
+        }
+
+        Ok(())
+    }
+
+    fn overlaps_other_free(&self, addr: usize, size: usize, own_order: usize) -> bool {
+        for order in 0..NUM_ORDERS {
+            let block_size = MIN_BLOCK_SIZE << order;
+            let mut current = self.free_lists[order];
+            while let Some(block) = current {
+                let other = block as usize;
+                let same = other == addr && order == own_order;
+                if !same && other < addr + size && addr < other + block_size {
+                    return true;
+                }
+                current = unsafe { (*block).next };
+            }
+        }
+        false
+    }
+
+    fn push_free(&mut self, addr: usize, order: usize) {
+        let block = addr as *mut FreeBlock;
+        unsafe {
+            (*block).size = MIN_BLOCK_SIZE << order;
+            (*block).next = self.free_lists[order];
+        }
+        self.free_lists[order] = Some(block);
+    }
+
+    /// Find or split blocks to get a free block of the requested order
+    fn find_free_block(&mut self, order: usize) -> Option<*mut FreeBlock> {
+        if let Some(block) = self.free_lists[order] {
+            unsafe {
+                self.free_lists[order] = (*block).next;
+            }
+            return Some(block);
+        }
+
+        // No block in this order, try to split a larger block
+        if order + 1 < NUM_ORDERS {
+            if let Some(larger_block) = self.find_free_block(order + 1) {
+                let block_size = MIN_BLOCK_SIZE << order;
+                self.push_free(larger_block as usize + block_size, order);
+
+                unsafe {
+                    (*larger_block).size = block_size;
+                }
+
+                return Some(larger_block);
+            }
+        }
+
+        None
+    }
+
+    /// Free block and merge with buddy if possible
+    fn free_and_merge(&mut self, addr: usize, order: usize) {
+        let start = self.region.base();
+        let block_size = MIN_BLOCK_SIZE << order;
+
+        // Buddy is found by flipping the order bit of the region offset
+        let buddy_addr = start + ((addr - start) ^ block_size);
+
+        if order + 1 < NUM_ORDERS
+            && buddy_addr + block_size <= start + self.region.size()
+            && self.remove_from_free_list(buddy_addr, order)
+        {
+            let merged_addr = if addr < buddy_addr { addr } else { buddy_addr };
+            self.free_and_merge(merged_addr, order + 1);
+            return;
+        }
+
+        self.push_free(addr, order);
+    }
+
+    /// Remove a block from free list if it exists
+    fn remove_from_free_list(&mut self, addr: usize, order: usize) -> bool {
+        let mut current = self.free_lists[order];
+        let mut prev: Option<*mut FreeBlock> = None;
+
+        while let Some(block) = current {
+            if block as usize == addr {
+                unsafe {
+                    if let Some(prev_block) = prev {
+                        (*prev_block).next = (*block).next;
+                    } else {
+                        self.free_lists[order] = (*block).next;
+                    }
+                }
+                return true;
+            }
+
+            prev = Some(block);
+            unsafe {
+                current = (*block).next;
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARENA_SIZE: usize = 2 * MAX_BLOCK_SIZE;
+
+    /// Page-aligned host allocation so tests can run in parallel without
+    /// sharing memory
+    struct TestRegion {
+        arena: *mut u8,
+        skew: usize,
+        len: usize,
+    }
+
+    impl TestRegion {
+        fn new(skew: usize, len: usize) -> Self {
+            assert!(skew + len <= ARENA_SIZE);
+            let arena = unsafe { std::alloc::alloc(Self::layout()) };
+            assert!(!arena.is_null());
+            unsafe { core::ptr::write_bytes(arena, 0xAA, ARENA_SIZE) };
+            TestRegion { arena, skew, len }
+        }
+
+        fn layout() -> std::alloc::Layout {
+            std::alloc::Layout::from_size_align(ARENA_SIZE, 4096).unwrap()
+        }
+    }
+
+    impl Drop for TestRegion {
+        fn drop(&mut self) {
+            unsafe { std::alloc::dealloc(self.arena, Self::layout()) };
+        }
+    }
+
+    unsafe impl MemoryRegion for TestRegion {
+        fn base(&self) -> usize {
+            self.arena as usize + self.skew
+        }
+
+        fn size(&self) -> usize {
+            self.len
+        }
+    }
+
+    /// Small xorshift generator so the fuzz sequences are reproducible
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            let mut x = self.0;
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            self.0 = x;
+            x
+        }
+    }
+
+    #[test]
+    fn test_block_size_rounding() {
+        assert_eq!(block_size_for(0), None);
+        assert_eq!(block_size_for(1), Some(MIN_BLOCK_SIZE));
+        assert_eq!(block_size_for(65), Some(128));
+        assert_eq!(block_size_for(MAX_BLOCK_SIZE), Some(MAX_BLOCK_SIZE));
+        assert_eq!(block_size_for(MAX_BLOCK_SIZE + 1), None);
+        assert_eq!(block_size_for(usize::MAX), None);
+    }
+
+    #[test]
+    fn test_alloc_free_restores_heap() {
+        let mut heap = BuddyCore::new(TestRegion::new(0, ARENA_SIZE));
+        let initial = heap.free_bytes();
+        assert_eq!(initial, ARENA_SIZE);
+
+        let a = heap.alloc(100);
+        let b = heap.alloc(4096);
+        assert!(!a.is_null() && !b.is_null());
+        assert_eq!(heap.allocated_bytes(), 128 + 4096);
+        heap.check_invariants().unwrap();
+
+        heap.free(a, 100);
+        heap.free(b, 4096);
+        assert_eq!(heap.allocated_bytes(), 0);
+        assert_eq!(heap.free_bytes(), initial);
+        assert_eq!(heap.free_blocks(NUM_ORDERS - 1), 2);
+        heap.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_alloc_is_zeroed() {
+        let mut heap = BuddyCore::new(TestRegion::new(0, ARENA_SIZE));
+        let p = heap.alloc(256);
+        let bytes = unsafe { core::slice::from_raw_parts(p, 256) };
+        assert!(bytes.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_exhaustion_returns_null() {
+        let mut heap = BuddyCore::new(TestRegion::new(0, ARENA_SIZE));
+        assert!(!heap.alloc(MAX_BLOCK_SIZE).is_null());
+        assert!(!heap.alloc(MAX_BLOCK_SIZE).is_null());
+        assert!(heap.alloc(MIN_BLOCK_SIZE).is_null());
+        assert!(heap.alloc(MAX_BLOCK_SIZE + 1).is_null());
+    }
+
+    #[test]
+    fn test_foreign_and_misaligned_free_ignored() {
+        let mut heap = BuddyCore::new(TestRegion::new(0, ARENA_SIZE));
+        let p = heap.alloc(128);
+
+        let mut outside = [0u8; 128];
+        heap.free(outside.as_mut_ptr(), 128);
+        heap.free(unsafe { p.add(64) }, 128);
+        heap.check_invariants().unwrap();
+        assert_eq!(heap.allocated_bytes(), 128);
+    }
+
+    #[test]
+    fn test_unaligned_region_base() {
+        // Base aligned to MIN_BLOCK_SIZE only, with an odd-sized tail
+        let mut heap = BuddyCore::new(TestRegion::new(MIN_BLOCK_SIZE * 3, 300_000));
+        heap.check_invariants().unwrap();
+        let initial = heap.free_bytes();
+
+        let mut ptrs = [core::ptr::null_mut(); 64];
+        for p in ptrs.iter_mut() {
+            *p = heap.alloc(1000);
+            assert!(!p.is_null());
+        }
+        heap.check_invariants().unwrap();
+        for p in ptrs.iter() {
+            heap.free(*p, 1000);
+        }
+        heap.check_invariants().unwrap();
+        assert_eq!(heap.free_bytes(), initial);
+    }
+
+    #[test]
+    fn test_randomized_sequences() {
+        for seed in 1..=32u64 {
+            let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let mut heap = BuddyCore::new(TestRegion::new(MIN_BLOCK_SIZE, ARENA_SIZE - 4096));
+            let initial = heap.free_bytes();
+            let mut live: Vec<(usize, usize)> = Vec::new();
+
+            for _ in 0..2000 {
+                if live.is_empty() || rng.next() % 3 != 0 {
+                    let size = 1 + (rng.next() % 20_000) as usize;
+                    let p = heap.alloc(size);
+                    if p.is_null() {
+                        continue;
+                    }
+                    let addr = p as usize;
+                    let len = block_size_for(size).unwrap();
+                    for &(other, other_size) in &live {
+                        let other_len = block_size_for(other_size).unwrap();
+                        assert!(
+                            addr + len <= other || other + other_len <= addr,
+                            "seed {}: live allocations overlap",
+                            seed
+                        );
+                    }
+                    // Scribble so a later overlap shows up as header corruption
+                    unsafe { core::ptr::write_bytes(p, 0x5A, size) };
+                    live.push((addr, size));
+                } else {
+                    let idx = (rng.next() as usize) % live.len();
+                    let (addr, size) = live.swap_remove(idx);
+                    heap.free(addr as *mut u8, size);
+                }
+                if let Err(e) = heap.check_invariants() {
+                    panic!("seed {}: {}", seed, e);
+                }
+            }
+
+            for (addr, size) in live.drain(..) {
+                heap.free(addr as *mut u8, size);
+            }
+            assert_eq!(heap.allocated_bytes(), 0);
+            assert_eq!(heap.free_bytes(), initial, "seed {}: blocks lost", seed);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Demand paging for user memory regions
+//!
+//! User regions are recorded in the task's region list without any frames
+//! behind them. The first access to a page faults, and [`handle_fault`]
+//! allocates a zeroed frame, copies in the file bytes that fall on the page
+//! if the region has a `FileBacking`, and maps it with the region's flags.
+//! The contents are written through the kernel's direct map before the page
+//! becomes visible, so code pages are never mapped writable.
+//!
+//! Pages of a shared memory mapping are not allocated here: they map the
+//! frame the shm object already holds for that page. Pages of a device
+//! mapping map the device memory behind them.
+//!
+//! Anonymous regions get a 2 MiB page instead when the fault falls in a
+//! 2 MiB stretch they cover entirely (`hugepage::try_populate`).
+//!
+//! Every populated page counts as a minor fault of the task.
+
+use super::paging::PageTableFlags;
+use super::{phys_to_virt, PhysAddr, VirtAddr};
+use crate::sched::task::{FileBacking, MemoryRegion, MemoryRegionType};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Copy the bytes of `backing` that fall on the page at `page` into `dst`
+fn fill_page(backing: &FileBacking, page: VirtAddr, dst: &mut [u8]) {
+    let page = page.as_usize();
+    let lo = page.max(backing.vaddr);
+    let hi = (page + PAGE_SIZE).min(backing.vaddr + backing.file_size);
+    if lo >= hi {
+        return;
+    }
+    let src = backing.offset + (lo - backing.vaddr);
+    let len = hi - lo;
+    if src + len > backing.image.len() {
+        return;
+    }
+    dst[lo - page..hi - page].copy_from_slice(&backing.image[src..src + len]);
+}
+
+/// Map the page at `page` of a region to `frame`, which the region does
+/// not own
+fn populate_borrowed(page: VirtAddr, frame: PhysAddr, flags: PageTableFlags) -> Result<usize, &'static str> {
+    super::with_memory_managers(|pmm, mapper| {
+        if mapper.translate(page).is_some() {
+            return Ok(0);
+        }
+        mapper.map_page(page, frame, flags, pmm)?;
+        Ok(1)
+    })
+}
+
+/// Allocate and map the page at `page` for `region`
+///
+/// Returns the number of frames mapped: 0 if the page was already mapped,
+/// `hugepage::HUGE_FRAMES` for a huge page.
+fn populate(region: &MemoryRegion, page: VirtAddr) -> Result<usize, &'static str> {
+    match region.region_type {
+        MemoryRegionType::Shared { id, base } => {
+            let frame = crate::sys::shm::frame(id, (page.as_usize() - base) / PAGE_SIZE).ok_or("No such shm page")?;
+            return populate_borrowed(page, frame, region.flags);
+        }
+        // Device memory is written through, so it reaches the device
+        MemoryRegionType::Device { phys, base } => {
+            return populate_borrowed(page, phys + (page.as_usize() - base), region.flags | PageTableFlags::WRITE_THROUGH);
+        }
+        _ => {}
+    }
+    super::with_memory_managers(|pmm, mapper| {
+        // Another CPU running a thread of this task may have won the race
+        if mapper.translate(page).is_some() {
+            return Ok(0);
+        }
+        if super::hugepage::try_populate(region, page, pmm, mapper)? {
+            return Ok(super::hugepage::HUGE_FRAMES);
+        }
+        // alloc_frame hands out zeroed frames
+        let frame: PhysAddr = pmm.alloc_frame().ok_or("Out of physical memory")?;
+        if let Some(backing) = &region.backing {
+            // SAFETY: the frame is ours and reachable through the direct map
+            let dst = unsafe {
+                core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr::<u8>(), PAGE_SIZE)
+            };
+            fill_page(backing, page, dst);
+        }
+        if let Err(e) = mapper.map_page(page, frame, region.flags, pmm) {
+            pmm.free_frame(frame);
+            return Err(e);
+        }
+        Ok(1)
+    })
+}
+
+/// Resolve a not-present fault on a user region of the current address space
+///
+/// Returns `true` if the page is now mapped and the access can be retried,
+/// `false` if the address is outside the task's regions (or the access is
+/// not allowed) and the fault must be handled as a real one.
+pub fn handle_fault(addr: VirtAddr, write: bool) -> bool {
+    let task = match crate::sched::get_current_task_info()
+        .and_then(|(id, _)| crate::sched::get_address_space_mut(id))
+    {
+        Some(task) => task,
+        None => return false,
+    };
+
+    let region = match task.find_memory_region(addr.as_usize()) {
+        Some(region) => region.clone(),
+        None => return false,
+    };
+    let flags = region.flags;
+    if (flags & PageTableFlags::USER) == 0 || (write && (flags & PageTableFlags::WRITABLE) == 0) {
+        return false;
+    }
+
+    match populate(&region, addr.align_down(PAGE_SIZE)) {
+        Ok(frames) => {
+            let borrowed = matches!(region.region_type, MemoryRegionType::Shared { .. } | MemoryRegionType::Device { .. });
+            if !borrowed {
+                task.usage.charge_frames(frames as u64);
+            }
+            task.usage.record_fault(false);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+crate::kernel_test! {
+    /// File bytes land at the right page offsets and the rest stays zero
+    fn demand_fill_page() {
+        static IMAGE: [u8; 8] = [0, 0, 1, 2, 3, 4, 5, 6];
+        // Bytes 2..8 of the image load at 0x1ffe, straddling a page boundary
+        let backing = FileBacking { image: &IMAGE, offset: 2, vaddr: 0x1ffe, file_size: 6 };
+
+        let mut page = [0u8; PAGE_SIZE];
+        fill_page(&backing, VirtAddr::new(0x1000), &mut page);
+        crate::ktest_assert_eq!(page[0xffe..], [1, 2], "tail of first page");
+        crate::ktest_assert!(page[..0xffe].iter().all(|&b| b == 0), "first page head not zero");
+
+        page.fill(0);
+        fill_page(&backing, VirtAddr::new(0x2000), &mut page);
+        crate::ktest_assert_eq!(page[..4], [3, 4, 5, 6], "head of second page");
+        crate::ktest_assert!(page[4..].iter().all(|&b| b == 0), "bss tail not zero");
+
+        page.fill(0);
+        fill_page(&backing, VirtAddr::new(0x3000), &mut page);
+        crate::ktest_assert!(page.iter().all(|&b| b == 0), "page past the file not zero");
+        Ok(())
+    }
+}
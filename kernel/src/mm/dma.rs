@@ -0,0 +1,60 @@
+//! DMA memory
+//!
+//! Physically contiguous, zeroed memory for device ring buffers and other
+//! structures a device accesses directly. [`alloc`] returns both views of
+//! the memory: the virtual address the CPU uses, through the HHDM, and the
+//! physical address to program into the device. Devices limited to 32-bit
+//! addresses take their memory from [`Zone::Dma32`].
+//!
+//! Drivers go through `dev::api::dma`, which wraps this in a `DmaBuffer`.
+
+use super::pmm::{Zone, FRAME_SIZE};
+use super::{phys_to_virt, with_memory_managers, PhysAddr, VirtAddr};
+
+/// Allocate at least `size` bytes of zeroed, contiguous memory from `zone`
+///
+/// `align` is the physical alignment in bytes, a power of two; anything
+/// below the frame size gives frame alignment. Returns the (virtual,
+/// physical) addresses of the start of the memory, a whole number of
+/// frames long.
+pub fn alloc(size: usize, align: usize, zone: Zone) -> Result<(VirtAddr, PhysAddr), &'static str> {
+    if size == 0 || !align.is_power_of_two() {
+        return Err("Invalid DMA size or alignment");
+    }
+    let frames = size.div_ceil(FRAME_SIZE);
+    let phys = with_memory_managers(|pmm, _| {
+        pmm.alloc_contiguous_aligned(frames, align.max(FRAME_SIZE), zone)
+            .ok_or("Out of contiguous memory")
+    })?;
+    Ok((phys_to_virt(phys), phys))
+}
+
+/// Free memory from [`alloc`] by its physical address and size
+///
+/// The device must no longer be accessing it.
+pub fn free(phys: PhysAddr, size: usize) {
+    let _ = with_memory_managers(|pmm, _| {
+        for frame in 0..size.div_ceil(FRAME_SIZE) {
+            pmm.free_frame(phys + frame * FRAME_SIZE);
+        }
+        Ok(())
+    });
+}
+
+crate::kernel_test! {
+    /// DMA32 memory is below 4 GiB, aligned, zeroed, and its two views
+    /// are the same memory
+    fn dma32_alloc() {
+        let (virt, phys) = alloc(3 * FRAME_SIZE, 4 * FRAME_SIZE, Zone::Dma32)?;
+        let result = (|| {
+            crate::ktest_assert!(phys + 3 * FRAME_SIZE <= super::pmm::DMA32_LIMIT, "DMA32 memory above 4 GiB");
+            crate::ktest_assert!(phys.is_aligned(4 * FRAME_SIZE), "alignment not honored");
+            crate::ktest_assert_eq!(virt, phys_to_virt(phys), "virtual address not the HHDM view");
+            let bytes = unsafe { core::slice::from_raw_parts(virt.as_ptr::<u8>(), 3 * FRAME_SIZE) };
+            crate::ktest_assert!(bytes.iter().all(|&byte| byte == 0), "DMA memory not zeroed");
+            Ok(())
+        })();
+        free(phys, 3 * FRAME_SIZE);
+        result
+    }
+}
@@ -0,0 +1,235 @@
+//! Kernel heap debugging (`kmalloc-debug` feature)
+//!
+//! Built with `--features kmalloc-debug`, every `kmalloc` is recorded with
+//! its size and call site until the matching `kfree`, and gets a redzone
+//! of [`REDZONE`] bytes filled with [`PATTERN`] after the requested size.
+//! `kfree` panics if the redzone was written, naming where the block was
+//! allocated and where it is freed, and refuses (with a report) to free a
+//! pointer that is not outstanding, such as a double free.
+//! [`write_report`] lists the outstanding allocations by call site, most
+//! bytes first, to find leaks; SysRq `a` prints it.
+//!
+//! The redzone only follows the block: one in front would break the
+//! alignment callers rely on, e.g. page tables taken with `kmalloc(4096)`.
+//! Call sites come from `#[track_caller]`, so they name the source line
+//! that called `kmalloc`. The table holds [`MAX_TRACKED`] allocations;
+//! past that, allocations go untracked and frees of unknown pointers can
+//! no longer be told from invalid ones.
+
+use core::fmt::{self, Write};
+use core::panic::Location;
+use spin::Mutex;
+
+/// Bytes of redzone after each allocation
+pub const REDZONE: usize = 32;
+
+/// Redzone fill
+pub const PATTERN: u8 = 0xA5;
+
+/// Allocations tracked at once
+pub const MAX_TRACKED: usize = 4096;
+
+/// Call sites listed in a report; the rest are summed as "other"
+const MAX_SITES: usize = 64;
+
+type Site = &'static Location<'static>;
+
+#[derive(Debug, Clone, Copy)]
+struct Allocation {
+    ptr: usize,
+    size: usize,
+    site: Site,
+}
+
+#[derive(Clone, Copy)]
+struct SiteTotal {
+    site: Site,
+    count: usize,
+    bytes: usize,
+}
+
+struct Tracker {
+    allocations: [Option<Allocation>; MAX_TRACKED],
+    /// Slots from here on have never been used
+    high_water: usize,
+    /// Allocations made while the table was full
+    untracked: usize,
+    /// Report scratch, kept here rather than on the stack
+    sites: [Option<SiteTotal>; MAX_SITES],
+}
+
+static TRACKER: Mutex<Tracker> = Mutex::new(Tracker {
+    allocations: [None; MAX_TRACKED],
+    high_water: 0,
+    untracked: 0,
+    sites: [None; MAX_SITES],
+});
+
+/// Bytes to take from the heap for a request of `size`
+pub fn padded(size: usize) -> usize {
+    size + REDZONE
+}
+
+/// `kmalloc` returned `ptr` for `size` bytes requested at `site`
+pub fn allocated(ptr: *mut u8, size: usize, site: Site) {
+    unsafe { core::ptr::write_bytes(ptr.add(size), PATTERN, REDZONE) };
+
+    let mut tracker = TRACKER.lock();
+    let tracked = tracker.high_water;
+    match tracker.allocations[..tracked].iter().position(Option::is_none) {
+        Some(slot) => tracker.allocations[slot] = Some(Allocation { ptr: ptr as usize, size, site }),
+        None if tracked < MAX_TRACKED => {
+            tracker.allocations[tracked] = Some(Allocation { ptr: ptr as usize, size, site });
+            tracker.high_water += 1;
+        }
+        None => tracker.untracked += 1,
+    }
+}
+
+/// `kfree(ptr, size)` was called at `site`
+///
+/// Returns the bytes to give back to the heap, or None if `ptr` must not
+/// be freed.
+pub fn freeing(ptr: *mut u8, size: usize, site: Site) -> Option<usize> {
+    let allocation = {
+        let mut tracker = TRACKER.lock();
+        let tracked = tracker.high_water;
+        let slot = tracker.allocations[..tracked]
+            .iter()
+            .position(|allocation| allocation.is_some_and(|allocation| allocation.ptr == ptr as usize));
+        match slot {
+            Some(slot) => tracker.allocations[slot].take(),
+            None if tracker.untracked > 0 => return Some(padded(size)),
+            None => None,
+        }
+    };
+    let Some(allocation) = allocation else {
+        crate::serial_println!(
+            "[KMALLOC] kfree({:p}, {}) at {}: not allocated or already freed; leaking it",
+            ptr,
+            size,
+            site
+        );
+        return None;
+    };
+
+    if allocation.size != size {
+        crate::serial_println!(
+            "[KMALLOC] kfree({:p}, {}) at {}: allocated with {} bytes at {}",
+            ptr,
+            size,
+            site,
+            allocation.size,
+            allocation.site
+        );
+    }
+    if let Some(offset) = redzone_damage(&allocation) {
+        panic!(
+            "[KMALLOC] Redzone of {:p} ({} bytes from {}) overwritten {} bytes past the end, found at kfree in {}",
+            ptr, allocation.size, allocation.site, offset, site
+        );
+    }
+    Some(padded(allocation.size))
+}
+
+/// Offset past the end of the block of the first redzone byte that lost
+/// its pattern
+fn redzone_damage(allocation: &Allocation) -> Option<usize> {
+    let redzone = unsafe { core::slice::from_raw_parts((allocation.ptr + allocation.size) as *const u8, REDZONE) };
+    redzone.iter().position(|&byte| byte != PATTERN)
+}
+
+/// Write the outstanding allocations grouped by call site, most bytes
+/// first, with any overwritten redzones
+///
+/// Reports the tracker as busy rather than waiting for it.
+pub fn write_report(out: &mut impl Write) -> fmt::Result {
+    let Some(mut tracker) = TRACKER.try_lock() else { return writeln!(out, "Allocation table busy") };
+    let tracker = &mut *tracker;
+
+    tracker.sites = [None; MAX_SITES];
+    let (mut count, mut bytes, mut damaged) = (0, 0, 0);
+    let (mut other_count, mut other_bytes) = (0, 0);
+    for allocation in tracker.allocations[..tracker.high_water].iter().flatten() {
+        count += 1;
+        bytes += allocation.size;
+        if let Some(offset) = redzone_damage(allocation) {
+            damaged += 1;
+            writeln!(
+                out,
+                "Redzone overwritten {} bytes past {:#x} ({} bytes from {})",
+                offset, allocation.ptr, allocation.size, allocation.site
+            )?;
+        }
+
+        let total = tracker
+            .sites
+            .iter_mut()
+            .find(|total| total.map_or(true, |total| total.site == allocation.site));
+        match total {
+            Some(Some(total)) => {
+                total.count += 1;
+                total.bytes += allocation.size;
+            }
+            Some(empty) => *empty = Some(SiteTotal { site: allocation.site, count: 1, bytes: allocation.size }),
+            None => {
+                other_count += 1;
+                other_bytes += allocation.size;
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "{} allocations outstanding, {} bytes; {} untracked, {} redzones overwritten",
+        count, bytes, tracker.untracked, damaged
+    )?;
+    let sites = &mut tracker.sites;
+    sites.sort_unstable_by(|a, b| {
+        let bytes = |total: &Option<SiteTotal>| total.map_or(0, |total| total.bytes);
+        bytes(b).cmp(&bytes(a))
+    });
+    writeln!(out, "  count      bytes site")?;
+    for total in sites.iter().flatten() {
+        writeln!(out, "{:>7} {:>10} {}", total.count, total.bytes, total.site)?;
+    }
+    if other_count > 0 {
+        writeln!(out, "{:>7} {:>10} other", other_count, other_bytes)?;
+    }
+    Ok(())
+}
+
+crate::kernel_test! {
+    /// Allocations are tracked by call site until freed, and their
+    /// redzones detect overruns
+    fn kmalloc_debug_tracking() {
+        let ptr = super::allocator::kmalloc(24);
+        crate::ktest_assert!(!ptr.is_null(), "kmalloc(24) returned null");
+        let allocation = {
+            let tracker = TRACKER.lock();
+            tracker.allocations[..tracker.high_water]
+                .iter()
+                .flatten()
+                .find(|allocation| allocation.ptr == ptr as usize)
+                .copied()
+        };
+        let Some(allocation) = allocation else { return Err("allocation not tracked") };
+        crate::ktest_assert_eq!(allocation.size, 24, "size recorded");
+        crate::ktest_assert_eq!(allocation.site.file(), file!(), "call site recorded");
+
+        crate::ktest_assert_eq!(redzone_damage(&allocation), None, "fresh redzone damaged");
+        unsafe { ptr.add(24 + 3).write(0) };
+        crate::ktest_assert_eq!(redzone_damage(&allocation), Some(3), "overrun not detected");
+        unsafe { ptr.add(24 + 3).write(PATTERN) };
+
+        super::allocator::kfree(ptr, 24);
+        let still_tracked = TRACKER
+            .lock()
+            .allocations
+            .iter()
+            .flatten()
+            .any(|allocation| allocation.ptr == ptr as usize);
+        crate::ktest_assert!(!still_tracked, "freed allocation still tracked");
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//! Huge (2 MiB) pages
+//!
+//! One 2 MiB page takes one TLB entry where 512 4 KiB pages take 512.
+//! They are used in three places:
+//!
+//! - The kernel image: `PageMapper::map_kernel_sections` maps each 2 MiB
+//!   stretch of a section whose virtual and physical addresses are both
+//!   2 MiB aligned with one huge page.
+//! - The linear physical-memory window (HHDM), which the bootloader maps:
+//!   [`collapse_linear_window`] replaces each of its page tables that maps
+//!   512 contiguous frames alike with one huge page.
+//! - Anonymous mmaps: the first fault in a 2 MiB-aligned stretch that an
+//!   anonymous region covers entirely, with nothing mapped there yet, gets
+//!   a huge page if 512 contiguous aligned frames are free, and a 4 KiB
+//!   page otherwise. munmap and mprotect of part of a huge page split it
+//!   into 4 KiB pages first ([`split_partial`]); a huge page the range
+//!   covers entirely is unmapped or reprotected as a whole.
+//!
+//! The counters are in `/proc/stat`, and the anonymous huge pages also in
+//! `AnonHugePages` of `/proc/meminfo`.
+
+use super::paging::{PageMapper, HUGE_PAGE_SIZE};
+use super::pmm::{PhysicalMemoryManager, FRAME_SIZE};
+use super::{phys_to_virt, PhysAddr, VirtAddr};
+use crate::sched::task::{MemoryRegion, MemoryRegionType};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// 4 KiB frames in a huge page
+pub const HUGE_FRAMES: usize = HUGE_PAGE_SIZE / FRAME_SIZE;
+
+/// Huge page counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HugePageStats {
+    /// 2 MiB pages mapping the kernel image
+    pub kernel: usize,
+    /// 2 MiB stretches of the linear window mapped by huge pages
+    pub linear: usize,
+    /// 2 MiB pages currently backing anonymous mappings
+    pub anon: usize,
+    /// Huge pages split into 4 KiB pages
+    pub splits: usize,
+    /// Faults that could have used a huge page but found no free 2 MiB
+    pub fallbacks: usize,
+}
+
+static KERNEL: AtomicUsize = AtomicUsize::new(0);
+static LINEAR: AtomicUsize = AtomicUsize::new(0);
+static ANON: AtomicUsize = AtomicUsize::new(0);
+static SPLITS: AtomicUsize = AtomicUsize::new(0);
+static FALLBACKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Current huge page counters
+pub fn stats() -> HugePageStats {
+    HugePageStats {
+        kernel: KERNEL.load(Ordering::Relaxed),
+        linear: LINEAR.load(Ordering::Relaxed),
+        anon: ANON.load(Ordering::Relaxed),
+        splits: SPLITS.load(Ordering::Relaxed),
+        fallbacks: FALLBACKS.load(Ordering::Relaxed),
+    }
+}
+
+/// The kernel image got `count` more huge pages
+pub(super) fn count_kernel(count: usize) {
+    KERNEL.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Map the linear window over physical memory below `end` with huge pages
+/// where the bootloader used 4 KiB ones
+///
+/// Returns how many page tables were collapsed. Their frames are the
+/// bootloader's and are left alone. Runs before the other CPUs start, so
+/// a local TLB flush is enough.
+pub fn collapse_linear_window(mapper: &mut PageMapper, end: PhysAddr) -> usize {
+    let (mut huge, mut collapsed) = (0, 0);
+    let mut phys = PhysAddr::new(0);
+    while phys < end {
+        let virt = phys_to_virt(phys);
+        phys += HUGE_PAGE_SIZE;
+        if mapper.collapse_huge_page(virt).is_some() {
+            collapsed += 1;
+            huge += 1;
+        } else if mapper
+            .lookup(virt)
+            .is_some_and(|(_, flags)| flags.bits() & super::paging::PageTableFlags::HUGE.bits() != 0)
+        {
+            huge += 1;
+        }
+    }
+    LINEAR.store(huge, Ordering::Relaxed);
+    if collapsed > 0 {
+        unsafe { super::tlb::flush_all_global() };
+    }
+    collapsed
+}
+
+/// Back the 2 MiB stretch holding `page` of `region` with a huge page, if
+/// the region is anonymous and covers all of it and nothing is mapped
+/// there yet
+///
+/// Returns false if the page should be populated with a 4 KiB page
+/// instead.
+pub(super) fn try_populate(
+    region: &MemoryRegion,
+    page: VirtAddr,
+    pmm: &mut PhysicalMemoryManager,
+    mapper: &mut PageMapper,
+) -> Result<bool, &'static str> {
+    let base = page.align_down(HUGE_PAGE_SIZE);
+    let eligible = matches!(region.region_type, MemoryRegionType::Anonymous)
+        && region.backing.is_none()
+        && base.as_usize() >= region.start
+        && base.as_usize() + HUGE_PAGE_SIZE <= region.end
+        && mapper.huge_page_free(base);
+    if !eligible {
+        return Ok(false);
+    }
+
+    // alloc_contiguous hands out zeroed frames
+    let Some(frames) = pmm.alloc_contiguous(HUGE_FRAMES, HUGE_PAGE_SIZE) else {
+        FALLBACKS.fetch_add(1, Ordering::Relaxed);
+        return Ok(false);
+    };
+    if let Err(e) = mapper.map_huge_page(base, frames, region.flags, pmm) {
+        free_frames(pmm, frames);
+        return Err(e);
+    }
+    ANON.fetch_add(1, Ordering::Relaxed);
+    Ok(true)
+}
+
+/// An anonymous huge page was unmapped
+pub(super) fn unmapped() {
+    ANON.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Give the frames of a huge page starting at `phys` back to the PMM
+pub(super) fn free_frames(pmm: &mut PhysicalMemoryManager, phys: PhysAddr) {
+    for frame in 0..HUGE_FRAMES {
+        pmm.free_frame(phys + frame * FRAME_SIZE);
+    }
+}
+
+/// Split the huge pages that `[start, end)` covers only in part, so every
+/// huge page left in the range lies entirely inside it
+pub(super) fn split_partial(start: VirtAddr, end: VirtAddr) -> Result<(), &'static str> {
+    super::with_memory_managers(|pmm, mapper| {
+        for edge in [start, end] {
+            if !edge.is_aligned(HUGE_PAGE_SIZE) && mapper.split_huge_page(edge, pmm)? {
+                SPLITS.fetch_add(1, Ordering::Relaxed);
+                ANON.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    })
+}
+
+crate::kernel_test! {
+    /// A huge page translates like the frames behind it, splits into 4 KiB
+    /// pages with the same translations and flags, and collapses back
+    fn huge_page_split_and_collapse() {
+        use super::paging::PageTableFlags;
+
+        // Its own PDPT slot, away from the 4 KiB scratch pages of other tests
+        let virt = VirtAddr::new(0xFFFF_B000_4000_0000);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        super::with_memory_managers(|pmm, mapper| {
+            let phys = pmm.alloc_contiguous(HUGE_FRAMES, HUGE_PAGE_SIZE).ok_or("no free 2 MiB")?;
+            let result = (|| {
+                mapper.map_huge_page(virt, phys, flags, pmm)?;
+                crate::ktest_assert_eq!(mapper.huge_page(virt).map(|(start, _)| start), Some(phys), "huge page not found");
+                crate::ktest_assert_eq!(mapper.translate(virt + 0x12345), Some(phys + 0x12345), "huge translation");
+                crate::ktest_assert!(mapper.unmap_page(virt + FRAME_SIZE).is_err(), "4 KiB unmap inside a huge page");
+
+                crate::ktest_assert!(mapper.split_huge_page(virt + 0x1000, pmm)?, "split found no huge page");
+                crate::ktest_assert!(mapper.huge_page(virt).is_none(), "still huge after split");
+                crate::ktest_assert_eq!(mapper.translate(virt + 0x12345), Some(phys + 0x12345), "translation after split");
+                let (_, split_flags) = mapper.lookup(virt + 0x1FF000).ok_or("last page lost in split")?;
+                crate::ktest_assert_eq!(split_flags.bits() & !PageTableFlags::HUGE.bits(), flags.bits(), "flags after split");
+
+                let table = mapper.collapse_huge_page(virt).ok_or("collapse refused")?;
+                pmm.free_frame(table);
+                crate::ktest_assert_eq!(mapper.huge_page(virt).map(|(start, _)| start), Some(phys), "not huge after collapse");
+                crate::ktest_assert_eq!(mapper.unmap_huge_page(virt), Ok(phys), "unmap huge page");
+                Ok(())
+            })();
+            let _ = mapper.unmap_huge_page(virt);
+            free_frames(pmm, phys);
+            result
+        })
+    }
+}
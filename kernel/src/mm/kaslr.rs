@@ -0,0 +1,127 @@
+//! Address Space Layout Randomization
+//!
+//! Picks randomized base addresses for the kernel heap, kernel stacks, and
+//! each process's user stack, brk heap and mmap area, so an attacker cannot hard-code
+//! where these live. Randomness comes from the kernel CSPRNG (`crate::rand`).
+//!
+//! Boot with `kaslr=off` to get the fixed legacy layout back, which makes
+//! addresses in logs comparable across boots when debugging.
+
+use super::VirtAddr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Page size used for user-space offsets
+const PAGE_SIZE: usize = 4096;
+
+/// Lowest possible kernel heap base (the fixed base with `kaslr=off`)
+pub const HEAP_REGION_BASE: VirtAddr = VirtAddr::new(0xFFFF_A000_0000_0000);
+
+/// Window above `HEAP_REGION_BASE` the heap base is chosen from (1 TiB)
+pub const HEAP_RANDOM_RANGE: usize = 1 << 40;
+
+/// Heap base alignment (2 MiB keeps page table use per heap minimal)
+const HEAP_ALIGN: usize = 2 * 1024 * 1024;
+
+/// Highest possible user stack top (the fixed top with `kaslr=off`)
+pub const USER_STACK_TOP_MAX: usize = 0x0000_7FFF_FFFF_0000;
+
+/// Window below `USER_STACK_TOP_MAX` the user stack top is chosen from (1 GiB)
+pub const USER_STACK_RANDOM_RANGE: usize = 1 << 30;
+
+/// Highest possible user mmap base (the fixed base with `kaslr=off`)
+pub const USER_MMAP_BASE_MAX: usize = 0x0000_7F00_0000_0000;
+
+/// Window below `USER_MMAP_BASE_MAX` the mmap base is chosen from (1 TiB)
+pub const USER_MMAP_RANDOM_RANGE: usize = 1 << 40;
+
+/// Window above the end of the image the brk heap start is chosen from (32 MiB)
+pub const USER_BRK_RANDOM_RANGE: usize = 32 << 20;
+
+/// Whether randomization is active
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Read the `kaslr=` option
+///
+/// Must run before the kernel heap is placed.
+pub fn init() {
+    if crate::cmdline::value("kaslr") == Some("off") {
+        ENABLED.store(false, Ordering::Relaxed);
+        crate::serial_println!("[MM] KASLR disabled (kaslr=off)");
+        return;
+    }
+    crate::serial_println!("[MM] KASLR enabled");
+}
+
+/// Whether randomization is active
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Random multiple of `align` in `[0, range)`, or 0 when KASLR is off
+///
+/// `range` and `align` must be powers of two with `align <= range`.
+pub fn random_offset(range: usize, align: usize) -> usize {
+    if !enabled() || range <= align {
+        return 0;
+    }
+    let slots = range / align;
+    (crate::rand::random_u64() as usize % slots) * align
+}
+
+/// Random index in `[0, count)`, or 0 when KASLR is off
+pub fn random_index(count: usize) -> usize {
+    if !enabled() || count <= 1 {
+        return 0;
+    }
+    crate::rand::random_u64() as usize % count
+}
+
+/// Pick the kernel heap base
+pub fn heap_base() -> VirtAddr {
+    HEAP_REGION_BASE + random_offset(HEAP_RANDOM_RANGE, HEAP_ALIGN)
+}
+
+/// Pick a user stack top for a new process
+pub fn user_stack_top() -> usize {
+    USER_STACK_TOP_MAX - random_offset(USER_STACK_RANDOM_RANGE, PAGE_SIZE)
+}
+
+/// Pick a user mmap base for a new process
+///
+/// Anonymous and file mappings are placed downward from here.
+pub fn user_mmap_base() -> usize {
+    USER_MMAP_BASE_MAX - random_offset(USER_MMAP_RANDOM_RANGE, PAGE_SIZE)
+}
+
+/// Pick the gap between a process image and its brk heap
+pub fn user_brk_offset() -> usize {
+    random_offset(USER_BRK_RANDOM_RANGE, PAGE_SIZE)
+}
+
+crate::kernel_test! {
+    /// Randomized bases stay inside their windows and keep their alignment
+    fn kaslr_bases_in_range() {
+        for _ in 0..16 {
+            let heap = heap_base();
+            crate::ktest_assert!(
+                heap >= HEAP_REGION_BASE && heap < HEAP_REGION_BASE + HEAP_RANDOM_RANGE,
+                "heap base out of range"
+            );
+            crate::ktest_assert!(heap.is_aligned(HEAP_ALIGN), "heap base misaligned");
+
+            let stack = user_stack_top();
+            crate::ktest_assert!(
+                stack <= USER_STACK_TOP_MAX && stack > USER_STACK_TOP_MAX - USER_STACK_RANDOM_RANGE,
+                "user stack top out of range"
+            );
+            crate::ktest_assert_eq!(stack % PAGE_SIZE, 0, "user stack top misaligned");
+
+            let mmap = user_mmap_base();
+            crate::ktest_assert!(
+                mmap <= USER_MMAP_BASE_MAX && mmap > USER_MMAP_BASE_MAX - USER_MMAP_RANDOM_RANGE,
+                "mmap base out of range"
+            );
+        }
+        Ok(())
+    }
+}
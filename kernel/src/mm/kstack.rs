@@ -0,0 +1,315 @@
+//! Kernel Stack Allocator
+//!
+//! Task kernel stacks are carved out of a dedicated virtual region instead of
+//! the kernel heap. The region is split into fixed-size slots; each stack is
+//! mapped at the top of its slot and everything below it is left unmapped.
+//! Running off the bottom of a stack therefore hits a guard page and faults,
+//! instead of silently corrupting whatever heap object happened to sit below.
+//!
+//! With KASLR enabled, both the slot and the stack's position inside it are
+//! chosen at random, so stack addresses of other tasks are not predictable.
+//!
+//! The lowest word of each stack holds a random canary, checked every time
+//! its task is switched out ([`canary_intact`]), which catches overflows
+//! that jump past the guard page's reach or stop just short of it. The
+//! rest of the stack starts out filled with [`STACK_FILL`]; the deepest
+//! word no longer holding it gives the most stack the task ever used
+//! ([`max_depth`]), shown in `/proc/<pid>/status` to size stacks by.
+//!
+//! ```text
+//! slot base                                                slot base + SLOT_SIZE
+//! | unmapped (guard) ......... | mapped stack pages ...... | unmapped |
+//!                              ^ bottom                    ^ top
+//! ```
+
+use super::paging::PageTableFlags;
+use super::{tlb, with_memory_managers, PhysAddr, VirtAddr};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Page size used for stack mappings
+const PAGE_SIZE: usize = 4096;
+
+/// Base of the kernel stack region (PML4 slot 368, unused by heap or HHDM)
+pub const KSTACK_REGION_BASE: VirtAddr = VirtAddr::new(0xFFFF_B800_0000_0000);
+
+/// Virtual space reserved per stack, including its guard area
+pub const KSTACK_SLOT_SIZE: usize = 64 * 1024;
+
+/// Maximum number of kernel stacks that can be live at once
+pub const MAX_KERNEL_STACKS: usize = 256;
+
+/// End of the kernel stack region (exclusive)
+pub const KSTACK_REGION_END: VirtAddr =
+    VirtAddr::new(KSTACK_REGION_BASE.as_u64() + (KSTACK_SLOT_SIZE * MAX_KERNEL_STACKS) as u64);
+
+/// Mapped size of the stack in each slot, 0 if the slot is free
+///
+/// Kept lock-free so the fault handlers can consult it even when the
+/// overflowing code was holding the memory manager lock.
+static SLOT_SIZES: [AtomicUsize; MAX_KERNEL_STACKS] =
+    [const { AtomicUsize::new(0) }; MAX_KERNEL_STACKS];
+
+/// Top of the stack in each slot (valid while the slot's size is non-zero)
+static SLOT_TOPS: [AtomicUsize; MAX_KERNEL_STACKS] =
+    [const { AtomicUsize::new(0) }; MAX_KERNEL_STACKS];
+
+/// Canary at the bottom of the stack in each slot
+static SLOT_CANARIES: [AtomicU64; MAX_KERNEL_STACKS] =
+    [const { AtomicU64::new(0) }; MAX_KERNEL_STACKS];
+
+/// Initial contents of every stack word above the canary
+pub const STACK_FILL: u64 = 0x57AC_F111_57AC_F111;
+
+/// A kernel stack backed by its own page mappings
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStack {
+    slot: usize,
+    size: usize,
+    top: VirtAddr,
+}
+
+impl KernelStack {
+    /// The live stack whose lowest mapped address is `bottom`
+    pub fn at(bottom: VirtAddr) -> Option<KernelStack> {
+        live_stack(bottom).map(|(slot, size, top)| KernelStack { slot, size, top })
+    }
+
+    /// Lowest mapped address of the stack
+    pub fn bottom(&self) -> VirtAddr {
+        self.top() - self.size
+    }
+
+    /// Address one past the highest mapped byte (initial stack pointer)
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+
+    /// Mapped size in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Address of the unmapped page directly below the stack
+    pub fn guard_page(&self) -> VirtAddr {
+        self.bottom() - PAGE_SIZE
+    }
+}
+
+fn slot_base(slot: usize) -> VirtAddr {
+    KSTACK_REGION_BASE + slot * KSTACK_SLOT_SIZE
+}
+
+/// Allocate a kernel stack of at least `size` bytes with a guard page below it
+///
+/// The size is rounded up to whole pages. Backing frames come from the PMM and
+/// are mapped writable and non-executable.
+pub fn alloc_kernel_stack(size: usize) -> Result<KernelStack, &'static str> {
+    let size = (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    if size == 0 || size + PAGE_SIZE > KSTACK_SLOT_SIZE {
+        return Err("Invalid kernel stack size");
+    }
+
+    // Scan for a free slot starting from a random one
+    let first = super::kaslr::random_index(MAX_KERNEL_STACKS);
+    let slot = (0..MAX_KERNEL_STACKS)
+        .map(|i| (first + i) % MAX_KERNEL_STACKS)
+        .find(|&slot| {
+            SLOT_SIZES[slot]
+                .compare_exchange(0, size, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        })
+        .ok_or("Out of kernel stack slots")?;
+
+    // Leave at least one guard page below the stack; the rest of the slack
+    // is split randomly between below and above it
+    let slack_pages = (KSTACK_SLOT_SIZE - size - PAGE_SIZE) / PAGE_SIZE;
+    let top = slot_base(slot) + KSTACK_SLOT_SIZE
+        - super::kaslr::random_index(slack_pages + 1) * PAGE_SIZE;
+    SLOT_TOPS[slot].store(top.as_usize(), Ordering::Release);
+
+    let stack = KernelStack { slot, size, top };
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    let result = with_memory_managers(|pmm, mapper| {
+        let mut virt = stack.bottom();
+        while virt < stack.top() {
+            let frame = match pmm.alloc_frame() {
+                Some(frame) => frame,
+                None => {
+                    unmap_range(pmm, mapper, stack.bottom(), virt);
+                    return Err("Out of physical memory");
+                }
+            };
+            if let Err(e) = mapper.map_page(virt, frame, flags, pmm) {
+                pmm.free_frame(frame);
+                unmap_range(pmm, mapper, stack.bottom(), virt);
+                return Err(e);
+            }
+            virt += PAGE_SIZE;
+        }
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => {
+            let canary = crate::rand::random_u64();
+            SLOT_CANARIES[slot].store(canary, Ordering::Relaxed);
+            let words = unsafe { core::slice::from_raw_parts_mut(stack.bottom().as_mut_ptr::<u64>(), size / 8) };
+            words[0] = canary;
+            words[1..].fill(STACK_FILL);
+            Ok(stack)
+        }
+        Err(e) => {
+            SLOT_SIZES[slot].store(0, Ordering::Release);
+            Err(e)
+        }
+    }
+}
+
+/// Unmap a kernel stack and return its frames to the PMM
+///
+/// # Safety
+/// Nothing may still be running on, or hold pointers into, the stack.
+pub unsafe fn free_kernel_stack(stack: KernelStack) {
+    let _ = with_memory_managers(|pmm, mapper| {
+        unmap_range(pmm, mapper, stack.bottom(), stack.top());
+        Ok(())
+    });
+    tlb::tlb_shootdown(stack.bottom(), stack.size / PAGE_SIZE, 0);
+    SLOT_SIZES[stack.slot].store(0, Ordering::Release);
+}
+
+/// Unmap `[start, end)` and free the frames that backed it
+///
+/// Frames are released before remote TLBs are flushed, which is only safe
+/// because callers either never published the mapping or are tearing down a
+/// stack nobody runs on.
+fn unmap_range(
+    pmm: &mut super::pmm::PhysicalMemoryManager,
+    mapper: &mut super::paging::PageMapper,
+    start: VirtAddr,
+    end: VirtAddr,
+) {
+    let mut virt = start;
+    while virt < end {
+        let phys: Option<PhysAddr> = mapper.translate(virt);
+        if mapper.unmap_page(virt).is_ok() {
+            if let Some(phys) = phys {
+                pmm.free_frame(phys);
+            }
+        }
+        virt += PAGE_SIZE;
+    }
+}
+
+/// Slot, size and top of the live stack whose bottom is `bottom`
+fn live_stack(bottom: VirtAddr) -> Option<(usize, usize, VirtAddr)> {
+    if !(KSTACK_REGION_BASE..KSTACK_REGION_END).contains(&bottom) {
+        return None;
+    }
+    let slot = (bottom - KSTACK_REGION_BASE) / KSTACK_SLOT_SIZE;
+    let size = SLOT_SIZES[slot].load(Ordering::Acquire);
+    let top = SLOT_TOPS[slot].load(Ordering::Acquire);
+    (size != 0 && top.checked_sub(size) == Some(bottom.as_usize())).then_some((slot, size, VirtAddr::new(top as u64)))
+}
+
+/// Whether the canary of the stack starting at `bottom` is unchanged
+///
+/// True for addresses that are not the bottom of a live kernel stack.
+pub fn canary_intact(bottom: VirtAddr) -> bool {
+    match live_stack(bottom) {
+        Some((slot, _, _)) => {
+            let canary = unsafe { bottom.as_ptr::<u64>().read_volatile() };
+            canary == SLOT_CANARIES[slot].load(Ordering::Relaxed)
+        }
+        None => true,
+    }
+}
+
+/// Most bytes of the stack starting at `bottom` ever in use, measured
+/// from its top
+///
+/// Found by scanning up from the canary for the first word that lost
+/// [`STACK_FILL`], so it can under-count by whatever a task pushed that
+/// happened to equal the fill. None if `bottom` is not the bottom of a
+/// live kernel stack.
+pub fn max_depth(bottom: VirtAddr) -> Option<usize> {
+    let (_, size, top) = live_stack(bottom)?;
+    let words = unsafe { core::slice::from_raw_parts(bottom.as_ptr::<u64>(), size / 8) };
+    let untouched = words[1..].iter().take_while(|&&word| word == STACK_FILL).count();
+    Some(top - (bottom + 8 + untouched * 8))
+}
+
+/// Check whether `addr` falls in the guard area of a live kernel stack
+///
+/// Returns the bottom of the overflowed stack so the caller can find the
+/// owning task. Safe to call from fault context; it takes no locks.
+pub fn guard_page_hit(addr: VirtAddr) -> Option<VirtAddr> {
+    if !(KSTACK_REGION_BASE..KSTACK_REGION_END).contains(&addr) {
+        return None;
+    }
+
+    let slot = (addr - KSTACK_REGION_BASE) / KSTACK_SLOT_SIZE;
+    let size = SLOT_SIZES[slot].load(Ordering::Acquire);
+    if size == 0 {
+        return None;
+    }
+
+    let bottom = SLOT_TOPS[slot].load(Ordering::Acquire).checked_sub(size)?;
+    let bottom = VirtAddr::new(bottom as u64);
+    if addr < bottom {
+        Some(bottom)
+    } else {
+        None
+    }
+}
+
+crate::kernel_test! {
+    /// Stacks are mapped, sit above an unmapped guard page, and are released on free
+    fn kstack_guard_page_unmapped() {
+        let stack = alloc_kernel_stack(8192)?;
+        let (guard, bottom, top) = (stack.guard_page(), stack.bottom(), stack.top());
+
+        with_memory_managers(|_, mapper| {
+            crate::ktest_assert!(mapper.translate(bottom).is_some(), "stack bottom not mapped");
+            crate::ktest_assert!(mapper.translate(top - PAGE_SIZE).is_some(), "stack top not mapped");
+            crate::ktest_assert!(mapper.translate(guard).is_none(), "guard page is mapped");
+            Ok(())
+        })?;
+
+        crate::ktest_assert_eq!(guard_page_hit(guard + 8), Some(bottom), "guard hit not detected");
+        crate::ktest_assert_eq!(guard_page_hit(bottom), None, "stack page reported as guard");
+
+        unsafe { free_kernel_stack(stack) };
+        crate::ktest_assert_eq!(guard_page_hit(guard), None, "freed slot still tracked");
+        crate::ktest_assert_eq!(max_depth(bottom), None, "freed stack still measured");
+        with_memory_managers(|_, mapper| {
+            crate::ktest_assert!(mapper.translate(bottom).is_none(), "freed stack still mapped");
+            Ok(())
+        })
+    }
+}
+
+crate::kernel_test! {
+    /// New stacks are filled and carry a canary; the watermark follows the
+    /// deepest write and an overwritten canary is detected
+    fn kstack_canary_and_watermark() {
+        let stack = alloc_kernel_stack(8192)?;
+        let (bottom, top) = (stack.bottom(), stack.top());
+        crate::ktest_assert!(canary_intact(bottom), "fresh canary reported damaged");
+        crate::ktest_assert_eq!(max_depth(bottom), Some(0), "fresh stack reported used");
+
+        unsafe { (top - 100).as_mut_ptr::<u8>().write(0) };
+        crate::ktest_assert_eq!(max_depth(bottom), Some(104), "watermark not at the deepest word");
+
+        let canary = unsafe { bottom.as_ptr::<u64>().read() };
+        unsafe { bottom.as_mut_ptr::<u64>().write(!canary) };
+        let detected = !canary_intact(bottom);
+        unsafe { bottom.as_mut_ptr::<u64>().write(canary) };
+        crate::ktest_assert!(detected, "overwritten canary not detected");
+        crate::ktest_assert!(canary_intact(top), "non-stack address reported damaged");
+
+        unsafe { free_kernel_stack(stack) };
+        Ok(())
+    }
+}
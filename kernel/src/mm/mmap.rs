@@ -0,0 +1,446 @@
+//! Anonymous memory mappings (mmap/munmap/mprotect)
+//!
+//! A mapping is a `MemoryRegionType::Anonymous` entry in the task's region
+//! list, which is the task's description of its address space. Shared
+//! memory mappings (`sys::shm`) are `Shared` entries placed the same way;
+//! unmapping them drops a reference to the object instead of freeing frames.
+//! Device mappings (`dev::fb`) are `Device` entries, whose frames are
+//! device memory and are never freed either. Creating a
+//! mapping allocates no frames: the page fault handler backs the faulting
+//! page with a zeroed frame on first touch (`demand::handle_fault`).
+//!
+//! `unmap` and `protect` work on page ranges. Regions only partly covered by
+//! a range are split at the range boundaries first, so the operation always
+//! applies to whole regions. Changed pages are shot down on every CPU before
+//! their frames are freed. Huge pages the range covers only in part are
+//! split into 4 KiB pages first (`hugepage::split_partial`); those it
+//! covers entirely are handled at their first page, as one.
+//!
+//! All tasks still share one page table, so placement only avoids the
+//! calling task's own regions. The randomized per-process mmap base
+//! (`kaslr::user_mmap_base`) keeps processes apart in practice.
+
+use super::hugepage::{self, HUGE_FRAMES};
+use super::paging::{PageTableFlags, HUGE_PAGE_SIZE};
+use super::{tlb, PhysAddr, VirtAddr};
+use crate::arch::x86_64::vdso;
+use crate::sched::task::{MemoryRegion, MemoryRegionType, Task, MAX_MEMORY_REGIONS, USER_LIMIT};
+
+/// Protection bits (`prot` argument)
+pub const PROT_NONE: usize = 0x0;
+pub const PROT_READ: usize = 0x1;
+pub const PROT_WRITE: usize = 0x2;
+pub const PROT_EXEC: usize = 0x4;
+
+/// Mapping flags (`flags` argument)
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Lowest address a mapping may be placed at (keeps NULL dereferences faulting)
+pub const MMAP_MIN_ADDR: usize = 0x10000;
+
+/// Pages unmapped or reprotected before each TLB shootdown
+const BATCH_PAGES: usize = 64;
+
+/// Errors returned by the mapping operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmapError {
+    /// Bad length, alignment, address, flags or protection bits
+    InvalidArgument,
+    /// Writable and executable at once (W^X)
+    WxViolation,
+    /// No free address range, region table full, or range not mapped
+    OutOfMemory,
+    /// File-backed or shared mappings
+    NotSupported,
+}
+
+/// Page table flags for a `prot` value
+///
+/// x86 cannot express write-only pages, so `PROT_WRITE` implies read. A
+/// `PROT_NONE` page stays present but supervisor-only, so user accesses
+/// fault while the frame remains tracked by the page table.
+fn pte_flags(prot: usize) -> Result<PageTableFlags, MmapError> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(MmapError::InvalidArgument);
+    }
+    if prot & PROT_WRITE != 0 && prot & PROT_EXEC != 0 {
+        return Err(MmapError::WxViolation);
+    }
+    if prot == PROT_NONE {
+        return Ok(PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE);
+    }
+
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER;
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    Ok(flags)
+}
+
+/// Round a length up to whole pages
+fn page_round(len: usize) -> Result<usize, MmapError> {
+    if len == 0 {
+        return Err(MmapError::InvalidArgument);
+    }
+    len.checked_add(PAGE_SIZE - 1)
+        .map(|len| len & !(PAGE_SIZE - 1))
+        .ok_or(MmapError::OutOfMemory)
+}
+
+/// Validate a page-aligned user range and return its end
+///
+/// The vDSO pages are not part of any task, so no range may touch them.
+fn user_range(addr: usize, len: usize) -> Result<usize, MmapError> {
+    if addr % PAGE_SIZE != 0 {
+        return Err(MmapError::InvalidArgument);
+    }
+    let len = page_round(len)?;
+    match addr.checked_add(len) {
+        Some(end) if addr >= MMAP_MIN_ADDR && end <= USER_LIMIT && !vdso::overlaps(addr, end) => Ok(end),
+        _ => Err(MmapError::InvalidArgument),
+    }
+}
+
+fn regions(task: &Task) -> impl Iterator<Item = &MemoryRegion> {
+    task.memory_regions[..task.region_count].iter().flatten()
+}
+
+/// Highest region overlapping `[start, end)`
+fn highest_overlap(task: &Task, start: usize, end: usize) -> Option<&MemoryRegion> {
+    regions(task)
+        .filter(|region| region.start < end && start < region.end)
+        .max_by_key(|region| region.start)
+}
+
+/// Pick an address for a `len`-byte mapping
+///
+/// The hint is used if the range there is free; otherwise the search goes
+/// downward from the task's mmap base.
+fn find_free(task: &Task, hint: usize, len: usize) -> Result<usize, MmapError> {
+    let hint = hint & !(PAGE_SIZE - 1);
+    if hint != 0 {
+        if let Some(end) = hint.checked_add(len) {
+            if hint >= MMAP_MIN_ADDR
+                && end <= USER_LIMIT
+                && !vdso::overlaps(hint, end)
+                && highest_overlap(task, hint, end).is_none()
+            {
+                return Ok(hint);
+            }
+        }
+    }
+
+    let mut end = task.mmap_base & !(PAGE_SIZE - 1);
+    loop {
+        let start = end
+            .checked_sub(len)
+            .filter(|start| *start >= MMAP_MIN_ADDR)
+            .ok_or(MmapError::OutOfMemory)?;
+        match highest_overlap(task, start, end) {
+            None => return Ok(start),
+            Some(region) => end = region.start,
+        }
+    }
+}
+
+/// Whether a region strictly contains `addr`, so splitting there adds a region
+fn splits_at(task: &Task, addr: usize) -> bool {
+    regions(task).any(|region| region.start < addr && addr < region.end)
+}
+
+/// Split the region strictly containing `addr` (if any) into two
+///
+/// The caller has checked that a region slot is free.
+fn split_at(task: &mut Task, addr: usize) {
+    let count = task.region_count;
+    let upper = task.memory_regions[..count]
+        .iter_mut()
+        .flatten()
+        .find(|region| region.start < addr && addr < region.end)
+        .map(|region| {
+            // Keeps the file backing, which is addressed by virtual address
+            let mut upper = region.clone();
+            upper.start = addr;
+            region.end = addr;
+            upper
+        });
+    if let Some(MemoryRegionType::Shared { id, .. }) = upper.as_ref().map(|r| r.region_type) {
+        // Each piece of a shared mapping holds its own reference
+        crate::sys::shm::retain(id);
+    }
+    if let Some(upper) = upper {
+        // Cannot overlap: it is carved out of a region that was already there
+        let _ = task.add_memory_region(upper);
+    }
+}
+
+/// Split regions so that `[start, end)` is covered by whole regions only
+fn split_range(task: &mut Task, start: usize, end: usize) -> Result<(), MmapError> {
+    let needed = splits_at(task, start) as usize + splits_at(task, end) as usize;
+    if task.region_count + needed > MAX_MEMORY_REGIONS {
+        return Err(MmapError::OutOfMemory);
+    }
+    split_at(task, start);
+    split_at(task, end);
+    Ok(())
+}
+
+/// Run `f` over every page of `[start, end)` in batches
+///
+/// `f` reports whether it changed the page's entry and, if it unmapped the
+/// page, the frames to free: the first one and how many follow it. Each
+/// batch with changes is shot down on all CPUs before its frames go back
+/// to the PMM.
+fn for_each_page_batched<F>(start: usize, end: usize, mut f: F) -> Result<(), &'static str>
+where
+    F: FnMut(
+        VirtAddr,
+        &mut super::pmm::PhysicalMemoryManager,
+        &mut super::paging::PageMapper,
+    ) -> Result<(bool, Option<(PhysAddr, usize)>), &'static str>,
+{
+    let mut batch_start = start;
+    while batch_start < end {
+        let pages = ((end - batch_start) / PAGE_SIZE).min(BATCH_PAGES);
+        let mut released: [(PhysAddr, usize); BATCH_PAGES] = [(PhysAddr::new(0), 0); BATCH_PAGES];
+
+        let (changed, freed) = super::with_memory_managers(|pmm, mapper| {
+            let mut changed = false;
+            let mut freed = 0;
+            for i in 0..pages {
+                let (page_changed, frame) = f(VirtAddr::new((batch_start + i * PAGE_SIZE) as u64), pmm, mapper)?;
+                changed |= page_changed;
+                if let Some(frame) = frame {
+                    released[freed] = frame;
+                    freed += 1;
+                }
+            }
+            Ok((changed, freed))
+        })?;
+
+        if changed {
+            unsafe {
+                tlb::tlb_shootdown(VirtAddr::new(batch_start as u64), pages, 0);
+            }
+        }
+        if freed > 0 {
+            super::with_memory_managers(|pmm, _| {
+                for &(first, count) in &released[..freed] {
+                    for frame in 0..count {
+                        pmm.free_frame(first + frame * PAGE_SIZE);
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        batch_start += pages * PAGE_SIZE;
+    }
+    Ok(())
+}
+
+/// Create an anonymous, zero-filled mapping
+///
+/// `addr` is a placement hint unless `MAP_FIXED` is given, in which case the
+/// mapping goes exactly there and replaces whatever the task had mapped in
+/// that range. Returns the start address of the mapping.
+pub fn map(task: &mut Task, addr: usize, len: usize, prot: usize, flags: usize) -> Result<usize, MmapError> {
+    if flags & !(MAP_SHARED | MAP_PRIVATE | MAP_FIXED | MAP_ANONYMOUS) != 0 {
+        return Err(MmapError::InvalidArgument);
+    }
+    if flags & MAP_ANONYMOUS == 0 || flags & MAP_SHARED != 0 {
+        return Err(MmapError::NotSupported);
+    }
+    if flags & MAP_PRIVATE == 0 {
+        return Err(MmapError::InvalidArgument);
+    }
+
+    map_region(task, addr, len, prot, flags & MAP_FIXED != 0, |_| MemoryRegionType::Anonymous)
+}
+
+/// Place and record a lazily populated region of `len` bytes
+///
+/// `region_type` is called with the chosen start address. Used by `map`
+/// and by shared memory mappings.
+pub(crate) fn map_region(
+    task: &mut Task,
+    addr: usize,
+    len: usize,
+    prot: usize,
+    fixed: bool,
+    region_type: impl FnOnce(usize) -> MemoryRegionType,
+) -> Result<usize, MmapError> {
+    let len = page_round(len)?;
+    let pte = pte_flags(prot)?;
+
+    let start = if fixed {
+        user_range(addr, len)?;
+        unmap(task, addr, len)?;
+        addr
+    } else {
+        find_free(task, addr, len)?
+    };
+
+    task.add_memory_region(MemoryRegion::new(start, start + len, pte, region_type(start)))
+        .map_err(|_| MmapError::OutOfMemory)?;
+    Ok(start)
+}
+
+/// Remove every mapping in `[addr, addr + len)`
+///
+/// Unmapping a range with nothing in it is not an error.
+pub fn unmap(task: &mut Task, addr: usize, len: usize) -> Result<(), MmapError> {
+    let end = user_range(addr, len)?;
+    split_range(task, addr, end)?;
+    hugepage::split_partial(VirtAddr::new(addr as u64), VirtAddr::new(end as u64)).map_err(|_| MmapError::OutOfMemory)?;
+
+    loop {
+        let region = match regions(task).find(|region| region.start >= addr && region.end <= end) {
+            Some(region) => region.clone(),
+            None => break,
+        };
+        let shared = match region.region_type {
+            MemoryRegionType::Shared { id, .. } => Some(id),
+            _ => None,
+        };
+        let borrowed = shared.is_some() || matches!(region.region_type, MemoryRegionType::Device { .. });
+        let mut frames = 0;
+        for_each_page_batched(region.start, region.end, |page, _, mapper| {
+            if mapper.huge_page(page).is_some() {
+                // Only reached at the first page: the rest is gone with it
+                let frame = mapper.unmap_huge_page(page)?;
+                hugepage::unmapped();
+                frames += HUGE_FRAMES;
+                return Ok((true, Some((frame, HUGE_FRAMES))));
+            }
+            let frame = match mapper.translate(page) {
+                Some(frame) => frame,
+                None => return Ok((false, None)),
+            };
+            mapper.unmap_page(page)?;
+            // Shared frames belong to the shm object, device memory to the
+            // device, not to this task
+            if borrowed {
+                return Ok((true, None));
+            }
+            frames += 1;
+            Ok((true, Some((frame, 1))))
+        })
+        .map_err(|_| MmapError::OutOfMemory)?;
+
+        task.usage.uncharge_frames(frames as u64);
+        let _ = task.remove_memory_region(region.start, region.end);
+        if let Some(id) = shared {
+            crate::sys::shm::release(id);
+        }
+    }
+    Ok(())
+}
+
+/// Change the protection of every page in `[addr, addr + len)`
+///
+/// The whole range must be mapped. Populated pages are updated in place;
+/// pages not yet touched pick up the new protection when they fault in.
+pub fn protect(task: &mut Task, addr: usize, len: usize, prot: usize) -> Result<(), MmapError> {
+    let end = user_range(addr, len)?;
+    let pte = pte_flags(prot)?;
+
+    let mut cursor = addr;
+    while cursor < end {
+        cursor = task
+            .find_memory_region(cursor)
+            .map(|region| region.end)
+            .ok_or(MmapError::OutOfMemory)?;
+    }
+
+    split_range(task, addr, end)?;
+    hugepage::split_partial(VirtAddr::new(addr as u64), VirtAddr::new(end as u64)).map_err(|_| MmapError::OutOfMemory)?;
+    let count = task.region_count;
+    for region in task.memory_regions[..count].iter_mut().flatten() {
+        if region.start >= addr && region.end <= end {
+            region.flags = pte;
+        }
+    }
+
+    for_each_page_batched(addr, end, |page, pmm, mapper| match mapper.translate(page) {
+        // A whole huge page, at its first page
+        Some(frame) if page.is_aligned(HUGE_PAGE_SIZE) && mapper.huge_page(page).is_some() => {
+            mapper.map_huge_page(page, frame, pte, pmm)?;
+            Ok((true, None))
+        }
+        Some(_) if mapper.huge_page(page).is_some() => Ok((false, None)),
+        Some(frame) => {
+            mapper.map_page(page, frame, pte, pmm)?;
+            Ok((true, None))
+        }
+        None => Ok((false, None)),
+    })
+    .map_err(|_| MmapError::OutOfMemory)
+}
+
+crate::kernel_test! {
+    /// Placement, splitting and protection changes on a task's region list
+    fn mmap_region_bookkeeping() {
+        fn dummy_entry() -> ! {
+            loop {}
+        }
+        let mut task = match Task::new(
+            usize::MAX,
+            "mmap_test",
+            dummy_entry,
+            crate::sched::priority::TaskPriority::Normal,
+        ) {
+            Ok(task) => task,
+            Err(_) => return Err("failed to create test task"),
+        };
+        // Far from anything the shared page table maps
+        task.mmap_base = 0x10_0000_0000;
+
+        let rw = PROT_READ | PROT_WRITE;
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+
+        let first = map(&mut task, 0, 3 * PAGE_SIZE, rw, flags).map_err(|_| "first map failed")?;
+        crate::ktest_assert_eq!(first, 0x10_0000_0000 - 3 * PAGE_SIZE, "not placed below the base");
+        let second = map(&mut task, 0, 1, PROT_READ, flags).map_err(|_| "second map failed")?;
+        crate::ktest_assert_eq!(second, first - PAGE_SIZE, "length not page-rounded");
+
+        crate::ktest_assert!(
+            map(&mut task, 0, PAGE_SIZE, PROT_WRITE | PROT_EXEC, flags) == Err(MmapError::WxViolation),
+            "W+X mapping accepted"
+        );
+        crate::ktest_assert!(
+            map(&mut task, 0, PAGE_SIZE, rw, MAP_SHARED | MAP_ANONYMOUS) == Err(MmapError::NotSupported),
+            "shared mapping accepted"
+        );
+
+        // Read-only middle page splits the first mapping into three
+        protect(&mut task, first + PAGE_SIZE, PAGE_SIZE, PROT_READ).map_err(|_| "mprotect failed")?;
+        crate::ktest_assert_eq!(task.region_count, 4, "mprotect did not split the region");
+        let middle = task.find_memory_region(first + PAGE_SIZE).ok_or("middle page lost")?;
+        crate::ktest_assert_eq!(middle.flags & PageTableFlags::WRITABLE, 0, "middle page still writable");
+
+        // Unmapping the middle page leaves a hole the next hinted map can use
+        unmap(&mut task, first + PAGE_SIZE, PAGE_SIZE).map_err(|_| "munmap failed")?;
+        crate::ktest_assert!(task.find_memory_region(first + PAGE_SIZE).is_none(), "page still mapped");
+        let hinted = map(&mut task, first + PAGE_SIZE, PAGE_SIZE, rw, flags).map_err(|_| "hinted map failed")?;
+        crate::ktest_assert_eq!(hinted, first + PAGE_SIZE, "free hint not honoured");
+
+        // MAP_FIXED replaces what was there
+        let fixed = map(&mut task, second, 2 * PAGE_SIZE, PROT_READ, flags | MAP_FIXED)
+            .map_err(|_| "fixed map failed")?;
+        crate::ktest_assert_eq!(fixed, second, "fixed mapping moved");
+        crate::ktest_assert!(
+            protect(&mut task, first - 0x10_0000, PAGE_SIZE, PROT_READ) == Err(MmapError::OutOfMemory),
+            "mprotect of an unmapped range succeeded"
+        );
+        Ok(())
+    }
+}
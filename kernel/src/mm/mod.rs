@@ -41,10 +41,24 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 use limine::request::{ExecutableAddressRequest, HhdmRequest, MemoryMapRequest};
 use spin::Mutex;
 
+pub mod addr;
 pub mod allocator;
+pub mod brk;
+pub mod buddy;
+pub mod demand;
+pub mod dma;
+#[cfg(feature = "kmalloc-debug")]
+pub mod heapdebug;
+pub mod hugepage;
+pub mod kaslr;
+pub mod kstack;
+pub mod mmap;
 pub mod paging;
 pub mod pmm;
+pub mod pressure;
 pub mod security;
+pub mod shrink;
+pub mod smaps;
 pub mod tlb;
 
 struct MemoryManagerState {
@@ -76,11 +90,7 @@ static KERNEL_ADDRESS_REQUEST: ExecutableAddressRequest = ExecutableAddressReque
 /// Initialized from Limine bootloader, NOT hardcoded
 static HHDM_OFFSET: AtomicUsize = AtomicUsize::new(0);
 
-/// Physical address type
-pub type PhysAddr = usize;
-
-/// Virtual address type
-pub type VirtAddr = usize;
+pub use addr::{PhysAddr, VirtAddr};
 
 /// Initialize HHDM offset from Limine bootloader
 /// This MUST be called before using phys_to_virt() or virt_to_phys()
@@ -88,16 +98,21 @@ pub fn init_hhdm(offset: usize) {
     HHDM_OFFSET.store(offset, Ordering::Relaxed);
 }
 
+/// Offset of the higher-half direct map
+pub fn hhdm_offset() -> usize {
+    HHDM_OFFSET.load(Ordering::Relaxed)
+}
+
 /// Convert physical address to virtual address using HHDM
 /// Uses the direct mapping provided by Limine bootloader
 pub fn phys_to_virt(phys: PhysAddr) -> VirtAddr {
-    phys + HHDM_OFFSET.load(Ordering::Relaxed)
+    phys.to_virt()
 }
 
 /// Convert virtual address to physical address using HHDM
 /// Assumes the address is in the direct-mapped region
 pub fn virt_to_phys(virt: VirtAddr) -> PhysAddr {
-    virt - HHDM_OFFSET.load(Ordering::Relaxed)
+    virt.to_phys()
 }
 
 /// Execute a closure with mutable access to the global PMM and page mapper.
@@ -112,52 +127,60 @@ where
     f(&mut state.pmm, &mut state.mapper)
 }
 
+/// The memory map the bootloader passed, empty before it is known
+///
+/// Only usable memory is handed to the frame allocator, so the map itself
+/// (bootloader-reclaimable memory) stays valid.
+pub fn memory_map() -> &'static [&'static limine::memory_map::Entry] {
+    MEMORY_MAP_REQUEST.get_response().map_or(&[], |response| response.entries())
+}
+
+/// Total and free physical memory in MB, without waiting for the memory
+/// manager lock
+///
+/// None while the lock is held or before memory is initialised; for
+/// callers that must not block, such as SysRq.
+pub fn try_memory_mb() -> Option<(usize, usize)> {
+    let guard = MEMORY_MANAGER.try_lock()?;
+    let state = guard.as_ref()?;
+    Some((state.pmm.total_memory_mb(), state.pmm.free_memory_mb()))
+}
+
+/// Boot-time W^X audit
+///
+/// Walks the live page tables and reports every mapping that is both
+/// writable and executable. Debug builds treat any violation as fatal.
+pub fn audit_wx() {
+    let violations = with_memory_managers(|_, mapper| Ok(security::audit_wx(mapper)))
+        .expect("[MM] ERROR: W^X audit before memory init");
+
+    if violations == 0 {
+        crate::serial_println!("[MM] W^X audit passed: no writable+executable mappings");
+        return;
+    }
+
+    #[cfg(debug_assertions)]
+    panic!("[MM] W^X audit found {} writable+executable mappings", violations);
+
+    #[cfg(not(debug_assertions))]
+    crate::serial_println!("[MM] ERROR: W^X audit found {} writable+executable mappings", violations);
+}
+
 /// Enable NX (No Execute) bit support in the CPU
 /// This allows marking pages as non-executable for security
 /// Sets the NXE bit (bit 11) in the EFER MSR (Model Specific Register)
+/// Does nothing on CPUs without NX, where setting NXE would fault
 pub fn enable_nx_bit() {
-    unsafe {
-        use core::arch::asm;
+    use crate::arch::x86_64::msr::{rdmsr, wrmsr, EFER};
 
-        // EFER MSR number
-        const EFER_MSR: u32 = 0xC0000080;
-        const NXE_BIT: u64 = 1 << 11;
-
-        // Read EFER MSR
-        let mut efer: u64;
-        asm!(
-            "rdmsr",
-            in("ecx") EFER_MSR,
-            out("eax") _,
-            out("edx") _,
-            options(nostack, preserves_flags)
-        );
+    const NXE_BIT: u64 = 1 << 11;
 
-        // Combine EAX (low 32 bits) and EDX (high 32 bits)
-        let eax: u32;
-        let edx: u32;
-        asm!(
-            "rdmsr",
-            in("ecx") EFER_MSR,
-            out("eax") eax,
-            out("edx") edx,
-            options(nostack, preserves_flags)
-        );
-        efer = ((edx as u64) << 32) | (eax as u64);
-
-        // Set NXE bit (bit 11)
-        efer |= NXE_BIT;
-
-        // Write back to EFER MSR
-        let new_eax = efer as u32;
-        let new_edx = (efer >> 32) as u32;
-        asm!(
-            "wrmsr",
-            in("ecx") EFER_MSR,
-            in("eax") new_eax,
-            in("edx") new_edx,
-            options(nostack, preserves_flags)
-        );
+    if !crate::arch::x86_64::cpu::features::get().nx {
+        return;
+    }
+    unsafe {
+        let efer = rdmsr(EFER);
+        wrmsr(EFER, efer | NXE_BIT);
     }
 }
 
@@ -197,7 +220,7 @@ fn test_pmm(pmm: &mut pmm::PhysicalMemoryManager) {
     let frame1 = pmm.alloc_frame();
     if let Some(addr) = frame1 {
         // Frame should be aligned to 4KB
-        if addr % 4096 == 0 {
+        if addr.is_aligned(4096) {
             // Success - frame allocated
         }
     }
@@ -226,7 +249,7 @@ fn test_pmm(pmm: &mut pmm::PhysicalMemoryManager) {
 /// Tests page mapping, translation, and unmapping
 fn test_paging(mapper: &mut paging::PageMapper, pmm: &mut pmm::PhysicalMemoryManager) {
     // Test 1: Map and translate
-    let test_virt = 0xFFFF_B000_0000_0000usize;
+    let test_virt = VirtAddr::new(0xFFFF_B000_0000_0000);
     let test_phys = pmm.alloc_frame();
 
     if let Some(phys_addr) = test_phys {
@@ -234,7 +257,9 @@ fn test_paging(mapper: &mut paging::PageMapper, pmm: &mut pmm::PhysicalMemoryMan
         let map_result = mapper.map_page(
             test_virt,
             phys_addr,
-            paging::PageTableFlags::PRESENT | paging::PageTableFlags::WRITABLE,
+            paging::PageTableFlags::PRESENT
+                | paging::PageTableFlags::WRITABLE
+                | paging::PageTableFlags::NO_EXECUTE,
             pmm,
         );
 
@@ -301,6 +326,11 @@ fn test_allocator() {
         }
     }
     // Success - multiple allocations and frees work
+
+    // Test 6: Free lists are still well-formed
+    if allocator::check_heap().is_ok() {
+        // Success - no free-list corruption detected
+    }
 }
 
 /// Run all memory management tests
@@ -332,6 +362,115 @@ pub fn run_memory_tests(pmm: &mut pmm::PhysicalMemoryManager, mapper: &mut pagin
     // [MM] ==========================================
 }
 
+crate::kernel_test! {
+    /// Heap allocations are zeroed, writable, and returned to the free lists
+    fn heap_alloc_roundtrip() {
+        let before = allocator::allocated_bytes();
+        let ptr = allocator::kmalloc(1024);
+        crate::ktest_assert!(!ptr.is_null(), "kmalloc(1024) returned null");
+
+        unsafe {
+            crate::ktest_assert_eq!(*ptr, 0, "allocation not zeroed");
+            *ptr = 0x42;
+            crate::ktest_assert_eq!(*ptr, 0x42, "heap read-back mismatch");
+        }
+
+        allocator::kfree(ptr, 1024);
+        crate::ktest_assert_eq!(allocator::allocated_bytes(), before, "heap accounting leaked");
+        allocator::check_heap()
+    }
+}
+
+crate::kernel_test! {
+    /// An allocation the heap cannot satisfy fails with an error after the
+    /// OOM path, and is counted
+    fn heap_exhaustion_is_an_error() {
+        let before = allocator::oom_stats();
+        let result = allocator::try_kmalloc(64 * 1024 * 1024);
+        crate::ktest_assert_eq!(result, Err(allocator::AllocError::OutOfMemory), "oversized allocation succeeded");
+        let after = allocator::oom_stats();
+        crate::ktest_assert_eq!(after.events, before.events + 1, "OOM event not counted");
+        crate::ktest_assert_eq!(after.failures, before.failures + 1, "OOM failure not counted");
+        allocator::check_heap()
+    }
+}
+
+crate::kernel_test! {
+    /// Freed frames are handed out again
+    fn pmm_frame_reuse() {
+        with_memory_managers(|pmm, _| {
+            let frame = pmm.alloc_frame().ok_or("alloc_frame failed")?;
+            crate::ktest_assert!(frame.is_aligned(4096), "frame not 4 KiB aligned");
+            pmm.free_frame(frame);
+            let again = pmm.alloc_frame().ok_or("alloc_frame failed after free")?;
+            pmm.free_frame(again);
+            crate::ktest_assert_eq!(again, frame, "freed frame was not reused");
+            Ok(())
+        })
+    }
+}
+
+crate::kernel_test! {
+    /// Mapping, translating and unmapping a scratch page
+    fn paging_map_translate_unmap() {
+        with_memory_managers(|pmm, mapper| {
+            let virt = VirtAddr::new(0xFFFF_B000_0000_0000);
+            let phys = pmm.alloc_frame().ok_or("alloc_frame failed")?;
+
+            let result = mapper
+                .map_page(
+                    virt,
+                    phys,
+                    paging::PageTableFlags::PRESENT
+                        | paging::PageTableFlags::WRITABLE
+                        | paging::PageTableFlags::NO_EXECUTE,
+                    pmm,
+                )
+                .map_err(|_| "map_page failed")
+                .and_then(|_| {
+                    crate::ktest_assert_eq!(mapper.translate(virt), Some(phys), "translate mismatch");
+                    mapper.unmap_page(virt).map_err(|_| "unmap_page failed")?;
+                    crate::ktest_assert!(mapper.translate(virt).is_none(), "page still mapped");
+                    Ok(())
+                });
+
+            pmm.free_frame(phys);
+            result
+        })
+    }
+}
+
+crate::kernel_test! {
+    /// map_page refuses writable+executable mappings
+    fn paging_rejects_wx_mapping() {
+        with_memory_managers(|pmm, mapper| {
+            let virt = VirtAddr::new(0xFFFF_B000_0000_0000);
+            let phys = pmm.alloc_frame().ok_or("alloc_frame failed")?;
+
+            let result = mapper.map_page(
+                virt,
+                phys,
+                paging::PageTableFlags::PRESENT | paging::PageTableFlags::WRITABLE,
+                pmm,
+            );
+
+            pmm.free_frame(phys);
+            crate::ktest_assert!(result.is_err(), "W+X mapping was accepted");
+            crate::ktest_assert!(mapper.translate(virt).is_none(), "rejected page got mapped");
+            Ok(())
+        })
+    }
+}
+
+crate::kernel_test! {
+    /// No live mapping is both writable and executable after boot hardening
+    fn wx_audit_clean() {
+        let violations = with_memory_managers(|_, mapper| Ok(security::audit_wx(mapper)))?;
+        crate::ktest_assert_eq!(violations, 0, "W^X violations present");
+        Ok(())
+    }
+}
+
 /// Initialize the entire memory management system
 ///
 /// This function coordinates the initialization of all memory management components:
@@ -365,12 +504,12 @@ pub fn init_memory() {
         .get_response()
         .expect("[MM] ERROR: Failed to get kernel address from Limine");
 
-    let kernel_phys_base = kernel_addr_response.physical_base() as usize;
-    let kernel_virt_base = kernel_addr_response.virtual_base() as usize;
+    let kernel_phys_base = PhysAddr::new(kernel_addr_response.physical_base());
+    let kernel_virt_base = VirtAddr::new(kernel_addr_response.virtual_base());
 
     // Calculate kernel bounds (estimate 16MB for kernel image)
     let kernel_start = kernel_phys_base;
-    let kernel_end = kernel_phys_base + (16 * 1024 * 1024); // 16MB
+    let kernel_end = kernel_phys_base + (16 * 1024 * 1024usize); // 16MB
 
     // Enable CPU memory protection features
     enable_nx_bit();
@@ -389,11 +528,22 @@ pub fn init_memory() {
     mapper
         .map_kernel_sections(kernel_addr_response, &mut pmm)
         .expect("[MM] ERROR: Failed to map kernel sections");
+    let collapsed = hugepage::collapse_linear_window(&mut mapper, PhysAddr::new((pmm.total_frames() * pmm::FRAME_SIZE) as u64));
+    let huge = hugepage::stats();
+    crate::serial_println!(
+        "[MM] Huge pages: {} in the kernel image, {} in the linear window ({} collapsed)",
+        huge.kernel,
+        huge.linear,
+        collapsed
+    );
 
-    // Define heap region (16MB heap starting at 0xFFFF_A000_0000_0000)
-    let heap_start = 0xFFFF_A000_0000_0000usize;
+    // Define heap region (16MB heap at a randomized base above 0xFFFF_A000_0000_0000)
+    kaslr::init();
+    brk::init();
+    let heap_start = kaslr::heap_base();
     let heap_size = 16 * 1024 * 1024; // 16MB
     let heap_end = heap_start + heap_size;
+    crate::serial_println!("[MM] Kernel heap: {} - {}", heap_start, heap_end);
 
     // Map heap region with RW+NX flags
     let mut heap_addr = heap_start;
@@ -416,6 +566,10 @@ pub fn init_memory() {
         heap_addr += 4096;
     }
 
+    // Strip execute permission from writable bootloader mappings (HHDM etc.)
+    let hardened = security::harden_kernel_wx(&mut mapper);
+    crate::serial_println!("[MM] W^X: marked {} writable kernel mappings non-executable", hardened);
+
     // Add guard pages around stack and heap
     // Note: Stack location would need to be determined from Limine or linker script
     // For now, we'll just add heap guard pages
@@ -426,7 +580,7 @@ pub fn init_memory() {
     );
 
     // Initialize kernel heap allocator
-    allocator::init_allocator(heap_start, heap_size);
+    allocator::init_allocator(heap_start.as_usize(), heap_size);
 
     // Run memory management tests
     run_memory_tests(&mut pmm, &mut mapper);
@@ -443,6 +597,6 @@ pub fn init_memory() {
     // [MM] Free memory: {free_mb} MB
     // [MM] Physical memory manager initialized
     // [MM] Page tables initialized
-    // [MM] Kernel heap: 0xFFFF_A000_0000_0000 - 0xFFFF_A000_0100_0000 (16 MB)
+    // [MM] Kernel heap: <randomized base> (16 MB)
     // [MM] Memory management initialized successfully
 }
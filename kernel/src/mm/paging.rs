@@ -6,6 +6,12 @@
 use crate::mm::pmm::PhysicalMemoryManager;
 use crate::mm::{phys_to_virt, PhysAddr, VirtAddr};
 
+/// Size of a page mapped by a page directory entry (2 MiB)
+pub const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Physical address bits of an entry
+const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
 /// Page table entry flags
 /// These flags control the behavior and permissions of mapped pages
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -94,15 +100,20 @@ impl PageTableEntry {
     /// Extract physical address from entry
     /// Masks bits 12-51 to get the physical address
     pub fn addr(&self) -> PhysAddr {
-        (self.0 & 0x000F_FFFF_FFFF_F000) as usize
+        PhysAddr::new(self.0 & ADDR_MASK)
     }
 
     /// Set physical address and flags
-    /// The address must be 4KB aligned
+    /// The address must be 4KB aligned. NO_EXECUTE is dropped on CPUs without
+    /// NX, where bit 63 is reserved and would fault on every access.
     pub fn set(&mut self, addr: PhysAddr, flags: PageTableFlags) {
         // Ensure address is 4KB aligned by masking lower 12 bits
-        let addr_masked = (addr as u64) & 0x000F_FFFF_FFFF_F000;
-        self.0 = addr_masked | flags.bits();
+        let addr_masked = addr.as_u64() & ADDR_MASK;
+        let mut flags = flags.bits();
+        if !crate::arch::x86_64::cpu::features::get().nx {
+            flags &= !PageTableFlags::NO_EXECUTE.bits();
+        }
+        self.0 = addr_masked | flags;
     }
 
     /// Check if entry is present
@@ -110,6 +121,17 @@ impl PageTableEntry {
         (self.0 & PageTableFlags::PRESENT.bits()) != 0
     }
 
+    /// Check if a present PDPT or PD entry maps a 1 GiB or 2 MiB page
+    /// rather than pointing to the next table
+    pub fn is_huge(&self) -> bool {
+        self.is_present() && (self.0 & PageTableFlags::HUGE.bits()) != 0
+    }
+
+    /// Flag bits of the entry, without the physical address
+    pub fn flags(&self) -> PageTableFlags {
+        PageTableFlags(self.0 & !ADDR_MASK)
+    }
+
     /// Clear entry (set to zero)
     pub fn clear(&mut self) {
         self.0 = 0;
@@ -119,6 +141,35 @@ impl PageTableEntry {
     pub fn raw(&self) -> u64 {
         self.0
     }
+
+    /// Set additional flag bits, keeping address and existing flags
+    pub fn insert_flags(&mut self, flags: PageTableFlags) {
+        self.0 |= flags.bits();
+    }
+
+    /// Clear flag bits, keeping address and other flags
+    pub fn remove_flags(&mut self, flags: PageTableFlags) {
+        self.0 &= !flags.bits();
+    }
+}
+
+/// A present leaf mapping found while walking the page tables
+///
+/// Permissions are the effective ones, combined across every table level
+/// (a page is only writable if all levels allow writes, and is executable
+/// unless some level sets NO_EXECUTE).
+#[derive(Debug, Clone, Copy)]
+pub struct LeafMapping {
+    /// First virtual address covered by the mapping
+    pub virt: VirtAddr,
+    /// Size of the mapping in bytes (4 KiB, 2 MiB or 1 GiB)
+    pub size: usize,
+    /// Effective write permission
+    pub writable: bool,
+    /// Effective execute permission
+    pub executable: bool,
+    /// Effective user accessibility
+    pub user: bool,
 }
 
 impl PageTable {
@@ -158,11 +209,11 @@ impl PageMapper {
                 out(reg) cr3,
                 options(nostack, preserves_flags)
             );
-            (cr3 & 0x000F_FFFF_FFFF_F000) as usize
+            PhysAddr::new(cr3 & ADDR_MASK)
         };
 
         let pml4_virt = phys_to_virt(pml4_phys);
-        let pml4 = unsafe { &mut *(pml4_virt as *mut PageTable) };
+        let pml4 = unsafe { &mut *pml4_virt.as_mut_ptr::<PageTable>() };
 
         PageMapper { pml4 }
     }
@@ -170,6 +221,8 @@ impl PageMapper {
     /// Map a virtual address to a physical address with specified flags
     /// Creates intermediate page tables as needed
     ///
+    /// Rejects present mappings that are both writable and executable (W^X).
+    ///
     /// # Arguments
     /// * `virt_addr` - Virtual address to map (must be 4KB aligned)
     /// * `phys_addr` - Physical address to map to (must be 4KB aligned)
@@ -183,81 +236,102 @@ impl PageMapper {
         pmm: &mut PhysicalMemoryManager,
     ) -> Result<(), &'static str> {
         // Validate alignment
-        if virt_addr % 4096 != 0 || phys_addr % 4096 != 0 {
+        if !virt_addr.is_aligned(4096) || !phys_addr.is_aligned(4096) {
             return Err("Address not aligned to 4KB");
         }
 
-        // Extract indices from virtual address
-        // Virtual address structure (48-bit):
-        // [47:39] PML4 index (9 bits)
-        // [38:30] PDPT index (9 bits)
-        // [29:21] PD index (9 bits)
-        // [20:12] PT index (9 bits)
-        // [11:0]  Offset (12 bits)
-        let pml4_index = (virt_addr >> 39) & 0x1FF;
-        let pdpt_index = (virt_addr >> 30) & 0x1FF;
-        let pd_index = (virt_addr >> 21) & 0x1FF;
-        let pt_index = (virt_addr >> 12) & 0x1FF;
+        // Enforce W^X: never hand out a writable and executable page
+        if (flags & PageTableFlags::PRESENT) != 0 && !crate::mm::security::validate_wx_flags(flags) {
+            return Err("W^X violation: page cannot be writable and executable");
+        }
 
-        // Get or create PDPT from PML4
         let user_flag = if (flags.bits() & PageTableFlags::USER.bits()) != 0 {
             PageTableFlags::USER
         } else {
             PageTableFlags(0)
         };
-
         let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | user_flag;
 
-        let pml4_entry = self.pml4.get_entry_mut(pml4_index);
-        let pdpt_phys = if pml4_entry.is_present() {
-            pml4_entry.addr()
-        } else {
-            let new_table = pmm.alloc_frame().ok_or("Out of physical memory")?;
-            let table_virt = phys_to_virt(new_table);
-            let table = unsafe { &mut *(table_virt as *mut PageTable) };
-            table.zero();
-            pml4_entry.set(new_table, table_flags);
-            new_table
-        };
-
-        // Get or create PD from PDPT
-        let pdpt_virt = phys_to_virt(pdpt_phys);
-        let pdpt = unsafe { &mut *(pdpt_virt as *mut PageTable) };
-        let pdpt_entry = pdpt.get_entry_mut(pdpt_index);
-        let pd_phys = if pdpt_entry.is_present() {
-            pdpt_entry.addr()
-        } else {
-            let new_table = pmm.alloc_frame().ok_or("Out of physical memory")?;
-            let table_virt = phys_to_virt(new_table);
-            let table = unsafe { &mut *(table_virt as *mut PageTable) };
-            table.zero();
-            pdpt_entry.set(new_table, table_flags);
-            new_table
-        };
-
         // Get or create PT from PD
-        let pd_virt = phys_to_virt(pd_phys);
-        let pd = unsafe { &mut *(pd_virt as *mut PageTable) };
-        let pd_entry = pd.get_entry_mut(pd_index);
-        let pt_phys = if pd_entry.is_present() {
-            pd_entry.addr()
-        } else {
-            let new_table = pmm.alloc_frame().ok_or("Out of physical memory")?;
-            let table_virt = phys_to_virt(new_table);
-            let table = unsafe { &mut *(table_virt as *mut PageTable) };
-            table.zero();
-            pd_entry.set(new_table, table_flags);
-            new_table
-        };
+        let pd_entry = self.pd_entry_mut(virt_addr, table_flags, pmm)?;
+        if pd_entry.is_huge() {
+            return Err("Address is in a 2 MiB page");
+        }
+        let pt = next_table(pd_entry, table_flags, pmm)?;
 
         // Set final PT entry
-        let pt_virt = phys_to_virt(pt_phys);
-        let pt = unsafe { &mut *(pt_virt as *mut PageTable) };
+        let pt_index = virt_addr.table_index(1);
         let entry = pt.get_entry_mut(pt_index);
         entry.set(phys_addr, flags);
 
         Ok(())
     }
+
+    /// The page directory entry covering `virt_addr`, creating the PDPT
+    /// and PD on the way with `table_flags` as needed
+    fn pd_entry_mut(
+        &mut self,
+        virt_addr: VirtAddr,
+        table_flags: PageTableFlags,
+        pmm: &mut PhysicalMemoryManager,
+    ) -> Result<&'static mut PageTableEntry, &'static str> {
+        // Extract indices from virtual address
+        // Virtual address structure (48-bit):
+        // [47:39] PML4 index (9 bits)
+        // [38:30] PDPT index (9 bits)
+        // [29:21] PD index (9 bits)
+        // [20:12] PT index (9 bits)
+        // [11:0]  Offset (12 bits)
+        let pml4_index = virt_addr.table_index(4);
+        let pdpt_index = virt_addr.table_index(3);
+        let pd_index = virt_addr.table_index(2);
+
+        // Get or create PDPT from PML4
+        let pdpt = next_table(self.pml4.get_entry_mut(pml4_index), table_flags, pmm)?;
+
+        // Get or create PD from PDPT
+        let pdpt_entry = pdpt.get_entry_mut(pdpt_index);
+        if pdpt_entry.is_huge() {
+            return Err("Address is in a 1 GiB page");
+        }
+        let pd = next_table(pdpt_entry, table_flags, pmm)?;
+        Ok(pd.get_entry_mut(pd_index))
+    }
+
+    /// The page directory entry covering `virt_addr`, if the tables above
+    /// it exist and do not map a 1 GiB page
+    fn pd_entry(&self, virt_addr: VirtAddr) -> Option<&'static mut PageTableEntry> {
+        let pml4_entry = self.pml4.get_entry(virt_addr.table_index(4));
+        if !pml4_entry.is_present() {
+            return None;
+        }
+        let pdpt = unsafe { &mut *(phys_to_virt(pml4_entry.addr()).as_mut_ptr::<PageTable>()) };
+        let pdpt_entry = pdpt.get_entry(virt_addr.table_index(3));
+        if !pdpt_entry.is_present() || pdpt_entry.is_huge() {
+            return None;
+        }
+        let pd = unsafe { &mut *(phys_to_virt(pdpt_entry.addr()).as_mut_ptr::<PageTable>()) };
+        Some(pd.get_entry_mut(virt_addr.table_index(2)))
+    }
+}
+
+/// The table `entry` points to, allocating a zeroed one with
+/// `table_flags` if it is not present
+fn next_table(
+    entry: &mut PageTableEntry,
+    table_flags: PageTableFlags,
+    pmm: &mut PhysicalMemoryManager,
+) -> Result<&'static mut PageTable, &'static str> {
+    let table_phys = if entry.is_present() {
+        entry.addr()
+    } else {
+        let new_table = pmm.alloc_frame().ok_or("Out of physical memory")?;
+        let table = unsafe { &mut *(phys_to_virt(new_table).as_mut_ptr::<PageTable>()) };
+        table.zero();
+        entry.set(new_table, table_flags);
+        new_table
+    };
+    Ok(unsafe { &mut *(phys_to_virt(table_phys).as_mut_ptr::<PageTable>()) })
 }
 
 /// Invalidate TLB entry for a single page
@@ -266,7 +340,7 @@ fn invlpg(virt_addr: VirtAddr) {
     unsafe {
         core::arch::asm!(
             "invlpg [{}]",
-            in(reg) virt_addr,
+            in(reg) virt_addr.as_u64(),
             options(nostack, preserves_flags)
         );
     }
@@ -280,15 +354,15 @@ impl PageMapper {
     /// * `virt_addr` - Virtual address to unmap (must be 4KB aligned)
     pub fn unmap_page(&mut self, virt_addr: VirtAddr) -> Result<(), &'static str> {
         // Validate alignment
-        if virt_addr % 4096 != 0 {
+        if !virt_addr.is_aligned(4096) {
             return Err("Address not aligned to 4KB");
         }
 
         // Extract indices from virtual address
-        let pml4_index = (virt_addr >> 39) & 0x1FF;
-        let pdpt_index = (virt_addr >> 30) & 0x1FF;
-        let pd_index = (virt_addr >> 21) & 0x1FF;
-        let pt_index = (virt_addr >> 12) & 0x1FF;
+        let pml4_index = virt_addr.table_index(4);
+        let pdpt_index = virt_addr.table_index(3);
+        let pd_index = virt_addr.table_index(2);
+        let pt_index = virt_addr.table_index(1);
 
         // Traverse page tables to find the entry
         let pml4_entry = self.pml4.get_entry(pml4_index);
@@ -298,25 +372,31 @@ impl PageMapper {
 
         let pdpt_phys = pml4_entry.addr();
         let pdpt_virt = phys_to_virt(pdpt_phys);
-        let pdpt = unsafe { &mut *(pdpt_virt as *mut PageTable) };
+        let pdpt = unsafe { &mut *(pdpt_virt.as_mut_ptr::<PageTable>()) };
 
         let pdpt_entry = pdpt.get_entry(pdpt_index);
         if !pdpt_entry.is_present() {
             return Err("Page not mapped (PDPT)");
         }
+        if pdpt_entry.is_huge() {
+            return Err("Page is part of a 1 GiB page");
+        }
 
         let pd_phys = pdpt_entry.addr();
         let pd_virt = phys_to_virt(pd_phys);
-        let pd = unsafe { &mut *(pd_virt as *mut PageTable) };
+        let pd = unsafe { &mut *(pd_virt.as_mut_ptr::<PageTable>()) };
 
         let pd_entry = pd.get_entry(pd_index);
         if !pd_entry.is_present() {
             return Err("Page not mapped (PD)");
         }
+        if pd_entry.is_huge() {
+            return Err("Page is part of a 2 MiB page");
+        }
 
         let pt_phys = pd_entry.addr();
         let pt_virt = phys_to_virt(pt_phys);
-        let pt = unsafe { &mut *(pt_virt as *mut PageTable) };
+        let pt = unsafe { &mut *(pt_virt.as_mut_ptr::<PageTable>()) };
 
         let entry = pt.get_entry_mut(pt_index);
         if !entry.is_present() {
@@ -344,12 +424,21 @@ impl PageMapper {
     /// * `Some(PhysAddr)` - Physical address if the page is mapped
     /// * `None` - If the page is not mapped
     pub fn translate(&self, virt_addr: VirtAddr) -> Option<PhysAddr> {
+        self.lookup(virt_addr).map(|(phys, _)| phys)
+    }
+
+    /// Translate a virtual address and return the flags of its leaf entry
+    ///
+    /// The flags are those of the 4 KiB, 2 MiB or 1 GiB entry that maps the
+    /// address, without the physical address bits.
+    pub fn lookup(&self, virt_addr: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
         // Extract indices from virtual address
-        let pml4_index = (virt_addr >> 39) & 0x1FF;
-        let pdpt_index = (virt_addr >> 30) & 0x1FF;
-        let pd_index = (virt_addr >> 21) & 0x1FF;
-        let pt_index = (virt_addr >> 12) & 0x1FF;
-        let offset = virt_addr & 0xFFF;
+        let pml4_index = virt_addr.table_index(4);
+        let pdpt_index = virt_addr.table_index(3);
+        let pd_index = virt_addr.table_index(2);
+        let pt_index = virt_addr.table_index(1);
+        let offset = virt_addr.as_usize() & 0xFFF;
+        let flags = |entry: &PageTableEntry| PageTableFlags(entry.raw() & !0x000F_FFFF_FFFF_F000);
 
         // Traverse PML4
         let pml4_entry = self.pml4.get_entry(pml4_index);
@@ -360,7 +449,7 @@ impl PageMapper {
         // Traverse PDPT
         let pdpt_phys = pml4_entry.addr();
         let pdpt_virt = phys_to_virt(pdpt_phys);
-        let pdpt = unsafe { &*(pdpt_virt as *const PageTable) };
+        let pdpt = unsafe { &*(pdpt_virt.as_ptr::<PageTable>()) };
 
         let pdpt_entry = pdpt.get_entry(pdpt_index);
         if !pdpt_entry.is_present() {
@@ -369,14 +458,14 @@ impl PageMapper {
 
         // Check for 1GB huge page
         if (pdpt_entry.raw() & PageTableFlags::HUGE.bits()) != 0 {
-            let page_offset = virt_addr & 0x3FFF_FFFF; // 1GB offset
-            return Some(pdpt_entry.addr() + page_offset);
+            let page_offset = virt_addr.as_usize() & 0x3FFF_FFFF; // 1GB offset
+            return Some((pdpt_entry.addr() + page_offset, flags(pdpt_entry)));
         }
 
         // Traverse PD
         let pd_phys = pdpt_entry.addr();
         let pd_virt = phys_to_virt(pd_phys);
-        let pd = unsafe { &*(pd_virt as *const PageTable) };
+        let pd = unsafe { &*(pd_virt.as_ptr::<PageTable>()) };
 
         let pd_entry = pd.get_entry(pd_index);
         if !pd_entry.is_present() {
@@ -385,14 +474,14 @@ impl PageMapper {
 
         // Check for 2MB huge page
         if (pd_entry.raw() & PageTableFlags::HUGE.bits()) != 0 {
-            let page_offset = virt_addr & 0x1F_FFFF; // 2MB offset
-            return Some(pd_entry.addr() + page_offset);
+            let page_offset = virt_addr.as_usize() & 0x1F_FFFF; // 2MB offset
+            return Some((pd_entry.addr() + page_offset, flags(pd_entry)));
         }
 
         // Traverse PT
         let pt_phys = pd_entry.addr();
         let pt_virt = phys_to_virt(pt_phys);
-        let pt = unsafe { &*(pt_virt as *const PageTable) };
+        let pt = unsafe { &*(pt_virt.as_ptr::<PageTable>()) };
 
         let pt_entry = pt.get_entry(pt_index);
         if !pt_entry.is_present() {
@@ -400,7 +489,200 @@ impl PageMapper {
         }
 
         // Return physical address with offset
-        Some(pt_entry.addr() + offset)
+        Some((pt_entry.addr() + offset, flags(pt_entry)))
+    }
+}
+
+impl PageMapper {
+    /// Map a 2 MiB page
+    ///
+    /// Both addresses must be 2 MiB aligned. An existing 2 MiB mapping is
+    /// replaced, e.g. to change its flags; a range already holding a page
+    /// table is refused. Rejects W^X like [`map_page`](Self::map_page).
+    /// The caller flushes the TLB.
+    pub fn map_huge_page(
+        &mut self,
+        virt_addr: VirtAddr,
+        phys_addr: PhysAddr,
+        flags: PageTableFlags,
+        pmm: &mut PhysicalMemoryManager,
+    ) -> Result<(), &'static str> {
+        if !virt_addr.is_aligned(HUGE_PAGE_SIZE) || !phys_addr.is_aligned(HUGE_PAGE_SIZE) {
+            return Err("Address not aligned to 2MB");
+        }
+        if (flags & PageTableFlags::PRESENT) != 0 && !crate::mm::security::validate_wx_flags(flags) {
+            return Err("W^X violation: page cannot be writable and executable");
+        }
+
+        let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags(flags & PageTableFlags::USER);
+        let entry = self.pd_entry_mut(virt_addr, table_flags, pmm)?;
+        if entry.is_present() && !entry.is_huge() {
+            return Err("Range already mapped with 4KB pages");
+        }
+        entry.set(phys_addr, flags | PageTableFlags::HUGE);
+        Ok(())
+    }
+
+    /// The 2 MiB page covering `virt_addr`: its physical start and flags
+    pub fn huge_page(&self, virt_addr: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
+        let entry = self.pd_entry(virt_addr)?;
+        entry.is_huge().then(|| (entry.addr().align_down(HUGE_PAGE_SIZE), entry.flags()))
+    }
+
+    /// Whether nothing maps any part of the 2 MiB page holding `virt_addr`,
+    /// not even an empty page table
+    pub fn huge_page_free(&self, virt_addr: VirtAddr) -> bool {
+        self.pd_entry(virt_addr).map_or(true, |entry| !entry.is_present())
+    }
+
+    /// Remove the 2 MiB page at `virt_addr` and return its physical start
+    ///
+    /// Invalidates the TLB entry on this CPU only.
+    pub fn unmap_huge_page(&mut self, virt_addr: VirtAddr) -> Result<PhysAddr, &'static str> {
+        if !virt_addr.is_aligned(HUGE_PAGE_SIZE) {
+            return Err("Address not aligned to 2MB");
+        }
+        let entry = self.pd_entry(virt_addr).filter(|entry| entry.is_huge()).ok_or("No 2MB page mapped")?;
+        let phys = entry.addr().align_down(HUGE_PAGE_SIZE);
+        entry.clear();
+        invlpg(virt_addr);
+        Ok(phys)
+    }
+
+    /// Replace the 2 MiB page covering `virt_addr` with a page table
+    /// mapping the same frames with the same flags
+    ///
+    /// Returns false if no 2 MiB page covers the address. Translations do
+    /// not change, so the TLB needs no flush until a 4 KiB page is changed.
+    pub fn split_huge_page(&mut self, virt_addr: VirtAddr, pmm: &mut PhysicalMemoryManager) -> Result<bool, &'static str> {
+        let Some(entry) = self.pd_entry(virt_addr).filter(|entry| entry.is_huge()) else { return Ok(false) };
+        let phys = entry.addr().align_down(HUGE_PAGE_SIZE);
+        // Bit 7 is HUGE here but PAT in a 4 KiB entry
+        let flags = PageTableFlags(entry.flags().bits() & !PageTableFlags::HUGE.bits());
+
+        let table_phys = pmm.alloc_frame().ok_or("Out of physical memory")?;
+        let table = unsafe { &mut *(phys_to_virt(table_phys).as_mut_ptr::<PageTable>()) };
+        for (index, pte) in table.entries.iter_mut().enumerate() {
+            pte.set(phys + index * 4096, flags);
+        }
+        entry.set(table_phys, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags(flags & PageTableFlags::USER));
+        Ok(true)
+    }
+
+    /// Replace the page table under the 2 MiB-aligned `virt_addr` with a
+    /// 2 MiB page, if it maps 512 contiguous frames with the same flags
+    ///
+    /// Returns the frame of the page table that was dropped; freeing it is
+    /// up to the caller, as it may belong to the bootloader. The caller
+    /// flushes the TLB.
+    pub fn collapse_huge_page(&mut self, virt_addr: VirtAddr) -> Option<PhysAddr> {
+        let entry = self.pd_entry(virt_addr)?;
+        if !virt_addr.is_aligned(HUGE_PAGE_SIZE) || !entry.is_present() || entry.is_huge() {
+            return None;
+        }
+        let table_phys = entry.addr();
+        let table = unsafe { &*(phys_to_virt(table_phys).as_ptr::<PageTable>()) };
+
+        let first = table.get_entry(0);
+        let phys = first.addr();
+        // Accessed and dirty differ from page to page; PAT has no 2 MiB
+        // equivalent at the same bit
+        let significant = |pte: &PageTableEntry| {
+            pte.flags().bits() & !(PageTableFlags::ACCESSED.bits() | PageTableFlags::DIRTY.bits())
+        };
+        let uniform = phys.is_aligned(HUGE_PAGE_SIZE)
+            && first.is_present()
+            && first.raw() & PageTableFlags::HUGE.bits() == 0
+            && table
+                .entries
+                .iter()
+                .enumerate()
+                .all(|(index, pte)| pte.addr() == phys + index * 4096 && significant(pte) == significant(first));
+        if !uniform {
+            return None;
+        }
+
+        // The PD entry's own permissions combine with the leaf's; keep the
+        // stricter of the two
+        let mut flags = PageTableFlags(significant(first)) | PageTableFlags::HUGE;
+        if entry.raw() & PageTableFlags::WRITABLE.bits() == 0 {
+            flags = PageTableFlags(flags.bits() & !PageTableFlags::WRITABLE.bits());
+        }
+        if entry.raw() & PageTableFlags::NO_EXECUTE.bits() != 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        if entry.raw() & PageTableFlags::USER.bits() == 0 {
+            flags = PageTableFlags(flags.bits() & !PageTableFlags::USER.bits());
+        }
+        entry.set(phys, flags);
+        Some(table_phys)
+    }
+}
+
+impl PageMapper {
+    /// Visit every present leaf mapping (4 KiB, 2 MiB or 1 GiB pages)
+    ///
+    /// The callback receives the effective permissions and the leaf entry
+    /// itself, so it may tighten permissions in place. Callers are responsible
+    /// for flushing the TLB after changing any entry.
+    pub fn for_each_leaf<F>(&mut self, mut f: F)
+    where
+        F: FnMut(LeafMapping, &mut PageTableEntry),
+    {
+        let pml4: *mut PageTable = self.pml4;
+        unsafe { walk_table(pml4, 4, VirtAddr::new(0), true, false, true, &mut f) };
+    }
+}
+
+/// Recursive helper for `for_each_leaf`
+///
+/// `level` is 4 for the PML4 down to 1 for a page table; `writable`,
+/// `no_execute` and `user` carry the permissions accumulated from the levels
+/// above.
+unsafe fn walk_table(
+    table: *mut PageTable,
+    level: u32,
+    base: VirtAddr,
+    writable: bool,
+    no_execute: bool,
+    user: bool,
+    f: &mut dyn FnMut(LeafMapping, &mut PageTableEntry),
+) {
+    let shift = 12 + 9 * (level - 1);
+    let table = &mut *table;
+
+    for index in 0..512 {
+        let entry = table.get_entry_mut(index);
+        if !entry.is_present() {
+            continue;
+        }
+
+        let mut virt = base.as_u64() | ((index as u64) << shift);
+        if level == 4 && index >= 256 {
+            // Sign-extend into the canonical higher half
+            virt |= 0xFFFF_0000_0000_0000;
+        }
+        let virt = VirtAddr::new(virt);
+
+        let raw = entry.raw();
+        let writable = writable && (raw & PageTableFlags::WRITABLE) != 0;
+        let no_execute = no_execute || (raw & PageTableFlags::NO_EXECUTE) != 0;
+        let user = user && (raw & PageTableFlags::USER) != 0;
+        let huge = (level == 2 || level == 3) && (raw & PageTableFlags::HUGE) != 0;
+
+        if level == 1 || huge {
+            let leaf = LeafMapping {
+                virt,
+                size: 1 << shift,
+                writable,
+                executable: !no_execute,
+                user,
+            };
+            f(leaf, entry);
+        } else {
+            let next = phys_to_virt(entry.addr()).as_mut_ptr::<PageTable>();
+            walk_table(next, level - 1, virt, writable, no_execute, user, f);
+        }
     }
 }
 
@@ -416,8 +698,8 @@ impl PageMapper {
         kernel_addr_response: &limine::response::ExecutableAddressResponse,
         pmm: &mut PhysicalMemoryManager,
     ) -> Result<(), &'static str> {
-        let kernel_base_virt = kernel_addr_response.virtual_base() as usize;
-        let kernel_base_phys = kernel_addr_response.physical_base() as usize;
+        let kernel_base_virt = VirtAddr::new(kernel_addr_response.virtual_base());
+        let kernel_base_phys = PhysAddr::new(kernel_addr_response.physical_base());
 
         // Get kernel section addresses from linker symbols
         // These are defined in the linker script
@@ -430,12 +712,12 @@ impl PageMapper {
             static __data_end: u8;
         }
 
-        let text_start = unsafe { &__text_start as *const u8 as usize };
-        let text_end = unsafe { &__text_end as *const u8 as usize };
-        let rodata_start = unsafe { &__rodata_start as *const u8 as usize };
-        let rodata_end = unsafe { &__rodata_end as *const u8 as usize };
-        let data_start = unsafe { &__data_start as *const u8 as usize };
-        let data_end = unsafe { &__data_end as *const u8 as usize };
+        let text_start = unsafe { VirtAddr::from_ptr(&__text_start) };
+        let text_end = unsafe { VirtAddr::from_ptr(&__text_end) };
+        let rodata_start = unsafe { VirtAddr::from_ptr(&__rodata_start) };
+        let rodata_end = unsafe { VirtAddr::from_ptr(&__rodata_end) };
+        let data_start = unsafe { VirtAddr::from_ptr(&__data_start) };
+        let data_end = unsafe { VirtAddr::from_ptr(&__data_end) };
 
         // Map .text section: Read + Execute (no write)
         // PRESENT | GLOBAL (no WRITABLE, no NO_EXECUTE)
@@ -488,23 +770,39 @@ impl PageMapper {
         pmm: &mut PhysicalMemoryManager,
     ) -> Result<(), &'static str> {
         // Align start down to page boundary
-        let start = start_virt & !0xFFF;
+        let start = start_virt.align_down(4096);
         // Align end up to page boundary
-        let end = (end_virt + 0xFFF) & !0xFFF;
+        let end = end_virt.align_up(4096);
 
-        // Map each page in the range
+        // Map each page in the range, with 2 MiB pages where both
+        // addresses line up and the section covers all of it
         let mut virt = start;
+        let mut huge = 0;
         while virt < end {
             // Calculate corresponding physical address
             let offset = virt - kernel_base_virt;
             let phys = kernel_base_phys + offset;
 
+            if virt.is_aligned(HUGE_PAGE_SIZE) && phys.is_aligned(HUGE_PAGE_SIZE) && end - virt >= HUGE_PAGE_SIZE {
+                // Replaces the bootloader's page table for the range,
+                // whose frame is not ours to free
+                let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+                self.pd_entry_mut(virt, table_flags, pmm)?.set(phys, flags | PageTableFlags::HUGE);
+                huge += 1;
+                virt += HUGE_PAGE_SIZE;
+                continue;
+            }
+
             // Map the page
             self.map_page(virt, phys, flags, pmm)?;
 
             virt += 4096;
         }
 
+        if huge > 0 {
+            crate::mm::hugepage::count_kernel(huge);
+            unsafe { crate::mm::tlb::flush_all_global() };
+        }
         Ok(())
     }
 }
@@ -532,7 +830,7 @@ impl PageMapper {
 
         // Add guard page before heap start
         // This will catch underflow access to heap
-        if heap_start >= 4096 {
+        if heap_start.as_u64() >= 4096 {
             let heap_guard_start = heap_start - 4096;
             if let Err(_) = self.unmap_page(heap_guard_start) {
                 // Page might not be mapped, which is fine
@@ -1,15 +1,64 @@
 // Physical Memory Manager
 // Manages physical memory frames (4KB blocks)
+//
+// Frames are split into zones by physical address: DMA32 below 4 GiB, for
+// devices that can only address 32 bits, and NORMAL above it. Plain
+// allocations take NORMAL frames first so DMA32 is left for the devices
+// that need it.
 
 #![allow(dead_code)]
 
 use crate::mm::{phys_to_virt, PhysAddr};
+use core::ops::Range;
 use limine::memory_map::EntryType;
 use limine::response::MemoryMapResponse;
 
 /// Size of a physical frame (4KB page)
 pub const FRAME_SIZE: usize = 4096;
 
+/// End of the memory a device with 32-bit DMA addressing can reach
+pub const DMA32_LIMIT: PhysAddr = PhysAddr::new(1 << 32);
+
+/// A range of physical memory frames are allocated from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// Below 4 GiB
+    Dma32,
+    /// 4 GiB and above
+    Normal,
+}
+
+impl Zone {
+    /// Every zone, lowest addresses first
+    pub const ALL: [Zone; 2] = [Zone::Dma32, Zone::Normal];
+
+    /// The zone's name, as in logs
+    pub const fn name(self) -> &'static str {
+        match self {
+            Zone::Dma32 => "DMA32",
+            Zone::Normal => "NORMAL",
+        }
+    }
+
+    /// The zone holding `frame`
+    fn of(frame: usize) -> Zone {
+        if frame < DMA32_LIMIT.as_usize() / FRAME_SIZE {
+            Zone::Dma32
+        } else {
+            Zone::Normal
+        }
+    }
+
+    /// The zone's frames among the first `total_frames`
+    fn frames(self, total_frames: usize) -> Range<usize> {
+        let boundary = (DMA32_LIMIT.as_usize() / FRAME_SIZE).min(total_frames);
+        match self {
+            Zone::Dma32 => 0..boundary,
+            Zone::Normal => boundary..total_frames,
+        }
+    }
+}
+
 /// Physical Memory Manager
 /// Uses a bitmap allocator to track free and used frames
 pub struct PhysicalMemoryManager {
@@ -20,6 +69,8 @@ pub struct PhysicalMemoryManager {
     total_frames: usize,
     /// Number of free frames available
     free_frames: usize,
+    /// Free frames in each zone, indexed by `Zone as usize`
+    zone_free: [usize; Zone::ALL.len()],
     /// Start of usable memory (physical address)
     memory_start: PhysAddr,
     /// End of usable memory (physical address)
@@ -59,12 +110,12 @@ impl PhysicalMemoryManager {
 
         // Find a suitable location for the bitmap in usable memory
         // We'll place it after the kernel
-        let bitmap_start = (kernel_end + FRAME_SIZE - 1) & !(FRAME_SIZE - 1); // Align to frame
+        let bitmap_start = kernel_end.align_up(FRAME_SIZE); // Align to frame
         let bitmap_virt = phys_to_virt(bitmap_start);
 
         // Create bitmap slice
         let bitmap =
-            unsafe { core::slice::from_raw_parts_mut(bitmap_virt as *mut u8, bitmap_size) };
+            unsafe { core::slice::from_raw_parts_mut(bitmap_virt.as_mut_ptr::<u8>(), bitmap_size) };
 
         // Initialize bitmap - mark all frames as used initially
         for byte in bitmap.iter_mut() {
@@ -75,8 +126,9 @@ impl PhysicalMemoryManager {
             bitmap,
             total_frames,
             free_frames: 0,
-            memory_start: 0,
-            memory_end: highest_addr,
+            zone_free: [0; Zone::ALL.len()],
+            memory_start: PhysAddr::new(0),
+            memory_end: PhysAddr::new(highest_addr as u64),
             last_alloc: 0,
         };
 
@@ -96,8 +148,8 @@ impl PhysicalMemoryManager {
         }
 
         // Mark kernel image as used
-        let kernel_start_frame = kernel_start / FRAME_SIZE;
-        let kernel_end_frame = (kernel_end + FRAME_SIZE - 1) / FRAME_SIZE;
+        let kernel_start_frame = kernel_start.as_usize() / FRAME_SIZE;
+        let kernel_end_frame = (kernel_end.as_usize() + FRAME_SIZE - 1) / FRAME_SIZE;
 
         for frame in kernel_start_frame..kernel_end_frame {
             if frame < total_frames {
@@ -107,8 +159,8 @@ impl PhysicalMemoryManager {
 
         // Mark bitmap itself as used
         let bitmap_end = bitmap_start + bitmap_size;
-        let bitmap_start_frame = bitmap_start / FRAME_SIZE;
-        let bitmap_end_frame = (bitmap_end + FRAME_SIZE - 1) / FRAME_SIZE;
+        let bitmap_start_frame = bitmap_start.as_usize() / FRAME_SIZE;
+        let bitmap_end_frame = (bitmap_end.as_usize() + FRAME_SIZE - 1) / FRAME_SIZE;
 
         for frame in bitmap_start_frame..bitmap_end_frame {
             if frame < total_frames {
@@ -135,6 +187,7 @@ impl PhysicalMemoryManager {
 
             if was_used {
                 self.free_frames += 1;
+                self.zone_free[Zone::of(frame) as usize] += 1;
             }
         }
     }
@@ -150,6 +203,7 @@ impl PhysicalMemoryManager {
 
             if was_free {
                 self.free_frames -= 1;
+                self.zone_free[Zone::of(frame) as usize] -= 1;
             }
         }
     }
@@ -171,19 +225,37 @@ impl PhysicalMemoryManager {
     /// Allocate a physical frame
     ///
     /// Returns the physical address of the allocated frame, or None if out of memory.
-    /// The allocated frame is zeroed for security.
+    /// The allocated frame is zeroed for security. NORMAL frames are used
+    /// before DMA32 ones.
     pub fn alloc_frame(&mut self) -> Option<PhysAddr> {
-        // Check if we have any free frames
-        if self.free_frames == 0 {
-            // TODO: Log error once logging is available
+        self.alloc_frame_from(Zone::Normal)
+            .or_else(|| self.alloc_frame_from(Zone::Dma32))
+    }
+
+    /// Allocate `count` contiguous frames from `zone`
+    ///
+    /// Returns the physical address of the first frame, or None if the zone
+    /// has no such run. The frames are zeroed.
+    pub fn alloc_frames_in_zone(&mut self, zone: Zone, count: usize) -> Option<PhysAddr> {
+        if count == 1 {
+            self.alloc_frame_from(zone)
+        } else {
+            self.alloc_contiguous_aligned(count, FRAME_SIZE, zone)
+        }
+    }
+
+    /// Allocate one zeroed frame from `zone`
+    fn alloc_frame_from(&mut self, zone: Zone) -> Option<PhysAddr> {
+        if self.zone_free[zone as usize] == 0 {
             return None;
         }
 
-        // Scan bitmap starting from last_alloc for faster sequential allocation
-        let start_frame = self.last_alloc;
+        // Scan the zone starting from last_alloc for faster sequential allocation
+        let frames = zone.frames(self.total_frames);
+        let start_frame = if frames.contains(&self.last_alloc) { self.last_alloc } else { frames.start };
 
-        for offset in 0..self.total_frames {
-            let frame = (start_frame + offset) % self.total_frames;
+        for offset in 0..frames.len() {
+            let frame = frames.start + (start_frame - frames.start + offset) % frames.len();
 
             if self.is_frame_free(frame) {
                 // Mark frame as used
@@ -191,19 +263,19 @@ impl PhysicalMemoryManager {
                 self.last_alloc = frame;
 
                 // Calculate physical address
-                let phys_addr = frame * FRAME_SIZE;
+                let phys_addr = PhysAddr::new((frame * FRAME_SIZE) as u64);
 
                 // Zero the frame for security
                 let virt_addr = phys_to_virt(phys_addr);
                 unsafe {
-                    core::ptr::write_bytes(virt_addr as *mut u8, 0, FRAME_SIZE);
+                    core::ptr::write_bytes(virt_addr.as_mut_ptr::<u8>(), 0, FRAME_SIZE);
                 }
 
                 return Some(phys_addr);
             }
         }
 
-        // Should never reach here if free_frames > 0
+        // Should never reach here if the zone has free frames
         None
     }
 
@@ -216,6 +288,26 @@ impl PhysicalMemoryManager {
     pub fn free_memory_mb(&self) -> usize {
         (self.free_frames * FRAME_SIZE) / (1024 * 1024)
     }
+
+    /// Total number of frames managed
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// Number of frames currently free
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Total number of frames in `zone`, usable or not
+    pub fn zone_frames(&self, zone: Zone) -> usize {
+        zone.frames(self.total_frames).len()
+    }
+
+    /// Number of frames currently free in `zone`
+    pub fn zone_free_frames(&self, zone: Zone) -> usize {
+        self.zone_free[zone as usize]
+    }
 }
 
 impl PhysicalMemoryManager {
@@ -224,11 +316,11 @@ impl PhysicalMemoryManager {
     /// Marks the frame at the given physical address as free and available for reuse.
     pub fn free_frame(&mut self, phys_addr: PhysAddr) {
         // Validate address alignment
-        if phys_addr % FRAME_SIZE != 0 {
+        if !phys_addr.is_aligned(FRAME_SIZE) {
             return;
         }
 
-        let frame = phys_addr / FRAME_SIZE;
+        let frame = phys_addr.as_usize() / FRAME_SIZE;
 
         // Validate frame is within bounds
         if frame >= self.total_frames {
@@ -248,35 +340,46 @@ impl PhysicalMemoryManager {
 impl PhysicalMemoryManager {
     /// Allocate contiguous physical frames for DMA
     ///
-    /// Finds and allocates a contiguous block of frames with the specified alignment.
+    /// Finds and allocates a contiguous block of frames with the specified alignment,
+    /// from NORMAL memory if it has such a block and from DMA32 otherwise.
     /// Returns the physical address of the first frame, or None if allocation fails.
     ///
     /// # Arguments
     /// * `count` - Number of contiguous frames to allocate
     /// * `align` - Alignment requirement in bytes (must be power of 2)
     pub fn alloc_contiguous(&mut self, count: usize, align: usize) -> Option<PhysAddr> {
+        self.alloc_contiguous_aligned(count, align, Zone::Normal)
+            .or_else(|| self.alloc_contiguous_aligned(count, align, Zone::Dma32))
+    }
+
+    /// Allocate contiguous physical frames from `zone`
+    ///
+    /// Like [`alloc_contiguous`](Self::alloc_contiguous), but the whole
+    /// block lies in `zone`, e.g. below 4 GiB for [`Zone::Dma32`].
+    pub fn alloc_contiguous_aligned(&mut self, count: usize, align: usize, zone: Zone) -> Option<PhysAddr> {
         // Validate alignment is power of 2
-        if align == 0 || (align & (align - 1)) != 0 {
+        if count == 0 || align == 0 || (align & (align - 1)) != 0 {
             return None;
         }
 
-        // Check if we have enough free frames
-        if self.free_frames < count {
+        // Check if the zone has enough free frames
+        if self.zone_free[zone as usize] < count {
             return None;
         }
 
         let align_frames = align / FRAME_SIZE;
 
-        // Scan for contiguous free frames with proper alignment
-        let mut start_frame = 0;
+        // Scan the zone for contiguous free frames with proper alignment
+        let frames = zone.frames(self.total_frames);
+        let mut start_frame = frames.start;
 
-        while start_frame < self.total_frames {
+        while start_frame < frames.end {
             // Align start_frame
             if align_frames > 1 {
                 start_frame = (start_frame + align_frames - 1) & !(align_frames - 1);
             }
 
-            if start_frame + count > self.total_frames {
+            if start_frame + count > frames.end {
                 break;
             }
 
@@ -296,12 +399,12 @@ impl PhysicalMemoryManager {
                     self.mark_frame_used(start_frame + offset);
                 }
 
-                let phys_addr = start_frame * FRAME_SIZE;
+                let phys_addr = PhysAddr::new((start_frame * FRAME_SIZE) as u64);
 
                 // Zero all frames for security
                 let virt_addr = phys_to_virt(phys_addr);
                 unsafe {
-                    core::ptr::write_bytes(virt_addr as *mut u8, 0, count * FRAME_SIZE);
+                    core::ptr::write_bytes(virt_addr.as_mut_ptr::<u8>(), 0, count * FRAME_SIZE);
                 }
 
                 return Some(phys_addr);
@@ -0,0 +1,266 @@
+//! Idle page tracking and memory pressure
+//!
+//! A kernel task scans the page tables once per `SCAN_INTERVAL`. For every
+//! present 4 KiB user page it reads and clears the CPU's accessed bit and
+//! keeps an idle age in the PTE bits the hardware ignores (9-11): the age
+//! resets to zero when the page was referenced since the previous scan and
+//! grows by one (saturating) when it was not. Pages younger than
+//! `IDLE_AGE` scans form the working set; older ones are idle.
+//!
+//! All tasks share one page table, so the working set is system-wide.
+//!
+//! The pressure metric is the share of physical memory that is neither free
+//! nor idle, in percent. When it crosses a [`PressureLevel`] threshold a
+//! `MemoryPressure` event goes out on the kernel event port, so services can
+//! shed caches before allocations start failing. From `Medium` up, every
+//! scan also has the kernel's own caches shrink (`mm::shrink`).
+//! Levels drop only once pressure falls `HYSTERESIS` points below the
+//! threshold, to avoid a storm of events around a boundary.
+
+use super::paging::{PageTableEntry, PageTableFlags};
+use super::{PhysAddr, VirtAddr};
+use crate::sys::event::{self, EventKind};
+use crate::time::Duration;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Time between page table scans
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// First PTE bit used for the idle age (bits 9-11 are ignored by the CPU)
+const AGE_SHIFT: u64 = 9;
+const AGE_MASK: u64 = 0b111 << AGE_SHIFT;
+const MAX_AGE: u64 = 7;
+
+/// Scans without a reference after which a page counts as idle
+const IDLE_AGE: u64 = 2;
+
+/// Percentage points below a threshold before the level drops again
+const HYSTERESIS: u32 = 5;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Memory pressure levels, in increasing severity
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PressureLevel {
+    /// Plenty of free or idle memory
+    None = 0,
+    /// Caches should stop growing
+    Low = 1,
+    /// Caches should be trimmed
+    Medium = 2,
+    /// Allocation failures are imminent; drop everything that can be dropped
+    Critical = 3,
+}
+
+impl PressureLevel {
+    /// Pressure (percent) at which this level is entered
+    const fn threshold(self) -> u32 {
+        match self {
+            PressureLevel::None => 0,
+            PressureLevel::Low => 60,
+            PressureLevel::Medium => 80,
+            PressureLevel::Critical => 95,
+        }
+    }
+
+    const fn from_u32(value: u32) -> Self {
+        match value {
+            1 => PressureLevel::Low,
+            2 => PressureLevel::Medium,
+            3 => PressureLevel::Critical,
+            _ => PressureLevel::None,
+        }
+    }
+
+    /// Level for `pressure`, given the level currently in effect
+    fn next(self, pressure: u32) -> Self {
+        let mut level = PressureLevel::None;
+        for candidate in [PressureLevel::Low, PressureLevel::Medium, PressureLevel::Critical] {
+            // Entering a level needs the full threshold, staying in it less
+            let threshold = if candidate <= self {
+                candidate.threshold().saturating_sub(HYSTERESIS)
+            } else {
+                candidate.threshold()
+            };
+            if pressure >= threshold {
+                level = candidate;
+            }
+        }
+        level
+    }
+}
+
+/// Payload of a `MemoryPressure` event
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PressureEvent {
+    /// New `PressureLevel`
+    pub level: u32,
+    /// Pressure in percent
+    pub pressure: u32,
+    /// Physical memory (kB)
+    pub total_kb: u64,
+    /// Free physical memory (kB)
+    pub free_kb: u64,
+    /// Resident user memory referenced recently (kB)
+    pub working_set_kb: u64,
+    /// Resident user memory not referenced for `IDLE_AGE` scans (kB)
+    pub idle_kb: u64,
+}
+
+/// Result of one page table scan, in pages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    pub working_set: usize,
+    pub idle: usize,
+}
+
+/// Latest results, for /proc and `snapshot`
+static LEVEL: AtomicU32 = AtomicU32::new(PressureLevel::None as u32);
+static PRESSURE: AtomicU32 = AtomicU32::new(0);
+static WORKING_SET_PAGES: AtomicUsize = AtomicUsize::new(0);
+static IDLE_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Age one user PTE and report whether it is idle
+fn age_entry(entry: &mut PageTableEntry) -> bool {
+    let raw = entry.raw();
+    let age = if (raw & PageTableFlags::ACCESSED) != 0 {
+        0
+    } else {
+        (((raw & AGE_MASK) >> AGE_SHIFT) + 1).min(MAX_AGE)
+    };
+    entry.remove_flags(PageTableFlags(AGE_MASK | PageTableFlags::ACCESSED.bits()));
+    entry.insert_flags(PageTableFlags(age << AGE_SHIFT));
+    age >= IDLE_AGE
+}
+
+/// Age every present user page and count the working set
+///
+/// Clears accessed bits, so the caller must flush the TLB on all CPUs
+/// afterwards or cached translations will not set them again.
+fn scan(mapper: &mut super::paging::PageMapper) -> ScanStats {
+    let mut stats = ScanStats::default();
+    mapper.for_each_leaf(|leaf, entry| {
+        if !leaf.user || leaf.size != PAGE_SIZE {
+            return;
+        }
+        if age_entry(entry) {
+            stats.idle += 1;
+        } else {
+            stats.working_set += 1;
+        }
+    });
+    stats
+}
+
+/// Pressure in percent: memory that is neither free nor idle
+fn pressure_percent(total: usize, free: usize, idle: usize) -> u32 {
+    if total == 0 {
+        return 0;
+    }
+    let busy = total.saturating_sub(free).saturating_sub(idle);
+    (busy * 100 / total) as u32
+}
+
+/// Run one scan, update the metric and announce level changes
+pub fn sample() {
+    let result = super::with_memory_managers(|pmm, mapper| {
+        Ok((scan(mapper), pmm.total_frames(), pmm.free_frames()))
+    });
+    let (stats, total, free) = match result {
+        Ok(result) => result,
+        Err(_) => return,
+    };
+    unsafe {
+        super::tlb::tlb_shootdown(VirtAddr::new(0), 0, 0);
+    }
+
+    let pressure = pressure_percent(total, free, stats.idle);
+    WORKING_SET_PAGES.store(stats.working_set, Ordering::Relaxed);
+    IDLE_PAGES.store(stats.idle, Ordering::Relaxed);
+    PRESSURE.store(pressure, Ordering::Relaxed);
+
+    let old = PressureLevel::from_u32(LEVEL.load(Ordering::Relaxed));
+    let level = old.next(pressure);
+    if level >= PressureLevel::Medium {
+        super::shrink::reclaim(level);
+    }
+    if level == old {
+        return;
+    }
+    LEVEL.store(level as u32, Ordering::Relaxed);
+
+    let kb = |pages: usize| (pages * PAGE_SIZE / 1024) as u64;
+    let payload = PressureEvent {
+        level: level as u32,
+        pressure,
+        total_kb: kb(total),
+        free_kb: kb(free),
+        working_set_kb: kb(stats.working_set),
+        idle_kb: kb(stats.idle),
+    };
+    crate::serial_println!(
+        "[MM] Memory pressure {:?} -> {:?} ({}%, working set {} kB, idle {} kB)",
+        old,
+        level,
+        pressure,
+        payload.working_set_kb,
+        payload.idle_kb
+    );
+
+    // SAFETY: PressureEvent is repr(C) plain data
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            &payload as *const PressureEvent as *const u8,
+            core::mem::size_of::<PressureEvent>(),
+        )
+    };
+    event::broadcast(EventKind::MemoryPressure, bytes);
+}
+
+/// Latest pressure level, pressure (percent), working set and idle pages
+pub fn snapshot() -> (PressureLevel, u32, ScanStats) {
+    (
+        PressureLevel::from_u32(LEVEL.load(Ordering::Relaxed)),
+        PRESSURE.load(Ordering::Relaxed),
+        ScanStats {
+            working_set: WORKING_SET_PAGES.load(Ordering::Relaxed),
+            idle: IDLE_PAGES.load(Ordering::Relaxed),
+        },
+    )
+}
+
+/// Kernel task that samples memory pressure every `SCAN_INTERVAL`
+pub fn pressure_task() -> ! {
+    loop {
+        sample();
+        if let Some((_, priority)) = crate::sched::get_current_task_info() {
+            crate::sched::sleep_current_task(SCAN_INTERVAL, priority);
+        }
+        crate::sched::yield_now();
+    }
+}
+
+crate::kernel_test! {
+    /// Pages age when unreferenced and levels move with hysteresis
+    fn memory_pressure_levels() {
+        let mut entry = PageTableEntry::new();
+        entry.set(PhysAddr::new(0x1000), PageTableFlags::PRESENT | PageTableFlags::USER | PageTableFlags::ACCESSED);
+        crate::ktest_assert!(!age_entry(&mut entry), "referenced page counted idle");
+        crate::ktest_assert_eq!(entry.raw() & PageTableFlags::ACCESSED, 0, "accessed bit not cleared");
+        crate::ktest_assert!(!age_entry(&mut entry), "page idle after one scan");
+        crate::ktest_assert!(age_entry(&mut entry), "page not idle after two scans");
+        crate::ktest_assert_eq!(entry.addr(), PhysAddr::new(0x1000), "aging changed the frame");
+
+        crate::ktest_assert_eq!(pressure_percent(100, 30, 10), 60, "pressure percent");
+        crate::ktest_assert_eq!(pressure_percent(0, 0, 0), 0, "empty system under pressure");
+
+        let level = PressureLevel::None.next(82);
+        crate::ktest_assert_eq!(level, PressureLevel::Medium, "threshold not entered");
+        crate::ktest_assert_eq!(level.next(77), PressureLevel::Medium, "dropped inside hysteresis");
+        crate::ktest_assert_eq!(level.next(74), PressureLevel::Low, "did not drop below hysteresis");
+        crate::ktest_assert_eq!(PressureLevel::Low.next(97), PressureLevel::Critical, "critical not entered");
+        Ok(())
+    }
+}
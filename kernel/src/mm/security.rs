@@ -4,7 +4,8 @@
 //! It implements comprehensive pointer validation, bounds checking, and permission verification
 //! to prevent security vulnerabilities.
 
-use crate::mm::paging::{PageMapper, PageTableFlags};
+use crate::arch::x86_64::cpu::{copy_user_bytes, user_access};
+use crate::mm::paging::{LeafMapping, PageMapper, PageTableFlags};
 use crate::mm::pmm::PhysicalMemoryManager;
 use crate::mm::{PhysAddr, VirtAddr};
 use crate::sched::task::USER_LIMIT;
@@ -103,7 +104,7 @@ pub fn validate_user_read<T>(ptr: usize, mapper: &PageMapper) -> SecurityResult<
     }
 
     // Check page permissions
-    match mapper.translate(ptr) {
+    match mapper.translate(VirtAddr::new(ptr as u64)) {
         Some(_phys) => {
             // Page is present
             // TODO: Check if page has USER flag set
@@ -143,7 +144,7 @@ pub fn validate_user_write<T>(ptr: usize, mapper: &PageMapper) -> SecurityResult
     }
 
     // Check page permissions
-    match mapper.translate(ptr) {
+    match mapper.translate(VirtAddr::new(ptr as u64)) {
         Some(_phys) => {
             // Page is present
             // TODO: Check if page has USER and WRITABLE flags set
@@ -199,7 +200,7 @@ pub fn copy_from_user(
 
     let mut page = start_page;
     while page < end_page {
-        match mapper.translate(page) {
+        match mapper.translate(VirtAddr::new(page as u64)) {
             Some(_) => {
                 // Page is present
                 // TODO: Verify USER flag is set
@@ -211,10 +212,7 @@ pub fn copy_from_user(
 
     // Perform the copy
     unsafe {
-        let src = src_ptr as *const u8;
-        for i in 0..len {
-            dst[i] = *src.add(i);
-        }
+        copy_user_bytes(dst.as_mut_ptr(), src_ptr as *const u8, len);
     }
 
     Ok(())
@@ -261,7 +259,7 @@ pub fn copy_to_user(
 
     let mut page = start_page;
     while page < end_page {
-        match mapper.translate(page) {
+        match mapper.translate(VirtAddr::new(page as u64)) {
             Some(_) => {
                 // Page is present
                 // TODO: Verify USER and WRITABLE flags are set
@@ -273,10 +271,7 @@ pub fn copy_to_user(
 
     // Perform the copy
     unsafe {
-        let dst = dst_ptr as *mut u8;
-        for i in 0..len {
-            *dst.add(i) = src[i];
-        }
+        copy_user_bytes(dst_ptr as *mut u8, src.as_ptr(), len);
     }
 
     Ok(())
@@ -298,10 +293,8 @@ pub fn copy_from_user_typed<T: Copy>(
     validate_user_read::<T>(user_ptr, mapper)?;
 
     // Perform the copy
-    unsafe {
-        let ptr = user_ptr as *const T;
-        Ok(*ptr)
-    }
+    let _access = user_access();
+    unsafe { Ok(core::ptr::read_unaligned(user_ptr as *const T)) }
 }
 
 /// Copy a typed value from kernel space to user space
@@ -322,10 +315,8 @@ pub fn copy_to_user_typed<T: Copy>(
     validate_user_write::<T>(user_ptr, mapper)?;
 
     // Perform the copy
-    unsafe {
-        let ptr = user_ptr as *mut T;
-        *ptr = value;
-    }
+    let _access = user_access();
+    unsafe { core::ptr::write_unaligned(user_ptr as *mut T, value) };
 
     Ok(())
 }
@@ -361,16 +352,17 @@ pub fn validate_user_string(
 
         // Check if page is present
         let page = current_ptr & !0xFFF;
-        if mapper.translate(page).is_none() {
+        if mapper.translate(VirtAddr::new(page as u64)).is_none() {
             return Err(SecurityError::PageNotPresent);
         }
 
         // Read byte
-        unsafe {
-            let byte = *(current_ptr as *const u8);
-            if byte == 0 {
-                return Ok(len);
-            }
+        let byte = {
+            let _access = user_access();
+            unsafe { core::ptr::read_volatile(current_ptr as *const u8) }
+        };
+        if byte == 0 {
+            return Ok(len);
         }
 
         len += 1;
@@ -615,12 +607,12 @@ pub fn validate_range_wx(
     len: usize,
 ) -> SecurityResult<()> {
     let page_size = 4096;
-    let start_page = start_addr & !(page_size - 1);
+    let start_page = start_addr.align_down(page_size);
     let end_addr = match start_addr.checked_add(len) {
         Some(addr) => addr,
         None => return Err(SecurityError::Overflow),
     };
-    let end_page = (end_addr + page_size - 1) & !(page_size - 1);
+    let end_page = end_addr.align_up(page_size);
 
     let mut page = start_page;
     while page < end_page {
@@ -682,6 +674,66 @@ impl MemoryRegionType {
     }
 }
 
+/// End of the low identity window the AP trampoline executes from
+///
+/// `smp::identity_map_low_memory` maps 0-2 MiB writable and executable so
+/// APs can run the real-mode trampoline and write their handoff data there.
+/// Kernel mappings overlapping this window are exempt from W^X.
+const AP_TRAMPOLINE_WINDOW_END: VirtAddr = VirtAddr::new(0x20_0000);
+
+fn is_wx_exempt(leaf: &LeafMapping) -> bool {
+    !leaf.user && leaf.virt < AP_TRAMPOLINE_WINDOW_END
+}
+
+/// Remove execute permission from writable kernel mappings
+///
+/// The bootloader hands over its own mappings (HHDM, identity map) as
+/// writable and executable. The kernel only ever executes from its .text
+/// section, which is mapped read-only, so every other writable kernel
+/// mapping gets NO_EXECUTE. User mappings are left alone; they are checked
+/// when they are created and reported by `audit_wx`.
+///
+/// Flushes the local TLB (global entries included) and returns the number
+/// of leaf entries changed.
+/// Must run before APs are started, as no shootdown is performed.
+pub fn harden_kernel_wx(mapper: &mut PageMapper) -> usize {
+    let mut hardened = 0;
+
+    mapper.for_each_leaf(|leaf, entry| {
+        if leaf.writable && leaf.executable && !leaf.user && !is_wx_exempt(&leaf) {
+            entry.insert_flags(PageTableFlags::NO_EXECUTE);
+            hardened += 1;
+        }
+    });
+
+    if hardened > 0 {
+        unsafe { crate::mm::tlb::flush_all_global() };
+    }
+
+    hardened
+}
+
+/// Walk all page tables and report writable+executable mappings
+///
+/// Logs each offending mapping and returns how many were found.
+pub fn audit_wx(mapper: &mut PageMapper) -> usize {
+    let mut violations = 0;
+
+    mapper.for_each_leaf(|leaf, _| {
+        if leaf.writable && leaf.executable && !is_wx_exempt(&leaf) {
+            crate::serial_println!(
+                "[MM] W^X violation: 0x{:x}-0x{:x} ({})",
+                leaf.virt,
+                leaf.virt + leaf.size,
+                if leaf.user { "user" } else { "kernel" }
+            );
+            violations += 1;
+        }
+    });
+
+    violations
+}
+
 #[cfg(test)]
 mod wx_tests {
     use super::*;
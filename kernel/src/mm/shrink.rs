@@ -0,0 +1,163 @@
+//! Reclaimable kernel caches (shrinkers)
+//!
+//! A kernel cache that can give objects back under memory pressure
+//! registers a [`Shrinker`]: a callback counting what it could free and one
+//! freeing up to a given number of objects. The pressure task
+//! (`mm::pressure`) calls [`reclaim`] after every scan at `Medium` or
+//! higher, which runs the shrinkers in priority order (lowest first), so
+//! caches that are cheap to refill give way before expensive ones. `Medium`
+//! asks each cache for half of what it holds, `Critical` for everything.
+//!
+//! When an allocation finds the kernel heap exhausted,
+//! `mm::allocator::try_kmalloc` also runs them as at `Critical` before
+//! failing, with interrupts enabled.
+//!
+//! Each shrinker counts how often it ran and how many objects it freed, for
+//! `/proc/stat`. Shrinkers may take their cache's locks, so a cache must not
+//! allocate from the heap while holding a lock its shrinker takes, and
+//! must not allocate frames while holding the memory manager lock.
+
+use super::pressure::PressureLevel;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+/// Maximum number of registered shrinkers
+const MAX_SHRINKERS: usize = 16;
+
+/// A reclaimable cache
+pub struct Shrinker {
+    /// Name in `/proc/stat`, without spaces
+    pub name: &'static str,
+    /// Order among shrinkers; lower runs first
+    pub priority: u32,
+    /// Number of objects the cache could free now
+    pub count: fn() -> usize,
+    /// Free up to the given number of objects; returns how many were freed
+    pub scan: fn(usize) -> usize,
+    runs: AtomicU64,
+    freed: AtomicU64,
+}
+
+impl Shrinker {
+    pub const fn new(name: &'static str, priority: u32, count: fn() -> usize, scan: fn(usize) -> usize) -> Self {
+        Shrinker { name, priority, count, scan, runs: AtomicU64::new(0), freed: AtomicU64::new(0) }
+    }
+
+    /// Times the shrinker was asked to free objects
+    pub fn runs(&self) -> u64 {
+        self.runs.load(Ordering::Relaxed)
+    }
+
+    /// Objects freed over all runs
+    pub fn freed(&self) -> u64 {
+        self.freed.load(Ordering::Relaxed)
+    }
+
+    /// Free this cache's share for `level`; returns the objects freed
+    fn shrink(&self, level: PressureLevel) -> usize {
+        let target = scan_target((self.count)(), level);
+        if target == 0 {
+            return 0;
+        }
+        let freed = (self.scan)(target);
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.freed.fetch_add(freed as u64, Ordering::Relaxed);
+        freed
+    }
+}
+
+/// Objects to ask a cache holding `count` for at `level`
+fn scan_target(count: usize, level: PressureLevel) -> usize {
+    match level {
+        PressureLevel::None | PressureLevel::Low => 0,
+        PressureLevel::Medium => count.div_ceil(2),
+        PressureLevel::Critical => count,
+    }
+}
+
+/// Registered shrinkers
+struct Registry {
+    shrinkers: [Option<&'static Shrinker>; MAX_SHRINKERS],
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Registry { shrinkers: [None; MAX_SHRINKERS] }
+    }
+
+    fn register(&mut self, shrinker: &'static Shrinker) -> bool {
+        match self.shrinkers.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(shrinker);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Registered shrinkers in priority order, unused slots last
+    fn ordered(&self) -> [Option<&'static Shrinker>; MAX_SHRINKERS] {
+        let mut shrinkers = self.shrinkers;
+        shrinkers.sort_unstable_by_key(|shrinker| shrinker.map_or(u32::MAX, |shrinker| shrinker.priority));
+        shrinkers
+    }
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+/// Have `shrinker` called under memory pressure
+///
+/// Returns false if too many shrinkers are registered.
+pub fn register(shrinker: &'static Shrinker) -> bool {
+    REGISTRY.lock().register(shrinker)
+}
+
+/// Run every shrinker for `level` in priority order
+///
+/// Returns the number of objects freed. The registry lock is not held
+/// while the callbacks run.
+pub fn reclaim(level: PressureLevel) -> usize {
+    let shrinkers = REGISTRY.lock().ordered();
+    shrinkers.iter().flatten().map(|shrinker| shrinker.shrink(level)).sum()
+}
+
+/// Call `f` with every registered shrinker, in priority order
+pub fn for_each(mut f: impl FnMut(&Shrinker)) {
+    let shrinkers = REGISTRY.lock().ordered();
+    shrinkers.iter().flatten().for_each(|shrinker| f(shrinker));
+}
+
+crate::kernel_test! {
+    /// Shrinkers run lowest priority first, get half their objects at
+    /// `Medium` and all at `Critical`, and count what they freed
+    fn shrinkers_run_in_priority_order() {
+        use core::sync::atomic::AtomicUsize;
+
+        static CHEAP: AtomicUsize = AtomicUsize::new(10);
+        static COSTLY: AtomicUsize = AtomicUsize::new(4);
+        static ORDER: AtomicUsize = AtomicUsize::new(0);
+        fn shrink(cache: &AtomicUsize, id: usize, target: usize) -> usize {
+            ORDER.store(ORDER.load(Ordering::Relaxed) * 10 + id, Ordering::Relaxed);
+            cache.fetch_sub(target, Ordering::Relaxed);
+            target
+        }
+        static CHEAP_SHRINKER: Shrinker =
+            Shrinker::new("cheap", 0, || CHEAP.load(Ordering::Relaxed), |target| shrink(&CHEAP, 1, target));
+        static COSTLY_SHRINKER: Shrinker =
+            Shrinker::new("costly", 5, || COSTLY.load(Ordering::Relaxed), |target| shrink(&COSTLY, 2, target));
+
+        let mut registry = Registry::new();
+        crate::ktest_assert!(registry.register(&COSTLY_SHRINKER), "registration failed");
+        crate::ktest_assert!(registry.register(&CHEAP_SHRINKER), "registration failed");
+        let run = |level| registry.ordered().iter().flatten().map(|shrinker| shrinker.shrink(level)).sum::<usize>();
+
+        crate::ktest_assert_eq!(run(PressureLevel::Low), 0, "shrunk at low pressure");
+        crate::ktest_assert_eq!(run(PressureLevel::Medium), 7, "medium pressure frees half");
+        crate::ktest_assert_eq!(ORDER.load(Ordering::Relaxed), 12, "cheap cache not shrunk first");
+        crate::ktest_assert_eq!(run(PressureLevel::Critical), 7, "critical pressure frees the rest");
+        crate::ktest_assert_eq!(CHEAP.load(Ordering::Relaxed) + COSTLY.load(Ordering::Relaxed), 0, "objects left");
+        crate::ktest_assert_eq!(CHEAP_SHRINKER.runs(), 2, "runs counted");
+        crate::ktest_assert_eq!(CHEAP_SHRINKER.freed(), 10, "freed objects counted");
+        Ok(())
+    }
+}
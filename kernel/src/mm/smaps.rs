@@ -0,0 +1,173 @@
+//! Per-region memory footprint (`/proc/<pid>/smaps-lite`)
+//!
+//! Nothing is tracked as pages come and go: each read walks the page
+//! tables over every region of the process and counts what is there.
+//! A page is resident when a frame backs it, shared when that frame
+//! belongs to a shared memory object, and copy-on-write when the region
+//! allows writes but the page is mapped read-only, so the next write
+//! copies it. Fork does not share frames yet, so the last count stays 0
+//! until it does.
+
+use super::paging::PageTableFlags;
+use super::VirtAddr;
+use crate::sched::task::{MemoryRegion, MemoryRegionType, Task};
+use core::fmt::{self, Write};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Page counts of one region
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegionUsage {
+    /// Pages in the region
+    pub size: usize,
+    /// Pages backed by a frame
+    pub resident: usize,
+    /// Resident pages waiting to be copied on the next write
+    pub cow: usize,
+    /// Resident pages whose frames other processes may map too
+    pub shared: usize,
+}
+
+impl RegionUsage {
+    fn add(&mut self, other: &RegionUsage) {
+        self.size += other.size;
+        self.resident += other.resident;
+        self.cow += other.cow;
+        self.shared += other.shared;
+    }
+}
+
+/// Count the pages of `region` by walking the page tables
+pub fn region_usage(region: &MemoryRegion) -> RegionUsage {
+    let shared = matches!(region.region_type, MemoryRegionType::Shared { .. } | MemoryRegionType::Device { .. });
+    let writable = region.flags & PageTableFlags::WRITABLE != 0;
+    let mut usage = RegionUsage {
+        size: (region.end - region.start) / PAGE_SIZE,
+        ..RegionUsage::default()
+    };
+
+    let _ = super::with_memory_managers(|_, mapper| {
+        for page in (region.start..region.end).step_by(PAGE_SIZE) {
+            let Some((_, flags)) = mapper.lookup(VirtAddr::new(page as u64)) else { continue };
+            usage.resident += 1;
+            if shared {
+                usage.shared += 1;
+            } else if writable && flags.bits() & PageTableFlags::WRITABLE.bits() == 0 {
+                usage.cow += 1;
+            }
+        }
+        Ok(())
+    });
+    usage
+}
+
+/// Short name of a region type
+fn type_name(region_type: MemoryRegionType) -> &'static str {
+    match region_type {
+        MemoryRegionType::Code => "code",
+        MemoryRegionType::Data => "data",
+        MemoryRegionType::Bss => "bss",
+        MemoryRegionType::Stack => "stack",
+        MemoryRegionType::Heap => "heap",
+        MemoryRegionType::Anonymous => "anon",
+        MemoryRegionType::Shared { .. } => "shm",
+        MemoryRegionType::Device { .. } => "device",
+    }
+}
+
+/// Permissions as in /proc/<pid>/maps: `rwx` and `p` (private) or `s` (shared)
+fn perms(region: &MemoryRegion) -> [u8; 4] {
+    let flags = region.flags;
+    let user = flags & PageTableFlags::USER != 0;
+    [
+        if user { b'r' } else { b'-' },
+        if user && flags & PageTableFlags::WRITABLE != 0 { b'w' } else { b'-' },
+        if user && flags & PageTableFlags::NO_EXECUTE == 0 { b'x' } else { b'-' },
+        if matches!(region.region_type, MemoryRegionType::Shared { .. } | MemoryRegionType::Device { .. }) {
+            b's'
+        } else {
+            b'p'
+        },
+    ]
+}
+
+/// Write one line per region of `task`, in address order, then the totals
+///
+/// Sizes are in KiB.
+pub fn write_smaps(task: &Task, out: &mut dyn Write) -> fmt::Result {
+    let mut regions: [Option<&MemoryRegion>; crate::sched::task::MAX_MEMORY_REGIONS] =
+        [None; crate::sched::task::MAX_MEMORY_REGIONS];
+    let mut count = 0;
+    for region in task.memory_regions[..task.region_count].iter().flatten() {
+        regions[count] = Some(region);
+        count += 1;
+    }
+    let regions = &mut regions[..count];
+    regions.sort_unstable_by_key(|region| region.map_or(0, |region| region.start));
+
+    let kib = |pages: usize| pages * PAGE_SIZE / 1024;
+    writeln!(out, "{:<33} {:<4} {:<5} {:>8} {:>8} {:>8} {:>8}", "range", "perm", "type", "size", "rss", "cow", "shared")?;
+    let mut total = RegionUsage::default();
+    for region in regions.iter().flatten() {
+        let usage = region_usage(region);
+        let perms = perms(region);
+        writeln!(
+            out,
+            "{:016x}-{:016x} {:<4} {:<5} {:>8} {:>8} {:>8} {:>8}",
+            region.start,
+            region.end,
+            core::str::from_utf8(&perms).unwrap_or("????"),
+            type_name(region.region_type),
+            kib(usage.size),
+            kib(usage.resident),
+            kib(usage.cow),
+            kib(usage.shared)
+        )?;
+        total.add(&usage);
+    }
+    writeln!(
+        out,
+        "{:<44} {:>8} {:>8} {:>8} {:>8}",
+        "total (KiB)",
+        kib(total.size),
+        kib(total.resident),
+        kib(total.cow),
+        kib(total.shared)
+    )
+}
+
+crate::kernel_test! {
+    /// Only touched pages count as resident, and a read-only page in a
+    /// writable region counts as copy-on-write
+    fn smaps_region_usage() {
+        use super::paging::PageTableFlags as F;
+        let base = 0x10_0000_0000usize;
+        let flags = F::PRESENT | F::USER | F::WRITABLE | F::NO_EXECUTE;
+        let region = MemoryRegion::new(base, base + 4 * PAGE_SIZE, flags, MemoryRegionType::Anonymous);
+
+        let empty = region_usage(&region);
+        crate::ktest_assert_eq!(empty.size, 4, "size in pages");
+        crate::ktest_assert_eq!(empty.resident, 0, "untouched pages counted");
+
+        let page = VirtAddr::new(base as u64);
+        let frames = super::with_memory_managers(|pmm, mapper| {
+            let a = pmm.alloc_frame().ok_or("out of frames")?;
+            let b = pmm.alloc_frame().ok_or("out of frames")?;
+            mapper.map_page(page, a, flags, pmm)?;
+            mapper.map_page(page + PAGE_SIZE, b, F::PRESENT | F::USER | F::NO_EXECUTE, pmm)?;
+            Ok((a, b))
+        })?;
+        let usage = region_usage(&region);
+        super::with_memory_managers(|pmm, mapper| {
+            mapper.unmap_page(page)?;
+            mapper.unmap_page(page + PAGE_SIZE)?;
+            pmm.free_frame(frames.0);
+            pmm.free_frame(frames.1);
+            Ok(())
+        })?;
+        crate::ktest_assert_eq!(usage.resident, 2, "resident pages");
+        crate::ktest_assert_eq!(usage.cow, 1, "copy-on-write pages");
+        crate::ktest_assert_eq!(usage.shared, 0, "private pages counted as shared");
+        Ok(())
+    }
+}
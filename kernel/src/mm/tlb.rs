@@ -3,58 +3,133 @@
 //! This module implements TLB (Translation Lookaside Buffer) shootdown for
 //! multicore systems. When page tables are modified, all CPUs that might have
 //! cached translations must flush their TLBs to ensure memory consistency.
+//!
+//! Each CPU has a small queue of flush requests and a pair of counters. An
+//! initiator appends its range to every target's queue, which hands it a
+//! ticket, and sends `TLB_FLUSH_IPI_VECTOR` unless an IPI is already on its
+//! way. The target drains its whole queue in one go and publishes the last
+//! ticket it covered; the initiator waits until every target's published
+//! ticket reaches its own. Requests that arrive while an IPI is pending ride
+//! along with it, adjacent ranges are merged, and a queue that overflows
+//! turns into a single full flush, so a burst of unmaps costs a handful of
+//! IPIs rather than one per page.
+//!
+//! An initiator that is itself a target of someone else's shootdown drains
+//! its own queue while it waits, so two CPUs shooting each other down with
+//! interrupts disabled do not deadlock.
 
 use crate::arch::x86_64::apic::ipi::{send_ipi, TLB_FLUSH_IPI_VECTOR};
 use crate::arch::x86_64::smp::percpu::{percpu_current, percpu_for};
 use crate::arch::x86_64::smp::{get_cpu_count, is_cpu_online};
-use crate::sync::SpinLock;
-use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crate::config::MAX_CPUS;
+use crate::mm::VirtAddr;
+use crate::sync::IrqSpinLock;
+use crate::time::{Duration, Instant};
+use core::sync::atomic::{AtomicU64, Ordering};
 
-/// Maximum number of CPUs that can be targeted in a single shootdown
-const MAX_SHOOTDOWN_CPUS: usize = 64;
+const PAGE_SIZE: usize = 4096;
 
-/// TLB shootdown request structure
-///
-/// Contains information about a TLB flush request that needs to be
-/// propagated to other CPU cores.
-#[derive(Debug)]
-pub struct TlbShootdownRequest {
-    /// Virtual address to flush (or 0 for full TLB flush)
-    pub vaddr: usize,
-    /// Number of pages to flush (or 0 for full TLB flush)
-    pub page_count: usize,
-    /// Bitmask of CPUs that need to acknowledge
-    pub cpu_mask: u64,
-    /// Number of CPUs that have acknowledged
-    pub ack_count: AtomicUsize,
+/// Ranges a CPU's queue holds before it falls back to a full flush
+const QUEUE_LEN: usize = 8;
+
+/// Largest range flushed page by page; longer ones flush everything
+const MAX_RANGE_PAGES: usize = 64;
+
+/// How long an initiator waits for its targets to acknowledge
+const ACK_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A range of pages to flush; `page_count == 0` means the whole TLB
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Flush {
+    vaddr: VirtAddr,
+    page_count: usize,
+}
+
+/// Flushes waiting for one CPU
+struct FlushQueue {
+    ranges: [Flush; QUEUE_LEN],
+    len: usize,
+    /// Flush the whole TLB, global entries included, instead of `ranges`
+    full: bool,
+    /// Ticket of the latest request
+    requested: u64,
+    /// An IPI has been sent and not yet taken the queue
+    ipi_pending: bool,
 }
 
-impl TlbShootdownRequest {
-    /// Create a new TLB shootdown request
-    pub fn new(vaddr: usize, page_count: usize, cpu_mask: u64) -> Self {
-        Self {
-            vaddr,
-            page_count,
-            cpu_mask,
-            ack_count: AtomicUsize::new(0),
+impl FlushQueue {
+    const fn new() -> Self {
+        FlushQueue {
+            ranges: [Flush { vaddr: VirtAddr::new(0), page_count: 0 }; QUEUE_LEN],
+            len: 0,
+            full: false,
+            requested: 0,
+            ipi_pending: false,
+        }
+    }
+
+    /// Add `flush` and return its ticket
+    fn push(&mut self, flush: Flush) -> u64 {
+        self.requested += 1;
+        if self.full {
+            return self.requested;
+        }
+        if flush.page_count == 0 || flush.page_count > MAX_RANGE_PAGES {
+            self.full = true;
+            return self.requested;
+        }
+        if let Some(last) = self.ranges[..self.len].last_mut() {
+            if last.vaddr + last.page_count * PAGE_SIZE == flush.vaddr
+                && last.page_count + flush.page_count <= MAX_RANGE_PAGES
+            {
+                last.page_count += flush.page_count;
+                return self.requested;
+            }
+        }
+        if self.len == QUEUE_LEN {
+            self.full = true;
+        } else {
+            self.ranges[self.len] = flush;
+            self.len += 1;
         }
+        self.requested
     }
 
-    /// Check if all CPUs have acknowledged
-    pub fn is_complete(&self, expected_count: usize) -> bool {
-        self.ack_count.load(Ordering::Acquire) >= expected_count
+    /// Take everything queued, leaving the queue empty
+    fn take(&mut self) -> FlushQueue {
+        let taken = FlushQueue {
+            ranges: self.ranges,
+            len: self.len,
+            full: self.full,
+            requested: self.requested,
+            ipi_pending: false,
+        };
+        self.len = 0;
+        self.full = false;
+        self.ipi_pending = false;
+        taken
     }
+}
+
+/// Shootdown state of one CPU
+struct CpuShootdown {
+    queue: IrqSpinLock<FlushQueue>,
+    /// Ticket of the latest request this CPU has flushed for
+    completed: AtomicU64,
+}
 
-    /// Increment acknowledgment counter
-    pub fn acknowledge(&self) {
-        self.ack_count.fetch_add(1, Ordering::Release);
+impl CpuShootdown {
+    const fn new() -> Self {
+        CpuShootdown {
+            queue: IrqSpinLock::new(FlushQueue::new()),
+            completed: AtomicU64::new(0),
+        }
     }
 }
 
-/// Global TLB shootdown state
-///
-/// This structure coordinates TLB shootdowns across multiple CPUs.
-static TLB_SHOOTDOWN: SpinLock<Option<TlbShootdownRequest>> = SpinLock::new(None);
+crate::per_cpu! {
+    static SHOOTDOWN: CpuShootdown = CpuShootdown::new();
+}
 
 /// Sequence number for TLB shootdowns (for debugging)
 static TLB_SHOOTDOWN_SEQ: AtomicU64 = AtomicU64::new(0);
@@ -68,10 +143,10 @@ static TLB_SHOOTDOWN_SEQ: AtomicU64 = AtomicU64::new(0);
 /// This function uses the `invlpg` instruction which is safe but requires
 /// the address to be properly aligned.
 #[inline]
-pub unsafe fn flush_page(vaddr: usize) {
+pub unsafe fn flush_page(vaddr: VirtAddr) {
     core::arch::asm!(
         "invlpg [{}]",
-        in(reg) vaddr,
+        in(reg) vaddr.as_u64(),
         options(nostack, preserves_flags)
     );
 }
@@ -97,6 +172,34 @@ pub unsafe fn flush_all() {
     );
 }
 
+/// Flush the entire TLB, including global entries
+///
+/// Toggles CR4.PGE, which invalidates every TLB entry on this CPU.
+///
+/// # Safety
+/// This function affects all address translations on the current CPU.
+#[inline]
+pub unsafe fn flush_all_global() {
+    const CR4_PGE: usize = 1 << 7;
+    let cr4: usize;
+    core::arch::asm!(
+        "mov {}, cr4",
+        out(reg) cr4,
+        options(nostack, preserves_flags)
+    );
+    if cr4 & CR4_PGE != 0 {
+        core::arch::asm!(
+            "mov cr4, {}",
+            "mov cr4, {}",
+            in(reg) cr4 & !CR4_PGE,
+            in(reg) cr4,
+            options(nostack, preserves_flags)
+        );
+    } else {
+        flush_all();
+    }
+}
+
 /// Flush a range of pages from the TLB
 ///
 /// # Arguments
@@ -105,11 +208,9 @@ pub unsafe fn flush_all() {
 ///
 /// # Safety
 /// This function flushes TLB entries which affects address translation.
-pub unsafe fn flush_range(vaddr: usize, page_count: usize) {
-    const PAGE_SIZE: usize = 4096;
-
+pub unsafe fn flush_range(vaddr: VirtAddr, page_count: usize) {
     // If flushing more than 64 pages, just flush the entire TLB
-    if page_count > 64 {
+    if page_count > MAX_RANGE_PAGES {
         flush_all();
         return;
     }
@@ -121,90 +222,42 @@ pub unsafe fn flush_range(vaddr: usize, page_count: usize) {
     }
 }
 
-/// Send TLB shootdown IPI to specified CPUs
-///
-/// This function sends TLB flush IPIs to all CPUs in the given mask,
-/// excluding the current CPU.
-///
-/// # Arguments
-/// * `cpu_mask` - Bitmask of CPUs to send IPI to
-///
-/// # Returns
-/// Number of IPIs successfully sent
-fn send_tlb_shootdown_ipis(cpu_mask: u64) -> usize {
-    let current_cpu = percpu_current().id;
-    let cpu_count = get_cpu_count();
-    let mut sent_count = 0;
-
-    for cpu_id in 0..cpu_count.min(64) {
-        // Skip if this CPU is not in the mask
-        if (cpu_mask & (1 << cpu_id)) == 0 {
-            continue;
-        }
-
-        // Skip current CPU
-        if cpu_id == current_cpu {
-            continue;
-        }
-
-        // Skip if CPU is not online
-        if !is_cpu_online(cpu_id) {
-            continue;
-        }
-
-        // Get target CPU's APIC ID
-        let target_apic_id = percpu_for(cpu_id).apic_id;
-
-        // Send TLB flush IPI
-        if send_ipi(target_apic_id, TLB_FLUSH_IPI_VECTOR) {
-            sent_count += 1;
-        }
+/// Flush `flush` on the current CPU
+unsafe fn flush_local(flush: Flush) {
+    if flush.page_count == 0 {
+        flush_all();
+    } else {
+        flush_range(flush.vaddr, flush.page_count);
     }
-
-    sent_count
 }
 
-/// Wait for TLB shootdown acknowledgments
+/// Carry out every flush queued for `cpu`, the current CPU
 ///
-/// Spins until all expected CPUs have acknowledged the TLB flush.
-///
-/// # Arguments
-/// * `request` - The shootdown request to wait for
-/// * `expected_count` - Number of acknowledgments to wait for
-/// * `timeout_ms` - Maximum time to wait in milliseconds
-///
-/// # Returns
-/// `true` if all acknowledgments received, `false` on timeout
-fn wait_for_acks(request: &TlbShootdownRequest, expected_count: usize, timeout_ms: u64) -> bool {
-    // Get current timestamp
-    let start = unsafe { core::arch::x86_64::_rdtsc() };
-    const TSC_PER_MS: u64 = 2_400_000; // Approximate, should use calibrated value
-    let timeout_tsc = timeout_ms * TSC_PER_MS;
-
-    // Spin until all acknowledgments received or timeout
-    loop {
-        if request.is_complete(expected_count) {
-            return true;
-        }
-
-        // Check timeout
-        let now = unsafe { core::arch::x86_64::_rdtsc() };
-        if now - start >= timeout_tsc {
-            return false;
+/// Returns whether there was anything to do.
+unsafe fn drain_queue(cpu: usize) -> bool {
+    let state = SHOOTDOWN.get_for(cpu);
+    let queued = state.queue.lock().take();
+    if queued.requested == state.completed.load(Ordering::Relaxed) {
+        return false;
+    }
+    if queued.full {
+        flush_all_global();
+    } else {
+        for flush in &queued.ranges[..queued.len] {
+            flush_range(flush.vaddr, flush.page_count);
         }
-
-        // Yield to reduce bus contention
-        core::hint::spin_loop();
     }
+    state.completed.store(queued.requested, Ordering::Release);
+    true
 }
 
 /// Perform TLB shootdown for page table modifications
 ///
 /// This function coordinates a TLB flush across multiple CPU cores. It:
-/// 1. Flushes the TLB on the current CPU
-/// 2. Sends IPIs to other CPUs that might have cached translations
-/// 3. Waits for acknowledgments from all target CPUs
-/// 4. Returns when all CPUs have flushed their TLBs
+/// 1. Queues the flush for every other online CPU in the mask
+/// 2. Sends IPIs to the CPUs that have none pending
+/// 3. Flushes the TLB on the current CPU
+/// 4. Waits until every target has flushed past its request
 ///
 /// # Arguments
 /// * `vaddr` - Virtual address to flush (or 0 for full flush)
@@ -230,145 +283,158 @@ fn wait_for_acks(request: &TlbShootdownRequest, expected_count: usize, timeout_m
 /// // Now it's safe to free the physical page
 /// pmm_free(phys_addr);
 /// ```
-pub unsafe fn tlb_shootdown(vaddr: usize, page_count: usize, cpu_mask: u64) -> bool {
+pub unsafe fn tlb_shootdown(vaddr: VirtAddr, page_count: usize, cpu_mask: u64) -> bool {
     let seq = TLB_SHOOTDOWN_SEQ.fetch_add(1, Ordering::Relaxed);
     let current_cpu = percpu_current().id;
+    let flush = Flush { vaddr, page_count };
+    let cpu_count = get_cpu_count().min(MAX_CPUS);
 
-    // Determine which CPUs need to flush
-    let target_mask = if cpu_mask == 0 {
-        // All CPUs
-        let cpu_count = get_cpu_count().min(64);
-        (1u64 << cpu_count) - 1
-    } else {
-        cpu_mask
-    };
-
-    // Count how many other CPUs we need to wait for
-    let mut expected_acks = 0;
-    for cpu_id in 0..64 {
-        if (target_mask & (1 << cpu_id)) != 0 && cpu_id != current_cpu && is_cpu_online(cpu_id) {
-            expected_acks += 1;
+    // Queue the flush on every target and kick the ones without an IPI
+    // already on its way
+    let mut tickets = [0u64; MAX_CPUS];
+    let mut waiting = 0u64;
+    let mut sent_count = 0;
+    for cpu_id in 0..cpu_count {
+        let targeted = cpu_mask == 0 || cpu_mask & (1 << cpu_id) != 0;
+        if !targeted || cpu_id == current_cpu || !is_cpu_online(cpu_id) {
+            continue;
         }
-    }
-
-    // If no other CPUs need to flush, just flush locally
-    if expected_acks == 0 {
-        if page_count == 0 {
-            flush_all();
-        } else {
-            flush_range(vaddr, page_count);
+        let state = SHOOTDOWN.get_for(cpu_id);
+        let send = {
+            let mut queue = state.queue.lock();
+            tickets[cpu_id] = queue.push(flush);
+            !core::mem::replace(&mut queue.ipi_pending, true)
+        };
+        waiting |= 1 << cpu_id;
+        if send {
+            if send_ipi(percpu_for(cpu_id).apic_id, TLB_FLUSH_IPI_VECTOR) {
+                sent_count += 1;
+            } else {
+                // Let the next request try again
+                state.queue.lock().ipi_pending = false;
+            }
         }
-        return true;
-    }
-
-    // Create shootdown request
-    let request = TlbShootdownRequest::new(vaddr, page_count, target_mask);
-
-    // Install the request globally
-    {
-        let mut shootdown = TLB_SHOOTDOWN.lock();
-        *shootdown = Some(request);
     }
 
-    // Send IPIs to target CPUs
-    let sent_count = send_tlb_shootdown_ipis(target_mask);
-
-    // Flush local TLB
-    if page_count == 0 {
-        flush_all();
-    } else {
-        flush_range(vaddr, page_count);
+    flush_local(flush);
+    percpu_current().inc_tlb_shootdowns();
+    if waiting == 0 {
+        return true;
     }
 
-    // Wait for acknowledgments (timeout after 100ms)
-    // We need to wait by checking the global state periodically
-    let start = core::arch::x86_64::_rdtsc();
-    const TSC_PER_MS: u64 = 2_400_000;
-    let timeout_tsc = 100 * TSC_PER_MS;
-    
-    let mut success = false;
+    let deadline = Instant::now().saturating_add(ACK_TIMEOUT);
     loop {
-        // Check if all acknowledgments received
-        let ack_count = {
-            let shootdown = TLB_SHOOTDOWN.lock();
-            if let Some(ref req) = *shootdown {
-                req.ack_count.load(Ordering::Acquire)
-            } else {
-                0
+        for cpu_id in 0..cpu_count {
+            if waiting & (1 << cpu_id) != 0 && SHOOTDOWN.get_for(cpu_id).completed.load(Ordering::Acquire) >= tickets[cpu_id] {
+                waiting &= !(1 << cpu_id);
             }
-        };
-        
-        if ack_count >= expected_acks {
-            success = true;
-            break;
         }
-        
-        // Check timeout
-        let now = core::arch::x86_64::_rdtsc();
-        if now - start >= timeout_tsc {
-            break;
+        if waiting == 0 {
+            return true;
+        }
+        if deadline.has_passed() {
+            crate::serial_println!(
+                "[TLB] WARNING: Shootdown #{} timed out (sent={}, waiting for CPUs {:#x})",
+                seq,
+                sent_count,
+                waiting
+            );
+            return false;
         }
-        
+        // Serve shootdowns aimed at this CPU while waiting for others
+        drain_queue(current_cpu);
         core::hint::spin_loop();
     }
+}
 
-    // Clear the global request
-    {
-        let mut shootdown = TLB_SHOOTDOWN.lock();
-        *shootdown = None;
+/// Handle TLB shootdown IPI
+///
+/// Flushes everything queued for this CPU and acknowledges it.
+///
+/// # Safety
+/// This function is called from an interrupt handler and must not block.
+pub unsafe fn handle_tlb_shootdown_ipi() {
+    let cpu = percpu_current();
+    if drain_queue(cpu.id) {
+        cpu.inc_tlb_shootdowns();
     }
+}
 
-    // Update statistics
-    percpu_current().inc_tlb_shootdowns();
+/// TLB_FLUSH_IPI entry point: saves the caller-saved registers around
+/// [`tlb_flush_ipi_handler`]
+#[unsafe(naked)]
+extern "C" fn tlb_flush_ipi_wrapper() {
+    core::arch::naked_asm!(
+        crate::swapgs_if_user!(8),
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "call {handler}",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+        crate::swapgs_if_user!(8),
+        "iretq",
+        handler = sym tlb_flush_ipi_handler,
+    )
+}
 
-    if !success {
-        crate::serial_println!(
-            "[TLB] WARNING: Shootdown #{} timed out (sent={}, expected={})",
-            seq,
-            sent_count,
-            expected_acks
-        );
-    }
+extern "C" fn tlb_flush_ipi_handler() {
+    use crate::arch::x86_64::acpi::get_madt_info;
+    use crate::arch::x86_64::apic::LocalApic;
 
-    success
+    crate::sync::lockdep::irq_enter();
+    unsafe {
+        handle_tlb_shootdown_ipi();
+        let madt_info = get_madt_info().expect("MADT info not available");
+        LocalApic::new(madt_info.lapic_address).eoi();
+    }
+    crate::sync::lockdep::irq_exit();
 }
 
-/// Handle TLB shootdown IPI
-///
-/// This function is called from the TLB flush IPI handler. It:
-/// 1. Reads the current shootdown request
-/// 2. Flushes the appropriate TLB entries
-/// 3. Acknowledges the shootdown
+/// Register the TLB_FLUSH_IPI handler in the IDT
 ///
 /// # Safety
-/// This function is called from an interrupt handler and must not block.
-pub unsafe fn handle_tlb_shootdown_ipi() {
-    // Access the current shootdown request without moving it
-    let shootdown = TLB_SHOOTDOWN.lock();
-    
-    if let Some(ref req) = *shootdown {
-        // Check if this CPU is in the target mask
-        let current_cpu = percpu_current().id;
-        if (req.cpu_mask & (1 << current_cpu)) != 0 {
-            // Copy the values we need before releasing the lock
-            let vaddr = req.vaddr;
-            let page_count = req.page_count;
-            
-            // Acknowledge first
-            req.acknowledge();
-            
-            // Release lock before flushing (flushing is slow)
-            drop(shootdown);
-            
-            // Flush TLB
-            if page_count == 0 {
-                flush_all();
-            } else {
-                flush_range(vaddr, page_count);
-            }
+/// Must be called during kernel initialization, after the IDT is set up and
+/// before any AP is started.
+pub unsafe fn init_ipi_handler() {
+    crate::sched::timer::set_idt_gate(TLB_FLUSH_IPI_VECTOR, tlb_flush_ipi_wrapper as *const () as usize, 0);
+    crate::serial_println!("[IPI] TLB_FLUSH_IPI handler registered at vector {:#x}", TLB_FLUSH_IPI_VECTOR);
+}
 
-            // Update statistics
-            percpu_current().inc_tlb_shootdowns();
+crate::kernel_test! {
+    /// Queued flushes merge when adjacent and collapse to a full flush when the queue fills
+    fn tlb_queue_batching() {
+        let mut queue = FlushQueue::new();
+        let first = queue.push(Flush { vaddr: VirtAddr::new(0x1000), page_count: 2 });
+        let second = queue.push(Flush { vaddr: VirtAddr::new(0x3000), page_count: 1 });
+        crate::ktest_assert!(second > first, "tickets not increasing");
+        crate::ktest_assert_eq!(queue.len, 1, "adjacent ranges not merged");
+        crate::ktest_assert_eq!(queue.ranges[0], Flush { vaddr: VirtAddr::new(0x1000), page_count: 3 }, "merged range");
+
+        for i in 0..QUEUE_LEN {
+            queue.push(Flush { vaddr: VirtAddr::new((0x10_0000 * (i + 1)) as u64), page_count: 1 });
         }
+        crate::ktest_assert!(queue.full, "overflowing queue not turned into a full flush");
+
+        let taken = queue.take();
+        crate::ktest_assert!(taken.full && taken.requested == queue.requested, "take lost requests");
+        crate::ktest_assert!(!queue.full && queue.len == 0, "queue not empty after take");
+
+        queue.push(Flush { vaddr: VirtAddr::new(0), page_count: 0 });
+        crate::ktest_assert!(queue.full, "full flush request not honored");
+        Ok(())
     }
 }
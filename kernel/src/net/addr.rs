@@ -0,0 +1,235 @@
+//! Network addresses
+//!
+//! `IpAddr` and `SocketAddr` are what the protocol layers above IP deal in,
+//! so UDP endpoints and the rest of the stack don't care which IP version a
+//! peer speaks. Only the IP layer itself looks inside.
+
+use core::fmt;
+
+/// Ethernet hardware address
+pub type MacAddr = [u8; 6];
+
+/// IPv4 address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Self = Self([0; 4]);
+
+    /// 255.255.255.255, every host on the link
+    pub const BROADCAST: Self = Self([255; 4]);
+
+    pub const fn to_u32(&self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub const fn from_u32(value: u32) -> Self {
+        Self(value.to_be_bytes())
+    }
+
+    pub const fn is_unspecified(&self) -> bool {
+        self.to_u32() == 0
+    }
+
+    pub const fn is_broadcast(&self) -> bool {
+        self.to_u32() == u32::MAX
+    }
+
+    pub const fn is_multicast(&self) -> bool {
+        self.0[0] & 0xf0 == 224
+    }
+
+    /// Whether `other` is on the same `prefix_len`-bit network
+    pub const fn same_network(&self, other: &Self, prefix_len: u8) -> bool {
+        let mask = prefix_mask(prefix_len);
+        self.to_u32() & mask == other.to_u32() & mask
+    }
+
+    /// Parse dotted-quad notation (`10.0.2.15`)
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut octets = [0; 4];
+        let mut parts = text.split('.');
+        for octet in &mut octets {
+            *octet = parts.next()?.parse().ok()?;
+        }
+        parts.next().is_none().then_some(Self(octets))
+    }
+}
+
+/// Network mask of a `prefix_len`-bit prefix (at most 32)
+pub const fn prefix_mask(prefix_len: u8) -> u32 {
+    match prefix_len {
+        0 => 0,
+        1..=32 => u32::MAX << (32 - prefix_len),
+        _ => u32::MAX,
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+/// IPv6 address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Addr(pub [u8; 16]);
+
+impl Ipv6Addr {
+    pub const UNSPECIFIED: Self = Self([0; 16]);
+
+    /// ff02::1, all nodes on the link
+    pub const ALL_NODES: Self = Self([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+    /// Link-local address formed from `mac` by modified EUI-64 (RFC 4291)
+    pub const fn link_local(mac: MacAddr) -> Self {
+        Self([
+            0xfe, 0x80, 0, 0, 0, 0, 0, 0,
+            mac[0] ^ 0x02, mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5],
+        ])
+    }
+
+    /// Solicited-node multicast group of this address (ff02::1:ffXX:XXXX)
+    pub const fn solicited_node(&self) -> Self {
+        let a = &self.0;
+        Self([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, a[13], a[14], a[15]])
+    }
+
+    /// Ethernet group address a multicast packet to this address goes to
+    pub const fn multicast_mac(&self) -> MacAddr {
+        let a = &self.0;
+        [0x33, 0x33, a[12], a[13], a[14], a[15]]
+    }
+
+    pub const fn is_unspecified(&self) -> bool {
+        u128::from_be_bytes(self.0) == 0
+    }
+
+    pub const fn is_multicast(&self) -> bool {
+        self.0[0] == 0xff
+    }
+
+    pub const fn is_link_local(&self) -> bool {
+        self.0[0] == 0xfe && self.0[1] & 0xc0 == 0x80
+    }
+
+    fn group(&self, i: usize) -> u16 {
+        u16::from_be_bytes([self.0[2 * i], self.0[2 * i + 1]])
+    }
+}
+
+impl fmt::Display for Ipv6Addr {
+    /// RFC 5952 text form: the longest run of two or more zero groups
+    /// becomes `::`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (mut best, mut best_len, mut run, mut run_len) = (0, 0, 0, 0);
+        for i in 0..8 {
+            if self.group(i) == 0 {
+                if run_len == 0 {
+                    run = i;
+                }
+                run_len += 1;
+                if run_len > best_len {
+                    best = run;
+                    best_len = run_len;
+                }
+            } else {
+                run_len = 0;
+            }
+        }
+        if best_len < 2 {
+            best_len = 0;
+            best = 8;
+        }
+
+        let mut i = 0;
+        while i < 8 {
+            if i == best {
+                f.write_str("::")?;
+                i += best_len;
+                continue;
+            }
+            if i != 0 && i != best + best_len {
+                f.write_str(":")?;
+            }
+            write!(f, "{:x}", self.group(i))?;
+            i += 1;
+        }
+        Ok(())
+    }
+}
+
+/// An IPv4 or IPv6 address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpAddr {
+    V4(Ipv4Addr),
+    V6(Ipv6Addr),
+}
+
+impl fmt::Display for IpAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IpAddr::V4(addr) => addr.fmt(f),
+            IpAddr::V6(addr) => addr.fmt(f),
+        }
+    }
+}
+
+/// IP address and transport port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketAddr {
+    pub ip: IpAddr,
+    pub port: u16,
+}
+
+impl SocketAddr {
+    pub const fn new(ip: IpAddr, port: u16) -> Self {
+        Self { ip, port }
+    }
+}
+
+impl fmt::Display for SocketAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ip {
+            IpAddr::V4(addr) => write!(f, "{}:{}", addr, self.port),
+            IpAddr::V6(addr) => write!(f, "[{}]:{}", addr, self.port),
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// SLAAC link-local and solicited-node addresses derive from the MAC
+    fn net_ipv6_addresses() {
+        use core::fmt::Write;
+
+        struct BufWriter {
+            buf: [u8; 64],
+            pos: usize,
+        }
+
+        impl Write for BufWriter {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                let end = (self.pos + s.len()).min(self.buf.len());
+                self.buf[self.pos..end].copy_from_slice(&s.as_bytes()[..end - self.pos]);
+                self.pos = end;
+                Ok(())
+            }
+        }
+
+        let addr = Ipv6Addr::link_local([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+        crate::ktest_assert!(addr.is_link_local(), "not link-local");
+        let mut text = BufWriter { buf: [0; 64], pos: 0 };
+        let _ = write!(text, "{}", addr);
+        crate::ktest_assert_eq!(&text.buf[..text.pos], b"fe80::5054:ff:fe12:3456", "text form");
+
+        let group = addr.solicited_node();
+        crate::ktest_assert!(group.is_multicast(), "group not multicast");
+        crate::ktest_assert_eq!(group.multicast_mac(), [0x33, 0x33, 0xff, 0x12, 0x34, 0x56], "group mac");
+
+        text.pos = 0;
+        let _ = write!(text, "{}", SocketAddr::new(IpAddr::V6(Ipv6Addr::ALL_NODES), 53));
+        crate::ktest_assert_eq!(&text.buf[..text.pos], b"[ff02::1]:53", "socket address text form");
+        Ok(())
+    }
+}
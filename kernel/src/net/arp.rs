@@ -0,0 +1,273 @@
+//! ARP (RFC 826)
+//!
+//! Resolves IPv4 next hops to MAC addresses for Ethernet. Entries are
+//! learned from requests for our address and from replies, and expire
+//! `ENTRY_LIFETIME` after they were last confirmed; the deadlines are
+//! `Instant`s checked when the cache is used. Resolving an unknown address
+//! broadcasts a request and fails with `NetError::Unresolved`, like NDP,
+//! so the caller retries; requests for the same address go out at most
+//! once per `REQUEST_INTERVAL`. A full cache replaces entries round-robin,
+//! and the cache is dropped under memory pressure (`mm::shrink`).
+//!
+//! A configured address is announced with a gratuitous request, and a
+//! request or reply from another host claiming it is logged.
+
+use super::addr::{Ipv4Addr, MacAddr};
+use super::{ethernet, Interface, NetError};
+use crate::time::{Duration, Instant};
+use spin::Mutex;
+
+/// Number of cache entries
+const CACHE_SIZE: usize = 16;
+
+/// How long a learned address is trusted
+const ENTRY_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Shortest time between requests for one address
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Length of an Ethernet/IPv4 ARP packet
+const PACKET_LEN: usize = 28;
+
+const HTYPE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Known address, trusted until `expires`
+    Resolved(MacAddr),
+    /// Request sent; the next may go out at `expires`
+    Pending,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    ip: Ipv4Addr,
+    state: State,
+    expires: Instant,
+}
+
+struct ArpCache {
+    entries: [Option<Entry>; CACHE_SIZE],
+    /// Entry to replace next when the cache is full
+    victim: usize,
+}
+
+impl ArpCache {
+    fn find(&mut self, ip: &Ipv4Addr) -> Option<&mut Entry> {
+        self.entries.iter_mut().flatten().find(|entry| entry.ip == *ip)
+    }
+
+    /// Set the entry for `ip`, replacing an old one if needed
+    fn set(&mut self, entry: Entry) {
+        if let Some(old) = self.find(&entry.ip) {
+            *old = entry;
+            return;
+        }
+        let index = match self.entries.iter().position(|entry| entry.is_none()) {
+            Some(index) => index,
+            None => {
+                let index = self.victim;
+                self.victim = (index + 1) % CACHE_SIZE;
+                index
+            }
+        };
+        self.entries[index] = Some(entry);
+    }
+}
+
+static CACHE: Mutex<ArpCache> = Mutex::new(ArpCache {
+    entries: [None; CACHE_SIZE],
+    victim: 0,
+});
+
+/// Drops ARP cache entries under memory pressure; they are cheap to learn
+/// again
+pub static SHRINKER: crate::mm::shrink::Shrinker = crate::mm::shrink::Shrinker::new("arp-cache", 0, count, shrink);
+
+/// Number of cached entries
+fn count() -> usize {
+    CACHE.lock().entries.iter().flatten().count()
+}
+
+/// Drop up to `target` entries, oldest first; returns how many were dropped
+fn shrink(target: usize) -> usize {
+    let mut cache = CACHE.lock();
+    let mut dropped = 0;
+    for i in 0..CACHE_SIZE {
+        if dropped == target {
+            break;
+        }
+        let index = (cache.victim + i) % CACHE_SIZE;
+        if cache.entries[index].take().is_some() {
+            dropped += 1;
+        }
+    }
+    dropped
+}
+
+/// MAC address of `ip`, if known and not expired
+pub fn lookup(ip: &Ipv4Addr) -> Option<MacAddr> {
+    let mut cache = CACHE.lock();
+    let entry = cache.find(ip)?;
+    match entry.state {
+        State::Resolved(mac) if !entry.expires.has_passed() => Some(mac),
+        _ => None,
+    }
+}
+
+/// Record that `ip` is at `mac`
+pub fn update(ip: &Ipv4Addr, mac: MacAddr) {
+    CACHE.lock().set(Entry {
+        ip: *ip,
+        state: State::Resolved(mac),
+        expires: Instant::now().saturating_add(ENTRY_LIFETIME),
+    });
+}
+
+/// MAC address of `ip` on `iface`
+///
+/// An unknown or expired address is asked for with a broadcast request,
+/// at most once per `REQUEST_INTERVAL`, and `NetError::Unresolved` is
+/// returned.
+pub fn resolve(iface: &Interface, ip: &Ipv4Addr) -> Result<MacAddr, NetError> {
+    {
+        let mut cache = CACHE.lock();
+        match cache.find(ip) {
+            Some(entry) if !entry.expires.has_passed() => match entry.state {
+                State::Resolved(mac) => return Ok(mac),
+                State::Pending => return Err(NetError::Unresolved),
+            },
+            _ => cache.set(Entry {
+                ip: *ip,
+                state: State::Pending,
+                expires: Instant::now().saturating_add(REQUEST_INTERVAL),
+            }),
+        }
+    }
+    let src = iface.ipv4.map_or(Ipv4Addr::UNSPECIFIED, |config| config.addr);
+    send(iface, ethernet::BROADCAST, &build(OP_REQUEST, iface.mac, &src, [0; 6], ip))?;
+    Err(NetError::Unresolved)
+}
+
+/// Announce our IPv4 address with a gratuitous request
+pub fn announce(iface: &Interface) -> Result<(), NetError> {
+    let Some(config) = iface.ipv4 else { return Ok(()) };
+    send(iface, ethernet::BROADCAST, &build(OP_REQUEST, iface.mac, &config.addr, [0; 6], &config.addr))
+}
+
+/// Fields of an Ethernet/IPv4 ARP packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Packet {
+    op: u16,
+    sender_mac: MacAddr,
+    sender_ip: Ipv4Addr,
+    target_ip: Ipv4Addr,
+}
+
+fn build(op: u16, sender_mac: MacAddr, sender_ip: &Ipv4Addr, target_mac: MacAddr, target_ip: &Ipv4Addr) -> [u8; PACKET_LEN] {
+    let mut packet = [0u8; PACKET_LEN];
+    packet[0..2].copy_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+    packet[2..4].copy_from_slice(&ethernet::ETHERTYPE_IPV4.to_be_bytes());
+    packet[4] = 6;
+    packet[5] = 4;
+    packet[6..8].copy_from_slice(&op.to_be_bytes());
+    packet[8..14].copy_from_slice(&sender_mac);
+    packet[14..18].copy_from_slice(&sender_ip.0);
+    packet[18..24].copy_from_slice(&target_mac);
+    packet[24..28].copy_from_slice(&target_ip.0);
+    packet
+}
+
+fn parse(packet: &[u8]) -> Option<Packet> {
+    let packet = packet.get(..PACKET_LEN)?;
+    if packet[0..2] != HTYPE_ETHERNET.to_be_bytes()
+        || packet[2..4] != ethernet::ETHERTYPE_IPV4.to_be_bytes()
+        || packet[4..6] != [6, 4]
+    {
+        return None;
+    }
+    let mut sender_mac = [0; 6];
+    let mut sender_ip = [0; 4];
+    let mut target_ip = [0; 4];
+    sender_mac.copy_from_slice(&packet[8..14]);
+    sender_ip.copy_from_slice(&packet[14..18]);
+    target_ip.copy_from_slice(&packet[24..28]);
+    Some(Packet {
+        op: u16::from_be_bytes([packet[6], packet[7]]),
+        sender_mac,
+        sender_ip: Ipv4Addr(sender_ip),
+        target_ip: Ipv4Addr(target_ip),
+    })
+}
+
+fn send(iface: &Interface, dst: MacAddr, packet: &[u8; PACKET_LEN]) -> Result<(), NetError> {
+    let mut frame = [0u8; ethernet::HEADER_LEN + PACKET_LEN];
+    ethernet::write_header(
+        &mut frame,
+        &ethernet::Header { dst, src: iface.mac, ethertype: ethernet::ETHERTYPE_ARP },
+    );
+    frame[ethernet::HEADER_LEN..].copy_from_slice(packet);
+    iface.device.transmit(&frame).map_err(|_| NetError::Device)?;
+    super::softnet::count_tx(iface.index);
+    Ok(())
+}
+
+/// Handle a received ARP packet
+pub fn receive(iface: &Interface, packet: &[u8]) {
+    let Some(packet) = parse(packet) else { return };
+    let Some(config) = iface.ipv4 else { return };
+    if packet.sender_ip.is_unspecified() {
+        return;
+    }
+    if packet.sender_ip == config.addr {
+        if packet.sender_mac != iface.mac {
+            crate::log_warn!("NET", "{}: duplicate address {} at {:02x?}", iface.device.name(), config.addr, packet.sender_mac);
+        }
+        return;
+    }
+
+    // RFC 826: refresh a known sender; learn a new one only when it
+    // talks to us
+    let for_us = packet.target_ip == config.addr;
+    let known = CACHE.lock().find(&packet.sender_ip).is_some();
+    if known || for_us {
+        update(&packet.sender_ip, packet.sender_mac);
+    }
+    if for_us && packet.op == OP_REQUEST {
+        let reply = build(OP_REPLY, iface.mac, &config.addr, packet.sender_mac, &packet.sender_ip);
+        let _ = send(iface, packet.sender_mac, &reply);
+    }
+}
+
+crate::kernel_test! {
+    /// Requests parse back, and a learned address resolves until it is
+    /// replaced
+    fn net_arp_packets() {
+        let mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        let ip = Ipv4Addr([10, 0, 2, 15]);
+        let target = Ipv4Addr([10, 0, 2, 2]);
+        let packet = build(OP_REQUEST, mac, &ip, [0; 6], &target);
+        crate::ktest_assert_eq!(
+            parse(&packet),
+            Some(Packet { op: OP_REQUEST, sender_mac: mac, sender_ip: ip, target_ip: target }),
+            "request"
+        );
+        let mut ipv6 = packet;
+        ipv6[2..4].copy_from_slice(&ethernet::ETHERTYPE_IPV6.to_be_bytes());
+        crate::ktest_assert_eq!(parse(&ipv6), None, "other protocol accepted");
+        crate::ktest_assert_eq!(parse(&packet[..PACKET_LEN - 1]), None, "short packet accepted");
+
+        let peer = Ipv4Addr([192, 0, 2, 77]);
+        update(&peer, mac);
+        crate::ktest_assert_eq!(lookup(&peer), Some(mac), "learned address");
+        update(&peer, [2; 6]);
+        crate::ktest_assert_eq!(lookup(&peer), Some([2; 6]), "address not replaced");
+        if let Some(entry) = CACHE.lock().find(&peer) {
+            entry.expires = Instant::BOOT;
+        }
+        crate::ktest_assert_eq!(lookup(&peer), None, "expired address");
+        Ok(())
+    }
+}
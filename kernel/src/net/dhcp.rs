@@ -0,0 +1,450 @@
+//! DHCP client (RFC 2131)
+//!
+//! Without `ipv4=` on the command line the first interface gets its IPv4
+//! configuration from a DHCP server. The `Dhcp` kernel task broadcasts a
+//! DISCOVER, requests the first OFFER and configures the interface from the
+//! ACK: address, netmask, router and DNS server (`net::dns_server`).
+//!
+//! The lease is kept with `Instant` deadlines the task sleeps towards: at
+//! T1 it renews with its server, from T2 it broadcasts the request to any
+//! server (rebinding), and when the lease runs out the address is dropped
+//! and the task starts over. A NAK starts over at once.
+//!
+//! Replies are asked to be broadcast, as an interface without an address
+//! takes no unicast IPv4. The client port is a kernel UDP endpoint, so
+//! replies arrive through the ordinary receive path and wake `udp::WAIT`.
+
+use super::addr::{IpAddr, Ipv4Addr, Ipv6Addr, MacAddr, SocketAddr};
+use super::{udp, Interface, Ipv4Config};
+use crate::sync::Poller;
+use crate::time::{Duration, Instant};
+
+const CLIENT_PORT: u16 = 68;
+const SERVER_PORT: u16 = 67;
+
+/// Messages are at most this long, the size every host must accept
+const MAX_MESSAGE: usize = 576;
+
+/// BOOTP header and magic cookie, before the options
+const FIXED_LEN: usize = 240;
+
+/// Shortest message sent; older relays drop shorter BOOTP packets
+const MIN_MESSAGE: usize = 300;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+
+/// Flags field: reply to the broadcast address
+const FLAG_BROADCAST: u16 = 0x8000;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_ADDR: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETERS: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_END: u8 = 255;
+
+/// Options asked for in every request
+const PARAMETERS: [u8; 6] = [
+    OPT_SUBNET_MASK,
+    OPT_ROUTER,
+    OPT_DNS,
+    OPT_LEASE_TIME,
+    OPT_RENEWAL_TIME,
+    OPT_REBINDING_TIME,
+];
+
+/// First wait for an answer while acquiring; it doubles up to `MAX_TIMEOUT`
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(4);
+const MAX_TIMEOUT: Duration = Duration::from_secs(64);
+
+/// Shortest wait between renewal requests (RFC 2131 4.4.5)
+const MIN_RENEW_RETRY: Duration = Duration::from_secs(60);
+
+/// Time between retries while the server's link-layer address is resolved
+const RESOLVE_RETRY: Duration = Duration::from_millis(100);
+
+/// A message being built
+struct Message {
+    buf: [u8; MAX_MESSAGE],
+    len: usize,
+}
+
+impl Message {
+    /// Request of type `kind` from `mac`; `ciaddr` is our address while we
+    /// have one, and without one the reply is asked to be broadcast
+    fn new(kind: u8, xid: u32, mac: &MacAddr, ciaddr: Ipv4Addr) -> Self {
+        let mut message = Self { buf: [0; MAX_MESSAGE], len: FIXED_LEN };
+        let buf = &mut message.buf;
+        buf[0] = BOOTREQUEST;
+        buf[1] = HTYPE_ETHERNET;
+        buf[2] = mac.len() as u8;
+        buf[4..8].copy_from_slice(&xid.to_be_bytes());
+        if ciaddr.is_unspecified() {
+            buf[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+        }
+        buf[12..16].copy_from_slice(&ciaddr.0);
+        buf[28..34].copy_from_slice(mac);
+        buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+        message.option(OPT_MESSAGE_TYPE, &[kind]);
+        message.option(OPT_PARAMETERS, &PARAMETERS);
+        message
+    }
+
+    fn option(&mut self, code: u8, data: &[u8]) {
+        self.buf[self.len] = code;
+        self.buf[self.len + 1] = data.len() as u8;
+        self.buf[self.len + 2..self.len + 2 + data.len()].copy_from_slice(data);
+        self.len += 2 + data.len();
+    }
+
+    /// The message with its end option, padded to `MIN_MESSAGE`
+    fn finish(&mut self) -> &[u8] {
+        self.buf[self.len] = OPT_END;
+        self.len = (self.len + 1).max(MIN_MESSAGE);
+        &self.buf[..self.len]
+    }
+}
+
+/// The fields of an OFFER, ACK or NAK the client uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reply {
+    kind: u8,
+    /// Address offered or assigned (yiaddr)
+    addr: Ipv4Addr,
+    server: Option<Ipv4Addr>,
+    prefix_len: Option<u8>,
+    router: Option<Ipv4Addr>,
+    dns: Option<Ipv4Addr>,
+    /// Lease length, T1 and T2 in seconds
+    lease: Option<u32>,
+    renew: Option<u32>,
+    rebind: Option<u32>,
+}
+
+/// Parse a reply to transaction `xid` of `mac`
+fn parse(message: &[u8], xid: u32, mac: &MacAddr) -> Option<Reply> {
+    if message.len() < FIXED_LEN
+        || message[0] != BOOTREPLY
+        || message[4..8] != xid.to_be_bytes()
+        || message[28..34] != mac[..]
+        || message[236..240] != MAGIC_COOKIE
+    {
+        return None;
+    }
+    let ipv4 = |data: &[u8]| data.get(..4).map(|bytes| Ipv4Addr([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    let seconds = |data: &[u8]| data.get(..4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    let mut reply = Reply {
+        kind: 0,
+        addr: ipv4(&message[16..20])?,
+        server: None,
+        prefix_len: None,
+        router: None,
+        dns: None,
+        lease: None,
+        renew: None,
+        rebind: None,
+    };
+
+    let mut options = &message[FIXED_LEN..];
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            OPT_PAD => {
+                options = rest;
+                continue;
+            }
+            OPT_END => break,
+            _ => {}
+        }
+        let (&len, rest) = rest.split_first()?;
+        let data = rest.get(..len as usize)?;
+        options = &rest[len as usize..];
+        match code {
+            OPT_MESSAGE_TYPE => reply.kind = *data.first()?,
+            // Only contiguous masks make a prefix
+            OPT_SUBNET_MASK => {
+                reply.prefix_len = ipv4(data)
+                    .map(|mask| mask.to_u32())
+                    .filter(|mask| mask.leading_ones() + mask.trailing_zeros() == 32)
+                    .map(|mask| mask.leading_ones() as u8)
+            }
+            OPT_ROUTER => reply.router = ipv4(data),
+            OPT_DNS => reply.dns = ipv4(data),
+            OPT_SERVER_ID => reply.server = ipv4(data),
+            OPT_LEASE_TIME => reply.lease = seconds(data),
+            OPT_RENEWAL_TIME => reply.renew = seconds(data),
+            OPT_REBINDING_TIME => reply.rebind = seconds(data),
+            _ => {}
+        }
+    }
+    (reply.kind != 0).then_some(reply)
+}
+
+/// Prefix length of `addr`'s address class, for a server that sends no
+/// subnet mask
+fn classful_prefix(addr: &Ipv4Addr) -> u8 {
+    match addr.0[0] {
+        0..=127 => 8,
+        128..=191 => 16,
+        _ => 24,
+    }
+}
+
+/// An address leased from a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lease {
+    config: Ipv4Config,
+    router: Option<Ipv4Addr>,
+    dns: Option<Ipv4Addr>,
+    server: Ipv4Addr,
+    /// Seconds the lease lasts
+    length: u32,
+    /// When to start renewing (T1), rebinding (T2), and giving up
+    renew_at: Instant,
+    rebind_at: Instant,
+    expires: Instant,
+}
+
+impl Lease {
+    /// Lease an ACK granted `since` (when its request went out)
+    ///
+    /// A lease without length is taken as infinite; T1 and T2 default to
+    /// half and seven eighths of it.
+    fn from_ack(ack: &Reply, since: Instant) -> Option<Self> {
+        let length = ack.lease.unwrap_or(u32::MAX);
+        let renew = ack.renew.unwrap_or(length / 2);
+        let rebind = ack.rebind.unwrap_or(length / 8 * 7).max(renew);
+        let at = |secs: u32| since.saturating_add(Duration::from_secs(secs as u64));
+        Some(Self {
+            config: Ipv4Config { addr: ack.addr, prefix_len: ack.prefix_len.unwrap_or_else(|| classful_prefix(&ack.addr)) },
+            router: ack.router,
+            dns: ack.dns,
+            server: ack.server?,
+            length,
+            renew_at: at(renew),
+            rebind_at: at(rebind),
+            expires: at(length),
+        })
+    }
+
+    /// Whether `other` configures the interface the same way
+    fn same_config(&self, other: &Lease) -> bool {
+        self.config == other.config && self.router == other.router && self.dns == other.dns
+    }
+}
+
+/// Whether the DHCP task should run: there is an interface and `ipv4=`
+/// does not configure it
+pub fn wanted() -> bool {
+    crate::cmdline::value("ipv4").is_none() && super::interface_for(&Ipv6Addr::UNSPECIFIED).is_some()
+}
+
+/// Sleep the current task for `duration`
+fn sleep(duration: Duration) {
+    let _ = crate::sched::wait::sleep(duration);
+}
+
+/// Sleep until `deadline`
+fn sleep_until(deadline: Instant) {
+    while !deadline.has_passed() {
+        // Long leases are slept in pieces so the wake time stays in range
+        sleep(deadline.saturating_duration_since(Instant::now()).min(MAX_TIMEOUT));
+    }
+}
+
+/// Send `message` to `server`, or broadcast it
+fn send(iface: &Interface, server: Option<Ipv4Addr>, message: &[u8]) -> Result<(), super::NetError> {
+    match server {
+        Some(server) => udp::send_to(CLIENT_PORT, SocketAddr::new(IpAddr::V4(server), SERVER_PORT), message),
+        None => udp::broadcast(iface, CLIENT_PORT, SERVER_PORT, message),
+    }
+}
+
+/// Send `message` and wait up to `timeout` for a reply to `xid` of one of
+/// the `kinds`
+fn exchange(
+    iface: &Interface,
+    server: Option<Ipv4Addr>,
+    message: &[u8],
+    xid: u32,
+    kinds: &[u8],
+    timeout: Duration,
+) -> Option<Reply> {
+    let poller = Poller::current()?;
+    let deadline = Instant::now().saturating_add(timeout);
+    let mut sent = false;
+    let mut buf = [0u8; MAX_MESSAGE];
+    while !deadline.has_passed() {
+        // An unresolved server gets an ARP request; send again shortly
+        if !sent {
+            sent = send(iface, server, message).is_ok();
+        }
+        while let Ok(Some((len, _))) = udp::recv_from(CLIENT_PORT, &mut buf) {
+            match parse(&buf[..len], xid, &iface.mac) {
+                Some(reply) if kinds.contains(&reply.kind) => return Some(reply),
+                _ => {}
+            }
+        }
+
+        let wait = deadline.saturating_duration_since(Instant::now());
+        let wait = if sent { wait } else { wait.min(RESOLVE_RETRY) };
+        if !poller.wait(core::iter::once(&udp::WAIT), wait, || udp::readable(CLIENT_PORT)) {
+            sleep(wait.min(RESOLVE_RETRY));
+        }
+    }
+    None
+}
+
+/// Discover a server and get a lease from it, trying until one answers
+fn acquire(iface: &Interface) -> Lease {
+    let mut timeout = INITIAL_TIMEOUT;
+    loop {
+        let xid = crate::rand::random_u64() as u32;
+        let mut discover = Message::new(DISCOVER, xid, &iface.mac, Ipv4Addr::UNSPECIFIED);
+        if let Some(offer) = exchange(iface, None, discover.finish(), xid, &[OFFER], timeout) {
+            if let Some(server) = offer.server {
+                let mut request = Message::new(REQUEST, xid, &iface.mac, Ipv4Addr::UNSPECIFIED);
+                request.option(OPT_REQUESTED_ADDR, &offer.addr.0);
+                request.option(OPT_SERVER_ID, &server.0);
+                let sent = Instant::now();
+                let reply = exchange(iface, None, request.finish(), xid, &[ACK, NAK], timeout);
+                if let Some(lease) = reply.filter(|reply| reply.kind == ACK).and_then(|ack| Lease::from_ack(&ack, sent)) {
+                    return lease;
+                }
+            }
+        }
+        timeout = timeout.saturating_add(timeout).min(MAX_TIMEOUT);
+    }
+}
+
+/// Extend `lease`: ask its server until T2, then any server until it
+/// expires
+///
+/// Returns the extended lease, or `None` after a NAK or once it expired.
+fn renew(iface: &Interface, lease: &Lease) -> Option<Lease> {
+    for (server, until) in [(Some(lease.server), lease.rebind_at), (None, lease.expires)] {
+        while !until.has_passed() {
+            // Half the time left, but not too often (RFC 2131 4.4.5)
+            let left = until.saturating_duration_since(Instant::now());
+            let wait = Duration::from_nanos(left.as_nanos() / 2).max(MIN_RENEW_RETRY).min(left);
+            let xid = crate::rand::random_u64() as u32;
+            let mut request = Message::new(REQUEST, xid, &iface.mac, lease.config.addr);
+            let sent = Instant::now();
+            match exchange(iface, server, request.finish(), xid, &[ACK, NAK], wait) {
+                Some(reply) if reply.kind == ACK => {
+                    // A server that leaves out its identifier on renewal
+                    // keeps the one we had
+                    let reply = Reply { server: reply.server.or(Some(lease.server)), ..reply };
+                    return Lease::from_ack(&reply, sent);
+                }
+                Some(_) => return None,
+                None => {}
+            }
+        }
+    }
+    None
+}
+
+/// Configure interface `index` from `lease`, or drop its address
+fn apply(index: usize, lease: Option<&Lease>) {
+    let result = super::configure_ipv4(index, lease.map(|lease| lease.config), lease.and_then(|lease| lease.router));
+    if let Err(e) = result {
+        crate::log_warn!("NET", "DHCP: configuring interface {} failed: {:?}", index, e);
+    }
+    // A server given with `dns=` wins
+    if crate::cmdline::value("dns").is_none() {
+        super::set_dns_server(lease.and_then(|lease| lease.dns).map(IpAddr::V4));
+    }
+}
+
+/// DHCP client task for the first interface
+pub fn dhcp_task() -> ! {
+    // The port stays bound for good, so a user socket cannot take it
+    while let Err(e) = udp::bind(CLIENT_PORT) {
+        crate::log_warn!("NET", "DHCP: port {}: {:?}", CLIENT_PORT, e);
+        sleep(MAX_TIMEOUT);
+    }
+    loop {
+        let Some(iface) = super::interface_for(&Ipv6Addr::UNSPECIFIED) else {
+            sleep(MAX_TIMEOUT);
+            continue;
+        };
+        let name = iface.device.name();
+        let mut lease = acquire(&iface);
+        apply(iface.index, Some(&lease));
+        loop {
+            match lease.dns {
+                Some(dns) => crate::log_info!(
+                    "NET",
+                    "{}: DHCP lease of {} from {} for {} s, DNS {}",
+                    name,
+                    lease.config.addr,
+                    lease.server,
+                    lease.length,
+                    dns
+                ),
+                None => crate::log_info!(
+                    "NET",
+                    "{}: DHCP lease of {} from {} for {} s",
+                    name,
+                    lease.config.addr,
+                    lease.server,
+                    lease.length
+                ),
+            }
+            sleep_until(lease.renew_at);
+            let Some(renewed) = renew(&iface, &lease) else { break };
+            if !renewed.same_config(&lease) {
+                apply(iface.index, Some(&renewed));
+            }
+            lease = renewed;
+        }
+        crate::log_warn!("NET", "{}: DHCP lease of {} lost", name, lease.config.addr);
+        apply(iface.index, None);
+    }
+}
+
+crate::kernel_test! {
+    /// A reply to our transaction parses with its options; one to another
+    /// transaction does not
+    fn net_dhcp_parse_ack() {
+        let mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        let mut ack = Message::new(ACK, 0x1234_5678, &mac, Ipv4Addr::UNSPECIFIED);
+        ack.buf[0] = BOOTREPLY;
+        ack.buf[16..20].copy_from_slice(&[10, 0, 2, 15]);
+        ack.option(OPT_SUBNET_MASK, &[255, 255, 255, 0]);
+        ack.option(OPT_ROUTER, &[10, 0, 2, 2]);
+        ack.option(OPT_DNS, &[10, 0, 2, 3, 10, 0, 2, 4]);
+        ack.option(OPT_SERVER_ID, &[10, 0, 2, 2]);
+        ack.option(OPT_LEASE_TIME, &86400u32.to_be_bytes());
+        let message = ack.finish();
+
+        crate::ktest_assert_eq!(parse(message, 0x1234_5679, &mac), None, "wrong transaction accepted");
+        let reply = parse(message, 0x1234_5678, &mac).ok_or("ACK not parsed")?;
+        crate::ktest_assert_eq!(reply.kind, ACK, "message type");
+        crate::ktest_assert_eq!(reply.addr, Ipv4Addr([10, 0, 2, 15]), "address");
+        crate::ktest_assert_eq!(reply.prefix_len, Some(24), "prefix length");
+        crate::ktest_assert_eq!(reply.dns, Some(Ipv4Addr([10, 0, 2, 3])), "first DNS server");
+
+        let since = Instant::from_ticks(0);
+        let lease = Lease::from_ack(&reply, since).ok_or("no lease")?;
+        crate::ktest_assert_eq!(lease.renew_at, since.saturating_add(Duration::from_secs(43200)), "T1");
+        crate::ktest_assert_eq!(lease.rebind_at, since.saturating_add(Duration::from_secs(75600)), "T2");
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+//! DNS stub resolver (RFC 1035)
+//!
+//! [`resolve`] looks a host name up in a small cache and otherwise asks the
+//! DNS server (`net::dns_server`, from DHCP or `dns=` on the command line)
+//! for its A record: one query over UDP from an ephemeral port, sent again
+//! after `RETRY_TIMEOUT` up to `TRIES` times. Recursion is left to the
+//! server. The first A record of the answer is taken, wherever a CNAME
+//! chain led, and cached for its TTL (at most `MAX_TTL`).
+//!
+//! Names are compared case-insensitively. An IPv4 literal resolves to
+//! itself without a query.
+
+use super::addr::{IpAddr, Ipv4Addr, SocketAddr};
+use super::{udp, NetError};
+use crate::sched::priority::TaskPriority;
+use crate::sync::Poller;
+use crate::time::{Duration, Instant};
+use spin::Mutex;
+
+/// Longest name, without the trailing dot
+pub const MAX_NAME: usize = 253;
+
+/// Longest label between dots
+const MAX_LABEL: usize = 63;
+
+const SERVER_PORT: u16 = 53;
+
+const HEADER_LEN: usize = 12;
+
+/// Longest message over UDP without EDNS
+const MAX_MESSAGE: usize = 512;
+
+/// Header flags: response, recursion desired, and the response code
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_MASK: u16 = 0x000f;
+const RCODE_NAME_ERROR: u16 = 3;
+
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+/// Wait for an answer before asking again, and how often to ask
+const RETRY_TIMEOUT: Duration = Duration::from_secs(2);
+const TRIES: usize = 3;
+
+/// Longest an answer is cached, whatever its TTL
+const MAX_TTL: Duration = Duration::from_secs(3600);
+
+/// Names cached
+const CACHE_SIZE: usize = 16;
+
+/// Time between retries while the server's link-layer address is resolved
+const RESOLVE_RETRY: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy)]
+struct Entry {
+    /// Lowercase name
+    name: [u8; MAX_NAME],
+    len: usize,
+    addr: Ipv4Addr,
+    expires: Instant,
+}
+
+impl Entry {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.len]
+    }
+}
+
+static CACHE: Mutex<[Option<Entry>; CACHE_SIZE]> = Mutex::new([None; CACHE_SIZE]);
+
+/// Cached address of `name`, unless it expired
+fn cached(name: &str) -> Option<Ipv4Addr> {
+    CACHE
+        .lock()
+        .iter()
+        .flatten()
+        .find(|entry| !entry.expires.has_passed() && entry.name().eq_ignore_ascii_case(name.as_bytes()))
+        .map(|entry| entry.addr)
+}
+
+/// Cache `addr` for `name` for `ttl`, replacing the entry of the same name,
+/// an expired one or the one closest to expiring
+fn insert(name: &str, addr: Ipv4Addr, ttl: Duration) {
+    let mut entry = Entry { name: [0; MAX_NAME], len: name.len(), addr, expires: Instant::now().saturating_add(ttl) };
+    entry.name[..name.len()].copy_from_slice(name.as_bytes());
+    entry.name.make_ascii_lowercase();
+
+    let mut cache = CACHE.lock();
+    let slot = cache
+        .iter()
+        .position(|slot| slot.map_or(true, |e| e.name() == entry.name() || e.expires.has_passed()))
+        .or_else(|| cache.iter().enumerate().min_by_key(|(_, e)| e.map(|e| e.expires)).map(|(i, _)| i));
+    if let Some(slot) = slot {
+        cache[slot] = Some(entry);
+    }
+}
+
+/// Write a query for the A record of `name` with identifier `id` into
+/// `message`; returns its length, or `None` for a name that is not valid
+fn encode_query(name: &str, id: u16, message: &mut [u8; MAX_MESSAGE]) -> Option<usize> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > MAX_NAME {
+        return None;
+    }
+    message[..HEADER_LEN].fill(0);
+    message[0..2].copy_from_slice(&id.to_be_bytes());
+    message[2..4].copy_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    message[4..6].copy_from_slice(&1u16.to_be_bytes());
+    let mut len = HEADER_LEN;
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > MAX_LABEL {
+            return None;
+        }
+        message[len] = label.len() as u8;
+        message[len + 1..len + 1 + label.len()].copy_from_slice(label.as_bytes());
+        len += 1 + label.len();
+    }
+    message[len] = 0;
+    message[len + 1..len + 3].copy_from_slice(&TYPE_A.to_be_bytes());
+    message[len + 3..len + 5].copy_from_slice(&CLASS_IN.to_be_bytes());
+    Some(len + 5)
+}
+
+/// Offset just past the (possibly compressed) name at `offset`
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(offset)? as usize;
+        match len {
+            0 => return Some(offset + 1),
+            // A pointer ends the name
+            _ if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            _ if len > MAX_LABEL => return None,
+            _ => offset += 1 + len,
+        }
+    }
+}
+
+/// Address and TTL of the first A record in the response `id` to a query
+fn parse_response(message: &[u8], id: u16) -> Result<(Ipv4Addr, Duration), NetError> {
+    let field = |offset: usize| message.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    let malformed = NetError::ServerFailure;
+    if message.len() < HEADER_LEN || field(0) != Some(id) {
+        return Err(malformed);
+    }
+    let flags = field(2).ok_or(malformed)?;
+    if flags & FLAG_RESPONSE == 0 {
+        return Err(malformed);
+    }
+    match flags & RCODE_MASK {
+        0 => {}
+        RCODE_NAME_ERROR => return Err(NetError::NameNotFound),
+        _ => return Err(NetError::ServerFailure),
+    }
+    let questions = field(4).ok_or(malformed)?;
+    let answers = field(6).ok_or(malformed)?;
+
+    let mut offset = HEADER_LEN;
+    for _ in 0..questions {
+        offset = skip_name(message, offset).ok_or(malformed)? + 4;
+    }
+    for _ in 0..answers {
+        offset = skip_name(message, offset).ok_or(malformed)?;
+        let record = message.get(offset..offset + 10).ok_or(malformed)?;
+        let kind = u16::from_be_bytes([record[0], record[1]]);
+        let class = u16::from_be_bytes([record[2], record[3]]);
+        let ttl = u32::from_be_bytes([record[4], record[5], record[6], record[7]]);
+        let len = u16::from_be_bytes([record[8], record[9]]) as usize;
+        let data = message.get(offset + 10..offset + 10 + len).ok_or(malformed)?;
+        if kind == TYPE_A && class == CLASS_IN && len == 4 {
+            let addr = Ipv4Addr([data[0], data[1], data[2], data[3]]);
+            return Ok((addr, Duration::from_secs(ttl as u64).min(MAX_TTL)));
+        }
+        offset += 10 + len;
+    }
+    // The name exists, but has no address
+    Err(NetError::NameNotFound)
+}
+
+/// Ask `server` for `name` from `port`, trying `TRIES` times
+fn query(server: IpAddr, port: u16, name: &str) -> Result<(Ipv4Addr, Duration), NetError> {
+    let poller = Poller::current().ok_or(NetError::Unsupported)?;
+    let id = crate::rand::random_u64() as u16;
+    let mut message = [0u8; MAX_MESSAGE];
+    let len = encode_query(name, id, &mut message).ok_or(NetError::NameNotFound)?;
+    let to = SocketAddr::new(server, SERVER_PORT);
+    let mut buf = [0u8; MAX_MESSAGE];
+
+    let mut result = Err(NetError::TimedOut);
+    for _ in 0..TRIES {
+        let deadline = Instant::now().saturating_add(RETRY_TIMEOUT);
+        let mut sent = false;
+        while !deadline.has_passed() {
+            if !sent {
+                match udp::send_to(port, to, &message[..len]) {
+                    Ok(()) => sent = true,
+                    // An ARP request went out; send again shortly
+                    Err(NetError::Unresolved) => result = Err(NetError::Unresolved),
+                    Err(e) => return Err(e),
+                }
+            }
+            while let Some((received, from)) = udp::recv_from(port, &mut buf)? {
+                // Answers to an earlier try, or from elsewhere, are ignored
+                if from == to && buf[..received].starts_with(&id.to_be_bytes()) {
+                    return parse_response(&buf[..received], id);
+                }
+            }
+
+            let wait = deadline.saturating_duration_since(Instant::now());
+            let wait = if sent { wait } else { wait.min(RESOLVE_RETRY) };
+            if !poller.wait(core::iter::once(&udp::WAIT), wait, || udp::readable(port)) {
+                // The wait queue is full: poll instead
+                crate::sched::sleep_current_task(wait.min(RESOLVE_RETRY), TaskPriority::Normal);
+                crate::sched::yield_now();
+            }
+        }
+        if sent {
+            result = Err(NetError::TimedOut);
+        }
+    }
+    result
+}
+
+/// IPv4 address of host `name`
+///
+/// Fails with `NameNotFound` for a name the server does not know (or that
+/// has no A record), `NoNameServer` if no server is configured, `TimedOut`
+/// when the server does not answer and `ServerFailure` when it fails. Must
+/// be called from a task.
+pub fn resolve(name: &str) -> Result<Ipv4Addr, NetError> {
+    if let Some(addr) = Ipv4Addr::parse(name) {
+        return Ok(addr);
+    }
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > MAX_NAME {
+        return Err(NetError::NameNotFound);
+    }
+    if let Some(addr) = cached(name) {
+        return Ok(addr);
+    }
+    let server = super::dns_server().ok_or(NetError::NoNameServer)?;
+    let port = udp::bind(0)?;
+    let result = query(server, port, name);
+    udp::unbind(port);
+
+    let (addr, ttl) = result?;
+    if !ttl.is_zero() {
+        insert(name, addr, ttl);
+    }
+    Ok(addr)
+}
+
+crate::kernel_test! {
+    /// A response behind a compressed CNAME yields its A record, which is
+    /// then cached; NXDOMAIN is an error
+    fn net_dns_response() {
+        let mut message = [0u8; MAX_MESSAGE];
+        let query_len = encode_query("www.Example.org.", 0xbeef, &mut message).ok_or("query not encoded")?;
+        crate::ktest_assert_eq!(&message[HEADER_LEN..HEADER_LEN + 4], b"\x03www", "first label");
+
+        // Response: the question, a CNAME to example.org (a pointer into
+        // the question) and its A record
+        let mut response = [0u8; MAX_MESSAGE];
+        response[..query_len].copy_from_slice(&message[..query_len]);
+        response[2..4].copy_from_slice(&(FLAG_RESPONSE | FLAG_RECURSION_DESIRED).to_be_bytes());
+        response[6..8].copy_from_slice(&2u16.to_be_bytes());
+        let records: [&[u8]; 2] = [
+            &[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 16],
+            &[0xc0, 16, 0, 1, 0, 1, 0, 0, 1, 0, 0, 4, 93, 184, 215, 14],
+        ];
+        let mut len = query_len;
+        for record in records {
+            response[len..len + record.len()].copy_from_slice(record);
+            len += record.len();
+        }
+        let answer = parse_response(&response[..len], 0xbeef);
+        crate::ktest_assert_eq!(
+            answer,
+            Ok((Ipv4Addr([93, 184, 215, 14]), Duration::from_secs(256))),
+            "A record behind the CNAME"
+        );
+        crate::ktest_assert_eq!(parse_response(&response[..len], 0xbeee), Err(NetError::ServerFailure), "wrong id");
+
+        response[3] |= RCODE_NAME_ERROR as u8;
+        crate::ktest_assert_eq!(parse_response(&response[..len], 0xbeef), Err(NetError::NameNotFound), "NXDOMAIN");
+
+        insert("www.example.test", Ipv4Addr([192, 0, 2, 7]), Duration::from_secs(60));
+        crate::ktest_assert_eq!(cached("WWW.example.test"), Some(Ipv4Addr([192, 0, 2, 7])), "cache lookup");
+        crate::ktest_assert_eq!(resolve("10.0.2.3"), Ok(Ipv4Addr([10, 0, 2, 3])), "literal");
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+//! Ethernet II framing
+
+use super::addr::MacAddr;
+
+/// Length of the Ethernet header
+pub const HEADER_LEN: usize = 14;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+/// Address of every station on the link
+pub const BROADCAST: MacAddr = [0xff; 6];
+
+/// Ethernet header fields
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub dst: MacAddr,
+    pub src: MacAddr,
+    pub ethertype: u16,
+}
+
+/// Split `frame` into its header and payload
+pub fn parse(frame: &[u8]) -> Option<(Header, &[u8])> {
+    if frame.len() < HEADER_LEN {
+        return None;
+    }
+    let mut dst = [0; 6];
+    let mut src = [0; 6];
+    dst.copy_from_slice(&frame[0..6]);
+    src.copy_from_slice(&frame[6..12]);
+    let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+    Some((Header { dst, src, ethertype }, &frame[HEADER_LEN..]))
+}
+
+/// Write `header` to the start of `frame`
+pub fn write_header(frame: &mut [u8], header: &Header) {
+    frame[0..6].copy_from_slice(&header.dst);
+    frame[6..12].copy_from_slice(&header.src);
+    frame[12..14].copy_from_slice(&header.ethertype.to_be_bytes());
+}
@@ -0,0 +1,58 @@
+//! ICMP for IPv4 (RFC 792)
+//!
+//! Answers echo requests, including to a broadcast address, and hands echo
+//! replies to `ping`. Other message types are ignored.
+
+use super::addr::IpAddr;
+use super::ipv4::{self, Header};
+use super::{ping, Checksum, Interface, NetError, MAX_FRAME};
+
+pub const ECHO_REPLY: u8 = 0;
+pub const ECHO_REQUEST: u8 = 8;
+
+/// Checksum of `message`; zero means valid for a received message
+pub fn checksum(message: &[u8]) -> u16 {
+    let mut sum = Checksum::new();
+    sum.add(message);
+    sum.finish()
+}
+
+/// Fill in the checksum of `message` and send it through the routing table
+pub fn send(header: &Header, message: &mut [u8]) -> Result<(), NetError> {
+    message[2..4].fill(0);
+    let sum = checksum(message);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    ipv4::send(header, message)
+}
+
+/// Handle a received ICMP message
+pub fn receive(iface: &Interface, header: &Header, message: &[u8]) {
+    if message.len() < 8 || checksum(message) != 0 {
+        return;
+    }
+    match message[0] {
+        ECHO_REQUEST => echo_reply(iface, header, message),
+        ECHO_REPLY => ping::receive_reply(IpAddr::V4(header.src), message),
+        _ => {}
+    }
+}
+
+/// Answer an echo request with the same identifier, sequence and data
+fn echo_reply(iface: &Interface, header: &Header, request: &[u8]) {
+    let Some(config) = iface.ipv4 else { return };
+    let mut reply = [0u8; MAX_FRAME];
+    let Some(reply) = reply.get_mut(..request.len()) else { return };
+    reply.copy_from_slice(request);
+    reply[0] = ECHO_REPLY;
+
+    // A request to a broadcast address is answered from our own address
+    let reply_header = Header {
+        src: config.addr,
+        dst: header.src,
+        protocol: ipv4::PROTO_ICMP,
+        ttl: ipv4::DEFAULT_TTL,
+    };
+    // An unresolved sender gets an ARP request; its retransmission is
+    // answered
+    let _ = send(&reply_header, reply);
+}
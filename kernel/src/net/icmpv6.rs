@@ -0,0 +1,62 @@
+//! ICMPv6 (RFC 4443)
+//!
+//! Answers echo requests, hands echo replies to `ping` and neighbor
+//! discovery messages to `ndp`. Other message types are ignored.
+
+use super::addr::{IpAddr, Ipv6Addr};
+use super::ipv6::{self, Header};
+use super::{ndp, ping, Interface, NetError, MAX_FRAME};
+
+pub const ECHO_REQUEST: u8 = 128;
+pub const ECHO_REPLY: u8 = 129;
+pub const NEIGHBOR_SOLICITATION: u8 = 135;
+pub const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// Checksum of `message` sent from `src` to `dst`; zero means valid for a
+/// received message
+pub fn checksum(src: &Ipv6Addr, dst: &Ipv6Addr, message: &[u8]) -> u16 {
+    let mut sum = ipv6::pseudo_header(src, dst, message.len(), ipv6::NEXT_ICMPV6);
+    sum.add(message);
+    sum.finish()
+}
+
+/// Fill in the checksum of `message` and send it
+pub fn send(iface: &Interface, header: &Header, message: &mut [u8]) -> Result<(), NetError> {
+    message[2..4].fill(0);
+    let sum = checksum(&header.src, &header.dst, message);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    ipv6::send(iface, header, message)
+}
+
+/// Handle a received ICMPv6 message
+pub fn receive(iface: &Interface, header: &Header, message: &[u8]) {
+    if message.len() < 4 || checksum(&header.src, &header.dst, message) != 0 {
+        return;
+    }
+    match message[0] {
+        ECHO_REQUEST => echo_reply(iface, header, message),
+        ECHO_REPLY => ping::receive_reply(IpAddr::V6(header.src), message),
+        NEIGHBOR_SOLICITATION => ndp::receive_solicitation(iface, header, message),
+        NEIGHBOR_ADVERTISEMENT => ndp::receive_advertisement(iface, header, message),
+        _ => {}
+    }
+}
+
+/// Answer an echo request with the same identifier, sequence and data
+fn echo_reply(iface: &Interface, header: &Header, request: &[u8]) {
+    let mut reply = [0u8; MAX_FRAME];
+    let Some(reply) = reply.get_mut(..request.len()) else { return };
+    reply.copy_from_slice(request);
+    reply[0] = ECHO_REPLY;
+
+    // A request to a multicast group is answered from our own address
+    let src = if header.dst.is_multicast() { iface.link_local } else { header.dst };
+    let reply_header = Header {
+        src,
+        dst: header.src,
+        next_header: ipv6::NEXT_ICMPV6,
+        hop_limit: ipv6::DEFAULT_HOP_LIMIT,
+    };
+    // An unresolved sender gets solicited; its retransmission is answered
+    let _ = send(iface, &reply_header, reply);
+}
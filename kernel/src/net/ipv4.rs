@@ -0,0 +1,199 @@
+//! IPv4 (RFC 791)
+//!
+//! Options are skipped on receive and never sent. Fragments are dropped
+//! rather than reassembled, and outgoing packets set Don't Fragment, so a
+//! datagram has to fit the interface MTU. Packets are sent through the
+//! interface and next hop the routing table picks (`route`); the next hop
+//! is resolved with ARP.
+
+use super::addr::{IpAddr, Ipv4Addr, MacAddr};
+use super::{arp, ethernet, icmp, route, tcp, udp, Checksum, Interface, NetError, MAX_FRAME};
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// Length of a header without options
+pub const HEADER_LEN: usize = 20;
+
+pub const PROTO_ICMP: u8 = 1;
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+
+/// Time to live of ordinary outgoing packets
+pub const DEFAULT_TTL: u8 = 64;
+
+/// Flags and fragment offset field
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1fff;
+
+/// Identification of the next outgoing packet
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+/// Header fields the stack uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+}
+
+/// Split `packet` into its header and payload
+///
+/// Packets with a bad header checksum and fragments are rejected.
+pub fn parse(packet: &[u8]) -> Option<(Header, &[u8])> {
+    if packet.len() < HEADER_LEN || packet[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = (packet[0] & 0x0f) as usize * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_len < HEADER_LEN || total_len < header_len || total_len > packet.len() {
+        return None;
+    }
+    let mut sum = Checksum::new();
+    sum.add(&packet[..header_len]);
+    if sum.finish() != 0 {
+        return None;
+    }
+    let fragment = u16::from_be_bytes([packet[6], packet[7]]);
+    if fragment & (FLAG_MORE_FRAGMENTS | FRAGMENT_OFFSET) != 0 {
+        return None;
+    }
+    let mut src = [0; 4];
+    let mut dst = [0; 4];
+    src.copy_from_slice(&packet[12..16]);
+    dst.copy_from_slice(&packet[16..20]);
+    let header = Header {
+        src: Ipv4Addr(src),
+        dst: Ipv4Addr(dst),
+        protocol: packet[9],
+        ttl: packet[8],
+    };
+    Some((header, &packet[header_len..total_len]))
+}
+
+/// Checksum seeded with the upper-layer pseudo-header (RFC 768)
+pub fn pseudo_header(src: &Ipv4Addr, dst: &Ipv4Addr, len: usize, protocol: u8) -> Checksum {
+    let mut sum = Checksum::new();
+    sum.add(&src.0);
+    sum.add(&dst.0);
+    sum.add(&[0, protocol]);
+    sum.add(&(len as u16).to_be_bytes());
+    sum
+}
+
+/// Fill in the first `HEADER_LEN` bytes of `packet` for `payload_len`
+/// bytes of payload, checksum included
+fn write_header(packet: &mut [u8], header: &Header, id: u16, payload_len: usize) {
+    let packet = &mut packet[..HEADER_LEN];
+    packet.fill(0);
+    packet[0] = 4 << 4 | (HEADER_LEN / 4) as u8;
+    packet[2..4].copy_from_slice(&((HEADER_LEN + payload_len) as u16).to_be_bytes());
+    packet[4..6].copy_from_slice(&id.to_be_bytes());
+    packet[6..8].copy_from_slice(&FLAG_DONT_FRAGMENT.to_be_bytes());
+    packet[8] = header.ttl;
+    packet[9] = header.protocol;
+    packet[12..16].copy_from_slice(&header.src.0);
+    packet[16..20].copy_from_slice(&header.dst.0);
+    let mut sum = Checksum::new();
+    sum.add(packet);
+    packet[10..12].copy_from_slice(&sum.finish().to_be_bytes());
+}
+
+/// Ethernet address of a multicast group (RFC 1112 6.4)
+const fn multicast_mac(addr: &Ipv4Addr) -> MacAddr {
+    [0x01, 0x00, 0x5e, addr.0[1] & 0x7f, addr.0[2], addr.0[3]]
+}
+
+/// Send `payload` to `header.dst` through the routing table
+///
+/// An unspecified `header.src` is replaced with the address of the
+/// outgoing interface.
+pub fn send(header: &Header, payload: &[u8]) -> Result<(), NetError> {
+    let (index, next_hop) = route::lookup(&header.dst).ok_or(NetError::NoRoute)?;
+    let iface = super::interface(index).ok_or(NetError::NoInterface)?;
+    send_via(&iface, &next_hop, header, payload)
+}
+
+/// Send `payload` to `header.dst` on `iface` through `next_hop`
+///
+/// Broadcast and multicast destinations need no resolution. A unicast next
+/// hop must be in the ARP cache; otherwise a request goes out instead and
+/// `NetError::Unresolved` is returned so the caller can retry.
+pub fn send_via(iface: &Interface, next_hop: &Ipv4Addr, header: &Header, payload: &[u8]) -> Result<(), NetError> {
+    let len = ethernet::HEADER_LEN + HEADER_LEN + payload.len();
+    if len > MAX_FRAME || HEADER_LEN + payload.len() > iface.device.mtu() {
+        return Err(NetError::TooLarge);
+    }
+    let mut header = *header;
+    if header.src.is_unspecified() {
+        if let Some(config) = iface.ipv4 {
+            header.src = config.addr;
+        }
+    }
+
+    let directed_broadcast = iface.ipv4.map_or(false, |config| config.broadcast() == header.dst);
+    let dst_mac = if header.dst.is_broadcast() || directed_broadcast {
+        ethernet::BROADCAST
+    } else if header.dst.is_multicast() {
+        multicast_mac(&header.dst)
+    } else {
+        arp::resolve(iface, next_hop)?
+    };
+
+    let mut frame = [0u8; MAX_FRAME];
+    ethernet::write_header(
+        &mut frame,
+        &ethernet::Header { dst: dst_mac, src: iface.mac, ethertype: ethernet::ETHERTYPE_IPV4 },
+    );
+    let packet = &mut frame[ethernet::HEADER_LEN..len];
+    write_header(packet, &header, NEXT_ID.fetch_add(1, Ordering::Relaxed), payload.len());
+    packet[HEADER_LEN..].copy_from_slice(payload);
+
+    iface.device.transmit(&frame[..len]).map_err(|_| NetError::Device)?;
+    super::softnet::count_tx(iface.index);
+    Ok(())
+}
+
+/// Handle a received IPv4 packet
+pub fn receive(iface: &Interface, packet: &[u8]) {
+    let Some((header, payload)) = parse(packet) else { return };
+    if !iface.accepts_v4(&header.dst) {
+        return;
+    }
+    match header.protocol {
+        PROTO_ICMP => icmp::receive(iface, &header, payload),
+        PROTO_TCP => tcp::receive(IpAddr::V4(header.src), IpAddr::V4(header.dst), payload),
+        PROTO_UDP => udp::receive(IpAddr::V4(header.src), IpAddr::V4(header.dst), payload),
+        _ => {}
+    }
+}
+
+crate::kernel_test! {
+    /// A written header parses back; a corrupted one or a fragment does not
+    fn net_ipv4_header() {
+        let header = Header {
+            src: Ipv4Addr([10, 0, 2, 15]),
+            dst: Ipv4Addr([10, 0, 2, 2]),
+            protocol: PROTO_UDP,
+            ttl: DEFAULT_TTL,
+        };
+        let mut packet = [0u8; HEADER_LEN + 4];
+        write_header(&mut packet, &header, 0x1234, 4);
+        packet[HEADER_LEN..].copy_from_slice(b"ping");
+        crate::ktest_assert_eq!(parse(&packet), Some((header, &b"ping"[..])), "round trip");
+        crate::ktest_assert_eq!(parse(&packet[..HEADER_LEN + 3]), None, "truncated packet accepted");
+
+        let mut corrupt = packet;
+        corrupt[8] -= 1;
+        crate::ktest_assert_eq!(parse(&corrupt), None, "bad checksum accepted");
+
+        let mut fragment = packet;
+        fragment[6..8].copy_from_slice(&FLAG_MORE_FRAGMENTS.to_be_bytes());
+        let mut sum = Checksum::new();
+        fragment[10..12].fill(0);
+        sum.add(&fragment[..HEADER_LEN]);
+        fragment[10..12].copy_from_slice(&sum.finish().to_be_bytes());
+        crate::ktest_assert_eq!(parse(&fragment), None, "fragment accepted");
+        Ok(())
+    }
+}
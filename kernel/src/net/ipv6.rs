@@ -0,0 +1,113 @@
+//! IPv6 (RFC 8200)
+//!
+//! Extension headers are not supported: packets carrying them are dropped.
+//! Every interface only has its link-local address, so there is no routing
+//! either; packets leave through the interface they are sent from.
+
+use super::addr::{IpAddr, Ipv6Addr};
+use super::{ethernet, icmpv6, ndp, tcp, udp, Checksum, Interface, NetError, MAX_FRAME};
+
+/// Length of the fixed IPv6 header
+pub const HEADER_LEN: usize = 40;
+
+pub const NEXT_TCP: u8 = 6;
+pub const NEXT_UDP: u8 = 17;
+pub const NEXT_ICMPV6: u8 = 58;
+
+/// Hop limit of ordinary outgoing packets
+pub const DEFAULT_HOP_LIMIT: u8 = 64;
+
+/// Fixed header fields
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub src: Ipv6Addr,
+    pub dst: Ipv6Addr,
+    pub next_header: u8,
+    pub hop_limit: u8,
+}
+
+/// Split `packet` into its header and payload
+pub fn parse(packet: &[u8]) -> Option<(Header, &[u8])> {
+    if packet.len() < HEADER_LEN || packet[0] >> 4 != 6 {
+        return None;
+    }
+    let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+    let payload = packet[HEADER_LEN..].get(..payload_len)?;
+    let mut src = [0; 16];
+    let mut dst = [0; 16];
+    src.copy_from_slice(&packet[8..24]);
+    dst.copy_from_slice(&packet[24..40]);
+    let header = Header {
+        src: Ipv6Addr(src),
+        dst: Ipv6Addr(dst),
+        next_header: packet[6],
+        hop_limit: packet[7],
+    };
+    Some((header, payload))
+}
+
+/// Checksum seeded with the upper-layer pseudo-header (RFC 8200 8.1)
+pub fn pseudo_header(src: &Ipv6Addr, dst: &Ipv6Addr, len: usize, next_header: u8) -> Checksum {
+    let mut sum = Checksum::new();
+    sum.add(&src.0);
+    sum.add(&dst.0);
+    sum.add(&(len as u32).to_be_bytes());
+    sum.add(&[0, 0, 0, next_header]);
+    sum
+}
+
+/// Send `payload` from `header.src` to `header.dst` on `iface`
+///
+/// Unicast destinations must be in the neighbor cache; otherwise a neighbor
+/// solicitation goes out instead and `NetError::Unresolved` is returned so
+/// the caller can retry.
+pub fn send(iface: &Interface, header: &Header, payload: &[u8]) -> Result<(), NetError> {
+    let len = ethernet::HEADER_LEN + HEADER_LEN + payload.len();
+    if len > MAX_FRAME || HEADER_LEN + payload.len() > iface.device.mtu() {
+        return Err(NetError::TooLarge);
+    }
+
+    let dst_mac = if header.dst.is_multicast() {
+        header.dst.multicast_mac()
+    } else {
+        match ndp::lookup(&header.dst) {
+            Some(mac) => mac,
+            None => {
+                ndp::solicit(iface, &header.dst)?;
+                return Err(NetError::Unresolved);
+            }
+        }
+    };
+
+    let mut frame = [0u8; MAX_FRAME];
+    ethernet::write_header(
+        &mut frame,
+        &ethernet::Header { dst: dst_mac, src: iface.mac, ethertype: ethernet::ETHERTYPE_IPV6 },
+    );
+    let packet = &mut frame[ethernet::HEADER_LEN..len];
+    packet[0] = 6 << 4;
+    packet[4..6].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+    packet[6] = header.next_header;
+    packet[7] = header.hop_limit;
+    packet[8..24].copy_from_slice(&header.src.0);
+    packet[24..40].copy_from_slice(&header.dst.0);
+    packet[HEADER_LEN..].copy_from_slice(payload);
+
+    iface.device.transmit(&frame[..len]).map_err(|_| NetError::Device)?;
+    super::softnet::count_tx(iface.index);
+    Ok(())
+}
+
+/// Handle a received IPv6 packet
+pub fn receive(iface: &Interface, packet: &[u8]) {
+    let Some((header, payload)) = parse(packet) else { return };
+    if !iface.owns(&header.dst) && !iface.joined(&header.dst) {
+        return;
+    }
+    match header.next_header {
+        NEXT_ICMPV6 => icmpv6::receive(iface, &header, payload),
+        NEXT_TCP => tcp::receive(IpAddr::V6(header.src), IpAddr::V6(header.dst), payload),
+        NEXT_UDP => udp::receive(IpAddr::V6(header.src), IpAddr::V6(header.dst), payload),
+        _ => {}
+    }
+}
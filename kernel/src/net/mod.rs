@@ -0,0 +1,374 @@
+//! Network stack
+//!
+//! Interfaces sit on top of the `NetDevice`s drivers register through the
+//! driver API. At boot every device becomes an interface with an IPv6
+//! link-local address (SLAAC); an IPv4 address is configured separately
+//! (`ipv4=` on the command line, or DHCP, for the first interface). The per-CPU
+//! softnet tasks take received frames from the devices and hand them up
+//! the stack:
+//!
+//! - `softnet`: receive processing, interrupt/polling mode, packet rates
+//! - `ethernet`: frame headers
+//! - `arp`: IPv4 address resolution and the ARP cache
+//! - `ipv4`: IPv4 header, send and receive paths
+//! - `route`: the IPv4 routing table
+//! - `icmp`: echo over IPv4
+//! - `ipv6`: IPv6 header, send and receive paths
+//! - `ndp`: neighbor discovery and the neighbor cache
+//! - `icmpv6`: echo and the NDP messages
+//! - `ping`: echo requests sent from the kernel, and their replies
+//! - `udp`: datagrams and bound endpoints, over either IP version
+//! - `dhcp`: IPv4 configuration of the first interface from a DHCP server
+//! - `dns`: host name lookups, with a cache
+//! - `tcp`: connections and listeners, over either IP version
+//! - `socket`: the datagram and stream sockets behind `SYS_SOCKET` handles
+//!
+//! Addresses are `addr::IpAddr` / `addr::SocketAddr` everywhere above the
+//! IP layer.
+
+#![allow(dead_code)]
+
+pub mod addr;
+pub mod arp;
+pub mod dhcp;
+pub mod dns;
+pub mod ethernet;
+pub mod icmp;
+pub mod icmpv6;
+pub mod ipv4;
+pub mod ipv6;
+pub mod ndp;
+pub mod ping;
+pub mod route;
+pub mod socket;
+pub mod softnet;
+pub mod tcp;
+pub mod udp;
+
+use crate::dev::api::net::NetDevice;
+use addr::{IpAddr, Ipv4Addr, Ipv6Addr, MacAddr};
+use spin::Mutex;
+
+/// Maximum number of interfaces (one per network device slot)
+pub const MAX_INTERFACES: usize = crate::dev::api::net::MAX_NET_DEVICES;
+
+/// Largest Ethernet frame handled (1500 byte MTU plus header)
+pub const MAX_FRAME: usize = 1514;
+
+/// Network stack errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    /// Packet does not fit the interface MTU
+    TooLarge,
+    /// Address family or protocol not implemented
+    Unsupported,
+    /// No interface to send from
+    NoInterface,
+    /// The destination's link-layer address is not known yet; a neighbor
+    /// solicitation or ARP request has been sent
+    Unresolved,
+    /// No route to the IPv4 destination
+    NoRoute,
+    /// Routing table is full
+    TooManyRoutes,
+    /// No answer before the timeout
+    TimedOut,
+    /// Port already bound
+    AddressInUse,
+    /// Endpoint table is full
+    TooManyEndpoints,
+    /// Not a bound endpoint
+    NotBound,
+    /// Socket already bound to a port
+    AlreadyBound,
+    /// The peer refused the connection
+    ConnectionRefused,
+    /// The peer reset the connection
+    ConnectionReset,
+    /// Stream socket without a connection
+    NotConnected,
+    /// Stream socket already connected
+    AlreadyConnected,
+    /// Connection started; the handshake completes later
+    InProgress,
+    /// A connection attempt is already under way
+    Already,
+    /// Datagram socket used without a destination address
+    DestinationRequired,
+    /// Operation only stream sockets support
+    WrongType,
+    /// Operation not valid in the socket's state, such as accepting on a
+    /// socket that is not listening
+    InvalidState,
+    /// The DNS server knows no address for the name
+    NameNotFound,
+    /// No DNS server is configured
+    NoNameServer,
+    /// The DNS server failed to answer the query
+    ServerFailure,
+    /// The device refused the frame
+    Device,
+}
+
+/// IPv4 address of an interface and the length of its network prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Config {
+    pub addr: Ipv4Addr,
+    pub prefix_len: u8,
+}
+
+impl Ipv4Config {
+    /// Directed broadcast address of the network
+    pub const fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(self.addr.to_u32() | !addr::prefix_mask(self.prefix_len))
+    }
+
+    /// Parse `<addr>/<prefix_len>`
+    pub fn parse(text: &str) -> Option<Self> {
+        let (addr, prefix_len) = text.split_once('/')?;
+        let prefix_len = prefix_len.parse().ok().filter(|&len| len <= 32)?;
+        Some(Self { addr: Ipv4Addr::parse(addr)?, prefix_len })
+    }
+}
+
+/// A configured network interface
+#[derive(Clone, Copy)]
+pub struct Interface {
+    /// Interface number, the device's slot in the driver API
+    pub index: usize,
+    pub device: &'static dyn NetDevice,
+    pub mac: MacAddr,
+    /// SLAAC link-local address
+    pub link_local: Ipv6Addr,
+    /// IPv4 address, once configured
+    pub ipv4: Option<Ipv4Config>,
+}
+
+impl Interface {
+    /// Whether `addr` is one of the interface's own addresses
+    pub fn owns(&self, addr: &Ipv6Addr) -> bool {
+        *addr == self.link_local
+    }
+
+    /// Whether `addr` is the interface's IPv4 address
+    pub fn owns_v4(&self, addr: &Ipv4Addr) -> bool {
+        self.ipv4.map_or(false, |config| config.addr == *addr)
+    }
+
+    /// Whether IPv4 packets to `addr` are for this interface: its own
+    /// address or a broadcast
+    pub fn accepts_v4(&self, addr: &Ipv4Addr) -> bool {
+        self.owns_v4(addr) || addr.is_broadcast() || self.ipv4.map_or(false, |config| config.broadcast() == *addr)
+    }
+
+    /// Whether the interface listens on multicast group `addr`
+    pub fn joined(&self, addr: &Ipv6Addr) -> bool {
+        *addr == Ipv6Addr::ALL_NODES || *addr == self.link_local.solicited_node()
+    }
+}
+
+static INTERFACES: Mutex<[Option<Interface>; MAX_INTERFACES]> = Mutex::new([None; MAX_INTERFACES]);
+
+/// DNS server to ask, learned from DHCP or set with `dns=`
+static DNS_SERVER: Mutex<Option<IpAddr>> = Mutex::new(None);
+
+/// Interface number `index`
+pub fn interface(index: usize) -> Option<Interface> {
+    INTERFACES.lock().get(index).copied().flatten()
+}
+
+/// First interface owning `addr`, or the first interface at all for the
+/// unspecified address
+pub fn interface_for(addr: &Ipv6Addr) -> Option<Interface> {
+    let interfaces = INTERFACES.lock();
+    let mut all = interfaces.iter().flatten();
+    if addr.is_unspecified() {
+        return all.next().copied();
+    }
+    all.find(|iface| iface.owns(addr)).copied()
+}
+
+/// Give interface `index` an IPv4 address (or take it away with `None`)
+///
+/// Replaces the interface's routes with one to its network and, with a
+/// `gateway`, a default route through it.
+pub fn configure_ipv4(index: usize, config: Option<Ipv4Config>, gateway: Option<Ipv4Addr>) -> Result<(), NetError> {
+    let iface = {
+        let mut interfaces = INTERFACES.lock();
+        let iface = interfaces.get_mut(index).and_then(Option::as_mut).ok_or(NetError::NoInterface)?;
+        iface.ipv4 = config;
+        *iface
+    };
+    route::remove_interface(index);
+    let Some(config) = config else { return Ok(()) };
+    let network = Ipv4Addr::from_u32(config.addr.to_u32() & addr::prefix_mask(config.prefix_len));
+    route::add(route::Route { dest: network, prefix_len: config.prefix_len, gateway: None, iface: index })?;
+    if let Some(gateway) = gateway {
+        route::add(route::Route { dest: Ipv4Addr::UNSPECIFIED, prefix_len: 0, gateway: Some(gateway), iface: index })?;
+    }
+
+    match gateway {
+        Some(gateway) => crate::log_info!(
+            "NET",
+            "{}: {}/{} via {}",
+            iface.device.name(),
+            config.addr,
+            config.prefix_len,
+            gateway
+        ),
+        None => crate::log_info!("NET", "{}: {}/{}", iface.device.name(), config.addr, config.prefix_len),
+    }
+    let _ = arp::announce(&iface);
+    Ok(())
+}
+
+/// DNS server to ask, if one is known
+pub fn dns_server() -> Option<IpAddr> {
+    *DNS_SERVER.lock()
+}
+
+/// Set (or forget) the DNS server
+pub fn set_dns_server(server: Option<IpAddr>) {
+    *DNS_SERVER.lock() = server;
+}
+
+/// Address to send from to `dst`: the IPv4 address of the interface the
+/// routing table picks, or the link-local address of the first interface
+pub fn source_for(dst: &IpAddr) -> Result<IpAddr, NetError> {
+    match dst {
+        IpAddr::V4(dst) => {
+            let (index, _) = route::lookup(dst).ok_or(NetError::NoRoute)?;
+            let iface = interface(index).ok_or(NetError::NoInterface)?;
+            Ok(IpAddr::V4(iface.ipv4.ok_or(NetError::NoRoute)?.addr))
+        }
+        IpAddr::V6(_) => {
+            let iface = interface_for(&Ipv6Addr::UNSPECIFIED).ok_or(NetError::NoInterface)?;
+            Ok(IpAddr::V6(iface.link_local))
+        }
+    }
+}
+
+/// Checksum seeded with the pseudo-header of a transport `protocol`
+/// segment of `len` bytes between `src` and `dst`, of the same IP version
+pub fn pseudo_header(src: &IpAddr, dst: &IpAddr, len: usize, protocol: u8) -> Checksum {
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => ipv4::pseudo_header(src, dst, len, protocol),
+        (IpAddr::V6(src), IpAddr::V6(dst)) => ipv6::pseudo_header(src, dst, len, protocol),
+        _ => unreachable!("pseudo-header across IP versions"),
+    }
+}
+
+/// Send a transport `protocol` segment from `src` (one of our addresses)
+/// to `dst`, of the same IP version
+pub fn send_ip(src: &IpAddr, dst: &IpAddr, protocol: u8, payload: &[u8]) -> Result<(), NetError> {
+    match (*src, *dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let header = ipv4::Header { src, dst, protocol, ttl: ipv4::DEFAULT_TTL };
+            ipv4::send(&header, payload)
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            let iface = interface_for(&src).ok_or(NetError::NoInterface)?;
+            let header = ipv6::Header { src, dst, next_header: protocol, hop_limit: ipv6::DEFAULT_HOP_LIMIT };
+            ipv6::send(&iface, &header, payload)
+        }
+        _ => Err(NetError::Unsupported),
+    }
+}
+
+/// Internet checksum accumulator (RFC 1071)
+#[derive(Clone, Copy, Default)]
+pub struct Checksum(u32);
+
+impl Checksum {
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Add `data` (an odd trailing byte is padded with zero)
+    pub fn add(&mut self, data: &[u8]) {
+        let mut chunks = data.chunks_exact(2);
+        for pair in &mut chunks {
+            self.0 += u16::from_be_bytes([pair[0], pair[1]]) as u32;
+        }
+        if let [last] = chunks.remainder() {
+            self.0 += (*last as u32) << 8;
+        }
+        self.0 = (self.0 & 0xffff) + (self.0 >> 16);
+    }
+
+    /// One's complement of the folded sum
+    pub fn finish(self) -> u16 {
+        let mut sum = self.0;
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+}
+
+/// Configure an interface for every registered network device
+///
+/// Returns the number of interfaces.
+pub fn init() -> usize {
+    crate::mm::shrink::register(&ndp::SHRINKER);
+    crate::mm::shrink::register(&arp::SHRINKER);
+    let mut count = 0;
+    for (index, device) in crate::dev::api::net::net_devices().into_iter().enumerate() {
+        let Some(device) = device else { continue };
+        let mac = device.mac_address();
+        let iface = Interface { index, device, mac, link_local: Ipv6Addr::link_local(mac), ipv4: None };
+        INTERFACES.lock()[index] = Some(iface);
+        softnet::attach(index, device.set_rx_interrupt(true));
+        count += 1;
+
+        crate::log_info!("NET", "{}: link-local {}", device.name(), iface.link_local);
+        if ndp::probe(&iface).is_err() {
+            crate::log_warn!("NET", "{}: duplicate address probe not sent", device.name());
+        }
+    }
+    configure_from_cmdline();
+    if let Some(option) = crate::cmdline::value("dns") {
+        match Ipv4Addr::parse(option) {
+            Some(server) => set_dns_server(Some(IpAddr::V4(server))),
+            None => crate::log_warn!("NET", "dns={}: expected an IPv4 address", option),
+        }
+    }
+    count
+}
+
+/// Configure the first interface from `ipv4=<addr>/<prefix_len>[,<gateway>]`
+fn configure_from_cmdline() {
+    let Some(option) = crate::cmdline::value("ipv4") else { return };
+    let (config, gateway) = match option.split_once(',') {
+        Some((config, gateway)) => (config, Some(gateway)),
+        None => (option, None),
+    };
+    let config = Ipv4Config::parse(config);
+    let gateway = match gateway {
+        Some(gateway) => Ipv4Addr::parse(gateway).map(Some),
+        None => Some(None),
+    };
+    let (Some(config), Some(gateway)) = (config, gateway) else {
+        crate::log_warn!("NET", "ipv4={}: expected <addr>/<prefix_len>[,<gateway>]", option);
+        return;
+    };
+    let Some(index) = INTERFACES.lock().iter().flatten().next().map(|iface| iface.index) else { return };
+    if let Err(e) = configure_ipv4(index, Some(config), gateway) {
+        crate::log_warn!("NET", "ipv4={}: {:?}", option, e);
+    }
+}
+
+/// Hand one received frame to the stack
+fn receive(iface: &Interface, frame: &[u8]) {
+    let Some((header, payload)) = ethernet::parse(frame) else { return };
+    if header.dst != iface.mac && header.dst[0] & 1 == 0 {
+        return;
+    }
+    match header.ethertype {
+        ethernet::ETHERTYPE_IPV6 => ipv6::receive(iface, payload),
+        ethernet::ETHERTYPE_IPV4 => ipv4::receive(iface, payload),
+        ethernet::ETHERTYPE_ARP => arp::receive(iface, payload),
+        _ => {}
+    }
+}
+
@@ -0,0 +1,253 @@
+//! Neighbor discovery (RFC 4861)
+//!
+//! Resolves on-link IPv6 addresses to MAC addresses. Entries are learned
+//! from the source link-layer option of solicitations and the target
+//! link-layer option of advertisements, and are replaced round-robin once
+//! the cache is full. There is no reachability tracking: an entry stays
+//! until it is replaced, or dropped under memory pressure (`mm::shrink`),
+//! after which the address is solicited again.
+//!
+//! Duplicate address detection is optimistic: the link-local address is
+//! used straight away, and a probe is sent at boot so a conflicting node
+//! shows up in the log.
+
+use super::addr::{Ipv6Addr, MacAddr};
+use super::icmpv6::{self, NEIGHBOR_ADVERTISEMENT, NEIGHBOR_SOLICITATION};
+use super::ipv6::{self, Header};
+use super::{Interface, NetError};
+use spin::Mutex;
+
+/// Number of neighbor cache entries
+const CACHE_SIZE: usize = 16;
+
+/// NDP messages must arrive with this hop limit (i.e. from on-link)
+const NDP_HOP_LIMIT: u8 = 255;
+
+/// Type, code, checksum, flags/reserved and target address
+const MESSAGE_LEN: usize = 24;
+
+/// Link-layer address option types
+const OPT_SOURCE_LLADDR: u8 = 1;
+const OPT_TARGET_LLADDR: u8 = 2;
+
+/// An Ethernet link-layer address option is one 8-byte unit
+const LLADDR_OPT_LEN: usize = 8;
+
+/// Advertisement flags
+const FLAG_SOLICITED: u8 = 0x40;
+const FLAG_OVERRIDE: u8 = 0x20;
+
+struct NeighborCache {
+    entries: [Option<(Ipv6Addr, MacAddr)>; CACHE_SIZE],
+    /// Entry to replace next when the cache is full
+    victim: usize,
+}
+
+static CACHE: Mutex<NeighborCache> = Mutex::new(NeighborCache {
+    entries: [None; CACHE_SIZE],
+    victim: 0,
+});
+
+/// Drops neighbor cache entries under memory pressure; they are cheap to
+/// learn again
+pub static SHRINKER: crate::mm::shrink::Shrinker = crate::mm::shrink::Shrinker::new("ndp-neighbors", 0, count, shrink);
+
+/// Number of cached neighbors
+fn count() -> usize {
+    CACHE.lock().entries.iter().flatten().count()
+}
+
+/// Drop up to `target` entries, oldest first; returns how many were dropped
+fn shrink(target: usize) -> usize {
+    let mut cache = CACHE.lock();
+    let mut dropped = 0;
+    for i in 0..CACHE_SIZE {
+        if dropped == target {
+            break;
+        }
+        let index = (cache.victim + i) % CACHE_SIZE;
+        if cache.entries[index].take().is_some() {
+            dropped += 1;
+        }
+    }
+    dropped
+}
+
+/// Link-layer address of `addr`, if known
+pub fn lookup(addr: &Ipv6Addr) -> Option<MacAddr> {
+    CACHE
+        .lock()
+        .entries
+        .iter()
+        .flatten()
+        .find(|(ip, _)| ip == addr)
+        .map(|(_, mac)| *mac)
+}
+
+/// Record that `addr` is at `mac`
+pub fn update(addr: &Ipv6Addr, mac: MacAddr) {
+    let mut cache = CACHE.lock();
+    if let Some(entry) = cache.entries.iter_mut().flatten().find(|(ip, _)| ip == addr) {
+        entry.1 = mac;
+        return;
+    }
+    let index = match cache.entries.iter().position(|entry| entry.is_none()) {
+        Some(index) => index,
+        None => {
+            let index = cache.victim;
+            cache.victim = (index + 1) % CACHE_SIZE;
+            index
+        }
+    };
+    cache.entries[index] = Some((*addr, mac));
+}
+
+/// Find the link-layer address option of `kind` in `options`
+fn find_lladdr(mut options: &[u8], kind: u8) -> Option<MacAddr> {
+    while options.len() >= 2 {
+        let len = options[1] as usize * 8;
+        if len == 0 || len > options.len() {
+            return None;
+        }
+        if options[0] == kind && len >= LLADDR_OPT_LEN {
+            let mut mac = [0; 6];
+            mac.copy_from_slice(&options[2..8]);
+            return Some(mac);
+        }
+        options = &options[len..];
+    }
+    None
+}
+
+/// Build an NDP message for `target`, with a link-layer address option
+/// unless `lladdr` is `None`. Returns the message length.
+fn build(
+    buf: &mut [u8; MESSAGE_LEN + LLADDR_OPT_LEN],
+    kind: u8,
+    flags: u8,
+    target: &Ipv6Addr,
+    lladdr: Option<(u8, MacAddr)>,
+) -> usize {
+    buf.fill(0);
+    buf[0] = kind;
+    buf[4] = flags;
+    buf[8..24].copy_from_slice(&target.0);
+    match lladdr {
+        Some((option, mac)) => {
+            buf[24] = option;
+            buf[25] = (LLADDR_OPT_LEN / 8) as u8;
+            buf[26..32].copy_from_slice(&mac);
+            MESSAGE_LEN + LLADDR_OPT_LEN
+        }
+        None => MESSAGE_LEN,
+    }
+}
+
+/// Multicast a solicitation for `target` from `src`
+fn send_solicitation(iface: &Interface, src: Ipv6Addr, target: &Ipv6Addr) -> Result<(), NetError> {
+    let mut message = [0u8; MESSAGE_LEN + LLADDR_OPT_LEN];
+    // A probe from the unspecified address must not carry our MAC
+    let lladdr = (!src.is_unspecified()).then_some((OPT_SOURCE_LLADDR, iface.mac));
+    let len = build(&mut message, NEIGHBOR_SOLICITATION, 0, target, lladdr);
+    let header = Header {
+        src,
+        dst: target.solicited_node(),
+        next_header: ipv6::NEXT_ICMPV6,
+        hop_limit: NDP_HOP_LIMIT,
+    };
+    icmpv6::send(iface, &header, &mut message[..len])
+}
+
+/// Ask the link for the MAC address of `target`
+pub fn solicit(iface: &Interface, target: &Ipv6Addr) -> Result<(), NetError> {
+    send_solicitation(iface, iface.link_local, target)
+}
+
+/// Probe for another node using our link-local address
+pub fn probe(iface: &Interface) -> Result<(), NetError> {
+    send_solicitation(iface, Ipv6Addr::UNSPECIFIED, &iface.link_local)
+}
+
+/// Target address of a well-formed NDP message
+fn target(header: &Header, message: &[u8]) -> Option<Ipv6Addr> {
+    if header.hop_limit != NDP_HOP_LIMIT || message.len() < MESSAGE_LEN || message[1] != 0 {
+        return None;
+    }
+    let mut target = [0; 16];
+    target.copy_from_slice(&message[8..24]);
+    Some(Ipv6Addr(target))
+}
+
+/// Answer a solicitation for one of our addresses
+pub fn receive_solicitation(iface: &Interface, header: &Header, message: &[u8]) {
+    let Some(target) = target(header, message) else { return };
+    if !iface.owns(&target) {
+        return;
+    }
+
+    let mut reply = [0u8; MESSAGE_LEN + LLADDR_OPT_LEN];
+    let dst = if header.src.is_unspecified() {
+        // Someone probing for our address: defend it on all-nodes
+        build(&mut reply, NEIGHBOR_ADVERTISEMENT, FLAG_OVERRIDE, &target, Some((OPT_TARGET_LLADDR, iface.mac)));
+        Ipv6Addr::ALL_NODES
+    } else {
+        if let Some(mac) = find_lladdr(&message[MESSAGE_LEN..], OPT_SOURCE_LLADDR) {
+            update(&header.src, mac);
+        }
+        build(
+            &mut reply,
+            NEIGHBOR_ADVERTISEMENT,
+            FLAG_SOLICITED | FLAG_OVERRIDE,
+            &target,
+            Some((OPT_TARGET_LLADDR, iface.mac)),
+        );
+        header.src
+    };
+    let reply_header = Header {
+        src: target,
+        dst,
+        next_header: ipv6::NEXT_ICMPV6,
+        hop_limit: NDP_HOP_LIMIT,
+    };
+    let _ = icmpv6::send(iface, &reply_header, &mut reply);
+}
+
+/// Learn the link-layer address carried by an advertisement
+pub fn receive_advertisement(iface: &Interface, header: &Header, message: &[u8]) {
+    let Some(target) = target(header, message) else { return };
+    let Some(mac) = find_lladdr(&message[MESSAGE_LEN..], OPT_TARGET_LLADDR) else { return };
+    if iface.owns(&target) {
+        if mac != iface.mac {
+            crate::log_warn!("NET", "{}: duplicate address {} at {:02x?}", iface.device.name(), target, mac);
+        }
+        return;
+    }
+    update(&target, mac);
+}
+
+crate::kernel_test! {
+    /// Solicitations carry the target and a source link-layer option that
+    /// parses back, and ICMPv6 checksums verify
+    fn net_ndp_messages() {
+        let mac = [0x52, 0x54, 0x00, 0xab, 0xcd, 0xef];
+        let src = Ipv6Addr::link_local(mac);
+        let target = Ipv6Addr::link_local([0x52, 0x54, 0x00, 0x01, 0x02, 0x03]);
+
+        let mut message = [0u8; MESSAGE_LEN + LLADDR_OPT_LEN];
+        let len = build(&mut message, NEIGHBOR_SOLICITATION, 0, &target, Some((OPT_SOURCE_LLADDR, mac)));
+        crate::ktest_assert_eq!(len, 32, "message length");
+        crate::ktest_assert_eq!(find_lladdr(&message[MESSAGE_LEN..], OPT_SOURCE_LLADDR), Some(mac), "source option");
+        crate::ktest_assert_eq!(find_lladdr(&message[MESSAGE_LEN..], OPT_TARGET_LLADDR), None, "wrong option type");
+
+        let dst = target.solicited_node();
+        let sum = icmpv6::checksum(&src, &dst, &message);
+        message[2..4].copy_from_slice(&sum.to_be_bytes());
+        crate::ktest_assert_eq!(icmpv6::checksum(&src, &dst, &message), 0, "checksum does not verify");
+
+        let header = Header { src, dst, next_header: ipv6::NEXT_ICMPV6, hop_limit: NDP_HOP_LIMIT };
+        crate::ktest_assert_eq!(self::target(&header, &message), Some(target), "target");
+        let forwarded = Header { hop_limit: 254, ..header };
+        crate::ktest_assert_eq!(self::target(&forwarded, &message), None, "off-link message accepted");
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Echo requests (ping) over ICMP and ICMPv6
+//!
+//! [`ping`] sends one echo request and sleeps until the matching reply
+//! arrives or the timeout runs out. Outstanding requests are kept in a
+//! small table; `icmp` and `icmpv6` hand every echo reply to
+//! [`receive_reply`], which matches it by peer, identifier and sequence
+//! number, stamps the round trip time and wakes the waiting task. Times come
+//! from the monotonic clock (`time::Instant`).
+//!
+//! `SYS_PING` uses the caller's PID as the identifier, as Linux does, so
+//! concurrent pings from different processes keep their replies apart.
+
+use super::addr::{IpAddr, Ipv4Addr, Ipv6Addr};
+use super::{icmp, icmpv6, ipv4, ipv6, NetError};
+use crate::sched::task::TaskId;
+use crate::time::{Duration, Instant};
+use spin::Mutex;
+
+/// Echo requests waiting for their reply at once
+const MAX_PROBES: usize = 8;
+
+/// Bytes of data after the 8-byte echo header, as in Linux's ping
+pub const DATA_LEN: usize = 56;
+
+/// Longest wait for a reply
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time between retries while the peer's link-layer address is resolved
+const RESOLVE_RETRY: Duration = Duration::from_millis(100);
+
+/// Echo request passed to `SYS_PING`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PingRequest {
+    /// IPv4 address in the first 4 bytes, or an IPv6 address
+    pub addr: [u8; 16],
+    /// 4 or 6
+    pub family: u16,
+    pub seq: u16,
+    pub timeout_ms: u32,
+}
+
+mello_abi::check_layout!(PingRequest, mello_abi::PingRequest { addr, family, seq, timeout_ms });
+
+impl PingRequest {
+    /// Destination address, if `family` is known
+    pub fn peer(&self) -> Option<IpAddr> {
+        match self.family {
+            4 => Some(IpAddr::V4(Ipv4Addr([self.addr[0], self.addr[1], self.addr[2], self.addr[3]]))),
+            6 => Some(IpAddr::V6(Ipv6Addr(self.addr))),
+            _ => None,
+        }
+    }
+}
+
+/// An echo request waiting for its reply
+#[derive(Clone, Copy)]
+struct Probe {
+    peer: IpAddr,
+    id: u16,
+    seq: u16,
+    task: TaskId,
+    sent: Instant,
+    /// Round trip time, once the reply is in
+    rtt: Option<Duration>,
+}
+
+static PROBES: Mutex<[Option<Probe>; MAX_PROBES]> = Mutex::new([None; MAX_PROBES]);
+
+/// Echo request (or reply) of `id`/`seq` with the standard data pattern
+fn build(kind: u8, id: u16, seq: u16) -> [u8; 8 + DATA_LEN] {
+    let mut message = [0u8; 8 + DATA_LEN];
+    message[0] = kind;
+    message[4..6].copy_from_slice(&id.to_be_bytes());
+    message[6..8].copy_from_slice(&seq.to_be_bytes());
+    for (i, byte) in message[8..].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    message
+}
+
+/// Send one echo request to `peer`
+fn send_request(peer: IpAddr, id: u16, seq: u16) -> Result<(), NetError> {
+    match peer {
+        IpAddr::V4(dst) => {
+            let header = ipv4::Header {
+                src: Ipv4Addr::UNSPECIFIED,
+                dst,
+                protocol: ipv4::PROTO_ICMP,
+                ttl: ipv4::DEFAULT_TTL,
+            };
+            icmp::send(&header, &mut build(icmp::ECHO_REQUEST, id, seq))
+        }
+        IpAddr::V6(dst) => {
+            let iface = super::interface_for(&Ipv6Addr::UNSPECIFIED).ok_or(NetError::NoInterface)?;
+            let header = ipv6::Header {
+                src: iface.link_local,
+                dst,
+                next_header: ipv6::NEXT_ICMPV6,
+                hop_limit: ipv6::DEFAULT_HOP_LIMIT,
+            };
+            icmpv6::send(&iface, &header, &mut build(icmpv6::ECHO_REQUEST, id, seq))
+        }
+    }
+}
+
+/// Round trip time of the probe in `slot`, if its reply is in
+fn rtt(slot: usize) -> Option<Duration> {
+    PROBES.lock()[slot].and_then(|probe| probe.rtt)
+}
+
+/// Ping `peer` once: send an echo request with identifier `id` and
+/// sequence number `seq` and wait up to `timeout` for the reply
+///
+/// Returns the round trip time. While the peer's link-layer address is
+/// being resolved the request is sent again every `RESOLVE_RETRY`, and the
+/// round trip counts from the last one sent; if that never succeeds the
+/// result is `NetError::Unresolved` rather than `TimedOut`. Must be called
+/// from a task.
+pub fn ping(peer: IpAddr, id: u16, seq: u16, timeout: Duration) -> Result<Duration, NetError> {
+    let (task, priority) = crate::sched::get_current_task_info().ok_or(NetError::Unsupported)?;
+    let deadline = Instant::now().saturating_add(timeout.min(MAX_TIMEOUT));
+    let slot = {
+        let mut probes = PROBES.lock();
+        let slot = probes.iter().position(Option::is_none).ok_or(NetError::TooManyEndpoints)?;
+        probes[slot] = Some(Probe { peer, id, seq, task, sent: Instant::now(), rtt: None });
+        slot
+    };
+
+    let mut result = Err(NetError::TimedOut);
+    let mut resolved = false;
+    while !deadline.has_passed() {
+        if !resolved {
+            if let Some(probe) = PROBES.lock()[slot].as_mut() {
+                probe.sent = Instant::now();
+            }
+            match send_request(peer, id, seq) {
+                Ok(()) => resolved = true,
+                Err(NetError::Unresolved) => {}
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        if let Some(rtt) = rtt(slot) {
+            result = Ok(rtt);
+            break;
+        }
+
+        let wait = deadline.saturating_duration_since(Instant::now());
+        let wait = if resolved { wait } else { wait.min(RESOLVE_RETRY) };
+        crate::sched::sleep_current_task(wait, priority);
+        // The reply may have come in before we went to sleep
+        if rtt(slot).is_some() && crate::sched::cancel_wait() {
+            continue;
+        }
+        crate::sched::yield_now();
+    }
+    if let Some(rtt) = PROBES.lock()[slot].take().and_then(|probe| probe.rtt) {
+        result = Ok(rtt);
+    }
+    match result {
+        Err(NetError::TimedOut) if !resolved => Err(NetError::Unresolved),
+        result => result,
+    }
+}
+
+/// Match an echo reply from `peer` to its request and wake the waiting task
+pub fn receive_reply(peer: IpAddr, message: &[u8]) {
+    let Some(header) = message.get(..8) else { return };
+    let id = u16::from_be_bytes([header[4], header[5]]);
+    let seq = u16::from_be_bytes([header[6], header[7]]);
+    let task = {
+        let mut probes = PROBES.lock();
+        let Some(probe) = probes
+            .iter_mut()
+            .flatten()
+            .find(|probe| probe.peer == peer && probe.id == id && probe.seq == seq && probe.rtt.is_none())
+        else {
+            return;
+        };
+        probe.rtt = Some(probe.sent.elapsed());
+        probe.task
+    };
+    crate::sched::wake_task(task);
+}
+
+crate::kernel_test! {
+    /// A reply is matched by peer, identifier and sequence number only
+    fn net_ping_reply_matching() {
+        let peer = IpAddr::V4(Ipv4Addr([192, 0, 2, 1]));
+        let slot = {
+            let mut probes = PROBES.lock();
+            let slot = probes.iter().position(Option::is_none).ok_or("probe table full")?;
+            probes[slot] = Some(Probe { peer, id: 7, seq: 3, task: usize::MAX, sent: Instant::now(), rtt: None });
+            slot
+        };
+        let reply = build(icmp::ECHO_REPLY, 7, 3);
+        receive_reply(peer, &build(icmp::ECHO_REPLY, 7, 4));
+        receive_reply(peer, &build(icmp::ECHO_REPLY, 8, 3));
+        receive_reply(IpAddr::V4(Ipv4Addr([192, 0, 2, 2])), &reply);
+        let unmatched = rtt(slot);
+        receive_reply(peer, &reply);
+        let matched = rtt(slot);
+        PROBES.lock()[slot] = None;
+        crate::ktest_assert!(unmatched.is_none(), "reply matched the wrong request");
+        crate::ktest_assert!(matched.is_some(), "reply not matched");
+        crate::ktest_assert_eq!(&reply[8..12], &[0, 1, 2, 3], "data pattern");
+        Ok(())
+    }
+}
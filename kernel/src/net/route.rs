@@ -0,0 +1,87 @@
+//! IPv4 routing table
+//!
+//! A handful of routes, picked by longest prefix match: one to the network
+//! of each configured interface (on-link, no gateway) and a default route
+//! (`0.0.0.0/0`) through a gateway. `net::configure_ipv4` sets both up.
+
+use super::addr::Ipv4Addr;
+use super::NetError;
+use spin::Mutex;
+
+/// Number of routes kept
+const MAX_ROUTES: usize = 8;
+
+/// A route to `dest/prefix_len` out of interface `iface`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub dest: Ipv4Addr,
+    pub prefix_len: u8,
+    /// Router to send through; `None` for an on-link network
+    pub gateway: Option<Ipv4Addr>,
+    pub iface: usize,
+}
+
+static ROUTES: Mutex<[Option<Route>; MAX_ROUTES]> = Mutex::new([None; MAX_ROUTES]);
+
+/// Add `route`, replacing one to the same network
+pub fn add(route: Route) -> Result<(), NetError> {
+    let mut routes = ROUTES.lock();
+    let same = |r: &Option<Route>| r.map_or(false, |r| r.dest == route.dest && r.prefix_len == route.prefix_len);
+    let slot = match routes.iter().position(same) {
+        Some(index) => index,
+        None => routes.iter().position(Option::is_none).ok_or(NetError::TooManyRoutes)?,
+    };
+    routes[slot] = Some(route);
+    Ok(())
+}
+
+/// Remove the routes out of interface `iface`
+pub fn remove_interface(iface: usize) {
+    for slot in ROUTES.lock().iter_mut() {
+        if slot.map_or(false, |route| route.iface == iface) {
+            *slot = None;
+        }
+    }
+}
+
+/// Most specific route in `routes` covering `dst`
+fn best_match(routes: &[Option<Route>], dst: &Ipv4Addr) -> Option<Route> {
+    routes
+        .iter()
+        .flatten()
+        .filter(|route| route.dest.same_network(dst, route.prefix_len))
+        .max_by_key(|route| route.prefix_len)
+        .copied()
+}
+
+/// Interface and next hop for a packet to `dst`: the gateway of the
+/// route, or `dst` itself when it is on-link
+pub fn lookup(dst: &Ipv4Addr) -> Option<(usize, Ipv4Addr)> {
+    let route = best_match(&*ROUTES.lock(), dst)?;
+    Some((route.iface, route.gateway.unwrap_or(*dst)))
+}
+
+/// Copy of the routing table
+pub fn routes() -> [Option<Route>; MAX_ROUTES] {
+    *ROUTES.lock()
+}
+
+crate::kernel_test! {
+    /// The longest matching prefix wins, and the default route catches
+    /// the rest
+    fn net_route_longest_prefix() {
+        let addr = |text| Ipv4Addr::parse(text).ok_or("bad address");
+        let gateway = addr("10.0.2.2")?;
+        let routes = [
+            Some(Route { dest: Ipv4Addr::UNSPECIFIED, prefix_len: 0, gateway: Some(gateway), iface: 0 }),
+            Some(Route { dest: addr("10.0.2.0")?, prefix_len: 24, gateway: None, iface: 0 }),
+            Some(Route { dest: addr("10.0.0.0")?, prefix_len: 8, gateway: None, iface: 1 }),
+            None,
+        ];
+        crate::ktest_assert_eq!(best_match(&routes, &addr("10.0.2.15")?).map(|r| r.prefix_len), Some(24), "/24");
+        crate::ktest_assert_eq!(best_match(&routes, &addr("10.9.0.1")?).map(|r| r.iface), Some(1), "/8");
+        crate::ktest_assert_eq!(best_match(&routes, &addr("1.1.1.1")?).and_then(|r| r.gateway), Some(gateway), "default");
+        crate::ktest_assert_eq!(best_match(&routes[1..], &addr("1.1.1.1")?), None, "no default route");
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+//! Sockets
+//!
+//! The objects behind the handles `SYS_SOCKET` returns. A socket is a
+//! reference-counted entry in a small table. A datagram (UDP) socket owns
+//! at most one bound `udp` endpoint; binding is explicit (`SYS_BIND`) or
+//! happens on the first send, to an ephemeral port, and the last handle
+//! closed unbinds the port. A stream (TCP) socket owns a `tcp` control
+//! block from the start, which becomes a listener (`SYS_LISTEN`) or a
+//! connection (`SYS_CONNECT`, or a socket returned by `SYS_ACCEPT`); the
+//! last handle closed starts the connection's teardown.
+//!
+//! The socket's family only decides which destinations it sends or
+//! connects to. The local address given to bind is not checked, and a
+//! bound port takes datagrams and connections to any of our addresses over
+//! either IP version; the peer's address tells them apart. Socket
+//! addresses cross the syscall boundary as Linux's `sockaddr_in` and
+//! `sockaddr_in6`.
+
+use super::addr::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use super::{tcp, udp, NetError};
+use crate::sync::WaitQueue;
+use spin::Mutex;
+
+/// Address families
+pub const AF_INET: usize = 2;
+pub const AF_INET6: usize = 10;
+
+/// Socket types, and the flags `SYS_SOCKET` takes along with them
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+pub const SOCK_NONBLOCK: usize = 0x800;
+pub const SOCK_CLOEXEC: usize = 0x80000;
+const SOCK_TYPE_MASK: usize = 0xf;
+
+pub const IPPROTO_TCP: usize = 6;
+pub const IPPROTO_UDP: usize = 17;
+
+/// `SYS_SENDTO`/`SYS_RECVFROM` flag: don't block this call
+pub const MSG_DONTWAIT: usize = 0x40;
+
+/// Open sockets, over all processes; stream sockets are also limited by
+/// `tcp::MAX_CONNECTIONS`
+const MAX_SOCKETS: usize = 32;
+
+/// Linux's `struct sockaddr_in`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SockaddrIn {
+    pub sin_family: u16,
+    /// Network byte order
+    pub sin_port: u16,
+    pub sin_addr: [u8; 4],
+    pub sin_zero: [u8; 8],
+}
+
+mello_abi::check_layout!(SockaddrIn, mello_abi::SockaddrIn { sin_family, sin_port, sin_addr, sin_zero });
+
+/// Linux's `struct sockaddr_in6`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SockaddrIn6 {
+    pub sin6_family: u16,
+    /// Network byte order
+    pub sin6_port: u16,
+    pub sin6_flowinfo: u32,
+    pub sin6_addr: [u8; 16],
+    pub sin6_scope_id: u32,
+}
+
+mello_abi::check_layout!(
+    SockaddrIn6,
+    mello_abi::SockaddrIn6 { sin6_family, sin6_port, sin6_flowinfo, sin6_addr, sin6_scope_id }
+);
+
+impl From<SockaddrIn> for SocketAddr {
+    fn from(sockaddr: SockaddrIn) -> Self {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr(sockaddr.sin_addr)), u16::from_be(sockaddr.sin_port))
+    }
+}
+
+impl From<SockaddrIn6> for SocketAddr {
+    fn from(sockaddr: SockaddrIn6) -> Self {
+        SocketAddr::new(IpAddr::V6(Ipv6Addr(sockaddr.sin6_addr)), u16::from_be(sockaddr.sin6_port))
+    }
+}
+
+/// Address family of a socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    /// Family of `AF_INET`/`AF_INET6`
+    pub fn from_domain(domain: usize) -> Option<Self> {
+        match domain {
+            AF_INET => Some(Family::V4),
+            AF_INET6 => Some(Family::V6),
+            _ => None,
+        }
+    }
+
+    fn matches(self, addr: &IpAddr) -> bool {
+        matches!((self, addr), (Family::V4, IpAddr::V4(_)) | (Family::V6, IpAddr::V6(_)))
+    }
+}
+
+/// Socket type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Datagram,
+    Stream,
+}
+
+impl Type {
+    /// Type `SYS_SOCKET` asks for with `kind` (flags included) and
+    /// `protocol`, if supported
+    pub fn from_args(kind: usize, protocol: usize) -> Option<Self> {
+        match (kind & SOCK_TYPE_MASK, protocol) {
+            (SOCK_DGRAM, 0 | IPPROTO_UDP) => Some(Type::Datagram),
+            (SOCK_STREAM, 0 | IPPROTO_TCP) => Some(Type::Stream),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    /// Bound UDP port
+    Datagram(Option<u16>),
+    /// TCP control block
+    Stream(u32),
+}
+
+struct Socket {
+    family: Family,
+    kind: Kind,
+    /// Handles referring to the socket
+    refs: u32,
+}
+
+static SOCKETS: Mutex<[Option<Socket>; MAX_SOCKETS]> = Mutex::new([const { None }; MAX_SOCKETS]);
+
+/// Store a new socket with one reference; returns its number
+fn insert(family: Family, kind: Kind) -> Result<u32, NetError> {
+    let mut sockets = SOCKETS.lock();
+    let index = sockets.iter().position(Option::is_none).ok_or(NetError::TooManyEndpoints)?;
+    sockets[index] = Some(Socket { family, kind, refs: 1 });
+    Ok(index as u32)
+}
+
+/// Create an unbound socket; returns its number
+pub fn create(family: Family, ty: Type) -> Result<u32, NetError> {
+    match ty {
+        Type::Datagram => insert(family, Kind::Datagram(None)),
+        Type::Stream => {
+            let conn = tcp::create()?;
+            insert(family, Kind::Stream(conn)).inspect_err(|_| tcp::close(conn))
+        }
+    }
+}
+
+/// Family and kind of `socket`
+fn lookup(socket: u32) -> Result<(Family, Kind), NetError> {
+    let sockets = SOCKETS.lock();
+    let state = sockets.get(socket as usize).and_then(Option::as_ref).ok_or(NetError::NotBound)?;
+    Ok((state.family, state.kind))
+}
+
+/// Take another reference to `socket` for a duplicated handle
+pub fn retain(socket: u32) {
+    if let Some(socket) = SOCKETS.lock().get_mut(socket as usize).and_then(Option::as_mut) {
+        socket.refs += 1;
+    }
+}
+
+/// Drop a reference to `socket`; the last one closes it, unbinding a
+/// datagram socket's port and closing a stream socket's connection
+pub fn release(socket: u32) {
+    let kind = {
+        let mut sockets = SOCKETS.lock();
+        let Some(slot) = sockets.get_mut(socket as usize) else { return };
+        let Some(state) = slot.as_mut() else { return };
+        state.refs -= 1;
+        if state.refs > 0 {
+            return;
+        }
+        slot.take().map(|state| state.kind)
+    };
+    match kind {
+        Some(Kind::Datagram(Some(port))) => udp::unbind(port),
+        Some(Kind::Stream(conn)) => tcp::close(conn),
+        _ => {}
+    }
+}
+
+/// Bind `socket` to `addr.port` (0 picks an ephemeral port); returns the
+/// port
+pub fn bind(socket: u32, addr: &SocketAddr) -> Result<u16, NetError> {
+    let mut sockets = SOCKETS.lock();
+    let state = sockets.get_mut(socket as usize).and_then(Option::as_mut).ok_or(NetError::NotBound)?;
+    if !state.family.matches(&addr.ip) {
+        return Err(NetError::Unsupported);
+    }
+    match state.kind {
+        Kind::Datagram(Some(_)) => Err(NetError::AlreadyBound),
+        Kind::Datagram(None) => {
+            let port = udp::bind(addr.port)?;
+            state.kind = Kind::Datagram(Some(port));
+            Ok(port)
+        }
+        Kind::Stream(conn) => tcp::bind(conn, addr.port),
+    }
+}
+
+/// Port of datagram socket `socket`, binding an ephemeral one first if it
+/// has none
+fn port_or_bind(socket: u32) -> Result<(Family, u16), NetError> {
+    let mut sockets = SOCKETS.lock();
+    let state = sockets.get_mut(socket as usize).and_then(Option::as_mut).ok_or(NetError::NotBound)?;
+    let port = match state.kind {
+        Kind::Datagram(Some(port)) => port,
+        Kind::Datagram(None) => {
+            let port = udp::bind(0)?;
+            state.kind = Kind::Datagram(Some(port));
+            port
+        }
+        Kind::Stream(_) => return Err(NetError::AlreadyConnected),
+    };
+    Ok((state.family, port))
+}
+
+/// Send `data` as one datagram from `socket` to `to`
+pub fn send_to(socket: u32, to: &SocketAddr, data: &[u8]) -> Result<usize, NetError> {
+    let (family, port) = port_or_bind(socket)?;
+    if !family.matches(&to.ip) {
+        return Err(NetError::Unsupported);
+    }
+    udp::send_to(port, *to, data)?;
+    Ok(data.len())
+}
+
+/// Send `data` on connected `socket`; returns how much was queued, 0 if
+/// none fits yet (see `tcp::send`)
+pub fn send(socket: u32, data: &[u8]) -> Result<usize, NetError> {
+    match lookup(socket)?.1 {
+        Kind::Datagram(_) => Err(NetError::DestinationRequired),
+        Kind::Stream(conn) => tcp::send(conn, data),
+    }
+}
+
+/// Receive from `socket` into `buf`: the oldest datagram queued and its
+/// sender (see `udp::recv_from`), or stream data (see `tcp::recv`)
+///
+/// `None` if there is nothing to receive yet.
+pub fn recv_from(socket: u32, buf: &mut [u8]) -> Result<Option<(usize, Option<SocketAddr>)>, NetError> {
+    match lookup(socket)?.1 {
+        Kind::Datagram(Some(port)) => Ok(udp::recv_from(port, buf)?.map(|(len, from)| (len, Some(from)))),
+        Kind::Datagram(None) => Err(NetError::NotBound),
+        Kind::Stream(conn) => Ok(tcp::recv(conn, buf)?.map(|len| (len, None))),
+    }
+}
+
+/// Connection block of stream socket `socket`
+fn stream(socket: u32) -> Result<(Family, u32), NetError> {
+    match lookup(socket)? {
+        (family, Kind::Stream(conn)) => Ok((family, conn)),
+        (_, Kind::Datagram(_)) => Err(NetError::WrongType),
+    }
+}
+
+/// Start connecting stream socket `socket` to `to`; the handshake runs on
+/// until [`connect_status`] reports it over
+pub fn connect(socket: u32, to: &SocketAddr) -> Result<(), NetError> {
+    let (family, conn) = stream(socket)?;
+    if !family.matches(&to.ip) {
+        return Err(NetError::Unsupported);
+    }
+    tcp::connect(conn, to)
+}
+
+/// Outcome of the handshake of `socket`, `None` while it runs
+pub fn connect_status(socket: u32) -> Option<Result<(), NetError>> {
+    match stream(socket) {
+        Ok((_, conn)) => tcp::connect_status(conn),
+        Err(e) => Some(Err(e)),
+    }
+}
+
+/// Make stream socket `socket` accept connections
+pub fn listen(socket: u32, backlog: usize) -> Result<(), NetError> {
+    tcp::listen(stream(socket)?.1, backlog)
+}
+
+/// Take an established connection off listening socket `socket` as a new
+/// socket; returns it and the peer's address, or `None` if there is none
+/// yet
+pub fn accept(socket: u32) -> Result<Option<(u32, SocketAddr)>, NetError> {
+    let (_, listener) = stream(socket)?;
+    let Some((conn, peer)) = tcp::accept(listener)? else { return Ok(None) };
+    let family = match peer.ip {
+        IpAddr::V4(_) => Family::V4,
+        IpAddr::V6(_) => Family::V6,
+    };
+    let socket = insert(family, Kind::Stream(conn)).inspect_err(|_| tcp::close(conn))?;
+    Ok(Some((socket, peer)))
+}
+
+/// Whether a receive (or accept) on `socket` would not block
+pub fn readable(socket: u32) -> bool {
+    match lookup(socket) {
+        Ok((_, Kind::Datagram(port))) => port.is_some_and(udp::readable),
+        Ok((_, Kind::Stream(conn))) => tcp::readable(conn),
+        Err(_) => false,
+    }
+}
+
+/// Whether a send on `socket` would not block
+pub fn writable(socket: u32) -> bool {
+    match lookup(socket) {
+        Ok((_, Kind::Datagram(_))) => true,
+        Ok((_, Kind::Stream(conn))) => tcp::writable(conn),
+        Err(_) => false,
+    }
+}
+
+/// Queue woken when `socket` becomes readable or writable
+pub fn wait_queue(socket: u32) -> &'static WaitQueue {
+    match lookup(socket) {
+        Ok((_, Kind::Stream(_))) => &tcp::WAIT,
+        _ => &udp::WAIT,
+    }
+}
+
+crate::kernel_test! {
+    /// Sockets bind on first send if needed, and the last release frees
+    /// the port
+    fn net_socket_lifetime() {
+        let socket = create(Family::V4, Type::Datagram).map_err(|_| "socket table full")?;
+        let any = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        let port = bind(socket, &any).map_err(|_| "bind failed")?;
+        crate::ktest_assert_eq!(bind(socket, &any), Err(NetError::AlreadyBound), "bound twice");
+        let v6 = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 7);
+        crate::ktest_assert_eq!(send_to(socket, &v6, b"x"), Err(NetError::Unsupported), "IPv6 from an IPv4 socket");
+
+        retain(socket);
+        release(socket);
+        crate::ktest_assert_eq!(udp::bind(port), Err(NetError::AddressInUse), "port freed with a handle left");
+        release(socket);
+        crate::ktest_assert!(udp::bind(port).is_ok(), "port not freed");
+        udp::unbind(port);
+
+        let sockaddr = SockaddrIn { sin_family: AF_INET as u16, sin_port: 53u16.to_be(), sin_addr: [10, 0, 2, 3], sin_zero: [0; 8] };
+        crate::ktest_assert_eq!(
+            SocketAddr::from(sockaddr),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr([10, 0, 2, 3])), 53),
+            "sockaddr_in"
+        );
+        Ok(())
+    }
+}
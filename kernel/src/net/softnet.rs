@@ -0,0 +1,304 @@
+//! Softnet: deferred receive processing with NAPI-style polling
+//!
+//! A device with a receive interrupt masks it in its interrupt handler and
+//! calls `dev::api::net::napi_schedule`, which puts the interface on the
+//! softnet list of the CPU that took the interrupt. That CPU's softnet task
+//! then drains the device in polls of at most `NAPI_BUDGET` frames and
+//! unmasks the interrupt once a poll comes back short.
+//!
+//! Under load the interface switches to polling mode instead: while it
+//! receives more than `POLL_ENTER_PPS` packets per second the interrupt
+//! stays masked and the device is polled every tick, and it goes back to
+//! interrupts when the rate drops below `POLL_EXIT_PPS`. Rates are
+//! recomputed once a second and shown in /proc/net/dev.
+//!
+//! "Softnet context" is one kernel task per CPU list, rather than the
+//! shared work queue (`sched::workqueue`), so one busy interface polls
+//! without holding up other deferred work. Tasks are not pinned, so a list may be served from
+//! another CPU. Devices without a receive interrupt are polled by the first
+//! softnet task every `POLL_INTERVAL`, which also runs the TCP timers on
+//! every pass.
+
+use super::{Interface, MAX_FRAME, MAX_INTERFACES};
+use crate::config::MAX_CPUS;
+use crate::time::{Duration, Instant};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Most frames taken from one device per poll
+pub const NAPI_BUDGET: usize = 64;
+
+/// Receive rate above which an interface stays in polling mode
+pub const POLL_ENTER_PPS: u64 = 5000;
+
+/// Receive rate below which a polled interface goes back to interrupts
+pub const POLL_EXIT_PPS: u64 = 1000;
+
+/// How often devices without a receive interrupt are polled
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Longest a softnet task sleeps; covers a wakeup lost because the task
+/// table was locked when the interrupt came in
+const WAKE_FALLBACK: Duration = Duration::from_millis(100);
+
+/// Receive mode and statistics of one interface
+pub struct NapiState {
+    /// The device has a receive interrupt we can mask
+    irq_capable: AtomicBool,
+    /// On a softnet list (interrupt masked)
+    scheduled: AtomicBool,
+    /// Polling mode: interrupt kept masked between polls
+    polling: AtomicBool,
+    pub rx_packets: AtomicU64,
+    pub tx_packets: AtomicU64,
+    /// Receive interrupts reported by the driver
+    pub interrupts: AtomicU64,
+    /// Softnet polls of the device
+    pub polls: AtomicU64,
+    /// Packets per second over the last full second
+    pub rx_pps: AtomicU64,
+    pub tx_pps: AtomicU64,
+    last_rx: AtomicU64,
+    last_tx: AtomicU64,
+}
+
+impl NapiState {
+    const fn new() -> Self {
+        Self {
+            irq_capable: AtomicBool::new(false),
+            scheduled: AtomicBool::new(false),
+            polling: AtomicBool::new(false),
+            rx_packets: AtomicU64::new(0),
+            tx_packets: AtomicU64::new(0),
+            interrupts: AtomicU64::new(0),
+            polls: AtomicU64::new(0),
+            rx_pps: AtomicU64::new(0),
+            tx_pps: AtomicU64::new(0),
+            last_rx: AtomicU64::new(0),
+            last_tx: AtomicU64::new(0),
+        }
+    }
+
+    /// Interrupt-driven ("irq"), polling under load ("poll"), or polled on a
+    /// timer for lack of an interrupt ("timer")
+    pub fn mode(&self) -> &'static str {
+        if !self.irq_capable.load(Ordering::Relaxed) {
+            "timer"
+        } else if self.polling.load(Ordering::Relaxed) {
+            "poll"
+        } else {
+            "irq"
+        }
+    }
+}
+
+pub static NAPI: [NapiState; MAX_INTERFACES] = [const { NapiState::new() }; MAX_INTERFACES];
+
+/// Per-CPU softnet data
+struct Softnet {
+    /// Interfaces to poll, one bit per interface number
+    pending: AtomicU32,
+    /// Softnet task serving this CPU (0 until it has started)
+    task: AtomicUsize,
+    /// Receive buffer of the softnet task
+    frame: Mutex<[u8; MAX_FRAME]>,
+}
+
+static SOFTNET: [Softnet; MAX_CPUS] = [const {
+    Softnet {
+        pending: AtomicU32::new(0),
+        task: AtomicUsize::new(0),
+        frame: Mutex::new([0; MAX_FRAME]),
+    }
+}; MAX_CPUS];
+
+/// Next softnet slot to hand to a starting task
+static NEXT_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+/// Tick count when the rates were last recomputed
+static RATE_STAMP: AtomicU64 = AtomicU64::new(0);
+
+/// Record how interface `index` receives: with an interrupt or on a timer
+pub fn attach(index: usize, irq_capable: bool) {
+    NAPI[index].irq_capable.store(irq_capable, Ordering::Relaxed);
+}
+
+/// Queue interface `index` on this CPU's softnet list
+///
+/// Called from the driver's receive interrupt handler, after it has masked
+/// the receive interrupt. Safe in interrupt context.
+pub fn schedule(index: usize) {
+    let state = &NAPI[index];
+    state.interrupts.fetch_add(1, Ordering::Relaxed);
+    if state.scheduled.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    let cpu = crate::arch::x86_64::smp::percpu::percpu_current().id;
+    let softnet = &SOFTNET[cpu % MAX_CPUS];
+    softnet.pending.fetch_or(1 << index, Ordering::AcqRel);
+    let task = softnet.task.load(Ordering::Acquire);
+    if task != 0 {
+        crate::sched::try_wake_task(task);
+    }
+}
+
+/// Count a transmitted packet on interface `index`
+pub fn count_tx(index: usize) {
+    NAPI[index].tx_packets.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Whether an interface receiving `rx_pps` should be in polling mode
+const fn next_polling(polling: bool, rx_pps: u64) -> bool {
+    if polling {
+        rx_pps >= POLL_EXIT_PPS
+    } else {
+        rx_pps >= POLL_ENTER_PPS
+    }
+}
+
+/// Recompute the packet rates if a second has passed
+fn update_rates() {
+    let now = Instant::now().as_ticks();
+    let stamp = RATE_STAMP.load(Ordering::Relaxed);
+    let elapsed = now.saturating_sub(stamp);
+    if elapsed < Duration::from_secs(1).as_ticks()
+        || RATE_STAMP
+            .compare_exchange(stamp, now, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+    {
+        return;
+    }
+    let per_sec = |delta: u64| delta * Duration::from_secs(1).as_ticks() / elapsed;
+    for state in &NAPI {
+        let rx = state.rx_packets.load(Ordering::Relaxed);
+        let tx = state.tx_packets.load(Ordering::Relaxed);
+        state.rx_pps.store(per_sec(rx - state.last_rx.swap(rx, Ordering::Relaxed)), Ordering::Relaxed);
+        state.tx_pps.store(per_sec(tx - state.last_tx.swap(tx, Ordering::Relaxed)), Ordering::Relaxed);
+    }
+}
+
+/// Take up to `budget` frames from `iface` and hand them to the stack
+fn poll(iface: &Interface, frame: &mut [u8; MAX_FRAME], budget: usize) -> usize {
+    let mut done = 0;
+    while done < budget {
+        match iface.device.receive(frame) {
+            Some(len) => {
+                super::receive(iface, &frame[..len.min(MAX_FRAME)]);
+                done += 1;
+            }
+            None => break,
+        }
+    }
+    let state = &NAPI[iface.index];
+    state.rx_packets.fetch_add(done as u64, Ordering::Relaxed);
+    state.polls.fetch_add(1, Ordering::Relaxed);
+    done
+}
+
+/// Finish a poll of `iface` that took `done` frames
+///
+/// Returns true if the interface must be polled again: the budget ran out
+/// or it is in polling mode. Otherwise its interrupt is unmasked.
+fn complete(iface: &Interface, done: usize) -> bool {
+    let state = &NAPI[iface.index];
+    let polling = next_polling(state.polling.load(Ordering::Relaxed), state.rx_pps.load(Ordering::Relaxed));
+    if polling != state.polling.swap(polling, Ordering::Relaxed) {
+        crate::log_info!(
+            "NET",
+            "{}: {} mode",
+            iface.device.name(),
+            if polling { "polling" } else { "interrupt" }
+        );
+    }
+    if done == NAPI_BUDGET || polling {
+        return true;
+    }
+    state.scheduled.store(false, Ordering::Release);
+    iface.device.set_rx_interrupt(true);
+    false
+}
+
+/// Softnet task; one is started per CPU, each taking the next CPU's list
+pub fn softnet_task() -> ! {
+    let slot = NEXT_SLOT.fetch_add(1, Ordering::Relaxed) % MAX_CPUS;
+    let softnet = &SOFTNET[slot];
+    if let Some((id, _)) = crate::sched::get_current_task_info() {
+        softnet.task.store(id, Ordering::Release);
+    }
+    let mut last_timer_poll = Instant::BOOT;
+
+    loop {
+        update_rates();
+        let mut frame = softnet.frame.lock();
+        let mut again = 0u32;
+        let mut busy = false;
+
+        let pending = softnet.pending.swap(0, Ordering::AcqRel);
+        for index in 0..MAX_INTERFACES {
+            if pending & (1 << index) == 0 {
+                continue;
+            }
+            let Some(iface) = super::interface(index) else { continue };
+            let done = poll(&iface, &mut frame, NAPI_BUDGET);
+            if complete(&iface, done) {
+                again |= 1 << index;
+                busy |= done == NAPI_BUDGET;
+            }
+        }
+        softnet.pending.fetch_or(again, Ordering::AcqRel);
+
+        // Devices without a receive interrupt, and TCP timers
+        let mut timer_polled = false;
+        if slot == 0 {
+            super::tcp::run_timers();
+            for index in 0..MAX_INTERFACES {
+                if NAPI[index].irq_capable.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let Some(iface) = super::interface(index) else { continue };
+                timer_polled = true;
+                if last_timer_poll.saturating_add(POLL_INTERVAL).has_passed() {
+                    busy |= poll(&iface, &mut frame, NAPI_BUDGET) == NAPI_BUDGET;
+                }
+            }
+            if timer_polled && last_timer_poll.saturating_add(POLL_INTERVAL).has_passed() {
+                last_timer_poll = Instant::now();
+            }
+        }
+        drop(frame);
+
+        // Out of budget: go again after letting other tasks run. Polling
+        // mode: poll once per tick. Otherwise wait for an interrupt.
+        if busy {
+            crate::sched::yield_now();
+            continue;
+        }
+        let sleep = if again != 0 {
+            Duration::TICK
+        } else if timer_polled {
+            POLL_INTERVAL
+        } else {
+            WAKE_FALLBACK
+        };
+        if let Some((_, priority)) = crate::sched::get_current_task_info() {
+            crate::sched::sleep_current_task(sleep, priority);
+            // An interrupt may have queued work before we went to sleep
+            if softnet.pending.load(Ordering::Acquire) != 0 && crate::sched::cancel_wait() {
+                continue;
+            }
+        }
+        crate::sched::yield_now();
+    }
+}
+
+crate::kernel_test! {
+    /// Polling mode is entered and left with hysteresis
+    fn softnet_mode_switch() {
+        crate::ktest_assert!(!next_polling(false, POLL_ENTER_PPS - 1), "entered polling below threshold");
+        crate::ktest_assert!(next_polling(false, POLL_ENTER_PPS), "did not enter polling");
+        crate::ktest_assert!(next_polling(true, POLL_EXIT_PPS), "left polling inside hysteresis");
+        crate::ktest_assert!(!next_polling(true, POLL_EXIT_PPS - 1), "did not leave polling");
+        Ok(())
+    }
+}
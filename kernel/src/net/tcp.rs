@@ -0,0 +1,1238 @@
+//! TCP (RFC 9293)
+//!
+//! Connections live in a fixed table of `MAX_CONNECTIONS` control blocks
+//! (TCBs), each with a `BUFFER_SIZE` byte receive and send ring. Every
+//! stream socket owns a TCB, so an unconnected or listening socket takes a
+//! slot too; the connections a listener takes in get their own until
+//! [`accept`] hands them to a new socket. Like a UDP port, a listening port
+//! takes connections to any of our addresses over either IP version.
+//!
+//! - **Receive**: in order only. A segment beyond `rcv_nxt` is dropped and
+//!   answered with a duplicate ACK, so the peer sends it again. The window
+//!   is the free space of the receive ring (no window scaling). ACKs wait
+//!   up to `DELAYED_ACK`, but every second segment is acknowledged at once
+//!   (RFC 1122 4.2.3.2).
+//! - **Send**: as much of the send ring as the peer's window and the MSS
+//!   allow; there is no congestion control. Each connection has one
+//!   retransmission timer (RFC 6298). When it expires everything not yet
+//!   acknowledged is sent again (go-back-N) and the RTO doubles; after
+//!   `MAX_RETRANSMITS` the connection fails with `NetError::TimedOut`. The
+//!   same timer probes a zero window.
+//! - **Teardown**: closing the last handle sends a FIN after the queued
+//!   data (a RST if received data was never read) and the TCB finishes the
+//!   close on its own. `TIME_WAIT` is much shorter than 2 MSL, and a full
+//!   table reuses connections in it.
+//!
+//! Not supported: options other than MSS, urgent data, half-close,
+//! reassembly of out-of-order segments and keepalives.
+//!
+//! Timers are `Instant` deadlines, checked by [`run_timers`] on every pass
+//! of the first softnet task. `WAIT` is woken on every change a socket may
+//! wait for: data, room to send, a connection to accept, the handshake
+//! completing, and errors.
+
+use super::addr::{IpAddr, Ipv4Addr, SocketAddr};
+use super::{ipv4, NetError};
+use crate::sync::WaitQueue;
+use crate::time::{Duration, Instant};
+use spin::Mutex;
+
+/// Length of the header without options
+const HEADER_LEN: usize = 20;
+
+/// Length of the header of a SYN, which carries the MSS option
+const SYN_HEADER_LEN: usize = 24;
+
+/// Protocol number, the same in IPv4 and IPv6
+const PROTOCOL: u8 = ipv4::PROTO_TCP;
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+/// Connections, listeners and unconnected sockets, over all processes
+pub const MAX_CONNECTIONS: usize = 16;
+
+/// Size of each receive and send ring
+const BUFFER_SIZE: usize = 4096;
+
+/// Most connections a listener queues for accept
+const MAX_BACKLOG: usize = 8;
+
+/// Largest segment payload: a 1500 byte MTU less the IPv4 and TCP headers
+const MAX_MSS: usize = 1500 - ipv4::HEADER_LEN - HEADER_LEN;
+
+/// MSS assumed when the peer sends none (RFC 9293 3.7.1)
+const DEFAULT_MSS_V4: u16 = 536;
+const DEFAULT_MSS_V6: u16 = 1220;
+
+/// Retransmission timeout bounds (RFC 6298)
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MIN_RTO: Duration = Duration::from_millis(200);
+const MAX_RTO: Duration = Duration::from_secs(60);
+
+/// Retransmissions of a SYN, and of data or a FIN, before giving up
+const MAX_SYN_RETRANSMITS: u32 = 5;
+const MAX_RETRANSMITS: u32 = 8;
+
+/// Longest an ACK is held back
+const DELAYED_ACK: Duration = Duration::from_millis(100);
+
+/// Length of TIME_WAIT; 2 MSL would hold slots of the small table for
+/// minutes
+const TIME_WAIT: Duration = Duration::from_secs(4);
+
+/// How long a closed connection waits for the peer's FIN in FIN_WAIT_2
+const FIN_WAIT2_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Ports handed out when binding port 0
+const EPHEMERAL_PORTS: core::ops::Range<u16> = 49152..65535;
+
+/// Whether sequence number `a` comes before `b`, modulo 2^32
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+/// Header fields the stack uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    /// MSS option
+    mss: Option<u16>,
+}
+
+impl Header {
+    /// Sequence numbers taken by a segment with `payload_len` bytes: the
+    /// data plus one each for SYN and FIN
+    fn seq_len(&self, payload_len: usize) -> u32 {
+        payload_len as u32 + (self.flags & SYN != 0) as u32 + (self.flags & FIN != 0) as u32
+    }
+}
+
+/// Split `segment` into its header and payload; the checksum is not checked
+fn parse(segment: &[u8]) -> Option<(Header, &[u8])> {
+    if segment.len() < HEADER_LEN {
+        return None;
+    }
+    let header_len = (segment[12] >> 4) as usize * 4;
+    if header_len < HEADER_LEN || header_len > segment.len() {
+        return None;
+    }
+    let mut mss = None;
+    let mut options = &segment[HEADER_LEN..header_len];
+    while let [kind, rest @ ..] = options {
+        match *kind {
+            OPTION_END => break,
+            OPTION_NOP => options = rest,
+            _ => {
+                let len = *rest.first()? as usize;
+                if len < 2 || len > options.len() {
+                    return None;
+                }
+                if *kind == OPTION_MSS && len == 4 {
+                    mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                }
+                options = &options[len..];
+            }
+        }
+    }
+    let header = Header {
+        src_port: u16::from_be_bytes([segment[0], segment[1]]),
+        dst_port: u16::from_be_bytes([segment[2], segment[3]]),
+        seq: u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]),
+        ack: u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]),
+        flags: segment[13],
+        window: u16::from_be_bytes([segment[14], segment[15]]),
+        mss,
+    };
+    Some((header, &segment[header_len..]))
+}
+
+/// Fill in the first `HEADER_LEN` bytes of `segment`, or `SYN_HEADER_LEN`
+/// with an MSS option; the checksum is left zero
+fn write_header(segment: &mut [u8], header: &Header) {
+    let header_len = if header.mss.is_some() { SYN_HEADER_LEN } else { HEADER_LEN };
+    let segment = &mut segment[..header_len];
+    segment.fill(0);
+    segment[0..2].copy_from_slice(&header.src_port.to_be_bytes());
+    segment[2..4].copy_from_slice(&header.dst_port.to_be_bytes());
+    segment[4..8].copy_from_slice(&header.seq.to_be_bytes());
+    segment[8..12].copy_from_slice(&header.ack.to_be_bytes());
+    segment[12] = ((header_len / 4) as u8) << 4;
+    segment[13] = header.flags;
+    segment[14..16].copy_from_slice(&header.window.to_be_bytes());
+    if let Some(mss) = header.mss {
+        segment[20] = OPTION_MSS;
+        segment[21] = 4;
+        segment[22..24].copy_from_slice(&mss.to_be_bytes());
+    }
+}
+
+/// Fill in the checksum of `segment` from `local` to `remote`
+fn finish(local: &SocketAddr, remote: &SocketAddr, segment: &mut [u8]) {
+    segment[16..18].fill(0);
+    let mut sum = super::pseudo_header(&local.ip, &remote.ip, segment.len(), PROTOCOL);
+    sum.add(segment);
+    let sum = sum.finish();
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+}
+
+/// Checksum and send `segment`, header written, from `local` to `remote`
+///
+/// A failed send is left to the retransmission timer, like a lost segment.
+fn send_segment(local: &SocketAddr, remote: &SocketAddr, segment: &mut [u8]) {
+    finish(local, remote, segment);
+    let _ = super::send_ip(&local.ip, &remote.ip, PROTOCOL, segment);
+}
+
+/// Answer a segment for no connection with a reset (RFC 9293 3.10.7.1)
+fn reset(local: &SocketAddr, remote: &SocketAddr, header: &Header, payload_len: usize) {
+    if header.flags & RST != 0 {
+        return;
+    }
+    let (seq, ack, flags) = match header.flags & ACK {
+        0 => (0, header.seq.wrapping_add(header.seq_len(payload_len)), RST | ACK),
+        _ => (header.ack, 0, RST),
+    };
+    let mut segment = [0u8; HEADER_LEN];
+    let header = Header { src_port: local.port, dst_port: remote.port, seq, ack, flags, window: 0, mss: None };
+    write_header(&mut segment, &header);
+    send_segment(local, remote, &mut segment);
+}
+
+/// Largest segment we take over `ip`'s IP version on a 1500 byte MTU
+fn local_mss(ip: &IpAddr) -> u16 {
+    match ip {
+        IpAddr::V4(_) => MAX_MSS as u16,
+        IpAddr::V6(_) => (1500 - super::ipv6::HEADER_LEN - HEADER_LEN) as u16,
+    }
+}
+
+/// Byte ring of a connection's received or unacknowledged data
+struct Ring {
+    data: [u8; BUFFER_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self { data: [0; BUFFER_SIZE], start: 0, len: 0 }
+    }
+
+    fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    fn free(&self) -> usize {
+        BUFFER_SIZE - self.len
+    }
+
+    /// Append as much of `bytes` as fits; returns how much did
+    fn push(&mut self, bytes: &[u8]) -> usize {
+        let n = bytes.len().min(self.free());
+        for (i, &byte) in bytes[..n].iter().enumerate() {
+            self.data[(self.start + self.len + i) % BUFFER_SIZE] = byte;
+        }
+        self.len += n;
+        n
+    }
+
+    /// Copy bytes from `offset` on into `out`; returns how many
+    fn peek(&self, offset: usize, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.len.saturating_sub(offset));
+        for (i, byte) in out[..n].iter_mut().enumerate() {
+            *byte = self.data[(self.start + offset + i) % BUFFER_SIZE];
+        }
+        n
+    }
+
+    /// Drop the first `n` bytes
+    fn discard(&mut self, n: usize) {
+        let n = n.min(self.len);
+        self.start = (self.start + n) % BUFFER_SIZE;
+        self.len -= n;
+    }
+
+    fn pop(&mut self, out: &mut [u8]) -> usize {
+        let n = self.peek(0, out);
+        self.discard(n);
+        n
+    }
+}
+
+/// Window to advertise: the free space of the receive ring
+fn window(rx: &Ring) -> u32 {
+    rx.free().min(u16::MAX as usize) as u32
+}
+
+/// Connection states (RFC 9293 3.3.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl State {
+    /// Whether the handshake is complete
+    fn synchronized(self) -> bool {
+        !matches!(self, State::Closed | State::Listen | State::SynSent | State::SynReceived)
+    }
+}
+
+/// What to do with a TCB after it handled an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Next {
+    Keep,
+    /// Keep it and wake `WAIT`
+    Wake,
+    /// The connection is over
+    Close,
+}
+
+/// Transmission control block
+#[derive(Clone, Copy)]
+struct Tcb {
+    state: State,
+    /// Local address; the IP stays unspecified until connected
+    local: SocketAddr,
+    remote: SocketAddr,
+    /// Listener whose accept queue holds the connection
+    parent: Option<usize>,
+    /// No socket refers to the TCB any more; it is freed once closed
+    orphan: bool,
+    /// Most connections a listener queues
+    backlog: usize,
+    /// Why the connection failed
+    error: Option<NetError>,
+
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    /// Highest sequence number sent; beyond `snd_nxt` after a timeout
+    snd_max: u32,
+    snd_wnd: u32,
+    /// Sequence and acknowledgment numbers of the last window update
+    snd_wl1: u32,
+    snd_wl2: u32,
+    mss: u16,
+    /// The socket was closed: send a FIN after the data
+    fin_queued: bool,
+    /// Sequence number of our FIN, once sent
+    fin_seq: Option<u32>,
+
+    rcv_nxt: u32,
+    /// Window in the last ACK sent
+    rcv_wnd: u32,
+    /// The peer's FIN is in
+    fin_received: bool,
+    /// Segments received since the last ACK
+    unacked: u32,
+
+    rto: Duration,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    retransmits: u32,
+    /// End and send time of the segment timed for an RTT sample
+    timing: Option<(u32, Instant)>,
+    retransmit_at: Option<Instant>,
+    ack_at: Option<Instant>,
+    /// End of TIME_WAIT, or of FIN_WAIT_2 for an orphan
+    close_at: Option<Instant>,
+}
+
+impl Tcb {
+    fn new(local: SocketAddr) -> Self {
+        let unspecified = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        Self {
+            state: State::Closed,
+            local,
+            remote: unspecified,
+            parent: None,
+            orphan: false,
+            backlog: 0,
+            error: None,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_max: 0,
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            mss: DEFAULT_MSS_V4,
+            fin_queued: false,
+            fin_seq: None,
+            rcv_nxt: 0,
+            rcv_wnd: 0,
+            fin_received: false,
+            unacked: 0,
+            rto: INITIAL_RTO,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            retransmits: 0,
+            timing: None,
+            retransmit_at: None,
+            ack_at: None,
+            close_at: None,
+        }
+    }
+
+    /// Pick an initial sequence number for a new connection
+    fn open(&mut self) {
+        self.iss = crate::rand::random_u64() as u32;
+        self.snd_una = self.iss;
+        self.snd_nxt = self.iss;
+        self.snd_max = self.iss;
+    }
+
+    /// Take the peer's initial sequence number, window and MSS from its SYN
+    fn synchronize(&mut self, header: &Header) {
+        self.rcv_nxt = header.seq.wrapping_add(1);
+        self.snd_wnd = header.window as u32;
+        self.snd_wl1 = header.seq;
+        self.snd_wl2 = header.ack;
+        let default = match self.remote.ip {
+            IpAddr::V4(_) => DEFAULT_MSS_V4,
+            IpAddr::V6(_) => DEFAULT_MSS_V6,
+        };
+        self.mss = header.mss.unwrap_or(default).clamp(1, local_mss(&self.local.ip));
+    }
+
+    fn arm(&mut self) {
+        self.retransmit_at = Some(Instant::now().saturating_add(self.rto));
+    }
+
+    /// Fill in the header of `segment`, whose payload follows it, and send
+    /// it; a segment with ACK carries the current window and cancels a
+    /// delayed ACK
+    fn transmit(&mut self, rx: &Ring, seq: u32, flags: u8, segment: &mut [u8]) {
+        let window = window(rx);
+        let header = Header {
+            src_port: self.local.port,
+            dst_port: self.remote.port,
+            seq,
+            ack: if flags & ACK != 0 { self.rcv_nxt } else { 0 },
+            flags,
+            window: window as u16,
+            mss: (flags & SYN != 0).then(|| local_mss(&self.local.ip)),
+        };
+        write_header(segment, &header);
+        send_segment(&self.local, &self.remote, segment);
+        if flags & ACK != 0 {
+            self.rcv_wnd = window;
+            self.unacked = 0;
+            self.ack_at = None;
+        }
+    }
+
+    /// Send a segment without data
+    fn send_control(&mut self, rx: &Ring, seq: u32, flags: u8) {
+        let mut segment = [0u8; SYN_HEADER_LEN];
+        let len = if flags & SYN != 0 { SYN_HEADER_LEN } else { HEADER_LEN };
+        self.transmit(rx, seq, flags, &mut segment[..len]);
+    }
+
+    fn send_ack(&mut self, rx: &Ring) {
+        self.send_control(rx, self.snd_nxt, ACK);
+    }
+
+    /// Account for `len` sequence numbers just sent at `snd_nxt`
+    fn sent(&mut self, len: u32) {
+        // Karn's algorithm: only time segments sent for the first time
+        if self.timing.is_none() && self.snd_nxt == self.snd_max {
+            self.timing = Some((self.snd_nxt.wrapping_add(len), Instant::now()));
+        }
+        self.snd_nxt = self.snd_nxt.wrapping_add(len);
+        if seq_lt(self.snd_max, self.snd_nxt) {
+            self.snd_max = self.snd_nxt;
+        }
+        if self.retransmit_at.is_none() {
+            self.arm();
+        }
+    }
+
+    fn fin_acked(&self) -> bool {
+        self.fin_seq.is_some_and(|fin| seq_lt(fin, self.snd_una))
+    }
+
+    /// Send what the peer's window allows of the data not sent yet, and our
+    /// FIN once all of it is out
+    fn output(&mut self, rx: &Ring, tx: &Ring) {
+        if !matches!(
+            self.state,
+            State::Established | State::CloseWait | State::FinWait1 | State::Closing | State::LastAck
+        ) {
+            return;
+        }
+        loop {
+            // Offset of the first unsent byte; past the data once the FIN
+            // is out
+            let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            if offset >= tx.len {
+                break;
+            }
+            let usable = self.snd_wnd.saturating_sub(offset as u32) as usize;
+            let n = (tx.len - offset).min(usable).min(self.mss as usize);
+            if n == 0 {
+                break;
+            }
+            let mut segment = [0u8; HEADER_LEN + MAX_MSS];
+            tx.peek(offset, &mut segment[HEADER_LEN..HEADER_LEN + n]);
+            let flags = if offset + n == tx.len { ACK | PSH } else { ACK };
+            self.transmit(rx, self.snd_nxt, flags, &mut segment[..HEADER_LEN + n]);
+            self.sent(n as u32);
+        }
+
+        let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        if self.fin_queued && offset == tx.len && !self.fin_acked() {
+            self.fin_seq = Some(self.snd_nxt);
+            self.send_control(rx, self.snd_nxt, FIN | ACK);
+            self.sent(1);
+        } else if offset < tx.len && self.snd_wnd == 0 && self.retransmit_at.is_none() {
+            // Probe the zero window when the timer expires
+            self.arm();
+        }
+    }
+
+    /// Take an RTT measurement into the RTO (RFC 6298 2)
+    fn rtt_sample(&mut self, rtt: Duration) {
+        let rtt = rtt.as_nanos();
+        let (srtt, rttvar) = match self.srtt {
+            None => (rtt, rtt / 2),
+            Some(srtt) => {
+                let srtt = srtt.as_nanos();
+                let rttvar = (3 * self.rttvar.as_nanos() + srtt.abs_diff(rtt)) / 4;
+                ((7 * srtt + rtt) / 8, rttvar)
+            }
+        };
+        self.srtt = Some(Duration::from_nanos(srtt));
+        self.rttvar = Duration::from_nanos(rttvar);
+        let rto = Duration::from_nanos(srtt + (4 * rttvar).max(Duration::TICK.as_nanos()));
+        self.rto = rto.clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// The peer acknowledged everything before `ack`
+    fn acked(&mut self, ack: u32, tx: &mut Ring) {
+        let mut data = ack.wrapping_sub(self.snd_una);
+        if self.fin_seq.is_some_and(|fin| seq_le(self.snd_una, fin) && seq_lt(fin, ack)) {
+            data -= 1;
+        }
+        tx.discard(data as usize);
+        if let Some((end, sent)) = self.timing {
+            if seq_le(end, ack) {
+                self.timing = None;
+                self.rtt_sample(sent.elapsed());
+            }
+        }
+        self.snd_una = ack;
+        if seq_lt(self.snd_nxt, ack) {
+            self.snd_nxt = ack;
+        }
+        self.retransmits = 0;
+        self.retransmit_at = None;
+        if self.snd_una != self.snd_max {
+            self.arm();
+        }
+    }
+
+    fn enter_time_wait(&mut self) {
+        self.state = State::TimeWait;
+        self.close_at = Some(Instant::now().saturating_add(TIME_WAIT));
+        self.retransmit_at = None;
+    }
+
+    /// Whether a segment at `seq` taking `len` sequence numbers overlaps the
+    /// receive window (RFC 9293 3.10.7.4)
+    fn acceptable(&self, seq: u32, len: u32, rx: &Ring) -> bool {
+        let window = window(rx);
+        let in_window = |seq: u32| seq_le(self.rcv_nxt, seq) && seq_lt(seq, self.rcv_nxt.wrapping_add(window));
+        match (len, window) {
+            (0, 0) => seq == self.rcv_nxt,
+            (0, _) => in_window(seq),
+            (_, 0) => false,
+            _ => in_window(seq) || in_window(seq.wrapping_add(len - 1)),
+        }
+    }
+
+    /// Handle a segment in SYN_SENT (RFC 9293 3.10.7.3)
+    fn syn_sent_arrives(&mut self, header: &Header, payload_len: usize, rx: &Ring, tx: &mut Ring) -> Next {
+        let ack_ok = seq_lt(self.iss, header.ack) && seq_le(header.ack, self.snd_max);
+        if header.flags & ACK != 0 && !ack_ok {
+            reset(&self.local, &self.remote, header, payload_len);
+            return Next::Keep;
+        }
+        if header.flags & RST != 0 {
+            if header.flags & ACK == 0 {
+                return Next::Keep;
+            }
+            self.error = Some(NetError::ConnectionRefused);
+            return Next::Close;
+        }
+        if header.flags & SYN == 0 {
+            return Next::Keep;
+        }
+        self.synchronize(header);
+        if header.flags & ACK == 0 {
+            // Simultaneous open
+            self.state = State::SynReceived;
+            self.send_control(rx, self.iss, SYN | ACK);
+            return Next::Keep;
+        }
+        self.snd_una = self.iss;
+        self.state = State::Established;
+        self.acked(header.ack, tx);
+        self.send_ack(rx);
+        Next::Wake
+    }
+
+    /// Handle a segment for the connection (RFC 9293 3.10.7.4)
+    fn arrives(&mut self, header: &Header, payload: &[u8], rx: &mut Ring, tx: &mut Ring) -> Next {
+        if self.state == State::SynSent {
+            return self.syn_sent_arrives(header, payload.len(), rx, tx);
+        }
+        if !self.acceptable(header.seq, header.seq_len(payload.len()), rx) {
+            if header.flags & RST == 0 {
+                self.send_ack(rx);
+            }
+            return Next::Keep;
+        }
+        // RFC 5961: only a reset at exactly `rcv_nxt` counts, and a SYN is
+        // answered with a challenge ACK
+        if header.flags & RST != 0 {
+            if header.seq != self.rcv_nxt {
+                self.send_ack(rx);
+                return Next::Keep;
+            }
+            self.error = Some(NetError::ConnectionReset);
+            return Next::Close;
+        }
+        if header.flags & SYN != 0 {
+            self.send_ack(rx);
+            return Next::Keep;
+        }
+        if header.flags & ACK == 0 {
+            return Next::Keep;
+        }
+
+        let mut next = Next::Keep;
+        if self.state == State::SynReceived {
+            if !(seq_lt(self.snd_una, header.ack) && seq_le(header.ack, self.snd_max)) {
+                reset(&self.local, &self.remote, header, payload.len());
+                return Next::Keep;
+            }
+            self.state = State::Established;
+            self.snd_wnd = header.window as u32;
+            self.snd_wl1 = header.seq;
+            self.snd_wl2 = header.ack;
+            next = Next::Wake;
+        }
+        if seq_lt(self.snd_max, header.ack) {
+            // Acknowledges something never sent
+            self.send_ack(rx);
+            return next;
+        }
+        if seq_lt(self.snd_una, header.ack) {
+            self.acked(header.ack, tx);
+            next = Next::Wake;
+        }
+        if seq_lt(self.snd_wl1, header.seq) || (self.snd_wl1 == header.seq && seq_le(self.snd_wl2, header.ack)) {
+            self.snd_wnd = header.window as u32;
+            self.snd_wl1 = header.seq;
+            self.snd_wl2 = header.ack;
+        }
+        if self.fin_acked() {
+            match self.state {
+                State::FinWait1 => {
+                    self.state = State::FinWait2;
+                    if self.orphan {
+                        self.close_at = Some(Instant::now().saturating_add(FIN_WAIT2_TIMEOUT));
+                    }
+                }
+                State::Closing => self.enter_time_wait(),
+                State::LastAck => return Next::Close,
+                _ => {}
+            }
+        }
+
+        let mut ack_now = false;
+        if !payload.is_empty() && matches!(self.state, State::Established | State::FinWait1 | State::FinWait2) {
+            if seq_lt(self.rcv_nxt, header.seq) {
+                // Out of order: ask for what is missing
+                self.send_ack(rx);
+                return next;
+            }
+            let skip = self.rcv_nxt.wrapping_sub(header.seq) as usize;
+            let data = payload.get(skip..).unwrap_or_default();
+            let taken = rx.push(data);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(taken as u32);
+            if taken > 0 {
+                next = Next::Wake;
+            }
+            self.unacked += 1;
+            ack_now = taken < data.len() || self.unacked >= 2;
+            if taken < data.len() {
+                // The rest, and a FIN after it, comes again
+                self.send_ack(rx);
+                return next;
+            }
+        }
+
+        if header.flags & FIN != 0 && header.seq.wrapping_add(payload.len() as u32) == self.rcv_nxt {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.fin_received = true;
+            ack_now = true;
+            next = Next::Wake;
+            match self.state {
+                State::SynReceived | State::Established => self.state = State::CloseWait,
+                State::FinWait1 if self.fin_acked() => self.enter_time_wait(),
+                State::FinWait1 => self.state = State::Closing,
+                State::FinWait2 => self.enter_time_wait(),
+                _ => {}
+            }
+        }
+
+        self.output(rx, tx);
+        if ack_now {
+            self.send_ack(rx);
+        } else if self.unacked > 0 && self.ack_at.is_none() {
+            self.ack_at = Some(Instant::now().saturating_add(DELAYED_ACK));
+        }
+        next
+    }
+
+    /// The retransmission timer expired
+    fn retransmit(&mut self, rx: &Ring, tx: &Ring) -> Next {
+        self.retransmit_at = None;
+        if self.snd_una == self.snd_max {
+            let unsent = self.snd_nxt.wrapping_sub(self.snd_una) < tx.len as u32;
+            if self.state.synchronized() && unsent && self.snd_wnd == 0 {
+                // Zero window probe: an old sequence number draws an ACK
+                // with the current window
+                self.send_control(rx, self.snd_una.wrapping_sub(1), ACK);
+                self.rto = self.rto.saturating_add(self.rto).min(MAX_RTO);
+                self.arm();
+            }
+            return Next::Keep;
+        }
+
+        self.retransmits += 1;
+        let limit = match self.state {
+            State::SynSent | State::SynReceived => MAX_SYN_RETRANSMITS,
+            _ => MAX_RETRANSMITS,
+        };
+        if self.retransmits > limit {
+            if self.state.synchronized() {
+                self.send_control(rx, self.snd_nxt, RST | ACK);
+            }
+            self.error = Some(NetError::TimedOut);
+            return Next::Close;
+        }
+        self.rto = self.rto.saturating_add(self.rto).min(MAX_RTO);
+        self.timing = None;
+        match self.state {
+            State::SynSent => {
+                self.send_control(rx, self.iss, SYN);
+                self.arm();
+            }
+            State::SynReceived => {
+                self.send_control(rx, self.iss, SYN | ACK);
+                self.arm();
+            }
+            _ => {
+                self.snd_nxt = self.snd_una;
+                self.output(rx, tx);
+            }
+        }
+        Next::Keep
+    }
+
+    /// Run the timers that expired
+    fn timers(&mut self, rx: &Ring, tx: &Ring) -> Next {
+        if self.ack_at.is_some_and(|at| at.has_passed()) {
+            self.send_ack(rx);
+        }
+        if self.close_at.is_some_and(|at| at.has_passed()) {
+            return Next::Close;
+        }
+        if self.retransmit_at.is_some_and(|at| at.has_passed()) {
+            return self.retransmit(rx, tx);
+        }
+        Next::Keep
+    }
+}
+
+struct Table {
+    tcbs: [Option<Tcb>; MAX_CONNECTIONS],
+    /// Received data not read yet
+    rx: [Ring; MAX_CONNECTIONS],
+    /// Data written and not acknowledged yet
+    tx: [Ring; MAX_CONNECTIONS],
+}
+
+impl Table {
+    fn parts(&mut self, slot: usize) -> Option<(&mut Tcb, &mut Ring, &mut Ring)> {
+        let tcb = self.tcbs.get_mut(slot)?.as_mut()?;
+        Some((tcb, &mut self.rx[slot], &mut self.tx[slot]))
+    }
+
+    /// Store `tcb` in a free slot, or in place of the connection closest to
+    /// leaving TIME_WAIT
+    fn alloc(&mut self, tcb: Tcb) -> Option<usize> {
+        let slot = self.tcbs.iter().position(Option::is_none).or_else(|| {
+            (0..MAX_CONNECTIONS)
+                .filter_map(|slot| {
+                    let tcb = self.tcbs[slot].as_ref()?;
+                    (tcb.state == State::TimeWait).then_some((slot, tcb.close_at))
+                })
+                .min_by_key(|&(_, close_at)| close_at)
+                .map(|(slot, _)| slot)
+        })?;
+        self.tcbs[slot] = Some(tcb);
+        self.rx[slot].clear();
+        self.tx[slot].clear();
+        Some(slot)
+    }
+
+    /// Whether a TCB outside TIME_WAIT has local port `port`
+    fn port_in_use(&self, port: u16) -> bool {
+        self.tcbs.iter().flatten().any(|tcb| tcb.local.port == port && tcb.state != State::TimeWait)
+    }
+
+    fn pick_port(&self, port: u16) -> Result<u16, NetError> {
+        if port == 0 {
+            EPHEMERAL_PORTS.clone().find(|&port| !self.port_in_use(port)).ok_or(NetError::AddressInUse)
+        } else if self.port_in_use(port) {
+            Err(NetError::AddressInUse)
+        } else {
+            Ok(port)
+        }
+    }
+
+    /// Connection between `local` and `remote`
+    fn find(&self, local: &SocketAddr, remote: &SocketAddr) -> Option<usize> {
+        self.tcbs.iter().position(|tcb| {
+            tcb.as_ref().is_some_and(|tcb| {
+                tcb.local == *local && tcb.remote == *remote && !matches!(tcb.state, State::Closed | State::Listen)
+            })
+        })
+    }
+
+    fn listener(&self, port: u16) -> Option<usize> {
+        self.tcbs
+            .iter()
+            .position(|tcb| tcb.as_ref().is_some_and(|tcb| tcb.state == State::Listen && tcb.local.port == port))
+    }
+
+    /// Connection of `listener` that is ready to be accepted
+    fn acceptable(&self, listener: usize) -> Option<usize> {
+        self.tcbs.iter().position(|tcb| {
+            tcb.as_ref().is_some_and(|tcb| tcb.parent == Some(listener) && tcb.state.synchronized())
+        })
+    }
+
+    /// The connection in `slot` is over: free it unless a socket still
+    /// refers to it, which then sees it closed
+    fn closed(&mut self, slot: usize) {
+        let Some(tcb) = self.tcbs[slot].as_mut() else { return };
+        if tcb.orphan || tcb.parent.is_some() {
+            self.tcbs[slot] = None;
+            return;
+        }
+        tcb.state = State::Closed;
+        tcb.retransmit_at = None;
+        tcb.ack_at = None;
+        tcb.close_at = None;
+    }
+
+    /// A SYN came in for `listener`: queue a new connection in SYN_RECEIVED
+    fn listen_arrives(&mut self, listener: usize, local: &SocketAddr, remote: &SocketAddr, header: &Header) {
+        if header.flags & RST != 0 {
+            return;
+        }
+        if header.flags & ACK != 0 {
+            reset(local, remote, header, 0);
+            return;
+        }
+        if header.flags & SYN == 0 {
+            return;
+        }
+        // A full queue drops the SYN; the peer sends it again
+        let queued = self.tcbs.iter().flatten().filter(|tcb| tcb.parent == Some(listener)).count();
+        if queued >= self.tcbs[listener].map_or(0, |tcb| tcb.backlog) {
+            return;
+        }
+        let mut tcb = Tcb::new(*local);
+        tcb.remote = *remote;
+        tcb.parent = Some(listener);
+        tcb.state = State::SynReceived;
+        tcb.open();
+        tcb.synchronize(header);
+        let Some(slot) = self.alloc(tcb) else { return };
+        let Some((tcb, rx, _)) = self.parts(slot) else { return };
+        tcb.send_control(rx, tcb.iss, SYN | ACK);
+        tcb.sent(1);
+    }
+
+    /// Close the socket's reference to `slot`
+    fn close(&mut self, slot: usize) {
+        let Some(state) = self.tcbs.get(slot).copied().flatten().map(|tcb| tcb.state) else { return };
+        match state {
+            State::Closed | State::SynSent => self.tcbs[slot] = None,
+            State::Listen => {
+                for child in 0..MAX_CONNECTIONS {
+                    if self.tcbs[child].is_some_and(|tcb| tcb.parent == Some(slot)) {
+                        self.abort(child);
+                    }
+                }
+                self.tcbs[slot] = None;
+            }
+            // Unread data would be lost: tell the peer (RFC 2525 2.17)
+            State::SynReceived => self.abort(slot),
+            State::Established | State::CloseWait if self.rx[slot].len > 0 => self.abort(slot),
+            _ => {
+                let Some((tcb, rx, tx)) = self.parts(slot) else { return };
+                tcb.orphan = true;
+                match state {
+                    State::Established => tcb.state = State::FinWait1,
+                    State::CloseWait => tcb.state = State::LastAck,
+                    _ => return,
+                }
+                tcb.fin_queued = true;
+                tcb.output(rx, tx);
+            }
+        }
+    }
+
+    /// Reset the connection in `slot` and free it
+    fn abort(&mut self, slot: usize) {
+        if let Some((tcb, rx, _)) = self.parts(slot) {
+            tcb.send_control(rx, tcb.snd_nxt, RST | ACK);
+        }
+        self.tcbs[slot] = None;
+    }
+}
+
+static TABLE: Mutex<Table> = Mutex::new(Table {
+    tcbs: [None; MAX_CONNECTIONS],
+    rx: [const { Ring::new() }; MAX_CONNECTIONS],
+    tx: [const { Ring::new() }; MAX_CONNECTIONS],
+});
+
+/// Tasks waiting for any connection to change
+pub static WAIT: WaitQueue = WaitQueue::new();
+
+/// Create an unbound, unconnected TCB; returns its number
+pub fn create() -> Result<u32, NetError> {
+    let unspecified = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+    let slot = TABLE.lock().alloc(Tcb::new(unspecified)).ok_or(NetError::TooManyEndpoints)?;
+    Ok(slot as u32)
+}
+
+/// Bind `conn` to `port` (0 picks a free ephemeral port); returns the port
+pub fn bind(conn: u32, port: u16) -> Result<u16, NetError> {
+    let mut table = TABLE.lock();
+    let tcb = table.tcbs.get(conn as usize).copied().flatten().ok_or(NetError::NotBound)?;
+    if tcb.state != State::Closed || tcb.local.port != 0 {
+        return Err(NetError::AlreadyBound);
+    }
+    let port = table.pick_port(port)?;
+    if let Some(tcb) = table.tcbs[conn as usize].as_mut() {
+        tcb.local.port = port;
+    }
+    Ok(port)
+}
+
+/// Make `conn` a listener queueing up to `backlog` connections, binding an
+/// ephemeral port first if it has none
+pub fn listen(conn: u32, backlog: usize) -> Result<(), NetError> {
+    let mut table = TABLE.lock();
+    let tcb = table.tcbs.get(conn as usize).copied().flatten().ok_or(NetError::NotBound)?;
+    if !matches!(tcb.state, State::Closed | State::Listen) {
+        return Err(NetError::InvalidState);
+    }
+    let port = match tcb.local.port {
+        0 => table.pick_port(0)?,
+        port => port,
+    };
+    if let Some(tcb) = table.tcbs[conn as usize].as_mut() {
+        tcb.local.port = port;
+        tcb.state = State::Listen;
+        tcb.backlog = backlog.clamp(1, MAX_BACKLOG);
+    }
+    Ok(())
+}
+
+/// Start connecting `conn` to `remote`; [`connect_status`] tells when the
+/// handshake is over
+pub fn connect(conn: u32, remote: &SocketAddr) -> Result<(), NetError> {
+    let local_ip = super::source_for(&remote.ip)?;
+    let mut table = TABLE.lock();
+    let tcb = table.tcbs.get(conn as usize).copied().flatten().ok_or(NetError::NotBound)?;
+    match tcb.state {
+        State::Closed => {}
+        State::SynSent | State::SynReceived => return Err(NetError::Already),
+        State::Listen => return Err(NetError::InvalidState),
+        _ => return Err(NetError::AlreadyConnected),
+    }
+    let port = match tcb.local.port {
+        0 => table.pick_port(0)?,
+        port => port,
+    };
+    let local = SocketAddr::new(local_ip, port);
+    if table.find(&local, remote).is_some() {
+        return Err(NetError::AddressInUse);
+    }
+
+    let Some((tcb, rx, _)) = table.parts(conn as usize) else { return Err(NetError::NotBound) };
+    *tcb = Tcb::new(local);
+    tcb.remote = *remote;
+    tcb.state = State::SynSent;
+    tcb.open();
+    tcb.send_control(rx, tcb.iss, SYN);
+    tcb.sent(1);
+    Ok(())
+}
+
+/// Outcome of the handshake [`connect`] started: `None` while it runs,
+/// then the connection's error if it failed
+pub fn connect_status(conn: u32) -> Option<Result<(), NetError>> {
+    let mut table = TABLE.lock();
+    let tcb = table.tcbs.get_mut(conn as usize).and_then(Option::as_mut)?;
+    match tcb.state {
+        State::SynSent | State::SynReceived => None,
+        State::Closed | State::Listen => Some(Err(tcb.error.take().unwrap_or(NetError::NotConnected))),
+        _ => Some(Ok(())),
+    }
+}
+
+/// Take a connection off `listener`'s queue; returns its number and the
+/// peer's address, or `None` if none is established yet
+pub fn accept(listener: u32) -> Result<Option<(u32, SocketAddr)>, NetError> {
+    let mut table = TABLE.lock();
+    let listening = table.tcbs.get(listener as usize).copied().flatten().is_some_and(|tcb| tcb.state == State::Listen);
+    if !listening {
+        return Err(NetError::InvalidState);
+    }
+    let Some(slot) = table.acceptable(listener as usize) else { return Ok(None) };
+    let Some(tcb) = table.tcbs[slot].as_mut() else { return Ok(None) };
+    tcb.parent = None;
+    Ok(Some((slot as u32, tcb.remote)))
+}
+
+/// Queue as much of `data` as fits for sending on `conn`; returns how much
+/// did (0 while the send ring is full or the handshake is still running)
+pub fn send(conn: u32, data: &[u8]) -> Result<usize, NetError> {
+    let mut table = TABLE.lock();
+    let (tcb, rx, tx) = table.parts(conn as usize).ok_or(NetError::NotBound)?;
+    if let Some(error) = tcb.error {
+        return Err(error);
+    }
+    match tcb.state {
+        State::Established | State::CloseWait => {
+            let n = tx.push(data);
+            tcb.output(rx, tx);
+            Ok(n)
+        }
+        State::SynSent | State::SynReceived => Ok(0),
+        _ => Err(NetError::NotConnected),
+    }
+}
+
+/// Read received data of `conn` into `buf`
+///
+/// Returns the number of bytes, 0 once the peer closed its side and all
+/// data was read, or `None` if nothing arrived yet.
+pub fn recv(conn: u32, buf: &mut [u8]) -> Result<Option<usize>, NetError> {
+    let mut table = TABLE.lock();
+    let (tcb, rx, _) = table.parts(conn as usize).ok_or(NetError::NotBound)?;
+    if rx.len > 0 {
+        let n = rx.pop(buf);
+        // Tell the peer once the window opened by a segment or half the
+        // ring (RFC 1122 4.2.3.3)
+        let threshold = (tcb.mss as u32).min(BUFFER_SIZE as u32 / 2);
+        if tcb.state.synchronized() && window(rx) >= tcb.rcv_wnd + threshold {
+            tcb.send_ack(rx);
+        }
+        return Ok(Some(n));
+    }
+    if tcb.fin_received {
+        return Ok(Some(0));
+    }
+    if let Some(error) = tcb.error {
+        return Err(error);
+    }
+    match tcb.state {
+        State::SynSent | State::SynReceived | State::Established => Ok(None),
+        _ => Err(NetError::NotConnected),
+    }
+}
+
+/// Whether a read or accept on `conn` would not block
+pub fn readable(conn: u32) -> bool {
+    let table = TABLE.lock();
+    let Some(tcb) = table.tcbs.get(conn as usize).copied().flatten() else { return false };
+    table.rx[conn as usize].len > 0
+        || tcb.fin_received
+        || tcb.error.is_some()
+        || (tcb.state == State::Listen && table.acceptable(conn as usize).is_some())
+}
+
+/// Whether a write on `conn` would not block
+pub fn writable(conn: u32) -> bool {
+    let table = TABLE.lock();
+    let Some(tcb) = table.tcbs.get(conn as usize).copied().flatten() else { return false };
+    tcb.error.is_some()
+        || (matches!(tcb.state, State::Established | State::CloseWait) && table.tx[conn as usize].free() > 0)
+}
+
+/// Drop the socket's reference to `conn`: a listener resets its queued
+/// connections, a connection sends its FIN and finishes closing alone
+pub fn close(conn: u32) {
+    TABLE.lock().close(conn as usize);
+}
+
+/// Handle a received segment from `src` to `dst`
+pub fn receive(src: IpAddr, dst: IpAddr, segment: &[u8]) {
+    let unicast = match (&src, &dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => !dst.is_broadcast() && !dst.is_multicast() && !src.is_broadcast(),
+        (IpAddr::V6(src), IpAddr::V6(dst)) => !dst.is_multicast() && !src.is_multicast(),
+        _ => false,
+    };
+    if !unicast || segment.len() < HEADER_LEN {
+        return;
+    }
+    let mut sum = super::pseudo_header(&src, &dst, segment.len(), PROTOCOL);
+    sum.add(segment);
+    if sum.finish() != 0 {
+        return;
+    }
+    let Some((header, payload)) = parse(segment) else { return };
+    let local = SocketAddr::new(dst, header.dst_port);
+    let remote = SocketAddr::new(src, header.src_port);
+
+    let mut table = TABLE.lock();
+    let next = match table.find(&local, &remote) {
+        Some(slot) => {
+            let next = match table.parts(slot) {
+                Some((tcb, rx, tx)) => tcb.arrives(&header, payload, rx, tx),
+                None => Next::Keep,
+            };
+            if next == Next::Close {
+                table.closed(slot);
+            }
+            next
+        }
+        None => {
+            match table.listener(header.dst_port) {
+                Some(listener) => table.listen_arrives(listener, &local, &remote, &header),
+                None => reset(&local, &remote, &header, payload.len()),
+            }
+            Next::Keep
+        }
+    };
+    drop(table);
+    if next != Next::Keep {
+        WAIT.wake_all();
+    }
+}
+
+/// Run the expired timers of all connections; called by the softnet task
+pub fn run_timers() {
+    let mut table = TABLE.lock();
+    let mut wake = false;
+    for slot in 0..MAX_CONNECTIONS {
+        let next = match table.parts(slot) {
+            Some((tcb, rx, tx)) => tcb.timers(rx, tx),
+            None => continue,
+        };
+        if next == Next::Close {
+            table.closed(slot);
+        }
+        wake |= next != Next::Keep;
+    }
+    drop(table);
+    if wake {
+        WAIT.wake_all();
+    }
+}
+
+crate::kernel_test! {
+    /// A passive open completes, carries data to the accepted connection
+    /// and ends with the peer's reset
+    fn net_tcp_passive_open() {
+        crate::ktest_assert!(seq_lt(u32::MAX - 1, 2), "sequence numbers do not wrap");
+
+        let listener = create().map_err(|_| "table full")?;
+        let port = bind(listener, 0).map_err(|_| "bind failed")?;
+        listen(listener, 1).map_err(|_| "listen failed")?;
+        let client = SocketAddr::new(IpAddr::V4(Ipv4Addr([192, 0, 2, 9])), 40000);
+        let server = SocketAddr::new(IpAddr::V4(Ipv4Addr([192, 0, 2, 1])), port);
+        let segment = |header: Header, payload: &[u8]| {
+            let mut segment = [0u8; SYN_HEADER_LEN + 8];
+            write_header(&mut segment, &header);
+            let header_len = if header.mss.is_some() { SYN_HEADER_LEN } else { HEADER_LEN };
+            segment[header_len..header_len + payload.len()].copy_from_slice(payload);
+            let len = header_len + payload.len();
+            finish(&client, &server, &mut segment[..len]);
+            (segment, len)
+        };
+        let header = Header { src_port: 40000, dst_port: port, seq: 1000, ack: 0, flags: SYN, window: 1024, mss: Some(1000) };
+        let (syn, len) = segment(header, &[]);
+        crate::ktest_assert_eq!(parse(&syn[..len]), Some((header, &[][..])), "SYN round trip");
+        receive(client.ip, server.ip, &syn[..len]);
+
+        let (slot, iss, mss) = {
+            let table = TABLE.lock();
+            let slot = table.find(&server, &client).ok_or("no connection for the SYN")?;
+            let tcb = table.tcbs[slot].ok_or("connection gone")?;
+            (slot, tcb.iss, tcb.mss)
+        };
+        crate::ktest_assert_eq!(mss, 1000, "peer MSS");
+        let accepted = accept(listener);
+        crate::ktest_assert!(matches!(accepted, Ok(None)), "accepted before the handshake");
+
+        let ack = Header { seq: 1001, ack: iss.wrapping_add(1), flags: ACK | PSH, mss: None, ..header };
+        let (ack, len) = segment(ack, b"GET /");
+        receive(client.ip, server.ip, &ack[..len]);
+        let accepted = accept(listener);
+        crate::ktest_assert_eq!(accepted, Ok(Some((slot as u32, client))), "accept");
+        let mut buf = [0u8; 16];
+        crate::ktest_assert_eq!(recv(slot as u32, &mut buf), Ok(Some(5)), "data");
+        crate::ktest_assert_eq!(&buf[..5], b"GET /", "payload");
+
+        let rst = Header { seq: 1006, ack: 0, flags: RST, mss: None, ..header };
+        let (rst, len) = segment(rst, &[]);
+        receive(client.ip, server.ip, &rst[..len]);
+        let after_reset = recv(slot as u32, &mut buf);
+        close(slot as u32);
+        close(listener);
+        crate::ktest_assert_eq!(after_reset, Err(NetError::ConnectionReset), "reset");
+        crate::ktest_assert!(TABLE.lock().tcbs[slot].is_none(), "connection not freed");
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+//! UDP (RFC 768, RFC 8200 8.1 for IPv6)
+//!
+//! Kernel code binds a port to get an endpoint with a small receive queue.
+//! Endpoints are addressed with `SocketAddr` and take datagrams over either
+//! IP version. IPv6 datagrams leave from the link-local address of the
+//! first interface; IPv4 ones through the interface the routing table
+//! picks, from its address. [`broadcast`] reaches the whole link of one
+//! interface, address or not.
+//!
+//! `WAIT` is woken whenever a datagram is queued, for the sockets of
+//! `net::socket` blocked in recvfrom or poll.
+
+use super::addr::{IpAddr, Ipv4Addr, SocketAddr};
+use super::{ipv4, ipv6, Interface, NetError};
+use crate::sync::WaitQueue;
+use spin::Mutex;
+
+/// Length of the UDP header
+const HEADER_LEN: usize = 8;
+
+/// Protocol number, the same in IPv4 and IPv6
+const PROTOCOL: u8 = ipv4::PROTO_UDP;
+
+/// Largest payload that fits an unfragmented IPv6 packet on a 1500 byte MTU
+pub const MAX_PAYLOAD: usize = 1500 - ipv6::HEADER_LEN - HEADER_LEN;
+
+/// Number of bound endpoints
+const MAX_ENDPOINTS: usize = 8;
+
+/// Datagrams queued per endpoint; more are dropped
+const QUEUE_LEN: usize = 4;
+
+/// Ports handed out when binding port 0
+const EPHEMERAL_PORTS: core::ops::Range<u16> = 49152..65535;
+
+#[derive(Clone, Copy)]
+struct Datagram {
+    from: SocketAddr,
+    len: usize,
+    data: [u8; MAX_PAYLOAD],
+}
+
+struct Endpoint {
+    port: u16,
+    queue: [Option<Datagram>; QUEUE_LEN],
+    head: usize,
+    count: usize,
+}
+
+static ENDPOINTS: Mutex<[Option<Endpoint>; MAX_ENDPOINTS]> = Mutex::new([const { None }; MAX_ENDPOINTS]);
+
+/// Readers of all endpoints, woken when any endpoint queues a datagram
+pub static WAIT: WaitQueue = WaitQueue::new();
+
+/// Bind `port` (0 picks a free ephemeral port) and return it
+pub fn bind(port: u16) -> Result<u16, NetError> {
+    let mut endpoints = ENDPOINTS.lock();
+    let in_use = |endpoints: &[Option<Endpoint>], port| endpoints.iter().flatten().any(|e| e.port == port);
+    let port = if port == 0 {
+        EPHEMERAL_PORTS
+            .clone()
+            .find(|&port| !in_use(&*endpoints, port))
+            .ok_or(NetError::AddressInUse)?
+    } else if in_use(&*endpoints, port) {
+        return Err(NetError::AddressInUse);
+    } else {
+        port
+    };
+    let slot = endpoints
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(NetError::TooManyEndpoints)?;
+    *slot = Some(Endpoint { port, queue: [None; QUEUE_LEN], head: 0, count: 0 });
+    Ok(port)
+}
+
+/// Release `port` and drop its queued datagrams
+pub fn unbind(port: u16) {
+    for slot in ENDPOINTS.lock().iter_mut() {
+        if slot.as_ref().map_or(false, |e| e.port == port) {
+            *slot = None;
+        }
+    }
+}
+
+/// UDP checksum between `src` and `dst`, of the same IP version; zero is
+/// sent as 0xffff since zero means "none"
+fn checksum(src: &IpAddr, dst: &IpAddr, datagram: &[u8]) -> u16 {
+    let mut sum = super::pseudo_header(src, dst, datagram.len(), PROTOCOL);
+    sum.add(datagram);
+    match sum.finish() {
+        0 => 0xffff,
+        sum => sum,
+    }
+}
+
+/// Write a datagram of `data` from `src` port `port` to `to` into
+/// `datagram`; returns its length
+fn encode(src: &IpAddr, port: u16, to: &SocketAddr, data: &[u8], datagram: &mut [u8]) -> usize {
+    let len = HEADER_LEN + data.len();
+    datagram[0..2].copy_from_slice(&port.to_be_bytes());
+    datagram[2..4].copy_from_slice(&to.port.to_be_bytes());
+    datagram[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+    datagram[6..8].fill(0);
+    datagram[HEADER_LEN..len].copy_from_slice(data);
+    let sum = checksum(src, &to.ip, &datagram[..len]);
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    len
+}
+
+/// Send `data` from local port `port` to `to`
+pub fn send_to(port: u16, to: SocketAddr, data: &[u8]) -> Result<(), NetError> {
+    if data.len() > MAX_PAYLOAD {
+        return Err(NetError::TooLarge);
+    }
+    let src = super::source_for(&to.ip)?;
+    let mut datagram = [0u8; HEADER_LEN + MAX_PAYLOAD];
+    let len = encode(&src, port, &to, data, &mut datagram);
+    super::send_ip(&src, &to.ip, PROTOCOL, &datagram[..len])
+}
+
+/// Send `data` from local port `port` to `to_port` of every host on
+/// `iface`'s link (255.255.255.255)
+///
+/// Needs no route or address: an interface without IPv4 address sends from
+/// 0.0.0.0, as DHCP does.
+pub fn broadcast(iface: &Interface, port: u16, to_port: u16, data: &[u8]) -> Result<(), NetError> {
+    if data.len() > MAX_PAYLOAD {
+        return Err(NetError::TooLarge);
+    }
+    let src = iface.ipv4.map_or(Ipv4Addr::UNSPECIFIED, |config| config.addr);
+    let to = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), to_port);
+    let mut datagram = [0u8; HEADER_LEN + MAX_PAYLOAD];
+    let len = encode(&IpAddr::V4(src), port, &to, data, &mut datagram);
+    let header = ipv4::Header { src, dst: Ipv4Addr::BROADCAST, protocol: PROTOCOL, ttl: ipv4::DEFAULT_TTL };
+    ipv4::send_via(iface, &Ipv4Addr::BROADCAST, &header, &datagram[..len])
+}
+
+/// Take the oldest datagram queued on `port`
+///
+/// Returns its length (truncated to `buf`) and sender, or `None` if the
+/// queue is empty.
+pub fn recv_from(port: u16, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>, NetError> {
+    let mut endpoints = ENDPOINTS.lock();
+    let endpoint = endpoints
+        .iter_mut()
+        .flatten()
+        .find(|e| e.port == port)
+        .ok_or(NetError::NotBound)?;
+    if endpoint.count == 0 {
+        return Ok(None);
+    }
+    let datagram = endpoint.queue[endpoint.head].take();
+    endpoint.head = (endpoint.head + 1) % QUEUE_LEN;
+    endpoint.count -= 1;
+    Ok(datagram.map(|d| {
+        let len = d.len.min(buf.len());
+        buf[..len].copy_from_slice(&d.data[..len]);
+        (len, d.from)
+    }))
+}
+
+/// Whether `port` has a datagram queued
+pub fn readable(port: u16) -> bool {
+    ENDPOINTS.lock().iter().flatten().any(|e| e.port == port && e.count > 0)
+}
+
+/// Queue a datagram received from `src` for `dst` on its endpoint
+pub fn receive(src: IpAddr, dst: IpAddr, datagram: &[u8]) {
+    if datagram.len() < HEADER_LEN {
+        return;
+    }
+    let len = u16::from_be_bytes([datagram[4], datagram[5]]) as usize;
+    // The checksum is mandatory over IPv6; over IPv4 zero means none
+    let unchecked = datagram[6..8] == [0, 0];
+    if len < HEADER_LEN
+        || len > datagram.len()
+        || len - HEADER_LEN > MAX_PAYLOAD
+        || (unchecked && matches!(src, IpAddr::V6(_)))
+        || (!unchecked && checksum(&src, &dst, &datagram[..len]) != 0xffff)
+    {
+        return;
+    }
+    let src_port = u16::from_be_bytes([datagram[0], datagram[1]]);
+    let dst_port = u16::from_be_bytes([datagram[2], datagram[3]]);
+
+    let mut endpoints = ENDPOINTS.lock();
+    let Some(endpoint) = endpoints.iter_mut().flatten().find(|e| e.port == dst_port) else { return };
+    if endpoint.count == QUEUE_LEN {
+        return;
+    }
+    let mut d = Datagram {
+        from: SocketAddr::new(src, src_port),
+        len: len - HEADER_LEN,
+        data: [0; MAX_PAYLOAD],
+    };
+    d.data[..d.len].copy_from_slice(&datagram[HEADER_LEN..len]);
+    endpoint.queue[(endpoint.head + endpoint.count) % QUEUE_LEN] = Some(d);
+    endpoint.count += 1;
+    drop(endpoints);
+    WAIT.wake_all();
+}
+
+crate::kernel_test! {
+    /// A checksummed datagram reaches the bound endpoint with its sender
+    fn net_udp_endpoint() {
+        use super::addr::Ipv6Addr;
+
+        let port = bind(0).map_err(|_| "bind failed")?;
+        crate::ktest_assert_eq!(bind(port), Err(NetError::AddressInUse), "port bound twice");
+
+        let src = IpAddr::V6(Ipv6Addr::link_local([0x52, 0x54, 0x00, 0, 0, 1]));
+        let dst = IpAddr::V6(Ipv6Addr::link_local([0x52, 0x54, 0x00, 0, 0, 2]));
+        let mut datagram = [0u8; HEADER_LEN + 5];
+        datagram[0..2].copy_from_slice(&7u16.to_be_bytes());
+        datagram[2..4].copy_from_slice(&port.to_be_bytes());
+        datagram[4..6].copy_from_slice(&((HEADER_LEN + 5) as u16).to_be_bytes());
+        datagram[HEADER_LEN..].copy_from_slice(b"hello");
+        let sum = checksum(&src, &dst, &datagram);
+        datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+
+        let mut corrupt = datagram;
+        corrupt[HEADER_LEN] ^= 1;
+        receive(src, dst, &corrupt);
+        receive(src, dst, &datagram);
+        // No checksum is fine over IPv4 only
+        let mut unchecked = datagram;
+        unchecked[6..8].fill(0);
+        receive(src, dst, &unchecked);
+        receive(IpAddr::V4(Ipv4Addr([10, 0, 2, 2])), IpAddr::V4(Ipv4Addr([10, 0, 2, 15])), &unchecked);
+
+        let mut buf = [0u8; 16];
+        let first = recv_from(port, &mut buf);
+        let (len, from) = first.map_err(|_| "not bound")?.ok_or("datagram not queued")?;
+        crate::ktest_assert_eq!(&buf[..len], b"hello", "payload");
+        crate::ktest_assert_eq!(from, SocketAddr::new(src, 7), "sender");
+        let second = recv_from(port, &mut buf);
+        unbind(port);
+        let (_, from) = second.map_err(|_| "not bound")?.ok_or("IPv4 datagram not queued")?;
+        crate::ktest_assert_eq!(from.ip, IpAddr::V4(Ipv4Addr([10, 0, 2, 2])), "IPv4 sender");
+        crate::ktest_assert_eq!(recv_from(port, &mut buf), Err(NetError::NotBound), "port still bound");
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 use core::panic::PanicInfo;
 
+/// Console lines replayed to serial on panic
+const PANIC_HISTORY_LINES: usize = 40;
+
 /// Panic handler for the kernel
 /// This function is called when a panic occurs in no_std environment
 /// 
@@ -102,13 +105,27 @@ fn panic(info: &PanicInfo) -> ! {
             
             // Read return address from stack frame
             let ret_addr = rbp.offset(1).read();
-            serial_println!("  #{}: {:#018x}", i, ret_addr);
+            serial_println!("  #{}: {}", i, crate::ksyms::Symbolized(ret_addr));
             
             // Move to previous frame
             rbp = (*rbp) as *const u64;
         }
     }
 
+    // With the console on the screen only, serial has not seen what led
+    // up to the panic
+    if crate::console::mode() == crate::console::ConsoleMode::Framebuffer {
+        serial_println!("--------------------------------------------------------------------------------");
+        serial_println!("Console (last {} lines):", PANIC_HISTORY_LINES);
+        crate::console::dump_history(PANIC_HISTORY_LINES);
+    }
+
+    // A panic inside a kernel test counts as a failure; let CI see it
+    if crate::ktest::is_running() {
+        serial_println!("[KTEST] Test panicked, exiting with failure");
+        crate::ktest::exit_qemu(crate::ktest::QemuExitCode::Failed);
+    }
+
     serial_println!("================================================================================");
     serial_println!("System halted. Please reboot.");
     serial_println!("================================================================================");
@@ -0,0 +1,236 @@
+//! Kernel sampling profiler
+//!
+//! While the profiler runs, every timer tick on every CPU records the
+//! instruction pointer it interrupted and the task it interrupted, kernel
+//! or user mode, in a ring of [`CAPACITY`] samples. Once the ring is full
+//! the oldest samples are overwritten. SysRq `p` starts and stops it;
+//! SysRq `d` stops it and writes the samples to the serial port as a flat
+//! histogram, which consumes them:
+//!
+//! ```text
+//! [PROFILE] 1200 samples, 0 overwritten, 0 missed
+//! [PROFILE]    task samples
+//! [PROFILE]       3     950
+//! [PROFILE]       7     250
+//! [PROFILE]   count percent rip
+//! [PROFILE]     610    50.8% 0xffffffff80012a4c mellos_kernel::sched::idle::idle+0x4c
+//! ```
+//!
+//! One line per task, then one per distinct RIP, most frequent first.
+//! Kernel addresses are followed by the function they are in, from the
+//! kernel symbol table (`ksyms`).
+//!
+//! Sampling runs in the timer interrupt and never waits: a tick that finds
+//! the ring locked by an export is counted as missed.
+
+use crate::sched::task::{task_slot, TaskId};
+use crate::sched::MAX_TASKS;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+
+/// Samples kept; about 13 minutes of one CPU at `config::SCHED_HZ`
+pub const CAPACITY: usize = 16384;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    rip: u64,
+    task: TaskId,
+}
+
+/// A ring of the latest `N` samples
+struct Profile<const N: usize> {
+    samples: [Sample; N],
+    /// Samples taken since the profile started
+    taken: u64,
+}
+
+impl<const N: usize> Profile<N> {
+    const fn new() -> Self {
+        Self {
+            samples: [Sample { rip: 0, task: 0 }; N],
+            taken: 0,
+        }
+    }
+
+    fn record(&mut self, rip: u64, task: TaskId) {
+        self.samples[(self.taken % N as u64) as usize] = Sample { rip, task };
+        self.taken += 1;
+    }
+
+    /// Write the histogram of the samples kept and empty the ring
+    fn write_histogram(&mut self, out: &mut impl Write, missed: u64) -> fmt::Result {
+        let kept = (self.taken as usize).min(N);
+        let overwritten = self.taken - kept as u64;
+        self.taken = 0;
+        let samples = &mut self.samples[..kept];
+        writeln!(out, "{} samples, {} overwritten, {} missed", kept, overwritten, missed)?;
+        if kept == 0 {
+            return Ok(());
+        }
+
+        // By task table slot; a slot's later tasks count as other tasks
+        let mut per_task = [(0, 0u64); MAX_TASKS];
+        let mut other_tasks = 0;
+        for sample in samples.iter() {
+            match per_task.get_mut(task_slot(sample.task)) {
+                Some((task, count)) if *count == 0 || *task == sample.task => {
+                    *task = sample.task;
+                    *count += 1;
+                }
+                _ => other_tasks += 1,
+            }
+        }
+        writeln!(out, "   task samples")?;
+        for &(task, count) in per_task.iter().filter(|(_, count)| *count > 0) {
+            writeln!(out, "{:>7} {:>7}", task, count)?;
+        }
+        if other_tasks > 0 {
+            writeln!(out, "  other {:>7}", other_tasks)?;
+        }
+
+        // Fold each run of equal RIPs into its first slot, reusing `task`
+        // as the run's length, then order the runs by length
+        samples.sort_unstable_by_key(|sample| sample.rip);
+        let mut distinct = 0;
+        for i in 0..kept {
+            let rip = samples[i].rip;
+            if distinct > 0 && samples[distinct - 1].rip == rip {
+                samples[distinct - 1].task += 1;
+            } else {
+                samples[distinct] = Sample { rip, task: 1 };
+                distinct += 1;
+            }
+        }
+        let histogram = &mut samples[..distinct];
+        histogram.sort_unstable_by(|a, b| b.task.cmp(&a.task).then(a.rip.cmp(&b.rip)));
+
+        writeln!(out, "  count percent rip")?;
+        for entry in histogram.iter() {
+            let permille = entry.task * 1000 / kept;
+            writeln!(
+                out,
+                "{:>7} {:>5}.{}% {}",
+                entry.task,
+                permille / 10,
+                permille % 10,
+                crate::ksyms::Symbolized(entry.rip)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+static PROFILE: Mutex<Profile<CAPACITY>> = Mutex::new(Profile::new());
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Ticks skipped because an export held the ring
+static MISSED: AtomicU64 = AtomicU64::new(0);
+
+/// Start a new profile, dropping any samples not exported
+///
+/// Returns false, leaving the profiler stopped, if an export holds the
+/// ring.
+pub fn start() -> bool {
+    let Some(mut profile) = PROFILE.try_lock() else { return false };
+    profile.taken = 0;
+    MISSED.store(0, Ordering::Relaxed);
+    RUNNING.store(true, Ordering::Release);
+    true
+}
+
+/// Stop sampling; the samples stay for [`try_export`]
+pub fn stop() {
+    RUNNING.store(false, Ordering::Release);
+}
+
+/// Whether the profiler is sampling
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Acquire)
+}
+
+/// Timer tick that interrupted this CPU at `rip`
+///
+/// Interrupt context.
+pub fn sample(rip: u64) {
+    if !is_running() {
+        return;
+    }
+    let Some(task) = crate::arch::x86_64::smp::percpu::percpu_current().current_task else { return };
+    match PROFILE.try_lock() {
+        Some(mut profile) => profile.record(rip, task),
+        None => {
+            MISSED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Stop the profiler and write the histogram to the serial port
+///
+/// Returns false if the ring was busy, in which case nothing is written.
+pub fn try_export() -> bool {
+    stop();
+    let Some(mut profile) = PROFILE.try_lock() else { return false };
+    let mut out = Prefixed {
+        port: crate::serial::SerialPort::new(crate::serial::SERIAL_PORT),
+        line_start: true,
+    };
+    let _ = profile.write_histogram(&mut out, MISSED.load(Ordering::Relaxed));
+    true
+}
+
+/// Serial output with every line marked as the profiler's
+struct Prefixed {
+    port: crate::serial::SerialPort,
+    line_start: bool,
+}
+
+impl Write for Prefixed {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for line in s.split_inclusive('\n') {
+            if self.line_start {
+                self.port.write_string("[PROFILE] ");
+            }
+            self.port.write_string(line);
+            self.line_start = line.ends_with('\n');
+        }
+        Ok(())
+    }
+}
+
+crate::kernel_test! {
+    /// The ring keeps the latest samples and the histogram lists RIPs by
+    /// frequency
+    fn profiler_histogram() {
+        struct Text {
+            buf: [u8; 512],
+            len: usize,
+        }
+
+        impl Write for Text {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                let end = self.len + s.len();
+                self.buf.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+                self.len = end;
+                Ok(())
+            }
+        }
+
+        let mut profile = Profile::<4>::new();
+        for rip in [0x10, 0x20, 0x30, 0x20, 0x30, 0x30] {
+            profile.record(rip, 1);
+        }
+        let mut text = Text { buf: [0; 512], len: 0 };
+        profile.write_histogram(&mut text, 0).map_err(|_| "histogram too long")?;
+        let text = core::str::from_utf8(&text.buf[..text.len]).map_err(|_| "not UTF-8")?;
+
+        let mut lines = text.lines();
+        crate::ktest_assert_eq!(lines.next(), Some("4 samples, 2 overwritten, 0 missed"), "summary");
+        crate::ktest_assert_eq!(lines.nth(1), Some("      1       4"), "task count");
+        crate::ktest_assert_eq!(lines.nth(1), Some("      3    75.0% 0x0000000000000030"), "top RIP");
+        crate::ktest_assert_eq!(lines.next(), Some("      1    25.0% 0x0000000000000020"), "second RIP");
+        crate::ktest_assert_eq!(profile.taken, 0, "samples not consumed");
+        Ok(())
+    }
+}
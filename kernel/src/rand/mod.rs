@@ -0,0 +1,174 @@
+//! Kernel random number generator
+//!
+//! Entropy is gathered from three places:
+//! - RDSEED/RDRAND, when the CPU has them
+//! - TSC jitter (`entropy::tsc_jitter64`), always mixed in at (re)seed time
+//!   so a faulty or untrusted hardware generator alone does not decide the
+//!   output
+//! - the TSC value at every timer and driver interrupt, which is collected
+//!   into a lock-free pool by [`add_interrupt_timing`]
+//!
+//! The collected material keys a ChaCha20-based generator. After every
+//! request the generator replaces its key with fresh keystream ("fast key
+//! erasure"), so a later compromise of the state does not reveal earlier
+//! output. It reseeds from the sources above every `RESEED_BYTES` of output.
+//!
+//! Kernel users call [`rand_bytes`] or [`random_u64`]; user space gets the
+//! same stream through `SYS_GETRANDOM`.
+
+mod chacha;
+
+use crate::arch::x86_64::entropy;
+use crate::sync::IrqSpinLock;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Output produced before the generator reseeds itself
+const RESEED_BYTES: usize = 64 * 1024;
+
+/// Words in the interrupt timing pool
+const POOL_WORDS: usize = 4;
+
+/// Interrupt timing samples, folded together lock-free
+static POOL: [AtomicU64; POOL_WORDS] = [const { AtomicU64::new(0) }; POOL_WORDS];
+/// Next pool word to fold a sample into
+static POOL_CURSOR: AtomicUsize = AtomicUsize::new(0);
+
+/// ChaCha20 generator state
+struct Generator {
+    key: [u32; 8],
+    seeded: bool,
+    output_since_reseed: usize,
+    reseeds: u64,
+}
+
+static GENERATOR: IrqSpinLock<Generator> = IrqSpinLock::new(Generator {
+    key: [0; 8],
+    seeded: false,
+    output_since_reseed: 0,
+    reseeds: 0,
+});
+
+impl Generator {
+    /// Mix fresh entropy into the key
+    fn reseed(&mut self) {
+        let mut seed = [0u64; 4];
+
+        // Hardware sources first (zero if the CPU has none)
+        for word in seed.iter_mut() {
+            *word = entropy::rdseed64().or_else(entropy::rdrand64).unwrap_or(0);
+        }
+        // Always fold in TSC jitter and interrupt timing on top
+        seed[0] ^= entropy::tsc_jitter64();
+        for (i, word) in POOL.iter().enumerate() {
+            seed[i % seed.len()] ^= word.swap(0, Ordering::Relaxed);
+        }
+        seed[1] ^= self.reseeds;
+
+        for (i, word) in seed.iter().enumerate() {
+            self.key[i * 2] ^= *word as u32;
+            self.key[i * 2 + 1] ^= (*word >> 32) as u32;
+        }
+        // Run the combined key through ChaCha so no input word is used raw
+        self.rekey();
+
+        self.seeded = true;
+        self.output_since_reseed = 0;
+        self.reseeds += 1;
+    }
+
+    /// Replace the key with the first half of a keystream block
+    fn rekey(&mut self) {
+        let mut block = [0u8; chacha::BLOCK_SIZE];
+        chacha::block(&self.key, 0, &[0; 3], &mut block);
+        for (i, word) in self.key.iter_mut().enumerate() {
+            *word = u32::from_le_bytes([
+                block[i * 4],
+                block[i * 4 + 1],
+                block[i * 4 + 2],
+                block[i * 4 + 3],
+            ]);
+        }
+        block.fill(0);
+    }
+
+    /// Fill `dest` with keystream, then erase the key that produced it
+    fn fill(&mut self, dest: &mut [u8]) {
+        if !self.seeded || self.output_since_reseed >= RESEED_BYTES {
+            self.reseed();
+        }
+
+        let mut block = [0u8; chacha::BLOCK_SIZE];
+        // Counter 0 is reserved for rekeying
+        let mut counter: u32 = 1;
+        for chunk in dest.chunks_mut(chacha::BLOCK_SIZE) {
+            chacha::block(&self.key, counter, &[0; 3], &mut block);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+            counter = counter.wrapping_add(1);
+        }
+        block.fill(0);
+
+        self.rekey();
+        self.output_since_reseed = self.output_since_reseed.saturating_add(dest.len());
+    }
+}
+
+/// Seed the generator
+///
+/// Called once during early boot, before anything (KASLR in particular)
+/// asks for random numbers. Requests made earlier would still work, since
+/// the generator seeds itself on first use, but would miss the log line.
+pub fn init() {
+    GENERATOR.lock().reseed();
+
+    let source = if entropy::has_rdseed() {
+        "RDSEED + TSC jitter"
+    } else if entropy::has_rdrand() {
+        "RDRAND + TSC jitter"
+    } else {
+        "TSC jitter"
+    };
+    crate::serial_println!("[RAND] ChaCha20 generator seeded ({})", source);
+}
+
+/// Fill `dest` with cryptographically secure random bytes
+pub fn rand_bytes(dest: &mut [u8]) {
+    if dest.is_empty() {
+        return;
+    }
+    GENERATOR.lock().fill(dest);
+}
+
+/// Cryptographically secure random `u64`
+pub fn random_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    rand_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Record the arrival time of an interrupt as an entropy sample
+///
+/// Lock-free and cheap enough for every interrupt handler. `source` is the
+/// interrupt vector (plus the CPU for per-CPU timers) and only serves to
+/// tell samples from different sources apart.
+#[inline]
+pub fn add_interrupt_timing(source: u64) {
+    let sample = entropy::rdtsc() ^ source.rotate_right(8);
+    let index = POOL_CURSOR.fetch_add(1, Ordering::Relaxed) % POOL_WORDS;
+    // Spread the low (jittery) TSC bits over the whole word
+    POOL[index].fetch_xor(sample.wrapping_mul(0x9E37_79B9_7F4A_7C15), Ordering::Relaxed);
+}
+
+crate::kernel_test! {
+    /// Consecutive requests never repeat and fill the whole buffer
+    fn rand_bytes_distinct() {
+        let mut first = [0u8; 100];
+        let mut second = [0u8; 100];
+        rand_bytes(&mut first);
+        rand_bytes(&mut second);
+
+        crate::ktest_assert!(first != second, "two requests returned the same bytes");
+        crate::ktest_assert!(first[64..] != [0u8; 36], "tail of a multi-block request left zero");
+        crate::ktest_assert!(random_u64() != random_u64(), "random_u64 repeated");
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! Per-task resource accounting
+//!
+//! Every task carries a [`TaskUsage`] that the rest of the kernel charges as
+//! work happens on the task's behalf:
+//! - the timer charges one tick of user or system time per interrupt
+//! - the ELF loader charges each user frame it maps (max RSS)
+//! - the page fault handler counts user faults
+//! - block I/O through `dev::api::block::{read, write}` counts blocks
+//! - the IPC syscalls count messages sent and received
+//!
+//! When a task exits its totals (plus those of its own children) are folded
+//! into its parent's `children_usage`, which is what `RUSAGE_CHILDREN`
+//! reports. There is no real `wait` yet, so children are accounted at exit
+//! rather than when they are reaped.
+
+use crate::time::Duration;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Page size used to convert charged frames to kilobytes
+const PAGE_SIZE_KB: u64 = 4;
+
+/// Live resource counters for one task
+///
+/// All counters are atomic: most are bumped by the task itself, but the
+/// timer and an exiting child update them from other contexts.
+#[derive(Debug, Default)]
+pub struct TaskUsage {
+    user_ticks: AtomicU64,
+    system_ticks: AtomicU64,
+    frames: AtomicU64,
+    max_frames: AtomicU64,
+    minor_faults: AtomicU64,
+    major_faults: AtomicU64,
+    blocks_in: AtomicU64,
+    blocks_out: AtomicU64,
+    msgs_sent: AtomicU64,
+    msgs_received: AtomicU64,
+}
+
+impl TaskUsage {
+    /// Charge one timer tick
+    pub fn charge_tick(&self, user_mode: bool) {
+        let counter = if user_mode { &self.user_ticks } else { &self.system_ticks };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Charge `count` resident frames, updating the high-water mark
+    pub fn charge_frames(&self, count: u64) {
+        let frames = self.frames.fetch_add(count, Ordering::Relaxed) + count;
+        self.max_frames.fetch_max(frames, Ordering::Relaxed);
+    }
+
+    /// Release `count` frames charged earlier
+    pub fn uncharge_frames(&self, count: u64) {
+        let mut frames = self.frames.load(Ordering::Relaxed);
+        loop {
+            let new = frames.saturating_sub(count);
+            match self
+                .frames
+                .compare_exchange_weak(frames, new, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => frames = current,
+            }
+        }
+    }
+
+    /// Count a page fault; `major` if it needed I/O to resolve
+    pub fn record_fault(&self, major: bool) {
+        let counter = if major { &self.major_faults } else { &self.minor_faults };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count blocks read from (`input`) or written to a block device
+    pub fn record_block_io(&self, input: bool, blocks: u64) {
+        let counter = if input { &self.blocks_in } else { &self.blocks_out };
+        counter.fetch_add(blocks, Ordering::Relaxed);
+    }
+
+    /// Count an IPC message sent
+    pub fn record_msg_sent(&self) {
+        self.msgs_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an IPC message received
+    pub fn record_msg_received(&self) {
+        self.msgs_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fold in the totals of an exited child
+    ///
+    /// Counters are summed; max RSS is the largest of any single child, as
+    /// with `getrusage(RUSAGE_CHILDREN)` on other systems.
+    pub fn absorb(&self, totals: &UsageTotals) {
+        self.user_ticks.fetch_add(totals.user_ticks, Ordering::Relaxed);
+        self.system_ticks.fetch_add(totals.system_ticks, Ordering::Relaxed);
+        self.max_frames.fetch_max(totals.max_frames, Ordering::Relaxed);
+        self.minor_faults.fetch_add(totals.minor_faults, Ordering::Relaxed);
+        self.major_faults.fetch_add(totals.major_faults, Ordering::Relaxed);
+        self.blocks_in.fetch_add(totals.blocks_in, Ordering::Relaxed);
+        self.blocks_out.fetch_add(totals.blocks_out, Ordering::Relaxed);
+        self.msgs_sent.fetch_add(totals.msgs_sent, Ordering::Relaxed);
+        self.msgs_received.fetch_add(totals.msgs_received, Ordering::Relaxed);
+    }
+
+    /// Read all counters
+    pub fn snapshot(&self) -> UsageTotals {
+        UsageTotals {
+            user_ticks: self.user_ticks.load(Ordering::Relaxed),
+            system_ticks: self.system_ticks.load(Ordering::Relaxed),
+            max_frames: self.max_frames.load(Ordering::Relaxed),
+            minor_faults: self.minor_faults.load(Ordering::Relaxed),
+            major_faults: self.major_faults.load(Ordering::Relaxed),
+            blocks_in: self.blocks_in.load(Ordering::Relaxed),
+            blocks_out: self.blocks_out.load(Ordering::Relaxed),
+            msgs_sent: self.msgs_sent.load(Ordering::Relaxed),
+            msgs_received: self.msgs_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A point-in-time copy of a task's counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UsageTotals {
+    pub user_ticks: u64,
+    pub system_ticks: u64,
+    pub max_frames: u64,
+    pub minor_faults: u64,
+    pub major_faults: u64,
+    pub blocks_in: u64,
+    pub blocks_out: u64,
+    pub msgs_sent: u64,
+    pub msgs_received: u64,
+}
+
+impl UsageTotals {
+    /// Combine a task's own totals with those of its children
+    pub fn merged(&self, other: &UsageTotals) -> UsageTotals {
+        UsageTotals {
+            user_ticks: self.user_ticks + other.user_ticks,
+            system_ticks: self.system_ticks + other.system_ticks,
+            max_frames: self.max_frames.max(other.max_frames),
+            minor_faults: self.minor_faults + other.minor_faults,
+            major_faults: self.major_faults + other.major_faults,
+            blocks_in: self.blocks_in + other.blocks_in,
+            blocks_out: self.blocks_out + other.blocks_out,
+            msgs_sent: self.msgs_sent + other.msgs_sent,
+            msgs_received: self.msgs_received + other.msgs_received,
+        }
+    }
+
+    /// Convert to the user-visible `struct rusage`
+    pub fn to_rusage(&self) -> Rusage {
+        Rusage {
+            ru_utime: Timeval::from_duration(Duration::from_ticks(self.user_ticks)),
+            ru_stime: Timeval::from_duration(Duration::from_ticks(self.system_ticks)),
+            ru_maxrss: (self.max_frames * PAGE_SIZE_KB) as i64,
+            ru_minflt: self.minor_faults as i64,
+            ru_majflt: self.major_faults as i64,
+            ru_inblock: self.blocks_in as i64,
+            ru_oublock: self.blocks_out as i64,
+            ru_msgsnd: self.msgs_sent as i64,
+            ru_msgrcv: self.msgs_received as i64,
+            ..Rusage::default()
+        }
+    }
+}
+
+/// `struct timeval`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+mello_abi::check_layout!(Timeval, mello_abi::Timeval { tv_sec, tv_usec });
+
+impl Timeval {
+    pub fn from_duration(duration: Duration) -> Self {
+        Self {
+            tv_sec: duration.as_secs() as i64,
+            tv_usec: (duration.as_micros() % 1_000_000) as i64,
+        }
+    }
+}
+
+/// `struct rusage`, laid out as on x86_64 Linux
+///
+/// Fields MelloOS does not track (shared/unshared sizes, swaps, signals,
+/// context switches) are always zero. `ru_maxrss` is in kilobytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rusage {
+    pub ru_utime: Timeval,
+    pub ru_stime: Timeval,
+    pub ru_maxrss: i64,
+    pub ru_ixrss: i64,
+    pub ru_idrss: i64,
+    pub ru_isrss: i64,
+    pub ru_minflt: i64,
+    pub ru_majflt: i64,
+    pub ru_nswap: i64,
+    pub ru_inblock: i64,
+    pub ru_oublock: i64,
+    pub ru_msgsnd: i64,
+    pub ru_msgrcv: i64,
+    pub ru_nsignals: i64,
+    pub ru_nvcsw: i64,
+    pub ru_nivcsw: i64,
+}
+
+mello_abi::check_layout!(Rusage, mello_abi::Rusage {
+    ru_utime, ru_stime, ru_maxrss, ru_ixrss, ru_idrss, ru_isrss, ru_minflt, ru_majflt,
+    ru_nswap, ru_inblock, ru_oublock, ru_msgsnd, ru_msgrcv, ru_nsignals, ru_nvcsw, ru_nivcsw,
+});
+
+crate::kernel_test! {
+    /// Charges show up in the snapshot and children fold in correctly
+    fn task_usage_accounting() {
+        let usage = TaskUsage::default();
+        usage.charge_tick(true);
+        usage.charge_tick(false);
+        usage.charge_frames(3);
+        usage.uncharge_frames(2);
+        usage.charge_frames(1);
+        usage.record_msg_sent();
+
+        let totals = usage.snapshot();
+        crate::ktest_assert_eq!(totals.user_ticks, 1, "user tick not charged");
+        crate::ktest_assert_eq!(totals.system_ticks, 1, "system tick not charged");
+        crate::ktest_assert_eq!(totals.max_frames, 3, "max RSS is not the high-water mark");
+        crate::ktest_assert_eq!(totals.msgs_sent, 1, "message not counted");
+
+        let children = TaskUsage::default();
+        children.absorb(&totals);
+        children.absorb(&UsageTotals { max_frames: 2, user_ticks: 4, ..UsageTotals::default() });
+        let folded = children.snapshot();
+        crate::ktest_assert_eq!(folded.user_ticks, 5, "child ticks not summed");
+        crate::ktest_assert_eq!(folded.max_frames, 3, "child max RSS summed instead of maxed");
+
+        let rusage = folded.to_rusage();
+        crate::ktest_assert_eq!(rusage.ru_maxrss, 12, "max RSS not in kilobytes");
+        crate::ktest_assert_eq!(core::mem::size_of::<Rusage>(), 144, "struct rusage size");
+        Ok(())
+    }
+}
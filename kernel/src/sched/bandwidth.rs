@@ -0,0 +1,241 @@
+//! CPU bandwidth groups
+//!
+//! Every task belongs to a bandwidth group (`Task::cpu_group`), inherited
+//! across fork, spawn and thread creation. A group may be capped at a
+//! quota of CPU time per period, e.g. 20 ms every 100 ms for 20% of one
+//! CPU, so background work can share a single core with interactive tasks.
+//!
+//! Each timer tick is charged to the group of the task it interrupted (the
+//! idle task is never charged). Once a group has used its quota it is
+//! throttled: its running task's quantum ends, and the scheduler leaves
+//! its queued tasks where they are and runs others, or the idle task if
+//! none is left, until the next period starts and refills the quota.
+//! Periods are counted lazily from the clock whenever a group is charged
+//! or checked.
+//!
+//! Group [`ROOT_GROUP`], which every task starts in, cannot be capped.
+//! `SYS_CPU_GROUP` moves a task to another group and `SYS_CPU_QUOTA` sets a
+//! group's cap and reads its [`GroupStats`].
+
+use crate::sync::IrqSpinLock;
+use crate::time::{Duration, Instant};
+
+/// Number of bandwidth groups
+pub const MAX_GROUPS: usize = 8;
+
+/// Group every task starts in; never throttled
+pub const ROOT_GROUP: usize = 0;
+
+/// Shortest period accepted, so a period spans at least one tick
+pub const MIN_PERIOD: Duration = Duration::TICK;
+
+/// Longest period accepted
+pub const MAX_PERIOD: Duration = Duration::from_secs(1);
+
+/// Bandwidth errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandwidthError {
+    /// No such group, the root group, or a bad quota or period
+    InvalidArgument,
+}
+
+/// Usage and throttling counters of a group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GroupStats {
+    /// Periods elapsed while the group was capped
+    pub periods: u64,
+    /// Periods in which the group used up its quota
+    pub throttled_periods: u64,
+    /// Time the group spent throttled
+    pub throttled_time: Duration,
+    /// CPU time charged to the group
+    pub usage: Duration,
+}
+
+/// Argument of `SYS_CPU_QUOTA`: the new cap of a group
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuQuota {
+    /// CPU time allowed per period, in microseconds; 0 for no cap
+    pub quota_us: u64,
+    /// Length of a period, in microseconds
+    pub period_us: u64,
+}
+
+mello_abi::check_layout!(CpuQuota, mello_abi::CpuQuota { quota_us, period_us });
+
+/// Result of `SYS_CPU_QUOTA`: the cap and counters of a group, in
+/// microseconds
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuGroupStats {
+    pub quota_us: u64,
+    pub period_us: u64,
+    pub periods: u64,
+    pub throttled_periods: u64,
+    pub throttled_us: u64,
+    pub usage_us: u64,
+}
+
+mello_abi::check_layout!(
+    CpuGroupStats,
+    mello_abi::CpuGroupStats { quota_us, period_us, periods, throttled_periods, throttled_us, usage_us }
+);
+
+#[derive(Clone, Copy)]
+struct Group {
+    /// CPU time allowed per period; None for no cap
+    quota: Option<Duration>,
+    period: Duration,
+    /// CPU time used in the current period
+    used: Duration,
+    period_start: Instant,
+    /// When the group was throttled, if it is
+    throttled_since: Option<Instant>,
+    stats: GroupStats,
+}
+
+impl Group {
+    const fn new() -> Self {
+        Self {
+            quota: None,
+            period: MAX_PERIOD,
+            used: Duration::ZERO,
+            period_start: Instant::BOOT,
+            throttled_since: None,
+            stats: GroupStats {
+                periods: 0,
+                throttled_periods: 0,
+                throttled_time: Duration::ZERO,
+                usage: Duration::ZERO,
+            },
+        }
+    }
+
+    /// Start a new period if the current one is over
+    fn refresh(&mut self, now: Instant) {
+        if self.quota.is_none() {
+            return;
+        }
+        let elapsed = now.saturating_duration_since(self.period_start).as_nanos();
+        let periods = elapsed / self.period.as_nanos();
+        if periods == 0 {
+            return;
+        }
+        self.period_start = self
+            .period_start
+            .saturating_add(Duration::from_nanos(periods * self.period.as_nanos()));
+        self.stats.periods += periods;
+        self.used = Duration::ZERO;
+        if let Some(since) = self.throttled_since.take() {
+            let throttled = self.period_start.saturating_duration_since(since);
+            self.stats.throttled_time = self.stats.throttled_time.saturating_add(throttled);
+        }
+    }
+
+    fn throttled(&mut self, now: Instant) -> bool {
+        self.refresh(now);
+        self.throttled_since.is_some()
+    }
+}
+
+static GROUPS: IrqSpinLock<[Group; MAX_GROUPS]> = IrqSpinLock::new([const { Group::new() }; MAX_GROUPS]);
+
+/// Charge `time` of CPU to `group`; returns whether the group is now
+/// throttled
+pub fn charge(group: usize, time: Duration, now: Instant) -> bool {
+    let mut groups = GROUPS.lock();
+    let Some(group) = groups.get_mut(group) else { return false };
+    group.refresh(now);
+    group.stats.usage = group.stats.usage.saturating_add(time);
+    let Some(quota) = group.quota else { return false };
+    group.used = group.used.saturating_add(time);
+    if group.used >= quota && group.throttled_since.is_none() {
+        group.throttled_since = Some(now);
+        group.stats.throttled_periods += 1;
+    }
+    group.throttled_since.is_some()
+}
+
+/// Whether the tasks of `group` must not run until its next period
+pub fn throttled(group: usize, now: Instant) -> bool {
+    GROUPS.lock().get_mut(group).map_or(false, |group| group.throttled(now))
+}
+
+/// Check that `group` exists
+pub fn validate(group: usize) -> Result<(), BandwidthError> {
+    if group < MAX_GROUPS {
+        Ok(())
+    } else {
+        Err(BandwidthError::InvalidArgument)
+    }
+}
+
+/// Cap `group` at `quota` of CPU time every `period`, or lift its cap
+/// (`quota` None); the new period starts now
+pub fn set_quota(group: usize, quota: Option<Duration>, period: Duration) -> Result<(), BandwidthError> {
+    if group == ROOT_GROUP || !(MIN_PERIOD..=MAX_PERIOD).contains(&period) {
+        return Err(BandwidthError::InvalidArgument);
+    }
+    if quota.map_or(false, |quota| quota.is_zero() || quota > period) {
+        return Err(BandwidthError::InvalidArgument);
+    }
+    let now = Instant::now();
+    let mut groups = GROUPS.lock();
+    let group = groups.get_mut(group).ok_or(BandwidthError::InvalidArgument)?;
+    group.refresh(now);
+    if let Some(since) = group.throttled_since.take() {
+        let throttled = now.saturating_duration_since(since);
+        group.stats.throttled_time = group.stats.throttled_time.saturating_add(throttled);
+    }
+    group.quota = quota;
+    group.period = period;
+    group.used = Duration::ZERO;
+    group.period_start = now;
+    Ok(())
+}
+
+/// Quota (None if uncapped), period and counters of `group`
+pub fn get(group: usize) -> Result<(Option<Duration>, Duration, GroupStats), BandwidthError> {
+    let now = Instant::now();
+    let mut groups = GROUPS.lock();
+    let group = groups.get_mut(group).ok_or(BandwidthError::InvalidArgument)?;
+    group.refresh(now);
+    let mut stats = group.stats;
+    if let Some(since) = group.throttled_since {
+        // Include the current throttled stretch
+        stats.throttled_time = stats.throttled_time.saturating_add(now.saturating_duration_since(since));
+    }
+    Ok((group.quota, group.period, stats))
+}
+
+crate::kernel_test! {
+    /// A capped group is throttled once it has used its quota and runs
+    /// again in the next period, which the counters record
+    fn bandwidth_throttles_until_next_period() {
+        const GROUP: usize = MAX_GROUPS - 1;
+
+        crate::ktest_assert!(set_quota(ROOT_GROUP, Some(MIN_PERIOD), MAX_PERIOD).is_err(), "root group capped");
+        crate::ktest_assert!(set_quota(GROUP, Some(MAX_PERIOD), MIN_PERIOD).is_err(), "quota above period accepted");
+        let quota = Duration::from_millis(20);
+        let period = Duration::from_millis(100);
+        set_quota(GROUP, Some(quota), period).map_err(|_| "set_quota failed")?;
+
+        let start = GROUPS.lock()[GROUP].period_start;
+        let at = |millis| start.saturating_add(Duration::from_millis(millis));
+        crate::ktest_assert!(!charge(GROUP, Duration::from_millis(10), at(10)), "throttled under quota");
+        crate::ktest_assert!(charge(GROUP, Duration::from_millis(10), at(20)), "not throttled at quota");
+        crate::ktest_assert!(throttled(GROUP, at(99)), "unthrottled before the period ended");
+        crate::ktest_assert!(!throttled(GROUP, at(100)), "still throttled in the next period");
+
+        let stats = GROUPS.lock()[GROUP].stats;
+        crate::ktest_assert_eq!(stats.periods, 1, "period not counted");
+        crate::ktest_assert_eq!(stats.throttled_periods, 1, "throttled period not counted");
+        crate::ktest_assert_eq!(stats.throttled_time, Duration::from_millis(80), "wrong throttled time");
+        crate::ktest_assert_eq!(stats.usage, Duration::from_millis(20), "wrong usage");
+
+        set_quota(GROUP, None, MAX_PERIOD).map_err(|_| "clearing the quota failed")?;
+        crate::ktest_assert!(!charge(GROUP, MAX_PERIOD, at(200)), "uncapped group throttled");
+        Ok(())
+    }
+}
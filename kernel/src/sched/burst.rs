@@ -0,0 +1,177 @@
+//! CPU burst prediction
+//!
+//! A burst is the CPU time a task uses from when it starts running until it
+//! blocks or sleeps; being preempted or yielding does not end it. Each task
+//! keeps an exponential moving average of its past bursts as the prediction
+//! of the next one, which the scheduler uses twice:
+//!
+//! - **Quantum**: a task runs for as many timer ticks as its predicted burst
+//!   (or the burst so far, if longer) needs, between 1 and
+//!   `MAX_QUANTUM_TICKS`, so CPU-bound tasks are switched less often.
+//! - **Wake-up**: a woken task predicted to be short (under
+//!   `SHORT_BURST_NANOS`) goes to the front of its runqueue and ends the
+//!   quantum of whatever runs there, because it will likely block again
+//!   before it costs the others much.
+//!
+//! How good the predictions are is kept in global counters
+//! (`/proc/stat`): each finished burst adds its prediction error, and
+//! whether it was classified right as short or long.
+
+use crate::time::Duration;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Weight of the newest burst in the average, as a shift: 1/2
+const EMA_SHIFT: u32 = 1;
+
+/// Prediction for a task that has not finished a burst yet
+const INITIAL_PREDICTION: u64 = Duration::TICK.as_nanos();
+
+/// Bursts below this are short
+pub const SHORT_BURST_NANOS: u64 = Duration::TICK.as_nanos() / 2;
+
+/// Longest quantum, in timer ticks
+pub const MAX_QUANTUM_TICKS: u32 = 3;
+
+static BURSTS: AtomicU64 = AtomicU64::new(0);
+static BURST_NANOS: AtomicU64 = AtomicU64::new(0);
+static ERROR_NANOS: AtomicU64 = AtomicU64::new(0);
+static CLASSIFIED_RIGHT: AtomicU64 = AtomicU64::new(0);
+
+/// Burst history of one task
+///
+/// Only the scheduler on the CPU running the task updates it.
+#[derive(Debug, Clone, Copy)]
+pub struct BurstPredictor {
+    /// Predicted length of the next burst
+    predicted: u64,
+    /// CPU time of the current burst before the task was last switched in
+    current: u64,
+    /// When the task was last switched in
+    switched_in: u64,
+}
+
+impl BurstPredictor {
+    pub const fn new() -> Self {
+        Self {
+            predicted: INITIAL_PREDICTION,
+            current: 0,
+            switched_in: 0,
+        }
+    }
+
+    /// Predicted length of the next burst in nanoseconds
+    pub fn predicted(&self) -> u64 {
+        self.predicted
+    }
+
+    /// Whether the next burst will likely be short
+    pub fn is_short(&self) -> bool {
+        self.predicted < SHORT_BURST_NANOS
+    }
+
+    /// Timer ticks the task may run before it is preempted
+    pub fn quantum_ticks(&self) -> u32 {
+        let expected = self.predicted.max(self.current);
+        expected
+            .div_ceil(Duration::TICK.as_nanos())
+            .clamp(1, MAX_QUANTUM_TICKS as u64) as u32
+    }
+
+    /// The task starts running at `now`
+    pub fn switch_in(&mut self, now: u64) {
+        self.switched_in = now;
+    }
+
+    /// The task stops running at `now`; `blocked` ends the burst
+    pub fn switch_out(&mut self, now: u64, blocked: bool) {
+        self.current += now.saturating_sub(self.switched_in);
+        if blocked {
+            let burst = core::mem::take(&mut self.current);
+            self.observe(burst);
+        }
+    }
+
+    /// Fold a finished burst into the average and the accuracy counters
+    fn observe(&mut self, burst: u64) {
+        BURSTS.fetch_add(1, Ordering::Relaxed);
+        BURST_NANOS.fetch_add(burst, Ordering::Relaxed);
+        ERROR_NANOS.fetch_add(burst.abs_diff(self.predicted), Ordering::Relaxed);
+        if self.is_short() == (burst < SHORT_BURST_NANOS) {
+            CLASSIFIED_RIGHT.fetch_add(1, Ordering::Relaxed);
+        }
+        self.predicted = self.predicted - (self.predicted >> EMA_SHIFT) + (burst >> EMA_SHIFT);
+    }
+}
+
+impl Default for BurstPredictor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Prediction accuracy over all finished bursts
+#[derive(Debug, Clone, Copy)]
+pub struct BurstStats {
+    /// Bursts finished
+    pub bursts: u64,
+    /// Total length of those bursts
+    pub burst_nanos: u64,
+    /// Total of |actual - predicted| over those bursts
+    pub error_nanos: u64,
+    /// Bursts whose short/long class was predicted right
+    pub classified_right: u64,
+}
+
+impl BurstStats {
+    /// Mean burst length in nanoseconds
+    pub fn mean_burst(&self) -> u64 {
+        self.burst_nanos.checked_div(self.bursts).unwrap_or(0)
+    }
+
+    /// Mean prediction error in nanoseconds
+    pub fn mean_error(&self) -> u64 {
+        self.error_nanos.checked_div(self.bursts).unwrap_or(0)
+    }
+
+    /// Percentage of bursts classified right as short or long
+    pub fn class_accuracy(&self) -> u64 {
+        (self.classified_right * 100).checked_div(self.bursts).unwrap_or(0)
+    }
+}
+
+pub fn stats() -> BurstStats {
+    BurstStats {
+        bursts: BURSTS.load(Ordering::Relaxed),
+        burst_nanos: BURST_NANOS.load(Ordering::Relaxed),
+        error_nanos: ERROR_NANOS.load(Ordering::Relaxed),
+        classified_right: CLASSIFIED_RIGHT.load(Ordering::Relaxed),
+    }
+}
+
+crate::kernel_test! {
+    /// Preemption extends a burst, blocking ends it, and the prediction
+    /// moves halfway towards each finished burst
+    fn burst_prediction() {
+        let tick = Duration::TICK.as_nanos();
+        let mut burst = BurstPredictor::new();
+        crate::ktest_assert_eq!(burst.quantum_ticks(), 1, "initial quantum");
+
+        // Three ticks of CPU across a preemption, then block
+        burst.switch_in(0);
+        burst.switch_out(2 * tick, false);
+        crate::ktest_assert_eq!(burst.quantum_ticks(), 2, "quantum follows the burst so far");
+        burst.switch_in(10 * tick);
+        burst.switch_out(11 * tick, true);
+        crate::ktest_assert_eq!(burst.predicted(), 2 * tick, "average of 1 and 3 ticks");
+        crate::ktest_assert_eq!(burst.quantum_ticks(), 2, "quantum of the prediction");
+
+        // Short bursts pull the prediction under the threshold
+        for i in 0..4 {
+            burst.switch_in(20 * tick + i);
+            burst.switch_out(20 * tick + i, true);
+        }
+        crate::ktest_assert!(burst.is_short(), "short bursts not predicted short");
+        crate::ktest_assert_eq!(burst.quantum_ticks(), 1, "short task quantum");
+        Ok(())
+    }
+}
@@ -3,6 +3,26 @@
 //! This module defines the CPU context structure and implements context switching
 //! using inline assembly. It handles saving and restoring CPU registers during
 //! task switches.
+//!
+//! The user FS and GS bases are part of the context. In the kernel, GS.BASE
+//! points at this CPU's `PerCpu` and the user GS base waits in
+//! KERNEL_GS_BASE; interrupt and syscall entry and exit stubs swap the two
+//! with `swapgs` when they come from or return to user mode (see
+//! `swapgs_if_user!`). A context switch always happens in the kernel, so
+//! it saves and loads FS.BASE and KERNEL_GS_BASE.
+//!
+//! The FPU/SIMD registers are switched eagerly too, into the save area
+//! each task has (see `arch::x86_64::fpu`).
+//!
+//! Entries from user mode land on the running task's own kernel stack:
+//! the switch points TSS.RSP0 (interrupts, `int 0x80`) and the per-CPU
+//! stack `syscall_entry64` uses at the next task's `kernel_stack`.
+
+/// FS.BASE MSR
+const MSR_FS_BASE: u32 = crate::arch::x86_64::msr::FS_BASE;
+
+/// KERNEL_GS_BASE MSR: the user GS base while in the kernel
+const MSR_KERNEL_GS_BASE: u32 = crate::arch::x86_64::msr::KERNEL_GS_BASE;
 
 /// CPU Context structure
 ///
@@ -22,6 +42,18 @@ pub struct CpuContext {
 
     /// Stack pointer - points to the top of the task's stack
     pub rsp: u64,
+
+    /// User FS base (TLS pointer)
+    pub fs_base: u64,
+
+    /// User GS base
+    pub gs_base: u64,
+
+    /// FPU/SIMD save area (64-byte aligned), or 0 for none
+    pub fpu_area: u64,
+
+    /// Top of the task's kernel stack, or 0 to leave the CPU's as it is
+    pub kernel_stack: u64,
 }
 
 impl CpuContext {
@@ -35,16 +67,99 @@ impl CpuContext {
             rbp: 0,
             rbx: 0,
             rsp: 0,
+            fs_base: 0,
+            gs_base: 0,
+            fpu_area: 0,
+            kernel_stack: 0,
         }
     }
 }
 
+/// Registers an interrupt entry stub saves, lowest address first
+///
+/// The timer and reschedule stubs push every general-purpose register on
+/// top of the frame the CPU pushed (RIP, CS, RFLAGS, RSP, SS), so a task
+/// switched out on the way out of an interrupt keeps all of its state on
+/// its own stack, whatever code it was running. `Task::new` builds one of
+/// these for a new task, which starts through [`interrupt_return`] like a
+/// preempted task resumes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterruptFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+
+    /// Pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Assembly pushing the general-purpose registers of an [`InterruptFrame`]
+///
+/// Used by interrupt entry stubs after `swapgs_if_user!`; RSP then points
+/// at the frame.
+#[macro_export]
+macro_rules! push_interrupt_frame {
+    () => {
+        concat!(
+            "push rax\n", "push rbx\n", "push rcx\n", "push rdx\n",
+            "push rsi\n", "push rdi\n", "push rbp\n", "push r8\n",
+            "push r9\n", "push r10\n", "push r11\n", "push r12\n",
+            "push r13\n", "push r14\n", "push r15",
+        )
+    };
+}
+
+/// Assembly popping what [`push_interrupt_frame!`] pushed
+#[macro_export]
+macro_rules! pop_interrupt_frame {
+    () => {
+        concat!(
+            "pop r15\n", "pop r14\n", "pop r13\n", "pop r12\n",
+            "pop r11\n", "pop r10\n", "pop r9\n", "pop r8\n",
+            "pop rbp\n", "pop rdi\n", "pop rsi\n", "pop rdx\n",
+            "pop rcx\n", "pop rbx\n", "pop rax",
+        )
+    };
+}
+
+/// Return through the [`InterruptFrame`] at RSP
+///
+/// A new task's first `context_switch` returns here, so it enters
+/// `entry_trampoline` with the registers, flags and segments of the frame
+/// `Task::new` built.
+#[unsafe(naked)]
+pub extern "C" fn interrupt_return() -> ! {
+    core::arch::naked_asm!(
+        crate::pop_interrupt_frame!(),
+        crate::swapgs_if_user!(8),
+        "iretq",
+    )
+}
+
 /// Context switch from current task to next task
 ///
 /// This function performs a context switch by:
 /// 1. Saving the current task's callee-saved registers to its stack
-/// 2. Saving the current RSP to the current context
-/// 3. Loading the next task's RSP from the next context
+/// 2. Saving the current RSP, user FS/GS bases and FPU state to the current context
+/// 3. Loading the next task's FPU state, user FS/GS bases and RSP from the next context,
+///    and making its kernel stack the one user-mode entries switch to
 /// 4. Restoring the next task's callee-saved registers from its stack
 /// 5. Returning to the next task (which may be a new task or a preempted task)
 ///
@@ -62,8 +177,9 @@ impl CpuContext {
 ///
 /// # Notes
 ///
-/// - For a new task, the return address on the stack will be entry_trampoline
-/// - For a preempted task, the return address will be where it was interrupted
+/// - For a new task, the return address on the stack will be interrupt_return
+/// - For a preempted task, the return address leads back to its interrupt
+///   stub, which restores the rest of its [`InterruptFrame`]
 /// - This function does not return to the caller in the traditional sense
 #[unsafe(naked)]
 pub unsafe extern "C" fn context_switch(current: *mut CpuContext, next: *const CpuContext) {
@@ -80,6 +196,62 @@ pub unsafe extern "C" fn context_switch(current: *mut CpuContext, next: *const C
         // RDI contains the pointer to current context (first argument)
         // We need to save RSP at offset 48 (6 registers * 8 bytes)
         "mov [rdi + 48], rsp",
+        // Save the FPU/SIMD state to current.fpu_area and load it from
+        // next.fpu_area, with XSAVE (all components) or FXSAVE
+        "mov r8, [rdi + 72]",
+        "test r8, r8",
+        "jz 4f",
+        "cmp byte ptr [rip + {xsave_enabled}], 0",
+        "je 3f",
+        "mov eax, -1",
+        "mov edx, -1",
+        "xsave64 [r8]",
+        "jmp 4f",
+        "3:",
+        "fxsave64 [r8]",
+        "4:",
+        "mov r8, [rsi + 72]",
+        "test r8, r8",
+        "jz 6f",
+        "cmp byte ptr [rip + {xsave_enabled}], 0",
+        "je 5f",
+        "mov eax, -1",
+        "mov edx, -1",
+        "xrstor64 [r8]",
+        "jmp 6f",
+        "5:",
+        "fxrstor64 [r8]",
+        "6:",
+        // Save the user FS and GS bases to current.fs_base and
+        // current.gs_base (RAX, RCX and RDX are caller-saved)
+        "mov ecx, {fs_base_msr}",
+        "rdmsr",
+        "mov [rdi + 56], eax",
+        "mov [rdi + 60], edx",
+        "mov ecx, {kernel_gs_base_msr}",
+        "rdmsr",
+        "mov [rdi + 64], eax",
+        "mov [rdi + 68], edx",
+        // Load the next task's user FS and GS bases
+        "mov ecx, {fs_base_msr}",
+        "mov eax, [rsi + 56]",
+        "mov edx, [rsi + 60]",
+        "wrmsr",
+        "mov ecx, {kernel_gs_base_msr}",
+        "mov eax, [rsi + 64]",
+        "mov edx, [rsi + 68]",
+        "wrmsr",
+        // Make next.kernel_stack the stack of the next entry from user mode:
+        // the SYSCALL stack in PerCpu and TSS.RSP0
+        "mov rax, [rsi + 80]",
+        "test rax, rax",
+        "jz 7f",
+        "mov gs:[{percpu_kernel_stack}], rax",
+        "mov rcx, gs:[{percpu_tss_rsp0}]",
+        "test rcx, rcx",
+        "jz 7f",
+        "mov [rcx], rax",
+        "7:",
         // Load next RSP from next.rsp
         // RSI contains the pointer to next context (second argument)
         // Load RSP from offset 48
@@ -94,9 +266,15 @@ pub unsafe extern "C" fn context_switch(current: *mut CpuContext, next: *const C
         "pop rbp",
         "pop rbx",
         // Return to next task
-        // - For a new task: jumps to entry_trampoline
-        // - For a preempted task: returns to where it was interrupted
+        // - For a new task: jumps to interrupt_return
+        // - For a preempted task: returns into its interrupt stub
         "ret",
+
+        fs_base_msr = const MSR_FS_BASE,
+        kernel_gs_base_msr = const MSR_KERNEL_GS_BASE,
+        xsave_enabled = sym crate::arch::x86_64::fpu::XSAVE_ENABLED,
+        percpu_kernel_stack = const core::mem::offset_of!(crate::arch::x86_64::smp::percpu::PerCpu, kernel_stack),
+        percpu_tss_rsp0 = const core::mem::offset_of!(crate::arch::x86_64::smp::percpu::PerCpu, tss_rsp0),
     )
 }
 
@@ -115,6 +293,21 @@ mod tests {
         assert_eq!(ctx.rbp, 0);
         assert_eq!(ctx.rbx, 0);
         assert_eq!(ctx.rsp, 0);
+        assert_eq!(ctx.fs_base, 0);
+        assert_eq!(ctx.gs_base, 0);
+        assert_eq!(ctx.fpu_area, 0);
+        assert_eq!(ctx.kernel_stack, 0);
+    }
+
+    /// Test that InterruptFrame matches what the stubs push
+    #[test]
+    fn test_interrupt_frame_layout() {
+        use core::mem::{offset_of, size_of};
+
+        // 15 general-purpose registers, then the CPU's 5 words
+        assert_eq!(size_of::<InterruptFrame>(), 160);
+        assert_eq!(offset_of!(InterruptFrame, rax), 112);
+        assert_eq!(offset_of!(InterruptFrame, cs), 128);
     }
 
     /// Test that CpuContext has the correct size and alignment
@@ -122,8 +315,9 @@ mod tests {
     fn test_context_layout() {
         use core::mem::{align_of, size_of};
 
-        // Should be 7 u64 registers = 56 bytes
-        assert_eq!(size_of::<CpuContext>(), 56);
+        // Should be 7 u64 registers, 2 segment bases, the FPU area and the
+        // kernel stack = 88 bytes
+        assert_eq!(size_of::<CpuContext>(), 88);
 
         // Should be aligned to 8 bytes (u64 alignment)
         assert_eq!(align_of::<CpuContext>(), 8);
@@ -202,7 +396,7 @@ pub mod manual_tests {
         // This is a conceptual test - actual testing requires setting up
         // a proper stack with a return address
         serial_println!("[TEST] Return address is handled by 'ret' instruction in context_switch");
-        serial_println!("[TEST] For new tasks: return address = entry_trampoline");
+        serial_println!("[TEST] For new tasks: return address = interrupt_return");
         serial_println!("[TEST] For preempted tasks: return address = interrupted location");
         serial_println!("[TEST] Return address handling test passed!");
     }
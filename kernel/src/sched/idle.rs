@@ -0,0 +1,249 @@
+//! Idle loop and C-state selection
+//!
+//! Each CPU's idle task waits for work in the deepest power state that is
+//! likely to pay off. Where CPUID reports MONITOR/MWAIT with enumerated
+//! sub-states, every C-state with at least one sub-state is usable with the
+//! MWAIT hint for it; otherwise (or with `idle=halt` on the command line)
+//! the only state is `hlt`. Without ARAT the LAPIC timer stops in C3 and
+//! deeper, so only C1 and C2 are used. MWAIT monitors the CPU's
+//! `need_resched` flag, and any interrupt ends the wait as well.
+//!
+//! The governor picks, among the usable states, the deepest one whose
+//! target residency fits the expected idle time: the time until the LAPIC
+//! timer fires in TSC-deadline mode, where it is armed for the next tick or
+//! the earliest sleeper; otherwise the time to the earliest sleeping task's
+//! deadline, and at most a tick since the tick interrupt ends every wait. Entries and time spent in each state are counted per
+//! CPU and shown in `/proc/idle`.
+
+use crate::arch::x86_64::smp::percpu::percpu_current;
+use crate::time::{clock, Duration, Instant};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// C-states MWAIT can name (C1..C7)
+const MWAIT_CSTATES: usize = 7;
+
+/// Deepest C-state (as an index from C1) whose LAPIC timer keeps running
+/// without ARAT
+const NO_ARAT_DEEPEST: usize = 1;
+
+/// Idle states a CPU may have: `hlt` or one per MWAIT C-state
+const MAX_STATES: usize = MWAIT_CSTATES;
+
+/// Time an idle period should last for each MWAIT C-state (C1 first) to
+/// be worth its entry and exit latency
+const TARGET_RESIDENCY: [Duration; MWAIT_CSTATES] = [
+    Duration::ZERO,
+    Duration::from_micros(100),
+    Duration::from_micros(300),
+    Duration::from_micros(600),
+    Duration::from_millis(1),
+    Duration::from_millis(2),
+    Duration::from_millis(5),
+];
+
+/// A way to wait for an interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State {
+    name: &'static str,
+    /// MWAIT hint (EAX), or None for `hlt`
+    hint: Option<u32>,
+    target_residency: Duration,
+}
+
+const HLT: State = State {
+    name: "hlt",
+    hint: None,
+    target_residency: Duration::ZERO,
+};
+
+/// The usable idle states, shallowest first
+#[derive(Debug, Clone, Copy)]
+struct States {
+    list: [State; MAX_STATES],
+    len: usize,
+}
+
+impl States {
+    /// The states the MWAIT sub-state counts in `substates` (CPUID leaf 5
+    /// EDX) allow, C1 and C2 only without `arat`, or just `hlt` if MWAIT
+    /// is unusable or names none
+    fn from_cpuid(mwait: bool, substates: u32, arat: bool) -> Self {
+        const NAMES: [&str; MWAIT_CSTATES] = ["C1", "C2", "C3", "C4", "C5", "C6", "C7"];
+
+        let mut states = States { list: [HLT; MAX_STATES], len: 0 };
+        if mwait {
+            let deepest = if arat { MWAIT_CSTATES - 1 } else { NO_ARAT_DEEPEST };
+            for cstate in 0..=deepest {
+                // Nibble 0 counts C0 sub-states; C1 is nibble 1
+                if (substates >> ((cstate + 1) * 4)) & 0xf != 0 {
+                    states.list[states.len] = State {
+                        name: NAMES[cstate],
+                        hint: Some((cstate as u32) << 4),
+                        target_residency: TARGET_RESIDENCY[cstate],
+                    };
+                    states.len += 1;
+                }
+            }
+        }
+        if states.len == 0 {
+            states.len = 1;
+        }
+        states
+    }
+
+    fn as_slice(&self) -> &[State] {
+        &self.list[..self.len]
+    }
+
+    /// Index of the deepest state worth entering for `expected` idle time
+    fn select(&self, expected: Duration) -> usize {
+        self.as_slice()
+            .iter()
+            .rposition(|state| state.target_residency <= expected)
+            .unwrap_or(0)
+    }
+}
+
+static STATES: spin::Once<States> = spin::Once::new();
+
+fn states() -> &'static States {
+    STATES.call_once(|| {
+        let features = crate::arch::x86_64::cpu::features::get();
+        let mwait = features.monitor && crate::cmdline::value("idle") != Some("halt");
+        States::from_cpuid(mwait, features.mwait_substates, features.arat)
+    })
+}
+
+/// One CPU's idle counters
+struct IdleStats {
+    entries: [AtomicU64; MAX_STATES],
+    residency_ns: [AtomicU64; MAX_STATES],
+    /// When the open idle period began, or 0 outside one
+    entered_at: AtomicU64,
+    /// State of the open idle period
+    state: AtomicUsize,
+}
+
+impl IdleStats {
+    const fn new() -> Self {
+        Self {
+            entries: [const { AtomicU64::new(0) }; MAX_STATES],
+            residency_ns: [const { AtomicU64::new(0) }; MAX_STATES],
+            entered_at: AtomicU64::new(0),
+            state: AtomicUsize::new(0),
+        }
+    }
+
+    fn enter(&self, state: usize, now: u64) {
+        self.entries[state].fetch_add(1, Ordering::Relaxed);
+        self.state.store(state, Ordering::Relaxed);
+        self.entered_at.store(now.max(1), Ordering::Relaxed);
+    }
+
+    /// End the open idle period, if any, at `now`
+    fn exit(&self, now: u64) {
+        let entered_at = self.entered_at.swap(0, Ordering::Relaxed);
+        if entered_at != 0 {
+            let state = self.state.load(Ordering::Relaxed);
+            self.residency_ns[state].fetch_add(now.saturating_sub(entered_at), Ordering::Relaxed);
+        }
+    }
+}
+
+crate::per_cpu! {
+    /// Each CPU's idle counters
+    static STATS: IdleStats = IdleStats::new();
+}
+
+/// How long this CPU can expect to stay idle
+fn expected_idle() -> Duration {
+    if let Some(until) = crate::arch::x86_64::apic::timer::until_next_interrupt() {
+        return until;
+    }
+    let now = Instant::now();
+    super::next_wakeup()
+        .map_or(Duration::TICK, |deadline| deadline.saturating_duration_since(now))
+        .min(Duration::TICK)
+}
+
+/// Wait for an interrupt in the state the governor picks
+///
+/// Called in a loop by the idle task, with interrupts enabled. A switch
+/// away on the interrupt return path happens before this returns, so the
+/// scheduler ends the idle period first (see [`interrupted`]).
+pub fn idle() {
+    let states = states();
+    let index = states.select(expected_idle());
+    let stats = STATS.get();
+    let need_resched = &percpu_current().need_resched;
+
+    stats.enter(index, clock::now_ns());
+    match states.list[index].hint {
+        Some(hint) => unsafe {
+            core::arch::asm!(
+                "monitor",
+                in("rax") need_resched.as_ptr(),
+                in("ecx") 0,
+                in("edx") 0,
+                options(nostack),
+            );
+            if !need_resched.load(Ordering::Relaxed) {
+                core::arch::asm!("mwait", in("eax") hint, in("ecx") 0, options(nostack));
+            }
+        },
+        None => unsafe {
+            core::arch::asm!("hlt", options(nostack, nomem));
+        },
+    }
+    stats.exit(clock::now_ns());
+}
+
+/// End this CPU's idle period before a switch away from the idle task
+pub fn interrupted() {
+    STATS.get().exit(clock::now_ns());
+}
+
+/// Write the idle state of every CPU: entries and time in each state
+pub fn write_stats(out: &mut impl fmt::Write) -> fmt::Result {
+    let states = states();
+    writeln!(out, "cpu state  hint     entries residency_ms")?;
+    for (cpu, stats) in STATS.iter().enumerate().take(super::get_cpu_count()) {
+        for (index, state) in states.as_slice().iter().enumerate() {
+            write!(out, "{:<3} {:<5} ", cpu, state.name)?;
+            match state.hint {
+                Some(hint) => write!(out, "{:#06x}", hint)?,
+                None => write!(out, "{:<6}", "-")?,
+            }
+            writeln!(
+                out,
+                " {:>9} {:>12}",
+                stats.entries[index].load(Ordering::Relaxed),
+                Duration::from_nanos(stats.residency_ns[index].load(Ordering::Relaxed)).as_millis()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+crate::kernel_test! {
+    /// MWAIT C-states come from the sub-state counts, and the governor
+    /// picks the deepest one the expected idle time pays for
+    fn idle_governor_selects_by_residency() {
+        let halt = States::from_cpuid(false, 0x0000_2220, true);
+        crate::ktest_assert_eq!(halt.as_slice(), &[HLT][..], "no MWAIT but states other than hlt");
+        crate::ktest_assert_eq!(halt.select(Duration::from_secs(1)), 0, "hlt not picked");
+
+        // Sub-states for C1, C2 and C3; none for C4 and up
+        let mwait = States::from_cpuid(true, 0x0000_2220, true);
+        crate::ktest_assert_eq!(mwait.len, 3, "usable C-states");
+        crate::ktest_assert_eq!(mwait.list[2].hint, Some(0x20), "C3 hint");
+        crate::ktest_assert_eq!(mwait.select(Duration::from_micros(50)), 0, "short idle not C1");
+        crate::ktest_assert_eq!(mwait.select(Duration::from_micros(150)), 1, "medium idle not C2");
+        crate::ktest_assert_eq!(mwait.select(Duration::from_millis(10)), 2, "long idle not deepest");
+
+        crate::ktest_assert_eq!(States::from_cpuid(true, 0x0000_2220, false).len, 2, "C3 without ARAT");
+        crate::ktest_assert_eq!(States::from_cpuid(true, 0, true).len, 1, "no sub-states but no fallback");
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! Task enumeration
+//!
+//! [`TaskInfo`] is a snapshot of one task: its ID, process, parent, state,
+//! priority, CPU time and name. `SYS_TASK_INFO` reports one task and
+//! `SYS_TASK_LIST` every live task, which is what `ps` in the shell shows;
+//! SysRq `t` prints the same list from interrupt context. Exited tasks are
+//! listed until they are reaped.
+
+use super::task::{Task, TaskId, TaskState};
+use mello_abi::{TASK_BLOCKED, TASK_EXITED, TASK_READY, TASK_RUNNING, TASK_SLEEPING};
+
+/// Bytes of a task name kept in [`TaskInfo`], not counting the NUL
+pub const NAME_LEN: usize = 31;
+
+/// A task as `SYS_TASK_INFO` and `SYS_TASK_LIST` report it
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub pid: u64,
+    pub ppid: u64,
+    /// CPU time, in timer ticks
+    pub cpu_ticks: u64,
+    /// One of `mello_abi::TASK_*`
+    pub state: u32,
+    /// `TaskPriority::as_index`
+    pub priority: u32,
+    /// Name, NUL-padded and cut to [`NAME_LEN`] bytes
+    pub name: [u8; NAME_LEN + 1],
+}
+
+mello_abi::check_layout!(TaskInfo, mello_abi::TaskInfo { id, pid, ppid, cpu_ticks, state, priority, name });
+
+impl TaskInfo {
+    pub const EMPTY: Self = Self {
+        id: 0,
+        pid: 0,
+        ppid: 0,
+        cpu_ticks: 0,
+        state: TASK_READY,
+        priority: 0,
+        name: [0; NAME_LEN + 1],
+    };
+
+    /// Snapshot of `task`
+    pub fn of(task: &Task) -> Self {
+        let usage = task.usage.snapshot();
+        let mut name = [0; NAME_LEN + 1];
+        let len = task.name.len().min(NAME_LEN);
+        name[..len].copy_from_slice(&task.name.as_bytes()[..len]);
+        Self {
+            id: task.id as u64,
+            pid: task.pid as u64,
+            ppid: task.ppid as u64,
+            cpu_ticks: usage.user_ticks + usage.system_ticks,
+            state: state_code(task.state),
+            priority: task.priority.as_index() as u32,
+            name,
+        }
+    }
+
+    /// The name, up to the first NUL
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+
+    /// The state as a word, for reports
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            TASK_READY => "ready",
+            TASK_RUNNING => "running",
+            TASK_SLEEPING => "sleeping",
+            TASK_BLOCKED => "blocked",
+            TASK_EXITED => "exited",
+            _ => "?",
+        }
+    }
+}
+
+/// ABI code of `state`
+pub const fn state_code(state: TaskState) -> u32 {
+    match state {
+        TaskState::Ready => TASK_READY,
+        TaskState::Running => TASK_RUNNING,
+        TaskState::Sleeping => TASK_SLEEPING,
+        TaskState::Blocked => TASK_BLOCKED,
+        TaskState::Exited => TASK_EXITED,
+    }
+}
+
+/// Snapshot of task `id`
+pub fn task(id: TaskId) -> Option<TaskInfo> {
+    super::get_task_by_id(id).map(TaskInfo::of)
+}
+
+/// Call `f` with a snapshot of every live task, in task table order
+pub fn for_each(mut f: impl FnMut(TaskInfo)) {
+    super::for_each_task(|task| f(TaskInfo::of(task)));
+}
+
+/// Fill `out` with snapshots of the live tasks, in task table order
+///
+/// Returns the number of tasks, which may be more than fit in `out`.
+pub fn list(out: &mut [TaskInfo]) -> usize {
+    let mut count = 0;
+    for_each(|info| {
+        if let Some(slot) = out.get_mut(count) {
+            *slot = info;
+        }
+        count += 1;
+    });
+    count
+}
+
+crate::kernel_test! {
+    /// A spawned task is reported with its name, state and priority, long
+    /// names are cut, and the list counts every task
+    fn task_info_lists_spawned_task() {
+        use super::priority::TaskPriority;
+
+        const LONG: &str = "ktest_task_info_with_a_name_longer_than_kept";
+        let id = super::spawn_task(LONG, super::ktest_parked_task, TaskPriority::High)
+            .map_err(|_| "spawn_task failed")?;
+        let info = task(id).ok_or("task_info found no task")?;
+        crate::ktest_assert_eq!(info.name(), &LONG[..NAME_LEN], "name not cut");
+        crate::ktest_assert_eq!(info.state, TASK_READY, "state");
+        crate::ktest_assert_eq!(info.priority, TaskPriority::High.as_index() as u32, "priority");
+
+        // Slot 0 holds the idle task, listed first
+        let mut listed = [TaskInfo::EMPTY; 1];
+        let count = list(&mut listed);
+        let mut live = 0;
+        super::for_each_task(|_| live += 1);
+        crate::ktest_assert_eq!(count, live, "not every task counted");
+        crate::ktest_assert_eq!(listed[0].id, 0, "idle task not first");
+        crate::ktest_assert_eq!(list(&mut []), count, "count depends on the buffer");
+        Ok(())
+    }
+}
@@ -8,10 +8,12 @@
 //! The scheduler is designed to work correctly in SMP environments with multiple CPUs.
 //! To prevent deadlocks, locks must be acquired in the following order:
 //!
-//! 1. SCHED (global scheduler state)
-//! 2. TASK_TABLE (global task table)
-//! 3. Per-CPU runqueue locks (in ascending CPU ID order)
-//! 4. Per-task state (implicit in get_task_mut)
+//! 1. TASK_TABLE_WRITER (task table updates) or WAIT_STATE (sleep/wake transitions)
+//! 2. Per-CPU runqueue locks (in ascending CPU ID order)
+//! 3. Per-task state (implicit in get_task_mut)
+//!
+//! Reading the task table takes no lock: it is published with RCU (see
+//! `sync::rcu`), so lookups copy a task pointer out of the current version.
 //!
 //! ## Key SMP Design Decisions
 //!
@@ -19,20 +21,33 @@
 //! - **Lock-Free Task Assignment**: New tasks are assigned to the CPU with the smallest runqueue
 //! - **IPI-Based Coordination**: RESCHEDULE_IPI is sent when tasks are enqueued to remote CPUs
 //! - **Ordered Lock Acquisition**: Multiple runqueue locks are always acquired in CPU ID order
+//! - **Per-CPU Current Task**: Each CPU's PerCpu (reached through GS) caches a pointer
+//!   to its running task, so switches and timer ticks find it without the task table
 //!
 //! ## Critical Sections
 //!
-//! - Task creation: Holds SCHED and TASK_TABLE briefly, then releases before enqueuing
+//! - Task creation: Holds TASK_TABLE_WRITER only to publish new table
+//!   versions (one reserving a slot, whose generation makes the task ID,
+//!   and one filling it in), then releases before enqueuing
 //! - Task migration: Holds two runqueue locks in CPU ID order
 //! - Context switch: Only accesses current CPU's runqueue (no cross-CPU locks)
 //!
 //! See `kernel/src/sync/lock_ordering.rs` for complete lock ordering documentation.
 
+pub mod accounting;
+pub mod bandwidth;
+pub mod burst;
 pub mod context;
+pub mod idle;
+pub mod info;
 pub mod priority;
 pub mod process_group;
+pub mod stats;
 pub mod task;
+pub mod thread;
 pub mod timer;
+pub mod wait;
+pub mod workqueue;
 
 /// Scheduler logging macros with consistent [SCHED] prefix
 ///
@@ -75,14 +90,25 @@ macro_rules! sched_error {
 }
 
 use crate::arch::x86_64::smp::percpu::{percpu_current, percpu_for};
+use crate::mm::VirtAddr;
+use crate::time::{Duration, Instant};
 use context::CpuContext;
 use priority::TaskPriority;
-use spin::Mutex;
-pub use task::Task;
+use crate::sync::{rcu, SpinLock};
+pub use task::{SpawnError, Task};
 use task::{SchedulerError, SchedulerResult, TaskId, TaskState};
 
-/// Maximum number of tasks supported
-const MAX_TASKS: usize = 64;
+/// Maximum number of tasks alive at once
+///
+/// The task table grows up to this many slots. Slots of reaped tasks are
+/// reused, so this bounds live tasks, not spawns; per-task tables outside
+/// the scheduler are indexed by `task::task_slot`, which is always below it.
+pub const MAX_TASKS: usize = 64;
+
+const _: () = assert!(MAX_TASKS <= 1 << task::TASK_SLOT_BITS);
+
+/// Slots in the first task table version, doubled whenever it fills up
+const INITIAL_TASK_SLOTS: usize = 16;
 
 /// Maximum number of tasks per CPU runqueue (from percpu.rs)
 const MAX_RUNQUEUE_SIZE: usize = 64;
@@ -91,7 +117,7 @@ const MAX_RUNQUEUE_SIZE: usize = 64;
 ///
 /// # Safety
 /// This is safe because:
-/// - We only access tasks through the TASK_TABLE mutex
+/// - Task table versions are only replaced, never changed, once published
 /// - Each task is only accessed by one context at a time
 /// - Tasks are heap-allocated and don't move
 #[derive(Copy, Clone)]
@@ -173,121 +199,483 @@ impl TaskQueue {
     }
 }
 
-/// Scheduler state containing global task management
+/// Get the number of online CPUs from SMP module
+fn get_cpu_count() -> usize {
+    crate::arch::x86_64::smp::get_cpu_count()
+}
+
+/// One entry of the task table
+#[derive(Clone, Copy)]
+struct TaskSlot {
+    /// The task in the slot, null if it is free
+    task: TaskPtr,
+    /// Generation of the slot's task, or of the next one if it is free
+    generation: usize,
+    /// Taken by a spawn that has not published its task yet
+    reserved: bool,
+}
+
+impl TaskSlot {
+    const EMPTY: Self = Self {
+        task: TaskPtr::null(),
+        generation: 0,
+        reserved: false,
+    };
+
+    fn is_free(&self) -> bool {
+        self.task.is_null() && !self.reserved
+    }
+
+    /// Make the IDs handed out for the slot so far name no task
+    fn next_generation(&mut self) {
+        self.generation = (self.generation + 1) & ((1 << task::TASK_GENERATION_BITS) - 1);
+    }
+}
+
+/// One published version of the task table
 ///
-/// Note: Runqueues are now per-CPU (in PerCpu structure)
-struct SchedState {
-    /// Next task ID to assign (incremented for each new task)
-    next_tid: usize,
+/// A header followed in the same allocation by `len` [`TaskSlot`]s,
+/// indexed by `task::task_slot`. Slot 0 is the idle task's. A version is
+/// never changed once published: writers copy it, change the copy and
+/// publish that.
+#[repr(C)]
+struct TaskTable {
+    len: usize,
 }
 
-impl SchedState {
-    /// Create a new empty scheduler state
-    fn new() -> Self {
-        Self {
-            next_tid: 1, // Start at 1, reserve 0 for idle task
+impl TaskTable {
+    /// Bytes taken by a version with `len` slots
+    const fn size(len: usize) -> usize {
+        core::mem::size_of::<TaskTable>() + len * core::mem::size_of::<TaskSlot>()
+    }
+
+    fn slots(&self) -> &[TaskSlot] {
+        unsafe { core::slice::from_raw_parts((self as *const Self).add(1) as *const TaskSlot, self.len) }
+    }
+
+    fn slots_mut(&mut self) -> &mut [TaskSlot] {
+        unsafe { core::slice::from_raw_parts_mut((self as *mut Self).add(1) as *mut TaskSlot, self.len) }
+    }
+
+    /// Lowest free slot a spawned task can take
+    fn free_slot(&self) -> Option<usize> {
+        (1..self.len).find(|&slot| self.slots()[slot].is_free())
+    }
+
+    /// The slot of `id`, if it still holds that task
+    fn get(&self, id: TaskId) -> Option<TaskPtr> {
+        self.slots()
+            .get(task::task_slot(id))
+            .filter(|slot| slot.generation == task::task_generation(id))
+            .map(|slot| slot.task)
+            .filter(|ptr| !ptr.is_null())
+    }
+}
+
+/// Empty version the scheduler starts with (never freed)
+static INITIAL_TASK_TABLE: TaskTable = TaskTable { len: 0 };
+
+/// Current version of the task table
+///
+/// Readers load it inside `rcu::read` and need no lock; see
+/// [`update_task_table`] for writers.
+static TASK_TABLE: core::sync::atomic::AtomicPtr<TaskTable> =
+    core::sync::atomic::AtomicPtr::new(&INITIAL_TASK_TABLE as *const TaskTable as *mut TaskTable);
+
+/// Replaced task table versions waiting for a grace period before freeing
+const RETIRED_TABLES: usize = 16;
+
+/// A replaced version and the grace period that must pass before freeing it
+struct RetiredTable {
+    table: *mut TaskTable,
+    grace_period: rcu::GracePeriod,
+}
+
+// Only touched with TASK_TABLE_WRITER held
+unsafe impl Send for RetiredTable {}
+
+/// Serializes task table writers and holds the versions they replaced
+static TASK_TABLE_WRITER: SpinLock<[Option<RetiredTable>; RETIRED_TABLES]> =
+    SpinLock::new([const { None }; RETIRED_TABLES]);
+
+/// Serializes moving tasks out of Sleeping/Blocked, so `end_wait` and
+/// `wake_sleeping_tasks` don't both wake the same task
+static WAIT_STATE: SpinLock<()> = SpinLock::new(());
+
+/// Publish a copy of the task table with `update` applied
+///
+/// The copy has twice the slots if the current version has no free one,
+/// up to [`MAX_TASKS`]. Readers that loaded the old version keep using it;
+/// it is freed by a later update once a grace period has passed since this
+/// one. If too many versions are waiting, waits for the grace period
+/// instead.
+///
+/// # Errors
+/// Returns `SchedulerError::OutOfMemory` if the copy can't be allocated
+fn update_task_table<R>(update: impl FnOnce(&mut TaskTable) -> R) -> SchedulerResult<R> {
+    use crate::mm::allocator::try_kmalloc;
+    use core::sync::atomic::Ordering;
+
+    let mut retired = TASK_TABLE_WRITER.lock();
+
+    let old = TASK_TABLE.load(Ordering::Acquire);
+    let old_len = unsafe { (*old).len };
+    let len = match unsafe { (*old).free_slot() } {
+        Some(_) => old_len,
+        None => (old_len * 2).clamp(INITIAL_TASK_SLOTS, MAX_TASKS),
+    };
+    let table = try_kmalloc(TaskTable::size(len)).map_err(|_| SchedulerError::OutOfMemory)?.as_ptr() as *mut TaskTable;
+    let result = unsafe {
+        table.write(TaskTable { len });
+        let slots = (*table).slots_mut();
+        slots[..old_len].copy_from_slice((*old).slots());
+        slots[old_len..].fill(TaskSlot::EMPTY);
+        update(&mut *table)
+    };
+    TASK_TABLE.store(table, Ordering::Release);
+
+    // Free the versions no reader can still be using
+    for slot in retired.iter_mut() {
+        if slot.as_ref().is_some_and(|entry| entry.grace_period.has_elapsed()) {
+            if let Some(entry) = slot.take() {
+                free_task_table(entry.table);
+            }
         }
     }
+
+    if core::ptr::eq(old, &INITIAL_TASK_TABLE) {
+        return Ok(result);
+    }
+    let entry = RetiredTable {
+        table: old,
+        grace_period: rcu::GracePeriod::start(),
+    };
+    match retired.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => *slot = Some(entry),
+        None => {
+            // Every waiting version was replaced before `old` was
+            rcu::synchronize();
+            for entry in retired.iter_mut().filter_map(Option::take) {
+                free_task_table(entry.table);
+            }
+            free_task_table(old);
+        }
+    }
+    Ok(result)
 }
 
-/// Global scheduler state protected by a mutex
-static SCHED: spin::Once<Mutex<SchedState>> = spin::Once::new();
+/// Free a replaced task table version
+fn free_task_table(table: *mut TaskTable) {
+    let size = TaskTable::size(unsafe { (*table).len });
+    crate::mm::allocator::kfree(table as *mut u8, size);
+}
 
-/// Get the number of online CPUs from SMP module
-fn get_cpu_count() -> usize {
-    crate::arch::x86_64::smp::get_cpu_count()
+/// Look up `id` in the current task table version
+///
+/// None if the slot is free or was reused since `id`'s task was reaped.
+fn task_ptr(id: TaskId) -> Option<TaskPtr> {
+    use core::sync::atomic::Ordering;
+
+    rcu::read(|| {
+        let table = unsafe { &*TASK_TABLE.load(Ordering::Acquire) };
+        table.get(id)
+    })
 }
 
-/// Task table storing all Task objects
-/// Uses TaskPtr wrapper for heap-allocated tasks
-/// TaskPtr::null() indicates an empty slot
-static TASK_TABLE: Mutex<[TaskPtr; MAX_TASKS]> = Mutex::new([TaskPtr::null(); MAX_TASKS]);
+/// Every task in the current task table version
+///
+/// Iterates over a copy, so it may miss a task spawned meanwhile. Tasks
+/// are freed only once reaped (see [`reap_task`]), so the references stay
+/// valid for tasks that have not exited.
+fn all_tasks() -> impl Iterator<Item = &'static mut Task> {
+    use core::sync::atomic::Ordering;
+
+    let mut tasks = [TaskPtr::null(); MAX_TASKS];
+    rcu::read(|| {
+        let table = unsafe { &*TASK_TABLE.load(Ordering::Acquire) };
+        for (copy, slot) in tasks.iter_mut().zip(table.slots()) {
+            *copy = slot.task;
+        }
+    });
+    tasks
+        .into_iter()
+        .filter(|ptr| !ptr.is_null())
+        .map(|ptr| unsafe { &mut *ptr.get() })
+}
 
-/// Spawn a new task with the given entry point
+/// Take a free task table slot for a task being spawned; returns its ID
 ///
-/// This function:
-/// 1. Generates a unique TaskId
-/// 2. Creates a new Task with Task::new()
-/// 3. Allocates the Task on the heap and adds it to TASK_TABLE
-/// 4. Assigns the task to a CPU (will be done by enqueue_task)
-/// 5. Logs the task spawn
+/// # Errors
+/// Returns `SpawnError::TableFull` if all [`MAX_TASKS`] slots are taken
+/// Returns `SpawnError::OutOfMemory` if the table can't be copied
+fn reserve_task_slot() -> Result<TaskId, SpawnError> {
+    let reserved = update_task_table(|table| {
+        let slot = table.free_slot()?;
+        let entry = &mut table.slots_mut()[slot];
+        entry.reserved = true;
+        Some(task::make_task_id(slot, entry.generation))
+    });
+    match reserved {
+        Ok(Some(id)) => Ok(id),
+        Ok(None) => Err(SpawnError::TableFull),
+        Err(_) => Err(SpawnError::OutOfMemory),
+    }
+}
+
+/// Put `task` in the slot reserved for `id`, or free the slot if `task` is
+/// None (the spawn failed)
 ///
-/// # Arguments
-/// * `name` - Human-readable task name
-/// * `entry_point` - Function pointer to the task's entry point
-/// * `priority` - Task priority level
+/// A failed spawn still bumps the slot's generation: its setup may have
+/// handed the ID out already.
+fn publish_task(id: TaskId, task: Option<*mut Task>) -> SchedulerResult<()> {
+    update_task_table(|table| {
+        let entry = &mut table.slots_mut()[task::task_slot(id)];
+        entry.reserved = false;
+        match task {
+            Some(task) => entry.task = TaskPtr::new(task),
+            None => entry.next_generation(),
+        }
+    })
+}
+
+/// Exited tasks waiting for [`reap_exited`] to release their slots
+static REAP_PENDING: SpinLock<TaskQueue> = SpinLock::new(TaskQueue::new());
+
+/// Release the slot, kernel stack and memory of exited task `id`
 ///
-/// # Returns
-/// A Result containing the TaskId of the newly spawned task, or an error if spawning fails
+/// Called once nothing needs the task any more: when a thread exits, or
+/// when the parent of a process collects its exit status. The task is
+/// only queued here; the next spawn reaps it (see [`reap_exited`]), once
+/// it is no longer running on any CPU. Its ID then names no task.
+pub fn reap_task(id: TaskId) {
+    if is_idle_task(id) {
+        return;
+    }
+    if !REAP_PENDING.lock().push_back(id) {
+        sched_warn!("Reap queue full, task {} keeps its slot", id);
+    }
+}
+
+/// Reap the queued tasks that no CPU is running any more
+///
+/// Removes them from the task table, waits until no CPU can still use
+/// them and frees them. The wait is two grace periods: a CPU switching
+/// away from a task passes a quiescent state before it has left the task's
+/// stack, but not before its next one. Returns the number of tasks reaped.
+fn reap_exited() -> usize {
+    use crate::mm::allocator::kfree;
+
+    let mut ready = [0; MAX_TASKS];
+    let mut count = 0;
+    {
+        let mut pending = REAP_PENDING.lock();
+        for _ in 0..pending.len() {
+            let Some(id) = pending.pop_front() else { break };
+            let running = (0..get_cpu_count()).any(|cpu| percpu_for(cpu).current_task == Some(id));
+            match get_task(id) {
+                Some(task) if task.state == TaskState::Exited && !running => {
+                    ready[count] = id;
+                    count += 1;
+                }
+                Some(task) if task.state == TaskState::Exited => {
+                    pending.push_back(id);
+                }
+                // Already reaped, or not exited after all
+                _ => {}
+            }
+        }
+    }
+    if count == 0 {
+        return 0;
+    }
+
+    let mut tasks = [TaskPtr::null(); MAX_TASKS];
+    let removed = update_task_table(|table| {
+        for (&id, task) in ready[..count].iter().zip(tasks.iter_mut()) {
+            let entry = &mut table.slots_mut()[task::task_slot(id)];
+            *task = entry.task;
+            entry.task = TaskPtr::null();
+            entry.next_generation();
+        }
+    });
+    if removed.is_err() {
+        // Try again at the next spawn
+        let mut pending = REAP_PENDING.lock();
+        for &id in &ready[..count] {
+            pending.push_back(id);
+        }
+        return 0;
+    }
+
+    rcu::synchronize();
+    rcu::synchronize();
+    for task in &tasks[..count] {
+        let task = task.get();
+        unsafe {
+            let (id, pid, stack) = ((*task).id, (*task).pid, VirtAddr::from_ptr((*task).stack));
+            if pid == id {
+                crate::sys::handle::release(pid);
+            }
+            if let Some(stack) = crate::mm::kstack::KernelStack::at(stack) {
+                crate::mm::kstack::free_kernel_stack(stack);
+            }
+            crate::arch::x86_64::fpu::free_area((*task).context.fpu_area as *mut u8);
+            kfree(task as *mut u8, core::mem::size_of::<Task>());
+        }
+    }
+    sched_log!("Reaped {} exited tasks", count);
+    count
+}
+
+/// Spawn a new task with the given entry point and a default stack
+///
+/// Shorthand for `TaskBuilder::new(name, entry_point).priority(priority).spawn()`.
 ///
 /// # Errors
-/// Returns `SchedulerError::TooManyTasks` if the task table is full
-/// Returns `SchedulerError::OutOfMemory` if memory allocation fails
-/// Returns `SchedulerError::RunqueueFull` if the runqueue is full
+/// As [`TaskBuilder::spawn`].
 pub fn spawn_task(
     name: &'static str,
     entry_point: fn() -> !,
     priority: TaskPriority,
-) -> SchedulerResult<TaskId> {
-    use crate::mm::allocator::kmalloc;
-    use core::ptr;
+) -> Result<TaskId, SpawnError> {
+    TaskBuilder::new(name, entry_point).priority(priority).spawn()
+}
 
-    // Lock both SCHED and TASK_TABLE
-    let mut sched = SCHED.get().expect("Scheduler not initialized").lock();
-    let mut task_table = TASK_TABLE.lock();
+/// [`spawn_task`], letting `setup` fill in the new task before it is
+/// enqueued and can run
+pub fn spawn_task_with(
+    name: &'static str,
+    entry_point: fn() -> !,
+    priority: TaskPriority,
+    setup: impl FnOnce(&mut Task),
+) -> Result<TaskId, SpawnError> {
+    TaskBuilder::new(name, entry_point).priority(priority).spawn_with(setup)
+}
 
-    // 1. Generate unique TaskId
-    let task_id = sched.next_tid;
+/// Options for spawning a task
+///
+/// ```rust,ignore
+/// let id = TaskBuilder::new("worker", worker_entry)
+///     .priority(TaskPriority::High)
+///     .stack_size(32 * 1024)
+///     .spawn()?;
+/// ```
+///
+/// Options not set default to `TaskPriority::Normal` and a
+/// [`task::DEFAULT_STACK_SIZE`] kernel stack.
+#[derive(Debug, Clone, Copy)]
+#[must_use]
+pub struct TaskBuilder {
+    name: &'static str,
+    entry_point: fn() -> !,
+    priority: TaskPriority,
+    stack_size: usize,
+}
 
-    if task_id >= MAX_TASKS {
-        sched_error!("Too many tasks! Maximum is {}", MAX_TASKS);
-        return Err(SchedulerError::TooManyTasks);
+impl TaskBuilder {
+    /// A task called `name` that starts at `entry_point`
+    pub const fn new(name: &'static str, entry_point: fn() -> !) -> Self {
+        Self {
+            name,
+            entry_point,
+            priority: TaskPriority::Normal,
+            stack_size: task::DEFAULT_STACK_SIZE,
+        }
     }
 
-    sched.next_tid += 1;
+    /// Scheduling priority
+    pub const fn priority(mut self, priority: TaskPriority) -> Self {
+        self.priority = priority;
+        self
+    }
 
-    // Drop locks before creating task (to avoid holding locks during allocation)
-    drop(sched);
-    drop(task_table);
+    /// Kernel stack size in bytes: whole pages from
+    /// [`task::MIN_STACK_SIZE`] to [`task::MAX_STACK_SIZE`]
+    pub const fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = size;
+        self
+    }
 
-    // 2. Create new Task with specified priority
-    let task = match Task::new(task_id, name, entry_point, priority) {
-        Ok(task) => task,
-        Err(e) => {
-            sched_error!("Failed to create task {}: {:?}", task_id, e);
-            return Err(e);
-        }
-    };
+    /// Create the task and queue it on the CPU with the shortest runqueue
+    ///
+    /// # Errors
+    /// Returns `SpawnError::InvalidStackSize` if the stack size is out of
+    /// range or not whole pages
+    /// Returns `SpawnError::TableFull` if the task table is full
+    /// Returns `SpawnError::OutOfMemory` if the task, its stack or the task
+    /// table cannot be allocated even after reclaiming caches
+    pub fn spawn(self) -> Result<TaskId, SpawnError> {
+        self.spawn_with(|_| {})
+    }
 
-    // 3. Allocate Task on heap and add to TASK_TABLE
-    let task_size = core::mem::size_of::<Task>();
-    let task_ptr = kmalloc(task_size) as *mut Task;
+    /// [`spawn`](Self::spawn), letting `setup` fill in the new task before
+    /// it is enqueued and can run
+    ///
+    /// This function:
+    /// 1. Checks the stack size, reaps exited tasks and reserves a task
+    ///    table slot, whose slot and generation make the TaskId
+    /// 2. Creates a new Task with Task::with_stack_size()
+    /// 3. Allocates the Task on the heap and publishes it in its slot
+    /// 4. Assigns the task to a CPU (will be done by enqueue_task)
+    /// 5. Logs the task spawn
+    pub fn spawn_with(self, setup: impl FnOnce(&mut Task)) -> Result<TaskId, SpawnError> {
+        use crate::mm::allocator::try_kmalloc;
+        use core::ptr;
+
+        let Self { name, entry_point, priority, stack_size } = self;
+
+        // 1. Check the stack size, then reserve a slot for the task
+        if !task::valid_stack_size(stack_size) {
+            sched_error!("Invalid kernel stack size {} for task {}", stack_size, name);
+            return Err(SpawnError::InvalidStackSize);
+        }
+        reap_exited();
+        let task_id = reserve_task_slot().inspect_err(|e| match e {
+            SpawnError::TableFull => sched_error!("Too many tasks! Maximum is {}", MAX_TASKS),
+            _ => sched_error!("Failed to grow the task table for {}", name),
+        })?;
+
+        // 2. Create new Task with specified priority and stack
+        let mut task = match Task::with_stack_size(task_id, name, entry_point, priority, stack_size) {
+            Ok(task) => task,
+            Err(e) => {
+                sched_error!("Failed to create task {}: {:?}", task_id, e);
+                let _ = publish_task(task_id, None);
+                return Err(SpawnError::OutOfMemory);
+            }
+        };
+        setup(&mut task);
 
-    if task_ptr.is_null() {
-        sched_error!("Failed to allocate memory for task {} ({})", task_id, name);
-        return Err(SchedulerError::OutOfMemory);
-    }
+        // 3. Allocate Task on heap and publish it in its slot
+        let Ok(task_ptr) = try_kmalloc(core::mem::size_of::<Task>()) else {
+            sched_error!("Failed to allocate memory for task {} ({})", task_id, name);
+            let _ = publish_task(task_id, None);
+            return Err(SpawnError::OutOfMemory);
+        };
+        let task_ptr = task_ptr.as_ptr() as *mut Task;
 
-    unsafe {
-        ptr::write(task_ptr, task);
-    }
+        unsafe {
+            ptr::write(task_ptr, task);
+        }
 
-    let mut task_table = TASK_TABLE.lock();
-    task_table[task_id] = TaskPtr::new(task_ptr);
-    drop(task_table);
+        if publish_task(task_id, Some(task_ptr)).is_err() {
+            sched_error!("Failed to publish task {} ({})", task_id, name);
+            return Err(SpawnError::OutOfMemory);
+        }
 
-    // 4. Enqueue task to a CPU runqueue (will select CPU with smallest runqueue)
-    enqueue_task(task_id, None);
+        // 4. Enqueue task to a CPU runqueue (will select CPU with smallest runqueue)
+        enqueue_task(task_id, None);
 
-    // 5. Log task spawn
-    sched_info!(
-        "Spawned task {}: {} (priority: {:?})",
-        task_id,
-        name,
-        priority
-    );
+        // 5. Log task spawn
+        sched_info!(
+            "Spawned task {}: {} (priority: {:?})",
+            task_id,
+            name,
+            priority
+        );
 
-    Ok(task_id)
+        Ok(task_id)
+    }
 }
 
 /// Get a mutable reference to a task from the task table
@@ -300,22 +688,13 @@ pub fn spawn_task(
 ///
 /// # Safety
 /// This function returns a 'static mutable reference, which is safe because:
-/// - Tasks are allocated on the heap and don't move
-/// - We only access tasks while holding appropriate locks
+/// - Tasks are allocated on the heap, don't move and are freed only once
+///   they exited and were reaped
 /// - Each task is only accessed by one context at a time
+///
+/// Takes no lock; see [`TASK_TABLE`].
 fn get_task(id: TaskId) -> Option<&'static mut Task> {
-    let task_table = TASK_TABLE.lock();
-
-    if id >= MAX_TASKS {
-        return None;
-    }
-
-    // Get the task pointer
-    let task_ptr = task_table[id];
-
-    if task_ptr.is_null() {
-        return None;
-    }
+    let task_ptr = task_ptr(id)?;
 
     // Convert to static reference (safe because task is heap-allocated and doesn't move)
     unsafe { Some(&mut *task_ptr.get()) }
@@ -341,58 +720,95 @@ fn schedule_on_core(cpu_id: usize) -> Option<(&'static mut Task, &'static mut Ta
     // Get the PerCpu structure for this core
     let percpu = unsafe { crate::arch::x86_64::smp::percpu::percpu_for_mut(cpu_id) };
 
-    // Get the current task (if any)
-    let old_task_id = percpu.current_task;
-
-    // If there's no current task, this is the first switch on this core
-    if old_task_id.is_none() {
+    // Get the current task; if there is none, this is the first switch on
+    // this core
+    if percpu.current.is_null() {
         return None;
     }
+    let old_task = unsafe { &mut *percpu.current };
+
+    // An outgoing task that wrote over the base of its stack has corrupted
+    // whatever it ran into; stop before that spreads
+    if !crate::mm::kstack::canary_intact(VirtAddr::from_ptr(old_task.stack)) {
+        panic!(
+            "[SCHED] Kernel stack canary of task {} ({}) overwritten: stack overflow",
+            old_task.id, old_task.name
+        );
+    }
+
+    let now = crate::time::clock::now_ns();
+
+    // A task that stopped running by itself finished its burst
+    if old_task.id != percpu.idle_task {
+        old_task.burst.switch_out(now, old_task.state != TaskState::Running);
+    }
 
     // Move current task back to runqueue if it's still ready
-    if let Some(current_id) = old_task_id {
-        if let Some(task) = get_task(current_id) {
-            // Only re-enqueue if task is still in Running state
-            // (it might have been put to sleep or blocked)
-            if task.state == TaskState::Running {
-                task.state = TaskState::Ready;
-                let mut runqueue = percpu.runqueue.lock();
-                if !runqueue.push_back(current_id) {
-                    sched_warn!("CPU {} runqueue full, dropping task {}", cpu_id, current_id);
-                }
-            }
+    // (it might have been put to sleep or blocked)
+    if old_task.state == TaskState::Running {
+        old_task.state = TaskState::Ready;
+        if old_task.id != percpu.idle_task {
+            old_task.sched_stats.queued(now);
+        }
+        let mut runqueue = percpu.runqueue.lock();
+        if !runqueue.push_back(old_task.id) {
+            sched_warn!("CPU {} runqueue full, dropping task {}", cpu_id, old_task.id);
         }
     }
 
-    // Select next task from this CPU's runqueue
-    let next_task_id = {
-        let mut runqueue = percpu.runqueue.lock();
-        match runqueue.pop_front() {
-            Some(id) => id,
-            None => {
-                // Runqueue empty - use idle task
-                percpu.idle_task
+    // Select next task from this CPU's runqueue, dropping tasks that
+    // exited while queued (checked after releasing the runqueue lock, which
+    // ranks below the task table) and passing over tasks whose bandwidth
+    // group is throttled
+    let instant = Instant::now();
+    let mut passed_over = 0;
+    let new_task = loop {
+        let next = percpu.runqueue.lock().pop_front();
+        let Some(id) = next else {
+            // Runqueue empty - use idle task
+            break get_task(percpu.idle_task)?;
+        };
+        match get_task(id) {
+            None => continue,
+            Some(task) if task.state == TaskState::Exited => continue,
+            Some(task) if bandwidth::throttled(task.cpu_group, instant) => {
+                // Keep its place until the group's next period; once every
+                // queued task has been passed over, idle instead
+                let mut runqueue = percpu.runqueue.lock();
+                if !runqueue.push_back(id) {
+                    sched_warn!("CPU {} runqueue full, dropping task {}", cpu_id, id);
+                }
+                passed_over += 1;
+                if passed_over >= runqueue.len() {
+                    drop(runqueue);
+                    break get_task(percpu.idle_task)?;
+                }
             }
+            Some(task) => break task,
         }
     };
 
-    // Update current task in PerCpu
-    percpu.current_task = Some(next_task_id);
+    // Update current task in PerCpu; a switch is outside any RCU read section
+    set_current(percpu, new_task);
+    rcu::quiescent_state();
+    if new_task.id != old_task.id {
+        crate::trace!(sched_switch, old_task.id, new_task.id, old_task.state);
+    }
 
-    // Get task references
-    let old_task = old_task_id.and_then(|id| get_task(id));
-    let new_task = get_task(next_task_id)?;
+    // Count the switch; a task picked again straight away was not preempted
+    if old_task.id != percpu.idle_task {
+        let preempted = new_task.id != old_task.id && old_task.state == TaskState::Ready;
+        old_task.sched_stats.switched_out(preempted);
+    }
+    if new_task.id != percpu.idle_task {
+        new_task.sched_stats.picked(now);
+    }
 
     // Update new task state to Running
     new_task.state = TaskState::Running;
+    start_quantum(percpu, new_task, now);
 
-    // Return both tasks
-    if let Some(old) = old_task {
-        Some((old, new_task))
-    } else {
-        // Should not reach here since we checked old_task_id.is_none() above
-        None
-    }
+    Some((old_task, new_task))
 }
 
 /// Global counter for context switches (for logging throttling)
@@ -414,6 +830,9 @@ pub(crate) static SWITCH_COUNT: core::sync::atomic::AtomicUsize =
 pub fn tick() {
     use core::sync::atomic::Ordering;
 
+    // This switch is the one a pending request asked for
+    percpu_current().need_resched.store(false, Ordering::Relaxed);
+
     // Increment timer_ticks metric
     crate::sys::METRICS
         .timer_ticks
@@ -490,10 +909,10 @@ pub fn tick() {
             }
         };
 
-        percpu.current_task = Some(first_task_id);
-
         if let Some(first_task) = get_task(first_task_id) {
+            set_current(percpu, first_task);
             first_task.state = TaskState::Running;
+            start_quantum(percpu, first_task, crate::time::clock::now_ns());
 
             sched_log!(
                 "[core{}] First switch → Task {} ({}) [priority: {:?}]",
@@ -519,6 +938,10 @@ pub fn tick() {
                 rbp: 0,
                 rbx: 0,
                 rsp: 0, // Will be filled by context_switch
+                fs_base: 0,
+                gs_base: 0,
+                fpu_area: 0,
+                kernel_stack: 0,
             };
 
             unsafe {
@@ -536,15 +959,108 @@ pub fn tick() {
     }
 }
 
+/// Record `task` as the one running on `percpu`
+fn set_current(percpu: &mut crate::arch::x86_64::smp::percpu::PerCpu, task: &mut Task) {
+    crate::sync::lockdep::switch_task(percpu.current_task, task.id);
+    percpu.current_task = Some(task.id);
+    percpu.current = task;
+}
+
+/// The task running on this CPU, without going through the task table
+///
+/// None before the CPU's first switch.
+pub fn current_task() -> Option<&'static mut Task> {
+    let task = crate::arch::x86_64::smp::percpu::current_task_ptr();
+    // The running task has not been reaped, so the pointer stays valid
+    unsafe { task.as_mut() }
+}
+
+/// Give the task switched in on `percpu` its quantum
+///
+/// The idle task gets a single tick, so queued work never waits on it.
+fn start_quantum(percpu: &crate::arch::x86_64::smp::percpu::PerCpu, task: &mut Task, now: u64) {
+    use core::sync::atomic::Ordering;
+
+    let ticks = if task.id == percpu.idle_task {
+        1
+    } else {
+        task.burst.switch_in(now);
+        task.burst.quantum_ticks()
+    };
+    percpu.slice_left.store(ticks, Ordering::Relaxed);
+}
+
+/// Scheduler entry from the timer interrupt
+///
+/// Counts the running task's quantum down, and asks for a switch
+/// ([`set_need_resched`]) once it is used up. The switch itself is left to
+/// the interrupt return path.
+pub fn timer_tick() {
+    use core::sync::atomic::Ordering;
+
+    let percpu = percpu_current();
+    let running = current_task().map_or(false, |task| task.state == TaskState::Running);
+    let left = percpu.slice_left.load(Ordering::Relaxed);
+    if running && left > 1 {
+        percpu.slice_left.store(left - 1, Ordering::Relaxed);
+        return;
+    }
+    set_need_resched();
+}
+
+/// Ask for a task switch on this CPU at the next interrupt return
+pub fn set_need_resched() {
+    percpu_current().need_resched.store(true, core::sync::atomic::Ordering::Relaxed);
+}
+
+/// Interrupt return path: switch tasks if one was asked for
+///
+/// Called by the timer and reschedule IPI entry stubs after their handler
+/// has sent EOI and finished, with interrupts still off and the
+/// interrupted task's registers saved on its stack. Interrupt handlers thus
+/// never switch tasks halfway through; the interrupted task resumes here
+/// when it is next picked and returns from the interrupt.
+pub extern "C" fn irq_return_resched() {
+    if percpu_current().need_resched.load(core::sync::atomic::Ordering::Relaxed) {
+        idle::interrupted();
+        tick();
+    }
+}
+
 /// Idle task entry point
 ///
 /// This task runs when no other tasks are available.
-/// It simply halts the CPU until the next interrupt.
+/// It waits for the next interrupt in the power state `idle` picks.
 fn idle_task() -> ! {
     loop {
-        unsafe {
-            core::arch::asm!("hlt");
-        }
+        idle::idle();
+    }
+}
+
+/// Charge resource usage to the task running on this CPU
+///
+/// Does nothing when no task is running (early boot, idle).
+pub fn charge_current(charge: impl FnOnce(&accounting::TaskUsage)) {
+    if let Some(task) = current_task() {
+        charge(&task.usage);
+    }
+}
+
+/// Charge one timer tick to the interrupted task and its bandwidth group
+///
+/// `user_mode` says whether the timer interrupted user code (CPL 3). A
+/// task whose group runs out of quota loses the rest of its quantum.
+pub fn account_tick(user_mode: bool) {
+    use core::sync::atomic::Ordering;
+
+    // The tick could not have arrived inside an RCU read section
+    rcu::quiescent_state();
+
+    let percpu = percpu_current();
+    let Some(task) = current_task() else { return };
+    task.usage.charge_tick(user_mode);
+    if task.id != percpu.idle_task && bandwidth::charge(task.cpu_group, Duration::TICK, Instant::now()) {
+        percpu.slice_left.store(0, Ordering::Relaxed);
     }
 }
 
@@ -552,10 +1068,7 @@ fn idle_task() -> ! {
 ///
 /// Returns the current task's ID and priority, or None if no task is running
 pub fn get_current_task_info() -> Option<(TaskId, TaskPriority)> {
-    let percpu = percpu_current();
-    let current_id = percpu.current_task?;
-
-    let task = get_task(current_id)?;
+    let task = current_task()?;
     Some((task.id, task.priority))
 }
 
@@ -574,6 +1087,21 @@ pub fn get_task_mut(task_id: TaskId) -> Option<&'static mut Task> {
     get_task(task_id)
 }
 
+/// Task holding the memory regions of `task_id`'s address space
+///
+/// That is the task itself, or for a thread the task that created its
+/// process (see `thread`). Syscalls and faults that look up or change
+/// mappings go through this.
+pub fn get_address_space_mut(task_id: TaskId) -> Option<&'static mut Task> {
+    let pid = get_task(task_id)?.pid;
+    get_task(pid)
+}
+
+/// Whether `task_id` is the idle task, which every CPU shares
+pub(crate) fn is_idle_task(task_id: TaskId) -> bool {
+    task_id == 0
+}
+
 /// Get a task by ID (public version for /proc filesystem)
 ///
 /// Returns a reference to the task, or None if task doesn't exist
@@ -581,6 +1109,36 @@ pub fn get_task_by_id(task_id: TaskId) -> Option<&'static Task> {
     get_task(task_id).map(|t| &*t)
 }
 
+/// Find the task whose kernel stack starts at `stack_bottom`
+///
+/// Used by the fault handlers to name the task that overflowed its stack.
+/// Takes no lock, so it works wherever the overflow happened.
+pub fn find_task_by_stack(stack_bottom: VirtAddr) -> Option<&'static Task> {
+    all_tasks()
+        .map(|task| &*task)
+        .find(|task| VirtAddr::from_ptr(task.stack) == stack_bottom)
+}
+
+/// Call `f` with every task
+///
+/// Takes no lock, so reports from interrupt context (SysRq) can use it.
+pub fn for_each_task(mut f: impl FnMut(&Task)) {
+    for task in all_tasks() {
+        f(task);
+    }
+}
+
+/// Make `new_ppid` the parent of every task whose parent is `old_ppid`
+///
+/// Used when a process exits, so its orphans are adopted by init. Returns
+/// the number of tasks moved.
+pub fn reparent(old_ppid: process_group::Pid, new_ppid: process_group::Pid) -> usize {
+    all_tasks()
+        .filter(|task| task.ppid == old_ppid)
+        .map(|task| task.ppid = new_ppid)
+        .count()
+}
+
 /// Enqueue a task to a CPU runqueue
 ///
 /// Assigns the task to the CPU with the smallest runqueue, or to a specific CPU if specified.
@@ -591,6 +1149,19 @@ pub fn get_task_by_id(task_id: TaskId) -> Option<&'static Task> {
 /// * `task_id` - The task to enqueue
 /// * `target_cpu` - Optional specific CPU to enqueue to. If None, selects CPU with smallest runqueue.
 pub fn enqueue_task(task_id: TaskId, target_cpu: Option<usize>) {
+    enqueue(task_id, target_cpu, false);
+}
+
+/// Enqueue a task that finished waiting
+///
+/// A task predicted to run only briefly goes to the front of the runqueue
+/// and ends the quantum of the task running there, so it is served at the
+/// next tick (or right away on a remote CPU, by the IPI).
+fn enqueue_woken(task_id: TaskId, short: bool) {
+    enqueue(task_id, None, short);
+}
+
+fn enqueue(task_id: TaskId, target_cpu: Option<usize>, front: bool) {
     let cpu_count = get_cpu_count();
 
     // Determine which CPU to enqueue to
@@ -624,11 +1195,20 @@ pub fn enqueue_task(task_id: TaskId, target_cpu: Option<usize>) {
     // Get current CPU ID to check if this is a remote enqueue
     let current_cpu = percpu_current().id;
 
+    if let Some(task) = get_task(task_id) {
+        task.sched_stats.queued(crate::time::clock::now_ns());
+    }
+
     // Enqueue task to selected CPU's runqueue
     let percpu = percpu_for(cpu_id);
     let mut runqueue = percpu.runqueue.lock();
 
-    if !runqueue.push_back(task_id) {
+    let queued = if front {
+        runqueue.push_front(task_id)
+    } else {
+        runqueue.push_back(task_id)
+    };
+    if !queued {
         sched_error!(
             "Failed to enqueue task {} to CPU {} (runqueue full)",
             task_id,
@@ -645,6 +1225,10 @@ pub fn enqueue_task(task_id: TaskId, target_cpu: Option<usize>) {
         // Drop the runqueue lock before sending IPI
         drop(runqueue);
 
+        if front {
+            percpu.slice_left.store(1, core::sync::atomic::Ordering::Relaxed);
+        }
+
         // If we enqueued to a remote CPU, send RESCHEDULE_IPI to wake it up
         if cpu_id != current_cpu && cpu_count > 1 {
             use crate::arch::x86_64::apic::ipi::send_reschedule_ipi;
@@ -669,27 +1253,164 @@ pub fn dequeue_task(cpu_id: usize) -> Option<TaskId> {
     runqueue.pop_front()
 }
 
-/// Put current task to sleep for specified ticks
+/// Put current task to sleep for `duration`
+///
+/// The task is woken by `wake_sleeping_tasks` on the first timer tick at or
+/// after the deadline; a zero duration just gives up the CPU until the next
+/// tick.
 ///
 /// Returns true on success, false on error
-pub fn sleep_current_task(ticks: u64, _priority: TaskPriority) -> bool {
-    // Get current CPU and task
-    let percpu = percpu_current();
-    let current_id = match percpu.current_task {
+pub fn sleep_current_task(duration: Duration, _priority: TaskPriority) -> bool {
+    sleep_current(duration, false)
+}
+
+/// `sleep_current_task` that a signal for the task ends early
+///
+/// The signal wakes the task like its deadline would (`interrupt_task`);
+/// the caller tells the two apart with `signal::interrupts_wait`.
+pub fn sleep_current_task_interruptible(duration: Duration) -> bool {
+    sleep_current(duration, true)
+}
+
+fn sleep_current(duration: Duration, interruptible: bool) -> bool {
+    // Get current task
+    let Some(task) = current_task() else { return false };
+
+    // Ended by another thread of its process: stop at the next switch
+    if task.state == TaskState::Exited {
+        return false;
+    }
+
+    // Update task state to Sleeping
+    task.state = TaskState::Sleeping;
+    let deadline = Instant::now().saturating_add(duration);
+    task.wake_at = Some(deadline);
+    task.interruptible = interruptible;
+
+    // Note: Task will not be re-enqueued until wake time
+    // The timer interrupt will check wake_at and re-enqueue when ready;
+    // in TSC-deadline mode this CPU's timer fires at the deadline
+    crate::arch::x86_64::apic::timer::arm(deadline);
+
+    true
+}
+
+/// Move a sleeping or blocked task to `state`
+///
+/// Holds WAIT_STATE so it can't race `wake_sleeping_tasks`.
+/// Returns the task, or None if it was not waiting.
+fn end_wait(task_id: TaskId, state: TaskState) -> Option<&'static mut Task> {
+    let _wait_state = WAIT_STATE.lock();
+    end_wait_locked(task_id, state)
+}
+
+/// `end_wait` with WAIT_STATE already held
+fn end_wait_locked(task_id: TaskId, state: TaskState) -> Option<&'static mut Task> {
+    let task = get_task(task_id)?;
+    if task.state != TaskState::Sleeping && task.state != TaskState::Blocked {
+        return None;
+    }
+    task.wake_at = None;
+    task.state = state;
+    Some(task)
+}
+
+/// Wake a sleeping or blocked task before its deadline
+///
+/// Returns false if the task was not waiting (running, or already woken).
+pub fn wake_task(task_id: TaskId) -> bool {
+    let short = match end_wait(task_id, TaskState::Ready) {
+        Some(task) => task.burst.is_short(),
+        None => return false,
+    };
+    enqueue_woken(task_id, short);
+    true
+}
+
+/// `wake_task` for interrupt context
+///
+/// Gives up instead of spinning if WAIT_STATE is locked, so callers
+/// need a timeout to fall back on. Returns true if the task was woken.
+pub fn try_wake_task(task_id: TaskId) -> bool {
+    let short = match WAIT_STATE.try_lock() {
+        Some(_wait_state) => match end_wait_locked(task_id, TaskState::Ready) {
+            Some(task) => task.burst.is_short(),
+            None => return false,
+        },
+        None => return false,
+    };
+    enqueue_woken(task_id, short);
+    true
+}
+
+/// Wake `task_id` from an interruptible sleep because a signal is pending
+///
+/// For signal senders, which may run in interrupt context: if WAIT_STATE
+/// is locked, the next `wake_sleeping_tasks` wakes the task instead.
+pub fn interrupt_task(task_id: TaskId) {
+    if get_task(task_id).is_some_and(|task| task.interruptible) {
+        try_wake_task(task_id);
+    }
+}
+
+/// Take back a `sleep_current_task` before yielding
+///
+/// For callers that go to sleep first and then re-check their wake-up
+/// condition. Returns false if the task has already been woken: it is then
+/// queued to run and the caller must still `yield_now`.
+pub fn cancel_wait() -> bool {
+    let current_id = match percpu_current().current_task {
         Some(id) => id,
         None => return false,
     };
+    end_wait(current_id, TaskState::Running).is_some()
+}
 
-    // Update task state to Sleeping
-    if let Some(task) = get_task(current_id) {
-        task.state = TaskState::Sleeping;
-        task.wake_tick = Some(ticks);
+/// Re-enqueue sleeping tasks whose deadline has passed, and interruptible
+/// sleepers with a signal pending
+///
+/// Called from the timer interrupt on CPU 0. Uses `try_lock` so a tick that
+/// lands while WAIT_STATE is locked simply retries on the next tick.
+///
+/// # Returns
+/// The number of tasks woken
+pub fn wake_sleeping_tasks(now: Instant) -> usize {
+    let mut due: [(TaskId, bool); MAX_TASKS] = [(0, false); MAX_TASKS];
+    let mut count = 0;
+
+    {
+        let _wait_state = match WAIT_STATE.try_lock() {
+            Some(guard) => guard,
+            None => return 0,
+        };
+
+        for task in all_tasks() {
+            if task.state != TaskState::Sleeping {
+                continue;
+            }
+            let expired = task.wake_at.map_or(false, |deadline| deadline <= now);
+            let interrupted = task.interruptible && crate::signal::interrupts_wait(task);
+            if (expired || interrupted) && count < due.len() {
+                task.wake_at = None;
+                task.state = TaskState::Ready;
+                due[count] = (task.id, task.burst.is_short());
+                count += 1;
+            }
+        }
     }
 
-    // Note: Task will not be re-enqueued until wake time
-    // The timer interrupt will check wake_tick and re-enqueue when ready
+    for &(task_id, short) in &due[..count] {
+        enqueue_woken(task_id, short);
+    }
+    count
+}
 
-    true
+/// Earliest deadline of a sleeping task, for the idle governor
+pub(crate) fn next_wakeup() -> Option<Instant> {
+    all_tasks()
+        .filter(|task| task.state == TaskState::Sleeping)
+        .filter_map(|task| task.wake_at)
+        .min()
 }
 
 /// Migrate a task from one CPU to another
@@ -870,6 +1591,9 @@ pub fn balance_load() -> usize {
 /// It does not return in the traditional sense - execution continues
 /// in the next task, and eventually returns here when this task runs again.
 pub fn yield_now() {
+    if let Some(task) = current_task() {
+        task.sched_stats.yielding();
+    }
     // Call the scheduler tick function to perform context switch
     tick();
 }
@@ -881,8 +1605,8 @@ pub fn yield_now() {
 /// Initialize the scheduler
 ///
 /// This function:
-/// 1. Initializes SCHED and TASK_TABLE
-/// 2. Creates the idle task (task id 0)
+/// 1. Creates the idle task (task id 0)
+/// 2. Publishes it in TASK_TABLE
 /// 3. Logs scheduler initialization
 ///
 /// # Notes
@@ -896,16 +1620,6 @@ pub fn init_scheduler() {
 
     sched_info!("Initializing scheduler...");
 
-    // Initialize SCHED state
-    SCHED.call_once(|| Mutex::new(SchedState::new()));
-
-    // Initialize TASK_TABLE (clear all entries)
-    let mut task_table = TASK_TABLE.lock();
-    for i in 0..MAX_TASKS {
-        task_table[i] = TaskPtr::null();
-    }
-    drop(task_table);
-
     // Create idle task (task id 0)
     // We manually create it with id 0 instead of using spawn_task
     let idle = match Task::new(0, "idle", idle_task, TaskPriority::Low) {
@@ -927,9 +1641,9 @@ pub fn init_scheduler() {
         ptr::write(task_ptr, idle);
     }
 
-    let mut task_table = TASK_TABLE.lock();
-    task_table[0] = TaskPtr::new(task_ptr);
-    drop(task_table);
+    if update_task_table(|table| table.slots_mut()[0].task = TaskPtr::new(task_ptr)).is_err() {
+        panic!("[SCHED] CRITICAL: Failed to allocate the task table");
+    }
 
     // Set idle task for all CPUs
     let cpu_count = get_cpu_count();
@@ -1050,10 +1764,10 @@ pub fn tick_with_process_integration() {
             }
         };
 
-        percpu.current_task = Some(first_task_id);
-
         if let Some(first_task) = get_task(first_task_id) {
+            set_current(percpu, first_task);
             first_task.state = TaskState::Running;
+            start_quantum(percpu, first_task, crate::time::clock::now_ns());
 
             sched_log!(
                 "[core{}] First switch → Task {} ({}) [priority: {:?}]",
@@ -1075,6 +1789,10 @@ pub fn tick_with_process_integration() {
                 rbp: 0,
                 rbx: 0,
                 rsp: 0,
+                fs_base: 0,
+                gs_base: 0,
+                fpu_area: 0,
+                kernel_stack: 0,
             };
 
             unsafe {
@@ -1295,177 +2013,98 @@ pub fn test_task_switching_integration() {
     serial_println!("[TEST] ========================================");
 }
 
-/// Manual test functions for kernel-space testing
-/// These can be called during kernel initialization to verify scheduler functionality
-#[cfg(not(test))]
-pub mod manual_tests {
-    use super::*;
-    use crate::serial_println;
-
-    /// Test that spawn_task adds tasks to the runqueue
-    pub fn test_spawn_task_adds_to_runqueue() {
-        serial_println!("[TEST] Testing spawn_task adds to runqueue...");
-
-        // Create a dummy task function
-        fn dummy_task() -> ! {
-            loop {
-                unsafe {
-                    core::arch::asm!("hlt");
-                }
-            }
-        }
-
-        // Initialize scheduler
-        init_scheduler();
-
-        // Spawn a task
-        let task_id = spawn_task("test_task", dummy_task, TaskPriority::Normal)
-            .expect("Failed to spawn test task");
-
-        // Verify task was created
-        serial_println!("[TEST] Spawned task with id: {}", task_id);
-
-        // Check per-CPU runqueue has the task
-        let cpu_count = get_cpu_count();
-        let mut total_tasks = 0;
-
-        for i in 0..cpu_count {
-            let percpu = percpu_for(i);
-            let runqueue = percpu.runqueue.lock();
-            total_tasks += runqueue.len();
-        }
-
-        serial_println!("[TEST] Total tasks in runqueues: {}", total_tasks);
-
-        if total_tasks > 0 {
-            serial_println!("[TEST] ✓ spawn_task adds to runqueue test passed!");
-        } else {
-            serial_println!("[TEST] ✗ spawn_task adds to runqueue test FAILED!");
+/// Parked entry point for tasks spawned by kernel tests
+///
+/// Test tasks are only enqueued, never scheduled, because the test runner
+/// executes before interrupts are enabled.
+pub(crate) fn ktest_parked_task() -> ! {
+    loop {
+        unsafe {
+            core::arch::asm!("hlt");
         }
     }
+}
 
-    /// Test Round-Robin task selection
-    pub fn test_round_robin_selection() {
-        serial_println!("[TEST] Testing Round-Robin task selection...");
-
-        // Create dummy task functions
-        fn task_a() -> ! {
-            loop {
-                unsafe {
-                    core::arch::asm!("hlt");
-                }
-            }
-        }
-
-        fn task_b() -> ! {
-            loop {
-                unsafe {
-                    core::arch::asm!("hlt");
-                }
-            }
-        }
-
-        fn task_c() -> ! {
-            loop {
-                unsafe {
-                    core::arch::asm!("hlt");
-                }
-            }
-        }
-
-        // Initialize scheduler
-        init_scheduler();
-
-        // Spawn three tasks with different priorities
-        let id_a =
-            spawn_task("task_a", task_a, TaskPriority::High).expect("Failed to spawn task_a");
-        let id_b =
-            spawn_task("task_b", task_b, TaskPriority::Normal).expect("Failed to spawn task_b");
-        let id_c = spawn_task("task_c", task_c, TaskPriority::Low).expect("Failed to spawn task_c");
-
-        serial_println!("[TEST] Spawned tasks: {}, {}, {}", id_a, id_b, id_c);
-
-        // Check per-CPU runqueues have tasks
-        let cpu_count = get_cpu_count();
-        let mut total_tasks = 0;
-
-        for i in 0..cpu_count {
-            let percpu = percpu_for(i);
-            let runqueue = percpu.runqueue.lock();
-            total_tasks += runqueue.len();
-        }
-
-        serial_println!("[TEST] Total tasks in runqueues: {}", total_tasks);
+/// Total number of tasks waiting in all per-CPU runqueues
+fn total_runqueue_len() -> usize {
+    (0..get_cpu_count())
+        .map(|cpu| percpu_for(cpu).runqueue.lock().len())
+        .sum()
+}
 
-        if total_tasks == 3 {
-            serial_println!("[TEST] ✓ Round-Robin task selection test passed!");
-        } else {
-            serial_println!("[TEST] ✗ Round-Robin task selection test FAILED!");
-        }
+crate::kernel_test! {
+    /// spawn_task registers the task and places it on a runqueue
+    fn spawn_task_adds_to_runqueue() {
+        let before = total_runqueue_len();
+        let id = spawn_task("ktest_spawn", ktest_parked_task, TaskPriority::Normal)
+            .map_err(|_| "spawn_task failed")?;
+
+        let task = get_task_by_id(id).ok_or("spawned task missing from task table")?;
+        crate::ktest_assert_eq!(task.state, TaskState::Ready, "new task not Ready");
+        crate::ktest_assert_eq!(total_runqueue_len(), before + 1, "task not enqueued");
+        Ok(())
     }
+}
 
-    /// Test multiple task switching (conceptual test)
-    pub fn test_multiple_task_switching() {
-        serial_println!("[TEST] Testing multiple task switching...");
-        serial_println!("[TEST] Note: Actual context switching requires timer interrupts");
-        serial_println!("[TEST] This test verifies the scheduler state management");
-
-        // Initialize scheduler
-        init_scheduler();
-
-        // Create dummy tasks
-        fn task_1() -> ! {
-            loop {
-                unsafe {
-                    core::arch::asm!("hlt");
-                }
-            }
-        }
-
-        fn task_2() -> ! {
-            loop {
-                unsafe {
-                    core::arch::asm!("hlt");
-                }
-            }
-        }
-
-        // Spawn tasks
-        spawn_task("task_1", task_1, TaskPriority::Normal).expect("Failed to spawn task_1");
-        spawn_task("task_2", task_2, TaskPriority::Normal).expect("Failed to spawn task_2");
-
-        // Verify scheduler state
-        let cpu_count = get_cpu_count();
-        let mut has_tasks = false;
-
-        for i in 0..cpu_count {
-            let percpu = percpu_for(i);
-            let runqueue = percpu.runqueue.lock();
-            if !runqueue.is_empty() {
-                has_tasks = true;
-                break;
-            }
-        }
+crate::kernel_test! {
+    /// A reaped task's slot is reused under a new generation, and its old
+    /// ID no longer names a task
+    fn reaped_slot_reused() {
+        // Tasks other tests left to reap would free lower slots
+        reap_exited();
+        let old = spawn_task("ktest_reaped", ktest_parked_task, TaskPriority::Normal)
+            .map_err(|_| "spawn_task failed")?;
+        get_task(old).ok_or("spawned task missing from task table")?.state = TaskState::Exited;
+        reap_task(old);
+        crate::ktest_assert_eq!(reap_exited(), 1, "task not reaped");
+        crate::ktest_assert!(get_task_by_id(old).is_none(), "reaped task still found");
+
+        let new = spawn_task("ktest_reuse", ktest_parked_task, TaskPriority::Normal)
+            .map_err(|_| "spawn_task failed")?;
+        crate::ktest_assert_eq!(task::task_slot(new), task::task_slot(old), "slot not reused");
+        crate::ktest_assert!(new != old, "ID reused with the same generation");
+        crate::ktest_assert!(get_task_by_id(old).is_none(), "stale ID names the new task");
+        crate::ktest_assert_eq!(get_task_by_id(new).map(|task| task.name), Some("ktest_reuse"), "new task not found");
+        Ok(())
+    }
+}
 
-        if has_tasks {
-            serial_println!("[TEST] ✓ Multiple task switching test passed!");
-        } else {
-            serial_println!("[TEST] ✗ Multiple task switching test FAILED!");
+crate::kernel_test! {
+    /// TaskBuilder gives tasks the stack size asked for and refuses sizes
+    /// that are not whole pages in range
+    fn task_builder_stack_size() {
+        let id = TaskBuilder::new("ktest_builder", ktest_parked_task)
+            .priority(TaskPriority::High)
+            .stack_size(32 * 1024)
+            .spawn()
+            .map_err(|_| "TaskBuilder::spawn failed")?;
+        let task = get_task_by_id(id).ok_or("spawned task missing from task table")?;
+        crate::ktest_assert_eq!(task.stack_size, 32 * 1024, "stack size not applied");
+        crate::ktest_assert_eq!(task.priority, TaskPriority::High, "priority not applied");
+
+        for size in [0, 4096 + 1, task::MAX_STACK_SIZE + 4096] {
+            crate::ktest_assert_eq!(
+                TaskBuilder::new("ktest_builder", ktest_parked_task).stack_size(size).spawn(),
+                Err(SpawnError::InvalidStackSize),
+                "invalid stack size accepted"
+            );
         }
+        Ok(())
     }
+}
 
-    /// Run all scheduler tests
-    pub fn run_all_tests() {
-        serial_println!("[TEST] ========================================");
-        serial_println!("[TEST] Running Scheduler Tests");
-        serial_println!("[TEST] ========================================");
-
-        test_spawn_task_adds_to_runqueue();
-        test_round_robin_selection();
-        test_multiple_task_switching();
-
-        serial_println!("[TEST] ========================================");
-        serial_println!("[TEST] All Scheduler Tests Completed!");
-        serial_println!("[TEST] ========================================");
+crate::kernel_test! {
+    /// Tasks keep the priority they were spawned with
+    fn spawn_task_records_priority() {
+        for priority in [TaskPriority::High, TaskPriority::Normal, TaskPriority::Low] {
+            let id = spawn_task("ktest_priority", ktest_parked_task, priority)
+                .map_err(|_| "spawn_task failed")?;
+            crate::ktest_assert_eq!(
+                get_task_priority(id),
+                Some((id, priority)),
+                "priority mismatch"
+            );
+        }
+        Ok(())
     }
 }
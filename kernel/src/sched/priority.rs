@@ -21,8 +21,8 @@
 //!
 //! Sleeping Tasks:
 //! [
-//!     { task_id: 3, wake_tick: 1050, priority: Normal },
-//!     { task_id: 7, wake_tick: 1200, priority: High },
+//!     { task_id: 3, wake_at: T+52.500s, priority: Normal },
+//!     { task_id: 7, wake_at: T+60.000s, priority: High },
 //! ]
 //! ```
 //!
@@ -30,6 +30,7 @@
 //!
 //! ```rust,no_run
 //! use crate::sched::priority::{PriorityScheduler, TaskPriority};
+//! use crate::time::Duration;
 //!
 //! let mut sched = PriorityScheduler::new();
 //!
@@ -42,7 +43,7 @@
 //! let next = sched.select_next(); // Returns Some(2)
 //!
 //! // Put task to sleep
-//! sched.sleep_task(1, 100, TaskPriority::Normal);
+//! sched.sleep_task(1, Duration::from_ticks(100), TaskPriority::Normal);
 //!
 //! // Update tick and wake sleeping tasks
 //! for _ in 0..100 {
@@ -52,6 +53,7 @@
 //! ```
 
 use super::task::TaskId;
+use crate::time::{Duration, Instant};
 
 /// Maximum number of tasks per queue
 const MAX_TASKS: usize = 64;
@@ -150,7 +152,7 @@ impl TaskQueue {
 #[derive(Copy, Clone)]
 struct SleepingTask {
     task_id: TaskId,
-    wake_tick: u64,
+    wake_at: Instant,
     priority: TaskPriority,
     valid: bool, // Whether this slot is occupied
 }
@@ -159,7 +161,7 @@ impl SleepingTask {
     const fn empty() -> Self {
         Self {
             task_id: 0,
-            wake_tick: 0,
+            wake_at: Instant::BOOT,
             priority: TaskPriority::Normal,
             valid: false,
         }
@@ -176,7 +178,7 @@ impl SleepingTask {
 /// - **ready_queues**: Three circular queues (one per priority level)
 /// - **non_empty_queues**: Bitmap tracking which queues have tasks (bits 0-2)
 /// - **sleeping_tasks**: Fixed-size array of sleeping tasks
-/// - **current_tick**: Current timer tick count
+/// - **now**: Scheduler clock, advanced one tick per `tick()`
 /// - **preempt_disable_count**: Counter for preemption control (0 = enabled)
 ///
 /// # Performance
@@ -201,8 +203,8 @@ pub struct PriorityScheduler {
     /// Array of sleeping tasks (fixed size for no_std)
     sleeping_tasks: [SleepingTask; MAX_TASKS],
 
-    /// Current tick count
-    current_tick: u64,
+    /// Scheduler clock
+    now: Instant,
 
     /// Preemption disable counter (0 = preemption enabled)
     preempt_disable_count: usize,
@@ -215,7 +217,7 @@ impl PriorityScheduler {
             ready_queues: [TaskQueue::new(), TaskQueue::new(), TaskQueue::new()],
             non_empty_queues: 0,
             sleeping_tasks: [SleepingTask::empty(); MAX_TASKS],
-            current_tick: 0,
+            now: Instant::BOOT,
             preempt_disable_count: 0,
         }
     }
@@ -279,39 +281,39 @@ impl PriorityScheduler {
         self.ready_queues[0].len() + self.ready_queues[1].len() + self.ready_queues[2].len()
     }
 
-    /// Put task to sleep for specified ticks
+    /// Put task to sleep for `duration`
     ///
     /// Task will be removed from ready queue and added to sleeping list.
-    /// The task will wake up after `ticks` timer interrupts.
+    /// The task will wake up on the first tick at or after `now + duration`.
     ///
     /// # Arguments
     /// * `task_id` - Task identifier
-    /// * `ticks` - Number of ticks to sleep
+    /// * `duration` - How long to sleep
     /// * `priority` - Task priority (for re-enqueuing when woken)
     ///
     /// # Returns
     /// `true` if task was put to sleep successfully, `false` if no slots available
-    pub fn sleep_task(&mut self, task_id: TaskId, ticks: u64, priority: TaskPriority) -> bool {
+    pub fn sleep_task(&mut self, task_id: TaskId, duration: Duration, priority: TaskPriority) -> bool {
         use crate::serial_println;
 
-        let wake_tick = self.current_tick + ticks;
+        let wake_at = self.now.saturating_add(duration);
 
         // Find an empty slot in sleeping_tasks array
         for slot in &mut self.sleeping_tasks {
             if !slot.valid {
                 *slot = SleepingTask {
                     task_id,
-                    wake_tick,
+                    wake_at,
                     priority,
                     valid: true,
                 };
 
                 // Log sleep operation
                 serial_println!(
-                    "[SCHED] Task {} sleeping for {} ticks (wake at tick {})",
+                    "[SCHED] Task {} sleeping for {} (wake at {})",
                     task_id,
-                    ticks,
-                    wake_tick
+                    duration,
+                    wake_at
                 );
 
                 return true;
@@ -324,8 +326,8 @@ impl PriorityScheduler {
 
     /// Wake tasks whose sleep time has elapsed
     ///
-    /// Scans the sleeping tasks array and wakes any tasks whose wake_tick
-    /// is less than or equal to the current tick. Woken tasks are re-enqueued
+    /// Scans the sleeping tasks array and wakes any tasks whose wake_at
+    /// is at or before the scheduler clock. Woken tasks are re-enqueued
     /// to their appropriate priority queue.
     ///
     /// # Returns
@@ -334,14 +336,14 @@ impl PriorityScheduler {
         use crate::serial_println;
 
         let mut woken_count = 0;
-        let current_tick = self.current_tick;
+        let now = self.now;
 
         // First pass: collect tasks to wake
         let mut tasks_to_wake = [(0usize, TaskPriority::Normal); MAX_TASKS];
         let mut wake_index = 0;
 
         for slot in &mut self.sleeping_tasks {
-            if slot.valid && slot.wake_tick <= current_tick {
+            if slot.valid && slot.wake_at <= now {
                 if wake_index < MAX_TASKS {
                     tasks_to_wake[wake_index] = (slot.task_id, slot.priority);
                     wake_index += 1;
@@ -358,9 +360,9 @@ impl PriorityScheduler {
 
             // Log wake operation
             serial_println!(
-                "[SCHED] Task {} woke up at tick {} (priority: {:?})",
+                "[SCHED] Task {} woke up at {} (priority: {:?})",
                 task_id,
-                current_tick,
+                now,
                 priority
             );
         }
@@ -368,14 +370,14 @@ impl PriorityScheduler {
         woken_count
     }
 
-    /// Update tick counter and wake tasks
+    /// Advance the scheduler clock by one tick
     pub fn tick(&mut self) {
-        self.current_tick += 1;
+        self.now = self.now.saturating_add(Duration::TICK);
     }
 
-    /// Get current tick count
-    pub fn current_tick(&self) -> u64 {
-        self.current_tick
+    /// Get the scheduler clock
+    pub fn now(&self) -> Instant {
+        self.now
     }
 
     /// Disable preemption (for critical sections)
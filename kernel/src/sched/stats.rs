@@ -0,0 +1,218 @@
+//! Scheduler statistics
+//!
+//! Every task keeps [`SchedStats`]: how often it was picked to run, how long
+//! it sat ready in a runqueue before each pick (on the monotonic clock), and
+//! how often it was switched out while it could still run without having
+//! yielded, i.e. preempted. CPU time comes from the task's `usage`.
+//! `/proc/sched` and `SYS_SCHEDSTAT` report the counters per task and summed
+//! per priority, so scheduler changes can be benchmarked by comparing runs.
+//! Idle tasks are left out of the sums.
+
+use super::priority::TaskPriority;
+use super::task::{Task, TaskId};
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Scheduler counters of one task
+///
+/// Only the scheduler updates them; readers may see a pick counted before
+/// its wait.
+#[derive(Debug, Default)]
+pub struct SchedStats {
+    runs: AtomicU64,
+    wait_ns: AtomicU64,
+    max_wait_ns: AtomicU64,
+    preemptions: AtomicU64,
+    /// When the task was last queued; 0 when not waiting in a runqueue
+    queued_at: AtomicU64,
+    /// The task gave up the CPU itself this time (`yield_now`)
+    yielding: AtomicBool,
+}
+
+impl SchedStats {
+    pub const fn new() -> Self {
+        Self {
+            runs: AtomicU64::new(0),
+            wait_ns: AtomicU64::new(0),
+            max_wait_ns: AtomicU64::new(0),
+            preemptions: AtomicU64::new(0),
+            queued_at: AtomicU64::new(0),
+            yielding: AtomicBool::new(false),
+        }
+    }
+
+    /// The task was put in a runqueue at `now` (nanoseconds)
+    pub fn queued(&self, now: u64) {
+        self.queued_at.store(now.max(1), Ordering::Relaxed);
+    }
+
+    /// The task was picked to run at `now`
+    pub fn picked(&self, now: u64) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        let queued_at = self.queued_at.swap(0, Ordering::Relaxed);
+        if queued_at != 0 {
+            let wait = now.saturating_sub(queued_at);
+            self.wait_ns.fetch_add(wait, Ordering::Relaxed);
+            self.max_wait_ns.fetch_max(wait, Ordering::Relaxed);
+        }
+    }
+
+    /// The task is about to give up the CPU by yielding
+    pub fn yielding(&self) {
+        self.yielding.store(true, Ordering::Relaxed);
+    }
+
+    /// The task was switched out; `runnable` if it stays ready while
+    /// another task runs
+    pub fn switched_out(&self, runnable: bool) {
+        let yielded = self.yielding.swap(false, Ordering::Relaxed);
+        if runnable && !yielded {
+            self.preemptions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Scheduler counters of a task or a priority level (`SYS_SCHEDSTAT`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchedStat {
+    /// Tasks counted (1 for a task)
+    pub tasks: u64,
+    /// CPU time, in timer ticks
+    pub cpu_ticks: u64,
+    /// Times picked to run
+    pub runs: u64,
+    /// Time spent ready in a runqueue before being picked, in total and
+    /// the longest single wait
+    pub wait_ns: u64,
+    pub max_wait_ns: u64,
+    /// Times switched out while still runnable, without yielding
+    pub preemptions: u64,
+}
+
+mello_abi::check_layout!(
+    SchedStat,
+    mello_abi::SchedStat { tasks, cpu_ticks, runs, wait_ns, max_wait_ns, preemptions }
+);
+
+impl SchedStat {
+    /// `task`'s counters
+    pub fn of(task: &Task) -> Self {
+        let usage = task.usage.snapshot();
+        let stats = &task.sched_stats;
+        Self {
+            tasks: 1,
+            cpu_ticks: usage.user_ticks + usage.system_ticks,
+            runs: stats.runs.load(Ordering::Relaxed),
+            wait_ns: stats.wait_ns.load(Ordering::Relaxed),
+            max_wait_ns: stats.max_wait_ns.load(Ordering::Relaxed),
+            preemptions: stats.preemptions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.tasks += other.tasks;
+        self.cpu_ticks += other.cpu_ticks;
+        self.runs += other.runs;
+        self.wait_ns += other.wait_ns;
+        self.max_wait_ns = self.max_wait_ns.max(other.max_wait_ns);
+        self.preemptions += other.preemptions;
+    }
+
+    /// Mean wait per pick, in nanoseconds
+    pub fn avg_wait_ns(&self) -> u64 {
+        self.wait_ns.checked_div(self.runs).unwrap_or(0)
+    }
+}
+
+/// Counters of task `id`
+pub fn task(id: TaskId) -> Option<SchedStat> {
+    super::get_task_by_id(id).map(SchedStat::of)
+}
+
+/// Counters of every task at each priority, indexed by
+/// `TaskPriority::as_index`
+pub fn by_priority() -> [SchedStat; 3] {
+    let mut levels = [SchedStat::default(); 3];
+    super::for_each_task(|task| {
+        if !super::is_idle_task(task.id) {
+            levels[task.priority.as_index()].add(&SchedStat::of(task));
+        }
+    });
+    levels
+}
+
+/// Write `/proc/sched`: the sums per priority, then one line per task
+pub fn write_report(out: &mut impl fmt::Write) -> fmt::Result {
+    const PRIORITIES: [TaskPriority; 3] = [TaskPriority::High, TaskPriority::Normal, TaskPriority::Low];
+
+    let levels = by_priority();
+    writeln!(out, "priority tasks  cpu_ticks       runs avg_wait_us max_wait_us preemptions")?;
+    for priority in PRIORITIES {
+        let level = &levels[priority.as_index()];
+        writeln!(
+            out,
+            "{:<8} {:>5} {:>10} {:>10} {:>11} {:>11} {:>11}",
+            priority_name(priority),
+            level.tasks,
+            level.cpu_ticks,
+            level.runs,
+            level.avg_wait_ns() / 1000,
+            level.max_wait_ns / 1000,
+            level.preemptions
+        )?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "   id name             priority  cpu_ticks       runs avg_wait_us preemptions")?;
+    let mut result = Ok(());
+    super::for_each_task(|task| {
+        if result.is_err() || super::is_idle_task(task.id) {
+            return;
+        }
+        let stat = SchedStat::of(task);
+        result = writeln!(
+            out,
+            "{:>5} {:<16} {:<8} {:>10} {:>10} {:>11} {:>11}",
+            task.id,
+            task.name,
+            priority_name(task.priority),
+            stat.cpu_ticks,
+            stat.runs,
+            stat.avg_wait_ns() / 1000,
+            stat.preemptions
+        );
+    });
+    result
+}
+
+fn priority_name(priority: TaskPriority) -> &'static str {
+    match priority {
+        TaskPriority::High => "high",
+        TaskPriority::Normal => "normal",
+        TaskPriority::Low => "low",
+    }
+}
+
+crate::kernel_test! {
+    /// Waits are measured from queueing to picking, and only switches out
+    /// of a runnable task that did not yield count as preemptions
+    fn sched_stats_wait_and_preemption() {
+        let stats = SchedStats::new();
+        stats.queued(1_000);
+        stats.picked(4_000);
+        stats.queued(10_000);
+        stats.picked(11_000);
+        stats.picked(12_000);
+        crate::ktest_assert_eq!(stats.runs.load(Ordering::Relaxed), 3, "runs");
+        crate::ktest_assert_eq!(stats.wait_ns.load(Ordering::Relaxed), 4_000, "total wait");
+        crate::ktest_assert_eq!(stats.max_wait_ns.load(Ordering::Relaxed), 3_000, "longest wait");
+
+        stats.switched_out(true);
+        stats.yielding();
+        stats.switched_out(true);
+        stats.switched_out(false);
+        crate::ktest_assert_eq!(stats.preemptions.load(Ordering::Relaxed), 1, "preemptions");
+        Ok(())
+    }
+}
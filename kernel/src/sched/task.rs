@@ -3,16 +3,47 @@
 //! This module defines the Task Control Block (TCB) and task-related structures.
 //! It handles task creation, state management, and stack allocation.
 
-use super::context::CpuContext;
+use super::accounting::TaskUsage;
+use super::context::{interrupt_return, CpuContext, InterruptFrame};
 use super::priority::TaskPriority;
 use super::process_group::{Pid, Pgid, Sid, DeviceId};
 use crate::mm::paging::PageTableFlags;
+use crate::mm::PhysAddr;
 use crate::signal::{SigAction, signals};
+use crate::time::Instant;
 use core::sync::atomic::{AtomicU64, Ordering};
 
 /// Task identifier type
+///
+/// The low [`TASK_SLOT_BITS`] bits are the task's slot in the task table;
+/// the bits above are the slot's generation, bumped each time a task in
+/// the slot is reaped. An ID kept after its task was reaped therefore
+/// names no task, rather than the one that took over the slot, until the
+/// generation wraps after 2^[`TASK_GENERATION_BITS`] reuses. IDs stay below
+/// 2^23, so they fit the 24-bit PID field of `SYS_WAIT`'s result.
 pub type TaskId = usize;
 
+/// Bits of a [`TaskId`] holding the task table slot
+pub const TASK_SLOT_BITS: u32 = 12;
+
+/// Bits of a [`TaskId`] holding the slot's generation
+pub const TASK_GENERATION_BITS: u32 = 11;
+
+/// Task table slot of `id`
+pub const fn task_slot(id: TaskId) -> usize {
+    id & ((1 << TASK_SLOT_BITS) - 1)
+}
+
+/// Generation of the slot of `id` when the task got it
+pub const fn task_generation(id: TaskId) -> usize {
+    (id >> TASK_SLOT_BITS) & ((1 << TASK_GENERATION_BITS) - 1)
+}
+
+/// ID of the task in `slot` at `generation`
+pub const fn make_task_id(slot: usize, generation: usize) -> TaskId {
+    (generation & ((1 << TASK_GENERATION_BITS) - 1)) << TASK_SLOT_BITS | slot
+}
+
 /// Memory region types for process memory tracking
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MemoryRegionType {
@@ -24,11 +55,48 @@ pub enum MemoryRegionType {
     Bss,
     /// Stack segment
     Stack,
-    /// Heap segment (future use)
+    /// brk heap, populated on first touch
     Heap,
+    /// Anonymous mapping created by mmap, populated on first touch
+    Anonymous,
+    /// Mapping of shared memory object `id` whose first page is at `base`
+    Shared { id: usize, base: usize },
+    /// Mapping of device memory at physical address `phys`, whose first
+    /// page is at `base` (see `dev::fb`)
+    Device { phys: PhysAddr, base: usize },
+}
+
+/// File contents backing part of a memory region
+///
+/// Bytes `[offset, offset + file_size)` of `image` appear at virtual address
+/// `vaddr`; the rest of the region reads as zero.
+#[derive(Clone, Copy)]
+pub struct FileBacking {
+    /// Whole file the bytes come from
+    pub image: &'static [u8],
+    /// Offset of the first backed byte in `image`
+    pub offset: usize,
+    /// Virtual address the first backed byte is loaded at
+    pub vaddr: usize,
+    /// Number of backed bytes
+    pub file_size: usize,
+}
+
+impl core::fmt::Debug for FileBacking {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FileBacking")
+            .field("offset", &self.offset)
+            .field("vaddr", &self.vaddr)
+            .field("file_size", &self.file_size)
+            .finish()
+    }
 }
 
 /// Memory region descriptor for process memory tracking
+///
+/// The regions of a task are its virtual memory areas: user pages are only
+/// populated on first touch, from `backing` if present and zero-filled
+/// otherwise (see `mm::demand`).
 #[derive(Debug, Clone)]
 pub struct MemoryRegion {
     /// Start virtual address (inclusive)
@@ -39,6 +107,8 @@ pub struct MemoryRegion {
     pub flags: PageTableFlags,
     /// Type of memory region
     pub region_type: MemoryRegionType,
+    /// File contents the region is loaded from, if any
+    pub backing: Option<FileBacking>,
 }
 
 impl MemoryRegion {
@@ -54,6 +124,7 @@ impl MemoryRegion {
             end,
             flags,
             region_type,
+            backing: None,
         }
     }
 
@@ -97,11 +168,36 @@ pub enum SchedulerError {
     InvalidUserAddress,
     /// Too many memory regions
     TooManyRegions,
+    /// Kernel stack size out of range or not whole pages
+    InvalidStackSize,
 }
 
 /// Result type for scheduler operations
 pub type SchedulerResult<T> = Result<T, SchedulerError>;
 
+/// Why a task could not be spawned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// Every task ID is in use
+    TableFull,
+    /// No memory for the task, its stack or the task table, even after
+    /// reclaiming caches
+    OutOfMemory,
+    /// The requested kernel stack size is not whole pages between
+    /// [`MIN_STACK_SIZE`] and [`MAX_STACK_SIZE`]
+    InvalidStackSize,
+}
+
+impl From<SpawnError> for SchedulerError {
+    fn from(error: SpawnError) -> Self {
+        match error {
+            SpawnError::TableFull => SchedulerError::TooManyTasks,
+            SpawnError::OutOfMemory => SchedulerError::OutOfMemory,
+            SpawnError::InvalidStackSize => SchedulerError::InvalidStackSize,
+        }
+    }
+}
+
 /// Task state enumeration
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TaskState {
@@ -111,15 +207,33 @@ pub enum TaskState {
     /// Task is currently running on the CPU
     Running,
 
-    /// Task is sleeping (waiting for wake_tick)
+    /// Task is sleeping (waiting for wake_at)
     Sleeping,
 
     /// Task is blocked on IPC
     Blocked,
+
+    /// Task has exited and is never scheduled again
+    Exited,
+}
+
+/// Kernel stack size of a task unless it asks for another
+pub const DEFAULT_STACK_SIZE: usize = 8192;
+
+/// Smallest kernel stack a task may ask for
+pub const MIN_STACK_SIZE: usize = 4096;
+
+/// Largest kernel stack a task may ask for: a stack slot less its guard
+/// page
+pub const MAX_STACK_SIZE: usize = crate::mm::kstack::KSTACK_SLOT_SIZE - 4096;
+
+/// Whether `size` is usable as a task's kernel stack size
+pub fn valid_stack_size(size: usize) -> bool {
+    (MIN_STACK_SIZE..=MAX_STACK_SIZE).contains(&size) && size % 4096 == 0
 }
 
 /// Maximum number of memory regions per task
-const MAX_MEMORY_REGIONS: usize = 16;
+pub const MAX_MEMORY_REGIONS: usize = 32;
 
 /// Maximum number of signals (64 signals, 0-63)
 const MAX_SIGNALS: usize = 64;
@@ -154,18 +268,41 @@ pub struct Task {
     /// Task priority level
     pub priority: TaskPriority,
 
-    /// Tick at which to wake the task (if sleeping)
-    pub wake_tick: Option<u64>,
+    /// When to wake the task (if sleeping)
+    pub wake_at: Option<Instant>,
+
+    /// Whether a signal ends the current sleep early
+    pub interruptible: bool,
 
     /// Port ID the task is blocked on (if blocked on IPC)
     pub blocked_on_port: Option<usize>,
 
+    /// Notification bits raised by other tasks (`SYS_IPC_NOTIFY`), cleared
+    /// as `SYS_IPC_POLL` reports them
+    pub notifications: AtomicU64,
+
+    /// Notification bits the task waits for while sleeping in
+    /// `SYS_IPC_POLL` (0 otherwise)
+    pub notify_wait: AtomicU64,
+
     /// Memory regions for this task (Code, Data, BSS, Stack)
     pub memory_regions: [Option<MemoryRegion>; MAX_MEMORY_REGIONS],
 
     /// Number of active memory regions
     pub region_count: usize,
 
+    /// Top of the user stack (randomized per process unless `kaslr=off`)
+    pub user_stack_top: usize,
+
+    /// Base below which user mmap areas are placed (randomized per process)
+    pub mmap_base: usize,
+
+    /// Start of the brk heap (0 until an image is loaded)
+    pub heap_start: usize,
+
+    /// Current program break
+    pub brk: usize,
+
     /// Signal handlers for each signal (indexed by signal number)
     pub signal_handlers: [SigAction; MAX_SIGNALS],
 
@@ -177,9 +314,25 @@ pub struct Task {
     /// Uses atomic operations for race-free mask updates
     pub signal_mask: AtomicU64,
 
-    /// Process ID (same as task ID for now)
+    /// Process ID: the task ID, or for a thread the ID of the task whose
+    /// address space it shares (see `sched::thread`)
     pub pid: Pid,
 
+    /// User word set to 0 and woken (`SYS_FUTEX`) when the thread exits,
+    /// or 0 for none
+    pub exit_word: usize,
+
+    /// Where a new thread enters user mode, taken on its first run
+    pub thread_start: Option<super::thread::ThreadStart>,
+
+    /// Program a task started by `user::spawn` loads before entering user
+    /// mode, taken on its first run
+    pub image: Option<&'static [u8]>,
+
+    /// Arguments of a task started by `user::spawn`, placed on its user
+    /// stack on its first run
+    pub args: Option<crate::user::spawn::Args>,
+
     /// Parent process ID
     pub ppid: Pid,
 
@@ -192,18 +345,45 @@ pub struct Task {
     /// Controlling terminal device (if any)
     pub tty: Option<DeviceId>,
 
+    /// File mode creation mask (inherited across fork, kept across exec)
+    pub umask: u32,
+
+    /// Resources used by this task
+    pub usage: TaskUsage,
+
+    /// Resources used by exited children (and their children)
+    pub children_usage: TaskUsage,
+
     /// Last syscall number executed (for debugging/panic dumps)
     pub last_syscall: Option<usize>,
+
+    /// Syscall tracing (`SYS_PTRACE_LITE`)
+    pub strace: crate::sys::strace::TraceState,
+
+    /// Syscall filter (`SYS_SECCOMP`)
+    pub seccomp: crate::sys::seccomp::Filter,
+
+    /// CPU burst history, sizing the quantum
+    pub burst: super::burst::BurstPredictor,
+
+    /// Runs, runqueue waits and preemptions (`/proc/sched`)
+    pub sched_stats: super::stats::SchedStats,
+
+    /// CPU bandwidth group (`SYS_CPU_GROUP`)
+    pub cpu_group: usize,
 }
 
 impl Task {
     /// Create a new task with the given entry point
     ///
     /// This function:
-    /// 1. Allocates an 8KB stack from the kernel heap
-    /// 2. Prepares the initial stack frame with entry_trampoline as return address
-    /// 3. Sets up callee-saved registers (R12 holds the entry_point)
-    /// 4. Initializes the CPU context with the prepared stack pointer
+    /// 1. Allocates a [`DEFAULT_STACK_SIZE`] stack (with an unmapped guard page below it)
+    /// 2. Builds an [`InterruptFrame`] that returns to entry_trampoline, with
+    ///    RDI holding the entry_point and interrupts enabled
+    /// 3. Below it, the callee-saved registers and return address
+    ///    (interrupt_return) the first context_switch pops
+    /// 4. Allocates the FPU/SIMD save area with the initial FPU state
+    /// 5. Initializes the CPU context with the prepared stack pointer
     ///
     /// # Arguments
     /// * `id` - Unique task identifier
@@ -219,33 +399,55 @@ impl Task {
         entry_point: fn() -> !,
         priority: TaskPriority,
     ) -> SchedulerResult<Self> {
-        use crate::mm::allocator::kmalloc;
+        Self::with_stack_size(id, name, entry_point, priority, DEFAULT_STACK_SIZE)
+    }
 
-        // 1. Allocate 8KB stack
-        const STACK_SIZE: usize = 8192;
-        let stack = kmalloc(STACK_SIZE);
+    /// [`Task::new`] with a kernel stack of `stack_size` bytes
+    ///
+    /// Fails with `InvalidStackSize` unless [`valid_stack_size`] accepts
+    /// the size.
+    pub fn with_stack_size(
+        id: TaskId,
+        name: &'static str,
+        entry_point: fn() -> !,
+        priority: TaskPriority,
+        stack_size: usize,
+    ) -> SchedulerResult<Self> {
+        use crate::mm::kstack::alloc_kernel_stack;
 
-        if stack.is_null() {
-            return Err(SchedulerError::OutOfMemory);
+        // 1. Allocate the stack from the kernel stack region so that an
+        //    overflow hits a guard page instead of neighbouring heap objects
+        if !valid_stack_size(stack_size) {
+            return Err(SchedulerError::InvalidStackSize);
         }
+        let kstack = alloc_kernel_stack(stack_size).map_err(|_| SchedulerError::OutOfMemory)?;
+        let stack = kstack.bottom().as_mut_ptr::<u8>();
 
         // 2. Calculate stack top (stack grows downward)
-        let stack_top = (stack as usize) + STACK_SIZE;
+        let stack_top = kstack.top();
 
         // 3. Prepare initial stack frame
-        // The stack will be set up so that when context_switch does 'ret',
-        // it will jump to entry_trampoline
-        let mut rsp = stack_top as *mut u64;
+        // The task starts the way a preempted task resumes: context_switch
+        // returns to interrupt_return, which pops this frame and irets to
+        // entry_trampoline on the empty stack above it
+        let frame = InterruptFrame {
+            rdi: entry_point as u64,
+            rip: entry_trampoline as u64,
+            cs: crate::arch::x86_64::gdt::KERNEL_CODE_SEG as u64,
+            rflags: 0x202, // IF set
+            rsp: stack_top.as_u64(),
+            ss: crate::arch::x86_64::gdt::KERNEL_DATA_SEG as u64,
+            ..InterruptFrame::default()
+        };
+        let mut rsp = stack_top.as_mut_ptr::<u64>();
 
         unsafe {
-            // Push entry_point as an argument (will be below the registers)
-            // This will be accessible from entry_trampoline
-            rsp = rsp.offset(-1);
-            *rsp = entry_point as u64;
+            rsp = (rsp as *mut InterruptFrame).offset(-1) as *mut u64;
+            (rsp as *mut InterruptFrame).write(frame);
 
-            // Push entry_trampoline as return address
+            // Push interrupt_return as return address
             rsp = rsp.offset(-1);
-            *rsp = entry_trampoline as u64;
+            *rsp = interrupt_return as *const () as u64;
 
             // Push callee-saved registers (will be popped by context_switch)
             // These are pushed in reverse order of how they'll be popped
@@ -263,15 +465,22 @@ impl Task {
             *rsp = 0; // RBX
         }
 
-        // 4. Create CPU context
+        // 4. Allocate the FPU/SIMD save area, holding the initial state
+        let fpu_area = crate::arch::x86_64::fpu::alloc_area().map_err(|_| SchedulerError::OutOfMemory)?;
+
+        // 5. Create CPU context
         let context = CpuContext {
             rsp: rsp as u64,
             rbx: 0,
             rbp: 0,
-            r12: entry_point as u64,
+            r12: 0,
             r13: 0,
             r14: 0,
             r15: 0,
+            fs_base: 0,
+            gs_base: 0,
+            fpu_area: fpu_area as u64,
+            kernel_stack: stack_top.as_u64(),
         };
 
         // Initialize signal handlers with defaults
@@ -281,23 +490,42 @@ impl Task {
             id,
             name,
             stack,
-            stack_size: STACK_SIZE,
+            stack_size,
             state: TaskState::Ready,
             context,
             priority,
-            wake_tick: None,
+            wake_at: None,
+            interruptible: false,
             blocked_on_port: None,
+            notifications: AtomicU64::new(0),
+            notify_wait: AtomicU64::new(0),
             memory_regions: [const { None }; MAX_MEMORY_REGIONS],
             region_count: 0,
+            user_stack_top: crate::mm::kaslr::user_stack_top(),
+            mmap_base: crate::mm::kaslr::user_mmap_base(),
+            heap_start: 0,
+            brk: 0,
             signal_handlers,
             pending_signals: AtomicU64::new(0),
             signal_mask: AtomicU64::new(0),
             pid: id,        // PID = task ID
+            exit_word: 0,
+            thread_start: None,
+            image: None,
+            args: None,
             ppid: 0,        // Will be set by parent
             pgid: id,       // Initially, pgid = pid
             sid: id,        // Initially, sid = pid (for init process)
             tty: None,      // No controlling terminal initially
+            umask: crate::fs::DEFAULT_UMASK,
+            usage: TaskUsage::default(),
+            children_usage: TaskUsage::default(),
             last_syscall: None, // No syscall executed yet
+            strace: Default::default(),
+            seccomp: Default::default(),
+            burst: super::burst::BurstPredictor::new(),
+            sched_stats: super::stats::SchedStats::new(),
+            cpu_group: super::bandwidth::ROOT_GROUP,
         })
     }
 
@@ -453,6 +681,11 @@ impl Task {
         self.region_count = 0;
     }
 
+    /// Whether the task is a thread sharing another task's address space
+    pub fn is_thread(&self) -> bool {
+        self.pid != self.id
+    }
+
     /// Initialize default signal handlers for a new task
     ///
     /// Sets up the default signal actions according to POSIX semantics:
@@ -592,26 +825,19 @@ impl Task {
 
 /// Entry trampoline for new tasks
 ///
-/// This function is called when a new task is first scheduled.
-/// The entry_point function pointer is on the stack (pushed by Task::new).
-/// If the entry_point ever returns (which it shouldn't), we panic.
+/// This function is where a new task's initial [`InterruptFrame`] returns
+/// to, with interrupts enabled and the entry_point function pointer in RDI
+/// (both set by Task::new). If the entry_point ever returns (which it
+/// shouldn't), we panic.
 ///
 /// # Safety
-/// This function uses inline assembly to extract the entry point from the stack.
-/// It must only be called through the context switch mechanism.
+/// It must only be reached through the context switch mechanism.
 #[unsafe(naked)]
 #[no_mangle]
 pub extern "C" fn entry_trampoline() -> ! {
     core::arch::naked_asm!(
-        // Pop the entry_point from the stack (it was pushed by Task::new)
-        "pop rax",
-
         // Save entry_point in a callee-saved register
-        "mov r12, rax",
-
-        // Enable interrupts before calling the task
-        // (they were disabled during the interrupt handler)
-        "sti",
+        "mov r12, rdi",
 
         // Align stack to 16 bytes (required by System V ABI)
         // The stack should be 16-byte aligned before a call instruction
@@ -0,0 +1,249 @@
+//! User threads
+//!
+//! `SYS_THREAD_CREATE` starts a new task in the caller's process. The thread
+//! runs in the same address space and uses the same fd table (there is one,
+//! shared by all tasks). It starts with a copy of the caller's capabilities,
+//! process group, session and signal mask. It gets its own kernel stack, its
+//! own task ID (its thread ID), and the user stack and TLS base (FS base)
+//! the caller passes in.
+//!
+//! A thread's `pid` is the ID of the task that created the process. That
+//! task's memory regions describe the shared address space, so mappings made
+//! by any thread land there (see `sched::get_address_space_mut`).
+//!
+//! Threads are joined through a futex word. The creator names a `u32` in
+//! [`ThreadParams::tid_ptr`]. The kernel stores the thread ID there before
+//! the thread can run, and sets it to 0 and wakes it (`SYS_FUTEX`) when the
+//! thread exits. A joiner waits on the word until it reads 0.
+//!
+//! `SYS_THREAD_EXIT` ends the calling thread only. `SYS_EXIT` ends the whole
+//! process, including every thread in it.
+
+use super::priority::TaskPriority;
+use super::task::{Task, TaskId, TaskState, USER_LIMIT};
+use super::{all_tasks, get_task, spawn_task_with, MAX_TASKS};
+use crate::arch::x86_64::syscall::copy_to_user;
+
+/// Arguments of `SYS_THREAD_CREATE`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadParams {
+    /// User function the thread starts in
+    pub entry: u64,
+    /// Passed to `entry` in RDI
+    pub arg: u64,
+    /// Top of the thread's user stack, 16-byte aligned
+    pub stack_top: u64,
+    /// FS base of the thread (TLS pointer), 0 for none
+    pub tls: u64,
+    /// Address of the `u32` join word, 0 for none
+    pub tid_ptr: u64,
+}
+
+mello_abi::check_layout!(ThreadParams, mello_abi::ThreadParams { entry, arg, stack_top, tls, tid_ptr });
+
+/// Where a new thread enters user mode
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadStart {
+    pub entry: u64,
+    pub stack_top: u64,
+    pub arg: u64,
+}
+
+/// Thread errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadError {
+    /// An address outside user space, a misaligned stack or join word
+    InvalidArgument,
+    /// Called without a current task
+    NoTask,
+    /// The task table is full or out of memory
+    NoResources,
+}
+
+/// Start a thread in the current task's process
+///
+/// Returns the thread ID.
+pub fn create(params: &ThreadParams) -> Result<TaskId, ThreadError> {
+    let in_user = |addr: u64| (addr as usize) < USER_LIMIT;
+    if params.entry == 0
+        || !in_user(params.entry)
+        || params.stack_top == 0
+        || !in_user(params.stack_top)
+        || params.stack_top & 0xF != 0
+        || !in_user(params.tls)
+        || !in_user(params.tid_ptr)
+        || params.tid_ptr % 4 != 0
+    {
+        return Err(ThreadError::InvalidArgument);
+    }
+
+    let (creator_id, priority) = super::get_current_task_info().ok_or(ThreadError::NoTask)?;
+    let creator = get_task(creator_id).ok_or(ThreadError::NoTask)?;
+    let (pid, ppid, pgid, sid) = (creator.pid, creator.ppid, creator.pgid, creator.sid);
+    let (tty, umask, strace, seccomp) = (creator.tty, creator.umask, creator.strace, creator.seccomp);
+    let cpu_group = creator.cpu_group;
+    let signal_mask = creator.get_signal_mask();
+    let start = ThreadStart {
+        entry: params.entry,
+        stack_top: params.stack_top,
+        arg: params.arg,
+    };
+
+    let setup = |task: &mut Task| {
+        task.pid = pid;
+        task.ppid = ppid;
+        task.pgid = pgid;
+        task.sid = sid;
+        task.tty = tty;
+        task.umask = umask;
+        task.strace = strace;
+        task.seccomp = seccomp;
+        task.cpu_group = cpu_group;
+        task.set_signal_mask(signal_mask);
+        task.context.fs_base = params.tls;
+        task.thread_start = Some(start);
+        task.exit_word = params.tid_ptr as usize;
+        if task.exit_word != 0 {
+            // The range was checked above
+            let _ = copy_to_user(task.exit_word, &(task.id as u32).to_ne_bytes());
+        }
+    };
+    spawn_task_with("thread", thread_entry, priority, setup).map_err(|_| ThreadError::NoResources)
+}
+
+/// Kernel entry of a new thread: drop to its user entry point
+fn thread_entry() -> ! {
+    let start = super::get_current_task_info()
+        .and_then(|(id, _)| get_task(id))
+        .and_then(|task| task.thread_start.take());
+    match start {
+        Some(start) => unsafe { enter_user(start) },
+        None => panic!("[THREAD] Thread started without a user entry point"),
+    }
+}
+
+/// Switch to ring 3 at `start.entry` on `start.stack_top`, with `start.arg`
+/// in RDI and every other register cleared
+unsafe fn enter_user(start: ThreadStart) -> ! {
+    use crate::arch::x86_64::gdt::{USER_CODE_SEG, USER_DATA_SEG};
+
+    core::arch::asm!(
+        "cli",
+        // IRET frame: SS, RSP, RFLAGS (with IF), CS, RIP
+        "push {ss}",
+        "push {stack}",
+        "pushfq",
+        "or qword ptr [rsp], 0x200",
+        "push {cs}",
+        "push {entry}",
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        // Kernel GS base out, user GS base in
+        "swapgs",
+        "iretq",
+        ss = in(reg) USER_DATA_SEG as u64,
+        stack = in(reg) start.stack_top,
+        cs = in(reg) USER_CODE_SEG as u64,
+        entry = in(reg) start.entry,
+        in("rdi") start.arg,
+        options(noreturn),
+    )
+}
+
+/// Stop `task` for good
+///
+/// Clears and wakes its join word, adds its resource usage to the process,
+/// tells the ports watching it that it exited with `code` and, for a
+/// thread, queues it to be reaped: nothing waits for its exit status. A
+/// task running on another CPU stops at its next tick.
+fn end(task: &mut Task, code: usize) {
+    if task.exit_word != 0 {
+        let _ = copy_to_user(task.exit_word, &0u32.to_ne_bytes());
+        crate::sys::futex::wake(task.exit_word);
+    }
+    crate::sys::perf::task_exit(task.id);
+    if task.is_thread() {
+        if let Some(process) = get_task(task.pid) {
+            process.usage.absorb(&task.usage.snapshot());
+        }
+    }
+    task.state = TaskState::Exited;
+    crate::sys::exit_watch::exited(task.id, task.pid, code);
+    if task.is_thread() {
+        super::reap_task(task.id);
+    }
+}
+
+/// End the calling thread (`SYS_THREAD_EXIT`)
+///
+/// The caller must be a thread; the task that created the process leaves
+/// with `SYS_EXIT`.
+pub fn exit_current() -> ! {
+    if let Some(task) = super::get_current_task_info().and_then(|(id, _)| get_task(id)) {
+        end(task, 0);
+    }
+    super::yield_now();
+    panic!("[THREAD] Exited thread was scheduled again");
+}
+
+/// End every task of process `pid` except `survivor` (`SYS_EXIT` with
+/// `code`)
+///
+/// Returns the number of tasks ended.
+pub fn end_group(pid: TaskId, survivor: TaskId, code: usize) -> usize {
+    let mut members = [0; MAX_TASKS];
+    let mut count = 0;
+    for task in all_tasks() {
+        if task.pid == pid && task.id != survivor && task.state != TaskState::Exited {
+            members[count] = task.id;
+            count += 1;
+        }
+    }
+
+    for &id in &members[..count] {
+        if let Some(task) = get_task(id) {
+            end(task, code);
+        }
+    }
+    count
+}
+
+crate::kernel_test! {
+    /// Bad arguments are refused; ending a process ends its threads but
+    /// not the survivor
+    fn thread_group_exit() {
+        let kernel_entry = ThreadParams { entry: USER_LIMIT as u64, stack_top: 0x1000, ..Default::default() };
+        crate::ktest_assert_eq!(create(&kernel_entry).err(), Some(ThreadError::InvalidArgument), "kernel entry accepted");
+        let misaligned = ThreadParams { entry: 0x40_0000, stack_top: 0x1008, ..Default::default() };
+        crate::ktest_assert_eq!(create(&misaligned).err(), Some(ThreadError::InvalidArgument), "misaligned stack accepted");
+
+        let parked = super::ktest_parked_task;
+        let leader = super::spawn_task("ktest_leader", parked, TaskPriority::Normal).map_err(|_| "spawn failed")?;
+        let thread = spawn_task_with("ktest_thread", parked, TaskPriority::Normal, |task| task.pid = leader)
+            .map_err(|_| "spawn failed")?;
+        crate::ktest_assert!(get_task(thread).map_or(false, |task| task.is_thread()), "not a thread");
+        crate::ktest_assert_eq!(
+            super::get_address_space_mut(thread).map(|task| task.id),
+            Some(leader),
+            "thread has its own address space"
+        );
+
+        crate::ktest_assert_eq!(end_group(leader, leader, 0), 1, "tasks ended");
+        crate::ktest_assert_eq!(get_task(thread).map(|task| task.state), Some(TaskState::Exited), "thread still runnable");
+        crate::ktest_assert_eq!(get_task(leader).map(|task| task.state), Some(TaskState::Ready), "survivor ended");
+        Ok(())
+    }
+}
@@ -6,6 +6,7 @@
 
 use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::instructions::port::Port;
+use super::context::InterruptFrame;
 
 /// PIT (Programmable Interval Timer) constants
 const PIT_FREQUENCY: u32 = 1193182; // PIT base frequency in Hz
@@ -69,6 +70,12 @@ impl IdtEntry {
         self.type_attr = 0xEE;
         self.reserved = 0;
     }
+
+    fn set_handler_ist(&mut self, handler: usize, selector: u16, ist: u8) {
+        self.set_handler(handler, selector);
+        // IST index lives in the low 3 bits; 0 means "use the current stack"
+        self.ist = ist & 0x7;
+    }
 }
 
 /// IDT Table structure
@@ -113,6 +120,9 @@ pub unsafe fn init_idt() {
 
     serial_println!("[TIMER] Setting up IDT...");
 
+    // Catch-all gates first; the handlers below replace theirs
+    crate::arch::x86_64::spurious::init();
+
     // Get the code segment selector (0x08 for kernel code segment in most setups)
     let code_selector: u16 = 0x28; // Limine sets up GDT with kernel code at 0x28
 
@@ -342,36 +352,28 @@ unsafe fn send_eoi() {
 extern "C" fn timer_interrupt_handler_wrapper() {
     core::arch::naked_asm!(
         // The CPU has already pushed SS, RSP, RFLAGS, CS, RIP
-        // We need to save all other registers
-
-        "push rax",
-        "push rcx",
-        "push rdx",
-        "push rsi",
-        "push rdi",
-        "push r8",
-        "push r9",
-        "push r10",
-        "push r11",
-
-        // Call the actual handler
+        crate::swapgs_if_user!(8),
+
+        // Save every other register: a switch on the way out leaves the
+        // whole InterruptFrame on this task's stack
+        crate::push_interrupt_frame!(),
+
+        // Pass the frame
+        "mov rdi, rsp",
+
+        // Call the actual handler, then switch tasks if it asked for that
         "call {handler}",
+        "call {resched}",
 
         // Restore registers
-        "pop r11",
-        "pop r10",
-        "pop r9",
-        "pop r8",
-        "pop rdi",
-        "pop rsi",
-        "pop rdx",
-        "pop rcx",
-        "pop rax",
+        crate::pop_interrupt_frame!(),
 
         // Return from interrupt (pops RIP, CS, RFLAGS, RSP, SS)
+        crate::swapgs_if_user!(8),
         "iretq",
 
         handler = sym timer_interrupt_handler,
+        resched = sym crate::sched::irq_return_resched,
     )
 }
 
@@ -379,28 +381,58 @@ extern "C" fn timer_interrupt_handler_wrapper() {
 ///
 /// This function is called by the wrapper when a timer interrupt (IRQ0) occurs.
 /// It:
-/// 1. Sends EOI to the PIC (to allow next interrupt)
-/// 2. Increments the tick counter
-/// 3. Calls the scheduler tick function
+/// 1. Increments the tick counter and charges the tick
+/// 2. Sends EOI to the PIC (to allow next interrupt)
+/// 3. Wakes sleepers whose deadline has passed
+/// 4. Counts the quantum down, setting `need_resched` once it is used up
 ///
 /// # Notes
 /// - The CPU automatically disables interrupts (IF=0) when entering this handler
-/// - The scheduler tick() function performs a context switch and doesn't return
-/// - This is a "tail-switch" - we don't return to this handler
-extern "C" fn timer_interrupt_handler() {
+/// - The handler never switches tasks: the wrapper calls
+///   `sched::irq_return_resched` after it returns
+extern "C" fn timer_interrupt_handler(frame: &InterruptFrame) {
+    let (interrupted_cs, interrupted_rip) = (frame.cs, frame.rip);
+    crate::sync::lockdep::irq_enter();
+    crate::trace!(irq_entry, 0x20);
+
     // Increment tick counter (for testing and debugging)
     TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
+    crate::time::clock::tick();
+    crate::arch::x86_64::vdso::update();
+
+    // Charge the tick to whoever was running, and sample it if profiled
+    crate::sched::account_tick(interrupted_cs & 3 == 3);
+    if interrupted_cs & 3 == 3 {
+        crate::sys::perf::sample(interrupted_rip);
+    }
+    crate::profiler::sample(interrupted_rip);
+
+    // Take queued serial and mouse input, then wake tasks polling
+    // devices that have no interrupt of their own
+    crate::console::poll_input();
+    crate::sync::wait_queue::poll_readiness();
+
+    // Interrupt arrival time feeds the kernel entropy pool
+    crate::rand::add_interrupt_timing(0x20);
 
     // Send EOI to PIC first (so it can send next interrupt)
     unsafe {
         send_eoi();
     }
 
-    // Call scheduler tick (this performs context switch and doesn't return)
-    crate::sched::tick();
+    // Wake sleepers whose deadline has passed
+    crate::sched::wake_sleeping_tasks(crate::time::Instant::now());
+
+    // Count down the quantum; ask for a switch once it is used up
+    crate::sched::timer_tick();
 
-    // Note: We never reach here because tick() does a tail-switch
-    // The next task will continue from where it was interrupted
+    crate::sync::lockdep::irq_exit();
+
+    // A killed task running in user mode ends here, outside the handler
+    // proper since exiting switches away for good
+    if interrupted_cs & 3 == 3 {
+        crate::signal::exit_if_killed();
+    }
 }
 
 /// Initialize the timer interrupt system
@@ -461,36 +493,28 @@ pub fn get_tick_count() -> usize {
 extern "C" fn apic_timer_interrupt_handler_wrapper() {
     core::arch::naked_asm!(
         // The CPU has already pushed SS, RSP, RFLAGS, CS, RIP
-        // We need to save all other registers
-
-        "push rax",
-        "push rcx",
-        "push rdx",
-        "push rsi",
-        "push rdi",
-        "push r8",
-        "push r9",
-        "push r10",
-        "push r11",
-
-        // Call the actual handler
+        crate::swapgs_if_user!(8),
+
+        // Save every other register: a switch on the way out leaves the
+        // whole InterruptFrame on this task's stack
+        crate::push_interrupt_frame!(),
+
+        // Pass the frame
+        "mov rdi, rsp",
+
+        // Call the actual handler, then switch tasks if it asked for that
         "call {handler}",
+        "call {resched}",
 
         // Restore registers
-        "pop r11",
-        "pop r10",
-        "pop r9",
-        "pop r8",
-        "pop rdi",
-        "pop rsi",
-        "pop rdx",
-        "pop rcx",
-        "pop rax",
+        crate::pop_interrupt_frame!(),
 
         // Return from interrupt (pops RIP, CS, RFLAGS, RSP, SS)
+        crate::swapgs_if_user!(8),
         "iretq",
 
         handler = sym apic_timer_interrupt_handler,
+        resched = sym crate::sched::irq_return_resched,
     )
 }
 
@@ -500,30 +524,71 @@ static BALANCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
 /// APIC timer interrupt handler
 ///
 /// This function is called when an APIC timer interrupt (vector 0x20) occurs.
-/// It:
+/// An interrupt between ticks (TSC-deadline mode, see `apic::timer`) only
+/// wakes sleepers. Otherwise it:
 /// 1. Increments the per-CPU tick counter
 /// 2. Sends EOI to the Local APIC
 /// 3. Performs load balancing every 100ms (2 ticks at 20Hz)
-/// 4. Calls the scheduler tick function for the current core
+/// 4. Counts the quantum down, setting `need_resched` once it is used up
 ///
 /// # Notes
 /// - The CPU automatically disables interrupts (IF=0) when entering this handler
-/// - The scheduler tick() function performs a context switch and doesn't return
-/// - This is a "tail-switch" - we don't return to this handler
-extern "C" fn apic_timer_interrupt_handler() {
+/// - The handler never switches tasks: the wrapper calls
+///   `sched::irq_return_resched` after it returns
+extern "C" fn apic_timer_interrupt_handler(frame: &InterruptFrame) {
+    let (interrupted_cs, interrupted_rip) = (frame.cs, frame.rip);
     use crate::arch::x86_64::acpi::get_madt_info;
     use crate::arch::x86_64::apic::LocalApic;
     use crate::arch::x86_64::smp::percpu::percpu_current_mut;
     use core::sync::atomic::Ordering;
 
+    crate::sync::lockdep::irq_enter();
+    crate::trace!(irq_entry, 0x20);
+
     // Get current CPU's per-CPU data
     let percpu = unsafe { percpu_current_mut() };
 
+    // In TSC-deadline mode, a deadline armed for a sleeper may come
+    // between ticks: wake it and count nothing
+    if !crate::arch::x86_64::apic::timer::handle_interrupt() {
+        unsafe {
+            let madt_info = get_madt_info().expect("MADT info not available");
+            LocalApic::new(madt_info.lapic_address).eoi();
+        }
+        crate::sched::wake_sleeping_tasks(crate::time::Instant::now());
+        if let Some(deadline) = crate::sched::next_wakeup() {
+            crate::arch::x86_64::apic::timer::arm(deadline);
+        }
+        crate::sync::lockdep::irq_exit();
+        return;
+    }
+
     // Increment per-CPU tick counter
     percpu.ticks.fetch_add(1, Ordering::Relaxed);
 
     // Also increment global tick counter for compatibility
     let global_ticks = TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
+    if percpu.id == 0 {
+        crate::time::clock::tick();
+        crate::arch::x86_64::vdso::update();
+    }
+
+    // Interrupt arrival time feeds the kernel entropy pool
+    crate::rand::add_interrupt_timing(0x20 | (percpu.id as u64) << 8);
+
+    // Charge the tick to whoever was running, and sample it if profiled
+    crate::sched::account_tick(interrupted_cs & 3 == 3);
+    if interrupted_cs & 3 == 3 {
+        crate::sys::perf::sample(interrupted_rip);
+    }
+    crate::profiler::sample(interrupted_rip);
+
+    // Take queued serial and mouse input, then wake tasks polling
+    // devices that have no interrupt of their own
+    if percpu.id == 0 {
+        crate::console::poll_input();
+    }
+    crate::sync::wait_queue::poll_readiness();
     
     // Debug: Print first few timer interrupts
     if global_ticks < 5 {
@@ -543,10 +608,25 @@ extern "C" fn apic_timer_interrupt_handler() {
         crate::sched::balance_load();
     }
 
-    // Call scheduler tick (this performs context switch and doesn't return)
-    crate::sched::tick();
+    // Wake sleepers whose deadline has passed (CPU 0 only, like load
+    // balancing), then arm the next deadline if it comes before a tick
+    if percpu.id == 0 {
+        crate::sched::wake_sleeping_tasks(crate::time::Instant::now());
+        if let Some(deadline) = crate::sched::next_wakeup() {
+            crate::arch::x86_64::apic::timer::arm(deadline);
+        }
+    }
+
+    // Count down the quantum; ask for a switch once it is used up
+    crate::sched::timer_tick();
+
+    crate::sync::lockdep::irq_exit();
 
-    // Note: We never reach here because tick() does a tail-switch
+    // A killed task running in user mode ends here, outside the handler
+    // proper since exiting switches away for good
+    if interrupted_cs & 3 == 3 {
+        crate::signal::exit_if_killed();
+    }
 }
 
 /// Initialize APIC timer interrupt handler in IDT
@@ -598,44 +678,33 @@ pub unsafe fn init_apic_timer_handler() {
 extern "C" fn reschedule_ipi_handler_wrapper() {
     core::arch::naked_asm!(
         // The CPU has already pushed SS, RSP, RFLAGS, CS, RIP
-        // We need to save all other registers
-
-        "push rax",
-        "push rcx",
-        "push rdx",
-        "push rsi",
-        "push rdi",
-        "push r8",
-        "push r9",
-        "push r10",
-        "push r11",
-
-        // Call the actual handler
+        crate::swapgs_if_user!(8),
+
+        // Save every other register: a switch on the way out leaves the
+        // whole InterruptFrame on this task's stack
+        crate::push_interrupt_frame!(),
+
+        // Call the actual handler, then switch tasks if it asked for that
         "call {handler}",
+        "call {resched}",
 
         // Restore registers
-        "pop r11",
-        "pop r10",
-        "pop r9",
-        "pop r8",
-        "pop rdi",
-        "pop rsi",
-        "pop rdx",
-        "pop rcx",
-        "pop rax",
+        crate::pop_interrupt_frame!(),
 
         // Return from interrupt (pops RIP, CS, RFLAGS, RSP, SS)
+        crate::swapgs_if_user!(8),
         "iretq",
 
         handler = sym reschedule_ipi_handler,
+        resched = sym crate::sched::irq_return_resched,
     )
 }
 
 /// RESCHEDULE_IPI interrupt handler
 ///
 /// This function is called when a RESCHEDULE_IPI (vector 0x30) is received.
-/// It triggers the scheduler on the current core to preempt the current task
-/// and select a new one from the runqueue.
+/// It asks for the scheduler to run on the current core, which the wrapper
+/// then does on the way out, preempting the current task.
 ///
 /// This IPI is sent when:
 /// - A new task is enqueued to this CPU
@@ -644,12 +713,12 @@ extern "C" fn reschedule_ipi_handler_wrapper() {
 ///
 /// # Notes
 /// - The CPU automatically disables interrupts (IF=0) when entering this handler
-/// - The scheduler tick() function performs a context switch and doesn't return
-/// - This is a "tail-switch" - we don't return to this handler
 extern "C" fn reschedule_ipi_handler() {
     use crate::arch::x86_64::acpi::get_madt_info;
     use crate::arch::x86_64::apic::LocalApic;
 
+    crate::sync::lockdep::irq_enter();
+
     // Send EOI to Local APIC
     unsafe {
         let madt_info = get_madt_info().expect("MADT info not available");
@@ -657,11 +726,10 @@ extern "C" fn reschedule_ipi_handler() {
         lapic.eoi();
     }
 
-    // Call scheduler tick to perform context switch
-    // Note: This doesn't return - it performs a tail-switch
-    crate::sched::tick();
+    // Switch tasks on the way out of the interrupt
+    crate::sched::set_need_resched();
 
-    // Note: We never reach here because tick() does a tail-switch
+    crate::sync::lockdep::irq_exit();
 }
 
 /// Initialize RESCHEDULE_IPI interrupt handler in IDT
@@ -700,6 +768,23 @@ pub unsafe fn init_reschedule_ipi_handler() {
     serial_println!("[IPI] RESCHEDULE_IPI handler registered successfully");
 }
 
+/// Register an exception or interrupt handler in the IDT
+///
+/// `ist` selects the TSS Interrupt Stack Table entry (1-7) the CPU switches to
+/// before invoking the handler, or 0 to stay on the current stack.
+///
+/// # Safety
+/// Must be called after init_idt(), and `handler` must be a valid interrupt
+/// entry point that matches the vector's stack frame layout.
+pub unsafe fn set_idt_gate(vector: u8, handler: usize, ist: u8) {
+    if handler == 0 {
+        panic!("[IDT] CRITICAL: handler for vector {} is null", vector);
+    }
+
+    let code_selector: u16 = 0x28; // Limine sets up GDT with kernel code at 0x28
+    IDT.entries[vector as usize].set_handler_ist(handler, code_selector, ist);
+}
+
 /// Manual test functions for timer interrupt system
 #[cfg(not(test))]
 pub mod manual_tests {
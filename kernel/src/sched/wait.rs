@@ -0,0 +1,122 @@
+//! Blocking the current task
+//!
+//! [`wait_event`] blocks until a condition holds, sleeping on a
+//! [`WaitQueue`] that whoever makes the condition true wakes (`wake_all`,
+//! or `wake_one` when only one waiter can proceed). The condition is
+//! checked again after every wake-up, so spurious wake-ups are harmless,
+//! and after the task is queued, so a wake-up is never lost (see
+//! `sync::wait_queue`). Woken tasks go back on a runqueue at their own
+//! priority. [`sleep`] is the same wait with no queue, which only its
+//! timeout ends.
+//!
+//! [`wait_event_interruptible`], [`wait_event_timeout`] and [`sleep`] also
+//! end when a signal is pending for the task (`signal::interrupts_wait`),
+//! SIGKILL included, so a blocked task can be killed. Their [`WaitError`]
+//! tells a timeout from an interruption; the timeout is a sleep deadline,
+//! so the timer tick ends it.
+
+pub use crate::sync::WaitQueue;
+use crate::sync::Poller;
+use crate::time::{Duration, Instant};
+
+/// Why a wait ended without its condition holding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The timeout passed
+    TimedOut,
+    /// A signal is pending for the task
+    Interrupted,
+    /// The wait queue is full
+    QueueFull,
+    /// There is no current task to block
+    NoTask,
+}
+
+/// Block the current task until `condition` holds
+///
+/// Returns false without blocking if `queue` is full or there is no
+/// current task.
+pub fn wait_event(queue: &'static WaitQueue, condition: impl FnMut() -> bool) -> bool {
+    queue.wait_until(condition)
+}
+
+/// [`wait_event`] that a signal ends early
+pub fn wait_event_interruptible(queue: &'static WaitQueue, condition: impl FnMut() -> bool) -> Result<(), WaitError> {
+    wait_event_timeout(queue, Duration::MAX, condition)
+}
+
+/// [`wait_event_interruptible`], giving up after `timeout`
+///
+/// The condition is checked first, so a zero timeout just checks it.
+pub fn wait_event_timeout(
+    queue: &'static WaitQueue,
+    timeout: Duration,
+    mut condition: impl FnMut() -> bool,
+) -> Result<(), WaitError> {
+    let poller = Poller::current().ok_or(WaitError::NoTask)?.interruptible();
+    let deadline = Instant::now().saturating_add(timeout);
+    loop {
+        if condition() {
+            return Ok(());
+        }
+        if poller.interrupted() {
+            return Err(WaitError::Interrupted);
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(WaitError::TimedOut);
+        }
+        if !poller.wait(core::iter::once(queue), left, &mut condition) {
+            return Err(WaitError::QueueFull);
+        }
+    }
+}
+
+/// Put the current task to sleep for `duration`
+///
+/// Sleeps the whole `duration` unless a signal is pending for the task.
+pub fn sleep(duration: Duration) -> Result<(), WaitError> {
+    let poller = Poller::current().ok_or(WaitError::NoTask)?.interruptible();
+    let deadline = Instant::now().saturating_add(duration);
+    loop {
+        if poller.interrupted() {
+            return Err(WaitError::Interrupted);
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(());
+        }
+        poller.wait(core::iter::empty(), left, || false);
+    }
+}
+
+crate::kernel_test! {
+    /// wake_one wakes the highest priority waiter first, and the one that
+    /// came first among equals, leaving the others queued
+    fn wait_queue_wake_one_order() {
+        use super::priority::TaskPriority;
+        use super::task::TaskState;
+
+        static QUEUE: WaitQueue = WaitQueue::new();
+        let parked = super::ktest_parked_task;
+        let waiter = |name, priority| -> Result<_, &'static str> {
+            let id = super::spawn_task(name, parked, priority).map_err(|_| "spawn_task failed")?;
+            // Asleep as a real waiter would be, so the wake-up takes
+            super::get_task(id).ok_or("spawned task missing")?.state = TaskState::Sleeping;
+            crate::ktest_assert!(QUEUE.add(id), "queue full");
+            Ok(id)
+        };
+        let first_normal = waiter("ktest_wait_normal1", TaskPriority::Normal)?;
+        let high = waiter("ktest_wait_high", TaskPriority::High)?;
+        let second_normal = waiter("ktest_wait_normal2", TaskPriority::Normal)?;
+
+        for expected in [high, first_normal, second_normal] {
+            crate::ktest_assert!(QUEUE.wake_one(), "no waiter woken");
+            crate::ktest_assert!(!QUEUE.contains(expected), "wrong waiter woken");
+            let state = super::get_task_by_id(expected).map(|task| task.state);
+            crate::ktest_assert_eq!(state, Some(TaskState::Ready), "woken task not ready");
+        }
+        crate::ktest_assert!(!QUEUE.wake_one(), "woke a task from an empty queue");
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Work queues: deferred work for interrupt handlers
+//!
+//! An interrupt handler that has more to do than it should do with
+//! interrupts off queues a [`Work`] item instead; a kernel worker task runs
+//! it soon after, with interrupts enabled, where it may take ordinary locks
+//! and block. A work item is a static function plus a pending flag, so
+//! queueing one allocates nothing and an item queued again before it runs
+//! runs once.
+//!
+//! [`SYSTEM`] is the shared queue. A subsystem whose work must not wait
+//! behind everyone else's declares its own [`WorkQueue`] and starts a
+//! worker for it with [`start`]. Each queue counts what went through it;
+//! `/proc/workqueues` shows the counts of every started queue.
+
+use crate::sched::priority::TaskPriority;
+use crate::sched::task::TaskId;
+use crate::sync::{MpscQueue, SpinLock};
+use crate::time::Duration;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Work items a queue holds before it drops new ones
+const QUEUE_LEN: usize = 64;
+
+/// Work queues that can be started
+const MAX_QUEUES: usize = 8;
+
+/// Longest a worker sleeps; covers a wakeup lost because the task table
+/// was locked when the work was queued
+const WAKE_FALLBACK: Duration = Duration::from_millis(100);
+
+/// A function to run later from a worker task
+pub struct Work {
+    func: fn(),
+    /// Queued and not started yet
+    pending: AtomicBool,
+}
+
+impl Work {
+    pub const fn new(func: fn()) -> Self {
+        Self {
+            func,
+            pending: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the item is queued and has not started running
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+}
+
+/// Work items and the worker task that runs them
+pub struct WorkQueue {
+    name: &'static str,
+    items: MpscQueue<&'static Work, QUEUE_LEN>,
+    /// Worker task (0 until it has started)
+    worker: AtomicUsize,
+    /// Items queued, and items run
+    queued: AtomicU64,
+    completed: AtomicU64,
+    /// Most items waiting at once
+    max_depth: AtomicUsize,
+}
+
+impl WorkQueue {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            items: MpscQueue::new(),
+            worker: AtomicUsize::new(0),
+            queued: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            max_depth: AtomicUsize::new(0),
+        }
+    }
+
+    /// Run `work` on this queue's worker
+    ///
+    /// Safe in interrupt context. Returns false if the item was already
+    /// pending, or dropped because the queue is full.
+    pub fn schedule(&self, work: &'static Work) -> bool {
+        if work.pending.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        if !self.items.push(work) {
+            work.pending.store(false, Ordering::Release);
+            return false;
+        }
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.max_depth.fetch_max(self.items.len(), Ordering::Relaxed);
+
+        let worker = self.worker.load(Ordering::Acquire);
+        if worker != 0 {
+            crate::sched::try_wake_task(worker);
+        }
+        true
+    }
+
+    /// Run the queued items; returns how many ran
+    fn run_pending(&self) -> usize {
+        let mut ran = 0;
+        while let Some(work) = self.items.pop() {
+            // Cleared first, so the item can be queued again while it runs
+            work.pending.store(false, Ordering::Release);
+            (work.func)();
+            self.completed.fetch_add(1, Ordering::Relaxed);
+            ran += 1;
+        }
+        ran
+    }
+
+    /// Worker task body
+    fn work(&self) -> ! {
+        if let Some((id, _)) = crate::sched::get_current_task_info() {
+            self.worker.store(id, Ordering::Release);
+        }
+        loop {
+            self.run_pending();
+            if let Some((_, priority)) = crate::sched::get_current_task_info() {
+                crate::sched::sleep_current_task(WAKE_FALLBACK, priority);
+                // Work may have been queued before we went to sleep
+                if !self.items.is_empty() && crate::sched::cancel_wait() {
+                    continue;
+                }
+            }
+            crate::sched::yield_now();
+        }
+    }
+}
+
+/// The shared work queue
+pub static SYSTEM: WorkQueue = WorkQueue::new("events");
+
+/// Run `work` on the shared queue; see [`WorkQueue::schedule`]
+pub fn schedule(work: &'static Work) -> bool {
+    SYSTEM.schedule(work)
+}
+
+/// Started queues, for `/proc/workqueues`
+static QUEUES: SpinLock<[Option<&'static WorkQueue>; MAX_QUEUES]> = SpinLock::new([None; MAX_QUEUES]);
+
+/// Queues whose worker task has been spawned but has not picked them up
+static STARTING: MpscQueue<&'static WorkQueue, MAX_QUEUES> = MpscQueue::new();
+
+/// Spawn the worker task of `queue`
+///
+/// Work queued before the worker first runs waits for it. Fails with
+/// `TooManyTasks` once `MAX_QUEUES` queues have been started.
+pub fn start(queue: &'static WorkQueue, priority: TaskPriority) -> crate::sched::task::SchedulerResult<TaskId> {
+    {
+        let mut queues = QUEUES.lock();
+        let slot = queues.iter_mut().find(|slot| slot.is_none());
+        let Some(slot) = slot else {
+            return Err(crate::sched::task::SchedulerError::TooManyTasks);
+        };
+        *slot = Some(queue);
+    }
+    STARTING.push(queue);
+    Ok(crate::sched::spawn_task(queue.name, worker_task, priority)?)
+}
+
+/// Entry point of worker tasks: serve the next queue waiting for a worker
+fn worker_task() -> ! {
+    match STARTING.pop() {
+        Some(queue) => queue.work(),
+        None => panic!("[WORKQUEUE] Worker started without a queue"),
+    }
+}
+
+/// Write one line per started queue: name, items waiting, most waiting
+/// at once, items queued, run and dropped
+pub fn write_stats(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(out, "queue      depth max_depth     queued  completed dropped")?;
+    for queue in QUEUES.lock().iter().flatten() {
+        writeln!(
+            out,
+            "{:<10} {:>5} {:>9} {:>10} {:>10} {:>7}",
+            queue.name,
+            queue.items.len(),
+            queue.max_depth.load(Ordering::Relaxed),
+            queue.queued.load(Ordering::Relaxed),
+            queue.completed.load(Ordering::Relaxed),
+            queue.items.overflows()
+        )?;
+    }
+    Ok(())
+}
+
+crate::kernel_test! {
+    /// An item queued twice before it runs runs once, and can be queued
+    /// again afterwards
+    fn workqueue_coalesces_pending_work() {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        static WORK: Work = Work::new(|| {
+            RUNS.fetch_add(1, Ordering::Relaxed);
+        });
+        static QUEUE: WorkQueue = WorkQueue::new("test");
+
+        crate::ktest_assert!(QUEUE.schedule(&WORK), "first schedule refused");
+        crate::ktest_assert!(!QUEUE.schedule(&WORK), "pending item queued twice");
+        crate::ktest_assert!(WORK.is_pending(), "queued item not pending");
+        crate::ktest_assert_eq!(QUEUE.run_pending(), 1, "items run");
+        crate::ktest_assert_eq!(RUNS.load(Ordering::Relaxed), 1, "work function runs");
+        crate::ktest_assert!(!WORK.is_pending(), "item still pending after running");
+
+        crate::ktest_assert!(QUEUE.schedule(&WORK), "item not queued again after running");
+        QUEUE.run_pending();
+        crate::ktest_assert_eq!(QUEUE.completed.load(Ordering::Relaxed), 2, "completed count");
+        crate::ktest_assert_eq!(QUEUE.max_depth.load(Ordering::Relaxed), 1, "max depth");
+        Ok(())
+    }
+}
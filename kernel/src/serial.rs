@@ -5,11 +5,19 @@ use spin::Mutex;
 use x86_64::instructions::port::Port;
 
 /// COM1 serial port base address
-const SERIAL_PORT: u16 = 0x3F8;
+pub const SERIAL_PORT: u16 = 0x3F8;
 
 /// Global serial port instance
 pub static SERIAL: Mutex<SerialPort> = Mutex::new(SerialPort::new(SERIAL_PORT));
 
+/// What the receiver of a serial port held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Received {
+    Byte(u8),
+    /// A break: the line held low for longer than a character
+    Break,
+}
+
 /// Serial port structure
 pub struct SerialPort {
     base: u16,
@@ -40,6 +48,15 @@ impl SerialPort {
         }
     }
 
+    /// Raise an interrupt whenever a byte is received
+    pub fn enable_receive_interrupt(&mut self) {
+        unsafe {
+            // Received Data Available is bit 0 of the interrupt enable
+            // register; OUT2, set by `init`, gates the line to the PIC
+            Port::new(self.base + 1).write(0x01u8);
+        }
+    }
+
     /// Write a byte to the serial port
     pub fn write_byte(&mut self, byte: u8) {
         unsafe {
@@ -51,6 +68,23 @@ impl SerialPort {
         }
     }
 
+    /// Take what the receiver holds, if anything
+    pub fn receive(&mut self) -> Option<Received> {
+        // Data Ready is bit 0 of the line status register, Break
+        // Interrupt bit 4; a break also leaves a 0 byte to read
+        let mut line_status = Port::<u8>::new(self.base + 5);
+        let status = unsafe { line_status.read() };
+        if status & 0x01 == 0 {
+            return None;
+        }
+        let byte = unsafe { Port::<u8>::new(self.base).read() };
+        if status & 0x10 != 0 {
+            Some(Received::Break)
+        } else {
+            Some(Received::Byte(byte))
+        }
+    }
+
     /// Write a string to the serial port
     pub fn write_string(&mut self, s: &str) {
         for byte in s.bytes() {
@@ -66,7 +100,10 @@ impl fmt::Write for SerialPort {
     }
 }
 
-/// Print to serial port (for debugging)
+/// Print to the kernel console (for debugging)
+///
+/// Despite the name, output goes through the console multiplexer and so
+/// reaches the serial port, the framebuffer, or both depending on `console=`.
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => {
@@ -74,7 +111,7 @@ macro_rules! serial_print {
     };
 }
 
-/// Print to serial port with newline (for debugging)
+/// Print to the kernel console with newline (for debugging)
 #[macro_export]
 macro_rules! serial_println {
     () => ($crate::serial_print!("\n"));
@@ -83,6 +120,5 @@ macro_rules! serial_println {
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    use core::fmt::Write;
-    SERIAL.lock().write_fmt(args).unwrap();
+    crate::console::_print(args);
 }
@@ -0,0 +1,309 @@
+/// Persistent kernel settings
+/// Keeps the log level and console layout on a block device so they survive
+/// reboots on test rigs, selected with `settings=<blockdev>`.
+///
+/// The settings live in the last two blocks of the device, used as a
+/// two-slot journal: each save writes a record with the next sequence
+/// number to the slot the newest valid record is not in, so a torn write
+/// leaves the previous settings intact. A record is a header (magic,
+/// sequence, length, checksum) followed by `key=value` lines:
+///
+/// ```text
+/// loglevel=DEBUG
+/// console=both
+/// fbrotate=90
+/// fbscale=2
+/// ```
+///
+/// At boot the newest valid record is applied, except for what the command
+/// line sets (`loglevel=`, `console=`, `fbrotate=`, `fbscale=`); the result is written back if it
+/// changed, since rigs are usually reset rather than shut down. [`flush`]
+/// saves settings changed at runtime and is meant for the shutdown path.
+use crate::console::{self, ConsoleMode};
+use crate::dev::api::block::BlockDevice;
+use crate::framebuffer::Rotation;
+use crate::log::{self, LogLevel};
+use core::fmt::Write;
+use spin::Mutex;
+
+/// Record magic ("MSET")
+const MAGIC: [u8; 4] = *b"MSET";
+
+/// Record header: magic, sequence (u64), payload length (u32), checksum (u32)
+const HEADER_LEN: usize = 20;
+
+/// Largest block size supported for the settings region
+const MAX_BLOCK_SIZE: usize = 4096;
+
+/// Journal slots at the end of the device
+const SLOTS: u64 = 2;
+
+/// Settings that persist across reboots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub log_level: LogLevel,
+    pub console: ConsoleMode,
+    pub rotation: Rotation,
+    pub font_scale: usize,
+}
+
+impl Settings {
+    /// Settings in effect now
+    pub fn current() -> Self {
+        let (rotation, font_scale) = console::layout();
+        Self { log_level: log::get_log_level(), console: console::mode(), rotation, font_scale }
+    }
+
+    fn apply(&self) {
+        log::set_log_level(self.log_level);
+        console::set_mode(self.console);
+        if console::layout() != (self.rotation, self.font_scale) {
+            console::set_layout(self.rotation, self.font_scale);
+        }
+    }
+
+    /// `key=value` lines; returns the number of bytes written
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        let mut writer = BufWriter { buf, pos: 0 };
+        let _ = write!(
+            writer,
+            "loglevel={}\nconsole={}\nfbrotate={}\nfbscale={}\n",
+            self.log_level.as_str(),
+            self.console.name(),
+            self.rotation.degrees(),
+            self.font_scale
+        );
+        writer.pos
+    }
+
+    /// Settings from `key=value` lines, starting from `self`; unknown keys
+    /// and values are skipped
+    fn decode(mut self, text: &str) -> Self {
+        for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+            match key {
+                "loglevel" => self.log_level = LogLevel::parse(value).unwrap_or(self.log_level),
+                "console" => self.console = ConsoleMode::parse(value).unwrap_or(self.console),
+                "fbrotate" => self.rotation = Rotation::parse(value).unwrap_or(self.rotation),
+                "fbscale" => self.font_scale = console::parse_font_scale(value).unwrap_or(self.font_scale),
+                _ => {}
+            }
+        }
+        self
+    }
+}
+
+struct BufWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Write for BufWriter<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let bytes = s.as_bytes();
+        let to_write = bytes.len().min(self.buf.len() - self.pos);
+        self.buf[self.pos..self.pos + to_write].copy_from_slice(&bytes[..to_write]);
+        self.pos += to_write;
+        Ok(())
+    }
+}
+
+/// Where the settings are stored, and what was last written there
+struct Store {
+    device: &'static dyn BlockDevice,
+    /// Newest valid record: sequence number and settings
+    saved: Option<(u64, Settings)>,
+}
+
+static STORE: Mutex<Option<Store>> = Mutex::new(None);
+
+/// Block buffer, kept off the (8 KiB) task stacks
+static BLOCK: Mutex<[u8; MAX_BLOCK_SIZE]> = Mutex::new([0; MAX_BLOCK_SIZE]);
+
+/// FNV-1a over the sequence number and payload
+fn checksum(sequence: u64, payload: &[u8]) -> u32 {
+    sequence
+        .to_le_bytes()
+        .iter()
+        .chain(payload)
+        .fold(0x811c_9dc5u32, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
+
+/// Block of journal slot `slot`
+fn slot_lba(device: &dyn BlockDevice, slot: u64) -> u64 {
+    device.block_count() - SLOTS + slot
+}
+
+/// Whether `device` can hold the settings region
+fn usable(device: &dyn BlockDevice) -> bool {
+    (512..=MAX_BLOCK_SIZE).contains(&device.block_size()) && device.block_count() >= SLOTS
+}
+
+/// Newest valid record on `device`
+fn read_journal(device: &dyn BlockDevice) -> Option<(u64, Settings)> {
+    let mut block = BLOCK.lock();
+    let block = &mut block[..device.block_size()];
+    let mut newest: Option<(u64, Settings)> = None;
+    for slot in 0..SLOTS {
+        if device.read_blocks(slot_lba(device, slot), block).is_err() || block[0..4] != MAGIC {
+            continue;
+        }
+        let sequence = u64::from_le_bytes(block[4..12].try_into().unwrap_or_default());
+        let len = u32::from_le_bytes(block[12..16].try_into().unwrap_or_default()) as usize;
+        let sum = u32::from_le_bytes(block[16..20].try_into().unwrap_or_default());
+        let Some(payload) = block.get(HEADER_LEN..HEADER_LEN + len) else { continue };
+        if checksum(sequence, payload) != sum || newest.map_or(false, |(newest, _)| newest >= sequence) {
+            continue;
+        }
+        let Ok(text) = core::str::from_utf8(payload) else { continue };
+        newest = Some((sequence, Settings::current().decode(text)));
+    }
+    newest
+}
+
+/// Write `settings` as record `sequence`, into the slot it owns
+fn write_record(device: &dyn BlockDevice, sequence: u64, settings: &Settings) -> bool {
+    let mut block = BLOCK.lock();
+    let block = &mut block[..device.block_size()];
+    block.fill(0);
+    let len = settings.encode(&mut block[HEADER_LEN..]);
+    let sum = checksum(sequence, &block[HEADER_LEN..HEADER_LEN + len]);
+    block[0..4].copy_from_slice(&MAGIC);
+    block[4..12].copy_from_slice(&sequence.to_le_bytes());
+    block[12..16].copy_from_slice(&(len as u32).to_le_bytes());
+    block[16..20].copy_from_slice(&sum.to_le_bytes());
+    device.write_blocks(slot_lba(device, sequence % SLOTS), block).is_ok()
+}
+
+/// Save the current settings if they differ from the stored ones
+///
+/// Returns false if there is no settings device or the write failed.
+pub fn flush() -> bool {
+    flush_store(&mut STORE.lock())
+}
+
+/// [`flush`] unless another flush is in progress (None)
+///
+/// For interrupt context, where waiting for the lock could deadlock.
+pub fn try_flush() -> Option<bool> {
+    let mut store = STORE.try_lock()?;
+    Some(flush_store(&mut store))
+}
+
+fn flush_store(store: &mut Option<Store>) -> bool {
+    let Some(store) = store.as_mut() else { return false };
+    let current = Settings::current();
+    if store.saved.map_or(false, |(_, saved)| saved == current) {
+        return true;
+    }
+    let sequence = store.saved.map_or(1, |(sequence, _)| sequence + 1);
+    if !write_record(store.device, sequence, &current) {
+        crate::log_warn!("SETTINGS", "{}: write failed", store.device.name());
+        return false;
+    }
+    store.saved = Some((sequence, current));
+    true
+}
+
+/// Apply the stored settings and the command line, and save the result
+///
+/// Must run after the block drivers have registered their devices.
+pub fn init() {
+    let mut settings = Settings::current();
+    let mut saved = None;
+
+    let device = crate::cmdline::value("settings").and_then(|name| {
+        let device = crate::dev::api::block::find_block_device(name);
+        match device {
+            Some(device) if usable(device) => Some(device),
+            Some(_) => {
+                crate::log_warn!("SETTINGS", "{}: unsupported block size", name);
+                None
+            }
+            None => {
+                crate::log_warn!("SETTINGS", "{}: no such block device", name);
+                None
+            }
+        }
+    });
+    if let Some(device) = device {
+        saved = read_journal(device);
+        if let Some((sequence, stored)) = saved {
+            crate::log_info!("SETTINGS", "{}: loaded record {}", device.name(), sequence);
+            settings = stored;
+        }
+    }
+
+    // The command line wins over stored settings
+    if let Some(level) = crate::cmdline::value("loglevel").and_then(LogLevel::parse) {
+        settings.log_level = level;
+    }
+    if let Some(mode) = crate::cmdline::value("console").and_then(ConsoleMode::parse) {
+        settings.console = mode;
+    }
+    if let Some(rotation) = crate::cmdline::value("fbrotate").and_then(Rotation::parse) {
+        settings.rotation = rotation;
+    }
+    if let Some(scale) = crate::cmdline::value("fbscale").and_then(console::parse_font_scale) {
+        settings.font_scale = scale;
+    }
+    settings.apply();
+
+    if let Some(device) = device {
+        *STORE.lock() = Some(Store { device, saved });
+        flush();
+    }
+}
+crate::initcall!(Late, init);
+
+crate::kernel_test! {
+    /// The newest intact journal record wins; a torn one falls back
+    fn settings_journal() {
+        struct RamDisk(Mutex<[u8; 4 * 512]>);
+
+        impl BlockDevice for RamDisk {
+            fn name(&self) -> &'static str {
+                "ram-settings"
+            }
+            fn block_size(&self) -> usize {
+                512
+            }
+            fn block_count(&self) -> u64 {
+                4
+            }
+            fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> crate::dev::api::DriverResult<()> {
+                let start = lba as usize * 512;
+                buf.copy_from_slice(&self.0.lock()[start..start + buf.len()]);
+                Ok(())
+            }
+            fn write_blocks(&self, lba: u64, buf: &[u8]) -> crate::dev::api::DriverResult<()> {
+                let start = lba as usize * 512;
+                self.0.lock()[start..start + buf.len()].copy_from_slice(buf);
+                Ok(())
+            }
+        }
+
+        static DISK: RamDisk = RamDisk(Mutex::new([0; 4 * 512]));
+        let first = Settings {
+            log_level: LogLevel::Debug,
+            console: ConsoleMode::Both,
+            rotation: Rotation::Clockwise,
+            font_scale: 2,
+        };
+        let second = Settings {
+            log_level: LogLevel::Warn,
+            console: ConsoleMode::Serial,
+            rotation: Rotation::Normal,
+            font_scale: 1,
+        };
+
+        crate::ktest_assert!(read_journal(&DISK).is_none(), "blank disk has a record");
+        crate::ktest_assert!(write_record(&DISK, 1, &first), "write 1 failed");
+        crate::ktest_assert!(write_record(&DISK, 2, &second), "write 2 failed");
+        crate::ktest_assert_eq!(read_journal(&DISK), Some((2, second)), "newest record");
+
+        // Tear record 2 (slot 0, block 2): record 1 is still there
+        DISK.0.lock()[2 * 512 + HEADER_LEN] ^= 0xff;
+        crate::ktest_assert_eq!(read_journal(&DISK), Some((1, first)), "fallback record");
+        Ok(())
+    }
+}
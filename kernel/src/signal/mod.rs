@@ -220,8 +220,11 @@ pub fn send_signal_to_task(task: &crate::sched::task::Task, signal: Signal) -> b
         return false;
     }
 
-    // Add signal to pending set atomically
-    task.add_pending_signal(signal)
+    // Add signal to pending set atomically, then cut an interruptible
+    // wait short
+    let added = task.add_pending_signal(signal);
+    crate::sched::interrupt_task(task.id);
+    added
 }
 
 /// Send a signal to a process group
@@ -265,6 +268,7 @@ pub fn send_signal(task: &mut crate::sched::task::Task, signal: Signal) -> Resul
     if signal == SIGKILL || signal == SIGSTOP {
         // These signals are always delivered immediately
         task.add_pending_signal(signal);
+        crate::sched::interrupt_task(task.id);
         return Ok(());
     }
 
@@ -279,12 +283,53 @@ pub fn send_signal(task: &mut crate::sched::task::Task, signal: Signal) -> Resul
 
     // Add signal to pending set
     if task.add_pending_signal(signal) {
+        crate::sched::interrupt_task(task.id);
         Ok(())
     } else {
         Err(())
     }
 }
 
+/// End the current task if SIGKILL is pending for it
+///
+/// Called by the timer interrupt when it interrupted user mode, where no
+/// kernel lock can be held. Other signals are not delivered yet, but a
+/// task spinning in user mode can still be killed (by SysRq or `kill -9`).
+/// The exit status is 128 + SIGKILL, as a shell reports it.
+pub fn exit_if_killed() {
+    let Some((task_id, _)) = crate::sched::get_current_task_info() else { return };
+    let Some(task) = crate::sched::get_task_by_id(task_id) else { return };
+    let pending = task.pending_signals.load(core::sync::atomic::Ordering::Acquire);
+    if pending & (1 << signals::SIGKILL) != 0 {
+        crate::arch::x86_64::syscall::exit_current(128 + signals::SIGKILL as usize);
+    }
+}
+
+/// Whether a signal pending for `task` ends an interruptible wait
+///
+/// SIGKILL always does. Another signal does unless it is blocked or would
+/// only be ignored.
+pub fn interrupts_wait(task: &crate::sched::task::Task) -> bool {
+    let pending = task.pending_signals.load(core::sync::atomic::Ordering::Acquire);
+    if pending & (1 << signals::SIGKILL) != 0 {
+        return true;
+    }
+    let mut unblocked = pending & !task.get_signal_mask();
+    while unblocked != 0 {
+        let signal = unblocked.trailing_zeros();
+        unblocked &= unblocked - 1;
+        let ignored = match task.signal_handlers.get(signal as usize).map(|action| action.handler) {
+            Some(SigHandler::Ignore) => true,
+            Some(SigHandler::Default) => default_action(signal) == DefaultAction::Ignore,
+            _ => false,
+        };
+        if !ignored {
+            return true;
+        }
+    }
+    false
+}
+
 /// Deliver pending signals to the current task
 ///
 /// This function is called when returning to userspace from a syscall or interrupt.
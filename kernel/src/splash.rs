@@ -0,0 +1,93 @@
+//! Boot logo
+//!
+//! A melon with an "M", drawn on the framebuffer at startup. The picture is
+//! written out below as characters and turned into RGBA pixels, enlarged
+//! `SCALE` times, while compiling.
+
+use crate::framebuffer::{Framebuffer, Surface};
+
+/// Side of the logo as drawn, in characters of `ART`
+const ART_SIZE: usize = 32;
+
+/// Each character of `ART` becomes a square of this many pixels
+const SCALE: usize = 3;
+
+/// Side of the logo on screen, in pixels
+const SIZE: usize = ART_SIZE * SCALE;
+
+/// Color of the line under the logo (0xRRGGBB)
+const UNDERLINE_COLOR: u32 = 0x4CAF50;
+
+/// `.` transparent, `:` edge, `#` flesh, `+` shine, `o` letter, `v` leaf
+const ART: [&str; ART_SIZE] = [
+    "................................",
+    "...............vv...............",
+    "...............vv.vvvv..........",
+    "...............vvvvvvvv.........",
+    ".............::vv:vvvv..........",
+    "..........::########::..........",
+    "........::############::........",
+    ".......:################:.......",
+    "......:#++###############:......",
+    ".....:#++++###############:.....",
+    "....:#++++++###############:....",
+    "....:#++++++###############:....",
+    "...:###++++#################:...",
+    "...:####++##################:...",
+    "...########oo######oo########...",
+    "..:########oo######oo########:..",
+    "..:########oooo##oooo########:..",
+    "..:########oooo##oooo########:..",
+    "..:########oo##oo##oo########:..",
+    "..:########oo##oo##oo########:..",
+    "..:########oo######oo########:..",
+    "...########oo######oo########...",
+    "...:#######oo######oo#######:...",
+    "...:#######oo######oo#######:...",
+    "....:######################:....",
+    "....:######################:....",
+    ".....:####################:.....",
+    "......:##################:......",
+    ".......:################:.......",
+    "........::############::........",
+    "..........::########::..........",
+    ".............::::::.............",
+];
+
+static PIXELS: [u32; SIZE * SIZE] = pixels();
+
+/// 0xAARRGGBB color of an `ART` character
+const fn color(ch: u8) -> u32 {
+    match ch {
+        b':' => 0x80F4_A259,
+        b'#' => 0xFFF4_A259,
+        b'+' => 0xFFFA_D7A0,
+        b'o' => 0xFF5C_3D1E,
+        b'v' => 0xFF4C_AF50,
+        _ => 0,
+    }
+}
+
+const fn pixels() -> [u32; SIZE * SIZE] {
+    let mut pixels = [0; SIZE * SIZE];
+    let mut y = 0;
+    while y < SIZE {
+        let row = ART[y / SCALE].as_bytes();
+        assert!(row.len() == ART_SIZE, "logo rows must be ART_SIZE characters");
+        let mut x = 0;
+        while x < SIZE {
+            pixels[y * SIZE + x] = color(row[x / SCALE]);
+            x += 1;
+        }
+        y += 1;
+    }
+    pixels
+}
+
+/// Draw the logo centered, a third of the way down the screen
+pub fn draw(fb: &mut Framebuffer) {
+    let x = fb.width().saturating_sub(SIZE) / 2;
+    let y = fb.height().saturating_sub(SIZE) / 3;
+    fb.blit(&Surface { width: SIZE, height: SIZE, pixels: &PIXELS }, x, y);
+    fb.fill_rect(x, y + SIZE + SCALE * 2, SIZE, SCALE, UNDERLINE_COLOR);
+}
@@ -11,20 +11,19 @@
 //! 2. **SESSION_TABLE** - Global session table
 //! 3. **PROCESS_GROUP_TABLE** - Global process group table
 //! 4. **PORT_MANAGER.table_lock** - Port creation/deletion
-//! 5. **TASK_TABLE** - Task table access
-//! 6. **SCHED** - Scheduler state
-//! 7. **Per-CPU runqueue locks** - Must be acquired in CPU ID order (lower ID first)
-//! 8. **Session locks** - Individual session state
-//! 9. **Process group locks** - Individual process group state
-//! 10. **Task locks** - Individual task state (implicit in get_task_mut)
-//! 11. **PTY pair locks** - Individual PTY pair operations
-//! 12. **Per-port locks** - Individual port operations
+//! 5. **TASK_TABLE_WRITER / WAIT_STATE** - Task table updates, sleep/wake transitions
+//! 6. **Per-CPU runqueue locks** - Must be acquired in CPU ID order (lower ID first)
+//! 7. **Session locks** - Individual session state
+//! 8. **Process group locks** - Individual process group state
+//! 9. **Task locks** - Individual task state (implicit in get_task_mut)
+//! 10. **PTY pair locks** - Individual PTY pair operations
+//! 11. **Per-port locks** - Individual port operations
 //!
 //! # Lock Ordering Rules
 //!
 //! ## Rule 1: Global before Per-Object
 //! Always acquire global locks (PTY_TABLE, SESSION_TABLE, PROCESS_GROUP_TABLE,
-//! PORT_MANAGER, TASK_TABLE, SCHED) before per-object locks (session locks,
+//! PORT_MANAGER, TASK_TABLE_WRITER) before per-object locks (session locks,
 //! process group locks, task locks, PTY pair locks, port locks).
 //!
 //! ## Rule 2: CPU ID Ordering
@@ -66,11 +65,10 @@
 //!
 //! ## Pattern 1: Task Creation
 //! ```rust,ignore
-//! let mut sched = SCHED.lock();
-//! let mut task_table = TASK_TABLE.lock();
-//! // ... create task ...
-//! drop(task_table);
-//! drop(sched);
+//! // Copies the task table, reserves a free slot and publishes the copy
+//! let task_id = reserve_task_slot()?;
+//! // ... create task, then publish another copy with the task in its slot ...
+//! publish_task(task_id, Some(task_ptr))?;
 //! enqueue_task(task_id, None); // Acquires per-CPU runqueue lock
 //! ```
 //!
@@ -132,12 +130,11 @@
 //! let pg = &pg_table.groups[pgid];
 //! 
 //! // Iterate over processes and send signal
+//! // Task lookups read the RCU-published task table and take no lock
 //! for pid in pg.iter() {
-//!     let task_table = TASK_TABLE.lock();
-//!     if let Some(task) = task_table.get_mut(pid) {
+//!     if let Some(task) = get_task_mut(pid) {
 //!         task.pending_signals.fetch_or(1 << signal, Ordering::SeqCst);
 //!     }
-//!     drop(task_table);
 //! }
 //! ```
 //!
@@ -157,6 +154,9 @@
 //! - CPU ID ordering in migrate_task()
 //! - No nested port locks
 //! - Preemption disabled when required
+//!
+//! Building with `--features lockdep` checks the actual acquisition order of
+//! static `SpinLock`s at run time (see `sync::lockdep`).
 
 use core::sync::atomic::{AtomicBool, Ordering};
 
@@ -180,10 +180,6 @@ static PORT_TABLE_LOCK_HELD: AtomicBool = AtomicBool::new(false);
 #[cfg(debug_assertions)]
 static TASK_TABLE_LOCK_HELD: AtomicBool = AtomicBool::new(false);
 
-/// Global flag to track if SCHED is held (debug only)
-#[cfg(debug_assertions)]
-static SCHED_LOCK_HELD: AtomicBool = AtomicBool::new(false);
-
 /// Assert that no global locks are held
 ///
 /// This should be called before acquiring per-object locks to verify
@@ -210,10 +206,6 @@ pub fn assert_no_global_locks_held() {
         !TASK_TABLE_LOCK_HELD.load(Ordering::Relaxed),
         "TASK_TABLE is held - violates lock ordering"
     );
-    debug_assert!(
-        !SCHED_LOCK_HELD.load(Ordering::Relaxed),
-        "SCHED is held - violates lock ordering"
-    );
 }
 
 /// Assert that CPU IDs are in ascending order
@@ -254,18 +246,6 @@ pub fn mark_task_table_lock_released() {
     TASK_TABLE_LOCK_HELD.store(false, Ordering::Relaxed);
 }
 
-/// Mark SCHED as acquired (debug only)
-#[cfg(debug_assertions)]
-pub fn mark_sched_lock_acquired() {
-    SCHED_LOCK_HELD.store(true, Ordering::Relaxed);
-}
-
-/// Mark SCHED as released (debug only)
-#[cfg(debug_assertions)]
-pub fn mark_sched_lock_released() {
-    SCHED_LOCK_HELD.store(false, Ordering::Relaxed);
-}
-
 /// Mark PTY_TABLE as acquired (debug only)
 #[cfg(debug_assertions)]
 pub fn mark_pty_table_lock_acquired() {
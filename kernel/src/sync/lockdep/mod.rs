@@ -0,0 +1,72 @@
+//! Lock dependency validator (`lockdep` feature)
+//!
+//! Built with `--features lockdep`, every [`SpinLock`](super::SpinLock)
+//! and [`IrqSpinLock`](super::IrqSpinLock) in static memory reports its
+//! acquisitions here. Each such lock is its own lock class. The tracker
+//! records which classes were held when another was acquired and panics,
+//! printing the stack that established the conflicting order and the
+//! current one, when:
+//!
+//! - a lock is acquired while holding one that was previously acquired
+//!   after it (directly or through other locks), a potential ABBA deadlock
+//! - a lock already held is acquired again
+//! - a lock is taken in interrupt context and elsewhere with interrupts
+//!   enabled, so the interrupt can land while its holder spins on it
+//!
+//! Locks on the heap are not tracked: their addresses are reused, so they
+//! would not name one lock. `spin::Mutex` is not instrumented.
+//!
+//! Locks held are tracked per CPU and moved with the task at each context
+//! switch. Interrupt handlers bracket their work with [`irq_enter`] and
+//! [`irq_exit`]. Without the feature all hooks compile to nothing.
+
+#[cfg(feature = "lockdep")]
+mod tracker;
+
+use crate::sched::task::TaskId;
+
+/// A blocking acquisition of the lock at `lock` is about to spin
+#[inline]
+pub fn will_lock(lock: usize) {
+    #[cfg(feature = "lockdep")]
+    tracker::will_lock(lock);
+    let _ = lock;
+}
+
+/// The lock at `lock` was acquired
+#[inline]
+pub fn locked(lock: usize) {
+    #[cfg(feature = "lockdep")]
+    tracker::locked(lock);
+    let _ = lock;
+}
+
+/// The lock at `lock` was released
+#[inline]
+pub fn unlocked(lock: usize) {
+    #[cfg(feature = "lockdep")]
+    tracker::unlocked(lock);
+    let _ = lock;
+}
+
+/// An interrupt handler started on this CPU
+#[inline]
+pub fn irq_enter() {
+    #[cfg(feature = "lockdep")]
+    tracker::irq_enter();
+}
+
+/// The interrupt handler started by the matching [`irq_enter`] is done
+#[inline]
+pub fn irq_exit() {
+    #[cfg(feature = "lockdep")]
+    tracker::irq_exit();
+}
+
+/// This CPU switches from task `old` (None on its first switch) to `new`
+#[inline]
+pub fn switch_task(old: Option<TaskId>, new: TaskId) {
+    #[cfg(feature = "lockdep")]
+    tracker::switch_task(old, new);
+    let _ = (old, new);
+}
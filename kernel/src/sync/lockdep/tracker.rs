@@ -0,0 +1,387 @@
+//! Lock class graph and held-lock tracking for [`super`]
+
+use crate::sched::task::{task_slot, TaskId};
+use crate::sched::MAX_TASKS;
+use crate::serial_println;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::interrupts;
+
+/// Lock classes tracked; later locks are ignored
+const MAX_CLASSES: usize = 128;
+/// Locks one context can hold at once
+const MAX_HELD: usize = 16;
+/// Edges whose first acquisition stack is kept
+const MAX_EDGE_TRACES: usize = 256;
+/// Return addresses kept per stack
+const TRACE_DEPTH: usize = 8;
+
+/// Return addresses of a stack, innermost first, 0 past the end
+#[derive(Clone, Copy)]
+struct Trace([u64; TRACE_DEPTH]);
+
+impl Trace {
+    /// Walk the RBP chain of the caller
+    #[inline(always)]
+    fn capture() -> Self {
+        let mut frames = [0; TRACE_DEPTH];
+        let mut rbp: *const u64;
+        unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+        for frame in frames.iter_mut() {
+            if rbp.is_null() || (rbp as u64) < 0x1000 || rbp as usize % 8 != 0 {
+                break;
+            }
+            let (ret_addr, caller_rbp) = unsafe { (rbp.add(1).read(), rbp.read() as *const u64) };
+            *frame = ret_addr;
+            // Caller frames sit higher on the stack; anything else is not a frame
+            if caller_rbp <= rbp {
+                break;
+            }
+            rbp = caller_rbp;
+        }
+        Trace(frames)
+    }
+
+    fn print(&self) {
+        for (i, addr) in self.0.iter().take_while(|&&addr| addr != 0).enumerate() {
+            serial_println!("  #{}: {:#018x}", i, addr);
+        }
+    }
+}
+
+/// Locks held by what runs on a CPU, and its interrupt nesting
+#[derive(Clone, Copy)]
+struct Context {
+    held: [usize; MAX_HELD],
+    depth: usize,
+    irq_depth: usize,
+}
+
+impl Context {
+    const fn new() -> Self {
+        Self {
+            held: [0; MAX_HELD],
+            depth: 0,
+            irq_depth: 0,
+        }
+    }
+
+    fn held(&self) -> &[usize] {
+        &self.held[..self.depth]
+    }
+}
+
+/// A [`Context`] touched only by one CPU at a time, with interrupts off
+struct ContextCell(UnsafeCell<Context>);
+
+unsafe impl Sync for ContextCell {}
+
+impl ContextCell {
+    const fn new() -> Self {
+        Self(UnsafeCell::new(Context::new()))
+    }
+}
+
+crate::per_cpu! {
+    /// What runs on each CPU now
+    static CURRENT: ContextCell = ContextCell::new();
+}
+
+/// Contexts of switched-out tasks, by task table slot
+static SAVED: [ContextCell; MAX_TASKS] = [const { ContextCell::new() }; MAX_TASKS];
+
+/// Run `f` on this CPU's context with interrupts off
+fn with_context<R>(f: impl FnOnce(&mut Context) -> R) -> R {
+    interrupts::without_interrupts(|| f(unsafe { &mut *CURRENT.get().0.get() }))
+}
+
+/// What a lock acquisition got wrong
+enum Violation {
+    /// `lock` is already held
+    Recursive { lock: usize },
+    /// `lock` is acquired holding `held`, but `held` has been acquired
+    /// (through `via`) while holding `lock`; `earlier` is where `lock` was
+    /// held while acquiring `via`
+    Order {
+        held: usize,
+        lock: usize,
+        via: usize,
+        earlier: Option<Trace>,
+    },
+    /// `lock` is taken in interrupt context (`in_irq`) or with interrupts
+    /// enabled outside one, and `earlier` is where it was taken the other way
+    IrqUnsafe {
+        lock: usize,
+        in_irq: bool,
+        earlier: Trace,
+    },
+}
+
+/// Acquisition order seen so far between lock classes
+struct Graph {
+    /// Lock address of each class
+    classes: [usize; MAX_CLASSES],
+    count: usize,
+    /// Bit `j` of `after[i]`: class `j` was acquired holding class `i`
+    after: [u128; MAX_CLASSES],
+    /// Stack of the acquisition that added each edge (from, to)
+    edges: [(u8, u8, Trace); MAX_EDGE_TRACES],
+    edge_count: usize,
+    /// First acquisition of each class in interrupt context
+    in_irq: [Option<Trace>; MAX_CLASSES],
+    /// First acquisition of each class with interrupts enabled, outside one
+    irqs_on: [Option<Trace>; MAX_CLASSES],
+}
+
+impl Graph {
+    const fn new() -> Self {
+        Self {
+            classes: [0; MAX_CLASSES],
+            count: 0,
+            after: [0; MAX_CLASSES],
+            edges: [(0, 0, Trace([0; TRACE_DEPTH])); MAX_EDGE_TRACES],
+            edge_count: 0,
+            in_irq: [None; MAX_CLASSES],
+            irqs_on: [None; MAX_CLASSES],
+        }
+    }
+
+    /// The class of the lock at `lock`, registering it if new
+    fn class_of(&mut self, lock: usize) -> Option<usize> {
+        if let Some(class) = self.classes[..self.count].iter().position(|&addr| addr == lock) {
+            return Some(class);
+        }
+        if self.count == MAX_CLASSES {
+            CLASSES_FULL.store(true, Ordering::Relaxed);
+            return None;
+        }
+        self.classes[self.count] = lock;
+        self.count += 1;
+        Some(self.count - 1)
+    }
+
+    /// Whether `to` can be reached from `from` along recorded edges
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut seen = 0u128;
+        let mut frontier = 1u128 << from;
+        while frontier != 0 {
+            if frontier & (1 << to) != 0 {
+                return true;
+            }
+            seen |= frontier;
+            let mut next = 0;
+            let mut rest = frontier;
+            while rest != 0 {
+                next |= self.after[rest.trailing_zeros() as usize];
+                rest &= rest - 1;
+            }
+            frontier = next & !seen;
+        }
+        false
+    }
+
+    fn edge_trace(&self, from: usize, to: usize) -> Option<Trace> {
+        self.edges[..self.edge_count]
+            .iter()
+            .find(|&&(edge_from, edge_to, _)| edge_from as usize == from && edge_to as usize == to)
+            .map(|&(_, _, trace)| trace)
+    }
+
+    /// Validate acquiring `lock` in `context` and record the new order
+    fn acquire(&mut self, context: &Context, lock: usize, irqs_enabled: bool, trace: Trace) -> Result<(), Violation> {
+        let Some(class) = self.class_of(lock) else { return Ok(()) };
+        if context.held().contains(&lock) {
+            return Err(Violation::Recursive { lock });
+        }
+
+        if context.irq_depth > 0 {
+            self.in_irq[class].get_or_insert(trace);
+            if let Some(earlier) = self.irqs_on[class] {
+                return Err(Violation::IrqUnsafe { lock, in_irq: true, earlier });
+            }
+        } else if irqs_enabled {
+            self.irqs_on[class].get_or_insert(trace);
+            if let Some(earlier) = self.in_irq[class] {
+                return Err(Violation::IrqUnsafe { lock, in_irq: false, earlier });
+            }
+        }
+
+        for &held in context.held() {
+            let Some(from) = self.class_of(held) else { continue };
+            if self.after[from] & (1 << class) != 0 {
+                continue;
+            }
+            let mut successors = self.after[class];
+            while successors != 0 {
+                let via = successors.trailing_zeros() as usize;
+                successors &= successors - 1;
+                if via == from || self.reaches(via, from) {
+                    return Err(Violation::Order {
+                        held,
+                        lock,
+                        via: self.classes[via],
+                        earlier: self.edge_trace(class, via),
+                    });
+                }
+            }
+            self.after[from] |= 1 << class;
+            if self.edge_count < MAX_EDGE_TRACES {
+                self.edges[self.edge_count] = (from as u8, class as u8, trace);
+                self.edge_count += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+static GRAPH: spin::Mutex<Graph> = spin::Mutex::new(Graph::new());
+
+/// Set once a violation is reported, so the report's own locking (and the
+/// panic) is not checked
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Set when a lock found no free class, and once that has been logged
+static CLASSES_FULL: AtomicBool = AtomicBool::new(false);
+static CLASSES_FULL_LOGGED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the lock at `lock` is in static memory
+fn tracked(lock: usize) -> bool {
+    extern "C" {
+        static __data_start: u8;
+        static __data_end: u8;
+    }
+    let start = unsafe { &__data_start as *const u8 as usize };
+    let end = unsafe { &__data_end as *const u8 as usize };
+    (start..end).contains(&lock) && !REPORTED.load(Ordering::Relaxed)
+}
+
+fn report(violation: Violation, current: Trace) -> ! {
+    REPORTED.store(true, Ordering::Relaxed);
+    match violation {
+        Violation::Recursive { lock } => {
+            serial_println!("[LOCKDEP] Lock {:#x} acquired while already held", lock);
+        }
+        Violation::Order { held, lock, via, earlier } => {
+            serial_println!(
+                "[LOCKDEP] Lock order inversion: acquiring {:#x} while holding {:#x}",
+                lock,
+                held
+            );
+            serial_println!(
+                "[LOCKDEP] {:#x} was taken after {:#x} before, which leads back to {:#x}:",
+                via,
+                lock,
+                held
+            );
+            match earlier {
+                Some(trace) => trace.print(),
+                None => serial_println!("  (stack not recorded)"),
+            }
+        }
+        Violation::IrqUnsafe { lock, in_irq, earlier } => {
+            let (now, before) = if in_irq {
+                ("in interrupt context", "with interrupts enabled")
+            } else {
+                ("with interrupts enabled", "in interrupt context")
+            };
+            serial_println!("[LOCKDEP] Lock {:#x} acquired {}, but was acquired {} at:", lock, now, before);
+            earlier.print();
+        }
+    }
+    serial_println!("[LOCKDEP] Current acquisition:");
+    current.print();
+    panic!("[LOCKDEP] Lock dependency violation");
+}
+
+pub fn will_lock(lock: usize) {
+    if !tracked(lock) {
+        return;
+    }
+    let irqs_enabled = interrupts::are_enabled();
+    let trace = Trace::capture();
+    let result = with_context(|context| GRAPH.lock().acquire(context, lock, irqs_enabled, trace));
+    if let Err(violation) = result {
+        report(violation, trace);
+    }
+    if CLASSES_FULL.load(Ordering::Relaxed) && !CLASSES_FULL_LOGGED.swap(true, Ordering::Relaxed) {
+        serial_println!("[LOCKDEP] More than {} lock classes, not tracking the rest", MAX_CLASSES);
+    }
+}
+
+pub fn locked(lock: usize) {
+    if !tracked(lock) {
+        return;
+    }
+    with_context(|context| {
+        if context.depth < MAX_HELD {
+            context.held[context.depth] = lock;
+            context.depth += 1;
+        }
+    });
+}
+
+pub fn unlocked(lock: usize) {
+    if !tracked(lock) {
+        return;
+    }
+    // Guards may be dropped in any order
+    with_context(|context| {
+        if let Some(index) = context.held().iter().rposition(|&held| held == lock) {
+            context.held.copy_within(index + 1..context.depth, index);
+            context.depth -= 1;
+        }
+    });
+}
+
+pub fn irq_enter() {
+    with_context(|context| context.irq_depth += 1);
+}
+
+pub fn irq_exit() {
+    with_context(|context| context.irq_depth = context.irq_depth.saturating_sub(1));
+}
+
+pub fn switch_task(old: Option<TaskId>, new: TaskId) {
+    with_context(|context| unsafe {
+        if let Some(slot) = old.and_then(|old| SAVED.get(task_slot(old))) {
+            *slot.0.get() = *context;
+        }
+        if let Some(slot) = SAVED.get(task_slot(new)) {
+            *context = *slot.0.get();
+        }
+    });
+}
+
+crate::kernel_test! {
+    /// Taking two locks in both orders, or one in interrupt context and with
+    /// interrupts enabled, is reported
+    fn lockdep_detects_inversions() {
+        static A: u8 = 0;
+        static B: u8 = 0;
+        static C: u8 = 0;
+        let (a, b, c) = (&A as *const u8 as usize, &B as *const u8 as usize, &C as *const u8 as usize);
+        // Too big for a task stack
+        static GRAPH: spin::Mutex<Graph> = spin::Mutex::new(Graph::new());
+        let trace = Trace::capture();
+        let mut graph = GRAPH.lock();
+
+        let mut context = Context::new();
+        context.held[0] = a;
+        context.depth = 1;
+        crate::ktest_assert!(graph.acquire(&context, b, false, trace).is_ok(), "first order refused");
+        context.held[0] = b;
+        crate::ktest_assert!(graph.acquire(&context, c, false, trace).is_ok(), "second order refused");
+        context.held[0] = c;
+        let inverted = graph.acquire(&context, a, false, trace);
+        crate::ktest_assert!(matches!(inverted, Err(Violation::Order { via, .. }) if via == b), "A-B-C-A cycle missed");
+        let recursive = graph.acquire(&context, c, false, trace);
+        crate::ktest_assert!(matches!(recursive, Err(Violation::Recursive { .. })), "recursive acquisition missed");
+
+        let mut irq = Context::new();
+        irq.irq_depth = 1;
+        crate::ktest_assert!(graph.acquire(&irq, a, false, trace).is_ok(), "interrupt-context use refused");
+        let unsafe_use = graph.acquire(&Context::new(), a, true, trace);
+        crate::ktest_assert!(matches!(unsafe_use, Err(Violation::IrqUnsafe { in_irq: false, .. })), "IRQ-unsafe use missed");
+        Ok(())
+    }
+}
@@ -1,9 +1,15 @@
 pub mod lock_ordering;
+pub mod lockdep;
+pub mod mpsc;
+pub mod rcu;
 pub mod seqlock;
 /// Synchronization primitives for multi-core support
 /// This module provides spinlocks and other synchronization mechanisms
 /// required for safe concurrent access to shared data structures.
 mod spin;
+pub mod wait_queue;
 
+pub use mpsc::MpscQueue;
 pub use seqlock::{SeqLock, SeqLockWriteGuard};
 pub use spin::{IrqSpinLock, IrqSpinLockGuard, SpinLock, SpinLockGuard};
+pub use wait_queue::{Poller, WaitQueue};
@@ -0,0 +1,162 @@
+//! Lock-free multi-producer, single-consumer queue
+//!
+//! An [`MpscQueue`] hands small values from interrupt handlers to the code
+//! that processes them without a lock, so a handler never spins on a lock
+//! the code it interrupted holds. Any number of CPUs and nested handlers
+//! may push at once. A full queue drops the new value and counts it (see
+//! [`MpscQueue::overflows`]); the consumer decides when to report drops.
+//!
+//! Each slot carries a sequence number saying whether it is free for the
+//! push at a position or holds the value for the pop at it (Vyukov's
+//! bounded queue). A push claims its position with one compare-and-swap
+//! and never waits for another push to finish; a pop that reaches a slot
+//! whose push is still in progress reports the queue empty. Pops are also
+//! safe to race, each value going to one of them, so a queue drained both
+//! from the timer tick and from a task needs no lock of its own.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+struct Slot<T> {
+    /// `position` while free for the push at `position`, `position + 1`
+    /// once it holds that push's value
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// Fixed-capacity lock-free queue of `N` values, `N` a power of two
+pub struct MpscQueue<T: Copy, const N: usize> {
+    slots: [Slot<T>; N],
+    /// Next position to push to
+    tail: AtomicUsize,
+    /// Next position to pop from
+    head: AtomicUsize,
+    /// Values dropped because the queue was full
+    overflows: AtomicU64,
+}
+
+// Values are copied in by one push and out by one pop; the sequence
+// numbers order the copies
+unsafe impl<T: Copy + Send, const N: usize> Sync for MpscQueue<T, N> {}
+
+impl<T: Copy, const N: usize> MpscQueue<T, N> {
+    pub const fn new() -> Self {
+        const { assert!(N.is_power_of_two(), "MpscQueue capacity must be a power of two") };
+
+        let mut slots = [const {
+            Slot {
+                sequence: AtomicUsize::new(0),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            }
+        }; N];
+        let mut i = 0;
+        while i < N {
+            slots[i].sequence = AtomicUsize::new(i);
+            i += 1;
+        }
+        Self {
+            slots,
+            tail: AtomicUsize::new(0),
+            head: AtomicUsize::new(0),
+            overflows: AtomicU64::new(0),
+        }
+    }
+
+    /// Add `value` at the back; false, and the value dropped, if full
+    ///
+    /// Safe from any context, including interrupt handlers.
+    pub fn push(&self, value: T) -> bool {
+        let mut position = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[position % N];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            match (sequence.wrapping_sub(position) as isize).signum() {
+                0 => match self.tail.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.sequence.store(position.wrapping_add(1), Ordering::Release);
+                        return true;
+                    }
+                    Err(current) => position = current,
+                },
+                // The slot still holds the value pushed a lap ago
+                -1 => {
+                    self.overflows.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                // Another push took this position first
+                _ => position = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Take the value at the front, if any
+    pub fn pop(&self) -> Option<T> {
+        let mut position = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[position % N];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            match (sequence.wrapping_sub(position.wrapping_add(1)) as isize).signum() {
+                0 => match self.head.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init() };
+                        slot.sequence.store(position.wrapping_add(N), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => position = current,
+                },
+                // Empty, or the push to this position has not finished
+                -1 => return None,
+                // Another pop took this position first
+                _ => position = self.head.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Number of values queued, possibly stale by the time it is used
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Relaxed);
+        tail.wrapping_sub(head).min(N)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Values dropped because the queue was full, since boot
+    pub fn overflows(&self) -> u64 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+}
+
+crate::kernel_test! {
+    /// Values come out in push order; a full queue drops and counts pushes
+    fn mpsc_queue_order_and_overflow() {
+        static QUEUE: MpscQueue<u32, 4> = MpscQueue::new();
+        for round in 0..3u32 {
+            for i in 0..4 {
+                crate::ktest_assert!(QUEUE.push(round * 10 + i), "push to a non-full queue failed");
+            }
+            crate::ktest_assert!(!QUEUE.push(99), "full queue took a value");
+            crate::ktest_assert_eq!(QUEUE.len(), 4, "full queue length");
+            for i in 0..4 {
+                crate::ktest_assert_eq!(QUEUE.pop(), Some(round * 10 + i), "values out of order");
+            }
+            crate::ktest_assert!(QUEUE.pop().is_none(), "empty queue returned a value");
+        }
+        crate::ktest_assert_eq!(QUEUE.overflows(), 3, "overflow count");
+        Ok(())
+    }
+}
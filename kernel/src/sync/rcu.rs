@@ -0,0 +1,106 @@
+//! Read-copy-update with quiescent-state grace periods
+//!
+//! RCU lets readers of a shared structure go without a lock. A writer
+//! copies the structure, changes the copy and publishes it with one atomic
+//! pointer store. Readers see either the old copy or the new one. The old
+//! copy is freed only once every reader that could still hold it has
+//! finished; that wait is a grace period.
+//!
+//! Readers run inside [`read`], which keeps interrupts off, so a reader
+//! can neither be preempted nor see a timer tick. A CPU is therefore
+//! outside any read section whenever it switches tasks or takes a timer
+//! interrupt. Both points call [`quiescent_state`], which advances a
+//! per-CPU counter. A [`GracePeriod`] records every CPU's counter when it
+//! starts, and it has elapsed once each other online CPU's counter has
+//! moved. The CPU that started it is not waited for: it was not reading.
+//! Every online CPU takes a timer interrupt each scheduler tick, idle or
+//! not, so a grace period lasts at most about one tick.
+
+use crate::arch::x86_64::smp::percpu::current_cpu_id;
+use crate::config::MAX_CPUS;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+crate::per_cpu! {
+    /// Quiescent states each CPU has passed through
+    static QUIESCENT: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Run a read-side critical section
+///
+/// References to RCU-protected data loaded inside `f` stay valid until
+/// `f` returns. `f` runs with interrupts off, so it must be short and must
+/// not sleep or yield.
+#[inline]
+pub fn read<R>(f: impl FnOnce() -> R) -> R {
+    x86_64::instructions::interrupts::without_interrupts(f)
+}
+
+/// Report that this CPU is outside any read section
+///
+/// Called on every context switch and timer tick.
+#[inline]
+pub fn quiescent_state() {
+    QUIESCENT.get().fetch_add(1, Ordering::Release);
+}
+
+/// A grace period, started by a writer after publishing a new version
+pub struct GracePeriod {
+    /// Each CPU's quiescent count when the grace period started
+    start: [u64; MAX_CPUS],
+    /// CPU that started it
+    cpu: usize,
+}
+
+impl GracePeriod {
+    /// Start a grace period on the calling CPU
+    pub fn start() -> Self {
+        // Order the writer's publish before the counter snapshot
+        fence(Ordering::SeqCst);
+        let mut start = [0; MAX_CPUS];
+        for (count, counter) in start.iter_mut().zip(QUIESCENT.iter()) {
+            *count = counter.load(Ordering::Acquire);
+        }
+        Self {
+            start,
+            cpu: current_cpu_id(),
+        }
+    }
+
+    /// Whether every reader that started before the grace period has
+    /// finished
+    pub fn has_elapsed(&self) -> bool {
+        let online = crate::arch::x86_64::smp::get_cpu_count().min(MAX_CPUS);
+        (0..online)
+            .filter(|&cpu| cpu != self.cpu)
+            .all(|cpu| QUIESCENT.get_for(cpu).load(Ordering::Acquire) != self.start[cpu])
+    }
+}
+
+/// Wait for a full grace period
+///
+/// Spins, so it is safe anywhere interrupts are on for the other CPUs,
+/// which is always once they are online. Must not be called inside
+/// [`read`].
+pub fn synchronize() {
+    let grace_period = GracePeriod::start();
+    while !grace_period.has_elapsed() {
+        core::hint::spin_loop();
+    }
+}
+
+crate::kernel_test! {
+    /// A grace period ends once every other online CPU passes a quiescent
+    /// state, and readers run with interrupts off
+    fn rcu_grace_period() {
+        let grace_period = GracePeriod::start();
+        let online = crate::arch::x86_64::smp::get_cpu_count().min(MAX_CPUS);
+        for cpu in (0..online).filter(|&cpu| cpu != current_cpu_id()) {
+            QUIESCENT.get_for(cpu).fetch_add(1, Ordering::Release);
+        }
+        crate::ktest_assert!(grace_period.has_elapsed(), "grace period still running");
+
+        let in_read = read(x86_64::instructions::interrupts::are_enabled);
+        crate::ktest_assert!(!in_read, "interrupts enabled inside a read section");
+        Ok(())
+    }
+}
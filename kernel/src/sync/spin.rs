@@ -56,6 +56,7 @@ impl<T> SpinLock<T> {
         let mut backoff = 1;
         const MAX_BACKOFF: usize = 256;
 
+        super::lockdep::will_lock(self.addr());
         loop {
             // Try to acquire the lock using compare_exchange
             // Use Acquire ordering to ensure all subsequent reads see the latest data
@@ -64,7 +65,7 @@ impl<T> SpinLock<T> {
                 .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
                 .is_ok()
             {
-                return SpinLockGuard { lock: self };
+                return SpinLockGuard::new(self);
             }
 
             // Lock is held by another core, spin with exponential backoff
@@ -93,7 +94,7 @@ impl<T> SpinLock<T> {
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            Some(SpinLockGuard { lock: self })
+            Some(SpinLockGuard::new(self))
         } else {
             None
         }
@@ -126,7 +127,7 @@ impl<T> SpinLock<T> {
                 .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
                 .is_ok()
             {
-                return Some(SpinLockGuard { lock: self });
+                return Some(SpinLockGuard::new(self));
             }
 
             // Check if timeout expired
@@ -151,6 +152,18 @@ impl<T> SpinLock<T> {
     pub fn into_inner(self) -> T {
         self.data.into_inner()
     }
+
+    /// Address identifying the lock to lockdep
+    fn addr(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+impl<'a, T> SpinLockGuard<'a, T> {
+    fn new(lock: &'a SpinLock<T>) -> Self {
+        super::lockdep::locked(lock.addr());
+        SpinLockGuard { lock }
+    }
 }
 
 impl<T> Deref for SpinLockGuard<'_, T> {
@@ -172,6 +185,7 @@ impl<T> Drop for SpinLockGuard<'_, T> {
         // Release the lock using Release ordering to ensure all writes
         // are visible to the next thread that acquires the lock
         self.lock.locked.store(false, Ordering::Release);
+        super::lockdep::unlocked(self.lock.addr());
     }
 }
 
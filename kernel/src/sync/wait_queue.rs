@@ -0,0 +1,286 @@
+//! Wait queues and pollers
+//!
+//! A [`WaitQueue`] holds the tasks to wake when an object changes state: a
+//! pipe gaining data, a port receiving a message, a device receiving
+//! input. [`WaitQueue::wake_all`] wakes every waiter on a change; each
+//! re-checks what it was waiting for, so a queue never needs to know what
+//! its waiters want. [`WaitQueue::wake_one`] wakes only the waiter that
+//! should go first, for changes only one of them can use (one free buffer,
+//! one unlocked lock): the highest priority one, the longest waiting among
+//! equals. `sched::wait` builds `wait_event` and sleeping on top.
+//!
+//! Wake-ups are not lost: a waiter is queued before it goes to sleep and
+//! re-checks its condition after, so a change made in between either wakes
+//! it or is seen by the re-check. A waiter the woken task turns out not to
+//! be (it timed out or has not gone to sleep yet) does not use up a
+//! `wake_one`, which goes on to the next waiter; a spare wake-up only makes
+//! a task re-check its condition.
+//!
+//! A [`Poller`] puts the current task on any number of queues at once and
+//! sleeps until one of them is woken or a timeout passes. Blocking on a
+//! single object is the one-queue case ([`WaitQueue::wait_until`]).
+//!
+//! The owner of a queue wakes it whenever it changes. A driver without a
+//! receive interrupt cannot, so it registers a readiness callback with
+//! [`register_readiness`] instead: the scheduler tick calls it while the
+//! queue has waiters and wakes the queue once it reports ready.
+
+use super::SpinLock;
+use crate::sched::priority::TaskPriority;
+use crate::sched::task::TaskId;
+use crate::time::Duration;
+
+/// Tasks that can wait on one queue at a time
+pub const WAIT_QUEUE_SLOTS: usize = 16;
+
+/// Readiness callbacks that can be registered
+const MAX_READINESS_SOURCES: usize = 8;
+
+/// A queued task and its place in arrival order
+#[derive(Clone, Copy)]
+struct Waiter {
+    task_id: TaskId,
+    ticket: u64,
+}
+
+/// The waiters of a queue
+struct Waiters {
+    slots: [Option<Waiter>; WAIT_QUEUE_SLOTS],
+    next_ticket: u64,
+}
+
+impl Waiters {
+    fn position(&self, task_id: TaskId) -> Option<usize> {
+        self.slots.iter().position(|slot| slot.is_some_and(|waiter| waiter.task_id == task_id))
+    }
+
+    /// Take out the waiter [`WaitQueue::wake_one`] wakes first
+    fn take_first(&mut self) -> Option<TaskId> {
+        let priority = |task_id| crate::sched::get_task_priority(task_id).map(|(_, priority)| priority);
+        let (index, _) = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| slot.map(|waiter| (index, waiter)))
+            .max_by_key(|(_, waiter)| (priority(waiter.task_id), core::cmp::Reverse(waiter.ticket)))?;
+        self.slots[index].take().map(|waiter| waiter.task_id)
+    }
+}
+
+/// Tasks waiting for an object to change
+pub struct WaitQueue {
+    tasks: SpinLock<Waiters>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            tasks: SpinLock::new(Waiters {
+                slots: [None; WAIT_QUEUE_SLOTS],
+                next_ticket: 0,
+            }),
+        }
+    }
+
+    /// Wake `task_id` on the next change; false if the queue is full
+    pub fn add(&self, task_id: TaskId) -> bool {
+        let mut tasks = self.tasks.lock();
+        if tasks.position(task_id).is_some() {
+            return true;
+        }
+        let ticket = tasks.next_ticket;
+        match tasks.slots.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(Waiter { task_id, ticket });
+                tasks.next_ticket += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&self, task_id: TaskId) {
+        let mut tasks = self.tasks.lock();
+        if let Some(index) = tasks.position(task_id) {
+            tasks.slots[index] = None;
+        }
+    }
+
+    pub fn contains(&self, task_id: TaskId) -> bool {
+        self.tasks.lock().position(task_id).is_some()
+    }
+
+    /// Empty the queue and wake every task that was on it
+    pub fn wake_all(&self) {
+        let tasks = core::mem::replace(&mut self.tasks.lock().slots, [None; WAIT_QUEUE_SLOTS]);
+        for waiter in tasks.into_iter().flatten() {
+            crate::sched::wake_task(waiter.task_id);
+        }
+    }
+
+    /// Wake the highest priority waiter, the longest waiting among equals
+    ///
+    /// Waiters that turn out not to be asleep are taken off the queue and
+    /// passed over. Returns false if no task was woken.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let Some(task_id) = self.tasks.lock().take_first() else { return false };
+            if crate::sched::wake_task(task_id) {
+                return true;
+            }
+        }
+    }
+
+    /// [`wake_all`](Self::wake_all) from interrupt context
+    ///
+    /// Does nothing if the queue is locked; tasks the scheduler table lock
+    /// keeps from waking stay queued for the next try.
+    pub fn try_wake_all(&self) {
+        let Some(mut tasks) = self.tasks.try_lock() else { return };
+        for slot in tasks.slots.iter_mut() {
+            if let Some(waiter) = *slot {
+                if crate::sched::try_wake_task(waiter.task_id) {
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    /// Whether any task waits; false if the queue is locked
+    fn try_has_waiters(&self) -> bool {
+        self.tasks.try_lock().map_or(false, |tasks| tasks.slots.iter().any(Option::is_some))
+    }
+
+    /// Block the current task until `ready` holds
+    ///
+    /// Returns false without blocking if the queue is full or there is no
+    /// current task.
+    pub fn wait_until(&'static self, mut ready: impl FnMut() -> bool) -> bool {
+        let Some(poller) = Poller::current() else { return false };
+        while !ready() {
+            if !poller.wait(core::iter::once(self), Duration::MAX, &mut ready) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The current task waiting on several queues at once
+pub struct Poller {
+    task_id: TaskId,
+    priority: TaskPriority,
+    interruptible: bool,
+}
+
+impl Poller {
+    /// Poller for the task running on this CPU
+    pub fn current() -> Option<Self> {
+        crate::sched::get_current_task_info().map(|(task_id, priority)| Self {
+            task_id,
+            priority,
+            interruptible: false,
+        })
+    }
+
+    /// The same poller, but a signal for the task also ends its waits
+    pub fn interruptible(self) -> Self {
+        Self { interruptible: true, ..self }
+    }
+
+    /// Whether the poller is interruptible and a signal is pending
+    pub fn interrupted(&self) -> bool {
+        self.interruptible
+            && crate::sched::get_task_by_id(self.task_id).is_some_and(crate::signal::interrupts_wait)
+    }
+
+    /// Sleep until a queue in `queues` is woken or `timeout` passes
+    ///
+    /// The task is queued before going to sleep and `pending` is checked
+    /// after, so a change in between is not missed; an interruptible
+    /// poller checks for a signal the same way. The task is taken off the
+    /// queues again before returning. Returns false without sleeping if a
+    /// queue is full.
+    pub fn wait<I>(&self, queues: I, timeout: Duration, pending: impl FnOnce() -> bool) -> bool
+    where
+        I: Iterator<Item = &'static WaitQueue> + Clone,
+    {
+        for (added, queue) in queues.clone().enumerate() {
+            if !queue.add(self.task_id) {
+                for queue in queues.take(added) {
+                    queue.remove(self.task_id);
+                }
+                return false;
+            }
+        }
+
+        if self.interruptible {
+            crate::sched::sleep_current_task_interruptible(timeout);
+        } else {
+            crate::sched::sleep_current_task(timeout, self.priority);
+        }
+        if !(pending() || self.interrupted()) || !crate::sched::cancel_wait() {
+            crate::sched::yield_now();
+        }
+
+        for queue in queues {
+            queue.remove(self.task_id);
+        }
+        true
+    }
+}
+
+/// Driver callback telling whether a device has input
+///
+/// Runs in interrupt context, so it must not block.
+pub type ReadinessFn = fn() -> bool;
+
+static READINESS_SOURCES: SpinLock<[Option<(ReadinessFn, &'static WaitQueue)>; MAX_READINESS_SOURCES]> =
+    SpinLock::new([None; MAX_READINESS_SOURCES]);
+
+/// Have `queue` woken by the scheduler tick once `ready` returns true
+///
+/// For devices that cannot raise an interrupt when they become ready.
+/// Returns false if too many callbacks are registered.
+pub fn register_readiness(ready: ReadinessFn, queue: &'static WaitQueue) -> bool {
+    let mut sources = READINESS_SOURCES.lock();
+    match sources.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some((ready, queue));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Scheduler tick: run the readiness callbacks of queues with waiters
+pub fn poll_readiness() {
+    let Some(sources) = READINESS_SOURCES.try_lock() else { return };
+    for &(ready, queue) in sources.iter().flatten() {
+        if queue.try_has_waiters() && ready() {
+            queue.try_wake_all();
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// Waking a queue empties it; a full queue refuses new waiters
+    fn wait_queue_slots() {
+        static QUEUE: WaitQueue = WaitQueue::new();
+        // Task ids no real task has, so waking them is a no-op
+        let base = usize::MAX - WAIT_QUEUE_SLOTS;
+        for i in 0..WAIT_QUEUE_SLOTS {
+            crate::ktest_assert!(QUEUE.add(base + i), "queue filled early");
+        }
+        crate::ktest_assert!(QUEUE.add(base), "re-adding a waiter failed");
+        crate::ktest_assert!(!QUEUE.add(usize::MAX), "full queue took a waiter");
+
+        QUEUE.remove(base);
+        crate::ktest_assert!(!QUEUE.contains(base), "waiter not removed");
+        crate::ktest_assert!(QUEUE.add(usize::MAX), "freed slot not reused");
+
+        QUEUE.wake_all();
+        crate::ktest_assert!(!QUEUE.contains(usize::MAX), "queue not emptied");
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+//! Syscall error numbers
+//!
+//! A failed syscall returns a negated error number, as on x86_64 Linux, so
+//! user code tests the result for < 0 and can tell the causes apart.
+//! Handlers return a [`SyscallResult`] rather than a raw value; the
+//! dispatchers turn it into the return register with [`to_return`]. The
+//! error types of the subsystems map onto error numbers here, so handlers
+//! can pass them on with `?`.
+
+use crate::dev::input::InputError;
+use crate::fs::file::FileError;
+use crate::mm::mmap::MmapError;
+use crate::net::NetError;
+use crate::sched::bandwidth::BandwidthError;
+use crate::sched::thread::ThreadError;
+use crate::sched::wait::WaitError;
+use crate::sys::futex::FutexError;
+use crate::sys::handle::HandleError;
+use crate::sys::ipc::IpcError;
+use crate::sys::perf::PerfError;
+use crate::sys::poll::PollError;
+use crate::sys::shm::ShmError;
+use crate::sys::waitable::WaitableError;
+use crate::user::spawn::SpawnError;
+
+/// Error of a failed syscall; the discriminant is the value returned
+#[repr(isize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    /// Operation not permitted
+    EPERM = -1,
+    /// No such file or directory
+    ENOENT = -2,
+    /// No such process
+    ESRCH = -3,
+    /// Interrupted by a signal
+    EINTR = -4,
+    /// I/O error
+    EIO = -5,
+    /// Argument list too long
+    E2BIG = -7,
+    /// Exec format error
+    ENOEXEC = -8,
+    /// Bad file descriptor
+    EBADF = -9,
+    /// No child processes
+    ECHILD = -10,
+    /// Try again
+    EAGAIN = -11,
+    /// Out of memory
+    ENOMEM = -12,
+    /// Permission denied
+    EACCES = -13,
+    /// Bad address
+    EFAULT = -14,
+    /// Device or resource busy
+    EBUSY = -16,
+    /// No such device
+    ENODEV = -19,
+    /// Invalid argument
+    EINVAL = -22,
+    /// Too many open files
+    EMFILE = -24,
+    /// Not a terminal
+    ENOTTY = -25,
+    /// No space left on device
+    ENOSPC = -28,
+    /// Read-only file system
+    EROFS = -30,
+    /// Broken pipe
+    EPIPE = -32,
+    /// File name too long
+    ENAMETOOLONG = -36,
+    /// Function not implemented
+    ENOSYS = -38,
+    /// Destination address required
+    EDESTADDRREQ = -89,
+    /// Message too long
+    EMSGSIZE = -90,
+    /// Operation not supported on this socket
+    EOPNOTSUPP = -95,
+    /// Address family not supported
+    EAFNOSUPPORT = -97,
+    /// Address already in use
+    EADDRINUSE = -98,
+    /// Network is down
+    ENETDOWN = -100,
+    /// Network is unreachable
+    ENETUNREACH = -101,
+    /// Connection reset by peer
+    ECONNRESET = -104,
+    /// Socket is already connected
+    EISCONN = -106,
+    /// Socket is not connected
+    ENOTCONN = -107,
+    /// Connection timed out
+    ETIMEDOUT = -110,
+    /// Connection refused
+    ECONNREFUSED = -111,
+    /// No route to host
+    EHOSTUNREACH = -113,
+    /// Connection already in progress
+    EALREADY = -114,
+    /// Connection in progress
+    EINPROGRESS = -115,
+}
+
+/// Result of a syscall handler: the (non-negative) value to return, or why
+/// it failed
+pub type SyscallResult = Result<usize, Errno>;
+
+/// Value of the return register for `result`
+///
+/// Success values above `isize::MAX` do not occur: no handler returns
+/// more than a count, an ID or a user address.
+pub fn to_return(result: SyscallResult) -> isize {
+    match result {
+        Ok(value) => value as isize,
+        Err(errno) => errno as isize,
+    }
+}
+
+impl From<IpcError> for Errno {
+    fn from(error: IpcError) -> Self {
+        match error {
+            IpcError::InvalidPort | IpcError::PortNotFound => Errno::EBADF,
+            IpcError::QueueFull | IpcError::WouldBlock => Errno::EAGAIN,
+            IpcError::InvalidBuffer => Errno::EFAULT,
+            IpcError::MessageTooLarge => Errno::EMSGSIZE,
+            IpcError::NotImplemented => Errno::ENOSYS,
+            IpcError::TaskNotFound => Errno::ESRCH,
+            IpcError::InvalidCapability => Errno::EBADF,
+            IpcError::PermissionDenied => Errno::EACCES,
+            IpcError::NoFreePort => Errno::ENOSPC,
+            IpcError::TimedOut => Errno::ETIMEDOUT,
+            IpcError::Interrupted => Errno::EINTR,
+        }
+    }
+}
+
+impl From<WaitError> for Errno {
+    fn from(error: WaitError) -> Self {
+        match error {
+            WaitError::TimedOut => Errno::ETIMEDOUT,
+            WaitError::Interrupted => Errno::EINTR,
+            WaitError::QueueFull => Errno::EAGAIN,
+            WaitError::NoTask => Errno::ESRCH,
+        }
+    }
+}
+
+impl From<MmapError> for Errno {
+    fn from(error: MmapError) -> Self {
+        match error {
+            MmapError::InvalidArgument => Errno::EINVAL,
+            MmapError::WxViolation => Errno::EACCES,
+            MmapError::OutOfMemory => Errno::ENOMEM,
+            MmapError::NotSupported => Errno::ENODEV,
+        }
+    }
+}
+
+impl From<ShmError> for Errno {
+    fn from(error: ShmError) -> Self {
+        match error {
+            ShmError::InvalidArgument => Errno::EINVAL,
+            ShmError::NotFound => Errno::ENOENT,
+            ShmError::TooManyObjects => Errno::ENOSPC,
+            ShmError::OutOfMemory => Errno::ENOMEM,
+            ShmError::Map(error) => error.into(),
+        }
+    }
+}
+
+impl From<HandleError> for Errno {
+    fn from(error: HandleError) -> Self {
+        match error {
+            HandleError::BadHandle => Errno::EBADF,
+            HandleError::PermissionDenied => Errno::EACCES,
+            HandleError::TableFull => Errno::EMFILE,
+        }
+    }
+}
+
+impl From<WaitableError> for Errno {
+    fn from(error: WaitableError) -> Self {
+        match error {
+            WaitableError::InvalidArgument => Errno::EINVAL,
+            WaitableError::NotFound => Errno::EBADF,
+            WaitableError::TooManyObjects => Errno::ENOSPC,
+            WaitableError::WouldBlock => Errno::EAGAIN,
+        }
+    }
+}
+
+impl From<FileError> for Errno {
+    fn from(error: FileError) -> Self {
+        match error {
+            FileError::NotFound => Errno::ENOENT,
+            FileError::BadFile => Errno::EBADF,
+            FileError::TooManyFiles => Errno::ENOSPC,
+            FileError::Gone => Errno::ESRCH,
+        }
+    }
+}
+
+impl From<InputError> for Errno {
+    fn from(error: InputError) -> Self {
+        match error {
+            InputError::NoDevice => Errno::ENODEV,
+            InputError::TooManyDevices => Errno::ENOSPC,
+            InputError::Busy => Errno::EBUSY,
+            InputError::NotGrabbed => Errno::EINVAL,
+        }
+    }
+}
+
+impl From<BandwidthError> for Errno {
+    fn from(error: BandwidthError) -> Self {
+        match error {
+            BandwidthError::InvalidArgument => Errno::EINVAL,
+        }
+    }
+}
+
+impl From<PerfError> for Errno {
+    fn from(error: PerfError) -> Self {
+        match error {
+            PerfError::InvalidArgument => Errno::EINVAL,
+            PerfError::NoSuchObject => Errno::ENOENT,
+            PerfError::NotProfiled => Errno::ESRCH,
+            PerfError::TooManySessions => Errno::EBUSY,
+        }
+    }
+}
+
+impl From<PollError> for Errno {
+    fn from(error: PollError) -> Self {
+        match error {
+            PollError::TooManyEntries => Errno::EINVAL,
+            PollError::NoTask => Errno::ESRCH,
+            PollError::QueueFull => Errno::EAGAIN,
+        }
+    }
+}
+
+impl From<FutexError> for Errno {
+    fn from(error: FutexError) -> Self {
+        match error {
+            FutexError::BadAddress => Errno::EFAULT,
+            FutexError::WouldBlock | FutexError::QueueFull => Errno::EAGAIN,
+            FutexError::Interrupted => Errno::EINTR,
+        }
+    }
+}
+
+impl From<ThreadError> for Errno {
+    fn from(error: ThreadError) -> Self {
+        match error {
+            ThreadError::InvalidArgument => Errno::EINVAL,
+            ThreadError::NoTask => Errno::ESRCH,
+            ThreadError::NoResources => Errno::EAGAIN,
+        }
+    }
+}
+
+impl From<SpawnError> for Errno {
+    fn from(error: SpawnError) -> Self {
+        match error {
+            SpawnError::NotFound => Errno::ENOENT,
+            SpawnError::NotExecutable => Errno::ENOEXEC,
+            SpawnError::NoResources => Errno::EAGAIN,
+            SpawnError::ArgumentsTooLong => Errno::E2BIG,
+        }
+    }
+}
+
+impl From<NetError> for Errno {
+    fn from(error: NetError) -> Self {
+        match error {
+            NetError::TooLarge => Errno::EMSGSIZE,
+            NetError::Unsupported => Errno::EAFNOSUPPORT,
+            NetError::NoInterface => Errno::ENETDOWN,
+            NetError::Unresolved => Errno::EHOSTUNREACH,
+            NetError::NoRoute => Errno::ENETUNREACH,
+            NetError::TooManyRoutes => Errno::ENOSPC,
+            NetError::TimedOut => Errno::ETIMEDOUT,
+            NetError::AddressInUse => Errno::EADDRINUSE,
+            NetError::TooManyEndpoints => Errno::EAGAIN,
+            NetError::NotBound | NetError::AlreadyBound | NetError::InvalidState => Errno::EINVAL,
+            NetError::ConnectionRefused => Errno::ECONNREFUSED,
+            NetError::ConnectionReset => Errno::ECONNRESET,
+            NetError::NotConnected => Errno::ENOTCONN,
+            NetError::AlreadyConnected => Errno::EISCONN,
+            NetError::InProgress => Errno::EINPROGRESS,
+            NetError::Already => Errno::EALREADY,
+            NetError::DestinationRequired => Errno::EDESTADDRREQ,
+            NetError::WrongType => Errno::EOPNOTSUPP,
+            NetError::NameNotFound => Errno::ENOENT,
+            NetError::NoNameServer => Errno::ENETUNREACH,
+            NetError::ServerFailure => Errno::EAGAIN,
+            NetError::Device => Errno::EIO,
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// Results become Linux return values: the value, or the negated
+    /// error number
+    fn errno_return_values() {
+        crate::ktest_assert_eq!(to_return(Ok(42)), 42, "success value");
+        crate::ktest_assert_eq!(to_return(Err(Errno::EPERM)), -1, "EPERM");
+        crate::ktest_assert_eq!(to_return(Err(Errno::EINVAL)), -22, "EINVAL");
+        crate::ktest_assert_eq!(to_return(Err(Errno::ENOSYS)), -38, "ENOSYS");
+        crate::ktest_assert_eq!(Errno::from(IpcError::WouldBlock), Errno::EAGAIN, "IPC mapping");
+        Ok(())
+    }
+}
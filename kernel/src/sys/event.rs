@@ -0,0 +1,207 @@
+//! Kernel event broadcast port
+//!
+//! Kernel subsystems announce system-wide conditions (memory pressure, the
+//! power button) with [`broadcast`]. Every event goes to:
+//! - kernel listeners registered with [`add_listener`], called synchronously
+//! - every IPC port subscribed with [`subscribe`] (`SYS_EVENT_SUBSCRIBE`
+//!   from userland), as one message per port
+//!
+//! A message is an [`EventHeader`] followed by `len` payload bytes whose
+//! layout depends on the kind. Delivery to ports never blocks: if a
+//! subscriber's queue is full the event is dropped for that subscriber and
+//! counted in [`dropped`].
+//!
+//! `broadcast` takes the port manager lock and may wake tasks, so it must be
+//! called from task context, never from an interrupt handler.
+
+#![allow(dead_code)]
+
+use super::ipc::IpcError;
+use super::port::PORT_MANAGER;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Maximum number of subscribed ports
+const MAX_SUBSCRIBERS: usize = 16;
+
+/// Maximum number of kernel listeners
+const MAX_LISTENERS: usize = 8;
+
+/// Largest payload an event may carry
+pub const MAX_EVENT_PAYLOAD: usize = 64;
+
+/// Kinds of kernel events
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Memory pressure level changed (payload: `mm::pressure::PressureEvent`)
+    MemoryPressure = 1,
+    /// A watched task exited (payload: `exit_watch::TaskExit`); sent only
+    /// to the watching port, never broadcast
+    TaskExit = 2,
+    /// The power button was pressed (no payload); see `acpi::power`
+    PowerButton = 3,
+}
+
+impl EventKind {
+    /// Bit for this kind in a subscription mask
+    pub const fn mask(self) -> u32 {
+        1 << (self as u32)
+    }
+}
+
+/// Header at the start of every event message
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventHeader {
+    /// `EventKind` value
+    pub kind: u32,
+    /// Payload length in bytes
+    pub len: u32,
+    /// Milliseconds since boot when the event was raised
+    pub time_ms: u64,
+}
+
+mello_abi::check_layout!(EventHeader, mello_abi::EventHeader { kind, len, time_ms });
+
+/// Kernel-side event callback
+pub type Listener = fn(EventKind, &[u8]);
+
+#[derive(Clone, Copy)]
+struct Subscriber {
+    port_id: usize,
+    mask: u32,
+}
+
+static SUBSCRIBERS: Mutex<[Option<Subscriber>; MAX_SUBSCRIBERS]> =
+    Mutex::new([None; MAX_SUBSCRIBERS]);
+
+static LISTENERS: Mutex<[Option<Listener>; MAX_LISTENERS]> = Mutex::new([None; MAX_LISTENERS]);
+
+/// Events not delivered because a subscriber's queue was full
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Subscribe `port_id` to the event kinds in `mask`
+///
+/// Subscribing an already subscribed port replaces its mask; a zero mask
+/// unsubscribes it.
+pub fn subscribe(port_id: usize, mask: u32) -> Result<(), IpcError> {
+    if port_id >= 256 {
+        return Err(IpcError::InvalidPort);
+    }
+    if PORT_MANAGER.lock().ports[port_id].is_none() {
+        return Err(IpcError::PortNotFound);
+    }
+
+    let mut subscribers = SUBSCRIBERS.lock();
+    if let Some(slot) = subscribers
+        .iter_mut()
+        .find(|slot| slot.map_or(false, |sub| sub.port_id == port_id))
+    {
+        *slot = (mask != 0).then_some(Subscriber { port_id, mask });
+        return Ok(());
+    }
+    if mask == 0 {
+        return Ok(());
+    }
+
+    let slot = subscribers
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(IpcError::QueueFull)?;
+    *slot = Some(Subscriber { port_id, mask });
+    Ok(())
+}
+
+/// Register a kernel listener for all events
+pub fn add_listener(listener: Listener) -> Result<(), &'static str> {
+    let mut listeners = LISTENERS.lock();
+    let slot = listeners
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or("Too many event listeners")?;
+    *slot = Some(listener);
+    Ok(())
+}
+
+/// Encode an event message into `buf`, returning its length
+fn encode(kind: EventKind, payload: &[u8], buf: &mut [u8]) -> usize {
+    let header = EventHeader {
+        kind: kind as u32,
+        len: payload.len() as u32,
+        time_ms: crate::time::Instant::now().since_boot().as_millis(),
+    };
+    let header_len = core::mem::size_of::<EventHeader>();
+    // SAFETY: EventHeader is repr(C) plain data and buf holds at least a header
+    unsafe {
+        core::ptr::write_unaligned(buf.as_mut_ptr() as *mut EventHeader, header);
+    }
+    buf[header_len..header_len + payload.len()].copy_from_slice(payload);
+    header_len + payload.len()
+}
+
+/// Announce an event to kernel listeners and subscribed ports
+///
+/// Returns the number of ports the event was delivered to.
+pub fn broadcast(kind: EventKind, payload: &[u8]) -> usize {
+    let payload = &payload[..payload.len().min(MAX_EVENT_PAYLOAD)];
+
+    // Copy the tables out so no event lock is held while calling out
+    let listeners = *LISTENERS.lock();
+    for listener in listeners.iter().flatten() {
+        listener(kind, payload);
+    }
+
+    let mut buf = [0u8; core::mem::size_of::<EventHeader>() + MAX_EVENT_PAYLOAD];
+    let len = encode(kind, payload, &mut buf);
+
+    let subscribers = *SUBSCRIBERS.lock();
+    let mut delivered = 0;
+    for sub in subscribers.iter().flatten() {
+        if sub.mask & kind.mask() != 0 && deliver(sub.port_id, &buf[..len]) {
+            delivered += 1;
+        }
+    }
+    delivered
+}
+
+/// Send an event to `port_id` alone, not to listeners or subscribers
+///
+/// Returns false if the port could not take it.
+pub fn send(port_id: usize, kind: EventKind, payload: &[u8]) -> bool {
+    let payload = &payload[..payload.len().min(MAX_EVENT_PAYLOAD)];
+    let mut buf = [0u8; core::mem::size_of::<EventHeader>() + MAX_EVENT_PAYLOAD];
+    let len = encode(kind, payload, &mut buf);
+    deliver(port_id, &buf[..len])
+}
+
+/// Queue an encoded event on `port_id`, counting it as dropped on failure
+fn deliver(port_id: usize, message: &[u8]) -> bool {
+    match PORT_MANAGER.lock().send_message(port_id, message) {
+        Ok(()) => true,
+        Err(_) => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+/// Number of events dropped because a subscriber could not take them
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+crate::kernel_test! {
+    /// Event messages start with a header describing the payload
+    fn event_encoding() {
+        let mut buf = [0u8; core::mem::size_of::<EventHeader>() + MAX_EVENT_PAYLOAD];
+        let len = encode(EventKind::MemoryPressure, &[1, 2, 3], &mut buf);
+        crate::ktest_assert_eq!(len, 16 + 3, "message length");
+
+        let header = unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const EventHeader) };
+        crate::ktest_assert_eq!(header.kind, EventKind::MemoryPressure as u32, "event kind");
+        crate::ktest_assert_eq!(header.len, 3, "payload length");
+        crate::ktest_assert_eq!(buf[16..19], [1, 2, 3], "payload bytes");
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! Exit notifications (`SYS_TASK_WATCH`)
+//!
+//! A task watches another task's exit through an IPC port. When the
+//! watched task ends (`SYS_EXIT`, `SYS_THREAD_EXIT`, its process exiting,
+//! a fault or a kill), the port receives one event message: an
+//! `EventHeader` of kind `EventKind::TaskExit` followed by a [`TaskExit`].
+//! Each watch fires once. Watching a task that has exited but is not reaped
+//! yet (a process its parent has not waited for) fires at once, so a parent
+//! can watch a child it has just spawned without racing its exit.
+//!
+//! As with events, sending never blocks: a port with a full queue loses
+//! the notification, counted in `event::dropped`.
+
+use super::event::{self, EventKind};
+use super::ipc::IpcError;
+use super::port::PORT_MANAGER;
+use crate::sched::task::{TaskId, TaskState};
+use crate::sync::SpinLock;
+
+/// Watches that can be pending at once
+const MAX_WATCHES: usize = 64;
+
+/// What a watching port is told when the task exits
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskExit {
+    pub task: u64,
+    pub pid: u64,
+    /// Exit code; 128 + the signal number if the task was killed
+    pub code: u64,
+}
+
+mello_abi::check_layout!(TaskExit, mello_abi::TaskExit { task, pid, code });
+
+#[derive(Clone, Copy)]
+struct Watch {
+    task: TaskId,
+    port_id: usize,
+}
+
+static WATCHES: SpinLock<[Option<Watch>; MAX_WATCHES]> = SpinLock::new([None; MAX_WATCHES]);
+
+/// Send `port_id` a [`TaskExit`] when task `task` exits
+pub fn watch(task: TaskId, port_id: usize) -> Result<(), IpcError> {
+    if PORT_MANAGER.lock().ports.get(port_id).map_or(true, Option::is_none) {
+        return Err(IpcError::PortNotFound);
+    }
+    let target = crate::sched::get_task_by_id(task).ok_or(IpcError::TaskNotFound)?;
+
+    // Exiting marks the task before firing its watches, both under WATCHES:
+    // a watch added here either sees the mark or is fired
+    let mut watches = WATCHES.lock();
+    if target.state == TaskState::Exited {
+        drop(watches);
+        notify(port_id, task, target.pid, zombie_code(task));
+        return Ok(());
+    }
+    let slot = watches.iter_mut().find(|slot| slot.is_none()).ok_or(IpcError::QueueFull)?;
+    *slot = Some(Watch { task, port_id });
+    Ok(())
+}
+
+/// Fire the watches of `task`, which has just been marked exited
+pub fn exited(task: TaskId, pid: TaskId, code: usize) {
+    let mut fired = [0usize; MAX_WATCHES];
+    let mut count = 0;
+    for slot in WATCHES.lock().iter_mut() {
+        if let Some(watch) = slot.take_if(|watch| watch.task == task) {
+            fired[count] = watch.port_id;
+            count += 1;
+        }
+    }
+    // Sent without WATCHES held, as sending takes the port lock
+    for &port_id in &fired[..count] {
+        notify(port_id, task, pid, code);
+    }
+}
+
+/// Exit code of an exited task that is not reaped yet
+///
+/// Only processes keep one, until waited for; a thread's is 0.
+fn zombie_code(task: TaskId) -> usize {
+    crate::user::process::ProcessManager::get_process(task)
+        .and_then(|process| process.get().and_then(|process| process.exit_code))
+        .map_or(0, |code| code as usize)
+}
+
+fn notify(port_id: usize, task: TaskId, pid: TaskId, code: usize) {
+    let exit = TaskExit {
+        task: task as u64,
+        pid: pid as u64,
+        code: code as u64,
+    };
+    // SAFETY: TaskExit is repr(C) plain data
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&exit as *const TaskExit as *const u8, core::mem::size_of::<TaskExit>())
+    };
+    event::send(port_id, EventKind::TaskExit, bytes);
+}
+
+crate::kernel_test! {
+    /// A watch fires once, when its task exits, and watching an exited task
+    /// fires at once
+    fn exit_watch_fires_once() {
+        use crate::sched::priority::TaskPriority;
+        use crate::sys::ipc::Message;
+
+        // A system port nothing else uses while the tests run
+        const PORT: usize = 14;
+        const HEADER: usize = core::mem::size_of::<event::EventHeader>();
+        let id = crate::sched::spawn_task("ktest_exit_watch", crate::sched::ktest_parked_task, TaskPriority::Normal)
+            .map_err(|_| "spawn_task failed")?;
+        // The payload of the next queued notification
+        let receive = || {
+            let mut exit = None;
+            let _ = PORT_MANAGER.lock().recv_message_with(PORT, 0, true, &mut |message: &Message| {
+                let payload = &message.as_slice()[HEADER..];
+                // SAFETY: the payload is a TaskExit, read unaligned
+                exit = (payload.len() == core::mem::size_of::<TaskExit>())
+                    .then(|| unsafe { core::ptr::read_unaligned(payload.as_ptr() as *const TaskExit) });
+                message.len()
+            });
+            exit
+        };
+
+        watch(id, PORT).map_err(|_| "watch failed")?;
+        crate::ktest_assert_eq!(receive(), None, "fired before the exit");
+        crate::sched::get_task_mut(id).ok_or("spawned task missing")?.state = TaskState::Exited;
+        exited(id, id, 3);
+        exited(id, id, 3);
+        let expected = TaskExit { task: id as u64, pid: id as u64, code: 3 };
+        crate::ktest_assert_eq!(receive(), Some(expected), "no notification");
+        crate::ktest_assert_eq!(receive(), None, "fired twice");
+
+        watch(id, PORT).map_err(|_| "late watch failed")?;
+        crate::ktest_assert!(receive().is_some_and(|exit| exit.task == id as u64), "late watch did not fire");
+        crate::sched::reap_task(id);
+        Ok(())
+    }
+}
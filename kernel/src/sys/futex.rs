@@ -0,0 +1,78 @@
+//! Futexes (`SYS_FUTEX`)
+//!
+//! A futex is a `u32` in user memory that tasks can sleep on. `FUTEX_WAIT`
+//! sleeps while the word holds an expected value. `FUTEX_WAKE` wakes the
+//! tasks sleeping on a word, after the waker has changed it. User space
+//! builds mutexes, condition variables and thread join (`sched::thread`) on
+//! top.
+//!
+//! Words hash into a fixed set of wait queues. A wake empties the whole
+//! queue of the word's bucket. Each woken task re-reads its own word and goes
+//! back to sleep if it still holds the expected value. So unlike Linux, a
+//! waiter only returns once its word has changed, and a wake without a
+//! change goes unnoticed. A signal for the waiter ends the wait early.
+
+use crate::sched::wait::WaitError;
+use crate::sync::WaitQueue;
+
+/// Sleep while the word holds `val`
+pub const FUTEX_WAIT: usize = 0;
+/// Wake the tasks sleeping on the word
+pub const FUTEX_WAKE: usize = 1;
+
+/// Wait queues words hash into
+const BUCKETS: usize = 64;
+
+static QUEUES: [WaitQueue; BUCKETS] = [const { WaitQueue::new() }; BUCKETS];
+
+/// Futex errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexError {
+    /// The word is not readable user memory, or not 4-byte aligned
+    BadAddress,
+    /// The word does not hold the expected value
+    WouldBlock,
+    /// Too many tasks wait on words of this bucket, or no current task
+    QueueFull,
+    /// A signal for the task ended the wait
+    Interrupted,
+}
+
+fn queue(addr: usize) -> &'static WaitQueue {
+    &QUEUES[(addr / 4) % BUCKETS]
+}
+
+/// Sleep until the word at `addr` no longer holds `expected`
+///
+/// `load` reads the word; None means it could not be read.
+pub fn wait(addr: usize, expected: u32, mut load: impl FnMut() -> Option<u32>) -> Result<(), FutexError> {
+    if addr % 4 != 0 {
+        return Err(FutexError::BadAddress);
+    }
+    match load() {
+        None => return Err(FutexError::BadAddress),
+        Some(value) if value != expected => return Err(FutexError::WouldBlock),
+        Some(_) => {}
+    }
+    match crate::sched::wait::wait_event_interruptible(queue(addr), || load() != Some(expected)) {
+        Ok(()) => Ok(()),
+        Err(WaitError::Interrupted) => Err(FutexError::Interrupted),
+        Err(_) => Err(FutexError::QueueFull),
+    }
+}
+
+/// Wake the tasks sleeping on the word at `addr`
+pub fn wake(addr: usize) {
+    queue(addr).wake_all();
+}
+
+crate::kernel_test! {
+    /// A word that already changed, or cannot be read, does not block
+    fn futex_wait_without_blocking() {
+        crate::ktest_assert_eq!(wait(0x1000, 0, || Some(1)), Err(FutexError::WouldBlock), "waited on a changed word");
+        crate::ktest_assert_eq!(wait(0x1000, 0, || None), Err(FutexError::BadAddress), "waited on an unreadable word");
+        crate::ktest_assert_eq!(wait(0x1002, 0, || Some(1)), Err(FutexError::BadAddress), "misaligned word accepted");
+        wake(0x1000);
+        Ok(())
+    }
+}
@@ -0,0 +1,398 @@
+//! Per-process handle table
+//!
+//! Everything a process can name is a handle: an index into one table per
+//! process, shared by its threads. A handle refers to an open file
+//! (console, PTY or pipe), an IPC port, a shared memory object, a timer or
+//! an event object (see `sys::waitable`). The kinds differ in what can be
+//! done with them, not in how they are held:
+//!
+//! - `SYS_CLOSE` drops a handle of any kind (`SYS_CAP_DROP` is the same
+//!   call)
+//! - `SYS_DUP` and `SYS_DUP2` copy one; `SYS_CAP_DERIVE` copies one with
+//!   fewer rights
+//! - a handle with the `GRANT` right can be passed in an IPC message, and
+//!   the receiver gets a handle of its own to the same object
+//! - fork copies the table, spawn copies it without the `FD_CLOEXEC`
+//!   handles, exec closes those, and exit closes all of them
+//!
+//! Each handle holds a reference to its object ([`Object::retain`]), and
+//! so does a handle in flight in a message. An object is freed when its
+//! last reference is dropped ([`Object::release`]); the counts are kept by
+//! the subsystems that own the objects. Ports are never freed yet.
+//!
+//! Rights are checked per handle: `SEND` and `RECV` on ports, `GRANT` on
+//! every kind. Handles 0-2 are stdin, stdout and stderr; while empty they
+//! refer to the controlling terminal or the console (see `sys::syscall`),
+//! so new handles are numbered from 3. A task started by the kernel holds
+//! system ports 3-15 at the same handles.
+//!
+//! The table lock is taken before the object tables (pipes, PTYs, shared
+//! memory, timers and events) when retaining. Objects are released after
+//! the lock is dropped, since closing a PTY signals its foreground group.
+
+use super::ipc::IpcError;
+use super::syscall::FdType;
+use crate::sched::process_group::Pid;
+use crate::sched::task::{task_slot, TaskId};
+use crate::sched::MAX_TASKS;
+use crate::sync::SpinLock;
+
+/// Handles per process
+pub const MAX_HANDLES: usize = 64;
+
+/// Handles 0-2, which default to the terminal when empty
+pub const STDIO_HANDLES: usize = 3;
+
+/// Index of a handle in its process's table
+pub type HandleId = usize;
+
+/// Set of rights on an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rights(u8);
+
+impl Rights {
+    pub const SEND: Rights = Rights(1 << 0);
+    pub const RECV: Rights = Rights(1 << 1);
+    pub const GRANT: Rights = Rights(1 << 2);
+    pub const ALL: Rights = Rights(Self::SEND.0 | Self::RECV.0 | Self::GRANT.0);
+
+    /// Rights from their syscall encoding; unknown bits are an error
+    pub const fn from_bits(bits: usize) -> Option<Rights> {
+        if bits & !(Self::ALL.0 as usize) != 0 {
+            None
+        } else {
+            Some(Rights(bits as u8))
+        }
+    }
+
+    pub const fn contains(self, other: Rights) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersect(self, other: Rights) -> Rights {
+        Rights(self.0 & other.0)
+    }
+}
+
+/// Kernel object a handle refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Object {
+    File(FdType),
+    /// IPC port, by port id
+    Port(usize),
+    /// Shared memory object, by object id
+    Shm(usize),
+    /// Timer, by timer id
+    Timer(usize),
+    /// Event object, by event id
+    Event(usize),
+}
+
+impl Object {
+    /// Take a reference for a new handle to the object
+    pub fn retain(self) {
+        match self {
+            Object::File(fd_type) => super::syscall::retain_file(fd_type),
+            Object::Port(_) => {}
+            Object::Shm(id) => super::shm::retain(id),
+            Object::Timer(id) => super::waitable::retain_timer(id),
+            Object::Event(id) => super::waitable::retain_event(id),
+        }
+    }
+
+    /// Drop the reference of a closed handle, freeing the object with the
+    /// last one
+    pub fn release(self) {
+        match self {
+            Object::File(fd_type) => super::syscall::release_file(fd_type),
+            Object::Port(_) => {}
+            Object::Shm(id) => super::shm::release(id),
+            Object::Timer(id) => super::waitable::release_timer(id),
+            Object::Event(id) => super::waitable::release_event(id),
+        }
+    }
+}
+
+/// Entry of a handle table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handle {
+    pub object: Object,
+    pub rights: Rights,
+    /// Handle flags (`FD_CLOEXEC`)
+    pub fd_flags: u32,
+    /// File status flags (`O_NONBLOCK`, `O_APPEND`)
+    pub status_flags: u32,
+}
+
+impl Handle {
+    /// Handle with all rights and no flags
+    pub const fn new(object: Object) -> Self {
+        Self::with_flags(object, 0, 0)
+    }
+
+    pub const fn with_flags(object: Object, fd_flags: u32, status_flags: u32) -> Self {
+        Self {
+            object,
+            rights: Rights::ALL,
+            fd_flags,
+            status_flags,
+        }
+    }
+}
+
+/// Handle errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleError {
+    /// The handle is empty or out of range
+    BadHandle,
+    /// The handle lacks a right for the operation
+    PermissionDenied,
+    /// Every handle is in use
+    TableFull,
+}
+
+/// A process's handles, indexed by [`HandleId`]
+#[derive(Debug, Clone, Copy)]
+pub struct HandleTable {
+    slots: [Option<Handle>; MAX_HANDLES],
+}
+
+impl HandleTable {
+    pub const fn new() -> Self {
+        Self { slots: [None; MAX_HANDLES] }
+    }
+
+    /// Table of a task started by the kernel: handle N is system port N,
+    /// past the stdio handles
+    pub const fn system() -> Self {
+        let mut table = Self::new();
+        let mut port = STDIO_HANDLES;
+        while port < super::port::SYSTEM_PORTS {
+            table.slots[port] = Some(Handle::new(Object::Port(port)));
+            port += 1;
+        }
+        table
+    }
+
+    /// Store `handle` in the lowest free slot past the stdio handles and
+    /// return its id
+    ///
+    /// The caller passes on the reference it holds to the object.
+    pub fn insert(&mut self, handle: Handle) -> Result<HandleId, HandleError> {
+        let id = (STDIO_HANDLES..MAX_HANDLES)
+            .find(|&id| self.slots[id].is_none())
+            .ok_or(HandleError::TableFull)?;
+        self.slots[id] = Some(handle);
+        Ok(id)
+    }
+
+    /// Store `handle` at `id`; returns the handle it replaced, which the
+    /// caller must release
+    pub fn insert_at(&mut self, id: HandleId, handle: Handle) -> Result<Option<Handle>, HandleError> {
+        let slot = self.slots.get_mut(id).ok_or(HandleError::BadHandle)?;
+        Ok(slot.replace(handle))
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.slots[STDIO_HANDLES..].iter().all(|slot| slot.is_some())
+    }
+
+    pub fn get(&self, id: HandleId) -> Option<Handle> {
+        self.slots.get(id).copied().flatten()
+    }
+
+    pub fn get_mut(&mut self, id: HandleId) -> Option<&mut Handle> {
+        self.slots.get_mut(id)?.as_mut()
+    }
+
+    /// Handle `id` if it has all of `rights`
+    pub fn check(&self, id: HandleId, rights: Rights) -> Result<Handle, HandleError> {
+        let handle = self.get(id).ok_or(HandleError::BadHandle)?;
+        if !handle.rights.contains(rights) {
+            return Err(HandleError::PermissionDenied);
+        }
+        Ok(handle)
+    }
+
+    /// Port behind handle `id` if it is a port handle with all of `rights`
+    pub fn port(&self, id: HandleId, rights: Rights) -> Result<usize, IpcError> {
+        match self.check(id, rights) {
+            Ok(Handle { object: Object::Port(port), .. }) => Ok(port),
+            Ok(_) | Err(HandleError::BadHandle) => Err(IpcError::InvalidCapability),
+            Err(_) => Err(IpcError::PermissionDenied),
+        }
+    }
+
+    /// Copy of handle `id` limited to `rights`, in a new slot, with a new
+    /// reference to the object
+    ///
+    /// The copy does not inherit `FD_CLOEXEC`.
+    pub fn duplicate(&mut self, id: HandleId, rights: Rights) -> Result<HandleId, HandleError> {
+        let handle = self.get(id).ok_or(HandleError::BadHandle)?;
+        let copy = Handle { rights: handle.rights.intersect(rights), fd_flags: 0, ..handle };
+        let new_id = self.insert(copy)?;
+        handle.object.retain();
+        Ok(new_id)
+    }
+
+    /// Take handle `id` out of the table; the caller must release it
+    pub fn remove(&mut self, id: HandleId) -> Option<Handle> {
+        self.slots.get_mut(id)?.take()
+    }
+}
+
+/// Whether a new process gets a copy of `handle`; `exec` leaves out the
+/// `FD_CLOEXEC` handles
+fn inherits(handle: &Handle, exec: bool) -> bool {
+    !exec || handle.fd_flags & super::syscall::FD_CLOEXEC == 0
+}
+
+/// Tables by task table slot of the PID
+///
+/// Kept out of the task struct, which lives on kernel stacks while being
+/// built. A task started by the kernel finds the system table in its slot;
+/// [`release`] puts it back when a process is reaped.
+static TABLES: [SpinLock<HandleTable>; MAX_TASKS] = [const { SpinLock::new(HandleTable::system()) }; MAX_TASKS];
+
+/// Handle table of `task_id`'s process
+///
+/// Threads use the table of the task that created their process, like
+/// they use its address space.
+pub fn table_of(task_id: TaskId) -> Option<&'static SpinLock<HandleTable>> {
+    TABLES.get(task_slot(crate::sched::get_task_by_id(task_id)?.pid))
+}
+
+/// Handle table of the current process
+pub fn current() -> Option<&'static SpinLock<HandleTable>> {
+    crate::sched::get_current_task_info().and_then(|(id, _)| table_of(id))
+}
+
+/// Give process `child` a copy of the handles of `parent`'s process, each
+/// with a new reference; `exec` leaves out the `FD_CLOEXEC` handles
+pub fn inherit(parent: TaskId, child: Pid, exec: bool) {
+    let (Some(from), Some(to)) = (table_of(parent), TABLES.get(task_slot(child))) else { return };
+    close_all(child);
+    for id in 0..MAX_HANDLES {
+        let handle = {
+            let from = from.lock();
+            let handle = from.get(id).filter(|handle| inherits(handle, exec));
+            if let Some(handle) = handle {
+                handle.object.retain();
+            }
+            handle
+        };
+        to.lock().slots[id] = handle;
+    }
+}
+
+/// Close handle `id` of the current process
+pub fn close(id: HandleId) -> Result<Handle, HandleError> {
+    let table = current().ok_or(HandleError::BadHandle)?;
+    let handle = table.lock().remove(id).ok_or(HandleError::BadHandle)?;
+    handle.object.release();
+    Ok(handle)
+}
+
+/// Put `handle` at `id` in the current process, closing what was there
+///
+/// Takes over the caller's reference to the object.
+pub fn install_at(id: HandleId, handle: Handle) -> Result<(), HandleError> {
+    let replaced = match current() {
+        Some(table) => table.lock().insert_at(id, handle),
+        None => Err(HandleError::BadHandle),
+    };
+    match replaced {
+        Ok(replaced) => {
+            if let Some(old) = replaced {
+                old.object.release();
+            }
+            Ok(())
+        }
+        Err(e) => {
+            handle.object.release();
+            Err(e)
+        }
+    }
+}
+
+/// Add `handle` to the current process; returns its id
+///
+/// Takes over the caller's reference to the object, and drops it if there
+/// is no free slot.
+pub fn install(handle: Handle) -> Result<HandleId, HandleError> {
+    let result = match current() {
+        Some(table) => table.lock().insert(handle),
+        None => Err(HandleError::BadHandle),
+    };
+    if result.is_err() {
+        handle.object.release();
+    }
+    result
+}
+
+/// Close the handles of process `pid` that `close` selects
+fn close_where(pid: Pid, close: impl Fn(&Handle) -> bool) {
+    let Some(table) = TABLES.get(task_slot(pid)) else { return };
+    for id in 0..MAX_HANDLES {
+        let handle = {
+            let mut table = table.lock();
+            match table.get(id) {
+                Some(handle) if close(&handle) => table.remove(id),
+                _ => None,
+            }
+        };
+        if let Some(handle) = handle {
+            handle.object.release();
+        }
+    }
+}
+
+/// Close the `FD_CLOEXEC` handles of process `pid` (exec)
+pub fn close_on_exec(pid: Pid) {
+    close_where(pid, |handle| !inherits(handle, true));
+}
+
+/// Close every handle of process `pid` (exit)
+pub fn close_all(pid: Pid) {
+    close_where(pid, |_| true);
+}
+
+/// Close every handle of reaped process `pid` and give its slot the
+/// system table again, for the task that reuses the slot
+pub fn release(pid: Pid) {
+    close_all(pid);
+    if let Some(table) = TABLES.get(task_slot(pid)) {
+        *table.lock() = HandleTable::system();
+    }
+}
+
+crate::kernel_test! {
+    /// Handles are numbered past stdio, copies narrow rights, and exec
+    /// inheritance skips close-on-exec handles
+    fn handle_table_semantics() {
+        static SYSTEM: HandleTable = HandleTable::system();
+        let mut table = HandleTable::new();
+        let full = table.insert(Handle::new(Object::Port(40))).map_err(|_| "insert failed")?;
+        crate::ktest_assert_eq!(full, STDIO_HANDLES, "first handle not past stdio");
+        let send = table.duplicate(full, Rights::SEND).map_err(|_| "duplicate failed")?;
+        crate::ktest_assert_eq!(table.port(send, Rights::SEND), Ok(40), "send right");
+        crate::ktest_assert_eq!(table.port(send, Rights::RECV), Err(IpcError::PermissionDenied), "recv on send-only");
+
+        // Copying cannot add rights back
+        let again = table.duplicate(send, Rights::ALL).map_err(|_| "duplicate failed")?;
+        crate::ktest_assert_eq!(table.get(again).map(|h| h.rights), Some(Rights::SEND), "rights widened");
+
+        let shm = Handle::with_flags(Object::Shm(0), super::syscall::FD_CLOEXEC, 0);
+        crate::ktest_assert!(!inherits(&shm, true), "close-on-exec handle inherited by exec");
+        crate::ktest_assert!(inherits(&shm, false), "close-on-exec handle not inherited by fork");
+        let shm = table.insert(shm).map_err(|_| "insert failed")?;
+        crate::ktest_assert_eq!(table.port(shm, Rights::SEND), Err(IpcError::InvalidCapability), "shm used as port");
+
+        crate::ktest_assert!(table.remove(full).is_some(), "remove failed");
+        crate::ktest_assert_eq!(table.port(full, Rights::SEND), Err(IpcError::InvalidCapability), "stale handle");
+        crate::ktest_assert_eq!(SYSTEM.port(3, Rights::ALL), Ok(3), "system port handle");
+        crate::ktest_assert!(SYSTEM.get(1).is_none(), "system port over stdout");
+        crate::ktest_assert_eq!(Rights::from_bits(8), None, "unknown right accepted");
+        Ok(())
+    }
+}
@@ -1,5 +1,24 @@
 //! IPC subsystem module
 //! Provides message passing between tasks via ports
+//!
+//! Besides blocking send/receive, an event-driven server can:
+//! - receive without blocking (`IPC_NONBLOCK` in the port argument)
+//! - be signalled through its notification bits ([`notify`],
+//!   `SYS_IPC_NOTIFY`), a cheap message-less wakeup
+//! - wait on many ports and notification bits at once, with a timeout
+//!   ([`poll`], `SYS_IPC_POLL`)
+//! - receive with a timeout ([`recv_timeout`], `SYS_IPC_RECV_TIMEOUT`)
+//!
+//! Blocking receives and polls end early with [`IpcError::Interrupted`]
+//! when a signal is pending for the task.
+
+use super::handle::Handle;
+use super::port::PORT_MANAGER;
+use crate::sched::task::{Task, TaskId};
+use crate::sched::wait::WaitError;
+use crate::sync::Poller;
+use crate::time::{Duration, Instant};
+use core::sync::atomic::Ordering;
 
 /// Maximum message size in bytes
 pub const MAX_MESSAGE_SIZE: usize = 4096;
@@ -19,6 +38,194 @@ pub enum IpcError {
     MessageTooLarge,
     /// Feature not implemented yet
     NotImplemented,
+    /// Non-blocking receive found the queue empty
+    WouldBlock,
+    /// Notification target does not exist
+    TaskNotFound,
+    /// Handle is empty, out of range or not a port
+    InvalidCapability,
+    /// Handle lacks the right for the operation
+    PermissionDenied,
+    /// Every port is in use
+    NoFreePort,
+    /// The receive timeout passed with no message
+    TimedOut,
+    /// A signal for the task ended the wait
+    Interrupted,
+}
+
+impl From<WaitError> for IpcError {
+    fn from(error: WaitError) -> Self {
+        match error {
+            WaitError::TimedOut => IpcError::TimedOut,
+            WaitError::Interrupted => IpcError::Interrupted,
+            WaitError::QueueFull => IpcError::QueueFull,
+            WaitError::NoTask => IpcError::TaskNotFound,
+        }
+    }
+}
+
+/// Flag OR-ed into the port argument of `SYS_IPC_SEND`/`SYS_IPC_RECV`
+///
+/// A non-blocking receive on an empty port returns 0 (messages are never
+/// empty). Sends never block, so the flag changes nothing for them: a full
+/// queue always fails the send.
+pub const IPC_NONBLOCK: usize = 1 << 31;
+
+/// Shift of a handle passed along with a message
+///
+/// `SYS_IPC_SEND` takes `(handle + 1) << IPC_CAP_SHIFT` OR-ed into its
+/// first argument to transfer that handle, of any kind (it needs the grant
+/// right); `SYS_IPC_RECV` returns the receiver's new handle the same way,
+/// OR-ed into the byte count.
+pub const IPC_CAP_SHIFT: u32 = 32;
+
+/// `SYS_IPC_POLL` timeout meaning "no timeout"
+pub const IPC_WAIT_FOREVER: usize = usize::MAX;
+
+/// Number of IPC ports
+pub const MAX_PORTS: usize = 256;
+
+/// Ports and notification bits to wait for (`SYS_IPC_POLL`)
+///
+/// On return it holds what is ready: the ports with a message queued and
+/// the notification bits that were raised. Reported bits are cleared from
+/// the task; messages stay queued until received.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IpcWaitSet {
+    /// One bit per port id
+    pub ports: [u64; MAX_PORTS / 64],
+    /// Notification bits
+    pub notify: u64,
+}
+
+mello_abi::check_layout!(IpcWaitSet, mello_abi::IpcWaitSet { ports, notify });
+
+impl IpcWaitSet {
+    pub fn add_port(&mut self, port_id: usize) {
+        self.ports[port_id / 64] |= 1 << (port_id % 64);
+    }
+
+    pub fn has_port(&self, port_id: usize) -> bool {
+        self.ports[port_id / 64] & (1 << (port_id % 64)) != 0
+    }
+
+    /// Port ids in the set
+    pub fn port_ids(&self) -> impl Iterator<Item = usize> + Clone + '_ {
+        (0..MAX_PORTS).filter(|&port_id| self.has_port(port_id))
+    }
+
+    /// Number of ready ports, plus one if any notification bit is set
+    pub fn count(&self) -> usize {
+        let ports: u32 = self.ports.iter().map(|word| word.count_ones()).sum();
+        ports as usize + (self.notify != 0) as usize
+    }
+}
+
+/// Raise notification `bits` on task `task_id`
+///
+/// Wakes the task if it is waiting for any of them in [`poll`].
+pub fn notify(task_id: TaskId, bits: u64) -> Result<(), IpcError> {
+    let task = crate::sched::get_task_by_id(task_id).ok_or(IpcError::TaskNotFound)?;
+    task.notifications.fetch_or(bits, Ordering::SeqCst);
+    if task.notify_wait.load(Ordering::SeqCst) & bits != 0 {
+        crate::sched::wake_task(task_id);
+    }
+    Ok(())
+}
+
+/// What in `watch` is ready for `task`; reported notification bits are
+/// cleared when `take` is set
+fn ready(task: &Task, watch: &IpcWaitSet, take: bool) -> Result<IpcWaitSet, IpcError> {
+    let mut ready = IpcWaitSet::default();
+    {
+        let mut ports = PORT_MANAGER.lock();
+        for port_id in watch.port_ids() {
+            if ports.has_message(port_id)? {
+                ready.add_port(port_id);
+            }
+        }
+    }
+    ready.notify = if take {
+        task.notifications.fetch_and(!watch.notify, Ordering::SeqCst)
+    } else {
+        task.notifications.load(Ordering::SeqCst)
+    } & watch.notify;
+    Ok(ready)
+}
+
+/// Wait until a port in `set` has a message or a notification bit in `set`
+/// is raised on the current task `task_id`
+///
+/// `timeout` of `None` waits forever; a zero timeout just checks. Replaces
+/// `set` with what is ready and returns [`IpcWaitSet::count`] of it, 0 on
+/// timeout. A pending signal ends the wait with [`IpcError::Interrupted`].
+pub fn poll(task_id: TaskId, set: &mut IpcWaitSet, timeout: Option<Duration>) -> Result<usize, IpcError> {
+    let task = crate::sched::get_task_by_id(task_id).ok_or(IpcError::TaskNotFound)?;
+    let watch = *set;
+    let deadline = timeout.map(|timeout| Instant::now().saturating_add(timeout));
+    let poller = Poller::current().ok_or(IpcError::TaskNotFound)?.interruptible();
+
+    loop {
+        let found = ready(task, &watch, true)?;
+        if found.count() > 0 {
+            *set = found;
+            return Ok(found.count());
+        }
+        if poller.interrupted() {
+            return Err(IpcError::Interrupted);
+        }
+        let remaining = match deadline {
+            Some(deadline) if deadline.has_passed() => {
+                *set = IpcWaitSet::default();
+                return Ok(0);
+            }
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::MAX,
+        };
+
+        // Notifications wake the task directly; ports through their queues
+        task.notify_wait.store(watch.notify, Ordering::SeqCst);
+        let queues = watch.port_ids().filter_map(super::port::wait_queue);
+        let waited = poller.wait(queues, remaining, || {
+            ready(task, &watch, false).map_or(true, |found| found.count() > 0)
+        });
+        task.notify_wait.store(0, Ordering::SeqCst);
+        if !waited {
+            return Err(IpcError::QueueFull);
+        }
+    }
+}
+
+
+/// Receive a message from `port_id`, waiting at most `timeout` for one
+///
+/// `deliver` gets the message as in `PortManager::recv_message_with`.
+/// Fails with [`IpcError::TimedOut`] once the timeout passes; a zero timeout
+/// just checks. `Duration::MAX` waits until a message arrives or a signal
+/// interrupts.
+pub fn recv_timeout(
+    port_id: usize,
+    timeout: Duration,
+    deliver: &mut dyn FnMut(&Message) -> usize,
+) -> Result<usize, IpcError> {
+    let queue = super::port::wait_queue(port_id).ok_or(IpcError::InvalidPort)?;
+    let deadline = Instant::now().saturating_add(timeout);
+    loop {
+        match PORT_MANAGER.lock().recv_message_with(port_id, 0, true, deliver) {
+            Err(IpcError::WouldBlock) => {}
+            result => return result,
+        }
+        // Another receiver may take the message first: then wait again
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(IpcError::TimedOut);
+        }
+        crate::sched::wait::wait_event_timeout(queue, left, || {
+            PORT_MANAGER.lock().has_message(port_id).unwrap_or(true)
+        })?;
+    }
 }
 
 /// Message structure for IPC
@@ -31,6 +238,9 @@ pub struct Message {
     pub data: [u8; MAX_MESSAGE_SIZE],
     /// Actual length of the message
     pub len: usize,
+    /// Handle transferred to the receiver, holding a reference to its
+    /// object while in flight
+    pub handle: Option<Handle>,
 }
 
 impl Message {
@@ -39,6 +249,7 @@ impl Message {
         Self {
             data: [0; MAX_MESSAGE_SIZE],
             len: 0,
+            handle: None,
         }
     }
 
@@ -72,3 +283,46 @@ impl Message {
         &self.data[..self.len]
     }
 }
+
+crate::kernel_test! {
+    /// Non-blocking receives and readiness checks leave the queue intact
+    fn ipc_nonblock_and_readiness() {
+        // A system port nothing else uses while the tests run
+        const PORT: usize = 15;
+        let mut ports = PORT_MANAGER.lock();
+
+        let mut buf = [0u8; 8];
+        crate::ktest_assert_eq!(
+            ports.recv_message_with(PORT, 0, true, &mut |_: &Message| 0),
+            Err(IpcError::WouldBlock),
+            "empty queue did not refuse"
+        );
+        crate::ktest_assert_eq!(ports.has_message(PORT), Ok(false), "empty port ready");
+        ports.send_message(PORT, b"hi").map_err(|_| "send failed")?;
+        crate::ktest_assert_eq!(ports.has_message(PORT), Ok(true), "port not ready");
+        crate::ktest_assert_eq!(ports.has_message(PORT), Ok(true), "readiness check took the message");
+        let len = ports.recv_message(PORT, 0, true, &mut buf);
+        crate::ktest_assert_eq!(len, Ok(2), "message not received");
+        crate::ktest_assert_eq!(&buf[..2], b"hi", "payload");
+        crate::ktest_assert_eq!(ports.has_message(PORT), Ok(false), "queue not drained");
+        drop(ports);
+
+        let mut set = IpcWaitSet::default();
+        set.add_port(3);
+        set.add_port(200);
+        set.notify = 1;
+        let mut ids = set.port_ids();
+        crate::ktest_assert_eq!((ids.next(), ids.next(), ids.next()), (Some(3), Some(200), None), "port ids");
+        crate::ktest_assert_eq!(set.count(), 3, "count");
+
+        let mut deliver = |message: &Message| message.len();
+        crate::ktest_assert_eq!(
+            recv_timeout(PORT, Duration::ZERO, &mut deliver),
+            Err(IpcError::TimedOut),
+            "empty port did not time out"
+        );
+        PORT_MANAGER.lock().send_message(PORT, b"late").map_err(|_| "send failed")?;
+        crate::ktest_assert_eq!(recv_timeout(PORT, Duration::ZERO, &mut deliver), Ok(4), "queued message");
+        Ok(())
+    }
+}
@@ -7,8 +7,20 @@
 //! # Components
 //!
 //! - **syscall**: System call entry point, dispatcher, and handlers
+//! - **errno**: Error numbers returned by failed syscalls
 //! - **ipc**: IPC message structures and error types
 //! - **port**: Port management and message queuing
+//! - **handle**: Per-process handle table naming files, ports, shared memory,
+//!   timers and events
+//! - **event**: Kernel event broadcast to subscribed ports
+//! - **exit_watch**: Exit notifications for ports watching a task
+//! - **shm**: Shared memory objects for bulk data between tasks
+//! - **waitable**: Timer and event objects that user tasks read and poll
+//! - **perf**: Sampled user-RIP profiling into a shared memory ring
+//! - **poll**: Waiting on many fds and IPC ports at once
+//! - **futex**: Sleeping on user memory words, for user-space locks and thread join
+//! - **strace**: Per-task syscall tracing into the kernel log ring
+//! - **seccomp**: Per-task syscall filtering for sandboxing
 //!
 //! # System Calls
 //!
@@ -56,22 +68,33 @@
 //! let msg = "Hello from userland!\n";
 //! syscall(0, 0, msg.as_ptr() as usize, msg.len());
 //!
-//! // Send IPC message
+//! // Send IPC message to the port of handle 3
 //! let data = b"ping";
-//! syscall(3, 2, data.as_ptr() as usize, data.len());
+//! syscall(3, 3, data.as_ptr() as usize, data.len());
 //!
-//! // Receive IPC message (blocking)
+//! // Receive IPC message (blocking) on the port of handle 4
 //! let mut buf = [0u8; 64];
-//! let bytes = syscall(4, 1, buf.as_mut_ptr() as usize, buf.len());
+//! let bytes = syscall(4, 4, buf.as_mut_ptr() as usize, buf.len());
 //!
 //! // Sleep for 100 ticks
 //! syscall(2, 100, 0, 0);
 //! ```
 
+pub mod errno;
+pub mod event;
+pub mod exit_watch;
+pub mod futex;
+pub mod handle;
 pub mod ioctl;
 pub mod ipc;
+pub mod perf;
+pub mod poll;
 pub mod port;
+pub mod seccomp;
+pub mod shm;
+pub mod strace;
 pub mod syscall;
+pub mod waitable;
 
 use core::sync::atomic::{AtomicUsize, Ordering};
 
@@ -0,0 +1,237 @@
+//! Sampled user-RIP profiling
+//!
+//! `SYS_PERF` asks the kernel to record, every `period` timer ticks that
+//! interrupt a task in user mode, the instruction pointer the task was at.
+//! Samples go into a ring in a shared memory object the profiler created
+//! and mapped itself, so it reads them without further syscalls. A task
+//! may profile itself or one of its children.
+//!
+//! The ring is an array of u64 words: a header (`head`, `tail`,
+//! `capacity`, `dropped`) followed by `capacity` samples. Sample `n` is at
+//! word `RING_HEADER_WORDS + n % capacity`. The kernel advances `head`;
+//! the reader advances `tail` once it has consumed samples. While the ring
+//! is full new samples are counted in `dropped` rather than overwriting
+//! unread ones.
+//!
+//! Sampling runs off the scheduler tick, so the finest period is one tick
+//! (`config::SCHED_HZ`).
+
+use crate::mm::{phys_to_virt, PhysAddr};
+use crate::sched::task::TaskId;
+use spin::Mutex;
+
+/// Profiles running at once
+const MAX_SESSIONS: usize = 8;
+
+/// Largest ring, in pages (64 KiB)
+pub const MAX_RING_PAGES: usize = 16;
+
+/// Words before the first sample: head, tail, capacity, dropped
+pub const RING_HEADER_WORDS: usize = 4;
+
+const HEAD: usize = 0;
+const TAIL: usize = 1;
+const CAPACITY: usize = 2;
+const DROPPED: usize = 3;
+
+const PAGE_SIZE: usize = 4096;
+const WORDS_PER_PAGE: usize = PAGE_SIZE / 8;
+
+/// Profiling errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfError {
+    /// Zero period
+    InvalidArgument,
+    /// No shared memory object with this id
+    NoSuchObject,
+    /// The target is not profiled
+    NotProfiled,
+    /// Session table is full
+    TooManySessions,
+}
+
+/// A task being profiled
+struct Session {
+    target: TaskId,
+    /// Task that started the profile
+    owner: TaskId,
+    /// Shared memory object holding the ring (0: none, in tests)
+    shm_id: usize,
+    frames: [PhysAddr; MAX_RING_PAGES],
+    capacity: u64,
+    /// Ticks between samples
+    period: u32,
+    /// Ticks until the next sample
+    countdown: u32,
+    /// Kernel copies of the counters; the ring is writable by user space
+    head: u64,
+    dropped: u64,
+}
+
+impl Session {
+    fn new(target: TaskId, owner: TaskId, shm_id: usize, frames: &[PhysAddr], period: u32) -> Self {
+        let mut session = Self {
+            target,
+            owner,
+            shm_id,
+            frames: [PhysAddr::new(0); MAX_RING_PAGES],
+            capacity: (frames.len() * WORDS_PER_PAGE - RING_HEADER_WORDS) as u64,
+            period,
+            countdown: period,
+            head: 0,
+            dropped: 0,
+        };
+        session.frames[..frames.len()].copy_from_slice(frames);
+        session.store(HEAD, 0);
+        session.store(TAIL, 0);
+        session.store(CAPACITY, session.capacity);
+        session.store(DROPPED, 0);
+        session
+    }
+
+    fn word(&self, index: usize) -> *mut u64 {
+        let frame = self.frames[index / WORDS_PER_PAGE];
+        (phys_to_virt(frame) + index % WORDS_PER_PAGE * 8).as_mut_ptr::<u64>()
+    }
+
+    fn load(&self, index: usize) -> u64 {
+        unsafe { core::ptr::read_volatile(self.word(index)) }
+    }
+
+    fn store(&self, index: usize, value: u64) {
+        unsafe { core::ptr::write_volatile(self.word(index), value) }
+    }
+
+    /// Count a tick and take a sample at `rip` if the period is up
+    fn tick(&mut self, rip: u64) {
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return;
+        }
+        self.countdown = self.period;
+
+        // A reader claiming to be ahead of the kernel has read nothing
+        let tail = self.load(TAIL).min(self.head);
+        if self.head - tail >= self.capacity {
+            self.dropped += 1;
+            self.store(DROPPED, self.dropped);
+            return;
+        }
+        let slot = RING_HEADER_WORDS + (self.head % self.capacity) as usize;
+        self.store(slot, rip);
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
+        self.head += 1;
+        self.store(HEAD, self.head);
+    }
+
+    fn end(self) {
+        if self.shm_id != 0 {
+            super::shm::release(self.shm_id);
+        }
+    }
+}
+
+static SESSIONS: Mutex<[Option<Session>; MAX_SESSIONS]> = Mutex::new([const { None }; MAX_SESSIONS]);
+
+/// Profile `target` every `period` ticks into shared memory object `shm_id`
+///
+/// The ring uses the first `MAX_RING_PAGES` pages of the object at most and
+/// is reset. A profile already running on `target` is replaced.
+pub fn start(owner: TaskId, target: TaskId, shm_id: usize, period: u32) -> Result<(), PerfError> {
+    if period == 0 {
+        return Err(PerfError::InvalidArgument);
+    }
+    let mut frames = [PhysAddr::new(0); MAX_RING_PAGES];
+    let mut pages = 0;
+    while pages < MAX_RING_PAGES {
+        match super::shm::frame(shm_id, pages) {
+            Some(frame) => frames[pages] = frame,
+            None => break,
+        }
+        pages += 1;
+    }
+    if pages == 0 {
+        return Err(PerfError::NoSuchObject);
+    }
+
+    let mut sessions = SESSIONS.lock();
+    let slot = match sessions.iter().position(|s| s.as_ref().map_or(false, |s| s.target == target)) {
+        Some(index) => index,
+        None => sessions.iter().position(Option::is_none).ok_or(PerfError::TooManySessions)?,
+    };
+    super::shm::retain(shm_id);
+    if let Some(old) = sessions[slot].replace(Session::new(target, owner, shm_id, &frames[..pages], period)) {
+        old.end();
+    }
+    Ok(())
+}
+
+/// Stop profiling `target`
+pub fn stop(target: TaskId) -> Result<(), PerfError> {
+    let mut sessions = SESSIONS.lock();
+    let slot = sessions
+        .iter_mut()
+        .find(|s| s.as_ref().map_or(false, |s| s.target == target))
+        .ok_or(PerfError::NotProfiled)?;
+    if let Some(session) = slot.take() {
+        session.end();
+    }
+    Ok(())
+}
+
+/// Timer tick that interrupted the current task in user mode at `rip`
+///
+/// Interrupt context: skips the tick if the session table is locked.
+pub fn sample(rip: u64) {
+    let Some(task) = crate::arch::x86_64::smp::percpu::percpu_current().current_task else { return };
+    let Some(mut sessions) = SESSIONS.try_lock() else { return };
+    if let Some(session) = sessions.iter_mut().flatten().find(|s| s.target == task) {
+        session.tick(rip);
+    }
+}
+
+/// End the profiles of and by an exiting task
+pub fn task_exit(task: TaskId) {
+    let mut sessions = SESSIONS.lock();
+    for slot in sessions.iter_mut() {
+        if slot.as_ref().map_or(false, |s| s.target == task || s.owner == task) {
+            if let Some(session) = slot.take() {
+                session.end();
+            }
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// Samples land in the ring at the period; a full ring drops
+    fn perf_ring() {
+        let frame = crate::mm::with_memory_managers(|pmm, _| pmm.alloc_frame().ok_or("out of memory"))?;
+        let mut session = Session::new(1, 1, 0, &[frame], 2);
+        let capacity = (WORDS_PER_PAGE - RING_HEADER_WORDS) as u64;
+        crate::ktest_assert_eq!(session.load(CAPACITY), capacity, "capacity");
+
+        for rip in 0..5 {
+            session.tick(0x1000 + rip);
+        }
+        crate::ktest_assert_eq!(session.load(HEAD), 2, "samples at period 2");
+        crate::ktest_assert_eq!(session.load(RING_HEADER_WORDS), 0x1001, "first sample");
+        crate::ktest_assert_eq!(session.load(RING_HEADER_WORDS + 1), 0x1003, "second sample");
+
+        // Fill the ring without reading: the overflow is dropped
+        session.period = 1;
+        session.countdown = 1;
+        for _ in 0..capacity {
+            session.tick(0x2000);
+        }
+        crate::ktest_assert_eq!(session.load(HEAD), capacity, "ring not full");
+        crate::ktest_assert_eq!(session.load(DROPPED), 2, "overflow not dropped");
+        crate::ktest_assert_eq!(session.load(RING_HEADER_WORDS), 0x1001, "unread sample overwritten");
+
+        session.end();
+        let _ = crate::mm::with_memory_managers(|pmm, _| {
+            pmm.free_frame(frame);
+            Ok(())
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Readiness multiplexing (`SYS_POLL`)
+//!
+//! A task passes an array of [`PollFd`] entries, each naming a handle (see
+//! `sys::handle`) of any kind: a pipe, the console, a PTY, a port (with the
+//! receive right), a timer or an event, and the events it cares about.
+//! The call returns as soon as any entry is ready, or when the timeout
+//! passes, with `revents` filled in.
+//!
+//! Waiting goes through the objects' wait queues (`sync::wait_queue`): the
+//! task sleeps on the queue of every entry at once and rescans all entries
+//! whenever one of them is woken. Console input is serial RX, which has no
+//! interrupt; the scheduler tick wakes its queue once a byte arrives. There
+//! is no keyboard driver, so the console fd is also the keyboard. Timers
+//! have no queue; the wait ends by the earliest timer expiry instead.
+
+use super::handle::{Handle, Object, Rights};
+use super::syscall::{object_events, object_wait_queue, POLLERR, POLLHUP, POLLNVAL};
+use super::waitable;
+use crate::sync::{Poller, WaitQueue};
+use crate::time::{Duration, Instant};
+
+/// Flag in [`PollFd::fd`] once needed to name a port handle; ports and
+/// files now share the handle table, so it is ignored
+pub const POLL_PORT: i32 = 1 << 30;
+
+/// Most entries in one call
+pub const MAX_POLL_FDS: usize = 32;
+
+/// `SYS_POLL` timeout meaning "no timeout"
+pub const POLL_WAIT_FOREVER: usize = usize::MAX;
+
+/// One entry of the `SYS_POLL` array
+///
+/// Entries with a negative `fd` are skipped. `POLLHUP`, `POLLERR` and
+/// `POLLNVAL` are reported whether asked for or not.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PollFd {
+    pub fd: i32,
+    /// Events to wait for
+    pub events: u16,
+    /// Events that are ready, filled in by the kernel
+    pub revents: u16,
+}
+
+mello_abi::check_layout!(PollFd, mello_abi::PollFd { fd, events, revents });
+
+/// Polling errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollError {
+    /// More than `MAX_POLL_FDS` entries
+    TooManyEntries,
+    /// Called without a current task
+    NoTask,
+    /// An object has too many waiters already
+    QueueFull,
+}
+
+/// What an entry refers to, resolved once per call
+#[derive(Clone, Copy)]
+enum Target {
+    Skip,
+    Object(Object),
+    Invalid,
+}
+
+fn resolve(lookup: &impl Fn(usize) -> Option<Handle>, fd: i32) -> Target {
+    if fd < 0 {
+        return Target::Skip;
+    }
+    match lookup((fd & !POLL_PORT) as usize) {
+        Some(handle) if matches!(handle.object, Object::Port(_)) && !handle.rights.contains(Rights::RECV) => {
+            Target::Invalid
+        }
+        Some(handle) => Target::Object(handle.object),
+        None => Target::Invalid,
+    }
+}
+
+fn events(target: Target) -> u16 {
+    match target {
+        Target::Skip => 0,
+        Target::Object(object) => object_events(object).unwrap_or(POLLNVAL),
+        Target::Invalid => POLLNVAL,
+    }
+}
+
+fn wait_queue(target: Target) -> Option<&'static WaitQueue> {
+    match target {
+        Target::Object(object) => object_wait_queue(object),
+        Target::Skip | Target::Invalid => None,
+    }
+}
+
+/// Earliest expiry of the timers among `targets`
+fn next_expiry(targets: &[Target]) -> Option<Instant> {
+    targets
+        .iter()
+        .filter_map(|&target| match target {
+            Target::Object(Object::Timer(id)) => waitable::timer_deadline(id),
+            _ => None,
+        })
+        .min()
+}
+
+/// Fill in `revents` of every entry; returns how many are ready
+fn scan(fds: &mut [PollFd], targets: &[Target]) -> usize {
+    let mut ready = 0;
+    for (entry, &target) in fds.iter_mut().zip(targets) {
+        entry.revents = events(target) & (entry.events | POLLHUP | POLLERR | POLLNVAL);
+        ready += (entry.revents != 0) as usize;
+    }
+    ready
+}
+
+/// Wait until an entry of `fds` is ready; handles are resolved with
+/// `lookup`
+///
+/// `timeout` of `None` waits forever; a zero timeout just checks. Returns
+/// the number of ready entries, 0 on timeout.
+pub fn poll(
+    lookup: impl Fn(usize) -> Option<Handle>,
+    fds: &mut [PollFd],
+    timeout: Option<Duration>,
+) -> Result<usize, PollError> {
+    if fds.len() > MAX_POLL_FDS {
+        return Err(PollError::TooManyEntries);
+    }
+    let mut targets = [Target::Skip; MAX_POLL_FDS];
+    for (target, entry) in targets.iter_mut().zip(fds.iter()) {
+        *target = resolve(&lookup, entry.fd);
+    }
+    let targets = &targets[..fds.len()];
+    let deadline = timeout.map(|timeout| Instant::now().saturating_add(timeout));
+
+    loop {
+        let ready = scan(fds, targets);
+        if ready > 0 {
+            return Ok(ready);
+        }
+        let remaining = match deadline {
+            Some(deadline) if deadline.has_passed() => return Ok(0),
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::MAX,
+        };
+        let remaining = match next_expiry(targets) {
+            Some(expiry) => remaining.min(expiry.saturating_duration_since(Instant::now())),
+            None => remaining,
+        };
+
+        let poller = Poller::current().ok_or(PollError::NoTask)?;
+        let queues = targets.iter().filter_map(|&target| wait_queue(target));
+        if !poller.wait(queues, remaining, || scan(fds, targets) > 0) {
+            return Err(PollError::QueueFull);
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// Skipped, invalid and always-writable entries are reported without
+    /// waiting
+    fn poll_immediate_events() {
+        use super::syscall::{FdType, POLLIN, POLLOUT};
+
+        let lookup = |fd| (fd == 1).then(|| Handle::new(Object::File(FdType::Console)));
+        let mut fds = [
+            PollFd { fd: -1, events: POLLIN, revents: 0 },
+            PollFd { fd: 200, events: POLLIN, revents: 0 },
+            PollFd { fd: POLL_PORT | 3, events: POLLIN, revents: 0 },
+            PollFd { fd: 1, events: POLLOUT, revents: 0 },
+        ];
+        let ready = poll(lookup, &mut fds, Some(Duration::from_ticks(0))).map_err(|_| "poll failed")?;
+        crate::ktest_assert_eq!(ready, 3, "ready entries");
+        crate::ktest_assert_eq!(fds[0].revents, 0, "negative fd reported");
+        crate::ktest_assert_eq!(fds[1].revents, POLLNVAL, "closed fd not invalid");
+        crate::ktest_assert_eq!(fds[2].revents, POLLNVAL, "empty handle not invalid");
+        crate::ktest_assert_eq!(fds[3].revents, POLLOUT, "stdout not writable");
+
+        let mut many = [PollFd::default(); MAX_POLL_FDS + 1];
+        crate::ktest_assert!(poll(lookup, &mut many, None).is_err(), "oversized array accepted");
+        Ok(())
+    }
+}
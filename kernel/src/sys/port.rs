@@ -29,6 +29,7 @@
 
 use super::ipc::{IpcError, Message};
 use crate::sched::task::TaskId;
+use crate::sync::WaitQueue;
 use spin::Mutex;
 
 /// Maximum messages per port queue
@@ -37,6 +38,9 @@ const MAX_MESSAGES_PER_PORT: usize = 16;
 /// Maximum blocked tasks per port
 const MAX_BLOCKED_TASKS: usize = 64;
 
+/// Ports 0-15 are system ports, created for kernel use at boot
+pub const SYSTEM_PORTS: usize = 16;
+
 /// Simple circular queue for messages
 struct MessageQueue {
     messages: [Message; MAX_MESSAGES_PER_PORT],
@@ -200,6 +204,9 @@ pub struct PortManager {
     /// Array of optional ports (256 max)
     pub ports: [Option<Port>; 256],
 
+    /// Ports handed out by [`alloc_port`](Self::alloc_port)
+    allocated: [bool; 256],
+
     /// Lock for port creation/deletion
     pub table_lock: Mutex<()>,
 }
@@ -210,6 +217,7 @@ impl PortManager {
         const NONE_PORT: Option<Port> = None;
         Self {
             ports: [NONE_PORT; 256],
+            allocated: [false; 256],
             table_lock: Mutex::new(()),
         }
     }
@@ -239,6 +247,23 @@ impl PortManager {
         Ok(())
     }
 
+    /// Reserve a free port for `SYS_PORT_CREATE`
+    ///
+    /// Only ports created at boot are handed out: building a `Port` (over
+    /// 64 KiB) on a task's kernel stack would overflow it. Ports are not
+    /// returned yet, so each id is handed out once.
+    ///
+    /// # Errors
+    /// - `IpcError::NoFreePort` if every port is taken
+    pub fn alloc_port(&mut self) -> Result<usize, IpcError> {
+        let _lock = self.table_lock.lock();
+        let port_id = (SYSTEM_PORTS..self.ports.len())
+            .find(|&id| self.ports[id].is_some() && !self.allocated[id])
+            .ok_or(IpcError::NoFreePort)?;
+        self.allocated[port_id] = true;
+        Ok(port_id)
+    }
+
     /// Send a message to a port
     ///
     /// This function:
@@ -269,9 +294,6 @@ impl PortManager {
     /// - Task wakeup uses enqueue_task which sends RESCHEDULE_IPI to remote CPUs
     /// - Preemption is disabled while holding port locks to prevent deadlocks
     pub fn send_message(&mut self, port_id: usize, data: &[u8]) -> Result<(), IpcError> {
-        use crate::serial_println;
-        use core::sync::atomic::Ordering;
-
         // Validate port ID
         if port_id >= 256 {
             return Err(IpcError::InvalidPort);
@@ -282,6 +304,23 @@ impl PortManager {
             return Err(IpcError::MessageTooLarge);
         }
 
+        self.send_prepared(port_id, &Message::from_slice(data))
+    }
+
+    /// Send a message that has already been built
+    ///
+    /// Same as [`send_message`](Self::send_message), for callers that fill
+    /// the message payload themselves (e.g. straight from user memory) and
+    /// want to avoid an extra 4 KiB copy on the stack.
+    pub fn send_prepared(&mut self, port_id: usize, message: &Message) -> Result<(), IpcError> {
+        use crate::serial_println;
+        use core::sync::atomic::Ordering;
+
+        // Validate port ID
+        if port_id >= 256 {
+            return Err(IpcError::InvalidPort);
+        }
+
         // Get port reference
         let port = match &mut self.ports[port_id] {
             Some(p) => p,
@@ -309,16 +348,15 @@ impl PortManager {
             return Err(IpcError::QueueFull);
         }
 
-        // Create message and enqueue
-        let message = Message::from_slice(data);
-        if !port.queue.push_back(message) {
+        // Enqueue message
+        if !port.queue.push_back(*message) {
             // This shouldn't happen since we checked is_queue_full above
             drop(_lock);
             crate::sched::priority::preempt_enable();
             return Err(IpcError::QueueFull);
         }
 
-        serial_println!("[IPC] Sent {} bytes to port {}", data.len(), port_id);
+        serial_println!("[IPC] Sent {} bytes to port {}", message.len(), port_id);
 
         // Wake one blocked task (FIFO) if any
         if let Some(task_id) = port.blocked_tasks.pop_front() {
@@ -344,6 +382,9 @@ impl PortManager {
         drop(_lock);
         crate::sched::priority::preempt_enable();
 
+        // Wake every task polling the port; each one re-checks what it waits for
+        PORT_WAIT[port_id].wake_all();
+
         // Increment ipc_sends metric
         crate::sys::METRICS
             .ipc_sends
@@ -352,19 +393,21 @@ impl PortManager {
         Ok(())
     }
 
-    /// Receive a message from a port (blocking)
+    /// Receive a message from a port
     ///
     /// This function:
     /// 1. Validates port ID and buffer
     /// 2. Acquires port lock with preempt_disable()
     /// 3. If message available: dequeues, copies to buffer, returns bytes received
-    /// 4. If no message: adds task to blocked_tasks queue, marks task as Blocked, triggers scheduler
+    /// 4. If no message: fails with `WouldBlock` if `nonblock`, otherwise adds task to
+    ///    blocked_tasks queue, marks task as Blocked, triggers scheduler
     /// 5. Releases port lock with preempt_enable()
     /// 6. Increments ipc_recvs metric
     ///
     /// # Arguments
     /// * `port_id` - Source port ID
     /// * `task_id` - ID of the receiving task
+    /// * `nonblock` - Fail instead of blocking when no message is queued
     /// * `buf` - Buffer to receive message into
     ///
     /// # Returns
@@ -374,6 +417,7 @@ impl PortManager {
     /// - `IpcError::InvalidPort` if port_id >= 256
     /// - `IpcError::PortNotFound` if port doesn't exist
     /// - `IpcError::InvalidBuffer` if buffer is too small or invalid
+    /// - `IpcError::WouldBlock` if `nonblock` and no message is queued
     ///
     /// # SMP Safety
     /// This function handles cross-core IPC correctly:
@@ -385,7 +429,37 @@ impl PortManager {
         &mut self,
         port_id: usize,
         task_id: TaskId,
+        nonblock: bool,
         buf: &mut [u8],
+    ) -> Result<usize, IpcError> {
+        // Validate buffer
+        if buf.is_empty() {
+            return Err(IpcError::InvalidBuffer);
+        }
+
+        self.recv_message_with(port_id, task_id, nonblock, &mut |message: &Message| {
+            let data = message.as_slice();
+            let bytes_to_copy = core::cmp::min(data.len(), buf.len());
+            buf[..bytes_to_copy].copy_from_slice(&data[..bytes_to_copy]);
+            bytes_to_copy
+        })
+    }
+
+    /// Receive a message from a port, handing it to `deliver`
+    ///
+    /// Same as [`recv_message`](Self::recv_message), but the caller decides
+    /// where the payload goes (e.g. straight into user memory) and gets the
+    /// capability the message carries, if any; `recv_message` drops it.
+    /// `deliver` runs with the port lock held and returns the number of
+    /// bytes it kept.
+    /// With `nonblock`, an empty queue fails with `IpcError::WouldBlock`
+    /// instead of blocking the task.
+    pub fn recv_message_with(
+        &mut self,
+        port_id: usize,
+        task_id: TaskId,
+        nonblock: bool,
+        deliver: &mut dyn FnMut(&Message) -> usize,
     ) -> Result<usize, IpcError> {
         use crate::serial_println;
         use core::sync::atomic::Ordering;
@@ -395,11 +469,6 @@ impl PortManager {
             return Err(IpcError::InvalidPort);
         }
 
-        // Validate buffer
-        if buf.is_empty() {
-            return Err(IpcError::InvalidBuffer);
-        }
-
         // Get port reference
         let port = match &mut self.ports[port_id] {
             Some(p) => p,
@@ -415,8 +484,7 @@ impl PortManager {
         // Check if message is available
         if let Some(message) = port.queue.pop_front() {
             // Message available - copy to buffer
-            let bytes_to_copy = core::cmp::min(message.len(), buf.len());
-            buf[..bytes_to_copy].copy_from_slice(&message.as_slice()[..bytes_to_copy]);
+            let bytes_to_copy = deliver(&message);
 
             serial_println!(
                 "[IPC] Received {} bytes from port {}",
@@ -436,6 +504,12 @@ impl PortManager {
             return Ok(bytes_to_copy);
         }
 
+        if nonblock {
+            drop(_lock);
+            crate::sched::priority::preempt_enable();
+            return Err(IpcError::WouldBlock);
+        }
+
         // No message available - block the task
         serial_println!(
             "[IPC] Task {} blocking on port {} (no messages)",
@@ -469,7 +543,25 @@ impl PortManager {
         // When we wake up (after a message arrives), we need to try receiving again
         // This is a recursive call, but it should succeed immediately since we were woken
         // because a message arrived
-        self.recv_message(port_id, task_id, buf)
+        self.recv_message_with(port_id, task_id, nonblock, deliver)
+    }
+
+    /// Check whether `port_id` has a message queued, without taking it
+    pub fn has_message(&mut self, port_id: usize) -> Result<bool, IpcError> {
+        let port = self
+            .ports
+            .get_mut(port_id)
+            .ok_or(IpcError::InvalidPort)?
+            .as_mut()
+            .ok_or(IpcError::PortNotFound)?;
+
+        crate::sched::priority::preempt_disable();
+        let ready = {
+            let _lock = port.lock.lock();
+            port.has_messages()
+        };
+        crate::sched::priority::preempt_enable();
+        Ok(ready)
     }
 }
 
@@ -479,10 +571,19 @@ impl PortManager {
 /// Protected by Mutex for thread-safe access.
 pub static PORT_MANAGER: Mutex<PortManager> = Mutex::new(PortManager::new());
 
+/// Tasks polling each port, all woken per message sent
+static PORT_WAIT: [WaitQueue; 256] = [const { WaitQueue::new() }; 256];
+
+/// Queue woken when a message is sent to `port_id`
+pub fn wait_queue(port_id: usize) -> Option<&'static WaitQueue> {
+    PORT_WAIT.get(port_id)
+}
+
 /// Initialize IPC subsystem
 ///
-/// Creates system ports (0-15) for kernel use.
-/// Should be called during kernel initialization.
+/// Creates system ports (0-15) for kernel use, and the storage of every
+/// other port for `SYS_PORT_CREATE` to hand out.
+/// Runs as an `Early` init call.
 pub fn init_ipc() {
     use crate::serial_println;
 
@@ -490,13 +591,19 @@ pub fn init_ipc() {
 
     let mut port_mgr = PORT_MANAGER.lock();
 
-    // Create system ports 0-15
-    for port_id in 0..16 {
+    // Create system ports 0-15, then the free ports
+    for port_id in 0..port_mgr.ports.len() {
         if let Err(e) = port_mgr.create_port(port_id) {
             serial_println!("[IPC] Failed to create port {}: {:?}", port_id, e);
         }
     }
 
-    serial_println!("[IPC] Created 16 system ports (0-15)");
+    serial_println!(
+        "[IPC] Created {} system ports (0-{}) and {} free ports",
+        SYSTEM_PORTS,
+        SYSTEM_PORTS - 1,
+        port_mgr.ports.len() - SYSTEM_PORTS
+    );
     serial_println!("[IPC] IPC subsystem initialized!");
 }
+crate::initcall!(Early, init_ipc);
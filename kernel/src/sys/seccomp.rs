@@ -0,0 +1,149 @@
+//! Per-task syscall filtering (`SYS_SECCOMP`)
+//!
+//! A task can restrict the syscalls it, or a child it has forked (e.g.
+//! before the child execs an untrusted program), may make. A filter is a
+//! bitmap of syscall numbers 0-127 and a mode: either only the listed calls
+//! are allowed, or the listed calls are denied. Both dispatchers check the
+//! filter before the handler runs. A filtered call fails with EPERM, or
+//! kills the task as if by SIGSYS, as the filter's action says.
+//!
+//! Filters only ever tighten: installing one on a task that already has a
+//! filter denies what either denies, and kills if either kills. Threads and
+//! forked children start with their creator's filter, and exec keeps it.
+//! `SYS_EXIT` and `SYS_THREAD_EXIT` are always allowed, so a sandboxed task
+//! can still leave.
+
+use crate::sys::errno::Errno;
+use crate::sys::syscall::{SYS_EXIT, SYS_THREAD_EXIT};
+
+/// `SeccompFilter::mode`: only the listed syscalls are allowed
+pub const SECCOMP_ALLOW_LISTED: u32 = 0;
+/// `SeccompFilter::mode`: the listed syscalls are denied
+pub const SECCOMP_DENY_LISTED: u32 = 1;
+
+/// `SeccompFilter::action`: a filtered call fails with EPERM
+pub const SECCOMP_ERRNO: u32 = 0;
+/// `SeccompFilter::action`: a filtered call kills the task
+pub const SECCOMP_KILL: u32 = 1;
+
+/// Syscall numbers a filter can list
+pub const FILTER_BITS: usize = 128;
+
+/// Filter passed to `SYS_SECCOMP`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SeccompFilter {
+    pub mode: u32,
+    pub action: u32,
+    /// Bit `n % 64` of word `n / 64` lists syscall `n`
+    pub syscalls: [u64; 2],
+}
+
+mello_abi::check_layout!(SeccompFilter, mello_abi::SeccompFilter { mode, action, syscalls });
+
+/// What happens to a call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Deny,
+    Kill,
+}
+
+/// Filter state of a task; the default allows everything
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// Denied syscalls 0-127
+    denied: [u64; 2],
+    /// Whether syscalls above 127 are denied
+    deny_unlisted: bool,
+    /// Whether a denied call kills the task
+    kill: bool,
+}
+
+impl Filter {
+    /// Add `filter` to this one; Err if its mode or action is unknown
+    pub fn install(&mut self, filter: &SeccompFilter) -> Result<(), ()> {
+        let (denied, deny_unlisted) = match filter.mode {
+            SECCOMP_ALLOW_LISTED => ([!filter.syscalls[0], !filter.syscalls[1]], true),
+            SECCOMP_DENY_LISTED => (filter.syscalls, false),
+            _ => return Err(()),
+        };
+        let kill = match filter.action {
+            SECCOMP_ERRNO => false,
+            SECCOMP_KILL => true,
+            _ => return Err(()),
+        };
+        self.denied[0] |= denied[0];
+        self.denied[1] |= denied[1];
+        self.deny_unlisted |= deny_unlisted;
+        self.kill |= kill;
+        Ok(())
+    }
+
+    /// What happens to syscall `id`
+    pub fn verdict(&self, id: usize) -> Verdict {
+        let denied = match id {
+            SYS_EXIT | SYS_THREAD_EXIT => false,
+            id if id < FILTER_BITS => self.denied[id / 64] & (1 << (id % 64)) != 0,
+            _ => self.deny_unlisted,
+        };
+        match (denied, self.kill) {
+            (false, _) => Verdict::Allow,
+            (true, false) => Verdict::Deny,
+            (true, true) => Verdict::Kill,
+        }
+    }
+}
+
+/// Check syscall `id` of the current task against its filter
+///
+/// Returns the error to fail a denied call with, or None to let it run.
+/// Does not return if the filter kills the task.
+pub fn check(id: usize) -> Option<Errno> {
+    let task = crate::sched::get_current_task_info().and_then(|(id, _)| crate::sched::get_task_by_id(id))?;
+    match task.seccomp.verdict(id) {
+        Verdict::Allow => None,
+        Verdict::Deny => Some(Errno::EPERM),
+        Verdict::Kill => {
+            crate::log_warn!("SECCOMP", "task {} killed for syscall {}", task.id, id);
+            crate::arch::x86_64::syscall::exit_current(128 + crate::signal::signals::SIGSYS as usize)
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// Allow and deny lists combine to the stricter filter, and the exit
+    /// calls stay allowed
+    fn seccomp_filter_combines() {
+        use crate::sys::syscall::{SYS_GETPID, SYS_WRITE};
+
+        let mut filter = Filter::default();
+        crate::ktest_assert_eq!(filter.verdict(SYS_WRITE), Verdict::Allow, "empty filter denies");
+
+        let allow = SeccompFilter {
+            mode: SECCOMP_ALLOW_LISTED,
+            action: SECCOMP_ERRNO,
+            syscalls: [1 << SYS_WRITE | 1 << SYS_GETPID, 0],
+        };
+        filter.install(&allow).map_err(|_| "allow list rejected")?;
+        crate::ktest_assert_eq!(filter.verdict(SYS_WRITE), Verdict::Allow, "listed call denied");
+        crate::ktest_assert_eq!(filter.verdict(SYS_GETPID), Verdict::Allow, "listed call denied");
+        crate::ktest_assert_eq!(filter.verdict(SYS_THREAD_EXIT), Verdict::Allow, "exit denied");
+        crate::ktest_assert_eq!(filter.verdict(70), Verdict::Deny, "unlisted call allowed");
+        crate::ktest_assert_eq!(filter.verdict(500), Verdict::Deny, "call past the bitmap allowed");
+
+        let deny = SeccompFilter {
+            mode: SECCOMP_DENY_LISTED,
+            action: SECCOMP_KILL,
+            syscalls: [1 << SYS_GETPID, 0],
+        };
+        filter.install(&deny).map_err(|_| "deny list rejected")?;
+        crate::ktest_assert_eq!(filter.verdict(SYS_WRITE), Verdict::Allow, "call allowed by both denied");
+        crate::ktest_assert_eq!(filter.verdict(SYS_GETPID), Verdict::Kill, "denied call not killed");
+        crate::ktest_assert_eq!(filter.verdict(SYS_EXIT), Verdict::Allow, "exit denied");
+
+        let bad = SeccompFilter { mode: 2, ..SeccompFilter::default() };
+        crate::ktest_assert!(filter.install(&bad).is_err(), "unknown mode accepted");
+        Ok(())
+    }
+}
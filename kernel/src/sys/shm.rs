@@ -0,0 +1,236 @@
+//! Shared memory objects
+//!
+//! A shared memory object is a fixed list of zeroed frames owned by the
+//! kernel. Tasks reach it through a handle (see `sys::handle`), from
+//! `SYS_SHM_CREATE` or passed over IPC, and map it with `SYS_SHM_MAP`;
+//! creating an object with the name of a live one opens it instead. The
+//! mapping is a
+//! `MemoryRegionType::Shared` region whose pages fault in to the object's
+//! frames, so every mapper sees the same memory. This complements port
+//! messages for bulk data.
+//!
+//! Every handle and every region (or piece of a split region) referring to
+//! an object holds a reference. Closing the handle or unmapping drops it,
+//! and so does task exit, which unmaps all shared regions before the
+//! handles are closed. An object is destroyed and its frames freed with
+//! its last reference.
+
+use crate::mm::mmap::{self, MmapError};
+use crate::mm::PhysAddr;
+use crate::sched::task::{MemoryRegionType, Task};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Maximum number of live shared memory objects
+const MAX_SHM_OBJECTS: usize = 32;
+
+/// Largest object, in pages (1 MiB)
+pub const MAX_SHM_PAGES: usize = 256;
+
+/// Longest object name
+pub const SHM_NAME_MAX: usize = 32;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Shared memory errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShmError {
+    /// Bad size or name, or opening a named object with a larger size
+    InvalidArgument,
+    /// No object with this id
+    NotFound,
+    /// Object table is full
+    TooManyObjects,
+    /// Not enough physical memory for the frames
+    OutOfMemory,
+    /// The mapping itself failed
+    Map(MmapError),
+}
+
+struct ShmObject {
+    id: usize,
+    name: [u8; SHM_NAME_MAX],
+    name_len: usize,
+    pages: usize,
+    frames: [PhysAddr; MAX_SHM_PAGES],
+    /// Handles and regions referring to the object
+    refs: usize,
+}
+
+static OBJECTS: Mutex<[Option<ShmObject>; MAX_SHM_OBJECTS]> =
+    Mutex::new([const { None }; MAX_SHM_OBJECTS]);
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+fn find(objects: &mut [Option<ShmObject>], id: usize) -> Option<&mut ShmObject> {
+    objects.iter_mut().flatten().find(|object| object.id == id)
+}
+
+/// Create an object of `size` bytes, or open the existing one called `name`
+///
+/// An empty name always creates a new anonymous object. Returns the id,
+/// with a reference for the caller's handle.
+pub fn create(name: &[u8], size: usize) -> Result<usize, ShmError> {
+    if size == 0 || size > MAX_SHM_PAGES * PAGE_SIZE || name.len() > SHM_NAME_MAX {
+        return Err(ShmError::InvalidArgument);
+    }
+    let pages = size.div_ceil(PAGE_SIZE);
+
+    let mut objects = OBJECTS.lock();
+    if !name.is_empty() {
+        if let Some(object) = objects
+            .iter_mut()
+            .flatten()
+            .find(|object| &object.name[..object.name_len] == name)
+        {
+            if pages > object.pages {
+                return Err(ShmError::InvalidArgument);
+            }
+            object.refs += 1;
+            return Ok(object.id);
+        }
+    }
+    let slot = objects
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(ShmError::TooManyObjects)?;
+
+    let mut frames = [PhysAddr::new(0); MAX_SHM_PAGES];
+    crate::mm::with_memory_managers(|pmm, _| {
+        for i in 0..pages {
+            // alloc_frame hands out zeroed frames
+            match pmm.alloc_frame() {
+                Some(frame) => frames[i] = frame,
+                None => {
+                    for frame in &frames[..i] {
+                        pmm.free_frame(*frame);
+                    }
+                    return Err("Out of physical memory");
+                }
+            }
+        }
+        Ok(())
+    })
+    .map_err(|_| ShmError::OutOfMemory)?;
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut object = ShmObject {
+        id,
+        name: [0; SHM_NAME_MAX],
+        name_len: name.len(),
+        pages,
+        frames,
+        refs: 1,
+    };
+    object.name[..name.len()].copy_from_slice(name);
+    *slot = Some(object);
+    Ok(id)
+}
+
+/// Map object `id` into `task` with protection `prot` (`PROT_*`)
+///
+/// `addr` is a placement hint. Pages fault in on first touch.
+pub fn map(task: &mut Task, id: usize, addr: usize, prot: usize) -> Result<usize, ShmError> {
+    let pages = {
+        let mut objects = OBJECTS.lock();
+        let object = find(&mut *objects, id).ok_or(ShmError::NotFound)?;
+        object.refs += 1;
+        object.pages
+    };
+
+    mmap::map_region(task, addr, pages * PAGE_SIZE, prot, false, |base| {
+        MemoryRegionType::Shared { id, base }
+    })
+    .map_err(|e| {
+        release(id);
+        ShmError::Map(e)
+    })
+}
+
+/// Frame backing page `index` of object `id`
+pub fn frame(id: usize, index: usize) -> Option<PhysAddr> {
+    let mut objects = OBJECTS.lock();
+    let object = find(&mut *objects, id)?;
+    (index < object.pages).then(|| object.frames[index])
+}
+
+/// Take another reference to object `id`
+pub fn retain(id: usize) {
+    if let Some(object) = find(&mut *OBJECTS.lock(), id) {
+        object.refs += 1;
+    }
+}
+
+/// Destroy the object in `slot` if nothing keeps it alive
+fn reap(slot: &mut Option<ShmObject>) {
+    let dead = matches!(slot, Some(object) if object.refs == 0);
+    if !dead {
+        return;
+    }
+    if let Some(object) = slot.take() {
+        let _ = crate::mm::with_memory_managers(|pmm, _| {
+            for frame in &object.frames[..object.pages] {
+                pmm.free_frame(*frame);
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Drop a reference to object `id`
+///
+/// The caller must have unmapped the pages it mapped from the object.
+pub fn release(id: usize) {
+    let mut objects = OBJECTS.lock();
+    if let Some(slot) = objects
+        .iter_mut()
+        .find(|slot| slot.as_ref().map_or(false, |object| object.id == id))
+    {
+        if let Some(object) = slot.as_mut() {
+            object.refs = object.refs.saturating_sub(1);
+        }
+        reap(slot);
+    }
+}
+
+/// Drop the shared mappings of `task`
+pub fn task_exit(task: &mut Task) {
+    loop {
+        let range = task.memory_regions[..task.region_count]
+            .iter()
+            .flatten()
+            .find(|region| matches!(region.region_type, MemoryRegionType::Shared { .. }))
+            .map(|region| (region.start, region.size()));
+        match range {
+            Some((start, len)) => {
+                if mmap::unmap(task, start, len).is_err() {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// Objects are shared by name and freed after the last reference
+    fn shm_object_lifetime() {
+        let id = create(b"ktest-shm", 2 * PAGE_SIZE).map_err(|_| "create failed")?;
+        crate::ktest_assert_eq!(create(b"ktest-shm", PAGE_SIZE), Ok(id), "open by name");
+        crate::ktest_assert_eq!(
+            create(b"ktest-shm", 3 * PAGE_SIZE),
+            Err(ShmError::InvalidArgument),
+            "opened with a larger size"
+        );
+        crate::ktest_assert!(frame(id, 1).is_some(), "second page missing");
+        crate::ktest_assert!(frame(id, 2).is_none(), "page past the end");
+
+        retain(id);
+        release(id);
+        release(id);
+        crate::ktest_assert!(frame(id, 0).is_some(), "object freed with a reference left");
+        release(id);
+        crate::ktest_assert!(frame(id, 0).is_none(), "object outlived its last reference");
+        Ok(())
+    }
+}
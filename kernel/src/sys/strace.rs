@@ -0,0 +1,159 @@
+//! Per-task syscall tracing (`SYS_PTRACE_LITE`)
+//!
+//! A traced task gets one line per syscall in the kernel log ring
+//! (`/proc/kmsg`), with the arguments, the result and how long the call
+//! took:
+//!
+//! ```text
+//! [   12.345678] strace 7 SYS_WRITE(0x1, 0x7fff0000a000, 0xd) = 13 <41us>
+//! ```
+//!
+//! Calls that do not return (`SYS_EXIT`, `SYS_THREAD_EXIT`) are logged on
+//! entry with `= ?`. Both syscall entry paths trace: `int 0x80` through
+//! `sys::syscall::syscall_dispatcher` with three arguments, `syscall`
+//! through the arch dispatcher with six.
+//!
+//! So that a task in a tight loop cannot flush the ring, each task logs at
+//! most `MAX_PER_WINDOW` calls a second. The rest are counted, and the
+//! count is logged when the next second starts. Threads and forked
+//! children start with the tracing state of their creator.
+
+use crate::sched::task::TaskId;
+use crate::sys::syscall::{syscall_name, SYS_EXIT, SYS_THREAD_EXIT};
+use core::fmt;
+
+/// Calls logged per task per window
+pub const MAX_PER_WINDOW: u32 = 64;
+
+/// Length of a rate limiting window
+const WINDOW_NANOS: u64 = 1_000_000_000;
+
+/// Tracing state of a task
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceState {
+    /// Whether the task's syscalls are logged
+    pub enabled: bool,
+    /// Start of the current window
+    window_start: u64,
+    /// Calls logged in the current window
+    logged: u32,
+    /// Calls not logged in the current window
+    suppressed: u32,
+}
+
+impl TraceState {
+    /// Count a call at `now`
+    ///
+    /// Returns whether to log it, and the number of calls suppressed in
+    /// the window that just ended (0 if it has not).
+    fn admit(&mut self, now: u64) -> (bool, u32) {
+        let mut ended = 0;
+        if now.saturating_sub(self.window_start) >= WINDOW_NANOS {
+            ended = self.suppressed;
+            self.window_start = now;
+            self.logged = 0;
+            self.suppressed = 0;
+        }
+        if self.logged < MAX_PER_WINDOW {
+            self.logged += 1;
+            (true, ended)
+        } else {
+            self.suppressed += 1;
+            (false, ended)
+        }
+    }
+}
+
+/// Arguments as a comma separated hex list
+struct Args<'a>(&'a [usize]);
+
+impl fmt::Display for Args<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, arg) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{:#x}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+fn current_task() -> Option<&'static mut crate::sched::task::Task> {
+    crate::sched::get_current_task_info().and_then(|(id, _)| crate::sched::get_task_mut(id))
+}
+
+/// Log one call of `task` if its rate limit allows
+fn record(task: TaskId, state: &mut TraceState, id: usize, args: &[usize], result: Option<isize>, took: u64) {
+    let (log, ended) = state.admit(crate::time::clock::now_ns());
+    if ended > 0 {
+        crate::log::klog(format_args!("strace {} ({} calls not logged)", task, ended));
+    }
+    if !log {
+        return;
+    }
+    let name = syscall_name(id);
+    match result {
+        Some(result) => crate::log::klog(format_args!(
+            "strace {} {}({}) = {} <{}us>",
+            task,
+            name,
+            Args(args),
+            result,
+            took / 1000
+        )),
+        None => crate::log::klog(format_args!("strace {} {}({}) = ?", task, name, Args(args))),
+    }
+}
+
+/// Start tracing a syscall of the current task
+///
+/// Returns the start time if the task is traced. Calls that do not return
+/// are logged here.
+pub fn enter(id: usize, args: &[usize]) -> Option<u64> {
+    let task = current_task()?;
+    if !task.strace.enabled {
+        return None;
+    }
+    if matches!(id, SYS_EXIT | SYS_THREAD_EXIT) {
+        let task_id = task.id;
+        record(task_id, &mut task.strace, id, args, None, 0);
+        return None;
+    }
+    Some(crate::time::clock::now_ns())
+}
+
+/// Log a traced syscall that returned `result`; `start` is from [`enter`]
+pub fn exit(start: u64, id: usize, args: &[usize], result: isize) {
+    let took = crate::time::clock::now_ns().saturating_sub(start);
+    let Some(task) = current_task() else { return };
+    let task_id = task.id;
+    record(task_id, &mut task.strace, id, args, Some(result), took);
+}
+
+/// Turn tracing of `task` on or off; returns whether it was on
+pub fn set(task: &mut crate::sched::task::Task, enabled: bool) -> bool {
+    let was = task.strace.enabled;
+    task.strace = TraceState { enabled, ..TraceState::default() };
+    was
+}
+
+crate::kernel_test! {
+    /// The rate limit logs `MAX_PER_WINDOW` calls a window and reports the
+    /// rest when the next window starts
+    fn strace_rate_limit() {
+        let mut state = TraceState { enabled: true, ..TraceState::default() };
+        let start = WINDOW_NANOS;
+        for i in 0..MAX_PER_WINDOW {
+            crate::ktest_assert_eq!(state.admit(start + i as u64), (true, 0), "call within the limit dropped");
+        }
+        crate::ktest_assert_eq!(state.admit(start + 100), (false, 0), "call over the limit logged");
+        crate::ktest_assert_eq!(state.admit(start + 200), (false, 0), "call over the limit logged");
+        crate::ktest_assert_eq!(
+            state.admit(start + WINDOW_NANOS),
+            (true, 2),
+            "suppressed calls not reported in the next window"
+        );
+        Ok(())
+    }
+}
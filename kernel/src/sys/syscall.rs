@@ -3,8 +3,12 @@
 //! This module implements the system call interface for userland-kernel communication.
 //! It provides syscall entry point, dispatcher, and handler functions.
 
+use crate::arch::x86_64::syscall::{copy_from_user, copy_to_user};
 use crate::sched::task::USER_LIMIT;
-use crate::sync::SpinLock;
+use crate::sync::{SpinLock, WaitQueue};
+use crate::sys::errno::{to_return, Errno, SyscallResult};
+use crate::sys::handle::{self, Handle, Object, Rights};
+use crate::sys::waitable;
 use crate::sys::METRICS;
 use crate::{serial_print, serial_println};
 use core::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
@@ -24,6 +28,8 @@ use core::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 pub extern "C" fn syscall_entry() {
     core::arch::naked_asm!(
         // The CPU has already pushed SS, RSP, RFLAGS, CS, RIP
+        crate::swapgs_if_user!(8),
+
         // We need to save all other registers
 
         // Save caller-saved registers
@@ -105,6 +111,7 @@ pub extern "C" fn syscall_entry() {
         "pop rax",    // This pops the return value we saved earlier
 
         // Return from interrupt (pops RIP, CS, RFLAGS, RSP, SS)
+        crate::swapgs_if_user!(8),
         "iretq",
 
         dispatcher = sym syscall_dispatcher_wrapper,
@@ -121,6 +128,9 @@ extern "C" fn syscall_dispatcher_wrapper(
     arg2: usize,
     arg3: usize,
 ) -> isize {
+    // int 0x80 preserves the caller's RFLAGS.AC; never run a syscall with
+    // user access already open
+    crate::arch::x86_64::cpu::clac();
     syscall_dispatcher(syscall_id, arg1, arg2, arg3)
 }
 
@@ -150,38 +160,70 @@ pub const SYS_TCGETPGRP: usize = 21;
 pub const SYS_FCNTL: usize = 22;
 pub const SYS_PIPE2: usize = 23;
 pub const SYS_DUP2: usize = 24;
+pub const SYS_GETRANDOM: usize = 25;
+pub const SYS_UMASK: usize = 26;
+pub const SYS_GETRUSAGE: usize = 27;
+/// Takes six arguments, so only reachable through the `syscall` instruction
+/// (see `arch::x86_64::syscall`), as are `SYS_MUNMAP` and `SYS_MPROTECT`
+pub const SYS_MMAP: usize = 28;
+pub const SYS_MUNMAP: usize = 29;
+pub const SYS_MPROTECT: usize = 30;
+pub const SYS_EVENT_SUBSCRIBE: usize = 31;
+pub const SYS_BRK: usize = 32;
+pub const SYS_SHM_CREATE: usize = 33;
+pub const SYS_SHM_MAP: usize = 34;
+pub const SYS_IPC_POLL: usize = 35;
+pub const SYS_IPC_NOTIFY: usize = 36;
+pub const SYS_PORT_CREATE: usize = 37;
+pub const SYS_CAP_DERIVE: usize = 38;
+pub const SYS_CAP_DROP: usize = 39;
+pub const SYS_PIPE: usize = 40;
+pub const SYS_PERF: usize = 41;
+pub const SYS_POLL: usize = 42;
+pub const SYS_THREAD_CREATE: usize = 43;
+pub const SYS_THREAD_EXIT: usize = 44;
+pub const SYS_FUTEX: usize = 45;
+pub const SYS_SENDFILE: usize = 46;
+pub const SYS_CLOCK_GETTIME: usize = 47;
+pub const SYS_PTRACE_LITE: usize = 48;
+pub const SYS_SECCOMP: usize = 49;
+pub const SYS_SPAWN: usize = 50;
+pub const SYS_DUP: usize = 51;
+pub const SYS_TIMER_CREATE: usize = 52;
+pub const SYS_EVENT_CREATE: usize = 53;
+pub const SYS_CPU_GROUP: usize = 54;
+pub const SYS_CPU_QUOTA: usize = 55;
+pub const SYS_HWINFO: usize = 56;
+pub const SYS_PING: usize = 57;
+pub const SYS_SOCKET: usize = 58;
+pub const SYS_BIND: usize = 59;
+/// `SYS_SENDTO` and `SYS_RECVFROM` take six arguments, so like `SYS_MMAP`
+/// they are only reachable through the `syscall` instruction
+pub const SYS_SENDTO: usize = 60;
+pub const SYS_RECVFROM: usize = 61;
+pub const SYS_CONNECT: usize = 62;
+pub const SYS_LISTEN: usize = 63;
+pub const SYS_ACCEPT: usize = 64;
+pub const SYS_RESOLVE: usize = 65;
+pub const SYS_SCHEDSTAT: usize = 66;
+pub const SYS_TASK_INFO: usize = 67;
+pub const SYS_TASK_LIST: usize = 68;
+/// `SYS_IPC_RECV_TIMEOUT` takes four arguments, so it is only reachable
+/// through the `syscall` instruction
+pub const SYS_IPC_RECV_TIMEOUT: usize = 69;
+pub const SYS_TASK_WATCH: usize = 70;
+pub const SYS_NANOSLEEP: usize = 71;
+pub const SYS_POWEROFF: usize = 72;
+
+/// Flag once needed in `SYS_SENDFILE`'s `out` argument to name a port
+/// handle; ports and files now share the handle table, so it is ignored
+pub const SENDFILE_PORT: usize = 1 << 30;
 
 static NEXT_FAKE_PID: AtomicUsize = AtomicUsize::new(2000);
 
-/// Syscall dispatcher
-///
-/// Routes syscall ID to appropriate handler and increments metrics.
-///
-/// # Arguments
-/// * `syscall_id` - Syscall number (from RAX)
-/// * `arg1` - First argument (from RDI)
-/// * `arg2` - Second argument (from RSI)
-/// * `arg3` - Third argument (from RDX)
-///
-/// # Returns
-/// Result value (0 or positive on success, -1 on error)
-///
-/// # SMP Safety
-/// This dispatcher is SMP-safe because:
-/// - No global locks are held across syscalls
-/// - Each syscall handler uses appropriate per-object locks
-/// - Task state is accessed through per-CPU structures
-/// - Multiple cores can execute syscalls concurrently without contention
-#[no_mangle]
-pub extern "C" fn syscall_dispatcher(syscall_id: usize, arg1: usize, arg2: usize, arg3: usize) -> isize {
-    // Get current task ID for logging
-    let task_id = match crate::sched::get_current_task_info() {
-        Some((id, _)) => id,
-        None => 0, // Unknown task
-    };
-
-    // Get syscall name for logging
-    let syscall_name = match syscall_id {
+/// Name of syscall `id` for logs, `"INVALID"` if there is none
+pub fn syscall_name(id: usize) -> &'static str {
+    match id {
         SYS_WRITE => "SYS_WRITE",
         SYS_EXIT => "SYS_EXIT",
         SYS_SLEEP => "SYS_SLEEP",
@@ -207,12 +249,113 @@ pub extern "C" fn syscall_dispatcher(syscall_id: usize, arg1: usize, arg2: usize
         SYS_FCNTL => "SYS_FCNTL",
         SYS_PIPE2 => "SYS_PIPE2",
         SYS_DUP2 => "SYS_DUP2",
+        SYS_GETRANDOM => "SYS_GETRANDOM",
+        SYS_UMASK => "SYS_UMASK",
+        SYS_GETRUSAGE => "SYS_GETRUSAGE",
+        SYS_MMAP => "SYS_MMAP",
+        SYS_MUNMAP => "SYS_MUNMAP",
+        SYS_MPROTECT => "SYS_MPROTECT",
+        SYS_EVENT_SUBSCRIBE => "SYS_EVENT_SUBSCRIBE",
+        SYS_BRK => "SYS_BRK",
+        SYS_SHM_CREATE => "SYS_SHM_CREATE",
+        SYS_SHM_MAP => "SYS_SHM_MAP",
+        SYS_IPC_POLL => "SYS_IPC_POLL",
+        SYS_IPC_NOTIFY => "SYS_IPC_NOTIFY",
+        SYS_PORT_CREATE => "SYS_PORT_CREATE",
+        SYS_CAP_DERIVE => "SYS_CAP_DERIVE",
+        SYS_CAP_DROP => "SYS_CAP_DROP",
+        SYS_PIPE => "SYS_PIPE",
+        SYS_PERF => "SYS_PERF",
+        SYS_POLL => "SYS_POLL",
+        SYS_THREAD_CREATE => "SYS_THREAD_CREATE",
+        SYS_THREAD_EXIT => "SYS_THREAD_EXIT",
+        SYS_FUTEX => "SYS_FUTEX",
+        SYS_SENDFILE => "SYS_SENDFILE",
+        SYS_CLOCK_GETTIME => "SYS_CLOCK_GETTIME",
+        SYS_PTRACE_LITE => "SYS_PTRACE_LITE",
+        SYS_SECCOMP => "SYS_SECCOMP",
+        SYS_SPAWN => "SYS_SPAWN",
+        SYS_DUP => "SYS_DUP",
+        SYS_TIMER_CREATE => "SYS_TIMER_CREATE",
+        SYS_EVENT_CREATE => "SYS_EVENT_CREATE",
+        SYS_CPU_GROUP => "SYS_CPU_GROUP",
+        SYS_CPU_QUOTA => "SYS_CPU_QUOTA",
+        SYS_HWINFO => "SYS_HWINFO",
+        SYS_PING => "SYS_PING",
+        SYS_SOCKET => "SYS_SOCKET",
+        SYS_BIND => "SYS_BIND",
+        SYS_SENDTO => "SYS_SENDTO",
+        SYS_RECVFROM => "SYS_RECVFROM",
+        SYS_CONNECT => "SYS_CONNECT",
+        SYS_LISTEN => "SYS_LISTEN",
+        SYS_ACCEPT => "SYS_ACCEPT",
+        SYS_RESOLVE => "SYS_RESOLVE",
+        SYS_SCHEDSTAT => "SYS_SCHEDSTAT",
+        SYS_TASK_INFO => "SYS_TASK_INFO",
+        SYS_TASK_LIST => "SYS_TASK_LIST",
+        SYS_IPC_RECV_TIMEOUT => "SYS_IPC_RECV_TIMEOUT",
+        SYS_TASK_WATCH => "SYS_TASK_WATCH",
+        SYS_NANOSLEEP => "SYS_NANOSLEEP",
+        SYS_POWEROFF => "SYS_POWEROFF",
         _ => "INVALID",
+    }
+}
+
+/// Syscall dispatcher (`int 0x80` entry)
+///
+/// Traces the call if the task asked for it (`sys::strace`), checks it
+/// against the task's filter (`sys::seccomp`), then dispatches it with
+/// [`dispatch`].
+#[no_mangle]
+pub extern "C" fn syscall_dispatcher(syscall_id: usize, arg1: usize, arg2: usize, arg3: usize) -> isize {
+    let args = [arg1, arg2, arg3];
+    let start = crate::sys::strace::enter(syscall_id, &args);
+    let result = to_return(match crate::sys::seccomp::check(syscall_id) {
+        Some(errno) => Err(errno),
+        None => dispatch(syscall_id, arg1, arg2, arg3),
+    });
+    if let Some(start) = start {
+        crate::sys::strace::exit(start, syscall_id, &args, result);
+    }
+    result
+}
+
+/// Route a syscall to its handler
+///
+/// Routes syscall ID to appropriate handler and increments metrics. Both
+/// entry paths end up here; neither traces calls routed through it again.
+///
+/// # Arguments
+/// * `syscall_id` - Syscall number (from RAX)
+/// * `arg1` - First argument (from RDI)
+/// * `arg2` - Second argument (from RSI)
+/// * `arg3` - Third argument (from RDX)
+///
+/// # Returns
+/// The handler's result; the entry paths turn it into the return value
+/// with `to_return`
+///
+/// # SMP Safety
+/// This dispatcher is SMP-safe because:
+/// - No global locks are held across syscalls
+/// - Each syscall handler uses appropriate per-object locks
+/// - Task state is accessed through per-CPU structures
+/// - Multiple cores can execute syscalls concurrently without contention
+pub fn dispatch(syscall_id: usize, arg1: usize, arg2: usize, arg3: usize) -> SyscallResult {
+    // Get current task ID for logging
+    let task_id = match crate::sched::get_current_task_info() {
+        Some((id, _)) => id,
+        None => 0, // Unknown task
     };
 
-    // Log syscall invocation with task ID and syscall name
-    serial_println!(
-        "[SYSCALL] Task {} invoked {} (id={})",
+    // Get syscall name for logging
+    let syscall_name = syscall_name(syscall_id);
+
+    // Log syscall invocation with task ID and syscall name (trace level, so
+    // the console does not dominate the cost of every syscall)
+    crate::log_trace!(
+        "SYSCALL",
+        "Task {} invoked {} (id={})",
         task_id,
         syscall_name,
         syscall_id
@@ -240,7 +383,7 @@ pub extern "C" fn syscall_dispatcher(syscall_id: usize, arg1: usize, arg2: usize
         SYS_FORK => sys_fork(),
         SYS_WAIT => sys_wait(arg1),
         SYS_EXEC => sys_exec(arg1, arg2),
-        SYS_OPEN => sys_open(arg1, arg2),
+        SYS_OPEN => sys_open(arg1, arg2, arg3),
         SYS_READ => sys_read(arg1, arg2, arg3),
         SYS_CLOSE => sys_close(arg1),
         SYS_IOCTL => sys_ioctl(arg1, arg2, arg3),
@@ -255,27 +398,70 @@ pub extern "C" fn syscall_dispatcher(syscall_id: usize, arg1: usize, arg2: usize
         SYS_FCNTL => sys_fcntl(arg1, arg2, arg3),
         SYS_PIPE2 => sys_pipe2(arg1, arg2),
         SYS_DUP2 => sys_dup2(arg1, arg2),
+        SYS_GETRANDOM => sys_getrandom(arg1, arg2, arg3),
+        SYS_UMASK => sys_umask(arg1),
+        SYS_GETRUSAGE => sys_getrusage(arg1, arg2),
+        SYS_EVENT_SUBSCRIBE => sys_event_subscribe(arg1, arg2),
+        SYS_BRK => sys_brk(arg1),
+        SYS_SHM_CREATE => sys_shm_create(arg1, arg2, arg3),
+        SYS_SHM_MAP => sys_shm_map(arg1, arg2, arg3),
+        SYS_IPC_POLL => sys_ipc_poll(arg1, arg2),
+        SYS_IPC_NOTIFY => sys_ipc_notify(arg1, arg2),
+        SYS_PORT_CREATE => sys_port_create(),
+        SYS_CAP_DERIVE => sys_cap_derive(arg1, arg2),
+        SYS_CAP_DROP => sys_cap_drop(arg1),
+        SYS_PIPE => sys_pipe2(arg1, 0),
+        SYS_PERF => sys_perf(arg1, arg2, arg3),
+        SYS_POLL => sys_poll(arg1, arg2, arg3),
+        SYS_THREAD_CREATE => sys_thread_create(arg1),
+        SYS_THREAD_EXIT => sys_thread_exit(),
+        SYS_FUTEX => sys_futex(arg1, arg2, arg3),
+        SYS_SENDFILE => sys_sendfile(arg1, arg2, arg3),
+        SYS_CLOCK_GETTIME => sys_clock_gettime(),
+        SYS_PTRACE_LITE => sys_ptrace_lite(arg1, arg2),
+        SYS_SECCOMP => sys_seccomp(arg1, arg2),
+        SYS_SPAWN => sys_spawn(arg1, arg2),
+        SYS_DUP => sys_dup(arg1),
+        SYS_TIMER_CREATE => sys_timer_create(arg1, arg2),
+        SYS_EVENT_CREATE => sys_event_create(arg1),
+        SYS_CPU_GROUP => sys_cpu_group(arg1, arg2),
+        SYS_CPU_QUOTA => sys_cpu_quota(arg1, arg2, arg3),
+        SYS_HWINFO => sys_hwinfo(arg1, arg2, arg3),
+        SYS_PING => sys_ping(arg1),
+        SYS_SOCKET => sys_socket(arg1, arg2, arg3),
+        SYS_BIND => sys_bind(arg1, arg2, arg3),
+        SYS_CONNECT => sys_connect(arg1, arg2, arg3),
+        SYS_LISTEN => sys_listen(arg1, arg2),
+        SYS_ACCEPT => sys_accept(arg1, arg2, arg3),
+        SYS_RESOLVE => sys_resolve(arg1, arg2, arg3),
+        SYS_SCHEDSTAT => sys_schedstat(arg1, arg2, arg3),
+        SYS_TASK_INFO => sys_task_info(arg1, arg2),
+        SYS_TASK_LIST => sys_task_list(arg1, arg2),
+        SYS_TASK_WATCH => sys_task_watch(arg1, arg2),
+        SYS_NANOSLEEP => sys_nanosleep(arg1),
+        SYS_POWEROFF => sys_poweroff(),
         _ => {
             serial_println!("[SYSCALL] ERROR: Invalid syscall ID: {}", syscall_id);
-            -1 // Invalid syscall
+            Err(Errno::ENOSYS)
         }
     };
 
     // Log syscall return value
-    if result >= 0 {
-        serial_println!(
-            "[SYSCALL] Task {} {} returned: {}",
+    match result {
+        Ok(value) => crate::log_trace!(
+            "SYSCALL",
+            "Task {} {} returned: {}",
             task_id,
             syscall_name,
-            result
-        );
-    } else {
-        serial_println!(
-            "[SYSCALL] ERROR: Task {} {} failed with error: {}",
+            value
+        ),
+        Err(errno) => crate::log_trace!(
+            "SYSCALL",
+            "Task {} {} failed with error: {:?}",
             task_id,
             syscall_name,
-            result
-        );
+            errno
+        ),
     }
 
     result
@@ -307,6 +493,102 @@ fn kernel_buffer_allowed() -> bool {
     }
 }
 
+/// Bytes staged on the kernel stack per copy when a syscall streams a user
+/// buffer
+const USER_IO_CHUNK: usize = 256;
+
+/// Copy a `T` in from user memory
+///
+/// Goes through `copy_from_user` so it works with SMAP enabled. Returns None
+/// if `ptr` is not a valid user range.
+fn read_user<T: Copy>(ptr: usize) -> Option<T> {
+    let size = core::mem::size_of::<T>();
+    if !validate_user_buffer(ptr, size) {
+        return None;
+    }
+
+    let mut value = core::mem::MaybeUninit::<T>::zeroed();
+    let bytes = unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size) };
+    copy_from_user(bytes, ptr, size).ok()?;
+    Some(unsafe { value.assume_init() })
+}
+
+/// Copy a `T` out to user memory
+///
+/// Goes through `copy_to_user` so it works with SMAP enabled. Returns false
+/// if `ptr` is not a valid user range.
+fn write_user<T: Copy>(ptr: usize, value: T) -> bool {
+    let size = core::mem::size_of::<T>();
+    if !validate_user_buffer(ptr, size) {
+        return false;
+    }
+
+    let bytes = unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, size) };
+    copy_to_user(ptr, bytes).is_ok()
+}
+
+/// Feed a syscall input buffer to `consume`
+///
+/// Kernel buffers (`user == false`) are handed over in one piece. User
+/// buffers are copied in `USER_IO_CHUNK` bytes at a time, since the kernel
+/// may not touch user memory directly. `consume` returns the number of bytes
+/// it accepted or an error; a short count stops the loop.
+fn consume_input(
+    buf_ptr: usize,
+    len: usize,
+    user: bool,
+    mut consume: impl FnMut(&[u8]) -> SyscallResult,
+) -> SyscallResult {
+    if !user {
+        let buffer = unsafe { core::slice::from_raw_parts(buf_ptr as *const u8, len) };
+        return consume(buffer);
+    }
+
+    let mut chunk = [0u8; USER_IO_CHUNK];
+    let mut done = 0;
+    while done < len {
+        let n = core::cmp::min(len - done, USER_IO_CHUNK);
+        if copy_from_user(&mut chunk[..n], buf_ptr + done, n).is_err() {
+            return if done > 0 { Ok(done) } else { Err(Errno::EFAULT) };
+        }
+
+        let accepted = match consume(&chunk[..n]) {
+            Ok(accepted) => accepted,
+            Err(errno) => return if done > 0 { Ok(done) } else { Err(errno) },
+        };
+        done += accepted;
+        if accepted < n {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+/// Let `produce` fill a syscall output buffer
+///
+/// Kernel buffers are handed over directly. For user buffers `produce`
+/// fills a staging buffer of at most `USER_IO_CHUNK` bytes (a short read),
+/// which is then copied out with `copy_to_user`.
+fn produce_output(
+    buf_ptr: usize,
+    len: usize,
+    user: bool,
+    produce: impl FnOnce(&mut [u8]) -> SyscallResult,
+) -> SyscallResult {
+    if !user {
+        let buffer = unsafe { core::slice::from_raw_parts_mut(buf_ptr as *mut u8, len) };
+        return produce(buffer);
+    }
+
+    let mut chunk = [0u8; USER_IO_CHUNK];
+    let n = core::cmp::min(len, USER_IO_CHUNK);
+    let produced = produce(&mut chunk[..n])?;
+    if produced > 0 && copy_to_user(buf_ptr, &chunk[..produced]).is_err() {
+        return Err(Errno::EFAULT);
+    }
+    Ok(produced)
+}
+
 /// sys_write handler - Write data to file descriptor
 ///
 /// # Arguments
@@ -315,82 +597,106 @@ fn kernel_buffer_allowed() -> bool {
 /// * `len` - Length of data to write
 ///
 /// # Returns
-/// Number of bytes written, or -1 on error
-fn sys_write(fd: usize, buf_ptr: usize, len: usize) -> isize {
+/// Number of bytes written, or an error
+fn sys_write(fd: usize, buf_ptr: usize, len: usize) -> SyscallResult {
     if len == 0 {
-        return 0; // Nothing to write
+        return Ok(0); // Nothing to write
     }
 
     let user_ok = validate_user_buffer(buf_ptr, len);
     if !user_ok {
         let allow_kernel = buf_ptr >= USER_LIMIT && kernel_buffer_allowed();
         if !allow_kernel {
-            return -1;
+            return Err(Errno::EFAULT);
         }
     }
 
-    // Convert pointer to slice
-    let buffer = unsafe { core::slice::from_raw_parts(buf_ptr as *const u8, len) };
-
-    // Handle stdout/stderr (FD 0/1) - write to serial
-    if fd == 0 || fd == 1 {
-        // Convert to string (lossy for non-UTF8)
-        let s = core::str::from_utf8(buffer).unwrap_or("[invalid UTF-8]");
-        serial_print!("{}", s);
-        return len as isize;
-    }
+    consume_input(buf_ptr, len, user_ok, |buffer| write_fd(fd, buffer))
+}
 
-    // Look up file descriptor
-    let fd_table = FD_TABLE.lock();
-    let fd_entry = match fd_table.get(fd) {
-        Some(entry) => entry,
+/// Write a kernel-side buffer to a file descriptor
+///
+/// Writing to an event handle adds the `u64` at the start of the buffer to
+/// its count.
+fn write_fd(fd: usize, buffer: &[u8]) -> SyscallResult {
+    // Look up the handle
+    let handle = match lookup(fd) {
+        Some(handle) => handle,
         None => {
             serial_println!("[SYSCALL] sys_write: invalid FD {}", fd);
-            return -1; // EBADF
+            return Err(Errno::EBADF);
+        }
+    };
+    let fd_type = match handle.object {
+        Object::File(fd_type) => fd_type,
+        Object::Event(id) => {
+            let value = buffer.get(..8).ok_or(Errno::EINVAL)?;
+            waitable::signal_event(id, u64::from_ne_bytes(value.try_into().unwrap_or_default()))?;
+            return Ok(8);
+        }
+        _ => {
+            serial_println!("[SYSCALL] sys_write: handle {} is not writable", fd);
+            return Err(Errno::EBADF);
         }
     };
-    drop(fd_table);
 
     // Handle based on FD type
-    match fd_entry.fd_type {
+    match fd_type {
+        FdType::Console => {
+            crate::console::write_bytes(buffer);
+            Ok(buffer.len())
+        }
         FdType::PtyMaster(pty_num) => {
             // Write to PTY master (writes to slave input)
             let bytes_written = crate::dev::pty::write_master(pty_num, buffer);
-            bytes_written as isize
+            Ok(bytes_written)
         }
         FdType::PtySlave(pty_num) => {
             // Write to PTY slave (writes to master output)
             let bytes_written = crate::dev::pty::write_slave(pty_num, buffer);
-            bytes_written as isize
+            Ok(bytes_written)
         }
         FdType::PipeWrite(pipe_id) => {
-            // Write to pipe
-            let mut pipe_table = PIPE_TABLE.lock();
-            match pipe_table.get_mut(pipe_id) {
-                Some(pipe) => {
-                    // Check if there are any readers
-                    if pipe.readers == 0 {
-                        serial_println!("[SYSCALL] sys_write: pipe has no readers (SIGPIPE)");
-                        // TODO: Send SIGPIPE to current process
-                        return -1; // EPIPE
+            // Write to pipe, blocking while it is full until all is written
+            let mut written = 0;
+            loop {
+                let mut pipe_table = PIPE_TABLE.lock();
+                let pipe = match pipe_table.get_mut(pipe_id) {
+                    Some(pipe) => pipe,
+                    None => {
+                        serial_println!("[SYSCALL] sys_write: invalid pipe");
+                        return Err(Errno::EBADF);
                     }
-                    let bytes_written = pipe.write(buffer);
-                    bytes_written as isize
+                };
+                // Check if there are any readers
+                if pipe.readers == 0 {
+                    serial_println!("[SYSCALL] sys_write: pipe has no readers (SIGPIPE)");
+                    // TODO: Send SIGPIPE to current process
+                    return if written > 0 { Ok(written) } else { Err(Errno::EPIPE) };
                 }
-                None => {
-                    serial_println!("[SYSCALL] sys_write: invalid pipe");
-                    -1 // EBADF
+                let bytes_written = pipe.write(&buffer[written..]);
+                written += bytes_written;
+                drop(pipe_table);
+                if bytes_written > 0 {
+                    PIPE_WAIT[pipe_id as usize].wake_all();
+                }
+
+                if written == buffer.len() {
+                    return Ok(written);
+                }
+                if handle.status_flags & O_NONBLOCK != 0 || !pipe_wait(pipe_id, Pipe::can_write) {
+                    return if written > 0 { Ok(written) } else { Err(Errno::EAGAIN) };
                 }
             }
         }
         FdType::PipeRead(_) => {
             serial_println!("[SYSCALL] sys_write: cannot write to pipe read end");
-            -1 // EBADF
-        }
-        FdType::Invalid => {
-            serial_println!("[SYSCALL] sys_write: invalid FD type");
-            -1 // EBADF
+            Err(Errno::EBADF)
         }
+        FdType::File(_) => Err(Errno::EBADF),
+        FdType::Framebuffer | FdType::Input(_) => Err(Errno::EINVAL),
+        // Datagram sockets are never connected: they need SYS_SENDTO
+        FdType::Socket(socket) => send_socket(socket, buffer, handle.status_flags & O_NONBLOCK != 0),
     }
 }
 
@@ -419,115 +725,236 @@ fn sys_exit(code: usize) -> ! {
 /// * `ticks` - Number of ticks to sleep
 ///
 /// # Returns
-/// 0 on success, -1 on error
+/// 0 once the ticks have passed, or `EINTR` if a signal for the task cut
+/// the sleep short
 ///
 /// # SMP Safety
-/// This function is SMP-safe because:
-/// - Task state modifications are protected by per-task locks (implicit in get_task_mut)
-/// - Uses current core's context via percpu_current()
-/// - yield_now() operates on current core's runqueue
-fn sys_sleep(ticks: usize) -> isize {
+/// The sleep goes through `sched::wait::sleep`, which queues the wake-up
+/// before switching away, so it cannot be lost to another core
+fn sys_sleep(ticks: usize) -> SyscallResult {
     // Validate tick count
     if ticks == 0 {
-        return 0; // Sleep for 0 ticks is a no-op
+        return Ok(0); // Sleep for 0 ticks is a no-op
     }
 
-    // Get current task ID and priority from scheduler
-    let (_task_id, priority) = match crate::sched::get_current_task_info() {
-        Some(info) => info,
-        None => {
-            return -1;
-        }
-    };
+    // Increment sleep counter metric
+    use core::sync::atomic::Ordering;
+    METRICS.sleep_count.fetch_add(1, Ordering::Relaxed);
 
-    // Call scheduler to put task to sleep
-    // This modifies task state with proper locking
-    if !crate::sched::sleep_current_task(ticks as u64, priority) {
-        return -1;
+    // Sleep as a wait that only the timeout or a signal ends; the
+    // scheduler switches away from this task until then
+    let duration = crate::time::Duration::from_ticks(ticks as u64);
+    crate::sched::wait::sleep(duration)?;
+    Ok(0)
+}
+
+/// sys_nanosleep handler - Put task to sleep for `nanos` nanoseconds
+///
+/// Like `sys_sleep`, but not rounded to ticks: in TSC-deadline mode the
+/// LAPIC timer fires at the deadline (see `apic::timer`); otherwise the
+/// sleep ends at the first tick after it.
+///
+/// # Returns
+/// 0 once the time has passed, or `EINTR` if a signal for the task cut
+/// the sleep short
+fn sys_nanosleep(nanos: usize) -> SyscallResult {
+    if nanos == 0 {
+        return Ok(0);
     }
 
-    // Increment sleep counter metric
     use core::sync::atomic::Ordering;
     METRICS.sleep_count.fetch_add(1, Ordering::Relaxed);
 
-    // Trigger scheduler to select next task on current core
-    // This will context switch away from the current task
-    crate::sched::yield_now();
+    crate::sched::wait::sleep(crate::time::Duration::from_nanos(nanos as u64))?;
+    Ok(0)
+}
 
-    // When we wake up, we return here
-    0
+/// sys_poweroff handler - Write the settings out and turn the machine off
+///
+/// Only init may call it, once it has stopped everything else.
+///
+/// # Returns
+/// Nothing on success; `EPERM` for any other process, or `ENOSYS` if the
+/// firmware gives no way to power off (see `acpi::power`)
+fn sys_poweroff() -> SyscallResult {
+    let pid = crate::sched::get_current_task_info()
+        .and_then(|(id, _)| crate::sched::get_task_by_id(id))
+        .map(|task| task.pid);
+    if pid != Some(crate::user::spawn::INIT_PID) {
+        return Err(Errno::EPERM);
+    }
+    let e = crate::arch::x86_64::acpi::power::shutdown();
+    serial_println!("[SYSCALL] sys_poweroff: {:?}", e);
+    Err(Errno::ENOSYS)
 }
 
 /// sys_ipc_send handler - Send message to port
 ///
 /// # Arguments
-/// * `port_id` - Target port ID
+/// * `cap` - Handle of the target port (needs the send right), optionally
+///   OR-ed with `IPC_NONBLOCK` (sends never block, so the flag is accepted
+///   and ignored) and with a handle of any kind to pass along, encoded as
+///   `(handle + 1) << IPC_CAP_SHIFT` (needs the grant right). The receiver
+///   gets a copy; the sender keeps its own.
 /// * `buf_ptr` - Pointer to message buffer
 /// * `len` - Length of message
 ///
 /// # Returns
-/// 0 on success, -1 on error
+/// 0 on success, or an error
 ///
 /// # SMP Safety
 /// This function is SMP-safe because:
 /// - PORT_MANAGER uses a global mutex for port table access
 /// - Individual ports use per-port locks for queue operations
 /// - Task wakeup sends RESCHEDULE_IPI to receiver's CPU if needed
-fn sys_ipc_send(port_id: usize, buf_ptr: usize, len: usize) -> isize {
+fn sys_ipc_send(cap: usize, buf_ptr: usize, len: usize) -> SyscallResult {
+    use crate::sys::ipc::{Message, IPC_CAP_SHIFT, IPC_NONBLOCK, MAX_MESSAGE_SIZE};
     use crate::sys::port::PORT_MANAGER;
 
+    let grant = cap >> IPC_CAP_SHIFT;
+    let handle = cap & !IPC_NONBLOCK & ((1 << IPC_CAP_SHIFT) - 1);
+
     // Validate buffer pointer and length
     if len == 0 {
-        return 0;
+        return Ok(0);
     }
     let user_ok = validate_user_buffer(buf_ptr, len);
     if !user_ok {
         let allow_kernel = buf_ptr >= USER_LIMIT && kernel_buffer_allowed();
         if !allow_kernel {
-            return -1;
+            return Err(Errno::EFAULT);
+        }
+    }
+
+    // Resolve the target port and the handle to transfer
+    let Some(table) = handle::current() else { return Err(Errno::ESRCH) };
+    let port_id = match table.lock().port(handle, Rights::SEND) {
+        Ok(port_id) => port_id,
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_ipc_send: handle {}: {:?}", handle, e);
+            return Err(e.into());
+        }
+    };
+    crate::trace!(ipc_send, port_id, len);
+    let transfer = match grant {
+        0 => None,
+        grant => {
+            // The message holds its own reference while in flight
+            let table = table.lock();
+            match table.check(grant - 1, Rights::GRANT) {
+                Ok(transfer) => {
+                    transfer.object.retain();
+                    Some(transfer)
+                }
+                Err(e) => {
+                    serial_println!("[SYSCALL] sys_ipc_send: grant {}: {:?}", grant - 1, e);
+                    return Err(e.into());
+                }
+            }
         }
+    };
+
+    if !user_ok && transfer.is_none() {
+        // Kernel task passing a kernel buffer
+        let buffer = unsafe { core::slice::from_raw_parts(buf_ptr as *const u8, len) };
+        let result = PORT_MANAGER.lock().send_message(port_id, buffer);
+        return ipc_send_result(result);
     }
 
-    // Phase 4: No pointer validation, assume kernel-accessible
-    // Convert pointer to slice
-    let buffer = unsafe { core::slice::from_raw_parts(buf_ptr as *const u8, len) };
+    // Copy the payload straight from user memory into the message
+    let mut message = Message::new();
+    let copied = if len > MAX_MESSAGE_SIZE {
+        Err(Errno::EMSGSIZE)
+    } else if user_ok {
+        copy_from_user(&mut message.data[..len], buf_ptr, len).map_err(|_| Errno::EFAULT)
+    } else {
+        let buffer = unsafe { core::slice::from_raw_parts(buf_ptr as *const u8, len) };
+        message.data[..len].copy_from_slice(buffer);
+        Ok(())
+    };
+    message.len = len;
+    message.handle = transfer;
 
-    // Get PORT_MANAGER and send message
-    let mut port_mgr = PORT_MANAGER.lock();
-    match port_mgr.send_message(port_id, buffer) {
-        Ok(()) => 0,
-        Err(_e) => -1,
+    // Get PORT_MANAGER and send message; a message that is not queued
+    // drops its reference
+    let result = copied.and_then(|()| Ok(PORT_MANAGER.lock().send_prepared(port_id, &message)?));
+    if result.is_err() {
+        if let Some(transfer) = transfer {
+            transfer.object.release();
+        }
     }
+    ipc_send_result(result)
+}
+
+/// Map a send result to the syscall return value, counting the message
+fn ipc_send_result(result: Result<(), impl Into<Errno>>) -> SyscallResult {
+    result.map_err(Into::into)?;
+    crate::sched::charge_current(|usage| usage.record_msg_sent());
+    Ok(0)
 }
 
-/// sys_ipc_recv handler - Receive message from port (blocking)
+/// sys_ipc_recv handler - Receive message from port
 ///
 /// # Arguments
-/// * `port_id` - Source port ID
+/// * `cap` - Capability handle of the source port (needs the receive
+///   right), optionally OR-ed with `IPC_NONBLOCK`
 /// * `buf_ptr` - Pointer to receive buffer
 /// * `len` - Maximum length to receive
 ///
 /// # Returns
-/// Number of bytes received, or -1 on error
+/// Number of bytes received, 0 if `IPC_NONBLOCK` was given and no message
+/// is queued, or an error. If the message carried a handle, it is added to
+/// the task's table and `(handle + 1) << IPC_CAP_SHIFT` is OR-ed into the
+/// result; with the table full the handle is closed.
+///
+/// A blocking receive fails with `EINTR` if a signal for the task arrives
+/// first.
 ///
 /// # SMP Safety
 /// This function is SMP-safe because:
 /// - PORT_MANAGER uses a global mutex for port table access
 /// - Individual ports use per-port locks for queue operations
-/// - Task blocking/unblocking uses proper task state locks
-/// - yield_now() operates on current core's runqueue
-fn sys_ipc_recv(port_id: usize, buf_ptr: usize, len: usize) -> isize {
+/// - A blocking receive waits on the port's wait queue, not holding
+///   PORT_MANAGER
+fn sys_ipc_recv(cap: usize, buf_ptr: usize, len: usize) -> SyscallResult {
+    ipc_recv(cap, buf_ptr, len, crate::time::Duration::MAX)
+}
+
+/// sys_ipc_recv_timeout handler - Receive from a port, waiting at most
+/// `timeout` ticks
+///
+/// Same as [`sys_ipc_recv`], but a blocking receive fails with `ETIMEDOUT`
+/// once `timeout` ticks pass with no message; `IPC_WAIT_FOREVER` waits as
+/// long as `SYS_IPC_RECV`. Takes four arguments, so it is only reachable
+/// through the `syscall` instruction.
+pub fn sys_ipc_recv_timeout(cap: usize, buf_ptr: usize, len: usize, timeout: usize) -> SyscallResult {
+    use crate::sys::ipc::IPC_WAIT_FOREVER;
+    use crate::time::Duration;
+
+    let timeout = match timeout {
+        IPC_WAIT_FOREVER => Duration::MAX,
+        ticks => Duration::from_ticks(ticks as u64),
+    };
+    ipc_recv(cap, buf_ptr, len, timeout)
+}
+
+/// Receive for `SYS_IPC_RECV` and `SYS_IPC_RECV_TIMEOUT`
+fn ipc_recv(cap: usize, buf_ptr: usize, len: usize, timeout: crate::time::Duration) -> SyscallResult {
+    use crate::sys::ipc::{self, IpcError, Message, IPC_CAP_SHIFT, IPC_NONBLOCK};
     use crate::sys::port::PORT_MANAGER;
 
+    let nonblock = cap & IPC_NONBLOCK != 0;
+    let handle = cap & !IPC_NONBLOCK;
+
     // Validate buffer pointer and length
     if len == 0 {
-        return 0;
+        return Ok(0);
     }
     let user_ok = validate_user_buffer(buf_ptr, len);
     if !user_ok {
         let allow_kernel = buf_ptr >= USER_LIMIT && kernel_buffer_allowed();
         if !allow_kernel {
-            return -1;
+            return Err(Errno::EFAULT);
         }
     }
 
@@ -535,54 +962,93 @@ fn sys_ipc_recv(port_id: usize, buf_ptr: usize, len: usize) -> isize {
     let task_id = match crate::sched::get_current_task_info() {
         Some((id, _)) => id,
         None => {
-            return -1;
+            return Err(Errno::ESRCH);
+        }
+    };
+    let Some(table) = handle::current() else { return Err(Errno::ESRCH) };
+    let port_id = match table.lock().port(handle, Rights::RECV) {
+        Ok(port_id) => port_id,
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_ipc_recv: handle {}: {:?}", handle, e);
+            return Err(e.into());
         }
     };
 
-    // Phase 4: No pointer validation, assume kernel-accessible
-    // Convert pointer to mutable slice
-    let buffer = unsafe { core::slice::from_raw_parts_mut(buf_ptr as *mut u8, len) };
+    // Receive, waiting on the port's queue if blocking
+    let mut received = None;
+    let mut deliver = |message: &Message| {
+        received = message.handle;
+        let n = core::cmp::min(message.len(), len);
+        if user_ok {
+            // Copy the payload straight out to user memory
+            match copy_to_user(buf_ptr, &message.as_slice()[..n]) {
+                Ok(()) => n,
+                Err(_) => 0,
+            }
+        } else {
+            // Kernel task passing a kernel buffer
+            let buffer = unsafe { core::slice::from_raw_parts_mut(buf_ptr as *mut u8, n) };
+            buffer.copy_from_slice(&message.as_slice()[..n]);
+            n
+        }
+    };
+    let result = if nonblock {
+        PORT_MANAGER.lock().recv_message_with(port_id, task_id, true, &mut deliver)
+    } else {
+        ipc::recv_timeout(port_id, timeout, &mut deliver)
+    };
 
-    // Get PORT_MANAGER and receive message
-    let mut port_mgr = PORT_MANAGER.lock();
-    match port_mgr.recv_message(port_id, task_id, buffer) {
-        Ok(bytes_received) => bytes_received as isize,
-        Err(_e) => -1,
+    match result {
+        Ok(bytes_received) => {
+            crate::sched::charge_current(|usage| usage.record_msg_received());
+            let granted = match received.map(handle::install) {
+                Some(Ok(handle)) => (handle + 1) << IPC_CAP_SHIFT,
+                Some(Err(e)) => {
+                    serial_println!("[SYSCALL] sys_ipc_recv: handle dropped: {:?}", e);
+                    0
+                }
+                None => 0,
+            };
+            Ok(bytes_received | granted)
+        }
+        Err(IpcError::WouldBlock) => Ok(0),
+        Err(e) => Err(e.into()),
     }
 }
 
-fn sys_getpid() -> isize {
-    crate::sched::get_current_task_info()
-        .map(|(id, _)| id as isize)
-        .unwrap_or(1)
+fn sys_getpid() -> SyscallResult {
+    Ok(crate::sched::get_current_task_info()
+        .and_then(|(id, _)| crate::sched::get_task_by_id(id))
+        .map(|task| task.pid)
+        .unwrap_or(1))
 }
 
-fn sys_yield() -> isize {
+fn sys_yield() -> SyscallResult {
     crate::sched::yield_now();
-    0
+    Ok(0)
 }
 
-fn sys_fork() -> isize {
+fn sys_fork() -> SyscallResult {
     let child_pid = NEXT_FAKE_PID.fetch_add(1, AtomicOrdering::Relaxed);
     serial_println!("Child process created in fork chain");
-    child_pid as isize
+    Ok(child_pid)
 }
 
-fn sys_wait(_child_pid: usize) -> isize {
+fn sys_wait(_child_pid: usize) -> SyscallResult {
     serial_println!("[SYSCALL] SYS_WAIT: not implemented, returning 0");
-    0
+    Ok(0)
 }
 
-fn sys_exec(_elf_ptr: usize, _len: usize) -> isize {
+fn sys_exec(_elf_ptr: usize, _len: usize) -> SyscallResult {
     serial_println!("[SYSCALL] SYS_EXEC: not implemented");
-    -1
+    Err(Errno::ENOSYS)
 }
 
 /// File descriptor type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FdType {
-    /// Invalid/closed FD
-    Invalid,
+    /// Kernel console (default stdio without a controlling terminal)
+    Console,
     /// PTY master device
     PtyMaster(u32),
     /// PTY slave device
@@ -591,54 +1057,28 @@ pub enum FdType {
     PipeRead(u32),
     /// Pipe write end
     PipeWrite(u32),
+    /// Read-only initrd or /proc file (see `fs::file`)
+    File(u32),
+    /// Framebuffer device, for ioctl and mmap only (see `dev::fb`)
+    Framebuffer,
+    /// Client of an input device (see `dev::input`)
+    Input(u32),
+    /// Datagram socket (see `net::socket`)
+    Socket(u32),
 }
 
 /// File descriptor flags (FD_CLOEXEC)
-const FD_CLOEXEC: u32 = 1;
+pub(crate) const FD_CLOEXEC: u32 = 1;
 
 /// File status flags
 const O_NONBLOCK: u32 = 0x800;
 const O_APPEND: u32 = 0x400;
 
-/// File descriptor table entry
-#[derive(Debug, Clone, Copy)]
-pub struct FileDescriptor {
-    /// Type of file descriptor
-    fd_type: FdType,
-    /// FD flags (FD_CLOEXEC, etc.)
-    fd_flags: u32,
-    /// File status flags (O_NONBLOCK, O_APPEND, etc.)
-    status_flags: u32,
-}
-
-impl FileDescriptor {
-    const fn new() -> Self {
-        Self {
-            fd_type: FdType::Invalid,
-            fd_flags: 0,
-            status_flags: 0,
-        }
-    }
-
-    fn with_type(fd_type: FdType) -> Self {
-        Self {
-            fd_type,
-            fd_flags: 0,
-            status_flags: 0,
-        }
-    }
-
-    fn with_flags(fd_type: FdType, fd_flags: u32, status_flags: u32) -> Self {
-        Self {
-            fd_type,
-            fd_flags,
-            status_flags,
-        }
-    }
-}
-
-/// Maximum number of file descriptors per process
-const MAX_FDS: usize = 256;
+/// Open flags that only affect the open call itself
+const O_ACCMODE: usize = 0x3;
+const O_RDONLY: usize = 0;
+const O_CREAT: usize = 0x40;
+const O_CLOEXEC: usize = 0x80000;
 
 /// Maximum number of pipes
 const MAX_PIPES: usize = 64;
@@ -646,7 +1086,20 @@ const MAX_PIPES: usize = 64;
 /// Pipe buffer size (4KB)
 const PIPE_BUF_SIZE: usize = 4096;
 
+/// Poll events reported by `fd_events`
+pub const POLLIN: u16 = 0x1;
+pub const POLLOUT: u16 = 0x4;
+pub const POLLERR: u16 = 0x8;
+pub const POLLHUP: u16 = 0x10;
+/// Not an open fd or a valid port handle (`SYS_POLL` only)
+pub const POLLNVAL: u16 = 0x20;
+
 /// Pipe structure
+///
+/// Reads block while the pipe is empty and writes while it is full, unless
+/// the fd has `O_NONBLOCK`. Blocked and polling tasks sit on the pipe's
+/// queue in `PIPE_WAIT` and are all woken whenever data, space or an end
+/// goes away; each re-checks what it was waiting for.
 struct Pipe {
     /// Ring buffer for data
     buffer: [u8; PIPE_BUF_SIZE],
@@ -706,8 +1159,39 @@ impl Pipe {
     fn is_full(&self) -> bool {
         self.count == PIPE_BUF_SIZE
     }
+
+    /// A read would not block: data, or end of file
+    fn can_read(&self) -> bool {
+        !self.is_empty() || self.writers == 0
+    }
+
+    /// A write would not block: space, or no reader left (EPIPE)
+    fn can_write(&self) -> bool {
+        !self.is_full() || self.readers == 0
+    }
+
+    /// Poll events of the read end or the write end
+    fn events(&self, read_end: bool) -> u16 {
+        let mut events = 0;
+        if read_end {
+            if !self.is_empty() {
+                events |= POLLIN;
+            }
+            if self.writers == 0 {
+                events |= POLLHUP;
+            }
+        } else if self.readers == 0 {
+            events |= POLLERR;
+        } else if !self.is_full() {
+            events |= POLLOUT;
+        }
+        events
+    }
 }
 
+/// Tasks waiting on each pipe
+static PIPE_WAIT: [WaitQueue; MAX_PIPES] = [const { WaitQueue::new() }; MAX_PIPES];
+
 /// Global pipe table
 struct PipeTable {
     pipes: [Pipe; MAX_PIPES],
@@ -771,175 +1255,245 @@ impl PipeTable {
 
 static PIPE_TABLE: SpinLock<PipeTable> = SpinLock::new(PipeTable::new());
 
-/// Close all file descriptors with FD_CLOEXEC flag set
-///
-/// This is called during exec to close file descriptors that should not
-/// be inherited by the new program.
-pub fn close_fds_with_cloexec() {
-    let mut fd_table = FD_TABLE.lock();
-    
-    // Scan all file descriptors
-    for fd in 0..MAX_FDS {
-        if let Some(fd_entry) = fd_table.get(fd) {
-            // Check if FD_CLOEXEC flag is set
-            if (fd_entry.fd_flags & FD_CLOEXEC) != 0 {
-                serial_println!("[SYSCALL] Closing FD {} (FD_CLOEXEC set)", fd);
-                
-                // Get the FD type before closing
-                let fd_type = fd_entry.fd_type;
-                
-                // Close the FD
-                fd_table.close(fd);
-                
-                // Handle cleanup based on FD type
-                match fd_type {
-                    FdType::PtyMaster(pty_num) => {
-                        crate::dev::pty::deallocate_pty(pty_num);
-                    }
-                    FdType::PipeRead(pipe_id) => {
-                        let mut pipe_table = PIPE_TABLE.lock();
-                        pipe_table.close_reader(pipe_id);
-                    }
-                    FdType::PipeWrite(pipe_id) => {
-                        let mut pipe_table = PIPE_TABLE.lock();
-                        pipe_table.close_writer(pipe_id);
-                    }
-                    _ => {}
-                }
-            }
-        }
+/// Close one end of a pipe, waking tasks blocked on the other end (readers
+/// see end of file, writers EPIPE)
+fn close_pipe_end(pipe_id: u32, read_end: bool) {
+    let mut pipe_table = PIPE_TABLE.lock();
+    if read_end {
+        pipe_table.close_reader(pipe_id);
+    } else {
+        pipe_table.close_writer(pipe_id);
+    }
+    drop(pipe_table);
+    if let Some(queue) = PIPE_WAIT.get(pipe_id as usize) {
+        queue.wake_all();
     }
 }
 
-/// Per-task file descriptor table
+/// Block the current task until `ready` holds for pipe `pipe_id`
 ///
-/// For now, we use a simple global table. In a full implementation,
-/// this would be per-task.
-struct FdTable {
-    fds: [FileDescriptor; MAX_FDS],
-}
-
-impl FdTable {
-    const fn new() -> Self {
-        Self {
-            fds: [FileDescriptor::new(); MAX_FDS],
+/// Returns false if the pipe is gone or has too many waiters.
+fn pipe_wait(pipe_id: u32, ready: fn(&Pipe) -> bool) -> bool {
+    let Some(queue) = PIPE_WAIT.get(pipe_id as usize) else { return false };
+    let mut gone = false;
+    let waited = queue.wait_until(|| match PIPE_TABLE.lock().get(pipe_id) {
+        Some(pipe) => ready(pipe),
+        None => {
+            gone = true;
+            true
         }
+    });
+    if !waited {
+        serial_println!("[SYSCALL] pipe {}: too many waiters", pipe_id);
     }
+    waited && !gone
+}
 
-    fn allocate(&mut self, fd_type: FdType) -> Option<usize> {
-        // Start from FD 3 (after stdin/stdout/stderr)
-        for i in 3..MAX_FDS {
-            if matches!(self.fds[i].fd_type, FdType::Invalid) {
-                self.fds[i] = FileDescriptor::with_type(fd_type);
-                return Some(i);
+/// Poll events of `object` (`POLLIN`, `POLLOUT`, `POLLHUP`, `POLLERR`), or
+/// None if it no longer exists
+///
+/// The console is readable once input has arrived; PTYs always report
+/// both directions ready. A port is readable when it has a message, a
+/// timer once it has expired and an event while its count is not 0.
+pub(crate) fn object_events(object: Object) -> Option<u16> {
+    let (pipe_id, read_end) = match object {
+        Object::File(FdType::PipeRead(pipe_id)) => (pipe_id, true),
+        Object::File(FdType::PipeWrite(pipe_id)) => (pipe_id, false),
+        Object::File(FdType::Console) if crate::console::input_ready() => return Some(POLLIN | POLLOUT),
+        Object::File(FdType::Console) => return Some(POLLOUT),
+        Object::File(FdType::Input(client)) => return Some(if crate::dev::input::readable(client) { POLLIN } else { 0 }),
+        Object::File(FdType::Socket(socket)) => {
+            let readable = if crate::net::socket::readable(socket) { POLLIN } else { 0 };
+            let writable = if crate::net::socket::writable(socket) { POLLOUT } else { 0 };
+            return Some(readable | writable);
+        }
+        Object::File(_) => return Some(POLLIN | POLLOUT),
+        Object::Port(port_id) => {
+            return match crate::sys::port::PORT_MANAGER.lock().has_message(port_id) {
+                Ok(true) => Some(POLLIN),
+                Ok(false) => Some(0),
+                Err(_) => None,
             }
         }
-        None
+        Object::Shm(_) => return Some(0),
+        Object::Timer(id) => return waitable::timer_pending(id).map(|count| if count > 0 { POLLIN } else { 0 }),
+        Object::Event(id) => return waitable::event_count(id).map(|count| if count > 0 { POLLIN | POLLOUT } else { POLLOUT }),
+    };
+    PIPE_TABLE.lock().get(pipe_id).map(|pipe| pipe.events(read_end))
+}
+
+/// Queue woken on readiness changes of `object`, for pollers, which then
+/// re-check `object_events`
+///
+/// None for objects that never block, and for timers, which pollers wait
+/// for until their deadline.
+pub(crate) fn object_wait_queue(object: Object) -> Option<&'static WaitQueue> {
+    match object {
+        Object::File(FdType::PipeRead(pipe_id) | FdType::PipeWrite(pipe_id)) => PIPE_WAIT.get(pipe_id as usize),
+        Object::File(FdType::Console) => Some(&crate::console::INPUT_WAIT),
+        Object::File(FdType::Input(_)) => Some(&crate::dev::input::WAIT),
+        Object::File(FdType::Socket(socket)) => Some(crate::net::socket::wait_queue(socket)),
+        Object::Port(port_id) => crate::sys::port::wait_queue(port_id),
+        Object::Event(id) => waitable::event_wait_queue(id),
+        _ => None,
     }
+}
 
-    fn allocate_with_flags(&mut self, fd_type: FdType, fd_flags: u32, status_flags: u32) -> Option<usize> {
-        // Start from FD 3 (after stdin/stdout/stderr)
-        for i in 3..MAX_FDS {
-            if matches!(self.fds[i].fd_type, FdType::Invalid) {
-                self.fds[i] = FileDescriptor::with_flags(fd_type, fd_flags, status_flags);
-                return Some(i);
+/// Take another reference to a file object (a handle was duplicated or
+/// inherited)
+pub(crate) fn retain_file(fd_type: FdType) {
+    match fd_type {
+        FdType::PtyMaster(pty_num) => crate::dev::pty::retain_master(pty_num),
+        FdType::PipeRead(pipe_id) | FdType::PipeWrite(pipe_id) => {
+            if let Some(pipe) = PIPE_TABLE.lock().get_mut(pipe_id) {
+                if matches!(fd_type, FdType::PipeRead(_)) {
+                    pipe.readers += 1;
+                } else {
+                    pipe.writers += 1;
+                }
             }
         }
-        None
+        FdType::File(file_id) => crate::fs::file::retain(file_id),
+        FdType::Framebuffer => crate::dev::fb::retain(),
+        FdType::Input(client) => crate::dev::input::retain(client),
+        FdType::Socket(socket) => crate::net::socket::retain(socket),
+        FdType::PtySlave(_) | FdType::Console => {}
     }
+}
 
-    fn allocate_at(&mut self, fd: usize, fd_type: FdType, fd_flags: u32, status_flags: u32) -> bool {
-        if fd >= MAX_FDS {
-            return false;
-        }
-        // Close existing FD if open
-        if !matches!(self.fds[fd].fd_type, FdType::Invalid) {
-            self.close(fd);
-        }
-        self.fds[fd] = FileDescriptor::with_flags(fd_type, fd_flags, status_flags);
-        true
+/// Drop a reference to a file object (a handle was closed)
+pub(crate) fn release_file(fd_type: FdType) {
+    match fd_type {
+        // The last master handle deallocates the PTY pair
+        FdType::PtyMaster(pty_num) => crate::dev::pty::release_master(pty_num),
+        // Blocked writers get EPIPE once the last read end is closed
+        FdType::PipeRead(pipe_id) => close_pipe_end(pipe_id, true),
+        // Blocked readers get EOF once the last write end is closed
+        FdType::PipeWrite(pipe_id) => close_pipe_end(pipe_id, false),
+        // The last handle frees an open file
+        FdType::File(file_id) => crate::fs::file::release(file_id),
+        // The last handle gives the screen back to the console
+        FdType::Framebuffer => crate::dev::fb::release(),
+        // The last handle closes the client and ends its grab
+        FdType::Input(client) => crate::dev::input::release(client),
+        // The last handle unbinds the socket's port
+        FdType::Socket(socket) => crate::net::socket::release(socket),
+        // Slave and console close don't deallocate anything
+        FdType::PtySlave(_) | FdType::Console => {}
     }
+}
 
-    fn get_mut(&mut self, fd: usize) -> Option<&mut FileDescriptor> {
-        if fd < MAX_FDS && !matches!(self.fds[fd].fd_type, FdType::Invalid) {
-            Some(&mut self.fds[fd])
-        } else {
-            None
-        }
-    }
+/// Look up handle `fd` of the current process, falling back to the
+/// default stdio wiring
+///
+/// Handles 0-2 that are empty (never opened, or closed) refer to the
+/// calling task's controlling terminal, or to the kernel console if it
+/// has none. A handle of their own, e.g. one installed by dup2, always
+/// wins.
+fn lookup(fd: usize) -> Option<Handle> {
+    let handle = handle::current().and_then(|table| table.lock().get(fd));
+    handle.or_else(|| stdio_handle(fd))
+}
 
-    fn get(&self, fd: usize) -> Option<FileDescriptor> {
-        if fd < MAX_FDS && !matches!(self.fds[fd].fd_type, FdType::Invalid) {
-            Some(self.fds[fd])
-        } else {
-            None
-        }
-    }
+/// The default stdio wiring of handle `fd`, if it is 0-2
+fn stdio_handle(fd: usize) -> Option<Handle> {
+    (fd < handle::STDIO_HANDLES).then(|| Handle::new(Object::File(default_stdio())))
+}
 
-    fn close(&mut self, fd: usize) -> Option<FileDescriptor> {
-        if fd < MAX_FDS && !matches!(self.fds[fd].fd_type, FdType::Invalid) {
-            let old = self.fds[fd];
-            self.fds[fd] = FileDescriptor::new();
-            Some(old)
-        } else {
-            None
-        }
+/// Copy of handle `fd` for dup and dup2, with a new reference to the object
+///
+/// The copy does not inherit `FD_CLOEXEC`; a default stdio handle can be
+/// copied too.
+fn copy_handle(fd: usize) -> Result<Handle, Errno> {
+    let table = handle::current().ok_or(Errno::EBADF)?;
+    let table = table.lock();
+    let handle = table.get(fd).or_else(|| stdio_handle(fd)).ok_or(Errno::EBADF)?;
+    handle.object.retain();
+    Ok(Handle { fd_flags: 0, ..handle })
+}
+
+/// Look up handle `fd` as a file; None if it is empty or another kind of
+/// object
+pub(crate) fn lookup_fd(fd: usize) -> Option<FdType> {
+    match lookup(fd)?.object {
+        Object::File(fd_type) => Some(fd_type),
+        _ => None,
     }
 }
 
-static FD_TABLE: SpinLock<FdTable> = SpinLock::new(FdTable::new());
+/// Where stdin/stdout/stderr go when the task has not redirected them
+fn default_stdio() -> FdType {
+    let tty = crate::sched::get_current_task_info()
+        .and_then(|(id, _)| crate::sched::get_task_by_id(id))
+        .and_then(|task| task.tty);
+
+    match tty {
+        // The controlling terminal's device ID is its PTY number
+        Some(device_id) => FdType::PtySlave(device_id as u32),
+        None => FdType::Console,
+    }
+}
 
 /// sys_open handler - Open a device or file
 ///
+/// Devices are `/dev/ptmx`, `/dev/pts/N`, `/dev/fb0` and `/dev/input/eventN`.
+/// Files are the regular files of the initrd and the files of `/proc`,
+/// which can only be opened read-only (see `fs::file`).
+///
 /// # Arguments
 /// * `path_ptr` - Pointer to null-terminated path string
-/// * `flags` - Open flags (O_RDONLY, O_WRONLY, O_RDWR, etc.)
+/// * `flags` - Open flags (O_RDONLY, O_WRONLY, O_RDWR, O_CLOEXEC, etc.)
+/// * `mode` - Permissions for a file created with O_CREAT, before the umask
 ///
 /// # Returns
-/// File descriptor on success, or -1 on error
-fn sys_open(path_ptr: usize, _flags: usize) -> isize {
+/// File descriptor on success, or an error
+fn sys_open(path_ptr: usize, flags: usize, mode: usize) -> SyscallResult {
     // Validate path pointer
     if !validate_user_buffer(path_ptr, 1) {
-        return -1;
+        return Err(Errno::EFAULT);
     }
 
     // Read path string (simplified - just check for /dev/ptmx)
     // In a full implementation, we'd properly parse the path
-    let path_bytes = unsafe {
-        let mut len = 0;
-        let ptr = path_ptr as *const u8;
-        while len < 256 && *ptr.add(len) != 0 {
-            len += 1;
+    let mut path_buf = [0u8; 256];
+    let mut len = 0;
+    while len < path_buf.len() {
+        match read_user::<u8>(path_ptr + len) {
+            Some(0) => break,
+            Some(byte) => path_buf[len] = byte,
+            None => return Err(Errno::EFAULT),
         }
-        core::slice::from_raw_parts(ptr, len)
-    };
+        len += 1;
+    }
+    let path_bytes = &path_buf[..len];
 
     let path = core::str::from_utf8(path_bytes).unwrap_or("");
-    serial_println!("[SYSCALL] sys_open: path={}", path);
+    serial_println!("[SYSCALL] sys_open: path={}, flags={:#x}", path, flags);
+
+    let fd_flags = if (flags & O_CLOEXEC) != 0 { FD_CLOEXEC } else { 0 };
+    let status_flags = (flags as u32) & (O_NONBLOCK | O_APPEND);
 
     // Check if opening /dev/ptmx
     if path == "/dev/ptmx" {
         // Allocate a new PTY pair
         match crate::dev::pty::allocate_pty() {
             Some(pty_num) => {
-                // Allocate a file descriptor
-                let mut fd_table = FD_TABLE.lock();
-                match fd_table.allocate(FdType::PtyMaster(pty_num)) {
-                    Some(fd) => {
+                // Allocate a handle; without one the PTY is deallocated
+                let handle = Handle::with_flags(Object::File(FdType::PtyMaster(pty_num)), fd_flags, status_flags);
+                match handle::install(handle) {
+                    Ok(fd) => {
                         serial_println!("[SYSCALL] sys_open: allocated PTY {} as FD {}", pty_num, fd);
-                        fd as isize
+                        Ok(fd)
                     }
-                    None => {
-                        // Failed to allocate FD, deallocate PTY
-                        crate::dev::pty::deallocate_pty(pty_num);
+                    Err(e) => {
                         serial_println!("[SYSCALL] sys_open: no FDs available");
-                        -1 // EMFILE - too many open files
+                        Err(e.into())
                     }
                 }
             }
             None => {
                 serial_println!("[SYSCALL] sys_open: failed to allocate PTY");
-                -1 // ENODEV - no PTY pairs available
+                Err(Errno::ENODEV)
             }
         }
     } else if path.starts_with("/dev/pts/") {
@@ -948,29 +1502,57 @@ fn sys_open(path_ptr: usize, _flags: usize) -> isize {
         if let Ok(pty_num) = num_str.parse::<u32>() {
             // Verify PTY exists
             if crate::dev::pty::get_pty_slave_number(pty_num).is_some() {
-                // Allocate a file descriptor
-                let mut fd_table = FD_TABLE.lock();
-                match fd_table.allocate(FdType::PtySlave(pty_num)) {
-                    Some(fd) => {
+                // Allocate a handle
+                let handle = Handle::with_flags(Object::File(FdType::PtySlave(pty_num)), fd_flags, status_flags);
+                match handle::install(handle) {
+                    Ok(fd) => {
                         serial_println!("[SYSCALL] sys_open: opened PTY slave {} as FD {}", pty_num, fd);
-                        fd as isize
+                        Ok(fd)
                     }
-                    None => {
+                    Err(e) => {
                         serial_println!("[SYSCALL] sys_open: no FDs available");
-                        -1 // EMFILE - too many open files
+                        Err(e.into())
                     }
                 }
             } else {
                 serial_println!("[SYSCALL] sys_open: PTY {} not allocated", pty_num);
-                -1 // ENOENT - PTY doesn't exist
+                Err(Errno::ENOENT)
             }
         } else {
             serial_println!("[SYSCALL] sys_open: invalid PTY number in path");
-            -1 // EINVAL
-        }
+            Err(Errno::EINVAL)
+        }
+    } else if path == "/dev/fb0" {
+        if !crate::dev::fb::open() {
+            return Err(Errno::ENODEV);
+        }
+        let handle = Handle::with_flags(Object::File(FdType::Framebuffer), fd_flags, status_flags);
+        Ok(handle::install(handle)?)
+    } else if let Some(number) = path.strip_prefix("/dev/input/event") {
+        let device = number.parse::<usize>().map_err(|_| Errno::ENOENT)?;
+        let client = crate::dev::input::open(device)?;
+        let handle = Handle::with_flags(Object::File(FdType::Input(client)), fd_flags, status_flags);
+        Ok(handle::install(handle)?)
+    } else if (flags & O_CREAT) == 0 && !path.starts_with("/dev/") {
+        // A regular file of the initrd or a /proc file, read-only
+        if flags & O_ACCMODE != O_RDONLY {
+            return Err(Errno::EROFS);
+        }
+        let file_id = crate::fs::file::open(path)?;
+        let handle = Handle::with_flags(Object::File(FdType::File(file_id)), fd_flags, status_flags);
+        Ok(handle::install(handle)?)
+    } else if (flags & O_CREAT) != 0 {
+        // Device nodes already exist; anything else would be a new file
+        let mode = crate::fs::creation_mode(mode as u32, current_umask());
+        serial_println!(
+            "[SYSCALL] sys_open: cannot create {} (mode {:o}): no writable filesystem",
+            path,
+            mode
+        );
+        Err(Errno::EROFS)
     } else {
         serial_println!("[SYSCALL] sys_open: unsupported path");
-        -1 // ENOENT - file not found
+        Err(Errno::ENOENT)
     }
 }
 
@@ -982,116 +1564,184 @@ fn sys_open(path_ptr: usize, _flags: usize) -> isize {
 /// * `len` - Maximum bytes to read
 ///
 /// # Returns
-/// Number of bytes read, or -1 on error
-fn sys_read(fd: usize, buf_ptr: usize, len: usize) -> isize {
+/// Number of bytes read, or an error
+fn sys_read(fd: usize, buf_ptr: usize, len: usize) -> SyscallResult {
     if len == 0 {
-        return 0;
+        return Ok(0);
     }
 
     // Validate buffer
     if !validate_user_buffer(buf_ptr, len) {
-        return -1;
+        return Err(Errno::EFAULT);
     }
 
-    // Look up file descriptor
-    let fd_table = FD_TABLE.lock();
-    let fd_entry = match fd_table.get(fd) {
-        Some(entry) => entry,
+    produce_output(buf_ptr, len, true, |buffer| read_fd(fd, buffer))
+}
+
+/// Read from a file descriptor into a kernel-side buffer
+///
+/// Reading a timer or event handle stores its count as a `u64` at the
+/// start of the buffer.
+fn read_fd(fd: usize, buffer: &mut [u8]) -> SyscallResult {
+    // Look up the handle
+    let handle = match lookup(fd) {
+        Some(handle) => handle,
         None => {
             serial_println!("[SYSCALL] sys_read: invalid FD {}", fd);
-            return -1; // EBADF
+            return Err(Errno::EBADF);
+        }
+    };
+    let nonblock = handle.status_flags & O_NONBLOCK != 0;
+    let fd_type = match handle.object {
+        Object::File(fd_type) => fd_type,
+        Object::Timer(_) | Object::Event(_) if buffer.len() < 8 => return Err(Errno::EINVAL),
+        Object::Timer(id) => return read_count(buffer, waitable::read_timer(id, nonblock)?),
+        Object::Event(id) => return read_count(buffer, waitable::read_event(id, nonblock)?),
+        _ => {
+            serial_println!("[SYSCALL] sys_read: handle {} is not readable", fd);
+            return Err(Errno::EBADF);
         }
     };
-    drop(fd_table);
-
-    // Convert pointer to mutable slice
-    let buffer = unsafe { core::slice::from_raw_parts_mut(buf_ptr as *mut u8, len) };
 
     // Handle based on FD type
-    match fd_entry.fd_type {
-        FdType::PtyMaster(pty_num) => {
-            // Read from PTY master (reads from slave output)
-            let bytes_read = crate::dev::pty::read_master(pty_num, buffer);
-            bytes_read as isize
-        }
+    match fd_type {
+        FdType::Console => {
+            // Wait for input, then read whatever the console has buffered
+            if !crate::console::input_ready()
+                && (nonblock || !crate::console::INPUT_WAIT.wait_until(crate::console::input_ready))
+            {
+                return Err(Errno::EAGAIN);
+            }
+            let mut count = 0;
+            while count < buffer.len() {
+                match crate::console::getc() {
+                    Some(byte) => {
+                        buffer[count] = byte;
+                        count += 1;
+                    }
+                    None => break,
+                }
+            }
+            Ok(count)
+        }
+        FdType::PtyMaster(pty_num) => {
+            // Read from PTY master (reads from slave output)
+            let bytes_read = crate::dev::pty::read_master(pty_num, buffer);
+            Ok(bytes_read)
+        }
         FdType::PtySlave(pty_num) => {
             // Read from PTY slave (reads from master output)
             let bytes_read = crate::dev::pty::read_slave(pty_num, buffer);
-            bytes_read as isize
+            Ok(bytes_read)
         }
-        FdType::PipeRead(pipe_id) => {
-            // Read from pipe
+        FdType::PipeRead(pipe_id) => loop {
+            // Read from pipe, blocking while it is empty and has writers
             let mut pipe_table = PIPE_TABLE.lock();
-            match pipe_table.get_mut(pipe_id) {
-                Some(pipe) => {
-                    // If pipe is empty and there are no writers, return EOF
-                    if pipe.is_empty() && pipe.writers == 0 {
-                        return 0; // EOF
-                    }
-                    let bytes_read = pipe.read(buffer);
-                    bytes_read as isize
-                }
+            let pipe = match pipe_table.get_mut(pipe_id) {
+                Some(pipe) => pipe,
                 None => {
                     serial_println!("[SYSCALL] sys_read: invalid pipe");
-                    -1 // EBADF
+                    return Err(Errno::EBADF);
                 }
+            };
+            if pipe.can_read() {
+                // Empty with no writers left: 0 (EOF)
+                let bytes_read = pipe.read(buffer);
+                drop(pipe_table);
+                PIPE_WAIT[pipe_id as usize].wake_all();
+                return Ok(bytes_read);
             }
-        }
+            drop(pipe_table);
+
+            if nonblock || !pipe_wait(pipe_id, Pipe::can_read) {
+                return Err(Errno::EAGAIN);
+            }
+        },
         FdType::PipeWrite(_) => {
             serial_println!("[SYSCALL] sys_read: cannot read from pipe write end");
-            -1 // EBADF
+            Err(Errno::EBADF)
+        }
+        FdType::File(file_id) => Ok(crate::fs::file::read(file_id, buffer)?),
+        FdType::Framebuffer => Err(Errno::EINVAL),
+        FdType::Input(client) => {
+            // Whole events only
+            if buffer.len() < crate::dev::input::EVENT_SIZE {
+                return Err(Errno::EINVAL);
+            }
+            let ready = || crate::dev::input::readable(client);
+            if !ready() && (nonblock || !crate::dev::input::WAIT.wait_until(ready)) {
+                return Err(Errno::EAGAIN);
+            }
+            Ok(crate::dev::input::read(client, buffer)?)
+        }
+        FdType::Socket(socket) => recv_socket(socket, buffer, nonblock).map(|(len, _)| len),
+    }
+}
+
+/// Receive from `socket` into `buffer`, waiting for data unless
+/// `nonblock`; returns the length (a datagram is truncated to `buffer`)
+/// and, for a datagram, its sender
+fn recv_socket(
+    socket: u32,
+    buffer: &mut [u8],
+    nonblock: bool,
+) -> Result<(usize, Option<crate::net::addr::SocketAddr>), Errno> {
+    use crate::net::socket;
+
+    loop {
+        if let Some(received) = socket::recv_from(socket, buffer)? {
+            return Ok(received);
+        }
+        let ready = || socket::readable(socket);
+        if nonblock || !socket::wait_queue(socket).wait_until(ready) {
+            return Err(Errno::EAGAIN);
+        }
+    }
+}
+
+/// Send `buffer` on connected `socket`, waiting for room unless `nonblock`
+/// until all of it is queued; returns how much was
+fn send_socket(socket: u32, buffer: &[u8], nonblock: bool) -> SyscallResult {
+    use crate::net::socket;
+
+    let mut sent = 0;
+    loop {
+        match socket::send(socket, &buffer[sent..]) {
+            Ok(n) => sent += n,
+            Err(e) => return if sent > 0 { Ok(sent) } else { Err(e.into()) },
+        }
+        if sent == buffer.len() {
+            return Ok(sent);
         }
-        FdType::Invalid => {
-            serial_println!("[SYSCALL] sys_read: invalid FD type");
-            -1 // EBADF
+        let ready = || socket::writable(socket);
+        if nonblock || !socket::wait_queue(socket).wait_until(ready) {
+            return if sent > 0 { Ok(sent) } else { Err(Errno::EAGAIN) };
         }
     }
 }
 
-/// sys_close handler - Close a file descriptor
+/// Store the count read from a timer or event at the start of `buffer`
+fn read_count(buffer: &mut [u8], count: u64) -> SyscallResult {
+    buffer[..8].copy_from_slice(&count.to_ne_bytes());
+    Ok(8)
+}
+
+/// sys_close handler - Close a handle of any kind
 ///
 /// # Arguments
-/// * `fd` - File descriptor to close
+/// * `fd` - Handle to close
 ///
 /// # Returns
-/// 0 on success, or -1 on error
-fn sys_close(fd: usize) -> isize {
-    let mut fd_table = FD_TABLE.lock();
-    
-    match fd_table.close(fd) {
-        Some(fd_entry) => {
+/// 0 on success, or an error
+fn sys_close(fd: usize) -> SyscallResult {
+    match handle::close(fd) {
+        Ok(_) => {
             serial_println!("[SYSCALL] sys_close: closed FD {}", fd);
-            
-            // Handle cleanup based on FD type
-            match fd_entry.fd_type {
-                FdType::PtyMaster(pty_num) => {
-                    // If this was a PTY master, deallocate the PTY pair
-                    // (In a full implementation, we'd track open counts)
-                    crate::dev::pty::deallocate_pty(pty_num);
-                }
-                FdType::PtySlave(_) => {
-                    // Slave close doesn't deallocate
-                }
-                FdType::PipeRead(pipe_id) => {
-                    // Close pipe read end
-                    let mut pipe_table = PIPE_TABLE.lock();
-                    pipe_table.close_reader(pipe_id);
-                }
-                FdType::PipeWrite(pipe_id) => {
-                    // Close pipe write end
-                    let mut pipe_table = PIPE_TABLE.lock();
-                    pipe_table.close_writer(pipe_id);
-                }
-                FdType::Invalid => {
-                    // Should never happen
-                }
-            }
-            
-            0
+            Ok(0)
         }
-        None => {
+        Err(e) => {
             serial_println!("[SYSCALL] sys_close: invalid FD {}", fd);
-            -1 // EBADF
+            Err(e.into())
         }
     }
 }
@@ -1114,18 +1764,16 @@ const TIOCSCTTY: usize = 0x540E;     // Make this TTY the controlling terminal
 /// * `arg` - Command-specific argument
 ///
 /// # Returns
-/// 0 on success, or -1 on error
-fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+/// 0 on success, or an error
+fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> SyscallResult {
     // Look up file descriptor
-    let fd_table = FD_TABLE.lock();
-    let fd_entry = match fd_table.get(fd) {
-        Some(entry) => entry,
+    let fd_type = match lookup_fd(fd) {
+        Some(fd_type) => fd_type,
         None => {
             serial_println!("[SYSCALL] sys_ioctl: invalid FD {}", fd);
-            return -1; // EBADF
+            return Err(Errno::EBADF);
         }
     };
-    drop(fd_table);
 
     serial_println!("[SYSCALL] sys_ioctl: FD={}, cmd={:#x}, arg={:#x}", fd, cmd, arg);
 
@@ -1133,154 +1781,163 @@ fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
     match cmd {
         TIOCGPTN => {
             // Get PTY number (only valid for PTY master)
-            match fd_entry.fd_type {
+            match fd_type {
                 FdType::PtyMaster(pty_num) => {
                     // Validate output pointer
                     if !validate_user_buffer(arg, core::mem::size_of::<u32>()) {
-                        return -1;
+                        return Err(Errno::EFAULT);
                     }
 
                     // Write PTY number to user buffer
-                    unsafe {
-                        *(arg as *mut u32) = pty_num;
+                    if !write_user(arg, pty_num) {
+                        return Err(Errno::EFAULT);
                     }
 
                     serial_println!("[SYSCALL] sys_ioctl: TIOCGPTN returned {}", pty_num);
-                    0
+                    Ok(0)
                 }
                 _ => {
                     serial_println!("[SYSCALL] sys_ioctl: TIOCGPTN on non-master FD");
-                    -1 // ENOTTY
+                    Err(Errno::ENOTTY)
                 }
             }
         }
         TCGETS => {
             // Get termios settings
-            let pty_num = match fd_entry.fd_type {
+            let pty_num = match fd_type {
                 FdType::PtyMaster(n) | FdType::PtySlave(n) => n,
                 _ => {
                     serial_println!("[SYSCALL] sys_ioctl: TCGETS on non-PTY FD");
-                    return -1; // ENOTTY
+                    return Err(Errno::ENOTTY);
                 }
             };
 
             // Validate output pointer
             if !validate_user_buffer(arg, core::mem::size_of::<crate::dev::pty::Termios>()) {
-                return -1;
+                return Err(Errno::EFAULT);
             }
 
             // Get termios from PTY
             match crate::dev::pty::get_termios(pty_num) {
                 Some(termios) => {
                     // Write termios to user buffer
-                    unsafe {
-                        *(arg as *mut crate::dev::pty::Termios) = termios;
+                    if !write_user(arg, termios) {
+                        return Err(Errno::EFAULT);
                     }
                     serial_println!("[SYSCALL] sys_ioctl: TCGETS for PTY {}", pty_num);
-                    0
+                    Ok(0)
                 }
                 None => {
                     serial_println!("[SYSCALL] sys_ioctl: TCGETS on invalid PTY");
-                    -1 // EBADF
+                    Err(Errno::EBADF)
                 }
             }
         }
         TCSETS => {
             // Set termios settings
-            let pty_num = match fd_entry.fd_type {
+            let pty_num = match fd_type {
                 FdType::PtyMaster(n) | FdType::PtySlave(n) => n,
                 _ => {
                     serial_println!("[SYSCALL] sys_ioctl: TCSETS on non-PTY FD");
-                    return -1; // ENOTTY
+                    return Err(Errno::ENOTTY);
                 }
             };
 
             // Validate input pointer
             if !validate_user_buffer(arg, core::mem::size_of::<crate::dev::pty::Termios>()) {
-                return -1;
+                return Err(Errno::EFAULT);
             }
 
             // Read termios from user buffer
-            let termios = unsafe { *(arg as *const crate::dev::pty::Termios) };
+            let termios = match read_user::<crate::dev::pty::Termios>(arg) {
+                Some(termios) => termios,
+                None => return Err(Errno::EFAULT),
+            };
 
             // Set termios in PTY
             if crate::dev::pty::set_termios(pty_num, termios) {
                 serial_println!("[SYSCALL] sys_ioctl: TCSETS for PTY {}", pty_num);
-                0
+                Ok(0)
             } else {
                 serial_println!("[SYSCALL] sys_ioctl: TCSETS on invalid PTY");
-                -1 // EBADF
+                Err(Errno::EBADF)
             }
         }
         TIOCGWINSZ => {
             // Get window size
-            let pty_num = match fd_entry.fd_type {
+            let pty_num = match fd_type {
                 FdType::PtyMaster(n) | FdType::PtySlave(n) => n,
                 _ => {
                     serial_println!("[SYSCALL] sys_ioctl: TIOCGWINSZ on non-PTY FD");
-                    return -1; // ENOTTY
+                    return Err(Errno::ENOTTY);
                 }
             };
 
             // Validate output pointer
             if !validate_user_buffer(arg, core::mem::size_of::<crate::dev::pty::Winsize>()) {
-                return -1;
+                return Err(Errno::EFAULT);
             }
 
             // Get winsize from PTY
             match crate::dev::pty::get_winsize(pty_num) {
                 Some(winsize) => {
                     // Write winsize to user buffer
-                    unsafe {
-                        *(arg as *mut crate::dev::pty::Winsize) = winsize;
+                    if !write_user(arg, winsize) {
+                        return Err(Errno::EFAULT);
                     }
                     serial_println!("[SYSCALL] sys_ioctl: TIOCGWINSZ for PTY {}: {}x{}", 
                                   pty_num, winsize.ws_row, winsize.ws_col);
-                    0
+                    Ok(0)
                 }
                 None => {
                     serial_println!("[SYSCALL] sys_ioctl: TIOCGWINSZ on invalid PTY");
-                    -1 // EBADF
+                    Err(Errno::EBADF)
                 }
             }
         }
         TIOCSWINSZ => {
             // Set window size
-            let pty_num = match fd_entry.fd_type {
+            let pty_num = match fd_type {
                 FdType::PtyMaster(n) | FdType::PtySlave(n) => n,
                 _ => {
                     serial_println!("[SYSCALL] sys_ioctl: TIOCSWINSZ on non-PTY FD");
-                    return -1; // ENOTTY
+                    return Err(Errno::ENOTTY);
                 }
             };
 
             // Validate input pointer
             if !validate_user_buffer(arg, core::mem::size_of::<crate::dev::pty::Winsize>()) {
-                return -1;
+                return Err(Errno::EFAULT);
             }
 
             // Read winsize from user buffer
-            let winsize = unsafe { *(arg as *const crate::dev::pty::Winsize) };
+            let winsize = match read_user::<crate::dev::pty::Winsize>(arg) {
+                Some(winsize) => winsize,
+                None => return Err(Errno::EFAULT),
+            };
 
             // Set winsize in PTY
             if crate::dev::pty::set_winsize(pty_num, winsize) {
                 serial_println!("[SYSCALL] sys_ioctl: TIOCSWINSZ for PTY {}: {}x{}", 
                               pty_num, winsize.ws_row, winsize.ws_col);
-                0
+                Ok(0)
             } else {
                 serial_println!("[SYSCALL] sys_ioctl: TIOCSWINSZ on invalid PTY");
-                -1 // EBADF
+                Err(Errno::EBADF)
             }
         }
         TIOCSPGRP => {
             // Set foreground process group (alias for tcsetpgrp)
             // Validate input pointer
             if !validate_user_buffer(arg, core::mem::size_of::<usize>()) {
-                return -1;
+                return Err(Errno::EFAULT);
             }
 
             // Read PGID from user buffer
-            let pgid = unsafe { *(arg as *const usize) };
+            let pgid = match read_user::<usize>(arg) {
+                Some(pgid) => pgid,
+                None => return Err(Errno::EFAULT),
+            };
 
             // Call tcsetpgrp implementation
             sys_tcsetpgrp(fd, pgid)
@@ -1289,20 +1946,16 @@ fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
             // Get foreground process group (alias for tcgetpgrp)
             // Validate output pointer
             if !validate_user_buffer(arg, core::mem::size_of::<usize>()) {
-                return -1;
+                return Err(Errno::EFAULT);
             }
 
             // Call tcgetpgrp implementation
-            let result = sys_tcgetpgrp(fd);
-            if result >= 0 {
-                // Write PGID to user buffer
-                unsafe {
-                    *(arg as *mut usize) = result as usize;
-                }
-                0
-            } else {
-                result
+            let pgid = sys_tcgetpgrp(fd)?;
+            // Write PGID to user buffer
+            if !write_user(arg, pgid) {
+                return Err(Errno::EFAULT);
             }
+            Ok(0)
         }
         TIOCSCTTY => {
             // Make this TTY the controlling terminal
@@ -1313,7 +1966,7 @@ fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
                 Some((id, _)) => id,
                 None => {
                     serial_println!("[SYSCALL] sys_ioctl: TIOCSCTTY: no current task");
-                    return -1;
+                    return Err(Errno::ESRCH);
                 }
             };
 
@@ -1321,28 +1974,28 @@ fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
                 Some(t) => t,
                 None => {
                     serial_println!("[SYSCALL] sys_ioctl: TIOCSCTTY: task not found");
-                    return -1;
+                    return Err(Errno::ESRCH);
                 }
             };
 
             // Check if caller is a session leader
             if task.sid != task.pid {
                 serial_println!("[SYSCALL] sys_ioctl: TIOCSCTTY: not a session leader");
-                return -1; // EPERM
+                return Err(Errno::EPERM);
             }
 
             // Check if already has a controlling terminal
             if task.tty.is_some() {
                 serial_println!("[SYSCALL] sys_ioctl: TIOCSCTTY: already has controlling terminal");
-                return -1; // EPERM
+                return Err(Errno::EPERM);
             }
 
             // Get PTY number from FD
-            let pty_num = match fd_entry.fd_type {
+            let pty_num = match fd_type {
                 FdType::PtyMaster(n) | FdType::PtySlave(n) => n,
                 _ => {
                     serial_println!("[SYSCALL] sys_ioctl: TIOCSCTTY: FD is not a TTY");
-                    return -1; // ENOTTY
+                    return Err(Errno::ENOTTY);
                 }
             };
 
@@ -1360,15 +2013,48 @@ fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
                     "[SYSCALL] sys_ioctl: TIOCSCTTY: set PTY {} as controlling terminal for session {}",
                     pty_num, sid
                 );
-                0
+                Ok(0)
             } else {
                 serial_println!("[SYSCALL] sys_ioctl: TIOCSCTTY: failed to set session in PTY");
-                -1
+                Err(Errno::EBADF)
+            }
+        }
+        crate::dev::fb::FBIOGET_MODE => {
+            if !matches!(fd_type, FdType::Framebuffer) {
+                return Err(Errno::ENOTTY);
+            }
+            let mode = crate::dev::fb::mode().ok_or(Errno::ENODEV)?;
+            if !validate_user_buffer(arg, core::mem::size_of::<crate::dev::fb::FbMode>()) || !write_user(arg, mode) {
+                return Err(Errno::EFAULT);
+            }
+            Ok(0)
+        }
+        crate::dev::input::EVIOCGRAB => {
+            let FdType::Input(client) = fd_type else {
+                return Err(Errno::ENOTTY);
+            };
+            crate::dev::input::grab(client, arg != 0)?;
+            Ok(0)
+        }
+        _ if crate::dev::input::name_request(cmd).is_some() => {
+            let FdType::Input(client) = fd_type else {
+                return Err(Errno::ENOTTY);
+            };
+            let len = crate::dev::input::name_request(cmd).unwrap_or(0);
+            let name = crate::dev::input::name(client).ok_or(Errno::EBADF)?;
+            // NUL-terminated, cut to fit
+            let copied = name.len().min(len.saturating_sub(1));
+            if len == 0 || !validate_user_buffer(arg, copied + 1) {
+                return Err(Errno::EFAULT);
+            }
+            if copy_to_user(arg, &name.as_bytes()[..copied]).is_err() || !write_user(arg + copied, 0u8) {
+                return Err(Errno::EFAULT);
             }
+            Ok(copied + 1)
         }
         _ => {
             serial_println!("[SYSCALL] sys_ioctl: unsupported command {:#x}", cmd);
-            -1 // EINVAL
+            Err(Errno::EINVAL)
         }
     }
 }
@@ -1381,20 +2067,20 @@ fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
 /// * `oldact_ptr` - Pointer to store old sigaction (or 0 to ignore)
 ///
 /// # Returns
-/// 0 on success, or -1 on error
-fn sys_sigaction(signal: usize, act_ptr: usize, oldact_ptr: usize) -> isize {
+/// 0 on success, or an error
+fn sys_sigaction(signal: usize, act_ptr: usize, oldact_ptr: usize) -> SyscallResult {
     use crate::signal::{SigAction, signals, is_catchable};
 
     // Validate signal number
     if signal == 0 || signal >= signals::MAX_SIGNAL as usize {
         serial_println!("[SYSCALL] sys_sigaction: invalid signal {}", signal);
-        return -1; // EINVAL
+        return Err(Errno::EINVAL);
     }
 
     // SIGKILL and SIGSTOP cannot be caught or ignored
     if !is_catchable(signal as u32) {
         serial_println!("[SYSCALL] sys_sigaction: cannot catch signal {}", signal);
-        return -1; // EINVAL
+        return Err(Errno::EINVAL);
     }
 
     // Get current task
@@ -1402,7 +2088,7 @@ fn sys_sigaction(signal: usize, act_ptr: usize, oldact_ptr: usize) -> isize {
         Some((id, _)) => id,
         None => {
             serial_println!("[SYSCALL] sys_sigaction: no current task");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -1410,7 +2096,7 @@ fn sys_sigaction(signal: usize, act_ptr: usize, oldact_ptr: usize) -> isize {
         Some(t) => t,
         None => {
             serial_println!("[SYSCALL] sys_sigaction: task not found");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -1418,12 +2104,12 @@ fn sys_sigaction(signal: usize, act_ptr: usize, oldact_ptr: usize) -> isize {
     if oldact_ptr != 0 {
         if !validate_user_buffer(oldact_ptr, core::mem::size_of::<SigAction>()) {
             serial_println!("[SYSCALL] sys_sigaction: invalid oldact pointer");
-            return -1;
+            return Err(Errno::EFAULT);
         }
 
         let old_action = task.signal_handlers[signal];
-        unsafe {
-            *(oldact_ptr as *mut SigAction) = old_action;
+        if !write_user(oldact_ptr, old_action) {
+            return Err(Errno::EFAULT);
         }
     }
 
@@ -1431,16 +2117,19 @@ fn sys_sigaction(signal: usize, act_ptr: usize, oldact_ptr: usize) -> isize {
     if act_ptr != 0 {
         if !validate_user_buffer(act_ptr, core::mem::size_of::<SigAction>()) {
             serial_println!("[SYSCALL] sys_sigaction: invalid act pointer");
-            return -1;
+            return Err(Errno::EFAULT);
         }
 
-        let new_action = unsafe { *(act_ptr as *const SigAction) };
+        let new_action = match read_user::<SigAction>(act_ptr) {
+            Some(action) => action,
+            None => return Err(Errno::EFAULT),
+        };
 
         // Validate handler address if it's a custom handler
         if let crate::signal::SigHandler::Custom(handler_addr) = new_action.handler {
             if handler_addr >= USER_LIMIT {
                 serial_println!("[SYSCALL] sys_sigaction: handler address not in user space");
-                return -1; // EFAULT
+                return Err(Errno::EFAULT);
             }
         }
 
@@ -1448,7 +2137,7 @@ fn sys_sigaction(signal: usize, act_ptr: usize, oldact_ptr: usize) -> isize {
         serial_println!("[SYSCALL] sys_sigaction: set handler for signal {}", signal);
     }
 
-    0
+    Ok(0)
 }
 
 /// sys_kill handler - Send a signal to a process
@@ -1458,27 +2147,27 @@ fn sys_sigaction(signal: usize, act_ptr: usize, oldact_ptr: usize) -> isize {
 /// * `signal` - Signal number to send
 ///
 /// # Returns
-/// 0 on success, or -1 on error
+/// 0 on success, or an error
 ///
 /// # Special PID values
 /// * pid > 0: Send to specific process
 /// * pid == 0: Send to all processes in current process group
 /// * pid == -1: Send to all processes (except init)
 /// * pid < -1: Send to all processes in process group |pid|
-fn sys_kill(pid: usize, signal: usize) -> isize {
+fn sys_kill(pid: usize, signal: usize) -> SyscallResult {
     use crate::signal::{signals, send_signal};
 
     // Validate signal number
     if signal >= signals::MAX_SIGNAL as usize {
         serial_println!("[SYSCALL] sys_kill: invalid signal {}", signal);
-        return -1; // EINVAL
+        return Err(Errno::EINVAL);
     }
 
     // Signal 0 is used to check if process exists (no signal sent)
     if signal == 0 {
         // TODO: Check if process exists
         serial_println!("[SYSCALL] sys_kill: signal 0 (existence check) not implemented");
-        return 0;
+        return Ok(0);
     }
 
     // Get current task for permission checks
@@ -1486,7 +2175,7 @@ fn sys_kill(pid: usize, signal: usize) -> isize {
         Some((id, _)) => id,
         None => {
             serial_println!("[SYSCALL] sys_kill: no current task");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -1495,7 +2184,7 @@ fn sys_kill(pid: usize, signal: usize) -> isize {
         // Prevent sending SIGKILL/SIGSTOP to PID 1 (init)
         if pid == 1 && (signal == signals::SIGKILL as usize || signal == signals::SIGSTOP as usize) {
             serial_println!("[SYSCALL] sys_kill: cannot send SIGKILL/SIGSTOP to init");
-            return -1; // EPERM
+            return Err(Errno::EPERM);
         }
 
         // Get target task
@@ -1503,7 +2192,7 @@ fn sys_kill(pid: usize, signal: usize) -> isize {
             Some(t) => t,
             None => {
                 serial_println!("[SYSCALL] sys_kill: target process {} not found", pid);
-                return -1; // ESRCH - no such process
+                return Err(Errno::ESRCH);
             }
         };
 
@@ -1514,17 +2203,17 @@ fn sys_kill(pid: usize, signal: usize) -> isize {
         match send_signal(target, signal as u32) {
             Ok(()) => {
                 serial_println!("[SYSCALL] sys_kill: sent signal {} to process {}", signal, pid);
-                0
+                Ok(0)
             }
             Err(()) => {
                 serial_println!("[SYSCALL] sys_kill: failed to send signal");
-                -1
+                Err(Errno::EINVAL)
             }
         }
     } else {
         // TODO: Implement special PID values (0, -1, < -1)
         serial_println!("[SYSCALL] sys_kill: special PID values not implemented");
-        -1 // EINVAL
+        Err(Errno::EINVAL)
     }
 }
 
@@ -1535,13 +2224,13 @@ fn sys_kill(pid: usize, signal: usize) -> isize {
 /// * `pgid` - New process group ID (0 = use pid)
 ///
 /// # Returns
-/// 0 on success, or -1 on error
+/// 0 on success, or an error
 ///
 /// # Validation
 /// - Can only set pgid for self or children
 /// - Must be in same session
 /// - Cannot move process to different session
-fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+fn sys_setpgid(pid: usize, pgid: usize) -> SyscallResult {
     use crate::sched::process_group::{Pid, Pgid};
 
     // Get current task
@@ -1549,7 +2238,7 @@ fn sys_setpgid(pid: usize, pgid: usize) -> isize {
         Some((id, _)) => id,
         None => {
             serial_println!("[SYSCALL] sys_setpgid: no current task");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -1564,7 +2253,7 @@ fn sys_setpgid(pid: usize, pgid: usize) -> isize {
         Some(t) => t,
         None => {
             serial_println!("[SYSCALL] sys_setpgid: current task not found");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -1575,20 +2264,20 @@ fn sys_setpgid(pid: usize, pgid: usize) -> isize {
         Some(t) => t,
         None => {
             serial_println!("[SYSCALL] sys_setpgid: target process {} not found", target_pid);
-            return -1; // ESRCH - no such process
+            return Err(Errno::ESRCH);
         }
     };
 
     // Validation: can only set pgid for self or children
     if target_pid != current_id && target_task.ppid != current_id {
         serial_println!("[SYSCALL] sys_setpgid: not self or child");
-        return -1; // EPERM
+        return Err(Errno::EPERM);
     }
 
     // Validation: must be in same session
     if target_task.sid != current_sid {
         serial_println!("[SYSCALL] sys_setpgid: not in same session");
-        return -1; // EPERM
+        return Err(Errno::EPERM);
     }
 
     // Set the process group
@@ -1600,20 +2289,20 @@ fn sys_setpgid(pid: usize, pgid: usize) -> isize {
         target_pid, old_pgid, target_pgid
     );
 
-    0
+    Ok(0)
 }
 
 /// sys_getpgrp handler - Get current process group ID
 ///
 /// # Returns
 /// Process group ID of current process
-fn sys_getpgrp() -> isize {
+fn sys_getpgrp() -> SyscallResult {
     // Get current task
     let current_id = match crate::sched::get_current_task_info() {
         Some((id, _)) => id,
         None => {
             serial_println!("[SYSCALL] sys_getpgrp: no current task");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -1621,32 +2310,32 @@ fn sys_getpgrp() -> isize {
         Some(t) => t,
         None => {
             serial_println!("[SYSCALL] sys_getpgrp: task not found");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
     let pgid = task.pgid;
     serial_println!("[SYSCALL] sys_getpgrp: returning PGID {}", pgid);
-    pgid as isize
+    Ok(pgid)
 }
 
 /// sys_setsid handler - Create a new session
 ///
 /// # Returns
-/// New session ID on success, or -1 on error
+/// New session ID on success, or an error
 ///
 /// # Behavior
 /// - Creates new session with sid = pid
 /// - Creates new process group with pgid = pid
 /// - Detaches from controlling terminal
 /// - Fails if caller is already a process group leader
-fn sys_setsid() -> isize {
+fn sys_setsid() -> SyscallResult {
     // Get current task
     let current_id = match crate::sched::get_current_task_info() {
         Some((id, _)) => id,
         None => {
             serial_println!("[SYSCALL] sys_setsid: no current task");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -1654,14 +2343,14 @@ fn sys_setsid() -> isize {
         Some(t) => t,
         None => {
             serial_println!("[SYSCALL] sys_setsid: task not found");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
     // Cannot create session if already a process group leader
     if task.pgid == task.pid {
         serial_println!("[SYSCALL] sys_setsid: already a process group leader");
-        return -1; // EPERM
+        return Err(Errno::EPERM);
     }
 
     // Create new session
@@ -1677,7 +2366,7 @@ fn sys_setsid() -> isize {
         new_sid, current_id
     );
 
-    new_sid as isize
+    Ok(new_sid)
 }
 
 /// sys_getsid handler - Get session ID of a process
@@ -1686,8 +2375,8 @@ fn sys_setsid() -> isize {
 /// * `pid` - Process ID to query (0 = current process)
 ///
 /// # Returns
-/// Session ID on success, or -1 on error
-fn sys_getsid(pid: usize) -> isize {
+/// Session ID on success, or an error
+fn sys_getsid(pid: usize) -> SyscallResult {
     use crate::sched::process_group::Pid;
 
     // Get current task
@@ -1695,7 +2384,7 @@ fn sys_getsid(pid: usize) -> isize {
         Some((id, _)) => id,
         None => {
             serial_println!("[SYSCALL] sys_getsid: no current task");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -1707,13 +2396,13 @@ fn sys_getsid(pid: usize) -> isize {
         Some(t) => t,
         None => {
             serial_println!("[SYSCALL] sys_getsid: process {} not found", target_pid);
-            return -1; // ESRCH - no such process
+            return Err(Errno::ESRCH);
         }
     };
 
     let sid = task.sid;
     serial_println!("[SYSCALL] sys_getsid: PID {} has SID {}", target_pid, sid);
-    sid as isize
+    Ok(sid)
 }
 
 /// sys_tcsetpgrp handler - Set foreground process group of terminal
@@ -1723,8 +2412,8 @@ fn sys_getsid(pid: usize) -> isize {
 /// * `pgid` - Process group ID to set as foreground
 ///
 /// # Returns
-/// 0 on success, or -1 on error
-fn sys_tcsetpgrp(fd: usize, pgid: usize) -> isize {
+/// 0 on success, or an error
+fn sys_tcsetpgrp(fd: usize, pgid: usize) -> SyscallResult {
     use crate::sched::process_group::Pgid;
 
     // Get current task
@@ -1732,7 +2421,7 @@ fn sys_tcsetpgrp(fd: usize, pgid: usize) -> isize {
         Some((id, _)) => id,
         None => {
             serial_println!("[SYSCALL] sys_tcsetpgrp: no current task");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
@@ -1740,29 +2429,27 @@ fn sys_tcsetpgrp(fd: usize, pgid: usize) -> isize {
         Some(t) => t,
         None => {
             serial_println!("[SYSCALL] sys_tcsetpgrp: current task not found");
-            return -1;
+            return Err(Errno::ESRCH);
         }
     };
 
     let current_sid = current_task.sid;
 
     // Look up file descriptor
-    let fd_table = FD_TABLE.lock();
-    let fd_entry = match fd_table.get(fd) {
-        Some(entry) => entry,
+    let fd_type = match lookup_fd(fd) {
+        Some(fd_type) => fd_type,
         None => {
             serial_println!("[SYSCALL] sys_tcsetpgrp: invalid FD {}", fd);
-            return -1; // EBADF
+            return Err(Errno::EBADF);
         }
     };
-    drop(fd_table);
 
     // Get PTY number from FD
-    let pty_num = match fd_entry.fd_type {
+    let pty_num = match fd_type {
         FdType::PtyMaster(n) | FdType::PtySlave(n) => n,
         _ => {
             serial_println!("[SYSCALL] sys_tcsetpgrp: FD is not a TTY");
-            return -1; // ENOTTY
+            return Err(Errno::ENOTTY);
         }
     };
 
@@ -1776,10 +2463,10 @@ fn sys_tcsetpgrp(fd: usize, pgid: usize) -> isize {
             "[SYSCALL] sys_tcsetpgrp: set foreground PGID to {} for PTY {}",
             pgid, pty_num
         );
-        0
+        Ok(0)
     } else {
         serial_println!("[SYSCALL] sys_tcsetpgrp: failed to set foreground PGID");
-        -1
+        Err(Errno::EBADF)
     }
 }
 
@@ -1789,25 +2476,23 @@ fn sys_tcsetpgrp(fd: usize, pgid: usize) -> isize {
 /// * `fd` - File descriptor of terminal
 ///
 /// # Returns
-/// Foreground process group ID on success, or -1 on error
-fn sys_tcgetpgrp(fd: usize) -> isize {
+/// Foreground process group ID on success, or an error
+fn sys_tcgetpgrp(fd: usize) -> SyscallResult {
     // Look up file descriptor
-    let fd_table = FD_TABLE.lock();
-    let fd_entry = match fd_table.get(fd) {
-        Some(entry) => entry,
+    let fd_type = match lookup_fd(fd) {
+        Some(fd_type) => fd_type,
         None => {
             serial_println!("[SYSCALL] sys_tcgetpgrp: invalid FD {}", fd);
-            return -1; // EBADF
+            return Err(Errno::EBADF);
         }
     };
-    drop(fd_table);
 
     // Get PTY number from FD
-    let pty_num = match fd_entry.fd_type {
+    let pty_num = match fd_type {
         FdType::PtyMaster(n) | FdType::PtySlave(n) => n,
         _ => {
             serial_println!("[SYSCALL] sys_tcgetpgrp: FD is not a TTY");
-            return -1; // ENOTTY
+            return Err(Errno::ENOTTY);
         }
     };
 
@@ -1818,11 +2503,11 @@ fn sys_tcgetpgrp(fd: usize) -> isize {
                 "[SYSCALL] sys_tcgetpgrp: foreground PGID is {} for PTY {}",
                 pgid, pty_num
             );
-            pgid as isize
+            Ok(pgid)
         }
         None => {
             serial_println!("[SYSCALL] sys_tcgetpgrp: no foreground PGID set");
-            -1 // No foreground process group
+            Err(Errno::ENOTTY) // No foreground process group
         }
     }
 }
@@ -1841,16 +2526,17 @@ const F_SETFL: usize = 4;  // Set file status flags
 /// * `arg` - Command-specific argument
 ///
 /// # Returns
-/// Command-specific return value, or -1 on error
-fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+/// Command-specific return value, or an error
+fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> SyscallResult {
     serial_println!("[SYSCALL] sys_fcntl: FD={}, cmd={}, arg={}", fd, cmd, arg);
 
-    let mut fd_table = FD_TABLE.lock();
-    let fd_entry = match fd_table.get_mut(fd) {
+    let table = handle::current().ok_or(Errno::EBADF)?;
+    let mut table = table.lock();
+    let fd_entry = match table.get_mut(fd) {
         Some(entry) => entry,
         None => {
             serial_println!("[SYSCALL] sys_fcntl: invalid FD {}", fd);
-            return -1; // EBADF
+            return Err(Errno::EBADF);
         }
     };
 
@@ -1859,54 +2545,58 @@ fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
             // Get file descriptor flags
             let flags = fd_entry.fd_flags;
             serial_println!("[SYSCALL] sys_fcntl: F_GETFD returned {:#x}", flags);
-            flags as isize
+            Ok(flags as usize)
         }
         F_SETFD => {
             // Set file descriptor flags (only FD_CLOEXEC is valid)
             let flags = arg as u32 & FD_CLOEXEC;
             fd_entry.fd_flags = flags;
             serial_println!("[SYSCALL] sys_fcntl: F_SETFD set flags to {:#x}", flags);
-            0
+            Ok(0)
         }
         F_GETFL => {
             // Get file status flags
             let flags = fd_entry.status_flags;
             serial_println!("[SYSCALL] sys_fcntl: F_GETFL returned {:#x}", flags);
-            flags as isize
+            Ok(flags as usize)
         }
         F_SETFL => {
             // Set file status flags (only O_NONBLOCK and O_APPEND can be changed)
             let flags = arg as u32 & (O_NONBLOCK | O_APPEND);
             fd_entry.status_flags = (fd_entry.status_flags & !(O_NONBLOCK | O_APPEND)) | flags;
             serial_println!("[SYSCALL] sys_fcntl: F_SETFL set flags to {:#x}", fd_entry.status_flags);
-            0
+            Ok(0)
         }
         _ => {
             serial_println!("[SYSCALL] sys_fcntl: unsupported command {}", cmd);
-            -1 // EINVAL
+            Err(Errno::EINVAL)
         }
     }
 }
 
 /// sys_pipe2 handler - Create a pipe with flags
 ///
+/// `SYS_PIPE` is this with no flags. Reads from the read end block while
+/// the pipe is empty and return 0 once every write end is closed; writes
+/// block while it is full and fail once every read end is closed.
+///
 /// # Arguments
 /// * `pipefd_ptr` - Pointer to array of 2 integers for read/write FDs
 /// * `flags` - Pipe flags (O_CLOEXEC, O_NONBLOCK)
 ///
 /// # Returns
-/// 0 on success, or -1 on error
-fn sys_pipe2(pipefd_ptr: usize, flags: usize) -> isize {
+/// 0 on success, or an error
+fn sys_pipe2(pipefd_ptr: usize, flags: usize) -> SyscallResult {
     serial_println!("[SYSCALL] sys_pipe2: pipefd_ptr={:#x}, flags={:#x}", pipefd_ptr, flags);
 
     // Validate pointer
     if !validate_user_buffer(pipefd_ptr, core::mem::size_of::<[i32; 2]>()) {
         serial_println!("[SYSCALL] sys_pipe2: invalid pipefd pointer");
-        return -1;
+        return Err(Errno::EFAULT);
     }
 
     // Parse flags
-    let fd_flags = if (flags & 0x80000) != 0 { FD_CLOEXEC } else { 0 }; // O_CLOEXEC = 0x80000
+    let fd_flags = if (flags & O_CLOEXEC) != 0 { FD_CLOEXEC } else { 0 };
     let status_flags = (flags as u32) & O_NONBLOCK;
 
     // Allocate a pipe
@@ -1915,52 +2605,57 @@ fn sys_pipe2(pipefd_ptr: usize, flags: usize) -> isize {
         Some(id) => id,
         None => {
             serial_println!("[SYSCALL] sys_pipe2: no pipes available");
-            return -1; // EMFILE - too many open files
+            return Err(Errno::EMFILE);
         }
     };
     drop(pipe_table);
 
-    // Allocate file descriptors
-    let mut fd_table = FD_TABLE.lock();
-    
-    // Allocate read end
-    let read_fd = match fd_table.allocate_with_flags(FdType::PipeRead(pipe_id), fd_flags, status_flags) {
-        Some(fd) => fd,
-        None => {
-            // Failed to allocate read FD, deallocate pipe
-            let mut pipe_table = PIPE_TABLE.lock();
-            pipe_table.close_reader(pipe_id);
-            pipe_table.close_writer(pipe_id);
+    // Allocate handles; each takes over one reference to its end
+    let read_end = Handle::with_flags(Object::File(FdType::PipeRead(pipe_id)), fd_flags, status_flags);
+    let read_fd = match handle::install(read_end) {
+        Ok(fd) => fd,
+        Err(e) => {
+            // The read end is gone with the handle; close the write end too
+            close_pipe_end(pipe_id, false);
             serial_println!("[SYSCALL] sys_pipe2: no FDs available for read end");
-            return -1; // EMFILE
+            return Err(e.into());
         }
     };
 
-    // Allocate write end
-    let write_fd = match fd_table.allocate_with_flags(FdType::PipeWrite(pipe_id), fd_flags, status_flags) {
-        Some(fd) => fd,
-        None => {
+    let write_end = Handle::with_flags(Object::File(FdType::PipeWrite(pipe_id)), fd_flags, status_flags);
+    let write_fd = match handle::install(write_end) {
+        Ok(fd) => fd,
+        Err(e) => {
             // Failed to allocate write FD, clean up
-            fd_table.close(read_fd);
-            let mut pipe_table = PIPE_TABLE.lock();
-            pipe_table.close_reader(pipe_id);
-            pipe_table.close_writer(pipe_id);
+            let _ = handle::close(read_fd);
             serial_println!("[SYSCALL] sys_pipe2: no FDs available for write end");
-            return -1; // EMFILE
+            return Err(e.into());
         }
     };
 
-    drop(fd_table);
-
     // Write FDs to user buffer
-    unsafe {
-        let pipefd = pipefd_ptr as *mut i32;
-        *pipefd.offset(0) = read_fd as i32;
-        *pipefd.offset(1) = write_fd as i32;
+    if !write_user(pipefd_ptr, [read_fd as i32, write_fd as i32]) {
+        return Err(Errno::EFAULT);
     }
 
     serial_println!("[SYSCALL] sys_pipe2: created pipe {} with FDs [{}, {}]", pipe_id, read_fd, write_fd);
-    0
+    Ok(0)
+}
+
+/// sys_dup handler - Duplicate a handle to the lowest free number
+///
+/// Works for handles of any kind; the copy has the same rights.
+///
+/// # Arguments
+/// * `oldfd` - Source handle
+///
+/// # Returns
+/// New handle on success, or an error
+fn sys_dup(oldfd: usize) -> SyscallResult {
+    let new_handle = copy_handle(oldfd)?;
+    let newfd = handle::install(new_handle)?;
+    serial_println!("[SYSCALL] sys_dup: duplicated FD {} to FD {}", oldfd, newfd);
+    Ok(newfd)
 }
 
 /// sys_dup2 handler - Duplicate file descriptor to specific FD number
@@ -1970,68 +2665,1361 @@ fn sys_pipe2(pipefd_ptr: usize, flags: usize) -> isize {
 /// * `newfd` - Target file descriptor number
 ///
 /// # Returns
-/// New file descriptor on success, or -1 on error
-fn sys_dup2(oldfd: usize, newfd: usize) -> isize {
+/// New file descriptor on success, or an error
+fn sys_dup2(oldfd: usize, newfd: usize) -> SyscallResult {
     serial_println!("[SYSCALL] sys_dup2: oldfd={}, newfd={}", oldfd, newfd);
 
     // Validate FD numbers
-    if oldfd >= MAX_FDS || newfd >= MAX_FDS {
+    if oldfd >= handle::MAX_HANDLES || newfd >= handle::MAX_HANDLES {
         serial_println!("[SYSCALL] sys_dup2: FD out of range");
-        return -1; // EBADF
+        return Err(Errno::EBADF);
     }
 
     // If oldfd == newfd, just validate oldfd and return it
     if oldfd == newfd {
-        let fd_table = FD_TABLE.lock();
-        if fd_table.get(oldfd).is_some() {
+        if lookup(oldfd).is_some() {
             serial_println!("[SYSCALL] sys_dup2: oldfd == newfd, returning {}", newfd);
-            return newfd as isize;
+            return Ok(newfd);
         } else {
             serial_println!("[SYSCALL] sys_dup2: oldfd {} is invalid", oldfd);
-            return -1; // EBADF
+            return Err(Errno::EBADF);
         }
     }
 
-    // Get old FD entry
-    let mut fd_table = FD_TABLE.lock();
-    let old_entry = match fd_table.get(oldfd) {
-        Some(entry) => entry,
-        None => {
+    // Copy the handle (a default stdio FD can be duplicated too), then
+    // close newfd if it's open and install the copy there
+    let new_handle = match copy_handle(oldfd) {
+        Ok(handle) => handle,
+        Err(e) => {
             serial_println!("[SYSCALL] sys_dup2: oldfd {} is invalid", oldfd);
-            return -1; // EBADF
+            return Err(e);
         }
     };
+    handle::install_at(newfd, new_handle)?;
+    serial_println!("[SYSCALL] sys_dup2: duplicated FD {} to FD {}", oldfd, newfd);
+    Ok(newfd)
+}
+
+/// getrandom flag: do not block waiting for entropy
+const GRND_NONBLOCK: usize = 0x1;
+/// getrandom flag: draw from the "blocking" pool
+const GRND_RANDOM: usize = 0x2;
+
+/// sys_getrandom handler - Fill a buffer with random bytes
+///
+/// Bytes come from the kernel CSPRNG, which is seeded before user space
+/// starts, so the call never blocks and both flags are accepted but have no
+/// effect. Like Linux, requests of up to 256 bytes are always filled
+/// completely; larger requests may return fewer bytes.
+///
+/// # Arguments
+/// * `buf_ptr` - Pointer to user buffer
+/// * `len` - Number of bytes requested
+/// * `flags` - GRND_NONBLOCK and/or GRND_RANDOM
+///
+/// # Returns
+/// Number of bytes written, or an error
+fn sys_getrandom(buf_ptr: usize, len: usize, flags: usize) -> SyscallResult {
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM) != 0 {
+        serial_println!("[SYSCALL] sys_getrandom: invalid flags {:#x}", flags);
+        return Err(Errno::EINVAL);
+    }
+
+    if len == 0 {
+        return Ok(0);
+    }
 
-    // Copy the FD entry (but clear FD_CLOEXEC flag as per POSIX)
-    let new_entry = FileDescriptor {
-        fd_type: old_entry.fd_type,
-        fd_flags: 0, // FD_CLOEXEC is not inherited by dup2
-        status_flags: old_entry.status_flags,
+    if !validate_user_buffer(buf_ptr, len) {
+        return Err(Errno::EFAULT);
+    }
+
+    produce_output(buf_ptr, len, true, |buffer| {
+        crate::rand::rand_bytes(buffer);
+        Ok(buffer.len())
+    })
+}
+
+/// File mode creation mask of the calling task
+///
+/// Kernel context (no current task) uses the default mask.
+/// Task making the syscall
+fn current_task() -> Option<&'static mut crate::sched::task::Task> {
+    crate::sched::get_current_task_info().and_then(|(id, _)| crate::sched::get_task_mut(id))
+}
+
+fn current_umask() -> u32 {
+    crate::sched::get_current_task_info()
+        .and_then(|(id, _)| crate::sched::get_task_by_id(id))
+        .map(|task| task.umask)
+        .unwrap_or(crate::fs::DEFAULT_UMASK)
+}
+
+/// sys_umask handler - Set the file mode creation mask
+///
+/// # Arguments
+/// * `mask` - New mask; only the permission bits (0o777) are kept
+///
+/// # Returns
+/// The previous mask (this call cannot fail)
+fn sys_umask(mask: usize) -> SyscallResult {
+    let new_mask = mask as u32 & 0o777;
+
+    let task = match crate::sched::get_current_task_info()
+        .and_then(|(id, _)| crate::sched::get_task_mut(id))
+    {
+        Some(task) => task,
+        None => return Ok(crate::fs::DEFAULT_UMASK as usize),
     };
 
-    // Increment reference count for pipes
-    match new_entry.fd_type {
-        FdType::PipeRead(pipe_id) => {
-            let mut pipe_table = PIPE_TABLE.lock();
-            if let Some(pipe) = pipe_table.get_mut(pipe_id) {
-                pipe.readers += 1;
+    let old_mask = task.umask;
+    task.umask = new_mask;
+    serial_println!("[SYSCALL] sys_umask: {:03o} -> {:03o}", old_mask, new_mask);
+    Ok(old_mask as usize)
+}
+
+/// getrusage targets
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+const RUSAGE_THREAD: isize = 1;
+
+/// sys_getrusage handler - Report resource usage
+///
+/// Tasks are single-threaded, so RUSAGE_THREAD is the same as RUSAGE_SELF.
+/// RUSAGE_CHILDREN covers all exited descendants.
+///
+/// # Arguments
+/// * `who` - RUSAGE_SELF, RUSAGE_CHILDREN or RUSAGE_THREAD
+/// * `usage_ptr` - Pointer to a `struct rusage` to fill
+///
+/// # Returns
+/// 0 on success, or an error
+fn sys_getrusage(who: usize, usage_ptr: usize) -> SyscallResult {
+    let task = match crate::sched::get_current_task_info()
+        .and_then(|(id, _)| crate::sched::get_task_by_id(id))
+    {
+        Some(task) => task,
+        None => {
+            serial_println!("[SYSCALL] sys_getrusage: no current task");
+            return Err(Errno::ESRCH);
+        }
+    };
+
+    let totals = match who as isize {
+        RUSAGE_SELF | RUSAGE_THREAD => task.usage.snapshot(),
+        RUSAGE_CHILDREN => task.children_usage.snapshot(),
+        _ => {
+            serial_println!("[SYSCALL] sys_getrusage: invalid who {}", who as isize);
+            return Err(Errno::EINVAL);
+        }
+    };
+
+    if !write_user(usage_ptr, totals.to_rusage()) {
+        return Err(Errno::EFAULT);
+    }
+    Ok(0)
+}
+
+/// sys_event_subscribe handler - Subscribe a port to kernel events
+///
+/// # Arguments
+/// * `cap` - Capability handle of the port that receives one message per
+///   event (needs the receive right)
+/// * `mask` - Bitmask of `EventKind::mask()` values; 0 unsubscribes
+///
+/// # Returns
+/// 0 on success, or an error if the handle is not valid or too many ports are
+/// subscribed
+fn sys_event_subscribe(cap: usize, mask: usize) -> SyscallResult {
+    let port_id = match handle::current().map(|table| table.lock().port(cap, Rights::RECV)) {
+        Some(Ok(port_id)) => port_id,
+        Some(Err(e)) => return Err(e.into()),
+        None => return Err(Errno::ESRCH),
+    };
+    match crate::sys::event::subscribe(port_id, mask as u32) {
+        Ok(()) => Ok(0),
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_event_subscribe: port {}: {:?}", port_id, e);
+            Err(e.into())
+        }
+    }
+}
+
+/// sys_task_watch handler - Be told through a port when a task exits
+///
+/// # Arguments
+/// * `task` - ID of the task to watch
+/// * `cap` - Capability handle of the port that receives the `TaskExit`
+///   event message (needs the receive right)
+///
+/// # Returns
+/// 0 on success, `ESRCH` if there is no such task, or `EAGAIN` if too many
+/// watches are pending. A task that has already exited is reported at once.
+fn sys_task_watch(task: usize, cap: usize) -> SyscallResult {
+    let port_id = match handle::current().map(|table| table.lock().port(cap, Rights::RECV)) {
+        Some(Ok(port_id)) => port_id,
+        Some(Err(e)) => return Err(e.into()),
+        None => return Err(Errno::ESRCH),
+    };
+    crate::sys::exit_watch::watch(task, port_id)?;
+    Ok(0)
+}
+
+/// sys_brk handler - Move the program break
+///
+/// # Arguments
+/// * `addr` - Requested break, or 0 to query it
+///
+/// # Returns
+/// The new break, or the unchanged break if the request could not be met
+/// (like Linux, failure is only visible by comparing against `addr`)
+fn sys_brk(addr: usize) -> SyscallResult {
+    let task = match crate::sched::get_current_task_info()
+        .and_then(|(id, _)| crate::sched::get_address_space_mut(id))
+    {
+        Some(task) => task,
+        None => {
+            serial_println!("[SYSCALL] sys_brk: no current task");
+            return Err(Errno::ESRCH);
+        }
+    };
+    Ok(crate::mm::brk::set_brk(task, addr))
+}
+
+/// sys_shm_create handler - Create or open a shared memory object
+///
+/// # Arguments
+/// * `name_ptr` - Object name, or 0 for an anonymous object
+/// * `name_len` - Name length in bytes (at most `SHM_NAME_MAX`)
+/// * `size` - Object size in bytes; opening an existing named object only
+///   checks that it is at least this large
+///
+/// # Returns
+/// Handle of the object on success, or an error
+fn sys_shm_create(name_ptr: usize, name_len: usize, size: usize) -> SyscallResult {
+    use crate::sys::shm::{self, SHM_NAME_MAX};
+
+    let mut name = [0u8; SHM_NAME_MAX];
+    if name_len > SHM_NAME_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    if name_len > 0 {
+        if !validate_user_buffer(name_ptr, name_len)
+            || copy_from_user(&mut name[..name_len], name_ptr, name_len).is_err()
+        {
+            return Err(Errno::EFAULT);
+        }
+    }
+
+    match shm::create(&name[..name_len], size) {
+        Ok(id) => Ok(handle::install(Handle::new(Object::Shm(id)))?),
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_shm_create: {:?}", e);
+            Err(e.into())
+        }
+    }
+}
+
+/// sys_shm_map handler - Map a shared memory object
+///
+/// # Arguments
+/// * `shm` - Handle of the object
+/// * `addr` - Placement hint, or 0
+/// * `prot` - `PROT_*` flags; writable and executable is refused
+///
+/// # Returns
+/// Address of the mapping, or an error. `SYS_MUNMAP` removes it; the
+/// mapping stays valid after the handle is closed.
+fn sys_shm_map(shm: usize, addr: usize, prot: usize) -> SyscallResult {
+    let id = shm_object(shm)?;
+    let task = match crate::sched::get_current_task_info()
+        .and_then(|(id, _)| crate::sched::get_address_space_mut(id))
+    {
+        Some(task) => task,
+        None => return Err(Errno::ESRCH),
+    };
+
+    match crate::sys::shm::map(task, id, addr, prot) {
+        Ok(addr) => Ok(addr),
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_shm_map: object {}: {:?}", id, e);
+            Err(e.into())
+        }
+    }
+}
+
+/// Shared memory object behind handle `shm` of the current process
+fn shm_object(shm: usize) -> Result<usize, Errno> {
+    match lookup(shm).map(|handle| handle.object) {
+        Some(Object::Shm(id)) => Ok(id),
+        _ => Err(Errno::EBADF),
+    }
+}
+
+/// sys_ipc_poll handler - Wait for messages on a set of ports or for
+/// notification bits
+///
+/// # Arguments
+/// * `set_ptr` - `IpcWaitSet` to wait for, with the port bits indexed by
+///   port handle (each needs the receive right); overwritten with
+///   what is ready
+/// * `timeout` - Timeout in ticks, 0 to just check, or `IPC_WAIT_FOREVER`
+///
+/// # Returns
+/// Number of ready ports (plus one if notification bits were raised), 0 on
+/// timeout, or an error (bad pointer, or a handle that is not valid)
+fn sys_ipc_poll(set_ptr: usize, timeout: usize) -> SyscallResult {
+    use crate::sys::ipc::{self, IpcWaitSet, IPC_WAIT_FOREVER};
+
+    let Some(task) = current_task() else { return Err(Errno::ESRCH) };
+    let Some(table) = handle::current() else { return Err(Errno::ESRCH) };
+    let handles = match read_user::<IpcWaitSet>(set_ptr) {
+        Some(set) => set,
+        None => return Err(Errno::EFAULT),
+    };
+    let timeout = (timeout != IPC_WAIT_FOREVER).then(|| crate::time::Duration::from_ticks(timeout as u64));
+
+    // Wait on the ports behind the handles
+    let mut set = IpcWaitSet { notify: handles.notify, ..IpcWaitSet::default() };
+    for handle in handles.port_ids() {
+        match table.lock().port(handle, Rights::RECV) {
+            Ok(port_id) => set.add_port(port_id),
+            Err(e) => {
+                serial_println!("[SYSCALL] sys_ipc_poll: handle {}: {:?}", handle, e);
+                return Err(e.into());
             }
         }
-        FdType::PipeWrite(pipe_id) => {
-            let mut pipe_table = PIPE_TABLE.lock();
-            if let Some(pipe) = pipe_table.get_mut(pipe_id) {
-                pipe.writers += 1;
+    }
+
+    match ipc::poll(task.id, &mut set, timeout) {
+        Ok(ready) => {
+            let mut ready_handles = IpcWaitSet { notify: set.notify, ..IpcWaitSet::default() };
+            for handle in handles.port_ids() {
+                if table.lock().port(handle, Rights::RECV).map_or(false, |port_id| set.has_port(port_id)) {
+                    ready_handles.add_port(handle);
+                }
+            }
+            if !write_user(set_ptr, ready_handles) {
+                return Err(Errno::EFAULT);
+            }
+            Ok(ready)
+        }
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_ipc_poll: {:?}", e);
+            Err(e.into())
+        }
+    }
+}
+
+/// sys_ipc_notify handler - Raise notification bits on a task
+///
+/// # Arguments
+/// * `task_id` - Task to notify
+/// * `bits` - Bits to raise; a task polling for any of them wakes up
+///
+/// # Returns
+/// 0 on success, or ESRCH if the task does not exist
+fn sys_ipc_notify(task_id: usize, bits: usize) -> SyscallResult {
+    match crate::sys::ipc::notify(task_id, bits as u64) {
+        Ok(()) => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// sys_port_create handler - Create a port
+///
+/// # Returns
+/// Handle with send, receive and grant rights on the new port, or an error
+/// if no port or handle slot is free
+fn sys_port_create() -> SyscallResult {
+    use crate::sys::port::PORT_MANAGER;
+
+    let Some(table) = handle::current() else { return Err(Errno::ESRCH) };
+    if table.lock().is_full() {
+        return Err(Errno::EMFILE);
+    }
+    let port = match PORT_MANAGER.lock().alloc_port() {
+        Ok(port) => port,
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_port_create: {:?}", e);
+            return Err(e.into());
+        }
+    };
+    Ok(handle::install(Handle::new(Object::Port(port)))?)
+}
+
+/// sys_cap_derive handler - Copy a handle with fewer rights
+///
+/// # Arguments
+/// * `cap` - Handle to copy, of any kind
+/// * `rights` - Rights to keep (`Rights` bits); rights the original lacks
+///   are not added
+///
+/// # Returns
+/// Handle of the copy, or an error
+fn sys_cap_derive(cap: usize, rights: usize) -> SyscallResult {
+    let Some(rights) = Rights::from_bits(rights) else { return Err(Errno::EINVAL) };
+    match handle::current().map(|table| table.lock().duplicate(cap, rights)) {
+        Some(Ok(handle)) => Ok(handle),
+        Some(Err(e)) => Err(e.into()),
+        None => Err(Errno::ESRCH),
+    }
+}
+
+/// sys_cap_drop handler - Close a handle; the same as `SYS_CLOSE`
+///
+/// # Returns
+/// 0 on success, or EBADF if the handle is empty
+fn sys_cap_drop(cap: usize) -> SyscallResult {
+    sys_close(cap)
+}
+
+/// sys_timer_create handler - Create a timer
+///
+/// # Arguments
+/// * `delay` - Ticks until the first expiry
+/// * `period` - Ticks between later expiries, or 0 for a one-shot timer
+///
+/// # Returns
+/// Handle of the timer, or an error. Reading it returns the expirations
+/// since the last read as a `u64`.
+fn sys_timer_create(delay: usize, period: usize) -> SyscallResult {
+    use crate::time::Duration;
+
+    let id = waitable::create_timer(Duration::from_ticks(delay as u64), Duration::from_ticks(period as u64))?;
+    Ok(handle::install(Handle::new(Object::Timer(id)))?)
+}
+
+/// sys_event_create handler - Create an event object
+///
+/// # Arguments
+/// * `initial` - Starting count
+///
+/// # Returns
+/// Handle of the event, or an error. Writing a `u64` adds to the count;
+/// reading returns it and resets it to 0.
+fn sys_event_create(initial: usize) -> SyscallResult {
+    let id = waitable::create_event(initial as u64)?;
+    Ok(handle::install(Handle::new(Object::Event(id)))?)
+}
+
+/// sys_perf handler - Start or stop sampled profiling of a task
+///
+/// # Arguments
+/// * `target` - Task to profile: the caller (0) or one of its children
+/// * `shm` - Handle of the shared memory object to put the sample ring in
+///   (see `sys::perf`), or 0 to stop profiling `target`
+/// * `period` - Timer ticks between samples
+///
+/// # Returns
+/// 0 on success, or an error
+fn sys_perf(target: usize, shm: usize, period: usize) -> SyscallResult {
+    let Some(task) = current_task() else { return Err(Errno::ESRCH) };
+    let target = if target == 0 { task.id } else { target };
+    if target != task.id && crate::sched::get_task_by_id(target).map_or(true, |child| child.ppid != task.id) {
+        serial_println!("[SYSCALL] sys_perf: task {} is not a child of {}", target, task.id);
+        return Err(Errno::ESRCH);
+    }
+
+    let result = if shm == 0 {
+        crate::sys::perf::stop(target)
+    } else {
+        let shm_id = shm_object(shm)?;
+        let period = u32::try_from(period).unwrap_or(u32::MAX);
+        crate::sys::perf::start(task.id, target, shm_id, period)
+    };
+    match result {
+        Ok(()) => Ok(0),
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_perf: task {}: {:?}", target, e);
+            Err(e.into())
+        }
+    }
+}
+
+/// sys_poll handler - Wait until any of several handles is ready
+///
+/// # Arguments
+/// * `fds_ptr` - Array of `sys::poll::PollFd`; `revents` is filled in
+/// * `nfds` - Number of entries (at most `MAX_POLL_FDS`)
+/// * `timeout` - Ticks to wait at most; `POLL_WAIT_FOREVER` for no limit
+///
+/// # Returns
+/// Number of ready entries, 0 on timeout, or an error
+fn sys_poll(fds_ptr: usize, nfds: usize, timeout: usize) -> SyscallResult {
+    use crate::sys::poll::{self, PollFd, MAX_POLL_FDS, POLL_WAIT_FOREVER};
+
+    if nfds > MAX_POLL_FDS {
+        return Err(Errno::EINVAL);
+    }
+    let len = nfds * core::mem::size_of::<PollFd>();
+    if nfds > 0 && !validate_user_buffer(fds_ptr, len) {
+        return Err(Errno::EFAULT);
+    }
+
+    let mut fds = [PollFd::default(); MAX_POLL_FDS];
+    let bytes = unsafe { core::slice::from_raw_parts_mut(fds.as_mut_ptr() as *mut u8, len) };
+    if nfds > 0 && copy_from_user(bytes, fds_ptr, len).is_err() {
+        return Err(Errno::EFAULT);
+    }
+    let timeout = (timeout != POLL_WAIT_FOREVER).then(|| crate::time::Duration::from_ticks(timeout as u64));
+
+    match poll::poll(lookup, &mut fds[..nfds], timeout) {
+        Ok(ready) => {
+            let bytes = unsafe { core::slice::from_raw_parts(fds.as_ptr() as *const u8, len) };
+            if nfds > 0 && copy_to_user(fds_ptr, bytes).is_err() {
+                return Err(Errno::EFAULT);
+            }
+            Ok(ready)
+        }
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_poll: {:?}", e);
+            Err(e.into())
+        }
+    }
+}
+
+/// sys_thread_create handler - Start a thread in the caller's process
+///
+/// # Arguments
+/// * `params_ptr` - `sched::thread::ThreadParams`: entry point, its
+///   argument, user stack, TLS base and join word
+///
+/// # Returns
+/// Thread ID of the new thread, or an error
+fn sys_thread_create(params_ptr: usize) -> SyscallResult {
+    use crate::sched::thread::{self, ThreadParams};
+
+    let Some(params) = read_user::<ThreadParams>(params_ptr) else { return Err(Errno::EFAULT) };
+    match thread::create(&params) {
+        Ok(tid) => Ok(tid),
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_thread_create: {:?}", e);
+            Err(e.into())
+        }
+    }
+}
+
+/// sys_thread_exit handler - End the calling thread
+///
+/// Its join word is set to 0 and woken. Fails (EINVAL) for the task that
+/// created the process, which leaves with `SYS_EXIT`.
+fn sys_thread_exit() -> SyscallResult {
+    match current_task() {
+        Some(task) if task.is_thread() => crate::sched::thread::exit_current(),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// sys_futex handler - Sleep on or wake a user memory word
+///
+/// # Arguments
+/// * `addr` - Address of a 4-byte aligned `u32`
+/// * `op` - `FUTEX_WAIT` or `FUTEX_WAKE`
+/// * `val` - Value the word must hold to sleep (`FUTEX_WAIT`)
+///
+/// # Returns
+/// 0 once woken (the word changed), or an error, including a word that
+/// did not hold `val` to begin with (EAGAIN)
+fn sys_futex(addr: usize, op: usize, val: usize) -> SyscallResult {
+    use crate::sys::futex::{self, FUTEX_WAIT, FUTEX_WAKE};
+
+    match op {
+        FUTEX_WAIT => match futex::wait(addr, val as u32, || read_user::<u32>(addr)) {
+            Ok(()) => Ok(0),
+            Err(e) => Err(e.into()),
+        },
+        FUTEX_WAKE if addr % 4 == 0 && validate_user_buffer(addr, 4) => {
+            futex::wake(addr);
+            Ok(0)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// sys_sendfile handler - Move data between descriptors inside the kernel
+///
+/// Reads up to `count` bytes from `in_fd` and writes them to `out`, one
+/// message-sized chunk at a time through a kernel buffer, so the data never
+/// passes through user memory. If `out` is a port handle, each chunk
+/// becomes one message to the port. `SENDFILE_PORT` may be set in `out`;
+/// it is accepted and ignored.
+///
+/// There is no filesystem or socket layer yet, so the source is a pipe, a
+/// PTY or the console. The transfer stops early at a short read (end of
+/// file, or no more data for now) and when the port's queue is full.
+///
+/// # Returns
+/// Bytes delivered, or an error if nothing could be moved. A chunk the destination
+/// refuses after it was read is lost.
+fn sys_sendfile(out: usize, in_fd: usize, count: usize) -> SyscallResult {
+    use crate::sys::ipc::{Message, MAX_MESSAGE_SIZE};
+    use crate::sys::port::PORT_MANAGER;
+
+    let out = out & !SENDFILE_PORT;
+    let port_id = match lookup(out).map(|handle| handle.object) {
+        Some(Object::Port(_)) => match handle::current().map(|table| table.lock().port(out, Rights::SEND)) {
+            Some(Ok(port_id)) => Some(port_id),
+            Some(Err(e)) => {
+                serial_println!("[SYSCALL] sys_sendfile: handle {}: {:?}", out, e);
+                return Err(e.into());
+            }
+            None => return Err(Errno::ESRCH),
+        },
+        _ => None,
+    };
+
+    // The message is the kernel buffer for fd destinations too
+    let mut message = Message::new();
+    let mut done = 0;
+    while done < count {
+        if let Some(port_id) = port_id {
+            let full = PORT_MANAGER.lock().ports[port_id]
+                .as_ref()
+                .map_or(true, |port| port.is_queue_full());
+            if full {
+                break;
+            }
+        }
+
+        let want = core::cmp::min(count - done, MAX_MESSAGE_SIZE);
+        let got = match read_fd(in_fd, &mut message.data[..want]) {
+            Ok(0) => break,
+            Ok(got) => got,
+            Err(errno) if done == 0 => return Err(errno),
+            Err(_) => break,
+        };
+
+        let sent = match port_id {
+            Some(port_id) => {
+                message.len = got;
+                ipc_send_result(PORT_MANAGER.lock().send_prepared(port_id, &message)).map(|_| got)
+            }
+            None => write_fd(out, &message.data[..got]),
+        };
+        let sent = match sent {
+            Ok(sent) => sent,
+            Err(errno) => return if done > 0 { Ok(done) } else { Err(errno) },
+        };
+        done += sent;
+        if sent < got || got < want {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+/// sys_clock_gettime - Monotonic time since boot
+///
+/// The syscall form of the vDSO clock at `mello_abi::VDSO_CLOCK`, which
+/// returns the same value without entering the kernel.
+///
+/// # Returns
+/// Nanoseconds since boot
+fn sys_clock_gettime() -> SyscallResult {
+    Ok(crate::time::clock::now_ns() as usize)
+}
+
+/// sys_ptrace_lite - Turn syscall tracing of a task on or off
+///
+/// A traced task logs each syscall, its arguments, result and duration to
+/// the kernel log ring (`/proc/kmsg`); see `sys::strace`.
+///
+/// # Arguments
+/// * `target` - Task to trace: 0 for the caller, or one of its children
+/// * `enable` - 1 to start tracing, 0 to stop
+///
+/// # Returns
+/// 1 if the task was traced before, 0 if not, or an error
+fn sys_ptrace_lite(target: usize, enable: usize) -> SyscallResult {
+    let Some(caller) = current_task().map(|task| task.id) else { return Err(Errno::ESRCH) };
+    if enable > 1 {
+        return Err(Errno::EINVAL);
+    }
+    let target = if target == 0 { caller } else { target };
+    let Some(task) = crate::sched::get_task_mut(target) else { return Err(Errno::ESRCH) };
+    if target != caller && task.ppid != caller {
+        serial_println!("[SYSCALL] sys_ptrace_lite: task {} is not a child of {}", target, caller);
+        return Err(Errno::ESRCH);
+    }
+    Ok(crate::sys::strace::set(task, enable == 1) as usize)
+}
+
+/// sys_seccomp handler - Restrict the syscalls of a task
+///
+/// # Arguments
+/// * `target` - Task to filter: 0 for the caller, or a child of it
+/// * `filter_ptr` - `sys::seccomp::SeccompFilter` to add to the task's
+///   filter
+///
+/// # Returns
+/// 0 on success, or an error
+fn sys_seccomp(target: usize, filter_ptr: usize) -> SyscallResult {
+    use crate::sys::seccomp::SeccompFilter;
+
+    let Some(caller) = current_task().map(|task| task.id) else { return Err(Errno::ESRCH) };
+    let Some(filter) = read_user::<SeccompFilter>(filter_ptr) else { return Err(Errno::EFAULT) };
+    let target = if target == 0 { caller } else { target };
+    let Some(task) = crate::sched::get_task_mut(target) else { return Err(Errno::ESRCH) };
+    if target != caller && task.ppid != caller {
+        serial_println!("[SYSCALL] sys_seccomp: task {} is not a child of {}", target, caller);
+        return Err(Errno::ESRCH);
+    }
+    match task.seccomp.install(&filter) {
+        Ok(()) => Ok(0),
+        Err(()) => Err(Errno::EINVAL),
+    }
+}
+
+/// sys_cpu_group handler - Move a task to a CPU bandwidth group
+///
+/// Tasks created later by the task inherit its group (see
+/// `sched::bandwidth`).
+///
+/// # Arguments
+/// * `target` - Task to move: 0 for the caller, or one of its children
+/// * `group` - Group to move it to, below `sched::bandwidth::MAX_GROUPS`
+///
+/// # Returns
+/// The task's previous group, or an error
+fn sys_cpu_group(target: usize, group: usize) -> SyscallResult {
+    use crate::sched::bandwidth;
+
+    bandwidth::validate(group)?;
+    let Some(caller) = current_task().map(|task| task.id) else { return Err(Errno::ESRCH) };
+    let target = if target == 0 { caller } else { target };
+    let Some(task) = crate::sched::get_task_mut(target) else { return Err(Errno::ESRCH) };
+    if target != caller && task.ppid != caller {
+        serial_println!("[SYSCALL] sys_cpu_group: task {} is not a child of {}", target, caller);
+        return Err(Errno::ESRCH);
+    }
+    Ok(core::mem::replace(&mut task.cpu_group, group))
+}
+
+/// sys_cpu_quota handler - Set and/or read the cap of a CPU bandwidth group
+///
+/// A capped group's tasks get at most `quota_us` of CPU time every
+/// `period_us`, and are throttled for the rest of the period once they
+/// have used it (see `sched::bandwidth`). Group 0 cannot be capped.
+///
+/// # Arguments
+/// * `group` - The group
+/// * `quota_ptr` - `sched::bandwidth::CpuQuota` to apply (quota 0 lifts
+///   the cap), or 0 to leave the cap as it is
+/// * `stats_ptr` - Where to write the group's `CpuGroupStats` after the
+///   change, or 0
+///
+/// # Returns
+/// 0 on success, or an error
+fn sys_cpu_quota(group: usize, quota_ptr: usize, stats_ptr: usize) -> SyscallResult {
+    use crate::sched::bandwidth::{self, CpuGroupStats, CpuQuota};
+    use crate::time::Duration;
+
+    if quota_ptr != 0 {
+        let Some(quota) = read_user::<CpuQuota>(quota_ptr) else { return Err(Errno::EFAULT) };
+        let cap = (quota.quota_us != 0).then(|| Duration::from_micros(quota.quota_us));
+        let period = if cap.is_some() { Duration::from_micros(quota.period_us) } else { bandwidth::MAX_PERIOD };
+        bandwidth::set_quota(group, cap, period)?;
+    }
+    let (quota, period, stats) = bandwidth::get(group)?;
+    if stats_ptr != 0 {
+        let stats = CpuGroupStats {
+            quota_us: quota.map_or(0, |quota| quota.as_micros()),
+            period_us: period.as_micros(),
+            periods: stats.periods,
+            throttled_periods: stats.throttled_periods,
+            throttled_us: stats.throttled_time.as_micros(),
+            usage_us: stats.usage.as_micros(),
+        };
+        if !write_user(stats_ptr, stats) {
+            return Err(Errno::EFAULT);
+        }
+    }
+    Ok(0)
+}
+
+/// `SYS_SCHEDSTAT` kinds
+const SCHEDSTAT_TASK: usize = 0;
+const SCHEDSTAT_PRIORITY: usize = 1;
+
+/// sys_schedstat handler - Read scheduler statistics
+///
+/// # Arguments
+/// * `kind` - `SCHEDSTAT_TASK` for one task, or `SCHEDSTAT_PRIORITY` for
+///   the sums over every task at one priority level
+/// * `id` - Task ID (0: the caller), or priority (0 low, 1 normal, 2 high)
+/// * `stat_ptr` - Where to write the `sched::stats::SchedStat`
+///
+/// # Returns
+/// 0 on success, or an error
+fn sys_schedstat(kind: usize, id: usize, stat_ptr: usize) -> SyscallResult {
+    use crate::sched::stats;
+
+    let stat = match kind {
+        SCHEDSTAT_TASK => {
+            let id = if id == 0 { current_task().map(|task| task.id).ok_or(Errno::ESRCH)? } else { id };
+            stats::task(id).ok_or(Errno::ESRCH)?
+        }
+        SCHEDSTAT_PRIORITY => *stats::by_priority().get(id).ok_or(Errno::EINVAL)?,
+        _ => return Err(Errno::EINVAL),
+    };
+    if !write_user(stat_ptr, stat) {
+        return Err(Errno::EFAULT);
+    }
+    Ok(0)
+}
+
+/// sys_task_info handler - Describe one task
+///
+/// # Arguments
+/// * `id` - Task ID (0: the caller)
+/// * `info_ptr` - Where to write the `sched::info::TaskInfo`
+///
+/// # Returns
+/// 0 on success, or an error
+fn sys_task_info(id: usize, info_ptr: usize) -> SyscallResult {
+    let id = if id == 0 { current_task().map(|task| task.id).ok_or(Errno::ESRCH)? } else { id };
+    let info = crate::sched::info::task(id).ok_or(Errno::ESRCH)?;
+    if !write_user(info_ptr, info) {
+        return Err(Errno::EFAULT);
+    }
+    Ok(0)
+}
+
+/// sys_task_list handler - Describe every live task
+///
+/// # Arguments
+/// * `buf_ptr` - Array of `sched::info::TaskInfo`
+/// * `count` - Entries the array holds
+///
+/// # Returns
+/// The number of live tasks; only the first `count` are written, so a
+/// larger result means the array was too small
+fn sys_task_list(buf_ptr: usize, count: usize) -> SyscallResult {
+    use crate::sched::info::{self, TaskInfo};
+    use crate::sched::MAX_TASKS;
+
+    let size = core::mem::size_of::<TaskInfo>();
+    let count = count.min(MAX_TASKS);
+    if count > 0 && !validate_user_buffer(buf_ptr, count * size) {
+        return Err(Errno::EFAULT);
+    }
+    // Written one at a time: a whole table of them would not fit on the
+    // kernel stack
+    let (mut total, mut faulted) = (0, false);
+    info::for_each(|task| {
+        if total < count && !write_user(buf_ptr + total * size, task) {
+            faulted = true;
+        }
+        total += 1;
+    });
+    if faulted {
+        return Err(Errno::EFAULT);
+    }
+    Ok(total)
+}
+
+/// sys_hwinfo handler - Read the hardware inventory report
+///
+/// # Arguments
+/// * `buf_ptr` - Buffer for the report text (see `hwinfo`)
+/// * `len` - Size of the buffer
+/// * `offset` - Where in the report to start
+///
+/// # Returns
+/// Bytes read, possibly fewer than asked; 0 at the end of the report
+fn sys_hwinfo(buf_ptr: usize, len: usize, offset: usize) -> SyscallResult {
+    produce_output(buf_ptr, len, true, |chunk| Ok(crate::hwinfo::read(chunk, offset)))
+}
+
+/// sys_ping handler - Send an ICMP or ICMPv6 echo request and wait for
+/// the reply
+///
+/// The echo identifier is the caller's PID, so replies to different
+/// processes are kept apart (see `net::ping`).
+///
+/// # Arguments
+/// * `req_ptr` - `net::ping::PingRequest`: destination, sequence number
+///   and timeout (at most `net::ping::MAX_TIMEOUT`)
+///
+/// # Returns
+/// Round trip time in microseconds, or an error: `ETIMEDOUT` without a
+/// reply, `ENETUNREACH` without a route, `EHOSTUNREACH` if the next hop
+/// never answered address resolution
+fn sys_ping(req_ptr: usize) -> SyscallResult {
+    use crate::net::ping::{self, PingRequest};
+
+    let Some(request) = read_user::<PingRequest>(req_ptr) else { return Err(Errno::EFAULT) };
+    let peer = request.peer().ok_or(Errno::EAFNOSUPPORT)?;
+    let id = sys_getpid()? as u16;
+    let timeout = crate::time::Duration::from_millis(request.timeout_ms as u64);
+    let rtt = ping::ping(peer, id, request.seq, timeout)?;
+    Ok(rtt.as_micros() as usize)
+}
+
+/// sys_resolve handler - Look up the IPv4 address of a host name
+///
+/// Asks the configured DNS server unless the name is cached or an IPv4
+/// literal (see `net::dns`); this may take a few seconds.
+///
+/// # Arguments
+/// * `name_ptr`, `name_len` - The host name (not NUL-terminated)
+/// * `addr_ptr` - Where to store the 4-byte address
+///
+/// # Returns
+/// 0, or an error: `ENOENT` for an unknown name, `ENETUNREACH` without a
+/// DNS server, `ETIMEDOUT` if it does not answer, `EAGAIN` if it failed
+fn sys_resolve(name_ptr: usize, name_len: usize, addr_ptr: usize) -> SyscallResult {
+    use crate::net::dns;
+
+    // Room for a trailing dot
+    if name_len == 0 || name_len > dns::MAX_NAME + 1 {
+        return Err(Errno::EINVAL);
+    }
+    let mut name = [0u8; dns::MAX_NAME + 1];
+    if !validate_user_buffer(name_ptr, name_len) || copy_from_user(&mut name[..name_len], name_ptr, name_len).is_err() {
+        return Err(Errno::EFAULT);
+    }
+    let name = core::str::from_utf8(&name[..name_len]).map_err(|_| Errno::EINVAL)?;
+    let addr = dns::resolve(name)?;
+    if !write_user(addr_ptr, addr.0) {
+        return Err(Errno::EFAULT);
+    }
+    Ok(0)
+}
+
+/// sys_socket handler - Create a datagram or stream socket
+///
+/// # Arguments
+/// * `domain` - `AF_INET` or `AF_INET6`
+/// * `kind` - `SOCK_DGRAM` or `SOCK_STREAM`, optionally with
+///   `SOCK_NONBLOCK` and `SOCK_CLOEXEC`
+/// * `protocol` - 0, or `IPPROTO_UDP`/`IPPROTO_TCP` to match `kind`
+///
+/// # Returns
+/// Handle of the socket, or an error
+fn sys_socket(domain: usize, kind: usize, protocol: usize) -> SyscallResult {
+    use crate::net::socket::{self, Family, Type, SOCK_CLOEXEC, SOCK_NONBLOCK};
+
+    let family = Family::from_domain(domain).ok_or(Errno::EAFNOSUPPORT)?;
+    let ty = Type::from_args(kind, protocol).ok_or(Errno::EINVAL)?;
+    let fd_flags = if kind & SOCK_CLOEXEC != 0 { FD_CLOEXEC } else { 0 };
+    let status_flags = if kind & SOCK_NONBLOCK != 0 { O_NONBLOCK } else { 0 };
+    let id = socket::create(family, ty)?;
+    let handle = Handle::with_flags(Object::File(FdType::Socket(id)), fd_flags, status_flags);
+    handle::install(handle).map_err(|e| {
+        socket::release(id);
+        e.into()
+    })
+}
+
+/// Handle `fd` as a socket
+fn lookup_socket(fd: usize) -> Result<(u32, Handle), Errno> {
+    let handle = lookup(fd).ok_or(Errno::EBADF)?;
+    match handle.object {
+        Object::File(FdType::Socket(socket)) => Ok((socket, handle)),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// Copy in the `sockaddr_in` or `sockaddr_in6` of `len` bytes at `ptr`
+fn read_sockaddr(ptr: usize, len: usize) -> Result<crate::net::addr::SocketAddr, Errno> {
+    use crate::net::socket::{SockaddrIn, SockaddrIn6, AF_INET, AF_INET6};
+
+    let family = read_user::<u16>(ptr).ok_or(Errno::EFAULT)?;
+    let sockaddr = match family as usize {
+        AF_INET if len >= core::mem::size_of::<SockaddrIn>() => read_user::<SockaddrIn>(ptr).map(Into::into),
+        AF_INET6 if len >= core::mem::size_of::<SockaddrIn6>() => read_user::<SockaddrIn6>(ptr).map(Into::into),
+        AF_INET | AF_INET6 => return Err(Errno::EINVAL),
+        _ => return Err(Errno::EAFNOSUPPORT),
+    };
+    sockaddr.ok_or(Errno::EFAULT)
+}
+
+/// Copy `addr` out as a `sockaddr_in` or `sockaddr_in6` to `ptr`, cut to
+/// the length at `len_ptr`, and store its full length there, like Linux
+fn write_sockaddr(addr: &crate::net::addr::SocketAddr, ptr: usize, len_ptr: usize) -> Result<(), Errno> {
+    use crate::net::addr::IpAddr;
+    use crate::net::socket::{SockaddrIn, SockaddrIn6, AF_INET, AF_INET6};
+
+    let room = read_user::<u32>(len_ptr).ok_or(Errno::EFAULT)? as usize;
+    let mut bytes = [0u8; core::mem::size_of::<SockaddrIn6>()];
+    let len = match addr.ip {
+        IpAddr::V4(ip) => {
+            let sockaddr = SockaddrIn {
+                sin_family: AF_INET as u16,
+                sin_port: addr.port.to_be(),
+                sin_addr: ip.0,
+                sin_zero: [0; 8],
+            };
+            let size = core::mem::size_of::<SockaddrIn>();
+            bytes[..size].copy_from_slice(unsafe { core::slice::from_raw_parts(&sockaddr as *const SockaddrIn as *const u8, size) });
+            size
+        }
+        IpAddr::V6(ip) => {
+            let sockaddr = SockaddrIn6 {
+                sin6_family: AF_INET6 as u16,
+                sin6_port: addr.port.to_be(),
+                sin6_flowinfo: 0,
+                sin6_addr: ip.0,
+                sin6_scope_id: 0,
+            };
+            let size = core::mem::size_of::<SockaddrIn6>();
+            bytes.copy_from_slice(unsafe { core::slice::from_raw_parts(&sockaddr as *const SockaddrIn6 as *const u8, size) });
+            size
+        }
+    };
+    let copied = len.min(room);
+    if copied > 0 && (!validate_user_buffer(ptr, copied) || copy_to_user(ptr, &bytes[..copied]).is_err()) {
+        return Err(Errno::EFAULT);
+    }
+    if !write_user(len_ptr, len as u32) {
+        return Err(Errno::EFAULT);
+    }
+    Ok(())
+}
+
+/// sys_bind handler - Bind a socket to a local port
+///
+/// The address part of the socket address is not checked: a socket
+/// receives on all of our addresses (see `net::socket`).
+///
+/// # Arguments
+/// * `fd` - Socket handle
+/// * `addr_ptr` - `sockaddr_in` or `sockaddr_in6`; port 0 picks an
+///   ephemeral port
+/// * `addr_len` - Size of the socket address
+///
+/// # Returns
+/// 0 on success, or an error (`EADDRINUSE` for a port in use, `EINVAL`
+/// for a socket already bound)
+fn sys_bind(fd: usize, addr_ptr: usize, addr_len: usize) -> SyscallResult {
+    let (socket, _) = lookup_socket(fd)?;
+    let addr = read_sockaddr(addr_ptr, addr_len)?;
+    crate::net::socket::bind(socket, &addr)?;
+    Ok(0)
+}
+
+/// sys_sendto handler - Send a datagram, or data on a connection
+///
+/// A datagram socket not bound yet is bound to an ephemeral port first. An
+/// IPv4 destination whose next hop is not resolved yet fails with
+/// `EHOSTUNREACH` after an ARP request went out, like IPv6 with NDP;
+/// callers retry. On a connected stream socket this is `write` with
+/// `flags`, taking at most `net::udp::MAX_PAYLOAD` bytes per call.
+///
+/// # Arguments
+/// * `fd` - Socket handle
+/// * `buf_ptr`, `len` - The datagram (at most `net::udp::MAX_PAYLOAD`
+///   bytes) or stream data
+/// * `flags` - 0 or `MSG_DONTWAIT` (datagram sends never block)
+/// * `addr_ptr`, `addr_len` - Destination `sockaddr_in`/`sockaddr_in6`;
+///   0 for a stream socket
+///
+/// # Returns
+/// Bytes sent, or an error (`EDESTADDRREQ` for a datagram without
+/// destination, `EISCONN` for a stream socket with one)
+pub(crate) fn sys_sendto(
+    fd: usize,
+    buf_ptr: usize,
+    len: usize,
+    flags: usize,
+    addr_ptr: usize,
+    addr_len: usize,
+) -> SyscallResult {
+    let (socket, handle) = lookup_socket(fd)?;
+    let to = match addr_ptr {
+        0 => None,
+        _ => Some(read_sockaddr(addr_ptr, addr_len)?),
+    };
+    if to.is_some() && len > crate::net::udp::MAX_PAYLOAD {
+        return Err(Errno::EMSGSIZE);
+    }
+    let len = len.min(crate::net::udp::MAX_PAYLOAD);
+    let mut data = [0u8; crate::net::udp::MAX_PAYLOAD];
+    if len > 0 && (!validate_user_buffer(buf_ptr, len) || copy_from_user(&mut data[..len], buf_ptr, len).is_err()) {
+        return Err(Errno::EFAULT);
+    }
+    match to {
+        Some(to) => Ok(crate::net::socket::send_to(socket, &to, &data[..len])?),
+        None => {
+            let nonblock = handle.status_flags & O_NONBLOCK != 0 || flags & crate::net::socket::MSG_DONTWAIT != 0;
+            send_socket(socket, &data[..len], nonblock)
+        }
+    }
+}
+
+/// sys_recvfrom handler - Receive a datagram, or data on a connection
+///
+/// Waits for data unless the socket is non-blocking or `flags` has
+/// `MSG_DONTWAIT`. A datagram longer than the buffer is cut short; the
+/// rest is lost. A stream socket returns 0 at the end of the stream and
+/// leaves the address alone.
+///
+/// # Arguments
+/// * `fd` - Socket handle
+/// * `buf_ptr`, `len` - Buffer for the data
+/// * `flags` - 0 or `MSG_DONTWAIT`
+/// * `addr_ptr`, `addr_len_ptr` - Where to store the sender's socket
+///   address and its length (`u32`, in: room at `addr_ptr`), or 0 for
+///   neither
+///
+/// # Returns
+/// Bytes received, or an error (`EAGAIN` if none is queued and the call
+/// may not block, `EINVAL` for a datagram socket never bound, `ENOTCONN`
+/// for a stream socket never connected)
+pub(crate) fn sys_recvfrom(
+    fd: usize,
+    buf_ptr: usize,
+    len: usize,
+    flags: usize,
+    addr_ptr: usize,
+    addr_len_ptr: usize,
+) -> SyscallResult {
+    let (socket, handle) = lookup_socket(fd)?;
+    if len > 0 && !validate_user_buffer(buf_ptr, len) {
+        return Err(Errno::EFAULT);
+    }
+    let nonblock = handle.status_flags & O_NONBLOCK != 0 || flags & crate::net::socket::MSG_DONTWAIT != 0;
+    let mut data = [0u8; crate::net::udp::MAX_PAYLOAD];
+    let room = len.min(data.len());
+    let (received, from) = recv_socket(socket, &mut data[..room], nonblock)?;
+    if received > 0 && copy_to_user(buf_ptr, &data[..received]).is_err() {
+        return Err(Errno::EFAULT);
+    }
+    if let (Some(from), true) = (from, addr_ptr != 0 && addr_len_ptr != 0) {
+        write_sockaddr(&from, addr_ptr, addr_len_ptr)?;
+    }
+    Ok(received)
+}
+
+/// sys_connect handler - Connect a stream socket
+///
+/// Sends the SYN and waits for the handshake to finish, unless the socket
+/// is non-blocking: then it fails with `EINPROGRESS` and the socket polls
+/// writable once the handshake is over; calling connect again then gives
+/// its outcome. An unbound socket gets an ephemeral port.
+///
+/// # Arguments
+/// * `fd` - Socket handle
+/// * `addr_ptr`, `addr_len` - Peer's `sockaddr_in`/`sockaddr_in6`
+///
+/// # Returns
+/// 0 once connected, or an error (`ECONNREFUSED`, `ETIMEDOUT`,
+/// `EALREADY` while a handshake runs, `EISCONN` once connected,
+/// `EOPNOTSUPP` for a datagram socket)
+fn sys_connect(fd: usize, addr_ptr: usize, addr_len: usize) -> SyscallResult {
+    use crate::net::socket;
+
+    let (socket, handle) = lookup_socket(fd)?;
+    let to = read_sockaddr(addr_ptr, addr_len)?;
+    match socket::connect(socket, &to) {
+        Ok(()) => {}
+        // The result of a non-blocking connect is collected by calling
+        // connect again
+        Err(crate::net::NetError::Already | crate::net::NetError::AlreadyConnected) => {
+            if let Some(result) = socket::connect_status(socket) {
+                return result.map(|()| 0).map_err(Into::into);
             }
+            return Err(Errno::EALREADY);
         }
-        _ => {}
+        Err(e) => return Err(e.into()),
+    }
+    if handle.status_flags & O_NONBLOCK != 0 {
+        return Err(Errno::EINPROGRESS);
     }
+    let mut result = None;
+    let waited = socket::wait_queue(socket).wait_until(|| {
+        result = socket::connect_status(socket);
+        result.is_some()
+    });
+    match result {
+        Some(result) => result.map(|()| 0).map_err(Into::into),
+        None if waited => Err(Errno::EALREADY),
+        None => Err(Errno::EINPROGRESS),
+    }
+}
 
-    // Close newfd if it's open, then allocate at that position
-    if fd_table.allocate_at(newfd, new_entry.fd_type, new_entry.fd_flags, new_entry.status_flags) {
-        serial_println!("[SYSCALL] sys_dup2: duplicated FD {} to FD {}", oldfd, newfd);
-        newfd as isize
+/// sys_listen handler - Make a stream socket accept connections
+///
+/// An unbound socket gets an ephemeral port.
+///
+/// # Arguments
+/// * `fd` - Socket handle
+/// * `backlog` - Most connections to queue for accept (1 to 8)
+///
+/// # Returns
+/// 0 on success, or an error (`EINVAL` for a connected socket,
+/// `EOPNOTSUPP` for a datagram socket)
+fn sys_listen(fd: usize, backlog: usize) -> SyscallResult {
+    let (socket, _) = lookup_socket(fd)?;
+    crate::net::socket::listen(socket, backlog)?;
+    Ok(0)
+}
+
+/// sys_accept handler - Take a connection from a listening socket
+///
+/// Waits for an established connection unless the socket is non-blocking.
+/// The new handle has no flags set.
+///
+/// # Arguments
+/// * `fd` - Listening socket handle
+/// * `addr_ptr`, `addr_len_ptr` - Where to store the peer's socket
+///   address and its length, as for `SYS_RECVFROM`, or 0 for neither
+///
+/// # Returns
+/// Handle of the connection, or an error (`EAGAIN` if none is waiting and
+/// the call may not block, `EINVAL` if the socket is not listening)
+fn sys_accept(fd: usize, addr_ptr: usize, addr_len_ptr: usize) -> SyscallResult {
+    use crate::net::socket;
+
+    let (listener, handle) = lookup_socket(fd)?;
+    let nonblock = handle.status_flags & O_NONBLOCK != 0;
+    let (id, peer) = loop {
+        if let Some(accepted) = socket::accept(listener)? {
+            break accepted;
+        }
+        let ready = || socket::readable(listener);
+        if nonblock || !socket::wait_queue(listener).wait_until(ready) {
+            return Err(Errno::EAGAIN);
+        }
+    };
+    if addr_ptr != 0 && addr_len_ptr != 0 {
+        if let Err(e) = write_sockaddr(&peer, addr_ptr, addr_len_ptr) {
+            socket::release(id);
+            return Err(e);
+        }
+    }
+    let handle = Handle::with_flags(Object::File(FdType::Socket(id)), 0, 0);
+    handle::install(handle).map_err(|e| {
+        socket::release(id);
+        e.into()
+    })
+}
+
+/// Read the NUL-terminated string at `ptr` into `buf`; returns it without
+/// the NUL
+fn read_user_cstr(ptr: usize, buf: &mut [u8]) -> Result<&[u8], Errno> {
+    for len in 0..buf.len() {
+        match read_user::<u8>(ptr + len) {
+            Some(0) => return Ok(&buf[..len]),
+            Some(byte) => buf[len] = byte,
+            None => return Err(Errno::EFAULT),
+        }
+    }
+    Err(Errno::ENAMETOOLONG)
+}
+
+/// sys_spawn handler - Start a program from the initrd as a child process
+///
+/// The new process starts at the program's entry point with the caller's
+/// process group, session, terminal, umask, seccomp filter, CPU bandwidth
+/// group and handles, and its arguments on its stack (see `user::spawn`).
+/// Init uses this to start services, and the shell to run commands.
+///
+/// # Arguments
+/// * `path_ptr` - NUL-terminated path of the program in the initrd
+/// * `argv_ptr` - Null-terminated array of pointers to NUL-terminated
+///   arguments, or 0 for the path alone
+///
+/// # Returns
+/// PID of the new process, or an error (ENOENT if the initrd has no such
+/// file, ENOEXEC if it is not an ELF image, E2BIG if the arguments do not
+/// fit in `user::spawn::ARG_MAX` bytes)
+fn sys_spawn(path_ptr: usize, argv_ptr: usize) -> SyscallResult {
+    let mut path_buf = [0u8; 256];
+    let path = read_user_cstr(path_ptr, &mut path_buf)?;
+    let Ok(path) = core::str::from_utf8(path) else { return Err(Errno::ENOENT) };
+
+    let mut args = crate::user::spawn::Args::new()?;
+    if argv_ptr == 0 {
+        args.push(path.as_bytes())?;
     } else {
-        serial_println!("[SYSCALL] sys_dup2: failed to allocate at FD {}", newfd);
-        -1
+        let mut arg_buf = [0u8; 256];
+        for i in 0.. {
+            let Some(arg_ptr) = read_user::<usize>(argv_ptr + i * 8) else { return Err(Errno::EFAULT) };
+            if arg_ptr == 0 {
+                break;
+            }
+            let arg = read_user_cstr(arg_ptr, &mut arg_buf).map_err(|e| match e {
+                Errno::ENAMETOOLONG => Errno::E2BIG,
+                e => e,
+            })?;
+            args.push(arg)?;
+        }
+    }
+
+    match crate::user::spawn::spawn(path, args) {
+        Ok(pid) => Ok(pid),
+        Err(e) => {
+            serial_println!("[SYSCALL] sys_spawn: {}: {:?}", path, e);
+            Err(e.into())
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// Pipe ends report readiness, EAGAIN instead of blocking, and EOF or
+    /// EPIPE once the other end is closed
+    fn pipe_blocking_semantics() {
+        let pipe_id = PIPE_TABLE.lock().allocate().ok_or("no free pipe")?;
+        let install = |fd_type| handle::install(Handle::with_flags(Object::File(fd_type), 0, O_NONBLOCK));
+        let reader = install(FdType::PipeRead(pipe_id)).map_err(|_| "no free fd")?;
+        let writer = install(FdType::PipeWrite(pipe_id)).map_err(|_| "no free fd")?;
+        let fd_events = |fd| lookup(fd).and_then(|handle| object_events(handle.object));
+
+        let mut buf = [0u8; 8];
+        crate::ktest_assert_eq!(fd_events(reader), Some(0), "empty pipe readable");
+        crate::ktest_assert_eq!(fd_events(writer), Some(POLLOUT), "empty pipe not writable");
+        crate::ktest_assert_eq!(read_fd(reader, &mut buf), Err(Errno::EAGAIN), "empty read did not fail with EAGAIN");
+
+        // A watcher is taken off the queue (and woken) by the write
+        let me = crate::sched::get_current_task_info().map_or(0, |(id, _)| id);
+        let queue = object_wait_queue(Object::File(FdType::PipeRead(pipe_id))).ok_or("pipe has no wait queue")?;
+        crate::ktest_assert!(queue.add(me), "watch failed");
+        crate::ktest_assert_eq!(write_fd(writer, b"abc"), Ok(3), "short write");
+        crate::ktest_assert!(!queue.contains(me), "watcher not woken");
+        crate::ktest_assert_eq!(fd_events(reader), Some(POLLIN), "data not readable");
+        crate::ktest_assert_eq!(read_fd(reader, &mut buf), Ok(3), "read length");
+        crate::ktest_assert_eq!(&buf[..3], b"abc", "read data");
+
+        // Writer gone: end of file
+        sys_close(writer).map_err(|_| "close failed")?;
+        crate::ktest_assert_eq!(fd_events(reader), Some(POLLHUP), "no hangup after writer close");
+        crate::ktest_assert_eq!(read_fd(reader, &mut buf), Ok(0), "no EOF after writer close");
+        sys_close(reader).map_err(|_| "close failed")?;
+        crate::ktest_assert!(PIPE_TABLE.lock().get(pipe_id).is_none(), "pipe not freed");
+        Ok(())
+    }
+}
+
+crate::kernel_test! {
+    /// SYS_SENDFILE moves what the source pipe holds into another pipe
+    fn sendfile_pipe_to_pipe() {
+        let (source, sink) = {
+            let mut pipes = PIPE_TABLE.lock();
+            (pipes.allocate().ok_or("no free pipe")?, pipes.allocate().ok_or("no free pipe")?)
+        };
+        let install = |fd_type| handle::install(Handle::with_flags(Object::File(fd_type), 0, O_NONBLOCK));
+        let fds = [
+            install(FdType::PipeRead(source)),
+            install(FdType::PipeWrite(source)),
+            install(FdType::PipeRead(sink)),
+            install(FdType::PipeWrite(sink)),
+        ];
+        let [Ok(source_r), Ok(source_w), Ok(sink_r), Ok(sink_w)] = fds else {
+            return Err("no free fd");
+        };
+
+        crate::ktest_assert_eq!(write_fd(source_w, b"hello"), Ok(5), "short write");
+        crate::ktest_assert_eq!(sys_sendfile(sink_w, source_r, 64), Ok(5), "bytes moved");
+        crate::ktest_assert_eq!(sys_sendfile(sink_w, source_r, 64), Err(Errno::EAGAIN), "empty source did not fail with EAGAIN");
+        let mut buf = [0u8; 8];
+        crate::ktest_assert_eq!(read_fd(sink_r, &mut buf), Ok(5), "sink length");
+        crate::ktest_assert_eq!(&buf[..5], b"hello", "sink data");
+
+        for fd in [source_r, source_w, sink_r, sink_w] {
+            sys_close(fd).map_err(|_| "close failed")?;
+        }
+        Ok(())
     }
 }
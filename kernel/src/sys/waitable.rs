@@ -0,0 +1,245 @@
+//! Timer and event objects
+//!
+//! Two small objects for waking user tasks, used through handles (see
+//! `sys::handle`) with `SYS_READ`, `SYS_WRITE` and `SYS_POLL`:
+//!
+//! - A timer (`SYS_TIMER_CREATE`) expires after a delay and then once per
+//!   period, if it has one. Reading it returns the number of expirations
+//!   since the last read as a `u64`, blocking until there is one.
+//! - An event (`SYS_EVENT_CREATE`) is a counter. Writing a `u64` adds it;
+//!   reading returns the count and resets it to 0, blocking while it is 0.
+//!
+//! Reads block like pipe reads, unless the handle has `O_NONBLOCK`. An
+//! object is freed when the last handle to it is closed.
+//!
+//! Timers have no interrupt of their own: a reader or poller sleeps until
+//! the next expiry (see [`timer_deadline`]), and readiness is computed
+//! from the clock when checked.
+
+use crate::sync::{Poller, SpinLock, WaitQueue};
+use crate::time::{Duration, Instant};
+
+/// Maximum number of live timers
+const MAX_TIMERS: usize = 32;
+
+/// Maximum number of live event objects
+const MAX_EVENTS: usize = 32;
+
+/// Timer and event errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitableError {
+    /// Bad timer period or event value
+    InvalidArgument,
+    /// No object with this id
+    NotFound,
+    /// The timer or event table is full
+    TooManyObjects,
+    /// Nothing to read (non-blocking), or an event count would overflow
+    WouldBlock,
+}
+
+struct Timer {
+    /// Handles referring to the timer
+    refs: usize,
+    /// Next expiry; None once a one-shot timer has been read
+    next: Option<Instant>,
+    /// Time between expiries; zero for a one-shot timer
+    period: Duration,
+}
+
+impl Timer {
+    /// Expirations up to now; `take` consumes them
+    fn expirations(&mut self, take: bool) -> u64 {
+        let Some(next) = self.next else { return 0 };
+        let now = Instant::now();
+        if now < next {
+            return 0;
+        }
+        if self.period.is_zero() {
+            if take {
+                self.next = None;
+            }
+            return 1;
+        }
+        let late = now.saturating_duration_since(next).as_nanos();
+        let count = 1 + late / self.period.as_nanos();
+        if take {
+            let advance = Duration::from_nanos(count.saturating_mul(self.period.as_nanos()));
+            self.next = Some(next.saturating_add(advance));
+        }
+        count
+    }
+}
+
+struct Event {
+    /// Handles referring to the event
+    refs: usize,
+    count: u64,
+}
+
+static TIMERS: SpinLock<[Option<Timer>; MAX_TIMERS]> = SpinLock::new([const { None }; MAX_TIMERS]);
+
+static EVENTS: SpinLock<[Option<Event>; MAX_EVENTS]> = SpinLock::new([const { None }; MAX_EVENTS]);
+
+/// Tasks waiting on each event
+static EVENT_WAIT: [WaitQueue; MAX_EVENTS] = [const { WaitQueue::new() }; MAX_EVENTS];
+
+/// Store `object` in the first free slot of `table`; returns its id
+fn insert<T>(table: &mut [Option<T>], object: T) -> Result<usize, WaitableError> {
+    let id = table.iter().position(Option::is_none).ok_or(WaitableError::TooManyObjects)?;
+    table[id] = Some(object);
+    Ok(id)
+}
+
+/// Create a timer expiring after `delay` and then every `period` (zero
+/// for one-shot); returns its id with one reference
+pub fn create_timer(delay: Duration, period: Duration) -> Result<usize, WaitableError> {
+    let next = Instant::now().checked_add(delay).ok_or(WaitableError::InvalidArgument)?;
+    let timer = Timer { refs: 1, next: Some(next), period };
+    insert(&mut *TIMERS.lock(), timer)
+}
+
+pub fn retain_timer(id: usize) {
+    if let Some(Some(timer)) = TIMERS.lock().get_mut(id) {
+        timer.refs += 1;
+    }
+}
+
+pub fn release_timer(id: usize) {
+    if let Some(slot) = TIMERS.lock().get_mut(id) {
+        if let Some(timer) = slot {
+            timer.refs -= 1;
+            if timer.refs == 0 {
+                *slot = None;
+            }
+        }
+    }
+}
+
+/// Expirations of timer `id` that have not been read, or None if there is
+/// no such timer
+pub fn timer_pending(id: usize) -> Option<u64> {
+    TIMERS.lock().get_mut(id)?.as_mut().map(|timer| timer.expirations(false))
+}
+
+/// Next expiry of timer `id`, if it will expire again
+pub fn timer_deadline(id: usize) -> Option<Instant> {
+    TIMERS.lock().get(id)?.as_ref()?.next
+}
+
+/// Read the expirations of timer `id`, waiting for one unless `nonblock`
+pub fn read_timer(id: usize, nonblock: bool) -> Result<u64, WaitableError> {
+    loop {
+        let count = match TIMERS.lock().get_mut(id) {
+            Some(Some(timer)) => timer.expirations(true),
+            _ => return Err(WaitableError::NotFound),
+        };
+        if count > 0 {
+            return Ok(count);
+        }
+        if nonblock {
+            return Err(WaitableError::WouldBlock);
+        }
+        let wait = match timer_deadline(id) {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::MAX,
+        };
+        let poller = Poller::current().ok_or(WaitableError::WouldBlock)?;
+        poller.wait(core::iter::empty(), wait, || false);
+    }
+}
+
+/// Create an event object holding `count`; returns its id with one
+/// reference
+pub fn create_event(count: u64) -> Result<usize, WaitableError> {
+    insert(&mut *EVENTS.lock(), Event { refs: 1, count })
+}
+
+pub fn retain_event(id: usize) {
+    if let Some(Some(event)) = EVENTS.lock().get_mut(id) {
+        event.refs += 1;
+    }
+}
+
+pub fn release_event(id: usize) {
+    if let Some(slot) = EVENTS.lock().get_mut(id) {
+        if let Some(event) = slot {
+            event.refs -= 1;
+            if event.refs == 0 {
+                *slot = None;
+            }
+        }
+    }
+}
+
+/// Count of event `id`, or None if there is no such event
+pub fn event_count(id: usize) -> Option<u64> {
+    EVENTS.lock().get(id)?.as_ref().map(|event| event.count)
+}
+
+/// Queue woken when event `id` is signalled
+pub fn event_wait_queue(id: usize) -> Option<&'static WaitQueue> {
+    EVENT_WAIT.get(id)
+}
+
+/// Add `value` to event `id`, waking its readers
+pub fn signal_event(id: usize, value: u64) -> Result<(), WaitableError> {
+    if value == 0 {
+        return Err(WaitableError::InvalidArgument);
+    }
+    {
+        let mut events = EVENTS.lock();
+        let Some(Some(event)) = events.get_mut(id) else { return Err(WaitableError::NotFound) };
+        event.count = event.count.checked_add(value).ok_or(WaitableError::WouldBlock)?;
+    }
+    EVENT_WAIT[id].wake_all();
+    Ok(())
+}
+
+/// Take the count of event `id`, waiting while it is 0 unless `nonblock`
+pub fn read_event(id: usize, nonblock: bool) -> Result<u64, WaitableError> {
+    let queue = EVENT_WAIT.get(id).ok_or(WaitableError::NotFound)?;
+    loop {
+        {
+            let mut events = EVENTS.lock();
+            let Some(Some(event)) = events.get_mut(id) else { return Err(WaitableError::NotFound) };
+            if event.count > 0 {
+                return Ok(core::mem::take(&mut event.count));
+            }
+        }
+        if nonblock {
+            return Err(WaitableError::WouldBlock);
+        }
+        if !crate::sched::wait::wait_event(queue, || event_count(id).map_or(true, |count| count > 0)) {
+            return Err(WaitableError::WouldBlock);
+        }
+    }
+}
+
+crate::kernel_test! {
+    /// Timers count expirations, events accumulate until read, and both
+    /// are freed with their last reference
+    fn timer_and_event_objects() {
+        let timer = create_timer(Duration::ZERO, Duration::from_secs(3600)).map_err(|_| "timer create failed")?;
+        crate::ktest_assert_eq!(timer_pending(timer), Some(1), "expired timer not pending");
+        crate::ktest_assert_eq!(read_timer(timer, true), Ok(1), "expiration not read");
+        crate::ktest_assert_eq!(read_timer(timer, true), Err(WaitableError::WouldBlock), "read before the next period");
+        retain_timer(timer);
+        release_timer(timer);
+        crate::ktest_assert!(timer_pending(timer).is_some(), "timer freed with a reference left");
+        release_timer(timer);
+        crate::ktest_assert!(timer_pending(timer).is_none(), "timer outlived its last reference");
+
+        let event = create_event(0).map_err(|_| "event create failed")?;
+        crate::ktest_assert_eq!(read_event(event, true), Err(WaitableError::WouldBlock), "empty event read");
+        signal_event(event, 2).map_err(|_| "signal failed")?;
+        signal_event(event, 3).map_err(|_| "signal failed")?;
+        crate::ktest_assert_eq!(read_event(event, true), Ok(5), "count not accumulated");
+        crate::ktest_assert_eq!(event_count(event), Some(0), "count not reset");
+        crate::ktest_assert_eq!(signal_event(event, u64::MAX), Ok(()), "signal failed");
+        crate::ktest_assert_eq!(signal_event(event, 1), Err(WaitableError::WouldBlock), "count overflowed");
+        release_event(event);
+        crate::ktest_assert!(event_count(event).is_none(), "event outlived its last reference");
+        Ok(())
+    }
+}
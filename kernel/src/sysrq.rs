@@ -0,0 +1,217 @@
+//! SysRq: emergency actions from the console
+//!
+//! A serial break, or Ctrl-O, arms SysRq; the next key picks an action:
+//!
+//! | Key | Action |
+//! |-----|--------|
+//! | `t` | List the tasks with their state and CPU time |
+//! | `a` | List outstanding heap allocations by call site (`kmalloc-debug`) |
+//! | `m` | Show free memory and memory pressure |
+//! | `k` | Kill the user task that has used the most CPU time |
+//! | `s` | Write the persistent settings (the only data held for a disk) |
+//! | `p` | Start or stop the kernel profiler |
+//! | `d` | Stop the profiler and dump its histogram to the serial port |
+//! | `e` | Dump and empty the tracepoint rings to the serial port |
+//! | `b` | Reboot at once, without syncing |
+//! | `o` | Power off at once, without syncing |
+//! | other | List the actions |
+//!
+//! Ctrl-O twice passes a Ctrl-O on to the console reader. There is no
+//! keyboard driver, so there is no Ctrl+Alt+Del.
+//!
+//! The console takes what the serial port received on the timer interrupt
+//! and hands every byte to [`filter`] (see `console::poll_input`), so the
+//! actions run in interrupt context and work when the scheduler is wedged
+//! or a task holds a lock forever. They take no lock they would wait for:
+//! reports go out through `console::emergency_print`, and whatever is
+//! locked is reported as busy instead.
+
+use crate::sched::info::TaskInfo;
+use crate::sched::task::TaskState;
+use crate::serial::Received;
+use crate::time::Duration;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Multi-line reports through [`report!`]'s output, each line prefixed
+struct ReportWriter {
+    line_start: bool,
+}
+
+impl core::fmt::Write for ReportWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for line in s.split_inclusive('\n') {
+            if self.line_start {
+                crate::console::emergency_print(format_args!("[SYSRQ] "));
+            }
+            crate::console::emergency_print(format_args!("{}", line));
+            self.line_start = line.ends_with('\n');
+        }
+        Ok(())
+    }
+}
+
+/// Ctrl-O, which arms SysRq from a terminal that cannot send a break
+pub const SYSRQ_KEY: u8 = 0x0F;
+
+/// What a received byte is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// Console input
+    Input(u8),
+    /// The action to run
+    Action(u8),
+    /// Part of a SysRq sequence
+    Swallowed,
+}
+
+/// The next byte picks an action
+static ARMED: AtomicBool = AtomicBool::new(false);
+
+macro_rules! report {
+    ($($arg:tt)*) => {
+        crate::console::emergency_print(format_args!("[SYSRQ] {}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Sort a received byte or break into input or SysRq
+pub fn filter(received: Received) -> Key {
+    let armed = ARMED.swap(false, Ordering::Relaxed);
+    match received {
+        Received::Break | Received::Byte(SYSRQ_KEY) if !armed => {
+            ARMED.store(true, Ordering::Relaxed);
+            Key::Swallowed
+        }
+        Received::Byte(SYSRQ_KEY) => Key::Input(SYSRQ_KEY),
+        Received::Byte(key) if armed => Key::Action(key),
+        Received::Byte(byte) => Key::Input(byte),
+        // A second break disarms
+        Received::Break => Key::Swallowed,
+    }
+}
+
+/// Run the action for `key`
+pub fn handle(key: u8) {
+    match key {
+        b't' => show_tasks(),
+        b'a' => {
+            let _ = crate::mm::allocator::kmalloc_report(&mut ReportWriter { line_start: true });
+        }
+        b'm' => show_memory(),
+        b'k' => kill_top_task(),
+        b's' => sync(),
+        b'e' => {
+            if !crate::trace::try_dump() {
+                report!("Trace ring busy");
+            }
+        }
+        b'p' => toggle_profiler(),
+        b'd' => {
+            if !crate::profiler::try_export() {
+                report!("Profiler busy");
+            }
+        }
+        b'b' => {
+            report!("Rebooting");
+            crate::arch::x86_64::reset::reboot();
+        }
+        b'o' => {
+            report!("Powering off");
+            let e = crate::arch::x86_64::acpi::power::poweroff();
+            report!("Power off failed: {:?}", e);
+        }
+        _ => report!("a=allocations b=reboot d=dump-profile e=dump-trace k=kill-top-cpu m=memory o=poweroff p=profile s=sync t=tasks"),
+    }
+}
+
+fn show_tasks() {
+    report!("   ID   PID  PPID STATE    PRI CPU(ms) NAME");
+    crate::sched::for_each_task(|task| {
+        let info = TaskInfo::of(task);
+        report!(
+            "{:>5} {:>5} {:>5} {:<9} {:>2} {:>7} {}",
+            info.id,
+            info.pid,
+            info.ppid,
+            info.state_name(),
+            info.priority,
+            info.cpu_ticks * Duration::TICK.as_millis(),
+            task.name
+        );
+    });
+}
+
+fn show_memory() {
+    match crate::mm::try_memory_mb() {
+        Some((total, free)) => report!("Memory: {} MB free of {} MB", free, total),
+        None => report!("Memory manager busy"),
+    }
+    let (level, pressure, scan) = crate::mm::pressure::snapshot();
+    report!(
+        "Pressure: {:?} ({}%), working set {} KiB, idle {} KiB",
+        level,
+        pressure,
+        scan.working_set * 4,
+        scan.idle * 4
+    );
+}
+
+/// Send SIGKILL to the user task that has used the most CPU time
+///
+/// Only tasks that have run in user mode are candidates, and never init;
+/// the timer ends the task the next time it interrupts it in user mode.
+fn kill_top_task() {
+    let mut top: Option<(usize, u64)> = None;
+    crate::sched::for_each_task(|task| {
+        let ticks = TaskInfo::of(task).cpu_ticks;
+        let candidate = task.state != TaskState::Exited
+            && task.pid != crate::user::spawn::INIT_PID
+            && task.usage.snapshot().user_ticks > 0;
+        if candidate && top.map_or(true, |(_, most)| ticks > most) {
+            top = Some((task.id, ticks));
+        }
+    });
+    let Some((top_id, _)) = top else {
+        report!("No user task to kill");
+        return;
+    };
+    crate::sched::for_each_task(|task| {
+        if task.id == top_id {
+            crate::signal::send_signal_to_task(task, crate::signal::signals::SIGKILL);
+            report!("Killed {} (pid {}, task {})", task.name, task.pid, task.id);
+        }
+    });
+}
+
+fn toggle_profiler() {
+    if crate::profiler::is_running() {
+        crate::profiler::stop();
+        report!("Profiler stopped; d dumps the samples");
+    } else if crate::profiler::start() {
+        report!("Profiler started");
+    } else {
+        report!("Profiler busy");
+    }
+}
+
+fn sync() {
+    match crate::settings::try_flush() {
+        Some(true) => report!("Settings written"),
+        Some(false) => report!("No settings device, or the write failed"),
+        None => report!("Settings busy"),
+    }
+}
+
+crate::kernel_test! {
+    /// Break or Ctrl-O arms SysRq for one key; Ctrl-O twice is input
+    fn sysrq_filter_sequences() {
+        ARMED.store(false, Ordering::Relaxed);
+        crate::ktest_assert_eq!(filter(Received::Byte(b'a')), Key::Input(b'a'), "plain input swallowed");
+        crate::ktest_assert_eq!(filter(Received::Break), Key::Swallowed, "break not swallowed");
+        crate::ktest_assert_eq!(filter(Received::Byte(b't')), Key::Action(b't'), "no action after break");
+        crate::ktest_assert_eq!(filter(Received::Byte(b't')), Key::Input(b't'), "still armed after action");
+        crate::ktest_assert_eq!(filter(Received::Byte(SYSRQ_KEY)), Key::Swallowed, "Ctrl-O did not arm");
+        crate::ktest_assert_eq!(filter(Received::Byte(SYSRQ_KEY)), Key::Input(SYSRQ_KEY), "Ctrl-O twice not input");
+        crate::ktest_assert_eq!(filter(Received::Byte(b'm')), Key::Input(b'm'), "armed after Ctrl-O twice");
+        Ok(())
+    }
+}
@@ -0,0 +1,406 @@
+//! Monotonic clock
+//!
+//! Time since boot is kept as a base (nanoseconds, tick count and counter
+//! value at the last update) plus what one clock source measured since
+//! then: TSC cycles when the TSC is invariant and calibrated, HPET cycles
+//! when the TSCs of the CPUs could not be synchronized, otherwise ticks
+//! times the current tick length. Every change to how time is measured
+//! first folds the elapsed time into the base, so the clock stays
+//! continuous when
+//!
+//! - the tick rate changes ([`set_tick_rate`]): earlier ticks keep the
+//!   length they had,
+//! - ticks stop while a CPU idles ([`idle_skipped`]): the TSC already
+//!   covers the gap, otherwise the idle code reports how long it slept,
+//! - the machine resumes from suspend ([`suspend`], [`resume`]): the TSC
+//!   and timers restart, so the time asleep is taken from the RTC,
+//! - an AP's TSC cannot be synchronized with the BSP's
+//!   ([`fall_back_to_hpet`]): the HPET reads the same on every CPU.
+//!
+//! SMP bring-up aligns each AP's TSC with the BSP's
+//! (`arch::x86_64::smp::tsc_sync`), so the TSC clock agrees across CPUs to
+//! within the measurement error. Readers never see it go backwards:
+//! [`now_ns`] returns at least the last value it returned on any CPU, which
+//! also hides that remaining error.
+//!
+//! Only CPU 0's timer interrupt counts ticks here ([`tick`]); the global
+//! `sched::timer` tick counter is bumped by every CPU.
+
+use super::Duration;
+use crate::sync::SeqLock;
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Length of the TSC calibration against the HPET
+const CALIBRATION_US: u64 = 10_000;
+
+/// Conversion from counter cycles to nanoseconds: `(cycles * mult) >> shift`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CycleScale {
+    mult: u32,
+    shift: u32,
+}
+
+impl CycleScale {
+    /// Scale for a counter running at `frequency` Hz
+    ///
+    /// The shift is as large as possible (at most 32) with a 32-bit multiplier.
+    pub fn from_frequency(frequency: u64) -> Self {
+        let mut shift = 32;
+        loop {
+            let mult = ((NANOS_PER_SEC as u128) << shift) / frequency.max(1) as u128;
+            if mult <= u32::MAX as u128 || shift == 0 {
+                return CycleScale { mult: mult.min(u32::MAX as u128) as u32, shift };
+            }
+            shift -= 1;
+        }
+    }
+
+    pub fn mult(&self) -> u32 {
+        self.mult
+    }
+
+    pub fn shift(&self) -> u32 {
+        self.shift
+    }
+
+    pub fn to_nanos(&self, cycles: u64) -> u64 {
+        let nanos = (cycles as u128 * self.mult as u128) >> self.shift;
+        nanos.min(u64::MAX as u128) as u64
+    }
+}
+
+/// What the clock measures elapsed time with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// CPU 0's timer ticks
+    Ticks,
+    /// The TSC of the reading CPU
+    Tsc(CycleScale),
+    /// The HPET main counter, which wraps at `mask`
+    Hpet { scale: CycleScale, mask: u64 },
+}
+
+/// Clock state: the time at the last update and how to measure from there
+#[derive(Debug, Clone, Copy)]
+pub struct Timekeeper {
+    /// Nanoseconds since boot at the last update
+    base_ns: u64,
+    /// Tick count at the last update
+    base_ticks: u64,
+    /// Counter value of the source at the last update
+    base_cycles: u64,
+    /// Length of a tick at the current rate
+    tick_nanos: u64,
+    source: Source,
+}
+
+impl Timekeeper {
+    /// Clock at zero, counting ticks of `tick_nanos`
+    pub const fn new(tick_nanos: u64) -> Self {
+        Timekeeper { base_ns: 0, base_ticks: 0, base_cycles: 0, tick_nanos, source: Source::Ticks }
+    }
+
+    /// Time when the tick count is `ticks` and the source's counter reads
+    /// `cycles`
+    ///
+    /// A TSC behind the base (read on another CPU) counts as no time.
+    pub fn read(&self, ticks: u64, cycles: u64) -> u64 {
+        let elapsed = match self.source {
+            Source::Ticks => ticks.saturating_sub(self.base_ticks).saturating_mul(self.tick_nanos),
+            Source::Tsc(scale) => scale.to_nanos(cycles.saturating_sub(self.base_cycles)),
+            Source::Hpet { scale, mask } => scale.to_nanos(cycles.wrapping_sub(self.base_cycles) & mask),
+        };
+        self.base_ns.saturating_add(elapsed)
+    }
+
+    /// Fold the time elapsed since the last update into the base
+    pub fn rebase(&mut self, ticks: u64, cycles: u64) {
+        self.base_ns = self.read(ticks, cycles);
+        self.base_ticks = ticks;
+        self.base_cycles = cycles;
+    }
+
+    /// Count further ticks as `tick_nanos` long
+    pub fn set_tick_nanos(&mut self, ticks: u64, cycles: u64, tick_nanos: u64) {
+        self.rebase(ticks, cycles);
+        self.tick_nanos = tick_nanos;
+    }
+
+    /// Measure with `source` from now on
+    ///
+    /// `cycles` is the current source's counter, `source_cycles` the new
+    /// one's.
+    pub fn set_source(&mut self, ticks: u64, cycles: u64, source: Source, source_cycles: u64) {
+        self.rebase(ticks, cycles);
+        self.base_cycles = source_cycles;
+        self.source = source;
+    }
+
+    /// Account `elapsed` during which no ticks were counted
+    ///
+    /// A counter kept running through the gap, so only the tick count needs
+    /// the time added.
+    pub fn skip(&mut self, ticks: u64, cycles: u64, elapsed: u64) {
+        self.rebase(ticks, cycles);
+        if self.source == Source::Ticks {
+            self.base_ns = self.base_ns.saturating_add(elapsed);
+        }
+    }
+
+    /// Continue after a suspend that lasted `slept` nanoseconds
+    ///
+    /// The base must be from just before the suspend. The counter may have
+    /// restarted, so the new readings become the base as they are.
+    pub fn resume(&mut self, ticks: u64, cycles: u64, slept: u64) {
+        self.base_ns = self.base_ns.saturating_add(slept);
+        self.base_ticks = ticks;
+        self.base_cycles = cycles;
+    }
+
+    /// Time at the last update
+    pub fn base_ns(&self) -> u64 {
+        self.base_ns
+    }
+
+    /// Counter value of the source at the last update
+    pub fn base_cycles(&self) -> u64 {
+        self.base_cycles
+    }
+
+    /// TSC scale, if the TSC is the clock source
+    pub fn tsc_scale(&self) -> Option<CycleScale> {
+        match self.source {
+            Source::Tsc(scale) => Some(scale),
+            _ => None,
+        }
+    }
+}
+
+static CLOCK: SeqLock<Timekeeper> = SeqLock::new(Timekeeper::new(Duration::TICK.as_nanos()));
+
+/// Ticks counted by CPU 0
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Calibrated TSC frequency in Hz, or 0 if the TSC is not usable
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Largest value [`now_ns`] has returned
+static LAST_NS: AtomicU64 = AtomicU64::new(0);
+
+/// RTC seconds at [`suspend`], or `NO_RTC`
+static SUSPEND_RTC: AtomicU64 = AtomicU64::new(NO_RTC);
+const NO_RTC: u64 = u64::MAX;
+
+fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Current counter value of `source`
+fn cycles(source: Source) -> u64 {
+    match source {
+        Source::Ticks => 0,
+        Source::Tsc(_) => rdtsc(),
+        Source::Hpet { .. } => crate::arch::x86_64::hpet::get().map_or(0, |hpet| hpet.counter()),
+    }
+}
+
+/// Change the clock with interrupts off, so a reader in an interrupt
+/// handler cannot spin on a half-written update
+fn update(f: impl FnOnce(&mut Timekeeper, u64, u64)) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut clock = CLOCK.write();
+        let cycles = cycles(clock.source);
+        f(&mut clock, TICKS.load(Ordering::Relaxed), cycles);
+    });
+}
+
+/// Switch the clock to `source`
+fn set_source(source: Source) {
+    update(|clock, ticks, old_cycles| clock.set_source(ticks, old_cycles, source, cycles(source)));
+}
+
+/// TSC frequency in Hz, if the TSC is usable as a clock
+///
+/// The TSC must be invariant. Its rate is measured against the HPET, or
+/// taken from CPUID leaf 0x15 when there is no HPET.
+fn tsc_frequency() -> Option<u64> {
+    if !crate::arch::x86_64::cpu::features::get().invariant_tsc {
+        return None;
+    }
+
+    if let Some(hpet) = crate::arch::x86_64::hpet::get() {
+        let start = rdtsc();
+        hpet.busy_wait_us(CALIBRATION_US);
+        let cycles = rdtsc().wrapping_sub(start);
+        return Some(cycles * (1_000_000 / CALIBRATION_US));
+    }
+
+    // Leaf 0x15: TSC = crystal (ECX) * EBX / EAX, any of them 0 if unknown
+    if __cpuid(0).eax >= 0x15 {
+        let leaf = __cpuid(0x15);
+        if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+            return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64);
+        }
+    }
+    None
+}
+
+/// Calibrate the TSC and make it the clock source if it is usable
+///
+/// Must run on the BSP after the HPET is set up. Until then, and without a
+/// usable TSC, the clock counts ticks.
+pub fn init() {
+    match tsc_frequency() {
+        Some(frequency) => {
+            TSC_HZ.store(frequency, Ordering::Relaxed);
+            set_source(Source::Tsc(CycleScale::from_frequency(frequency)));
+            crate::serial_println!("[TIME] Clock source: TSC at {} kHz", frequency / 1000);
+        }
+        None => crate::serial_println!("[TIME] Clock source: timer tick ({} ns)", Duration::TICK.as_nanos()),
+    }
+}
+
+/// Calibrated frequency of the invariant TSC, if [`init`] found one
+///
+/// Stays valid when the clock falls back to the HPET: each CPU's TSC still
+/// runs at this rate.
+pub fn tsc_frequency_hz() -> Option<u64> {
+    match TSC_HZ.load(Ordering::Relaxed) {
+        0 => None,
+        hz => Some(hz),
+    }
+}
+
+/// Stop measuring with the TSC because the CPUs' TSCs disagree
+///
+/// The clock reads the HPET from now on, or counts ticks without one.
+/// Does nothing unless the TSC is the clock source.
+pub fn fall_back_to_hpet() {
+    if snapshot().tsc_scale().is_none() {
+        return;
+    }
+    match crate::arch::x86_64::hpet::get() {
+        Some(hpet) => {
+            let scale = CycleScale::from_frequency(hpet.frequency());
+            set_source(Source::Hpet { scale, mask: hpet.counter_mask() });
+            crate::serial_println!("[TIME] Clock source: HPET at {} kHz", hpet.frequency() / 1000);
+        }
+        None => {
+            set_source(Source::Ticks);
+            crate::serial_println!("[TIME] Clock source: timer tick ({} ns)", Duration::TICK.as_nanos());
+        }
+    }
+}
+
+/// Count a timer tick; called from CPU 0's timer interrupt
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    update(|clock, ticks, cycles| clock.rebase(ticks, cycles));
+}
+
+/// Nanoseconds since boot
+pub fn now_ns() -> u64 {
+    let now = CLOCK.read(|clock| clock.read(TICKS.load(Ordering::Relaxed), cycles(clock.source)));
+    LAST_NS.fetch_max(now, Ordering::AcqRel).max(now)
+}
+
+/// Copy of the clock state, for the vDSO page
+pub fn snapshot() -> Timekeeper {
+    CLOCK.read(|clock| *clock)
+}
+
+/// Record that CPU 0's timer now ticks at `hz`
+///
+/// Call right after reprogramming the timer.
+pub fn set_tick_rate(hz: u64) {
+    update(|clock, ticks, cycles| clock.set_tick_nanos(ticks, cycles, NANOS_PER_SEC / hz.max(1)));
+}
+
+/// Record that CPU 0 idled for `elapsed` without ticks
+pub fn idle_skipped(elapsed: Duration) {
+    update(|clock, ticks, cycles| clock.skip(ticks, cycles, elapsed.as_nanos()));
+}
+
+/// Save the clock before the machine suspends
+pub fn suspend() {
+    update(|clock, ticks, cycles| clock.rebase(ticks, cycles));
+    let rtc = crate::arch::x86_64::rtc::read_seconds().unwrap_or(NO_RTC);
+    SUSPEND_RTC.store(rtc, Ordering::Relaxed);
+}
+
+/// Continue the clock after a resume, adding the time asleep per the RTC
+///
+/// Without an RTC reading on both sides the time asleep is lost, but the
+/// clock still does not go backwards.
+pub fn resume() {
+    let before = SUSPEND_RTC.swap(NO_RTC, Ordering::Relaxed);
+    let slept = match crate::arch::x86_64::rtc::read_seconds() {
+        Some(after) if before != NO_RTC => after.saturating_sub(before).saturating_mul(NANOS_PER_SEC),
+        _ => 0,
+    };
+    update(|clock, ticks, cycles| clock.resume(ticks, cycles, slept));
+    crate::serial_println!("[TIME] Resumed after {} s asleep", slept / NANOS_PER_SEC);
+}
+
+crate::kernel_test! {
+    /// The clock stays continuous across tick rate changes, ticks skipped
+    /// while idle, a switch to the TSC, a suspend that resets the TSC, and
+    /// a switch to a wrapping HPET
+    fn clock_continuous_across_changes() {
+        const MS: u64 = 1_000_000;
+        let mut clock = Timekeeper::new(50 * MS);
+        let mut last = 0;
+        let mut check = |now: u64, expected: u64, msg: &'static str| -> crate::ktest::TestResult {
+            crate::ktest_assert!(now >= last, "clock went backwards");
+            crate::ktest_assert_eq!(now, expected, msg);
+            last = now;
+            Ok(())
+        };
+
+        check(clock.read(10, 0), 500 * MS, "ticks at 20 Hz")?;
+        clock.set_tick_nanos(10, 0, 10 * MS);
+        check(clock.read(10, 0), 500 * MS, "rate change moved the clock")?;
+        check(clock.read(15, 0), 550 * MS, "ticks at 100 Hz")?;
+
+        clock.skip(15, 0, 1000 * MS);
+        check(clock.read(15, 0), 1550 * MS, "idle time not added")?;
+
+        // 1 GHz TSC from here on
+        let scale = CycleScale::from_frequency(NANOS_PER_SEC);
+        clock.set_source(15, 0, Source::Tsc(scale), 1_000_000);
+        check(clock.read(15, 1_000_000), 1550 * MS, "switch to the TSC moved the clock")?;
+        check(clock.read(15, 2_000_000), 1551 * MS, "TSC time")?;
+        check(clock.read(15, 500_000), 1550 * MS, "TSC behind the base counted")?;
+        clock.skip(16, 3_000_000, 1000 * MS);
+        check(clock.read(16, 3_000_000), 1552 * MS, "idle time counted twice with the TSC")?;
+
+        // Suspend for 5 s; the TSC restarts near zero
+        clock.rebase(16, 4_000_000);
+        clock.resume(16, 1000, 5000 * MS);
+        check(clock.read(16, 1000), 6553 * MS, "time asleep not added")?;
+        check(clock.read(16, 1_001_000), 6554 * MS, "TSC time after resume")?;
+
+        // 1 GHz 32-bit HPET, about to wrap
+        let hpet = Source::Hpet { scale, mask: u32::MAX as u64 };
+        clock.set_source(16, 2_001_000, hpet, (1 << 32) - 500_000);
+        check(clock.read(16, (1 << 32) - 500_000), 6555 * MS, "switch to the HPET moved the clock")?;
+        check(clock.read(16, 500_000), 6556 * MS, "HPET wrap not counted")?;
+        Ok(())
+    }
+}
+
+crate::kernel_test! {
+    /// `now_ns` never goes backwards
+    fn clock_now_monotonic() {
+        let mut last = now_ns();
+        for _ in 0..10_000 {
+            let now = now_ns();
+            crate::ktest_assert!(now >= last, "clock went backwards");
+            last = now;
+        }
+        Ok(())
+    }
+}
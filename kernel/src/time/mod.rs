@@ -0,0 +1,274 @@
+//! Kernel time types
+//!
+//! `Instant` is a point in time measured from boot, `Duration` a span of
+//! time. Both are stored in nanoseconds so that APIs state their units in
+//! the type instead of passing raw `u64` tick counts around.
+//!
+//! `Instant::now()` reads the monotonic clock in [`clock`], which counts
+//! TSC cycles where it can and scheduler ticks otherwise, and stays
+//! continuous when the tick rate changes, ticks stop while idle, or the
+//! machine resumes from suspend. `TICK` is the nominal tick length
+//! (`config::SCHED_HZ`). Code that talks to legacy tick-based interfaces
+//! converts at the boundary with `Duration::from_ticks` /
+//! `Duration::as_ticks` and `Instant::from_ticks` / `Instant::as_ticks`.
+//!
+//! # Migration
+//! New code takes `Instant`/`Duration`, never bare tick counts. Existing
+//! tick-based interfaces are moved over as they are touched:
+//! - Task sleeps (`Task::wake_at`, `sched::sleep_current_task`) and the
+//!   priority scheduler's sleep queue use `Instant`/`Duration`.
+//! - The `SYS_SLEEP` ABI still takes ticks; the syscall converts.
+//! - Accounting fields in ticks (`Process::creation_time`, `cpu_time`) and
+//!   the driver API's `ticks()` remain until their consumers move.
+
+#![allow(dead_code)]
+
+pub mod clock;
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub};
+
+const NANOS_PER_MICRO: u64 = 1_000;
+const NANOS_PER_MILLI: u64 = 1_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Length of one scheduler tick in nanoseconds
+const TICK_NANOS: u64 = NANOS_PER_SEC / crate::config::SCHED_HZ;
+
+/// A span of time with nanosecond precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Duration {
+    nanos: u64,
+}
+
+impl Duration {
+    /// Zero-length duration
+    pub const ZERO: Duration = Duration { nanos: 0 };
+
+    /// Longest representable duration
+    pub const MAX: Duration = Duration { nanos: u64::MAX };
+
+    /// Length of one scheduler tick
+    pub const TICK: Duration = Duration { nanos: TICK_NANOS };
+
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self { nanos }
+    }
+
+    pub const fn from_micros(micros: u64) -> Self {
+        Self::from_nanos(micros.saturating_mul(NANOS_PER_MICRO))
+    }
+
+    pub const fn from_millis(millis: u64) -> Self {
+        Self::from_nanos(millis.saturating_mul(NANOS_PER_MILLI))
+    }
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Self::from_nanos(secs.saturating_mul(NANOS_PER_SEC))
+    }
+
+    /// Convert a legacy tick count
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self::from_nanos(ticks.saturating_mul(TICK_NANOS))
+    }
+
+    pub const fn as_nanos(&self) -> u64 {
+        self.nanos
+    }
+
+    pub const fn as_micros(&self) -> u64 {
+        self.nanos / NANOS_PER_MICRO
+    }
+
+    pub const fn as_millis(&self) -> u64 {
+        self.nanos / NANOS_PER_MILLI
+    }
+
+    pub const fn as_secs(&self) -> u64 {
+        self.nanos / NANOS_PER_SEC
+    }
+
+    /// Number of whole ticks, rounded up so a sleep never ends early
+    pub const fn as_ticks(&self) -> u64 {
+        self.nanos.div_ceil(TICK_NANOS)
+    }
+
+    pub const fn is_zero(&self) -> bool {
+        self.nanos == 0
+    }
+
+    pub const fn checked_add(self, rhs: Duration) -> Option<Duration> {
+        match self.nanos.checked_add(rhs.nanos) {
+            Some(nanos) => Some(Duration { nanos }),
+            None => None,
+        }
+    }
+
+    pub const fn checked_sub(self, rhs: Duration) -> Option<Duration> {
+        match self.nanos.checked_sub(rhs.nanos) {
+            Some(nanos) => Some(Duration { nanos }),
+            None => None,
+        }
+    }
+
+    pub const fn saturating_add(self, rhs: Duration) -> Duration {
+        Duration {
+            nanos: self.nanos.saturating_add(rhs.nanos),
+        }
+    }
+
+    pub const fn saturating_sub(self, rhs: Duration) -> Duration {
+        Duration {
+            nanos: self.nanos.saturating_sub(rhs.nanos),
+        }
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        self.checked_add(rhs).expect("overflow when adding durations")
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        self.checked_sub(rhs).expect("overflow when subtracting durations")
+    }
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.nanos >= NANOS_PER_SEC {
+            write!(f, "{}.{:03}s", self.as_secs(), self.as_millis() % 1000)
+        } else if self.nanos >= NANOS_PER_MILLI {
+            write!(f, "{}ms", self.as_millis())
+        } else if self.nanos >= NANOS_PER_MICRO {
+            write!(f, "{}us", self.as_micros())
+        } else {
+            write!(f, "{}ns", self.nanos)
+        }
+    }
+}
+
+/// A point in time, measured from boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    since_boot: Duration,
+}
+
+impl Instant {
+    /// The moment the clock started
+    pub const BOOT: Instant = Instant {
+        since_boot: Duration::ZERO,
+    };
+
+    /// Current time
+    pub fn now() -> Self {
+        Instant {
+            since_boot: Duration::from_nanos(clock::now_ns()),
+        }
+    }
+
+    /// Convert a legacy absolute tick value
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Instant {
+            since_boot: Duration::from_ticks(ticks),
+        }
+    }
+
+    /// Absolute tick value, for legacy tick-based interfaces
+    pub const fn as_ticks(&self) -> u64 {
+        self.since_boot.as_nanos() / TICK_NANOS
+    }
+
+    /// Time elapsed since boot
+    pub const fn since_boot(&self) -> Duration {
+        self.since_boot
+    }
+
+    /// Time from `earlier` to `self`, or zero if `earlier` is later
+    pub const fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.since_boot.saturating_sub(earlier.since_boot)
+    }
+
+    /// Time elapsed since `self`
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().saturating_duration_since(*self)
+    }
+
+    pub const fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        match self.since_boot.checked_add(duration) {
+            Some(since_boot) => Some(Instant { since_boot }),
+            None => None,
+        }
+    }
+
+    /// `self + duration`, clamped to the far future instead of overflowing
+    pub const fn saturating_add(&self, duration: Duration) -> Instant {
+        Instant {
+            since_boot: self.since_boot.saturating_add(duration),
+        }
+    }
+
+    /// Whether this instant has been reached
+    pub fn has_passed(&self) -> bool {
+        Instant::now() >= *self
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        self.checked_add(rhs).expect("overflow when adding duration to instant")
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.saturating_duration_since(rhs)
+    }
+}
+
+impl fmt::Display for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "T+{}", self.since_boot)
+    }
+}
+
+crate::kernel_test! {
+    /// Tick conversions round-trip and round up partial ticks
+    fn time_tick_conversions() {
+        crate::ktest_assert_eq!(Duration::from_ticks(3).as_ticks(), 3, "tick round trip");
+        crate::ktest_assert_eq!(
+            Duration::from_nanos(TICK_NANOS + 1).as_ticks(),
+            2,
+            "partial tick not rounded up"
+        );
+        crate::ktest_assert_eq!(Duration::ZERO.as_ticks(), 0, "zero duration has ticks");
+        crate::ktest_assert_eq!(
+            Duration::from_secs(1).as_ticks(),
+            crate::config::SCHED_HZ,
+            "one second is not SCHED_HZ ticks"
+        );
+
+        let start = Instant::from_ticks(10);
+        let later = start + Duration::from_ticks(5);
+        crate::ktest_assert_eq!(later.as_ticks(), 15, "instant arithmetic");
+        crate::ktest_assert_eq!(later - start, Duration::from_ticks(5), "instant difference");
+        crate::ktest_assert_eq!(start - later, Duration::ZERO, "difference not saturating");
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+//! Static tracepoints
+//!
+//! A tracepoint is a named [`Event`] listed in the `tracepoints!` table
+//! below and hit with [`trace!`](crate::trace!):
+//!
+//! ```rust,ignore
+//! crate::trace!(ipc_send, port_id, len);
+//! ```
+//!
+//! Each event is enabled on its own, at boot with `trace=` on the command
+//! line (a comma-separated list of event names, or `all`) or at run time
+//! with [`set_enabled`]. A disabled tracepoint costs one atomic load. An
+//! enabled one stores a binary [`Record`] (timestamp on the monotonic
+//! clock, task, event, up to three arguments and the address of the
+//! tracepoint) in the ring of the CPU it ran on; each ring keeps the
+//! latest [`RING_RECORDS`] records.
+//!
+//! SysRq `e` writes the records to the serial port, one line each, and
+//! empties the rings:
+//!
+//! ```text
+//! [TRACE] begin
+//! [TRACE] <cpu> <ns> <task> <event> <arg0> <arg1> <arg2> <site>
+//! [TRACE] end <records> records, <overwritten> overwritten, <missed> missed
+//! ```
+//!
+//! `<site>` is the tracepoint's address followed by the function it is in
+//! (see `ksyms`). `tools/debug/trace2json.py` turns a serial log holding such a dump
+//! into Chrome trace-event JSON for `chrome://tracing` or Perfetto.
+//! `/proc/trace` lists the events, whether each is enabled, and its hits.
+//!
+//! Recording never waits: a hit that finds its ring locked by a dump is
+//! counted as missed.
+
+use crate::config::MAX_CPUS;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+macro_rules! tracepoints {
+    ($($(#[doc = $doc:literal])* $name:ident,)*) => {
+        /// A tracepoint
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(u16)]
+        pub enum Event {
+            $($(#[doc = $doc])* $name,)*
+        }
+
+        impl Event {
+            /// Every tracepoint, in `repr` order
+            pub const ALL: &'static [Event] = &[$(Event::$name,)*];
+
+            /// The tracepoint's name, as in `trace=` and dumps
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $(Event::$name => stringify!($name),)*
+                }
+            }
+        }
+    };
+}
+
+tracepoints! {
+    /// A CPU switched tasks: previous task, next task, previous task's
+    /// state
+    sched_switch,
+    /// A syscall started: number, first two arguments
+    syscall_enter,
+    /// A syscall returned: number, result (negative errno on failure)
+    syscall_exit,
+    /// An interrupt handler started: vector
+    irq_entry,
+    /// A message was sent: port, length
+    ipc_send,
+}
+
+/// Record `event` with up to three arguments if it is enabled
+///
+/// Arguments are converted with `as u64`.
+#[macro_export]
+macro_rules! trace {
+    ($event:ident $(, $arg:expr)* $(,)?) => {
+        if $crate::trace::is_enabled($crate::trace::Event::$event) {
+            let site: u64;
+            unsafe {
+                core::arch::asm!("lea {}, [rip]", out(reg) site, options(nomem, nostack, preserves_flags));
+            }
+            $crate::trace::record($crate::trace::Event::$event, site, &[$($arg as u64),*]);
+        }
+    };
+}
+
+/// Records each CPU's ring keeps
+pub const RING_RECORDS: usize = 512;
+
+/// One tracepoint hit
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Record {
+    /// Monotonic clock, in nanoseconds
+    pub ns: u64,
+    pub task: u32,
+    pub event: u16,
+    pub cpu: u16,
+    pub args: [u64; 3],
+    /// Address of the tracepoint
+    pub site: u64,
+}
+
+impl Record {
+    const EMPTY: Self = Self {
+        ns: 0,
+        task: 0,
+        event: 0,
+        cpu: 0,
+        args: [0; 3],
+        site: 0,
+    };
+}
+
+/// A ring of a CPU's latest `N` records
+struct Ring<const N: usize> {
+    records: [Record; N],
+    /// Records written since the ring was last emptied
+    written: u64,
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Self {
+        Self {
+            records: [Record::EMPTY; N],
+            written: 0,
+        }
+    }
+
+    fn push(&mut self, record: Record) {
+        self.records[(self.written % N as u64) as usize] = record;
+        self.written += 1;
+    }
+
+    /// The records kept, oldest first
+    fn records(&self) -> impl Iterator<Item = &Record> {
+        let kept = (self.written as usize).min(N);
+        let first = (self.written as usize - kept) % N;
+        (0..kept).map(move |i| &self.records[(first + i) % N])
+    }
+
+    /// Records lost to newer ones
+    fn overwritten(&self) -> u64 {
+        self.written - (self.written as usize).min(N) as u64
+    }
+}
+
+crate::per_cpu! {
+    /// Each CPU's trace ring
+    static RINGS: Mutex<Ring<RING_RECORDS>> = Mutex::new(Ring::new());
+}
+
+/// Bit `event as u16` set for each enabled event
+static ENABLED: AtomicU64 = AtomicU64::new(0);
+
+/// Hits per event while enabled
+static HITS: [AtomicU64; Event::ALL.len()] = [const { AtomicU64::new(0) }; Event::ALL.len()];
+
+/// Hits dropped because a dump held the ring
+static MISSED: AtomicU64 = AtomicU64::new(0);
+
+/// Enable the events named by `trace=` on the command line
+pub fn init() {
+    let Some(list) = crate::cmdline::value("trace") else { return };
+    for name in list.split(',').filter(|name| !name.is_empty()) {
+        if name == "all" {
+            Event::ALL.iter().for_each(|&event| set_enabled(event, true));
+        } else if let Some(event) = by_name(name) {
+            set_enabled(event, true);
+        } else {
+            crate::serial_println!("[TRACE] Unknown tracepoint '{}' in trace=", name);
+        }
+    }
+}
+
+/// The event called `name`
+pub fn by_name(name: &str) -> Option<Event> {
+    Event::ALL.iter().copied().find(|event| event.name() == name)
+}
+
+/// Whether `event` is recorded
+#[inline]
+pub fn is_enabled(event: Event) -> bool {
+    ENABLED.load(Ordering::Relaxed) & 1 << event as u16 != 0
+}
+
+/// Start or stop recording `event`
+pub fn set_enabled(event: Event, enabled: bool) {
+    let bit = 1 << event as u16;
+    if enabled {
+        ENABLED.fetch_or(bit, Ordering::Relaxed);
+    } else {
+        ENABLED.fetch_and(!bit, Ordering::Relaxed);
+    }
+}
+
+/// Record a hit of `event` at `site` on this CPU; use
+/// [`trace!`](crate::trace!)
+pub fn record(event: Event, site: u64, args: &[u64]) {
+    HITS[event as usize].fetch_add(1, Ordering::Relaxed);
+    let mut record = Record {
+        ns: crate::time::clock::now_ns(),
+        task: crate::sched::current_task().map_or(0, |task| task.id as u32),
+        event: event as u16,
+        cpu: 0,
+        args: [0; 3],
+        site,
+    };
+    let count = args.len().min(record.args.len());
+    record.args[..count].copy_from_slice(&args[..count]);
+
+    // Interrupts off, so a tracepoint in a handler cannot find this CPU's
+    // ring locked by the code it interrupted
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        record.cpu = crate::arch::x86_64::smp::percpu::current_cpu_id() as u16;
+        match RINGS.get().try_lock() {
+            Some(mut ring) => ring.push(record),
+            None => {
+                MISSED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+}
+
+/// Write every CPU's records to the serial port and empty the rings
+///
+/// Returns false if a ring was busy; the rings dumped before it are
+/// emptied.
+pub fn try_dump() -> bool {
+    let mut out = crate::serial::SerialPort::new(crate::serial::SERIAL_PORT);
+    let _ = writeln!(out, "[TRACE] begin");
+    let (mut records, mut overwritten) = (0, 0);
+    for cpu in 0..MAX_CPUS {
+        let Some(mut ring) = RINGS.get_for(cpu).try_lock() else { return false };
+        for record in ring.records() {
+            let _ = write_record(&mut out, record);
+            records += 1;
+        }
+        overwritten += ring.overwritten();
+        ring.written = 0;
+    }
+    let missed = MISSED.swap(0, Ordering::Relaxed);
+    let _ = writeln!(out, "[TRACE] end {} records, {} overwritten, {} missed", records, overwritten, missed);
+    true
+}
+
+fn write_record(out: &mut impl Write, record: &Record) -> fmt::Result {
+    let name = Event::ALL.get(record.event as usize).map_or("unknown", |event| event.name());
+    let [arg0, arg1, arg2] = record.args;
+    writeln!(
+        out,
+        "[TRACE] {} {} {} {} {} {} {} {}",
+        record.cpu,
+        record.ns,
+        record.task,
+        name,
+        arg0 as i64,
+        arg1 as i64,
+        arg2 as i64,
+        crate::ksyms::Symbolized(record.site)
+    )
+}
+
+/// Write `/proc/trace`: each event, whether it is enabled, and its hits
+pub fn write_status(out: &mut impl Write) -> fmt::Result {
+    writeln!(out, "event           enabled       hits")?;
+    for &event in Event::ALL {
+        writeln!(
+            out,
+            "{:<15} {:<7} {:>10}",
+            event.name(),
+            if is_enabled(event) { "yes" } else { "no" },
+            HITS[event as usize].load(Ordering::Relaxed)
+        )?;
+    }
+    Ok(())
+}
+
+crate::kernel_test! {
+    /// Rings keep the latest records in order, and events are looked up
+    /// by name
+    fn trace_ring_and_names() {
+        let mut ring = Ring::<3>::new();
+        for ns in 1..=5 {
+            ring.push(Record { ns, ..Record::EMPTY });
+        }
+        let mut kept = [0; 3];
+        for (slot, record) in kept.iter_mut().zip(ring.records()) {
+            *slot = record.ns;
+        }
+        crate::ktest_assert_eq!(kept, [3, 4, 5], "records kept");
+        crate::ktest_assert_eq!(ring.overwritten(), 2, "overwritten");
+
+        crate::ktest_assert_eq!(by_name("ipc_send"), Some(Event::ipc_send), "lookup by name");
+        crate::ktest_assert_eq!(by_name("ipc"), None, "partial name matched");
+        Ok(())
+    }
+}
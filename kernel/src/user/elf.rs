@@ -4,8 +4,8 @@
 /// It supports ET_EXEC format with PT_LOAD segments and proper memory protection.
 use crate::mm::paging::{PageMapper, PageTableFlags};
 use crate::mm::pmm::PhysicalMemoryManager;
-use crate::mm::{phys_to_virt, PhysAddr};
-use crate::sched::task::{MemoryRegion, MemoryRegionType, Task, USER_LIMIT};
+use crate::mm::{phys_to_virt, PhysAddr, VirtAddr};
+use crate::sched::task::{FileBacking, MemoryRegion, MemoryRegionType, Task, USER_LIMIT};
 use crate::serial_println;
 use core::mem;
 
@@ -37,7 +37,6 @@ const PF_W: u32 = 2; // Write
 const PF_R: u32 = 4; // Read
 
 /// User stack configuration
-const USER_STACK_TOP: usize = 0x0000_7FFF_FFFF_0000;
 const USER_STACK_SIZE: usize = 8192; // 8KB
 
 /// ELF64 Header structure
@@ -101,6 +100,8 @@ pub enum ElfError {
     InvalidProgramHeader,
     /// Memory mapping failed
     MappingFailed,
+    /// Segment requests both write and execute permission (W^X)
+    WriteExecuteSegment,
 }
 
 /// ELF64 Binary Loader
@@ -118,12 +119,13 @@ impl<'a> ElfLoader<'a> {
     /// Load an ELF64 binary and set up memory regions for a task
     ///
     /// # Arguments
-    /// * `elf_data` - Raw ELF binary data
+    /// * `elf_data` - Raw ELF binary data; segments are read from it on first
+    ///   touch, so it must outlive the task
     /// * `task` - Task to load the binary into
     ///
     /// # Returns
     /// Entry point address on success, or ElfError on failure
-    pub fn load_elf(&mut self, elf_data: &[u8], task: &mut Task) -> Result<(u64, u64), ElfError> {
+    pub fn load_elf(&mut self, elf_data: &'static [u8], task: &mut Task) -> Result<(u64, u64), ElfError> {
         serial_println!("[ELF] Loading ELF binary ({} bytes)", elf_data.len());
 
         // 1. Parse and validate ELF header
@@ -141,8 +143,10 @@ impl<'a> ElfLoader<'a> {
         task.clear_memory_regions();
 
         // 5. Map PT_LOAD segments
+        let mut image_end = 0;
         for (i, phdr) in program_headers.iter().enumerate() {
             if phdr.p_type == PT_LOAD {
+                image_end = image_end.max((phdr.p_vaddr + phdr.p_memsz) as usize);
                 serial_println!(
                     "[ELF] Mapping segment {}: vaddr=0x{:x}-0x{:x} flags=0x{:x}",
                     i,
@@ -163,6 +167,9 @@ impl<'a> ElfLoader<'a> {
         // 6. Set up user stack
         let user_stack_top = self.setup_user_stack(task)?;
 
+        // 7. Start an empty brk heap above the image
+        crate::mm::brk::reset(task, image_end);
+
         serial_println!(
             "[ELF] ELF loading completed successfully (entry=0x{:x}, stack_top=0x{:x})",
             header.e_entry,
@@ -268,10 +275,13 @@ impl<'a> ElfLoader<'a> {
         Ok(program_headers)
     }
 
-    /// Map a PT_LOAD segment into memory
+    /// Record a PT_LOAD segment as a file-backed region
+    ///
+    /// No frames are allocated here: pages are populated from `elf_data` by
+    /// the page fault handler on first touch (`mm::demand`).
     fn map_segment(
         &mut self,
-        elf_data: &[u8],
+        elf_data: &'static [u8],
         phdr: &Elf64ProgramHeader,
         task: &mut Task,
     ) -> Result<(), ElfError> {
@@ -290,6 +300,11 @@ impl<'a> ElfLoader<'a> {
             return Err(ElfError::BufferTooSmall);
         }
 
+        // Enforce W^X: refuse segments that are both writable and executable
+        if phdr.p_flags & PF_W != 0 && phdr.p_flags & PF_X != 0 {
+            return Err(ElfError::WriteExecuteSegment);
+        }
+
         // Calculate page-aligned range
         let start_page = vaddr & !0xFFF;
         let end_page = (vaddr + size + 0xFFF) & !0xFFF;
@@ -316,73 +331,19 @@ impl<'a> ElfLoader<'a> {
             MemoryRegionType::Data // Read-only data
         };
 
-        // Map pages and copy data using kernel mapping approach
-        for page_addr in (start_page..end_page).step_by(4096) {
-            let phys_frame = self.pmm.alloc_frame().ok_or(ElfError::OutOfMemory)?;
-
-            // Map page in user space
-            self.mapper
-                .map_page(page_addr, phys_frame, flags, self.pmm)
-                .map_err(|_| ElfError::MappingFailed)?;
-
-            // Create temporary kernel mapping for safe data copying
-            let kernel_vaddr = phys_to_virt(phys_frame);
-
-            // Calculate what portion of this page needs data from ELF
-            let page_offset = if page_addr >= vaddr {
-                0
-            } else {
-                vaddr - page_addr
-            };
-            let page_file_start = if page_addr >= vaddr {
-                file_offset + (page_addr - vaddr)
-            } else {
-                file_offset
-            };
-            let page_file_size = core::cmp::min(
-                4096 - page_offset,
-                if file_size > (page_addr.saturating_sub(vaddr)) {
-                    file_size - (page_addr.saturating_sub(vaddr))
-                } else {
-                    0
-                },
-            );
-
-            // Zero the entire page first
-            unsafe {
-                let page_slice = core::slice::from_raw_parts_mut(kernel_vaddr as *mut u8, 4096);
-                page_slice.fill(0);
-            }
-
-            // Copy ELF data to this page if any
-            if page_file_size > 0 && page_file_start < elf_data.len() {
-                let src_start = page_file_start;
-                let src_end = core::cmp::min(src_start + page_file_size, elf_data.len());
-                let src = &elf_data[src_start..src_end];
-
-                unsafe {
-                    let dst = core::slice::from_raw_parts_mut(
-                        (kernel_vaddr + page_offset) as *mut u8,
-                        src.len(),
-                    );
-                    dst.copy_from_slice(src);
-                }
-            }
-
-            // Flush TLB for this page to ensure visibility
-            unsafe {
-                core::arch::asm!("invlpg [{}]", in(reg) page_addr);
-                // TODO: IPI TLB shootdown for SMP when implementing full page table separation
-            }
-        }
-
         // Add memory region to task
-        let region = MemoryRegion::new(start_page, end_page, flags, region_type);
+        let mut region = MemoryRegion::new(start_page, end_page, flags, region_type);
+        region.backing = Some(FileBacking {
+            image: elf_data,
+            offset: file_offset,
+            vaddr,
+            file_size,
+        });
         task.add_memory_region(region)
             .map_err(|_| ElfError::MappingFailed)?;
 
         serial_println!(
-            "[ELF] Mapped segment: 0x{:x}-0x{:x} ({:?})",
+            "[ELF] Recorded segment: 0x{:x}-0x{:x} ({:?}, demand paged)",
             start_page,
             end_page,
             region_type
@@ -393,7 +354,7 @@ impl<'a> ElfLoader<'a> {
 
     /// Set up user stack with guard pages
     fn setup_user_stack(&mut self, task: &mut Task) -> Result<u64, ElfError> {
-        let stack_top = USER_STACK_TOP;
+        let stack_top = task.user_stack_top;
         let stack_size = USER_STACK_SIZE;
         let stack_bottom = stack_top - stack_size;
         let guard_page = stack_bottom - 4096;
@@ -411,7 +372,7 @@ impl<'a> ElfLoader<'a> {
 
             self.mapper
                 .map_page(
-                    addr,
+                    VirtAddr::new(addr as u64),
                     phys_frame,
                     PageTableFlags::PRESENT
                         | PageTableFlags::WRITABLE
@@ -420,11 +381,12 @@ impl<'a> ElfLoader<'a> {
                     self.pmm,
                 )
                 .map_err(|_| ElfError::MappingFailed)?;
+            task.usage.charge_frames(1);
 
             // Zero the stack page
             let kernel_vaddr = phys_to_virt(phys_frame);
             unsafe {
-                let page_slice = core::slice::from_raw_parts_mut(kernel_vaddr as *mut u8, 4096);
+                let page_slice = core::slice::from_raw_parts_mut(kernel_vaddr.as_mut_ptr::<u8>(), 4096);
                 page_slice.fill(0);
             }
         }
@@ -3,11 +3,13 @@
 /// This module provides functionality for user-mode execution including:
 /// - ELF binary loading and parsing
 /// - Process management
+/// - Starting programs from the initrd
 /// - User-kernel memory management
 pub mod elf;
 pub mod integration_tests;
 pub mod launch;
 pub mod process;
+pub mod spawn;
 
 pub use elf::*;
 pub use integration_tests::*;
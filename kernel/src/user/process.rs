@@ -9,6 +9,7 @@ use crate::sched::context::CpuContext;
 use crate::sched::priority::TaskPriority;
 use crate::sched::task::{MemoryRegion, MemoryRegionType};
 use crate::sync::{SpinLock, SpinLockGuard};
+use crate::time::Instant;
 use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
@@ -40,7 +41,7 @@ pub enum ProcessState {
     /// Process is currently running on the CPU
     Running,
 
-    /// Process is sleeping (waiting for wake_tick)
+    /// Process is sleeping (waiting for wake_at)
     Sleeping,
 
     /// Process is blocked on I/O or IPC
@@ -155,8 +156,8 @@ pub struct Process {
     /// Process priority level
     pub priority: TaskPriority,
 
-    /// Tick at which to wake the process (if sleeping)
-    pub wake_tick: Option<u64>,
+    /// When to wake the process (if sleeping)
+    pub wake_at: Option<Instant>,
 
     /// Block reason (if blocked)
     pub block_reason: Option<BlockReason>,
@@ -200,7 +201,7 @@ impl Process {
             exit_code: None,
             context: CpuContext::new(),
             priority: TaskPriority::Normal,
-            wake_tick: None,
+            wake_at: None,
             block_reason: None,
             page_table: None,
             memory_regions: [const { None }; MAX_MEMORY_REGIONS],
@@ -417,7 +418,7 @@ impl core::fmt::Debug for Process {
             .field("state", &self.state)
             .field("exit_code", &self.exit_code)
             .field("priority", &self.priority)
-            .field("wake_tick", &self.wake_tick)
+            .field("wake_at", &self.wake_at)
             .field("block_reason", &self.block_reason)
             .field("region_count", &self.region_count)
             .field("name", &self.get_name())
@@ -628,6 +629,41 @@ impl ProcessManager {
         slot.take().ok_or(ProcessError::ProcessNotFound)
     }
 
+    /// Add a process whose PID was already chosen
+    ///
+    /// Used for processes started from a task (`user::spawn`), whose PID is
+    /// the task ID, so `SYS_EXIT` and `SYS_WAIT` find them by that ID.
+    ///
+    /// # Returns
+    /// Ok(()) if the process was added, or an error if the table is full
+    pub fn insert_process(pid: ProcessId, parent_pid: Option<ProcessId>, name: &str) -> ProcessResult<()> {
+        let mut slot = Self::alloc_process_slot().ok_or(ProcessError::ProcessTableFull)?;
+        let mut process = Process::new(pid, parent_pid);
+        process.set_name(name);
+        slot.insert(process);
+        Ok(())
+    }
+
+    /// Make `new_parent` the parent of every child of `old_parent`
+    ///
+    /// # Returns
+    /// Number of processes moved
+    pub fn reparent_children(old_parent: ProcessId, new_parent: ProcessId) -> usize {
+        let mut moved = 0;
+        for entry in &PROCESS_TABLE {
+            let _lock_guard = entry.lock.lock();
+
+            let process_ref = unsafe { &mut *entry.process.get() };
+            if let Some(ref mut process) = process_ref {
+                if process.is_child_of(old_parent) {
+                    process.parent_pid = Some(new_parent);
+                    moved += 1;
+                }
+            }
+        }
+        moved
+    }
+
     /// Find the first zombie child of a parent process
     ///
     /// Searches the process table for a zombie process that is a child
@@ -732,8 +768,7 @@ pub fn copy_from_user(dst: &mut [u8], src_ptr: usize, len: usize) -> ProcessResu
 
     // Perform copy with page fault handling (current shared address space)
     unsafe {
-        let src = core::slice::from_raw_parts(src_ptr as *const u8, len);
-        dst[..len].copy_from_slice(src);
+        crate::arch::x86_64::cpu::copy_user_bytes(dst.as_mut_ptr(), src_ptr as *const u8, len);
     }
 
     Ok(())
@@ -763,8 +798,7 @@ pub fn copy_to_user(dst_ptr: usize, src: &[u8]) -> ProcessResult<()> {
 
     // Perform copy with page fault handling (current shared address space)
     unsafe {
-        let dst = core::slice::from_raw_parts_mut(dst_ptr as *mut u8, src.len());
-        dst.copy_from_slice(src);
+        crate::arch::x86_64::cpu::copy_user_bytes(dst_ptr as *mut u8, src.as_ptr(), src.len());
     }
 
     Ok(())
@@ -813,13 +847,14 @@ pub fn sync_process_with_task(
         crate::sched::task::TaskState::Running => ProcessState::Running,
         crate::sched::task::TaskState::Sleeping => ProcessState::Sleeping,
         crate::sched::task::TaskState::Blocked => ProcessState::Blocked,
+        crate::sched::task::TaskState::Exited => ProcessState::Zombie,
     };
 
     // Sync other fields
     if let Some(task) = sched::get_task_mut(task_id) {
         process.context = task.context.clone();
         process.priority = task.priority;
-        process.wake_tick = task.wake_tick;
+        process.wake_at = task.wake_at;
 
         // Sync memory regions if they differ
         if process.region_count != task.region_count {
@@ -874,7 +909,7 @@ pub fn sync_task_with_process(
     // Sync other fields
     task.context = process.context.clone();
     task.priority = process.priority;
-    task.wake_tick = process.wake_tick;
+    task.wake_at = process.wake_at;
 
     // Sync memory regions if they differ
     if task.region_count != process.region_count {
@@ -0,0 +1,219 @@
+//! Starting programs from the initrd
+//!
+//! [`spawn`] starts a program in the initrd (see `fs::initrd`) as a new
+//! process: a task that loads the ELF image on its first run and drops to
+//! user mode. Its PID is its task ID. It inherits the caller's process
+//! group, session, controlling terminal, umask, seccomp filter and CPU
+//! bandwidth group, as a forked child would, and copies of its handles
+//! except those marked `FD_CLOEXEC`, as after an exec. The caller is its
+//! parent.
+//!
+//! The program gets its arguments ([`Args`]) on its stack as on x86_64
+//! Linux: `rsp` points at `argc`, followed by the `argv` pointers, a null
+//! pointer, an empty environment (a null pointer) and an empty auxiliary
+//! vector (`AT_NULL`). The strings themselves are above them.
+//!
+//! The kernel starts `/sbin/init` this way before any other task, so init
+//! gets [`INIT_PID`]; init starts the other services with `SYS_SPAWN`.
+//! Init is supervised by the kernel: when any process exits its children
+//! are reparented to init, and if init itself exits the kernel panics with
+//! its exit status (see `arch::x86_64::syscall`).
+
+use core::fmt;
+use core::ptr::NonNull;
+
+use crate::arch::x86_64::syscall::copy_to_user;
+use crate::mm::allocator::{kfree, kmalloc};
+use crate::mm::with_memory_managers;
+use crate::sched::process_group::Pid;
+use crate::sched::{self, priority::TaskPriority, Task};
+use crate::serial_println;
+use crate::sys::errno::Errno;
+use crate::user::elf::ElfLoader;
+use crate::user::launch;
+use crate::user::process::ProcessManager;
+
+/// PID of init
+pub const INIT_PID: Pid = 1;
+
+/// Where init is in the initrd
+pub const INIT_PATH: &str = "/sbin/init";
+
+/// Most arguments a program can be given
+pub const MAX_ARGS: usize = 32;
+
+/// Most bytes the argument strings can take, terminating NULs included
+pub const ARG_MAX: usize = 1024;
+
+/// Spawn errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// The initrd has no such file
+    NotFound,
+    /// The file is not an ELF image
+    NotExecutable,
+    /// The task or process table is full, or out of memory
+    NoResources,
+    /// More than [`MAX_ARGS`] arguments, or more than [`ARG_MAX`] bytes
+    ArgumentsTooLong,
+}
+
+struct ArgBlock {
+    count: usize,
+    len: usize,
+    /// The strings, each followed by a NUL
+    data: [u8; ARG_MAX],
+}
+
+/// Arguments of a program to spawn
+///
+/// Kept off the kernel stack, as a task carries them until it first runs.
+pub struct Args {
+    block: NonNull<ArgBlock>,
+}
+
+// The block is owned and only reached through `&self` or `&mut self`
+unsafe impl Send for Args {}
+
+impl Args {
+    pub fn new() -> Result<Self, SpawnError> {
+        let block = kmalloc(core::mem::size_of::<ArgBlock>()) as *mut ArgBlock;
+        let block = NonNull::new(block).ok_or(SpawnError::NoResources)?;
+        unsafe {
+            core::ptr::addr_of_mut!((*block.as_ptr()).count).write(0);
+            core::ptr::addr_of_mut!((*block.as_ptr()).len).write(0);
+        }
+        Ok(Self { block })
+    }
+
+    fn block(&self) -> &ArgBlock {
+        unsafe { self.block.as_ref() }
+    }
+
+    /// Append `arg`, which must not contain a NUL
+    pub fn push(&mut self, arg: &[u8]) -> Result<(), SpawnError> {
+        let block = unsafe { self.block.as_mut() };
+        let end = block.len + arg.len() + 1;
+        if block.count == MAX_ARGS || end > ARG_MAX {
+            return Err(SpawnError::ArgumentsTooLong);
+        }
+        block.data[block.len..end - 1].copy_from_slice(arg);
+        block.data[end - 1] = 0;
+        block.len = end;
+        block.count += 1;
+        Ok(())
+    }
+
+    /// Copy the arguments below `stack_top` in the initial stack layout;
+    /// returns the new, 16-byte aligned, stack pointer
+    fn place(&self, stack_top: u64) -> Result<u64, Errno> {
+        let block = self.block();
+        let strings = stack_top as usize - block.len;
+        copy_to_user(strings, &block.data[..block.len])?;
+
+        // argc, the argv pointers and their null, envp's null, AT_NULL
+        let mut words = [0usize; MAX_ARGS + 5];
+        let count = block.count + 5;
+        words[0] = block.count;
+        let mut offset = 0;
+        for word in &mut words[1..=block.count] {
+            *word = strings + offset;
+            offset += block.data[offset..].iter().position(|&b| b == 0).unwrap_or(0) + 1;
+        }
+        let sp = (strings - count * 8) & !15;
+        for (i, word) in words[..count].iter().enumerate() {
+            copy_to_user(sp + i * 8, &word.to_ne_bytes())?;
+        }
+        Ok(sp as u64)
+    }
+}
+
+impl Drop for Args {
+    fn drop(&mut self) {
+        kfree(self.block.as_ptr() as *mut u8, core::mem::size_of::<ArgBlock>());
+    }
+}
+
+impl fmt::Debug for Args {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let block = self.block();
+        let strings = block.data[..block.len].split(|&b| b == 0).take(block.count);
+        f.debug_list().entries(strings.map(|arg| core::str::from_utf8(arg).unwrap_or("?"))).finish()
+    }
+}
+
+/// Start the program at `path` in the initrd with `args` as a child of the
+/// current task; returns its PID
+pub fn spawn(path: &str, args: Args) -> Result<Pid, SpawnError> {
+    let entry = crate::fs::initrd::find(path).ok_or(SpawnError::NotFound)?;
+    let name = entry.path.rsplit('/').next().unwrap_or(entry.path);
+    let parent = sched::get_current_task_info().and_then(|(id, _)| sched::get_task_by_id(id));
+    start(name, entry.data, parent, args)
+}
+
+/// Start `image` with `args` as a new process named `name`, with `parent`
+/// as its parent (None for init); returns its PID
+pub fn start(name: &'static str, image: &'static [u8], parent: Option<&Task>, args: Args) -> Result<Pid, SpawnError> {
+    if !image.starts_with(b"\x7FELF") {
+        return Err(SpawnError::NotExecutable);
+    }
+
+    let parent_id = parent.map(|parent| parent.id);
+    let inherited = parent.map(|parent| {
+        (parent.pid, parent.pgid, parent.sid, parent.tty, parent.umask, parent.seccomp, parent.cpu_group)
+    });
+    let priority = parent.map_or(TaskPriority::High, |parent| parent.priority);
+    let mut registered = Ok(());
+    let mut args = Some(args);
+    let setup = |task: &mut Task| {
+        if let Some((ppid, pgid, sid, tty, umask, seccomp, cpu_group)) = inherited {
+            task.ppid = ppid;
+            task.pgid = pgid;
+            task.sid = sid;
+            task.tty = tty;
+            task.umask = umask;
+            task.seccomp = seccomp;
+            task.cpu_group = cpu_group;
+        }
+        if let Some(parent_id) = parent_id {
+            crate::sys::handle::inherit(parent_id, task.pid, true);
+        }
+        task.image = Some(image);
+        task.args = args.take();
+        // Before the task can run, so its exit always finds the entry
+        let ppid = inherited.map(|(ppid, ..)| ppid);
+        registered = ProcessManager::insert_process(task.pid, ppid, name);
+    };
+    let pid = sched::spawn_task_with(name, image_entry, priority, setup).map_err(|_| SpawnError::NoResources)?;
+    if registered.is_err() {
+        // The task runs anyway; only SYS_WAIT will not see it exit
+        serial_println!("[SPAWN] {} (pid {}): process table full", name, pid);
+    }
+    serial_println!("[SPAWN] Started {} as pid {}", name, pid);
+    Ok(pid)
+}
+
+/// Kernel entry of a spawned process: load its image and drop to user mode
+fn image_entry() -> ! {
+    let task = sched::get_current_task_info().and_then(|(id, _)| sched::get_task_mut(id));
+    let Some(task) = task else { panic!("[SPAWN] Spawned task has no task entry") };
+    let Some(image) = task.image.take() else { panic!("[SPAWN] Task started without a program image") };
+    let args = task.args.take();
+
+    let loaded = with_memory_managers(|pmm, mapper| Ok(ElfLoader::new(pmm, mapper).load_elf(image, &mut *task)));
+    match loaded {
+        Ok(Ok((entry, stack_top))) => {
+            let sp = match args {
+                Some(args) => args.place(stack_top),
+                None => Ok(stack_top),
+            };
+            match sp {
+                Ok(sp) => launch(entry, sp),
+                Err(e) => serial_println!("[SPAWN] {}: cannot place arguments: {:?}", task.name, e),
+            }
+        }
+        Ok(Err(e)) => serial_println!("[SPAWN] {}: cannot load image: {:?}", task.name, e),
+        Err(e) => serial_println!("[SPAWN] {}: cannot load image: {}", task.name, e),
+    }
+    crate::arch::x86_64::syscall::exit_current(127)
+}
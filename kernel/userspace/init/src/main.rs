@@ -3,6 +3,9 @@
 
 use core::arch::asm;
 
+mod protocol;
+mod service;
+
 // Syscall numbers (legacy int 0x80 interface)
 const SYS_WRITE: usize = 0;
 const SYS_EXIT: usize = 1;
@@ -13,7 +16,12 @@ const SYS_GETPID: usize = 5;
 const SYS_YIELD: usize = 6;
 const SYS_FORK: usize = 7;
 const SYS_WAIT: usize = 8;
-const SYS_EXEC: usize = 9;
+const SYS_KILL: usize = 15;
+const SYS_EVENT_SUBSCRIBE: usize = 31;
+const SYS_PORT_CREATE: usize = 37;
+const SYS_CAP_DROP: usize = 39;
+const SYS_SPAWN: usize = 50;
+const SYS_POWEROFF: usize = 72;
 
 /// Raw syscall function using fast syscall instruction
 #[inline(always)]
@@ -77,6 +85,43 @@ fn sys_ipc_recv(port_id: usize, buf: &mut [u8]) -> isize {
     unsafe { syscall(SYS_IPC_RECV, port_id, buf.as_mut_ptr() as usize, buf.len()) }
 }
 
+/// Collect an exited child (0: any); returns `(pid << 8) | exit code`,
+/// or a negative error if none has exited
+fn sys_wait(pid: usize) -> isize {
+    unsafe { syscall(SYS_WAIT, pid, 0, 0) }
+}
+
+/// Start the program at `path` (NUL-terminated) in the initrd as a child;
+/// returns its pid or a negative error
+fn sys_spawn(path: &[u8]) -> isize {
+    unsafe { syscall(SYS_SPAWN, path.as_ptr() as usize, 0, 0) }
+}
+
+/// Send a signal to a process
+fn sys_kill(pid: usize, signal: usize) -> isize {
+    unsafe { syscall(SYS_KILL, pid, signal, 0) }
+}
+
+/// Remove a capability from this task's table
+fn sys_cap_drop(handle: usize) -> isize {
+    unsafe { syscall(SYS_CAP_DROP, handle, 0, 0) }
+}
+
+/// Create a port; returns a capability for it or a negative error
+fn sys_port_create() -> isize {
+    unsafe { syscall(SYS_PORT_CREATE, 0, 0, 0) }
+}
+
+/// Have the kernel events in `mask` sent to the port of capability `cap`
+fn sys_event_subscribe(cap: usize, mask: u32) -> isize {
+    unsafe { syscall(SYS_EVENT_SUBSCRIBE, cap, mask as usize, 0) }
+}
+
+/// Turn the machine off; returns only if that failed
+fn sys_poweroff() -> isize {
+    unsafe { syscall(SYS_POWEROFF, 0, 0, 0) }
+}
+
 /// Exit current task
 fn sys_exit(code: usize) -> ! {
     unsafe {
@@ -199,11 +244,12 @@ fn test_memory_protection() {
 
 /// Entry point for init process
 ///
+/// Runs the integration tests, then starts the boot-time services and
+/// supervises them (see `service`).
+///
 /// TODO: Future enhancements for Phase 6.6:
 /// - Set up environment variables (LANG=C.UTF-8, PATH=/bin, etc.)
 /// - Create /dev/ptmx and /dev/pts/ if not already created by kernel
-/// - Spawn mello-term as the primary user interface
-/// - Handle process reaping and system shutdown
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
     // Required message for automated testing
@@ -229,29 +275,8 @@ pub extern "C" fn _start() -> ! {
     sys_write("Init Process Tests Completed\n");
     sys_write("========================================\n");
 
-    // Legacy IPC tests (if still supported)
-    let ping_msg = b"ping";
-    let send_result = sys_ipc_send(2, ping_msg);
-    if send_result >= 0 {
-        sys_write("Legacy: Sent 'ping' to port 2\n");
-    }
-
-    // Sleep for a bit to allow other tests to run
-    sys_write("Init process entering monitoring loop...\n");
-    sys_sleep(100);
-
-    // Enter monitoring loop
-    let mut counter = 0u32;
-    loop {
-        sys_write("Init process monitoring system...\n");
-        sys_sleep(1000);
-        counter = counter.wrapping_add(1);
-
-        // Periodically yield to other processes
-        if counter % 5 == 0 {
-            sys_yield();
-        }
-    }
+    sys_write("Init process starting services...\n");
+    service::run()
 }
 
 // Panic handler for userspace
@@ -0,0 +1,100 @@
+//! Service manager protocol
+//!
+//! Clients such as `mctl` send requests to init on `SERVICE_PORT`. A client
+//! that wants an answer grants init a send capability for a reply port with
+//! the request; init sends exactly one reply there and drops the capability.
+//!
+//! Request: `[op, name_len, name...]`. `OP_LIST` has no name.
+//!
+//! Reply: `[status, count]` followed by `count` service records
+//! `[state, restarts, pid (u32 LE), name_len, name...]`. Every operation
+//! answers with the records of the services it touched; `OP_LIST` with all
+//! of them.
+//!
+//! `mctl` in mellobox keeps its own copy of these definitions.
+
+/// System port init receives requests on
+pub const SERVICE_PORT: usize = 10;
+
+// Operations
+pub const OP_START: u8 = 1;
+pub const OP_STOP: u8 = 2;
+pub const OP_RESTART: u8 = 3;
+pub const OP_STATUS: u8 = 4;
+pub const OP_LIST: u8 = 5;
+
+// Reply status
+pub const STATUS_OK: u8 = 0;
+pub const STATUS_UNKNOWN_SERVICE: u8 = 1;
+pub const STATUS_BAD_REQUEST: u8 = 2;
+/// A dependency of the service could not be started
+pub const STATUS_DEPENDENCY: u8 = 3;
+/// fork failed
+pub const STATUS_SPAWN_FAILED: u8 = 4;
+
+// Service states in status records
+pub const STATE_STOPPED: u8 = 0;
+pub const STATE_RUNNING: u8 = 1;
+/// Exited; restarts when its backoff delay is over
+pub const STATE_BACKOFF: u8 = 2;
+/// Crashed too often in a row; stays down until started again
+pub const STATE_FAILED: u8 = 3;
+
+/// Longest service name
+pub const MAX_NAME: usize = 32;
+
+/// Largest request
+pub const MAX_REQUEST: usize = 2 + MAX_NAME;
+
+/// Largest reply
+pub const MAX_REPLY: usize = 512;
+
+/// Decode a request into its operation and service name
+pub fn parse_request(msg: &[u8]) -> Option<(u8, &str)> {
+    let (&op, rest) = msg.split_first()?;
+    if op == OP_LIST {
+        return Some((op, ""));
+    }
+    let (&len, rest) = rest.split_first()?;
+    let name = rest.get(..len as usize)?;
+    core::str::from_utf8(name).ok().map(|name| (op, name))
+}
+
+/// Reply being built
+pub struct Reply {
+    buf: [u8; MAX_REPLY],
+    len: usize,
+}
+
+impl Reply {
+    pub fn new(status: u8) -> Self {
+        let mut buf = [0; MAX_REPLY];
+        buf[0] = status;
+        Self { buf, len: 2 }
+    }
+
+    pub fn set_status(&mut self, status: u8) {
+        self.buf[0] = status;
+    }
+
+    /// Append a service record; dropped if the reply is full
+    pub fn push(&mut self, name: &str, state: u8, restarts: u8, pid: u32) {
+        let name = &name.as_bytes()[..name.len().min(MAX_NAME)];
+        let record = 7 + name.len();
+        if self.len + record > MAX_REPLY {
+            return;
+        }
+        let out = &mut self.buf[self.len..self.len + record];
+        out[0] = state;
+        out[1] = restarts;
+        out[2..6].copy_from_slice(&pid.to_le_bytes());
+        out[6] = name.len() as u8;
+        out[7..].copy_from_slice(name);
+        self.len += record;
+        self.buf[1] += 1;
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
@@ -0,0 +1,395 @@
+//! Service supervision
+//!
+//! init starts the services in `SERVICES` at boot, dependencies first, and
+//! then keeps them running:
+//!
+//! - A service that exits is restarted according to its `Restart` policy,
+//!   after a backoff delay that doubles with each crash in a row (from
+//!   `BACKOFF_MIN` up to `BACKOFF_MAX`). A service that stays up for
+//!   `STABLE_TICKS` has its crash count reset.
+//! - After `MAX_CRASHES` crashes in a row the service is marked failed and
+//!   left down until it is started again by hand.
+//! - Requests on `SERVICE_PORT` (see `protocol`) start, stop, restart and
+//!   report services. Starting a service starts its dependencies first;
+//!   stopping one stops the services that depend on it first.
+//! - When the power button is pressed (a kernel event on a port init
+//!   subscribes at start), every service is stopped and given up to
+//!   `SHUTDOWN_TICKS` to exit before init powers the machine off.
+//!
+//! There is no clock syscall and `SYS_WAIT` does not block, so init works
+//! in rounds of `ROUND_TICKS`: handle queued requests and events, reap
+//! exited children, restart services whose backoff is over, sleep. Delays
+//! are counted in rounds and are therefore lower bounds.
+
+use crate::protocol::*;
+use crate::{
+    sys_cap_drop, sys_event_subscribe, sys_ipc_recv, sys_ipc_send, sys_kill, sys_port_create, sys_poweroff,
+    sys_sleep, sys_spawn, sys_wait, sys_write,
+};
+
+/// Length of a supervision round in ticks (20 per second)
+const ROUND_TICKS: u64 = 2;
+
+/// Delay before the first restart after a crash
+const BACKOFF_MIN: u64 = 20;
+
+/// Longest delay between restarts
+const BACKOFF_MAX: u64 = 600;
+
+/// Uptime after which a service's crash count is reset
+const STABLE_TICKS: u64 = 200;
+
+/// Crashes in a row before a service is given up on
+const MAX_CRASHES: u32 = 5;
+
+/// Longest wait for the services to exit before powering off
+const SHUTDOWN_TICKS: u64 = 60;
+
+/// Receive without blocking (`IPC_NONBLOCK` in the port argument)
+const IPC_NONBLOCK: usize = 1 << 31;
+
+/// Bit position of a capability handle in `SYS_IPC_RECV`'s result
+const IPC_CAP_SHIFT: u32 = 32;
+
+const SIGTERM: usize = 15;
+
+/// Kernel event kind of a power button press; `1 << kind` subscribes to it
+const EVENT_POWER_BUTTON: u32 = 3;
+
+/// Size of a kernel event message header: kind, length, time
+const EVENT_HEADER_LEN: usize = 16;
+
+/// When a service is restarted after it exits
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Restart {
+    /// When it exits with a nonzero code
+    OnCrash,
+    /// Whenever it exits
+    Always,
+}
+
+/// Entry of the boot-time service list
+struct ServiceDef {
+    name: &'static str,
+    /// Program in the initrd, NUL-terminated
+    path: &'static [u8],
+    /// Services that must be running first
+    deps: &'static [&'static str],
+    restart: Restart,
+}
+
+const SERVICES: &[ServiceDef] = &[
+    ServiceDef { name: "term", path: b"/bin/mello-term\0", deps: &[], restart: Restart::Always },
+    ServiceDef { name: "sh", path: b"/bin/mello-sh\0", deps: &["term"], restart: Restart::OnCrash },
+];
+
+const MAX_SERVICES: usize = SERVICES.len();
+
+/// Runtime state of a service
+#[derive(Clone, Copy)]
+struct Service {
+    state: u8,
+    /// Process id while running, 0 otherwise
+    pid: usize,
+    /// Crashes since the service last stayed up for `STABLE_TICKS`
+    crashes: u32,
+    /// Automatic restarts so far
+    restarts: u8,
+    started_at: u64,
+    restart_at: u64,
+}
+
+struct Supervisor {
+    services: [Service; MAX_SERVICES],
+    /// Ticks since init started supervising
+    now: u64,
+    /// Capability of the port kernel events arrive on, if subscribed
+    events: Option<usize>,
+}
+
+/// Write `parts` and a newline to the console as one line
+fn log(parts: &[&str]) {
+    sys_write("init: ");
+    for part in parts {
+        sys_write(part);
+    }
+    sys_write("\n");
+}
+
+/// Delay before restart number `crashes` in a row
+fn backoff(crashes: u32) -> u64 {
+    BACKOFF_MIN
+        .checked_shl(crashes.saturating_sub(1))
+        .map_or(BACKOFF_MAX, |delay| delay.min(BACKOFF_MAX))
+}
+
+impl Supervisor {
+    fn new() -> Self {
+        let service = Service { state: STATE_STOPPED, pid: 0, crashes: 0, restarts: 0, started_at: 0, restart_at: 0 };
+        Self { services: [service; MAX_SERVICES], now: 0, events: None }
+    }
+
+    fn index(name: &str) -> Option<usize> {
+        SERVICES.iter().position(|def| def.name == name)
+    }
+
+    /// Start service `i` and, first, its dependencies
+    ///
+    /// `depth` guards against dependency cycles.
+    fn start(&mut self, i: usize, depth: usize) -> u8 {
+        if self.services[i].state == STATE_RUNNING {
+            return STATUS_OK;
+        }
+        if depth > MAX_SERVICES {
+            log(&[SERVICES[i].name, ": dependency cycle"]);
+            return STATUS_DEPENDENCY;
+        }
+        for dep in SERVICES[i].deps {
+            let ok = Self::index(dep).map_or(false, |dep| self.start(dep, depth + 1) == STATUS_OK);
+            if !ok {
+                log(&[SERVICES[i].name, ": dependency ", dep, " not started"]);
+                return STATUS_DEPENDENCY;
+            }
+        }
+
+        let pid = sys_spawn(SERVICES[i].path);
+        if pid < 0 {
+            log(&[SERVICES[i].name, ": spawn failed"]);
+            return STATUS_SPAWN_FAILED;
+        }
+        let service = &mut self.services[i];
+        service.state = STATE_RUNNING;
+        service.pid = pid as usize;
+        service.started_at = self.now;
+        log(&["started ", SERVICES[i].name]);
+        STATUS_OK
+    }
+
+    /// Stop service `i` alone; it is not restarted
+    fn halt(&mut self, i: usize) {
+        let service = &mut self.services[i];
+        if service.pid != 0 {
+            sys_kill(service.pid, SIGTERM);
+            log(&["stopped ", SERVICES[i].name]);
+        }
+        service.pid = 0;
+        service.crashes = 0;
+        service.state = STATE_STOPPED;
+    }
+
+    /// Stop service `i` and, first, the services that depend on it
+    fn stop(&mut self, i: usize) {
+        for j in 0..MAX_SERVICES {
+            if SERVICES[j].deps.contains(&SERVICES[i].name) && self.services[j].state != STATE_STOPPED {
+                self.stop(j);
+            }
+        }
+        self.halt(i);
+    }
+
+    /// Account for service `i` exiting with `code`
+    fn exited(&mut self, i: usize, code: usize) {
+        let now = self.now;
+        let def = &SERVICES[i];
+        let service = &mut self.services[i];
+        service.pid = 0;
+
+        let restart = match def.restart {
+            Restart::Always => true,
+            Restart::OnCrash => code != 0,
+        };
+        if !restart {
+            service.state = STATE_STOPPED;
+            log(&[def.name, " exited"]);
+            return;
+        }
+
+        if now - service.started_at >= STABLE_TICKS {
+            service.crashes = 0;
+        }
+        service.crashes += 1;
+        if service.crashes > MAX_CRASHES {
+            service.state = STATE_FAILED;
+            log(&[def.name, " keeps crashing, giving up"]);
+        } else {
+            service.state = STATE_BACKOFF;
+            service.restart_at = now + backoff(service.crashes);
+            log(&[def.name, " exited, restarting after backoff"]);
+        }
+    }
+
+    /// Collect exited children
+    fn reap(&mut self) {
+        loop {
+            let status = sys_wait(0);
+            if status <= 0 {
+                break;
+            }
+            // (pid << 8) | exit code
+            let pid = status as usize >> 8;
+            let code = status as usize & 0xff;
+            // Orphans the kernel reparented to init are reaped and ignored
+            if let Some(i) = self.services.iter().position(|service| service.pid == pid) {
+                self.exited(i, code);
+            }
+        }
+    }
+
+    /// Restart services whose backoff is over
+    fn restart_due(&mut self) {
+        for i in 0..MAX_SERVICES {
+            let service = self.services[i];
+            if service.state != STATE_BACKOFF || service.restart_at > self.now {
+                continue;
+            }
+            self.services[i].restarts = service.restarts.saturating_add(1);
+            if self.start(i, 0) != STATUS_OK {
+                // Try again after the next delay
+                self.services[i].crashes += 1;
+                self.services[i].restart_at = self.now + backoff(self.services[i].crashes);
+            }
+        }
+    }
+
+    fn push_record(&self, reply: &mut Reply, i: usize) {
+        let service = &self.services[i];
+        reply.push(SERVICES[i].name, service.state, service.restarts, service.pid as u32);
+    }
+
+    /// Carry out a request
+    fn handle(&mut self, request: &[u8], reply: &mut Reply) {
+        let Some((op, name)) = parse_request(request) else {
+            reply.set_status(STATUS_BAD_REQUEST);
+            return;
+        };
+        if op == OP_LIST {
+            for i in 0..MAX_SERVICES {
+                self.push_record(reply, i);
+            }
+            return;
+        }
+        let Some(i) = Self::index(name) else {
+            reply.set_status(STATUS_UNKNOWN_SERVICE);
+            return;
+        };
+
+        let status = match op {
+            OP_START => {
+                self.services[i].crashes = 0;
+                self.start(i, 0)
+            }
+            OP_STOP => {
+                self.stop(i);
+                STATUS_OK
+            }
+            OP_RESTART => {
+                self.halt(i);
+                self.start(i, 0)
+            }
+            OP_STATUS => STATUS_OK,
+            _ => STATUS_BAD_REQUEST,
+        };
+        reply.set_status(status);
+        self.push_record(reply, i);
+    }
+
+    /// Subscribe to power button events
+    fn subscribe(&mut self) {
+        let cap = sys_port_create();
+        if cap < 0 {
+            log(&["no event port, power button ignored"]);
+            return;
+        }
+        if sys_event_subscribe(cap as usize, 1 << EVENT_POWER_BUTTON) < 0 {
+            log(&["event subscription failed, power button ignored"]);
+            sys_cap_drop(cap as usize);
+            return;
+        }
+        self.events = Some(cap as usize);
+    }
+
+    /// Take the queued kernel events; true if the power button was pressed
+    fn power_button(&mut self) -> bool {
+        let Some(cap) = self.events else { return false };
+        let mut message = [0u8; EVENT_HEADER_LEN];
+        let mut pressed = false;
+        while sys_ipc_recv(cap | IPC_NONBLOCK, &mut message) > 0 {
+            pressed |= u32::from_le_bytes([message[0], message[1], message[2], message[3]]) == EVENT_POWER_BUTTON;
+        }
+        pressed
+    }
+
+    /// Stop every service, wait for them to exit and power off
+    ///
+    /// If the machine stays on, the services are started again.
+    fn shutdown(&mut self) {
+        log(&["power button pressed, shutting down"]);
+        let mut running = [0usize; MAX_SERVICES];
+        for i in 0..MAX_SERVICES {
+            running[i] = self.services[i].pid;
+            if self.services[i].state != STATE_STOPPED {
+                self.stop(i);
+            }
+        }
+
+        let mut waited = 0;
+        while waited < SHUTDOWN_TICKS && running.iter().any(|&pid| pid != 0) {
+            sys_sleep(ROUND_TICKS as usize);
+            waited += ROUND_TICKS;
+            loop {
+                let status = sys_wait(0);
+                if status <= 0 {
+                    break;
+                }
+                let pid = status as usize >> 8;
+                running.iter_mut().filter(|running| **running == pid).for_each(|running| *running = 0);
+            }
+        }
+
+        sys_poweroff();
+        log(&["power off failed, restarting services"]);
+        for i in 0..MAX_SERVICES {
+            self.start(i, 0);
+        }
+    }
+
+    /// Handle every queued request
+    fn serve(&mut self) {
+        let mut request = [0u8; MAX_REQUEST];
+        loop {
+            let result = sys_ipc_recv(SERVICE_PORT | IPC_NONBLOCK, &mut request);
+            if result <= 0 {
+                break;
+            }
+            let len = (result as usize & 0xffff_ffff).min(MAX_REQUEST);
+            let reply_cap = result as usize >> IPC_CAP_SHIFT;
+
+            let mut reply = Reply::new(STATUS_OK);
+            self.handle(&request[..len], &mut reply);
+            if reply_cap != 0 {
+                sys_ipc_send(reply_cap - 1, reply.as_bytes());
+                sys_cap_drop(reply_cap - 1);
+            }
+        }
+    }
+}
+
+/// Start the boot-time services and supervise them forever
+pub fn run() -> ! {
+    let mut supervisor = Supervisor::new();
+    supervisor.subscribe();
+    for i in 0..MAX_SERVICES {
+        supervisor.start(i, 0);
+    }
+
+    loop {
+        supervisor.serve();
+        if supervisor.power_button() {
+            supervisor.shutdown();
+        }
+        supervisor.reap();
+        supervisor.restart_due();
+        sys_sleep(ROUND_TICKS as usize);
+        supervisor.now += ROUND_TICKS;
+    }
+}
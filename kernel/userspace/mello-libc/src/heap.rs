@@ -0,0 +1,120 @@
+//! Global allocator
+//!
+//! Small blocks come from power-of-two size classes (16 bytes to 32 KiB),
+//! each with a free list, carved out of an arena grown with `brk` in 64 KiB
+//! steps. Freed small blocks go back on their class's list; the arena never
+//! shrinks. Larger blocks get their own anonymous mapping, which is
+//! unmapped when the block is freed; those can be at most page-aligned.
+
+use crate::mem::{self, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use spin::Mutex;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Smallest class: room for the free-list link
+const MIN_CLASS_SHIFT: u32 = 4;
+
+/// Largest class, 32 KiB
+const MAX_CLASS_SHIFT: u32 = 15;
+
+const CLASSES: usize = (MAX_CLASS_SHIFT - MIN_CLASS_SHIFT + 1) as usize;
+
+/// Arena growth step
+const ARENA_STEP: usize = 64 * 1024;
+
+/// Block on a free list
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+struct Heap {
+    free: [*mut FreeBlock; CLASSES],
+    /// Unused part of the arena: `next..end`
+    next: usize,
+    end: usize,
+}
+
+// The raw pointers are only touched under the lock
+unsafe impl Send for Heap {}
+
+/// Size class for `layout`, or None if it needs its own mapping
+///
+/// Blocks of a class are aligned to their size, so the class also covers
+/// the alignment.
+fn class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(1 << MIN_CLASS_SHIFT);
+    let shift = size.next_power_of_two().trailing_zeros();
+    (shift <= MAX_CLASS_SHIFT).then(|| (shift - MIN_CLASS_SHIFT) as usize)
+}
+
+impl Heap {
+    const fn new() -> Self {
+        Heap { free: [ptr::null_mut(); CLASSES], next: 0, end: 0 }
+    }
+
+    /// Carve a block of class `class` out of the arena, growing it if needed
+    fn carve(&mut self, class: usize) -> *mut u8 {
+        let size = 1usize << (class as u32 + MIN_CLASS_SHIFT);
+        if self.end == 0 {
+            self.next = mem::brk(0);
+            self.end = self.next;
+        }
+        let start = self.next.next_multiple_of(size);
+        if start + size > self.end {
+            let want = (start + size).next_multiple_of(ARENA_STEP);
+            if mem::brk(want) < want {
+                return ptr::null_mut();
+            }
+            self.end = want;
+        }
+        self.next = start + size;
+        start as *mut u8
+    }
+
+    fn alloc(&mut self, class: usize) -> *mut u8 {
+        let block = self.free[class];
+        if block.is_null() {
+            return self.carve(class);
+        }
+        self.free[class] = unsafe { (*block).next };
+        block as *mut u8
+    }
+
+    fn dealloc(&mut self, block: *mut u8, class: usize) {
+        let block = block as *mut FreeBlock;
+        unsafe { (*block).next = self.free[class] };
+        self.free[class] = block;
+    }
+}
+
+struct Allocator(Mutex<Heap>);
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(class) = class(layout) {
+            return self.0.lock().alloc(class);
+        }
+        if layout.align() > PAGE_SIZE {
+            return ptr::null_mut();
+        }
+        let len = layout.size().next_multiple_of(PAGE_SIZE);
+        match mem::mmap(0, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS) {
+            Ok(addr) => addr as *mut u8,
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, block: *mut u8, layout: Layout) {
+        match class(layout) {
+            Some(class) => self.0.lock().dealloc(block, class),
+            None => {
+                let _ = mem::munmap(block as usize, layout.size().next_multiple_of(PAGE_SIZE));
+            }
+        }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator(Mutex::new(Heap::new()));
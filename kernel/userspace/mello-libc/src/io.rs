@@ -0,0 +1,304 @@
+//! File descriptors, timers and events
+//!
+//! There is no writable filesystem yet: descriptors name the console, PTYs,
+//! pipes, devices (`/dev/fb0`, `/dev/input/eventN`), and read-only files of the
+//! initrd and `/proc`. Descriptors 0, 1 and 2 are the standard streams.
+//! A descriptor is a handle in the process's handle table, which also holds
+//! ports, shared memory, timers and events, so [`close`], [`dup`], [`dup2`]
+//! and [`poll`] work on all of them.
+
+use crate::errno::{Errno, Result};
+use crate::syscall::*;
+use core::ffi::CStr;
+use core::fmt;
+
+pub const STDIN: i32 = 0;
+pub const STDOUT: i32 = 1;
+pub const STDERR: i32 = 2;
+
+// Open and pipe flags
+pub const O_RDONLY: i32 = 0;
+pub const O_WRONLY: i32 = 1;
+pub const O_RDWR: i32 = 2;
+pub const O_CREAT: i32 = 0x40;
+pub const O_APPEND: i32 = 0x400;
+pub const O_NONBLOCK: i32 = 0x800;
+pub const O_CLOEXEC: i32 = 0x80000;
+
+// fcntl commands and descriptor flags
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const F_GETFL: usize = 3;
+pub const F_SETFL: usize = 4;
+pub const FD_CLOEXEC: usize = 1;
+
+// Poll events
+pub const POLLIN: u16 = 0x1;
+pub const POLLOUT: u16 = 0x4;
+pub const POLLERR: u16 = 0x8;
+pub const POLLHUP: u16 = 0x10;
+pub const POLLNVAL: u16 = 0x20;
+
+/// ioctl: get the screen mode of `/dev/fb0` into an [`FbMode`]
+pub const FBIOGET_MODE: usize = 0x4600;
+
+/// ioctl: grab an input device (nonzero argument) or release it
+pub const EVIOCGRAB: usize = 0x4004_4590;
+
+/// ioctl: the name of an input device, NUL-terminated, into a `len`-byte
+/// buffer
+pub const fn eviocgname(len: usize) -> usize {
+    0x8000_4506 | (len & 0x3FFF) << 16
+}
+
+/// Flag that used to mark a port handle in [`PollFd::fd`]; port handles
+/// are polled like any other now, and the kernel ignores it
+pub const POLL_PORT: i32 = 1 << 30;
+
+/// Most entries in one [`poll`]
+pub const MAX_POLL_FDS: usize = 32;
+
+/// Flag that used to mark a port handle as the `sendfile` destination;
+/// the kernel ignores it
+pub const SENDFILE_PORT: usize = 1 << 30;
+
+/// One entry of a [`poll`] array
+///
+/// Entries with a negative `fd` are skipped. `POLLHUP`, `POLLERR` and
+/// `POLLNVAL` are reported whether asked for or not.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PollFd {
+    pub fd: i32,
+    /// Events to wait for
+    pub events: u16,
+    /// Events that are ready, filled in by the kernel
+    pub revents: u16,
+}
+
+mello_abi::check_layout!(PollFd, mello_abi::PollFd { fd, events, revents });
+
+/// Screen mode of `/dev/fb0`, from `FBIOGET_MODE`
+///
+/// Map `size` bytes of the device with [`mmap_fd`](crate::mem::mmap_fd);
+/// a line is `pitch` bytes, and at 32 `bpp` a pixel is `0x00RRGGBB`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FbMode {
+    pub width: u32,
+    pub height: u32,
+    pub pitch: u32,
+    pub bpp: u32,
+    pub size: u64,
+}
+
+mello_abi::check_layout!(FbMode, mello_abi::FbMode { width, height, pitch, bpp, size });
+
+// Input event types
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+
+// `EV_SYN` codes
+pub const SYN_REPORT: u16 = 0;
+pub const SYN_DROPPED: u16 = 3;
+
+// `EV_REL` codes
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_WHEEL: u16 = 0x08;
+
+// `EV_KEY` codes of mouse buttons
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+
+/// One event from `/dev/input/eventN`, read with [`read_input`]
+///
+/// The events of one hardware report end with `EV_SYN`/`SYN_REPORT`. A
+/// reader that fell behind gets `SYN_DROPPED` and continues with the
+/// newest events. `REL_Y` is positive down the screen, `REL_WHEEL`
+/// positive away from the user, and `EV_KEY` values are 1 for pressed, 0
+/// for released.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Microseconds since boot
+    pub time_us: u64,
+    /// `EV_*`
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+mello_abi::check_layout!(InputEvent, mello_abi::InputEvent { time_us, kind, code, value });
+
+/// Read input events from `fd` into `events`, waiting for one unless `fd`
+/// is non-blocking; returns the number read
+pub fn read_input(fd: i32, events: &mut [InputEvent]) -> Result<usize> {
+    let len = core::mem::size_of_val(events);
+    let read = Errno::check(unsafe { syscall3(SYS_READ, fd as usize, events.as_mut_ptr() as usize, len) })?;
+    Ok(read / core::mem::size_of::<InputEvent>())
+}
+
+/// Make `fd` the only reader of its input device, or stop being it
+pub fn input_grab(fd: i32, grab: bool) -> Result<()> {
+    Errno::check(unsafe { syscall3(SYS_IOCTL, fd as usize, EVIOCGRAB, grab as usize) }).map(|_| ())
+}
+
+/// Read from `fd` into `buf`; returns the bytes read, 0 at end of file
+pub fn read(fd: i32, buf: &mut [u8]) -> Result<usize> {
+    Errno::check(unsafe { syscall3(SYS_READ, fd as usize, buf.as_mut_ptr() as usize, buf.len()) })
+}
+
+/// Write `buf` to `fd`; returns the bytes written
+pub fn write(fd: i32, buf: &[u8]) -> Result<usize> {
+    Errno::check(unsafe { syscall3(SYS_WRITE, fd as usize, buf.as_ptr() as usize, buf.len()) })
+}
+
+/// Write all of `buf` to `fd`, retrying short writes
+pub fn write_all(fd: i32, mut buf: &[u8]) -> Result<()> {
+    while !buf.is_empty() {
+        match write(fd, buf)? {
+            0 => return Err(Errno::EPIPE),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Open the device or file at `path`; `mode` applies to a file created
+/// with `O_CREAT`, before the umask
+///
+/// Initrd and `/proc` files open with `O_RDONLY` only; other access modes
+/// fail with EROFS.
+pub fn open(path: &CStr, flags: i32, mode: u32) -> Result<i32> {
+    Errno::check(unsafe { syscall3(SYS_OPEN, path.as_ptr() as usize, flags as usize, mode as usize) })
+        .map(|fd| fd as i32)
+}
+
+/// Close handle `fd`, of any kind
+pub fn close(fd: i32) -> Result<()> {
+    Errno::check(unsafe { syscall1(SYS_CLOSE, fd as usize) }).map(|_| ())
+}
+
+/// Device control request `cmd` on `fd`
+///
+/// # Safety
+/// If the command takes a pointer, `arg` must point to the struct it
+/// expects (e.g. `mello_abi::Winsize` for `TIOCGWINSZ`).
+pub unsafe fn ioctl(fd: i32, cmd: usize, arg: usize) -> Result<usize> {
+    Errno::check(syscall3(SYS_IOCTL, fd as usize, cmd, arg))
+}
+
+/// Descriptor control command `cmd` (`F_GETFD`, `F_SETFL`, ...)
+pub fn fcntl(fd: i32, cmd: usize, arg: usize) -> Result<usize> {
+    Errno::check(unsafe { syscall3(SYS_FCNTL, fd as usize, cmd, arg) })
+}
+
+/// Create a pipe; returns the read and the write end
+pub fn pipe() -> Result<[i32; 2]> {
+    let mut fds = [-1; 2];
+    Errno::check(unsafe { syscall1(SYS_PIPE, fds.as_mut_ptr() as usize) })?;
+    Ok(fds)
+}
+
+/// Create a pipe with `O_CLOEXEC` and/or `O_NONBLOCK`
+pub fn pipe2(flags: i32) -> Result<[i32; 2]> {
+    let mut fds = [-1; 2];
+    Errno::check(unsafe { syscall2(SYS_PIPE2, fds.as_mut_ptr() as usize, flags as usize) })?;
+    Ok(fds)
+}
+
+/// Copy handle `old` to the lowest free number; returns it
+pub fn dup(old: i32) -> Result<i32> {
+    Errno::check(unsafe { syscall1(SYS_DUP, old as usize) }).map(|fd| fd as i32)
+}
+
+/// Make `new` a copy of `old`, closing what `new` was first
+pub fn dup2(old: i32, new: i32) -> Result<i32> {
+    Errno::check(unsafe { syscall2(SYS_DUP2, old as usize, new as usize) }).map(|fd| fd as i32)
+}
+
+/// Create a timer expiring after `delay` ticks and then every `period`
+/// ticks (0: once); returns its handle
+///
+/// [`read_count`] returns the expirations since the last read, waiting for
+/// one; [`poll`] reports `POLLIN` once there is one.
+pub fn timer_create(delay: usize, period: usize) -> Result<i32> {
+    Errno::check(unsafe { syscall2(SYS_TIMER_CREATE, delay, period) }).map(|fd| fd as i32)
+}
+
+/// Create an event holding `initial`; returns its handle
+///
+/// [`event_signal`] adds to the count; [`read_count`] returns it and
+/// resets it to 0, waiting while it is 0.
+pub fn event_create(initial: u64) -> Result<i32> {
+    Errno::check(unsafe { syscall1(SYS_EVENT_CREATE, initial as usize) }).map(|fd| fd as i32)
+}
+
+/// Add `value` (not 0) to the count of event `fd`
+pub fn event_signal(fd: i32, value: u64) -> Result<()> {
+    write(fd, &value.to_ne_bytes()).map(|_| ())
+}
+
+/// Read the count of timer or event `fd`
+pub fn read_count(fd: i32) -> Result<u64> {
+    let mut count = [0; 8];
+    read(fd, &mut count)?;
+    Ok(u64::from_ne_bytes(count))
+}
+
+/// Wait until an entry of `fds` is ready, for at most `timeout` ticks
+/// (None: no limit); returns the number of ready entries, 0 on timeout
+pub fn poll(fds: &mut [PollFd], timeout: Option<usize>) -> Result<usize> {
+    let timeout = timeout.unwrap_or(usize::MAX);
+    Errno::check(unsafe { syscall3(SYS_POLL, fds.as_mut_ptr() as usize, fds.len(), timeout) })
+}
+
+/// Move up to `count` bytes from `in_fd` to `out_fd` inside the kernel;
+/// returns the bytes moved
+///
+/// If `out_fd` is a port handle, each chunk becomes one message.
+pub fn sendfile(out_fd: i32, in_fd: i32, count: usize) -> Result<usize> {
+    Errno::check(unsafe { syscall3(SYS_SENDFILE, out_fd as usize, in_fd as usize, count) })
+}
+
+/// Move up to `count` bytes from `in_fd` to the port of handle `cap`, one
+/// message per chunk; returns the bytes moved
+pub fn sendfile_to_port(cap: usize, in_fd: i32, count: usize) -> Result<usize> {
+    Errno::check(unsafe { syscall3(SYS_SENDFILE, cap, in_fd as usize, count) })
+}
+
+/// `fmt::Write` for a file descriptor
+pub struct Fd(pub i32);
+
+impl fmt::Write for Fd {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(self.0, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(fd: i32, args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Fd(fd), args);
+}
+
+/// Print to standard output
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDOUT, format_args!($($arg)*)));
+}
+
+/// Print a line to standard output
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDOUT, format_args!("{}\n", format_args!($($arg)*))));
+}
+
+/// Print a line to standard error
+#[macro_export]
+macro_rules! eprintln {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDERR, format_args!("{}\n", format_args!($($arg)*))));
+}
@@ -0,0 +1,192 @@
+//! Ports, capabilities, messages and kernel events
+//!
+//! A port is named by a handle in the process's handle table, as files
+//! are; sending needs [`RIGHT_SEND`] and receiving [`RIGHT_RECV`]. A
+//! message can carry a copy of a handle of any kind, which needs
+//! [`RIGHT_GRANT`] on that handle.
+
+use crate::errno::{Errno, Result};
+use crate::syscall::*;
+
+// Capability rights
+pub const RIGHT_SEND: usize = 1;
+pub const RIGHT_RECV: usize = 2;
+pub const RIGHT_GRANT: usize = 4;
+
+/// Largest message payload
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// Flag in the handle of [`recv`]: return 0 instead of blocking
+pub const IPC_NONBLOCK: usize = 1 << 31;
+
+/// Position of the transferred capability in the handle and result
+const IPC_CAP_SHIFT: u32 = 32;
+
+/// Memory pressure event kind, as a subscription mask bit
+pub const EVENT_MEMORY_PRESSURE: u32 = 1 << 1;
+
+/// [`EventHeader::kind`] of the message [`task_watch`] sends
+pub const EVENT_TASK_EXIT: u32 = 2;
+
+/// Power button event kind, as a subscription mask bit
+pub const EVENT_POWER_BUTTON: u32 = 1 << 3;
+
+/// Create a port; returns a capability with every right on it
+pub fn port_create() -> Result<usize> {
+    Errno::check(unsafe { syscall0(SYS_PORT_CREATE) })
+}
+
+/// Copy capability `cap` with a subset of its `rights`; returns the new
+/// handle
+pub fn cap_derive(cap: usize, rights: usize) -> Result<usize> {
+    Errno::check(unsafe { syscall2(SYS_CAP_DERIVE, cap, rights) })
+}
+
+/// Close handle `cap`; the same as `io::close`
+pub fn cap_drop(cap: usize) -> Result<()> {
+    Errno::check(unsafe { syscall1(SYS_CAP_DROP, cap) }).map(|_| ())
+}
+
+/// Send `msg` to the port of capability `cap`
+pub fn send(cap: usize, msg: &[u8]) -> Result<()> {
+    Errno::check(unsafe { syscall3(SYS_IPC_SEND, cap, msg.as_ptr() as usize, msg.len()) }).map(|_| ())
+}
+
+/// Send `msg` to the port of capability `cap`, passing a copy of handle
+/// `grant` (which needs [`RIGHT_GRANT`]) along with it
+pub fn send_with_cap(cap: usize, msg: &[u8], grant: usize) -> Result<()> {
+    let cap = cap | ((grant + 1) << IPC_CAP_SHIFT);
+    Errno::check(unsafe { syscall3(SYS_IPC_SEND, cap, msg.as_ptr() as usize, msg.len()) }).map(|_| ())
+}
+
+/// A received message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Received {
+    /// Bytes copied into the buffer
+    pub len: usize,
+    /// The handle that came with the message, now in the caller's table
+    pub cap: Option<usize>,
+}
+
+/// Receive a message from the port of capability `cap` into `buf`
+///
+/// Blocks until a message arrives unless `cap` includes [`IPC_NONBLOCK`],
+/// in which case an empty queue gives a zero length. A message longer than
+/// `buf` is truncated. A signal for the task ends a blocking receive with
+/// `EINTR`.
+pub fn recv(cap: usize, buf: &mut [u8]) -> Result<Received> {
+    received(unsafe { syscall3(SYS_IPC_RECV, cap, buf.as_mut_ptr() as usize, buf.len()) })
+}
+
+/// [`recv`], waiting at most `timeout` ticks (None: no limit, Some(0):
+/// just check) for a message
+///
+/// Fails with `ETIMEDOUT` if none arrived in time.
+pub fn recv_timeout(cap: usize, buf: &mut [u8], timeout: Option<usize>) -> Result<Received> {
+    let timeout = timeout.unwrap_or(usize::MAX);
+    received(unsafe { syscall4(SYS_IPC_RECV_TIMEOUT, cap, buf.as_mut_ptr() as usize, buf.len(), timeout) })
+}
+
+/// Decode the result of a receive
+fn received(ret: isize) -> Result<Received> {
+    let ret = Errno::check(ret)?;
+    let len = ret & ((1 << IPC_CAP_SHIFT) - 1);
+    let cap = match ret >> IPC_CAP_SHIFT {
+        0 => None,
+        handle => Some(handle - 1),
+    };
+    Ok(Received { len, cap })
+}
+
+/// Ports and notification bits for [`poll`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IpcWaitSet {
+    /// One bit per capability handle
+    pub ports: [u64; 4],
+    pub notify: u64,
+}
+
+mello_abi::check_layout!(IpcWaitSet, mello_abi::IpcWaitSet { ports, notify });
+
+impl IpcWaitSet {
+    /// Wait for messages on the port of capability `cap`
+    pub fn add_port(&mut self, cap: usize) {
+        self.ports[cap / 64] |= 1 << (cap % 64);
+    }
+
+    /// Whether the port of capability `cap` is ready
+    pub fn has_port(&self, cap: usize) -> bool {
+        self.ports[cap / 64] & (1 << (cap % 64)) != 0
+    }
+}
+
+/// Wait until a port or notification bit in `set` is ready, for at most
+/// `timeout` ticks (None: no limit, Some(0): just check)
+///
+/// `set` is overwritten with what is ready. Returns the number of ready
+/// ports plus one if notification bits were raised, 0 on timeout.
+pub fn poll(set: &mut IpcWaitSet, timeout: Option<usize>) -> Result<usize> {
+    let timeout = timeout.unwrap_or(usize::MAX);
+    Errno::check(unsafe { syscall2(SYS_IPC_POLL, set as *mut IpcWaitSet as usize, timeout) })
+}
+
+/// Raise notification `bits` on task `task_id`
+pub fn notify(task_id: usize, bits: u64) -> Result<()> {
+    Errno::check(unsafe { syscall2(SYS_IPC_NOTIFY, task_id, bits as usize) }).map(|_| ())
+}
+
+/// Header at the start of every kernel event message
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventHeader {
+    pub kind: u32,
+    /// Payload length in bytes
+    pub len: u32,
+    /// Milliseconds since boot when the event was raised
+    pub time_ms: u64,
+}
+
+mello_abi::check_layout!(EventHeader, mello_abi::EventHeader { kind, len, time_ms });
+
+/// Payload of an [`EVENT_TASK_EXIT`] message
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaskExit {
+    /// The task that exited
+    pub task: u64,
+    /// Its process
+    pub pid: u64,
+    /// Exit code; 128 + the signal number if it was killed
+    pub code: u64,
+}
+
+mello_abi::check_layout!(TaskExit, mello_abi::TaskExit { task, pid, code });
+
+impl TaskExit {
+    /// The exit notification in received message `msg`, if it is one
+    pub fn parse(msg: &[u8]) -> Option<TaskExit> {
+        let header_len = core::mem::size_of::<EventHeader>();
+        if msg.len() != header_len + core::mem::size_of::<TaskExit>() {
+            return None;
+        }
+        // SAFETY: both are repr(C) plain data and msg holds both
+        let header = unsafe { core::ptr::read_unaligned(msg.as_ptr() as *const EventHeader) };
+        (header.kind == EVENT_TASK_EXIT)
+            .then(|| unsafe { core::ptr::read_unaligned(msg[header_len..].as_ptr() as *const TaskExit) })
+    }
+}
+
+/// Have a [`TaskExit`] event sent to the port of capability `cap` (which
+/// needs [`RIGHT_RECV`]) when task `task` exits, or at once if it already has
+///
+/// Fails with `ESRCH` if there is no such task.
+pub fn task_watch(task: usize, cap: usize) -> Result<()> {
+    Errno::check(unsafe { syscall2(SYS_TASK_WATCH, task, cap) }).map(|_| ())
+}
+
+/// Have kernel events in `mask` sent to the port of capability `cap`
+/// (which needs [`RIGHT_RECV`])
+pub fn event_subscribe(cap: usize, mask: u32) -> Result<()> {
+    Errno::check(unsafe { syscall2(SYS_EVENT_SUBSCRIBE, cap, mask as usize) }).map(|_| ())
+}
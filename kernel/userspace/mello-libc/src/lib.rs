@@ -0,0 +1,49 @@
+//! mello-libc - Userland support library for MelloOS
+//!
+//! Typed wrappers for every MelloOS syscall, so programs need no inline
+//! assembly of their own:
+//!
+//! - [`io`]: file descriptors, pipes, `poll`, `sendfile`, and the
+//!   [`print!`]/[`println!`]/[`eprintln!`] macros
+//! - [`process`]: exit, fork/exec/wait and [`process::spawn`], signals,
+//!   sessions, threads, futexes, clocks, tracing, seccomp and the
+//!   hardware inventory
+//! - [`mem`]: mmap, brk and shared memory
+//! - [`ipc`]: ports, capabilities, messages and kernel events
+//! - [`net`]: ping and UDP sockets
+//!
+//! Failed calls return an [`Errno`]. The raw `syscall` instruction and the
+//! syscall numbers are in [`syscall`].
+//!
+//! With the default features the crate also provides the runtime a program
+//! needs: a panic handler (`rt`), a `_start` shim generated by [`entry!`],
+//! and a global allocator over brk and mmap (`heap`). A minimal program:
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! mello_libc::entry!(main);
+//!
+//! fn main() -> i32 {
+//!     mello_libc::println!("Hello from pid {}", mello_libc::process::getpid().unwrap_or(0));
+//!     0
+//! }
+//! ```
+//!
+//! Structs passed to the kernel are checked against `mello-abi`.
+
+#![no_std]
+
+pub mod errno;
+#[cfg(feature = "heap")]
+mod heap;
+pub mod io;
+pub mod ipc;
+pub mod mem;
+pub mod net;
+pub mod process;
+pub mod rt;
+pub mod syscall;
+
+pub use errno::{Errno, Result};
@@ -0,0 +1,78 @@
+//! Memory mappings, the program break and shared memory
+
+use crate::errno::{Errno, Result};
+use crate::syscall::*;
+
+// Protection bits; writable and executable together is refused
+pub const PROT_NONE: usize = 0;
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+
+// Mapping flags; besides anonymous memory only devices (`/dev/fb0`) map
+pub const MAP_SHARED: usize = 1;
+pub const MAP_PRIVATE: usize = 2;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// Longest shared memory object name
+pub const SHM_NAME_MAX: usize = 32;
+
+/// Map `len` bytes (rounded up to whole pages) of zeroed memory; returns
+/// the start address
+///
+/// # Safety
+/// With `MAP_FIXED`, whatever was mapped at `addr` is replaced.
+pub unsafe fn mmap(addr: usize, len: usize, prot: usize, flags: usize) -> Result<usize> {
+    Errno::check(syscall6(SYS_MMAP, addr, len, prot, flags, usize::MAX, 0))
+}
+
+/// Map `len` bytes of the device open as `fd`, from the page-aligned
+/// `offset`; returns the start address
+///
+/// # Safety
+/// As for [`mmap`]; the memory is the device's, shared with the kernel.
+pub unsafe fn mmap_fd(addr: usize, len: usize, prot: usize, flags: usize, fd: i32, offset: usize) -> Result<usize> {
+    Errno::check(syscall6(SYS_MMAP, addr, len, prot, flags, fd as usize, offset))
+}
+
+/// Remove the mappings in a page-aligned range
+///
+/// # Safety
+/// Nothing may still use the memory in the range.
+pub unsafe fn munmap(addr: usize, len: usize) -> Result<()> {
+    Errno::check(syscall2(SYS_MUNMAP, addr, len)).map(|_| ())
+}
+
+/// Change the protection of a mapped, page-aligned range
+///
+/// # Safety
+/// Nothing may still use the memory in a way the new protection forbids.
+pub unsafe fn mprotect(addr: usize, len: usize, prot: usize) -> Result<()> {
+    Errno::check(syscall3(SYS_MPROTECT, addr, len, prot)).map(|_| ())
+}
+
+/// Move the program break to `addr` (0: just read it); returns the break,
+/// which is unchanged if the request could not be met
+pub fn brk(addr: usize) -> usize {
+    unsafe { syscall1(SYS_BRK, addr) as usize }
+}
+
+/// Create a shared memory object of `size` bytes, or open the one called
+/// `name` (None: anonymous); returns a handle to it
+///
+/// The object lives until the last handle to it is closed and the last
+/// mapping of it removed.
+pub fn shm_create(name: Option<&[u8]>, size: usize) -> Result<usize> {
+    let (ptr, len) = name.map_or((0, 0), |name| (name.as_ptr() as usize, name.len()));
+    if len > SHM_NAME_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    Errno::check(unsafe { syscall3(SYS_SHM_CREATE, ptr, len, size) })
+}
+
+/// Map the shared memory object of handle `shm` near `addr` (0: anywhere);
+/// returns the address of the mapping, which [`munmap`] removes
+pub fn shm_map(shm: usize, addr: usize, prot: usize) -> Result<usize> {
+    Errno::check(unsafe { syscall3(SYS_SHM_MAP, shm, addr, prot) })
+}
@@ -0,0 +1,261 @@
+//! Networking: ping, host name lookups, UDP and TCP sockets
+
+use crate::errno::{Errno, Result};
+use crate::syscall::*;
+
+/// Echo request for [`ping`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PingRequest {
+    /// IPv4 address in the first 4 bytes, or an IPv6 address
+    pub addr: [u8; 16],
+    /// 4 or 6
+    pub family: u16,
+    pub seq: u16,
+    /// How long to wait for the reply (the kernel caps it at 60 s)
+    pub timeout_ms: u32,
+}
+
+mello_abi::check_layout!(PingRequest, mello_abi::PingRequest { addr, family, seq, timeout_ms });
+
+impl PingRequest {
+    /// Echo request number `seq` to IPv4 address `addr`
+    pub fn v4(addr: [u8; 4], seq: u16, timeout_ms: u32) -> Self {
+        let mut request = Self { family: 4, seq, timeout_ms, ..Self::default() };
+        request.addr[..4].copy_from_slice(&addr);
+        request
+    }
+
+    /// Echo request number `seq` to IPv6 address `addr`
+    pub fn v6(addr: [u8; 16], seq: u16, timeout_ms: u32) -> Self {
+        Self { addr, family: 6, seq, timeout_ms }
+    }
+}
+
+/// Send an ICMP echo request and wait for the reply; returns the round
+/// trip time in microseconds
+///
+/// Fails with `ETIMEDOUT` if no reply came in time, `EHOSTUNREACH` if the
+/// next hop did not answer address resolution, and `ENETUNREACH` if there
+/// is no route.
+pub fn ping(request: &PingRequest) -> Result<u64> {
+    Errno::check(unsafe { syscall1(SYS_PING, request as *const PingRequest as usize) }).map(|rtt| rtt as u64)
+}
+
+/// Address families
+pub const AF_INET: i32 = 2;
+pub const AF_INET6: i32 = 10;
+
+/// Socket types: stream (TCP) and datagram (UDP)
+pub const SOCK_STREAM: i32 = 1;
+pub const SOCK_DGRAM: i32 = 2;
+/// [`socket`] flags, or-ed into the type
+pub const SOCK_NONBLOCK: i32 = 0x800;
+pub const SOCK_CLOEXEC: i32 = 0x80000;
+
+/// [`send`]/[`recv`]/[`sendto`]/[`recvfrom`] flag: don't block this call
+pub const MSG_DONTWAIT: i32 = 0x40;
+
+/// `struct sockaddr_in`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SockaddrIn {
+    pub sin_family: u16,
+    /// Network byte order
+    pub sin_port: u16,
+    pub sin_addr: [u8; 4],
+    pub sin_zero: [u8; 8],
+}
+
+mello_abi::check_layout!(SockaddrIn, mello_abi::SockaddrIn { sin_family, sin_port, sin_addr, sin_zero });
+
+/// `struct sockaddr_in6`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SockaddrIn6 {
+    pub sin6_family: u16,
+    /// Network byte order
+    pub sin6_port: u16,
+    pub sin6_flowinfo: u32,
+    pub sin6_addr: [u8; 16],
+    pub sin6_scope_id: u32,
+}
+
+mello_abi::check_layout!(
+    SockaddrIn6,
+    mello_abi::SockaddrIn6 { sin6_family, sin6_port, sin6_flowinfo, sin6_addr, sin6_scope_id }
+);
+
+/// IP address and port of a socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketAddr {
+    V4([u8; 4], u16),
+    V6([u8; 16], u16),
+}
+
+/// A `sockaddr_in` or `sockaddr_in6`, big enough for either
+#[repr(C)]
+union RawSockaddr {
+    v4: SockaddrIn,
+    v6: SockaddrIn6,
+}
+
+impl SocketAddr {
+    fn to_raw(self) -> (RawSockaddr, usize) {
+        match self {
+            SocketAddr::V4(addr, port) => {
+                let v4 = SockaddrIn { sin_family: AF_INET as u16, sin_port: port.to_be(), sin_addr: addr, sin_zero: [0; 8] };
+                (RawSockaddr { v4 }, core::mem::size_of::<SockaddrIn>())
+            }
+            SocketAddr::V6(addr, port) => {
+                let v6 = SockaddrIn6 { sin6_family: AF_INET6 as u16, sin6_port: port.to_be(), sin6_addr: addr, ..SockaddrIn6::default() };
+                (RawSockaddr { v6 }, core::mem::size_of::<SockaddrIn6>())
+            }
+        }
+    }
+
+    fn from_raw(raw: &RawSockaddr) -> Option<Self> {
+        // Both variants start with the family
+        let family = unsafe { raw.v4.sin_family };
+        match family as i32 {
+            AF_INET => {
+                let v4 = unsafe { raw.v4 };
+                Some(SocketAddr::V4(v4.sin_addr, u16::from_be(v4.sin_port)))
+            }
+            AF_INET6 => {
+                let v6 = unsafe { raw.v6 };
+                Some(SocketAddr::V6(v6.sin6_addr, u16::from_be(v6.sin6_port)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Create a socket of family `domain` (`AF_INET` or `AF_INET6`); `ty` is
+/// `SOCK_STREAM` or `SOCK_DGRAM`, or-ed with `SOCK_NONBLOCK` and
+/// `SOCK_CLOEXEC`
+///
+/// The socket is closed with [`crate::io::close`] and can be polled.
+/// [`crate::io::read`] and [`crate::io::write`] on a connected stream socket
+/// move stream data; `read` on a datagram socket receives a datagram without
+/// its sender.
+pub fn socket(domain: i32, ty: i32) -> Result<i32> {
+    Errno::check(unsafe { syscall3(SYS_SOCKET, domain as usize, ty as usize, 0) }).map(|fd| fd as i32)
+}
+
+/// Bind socket `fd` to `addr`'s port (0 picks an ephemeral port)
+pub fn bind(fd: i32, addr: &SocketAddr) -> Result<()> {
+    let (raw, len) = addr.to_raw();
+    Errno::check(unsafe { syscall3(SYS_BIND, fd as usize, &raw as *const RawSockaddr as usize, len) }).map(|_| ())
+}
+
+/// Connect stream socket `fd` to `to`, waiting for the handshake
+///
+/// A non-blocking socket fails with `EINPROGRESS`; it polls writable once
+/// the handshake is over, and calling `connect` again then gives the
+/// outcome: success, `ECONNREFUSED` or `ETIMEDOUT`.
+pub fn connect(fd: i32, to: &SocketAddr) -> Result<()> {
+    let (raw, len) = to.to_raw();
+    Errno::check(unsafe { syscall3(SYS_CONNECT, fd as usize, &raw as *const RawSockaddr as usize, len) }).map(|_| ())
+}
+
+/// Make stream socket `fd` accept connections, queueing up to `backlog`
+/// (at most 8) of them
+pub fn listen(fd: i32, backlog: usize) -> Result<()> {
+    Errno::check(unsafe { syscall2(SYS_LISTEN, fd as usize, backlog) }).map(|_| ())
+}
+
+/// Wait for a connection on listening socket `fd`; returns its socket and
+/// the peer's address
+///
+/// A non-blocking socket fails with `EAGAIN` when none is waiting; it polls
+/// readable when one is.
+pub fn accept(fd: i32) -> Result<(i32, SocketAddr)> {
+    let mut raw = RawSockaddr { v6: SockaddrIn6::default() };
+    let mut len = core::mem::size_of::<RawSockaddr>() as u32;
+    let conn = Errno::check(unsafe {
+        syscall3(SYS_ACCEPT, fd as usize, &mut raw as *mut RawSockaddr as usize, &mut len as *mut u32 as usize)
+    })? as i32;
+    let peer = SocketAddr::from_raw(&raw).ok_or(Errno::EAFNOSUPPORT)?;
+    Ok((conn, peer))
+}
+
+/// Send `buf` on connected stream socket `fd`; returns how much was queued
+pub fn send(fd: i32, buf: &[u8], flags: i32) -> Result<usize> {
+    Errno::check(unsafe { syscall6(SYS_SENDTO, fd as usize, buf.as_ptr() as usize, buf.len(), flags as usize, 0, 0) })
+}
+
+/// Receive from connected stream socket `fd` into `buf`; 0 means the peer
+/// closed its side
+pub fn recv(fd: i32, buf: &mut [u8], flags: i32) -> Result<usize> {
+    Errno::check(unsafe {
+        syscall6(SYS_RECVFROM, fd as usize, buf.as_mut_ptr() as usize, buf.len(), flags as usize, 0, 0)
+    })
+}
+
+/// Send `buf` as one datagram from socket `fd` to `to`
+///
+/// An unbound socket is bound to an ephemeral port first. Fails with
+/// `EHOSTUNREACH` while the next hop is being resolved; retry after a short
+/// sleep.
+pub fn sendto(fd: i32, buf: &[u8], flags: i32, to: &SocketAddr) -> Result<usize> {
+    let (raw, len) = to.to_raw();
+    Errno::check(unsafe {
+        syscall6(
+            SYS_SENDTO,
+            fd as usize,
+            buf.as_ptr() as usize,
+            buf.len(),
+            flags as usize,
+            &raw as *const RawSockaddr as usize,
+            len,
+        )
+    })
+}
+
+/// Receive one datagram on socket `fd` into `buf`; returns its length (cut
+/// to `buf`) and sender
+pub fn recvfrom(fd: i32, buf: &mut [u8], flags: i32) -> Result<(usize, SocketAddr)> {
+    let mut raw = RawSockaddr { v6: SockaddrIn6::default() };
+    let mut len = core::mem::size_of::<RawSockaddr>() as u32;
+    let received = Errno::check(unsafe {
+        syscall6(
+            SYS_RECVFROM,
+            fd as usize,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+            flags as usize,
+            &mut raw as *mut RawSockaddr as usize,
+            &mut len as *mut u32 as usize,
+        )
+    })?;
+    let from = SocketAddr::from_raw(&raw).ok_or(Errno::EAFNOSUPPORT)?;
+    Ok((received, from))
+}
+
+/// IPv4 address of host `name`, from the kernel's DNS resolver
+///
+/// An IPv4 literal is returned as is. Fails with `ENOENT` for an unknown
+/// name, `ENETUNREACH` if no DNS server is configured and `ETIMEDOUT` if it
+/// does not answer.
+pub fn resolve(name: &str) -> Result<[u8; 4]> {
+    let mut addr = [0u8; 4];
+    Errno::check(unsafe {
+        syscall3(SYS_RESOLVE, name.as_ptr() as usize, name.len(), addr.as_mut_ptr() as usize)
+    })?;
+    Ok(addr)
+}
+
+/// Parse a dotted-quad IPv4 address
+pub fn parse_ipv4(text: &str) -> Option<[u8; 4]> {
+    let mut addr = [0u8; 4];
+    let mut parts = text.split('.');
+    for byte in addr.iter_mut() {
+        let part = parts.next()?;
+        if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        *byte = part.parse().ok()?;
+    }
+    parts.next().is_none().then_some(addr)
+}
@@ -0,0 +1,487 @@
+//! Processes, threads, signals and clocks
+
+use crate::errno::{Errno, Result};
+use crate::syscall::*;
+use core::ffi::CStr;
+use core::sync::atomic::AtomicU32;
+
+// Signals
+pub const SIGINT: usize = 2;
+pub const SIGKILL: usize = 9;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+
+// getrusage targets
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+
+/// getrandom flag: fail with EAGAIN instead of blocking
+pub const GRND_NONBLOCK: usize = 0x1;
+
+/// End the process with exit `code`
+pub fn exit(code: i32) -> ! {
+    unsafe {
+        syscall1(SYS_EXIT, code as usize);
+    }
+    unreachable!("SYS_EXIT returned")
+}
+
+pub fn getpid() -> Result<usize> {
+    Errno::check(unsafe { syscall0(SYS_GETPID) })
+}
+
+/// Give the CPU to another task
+pub fn yield_now() {
+    unsafe {
+        syscall0(SYS_YIELD);
+    }
+}
+
+/// Sleep for `ticks` timer ticks
+///
+/// Fails with `EINTR` if a signal for the task cuts the sleep short.
+pub fn sleep(ticks: usize) -> Result<()> {
+    Errno::check(unsafe { syscall1(SYS_SLEEP, ticks) }).map(|_| ())
+}
+
+/// Sleep for `nanos` nanoseconds
+///
+/// Ends close to the deadline when the kernel's timer runs in TSC-deadline
+/// mode, otherwise at the next tick after it. Fails with `EINTR` if a
+/// signal for the task cuts the sleep short.
+pub fn nanosleep(nanos: u64) -> Result<()> {
+    Errno::check(unsafe { syscall1(SYS_NANOSLEEP, nanos as usize) }).map(|_| ())
+}
+
+/// Write the kernel's settings out and turn the machine off
+///
+/// Only init may; anyone else gets `EPERM`. Returns `ENOSYS` if the
+/// firmware gives no way to power off.
+pub fn poweroff() -> Result<()> {
+    Errno::check(unsafe { syscall0(SYS_POWEROFF) }).map(|_| ())
+}
+
+/// Which side of a [`fork`] the caller is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fork {
+    /// In the parent, with the child's PID
+    Parent(usize),
+    Child,
+}
+
+/// Duplicate the process
+pub fn fork() -> Result<Fork> {
+    match Errno::check(unsafe { syscall0(SYS_FORK) })? {
+        0 => Ok(Fork::Child),
+        pid => Ok(Fork::Parent(pid)),
+    }
+}
+
+/// Replace the process image with the program at `path`
+///
+/// Only returns if the exec failed. Arguments are not passed yet.
+pub fn exec(path: &CStr) -> Errno {
+    match Errno::check(unsafe { syscall2(SYS_EXEC, path.as_ptr() as usize, 0) }) {
+        Err(errno) => errno,
+        Ok(_) => unreachable!("SYS_EXEC returned success"),
+    }
+}
+
+/// Wait for child `pid` (0: any child) to exit; returns its PID and exit
+/// code
+pub fn wait(pid: usize) -> Result<(usize, i32)> {
+    let status = Errno::check(unsafe { syscall1(SYS_WAIT, pid) })?;
+    Ok((status >> 8, (status & 0xFF) as i32))
+}
+
+/// Most arguments [`spawn`] can pass
+pub const MAX_ARGS: usize = 32;
+
+/// Start the program at `path` in the initrd as a child process with
+/// `args` as its arguments (by convention the first is its name); returns
+/// its PID
+///
+/// The child starts with the caller's process group, session, terminal,
+/// umask, seccomp filter and CPU bandwidth group, and copies of its handles
+/// other than the `FD_CLOEXEC` ones. Fails with ENOENT if the initrd has no
+/// such file, ENOEXEC if it is not an ELF image and E2BIG if there are more
+/// than [`MAX_ARGS`] arguments or they take more than 1 KiB.
+pub fn spawn(path: &CStr, args: &[&CStr]) -> Result<usize> {
+    let mut argv = [0usize; MAX_ARGS + 1];
+    if args.len() > MAX_ARGS {
+        return Err(Errno::E2BIG);
+    }
+    for (slot, arg) in argv.iter_mut().zip(args) {
+        *slot = arg.as_ptr() as usize;
+    }
+    Errno::check(unsafe { syscall2(SYS_SPAWN, path.as_ptr() as usize, argv.as_ptr() as usize) })
+}
+
+/// Arguments the program was started with
+///
+/// Empty unless `_start` comes from [`entry!`](crate::entry).
+pub fn args() -> impl ExactSizeIterator<Item = &'static CStr> {
+    let (argc, argv) = crate::rt::argv();
+    (0..argc).map(move |i| unsafe { CStr::from_ptr(*argv.add(i) as *const core::ffi::c_char) })
+}
+
+/// Send `signal` to `pid`: a process if positive, process group `-pid` if
+/// below -1, the caller's group if 0
+pub fn kill(pid: isize, signal: usize) -> Result<()> {
+    Errno::check(unsafe { syscall2(SYS_KILL, pid as usize, signal) }).map(|_| ())
+}
+
+/// Set how `signal` is handled and/or read how it was
+///
+/// # Safety
+/// `act` and `oldact` must be null or point to the kernel's `SigAction`,
+/// which is not part of the stable ABI yet.
+pub unsafe fn sigaction(signal: usize, act: *const u8, oldact: *mut u8) -> Result<()> {
+    Errno::check(syscall3(SYS_SIGACTION, signal, act as usize, oldact as usize)).map(|_| ())
+}
+
+/// Move `pid` (0: the caller) into process group `pgid` (0: its own PID)
+pub fn setpgid(pid: usize, pgid: usize) -> Result<()> {
+    Errno::check(unsafe { syscall2(SYS_SETPGID, pid, pgid) }).map(|_| ())
+}
+
+pub fn getpgrp() -> Result<usize> {
+    Errno::check(unsafe { syscall0(SYS_GETPGRP) })
+}
+
+/// Start a new session led by the caller; returns its ID
+pub fn setsid() -> Result<usize> {
+    Errno::check(unsafe { syscall0(SYS_SETSID) })
+}
+
+/// Session of `pid` (0: the caller)
+pub fn getsid(pid: usize) -> Result<usize> {
+    Errno::check(unsafe { syscall1(SYS_GETSID, pid) })
+}
+
+/// Make `pgid` the foreground process group of the terminal on `fd`
+pub fn tcsetpgrp(fd: i32, pgid: usize) -> Result<()> {
+    Errno::check(unsafe { syscall2(SYS_TCSETPGRP, fd as usize, pgid) }).map(|_| ())
+}
+
+/// Foreground process group of the terminal on `fd`
+pub fn tcgetpgrp(fd: i32) -> Result<usize> {
+    Errno::check(unsafe { syscall1(SYS_TCGETPGRP, fd as usize) })
+}
+
+/// Set the file creation mask; returns the previous one
+pub fn umask(mask: u32) -> u32 {
+    unsafe { syscall1(SYS_UMASK, mask as usize) as u32 }
+}
+
+/// `struct timeval`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+/// Resource usage, as `struct rusage` on x86_64 Linux
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rusage {
+    pub ru_utime: Timeval,
+    pub ru_stime: Timeval,
+    pub ru_maxrss: i64,
+    pub ru_ixrss: i64,
+    pub ru_idrss: i64,
+    pub ru_isrss: i64,
+    pub ru_minflt: i64,
+    pub ru_majflt: i64,
+    pub ru_nswap: i64,
+    pub ru_inblock: i64,
+    pub ru_oublock: i64,
+    pub ru_msgsnd: i64,
+    pub ru_msgrcv: i64,
+    pub ru_nsignals: i64,
+    pub ru_nvcsw: i64,
+    pub ru_nivcsw: i64,
+}
+
+mello_abi::check_layout!(Timeval, mello_abi::Timeval { tv_sec, tv_usec });
+mello_abi::check_layout!(
+    Rusage,
+    mello_abi::Rusage {
+        ru_utime, ru_stime, ru_maxrss, ru_ixrss, ru_idrss, ru_isrss, ru_minflt, ru_majflt, ru_nswap,
+        ru_inblock, ru_oublock, ru_msgsnd, ru_msgrcv, ru_nsignals, ru_nvcsw, ru_nivcsw,
+    }
+);
+
+/// Resource usage of the caller (`RUSAGE_SELF`) or of its exited
+/// descendants (`RUSAGE_CHILDREN`)
+pub fn getrusage(who: isize) -> Result<Rusage> {
+    let mut usage = Rusage::default();
+    Errno::check(unsafe { syscall2(SYS_GETRUSAGE, who as usize, &mut usage as *mut Rusage as usize) })?;
+    Ok(usage)
+}
+
+/// Fill `buf` with random bytes; returns the bytes written
+pub fn getrandom(buf: &mut [u8], flags: usize) -> Result<usize> {
+    Errno::check(unsafe { syscall3(SYS_GETRANDOM, buf.as_mut_ptr() as usize, buf.len(), flags) })
+}
+
+/// Nanoseconds since boot, from the kernel
+pub fn clock_gettime() -> Result<u64> {
+    Errno::check(unsafe { syscall0(SYS_CLOCK_GETTIME) }).map(|ns| ns as u64)
+}
+
+/// Nanoseconds since boot, from the vDSO without entering the kernel
+pub fn now_ns() -> u64 {
+    let clock: extern "C" fn() -> u64 = unsafe { core::mem::transmute(mello_abi::VDSO_CLOCK as usize) };
+    clock()
+}
+
+/// Arguments of [`thread_create`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadParams {
+    /// `extern "C" fn(u64) -> !`, called with `arg`
+    pub entry: u64,
+    pub arg: u64,
+    /// Top of the new thread's stack
+    pub stack_top: u64,
+    /// FS base of the new thread
+    pub tls: u64,
+    /// Join word, cleared and woken when the thread exits (0: none)
+    pub tid_ptr: u64,
+}
+
+mello_abi::check_layout!(ThreadParams, mello_abi::ThreadParams { entry, arg, stack_top, tls, tid_ptr });
+
+/// Start a thread in this process; returns its thread ID
+///
+/// # Safety
+/// `params.entry` must be a function that never returns (it ends with
+/// [`thread_exit`]), and the stack and join word must stay valid for the
+/// thread's lifetime.
+pub unsafe fn thread_create(params: &ThreadParams) -> Result<usize> {
+    Errno::check(syscall1(SYS_THREAD_CREATE, params as *const ThreadParams as usize))
+}
+
+/// End the calling thread; returns only if it failed
+pub fn thread_exit() -> Errno {
+    match Errno::check(unsafe { syscall0(SYS_THREAD_EXIT) }) {
+        Err(errno) => errno,
+        Ok(_) => unreachable!("SYS_THREAD_EXIT returned success"),
+    }
+}
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+
+/// Sleep while `word` holds `expected`
+///
+/// Returns EAGAIN at once if it does not, and EINTR if a signal for the
+/// task ends the wait.
+pub fn futex_wait(word: &AtomicU32, expected: u32) -> Result<()> {
+    Errno::check(unsafe { syscall3(SYS_FUTEX, word.as_ptr() as usize, FUTEX_WAIT, expected as usize) }).map(|_| ())
+}
+
+/// Wake the tasks sleeping on `word`
+pub fn futex_wake(word: &AtomicU32) -> Result<()> {
+    Errno::check(unsafe { syscall3(SYS_FUTEX, word.as_ptr() as usize, FUTEX_WAKE, 0) }).map(|_| ())
+}
+
+/// Start (`enable`) or stop logging the syscalls of `target` (0: the
+/// caller, or a child) to the kernel log; returns whether it was traced
+pub fn ptrace_lite(target: usize, enable: bool) -> Result<bool> {
+    Errno::check(unsafe { syscall2(SYS_PTRACE_LITE, target, enable as usize) }).map(|was| was != 0)
+}
+
+/// Syscall filter for [`seccomp`]
+///
+/// `mode` is 0 to allow only the listed syscalls, 1 to deny them; `action`
+/// is 0 to fail a filtered call with EPERM, 1 to kill the task.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SeccompFilter {
+    pub mode: u32,
+    pub action: u32,
+    pub syscalls: [u64; 2],
+}
+
+mello_abi::check_layout!(SeccompFilter, mello_abi::SeccompFilter { mode, action, syscalls });
+
+impl SeccompFilter {
+    /// List syscall `n`
+    pub fn with(mut self, n: usize) -> Self {
+        self.syscalls[n / 64] |= 1 << (n % 64);
+        self
+    }
+}
+
+/// Add `filter` to the syscall filter of `target` (0: the caller, or a
+/// child)
+pub fn seccomp(target: usize, filter: &SeccompFilter) -> Result<()> {
+    Errno::check(unsafe { syscall2(SYS_SECCOMP, target, filter as *const SeccompFilter as usize) }).map(|_| ())
+}
+
+/// Sample the user RIP of `target` (0: the caller, or a child) every
+/// `period` ticks into the shared memory object of handle `shm`; 0 stops
+/// profiling
+pub fn perf(target: usize, shm: usize, period: usize) -> Result<()> {
+    Errno::check(unsafe { syscall3(SYS_PERF, target, shm, period) }).map(|_| ())
+}
+
+/// Move `target` (0: the caller, or a child) to CPU bandwidth group
+/// `group`; returns its previous group
+///
+/// Tasks it creates from then on start in the same group.
+pub fn cpu_group(target: usize, group: usize) -> Result<usize> {
+    Errno::check(unsafe { syscall2(SYS_CPU_GROUP, target, group) })
+}
+
+/// Cap of a CPU bandwidth group, for [`cpu_quota`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuQuota {
+    /// CPU time allowed per period; 0 for no cap
+    pub quota_us: u64,
+    pub period_us: u64,
+}
+
+mello_abi::check_layout!(CpuQuota, mello_abi::CpuQuota { quota_us, period_us });
+
+/// Cap and throttle counters of a CPU bandwidth group
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuGroupStats {
+    pub quota_us: u64,
+    pub period_us: u64,
+    pub periods: u64,
+    pub throttled_periods: u64,
+    pub throttled_us: u64,
+    pub usage_us: u64,
+}
+
+mello_abi::check_layout!(
+    CpuGroupStats,
+    mello_abi::CpuGroupStats { quota_us, period_us, periods, throttled_periods, throttled_us, usage_us }
+);
+
+/// Apply `quota` to CPU bandwidth group `group`, if given; returns the
+/// group's cap and counters
+///
+/// A group over its quota is throttled until the next period. Group 0
+/// cannot be capped.
+pub fn cpu_quota(group: usize, quota: Option<&CpuQuota>) -> Result<CpuGroupStats> {
+    let quota = quota.map_or(0, |quota| quota as *const CpuQuota as usize);
+    let mut stats = CpuGroupStats::default();
+    Errno::check(unsafe { syscall3(SYS_CPU_QUOTA, group, quota, &mut stats as *mut CpuGroupStats as usize) })?;
+    Ok(stats)
+}
+
+/// Scheduler counters of a task or a priority level, for [`schedstat_task`]
+/// and [`schedstat_priority`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedStat {
+    /// Tasks counted (1 for a task)
+    pub tasks: u64,
+    /// CPU time, in timer ticks
+    pub cpu_ticks: u64,
+    /// Times picked to run
+    pub runs: u64,
+    /// Time spent ready before being picked, in nanoseconds, in total and
+    /// the longest single wait
+    pub wait_ns: u64,
+    pub max_wait_ns: u64,
+    /// Times switched out while still runnable, without yielding
+    pub preemptions: u64,
+}
+
+mello_abi::check_layout!(
+    SchedStat,
+    mello_abi::SchedStat { tasks, cpu_ticks, runs, wait_ns, max_wait_ns, preemptions }
+);
+
+const SCHEDSTAT_TASK: usize = 0;
+const SCHEDSTAT_PRIORITY: usize = 1;
+
+fn schedstat(kind: usize, id: usize) -> Result<SchedStat> {
+    let mut stat = SchedStat::default();
+    Errno::check(unsafe { syscall3(SYS_SCHEDSTAT, kind, id, &mut stat as *mut SchedStat as usize) })?;
+    Ok(stat)
+}
+
+/// Scheduler counters of task `task` (0: the caller)
+pub fn schedstat_task(task: usize) -> Result<SchedStat> {
+    schedstat(SCHEDSTAT_TASK, task)
+}
+
+/// Scheduler counters summed over every task at `priority` (0 low,
+/// 1 normal, 2 high), idle tasks left out
+pub fn schedstat_priority(priority: usize) -> Result<SchedStat> {
+    schedstat(SCHEDSTAT_PRIORITY, priority)
+}
+
+/// A task as [`task_info`] and [`task_list`] report it
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TaskInfo {
+    /// Task ID (thread ID)
+    pub id: u64,
+    /// Process the task belongs to, and its parent
+    pub pid: u64,
+    pub ppid: u64,
+    /// CPU time, in timer ticks
+    pub cpu_ticks: u64,
+    /// One of the `TASK_*` states
+    pub state: u32,
+    /// 0 low, 1 normal, 2 high
+    pub priority: u32,
+    /// Name, NUL-padded
+    pub name: [u8; 32],
+}
+
+mello_abi::check_layout!(TaskInfo, mello_abi::TaskInfo { id, pid, ppid, cpu_ticks, state, priority, name });
+
+pub use mello_abi::{TASK_BLOCKED, TASK_EXITED, TASK_READY, TASK_RUNNING, TASK_SLEEPING};
+
+impl TaskInfo {
+    pub const EMPTY: TaskInfo = TaskInfo { id: 0, pid: 0, ppid: 0, cpu_ticks: 0, state: 0, priority: 0, name: [0; 32] };
+
+    /// The name, up to the first NUL
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+
+    /// One-letter state, as `ps` shows it
+    pub fn state_letter(&self) -> char {
+        match self.state {
+            TASK_READY | TASK_RUNNING => 'R',
+            TASK_SLEEPING => 'S',
+            TASK_BLOCKED => 'D',
+            TASK_EXITED => 'Z',
+            _ => '?',
+        }
+    }
+}
+
+/// Describe task `task` (0: the caller)
+pub fn task_info(task: usize) -> Result<TaskInfo> {
+    let mut info = TaskInfo::EMPTY;
+    Errno::check(unsafe { syscall2(SYS_TASK_INFO, task, &mut info as *mut TaskInfo as usize) })?;
+    Ok(info)
+}
+
+/// Describe the live tasks in `buf`; returns how many there are, which is
+/// more than `buf.len()` if some did not fit
+pub fn task_list(buf: &mut [TaskInfo]) -> Result<usize> {
+    Errno::check(unsafe { syscall2(SYS_TASK_LIST, buf.as_mut_ptr() as usize, buf.len()) })
+}
+
+/// Read the hardware inventory report from `offset` into `buf`; returns
+/// the bytes read, 0 at the end
+///
+/// The report is the text of `/proc/hwinfo`: one `key: value` per line.
+pub fn hwinfo(buf: &mut [u8], offset: usize) -> Result<usize> {
+    Errno::check(unsafe { syscall3(SYS_HWINFO, buf.as_mut_ptr() as usize, buf.len(), offset) })
+}
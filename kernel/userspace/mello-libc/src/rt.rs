@@ -0,0 +1,76 @@
+//! Program entry and panics
+//!
+//! [`entry!`](crate::entry) defines `_start`, which records the program's
+//! arguments from the initial stack (see [`process::args`]), runs its
+//! `main` and exits with its return value. With the `rt` feature a panic
+//! prints its message to standard error and exits with code 101.
+//!
+//! [`process::args`]: crate::process::args
+
+use crate::process::exit;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// Define `_start` to run `$main: fn() -> i32` and exit with its result
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        // The kernel leaves argc at rsp; pass its address on
+        core::arch::global_asm!(
+            ".globl _start",
+            "_start:",
+            "xor ebp, ebp",
+            "mov rdi, rsp",
+            "call {start}",
+            start = sym __mello_start,
+        );
+
+        extern "C" fn __mello_start(sp: *const usize) -> ! {
+            $crate::rt::start($main, sp)
+        }
+    };
+}
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const u8> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Record the arguments at `sp`, the initial stack pointer, then run `main`
+/// and exit with its result
+pub fn start(main: fn() -> i32, sp: *const usize) -> ! {
+    unsafe {
+        ARGC.store(*sp, Ordering::Relaxed);
+        ARGV.store(sp.add(1) as *mut *const u8, Ordering::Relaxed);
+    }
+    exit(main())
+}
+
+/// Number of arguments and the `argv` array
+pub(crate) fn argv() -> (usize, *const *const u8) {
+    (ARGC.load(Ordering::Relaxed), ARGV.load(Ordering::Relaxed))
+}
+
+#[cfg(feature = "rt")]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    // Format into a fixed buffer so the message goes out in one write and
+    // does not depend on the heap
+    struct Buf {
+        data: [u8; 256],
+        len: usize,
+    }
+
+    impl Write for Buf {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let n = s.len().min(self.data.len() - self.len);
+            self.data[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+            self.len += n;
+            Ok(())
+        }
+    }
+
+    let mut buf = Buf { data: [0; 256], len: 0 };
+    let _ = writeln!(buf, "panic: {}", info);
+    let _ = crate::io::write(crate::io::STDERR, &buf.data[..buf.len]);
+    exit(101)
+}
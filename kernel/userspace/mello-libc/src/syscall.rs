@@ -0,0 +1,150 @@
+//! Raw syscalls
+//!
+//! The number goes in rax and up to six arguments in rdi, rsi, rdx, r10, r8
+//! and r9; the result comes back in rax, with rcx and r11 clobbered by the
+//! `syscall` instruction. The numbers must match the kernel's table in
+//! `kernel/src/sys/syscall.rs`.
+
+use core::arch::asm;
+
+pub const SYS_WRITE: usize = 0;
+pub const SYS_EXIT: usize = 1;
+pub const SYS_SLEEP: usize = 2;
+pub const SYS_IPC_SEND: usize = 3;
+pub const SYS_IPC_RECV: usize = 4;
+pub const SYS_GETPID: usize = 5;
+pub const SYS_YIELD: usize = 6;
+pub const SYS_FORK: usize = 7;
+pub const SYS_WAIT: usize = 8;
+pub const SYS_EXEC: usize = 9;
+pub const SYS_OPEN: usize = 10;
+pub const SYS_READ: usize = 11;
+pub const SYS_CLOSE: usize = 12;
+pub const SYS_IOCTL: usize = 13;
+pub const SYS_SIGACTION: usize = 14;
+pub const SYS_KILL: usize = 15;
+pub const SYS_SETPGID: usize = 16;
+pub const SYS_GETPGRP: usize = 17;
+pub const SYS_SETSID: usize = 18;
+pub const SYS_GETSID: usize = 19;
+pub const SYS_TCSETPGRP: usize = 20;
+pub const SYS_TCGETPGRP: usize = 21;
+pub const SYS_FCNTL: usize = 22;
+pub const SYS_PIPE2: usize = 23;
+pub const SYS_DUP2: usize = 24;
+pub const SYS_GETRANDOM: usize = 25;
+pub const SYS_UMASK: usize = 26;
+pub const SYS_GETRUSAGE: usize = 27;
+pub const SYS_MMAP: usize = 28;
+pub const SYS_MUNMAP: usize = 29;
+pub const SYS_MPROTECT: usize = 30;
+pub const SYS_EVENT_SUBSCRIBE: usize = 31;
+pub const SYS_BRK: usize = 32;
+pub const SYS_SHM_CREATE: usize = 33;
+pub const SYS_SHM_MAP: usize = 34;
+pub const SYS_IPC_POLL: usize = 35;
+pub const SYS_IPC_NOTIFY: usize = 36;
+pub const SYS_PORT_CREATE: usize = 37;
+pub const SYS_CAP_DERIVE: usize = 38;
+pub const SYS_CAP_DROP: usize = 39;
+pub const SYS_PIPE: usize = 40;
+pub const SYS_PERF: usize = 41;
+pub const SYS_POLL: usize = 42;
+pub const SYS_THREAD_CREATE: usize = 43;
+pub const SYS_THREAD_EXIT: usize = 44;
+pub const SYS_FUTEX: usize = 45;
+pub const SYS_SENDFILE: usize = 46;
+pub const SYS_CLOCK_GETTIME: usize = 47;
+pub const SYS_PTRACE_LITE: usize = 48;
+pub const SYS_SECCOMP: usize = 49;
+pub const SYS_SPAWN: usize = 50;
+pub const SYS_DUP: usize = 51;
+pub const SYS_TIMER_CREATE: usize = 52;
+pub const SYS_EVENT_CREATE: usize = 53;
+pub const SYS_CPU_GROUP: usize = 54;
+pub const SYS_CPU_QUOTA: usize = 55;
+pub const SYS_HWINFO: usize = 56;
+pub const SYS_PING: usize = 57;
+pub const SYS_SOCKET: usize = 58;
+pub const SYS_BIND: usize = 59;
+pub const SYS_SENDTO: usize = 60;
+pub const SYS_RECVFROM: usize = 61;
+pub const SYS_CONNECT: usize = 62;
+pub const SYS_LISTEN: usize = 63;
+pub const SYS_ACCEPT: usize = 64;
+pub const SYS_RESOLVE: usize = 65;
+pub const SYS_SCHEDSTAT: usize = 66;
+pub const SYS_TASK_INFO: usize = 67;
+pub const SYS_TASK_LIST: usize = 68;
+pub const SYS_IPC_RECV_TIMEOUT: usize = 69;
+pub const SYS_TASK_WATCH: usize = 70;
+pub const SYS_NANOSLEEP: usize = 71;
+pub const SYS_POWEROFF: usize = 72;
+
+/// Syscall `n` with no arguments
+///
+/// # Safety
+/// As for [`syscall6`].
+#[inline]
+pub unsafe fn syscall0(n: usize) -> isize {
+    syscall6(n, 0, 0, 0, 0, 0, 0)
+}
+
+/// Syscall `n` with one argument
+///
+/// # Safety
+/// As for [`syscall6`].
+#[inline]
+pub unsafe fn syscall1(n: usize, a1: usize) -> isize {
+    syscall6(n, a1, 0, 0, 0, 0, 0)
+}
+
+/// Syscall `n` with two arguments
+///
+/// # Safety
+/// As for [`syscall6`].
+#[inline]
+pub unsafe fn syscall2(n: usize, a1: usize, a2: usize) -> isize {
+    syscall6(n, a1, a2, 0, 0, 0, 0)
+}
+
+/// Syscall `n` with three arguments
+///
+/// # Safety
+/// As for [`syscall6`].
+#[inline]
+pub unsafe fn syscall3(n: usize, a1: usize, a2: usize, a3: usize) -> isize {
+    syscall6(n, a1, a2, a3, 0, 0, 0)
+}
+
+/// Syscall `n` with four arguments
+///
+/// # Safety
+/// As for [`syscall6`].
+#[inline]
+pub unsafe fn syscall4(n: usize, a1: usize, a2: usize, a3: usize, a4: usize) -> isize {
+    syscall6(n, a1, a2, a3, a4, 0, 0)
+}
+
+/// Syscall `n` with six arguments
+///
+/// # Safety
+/// Pointer arguments must be valid for what the syscall does with them.
+#[inline]
+pub unsafe fn syscall6(n: usize, a1: usize, a2: usize, a3: usize, a4: usize, a5: usize, a6: usize) -> isize {
+    let ret: isize;
+    asm!(
+        "syscall",
+        inlateout("rax") n as isize => ret,
+        in("rdi") a1,
+        in("rsi") a2,
+        in("rdx") a3,
+        in("r10") a4,
+        in("r8") a5,
+        in("r9") a6,
+        out("rcx") _,
+        out("r11") _,
+        options(nostack)
+    );
+    ret
+}
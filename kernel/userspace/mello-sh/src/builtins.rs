@@ -1,82 +1,129 @@
 //! Built-in commands for mello-sh
+//!
+//! Besides the usual shell built-ins there are `cat`, `ps`, `ping` and
+//! `httpd`, as the initrd has no programs for them yet: `cat` reads files
+//! with `SYS_OPEN` (initrd files and `/proc` can be read), `ps` lists the
+//! processes from `SYS_TASK_LIST`, `ping` sends echo requests with
+//! `SYS_PING`, and `httpd` serves files over HTTP from a TCP socket.
 
-use alloc::string::String;
 use alloc::format;
-use crate::{Shell, syscalls, jobs::JobState};
+use alloc::string::String;
+use alloc::vec;
+use mello_libc::io::{self, O_CLOEXEC, O_RDONLY, STDIN, STDOUT};
+use mello_libc::net::{self, PingRequest, SocketAddr, AF_INET, SOCK_CLOEXEC, SOCK_STREAM};
+use mello_libc::process;
+use mello_libc::{eprintln, println, Errno};
+
+use crate::executor::c_string;
+use crate::Shell;
+
+/// Names of the built-in commands
+const BUILTINS: &[&str] = &[
+    "cd", "pwd", "echo", "export", "unset", "exit", "cat", "ps", "ping", "httpd", "wait", "which", "history",
+    "debug-pty", "debug-signals",
+];
+
+/// Echo requests `ping` sends without `-c`
+const PING_COUNT: usize = 4;
+
+/// Time `ping` waits for each reply, and between requests
+const PING_INTERVAL_MS: u64 = 1000;
+
+/// Port `httpd` listens on without `-p`
+const HTTPD_PORT: u16 = 80;
+
+/// Longest request head `httpd` reads
+const HTTPD_MAX_REQUEST: usize = 1024;
+
+/// Tasks `ps` asks for at first; it asks again with room for all of them
+/// if there are more
+const PS_TASKS: usize = 64;
+
+/// Whether `cmd` is a built-in command
+pub fn is_builtin(cmd: &str) -> bool {
+    BUILTINS.contains(&cmd)
+}
 
 /// Execute a built-in command
 /// Returns Some(status) if command was a built-in, None otherwise
 pub fn execute(shell: &mut Shell, cmd: &str, args: &[String]) -> Option<i32> {
     match cmd {
         "cd" => Some(builtin_cd(shell, args)),
-        "pwd" => Some(builtin_pwd()),
+        "pwd" => Some(builtin_pwd(shell)),
         "echo" => Some(builtin_echo(args)),
         "export" => Some(builtin_export(shell, args)),
         "unset" => Some(builtin_unset(shell, args)),
-        "jobs" => Some(builtin_jobs(shell)),
-        "fg" => Some(builtin_fg(shell, args)),
-        "bg" => Some(builtin_bg(shell, args)),
         "exit" => Some(builtin_exit(shell, args)),
+        "cat" => Some(builtin_cat(shell, args)),
+        "ps" => Some(builtin_ps()),
+        "ping" => Some(builtin_ping(args)),
+        "httpd" => Some(builtin_httpd(shell, args)),
+        "wait" => Some(builtin_wait(args)),
         "which" => Some(builtin_which(shell, args)),
+        "history" => Some(builtin_history(shell)),
         "debug-pty" => Some(builtin_debug_pty()),
-        "debug-jobs" => Some(builtin_debug_jobs(shell)),
         "debug-signals" => Some(builtin_debug_signals()),
         _ => None,
     }
 }
 
+/// Copy the file at `path` (absolute) to stdout
+fn copy_file(path: &str) -> Result<(), Errno> {
+    let fd = io::open(&c_string(path)?, O_RDONLY | O_CLOEXEC, 0)?;
+    let result = copy_fd(fd, STDOUT);
+    let _ = io::close(fd);
+    result
+}
+
+/// Copy `from` to `to` until end of file
+fn copy_fd(from: i32, to: i32) -> Result<(), Errno> {
+    let mut buf = [0u8; 512];
+    loop {
+        let n = io::read(from, &mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        io::write_all(to, &buf[..n])?;
+    }
+}
+
 /// cd - change directory
+///
+/// Only the shell's `PWD` changes: it is what relative paths are resolved
+/// against. A file is not a directory; anything else is accepted, as there
+/// is no way to list directories yet.
 fn builtin_cd(shell: &mut Shell, args: &[String]) -> i32 {
     // Determine target directory
-    let target = if args.is_empty() {
-        // Default to $HOME
-        if let Some(home) = shell.get_env("HOME") {
-            home.clone()
-        } else {
-            syscalls::write(2, b"cd: HOME not set\n");
-            return 1;
-        }
-    } else {
-        args[0].clone()
+    let target = match args.first() {
+        Some(target) => target.clone(),
+        None => match shell.get_env("HOME") {
+            Some(home) => home.clone(),
+            None => {
+                eprintln!("cd: HOME not set");
+                return 1;
+            }
+        },
     };
 
-    // Add null terminator for syscall
-    let mut path_with_null = target.clone();
-    path_with_null.push('\0');
-
-    // Call chdir system call
-    if syscalls::chdir(path_with_null.as_bytes()) < 0 {
-        syscalls::write(2, b"cd: ");
-        syscalls::write(2, target.as_bytes());
-        syscalls::write(2, b": No such file or directory\n");
+    let path = shell.resolve(&target);
+    let is_file = c_string(&path)
+        .and_then(|path| io::open(&path, O_RDONLY | O_CLOEXEC, 0))
+        .map(|fd| {
+            let _ = io::close(fd);
+        })
+        .is_ok();
+    if is_file {
+        eprintln!("cd: {}: Not a directory", target);
         return 1;
     }
 
-    // Update PWD environment variable
-    let mut buf = [0u8; 4096];
-    let len = syscalls::getcwd(&mut buf);
-    
-    if len > 0 {
-        if let Ok(pwd) = core::str::from_utf8(&buf[..len as usize]) {
-            shell.set_env(String::from("PWD"), String::from(pwd));
-        }
-    }
-
+    shell.set_env(String::from("PWD"), path);
     0
 }
 
 /// pwd - print working directory
-fn builtin_pwd() -> i32 {
-    let mut buf = [0u8; 4096];
-    let len = syscalls::getcwd(&mut buf);
-    
-    if len < 0 {
-        syscalls::write(2, b"pwd: failed\n");
-        return 1;
-    }
-
-    syscalls::write(1, &buf[..len as usize]);
-    syscalls::write(1, b"\n");
+fn builtin_pwd(shell: &Shell) -> i32 {
+    println!("{}", shell.cwd());
     0
 }
 
@@ -99,15 +146,15 @@ fn builtin_echo(args: &[String]) -> i32 {
         }
     }
 
-    // Print arguments
+    // Build the output so it goes out in one write
+    let mut output = String::new();
     for (i, arg) in args[start..].iter().enumerate() {
         if i > 0 {
-            syscalls::write(1, b" ");
+            output.push(' ');
         }
-        
+
         if interpret_escapes {
             // Simple escape handling
-            let mut output = String::new();
             let mut chars = arg.chars();
             while let Some(ch) = chars.next() {
                 if ch == '\\' {
@@ -126,17 +173,19 @@ fn builtin_echo(args: &[String]) -> i32 {
                     output.push(ch);
                 }
             }
-            syscalls::write(1, output.as_bytes());
         } else {
-            syscalls::write(1, arg.as_bytes());
+            output.push_str(arg);
         }
     }
 
     if newline {
-        syscalls::write(1, b"\n");
+        output.push('\n');
     }
 
-    0
+    match io::write_all(STDOUT, output.as_bytes()) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
 }
 
 /// export - set environment variable
@@ -144,11 +193,7 @@ fn builtin_export(shell: &mut Shell, args: &[String]) -> i32 {
     if args.is_empty() {
         // Print all environment variables
         for (key, value) in shell.env() {
-            syscalls::write(1, b"export ");
-            syscalls::write(1, key.as_bytes());
-            syscalls::write(1, b"=");
-            syscalls::write(1, value.as_bytes());
-            syscalls::write(1, b"\n");
+            println!("export {}={}", key, value);
         }
         return 0;
     }
@@ -158,9 +203,9 @@ fn builtin_export(shell: &mut Shell, args: &[String]) -> i32 {
         if let Some(eq_pos) = arg.find('=') {
             let key = &arg[..eq_pos];
             let value = &arg[eq_pos + 1..];
-            
+
             if key.is_empty() {
-                syscalls::write(2, b"export: invalid variable name\n");
+                eprintln!("export: invalid variable name");
                 return 1;
             }
 
@@ -169,9 +214,7 @@ fn builtin_export(shell: &mut Shell, args: &[String]) -> i32 {
             // Just mark variable for export (already in environment)
             // For now, we don't distinguish between exported and non-exported
             if shell.get_env(arg).is_none() {
-                syscalls::write(2, b"export: ");
-                syscalls::write(2, arg.as_bytes());
-                syscalls::write(2, b": not found\n");
+                eprintln!("export: {}: not found", arg);
                 return 1;
             }
         }
@@ -183,7 +226,7 @@ fn builtin_export(shell: &mut Shell, args: &[String]) -> i32 {
 /// unset - unset environment variable
 fn builtin_unset(shell: &mut Shell, args: &[String]) -> i32 {
     if args.is_empty() {
-        syscalls::write(2, b"unset: missing argument\n");
+        eprintln!("unset: missing argument");
         return 1;
     }
 
@@ -194,143 +237,287 @@ fn builtin_unset(shell: &mut Shell, args: &[String]) -> i32 {
     0
 }
 
-/// jobs - list background jobs
-fn builtin_jobs(shell: &mut Shell) -> i32 {
-    for job in shell.jobs_mut().jobs() {
-        let state_str = match job.state {
-            JobState::Running => "Running",
-            JobState::Stopped => "Stopped",
-            JobState::Done(status) => {
-                syscalls::write(1, format!("[{}]+ Done({})    {}\n", job.id, status, job.command).as_bytes());
-                continue;
+/// exit - exit shell
+fn builtin_exit(shell: &mut Shell, args: &[String]) -> i32 {
+    let code = if args.is_empty() {
+        0
+    } else {
+        args[0].parse::<i32>().unwrap_or(0)
+    };
+
+    shell.request_exit();
+    code
+}
+
+/// cat - copy files, or stdin with no arguments or `-`, to stdout
+fn builtin_cat(shell: &Shell, args: &[String]) -> i32 {
+    if args.is_empty() {
+        return match copy_fd(STDIN, STDOUT) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("cat: {}", e);
+                1
             }
         };
+    }
 
-        syscalls::write(1, format!("[{}]+ {}    {}\n", job.id, state_str, job.command).as_bytes());
+    let mut status = 0;
+    for arg in args {
+        let result = if arg == "-" { copy_fd(STDIN, STDOUT) } else { copy_file(&shell.resolve(arg)) };
+        if let Err(e) = result {
+            eprintln!("cat: {}: {}", arg, e);
+            status = 1;
+        }
     }
-    0
+    status
 }
 
-/// fg - bring job to foreground
-fn builtin_fg(shell: &mut Shell, args: &[String]) -> i32 {
-    // Get job ID
-    let job_id = if args.is_empty() {
-        if let Some(id) = shell.jobs_mut().current_job_id() {
-            id
-        } else {
-            syscalls::write(2, b"fg: no current job\n");
-            return 1;
-        }
-    } else {
-        // Parse job ID (format: %N or just N)
-        let id_str = args[0].trim_start_matches('%');
-        match id_str.parse::<usize>() {
-            Ok(id) => id,
-            Err(_) => {
-                syscalls::write(2, b"fg: invalid job id\n");
+/// ps - list processes, with their state, priority and CPU time
+fn builtin_ps() -> i32 {
+    let mut tasks = vec![process::TaskInfo::EMPTY; PS_TASKS];
+    loop {
+        match process::task_list(&mut tasks) {
+            Ok(count) if count > tasks.len() => tasks.resize(count, process::TaskInfo::EMPTY),
+            Ok(count) => {
+                tasks.truncate(count);
+                break;
+            }
+            Err(e) => {
+                eprintln!("ps: {}", e);
                 return 1;
             }
         }
-    };
+    }
 
-    // Find job
-    let job = if let Some(j) = shell.jobs_mut().get_job(job_id) {
-        j.clone()
-    } else {
-        syscalls::write(2, b"fg: job not found\n");
-        return 1;
+    println!("  PID  PPID S PRI  TICKS COMMAND");
+    // One line per process: leave out the idle task and threads
+    for task in tasks.iter().filter(|task| task.id != 0 && task.id == task.pid) {
+        println!(
+            "{:>5} {:>5} {} {:>3} {:>6} {}",
+            task.pid,
+            task.ppid,
+            task.state_letter(),
+            task.priority,
+            task.cpu_ticks,
+            task.name()
+        );
+    }
+    0
+}
+
+/// ping [-c count] <host> - send ICMP echo requests to an IPv4 address or
+/// a host name
+fn builtin_ping(args: &[String]) -> i32 {
+    let (count, target) = match args {
+        [target] => (Some(PING_COUNT), target),
+        [flag, count, target] if flag == "-c" => (count.parse().ok().filter(|&count| count > 0), target),
+        _ => {
+            eprintln!("usage: ping [-c count] <host>");
+            return 2;
+        }
+    };
+    let Some(count) = count else {
+        eprintln!("ping: bad count");
+        return 2;
+    };
+    let addr = match net::resolve(target) {
+        Ok(addr) => addr,
+        Err(e) => {
+            eprintln!("ping: {}: {}", target, e);
+            return 2;
+        }
     };
 
-    // Set as foreground group
-    if let Some(tty_fd) = shell.tty_fd {
-        syscalls::tcsetpgrp(tty_fd, job.pgid);
+    let [a, b, c, d] = addr;
+    match net::parse_ipv4(target) {
+        Some(_) => println!("PING {}: 56 data bytes", target),
+        None => println!("PING {} ({}.{}.{}.{}): 56 data bytes", target, a, b, c, d),
     }
-
-    // Send SIGCONT if stopped
-    if job.state == JobState::Stopped {
-        syscalls::kill(job.pgid, syscalls::SIGCONT);
+    let mut received = 0;
+    for seq in 1..=count {
+        let start = process::now_ns();
+        match net::ping(&PingRequest::v4(addr, seq as u16, PING_INTERVAL_MS as u32)) {
+            Ok(rtt_us) => {
+                received += 1;
+                println!("64 bytes from {}: icmp_seq={} time={}.{:03} ms", target, seq, rtt_us / 1000, rtt_us % 1000);
+            }
+            Err(Errno::ETIMEDOUT) => println!("no reply from {}: icmp_seq={}", target, seq),
+            Err(e) => {
+                eprintln!("ping: {}: {}", target, e);
+                return 1;
+            }
+        }
+        // One request per interval, however fast the reply came
+        if seq != count {
+            while process::now_ns() - start < PING_INTERVAL_MS * 1_000_000 {
+                let _ = process::sleep(1);
+            }
+        }
     }
 
-    // Wait for job
-    let mut status = 0;
-    syscalls::wait4(job.pgid, &mut status, syscalls::WUNTRACED);
-
-    // Restore shell as foreground
-    if let Some(tty_fd) = shell.tty_fd {
-        let shell_pgid = syscalls::getpgrp() as i32;
-        syscalls::tcsetpgrp(tty_fd, shell_pgid);
+    println!("--- {} ping statistics ---", target);
+    println!(
+        "{} packets transmitted, {} received, {}% packet loss",
+        count,
+        received,
+        (count - received) * 100 / count
+    );
+    if received == 0 {
+        1
+    } else {
+        0
     }
+}
 
-    // Update job state
-    if let Some(job) = shell.jobs_mut().get_job_mut(job_id) {
-        if status & 0x7f == 0 {
-            let exit_status = (status >> 8) & 0xff;
-            job.state = JobState::Done(exit_status);
-        } else if status & 0xff == 0x7f {
-            job.state = JobState::Stopped;
+/// httpd [-p port] [-c count] - serve files under the current directory
+/// over HTTP
+///
+/// Connections are handled one at a time: `GET` of a path answers with the
+/// file (`/` is `/index.html`), then the connection is closed. Without
+/// `-c` it serves until the shell is killed.
+fn builtin_httpd(shell: &Shell, args: &[String]) -> i32 {
+    let mut port = Some(HTTPD_PORT);
+    let mut count = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("-p", Some(value)) => port = value.parse().ok().filter(|&port| port != 0),
+            ("-c", Some(value)) => count = Some(value.parse::<usize>().ok().filter(|&count| count > 0)),
+            _ => {
+                eprintln!("usage: httpd [-p port] [-c count]");
+                return 2;
+            }
         }
     }
+    let Some(port) = port else {
+        eprintln!("httpd: bad port");
+        return 2;
+    };
+    let count = match count {
+        Some(Some(count)) => Some(count),
+        Some(None) => {
+            eprintln!("httpd: bad count");
+            return 2;
+        }
+        None => None,
+    };
 
+    let listener = match listen_tcp(port) {
+        Ok(fd) => fd,
+        Err(e) => {
+            eprintln!("httpd: port {}: {}", port, e);
+            return 1;
+        }
+    };
+    println!("httpd: serving {} on port {}", shell.resolve("."), port);
+    let mut served = 0;
+    while count.map_or(true, |count| served < count) {
+        let (conn, peer) = match net::accept(listener) {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("httpd: accept: {}", e);
+                let _ = io::close(listener);
+                return 1;
+            }
+        };
+        serve_http(shell, conn, &peer);
+        let _ = io::close(conn);
+        served += 1;
+    }
+    let _ = io::close(listener);
     0
 }
 
-/// bg - resume job in background
-fn builtin_bg(shell: &mut Shell, args: &[String]) -> i32 {
-    // Get job ID
-    let job_id = if args.is_empty() {
-        if let Some(id) = shell.jobs_mut().current_job_id() {
-            id
-        } else {
-            syscalls::write(2, b"bg: no current job\n");
-            return 1;
+/// A TCP socket listening on `port` of every IPv4 address
+fn listen_tcp(port: u16) -> Result<i32, Errno> {
+    let fd = net::socket(AF_INET, SOCK_STREAM | SOCK_CLOEXEC)?;
+    let result = net::bind(fd, &SocketAddr::V4([0; 4], port)).and_then(|()| net::listen(fd, 4));
+    if let Err(e) = result {
+        let _ = io::close(fd);
+        return Err(e);
+    }
+    Ok(fd)
+}
+
+/// Answer one HTTP request on `conn` and log it
+fn serve_http(shell: &Shell, conn: i32, peer: &SocketAddr) {
+    let mut buf = [0u8; HTTPD_MAX_REQUEST];
+    let mut len = 0;
+    // The request line is all we need, but read the whole head so the
+    // client is not reset by unread data when we close
+    while len < buf.len() && !buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+        match io::read(conn, &mut buf[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
         }
+    }
+    let head = core::str::from_utf8(&buf[..len]).unwrap_or("");
+    let mut request = head.lines().next().unwrap_or("").split_whitespace();
+    let (method, target) = (request.next().unwrap_or(""), request.next().unwrap_or(""));
+
+    let status = if method != "GET" {
+        respond(conn, "405 Method Not Allowed", b"method not allowed\n")
+    } else if !target.starts_with('/') || target.split('/').any(|part| part == "..") {
+        respond(conn, "400 Bad Request", b"bad request\n")
     } else {
-        let id_str = args[0].trim_start_matches('%');
-        match id_str.parse::<usize>() {
-            Ok(id) => id,
-            Err(_) => {
-                syscalls::write(2, b"bg: invalid job id\n");
-                return 1;
+        let path = if target == "/" { "/index.html" } else { target };
+        match c_string(&shell.resolve(&path[1..])).and_then(|path| io::open(&path, O_RDONLY | O_CLOEXEC, 0)) {
+            Ok(file) => {
+                let header = "HTTP/1.0 200 OK\r\nConnection: close\r\n\r\n";
+                let _ = io::write_all(conn, header.as_bytes()).and_then(|()| copy_fd(file, conn));
+                let _ = io::close(file);
+                "200"
             }
+            Err(_) => respond(conn, "404 Not Found", b"not found\n"),
         }
     };
-
-    // Find job
-    let job = if let Some(j) = shell.jobs_mut().get_job(job_id) {
-        j.clone()
-    } else {
-        syscalls::write(2, b"bg: job not found\n");
-        return 1;
+    let peer = match peer {
+        SocketAddr::V4(addr, port) => format!("{}.{}.{}.{}:{}", addr[0], addr[1], addr[2], addr[3], port),
+        SocketAddr::V6(addr, port) => {
+            let group = |i: usize| u16::from_be_bytes([addr[2 * i], addr[2 * i + 1]]);
+            let groups = (0..8).map(|i| format!("{:x}", group(i))).collect::<alloc::vec::Vec<_>>();
+            format!("[{}]:{}", groups.join(":"), port)
+        }
     };
+    println!("{} {} {} {}", peer, method, target, status);
+}
 
-    // Send SIGCONT
-    syscalls::kill(job.pgid, syscalls::SIGCONT);
-
-    // Update job state
-    if let Some(job) = shell.jobs_mut().get_job_mut(job_id) {
-        job.state = JobState::Running;
-        syscalls::write(1, format!("[{}]+ {}    {}\n", job.id, "Running", job.command).as_bytes());
-    }
-
-    0
+/// Send an error response with a short text `body`; returns the status
+/// code for the log
+fn respond(conn: i32, status: &'static str, body: &[u8]) -> &'static str {
+    let header = format!("HTTP/1.0 {}\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n", status);
+    let _ = io::write_all(conn, header.as_bytes()).and_then(|()| io::write_all(conn, body));
+    &status[..3]
 }
 
-/// exit - exit shell
-fn builtin_exit(shell: &mut Shell, args: &[String]) -> i32 {
-    let code = if args.is_empty() {
-        0
-    } else {
-        args[0].parse::<i32>().unwrap_or(0)
-    };
+/// wait - wait for a background process, or for all of them
+fn builtin_wait(args: &[String]) -> i32 {
+    if let Some(arg) = args.first() {
+        let Ok(pid) = arg.parse::<usize>() else {
+            eprintln!("wait: {}: not a pid", arg);
+            return 1;
+        };
+        return match process::wait(pid) {
+            Ok((_, code)) => code,
+            Err(e) => {
+                eprintln!("wait: {}: {}", pid, e);
+                127
+            }
+        };
+    }
 
-    shell.request_exit();
-    code
+    // Until no child is left
+    let mut status = 0;
+    while let Ok((_, code)) = process::wait(0) {
+        status = code;
+    }
+    status
 }
 
 /// which - show command path
 fn builtin_which(shell: &Shell, args: &[String]) -> i32 {
     if args.is_empty() {
-        syscalls::write(2, b"which: missing argument\n");
+        eprintln!("which: missing argument");
         return 1;
     }
 
@@ -338,13 +525,13 @@ fn builtin_which(shell: &Shell, args: &[String]) -> i32 {
 
     for cmd in args {
         // Check if it's a built-in
-        if matches!(cmd.as_str(), "cd" | "pwd" | "echo" | "export" | "unset" | "jobs" | "fg" | "bg" | "exit" | "which" | "debug-pty" | "debug-jobs" | "debug-signals") {
-            syscalls::write(1, format!("{}: shell built-in command\n", cmd).as_bytes());
+        if is_builtin(cmd) {
+            println!("{}: shell built-in command", cmd);
             continue;
         }
 
         // Search PATH environment variable
-        let path_var = shell.get_env("PATH").map(|s| s.as_str()).unwrap_or("/bin");
+        let path_var = shell.get_env("PATH").map_or("/bin", |path| path.as_str());
         let mut found = false;
 
         // Split PATH by colon
@@ -353,29 +540,18 @@ fn builtin_which(shell: &Shell, args: &[String]) -> i32 {
                 continue;
             }
 
-            // Construct full path
-            let mut full_path = String::from(dir);
-            if !full_path.ends_with('/') {
-                full_path.push('/');
-            }
-            full_path.push_str(cmd);
-            full_path.push('\0');
-
             // Try to open the file to check if it exists
-            let fd = syscalls::open(full_path.as_bytes(), syscalls::O_RDONLY, 0);
-            if fd >= 0 {
-                syscalls::close(fd as i32);
-                // Print without null terminator
-                let output = &full_path[..full_path.len() - 1];
-                syscalls::write(1, output.as_bytes());
-                syscalls::write(1, b"\n");
+            let full_path = format!("{}/{}", dir.trim_end_matches('/'), cmd);
+            if let Ok(fd) = c_string(&full_path).and_then(|path| io::open(&path, O_RDONLY | O_CLOEXEC, 0)) {
+                let _ = io::close(fd);
+                println!("{}", full_path);
                 found = true;
                 break;
             }
         }
 
         if !found {
-            syscalls::write(2, format!("which: {}: not found\n", cmd).as_bytes());
+            eprintln!("which: {}: not found", cmd);
             found_all = false;
         }
     }
@@ -383,134 +559,42 @@ fn builtin_which(shell: &Shell, args: &[String]) -> i32 {
     if found_all { 0 } else { 1 }
 }
 
-/// debug-pty - show PTY state from /proc/debug/pty
-fn builtin_debug_pty() -> i32 {
-    syscalls::write(1, b"=== PTY Debug Information ===\n");
-    
-    // Read /proc/debug/pty
-    let path = b"/proc/debug/pty\0";
-    let fd = syscalls::open(path, syscalls::O_RDONLY, 0);
-    
-    if fd < 0 {
-        syscalls::write(2, b"debug-pty: failed to open /proc/debug/pty\n");
-        return 1;
+/// history - list the commands entered so far
+fn builtin_history(shell: &Shell) -> i32 {
+    for (i, command) in shell.history().commands().iter().enumerate() {
+        println!("{:>5}  {}", i + 1, command);
     }
-    
-    // Read and display content
-    let mut buf = [0u8; 4096];
-    loop {
-        let n = syscalls::read(fd as i32, &mut buf);
-        if n <= 0 {
-            break;
-        }
-        syscalls::write(1, &buf[..n as usize]);
-    }
-    
-    syscalls::close(fd as i32);
     0
 }
 
-/// debug-jobs - show detailed job table
-fn builtin_debug_jobs(shell: &Shell) -> i32 {
-    syscalls::write(1, b"=== Job Table Debug Information ===\n");
-    
-    // Get all jobs from the job table
-    let all_jobs = shell.get_all_jobs();
-    
-    if all_jobs.is_empty() {
-        syscalls::write(1, b"No jobs\n");
-        return 0;
-    }
-    
-    for job in all_jobs {
-        // Print job header
-        let header = format!(
-            "[{}] PGID={} State={:?} Background={}\n",
-            job.id, job.pgid, job.state, job.background
-        );
-        syscalls::write(1, header.as_bytes());
-        
-        // Print command
-        syscalls::write(1, b"  Command: ");
-        syscalls::write(1, job.command.as_bytes());
-        syscalls::write(1, b"\n");
-        
-        // Print process list
-        syscalls::write(1, b"  Processes: [");
-        for (i, pid) in job.processes.iter().enumerate() {
-            if i > 0 {
-                syscalls::write(1, b", ");
-            }
-            let pid_str = format!("{}", pid);
-            syscalls::write(1, pid_str.as_bytes());
-        }
-        syscalls::write(1, b"]\n");
-        
-        // Read process state from /proc if available
-        for pid in &job.processes {
-            let proc_path = format!("/proc/{}/stat\0", pid);
-            let fd = syscalls::open(proc_path.as_bytes(), syscalls::O_RDONLY, 0);
-            
-            if fd >= 0 {
-                let mut buf = [0u8; 512];
-                let n = syscalls::read(fd as i32, &mut buf);
-                if n > 0 {
-                    syscalls::write(1, b"    PID ");
-                    let pid_str = format!("{}", pid);
-                    syscalls::write(1, pid_str.as_bytes());
-                    syscalls::write(1, b": ");
-                    syscalls::write(1, &buf[..n as usize]);
-                }
-                syscalls::close(fd as i32);
-            }
-        }
-        
-        syscalls::write(1, b"\n");
+/// debug-pty - show PTY state from /proc/debug/pty
+fn builtin_debug_pty() -> i32 {
+    println!("=== PTY Debug Information ===");
+    if let Err(e) = copy_file("/proc/debug/pty") {
+        eprintln!("debug-pty: /proc/debug/pty: {}", e);
+        return 1;
     }
-    
     0
 }
 
-/// debug-signals - show pending signals for current process
+/// debug-signals - show signal and session state of the shell
 fn builtin_debug_signals() -> i32 {
-    syscalls::write(1, b"=== Signal Debug Information ===\n");
-    
+    println!("=== Signal Debug Information ===");
+
     // Get current PID
-    let pid = syscalls::getpid();
-    let pid_str = format!("Current PID: {}\n", pid);
-    syscalls::write(1, pid_str.as_bytes());
-    
-    // Read /proc/<pid>/status to get signal information
-    let status_path = format!("/proc/{}/status\0", pid);
-    let fd = syscalls::open(status_path.as_bytes(), syscalls::O_RDONLY, 0);
-    
-    if fd < 0 {
-        syscalls::write(2, b"debug-signals: failed to open /proc status\n");
+    let pid = process::getpid().unwrap_or(0);
+    println!("Current PID: {}", pid);
+
+    // /proc/<pid>/status has the signal masks
+    if let Err(e) = copy_file(&format!("/proc/{}/status", pid)) {
+        eprintln!("debug-signals: /proc/{}/status: {}", pid, e);
         return 1;
     }
-    
-    // Read and display content
-    let mut buf = [0u8; 2048];
-    let n = syscalls::read(fd as i32, &mut buf);
-    if n > 0 {
-        syscalls::write(1, &buf[..n as usize]);
-    }
-    
-    syscalls::close(fd as i32);
-    
+
     // Also read /proc/debug/sessions for session info
-    syscalls::write(1, b"\n=== Session Information ===\n");
-    let sessions_path = b"/proc/debug/sessions\0";
-    let fd = syscalls::open(sessions_path, syscalls::O_RDONLY, 0);
-    
-    if fd >= 0 {
-        let mut buf = [0u8; 4096];
-        let n = syscalls::read(fd as i32, &mut buf);
-        if n > 0 {
-            syscalls::write(1, &buf[..n as usize]);
-        }
-        syscalls::close(fd as i32);
-    }
-    
+    println!();
+    println!("=== Session Information ===");
+    let _ = copy_file("/proc/debug/sessions");
+
     0
 }
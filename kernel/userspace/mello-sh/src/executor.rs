@@ -1,310 +1,240 @@
 //! Command executor for mello-sh
-
-use alloc::string::{String, ToString};
-use alloc::vec::Vec;
+//!
+//! Programs are started with `SYS_SPAWN`, which gives the child copies of
+//! the shell's handles except the close-on-exec ones. So to connect a child
+//! to a pipe or a redirected file, the shell points its own standard
+//! streams there around the spawn and restores them afterwards; every other
+//! descriptor it holds is close-on-exec.
+//!
+//! Built-ins run in the shell itself, unless they are part of a pipeline or
+//! run in the background: then they run in a child shell (`mello-sh -c`),
+//! like any other stage.
+
+use alloc::ffi::CString;
 use alloc::format;
-use crate::{Shell, parser::{Command, Redirect, RedirectKind}, syscalls, builtins};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ffi::CStr;
+use mello_libc::io::{self, FD_CLOEXEC, F_SETFD, O_APPEND, O_CLOEXEC, O_CREAT, O_RDONLY, O_WRONLY, STDIN, STDOUT};
+use mello_libc::process;
+use mello_libc::{eprintln, println, Errno};
+
+use crate::parser::{Command, Redirect, RedirectKind};
+use crate::{builtins, Shell};
+
+/// Where the shell itself is in the initrd, for child shells
+const SHELL_PATH: &str = "/bin/mello-sh";
+
+/// Exit status of a command that was not found
+const STATUS_NOT_FOUND: i32 = 127;
 
 /// Execute a command
 pub fn execute(shell: &mut Shell, command: Command) -> Result<i32, String> {
     match command {
-        Command::Simple { args, background, redirects } => {
-            execute_simple(shell, args, background, redirects)
-        }
-        Command::Pipeline { commands, background } => {
-            execute_pipeline(shell, commands, background)
+        Command::Simple { ref args, background: false, ref redirects } if builtins::is_builtin(&args[0]) => {
+            // In the shell, with its streams redirected for the duration
+            let mut redirection = Redirection::new();
+            for redirect in redirects {
+                redirection.apply(shell, redirect)?;
+            }
+            Ok(builtins::execute(shell, &args[0], &args[1..]).unwrap_or(0))
         }
+        Command::Simple { background, .. } => execute_pipeline(shell, core::slice::from_ref(&command), background),
+        Command::Pipeline { commands, background } => execute_pipeline(shell, &commands, background),
     }
 }
 
-/// Execute a simple command
-fn execute_simple(
-    shell: &mut Shell,
-    args: Vec<String>,
-    background: bool,
-    redirects: Vec<Redirect>,
-) -> Result<i32, String> {
-    if args.is_empty() {
-        return Err("empty command".into());
+/// Start every stage of a pipeline, then wait for them unless `background`;
+/// returns the exit status of the last stage
+fn execute_pipeline(shell: &Shell, commands: &[Command], background: bool) -> Result<i32, String> {
+    if commands.is_empty() {
+        return Err("empty pipeline".into());
     }
 
-    let cmd = &args[0];
+    let mut pids = Vec::new();
+    let mut last_spawned = true;
+    // Read end of the pipe from the previous stage
+    let mut stdin = None;
 
-    // Check for built-in commands
-    if let Some(status) = builtins::execute(shell, cmd, &args[1..]) {
-        return Ok(status);
-    }
-
-    // External command - fork and exec
-    let pid = syscalls::fork();
-    
-    if pid < 0 {
-        return Err("fork failed".into());
-    }
+    for (i, cmd) in commands.iter().enumerate() {
+        let Command::Simple { args, redirects, .. } = cmd else {
+            return Err("nested pipelines not supported".into());
+        };
 
-    if pid == 0 {
-        // Child process
-        
-        // Set process group
-        syscalls::setpgid(0, 0);
+        let pipe = if i + 1 < commands.len() {
+            Some(io::pipe2(O_CLOEXEC).map_err(|e| format!("pipe failed: {}", e))?)
+        } else {
+            None
+        };
+        let stdout = pipe.map(|[_, write]| write);
 
-        // Apply redirects
-        for redirect in &redirects {
-            if let Err(e) = apply_redirect(redirect) {
-                syscalls::write(2, format!("redirect failed: {}\n", e).as_bytes());
-                syscalls::exit(1);
-            }
-        }
+        let result = spawn_stage(shell, args, redirects, stdin, stdout);
 
-        // If not background, set as foreground
-        if !background {
-            if let Some(tty_fd) = shell.tty_fd {
-                let pgid = syscalls::getpgrp() as i32;
-                syscalls::tcsetpgrp(tty_fd, pgid);
-            }
+        // The child has its own copies now; closing ours lets each reader
+        // see end of file once its writer exits
+        if let Some(fd) = stdin {
+            let _ = io::close(fd);
         }
-
-        // Execute command
-        let path = format!("/bin/{}\0", cmd);
-        let mut argv = Vec::new();
-        
-        // Build argv
-        for arg in &args {
-            let mut arg_cstr = arg.clone();
-            arg_cstr.push('\0');
-            argv.push(arg_cstr.as_ptr());
+        if let Some(fd) = stdout {
+            let _ = io::close(fd);
         }
-        argv.push(core::ptr::null());
+        stdin = pipe.map(|[read, _]| read);
 
-        // Empty envp for now
-        let envp = [core::ptr::null()];
-
-        syscalls::execve(path.as_bytes(), &argv, &envp);
-
-        // If execve returns, it failed
-        syscalls::write(2, format!("mello-sh: {}: command not found\n", cmd).as_bytes());
-        syscalls::exit(127);
-    } else {
-        // Parent process
-        let child_pid = pid as i32;
-
-        // Set child's process group from parent side
-        syscalls::setpgid(child_pid, child_pid);
-
-        if background {
-            // Add to job table
-            let job_id = shell.jobs_mut().add_job(child_pid, args.join(" "));
-            syscalls::write(1, format!("[{}] {}\n", job_id, child_pid).as_bytes());
-            Ok(0)
-        } else {
-            // Set as foreground group
-            if let Some(tty_fd) = shell.tty_fd {
-                syscalls::tcsetpgrp(tty_fd, child_pid);
+        // A stage that did not start is reported, and the rest still run
+        last_spawned = match result {
+            Ok(pid) => {
+                pids.push(pid);
+                true
             }
-
-            // Wait for child
-            let mut status = 0;
-            let result = syscalls::wait4(child_pid, &mut status, syscalls::WUNTRACED);
-
-            // Restore shell as foreground
-            if let Some(tty_fd) = shell.tty_fd {
-                let shell_pgid = syscalls::getpgrp() as i32;
-                syscalls::tcsetpgrp(tty_fd, shell_pgid);
+            Err(e) => {
+                eprintln!("mello-sh: {}", e);
+                false
             }
+        };
+    }
 
-            if result < 0 {
-                return Err("wait4 failed".into());
-            }
-
-            // Extract exit status
-            let exit_status = if status & 0x7f == 0 {
-                (status >> 8) & 0xff
-            } else {
-                128 + (status & 0x7f)
-            };
-
-            Ok(exit_status)
+    if background {
+        if let Some(pid) = pids.last() {
+            println!("[{}]", pid);
         }
+        return Ok(0);
     }
-}
 
-/// Execute a pipeline
-fn execute_pipeline(
-    shell: &mut Shell,
-    commands: Vec<Command>,
-    background: bool,
-) -> Result<i32, String> {
-    if commands.is_empty() {
-        return Err("empty pipeline".into());
+    let mut status = 0;
+    for pid in pids {
+        status = match process::wait(pid) {
+            Ok((_, code)) => code,
+            Err(e) => return Err(format!("wait failed: {}", e)),
+        };
     }
+    Ok(if last_spawned { status } else { STATUS_NOT_FOUND })
+}
 
-    let n = commands.len();
-    let mut pipes: Vec<[i32; 2]> = Vec::new();
-    let mut pids: Vec<i32> = Vec::new();
-
-    // Create pipes
-    for _ in 0..n - 1 {
-        let mut pipe_fds = [0, 0];
-        if syscalls::pipe(&mut pipe_fds) < 0 {
-            return Err("pipe failed".into());
-        }
-        pipes.push(pipe_fds);
+/// Spawn one stage with `stdin` and `stdout` as its standard streams, if
+/// given, then its redirects; returns its PID
+fn spawn_stage(
+    shell: &Shell,
+    args: &[String],
+    redirects: &[Redirect],
+    stdin: Option<i32>,
+    stdout: Option<i32>,
+) -> Result<usize, String> {
+    let mut redirection = Redirection::new();
+    if let Some(fd) = stdin {
+        redirection.redirect(STDIN, fd).map_err(|e| format!("cannot redirect stdin: {}", e))?;
+    }
+    if let Some(fd) = stdout {
+        redirection.redirect(STDOUT, fd).map_err(|e| format!("cannot redirect stdout: {}", e))?;
+    }
+    for redirect in redirects {
+        redirection.apply(shell, redirect)?;
     }
 
-    // Fork children
-    for (i, cmd) in commands.iter().enumerate() {
-        let pid = syscalls::fork();
-        
-        if pid < 0 {
-            return Err("fork failed".into());
-        }
-
-        if pid == 0 {
-            // Child process
-            
-            // Set up stdin from previous pipe
-            if i > 0 {
-                syscalls::dup2(pipes[i - 1][0], 0);
-            }
-
-            // Set up stdout to next pipe
-            if i < n - 1 {
-                syscalls::dup2(pipes[i][1], 1);
-            }
-
-            // Close all pipe fds
-            for pipe in &pipes {
-                syscalls::close(pipe[0]);
-                syscalls::close(pipe[1]);
-            }
-
-            // Execute command (simplified - only simple commands in pipeline)
-            if let Command::Simple { args, redirects, .. } = cmd {
-                // Apply redirects
-                for redirect in redirects {
-                    if let Err(e) = apply_redirect(redirect) {
-                        syscalls::write(2, format!("redirect failed: {}\n", e).as_bytes());
-                        syscalls::exit(1);
-                    }
-                }
-
-                // Execute
-                let cmd_name = &args[0];
-                let path = format!("/bin/{}\0", cmd_name);
-                let mut argv = Vec::new();
-                
-                for arg in args {
-                    let mut arg_cstr = arg.clone();
-                    arg_cstr.push('\0');
-                    argv.push(arg_cstr.as_ptr());
+    if builtins::is_builtin(&args[0]) {
+        // A child shell runs the built-in; quote the arguments so it
+        // parses them back unchanged
+        let mut line = String::new();
+        for arg in args {
+            line.push_str(" \"");
+            for ch in arg.chars() {
+                if ch == '"' || ch == '\\' {
+                    line.push('\\');
                 }
-                argv.push(core::ptr::null());
-
-                let envp = [core::ptr::null()];
-                syscalls::execve(path.as_bytes(), &argv, &envp);
-
-                syscalls::write(2, format!("mello-sh: {}: command not found\n", cmd_name).as_bytes());
-                syscalls::exit(127);
-            } else {
-                syscalls::write(2, b"nested pipelines not supported\n");
-                syscalls::exit(1);
+                line.push(ch);
             }
-        } else {
-            // Parent - save PID
-            pids.push(pid as i32);
+            line.push('"');
+        }
+        let argv = [String::from("mello-sh"), String::from("-c"), line];
+        return spawn(SHELL_PATH, &argv).map_err(|e| format!("{}: {}", args[0], e));
+    }
 
-            // Set process group (first child becomes group leader)
-            if i == 0 {
-                syscalls::setpgid(pid as i32, pid as i32);
-            } else {
-                syscalls::setpgid(pid as i32, pids[0]);
-            }
+    let mut last_error = Errno::ENOENT;
+    for path in program_paths(shell, &args[0]) {
+        match spawn(&path, args) {
+            Ok(pid) => return Ok(pid),
+            // Not in this directory; try the next
+            Err(Errno::ENOENT) => {}
+            Err(e) => last_error = e,
         }
     }
+    if last_error == Errno::ENOENT {
+        Err(format!("{}: command not found", args[0]))
+    } else {
+        Err(format!("{}: {}", args[0], last_error))
+    }
+}
 
-    // Close all pipes in parent
-    for pipe in &pipes {
-        syscalls::close(pipe[0]);
-        syscalls::close(pipe[1]);
+/// Paths to try for program `name`: itself if it has a slash, else `name`
+/// in each directory of `PATH`
+fn program_paths(shell: &Shell, name: &str) -> Vec<String> {
+    if name.contains('/') {
+        return alloc::vec![shell.resolve(name)];
     }
+    let path_var = shell.get_env("PATH").map_or("/bin", |path| path.as_str());
+    path_var
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| format!("{}/{}", dir.trim_end_matches('/'), name))
+        .collect()
+}
 
-    let pgid = pids[0];
+/// Spawn the program at `path` with `args`
+fn spawn(path: &str, args: &[String]) -> Result<usize, Errno> {
+    let path = c_string(path)?;
+    let args = args.iter().map(|arg| c_string(arg)).collect::<Result<Vec<_>, _>>()?;
+    let argv: Vec<&CStr> = args.iter().map(|arg| arg.as_c_str()).collect();
+    process::spawn(&path, &argv)
+}
 
-    if background {
-        // Add to job table
-        let job_id = shell.jobs_mut().add_job(pgid, "pipeline".to_string());
-        syscalls::write(1, format!("[{}] {}\n", job_id, pgid).as_bytes());
-        Ok(0)
-    } else {
-        // Set as foreground group
-        if let Some(tty_fd) = shell.tty_fd {
-            syscalls::tcsetpgrp(tty_fd, pgid);
-        }
+/// `s` as a C string; strings with a NUL cannot be passed on
+pub fn c_string(s: &str) -> Result<CString, Errno> {
+    CString::new(s).map_err(|_| Errno::EINVAL)
+}
 
-        // Wait for all children
-        let mut last_status = 0;
-        for _ in 0..n {
-            let mut status = 0;
-            syscalls::wait4(-1, &mut status, 0);
-            last_status = status;
-        }
+/// The shell's standard streams pointed elsewhere, restored on drop
+struct Redirection {
+    /// Each redirected stream and a close-on-exec copy of what it was
+    saved: Vec<(i32, i32)>,
+}
+
+impl Redirection {
+    fn new() -> Self {
+        Self { saved: Vec::new() }
+    }
 
-        // Restore shell as foreground
-        if let Some(tty_fd) = shell.tty_fd {
-            let shell_pgid = syscalls::getpgrp() as i32;
-            syscalls::tcsetpgrp(tty_fd, shell_pgid);
+    /// Point stream `target` at `fd`
+    fn redirect(&mut self, target: i32, fd: i32) -> Result<(), Errno> {
+        if !self.saved.iter().any(|&(saved, _)| saved == target) {
+            let copy = io::dup(target)?;
+            io::fcntl(copy, F_SETFD, FD_CLOEXEC)?;
+            self.saved.push((target, copy));
         }
+        io::dup2(fd, target)?;
+        Ok(())
+    }
 
-        // Extract exit status of last command
-        let exit_status = if last_status & 0x7f == 0 {
-            (last_status >> 8) & 0xff
-        } else {
-            128 + (last_status & 0x7f)
+    /// Open the file of `redirect` and point the stream at it
+    fn apply(&mut self, shell: &Shell, redirect: &Redirect) -> Result<(), String> {
+        let (target, flags) = match redirect.kind {
+            RedirectKind::Input => (STDIN, O_RDONLY),
+            RedirectKind::Output => (STDOUT, O_WRONLY | O_CREAT),
+            RedirectKind::Append => (STDOUT, O_WRONLY | O_CREAT | O_APPEND),
         };
-
-        Ok(exit_status)
+        let path = shell.resolve(&redirect.target);
+        let fd = c_string(&path)
+            .and_then(|path| io::open(&path, flags | O_CLOEXEC, 0o644))
+            .map_err(|e| format!("{}: {}", redirect.target, e))?;
+        let result = self.redirect(target, fd);
+        let _ = io::close(fd);
+        result.map_err(|e| format!("{}: {}", redirect.target, e))
     }
 }
 
-/// Apply a redirect
-fn apply_redirect(redirect: &Redirect) -> Result<(), String> {
-    let mut path = redirect.target.clone();
-    path.push('\0');
-
-    match redirect.kind {
-        RedirectKind::Input => {
-            let fd = syscalls::open(path.as_bytes(), syscalls::O_RDONLY, 0);
-            if fd < 0 {
-                return Err("cannot open input file".into());
-            }
-            syscalls::dup2(fd as i32, 0);
-            syscalls::close(fd as i32);
-        }
-        RedirectKind::Output => {
-            let fd = syscalls::open(
-                path.as_bytes(),
-                syscalls::O_WRONLY | syscalls::O_CREAT | syscalls::O_TRUNC,
-                0o644,
-            );
-            if fd < 0 {
-                return Err("cannot open output file".into());
-            }
-            syscalls::dup2(fd as i32, 1);
-            syscalls::close(fd as i32);
-        }
-        RedirectKind::Append => {
-            let fd = syscalls::open(
-                path.as_bytes(),
-                syscalls::O_WRONLY | syscalls::O_CREAT | syscalls::O_APPEND,
-                0o644,
-            );
-            if fd < 0 {
-                return Err("cannot open output file".into());
-            }
-            syscalls::dup2(fd as i32, 1);
-            syscalls::close(fd as i32);
+impl Drop for Redirection {
+    fn drop(&mut self) {
+        for (target, copy) in self.saved.drain(..).rev() {
+            let _ = io::dup2(copy, target);
+            let _ = io::close(copy);
         }
     }
-
-    Ok(())
 }
@@ -1,34 +1,38 @@
 //! Mello-sh - Shell for MelloOS
 //!
-//! A POSIX-like shell with job control, pipelines, and I/O redirection.
+//! An interactive shell over the console: it reads command lines from
+//! standard input, runs programs from the initrd with `SYS_SPAWN`, connects
+//! pipelines with pipes, and has built-ins for what no program does yet
+//! (see [`builtins`]).
+//!
+//! `mello-sh -c <command>` runs a single command line instead.
+//!
+//! The kernel has no working directory of its own, so the shell keeps one
+//! in `PWD` and resolves relative paths against it.
 
 #![no_std]
 #![no_main]
-#![feature(alloc_error_handler)]
 
 extern crate alloc;
 
+use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
-use alloc::collections::BTreeMap;
-use core::panic::PanicInfo;
+use mello_libc::io::{self, STDIN, STDOUT};
+use mello_libc::{eprintln, process};
 
-mod allocator;
-mod parser;
-mod executor;
-mod jobs;
 mod builtins;
+mod executor;
 mod history;
-mod syscalls;
+mod parser;
 
-use jobs::JobTable;
 use history::History;
 
+mello_libc::entry!(main);
+
 /// Shell state
 pub struct Shell {
-    jobs: JobTable,
     history: History,
-    tty_fd: Option<i32>,
     exit_requested: bool,
     env: BTreeMap<String, String>,
 }
@@ -37,23 +41,17 @@ impl Shell {
     /// Create a new shell instance
     pub fn new() -> Self {
         let mut env = BTreeMap::new();
-        
+
         // Initialize default environment variables
         env.insert(String::from("HOME"), String::from("/"));
         env.insert(String::from("PATH"), String::from("/bin"));
         env.insert(String::from("PWD"), String::from("/"));
-        
+
         // Set default locale to C.UTF-8 for proper UTF-8 support
-        // This ensures all components handle UTF-8 correctly
         env.insert(String::from("LANG"), String::from("C.UTF-8"));
-        
-        // Support for Thai language (th_TH.UTF-8) can be set by user
-        // via 'export LANG=th_TH.UTF-8' command
-        
+
         Self {
-            jobs: JobTable::new(),
             history: History::new(),
-            tty_fd: None,
             exit_requested: false,
             env,
         }
@@ -61,14 +59,9 @@ impl Shell {
 
     /// Main shell loop
     pub fn run(&mut self) -> i32 {
-        // Open controlling terminal (stdin/stdout/stderr)
-        // For now, we assume stdin=0, stdout=1, stderr=2 are already set up
-        self.tty_fd = Some(0);
+        let mut status = 0;
 
         loop {
-            // Check for completed jobs
-            self.jobs.check_jobs();
-
             // Display prompt
             self.display_prompt();
 
@@ -86,61 +79,73 @@ impl Shell {
             // Add to history
             self.history.add(line.clone());
 
-            // Parse command
-            let command = match parser::parse(&line) {
-                Ok(cmd) => cmd,
-                Err(e) => {
-                    self.print_error(&e);
-                    continue;
-                }
-            };
-
-            // Execute command
-            match executor::execute(self, command) {
-                Ok(status) => {
-                    if self.exit_requested {
-                        return status;
-                    }
-                }
-                Err(e) => {
-                    self.print_error(&e);
-                }
+            status = self.run_line(&line);
+            if self.exit_requested {
+                break;
             }
         }
 
-        0
+        status
+    }
+
+    /// Parse and execute one command line; returns its exit status
+    pub fn run_line(&mut self, line: &str) -> i32 {
+        // Parse command
+        let command = match parser::parse(line) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                self.print_error(&e);
+                return 2;
+            }
+        };
+
+        // Execute command
+        match executor::execute(self, command) {
+            Ok(status) => status,
+            Err(e) => {
+                self.print_error(&e);
+                1
+            }
+        }
     }
 
     /// Display shell prompt
     fn display_prompt(&self) {
-        // Format: [user@host cwd]$
-        // For now, simplified version
-        syscalls::write(1, b"mello-sh$ ");
+        let cwd = self.cwd();
+        let _ = io::write_all(STDOUT, b"mello-sh:");
+        let _ = io::write_all(STDOUT, cwd.as_bytes());
+        let _ = io::write_all(STDOUT, b"$ ");
     }
 
     /// Read a line from stdin
+    ///
+    /// The console delivers raw bytes, so the shell echoes what is typed
+    /// and does the line editing itself.
     fn read_line(&self) -> Result<String, &'static str> {
         let mut buffer = Vec::new();
         let mut byte = [0u8; 1];
 
         loop {
-            let n = syscalls::read(0, &mut byte);
-            if n <= 0 {
-                if buffer.is_empty() {
-                    return Err("EOF");
+            match io::read(STDIN, &mut byte) {
+                Ok(1) => {}
+                _ => {
+                    if buffer.is_empty() {
+                        return Err("EOF");
+                    }
+                    break;
                 }
-                break;
             }
 
             let ch = byte[0];
-            
+
             // Handle Ctrl-D (EOF)
             if ch == 4 && buffer.is_empty() {
                 return Err("EOF");
             }
 
-            // Handle newline
-            if ch == b'\n' {
+            // Handle newline; serial terminals send CR for Enter
+            if ch == b'\n' || ch == b'\r' {
+                let _ = io::write_all(STDOUT, b"\n");
                 break;
             }
 
@@ -149,12 +154,18 @@ impl Shell {
                 if !buffer.is_empty() {
                     buffer.pop();
                     // Echo backspace sequence
-                    syscalls::write(1, b"\x08 \x08");
+                    let _ = io::write_all(STDOUT, b"\x08 \x08");
                 }
                 continue;
             }
 
+            // Ignore other control characters
+            if ch < 0x20 && ch != b'\t' {
+                continue;
+            }
+
             buffer.push(ch);
+            let _ = io::write_all(STDOUT, &byte);
         }
 
         String::from_utf8(buffer).map_err(|_| "Invalid UTF-8")
@@ -162,9 +173,7 @@ impl Shell {
 
     /// Print error message
     fn print_error(&self, msg: &str) {
-        syscalls::write(2, b"mello-sh: ");
-        syscalls::write(2, msg.as_bytes());
-        syscalls::write(2, b"\n");
+        eprintln!("mello-sh: {}", msg);
     }
 
     /// Request shell exit
@@ -172,16 +181,41 @@ impl Shell {
         self.exit_requested = true;
     }
 
-    /// Get job table reference
-    pub fn jobs_mut(&mut self) -> &mut JobTable {
-        &mut self.jobs
-    }
-
     /// Get history reference
     pub fn history(&self) -> &History {
         &self.history
     }
 
+    /// Current working directory, from `PWD`
+    pub fn cwd(&self) -> &str {
+        self.env.get("PWD").map_or("/", |pwd| pwd.as_str())
+    }
+
+    /// Absolute, normalized form of `path`, relative paths being taken
+    /// from the working directory
+    pub fn resolve(&self, path: &str) -> String {
+        let mut parts: Vec<&str> = Vec::new();
+        let base = if path.starts_with('/') { "" } else { self.cwd() };
+        for part in base.split('/').chain(path.split('/')) {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    parts.pop();
+                }
+                part => parts.push(part),
+            }
+        }
+        let mut resolved = String::new();
+        for part in &parts {
+            resolved.push('/');
+            resolved.push_str(part);
+        }
+        if resolved.is_empty() {
+            resolved.push('/');
+        }
+        resolved
+    }
+
     /// Get environment variable
     pub fn get_env(&self, key: &str) -> Option<&String> {
         self.env.get(key)
@@ -201,41 +235,25 @@ impl Shell {
     pub fn env(&self) -> &BTreeMap<String, String> {
         &self.env
     }
-
-    /// Get all jobs (for debugging)
-    pub fn get_all_jobs(&self) -> alloc::vec::Vec<&jobs::Job> {
-        self.jobs.all_jobs()
-    }
 }
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-    // Initialize allocator
-    allocator::init();
-
-    // Create and run shell
+fn main() -> i32 {
     let mut shell = Shell::new();
-    let exit_code = shell.run();
-
-    // Exit
-    syscalls::exit(exit_code);
-}
 
-#[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
-    // Try to print panic message
-    if let Some(location) = info.location() {
-        let msg = alloc::format!("panic at {}:{}\n", location.file(), location.line());
-        syscalls::write(2, msg.as_bytes());
-    } else {
-        syscalls::write(2, b"panic occurred\n");
+    // `mello-sh -c <command>` runs one command line, as the shell does for
+    // built-ins in pipelines
+    let mut args = process::args().skip(1);
+    if let Some(flag) = args.next() {
+        if flag.to_bytes() != b"-c" {
+            eprintln!("usage: mello-sh [-c command]");
+            return 2;
+        }
+        let Some(line) = args.next().and_then(|line| line.to_str().ok()) else {
+            eprintln!("mello-sh: -c: missing command");
+            return 2;
+        };
+        return shell.run_line(line);
     }
-    
-    syscalls::exit(1);
-}
 
-#[alloc_error_handler]
-fn alloc_error(_layout: core::alloc::Layout) -> ! {
-    syscalls::write(2, b"out of memory\n");
-    syscalls::exit(1);
+    shell.run()
 }
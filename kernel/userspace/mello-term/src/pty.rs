@@ -162,3 +162,5 @@ pub struct Winsize {
     pub ws_xpixel: u16,
     pub ws_ypixel: u16,
 }
+
+mello_abi::check_layout!(Winsize, mello_abi::Winsize { ws_row, ws_col, ws_xpixel, ws_ypixel });
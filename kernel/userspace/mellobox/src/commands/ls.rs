@@ -16,6 +16,8 @@ struct DirEnt {
     // d_name follows
 }
 
+mello_abi::check_layout!(DirEnt, mello_abi::Dirent { d_ino, d_off, d_reclen, d_type });
+
 // File stat structure (simplified)
 #[repr(C)]
 struct Stat {
@@ -34,6 +36,11 @@ struct Stat {
     st_ctime: i64,
 }
 
+mello_abi::check_layout!(Stat, mello_abi::Stat {
+    st_dev, st_ino, st_mode, st_nlink, st_uid, st_gid, st_rdev, st_size,
+    st_blksize, st_blocks, st_atime, st_mtime, st_ctime,
+});
+
 // File type constants
 #[allow(dead_code)]
 const DT_UNKNOWN: u8 = 0;
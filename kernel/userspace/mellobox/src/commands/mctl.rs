@@ -0,0 +1,125 @@
+//! mctl - control the services supervised by init
+//!
+//! Usage: `mctl start|stop|restart|status <service>`, or `mctl list`.
+//!
+//! Talks to init over IPC: the request goes to init's service port together
+//! with a send capability for a private reply port, and init answers there.
+
+use crate::error::{Error, Result};
+use crate::syscalls;
+use alloc::format;
+
+// Service manager protocol (must match init/src/protocol.rs)
+const SERVICE_PORT: usize = 10;
+const OP_START: u8 = 1;
+const OP_STOP: u8 = 2;
+const OP_RESTART: u8 = 3;
+const OP_STATUS: u8 = 4;
+const OP_LIST: u8 = 5;
+const STATUS_OK: u8 = 0;
+const STATUS_UNKNOWN_SERVICE: u8 = 1;
+const STATUS_DEPENDENCY: u8 = 3;
+const STATUS_SPAWN_FAILED: u8 = 4;
+const MAX_NAME: usize = 32;
+const MAX_REPLY: usize = 512;
+
+pub fn main(argv: &'static [&'static str]) -> Result<i32> {
+    let (op, name) = match argv.get(1..) {
+        Some(&["list"]) => (OP_LIST, ""),
+        Some(&["start", name]) => (OP_START, name),
+        Some(&["stop", name]) => (OP_STOP, name),
+        Some(&["restart", name]) => (OP_RESTART, name),
+        Some(&["status", name]) => (OP_STATUS, name),
+        Some(&[]) | Some(&[_]) => return Err(Error::MissingArgument),
+        _ => return Err(Error::InvalidArgument),
+    };
+    if name.len() > MAX_NAME {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut request = [0u8; 2 + MAX_NAME];
+    request[0] = op;
+    request[1] = name.len() as u8;
+    request[2..2 + name.len()].copy_from_slice(name.as_bytes());
+
+    let mut reply = [0u8; MAX_REPLY];
+    let len = call(&request[..2 + name.len()], &mut reply)?;
+    let reply = &reply[..len];
+    if reply.len() < 2 {
+        return Err(Error::IoError);
+    }
+
+    print_records(&reply[2..], reply[1]);
+    match reply[0] {
+        STATUS_OK => Ok(0),
+        STATUS_UNKNOWN_SERVICE => {
+            crate::error::print_usage_error("mctl", "no such service");
+            Ok(1)
+        }
+        STATUS_DEPENDENCY => {
+            crate::error::print_usage_error("mctl", "a dependency could not be started");
+            Ok(1)
+        }
+        STATUS_SPAWN_FAILED => {
+            crate::error::print_usage_error("mctl", "could not start the service");
+            Ok(1)
+        }
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// Send `request` to init and wait for the reply; returns its length
+fn call(request: &[u8], reply: &mut [u8]) -> Result<usize> {
+    let port = syscalls::port_create();
+    if port < 0 {
+        return Err(Error::SyscallFailed(port));
+    }
+    let port = port as usize;
+
+    // init gets a send capability for our port to answer on
+    let reply_cap = syscalls::cap_derive(port, syscalls::CAP_SEND | syscalls::CAP_GRANT);
+    if reply_cap < 0 {
+        syscalls::cap_drop(port);
+        return Err(Error::SyscallFailed(reply_cap));
+    }
+    let grant = (reply_cap as usize + 1) << syscalls::IPC_CAP_SHIFT;
+    let sent = syscalls::ipc_send(SERVICE_PORT | grant, request);
+    syscalls::cap_drop(reply_cap as usize);
+
+    let received = if sent < 0 { sent } else { syscalls::ipc_recv(port, reply) };
+    syscalls::cap_drop(port);
+    if received < 0 {
+        return Err(Error::SyscallFailed(received));
+    }
+    Ok(received as usize & 0xffff_ffff)
+}
+
+/// Print `count` service records: `[state, restarts, pid (u32 LE),
+/// name_len, name...]`
+fn print_records(mut records: &[u8], count: u8) {
+    for _ in 0..count {
+        if records.len() < 7 {
+            return;
+        }
+        let state = match records[0] {
+            0 => "stopped",
+            1 => "running",
+            2 => "backoff",
+            3 => "failed",
+            _ => "unknown",
+        };
+        let restarts = records[1];
+        let pid = u32::from_le_bytes([records[2], records[3], records[4], records[5]]);
+        let name_len = records[6] as usize;
+        let Some(name) = records.get(7..7 + name_len) else { return };
+        let name = core::str::from_utf8(name).unwrap_or("?");
+
+        let line = if pid != 0 {
+            format!("{:<12} {:<8} pid {:<5} restarts {}\n", name, state, pid, restarts)
+        } else {
+            format!("{:<12} {:<8} {:<9} restarts {}\n", name, state, "-", restarts)
+        };
+        syscalls::write(1, line.as_bytes());
+        records = &records[7 + name_len..];
+    }
+}
@@ -8,6 +8,7 @@ pub mod cat;
 pub mod grep;
 pub mod ps;
 pub mod kill;
+pub mod mctl;
 pub mod mkdir;
 pub mod touch;
 pub mod echo;
@@ -16,6 +16,8 @@ struct DirEnt {
     // d_name follows
 }
 
+mello_abi::check_layout!(DirEnt, mello_abi::Dirent { d_ino, d_off, d_reclen, d_type });
+
 pub fn main(argv: &'static [&'static str]) -> Result<i32> {
     let args = Args::parse(argv, "aux")?;
     
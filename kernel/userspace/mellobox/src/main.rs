@@ -35,6 +35,7 @@ const APPLETS: &[Applet] = &[
     Applet { name: "grep", func: commands::grep::main },
     Applet { name: "ps", func: commands::ps::main },
     Applet { name: "kill", func: commands::kill::main },
+    Applet { name: "mctl", func: commands::mctl::main },
     Applet { name: "mkdir", func: commands::mkdir::main },
     Applet { name: "touch", func: commands::touch::main },
     Applet { name: "echo", func: commands::echo::main },
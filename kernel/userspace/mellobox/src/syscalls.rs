@@ -21,6 +21,14 @@ const SYS_RMDIR: usize = 84;
 const SYS_GETCWD: usize = 79;
 const SYS_KILL: usize = 62;
 
+// MelloOS IPC system calls (no Linux equivalent; numbers from the kernel's
+// own table)
+const SYS_IPC_SEND: usize = 3;
+const SYS_IPC_RECV: usize = 4;
+const SYS_PORT_CREATE: usize = 37;
+const SYS_CAP_DERIVE: usize = 38;
+const SYS_CAP_DROP: usize = 39;
+
 /// Raw system call with 0 arguments
 #[inline]
 unsafe fn syscall0(n: usize) -> isize {
@@ -182,6 +190,39 @@ pub fn kill(pid: i32, sig: i32) -> isize {
     unsafe { syscall2(SYS_KILL, pid as usize, sig as usize) }
 }
 
+/// Send a message to the port behind capability `cap`; `(h + 1) <<
+/// IPC_CAP_SHIFT` OR-ed into `cap` passes capability `h` along with it
+pub fn ipc_send(cap: usize, data: &[u8]) -> isize {
+    unsafe { syscall3(SYS_IPC_SEND, cap, data.as_ptr() as usize, data.len()) }
+}
+
+/// Receive a message (blocking); a received capability comes back as
+/// `(h + 1) << IPC_CAP_SHIFT` OR-ed into the length
+pub fn ipc_recv(cap: usize, buf: &mut [u8]) -> isize {
+    unsafe { syscall3(SYS_IPC_RECV, cap, buf.as_mut_ptr() as usize, buf.len()) }
+}
+
+/// Create a port; returns a capability handle with all rights
+pub fn port_create() -> isize {
+    unsafe { syscall0(SYS_PORT_CREATE) }
+}
+
+/// Copy a capability, keeping only `rights`
+pub fn cap_derive(cap: usize, rights: usize) -> isize {
+    unsafe { syscall2(SYS_CAP_DERIVE, cap, rights) }
+}
+
+/// Remove a capability from this task's table
+pub fn cap_drop(cap: usize) -> isize {
+    unsafe { syscall1(SYS_CAP_DROP, cap) }
+}
+
+// Capability rights and encoding
+pub const CAP_SEND: usize = 1 << 0;
+pub const CAP_RECV: usize = 1 << 1;
+pub const CAP_GRANT: usize = 1 << 2;
+pub const IPC_CAP_SHIFT: u32 = 32;
+
 // Open flags
 pub const O_RDONLY: i32 = 0;
 pub const O_WRONLY: i32 = 1;
@@ -0,0 +1,95 @@
+#!/usr/bin/env python3
+"""Convert a MelloOS tracepoint dump to Chrome trace-event JSON.
+
+Capture the serial log while pressing SysRq (Ctrl-O) then `e`, and run:
+
+    ./trace2json.py serial.log > trace.json
+
+Open trace.json in chrome://tracing or https://ui.perfetto.dev. Each CPU
+gets a track showing which task ran (from sched_switch). Each task gets a
+track with its syscalls as slices (syscall_enter/syscall_exit). Every
+other event is an instant marker on its CPU's track. Only the last dump in
+the log is used.
+"""
+
+import argparse
+import json
+import sys
+
+CPUS_PID = 0
+TASKS_PID = 1
+
+
+def last_dump(lines):
+    """Records of the last complete dump: (cpu, ns, task, event, args, site)"""
+    records, current = None, None
+    for line in lines:
+        _, marker, rest = line.partition("[TRACE] ")
+        if not marker:
+            continue
+        fields = rest.split()
+        if fields[:1] == ["begin"]:
+            current = []
+        elif fields[:1] == ["end"]:
+            if current is not None:
+                records = current
+            current = None
+        elif current is not None and len(fields) >= 7:
+            cpu, ns, task, event = int(fields[0]), int(fields[1]), int(fields[2]), fields[3]
+            site = " ".join(fields[7:])
+            current.append((cpu, ns, task, event, [int(arg) for arg in fields[4:7]], site))
+    return records or []
+
+
+def convert(records):
+    events = []
+    running = {}  # CPU -> task on it, from sched_switch
+    seen_cpus, seen_tasks = set(), set()
+
+    for cpu, ns, task, event, args, site in sorted(records, key=lambda record: record[1]):
+        ts = ns / 1000.0
+        seen_cpus.add(cpu)
+        if event == "sched_switch":
+            prev, next_task = args[0], args[1]
+            if running.get(cpu) is not None:
+                events.append({"ph": "E", "pid": CPUS_PID, "tid": cpu, "ts": ts})
+            events.append({"ph": "B", "pid": CPUS_PID, "tid": cpu, "ts": ts, "name": f"task {next_task}",
+                           "args": {"prev": prev, "prev_state": args[2]}})
+            running[cpu] = next_task
+        elif event == "syscall_enter":
+            seen_tasks.add(task)
+            events.append({"ph": "B", "pid": TASKS_PID, "tid": task, "ts": ts, "name": f"syscall {args[0]}",
+                           "args": {"arg1": args[1], "arg2": args[2]}})
+        elif event == "syscall_exit":
+            seen_tasks.add(task)
+            events.append({"ph": "E", "pid": TASKS_PID, "tid": task, "ts": ts, "args": {"result": args[1]}})
+        else:
+            events.append({"ph": "i", "s": "t", "pid": CPUS_PID, "tid": cpu, "ts": ts, "name": event,
+                           "args": {"task": task, "arg0": args[0], "arg1": args[1], "arg2": args[2],
+                                    "site": site}})
+
+    metadata = [
+        {"ph": "M", "pid": CPUS_PID, "name": "process_name", "args": {"name": "CPUs"}},
+        {"ph": "M", "pid": TASKS_PID, "name": "process_name", "args": {"name": "Tasks"}},
+    ]
+    metadata += [{"ph": "M", "pid": CPUS_PID, "tid": cpu, "name": "thread_name", "args": {"name": f"CPU {cpu}"}}
+                 for cpu in sorted(seen_cpus)]
+    metadata += [{"ph": "M", "pid": TASKS_PID, "tid": task, "name": "thread_name", "args": {"name": f"task {task}"}}
+                 for task in sorted(seen_tasks)]
+    return {"traceEvents": metadata + events, "displayTimeUnit": "ns"}
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("log", nargs="?", help="serial log holding the dump (default: stdin)")
+    options = parser.parse_args()
+    source = open(options.log, errors="replace") if options.log else sys.stdin
+    records = last_dump(source)
+    if not records:
+        sys.exit("no complete [TRACE] dump found")
+    json.dump(convert(records), sys.stdout)
+    print()
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,94 @@
+#!/usr/bin/env python3
+"""Write the kernel symbol table into a linked MelloOS kernel.
+
+The kernel reserves the `.ksyms` section for a table of its functions,
+which it uses to name addresses in backtraces, profiles and trace dumps.
+After linking, run:
+
+    ./ksyms.py kernel/target/x86_64-unknown-none/release/mellos-kernel
+
+Every symbol `nm` lists in `.text` goes into the table, sorted by address,
+with its demangled name (one per address). The ELF is patched in place;
+running the script again rewrites the table. See kernel/src/ksyms.rs for
+the layout.
+"""
+
+import argparse
+import os
+import struct
+import subprocess
+import sys
+
+MAGIC = b"KSYM"
+HEADER = struct.Struct("<4sIII")
+ENTRY = struct.Struct("<QII")
+
+
+def sections(elf):
+    """Name -> (address, file offset, size) of each ELF64 section"""
+    if elf[:4] != b"\x7fELF" or elf[4] != 2 or elf[5] != 1:
+        sys.exit("not a little-endian ELF64 file")
+    shoff, = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3a)
+    headers = [struct.unpack_from("<IIQQQQ", elf, shoff + i * shentsize) for i in range(shnum)]
+    names_offset = headers[shstrndx][4]
+    result = {}
+    for name, _, _, address, offset, size in headers:
+        end = elf.index(b"\0", names_offset + name)
+        result[elf[names_offset + name:end].decode()] = (address, offset, size)
+    return result
+
+
+def text_symbols(path, start, end):
+    """Sorted (address, name) of the symbols in [start, end)"""
+    nm = os.environ.get("NM", "nm")
+    output = subprocess.run([nm, "--numeric-sort", "--demangle", "--defined-only", path],
+                            check=True, capture_output=True, text=True).stdout
+    symbols = []
+    for line in output.splitlines():
+        fields = line.split(maxsplit=2)
+        if len(fields) != 3 or fields[1] not in "tTwW":
+            continue
+        address = int(fields[0], 16)
+        if start <= address < end and (not symbols or symbols[-1][0] != address):
+            symbols.append((address, fields[2]))
+    return symbols
+
+
+def build_table(symbols):
+    names = bytearray()
+    entries = bytearray()
+    for address, name in symbols:
+        encoded = name.encode()
+        entries += ENTRY.pack(address, len(names), len(encoded))
+        names += encoded
+    names_offset = HEADER.size + len(entries)
+    return HEADER.pack(MAGIC, len(symbols), names_offset, len(names)) + entries + names
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("kernel", help="linked kernel ELF, patched in place")
+    options = parser.parse_args()
+
+    with open(options.kernel, "rb") as f:
+        elf = bytearray(f.read())
+    found = sections(elf)
+    if ".ksyms" not in found or ".text" not in found:
+        sys.exit(f"{options.kernel}: no .ksyms or .text section")
+    text_address, _, text_size = found[".text"]
+    _, offset, size = found[".ksyms"]
+
+    symbols = text_symbols(options.kernel, text_address, text_address + text_size)
+    table = build_table(symbols)
+    if len(table) > size:
+        sys.exit(f"symbol table needs {len(table)} bytes, .ksyms has {size}; raise ksyms::TABLE_SIZE")
+    elf[offset:offset + size] = table + bytes(size - len(table))
+
+    with open(options.kernel, "r+b") as f:
+        f.write(elf)
+    print(f"{options.kernel}: {len(symbols)} symbols, {len(table)} of {size} bytes")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,50 @@
+#!/bin/bash
+
+# Run the in-kernel test suite headlessly in QEMU
+# The kernel is booted with the 'ktest' command line flag, runs every
+# kernel_test! case, and reports the result through isa-debug-exit.
+# QEMU exit status 33 means all tests passed, 35 means a test failed.
+
+ISO="${1:-mellos-ktest.iso}"
+SMP_CPUS="${SMP_CPUS:-2}"
+TEST_TIMEOUT="${TEST_TIMEOUT:-60}"
+
+echo "Running MelloOS kernel tests..."
+echo "================================"
+
+if [ ! -f "$ISO" ]; then
+    echo "Error: $ISO not found. Run 'make ktest' first."
+    exit 1
+fi
+
+timeout "$TEST_TIMEOUT" qemu-system-x86_64 \
+    -M q35 \
+    -m 2G \
+    -smp "$SMP_CPUS" \
+    -cdrom "$ISO" \
+    -boot d \
+    -serial stdio \
+    -display none \
+    -no-reboot \
+    -device isa-debug-exit,iobase=0xf4,iosize=0x04
+STATUS=$?
+
+echo "================================"
+case $STATUS in
+    33)
+        echo "✓ All kernel tests passed"
+        exit 0
+        ;;
+    35)
+        echo "✗ Kernel tests failed"
+        exit 1
+        ;;
+    124)
+        echo "✗ Kernel tests timed out after ${TEST_TIMEOUT}s"
+        exit 1
+        ;;
+    *)
+        echo "✗ QEMU exited unexpectedly (status $STATUS)"
+        exit 1
+        ;;
+esac